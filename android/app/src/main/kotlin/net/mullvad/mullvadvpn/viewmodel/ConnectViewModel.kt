@@ -178,9 +178,11 @@ class ConnectViewModel(
     }
 
     private fun TunnelState.isTunnelErrorStateDueToExpiredAccount(): Boolean {
-        return ((this as? TunnelState.Error)?.errorState?.cause as? ErrorStateCause.AuthFailed)
-            ?.isCausedByExpiredAccount()
-            ?: false
+        return when (val cause = (this as? TunnelState.Error)?.errorState?.cause) {
+            is ErrorStateCause.AccountExpired -> true
+            is ErrorStateCause.AuthFailed -> cause.isCausedByExpiredAccount()
+            else -> false
+        }
     }
 
     fun toggleTunnelInfoExpansion() {
@@ -52,6 +52,7 @@ fun ErrorStateCause.errorMessageId(): Int {
         is ErrorStateCause.SetDnsError -> R.string.set_dns_error
         is ErrorStateCause.StartTunnelError -> R.string.start_tunnel_error
         is ErrorStateCause.IsOffline -> R.string.is_offline
+        is ErrorStateCause.AccountExpired -> R.string.account_credit_has_expired
         is ErrorStateCause.TunnelParameterError -> {
             when (error) {
                 ParameterGenerationError.NoMatchingRelay,
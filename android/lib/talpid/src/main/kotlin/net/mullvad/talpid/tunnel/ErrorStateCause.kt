@@ -28,5 +28,7 @@ sealed class ErrorStateCause : Parcelable {
 
     @Parcelize object IsOffline : ErrorStateCause()
 
+    @Parcelize object AccountExpired : ErrorStateCause()
+
     @Parcelize object VpnPermissionDenied : ErrorStateCause()
 }
@@ -34,6 +34,7 @@ import {
   IDeviceRemoval,
   IDnsOptions,
   ILocation,
+  INetworkChange,
   IObfuscationEndpoint,
   IOpenVpnConstraints,
   IProxyEndpoint,
@@ -42,6 +43,7 @@ import {
   IRelayListHostname,
   IRelayListWithEndpointData,
   ISettings,
+  ISplitTunnelAppStatus,
   ITunnelOptions,
   ITunnelStateRelayInfo,
   IWireguardConstraints,
@@ -489,7 +491,7 @@ export class DaemonRpc {
   }
 
   public subscribeDaemonEventListener(listener: SubscriptionListener<DaemonEvent>) {
-    const call = this.isConnected && this.client.eventsListen(new Empty());
+    const call = this.isConnected && this.client.eventsListen(new grpcTypes.EventsListenRequest());
     if (!call) {
       throw noConnectionError;
     }
@@ -534,6 +536,12 @@ export class DaemonRpc {
     return response.getValue();
   }
 
+  // Changes several settings at once, so that the tunnel reconnects at most once. The patch is a
+  // JSON merge patch against the settings in the format of the settings file.
+  public async applySettingsPatch(patch: Record<string, unknown>): Promise<void> {
+    await this.callString(this.client.applySettingsPatch, JSON.stringify(patch));
+  }
+
   public async setDnsOptions(dns: IDnsOptions): Promise<void> {
     const dnsOptions = new grpcTypes.DnsOptions();
 
@@ -548,7 +556,18 @@ export class DaemonRpc {
 
     const customOptions = new grpcTypes.CustomDnsOptions();
     customOptions.setAddressesList(dns.customOptions.addresses);
+    customOptions.setTlsServersList(
+      (dns.customOptions.tlsServers ?? []).map((server) => {
+        const tlsServer = new grpcTypes.TlsDnsServer();
+        tlsServer.setAddress(server.address);
+        tlsServer.setHostname(server.hostname);
+        return tlsServer;
+      }),
+    );
     dnsOptions.setCustomOptions(customOptions);
+    dnsOptions.setPreserveSearchDomains(dns.preserveSearchDomains ?? false);
+    dnsOptions.setAllowLanDnsWhenBlocked(dns.allowLanDnsWhenBlocked ?? false);
+    dnsOptions.setKeepCustomDnsWhileDisconnected(dns.keepCustomDnsWhileDisconnected ?? false);
 
     if (dns.state === 'custom') {
       dnsOptions.setState(grpcTypes.DnsOptions.DnsState.CUSTOM);
@@ -981,6 +1000,11 @@ function convertFromTunnelStateError(state: grpcTypes.ErrorState.AsObject): Erro
         ...baseError,
         cause: ErrorStateCause.splitTunnelError,
       };
+    case grpcTypes.ErrorState.Cause.ACCOUNT_EXPIRED:
+      return {
+        ...baseError,
+        cause: ErrorStateCause.accountExpired,
+      };
     case grpcTypes.ErrorState.Cause.VPN_PERMISSION_DENIED:
       // VPN_PERMISSION_DENIED is only ever created on Android
       throw invalidErrorStateCause;
@@ -1101,7 +1125,11 @@ function convertFromSettings(settings: grpcTypes.Settings): ISettings | undefine
   const relaySettings = convertFromRelaySettings(settings.getRelaySettings())!;
   const bridgeSettings = convertFromBridgeSettings(settings.getBridgeSettings()!);
   const tunnelOptions = convertFromTunnelOptions(settingsObject.tunnelOptions!);
-  const splitTunnel = settingsObject.splitTunnel ?? { enableExclusions: false, appsList: [] };
+  const splitTunnel = settingsObject.splitTunnel ?? {
+    enableExclusions: false,
+    appsList: [],
+    publishersList: [],
+  };
   const obfuscationSettings = convertFromObfuscationSettings(settingsObject.obfuscationSettings);
   const customLists = convertFromCustomListSettings(settings.getCustomLists());
   return {
@@ -1324,7 +1352,12 @@ function convertFromTunnelOptions(tunnelOptions: grpcTypes.TunnelOptions.AsObjec
       },
       customOptions: {
         addresses: tunnelOptions.dnsOptions?.customOptions?.addressesList ?? [],
+        tlsServers: tunnelOptions.dnsOptions?.customOptions?.tlsServersList ?? [],
       },
+      preserveSearchDomains: tunnelOptions.dnsOptions?.preserveSearchDomains ?? false,
+      allowLanDnsWhenBlocked: tunnelOptions.dnsOptions?.allowLanDnsWhenBlocked ?? false,
+      keepCustomDnsWhileDisconnected:
+        tunnelOptions.dnsOptions?.keepCustomDnsWhileDisconnected ?? false,
     },
   };
 }
@@ -1394,6 +1427,31 @@ function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
     return { appVersionInfo: versionInfo.toObject() };
   }
 
+  if (data.hasDnsTampered()) {
+    return { dnsTampered: true };
+  }
+
+  const networkChange = data.getNetworkChanged();
+  if (networkChange !== undefined) {
+    return { networkChanged: convertFromNetworkChange(networkChange) };
+  }
+
+  if (data.hasRoutesUpdated()) {
+    return { routesUpdated: true };
+  }
+
+  const foreignTunnel = data.getForeignTunnel();
+  if (foreignTunnel !== undefined) {
+    return { foreignTunnel: foreignTunnel.getInterface() || undefined };
+  }
+
+  const splitTunnelAppStatus = data.getSplitTunnelAppStatus();
+  if (splitTunnelAppStatus !== undefined) {
+    return {
+      splitTunnelAppStatus: splitTunnelAppStatus.getAppsList().map(convertFromAppExclusionStatus),
+    };
+  }
+
   // Handle unknown daemon events
   const keys = Object.entries(data.toObject())
     .filter(([, value]) => value !== undefined)
@@ -1401,6 +1459,32 @@ function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
   throw new Error(`Unknown daemon event received containing ${keys}`);
 }
 
+function convertFromNetworkChange(networkChange: grpcTypes.NetworkChange): INetworkChange {
+  return {
+    interface: networkChange.getInterface() || undefined,
+    gateway: networkChange.getGateway() || undefined,
+    families: networkChange
+      .getFamiliesList()
+      .map((family) => (family === grpcTypes.IpVersion.V4 ? 'ipv4' : 'ipv6')),
+  };
+}
+
+function convertFromAppExclusionStatus(
+  status: grpcTypes.AppExclusionStatus,
+): ISplitTunnelAppStatus {
+  const app = status.hasPublisher()
+    ? { publisher: status.getPublisher() }
+    : { path: status.getPath() };
+  switch (status.getState()) {
+    case grpcTypes.AppExclusionStatus.State.APPLIED:
+      return { app, state: 'applied' };
+    case grpcTypes.AppExclusionStatus.State.PENDING:
+      return { app, state: 'pending' };
+    case grpcTypes.AppExclusionStatus.State.FAILED:
+      return { app, state: 'failed', error: status.getError() };
+  }
+}
+
 function convertFromOwnership(ownership: grpcTypes.Ownership): Ownership {
   switch (ownership) {
     case grpcTypes.Ownership.ANY:
@@ -9,6 +9,7 @@ export function getDefaultSettings(): ISettings {
     splitTunnel: {
       enableExclusions: false,
       appsList: [],
+      publishersList: [],
     },
     relaySettings: {
       normal: {
@@ -658,6 +658,30 @@ class ApplicationMain
           this.account.handleDeviceEvent(daemonEvent.device);
         } else if ('deviceRemoval' in daemonEvent) {
           IpcMainEventChannel.account.notifyDevices?.(daemonEvent.deviceRemoval);
+        } else if ('dnsTampered' in daemonEvent) {
+          log.warn('DNS settings were changed by another program and have been restored');
+        } else if ('networkChanged' in daemonEvent) {
+          const { interface: iface, gateway, families } = daemonEvent.networkChanged;
+          log.info(
+            families.length > 0
+              ? `Network changed: ${iface ?? 'unknown interface'} via ${gateway ?? 'unknown gateway'}`
+              : 'Network changed: no default route',
+          );
+        } else if ('foreignTunnel' in daemonEvent) {
+          if (daemonEvent.foreignTunnel !== undefined) {
+            log.warn(
+              `The default route uses ${daemonEvent.foreignTunnel}, which belongs to another VPN`,
+            );
+          } else {
+            log.info('The default route no longer uses the interface of another VPN');
+          }
+        } else if ('splitTunnelAppStatus' in daemonEvent) {
+          for (const { app, state, error } of daemonEvent.splitTunnelAppStatus) {
+            if (state === 'failed') {
+              const name = 'path' in app ? app.path : `publisher: ${app.publisher}`;
+              log.warn(`Failed to exclude ${name} from the tunnel: ${error}`);
+            }
+          }
         }
       },
       (error: Error) => {
@@ -1016,7 +1040,9 @@ class ApplicationMain
     this.notificationController.notifyTunnelState(
       tunnelState,
       this.settings.blockWhenDisconnected,
-      this.settings.splitTunnel.enableExclusions && this.settings.splitTunnel.appsList.length > 0,
+      this.settings.splitTunnel.enableExclusions &&
+        (this.settings.splitTunnel.appsList.length > 0 ||
+          this.settings.splitTunnel.publishersList.length > 0),
       this.userInterface?.isWindowVisible() ?? false,
       this.settings.gui.enableSystemNotifications,
     );
@@ -47,6 +47,7 @@ export enum ErrorStateCause {
   tunnelParameterError,
   isOffline,
   splitTunnelError,
+  accountExpired,
 }
 
 export enum AuthFailedError {
@@ -70,7 +71,8 @@ export type ErrorState =
         | ErrorStateCause.setDnsError
         | ErrorStateCause.startTunnelError
         | ErrorStateCause.isOffline
-        | ErrorStateCause.splitTunnelError;
+        | ErrorStateCause.splitTunnelError
+        | ErrorStateCause.accountExpired;
       blockingError?: FirewallPolicyError;
     }
   | {
@@ -159,13 +161,36 @@ export interface IProxyEndpoint {
   proxyType: ProxyType;
 }
 
+export interface INetworkChange {
+  interface?: string;
+  gateway?: string;
+  families: IpVersion[];
+}
+
 export type DaemonEvent =
   | { tunnelState: TunnelState }
   | { settings: ISettings }
   | { relayList: IRelayListWithEndpointData }
   | { appVersionInfo: IAppVersionInfo }
   | { device: DeviceEvent }
-  | { deviceRemoval: Array<IDevice> };
+  | { deviceRemoval: Array<IDevice> }
+  | { dnsTampered: true }
+  | { networkChanged: INetworkChange }
+  // Route updates are not used by the app, and their contents are not converted
+  | { routesUpdated: true }
+  | { foreignTunnel: string | undefined }
+  | { splitTunnelAppStatus: Array<ISplitTunnelAppStatus> };
+
+export type SplitTunnelApp = { path: string } | { publisher: string };
+
+export type AppExclusionState = 'applied' | 'pending' | 'failed';
+
+export interface ISplitTunnelAppStatus {
+  app: SplitTunnelApp;
+  state: AppExclusionState;
+  // Why the app could not be excluded, if `state` is 'failed'
+  error?: string;
+}
 
 export interface ITunnelStateRelayInfo {
   endpoint: ITunnelEndpoint;
@@ -335,10 +360,16 @@ export interface ITunnelOptions {
   dns: IDnsOptions;
 }
 
+export interface ITlsDnsServer {
+  address: string;
+  hostname: string;
+}
+
 export interface IDnsOptions {
   state: 'custom' | 'default';
   customOptions: {
     addresses: string[];
+    tlsServers?: ITlsDnsServer[];
   };
   defaultOptions: {
     blockAds: boolean;
@@ -348,6 +379,9 @@ export interface IDnsOptions {
     blockGambling: boolean;
     blockSocialMedia: boolean;
   };
+  preserveSearchDomains?: boolean;
+  allowLanDnsWhenBlocked?: boolean;
+  keepCustomDnsWhileDisconnected?: boolean;
 }
 
 export type ProxySettings = ILocalProxySettings | IRemoteProxySettings | IShadowsocksProxySettings;
@@ -433,6 +467,7 @@ export type BridgeState = 'auto' | 'on' | 'off';
 export type SplitTunnelSettings = {
   enableExclusions: boolean;
   appsList: string[];
+  publishersList: string[];
 };
 
 export type Udp2TcpObfuscationSettings = {
@@ -175,6 +175,8 @@ function getMessage(errorState: ErrorState): string {
           'notifications',
           'Unable to communicate with Mullvad kernel driver. Try reconnecting or send a problem report.',
         );
+      case ErrorStateCause.accountExpired:
+        return messages.pgettext('auth-failure', 'Blocking internet: account is out of time');
     }
   }
 }
@@ -18,7 +18,12 @@ impl TunnelObfuscatorRuntime {
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_all()
             .build()?;
-        let settings = ObfuscationSettings::Udp2Tcp(Udp2TcpSettings { peer });
+        let settings = ObfuscationSettings::Udp2Tcp(Udp2TcpSettings {
+            peer,
+            keepalive_interval: None,
+            idle_timeout: None,
+            upstream_proxy: None,
+        });
 
         Ok(Self { runtime, settings })
     }
@@ -1,9 +1,9 @@
-use super::API;
+use super::{nat64::Nat64Prefix, API};
 use std::{io, net::SocketAddr, path::Path, sync::Arc};
 use tokio::{
     fs,
     io::{AsyncReadExt, AsyncWriteExt},
-    sync::Mutex,
+    sync::{Mutex, Notify},
 };
 
 #[derive(err_derive::Error, Debug)]
@@ -20,12 +20,17 @@ pub enum Error {
 
     #[error(display = "Failed to update the address cache file")]
     Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove the address cache file")]
+    Remove(#[error(source)] io::Error),
 }
 
 #[derive(Clone)]
 pub struct AddressCache {
     inner: Arc<Mutex<AddressCacheInner>>,
     write_path: Option<Arc<Path>>,
+    /// Notified when the cache is cleared, so that new addresses are fetched.
+    cleared: Arc<Notify>,
 }
 
 impl AddressCache {
@@ -47,26 +52,83 @@ impl AddressCache {
         let address_cache = Self {
             inner: Arc::new(Mutex::new(cache)),
             write_path: write_path.map(Arc::from),
+            cleared: Arc::new(Notify::new()),
         };
         Ok(address_cache)
     }
 
-    /// Returns the address if the hostname equals `API.host`. Otherwise, returns `None`.
+    /// Returns the address if the hostname equals `API.host()`. Otherwise, returns `None`.
     pub async fn resolve_hostname(&self, hostname: &str) -> Option<SocketAddr> {
-        if hostname.eq_ignore_ascii_case(&API.host) {
+        if hostname.eq_ignore_ascii_case(&API.host()) {
             Some(self.get_address().await)
         } else {
             None
         }
     }
 
-    /// Returns the currently selected address.
+    /// Returns the currently selected address. If a NAT64 prefix is set, IPv4 addresses are
+    /// translated to IPv6 addresses using it.
     pub async fn get_address(&self) -> SocketAddr {
-        self.inner.lock().await.address
+        self.inner.lock().await.effective_address()
+    }
+
+    /// Picks the address to use out of those returned by the API. IPv6 addresses are preferred
+    /// while a NAT64 prefix is set.
+    pub async fn select_address(&self, addresses: &[SocketAddr]) -> Option<SocketAddr> {
+        let prefer_ipv6 = self.inner.lock().await.nat64_prefix.is_some();
+        prefer_ipv6
+            .then(|| addresses.iter().find(|address| address.is_ipv6()))
+            .flatten()
+            .or_else(|| addresses.first())
+            .copied()
+    }
+
+    /// Sets the NAT64 prefix used to reach IPv4 addresses from an IPv6-only network, or `None` if
+    /// the network does not use NAT64. Returns whether the prefix changed.
+    pub async fn set_nat64_prefix(&self, prefix: Option<Nat64Prefix>) -> bool {
+        let mut inner = self.inner.lock().await;
+        if inner.nat64_prefix == prefix {
+            return false;
+        }
+        match prefix {
+            Some(prefix) => log::info!("Detected NAT64 prefix {}", prefix),
+            None => log::info!("NAT64 is no longer in use"),
+        }
+        inner.nat64_prefix = prefix;
+        log::debug!("Using API address: {}", inner.effective_address());
+        true
+    }
+
+    /// Use `address` instead of the cached address until this is called with `None`. Addresses
+    /// passed to [`Self::set_address`] are ignored in the meantime, so that the cache is not
+    /// overwritten with addresses that are only valid for the override.
+    pub async fn set_address_override(&self, address: Option<SocketAddr>) {
+        let mut inner = self.inner.lock().await;
+        inner.override_address = address;
+        log::debug!("Using API address: {}", inner.effective_address());
+    }
+
+    pub async fn has_address_override(&self) -> bool {
+        self.inner.lock().await.override_address.is_some()
+    }
+
+    /// Use `address`, which has been looked up without the help of the API, until an address is
+    /// fetched from the API. The address is not written to disk.
+    pub async fn set_bootstrap_address(&self, address: SocketAddr) {
+        let mut inner = self.inner.lock().await;
+        if inner.override_address.is_some() {
+            return;
+        }
+        inner.address = address;
+        log::debug!("Using API address: {}", inner.effective_address());
     }
 
     pub async fn set_address(&self, address: SocketAddr) -> Result<(), Error> {
         let mut inner = self.inner.lock().await;
+        if inner.override_address.is_some() {
+            log::debug!("Ignoring API address {address} since the API endpoint is overridden");
+            return Ok(());
+        }
         if address != inner.address {
             self.save_to_disk(&address).await?;
             inner.address = address;
@@ -74,6 +136,26 @@ impl AddressCache {
         Ok(())
     }
 
+    /// Removes the cache file. The current address is kept, since it is needed to reach the API,
+    /// but new addresses are fetched right away.
+    pub async fn clear(&self) -> Result<(), Error> {
+        if let Some(write_path) = self.write_path.as_ref() {
+            log::debug!("Removing API address cache {}", write_path.display());
+            match fs::remove_file(&**write_path).await {
+                Ok(()) => (),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+                Err(error) => return Err(Error::Remove(error)),
+            }
+        }
+        self.cleared.notify_one();
+        Ok(())
+    }
+
+    /// Waits until the cache is cleared by [`Self::clear`].
+    pub(crate) async fn cleared(&self) {
+        self.cleared.notified().await
+    }
+
     async fn save_to_disk(&self, address: &SocketAddr) -> Result<(), Error> {
         let write_path = match self.write_path.as_ref() {
             Some(write_path) => write_path,
@@ -95,11 +177,25 @@ impl AddressCache {
 #[derive(Clone, PartialEq, Eq)]
 struct AddressCacheInner {
     address: SocketAddr,
+    override_address: Option<SocketAddr>,
+    nat64_prefix: Option<Nat64Prefix>,
 }
 
 impl AddressCacheInner {
     fn from_address(address: SocketAddr) -> Self {
-        Self { address }
+        Self {
+            address,
+            override_address: None,
+            nat64_prefix: None,
+        }
+    }
+
+    fn effective_address(&self) -> SocketAddr {
+        let address = self.override_address.unwrap_or(self.address);
+        match self.nat64_prefix {
+            Some(prefix) => prefix.synthesize_socket_addr(address),
+            None => address,
+        }
     }
 }
 
@@ -111,3 +207,79 @@ async fn read_address_file(path: &Path) -> Result<SocketAddr, Error> {
         .map_err(Error::Read)?;
     address.trim().parse().map_err(|_| Error::Parse)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+
+    #[tokio::test]
+    async fn test_address_override() {
+        let cache = AddressCache::new(None).unwrap();
+        let cached_address = cache.get_address().await;
+        let override_address: SocketAddr = "10.0.0.1:443".parse().unwrap();
+
+        cache.set_address_override(Some(override_address)).await;
+        assert_eq!(cache.get_address().await, override_address);
+        // Addresses fetched while the override is active must not replace the cached one.
+        cache
+            .set_address("10.0.0.2:443".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(cache.get_address().await, override_address);
+
+        cache.set_address_override(None).await;
+        assert_eq!(cache.get_address().await, cached_address);
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_address() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let path = cache_dir.path().join("api-ip-address.txt");
+        let cache = AddressCache::new(Some(Box::from(path.as_path()))).unwrap();
+        cache
+            .set_address("192.0.2.1:443".parse().unwrap())
+            .await
+            .unwrap();
+
+        let bootstrap_address: SocketAddr = "198.51.100.1:443".parse().unwrap();
+        cache.set_bootstrap_address(bootstrap_address).await;
+        assert_eq!(cache.get_address().await, bootstrap_address);
+
+        // Bootstrap addresses are never written to disk.
+        let loaded = AddressCache::from_file(&path, None).await.unwrap();
+        assert_eq!(loaded.get_address().await, "192.0.2.1:443".parse().unwrap());
+
+        // An address fetched from the API replaces it again.
+        cache
+            .set_address("192.0.2.2:443".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(cache.get_address().await, "192.0.2.2:443".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_clear() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let path = cache_dir.path().join("api-ip-address.txt");
+        let other_path = cache_dir.path().join("relays.json");
+        fs::write(&other_path, "{}").await.unwrap();
+        let cache = AddressCache::new(Some(Box::from(path.as_path()))).unwrap();
+        cache
+            .set_address("192.0.2.1:443".parse().unwrap())
+            .await
+            .unwrap();
+
+        cache.clear().await.unwrap();
+        assert!(!path.exists());
+        // The API must still be reachable until new addresses have been fetched.
+        assert_eq!(cache.get_address().await, "192.0.2.1:443".parse().unwrap());
+        assert!(cache.cleared().now_or_never().is_some());
+        // Other files in the cache directory are left alone.
+        assert_eq!(fs::read_to_string(&other_path).await.unwrap(), "{}");
+
+        // Clearing twice is fine.
+        cache.clear().await.unwrap();
+        cache.clear().await.unwrap();
+    }
+}
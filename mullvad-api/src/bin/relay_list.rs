@@ -13,7 +13,11 @@ async fn main() {
 
     let relay_list_request = RelayListProxy::new(
         runtime
-            .mullvad_rest_handle(ApiConnectionMode::Direct.into_repeat(), |_| async { true })
+            .mullvad_rest_handle(
+                ApiConnectionMode::Direct.into_repeat(),
+                |_| async { true },
+                |_| (),
+            )
             .await,
     )
     .relay_list(None)
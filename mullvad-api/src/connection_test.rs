@@ -0,0 +1,342 @@
+//! Measures each stage of reaching the API using a connection mode, to tell where an access
+//! method which does not work fails.
+#[cfg(target_os = "android")]
+use crate::https_client_with_sni::SocketBypassRequest;
+#[cfg(feature = "api-override")]
+use crate::API;
+use crate::{
+    https_client_with_sni::{HttpsConnectorWithSni, InnerConnectionMode},
+    proxy::ApiConnectionMode,
+    tls_stream::TlsStream,
+    APP_URL_PREFIX,
+};
+#[cfg(target_os = "android")]
+use futures::channel::mpsc;
+use hyper::{header, Body, Request, StatusCode};
+use mullvad_types::access_method::{AccessMethodTestReport, ConnectionStage};
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Time to wait for a TCP connection to the API or proxy to be established.
+pub const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait for the proxy to accept relaying the connection.
+pub const PROXY_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait for the TLS session with the API to be established.
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait for the API to respond to a request.
+pub const HTTP_ROUND_TRIP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for each [`ConnectionStage`] before giving up.
+#[derive(Clone, Copy, Debug)]
+pub struct StageTimeouts {
+    pub tcp_connect: Duration,
+    pub proxy_handshake: Duration,
+    pub tls_handshake: Duration,
+    pub http_round_trip: Duration,
+}
+
+impl Default for StageTimeouts {
+    fn default() -> Self {
+        Self {
+            tcp_connect: TCP_CONNECT_TIMEOUT,
+            proxy_handshake: PROXY_HANDSHAKE_TIMEOUT,
+            tls_handshake: TLS_HANDSHAKE_TIMEOUT,
+            http_round_trip: HTTP_ROUND_TRIP_TIMEOUT,
+        }
+    }
+}
+
+impl StageTimeouts {
+    fn get(&self, stage: ConnectionStage) -> Duration {
+        match stage {
+            ConnectionStage::TcpConnect => self.tcp_connect,
+            ConnectionStage::ProxyHandshake => self.proxy_handshake,
+            ConnectionStage::TlsHandshake => self.tls_handshake,
+            ConnectionStage::HttpRoundTrip => self.http_round_trip,
+        }
+    }
+}
+
+/// Runs the stages of a test one at a time and records the outcome of each in a report.
+struct Stages {
+    timeouts: StageTimeouts,
+    report: AccessMethodTestReport,
+}
+
+impl Stages {
+    fn new(timeouts: StageTimeouts) -> Self {
+        Self {
+            timeouts,
+            report: AccessMethodTestReport::default(),
+        }
+    }
+
+    /// Run `stage` until it completes or its timeout elapses. `None` is returned if the stage
+    /// failed, in which case no further stages should be run.
+    async fn run<T>(
+        &mut self,
+        stage: ConnectionStage,
+        stage_fut: impl Future<Output = io::Result<T>>,
+    ) -> Option<T> {
+        let timeout = self.timeouts.get(stage);
+        let start = Instant::now();
+        match tokio::time::timeout(timeout, stage_fut).await {
+            Ok(Ok(value)) => {
+                self.report.timings.push((stage, start.elapsed()));
+                Some(value)
+            }
+            Ok(Err(error)) => {
+                self.report.failure = Some((stage, error.to_string()));
+                None
+            }
+            Err(_) => {
+                let error = format!("Timed out after {} ms", timeout.as_millis());
+                self.report.failure = Some((stage, error));
+                None
+            }
+        }
+    }
+}
+
+/// Connect to the API at `addr` using `connection_mode`, and send a single request to it.
+pub(crate) async fn test_connection_mode(
+    connection_mode: ApiConnectionMode,
+    hostname: &str,
+    addr: SocketAddr,
+    timeouts: StageTimeouts,
+    #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+) -> AccessMethodTestReport {
+    let mut stages = Stages::new(timeouts);
+
+    let mode = match InnerConnectionMode::try_from(connection_mode) {
+        Ok(mode) => mode,
+        Err(error) => {
+            stages.report.failure = Some((ConnectionStage::TcpConnect, error.to_string()));
+            return stages.report;
+        }
+    };
+    let is_proxied = !matches!(mode, InnerConnectionMode::Direct);
+
+    let connect = HttpsConnectorWithSni::open_socket(
+        mode.first_hop(&addr),
+        None,
+        #[cfg(target_os = "android")]
+        socket_bypass_tx,
+    );
+    let Some(tcp_stream) = stages.run(ConnectionStage::TcpConnect, connect).await else {
+        return stages.report;
+    };
+
+    let proxy_handshake = mode.proxy_stream(tcp_stream, &addr);
+    let proxy_stream = if is_proxied {
+        stages
+            .run(ConnectionStage::ProxyHandshake, proxy_handshake)
+            .await
+    } else {
+        // Without a proxy, there is no handshake to measure.
+        proxy_handshake.await.ok()
+    };
+    let Some(proxy_stream) = proxy_stream else {
+        return stages.report;
+    };
+
+    #[cfg(feature = "api-override")]
+    if API.disable_tls {
+        let round_trip = http_round_trip(proxy_stream, hostname);
+        stages.run(ConnectionStage::HttpRoundTrip, round_trip).await;
+        return stages.report;
+    }
+
+    let tls_handshake = TlsStream::connect_https(proxy_stream, hostname);
+    let Some(tls_stream) = stages
+        .run(ConnectionStage::TlsHandshake, tls_handshake)
+        .await
+    else {
+        return stages.report;
+    };
+
+    let round_trip = http_round_trip(tls_stream, hostname);
+    stages.run(ConnectionStage::HttpRoundTrip, round_trip).await;
+    stages.report
+}
+
+/// Request the API addresses and wait for the response header. Any response counts as a success,
+/// since it shows that the API was reached.
+async fn http_round_trip<S>(stream: S, hostname: &str) -> io::Result<StatusCode>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let to_io_error = |error: hyper::Error| io::Error::new(io::ErrorKind::Other, error);
+
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(to_io_error)?;
+    // The connection is closed once `sender` is dropped.
+    tokio::spawn(connection);
+
+    let request = Request::get(format!("/{APP_URL_PREFIX}/api-addrs"))
+        .header(header::HOST, hostname)
+        .body(Body::empty())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let response = sender.send_request(request).await.map_err(to_io_error)?;
+    Ok(response.status())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proxy::ProxyConfig;
+    use mullvad_types::access_method::HttpProxy;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    const HOSTNAME: &str = "api.test";
+    const DELAY: Duration = Duration::from_millis(100);
+
+    fn timeouts() -> StageTimeouts {
+        let timeout = Duration::from_millis(500);
+        StageTimeouts {
+            tcp_connect: timeout,
+            proxy_handshake: timeout,
+            tls_handshake: timeout,
+            http_round_trip: timeout,
+        }
+    }
+
+    /// Start a server which accepts a single connection, answers every chunk of data it receives
+    /// with the next of `responses` after waiting `delay`, and then stops responding.
+    async fn start_server(delay: Duration, responses: &'static [&'static str]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            for response in responses {
+                if stream.read(&mut buf).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                tokio::time::sleep(delay).await;
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+            // Keep the connection open without responding.
+            let _ = stream.read_to_end(&mut Vec::new()).await;
+        });
+        addr
+    }
+
+    fn http_proxy_mode(proxy: SocketAddr) -> ApiConnectionMode {
+        ApiConnectionMode::Proxied(ProxyConfig::HttpProxy(HttpProxy::new(proxy, None)))
+    }
+
+    async fn run_test(
+        connection_mode: ApiConnectionMode,
+        addr: SocketAddr,
+    ) -> AccessMethodTestReport {
+        test_connection_mode(
+            connection_mode,
+            HOSTNAME,
+            addr,
+            timeouts(),
+            #[cfg(target_os = "android")]
+            None,
+        )
+        .await
+    }
+
+    fn stages(report: &AccessMethodTestReport) -> Vec<ConnectionStage> {
+        report.timings.iter().map(|(stage, _)| *stage).collect()
+    }
+
+    fn failed_stage(report: &AccessMethodTestReport) -> Option<ConnectionStage> {
+        report.failure.as_ref().map(|(stage, _)| *stage)
+    }
+
+    #[tokio::test]
+    async fn test_tcp_connect_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let report = run_test(ApiConnectionMode::Direct, addr).await;
+        assert!(report.timings.is_empty());
+        assert_eq!(failed_stage(&report), Some(ConnectionStage::TcpConnect));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_handshake_timeout() {
+        let proxy = start_server(DELAY, &[]).await;
+
+        let report = run_test(http_proxy_mode(proxy), proxy).await;
+        assert_eq!(stages(&report), [ConnectionStage::TcpConnect]);
+        let (stage, error) = report.failure.unwrap();
+        assert_eq!(stage, ConnectionStage::ProxyHandshake);
+        assert!(error.contains("Timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_timeout_after_slow_proxy() {
+        let proxy = start_server(DELAY, &["HTTP/1.1 200 Connection established\r\n\r\n"]).await;
+
+        let report = run_test(http_proxy_mode(proxy), proxy).await;
+        assert_eq!(
+            stages(&report),
+            [ConnectionStage::TcpConnect, ConnectionStage::ProxyHandshake]
+        );
+        assert!(report.timings[1].1 >= DELAY);
+        assert_eq!(failed_stage(&report), Some(ConnectionStage::TlsHandshake));
+    }
+
+    #[tokio::test]
+    async fn test_direct_skips_proxy_handshake() {
+        let server = start_server(DELAY, &[]).await;
+
+        let report = run_test(ApiConnectionMode::Direct, server).await;
+        assert_eq!(stages(&report), [ConnectionStage::TcpConnect]);
+        assert_eq!(failed_stage(&report), Some(ConnectionStage::TlsHandshake));
+    }
+
+    #[tokio::test]
+    async fn test_http_round_trip_delay() {
+        let server = start_server(DELAY, &["HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"]).await;
+        let stream = TcpStream::connect(server).await.unwrap();
+
+        let mut stages = Stages::new(timeouts());
+        let status = stages
+            .run(
+                ConnectionStage::HttpRoundTrip,
+                http_round_trip(stream, HOSTNAME),
+            )
+            .await;
+        assert_eq!(status, Some(StatusCode::OK));
+        assert!(stages.report.succeeded());
+        assert!(stages.report.timings[0].1 >= DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_http_round_trip_timeout() {
+        let server =
+            start_server(timeouts().http_round_trip * 2, &["HTTP/1.1 200 OK\r\n\r\n"]).await;
+        let stream = TcpStream::connect(server).await.unwrap();
+
+        let mut stages = Stages::new(timeouts());
+        let status = stages
+            .run(
+                ConnectionStage::HttpRoundTrip,
+                http_round_trip(stream, HOSTNAME),
+            )
+            .await;
+        assert_eq!(status, None);
+        assert!(stages.report.timings.is_empty());
+        assert_eq!(
+            failed_stage(&stages.report),
+            Some(ConnectionStage::HttpRoundTrip)
+        );
+    }
+}
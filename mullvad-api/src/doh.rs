@@ -0,0 +1,661 @@
+//! A minimal DNS-over-HTTPS (RFC 8484) client. It is used to look up the API when neither the
+//! cached address nor the system resolver works, e.g. because the local resolver has been
+//! tampered with.
+#[cfg(target_os = "android")]
+use crate::https_client_with_sni::SocketBypassRequest;
+use crate::{
+    https_client_with_sni::{AllowEndpoint, HttpsConnectorWithSni},
+    tls_stream::TlsStream,
+};
+#[cfg(target_os = "android")]
+use futures::channel::mpsc;
+use hyper::{
+    body::{Body, HttpBody},
+    header, Method, Request, StatusCode,
+};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str,
+    time::Duration,
+};
+use talpid_types::ErrorExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// DoH servers to query, by hostname and IP. They are reached by IP since the local resolver is
+/// not trusted. Their certificates are issued by Let's Encrypt, which means that they can be
+/// verified using the same root certificate as the API.
+const DOH_SERVERS: &[(&str, IpAddr)] = &[
+    ("dns.mullvad.net", IpAddr::V4(Ipv4Addr::new(194, 242, 2, 2))),
+    (
+        "dns.mullvad.net",
+        IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 2)),
+    ),
+    (
+        "base.dns.mullvad.net",
+        IpAddr::V4(Ipv4Addr::new(194, 242, 2, 4)),
+    ),
+];
+const DOH_PORT: u16 = 443;
+const DOH_PATH: &str = "/dns-query";
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+
+/// Timeout for looking up a hostname using a single DoH server.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RESPONSE_CODE_MASK: u16 = 0x000f;
+
+const MAX_MESSAGE_SIZE: usize = 65535;
+const MAX_NAME_LENGTH: usize = 253;
+const MAX_LABEL_LENGTH: usize = 63;
+/// Maximum number of compression pointers to follow in a single name. This guards against
+/// pointer loops.
+const MAX_POINTERS: usize = 16;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Invalid hostname: {}", _0)]
+    InvalidName(String),
+
+    #[error(display = "Failed to connect to the DoH server")]
+    Connect(#[error(source)] io::Error),
+
+    #[error(display = "DoH request failed")]
+    Http(#[error(source)] hyper::Error),
+
+    #[error(display = "DoH request timed out")]
+    Timeout,
+
+    #[error(display = "Unexpected HTTP status from the DoH server: {}", _0)]
+    HttpStatus(StatusCode),
+
+    #[error(display = "The DNS response is too large")]
+    ResponseTooLarge,
+
+    #[error(display = "The DNS response is malformed")]
+    Malformed,
+
+    #[error(display = "The DNS response does not match the query")]
+    UnexpectedResponse,
+
+    #[error(display = "The DNS server returned response code {}", _0)]
+    ResponseCode(u8),
+}
+
+/// Looks up the IPv4 and IPv6 addresses of `hostname` using the hardcoded DoH servers. The
+/// servers are tried in order, until one of them responds. `allow_endpoint` is called with each
+/// server before it is connected to, so that the firewall lets the query through.
+pub(crate) async fn resolve(
+    hostname: &str,
+    allow_endpoint: &AllowEndpoint,
+    #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+) -> Result<Vec<IpAddr>, Error> {
+    let servers = DOH_SERVERS
+        .iter()
+        .map(|(server_name, server_ip)| (*server_name, SocketAddr::new(*server_ip, DOH_PORT)));
+    resolve_with_servers(
+        servers,
+        hostname,
+        allow_endpoint,
+        #[cfg(target_os = "android")]
+        socket_bypass_tx,
+    )
+    .await
+}
+
+async fn resolve_with_servers<'a>(
+    servers: impl Iterator<Item = (&'a str, SocketAddr)>,
+    hostname: &str,
+    allow_endpoint: &AllowEndpoint,
+    #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+) -> Result<Vec<IpAddr>, Error> {
+    let mut last_error = Error::Timeout;
+    for (server_name, server_addr) in servers {
+        allow_endpoint(server_addr).await;
+        let lookup = resolve_with_server(
+            server_name,
+            server_addr,
+            hostname,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx.clone(),
+        );
+        let error = match tokio::time::timeout(LOOKUP_TIMEOUT, lookup).await {
+            Ok(Ok(addresses)) => return Ok(addresses),
+            Ok(Err(error)) => error,
+            Err(_) => Error::Timeout,
+        };
+        log::debug!(
+            "{}",
+            error.display_chain_with_msg(&format!("DoH lookup using {server_addr} failed"))
+        );
+        last_error = error;
+    }
+    Err(last_error)
+}
+
+async fn resolve_with_server(
+    server_name: &str,
+    server_addr: SocketAddr,
+    hostname: &str,
+    #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+) -> Result<Vec<IpAddr>, Error> {
+    let socket = HttpsConnectorWithSni::open_socket(
+        server_addr,
+        None,
+        #[cfg(target_os = "android")]
+        socket_bypass_tx,
+    )
+    .await
+    .map_err(Error::Connect)?;
+    let stream = TlsStream::connect_https(socket, server_name)
+        .await
+        .map_err(Error::Connect)?;
+    resolve_over_stream(stream, server_name, hostname).await
+}
+
+/// Sends an A and an AAAA query for `hostname` over an established connection to a DoH server.
+async fn resolve_over_stream<S>(
+    stream: S,
+    server_name: &str,
+    hostname: &str,
+) -> Result<Vec<IpAddr>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(Error::Http)?;
+    tokio::spawn(async move {
+        if let Err(error) = connection.await {
+            log::trace!("DoH connection closed: {error}");
+        }
+    });
+
+    let mut addresses = vec![];
+    for query_type in [TYPE_A, TYPE_AAAA] {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(DOH_PATH)
+            .header(header::HOST, server_name)
+            .header(header::CONTENT_TYPE, DNS_MESSAGE_CONTENT_TYPE)
+            .header(header::ACCEPT, DNS_MESSAGE_CONTENT_TYPE)
+            .body(Body::from(build_query(hostname, query_type)?))
+            .expect("failed to build DoH request");
+        let response = sender.send_request(request).await.map_err(Error::Http)?;
+        if response.status() != StatusCode::OK {
+            return Err(Error::HttpStatus(response.status()));
+        }
+        let message = read_message(response.into_body()).await?;
+        addresses.extend(parse_response(&message, hostname, query_type)?);
+    }
+    Ok(addresses)
+}
+
+async fn read_message(mut body: Body) -> Result<Vec<u8>, Error> {
+    let mut message = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(Error::Http)?;
+        if message.len() + chunk.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::ResponseTooLarge);
+        }
+        message.extend_from_slice(&chunk);
+    }
+    Ok(message)
+}
+
+/// Returns a DNS query for records of type `query_type` for `hostname`.
+fn build_query(hostname: &str, query_type: u16) -> Result<Vec<u8>, Error> {
+    let mut message = vec![];
+    // The ID is 0, as recommended by RFC 8484, since the query is matched to its response by
+    // HTTP.
+    message.extend_from_slice(&0u16.to_be_bytes());
+    message.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // One question, and no answer, authority or additional records.
+    for count in [1u16, 0, 0, 0] {
+        message.extend_from_slice(&count.to_be_bytes());
+    }
+    write_name(&mut message, hostname)?;
+    message.extend_from_slice(&query_type.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+fn write_name(message: &mut Vec<u8>, name: &str) -> Result<(), Error> {
+    let invalid_name = || Error::InvalidName(name.to_owned());
+
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(invalid_name());
+    }
+    for label in name.split('.') {
+        let valid_label = !label.is_empty()
+            && label.len() <= MAX_LABEL_LENGTH
+            && label
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-');
+        if !valid_label {
+            return Err(invalid_name());
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    Ok(())
+}
+
+/// Returns the addresses of type `query_type` for `hostname` which are contained in `message`.
+/// CNAME records are followed, as long as they are part of the same response.
+fn parse_response(message: &[u8], hostname: &str, query_type: u16) -> Result<Vec<IpAddr>, Error> {
+    let mut reader = Reader::new(message);
+
+    let id = reader.read_u16()?;
+    let flags = reader.read_u16()?;
+    let question_count = reader.read_u16()?;
+    let answer_count = reader.read_u16()?;
+    // Authority and additional records are not used.
+    reader.read_u16()?;
+    reader.read_u16()?;
+
+    if id != 0 || flags & FLAG_RESPONSE == 0 {
+        return Err(Error::UnexpectedResponse);
+    }
+    if flags & FLAG_TRUNCATED != 0 {
+        return Err(Error::Malformed);
+    }
+    let response_code = (flags & RESPONSE_CODE_MASK) as u8;
+    if response_code != 0 {
+        return Err(Error::ResponseCode(response_code));
+    }
+
+    if question_count != 1 {
+        return Err(Error::UnexpectedResponse);
+    }
+    let question_name = reader.read_name()?;
+    let question_type = reader.read_u16()?;
+    let question_class = reader.read_u16()?;
+    let hostname = hostname.strip_suffix('.').unwrap_or(hostname);
+    if !question_name.eq_ignore_ascii_case(hostname)
+        || question_type != query_type
+        || question_class != CLASS_IN
+    {
+        return Err(Error::UnexpectedResponse);
+    }
+
+    // Names which refer to `hostname`
+    let mut names = vec![question_name];
+    let mut addresses = vec![];
+
+    for _ in 0..answer_count {
+        let name = reader.read_name()?;
+        let record_type = reader.read_u16()?;
+        let record_class = reader.read_u16()?;
+        let _ttl = reader.read_u32()?;
+        let data_length = reader.read_u16()?;
+        let data_offset = reader.offset;
+        let data = reader.read_bytes(usize::from(data_length))?;
+
+        if record_class != CLASS_IN || !names.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+            continue;
+        }
+        match record_type {
+            TYPE_CNAME => {
+                let mut data_reader = Reader::new(message);
+                data_reader.offset = data_offset;
+                names.push(data_reader.read_name()?);
+            }
+            TYPE_A if query_type == TYPE_A => {
+                let octets = <[u8; 4]>::try_from(data).map_err(|_| Error::Malformed)?;
+                addresses.push(IpAddr::from(octets));
+            }
+            TYPE_AAAA if query_type == TYPE_AAAA => {
+                let octets = <[u8; 16]>::try_from(data).map_err(|_| Error::Malformed)?;
+                addresses.push(IpAddr::from(octets));
+            }
+            _ => (),
+        }
+    }
+
+    Ok(addresses)
+}
+
+struct Reader<'a> {
+    message: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(message: &'a [u8]) -> Self {
+        Self { message, offset: 0 }
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .message
+            .get(self.offset..self.offset + length)
+            .ok_or(Error::Malformed)?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name, and returns it without a trailing dot.
+    fn read_name(&mut self) -> Result<String, Error> {
+        let mut labels = vec![];
+        let mut name_length = 0;
+        let mut offset = self.offset;
+        let mut pointers = 0;
+
+        loop {
+            let length = usize::from(*self.message.get(offset).ok_or(Error::Malformed)?);
+            match length & 0xc0 {
+                0xc0 => {
+                    let low = usize::from(*self.message.get(offset + 1).ok_or(Error::Malformed)?);
+                    if pointers == 0 {
+                        self.offset = offset + 2;
+                    }
+                    pointers += 1;
+                    if pointers > MAX_POINTERS {
+                        return Err(Error::Malformed);
+                    }
+                    offset = ((length & 0x3f) << 8) | low;
+                }
+                0x00 if length == 0 => {
+                    if pointers == 0 {
+                        self.offset = offset + 1;
+                    }
+                    break;
+                }
+                0x00 => {
+                    let label = self
+                        .message
+                        .get(offset + 1..offset + 1 + length)
+                        .ok_or(Error::Malformed)?;
+                    name_length += length + 1;
+                    if name_length > MAX_NAME_LENGTH + 1 {
+                        return Err(Error::Malformed);
+                    }
+                    labels.push(str::from_utf8(label).map_err(|_| Error::Malformed)?);
+                    offset += 1 + length;
+                }
+                _ => return Err(Error::Malformed),
+            }
+        }
+
+        Ok(labels.join("."))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    /// Returns an [`AllowEndpoint`] callback which records every address that it is called with.
+    pub fn recording_allow_endpoint() -> (AllowEndpoint, Arc<Mutex<Vec<SocketAddr>>>) {
+        let allowed = Arc::new(Mutex::new(vec![]));
+        let recorded = allowed.clone();
+        let allow_endpoint: AllowEndpoint = Arc::new(move |address| {
+            recorded.lock().unwrap().push(address);
+            Box::pin(async { true })
+        });
+        (allow_endpoint, allowed)
+    }
+
+    /// Returns a local address which nothing listens on.
+    pub fn closed_address() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    /// Query for the A records of api.mullvad.net.
+    const API_A_QUERY: &[u8] = &[
+        0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Header
+        0x03, b'a', b'p', b'i', 0x07, b'm', b'u', b'l', b'l', b'v', b'a', b'd', 0x03, b'n', b'e',
+        b't', 0x00, // api.mullvad.net
+        0x00, 0x01, 0x00, 0x01, // A, IN
+    ];
+
+    /// Recorded response to `API_A_QUERY`, including an EDNS OPT record.
+    const RECORDED_A_RESPONSE: &[u8] = &[
+        0x00, 0x00, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, // Header
+        0x03, b'a', b'p', b'i', 0x07, b'm', b'u', b'l', b'l', b'v', b'a', b'd', 0x03, b'n', b'e',
+        b't', 0x00, 0x00, 0x01, 0x00, 0x01, // Question
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 45, 83, 223,
+        196, // Answer
+        0x00, 0x00, 0x29, 0x04, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // OPT
+    ];
+
+    /// Recorded response to the AAAA query for api.mullvad.net, which has no answers.
+    const RECORDED_AAAA_RESPONSE: &[u8] = &[
+        0x00, 0x00, 0x81, 0x80, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // Header
+        0x03, b'a', b'p', b'i', 0x07, b'm', b'u', b'l', b'l', b'v', b'a', b'd', 0x03, b'n', b'e',
+        b't', 0x00, 0x00, 0x1c, 0x00, 0x01, // Question
+        0x00, 0x00, 0x29, 0x04, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // OPT
+    ];
+
+    fn response_header(flags: u16, answer_count: u16) -> Vec<u8> {
+        let mut message = vec![0, 0];
+        message.extend_from_slice(&flags.to_be_bytes());
+        for count in [1, answer_count, 0, 0] {
+            message.extend_from_slice(&count.to_be_bytes());
+        }
+        write_name(&mut message, "api.mullvad.net").unwrap();
+        message.extend_from_slice(&TYPE_AAAA.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message
+    }
+
+    fn push_record(message: &mut Vec<u8>, name: &[u8], record_type: u16, data: &[u8]) {
+        message.extend_from_slice(name);
+        message.extend_from_slice(&record_type.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message.extend_from_slice(&60u32.to_be_bytes());
+        message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        message.extend_from_slice(data);
+    }
+
+    /// The firewall is told to allow each DoH server before it is connected to.
+    #[tokio::test]
+    async fn test_servers_are_allowed() {
+        let servers = [
+            ("dns.test", closed_address()),
+            ("base.dns.test", closed_address()),
+        ];
+        let (allow_endpoint, allowed) = recording_allow_endpoint();
+
+        let result = resolve_with_servers(
+            servers.iter().copied(),
+            "api.mullvad.net",
+            &allow_endpoint,
+            #[cfg(target_os = "android")]
+            None,
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::Connect(_))));
+        assert_eq!(*allowed.lock().unwrap(), [servers[0].1, servers[1].1]);
+    }
+
+    #[test]
+    fn test_build_query() {
+        assert_eq!(build_query("api.mullvad.net", TYPE_A).unwrap(), API_A_QUERY);
+        assert_eq!(
+            build_query("api.mullvad.net.", TYPE_A).unwrap(),
+            API_A_QUERY
+        );
+
+        let too_long_label = format!("{}.net", "a".repeat(MAX_LABEL_LENGTH + 1));
+        for name in ["", ".", "api..net", "api_1.mullvad.net", &too_long_label] {
+            assert!(
+                matches!(build_query(name, TYPE_A), Err(Error::InvalidName(_))),
+                "{name} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_recorded_response() {
+        let addresses = parse_response(RECORDED_A_RESPONSE, "api.mullvad.net", TYPE_A).unwrap();
+        assert_eq!(addresses, [IpAddr::V4(Ipv4Addr::new(45, 83, 223, 196))]);
+
+        let addresses =
+            parse_response(RECORDED_AAAA_RESPONSE, "api.mullvad.net", TYPE_AAAA).unwrap();
+        assert!(addresses.is_empty());
+    }
+
+    #[test]
+    fn test_parse_cname() {
+        let mut message = response_header(0x8180, 3);
+        // api.mullvad.net CNAME api.example (compressed, pointing at "net" in the question)
+        push_record(
+            &mut message,
+            &[0xc0, 0x0c],
+            TYPE_CNAME,
+            &[
+                0x03, b'a', b'p', b'i', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0xc0, 0x18,
+            ],
+        );
+        let alias_offset = message.len() - 14;
+        // api.example.net AAAA 2001:db8::1
+        let mut alias = vec![0xc0, alias_offset as u8];
+        let address = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        push_record(&mut message, &alias, TYPE_AAAA, &address.octets());
+        // Records for unrelated names are ignored.
+        alias = vec![0x04, b'e', b'v', b'i', b'l', 0x00];
+        push_record(&mut message, &alias, TYPE_AAAA, &[0xff; 16]);
+
+        let addresses = parse_response(&message, "api.mullvad.net", TYPE_AAAA).unwrap();
+        assert_eq!(addresses, [IpAddr::V6(address)]);
+    }
+
+    #[test]
+    fn test_parse_invalid_responses() {
+        // NXDOMAIN
+        let message = response_header(0x8183, 0);
+        assert!(matches!(
+            parse_response(&message, "api.mullvad.net", TYPE_AAAA),
+            Err(Error::ResponseCode(3))
+        ));
+
+        // Not a response
+        let message = response_header(0x0100, 0);
+        assert!(matches!(
+            parse_response(&message, "api.mullvad.net", TYPE_AAAA),
+            Err(Error::UnexpectedResponse)
+        ));
+
+        // Response to a different question
+        let message = response_header(0x8180, 0);
+        assert!(matches!(
+            parse_response(&message, "mullvad.net", TYPE_AAAA),
+            Err(Error::UnexpectedResponse)
+        ));
+        assert!(matches!(
+            parse_response(&message, "api.mullvad.net", TYPE_A),
+            Err(Error::UnexpectedResponse)
+        ));
+
+        // Truncated message
+        let message = &RECORDED_A_RESPONSE[..RECORDED_A_RESPONSE.len() - 16];
+        assert!(matches!(
+            parse_response(message, "api.mullvad.net", TYPE_A),
+            Err(Error::Malformed)
+        ));
+
+        // A record with a bad length
+        let mut message = response_header(0x8180, 1);
+        push_record(&mut message, &[0xc0, 0x0c], TYPE_AAAA, &[0; 4]);
+        assert!(matches!(
+            parse_response(&message, "api.mullvad.net", TYPE_AAAA),
+            Err(Error::Malformed)
+        ));
+
+        // Compression pointer loop
+        let mut message = response_header(0x8180, 1);
+        let offset = message.len() as u8;
+        push_record(&mut message, &[0xc0, offset], TYPE_AAAA, &[0; 16]);
+        assert!(matches!(
+            parse_response(&message, "api.mullvad.net", TYPE_AAAA),
+            Err(Error::Malformed)
+        ));
+    }
+
+    /// Serves the recorded responses to DoH queries over HTTP/1.1.
+    async fn serve_recorded_responses(stream: tokio::io::DuplexStream) {
+        let mut stream = BufReader::new(stream);
+        loop {
+            let mut request_line = String::new();
+            if stream.read_line(&mut request_line).await.unwrap() == 0 {
+                return;
+            }
+            assert_eq!(request_line, "POST /dns-query HTTP/1.1\r\n");
+
+            let mut content_length = 0;
+            let mut content_type = None;
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_once(": ").unwrap();
+                match name.to_ascii_lowercase().as_str() {
+                    "content-length" => content_length = value.parse().unwrap(),
+                    "content-type" => content_type = Some(value.to_owned()),
+                    _ => (),
+                }
+            }
+            assert_eq!(content_type.as_deref(), Some(DNS_MESSAGE_CONTENT_TYPE));
+
+            let mut query = vec![0; content_length];
+            stream.read_exact(&mut query).await.unwrap();
+            let response = match &query[query.len() - 4..] {
+                [0x00, 0x01, 0x00, 0x01] => RECORDED_A_RESPONSE,
+                [0x00, 0x1c, 0x00, 0x01] => RECORDED_AAAA_RESPONSE,
+                _ => panic!("unexpected query: {query:?}"),
+            };
+
+            let header = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: {DNS_MESSAGE_CONTENT_TYPE}\r\n\
+                 content-length: {}\r\n\r\n",
+                response.len()
+            );
+            stream.write_all(header.as_bytes()).await.unwrap();
+            stream.write_all(response).await.unwrap();
+            stream.flush().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_recorded_response() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(serve_recorded_responses(server));
+
+        let addresses = resolve_over_stream(client, "dns.mullvad.net", "api.mullvad.net")
+            .await
+            .unwrap();
+        assert_eq!(addresses, [IpAddr::V4(Ipv4Addr::new(45, 83, 223, 196))]);
+    }
+}
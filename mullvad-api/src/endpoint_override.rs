@@ -0,0 +1,169 @@
+//! Replacement of the API host and address while the daemon is running, e.g. to switch between
+//! the production and a staging environment. The override is stored in the cache directory so
+//! that it is kept across restarts until it is cleared.
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "api-override")]
+use std::sync::RwLock;
+use std::{io, net::SocketAddr, path::Path};
+use talpid_types::ErrorExt;
+use tokio::{fs, io::AsyncWriteExt};
+
+const OVERRIDE_FILENAME: &str = "api-endpoint-override.json";
+
+/// Set by [`crate::Runtime::set_endpoint_override`], and takes precedence over [`crate::API`].
+#[cfg(feature = "api-override")]
+static ENDPOINT_OVERRIDE: RwLock<Option<ApiEndpointOverride>> = RwLock::new(None);
+
+/// A hostname and address which replace those of [`crate::API`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiEndpointOverride {
+    pub host: String,
+    pub addr: SocketAddr,
+}
+
+impl ApiEndpointOverride {
+    /// Reads the override from `OVERRIDE_FILENAME`. This returns `None` if there is no override
+    /// or if reading it fails.
+    pub async fn from_cache(cache_dir: &Path) -> Option<Self> {
+        let path = cache_dir.join(OVERRIDE_FILENAME);
+        match fs::read_to_string(path).await {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(endpoint_override) => Some(endpoint_override),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Failed to deserialize \"{OVERRIDE_FILENAME}\""
+                        ))
+                    );
+                    None
+                }
+            },
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read API endpoint override")
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    /// Stores the override to `OVERRIDE_FILENAME`.
+    pub async fn save(&self, cache_dir: &Path) -> io::Result<()> {
+        let mut file = mullvad_fs::AtomicFile::new(cache_dir.join(OVERRIDE_FILENAME)).await?;
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "serialization failed"))?;
+        file.write_all(json.as_bytes()).await?;
+        file.write_all(b"\n").await?;
+        file.finalize().await
+    }
+
+    /// Removes `OVERRIDE_FILENAME`, if it exists.
+    pub async fn delete_cache(cache_dir: &Path) -> io::Result<()> {
+        match fs::remove_file(cache_dir.join(OVERRIDE_FILENAME)).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the override currently in use, if any.
+pub(crate) fn current() -> Option<ApiEndpointOverride> {
+    #[cfg(feature = "api-override")]
+    {
+        ENDPOINT_OVERRIDE.read().unwrap().clone()
+    }
+    #[cfg(not(feature = "api-override"))]
+    None
+}
+
+#[cfg(feature = "api-override")]
+pub(crate) fn set(endpoint_override: Option<ApiEndpointOverride>) {
+    *ENDPOINT_OVERRIDE.write().unwrap() = endpoint_override;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_persistence() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            ApiEndpointOverride::from_cache(cache_dir.path()).await,
+            None
+        );
+
+        let endpoint_override = ApiEndpointOverride {
+            host: "api.staging.test".to_owned(),
+            addr: "10.0.0.1:443".parse().unwrap(),
+        };
+        endpoint_override.save(cache_dir.path()).await.unwrap();
+        assert_eq!(
+            ApiEndpointOverride::from_cache(cache_dir.path()).await,
+            Some(endpoint_override)
+        );
+
+        ApiEndpointOverride::delete_cache(cache_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(
+            ApiEndpointOverride::from_cache(cache_dir.path()).await,
+            None
+        );
+        // Clearing an override which does not exist is not an error.
+        ApiEndpointOverride::delete_cache(cache_dir.path())
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "api-override")]
+    #[tokio::test]
+    async fn test_swap_endpoint() {
+        use crate::{Runtime, API};
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let runtime = Runtime::with_cache(
+            cache_dir.path(),
+            false,
+            #[cfg(target_os = "android")]
+            None,
+        )
+        .await
+        .unwrap();
+        let default_host = API.host();
+        let default_address = runtime.address_cache.get_address().await;
+
+        let endpoint_override = ApiEndpointOverride {
+            host: "api.staging.test".to_owned(),
+            addr: "10.0.0.1:443".parse().unwrap(),
+        };
+        runtime
+            .set_endpoint_override(Some(endpoint_override.clone()))
+            .await
+            .unwrap();
+        assert_eq!(API.host(), endpoint_override.host);
+        assert_eq!(
+            runtime
+                .address_cache
+                .resolve_hostname(&endpoint_override.host)
+                .await,
+            Some(endpoint_override.addr)
+        );
+        assert_eq!(
+            ApiEndpointOverride::from_cache(cache_dir.path()).await,
+            Some(endpoint_override)
+        );
+
+        runtime.set_endpoint_override(None).await.unwrap();
+        assert_eq!(API.host(), default_host);
+        assert_eq!(runtime.address_cache.get_address().await, default_address);
+        assert_eq!(
+            ApiEndpointOverride::from_cache(cache_dir.path()).await,
+            None
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Client side of the HTTP `CONNECT` method, used to tunnel API traffic through an HTTP proxy.
+use mullvad_types::access_method::HttpProxyAuth;
+use std::{io, net::SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Upper bound on the size of the response header sent by the proxy.
+const MAX_RESPONSE_HEADER_SIZE: usize = 8 * 1024;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "The HTTP proxy requires authentication")]
+    AuthenticationRequired,
+
+    #[error(display = "The HTTP proxy refused to connect: {} {}", _0, _1)]
+    Refused(u16, String),
+
+    #[error(display = "Received an invalid response from the HTTP proxy")]
+    InvalidResponse,
+
+    #[error(display = "The response from the HTTP proxy is too large")]
+    ResponseTooLarge,
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match error {
+            Error::AuthenticationRequired => io::ErrorKind::PermissionDenied,
+            Error::Refused(..) => io::ErrorKind::ConnectionRefused,
+            Error::InvalidResponse | Error::ResponseTooLarge => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+/// Ask the HTTP proxy at the other end of `stream` to open a tunnel to `target`. On success, the
+/// stream can be used to talk to `target` directly.
+pub async fn connect<S>(
+    mut stream: S,
+    target: &SocketAddr,
+    authentication: Option<&HttpProxyAuth>,
+) -> io::Result<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some(HttpProxyAuth { username, password }) = authentication {
+        let credentials = base64::encode(format!("{username}:{password}"));
+        request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let header = read_response_header(&mut stream).await?;
+    let (status, reason) = parse_status_line(&header)?;
+    match status {
+        200..=299 => Ok(stream),
+        407 => Err(Error::AuthenticationRequired.into()),
+        _ => Err(Error::Refused(status, reason.to_owned()).into()),
+    }
+}
+
+/// Read the response header, up to and including the empty line that terminates it. This reads a
+/// single byte at a time so that nothing sent by the target after the header is consumed.
+async fn read_response_header<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<String> {
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_RESPONSE_HEADER_SIZE {
+            return Err(Error::ResponseTooLarge.into());
+        }
+        header.push(stream.read_u8().await?);
+    }
+    String::from_utf8(header).map_err(|_| Error::InvalidResponse.into())
+}
+
+/// Parse the status code and reason phrase from a status line such as
+/// `HTTP/1.1 407 Proxy Authentication Required`.
+fn parse_status_line(header: &str) -> Result<(u16, &str), Error> {
+    let status_line = header.split("\r\n").next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    match parts.next() {
+        Some(version) if version.starts_with("HTTP/1.") => (),
+        _ => return Err(Error::InvalidResponse),
+    }
+    let status = parts
+        .next()
+        .and_then(|status| status.parse().ok())
+        .ok_or(Error::InvalidResponse)?;
+    Ok((status, parts.next().unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    const TARGET: &str = "10.0.0.1:443";
+
+    /// Start a minimal HTTP proxy which accepts a single `CONNECT` request and then echoes
+    /// everything back. If `credentials` is set, it is the expected value of the
+    /// `Proxy-Authorization` header. If `status_line` is set, it is sent instead of accepting the
+    /// request.
+    async fn start_proxy(
+        credentials: Option<&'static str>,
+        status_line: Option<&'static str>,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_response_header(&mut stream).await.unwrap();
+            assert!(request.starts_with(&format!("CONNECT {TARGET} HTTP/1.1\r\n")));
+
+            let authorized = match credentials {
+                Some(credentials) => request.contains(&format!(
+                    "Proxy-Authorization: Basic {}\r\n",
+                    base64::encode(credentials)
+                )),
+                None => true,
+            };
+            let response = match status_line {
+                Some(status_line) => status_line,
+                None if !authorized => "HTTP/1.1 407 Proxy Authentication Required",
+                None => "HTTP/1.1 200 Connection established",
+            };
+            stream
+                .write_all(format!("{response}\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+
+            let mut buf = [0u8; 64];
+            while let Ok(n) = stream.read(&mut buf).await {
+                if n == 0 || stream.write_all(&buf[..n]).await.is_err() {
+                    break;
+                }
+            }
+        });
+        addr
+    }
+
+    async fn connect_via(
+        proxy: SocketAddr,
+        authentication: Option<HttpProxyAuth>,
+    ) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect(proxy).await.unwrap();
+        connect(stream, &TARGET.parse().unwrap(), authentication.as_ref()).await
+    }
+
+    fn auth(password: &str) -> HttpProxyAuth {
+        HttpProxyAuth {
+            username: "user".to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    fn inner_error(error: &io::Error) -> &Error {
+        error.get_ref().unwrap().downcast_ref::<Error>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_authentication() {
+        let proxy = start_proxy(Some("user:pass"), None).await;
+        let mut stream = connect_via(proxy, Some(auth("pass"))).await.unwrap();
+
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_connect_authentication_required() {
+        let proxy = start_proxy(Some("user:pass"), None).await;
+        let error = connect_via(proxy, Some(auth("wrong"))).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(matches!(inner_error(&error), Error::AuthenticationRequired));
+
+        let proxy = start_proxy(Some("user:pass"), None).await;
+        let error = connect_via(proxy, None).await.unwrap_err();
+        assert!(matches!(inner_error(&error), Error::AuthenticationRequired));
+    }
+
+    #[tokio::test]
+    async fn test_connect_refused() {
+        let proxy = start_proxy(None, Some("HTTP/1.1 502 Bad Gateway")).await;
+        let error = connect_via(proxy, None).await.unwrap_err();
+        assert!(
+            matches!(inner_error(&error), Error::Refused(502, reason) if reason == "Bad Gateway")
+        );
+
+        let proxy = start_proxy(None, Some("SSH-2.0-OpenSSH")).await;
+        let error = connect_via(proxy, None).await.unwrap_err();
+        assert!(matches!(inner_error(&error), Error::InvalidResponse));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
     abortable_stream::{AbortableStream, AbortableStreamHandle},
+    doh, http_proxy,
     proxy::{ApiConnection, ApiConnectionMode, ProxyConfig},
     tls_stream::TlsStream,
     AddressCache,
@@ -13,6 +14,7 @@ use hyper::{
     service::Service,
     Uri,
 };
+use mullvad_types::access_method;
 use shadowsocks::{
     config::ServerType,
     context::{Context as SsContext, SharedContext},
@@ -31,7 +33,7 @@ use std::{
     str::{self, FromStr},
     sync::{Arc, Mutex},
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use talpid_types::ErrorExt;
 
@@ -45,6 +47,15 @@ use tokio::{
 use crate::{proxy::ConnectionDecorator, API};
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Minimum time between attempts to look up the API address when the cached address is
+/// unreachable.
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Makes the firewall allow traffic to an address. This is the [`crate::ApiEndpointUpdateCallback`]
+/// of the request service, which is used while looking up the API when the cached address is
+/// unreachable.
+pub(crate) type AllowEndpoint =
+    Arc<dyn Fn(SocketAddr) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
 
 #[derive(Clone)]
 pub struct HttpsConnectorWithSniHandle {
@@ -63,127 +74,56 @@ impl HttpsConnectorWithSniHandle {
             .tx
             .unbounded_send(HttpsConnectorRequest::SetConnectionMode(proxy));
     }
+
+    /// Connect directly from `addresses` instead of using the proxy settings, or stop doing so if
+    /// `None`. See [`crate::rest::RequestServiceHandle::set_tunnel_addresses`].
+    pub fn set_tunnel_addresses(&self, addresses: Option<Vec<IpAddr>>) {
+        let _ = self
+            .tx
+            .unbounded_send(HttpsConnectorRequest::SetTunnelAddresses(addresses));
+    }
 }
 
 enum HttpsConnectorRequest {
     Reset,
     SetConnectionMode(ApiConnectionMode),
+    SetTunnelAddresses(Option<Vec<IpAddr>>),
 }
 
 #[derive(Clone)]
-enum InnerConnectionMode {
+pub(crate) enum InnerConnectionMode {
     /// Connect directly to the target.
     Direct,
     /// Connect to the destination via a Shadowsocks proxy.
     Shadowsocks(ShadowsocksConfig),
     /// Connect to the destination via a Socks proxy.
     Socks5(SocksConfig),
+    /// Connect to the destination via an HTTP proxy.
+    HttpProxy(access_method::HttpProxy),
 }
 
+/// A stream which can send to and receive data from some server using any proxy protocol.
+pub(crate) trait ProxyStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ProxyStream for T {}
+
 impl InnerConnectionMode {
     async fn connect(
         self,
         hostname: &str,
         addr: &SocketAddr,
+        bind_address: Option<IpAddr>,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> Result<ApiConnection, std::io::Error> {
-        match self {
-            // Set up a TCP-socket connection.
-            InnerConnectionMode::Direct => {
-                let first_hop = *addr;
-                let make_proxy_stream = |tcp_stream| async { Ok(tcp_stream) };
-                Self::connect_proxied(
-                    first_hop,
-                    hostname,
-                    make_proxy_stream,
-                    #[cfg(target_os = "android")]
-                    socket_bypass_tx,
-                )
-                .await
-            }
-            // Set up a Shadowsocks-connection.
-            InnerConnectionMode::Shadowsocks(shadowsocks) => {
-                let first_hop = shadowsocks.params.peer;
-                let make_proxy_stream = |tcp_stream| async {
-                    Ok(ProxyClientStream::from_stream(
-                        shadowsocks.proxy_context,
-                        tcp_stream,
-                        &ServerConfig::from(shadowsocks.params),
-                        *addr,
-                    ))
-                };
-                Self::connect_proxied(
-                    first_hop,
-                    hostname,
-                    make_proxy_stream,
-                    #[cfg(target_os = "android")]
-                    socket_bypass_tx,
-                )
-                .await
-            }
-            // Set up a SOCKS5-connection.
-            InnerConnectionMode::Socks5(socks) => {
-                let first_hop = socks.peer;
-                let make_proxy_stream = |tcp_stream| async {
-                    match socks.authentication {
-                        SocksAuth::None => {
-                            tokio_socks::tcp::Socks5Stream::connect_with_socket(tcp_stream, addr)
-                                .await
-                        }
-                        SocksAuth::Password { username, password } => {
-                            tokio_socks::tcp::Socks5Stream::connect_with_password_and_socket(
-                                tcp_stream, addr, &username, &password,
-                            )
-                            .await
-                        }
-                    }
-                    .map_err(|error| {
-                        io::Error::new(io::ErrorKind::Other, format!("SOCKS error: {error}"))
-                    })
-                };
-                Self::connect_proxied(
-                    first_hop,
-                    hostname,
-                    make_proxy_stream,
-                    #[cfg(target_os = "android")]
-                    socket_bypass_tx,
-                )
-                .await
-            }
-        }
-    }
-
-    /// Create an [`ApiConnection`] from a [`TcpStream`].
-    ///
-    /// The `make_proxy_stream` closure receives a [`TcpStream`] and produces a
-    /// stream which can send to and receive data from some server using any
-    /// proxy protocol. The only restriction is that this stream must implement
-    /// [`tokio::io::AsyncRead`] and [`tokio::io::AsyncWrite`], as well as
-    /// [`Unpin`] and [`Send`].
-    ///
-    /// If a direct connection is to be established (i.e. the stream will not be
-    /// using any proxy protocol) `make_proxy_stream` may return the
-    /// [`TcpStream`] itself. See for example how a connection is established
-    /// from connection mode [`InnerConnectionMode::Direct`].
-    async fn connect_proxied<ProxyFactory, ProxyFuture, Proxy>(
-        first_hop: SocketAddr,
-        hostname: &str,
-        make_proxy_stream: ProxyFactory,
-        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
-    ) -> Result<ApiConnection, io::Error>
-    where
-        ProxyFactory: FnOnce(TcpStream) -> ProxyFuture,
-        ProxyFuture: Future<Output = io::Result<Proxy>>,
-        Proxy: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-    {
         let socket = HttpsConnectorWithSni::open_socket(
-            first_hop,
+            self.first_hop(addr),
+            bind_address,
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         )
         .await?;
 
-        let proxy = make_proxy_stream(socket).await?;
+        let proxy = self.proxy_stream(socket, addr).await?;
 
         #[cfg(feature = "api-override")]
         if API.disable_tls {
@@ -193,10 +133,67 @@ impl InnerConnectionMode {
         let tls_stream = TlsStream::connect_https(proxy, hostname).await?;
         Ok(ApiConnection::new(Box::new(tls_stream)))
     }
+
+    /// Returns the address to open a TCP connection to in order to reach `addr`. This is the
+    /// proxy, if one is used.
+    pub(crate) fn first_hop(&self, addr: &SocketAddr) -> SocketAddr {
+        match self {
+            InnerConnectionMode::Direct => *addr,
+            InnerConnectionMode::Shadowsocks(shadowsocks) => shadowsocks.params.peer,
+            InnerConnectionMode::Socks5(socks) => socks.peer,
+            InnerConnectionMode::HttpProxy(http) => http.peer,
+        }
+    }
+
+    /// Set up the proxy protocol on top of a TCP connection to [`Self::first_hop`]. The
+    /// returned stream can be used to talk to `addr`. If no proxy is used, this is the
+    /// [`TcpStream`] itself.
+    pub(crate) async fn proxy_stream(
+        self,
+        tcp_stream: TcpStream,
+        addr: &SocketAddr,
+    ) -> io::Result<Box<dyn ProxyStream>> {
+        match self {
+            InnerConnectionMode::Direct => Ok(Box::new(tcp_stream)),
+            // Set up a Shadowsocks-connection.
+            InnerConnectionMode::Shadowsocks(shadowsocks) => {
+                Ok(Box::new(ProxyClientStream::from_stream(
+                    shadowsocks.proxy_context,
+                    tcp_stream,
+                    &ServerConfig::from(shadowsocks.params),
+                    *addr,
+                )))
+            }
+            // Set up a SOCKS5-connection.
+            InnerConnectionMode::Socks5(socks) => {
+                let stream = match socks.authentication {
+                    SocksAuth::None => {
+                        tokio_socks::tcp::Socks5Stream::connect_with_socket(tcp_stream, addr).await
+                    }
+                    SocksAuth::Password { username, password } => {
+                        tokio_socks::tcp::Socks5Stream::connect_with_password_and_socket(
+                            tcp_stream, addr, &username, &password,
+                        )
+                        .await
+                    }
+                }
+                .map_err(|error| {
+                    io::Error::new(io::ErrorKind::Other, format!("SOCKS error: {error}"))
+                })?;
+                Ok(Box::new(stream))
+            }
+            // Set up a tunnel through an HTTP proxy.
+            InnerConnectionMode::HttpProxy(http) => {
+                let stream =
+                    http_proxy::connect(tcp_stream, addr, http.authentication.as_ref()).await?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
-struct ShadowsocksConfig {
+pub(crate) struct ShadowsocksConfig {
     proxy_context: SharedContext,
     params: ParsedShadowsocksConfig,
 }
@@ -215,7 +212,7 @@ impl From<ParsedShadowsocksConfig> for ServerConfig {
 }
 
 #[derive(Clone)]
-struct SocksConfig {
+pub(crate) struct SocksConfig {
     peer: SocketAddr,
     authentication: SocksAuth,
 }
@@ -227,7 +224,7 @@ pub enum SocksAuth {
 }
 
 #[derive(err_derive::Error, Debug)]
-enum ProxyConfigError {
+pub(crate) enum ProxyConfigError {
     #[error(display = "Unrecognized cipher selected: {}", _0)]
     InvalidCipher(String),
 }
@@ -236,7 +233,6 @@ impl TryFrom<ApiConnectionMode> for InnerConnectionMode {
     type Error = ProxyConfigError;
 
     fn try_from(config: ApiConnectionMode) -> Result<Self, Self::Error> {
-        use mullvad_types::access_method;
         use std::net::Ipv4Addr;
         Ok(match config {
             ApiConnectionMode::Direct => InnerConnectionMode::Direct,
@@ -272,6 +268,7 @@ impl TryFrom<ApiConnectionMode> for InnerConnectionMode {
                         })
                     }
                 },
+                ProxyConfig::HttpProxy(config) => InnerConnectionMode::HttpProxy(config),
             },
         })
     }
@@ -283,7 +280,10 @@ pub struct HttpsConnectorWithSni {
     inner: Arc<Mutex<HttpsConnectorWithSniInner>>,
     sni_hostname: Option<String>,
     address_cache: AddressCache,
+    allow_endpoint: AllowEndpoint,
     abort_notify: Arc<tokio::sync::Notify>,
+    /// Time of the last attempt to look up the API address.
+    last_bootstrap: Arc<Mutex<Option<Instant>>>,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -291,6 +291,8 @@ pub struct HttpsConnectorWithSni {
 struct HttpsConnectorWithSniInner {
     stream_handles: Vec<AbortableStreamHandle>,
     proxy_config: InnerConnectionMode,
+    /// Tunnel addresses to connect directly from, if API traffic is kept inside the tunnel.
+    tunnel_addresses: Option<Vec<IpAddr>>,
 }
 
 #[cfg(target_os = "android")]
@@ -300,6 +302,7 @@ impl HttpsConnectorWithSni {
     pub fn new(
         sni_hostname: Option<String>,
         address_cache: AddressCache,
+        allow_endpoint: AllowEndpoint,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> (Self, HttpsConnectorWithSniHandle) {
         let (tx, mut rx) = mpsc::unbounded();
@@ -307,6 +310,7 @@ impl HttpsConnectorWithSni {
         let inner = Arc::new(Mutex::new(HttpsConnectorWithSniInner {
             stream_handles: vec![],
             proxy_config: InnerConnectionMode::Direct,
+            tunnel_addresses: None,
         }));
 
         let inner_copy = inner.clone();
@@ -317,19 +321,34 @@ impl HttpsConnectorWithSni {
                 let handles = {
                     let mut inner = inner_copy.lock().unwrap();
 
-                    if let HttpsConnectorRequest::SetConnectionMode(config) = request {
-                        match InnerConnectionMode::try_from(config) {
-                            Ok(config) => {
-                                inner.proxy_config = config;
+                    match request {
+                        HttpsConnectorRequest::Reset => (),
+                        HttpsConnectorRequest::SetConnectionMode(config) => {
+                            match InnerConnectionMode::try_from(config) {
+                                Ok(config) => {
+                                    inner.proxy_config = config;
+                                }
+                                Err(error) => {
+                                    log::error!(
+                                        "{}",
+                                        error.display_chain_with_msg(
+                                            "Failed to parse new API proxy config"
+                                        )
+                                    );
+                                }
                             }
-                            Err(error) => {
-                                log::error!(
-                                    "{}",
-                                    error.display_chain_with_msg(
-                                        "Failed to parse new API proxy config"
-                                    )
-                                );
+                        }
+                        HttpsConnectorRequest::SetTunnelAddresses(addresses) => {
+                            if inner.tunnel_addresses == addresses {
+                                continue;
                             }
+                            match &addresses {
+                                Some(_) => log::debug!("Sending API traffic inside the tunnel"),
+                                None => {
+                                    log::debug!("No longer forcing API traffic into the tunnel")
+                                }
+                            }
+                            inner.tunnel_addresses = addresses;
                         }
                     }
 
@@ -347,7 +366,9 @@ impl HttpsConnectorWithSni {
                 inner,
                 sni_hostname,
                 address_cache,
+                allow_endpoint,
                 abort_notify,
+                last_bootstrap: Arc::new(Mutex::new(None)),
                 #[cfg(target_os = "android")]
                 socket_bypass_tx,
             },
@@ -355,17 +376,22 @@ impl HttpsConnectorWithSni {
         )
     }
 
-    /// Establishes a TCP connection with a peer at the specified socket address.
+    /// Establishes a TCP connection with a peer at the specified socket address. If
+    /// `bind_address` is set, the connection is made from that address.
     ///
     /// Will timeout after [`CONNECT_TIMEOUT`] seconds.
-    async fn open_socket(
+    pub(crate) async fn open_socket(
         addr: SocketAddr,
+        bind_address: Option<IpAddr>,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> std::io::Result<TcpStream> {
         let socket = match addr {
             SocketAddr::V4(_) => TcpSocket::new_v4()?,
             SocketAddr::V6(_) => TcpSocket::new_v6()?,
         };
+        if let Some(bind_address) = bind_address {
+            socket.bind(SocketAddr::new(bind_address, 0))?;
+        }
 
         #[cfg(target_os = "android")]
         if let Some(mut tx) = socket_bypass_tx {
@@ -403,17 +429,190 @@ impl HttpsConnectorWithSni {
 
         // Use getaddrinfo as a fallback
         //
-        let mut addrs = GaiResolver::new()
+        let addr = Self::resolve_with_system(hostname)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Empty DNS response"))?;
+        Ok(SocketAddr::new(addr.ip(), port.unwrap_or(DEFAULT_PORT)))
+    }
+
+    async fn resolve_with_system(hostname: &str) -> io::Result<impl Iterator<Item = SocketAddr>> {
+        GaiResolver::new()
             .call(
                 Name::from_str(hostname)
                     .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?,
             )
             .await
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
-        let addr = addrs
-            .next()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Empty DNS response"))?;
-        Ok(SocketAddr::new(addr.ip(), port.unwrap_or(DEFAULT_PORT)))
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    }
+
+    /// Connects to `addr`. This starts over if a new endpoint is selected while connecting.
+    async fn connect_with_proxy_config(
+        inner: &Mutex<HttpsConnectorWithSniInner>,
+        abort_notify: &tokio::sync::Notify,
+        hostname: &str,
+        addr: &SocketAddr,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> io::Result<ApiConnection> {
+        loop {
+            let notify = abort_notify.notified();
+            let (proxy_config, tunnel_addresses) = {
+                let inner = inner.lock().unwrap();
+                (inner.proxy_config.clone(), inner.tunnel_addresses.clone())
+            };
+            // Inside the tunnel, the API is connected to directly from the tunnel address of the
+            // same family as `addr`. Sockets are not bypassed, since that would move them out of
+            // the tunnel.
+            let (proxy_config, bind_address) = match &tunnel_addresses {
+                Some(addresses) => (
+                    InnerConnectionMode::Direct,
+                    addresses
+                        .iter()
+                        .find(|address| address.is_ipv4() == addr.is_ipv4())
+                        .copied(),
+                ),
+                None => (proxy_config, None),
+            };
+            let stream_fut = proxy_config.connect(
+                hostname,
+                addr,
+                bind_address,
+                #[cfg(target_os = "android")]
+                socket_bypass_tx
+                    .clone()
+                    .filter(|_| tunnel_addresses.is_none()),
+            );
+
+            pin_mut!(stream_fut);
+            pin_mut!(notify);
+
+            // Wait for connection. Abort and retry if we switched to a different server.
+            if let future::Either::Left((stream, _)) = future::select(stream_fut, notify).await {
+                return stream;
+            }
+        }
+    }
+
+    /// Looks up new addresses for the API after the cached address `failed_addr` turned out to
+    /// be unreachable, first using the system resolver and then using DNS-over-HTTPS. Addresses
+    /// are only used, and added to the address cache, once a TLS connection has been established
+    /// to them. This means that they have presented a valid certificate for `hostname`, issued
+    /// under the pinned root certificate.
+    ///
+    /// The firewall is made to allow traffic to each DoH server and candidate using
+    /// `allow_endpoint` before it is connected to. If no address is found, `failed_addr` is
+    /// allowed again.
+    ///
+    /// This is only done for direct connections, and at most once every [`BOOTSTRAP_INTERVAL`].
+    async fn bootstrap_api_address(
+        inner: &Mutex<HttpsConnectorWithSniInner>,
+        address_cache: &AddressCache,
+        allow_endpoint: &AllowEndpoint,
+        last_bootstrap: &Mutex<Option<Instant>>,
+        hostname: &str,
+        failed_addr: SocketAddr,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> Option<ApiConnection> {
+        #[cfg(feature = "api-override")]
+        if API.disable_tls {
+            return None;
+        }
+        let is_direct = {
+            let inner = inner.lock().unwrap();
+            matches!(inner.proxy_config, InnerConnectionMode::Direct)
+                && inner.tunnel_addresses.is_none()
+        };
+        if !is_direct || address_cache.has_address_override().await {
+            return None;
+        }
+        {
+            let mut last_bootstrap = last_bootstrap.lock().unwrap();
+            if last_bootstrap.is_some_and(|last| last.elapsed() < BOOTSTRAP_INTERVAL) {
+                return None;
+            }
+            *last_bootstrap = Some(Instant::now());
+        }
+
+        match Self::find_api_address(
+            allow_endpoint,
+            hostname,
+            failed_addr,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        )
+        .await
+        {
+            Some((address, stream)) => {
+                address_cache.set_bootstrap_address(address).await;
+                Some(stream)
+            }
+            None => {
+                allow_endpoint(failed_addr).await;
+                None
+            }
+        }
+    }
+
+    /// Returns the first reachable address of `hostname` other than `failed_addr`, and a
+    /// connection to it.
+    async fn find_api_address(
+        allow_endpoint: &AllowEndpoint,
+        hostname: &str,
+        failed_addr: SocketAddr,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> Option<(SocketAddr, ApiConnection)> {
+        let mut candidates: Vec<IpAddr> = match Self::resolve_with_system(hostname).await {
+            Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+            Err(error) => {
+                log::debug!("Failed to resolve {hostname} using the system resolver: {error}");
+                vec![]
+            }
+        };
+        candidates.retain(|ip| *ip != failed_addr.ip());
+
+        if candidates.is_empty() {
+            log::info!("Looking up {hostname} using DNS-over-HTTPS");
+            candidates = match doh::resolve(
+                hostname,
+                allow_endpoint,
+                #[cfg(target_os = "android")]
+                socket_bypass_tx.clone(),
+            )
+            .await
+            {
+                Ok(addrs) => addrs,
+                Err(error) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to look up the API using DoH")
+                    );
+                    return None;
+                }
+            };
+            candidates.retain(|ip| *ip != failed_addr.ip());
+        }
+
+        for ip in candidates {
+            let candidate = SocketAddr::new(ip, failed_addr.port());
+            allow_endpoint(candidate).await;
+            match InnerConnectionMode::Direct
+                .connect(
+                    hostname,
+                    &candidate,
+                    None,
+                    #[cfg(target_os = "android")]
+                    socket_bypass_tx.clone(),
+                )
+                .await
+            {
+                Ok(stream) => {
+                    log::info!("Found reachable API address {candidate}");
+                    return Some((candidate, stream));
+                }
+                Err(error) => log::debug!("Failed to connect to {candidate}: {error}"),
+            }
+        }
+        None
     }
 }
 
@@ -448,6 +647,8 @@ impl Service<Uri> for HttpsConnectorWithSni {
         #[cfg(target_os = "android")]
         let socket_bypass_tx = self.socket_bypass_tx.clone();
         let address_cache = self.address_cache.clone();
+        let allow_endpoint = self.allow_endpoint.clone();
+        let last_bootstrap = self.last_bootstrap.clone();
 
         let fut = async move {
             if uri.scheme() != Some(&Scheme::HTTPS) {
@@ -458,27 +659,37 @@ impl Service<Uri> for HttpsConnectorWithSni {
             }
 
             let hostname = sni_hostname?;
-            let addr = Self::resolve_address(address_cache, uri).await?;
-
-            // Loop until we have established a connection. This starts over if a new endpoint
-            // is selected while connecting.
-            let stream = loop {
-                let notify = abort_notify.notified();
-                let proxy_config = { inner.lock().unwrap().proxy_config.clone() };
-                let stream_fut = proxy_config.connect(
-                    &hostname,
-                    &addr,
-                    #[cfg(target_os = "android")]
-                    socket_bypass_tx.clone(),
-                );
+            let addr = Self::resolve_address(address_cache.clone(), uri).await?;
 
-                pin_mut!(stream_fut);
-                pin_mut!(notify);
-
-                // Wait for connection. Abort and retry if we switched to a different server.
-                if let future::Either::Left((stream, _)) = future::select(stream_fut, notify).await
-                {
-                    break stream?;
+            let stream = match Self::connect_with_proxy_config(
+                &inner,
+                &abort_notify,
+                &hostname,
+                &addr,
+                #[cfg(target_os = "android")]
+                socket_bypass_tx.clone(),
+            )
+            .await
+            {
+                Ok(stream) => stream,
+                Err(error) => {
+                    // Only cached API addresses are replaced
+                    let cached_addr = address_cache.resolve_hostname(&hostname).await;
+                    if cached_addr.map(|cached| cached.ip()) != Some(addr.ip()) {
+                        return Err(error);
+                    }
+                    Self::bootstrap_api_address(
+                        &inner,
+                        &address_cache,
+                        &allow_endpoint,
+                        &last_bootstrap,
+                        &hostname,
+                        addr,
+                        #[cfg(target_os = "android")]
+                        socket_bypass_tx,
+                    )
+                    .await
+                    .ok_or(error)?
                 }
             };
 
@@ -495,3 +706,108 @@ impl Service<Uri> for HttpsConnectorWithSni {
         Box::pin(fut)
     }
 }
+
+// Only Linux routes all of 127.0.0.0/8 to the loopback interface.
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    /// Connections inside the tunnel are made from the tunnel address.
+    #[tokio::test]
+    async fn test_open_socket_from_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bind_address = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+
+        let _stream =
+            HttpsConnectorWithSni::open_socket(listener.local_addr().unwrap(), Some(bind_address))
+                .await
+                .unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer.ip(), bind_address);
+    }
+
+    /// While the tunnel is up, the API is connected to directly from the tunnel address instead of
+    /// through the proxy. Once the tunnel is down, the proxy is used again.
+    #[tokio::test]
+    async fn test_tunnel_addresses_override_proxy() {
+        let api = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_addr = api.local_addr().unwrap();
+        let (allow_endpoint, _) = doh::test::recording_allow_endpoint();
+        let (connector, handle) =
+            HttpsConnectorWithSni::new(None, AddressCache::new(None).unwrap(), allow_endpoint);
+
+        let apply = |request: HttpsConnectorRequest| {
+            let applied = connector.abort_notify.notified();
+            handle.tx.unbounded_send(request).unwrap();
+            applied
+        };
+        apply(HttpsConnectorRequest::SetConnectionMode(
+            ApiConnectionMode::Proxied(ProxyConfig::Socks(access_method::Socks5::Remote(
+                access_method::Socks5Remote::new(proxy.local_addr().unwrap()),
+            ))),
+        ))
+        .await;
+
+        let tunnel_address = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+        let steps = [
+            // Connected
+            (Some(vec![tunnel_address]), &api, tunnel_address),
+            // Disconnected
+            (None, &proxy, IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        ];
+        for (tunnel_addresses, expected_listener, expected_source) in steps {
+            apply(HttpsConnectorRequest::SetTunnelAddresses(tunnel_addresses)).await;
+
+            let connect = HttpsConnectorWithSni::connect_with_proxy_config(
+                &connector.inner,
+                &connector.abort_notify,
+                "api.test",
+                &api_addr,
+            );
+            let accept = async {
+                // Closing the connection makes the connector give up on the handshake.
+                let (_stream, peer) = expected_listener.accept().await.unwrap();
+                peer
+            };
+            let (result, peer) = tokio::join!(connect, accept);
+            assert!(result.is_err());
+            assert_eq!(peer.ip(), expected_source);
+        }
+    }
+
+    /// Each candidate is allowed in the firewall before it is connected to. If none of them can
+    /// be reached, the cached address is allowed again.
+    #[tokio::test]
+    async fn test_bootstrap_allows_candidates() {
+        // `localhost` is resolved by the system resolver, and nothing listens on the port.
+        let port = doh::test::closed_address().port();
+        let candidate = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+        let failed_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)), port);
+        let address_cache = AddressCache::new(None).unwrap();
+        let cached_addr = address_cache.get_address().await;
+        let (allow_endpoint, allowed) = doh::test::recording_allow_endpoint();
+        let inner = Mutex::new(HttpsConnectorWithSniInner {
+            stream_handles: vec![],
+            proxy_config: InnerConnectionMode::Direct,
+            tunnel_addresses: None,
+        });
+
+        let stream = HttpsConnectorWithSni::bootstrap_api_address(
+            &inner,
+            &address_cache,
+            &allow_endpoint,
+            &Mutex::new(None),
+            "localhost",
+            failed_addr,
+        )
+        .await;
+
+        assert!(stream.is_none());
+        assert_eq!(address_cache.get_address().await, cached_addr);
+        let allowed = allowed.lock().unwrap();
+        assert!(allowed.contains(&candidate), "{allowed:?}");
+        assert_eq!(allowed.last(), Some(&failed_addr));
+    }
+}
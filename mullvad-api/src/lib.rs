@@ -6,10 +6,11 @@ use futures::channel::mpsc;
 use futures::Stream;
 use hyper::Method;
 use mullvad_types::{
+    access_method::AccessMethodTestReport,
     account::{AccountToken, VoucherSubmission},
     version::AppVersion,
 };
-use proxy::ApiConnectionMode;
+use proxy::{ApiConnectionMode, ConnectionModeOutcome};
 use std::sync::OnceLock;
 use std::{
     cell::Cell,
@@ -24,8 +25,13 @@ use talpid_types::ErrorExt;
 pub mod availability;
 use availability::{ApiAvailability, ApiAvailabilityHandle};
 pub mod rest;
+pub mod retry;
 
 mod abortable_stream;
+mod connection_test;
+mod doh;
+mod endpoint_override;
+mod http_proxy;
 mod https_client_with_sni;
 pub mod proxy;
 mod tls_stream;
@@ -35,9 +41,11 @@ pub use crate::https_client_with_sni::SocketBypassRequest;
 mod access;
 mod address_cache;
 pub mod device;
+pub mod nat64;
 mod relay_list;
 pub use address_cache::AddressCache;
 pub use device::DevicesProxy;
+pub use endpoint_override::ApiEndpointOverride;
 pub use hyper::StatusCode;
 pub use relay_list::RelayListProxy;
 
@@ -113,6 +121,14 @@ pub struct ApiEndpoint {
 }
 
 impl ApiEndpoint {
+    /// Returns the hostname of the API. If the endpoint has been overridden using
+    /// [`Runtime::set_endpoint_override`], this is the host of the override.
+    pub fn host(&self) -> String {
+        endpoint_override::current()
+            .map(|endpoint_override| endpoint_override.host)
+            .unwrap_or_else(|| self.host.clone())
+    }
+
     /// Returns the endpoint to connect to the API over.
     ///
     /// # Panics
@@ -199,6 +215,10 @@ pub struct Runtime {
     handle: tokio::runtime::Handle,
     pub address_cache: AddressCache,
     api_availability: availability::ApiAvailability,
+    /// Where the API endpoint override is stored, if the runtime was created using a cache
+    /// directory.
+    #[cfg(feature = "api-override")]
+    cache_dir: Option<Box<Path>>,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -217,11 +237,13 @@ pub enum Error {
 
 /// Closure that receives the next API (real or proxy) endpoint to use for `api.mullvad.net`.
 /// It should return a future that determines whether to reject the new endpoint or not.
+/// It is also called with the DoH servers and the addresses that are tried while looking up the
+/// API, when the cached address is unreachable.
 pub trait ApiEndpointUpdateCallback: Fn(SocketAddr) -> Self::AcceptedNewEndpoint {
-    type AcceptedNewEndpoint: Future<Output = bool> + Send;
+    type AcceptedNewEndpoint: Future<Output = bool> + Send + 'static;
 }
 
-impl<U, T: Future<Output = bool> + Send> ApiEndpointUpdateCallback for U
+impl<U, T: Future<Output = bool> + Send + 'static> ApiEndpointUpdateCallback for U
 where
     U: Fn(SocketAddr) -> T,
 {
@@ -246,6 +268,8 @@ impl Runtime {
             handle,
             address_cache: AddressCache::new(None)?,
             api_availability: ApiAvailability::new(availability::State::default()),
+            #[cfg(feature = "api-override")]
+            cache_dir: None,
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -253,10 +277,38 @@ impl Runtime {
 
     /// Create a new `Runtime` using the specified directories.
     /// Try to use the cache directory first, and fall back on the bundled address otherwise.
+    ///
+    /// In builds with the `api-override` feature, an API endpoint override stored in the cache
+    /// directory is applied.
     pub async fn with_cache(
         cache_dir: &Path,
         write_changes: bool,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> Result<Self, Error> {
+        #[cfg_attr(not(feature = "api-override"), allow(unused_mut))]
+        let mut runtime = Self::with_cache_inner(
+            cache_dir,
+            write_changes,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        )
+        .await?;
+        #[cfg(feature = "api-override")]
+        {
+            runtime.cache_dir = Some(Box::from(cache_dir));
+            if let Some(endpoint_override) = ApiEndpointOverride::from_cache(cache_dir).await {
+                runtime
+                    .apply_endpoint_override(Some(endpoint_override))
+                    .await;
+            }
+        }
+        Ok(runtime)
+    }
+
+    async fn with_cache_inner(
+        cache_dir: &Path,
+        write_changes: bool,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> Result<Self, Error> {
         let handle = tokio::runtime::Handle::current();
         #[cfg(feature = "api-override")]
@@ -294,6 +346,8 @@ impl Runtime {
             handle,
             address_cache,
             api_availability: ApiAvailability::new(availability::State::default()),
+            #[cfg(feature = "api-override")]
+            cache_dir: None,
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -305,6 +359,7 @@ impl Runtime {
         sni_hostname: Option<String>,
         proxy_provider: T,
         new_address_callback: impl ApiEndpointUpdateCallback + Send + Sync + 'static,
+        outcome_callback: impl Fn(ConnectionModeOutcome) + Send + Sync + 'static,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> rest::RequestServiceHandle {
         rest::RequestService::spawn(
@@ -313,30 +368,37 @@ impl Runtime {
             self.address_cache.clone(),
             proxy_provider,
             new_address_callback,
+            outcome_callback,
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         )
         .await
     }
 
-    /// Returns a request factory initialized to create requests for the master API
+    /// Returns a request factory initialized to create requests for the master API.
+    ///
+    /// `outcome_callback` is told whether each request reached the API using the connection mode
+    /// most recently returned by `proxy_provider`.
     pub async fn mullvad_rest_handle<
         T: Stream<Item = ApiConnectionMode> + Unpin + Send + 'static,
     >(
         &self,
         proxy_provider: T,
         new_address_callback: impl ApiEndpointUpdateCallback + Send + Sync + 'static,
+        outcome_callback: impl Fn(ConnectionModeOutcome) + Send + Sync + 'static,
     ) -> rest::MullvadRestHandle {
+        // The SNI hostname is taken from the request URI, which follows the current API host.
         let service = self
             .new_request_service(
-                Some(API.host.clone()),
+                None,
                 proxy_provider,
                 new_address_callback,
+                outcome_callback,
                 #[cfg(target_os = "android")]
                 self.socket_bypass_tx.clone(),
             )
             .await;
-        let factory = rest::RequestFactory::new(API.host.clone(), None);
+        let factory = rest::RequestFactory::for_api(None);
 
         rest::MullvadRestHandle::new(
             service,
@@ -352,6 +414,7 @@ impl Runtime {
             None,
             ApiConnectionMode::Direct.into_repeat(),
             |_| async { true },
+            |_| (),
             #[cfg(target_os = "android")]
             None,
         )
@@ -365,6 +428,72 @@ impl Runtime {
     pub fn availability_handle(&self) -> ApiAvailabilityHandle {
         self.api_availability.handle()
     }
+
+    /// Replace the host and address of [`API`] with `endpoint_override`, or go back to using
+    /// them if it is `None`. The override is stored in the cache directory, so that it is applied
+    /// again by [`Self::with_cache`] until it is cleared. Existing connections are not closed, so
+    /// the caller should make the request service select a new connection mode.
+    #[cfg(feature = "api-override")]
+    pub async fn set_endpoint_override(
+        &self,
+        endpoint_override: Option<ApiEndpointOverride>,
+    ) -> std::io::Result<()> {
+        if let Some(cache_dir) = &self.cache_dir {
+            match &endpoint_override {
+                Some(endpoint_override) => endpoint_override.save(cache_dir).await?,
+                None => ApiEndpointOverride::delete_cache(cache_dir).await?,
+            }
+        }
+        self.apply_endpoint_override(endpoint_override).await;
+        // Addresses that were fetched from the previous endpoint may not lead to the new one
+        if let Err(error) = self.address_cache.clear().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to clear the API address cache")
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "api-override")]
+    async fn apply_endpoint_override(&self, endpoint_override: Option<ApiEndpointOverride>) {
+        match &endpoint_override {
+            Some(endpoint_override) => log::info!(
+                "Overriding API endpoint. Using {} at {}",
+                endpoint_override.host,
+                endpoint_override.addr
+            ),
+            None => log::info!("Removed API endpoint override"),
+        }
+        let address = endpoint_override
+            .as_ref()
+            .map(|endpoint_override| endpoint_override.addr);
+        endpoint_override::set(endpoint_override);
+        self.address_cache.set_address_override(address).await;
+    }
+
+    /// Returns a future which tests whether the API can be reached using `connection_mode`, and
+    /// how long each stage of reaching it takes.
+    pub fn test_connection_mode(
+        &self,
+        connection_mode: ApiConnectionMode,
+    ) -> impl Future<Output = AccessMethodTestReport> + Send + 'static {
+        let address_cache = self.address_cache.clone();
+        #[cfg(target_os = "android")]
+        let socket_bypass_tx = self.socket_bypass_tx.clone();
+        async move {
+            let addr = address_cache.get_address().await;
+            connection_test::test_connection_mode(
+                connection_mode,
+                &API.host(),
+                addr,
+                connection_test::StageTimeouts::default(),
+                #[cfg(target_os = "android")]
+                socket_bypass_tx,
+            )
+            .await
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -518,9 +647,11 @@ impl ProblemReportProxy {
         };
 
         let service = self.handle.service.clone();
+        let mut factory = self.handle.factory.clone();
+        factory.class = rest::RequestClass::Upload;
 
         let request = rest::send_json_request(
-            &self.handle.factory,
+            &factory,
             service,
             &format!("{APP_URL_PREFIX}/problem-report"),
             Method::POST,
@@ -0,0 +1,218 @@
+//! Discovery of NAT64 prefixes (RFC 7050) and synthesis of IPv4-embedded IPv6 addresses
+//! (RFC 6052), used to reach IPv4-only endpoints from IPv6-only networks.
+
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+/// Name that only has A records. A DNS64 resolver synthesizes AAAA records for it, from which the
+/// NAT64 prefix can be inferred.
+const IPV4ONLY_ARPA: &str = "ipv4only.arpa";
+
+/// Addresses that `ipv4only.arpa` resolves to.
+const WELL_KNOWN_IPV4_ADDRESSES: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+
+/// Prefix lengths allowed by RFC 6052, longest first.
+const PREFIX_LENGTHS: [u8; 6] = [96, 64, 56, 48, 40, 32];
+
+/// A NAT64 prefix, used to translate IPv4 addresses to IPv6 addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nat64Prefix {
+    prefix: Ipv6Addr,
+    len: u8,
+}
+
+impl Nat64Prefix {
+    /// The well-known prefix `64:ff9b::/96`.
+    pub const WELL_KNOWN: Nat64Prefix = Nat64Prefix {
+        prefix: Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+        len: 96,
+    };
+
+    /// Returns a prefix of `len` bits taken from `address`, or `None` if `len` is not one of the
+    /// lengths allowed by RFC 6052.
+    pub fn new(address: Ipv6Addr, len: u8) -> Option<Self> {
+        if !PREFIX_LENGTHS.contains(&len) {
+            return None;
+        }
+        let mask = u128::MAX << (128 - u32::from(len));
+        Some(Nat64Prefix {
+            prefix: Ipv6Addr::from(u128::from(address) & mask),
+            len,
+        })
+    }
+
+    /// Infers the NAT64 prefix from the addresses that `ipv4only.arpa` resolved to, as described
+    /// in RFC 7050. Returns `None` if none of them were synthesized by a DNS64 resolver.
+    pub fn from_ipv4only_arpa(addresses: impl IntoIterator<Item = IpAddr>) -> Option<Self> {
+        addresses
+            .into_iter()
+            .filter_map(|address| match address {
+                IpAddr::V6(address) => Some(address),
+                IpAddr::V4(_) => None,
+            })
+            .find_map(|address| {
+                PREFIX_LENGTHS.iter().find_map(|&len| {
+                    let prefix = Nat64Prefix::new(address, len)?;
+                    let embedded = prefix.extract(address);
+                    (WELL_KNOWN_IPV4_ADDRESSES.contains(&embedded)
+                        && prefix.synthesize(embedded) == address)
+                        .then_some(prefix)
+                })
+            })
+    }
+
+    /// Returns the IPv6 address that the NAT64 translates to `address`.
+    pub fn synthesize(&self, address: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix.octets();
+        for (index, octet) in self.embedded_octets().into_iter().zip(address.octets()) {
+            octets[index] = octet;
+        }
+        Ipv6Addr::from(octets)
+    }
+
+    /// Translates IPv4 socket addresses to IPv6 addresses. IPv6 addresses are returned unchanged.
+    pub fn synthesize_socket_addr(&self, address: SocketAddr) -> SocketAddr {
+        match address {
+            SocketAddr::V4(address) => {
+                SocketAddr::new(IpAddr::V6(self.synthesize(*address.ip())), address.port())
+            }
+            SocketAddr::V6(_) => address,
+        }
+    }
+
+    fn extract(&self, address: Ipv6Addr) -> Ipv4Addr {
+        let octets = address.octets();
+        let embedded = self.embedded_octets().map(|index| octets[index]);
+        Ipv4Addr::from(embedded)
+    }
+
+    /// Indices of the octets that hold the IPv4 address. Bits 64 to 71 (octet 8) are reserved
+    /// and always skipped.
+    fn embedded_octets(&self) -> [usize; 4] {
+        match self.len {
+            32 => [4, 5, 6, 7],
+            40 => [5, 6, 7, 9],
+            48 => [6, 7, 9, 10],
+            56 => [7, 9, 10, 11],
+            64 => [9, 10, 11, 12],
+            _ => [12, 13, 14, 15],
+        }
+    }
+}
+
+impl fmt::Display for Nat64Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.prefix, self.len)
+    }
+}
+
+/// Looks up `ipv4only.arpa` using the system resolver and returns the NAT64 prefix in use, if
+/// any. This only works while DNS requests to the system resolver are allowed.
+pub async fn discover() -> io::Result<Option<Nat64Prefix>> {
+    let addresses = tokio::net::lookup_host((IPV4ONLY_ARPA, 0)).await?;
+    Ok(Nat64Prefix::from_ipv4only_arpa(
+        addresses.map(|address| address.ip()),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn ipv6(address: &str) -> Ipv6Addr {
+        address.parse().unwrap()
+    }
+
+    /// Examples from section 2.4 of RFC 6052.
+    const RFC_6052_EXAMPLES: [(&str, u8, &str); 6] = [
+        ("2001:db8::", 32, "2001:db8:c000:221::"),
+        ("2001:db8:100::", 40, "2001:db8:1c0:2:21::"),
+        ("2001:db8:122::", 48, "2001:db8:122:c000:2:2100::"),
+        ("2001:db8:122:300::", 56, "2001:db8:122:3c0:0:221::"),
+        ("2001:db8:122:344::", 64, "2001:db8:122:344:c0:2:2100:0"),
+        ("2001:db8:122:344::", 96, "2001:db8:122:344::192.0.2.33"),
+    ];
+
+    #[test]
+    fn test_synthesize() {
+        for (prefix, len, expected) in RFC_6052_EXAMPLES {
+            let prefix = Nat64Prefix::new(ipv6(prefix), len).unwrap();
+            assert_eq!(
+                prefix.synthesize(Ipv4Addr::new(192, 0, 2, 33)),
+                ipv6(expected),
+                "{prefix}"
+            );
+        }
+        assert_eq!(
+            Nat64Prefix::WELL_KNOWN.synthesize(Ipv4Addr::new(45, 83, 223, 196)),
+            ipv6("64:ff9b::2d53:dfc4")
+        );
+    }
+
+    #[test]
+    fn test_synthesize_socket_addr() {
+        let prefix = Nat64Prefix::WELL_KNOWN;
+        assert_eq!(
+            prefix.synthesize_socket_addr("192.0.2.33:443".parse().unwrap()),
+            "[64:ff9b::c000:221]:443".parse().unwrap()
+        );
+        let address = "[2a03:1b20:4:f011::999]:443".parse().unwrap();
+        assert_eq!(prefix.synthesize_socket_addr(address), address);
+    }
+
+    #[test]
+    fn test_invalid_prefix_length() {
+        assert!(Nat64Prefix::new(ipv6("64:ff9b::"), 80).is_none());
+        assert_eq!(
+            Nat64Prefix::new(ipv6("64:ff9b::1"), 96),
+            Some(Nat64Prefix::WELL_KNOWN)
+        );
+    }
+
+    #[test]
+    fn test_discover_well_known_prefix() {
+        let addresses = [
+            ip("192.0.0.170"),
+            ip("192.0.0.171"),
+            ip("64:ff9b::c000:aa"),
+            ip("64:ff9b::c000:ab"),
+        ];
+        assert_eq!(
+            Nat64Prefix::from_ipv4only_arpa(addresses),
+            Some(Nat64Prefix::WELL_KNOWN)
+        );
+    }
+
+    #[test]
+    fn test_discover_network_specific_prefix() {
+        for (prefix, len, _) in RFC_6052_EXAMPLES {
+            let expected = Nat64Prefix::new(ipv6(prefix), len).unwrap();
+            let synthesized = expected.synthesize(WELL_KNOWN_IPV4_ADDRESSES[1]);
+            assert_eq!(
+                Nat64Prefix::from_ipv4only_arpa([IpAddr::V6(synthesized)]),
+                Some(expected),
+                "{synthesized}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_discover_without_dns64() {
+        assert_eq!(
+            Nat64Prefix::from_ipv4only_arpa([ip("192.0.0.170"), ip("192.0.0.171")]),
+            None
+        );
+        // AAAA records that do not embed the well-known addresses
+        assert_eq!(
+            Nat64Prefix::from_ipv4only_arpa([ip("2001:db8::1"), ip("64:ff9b::808:808")]),
+            None
+        );
+    }
+}
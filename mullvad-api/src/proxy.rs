@@ -34,10 +34,20 @@ impl fmt::Display for ApiConnectionMode {
     }
 }
 
+/// Outcome of an API request which was sent using the current [`ApiConnectionMode`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionModeOutcome {
+    /// The API responded.
+    Success,
+    /// The request failed due to a network error.
+    Failure(String),
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum ProxyConfig {
     Shadowsocks(access_method::Shadowsocks),
     Socks(access_method::Socks5),
+    HttpProxy(access_method::HttpProxy),
 }
 
 impl ProxyConfig {
@@ -49,6 +59,7 @@ impl ProxyConfig {
                 access_method::Socks5::Local(s) => s.peer,
                 access_method::Socks5::Remote(s) => s.peer,
             },
+            ProxyConfig::HttpProxy(http) => http.peer,
         }
     }
 }
@@ -64,6 +75,7 @@ impl fmt::Display for ProxyConfig {
                 }
                 access_method::Socks5::Remote(s) => write!(f, "Socks5 {}/TCP", s.peer),
             },
+            ProxyConfig::HttpProxy(http) => write!(f, "HTTP proxy {}/TCP", http.peer),
         }
     }
 }
@@ -10,7 +10,6 @@ use std::{
     collections::BTreeMap,
     future::Future,
     net::{Ipv4Addr, Ipv6Addr},
-    time::Duration,
 };
 
 /// Fetches relay list from https://api.mullvad.net/app/v1/relays
@@ -19,8 +18,6 @@ pub struct RelayListProxy {
     handle: rest::MullvadRestHandle,
 }
 
-const RELAY_LIST_TIMEOUT: Duration = Duration::from_secs(15);
-
 impl RelayListProxy {
     /// Construct a new relay list rest client
     pub fn new(handle: rest::MullvadRestHandle) -> Self {
@@ -37,7 +34,7 @@ impl RelayListProxy {
 
         async move {
             let mut request = request?;
-            request.set_timeout(RELAY_LIST_TIMEOUT);
+            request.set_class(rest::RequestClass::Background);
 
             if let Some(ref tag) = etag {
                 request.add_header(header::IF_NONE_MATCH, tag)?;
@@ -308,6 +305,12 @@ struct WireGuardRelay {
     #[serde(flatten)]
     relay: Relay,
     public_key: wireguard::PublicKey,
+    /// Only present for relays that accept QUIC obfuscation
+    #[serde(default)]
+    quic: Option<relay_list::QuicEndpointData>,
+    /// Only present for relays that accept TLS obfuscation
+    #[serde(default)]
+    tls: Option<relay_list::TlsEndpointData>,
 }
 
 impl WireGuardRelay {
@@ -317,6 +320,8 @@ impl WireGuardRelay {
             location,
             relay_list::RelayEndpointData::Wireguard(relay_list::WireguardRelayEndpointData {
                 public_key: self.public_key,
+                quic: self.quic,
+                tls: self.tls,
             }),
         )
     }
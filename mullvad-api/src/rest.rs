@@ -4,30 +4,32 @@ use crate::{
     access::AccessTokenProxy,
     address_cache::AddressCache,
     availability::ApiAvailabilityHandle,
-    https_client_with_sni::{HttpsConnectorWithSni, HttpsConnectorWithSniHandle},
-    proxy::ApiConnectionMode,
+    https_client_with_sni::{AllowEndpoint, HttpsConnectorWithSni, HttpsConnectorWithSniHandle},
+    proxy::{ApiConnectionMode, ConnectionModeOutcome},
+    API,
 };
 use futures::{
     channel::{mpsc, oneshot},
-    stream::StreamExt,
-    Stream, TryFutureExt,
+    stream::{self, StreamExt},
+    Stream,
 };
 use hyper::{
-    client::Client,
+    body::Bytes,
+    client::{connect::Connect, Client},
     header::{self, HeaderValue},
     Method, Uri,
 };
 use mullvad_types::account::AccountToken;
+use once_cell::sync::Lazy;
 use std::{
     future::Future,
+    net::IpAddr,
     str::FromStr,
     sync::{Arc, Weak},
     time::Duration,
 };
 use talpid_types::ErrorExt;
-
-#[cfg(feature = "api-override")]
-use crate::API;
+use tokio::time::Instant;
 
 pub use hyper::StatusCode;
 
@@ -41,7 +43,134 @@ const API_IP_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
 const API_IP_CHECK_ERROR_INTERVAL: Duration = Duration::from_secs(15 * 60);
 
 pub type Result<T> = std::result::Result<T, Error>;
-const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upload bodies are sent in chunks of at most this size, so that the progress can be logged.
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Override [`RequestClass::timeouts`]. The values have the form
+/// `<connect ms>,<write ms>,<read ms>`.
+pub const INTERACTIVE_TIMEOUTS_VAR: &str = "MULLVAD_API_INTERACTIVE_TIMEOUTS";
+pub const BACKGROUND_TIMEOUTS_VAR: &str = "MULLVAD_API_BACKGROUND_TIMEOUTS";
+pub const UPLOAD_TIMEOUTS_VAR: &str = "MULLVAD_API_UPLOAD_TIMEOUTS";
+
+static INTERACTIVE_TIMEOUTS: Lazy<Timeouts> =
+    Lazy::new(|| RequestClass::Interactive.load_timeouts());
+static BACKGROUND_TIMEOUTS: Lazy<Timeouts> = Lazy::new(|| RequestClass::Background.load_timeouts());
+static UPLOAD_TIMEOUTS: Lazy<Timeouts> = Lazy::new(|| RequestClass::Upload.load_timeouts());
+
+/// What a request is for, which determines how long each stage of it may take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestClass {
+    /// Requests made on behalf of the user, who is waiting for the result. They fail quickly.
+    Interactive,
+    /// Requests made in the background, which may download a lot of data on slow networks, such
+    /// as fetching the relay list.
+    Background,
+    /// Requests which send a lot of data, such as problem reports. The progress is logged.
+    Upload,
+}
+
+/// How long each stage of a request may take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Time allowed for connecting to the API, including any proxy and the TLS handshake.
+    pub connect: Duration,
+    /// Time allowed for sending the request and receiving the response headers once connected.
+    pub write: Duration,
+    /// The longest time to wait for more of the response body.
+    pub read: Duration,
+}
+
+#[derive(err_derive::Error, Debug, PartialEq, Eq)]
+pub enum ParseTimeoutsError {
+    #[error(display = "Expected three comma-separated values")]
+    WrongNumberOfValues,
+
+    #[error(display = "Invalid value: {}", _0)]
+    InvalidValue(String),
+}
+
+impl RequestClass {
+    const fn default_timeouts(self) -> Timeouts {
+        match self {
+            RequestClass::Interactive => Timeouts {
+                connect: Duration::from_secs(5),
+                write: Duration::from_secs(10),
+                read: Duration::from_secs(5),
+            },
+            RequestClass::Background => Timeouts {
+                connect: Duration::from_secs(15),
+                write: Duration::from_secs(30),
+                read: Duration::from_secs(30),
+            },
+            RequestClass::Upload => Timeouts {
+                connect: Duration::from_secs(15),
+                write: Duration::from_secs(5 * 60),
+                read: Duration::from_secs(30),
+            },
+        }
+    }
+
+    fn timeouts_var(self) -> &'static str {
+        match self {
+            RequestClass::Interactive => INTERACTIVE_TIMEOUTS_VAR,
+            RequestClass::Background => BACKGROUND_TIMEOUTS_VAR,
+            RequestClass::Upload => UPLOAD_TIMEOUTS_VAR,
+        }
+    }
+
+    fn load_timeouts(self) -> Timeouts {
+        let var = self.timeouts_var();
+        let Ok(value) = std::env::var(var) else {
+            return self.default_timeouts();
+        };
+        match value.parse() {
+            Ok(timeouts) => {
+                log::debug!("Using timeouts {timeouts:?} for {self:?} requests");
+                timeouts
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Ignoring {var}"))
+                );
+                self.default_timeouts()
+            }
+        }
+    }
+
+    /// Returns the timeouts of the class, which can be overridden using environment variables
+    /// such as [`INTERACTIVE_TIMEOUTS_VAR`].
+    pub fn timeouts(self) -> Timeouts {
+        match self {
+            RequestClass::Interactive => *INTERACTIVE_TIMEOUTS,
+            RequestClass::Background => *BACKGROUND_TIMEOUTS,
+            RequestClass::Upload => *UPLOAD_TIMEOUTS,
+        }
+    }
+}
+
+impl FromStr for Timeouts {
+    type Err = ParseTimeoutsError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let values: Vec<&str> = s.split(',').map(str::trim).collect();
+        let [connect, write, read] = values[..] else {
+            return Err(ParseTimeoutsError::WrongNumberOfValues);
+        };
+        let parse_ms = |value: &str| {
+            value
+                .parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_| ParseTimeoutsError::InvalidValue(value.to_owned()))
+        };
+        Ok(Timeouts {
+            connect: parse_ms(connect)?,
+            write: parse_ms(write)?,
+            read: parse_ms(read)?,
+        })
+    }
+}
 
 /// Describes all the ways a REST request can fail
 #[derive(err_derive::Error, Debug)]
@@ -74,6 +203,10 @@ pub enum Error {
     #[error(display = "Unexpected response status code {} - {}", _0, _1)]
     ApiError(StatusCode, String),
 
+    /// Too many requests have been made. The API may say how long to wait before trying again.
+    #[error(display = "Too many requests were sent to the API")]
+    RateLimited(Option<Duration>),
+
     /// The string given was not a valid URI.
     #[error(display = "Not a valid URI")]
     UriError(#[error(source)] http::uri::InvalidUri),
@@ -126,9 +259,12 @@ pub(crate) struct RequestService<
     connector_handle: HttpsConnectorWithSniHandle,
     client: hyper::Client<HttpsConnectorWithSni, hyper::Body>,
     proxy_config_provider: T,
-    new_address_callback: F,
+    new_address_callback: Arc<F>,
+    outcome_callback: Arc<dyn Fn(ConnectionModeOutcome) + Send + Sync>,
     address_cache: AddressCache,
     api_availability: ApiAvailabilityHandle,
+    /// Whether the API is connected to directly, i.e. at the address in `address_cache`.
+    direct_connection: bool,
 }
 
 impl<
@@ -143,11 +279,18 @@ impl<
         address_cache: AddressCache,
         mut proxy_config_provider: T,
         new_address_callback: F,
+        outcome_callback: impl Fn(ConnectionModeOutcome) + Send + Sync + 'static,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> RequestServiceHandle {
+        let new_address_callback = Arc::new(new_address_callback);
+        let allow_endpoint: AllowEndpoint = {
+            let callback = new_address_callback.clone();
+            Arc::new(move |address| Box::pin(callback(address)))
+        };
         let (connector, connector_handle) = HttpsConnectorWithSni::new(
             sni_hostname,
             address_cache.clone(),
+            allow_endpoint,
             #[cfg(target_os = "android")]
             socket_bypass_tx.clone(),
         );
@@ -157,9 +300,11 @@ impl<
         #[cfg(not(feature = "api-override"))]
         let force_direct_connection = false;
 
+        let mut direct_connection = true;
         if force_direct_connection {
             log::debug!("API proxies are disabled");
         } else if let Some(config) = proxy_config_provider.next().await {
+            direct_connection = !config.is_proxy();
             connector_handle.set_connection_mode(config);
         }
 
@@ -175,8 +320,10 @@ impl<
             client,
             proxy_config_provider,
             new_address_callback,
+            outcome_callback: Arc::new(outcome_callback),
             address_cache,
             api_availability,
+            direct_connection,
         };
         let handle = RequestServiceHandle { tx: command_tx };
         tokio::spawn(service.into_future());
@@ -187,35 +334,43 @@ impl<
         match command {
             RequestCommand::NewRequest(request, completion_tx) => {
                 let tx = self.command_tx.upgrade();
-                let timeout = request.timeout();
+                let class = request.class();
 
                 let hyper_request = request.into_request();
 
                 let api_availability = self.api_availability.clone();
+                let outcome_callback = self.outcome_callback.clone();
                 let suspend_fut = api_availability.wait_for_unsuspend();
-                let request_fut = self.client.request(hyper_request).map_err(Error::from);
-
-                let request_future = async move {
-                    let _ = suspend_fut.await;
-                    request_fut.await
-                };
+                let client = self.client.clone();
 
                 let future = async move {
-                    let response = tokio::time::timeout(timeout, request_future)
-                        .await
-                        .map_err(Error::TimeoutError);
-
-                    let response = flatten_result(response).map_err(|error| error.map_aborted());
-
-                    if let Err(err) = &response {
-                        if err.is_network_error() && !api_availability.get_state().is_offline() {
+                    let response = send_with_timeouts(
+                        &client,
+                        hyper_request,
+                        class,
+                        class.timeouts(),
+                        async move {
+                            let _ = suspend_fut.await;
+                        },
+                    )
+                    .await
+                    .map_err(|error| error.map_aborted());
+
+                    match &response {
+                        Ok(_) => outcome_callback(ConnectionModeOutcome::Success),
+                        Err(err)
+                            if err.is_network_error()
+                                && !api_availability.get_state().is_offline() =>
+                        {
                             log::error!("{}", err.display_chain_with_msg("HTTP request failed"));
+                            outcome_callback(ConnectionModeOutcome::Failure(err.display_chain()));
                             if let Some(tx) = tx {
                                 let (completion_tx, _completion_rx) = oneshot::channel();
                                 let _ =
                                     tx.unbounded_send(RequestCommand::NextApiConfig(completion_tx));
                             }
                         }
+                        Err(_) => (),
                     }
 
                     if completion_tx.send(response).is_err() {
@@ -229,6 +384,19 @@ impl<
             RequestCommand::Reset => {
                 self.connector_handle.reset();
             }
+            RequestCommand::AddressChanged(completion_tx) => {
+                // Proxies are not affected by the API address
+                if self.direct_connection {
+                    let address = self.address_cache.get_address().await;
+                    if (self.new_address_callback)(address).await {
+                        self.connector_handle.reset();
+                    }
+                }
+                let _ = completion_tx.send(());
+            }
+            RequestCommand::SetTunnelAddresses(addresses) => {
+                self.connector_handle.set_tunnel_addresses(addresses);
+            }
             RequestCommand::NextApiConfig(completion_tx) => {
                 #[cfg(feature = "api-override")]
                 if API.force_direct_connection {
@@ -244,6 +412,7 @@ impl<
                     };
                     // Switch to new connection mode unless rejected by address change callback
                     if (self.new_address_callback)(endpoint).await {
+                        self.direct_connection = !new_config.is_proxy();
                         self.connector_handle.set_connection_mode(new_config);
                     }
                 }
@@ -273,6 +442,28 @@ impl RequestServiceHandle {
         let _ = self.tx.unbounded_send(RequestCommand::Reset);
     }
 
+    /// Notifies the request service that the address in the address cache has changed without
+    /// going through the service, e.g. because a NAT64 prefix was detected. If the API is
+    /// connected to directly, the new address is passed to the address change callback and open
+    /// connections are closed.
+    pub async fn address_changed(&self) -> Result<()> {
+        let (completion_tx, completion_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RequestCommand::AddressChanged(completion_tx))
+            .map_err(|_| Error::SendError)?;
+        completion_rx.await.map_err(|_| Error::ReceiveError)
+    }
+
+    /// Keeps API traffic inside the tunnel while it is up. When `addresses` is set, the API is
+    /// connected to directly, and from the tunnel address of the same family as the API address,
+    /// instead of using the current connection mode. Setting it back to `None` restores the
+    /// connection mode. Open connections are closed whenever this changes.
+    pub fn set_tunnel_addresses(&self, addresses: Option<Vec<IpAddr>>) {
+        let _ = self
+            .tx
+            .unbounded_send(RequestCommand::SetTunnelAddresses(addresses));
+    }
+
     /// Submits a `RestRequest` for execution to the request service.
     pub async fn request(&self, request: RestRequest) -> Result<Response> {
         let (completion_tx, completion_rx) = oneshot::channel();
@@ -301,13 +492,113 @@ pub(crate) enum RequestCommand {
     ),
     Reset,
     NextApiConfig(oneshot::Sender<std::result::Result<(), Error>>),
+    AddressChanged(oneshot::Sender<()>),
+    SetTunnelAddresses(Option<Vec<IpAddr>>),
 }
 
+/// Sends `request` using `client` once `ready` has completed, and fails if any stage of the
+/// request takes longer than allowed by `timeouts`. Waiting for `ready` counts as connecting.
+async fn send_with_timeouts<C>(
+    client: &Client<C, hyper::Body>,
+    mut request: Request,
+    class: RequestClass,
+    timeouts: Timeouts,
+    ready: impl Future<Output = ()>,
+) -> Result<Response>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let connect_deadline = Instant::now() + timeouts.connect;
+    tokio::time::timeout_at(connect_deadline, ready)
+        .await
+        .map_err(Error::TimeoutError)?;
+
+    if class == RequestClass::Upload {
+        let total = get_content_length(request.headers());
+        request = request.map(|body| with_upload_progress(body, total));
+    }
+    let mut connection = hyper::client::connect::capture_connection(&mut request);
+    let response = client.request(request);
+    tokio::pin!(response);
+
+    tokio::select! {
+        response = &mut response => {
+            return response
+                .map(|response| with_read_timeout(response, timeouts.read))
+                .map_err(Error::from);
+        }
+        connected = tokio::time::timeout_at(
+            connect_deadline,
+            connection.wait_for_connection_metadata(),
+        ) => {
+            if let Err(elapsed) = connected {
+                log::debug!("Timed out connecting to the API");
+                return Err(Error::TimeoutError(elapsed));
+            }
+        }
+    }
+
+    let response = tokio::time::timeout(timeouts.write, response)
+        .await
+        .map_err(Error::TimeoutError)??;
+    Ok(with_read_timeout(response, timeouts.read))
+}
+
+/// Fails reading the body of `response` if no data is received for `timeout`.
+fn with_read_timeout(response: Response, timeout: Duration) -> Response {
+    response.map(|body| {
+        let chunks = stream::unfold(Some(body), move |body| async move {
+            let mut body = body?;
+            match tokio::time::timeout(timeout, body.next()).await {
+                Ok(Some(chunk)) => Some((chunk.map_err(BoxError::from), Some(body))),
+                Ok(None) => None,
+                // Stop after the error.
+                Err(elapsed) => Some((Err(BoxError::from(elapsed)), None)),
+            }
+        });
+        hyper::Body::wrap_stream(chunks)
+    })
+}
+
+/// Sends `body` in chunks of at most [`UPLOAD_CHUNK_SIZE`], and logs how much has been sent.
+fn with_upload_progress(body: hyper::Body, total: usize) -> hyper::Body {
+    let mut sent = 0;
+    let mut next_report = 0;
+    let chunks = body
+        .flat_map(|chunk| match chunk {
+            Ok(bytes) => stream::iter(split_bytes(bytes).into_iter().map(Ok)).left_stream(),
+            Err(error) => stream::once(futures::future::ready(Err(error))).right_stream(),
+        })
+        .inspect(move |chunk| {
+            let Ok(bytes) = chunk else {
+                return;
+            };
+            sent += bytes.len();
+            // Log every 25 %, and once all of the body has been sent.
+            if total > 0 && (sent * 4 / total >= next_report || sent >= total) {
+                log::debug!("Uploaded {sent} of {total} bytes");
+                next_report = sent * 4 / total + 1;
+            }
+        });
+    hyper::Body::wrap_stream(chunks)
+}
+
+fn split_bytes(mut bytes: Bytes) -> Vec<Bytes> {
+    let mut chunks = Vec::with_capacity(bytes.len() / UPLOAD_CHUNK_SIZE + 1);
+    while bytes.len() > UPLOAD_CHUNK_SIZE {
+        chunks.push(bytes.split_to(UPLOAD_CHUNK_SIZE));
+    }
+    chunks.push(bytes);
+    chunks
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 /// A REST request that is sent to the RequestService to be executed.
 #[derive(Debug)]
 pub struct RestRequest {
     request: Request,
-    timeout: Duration,
+    class: RequestClass,
     auth: Option<HeaderValue>,
 }
 
@@ -330,7 +621,7 @@ impl RestRequest {
             .map_err(Error::HttpError)?;
 
         Ok(RestRequest {
-            timeout: DEFAULT_TIMEOUT,
+            class: RequestClass::Interactive,
             auth: None,
             request,
         })
@@ -350,14 +641,14 @@ impl RestRequest {
         Ok(())
     }
 
-    /// Sets timeout for the request.
-    pub fn set_timeout(&mut self, timeout: Duration) {
-        self.timeout = timeout;
+    /// Sets the class of the request, which determines its timeouts.
+    pub fn set_class(&mut self, class: RequestClass) {
+        self.class = class;
     }
 
-    /// Retrieves timeout
-    pub fn timeout(&self) -> Duration {
-        self.timeout
+    /// Retrieves the class of the request
+    pub fn class(&self) -> RequestClass {
+        self.class
     }
 
     pub fn add_header<T: header::IntoHeaderName>(&mut self, key: T, value: &str) -> Result<()> {
@@ -387,7 +678,7 @@ impl From<Request> for RestRequest {
     fn from(request: Request) -> Self {
         Self {
             request,
-            timeout: DEFAULT_TIMEOUT,
+            class: RequestClass::Interactive,
             auth: None,
         }
     }
@@ -400,36 +691,52 @@ pub struct ErrorResponse {
 
 #[derive(Clone)]
 pub struct RequestFactory {
-    hostname: String,
+    /// If `None`, requests are sent to the current host of the API.
+    hostname: Option<String>,
     path_prefix: Option<String>,
-    pub timeout: Duration,
+    /// Class of the created requests.
+    pub class: RequestClass,
 }
 
 impl RequestFactory {
     pub fn new(hostname: String, path_prefix: Option<String>) -> Self {
         Self {
-            hostname,
+            hostname: Some(hostname),
+            path_prefix,
+            class: RequestClass::Interactive,
+        }
+    }
+
+    /// Create a factory for requests to the Mullvad API. The hostname is looked up for every
+    /// request, so that requests follow an override of the API endpoint.
+    pub fn for_api(path_prefix: Option<String>) -> Self {
+        Self {
+            hostname: None,
             path_prefix,
-            timeout: DEFAULT_TIMEOUT,
+            class: RequestClass::Interactive,
         }
     }
 
+    fn hostname(&self) -> String {
+        self.hostname.clone().unwrap_or_else(|| API.host())
+    }
+
     pub fn request(&self, path: &str, method: Method) -> Result<RestRequest> {
         self.hyper_request(path, method)
             .map(RestRequest::from)
-            .map(|req| self.set_request_timeout(req))
+            .map(|req| self.set_request_class(req))
     }
 
     pub fn get(&self, path: &str) -> Result<RestRequest> {
         self.hyper_request(path, Method::GET)
             .map(RestRequest::from)
-            .map(|req| self.set_request_timeout(req))
+            .map(|req| self.set_request_class(req))
     }
 
     pub fn post(&self, path: &str) -> Result<RestRequest> {
         self.hyper_request(path, Method::POST)
             .map(RestRequest::from)
-            .map(|req| self.set_request_timeout(req))
+            .map(|req| self.set_request_class(req))
     }
 
     pub fn post_json<S: serde::Serialize>(&self, path: &str, body: &S) -> Result<RestRequest> {
@@ -458,13 +765,13 @@ impl RequestFactory {
             HeaderValue::from_static("application/json"),
         );
 
-        Ok(self.set_request_timeout(RestRequest::from(request)))
+        Ok(self.set_request_class(RestRequest::from(request)))
     }
 
     pub fn delete(&self, path: &str) -> Result<RestRequest> {
         self.hyper_request(path, Method::DELETE)
             .map(RestRequest::from)
-            .map(|req| self.set_request_timeout(req))
+            .map(|req| self.set_request_class(req))
     }
 
     fn hyper_request(&self, path: &str, method: Method) -> Result<Request> {
@@ -474,19 +781,19 @@ impl RequestFactory {
             .uri(uri)
             .header(header::USER_AGENT, HeaderValue::from_static(USER_AGENT))
             .header(header::ACCEPT, HeaderValue::from_static("application/json"))
-            .header(header::HOST, self.hostname.clone());
+            .header(header::HOST, self.hostname());
 
         request.body(hyper::Body::empty()).map_err(Error::HttpError)
     }
 
     fn get_uri(&self, path: &str) -> Result<Uri> {
         let prefix = self.path_prefix.as_ref().map(AsRef::as_ref).unwrap_or("");
-        let uri = format!("https://{}/{}{}", self.hostname, prefix, path);
+        let uri = format!("https://{}/{}{}", self.hostname(), prefix, path);
         hyper::Uri::from_str(&uri).map_err(Error::UriError)
     }
 
-    fn set_request_timeout(&self, mut request: RestRequest) -> RestRequest {
-        request.timeout = self.timeout;
+    fn set_request_class(&self, mut request: RestRequest) -> RestRequest {
+        request.class = self.class;
         request
     }
 }
@@ -562,9 +869,22 @@ async fn deserialize_body_inner<T: serde::de::DeserializeOwned>(
     serde_json::from_slice(&body).map_err(Error::DeserializeError)
 }
 
-fn get_body_length(response: &Response) -> usize {
+/// Returns the delay in the `Retry-After` header. Only delays given in seconds are supported.
+fn get_retry_after(response: &Response) -> Option<Duration> {
     response
         .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|header_value| header_value.to_str().ok())
+        .and_then(|delay| delay.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+fn get_body_length(response: &Response) -> usize {
+    get_content_length(response.headers())
+}
+
+fn get_content_length(headers: &header::HeaderMap) -> usize {
+    headers
         .get(header::CONTENT_LENGTH)
         .and_then(|header_value| header_value.to_str().ok())
         .and_then(|length| length.parse::<usize>().ok())
@@ -597,6 +917,9 @@ pub async fn handle_error_response<T>(response: Response) -> Result<T> {
     let status = response.status();
     let error_message = match status {
         hyper::StatusCode::METHOD_NOT_ALLOWED => "Method not allowed",
+        hyper::StatusCode::TOO_MANY_REQUESTS => {
+            return Err(Error::RateLimited(get_retry_after(&response)));
+        }
         status => match get_body_length(&response) {
             0 => status.canonical_reason().unwrap_or("Unexpected error"),
             body_length => {
@@ -648,7 +971,12 @@ impl MullvadRestHandle {
             let mut next_delay = API_IP_CHECK_INITIAL;
 
             loop {
-                talpid_time::sleep(next_delay).await;
+                tokio::select! {
+                    _ = talpid_time::sleep(next_delay) => (),
+                    _ = address_cache.cleared() => {
+                        log::debug!("Fetching API addresses since the cache was cleared");
+                    }
+                }
 
                 if let Err(error) = availability.wait_background().await {
                     log::error!("Failed while waiting for API: {}", error);
@@ -656,13 +984,13 @@ impl MullvadRestHandle {
                 }
                 match api_proxy.clone().get_api_addrs().await {
                     Ok(new_addrs) => {
-                        if let Some(addr) = new_addrs.get(0) {
+                        if let Some(addr) = address_cache.select_address(&new_addrs).await {
                             log::debug!(
                                 "Fetched new API address {:?}. Fetching again in {} hours",
                                 addr,
                                 API_IP_CHECK_INTERVAL.as_secs() / (60 * 60)
                             );
-                            if let Err(err) = address_cache.set_address(*addr).await {
+                            if let Err(err) = address_cache.set_address(addr).await {
                                 log::error!("Failed to save newly updated API address: {}", err);
                             }
                         } else {
@@ -694,11 +1022,310 @@ impl MullvadRestHandle {
     }
 }
 
-fn flatten_result<T, E>(
-    result: std::result::Result<std::result::Result<T, E>, E>,
-) -> std::result::Result<T, E> {
-    match result {
-        Ok(value) => value,
-        Err(err) => Err(err),
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{availability::ApiAvailability, nat64::Nat64Prefix};
+
+    const EXPECTED: &[StatusCode] = &[StatusCode::OK];
+
+    fn response(
+        status: StatusCode,
+        headers: &[(header::HeaderName, &str)],
+        body: &str,
+    ) -> Response {
+        let mut builder = hyper::Response::builder()
+            .status(status)
+            .header(header::CONTENT_LENGTH, body.len());
+        for (name, value) in headers {
+            builder = builder.header(name, *value);
+        }
+        builder.body(hyper::Body::from(body.to_owned())).unwrap()
+    }
+
+    fn error_response(status: StatusCode, code: &str) -> Response {
+        response(status, &[], &format!(r#"{{"code":"{code}"}}"#))
+    }
+
+    #[tokio::test]
+    async fn test_voucher_error_codes() {
+        for code in [crate::INVALID_VOUCHER, crate::VOUCHER_USED] {
+            let result =
+                parse_rest_response(error_response(StatusCode::BAD_REQUEST, code), EXPECTED).await;
+            match result {
+                Err(Error::ApiError(StatusCode::BAD_REQUEST, error_code)) => {
+                    assert_eq!(error_code, code)
+                }
+                result => panic!("unexpected result for {code}: {result:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited() {
+        let limited = response(
+            StatusCode::TOO_MANY_REQUESTS,
+            &[(header::RETRY_AFTER, "120")],
+            "",
+        );
+        let result = parse_rest_response(limited, EXPECTED).await;
+        assert!(matches!(
+            result,
+            Err(Error::RateLimited(Some(delay))) if delay == Duration::from_secs(120)
+        ));
+
+        // Dates are not supported, so the delay is unknown.
+        let limited = response(
+            StatusCode::TOO_MANY_REQUESTS,
+            &[(header::RETRY_AFTER, "Wed, 21 Oct 2026 07:28:00 GMT")],
+            "",
+        );
+        let result = parse_rest_response(limited, EXPECTED).await;
+        assert!(matches!(result, Err(Error::RateLimited(None))));
+    }
+
+    #[tokio::test]
+    async fn test_voucher_submission() {
+        let body = r#"{"time_added":2592000,"new_expiry":"2026-11-13T12:00:00Z"}"#;
+        let response = parse_rest_response(response(StatusCode::OK, &[], body), EXPECTED)
+            .await
+            .unwrap();
+        let submission: mullvad_types::account::VoucherSubmission =
+            deserialize_body(response).await.unwrap();
+        assert_eq!(submission.time_added, 30 * 24 * 60 * 60);
+        assert_eq!(
+            submission.new_expiry,
+            "2026-11-13T12:00:00Z"
+                .parse::<chrono::DateTime<chrono::Utc>>()
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_address_changed() {
+        let address_cache = AddressCache::new(None).unwrap();
+        let availability = ApiAvailability::new(Default::default());
+        let (address_tx, mut address_rx) = mpsc::unbounded();
+        let service = RequestService::spawn(
+            None,
+            availability.handle(),
+            address_cache.clone(),
+            ApiConnectionMode::Direct.into_repeat(),
+            move |address| {
+                let address_tx = address_tx.clone();
+                async move { address_tx.unbounded_send(address).is_ok() }
+            },
+            |_| (),
+            #[cfg(target_os = "android")]
+            None,
+        )
+        .await;
+
+        let api_address = address_cache.get_address().await;
+        assert!(
+            address_cache
+                .set_nat64_prefix(Some(Nat64Prefix::WELL_KNOWN))
+                .await
+        );
+        service.address_changed().await.unwrap();
+
+        // The firewall must be told to allow the NAT64 address
+        assert_eq!(
+            address_rx.next().await,
+            Some(Nat64Prefix::WELL_KNOWN.synthesize_socket_addr(api_address))
+        );
+    }
+
+    /// Connects to nothing, forever.
+    #[derive(Clone)]
+    struct StallingConnector;
+
+    impl hyper::service::Service<Uri> for StallingConnector {
+        type Response = tokio::net::TcpStream;
+        type Error = std::io::Error;
+        type Future = futures::future::Pending<std::io::Result<tokio::net::TcpStream>>;
+
+        fn poll_ready(
+            &mut self,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            futures::future::pending()
+        }
+    }
+
+    /// Serves a single connection. Once the request headers have been received, each of `chunks`
+    /// is sent after waiting for `delay`. The connection is then left open.
+    async fn serve(chunks: Vec<&'static str>, delay: Duration) -> std::net::SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut buf).await.unwrap();
+                if read == 0 {
+                    return;
+                }
+                request.extend_from_slice(&buf[..read]);
+            }
+            for chunk in chunks {
+                tokio::time::sleep(delay).await;
+                stream.write_all(chunk.as_bytes()).await.unwrap();
+            }
+            std::future::pending::<()>().await;
+        });
+        addr
+    }
+
+    fn get(addr: std::net::SocketAddr) -> Request {
+        hyper::Request::get(format!("http://{addr}/"))
+            .body(hyper::Body::empty())
+            .unwrap()
+    }
+
+    fn timeouts(connect: u64, write: u64, read: u64) -> Timeouts {
+        Timeouts {
+            connect: Duration::from_millis(connect),
+            write: Duration::from_millis(write),
+            read: Duration::from_millis(read),
+        }
+    }
+
+    const HEADERS: &str = "HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n";
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        let client = Client::builder().build::<_, hyper::Body>(StallingConnector);
+        let start = Instant::now();
+        let result = send_with_timeouts(
+            &client,
+            hyper::Request::get("http://127.0.0.1/")
+                .body(hyper::Body::empty())
+                .unwrap(),
+            RequestClass::Interactive,
+            timeouts(100, 2000, 2000),
+            async {},
+        )
+        .await;
+        assert!(matches!(result, Err(Error::TimeoutError(_))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Waiting until requests may be sent counts as connecting.
+        let addr = serve(vec![HEADERS, "0123456789"], Duration::ZERO).await;
+        let result = send_with_timeouts(
+            &Client::new(),
+            get(addr),
+            RequestClass::Interactive,
+            timeouts(100, 2000, 2000),
+            tokio::time::sleep(Duration::from_secs(2)),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::TimeoutError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        // Connecting succeeds, but there is never a response.
+        let addr = serve(vec![], Duration::ZERO).await;
+        let start = Instant::now();
+        let result = send_with_timeouts(
+            &Client::new(),
+            get(addr),
+            RequestClass::Interactive,
+            timeouts(2000, 100, 2000),
+            async {},
+        )
+        .await;
+        assert!(matches!(result, Err(Error::TimeoutError(_))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout_mid_body() {
+        let addr = serve(vec![HEADERS, "01234"], Duration::ZERO).await;
+        let start = Instant::now();
+        let response = send_with_timeouts(
+            &Client::new(),
+            get(addr),
+            RequestClass::Interactive,
+            timeouts(2000, 2000, 100),
+            async {},
+        )
+        .await
+        .expect("the headers should be received");
+        assert!(hyper::body::to_bytes(response.into_body()).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_slow_body() {
+        let chunks = vec![HEADERS, "01", "23", "45", "67", "89"];
+
+        // The read timeout applies to each chunk rather than to the whole body.
+        let addr = serve(chunks.clone(), Duration::from_millis(50)).await;
+        let response = send_with_timeouts(
+            &Client::new(),
+            get(addr),
+            RequestClass::Background,
+            timeouts(2000, 2000, 1000),
+            async {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+            "0123456789"
+        );
+
+        let addr = serve(chunks, Duration::from_millis(200)).await;
+        let response = send_with_timeouts(
+            &Client::new(),
+            get(addr),
+            RequestClass::Interactive,
+            timeouts(2000, 2000, 100),
+            async {},
+        )
+        .await
+        .unwrap();
+        assert!(hyper::body::to_bytes(response.into_body()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_progress_chunks() {
+        let body = vec![1u8; 3 * UPLOAD_CHUNK_SIZE + 10];
+        let uploaded = with_upload_progress(hyper::Body::from(body.clone()), body.len());
+        let chunks: Vec<Bytes> = uploaded.map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!(
+            chunks.iter().map(Bytes::len).collect::<Vec<_>>(),
+            [UPLOAD_CHUNK_SIZE, UPLOAD_CHUNK_SIZE, UPLOAD_CHUNK_SIZE, 10]
+        );
+        assert_eq!(chunks.concat(), body);
+    }
+
+    #[test]
+    fn test_class_timeouts() {
+        let interactive = RequestClass::Interactive.default_timeouts();
+        let background = RequestClass::Background.default_timeouts();
+        let upload = RequestClass::Upload.default_timeouts();
+        assert!(interactive.connect < background.connect);
+        assert!(interactive.read < background.read);
+        assert!(upload.write > background.write);
+
+        assert_eq!("100, 2000,300".parse(), Ok(timeouts(100, 2000, 300)));
+        assert_eq!(
+            "100,2000".parse::<Timeouts>(),
+            Err(ParseTimeoutsError::WrongNumberOfValues)
+        );
+        assert_eq!(
+            "100,2s,300".parse::<Timeouts>(),
+            Err(ParseTimeoutsError::InvalidValue("2s".to_owned()))
+        );
     }
 }
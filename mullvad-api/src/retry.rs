@@ -0,0 +1,333 @@
+//! Retrying of API requests which fail, according to a [`RetryPolicy`].
+//!
+//! The delay before each retry is multiplied by [`RetryPolicy::factor`], from
+//! [`RetryPolicy::base_delay`] up to [`RetryPolicy::max_delay`], and a random part of it, at most [`RetryPolicy::jitter`], is
+//! removed so that clients which fail at the same time do not retry at the same time.
+//!
+//! Only requests which can safely be sent more than once should be retried. For example, a
+//! voucher which is submitted twice is reported as used the second time even if the first
+//! submission succeeded.
+use once_cell::sync::Lazy;
+use rand::Rng;
+use std::{future::Future, str::FromStr, time::Duration};
+use talpid_types::ErrorExt;
+
+// `talpid_time::sleep` takes system sleep into account by using the system clock, which is not
+// affected by pausing the clock in tests.
+#[cfg(not(test))]
+use talpid_time::sleep;
+#[cfg(test)]
+use tokio::time::sleep;
+
+/// Overrides [`RetryPolicy::BACKGROUND`]. The value has the form
+/// `<max attempts>,<base delay in ms>,<max delay in ms>,<factor>,<jitter>`, where `0` attempts
+/// means that there is no limit.
+pub const BACKGROUND_POLICY_VAR: &str = "MULLVAD_API_BACKGROUND_RETRY_POLICY";
+
+static BACKGROUND_POLICY: Lazy<RetryPolicy> = Lazy::new(|| {
+    let Ok(value) = std::env::var(BACKGROUND_POLICY_VAR) else {
+        return RetryPolicy::BACKGROUND;
+    };
+    match value.parse() {
+        Ok(policy) => {
+            log::debug!("Using background retry policy {policy:?}");
+            policy
+        }
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!("Ignoring {BACKGROUND_POLICY_VAR}"))
+            );
+            RetryPolicy::BACKGROUND
+        }
+    }
+});
+
+#[derive(err_derive::Error, Debug, PartialEq)]
+pub enum ParsePolicyError {
+    #[error(display = "Expected five comma-separated values")]
+    WrongNumberOfValues,
+
+    #[error(display = "Invalid value: {}", _0)]
+    InvalidValue(String),
+
+    #[error(display = "The jitter must be between 0 and 1")]
+    InvalidJitter,
+}
+
+/// Determines how many times, and how often, a failed request is retried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of times the request is sent, including the first one. `None` means that
+    /// it is retried until it succeeds.
+    pub max_attempts: Option<u32>,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Upper bound on the delay between two attempts.
+    pub max_delay: Duration,
+    /// Number that each delay is multiplied by to get the next one.
+    pub factor: u32,
+    /// The largest fraction, between 0 and 1, of each delay which may be removed at random.
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// For requests made on behalf of the user, who is waiting for the result.
+    pub const INTERACTIVE: RetryPolicy = RetryPolicy {
+        max_attempts: Some(4),
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(2),
+        factor: 2,
+        jitter: 0.2,
+    };
+
+    /// For requests made in the background, such as fetching the relay list.
+    pub const BACKGROUND: RetryPolicy = RetryPolicy {
+        max_attempts: None,
+        base_delay: Duration::from_secs(16),
+        max_delay: Duration::from_secs(2 * 60 * 60),
+        factor: 8,
+        jitter: 0.5,
+    };
+
+    /// Sends the request once.
+    pub const NO_RETRY: RetryPolicy = RetryPolicy {
+        max_attempts: Some(1),
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+        factor: 1,
+        jitter: 0.0,
+    };
+
+    /// Returns [`Self::BACKGROUND`], or the policy in [`BACKGROUND_POLICY_VAR`] if it is set.
+    pub fn background() -> RetryPolicy {
+        *BACKGROUND_POLICY
+    }
+
+    /// Returns the delay before retry number `retry`, counting from 0. The jitter is taken from
+    /// `random`, which must be in the range `[0, 1)`.
+    fn delay(&self, retry: u32, random: f64) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(self.factor.checked_pow(retry).unwrap_or(u32::MAX))
+            .min(self.max_delay);
+        delay.mul_f64(1.0 - self.jitter * random)
+    }
+
+    /// Returns the delays between all attempts allowed by the policy.
+    pub fn delays(self) -> impl Iterator<Item = Duration> {
+        let retries = self.max_attempts.map(|attempts| attempts.saturating_sub(1));
+        (0..)
+            .take_while(move |retry| retries.map(|retries| *retry < retries).unwrap_or(true))
+            .map(move |retry| self.delay(retry, rand::thread_rng().gen()))
+    }
+}
+
+impl FromStr for RetryPolicy {
+    type Err = ParsePolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<&str> = s.split(',').map(str::trim).collect();
+        let [max_attempts, base_delay, max_delay, factor, jitter] = values[..] else {
+            return Err(ParsePolicyError::WrongNumberOfValues);
+        };
+        let parse_u64 = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| ParsePolicyError::InvalidValue(value.to_owned()))
+        };
+
+        let parse_u32 = |value: &str| {
+            value
+                .parse::<u32>()
+                .map_err(|_| ParsePolicyError::InvalidValue(value.to_owned()))
+        };
+
+        let max_attempts = parse_u32(max_attempts)?;
+        let jitter = jitter
+            .parse::<f64>()
+            .map_err(|_| ParsePolicyError::InvalidValue(jitter.to_owned()))?;
+        if !(0.0..=1.0).contains(&jitter) {
+            return Err(ParsePolicyError::InvalidJitter);
+        }
+
+        Ok(RetryPolicy {
+            max_attempts: Some(max_attempts).filter(|attempts| *attempts > 0),
+            base_delay: Duration::from_millis(parse_u64(base_delay)?),
+            max_delay: Duration::from_millis(parse_u64(max_delay)?),
+            factor: parse_u32(factor)?,
+            jitter,
+        })
+    }
+}
+
+/// Sends the request created by `factory` until `should_retry` returns `false` for the result,
+/// or until `policy` does not allow any more attempts.
+pub async fn retry_request<F, O, T, R>(
+    policy: RetryPolicy,
+    mut factory: F,
+    mut should_retry: R,
+) -> T
+where
+    F: FnMut() -> O,
+    O: Future<Output = T>,
+    R: FnMut(&T) -> bool,
+{
+    let mut delays = policy.delays();
+    loop {
+        let result = factory().await;
+        if should_retry(&result) {
+            if let Some(delay) = delays.next() {
+                sleep(delay).await;
+                continue;
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::time::Instant;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: Some(5),
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(5),
+        factor: 2,
+        jitter: 0.0,
+    };
+
+    /// Run a request which always fails, and return the times between its attempts.
+    async fn failing_request_delays(policy: RetryPolicy) -> Vec<Duration> {
+        let attempts = Arc::new(Mutex::new(vec![]));
+        let attempts_copy = attempts.clone();
+        retry_request(
+            policy,
+            move || {
+                attempts_copy.lock().unwrap().push(Instant::now());
+                async { Err::<(), ()>(()) }
+            },
+            |result| result.is_err(),
+        )
+        .await
+        .unwrap_err();
+
+        let attempts = attempts.lock().unwrap();
+        let delays = attempts
+            .windows(2)
+            .map(|pair| pair[1].duration_since(pair[0]))
+            .collect();
+        delays
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_sequence() {
+        let delays = failing_request_delays(POLICY).await;
+        assert_eq!(
+            delays,
+            [1, 2, 4, 5].map(Duration::from_secs),
+            "delays should double until the maximum delay"
+        );
+
+        let policy = RetryPolicy {
+            factor: 8,
+            max_delay: Duration::from_secs(100),
+            ..POLICY
+        };
+        assert_eq!(
+            failing_request_delays(policy).await,
+            [1, 8, 64, 100].map(Duration::from_secs),
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_jitter_bounds() {
+        let policy = RetryPolicy {
+            max_attempts: Some(50),
+            jitter: 0.5,
+            ..POLICY
+        };
+        let delays = failing_request_delays(policy).await;
+        assert_eq!(delays.len(), 49);
+        for (retry, delay) in delays.into_iter().enumerate() {
+            let unjittered = policy.delay(retry as u32, 0.0);
+            assert!(delay <= unjittered, "{delay:?} exceeds {unjittered:?}");
+            assert!(
+                delay >= unjittered / 2,
+                "{delay:?} is less than half of {unjittered:?}"
+            );
+        }
+
+        assert_eq!(policy.delay(0, 0.5), Duration::from_millis(750));
+        assert_eq!(
+            policy.delay(10, 0.99),
+            Duration::from_secs(5).mul_f64(0.505)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_on_success() {
+        let mut attempts = 0;
+        let start = Instant::now();
+        let result = retry_request(
+            POLICY,
+            || {
+                attempts += 1;
+                let result = if attempts < 3 { Err(()) } else { Ok(attempts) };
+                async move { result }
+            },
+            |result| result.is_err(),
+        )
+        .await;
+        assert_eq!(result, Ok(3));
+        assert_eq!(start.elapsed(), Duration::from_secs(1 + 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_retry() {
+        assert!(failing_request_delays(RetryPolicy::NO_RETRY)
+            .await
+            .is_empty());
+    }
+
+    #[test]
+    fn test_unlimited_attempts() {
+        assert_eq!(RetryPolicy::BACKGROUND.delays().take(100).count(), 100);
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(
+            "3, 100, 1000, 4, 0.25".parse(),
+            Ok(RetryPolicy {
+                max_attempts: Some(3),
+                base_delay: Duration::from_millis(100),
+                max_delay: Duration::from_secs(1),
+                factor: 4,
+                jitter: 0.25,
+            })
+        );
+        assert_eq!(
+            "0,100,1000,2,0"
+                .parse::<RetryPolicy>()
+                .unwrap()
+                .max_attempts,
+            None
+        );
+        assert_eq!(
+            "3,100,1000,0".parse::<RetryPolicy>(),
+            Err(ParsePolicyError::WrongNumberOfValues)
+        );
+        assert_eq!(
+            "3,100,1000,2,1.5".parse::<RetryPolicy>(),
+            Err(ParsePolicyError::InvalidJitter)
+        );
+        assert_eq!(
+            "3,-1,1000,2,0".parse::<RetryPolicy>(),
+            Err(ParsePolicyError::InvalidValue("-1".to_owned()))
+        );
+    }
+}
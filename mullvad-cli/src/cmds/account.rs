@@ -1,13 +1,63 @@
+use crate::{exit_code::InteractionRequired, format};
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use clap::Subcommand;
 use itertools::Itertools;
 use mullvad_management_interface::MullvadProxyClient;
-use mullvad_types::{account::AccountToken, device::DeviceState};
-use std::io::{self, Write};
+use mullvad_types::{
+    account::{AccountToken, SavedAccount},
+    device::DeviceState,
+    settings::AccountExpiryWarnings,
+};
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
 
 const NOT_LOGGED_IN_MESSAGE: &str = "Not logged in on any account";
 const REVOKED_MESSAGE: &str = "The current device has been revoked";
 
+/// What `mullvad account get` shows. It is also what `--json` prints, so the field names must not
+/// change.
+#[derive(Debug, Serialize)]
+struct AccountReport {
+    device: DeviceState,
+    /// Only set while logged in.
+    expiry: Option<DateTime<Utc>>,
+    /// The account that was last used. Only set if the device has been revoked.
+    account_token: Option<AccountToken>,
+    /// Only set with `--verbose`, while logged in.
+    expiry_warnings: Option<AccountExpiryWarnings>,
+}
+
+impl AccountReport {
+    async fn fetch(rpc: &mut MullvadProxyClient, verbose: bool) -> Result<Self> {
+        let _ = rpc.update_device().await;
+
+        let device = rpc.get_device().await?;
+        let mut report = AccountReport {
+            device,
+            expiry: None,
+            account_token: None,
+            expiry_warnings: None,
+        };
+        match &report.device {
+            DeviceState::LoggedIn(device) => {
+                let data = rpc.get_account_data(device.account_token.clone()).await?;
+                report.expiry = Some(data.expiry);
+                if verbose {
+                    report.expiry_warnings =
+                        Some(rpc.get_settings().await?.account_expiry_warnings);
+                }
+            }
+            DeviceState::LoggedOut => (),
+            DeviceState::Revoked => report.account_token = rpc.get_account_history().await?,
+        }
+        Ok(report)
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Account {
     /// Create and log in on a new account
@@ -17,8 +67,25 @@ pub enum Account {
     Login {
         /// The Mullvad account token to configure the client with
         account: Option<String>,
+
+        /// Give the account a label, which can be used to switch back to it later
+        #[arg(long)]
+        label: Option<String>,
+    },
+
+    /// List the accounts that have been used on this device, the most recently used first
+    ListSaved,
+
+    /// Log in to a saved account. The current device is removed and a new device is created on
+    /// the saved account
+    Switch {
+        /// Label or account number of the saved account
+        account: String,
     },
 
+    /// Set the label of the current account, or remove it if no label is given
+    Label { label: Option<String> },
+
     /// Log out of the current account
     Logout,
 
@@ -48,6 +115,10 @@ pub enum Account {
         /// Mullvad account number (current account if not specified)
         #[arg(long, short = 'a')]
         account: Option<String>,
+
+        /// Do not ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
     },
 
     /// Redeem a voucher
@@ -55,41 +126,118 @@ pub enum Account {
         /// Voucher code to submit
         voucher: String,
     },
+
+    /// Set how long before the account expires to warn about it, e.g. "3d 1d 1h". Warnings are
+    /// disabled if no thresholds are given
+    ExpiryWarnings {
+        /// Thresholds as a number followed by "d", "h" or "m"
+        #[arg(value_parser = parse_threshold, conflicts_with = "reset")]
+        thresholds: Vec<Duration>,
+
+        /// Restore the default thresholds
+        #[arg(long)]
+        reset: bool,
+    },
 }
 
 impl Account {
-    pub async fn handle(self) -> Result<()> {
+    pub async fn handle(self, json: bool, non_interactive: bool) -> Result<()> {
         let mut rpc = MullvadProxyClient::new().await?;
         match self {
             Account::Create => Self::create(&mut rpc).await,
-            Account::Login { account } => {
-                Self::login(
-                    &mut rpc,
-                    unwrap_or_from_stdin(account, "Enter an account number: ").await,
+            Account::Login { account, label } => {
+                let account = unwrap_or_from_stdin(
+                    account,
+                    "Enter an account number: ",
+                    non_interactive.then_some("Pass the account number as an argument"),
                 )
-                .await
+                .await?;
+                Self::login(&mut rpc, account, label).await
+            }
+            Account::ListSaved => Self::list_saved(&mut rpc).await,
+            Account::Switch { account } => Self::switch(&mut rpc, account).await,
+            Account::Label { label } => {
+                let token = account_else_current(&mut rpc, None).await?;
+                rpc.set_saved_account_label(token, label.clone()).await?;
+                match label {
+                    Some(label) => println!("Labeled the account \"{label}\""),
+                    None => println!("Removed the label"),
+                }
+                Ok(())
             }
             Account::Logout => Self::logout(&mut rpc).await,
-            Account::Get { verbose } => Self::get(&mut rpc, verbose).await,
+            Account::Get { verbose } => Self::get(&mut rpc, verbose, json).await,
             Account::ListDevices { account, verbose } => {
                 Self::list_devices(&mut rpc, account, verbose).await
             }
-            Account::RevokeDevice { device, account } => {
-                Self::revoke_device(&mut rpc, device, account).await
-            }
+            Account::RevokeDevice {
+                device,
+                account,
+                yes,
+            } => Self::revoke_device(&mut rpc, device, account, yes, non_interactive).await,
             Account::Redeem { voucher } => Self::redeem_voucher(&mut rpc, voucher).await,
+            Account::ExpiryWarnings { thresholds, reset } => {
+                let warnings = if reset {
+                    AccountExpiryWarnings::default()
+                } else {
+                    AccountExpiryWarnings { thresholds }
+                };
+                rpc.set_account_expiry_warnings(warnings.clone()).await?;
+                println!("Expiry warnings: {}", format_thresholds(&warnings));
+                Ok(())
+            }
         }
     }
 
     async fn create(rpc: &mut MullvadProxyClient) -> Result<()> {
         rpc.create_new_account().await?;
         println!("New account created!");
-        Self::get(rpc, false).await
+        Self::get(rpc, false, false).await
     }
 
-    async fn login(rpc: &mut MullvadProxyClient, token: AccountToken) -> Result<()> {
+    async fn login(
+        rpc: &mut MullvadProxyClient,
+        token: AccountToken,
+        label: Option<String>,
+    ) -> Result<()> {
         rpc.login_account(token.clone()).await?;
         println!("Mullvad account \"{token}\" set");
+        if let Some(label) = label {
+            rpc.set_saved_account_label(token, Some(label)).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_saved(rpc: &mut MullvadProxyClient) -> Result<()> {
+        let accounts = rpc.list_saved_accounts().await?;
+        if accounts.is_empty() {
+            println!("No saved accounts");
+            return Ok(());
+        }
+        let current_account = rpc
+            .get_device()
+            .await?
+            .into_device()
+            .map(|device| device.account_token);
+        for account in accounts {
+            println!(
+                "{}",
+                format_saved_account(&account, current_account.as_ref())
+            );
+        }
+        Ok(())
+    }
+
+    async fn switch(rpc: &mut MullvadProxyClient, label_or_account: String) -> Result<()> {
+        rpc.switch_account(label_or_account).await?;
+        match rpc.get_device().await? {
+            DeviceState::LoggedIn(device) => println!(
+                "Switched to Mullvad account \"{}\" as device \"{}\"",
+                device.account_token,
+                device.device.pretty_name()
+            ),
+            _ => println!("{NOT_LOGGED_IN_MESSAGE}"),
+        }
         Ok(())
     }
 
@@ -99,12 +247,13 @@ impl Account {
         Ok(())
     }
 
-    async fn get(rpc: &mut MullvadProxyClient, verbose: bool) -> Result<()> {
-        let _ = rpc.update_device().await;
-
-        let state = rpc.get_device().await?;
+    async fn get(rpc: &mut MullvadProxyClient, verbose: bool, json: bool) -> Result<()> {
+        let report = AccountReport::fetch(rpc, verbose).await?;
+        if json {
+            return format::print_json(&report);
+        }
 
-        match state {
+        match &report.device {
             DeviceState::LoggedIn(device) => {
                 println!("Mullvad account: {}", device.account_token);
                 println!("Device name    : {}", device.device.pretty_name());
@@ -113,18 +262,19 @@ impl Account {
                     println!("Device pubkey  : {}", device.device.pubkey);
                     println!("Device created : {}", device.device.created,);
                 }
-                let expiry = rpc.get_account_data(device.account_token).await?;
-                println!(
-                    "Expires at     : {}",
-                    expiry.expiry.with_timezone(&chrono::Local),
-                );
+                if let Some(expiry) = report.expiry {
+                    println!("Expires at     : {}", expiry.with_timezone(&chrono::Local));
+                }
+                if let Some(warnings) = &report.expiry_warnings {
+                    println!("Expiry warnings: {}", format_thresholds(warnings));
+                }
             }
             DeviceState::LoggedOut => {
                 println!("{NOT_LOGGED_IN_MESSAGE}");
             }
             DeviceState::Revoked => {
                 println!("{REVOKED_MESSAGE}");
-                if let Some(account_token) = rpc.get_account_history().await? {
+                if let Some(account_token) = &report.account_token {
                     println!("Mullvad account: {}", account_token);
                 }
             }
@@ -139,14 +289,20 @@ impl Account {
         verbose: bool,
     ) -> Result<()> {
         let token = account_else_current(rpc, account).await?;
+        let current_device = current_device_id(rpc).await?;
         let mut device_list = rpc.list_devices(token).await?;
 
         println!("Devices on the account:");
         device_list.sort_unstable_by_key(|dev| dev.created.timestamp());
         for device in device_list {
+            let current = if current_device.as_ref() == Some(&device.id) {
+                " (current device)"
+            } else {
+                ""
+            };
             if verbose {
                 println!();
-                println!("Name      : {}", device.pretty_name());
+                println!("Name      : {}{current}", device.pretty_name());
                 println!("Id        : {}", device.id);
                 println!("Public key: {}", device.pubkey);
                 println!(
@@ -154,7 +310,11 @@ impl Account {
                     device.created.with_timezone(&chrono::Local)
                 );
             } else {
-                println!("{}", device.pretty_name());
+                println!(
+                    "{}{current}, created {}",
+                    device.pretty_name(),
+                    device.created.with_timezone(&chrono::Local).date_naive()
+                );
             }
         }
 
@@ -165,27 +325,61 @@ impl Account {
         rpc: &mut MullvadProxyClient,
         device: String,
         account: Option<String>,
+        yes: bool,
+        non_interactive: bool,
     ) -> Result<()> {
         let token = account_else_current(rpc, account).await?;
 
         let device_list = rpc.list_devices(token.clone()).await?;
-        let device_id = device_list
+        let device = device_list
             .into_iter()
             .find(|dev| {
                 dev.name.eq_ignore_ascii_case(&device) || dev.id.eq_ignore_ascii_case(&device)
             })
-            .map(|dev| dev.id)
             .ok_or(mullvad_management_interface::Error::DeviceNotFound)?;
 
-        rpc.remove_device(token, device_id).await?;
+        let is_current_device = current_device_id(rpc).await?.as_ref() == Some(&device.id);
+        if !yes {
+            if non_interactive {
+                return Err(InteractionRequired("Pass --yes to revoke the device").into());
+            }
+            let prompt = if is_current_device {
+                format!(
+                    "\"{}\" is the current device. Revoke it and log out?",
+                    device.pretty_name()
+                )
+            } else {
+                format!(
+                    "Are you sure you want to revoke \"{}\"?",
+                    device.pretty_name()
+                )
+            };
+            if !receive_confirmation(prompt).await {
+                return Ok(());
+            }
+        }
+
+        rpc.remove_device(token, device.id).await?;
         println!("Removed device");
+        if is_current_device {
+            println!("Logged out since the current device was removed");
+        }
         Ok(())
     }
 
     async fn redeem_voucher(rpc: &mut MullvadProxyClient, mut voucher: String) -> Result<()> {
         voucher.retain(|c| c.is_alphanumeric());
 
-        let submission = rpc.submit_voucher(voucher).await?;
+        let submission = match rpc.submit_voucher(voucher).await {
+            Ok(submission) => submission,
+            Err(mullvad_management_interface::Error::VoucherRateLimited(Some(retry_after))) => {
+                return Err(anyhow!(
+                    "Too many attempts to redeem a voucher. Try again in {}",
+                    format_duration(retry_after.as_secs())
+                ));
+            }
+            Err(error) => return Err(error.into()),
+        };
         println!(
             "Added {} to the account",
             format_duration(submission.time_added)
@@ -214,14 +408,50 @@ async fn account_else_current(
     }
 }
 
-async fn unwrap_or_from_stdin(val: Option<String>, prompt_str: &'static str) -> String {
+async fn current_device_id(rpc: &mut MullvadProxyClient) -> Result<Option<String>> {
+    Ok(rpc
+        .get_device()
+        .await?
+        .into_device()
+        .map(|account| account.device.id))
+}
+
+async fn receive_confirmation(prompt: String) -> bool {
+    println!("{prompt} [Yes/No (default)]");
+
+    tokio::task::spawn_blocking(|| loop {
+        let mut buf = String::new();
+        if let Err(e) = io::stdin().read_line(&mut buf) {
+            eprintln!("Couldn't read from STDIN: {e}");
+            return false;
+        }
+        match buf.trim() {
+            "Yes" | "yes" => return true,
+            "No" | "no" | "" => return false,
+            _ => eprintln!("Unexpected response. Please enter \"Yes\" or \"No\""),
+        }
+    })
+    .await
+    .unwrap()
+}
+
+/// Returns `val`, or asks for it. If asking is not allowed, `non_interactive` says how to pass
+/// the value instead.
+async fn unwrap_or_from_stdin(
+    val: Option<String>,
+    prompt_str: &'static str,
+    non_interactive: Option<&'static str>,
+) -> Result<String> {
     if let Some(val) = val {
-        return val;
+        return Ok(val);
+    }
+    if let Some(hint) = non_interactive {
+        return Err(InteractionRequired(hint).into());
     }
 
-    tokio::task::spawn_blocking(|| from_stdin(prompt_str))
+    Ok(tokio::task::spawn_blocking(|| from_stdin(prompt_str))
         .await
-        .unwrap()
+        .unwrap())
 }
 
 fn from_stdin(prompt_str: &'static str) -> String {
@@ -236,6 +466,20 @@ fn from_stdin(prompt_str: &'static str) -> String {
     val.split_whitespace().join("")
 }
 
+fn format_saved_account(account: &SavedAccount, current_account: Option<&AccountToken>) -> String {
+    let mut line = account.account_token.clone();
+    if let Some(label) = &account.label {
+        line.push_str(&format!(" \"{label}\""));
+    }
+    if current_account == Some(&account.account_token) {
+        line.push_str(" (current account)");
+    }
+    if let Some(device_name) = &account.device_name {
+        line.push_str(&format!(", last used as device \"{device_name}\""));
+    }
+    line
+}
+
 fn format_duration(seconds: u64) -> String {
     let dur = chrono::Duration::seconds(seconds as i64);
     if dur.num_days() > 0 {
@@ -248,3 +492,115 @@ fn format_duration(seconds: u64) -> String {
         format!("{} seconds", dur.num_seconds())
     }
 }
+
+fn format_thresholds(warnings: &AccountExpiryWarnings) -> String {
+    if warnings.thresholds.is_empty() {
+        return "none".to_owned();
+    }
+    warnings
+        .thresholds
+        .iter()
+        .map(|threshold| format!("{} before", format_duration(threshold.as_secs())))
+        .join(", ")
+}
+
+fn parse_threshold(threshold: &str) -> Result<Duration, String> {
+    let invalid =
+        || format!("Expected a number followed by \"d\", \"h\" or \"m\", got \"{threshold}\"");
+    let (split, _) = threshold.char_indices().last().ok_or_else(invalid)?;
+    let (count, unit) = threshold.split_at(split);
+    let unit = match unit {
+        "d" => 24 * 60 * 60,
+        "h" => 60 * 60,
+        "m" => 60,
+        _ => return Err(invalid()),
+    };
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    count
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_saved_account() {
+        let current = "1234123412341234".to_owned();
+        let account = SavedAccount {
+            account_token: current.clone(),
+            label: Some("work".to_owned()),
+            device_name: Some("happy seal".to_owned()),
+        };
+        assert_eq!(
+            format_saved_account(&account, Some(&current)),
+            "1234123412341234 \"work\" (current account), last used as device \"happy seal\""
+        );
+        assert_eq!(
+            format_saved_account(&SavedAccount::new("5678567856785678".to_owned()), None),
+            "5678567856785678"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_non_interactive_login() {
+        let account = unwrap_or_from_stdin(
+            Some("1234123412341234".to_owned()),
+            "Enter an account number: ",
+            Some("Pass the account number as an argument"),
+        )
+        .await;
+        assert_eq!(account.unwrap(), "1234123412341234");
+
+        let error = unwrap_or_from_stdin(
+            None,
+            "Enter an account number: ",
+            Some("Pass the account number as an argument"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            crate::exit_code::ExitCode::from_error(&error),
+            crate::exit_code::ExitCode::InteractionRequired
+        );
+    }
+
+    #[test]
+    fn test_parse_threshold() {
+        assert_eq!(
+            parse_threshold("3d"),
+            Ok(Duration::from_secs(3 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_threshold("12h"),
+            Ok(Duration::from_secs(12 * 60 * 60))
+        );
+        assert_eq!(parse_threshold("30m"), Ok(Duration::from_secs(30 * 60)));
+        for invalid in ["", "d", "3", "3s", "-1h", "1.5d"] {
+            assert!(parse_threshold(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_account_json() {
+        let report = AccountReport {
+            device: DeviceState::Revoked,
+            expiry: None,
+            account_token: Some("1234123412341234".to_owned()),
+            expiry_warnings: Some(AccountExpiryWarnings {
+                thresholds: vec![Duration::from_secs(60 * 60)],
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(report).unwrap(),
+            serde_json::json!({
+                "device": "revoked",
+                "expiry": null,
+                "account_token": "1234123412341234",
+                "expiry_warnings": { "thresholds": [{ "secs": 3600, "nanos": 0 }] },
+            })
+        );
+    }
+}
@@ -1,8 +1,13 @@
 use anyhow::{anyhow, Result};
 use mullvad_management_interface::MullvadProxyClient;
-use mullvad_types::access_method::{AccessMethod, AccessMethodSetting, CustomAccessMethod};
+use mullvad_types::access_method::{
+    AccessMethod, AccessMethodSetting, AccessMethodStats, CustomAccessMethod,
+};
+use serde::Serialize;
 use std::net::IpAddr;
 
+use super::BooleanOption;
+use crate::format;
 use clap::{Args, Subcommand};
 use talpid_types::net::openvpn::SHADOWSOCKS_CIPHERS;
 
@@ -16,7 +21,11 @@ pub enum ApiAccess {
     /// Lists all API access methods
     ///
     /// * = Enabled
-    List,
+    List {
+        /// Show whether each enabled access method has been able to reach the API recently
+        #[arg(long, short = 'v')]
+        verbose: bool,
+    },
     /// Edit a custom API access method
     Edit(EditCustomCommands),
     /// Remove a custom API access method
@@ -31,15 +40,52 @@ pub enum ApiAccess {
     ///
     /// Selecting "Mullvad Bridges" respects your current bridge settings
     Use(SelectItem),
-    /// Try to reach the Mullvad API using a specific access method
+    /// Try to reach the Mullvad API using a specific access method, and show how long each stage
+    /// of connecting takes
     Test(SelectItem),
+    /// Try to reach the Mullvad API using every configured access method, and compare how long
+    /// each stage of connecting takes
+    TestAll,
+    /// Import an API access method which has been shared by someone else
+    ///
+    /// Both strings created by `export` and Shadowsocks URIs (ss://) are accepted.
+    Import {
+        /// The shared access method
+        encoded: String,
+    },
+    /// Print a custom API access method as a string which can be shared with someone else
+    ///
+    /// Anyone who has the string can use the access method, including any credentials.
+    Export(SelectItem),
+    /// Send API traffic inside the tunnel while connected, instead of using the access methods
+    #[clap(subcommand)]
+    PreferTunnel(PreferTunnel),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum PreferTunnel {
+    /// Display whether API traffic is sent inside the tunnel while connected
+    Get,
+    /// Change whether API traffic is sent inside the tunnel while connected. The access methods
+    /// are still used while not connected.
+    Set { policy: BooleanOption },
+}
+
+/// An access method as listed by `mullvad api-access list`. It is also what `--json` prints, so
+/// the field names must not change.
+#[derive(Debug, Serialize)]
+struct ListEntry<'a> {
+    #[serde(flatten)]
+    method: &'a AccessMethodSetting,
+    /// Only set with `--verbose`, and only once the access method has been used.
+    stats: Option<&'a AccessMethodStats>,
 }
 
 impl ApiAccess {
-    pub async fn handle(self) -> Result<()> {
+    pub async fn handle(self, json: bool) -> Result<()> {
         match self {
-            ApiAccess::List => {
-                Self::list().await?;
+            ApiAccess::List { verbose } => {
+                Self::list(verbose, json).await?;
             }
             ApiAccess::Add(cmd) => {
                 Self::add(cmd).await?;
@@ -55,25 +101,73 @@ impl ApiAccess {
             ApiAccess::Test(cmd) => {
                 Self::test(cmd).await?;
             }
+            ApiAccess::TestAll => {
+                Self::test_all().await?;
+            }
+            ApiAccess::Import { encoded } => {
+                Self::import(encoded).await?;
+            }
+            ApiAccess::Export(cmd) => {
+                Self::export(cmd).await?;
+            }
             ApiAccess::Use(cmd) => {
                 Self::set(cmd).await?;
             }
             ApiAccess::Get => {
                 Self::get().await?;
             }
+            ApiAccess::PreferTunnel(PreferTunnel::Get) => {
+                Self::get_prefer_tunnel().await?;
+            }
+            ApiAccess::PreferTunnel(PreferTunnel::Set { policy }) => {
+                Self::set_prefer_tunnel(policy).await?;
+            }
         };
         Ok(())
     }
 
+    async fn get_prefer_tunnel() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let prefer_tunnel = BooleanOption::from(rpc.get_settings().await?.prefer_api_in_tunnel);
+        println!("Prefer tunnel for API traffic: {prefer_tunnel}");
+        Ok(())
+    }
+
+    async fn set_prefer_tunnel(policy: BooleanOption) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        rpc.set_prefer_api_in_tunnel(*policy).await?;
+        println!("Changed API tunnel preference");
+        Ok(())
+    }
+
     /// Show all API access methods.
-    async fn list() -> Result<()> {
+    async fn list(verbose: bool, json: bool) -> Result<()> {
         let mut rpc = MullvadProxyClient::new().await?;
-        for (index, api_access_method) in rpc.get_api_access_methods().await?.iter().enumerate() {
+        let stats = if verbose {
+            rpc.get_api_access_method_stats().await?
+        } else {
+            vec![]
+        };
+        let methods = rpc.get_api_access_methods().await?;
+        let entries: Vec<_> = methods
+            .iter()
+            .map(|method| ListEntry {
+                method,
+                stats: stats.iter().find(|stats| stats.id == method.get_id()),
+            })
+            .collect();
+        if json {
+            return format::print_json(&entries);
+        }
+        for (index, entry) in entries.iter().enumerate() {
             println!(
                 "{}. {}",
                 index + 1,
-                pp::ApiAccessMethodFormatter::new(api_access_method)
+                pp::ApiAccessMethodFormatter::new(entry.method)
             );
+            if let Some(stats) = entry.stats {
+                pp::print_stats(stats);
+            }
         }
         Ok(())
     }
@@ -141,6 +235,28 @@ impl ApiAccess {
                         .map(AccessMethod::from)
                     }
                 },
+                CustomAccessMethod::HttpProxy(http) => {
+                    let ip = cmd.params.ip.unwrap_or(http.peer.ip()).to_string();
+                    let port = cmd.params.port.unwrap_or(http.peer.port());
+                    let authentication = match (
+                        http.authentication,
+                        cmd.params.username,
+                        cmd.params.password,
+                    ) {
+                        (Some(auth), username, password) => {
+                            Some(mullvad_types::access_method::HttpProxyAuth {
+                                username: username.unwrap_or(auth.username),
+                                password: password.unwrap_or(auth.password),
+                            })
+                        }
+                        (None, Some(username), Some(password)) => {
+                            Some(mullvad_types::access_method::HttpProxyAuth { username, password })
+                        }
+                        (None, _, _) => None,
+                    };
+                    mullvad_types::access_method::HttpProxy::from_args(ip, port, authentication)
+                        .map(AccessMethod::from)
+                }
             },
         };
 
@@ -177,25 +293,61 @@ impl ApiAccess {
     /// Test an access method to see if it successfully reaches the Mullvad API.
     async fn test(item: SelectItem) -> Result<()> {
         let mut rpc = MullvadProxyClient::new().await?;
-        // Retrieve the currently used access method. We will reset to this
-        // after we are done testing.
-        let previous_access_method = rpc.get_current_api_access_method().await?;
         let access_method = Self::get_access_method(&mut rpc, &item).await?;
 
         println!("Testing access method \"{}\"", access_method.name);
-        rpc.set_access_method(access_method.get_id()).await?;
-        // Make the daemon perform an network request which involves talking to the Mullvad API.
-        let result = match rpc.get_api_addresses().await {
-            Ok(_) => {
+        // The daemon connects to the API using the access method without switching to it.
+        let report = rpc.test_api_access_method(access_method.get_id()).await?;
+        pp::print_test_report(&report);
+        match report.failure {
+            None => {
                 println!("Success!");
                 Ok(())
             }
-            Err(_) => Err(anyhow!("Could not reach the Mullvad API")),
-        };
-        // In any case, switch back to the previous access method.
-        rpc.set_access_method(previous_access_method.get_id())
-            .await?;
-        result
+            Some((stage, _)) => Err(anyhow!("Could not reach the Mullvad API ({stage} failed)")),
+        }
+    }
+
+    /// Test every configured access method, one at a time, and print a table comparing them.
+    async fn test_all() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let access_methods = rpc.get_api_access_methods().await?;
+
+        pp::print_test_table_header();
+        for access_method in &access_methods {
+            let report = rpc.test_api_access_method(access_method.get_id()).await?;
+            pp::print_test_table_row(access_method, &report);
+        }
+        Ok(())
+    }
+
+    /// Add an access method from a string created by `export`, or a Shadowsocks URI.
+    async fn import(encoded: String) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let id = rpc.import_api_access_method(encoded).await?;
+        let access_method = rpc
+            .get_api_access_methods()
+            .await?
+            .into_iter()
+            .find(|access_method| access_method.get_id() == id)
+            .ok_or(anyhow!("The imported access method was not found"))?;
+        println!("Imported access method \"{}\"", access_method.name);
+        println!("It is disabled until it is enabled using `mullvad api-access enable`");
+        Ok(())
+    }
+
+    /// Print a custom access method as a string which can be imported by others.
+    async fn export(item: SelectItem) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let access_method = Self::get_access_method(&mut rpc, &item).await?;
+        if access_method.is_builtin() {
+            return Err(anyhow!("Can not export built-in access method"));
+        }
+        println!(
+            "{}",
+            rpc.export_api_access_method(access_method.get_id()).await?
+        );
+        Ok(())
     }
 
     /// Try to use of a specific [`AccessMethodSetting`] for subsequent calls to
@@ -286,6 +438,21 @@ pub enum AddCustomCommands {
         #[arg(default_value_t = false, short, long)]
         disabled: bool,
     },
+    /// Configure an HTTP proxy which supports the CONNECT method
+    HttpProxy {
+        /// An easy to remember name for this custom proxy
+        name: String,
+        /// The IP of the HTTP proxy
+        remote_ip: IpAddr,
+        /// Port on which the HTTP proxy listens for traffic
+        remote_port: u16,
+        #[clap(flatten)]
+        authentication: Option<HttpProxyAuthentication>,
+        /// Disable the use of this custom access method. It has to be manually
+        /// enabled at a later stage to be used when accessing the Mullvad API.
+        #[arg(default_value_t = false, short, long)]
+        disabled: bool,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -332,10 +499,21 @@ pub struct SocksAuthentication {
     password: String,
 }
 
+#[derive(Args, Debug, Clone)]
+pub struct HttpProxyAuthentication {
+    /// Username for Basic authentication against an HTTP proxy
+    #[arg(short, long)]
+    username: String,
+    /// Password for Basic authentication against an HTTP proxy
+    #[arg(short, long)]
+    password: String,
+}
+
 impl AddCustomCommands {
     fn name(&self) -> &str {
         match self {
             AddCustomCommands::Shadowsocks { name, .. }
+            | AddCustomCommands::HttpProxy { name, .. }
             | AddCustomCommands::Socks5(AddSocks5Commands::Remote { name, .. })
             | AddCustomCommands::Socks5(AddSocks5Commands::Local { name, .. }) => name,
         }
@@ -344,6 +522,7 @@ impl AddCustomCommands {
     fn enabled(&self) -> bool {
         match self {
             AddCustomCommands::Shadowsocks { disabled, .. }
+            | AddCustomCommands::HttpProxy { disabled, .. }
             | AddCustomCommands::Socks5(AddSocks5Commands::Remote { disabled, .. })
             | AddCustomCommands::Socks5(AddSocks5Commands::Local { disabled, .. }) => !disabled,
         }
@@ -388,19 +567,19 @@ pub struct EditParams {
     /// Name of the API access method in the Mullvad client [All]
     #[arg(long)]
     name: Option<String>,
-    /// Username for authentication [Socks5 (Remote proxy)]
+    /// Username for authentication [Socks5 (Remote proxy), HTTP proxy]
     #[arg(long)]
     username: Option<String>,
-    /// Password for authentication [Socks5 (Remote proxy), Shadowsocks]
+    /// Password for authentication [Socks5 (Remote proxy), Shadowsocks, HTTP proxy]
     #[arg(long)]
     password: Option<String>,
     /// Cipher to use [Shadowsocks]
     #[arg(value_parser = SHADOWSOCKS_CIPHERS, long)]
     cipher: Option<String>,
-    /// The IP of the remote proxy server [Socks5 (Local & Remote proxy), Shadowsocks]
+    /// The IP of the remote proxy server [Socks5 (Local & Remote proxy), Shadowsocks, HTTP proxy]
     #[arg(long)]
     ip: Option<IpAddr>,
-    /// The port of the remote proxy server [Socks5 (Local & Remote proxy), Shadowsocks]
+    /// The port of the remote proxy server [Socks5 (Local & Remote proxy), Shadowsocks, HTTP proxy]
     #[arg(long)]
     port: Option<u16>,
     /// The port that the server on localhost is listening on [Socks5 (Local proxy)]
@@ -416,7 +595,9 @@ mod conversions {
     use anyhow::{anyhow, Error};
     use mullvad_types::access_method as daemon_types;
 
-    use super::{AddCustomCommands, AddSocks5Commands, SocksAuthentication};
+    use super::{
+        AddCustomCommands, AddSocks5Commands, HttpProxyAuthentication, SocksAuthentication,
+    };
 
     impl TryFrom<AddCustomCommands> for daemon_types::AccessMethod {
         type Error = Error;
@@ -491,6 +672,30 @@ mod conversions {
                     .map(daemon_types::AccessMethod::from)
                     .ok_or(anyhow!("Could not create a Shadowsocks access method"))?
                 }
+                AddCustomCommands::HttpProxy {
+                    remote_ip,
+                    remote_port,
+                    authentication,
+                    name: _,
+                    disabled: _,
+                } => {
+                    let authentication = authentication.map(
+                        |HttpProxyAuthentication { username, password }| {
+                            println!("Adding HTTP proxy: {username}@{remote_ip}:{remote_port}");
+                            daemon_types::HttpProxyAuth { username, password }
+                        },
+                    );
+                    if authentication.is_none() {
+                        println!("Adding HTTP proxy: {remote_ip}:{remote_port}");
+                    }
+                    daemon_types::HttpProxy::from_args(
+                        remote_ip.to_string(),
+                        remote_port,
+                        authentication,
+                    )
+                    .map(daemon_types::AccessMethod::from)
+                    .ok_or(anyhow!("Could not create an HTTP proxy access method"))?
+                }
             })
         }
     }
@@ -499,8 +704,119 @@ mod conversions {
 /// Pretty printing of [`ApiAccessMethod`]s
 mod pp {
     use mullvad_types::access_method::{
-        AccessMethod, AccessMethodSetting, CustomAccessMethod, Socks5, SocksAuth,
+        AccessMethod, AccessMethodSetting, AccessMethodStats, AccessMethodTestReport,
+        ConnectionStage, CustomAccessMethod, Socks5, SocksAuth,
     };
+    use std::time::{Duration, SystemTime};
+
+    /// Print the duration of each stage of an access method test, and the error of the stage
+    /// which failed.
+    pub fn print_test_report(report: &AccessMethodTestReport) {
+        // Same layout as `print_option!`, which only accepts literal labels.
+        let print_stage = |stage: &ConnectionStage, value: String| {
+            println!(
+                "{:<4}{:<24}{}",
+                "",
+                format!("{}:", capitalize(stage)),
+                value
+            )
+        };
+        for (stage, duration) in &report.timings {
+            print_stage(stage, format_duration(*duration));
+        }
+        if let Some((stage, error)) = &report.failure {
+            print_stage(stage, format!("failed: {error}"));
+        }
+    }
+
+    const TEST_TABLE_STAGES: [ConnectionStage; 4] = [
+        ConnectionStage::TcpConnect,
+        ConnectionStage::ProxyHandshake,
+        ConnectionStage::TlsHandshake,
+        ConnectionStage::HttpRoundTrip,
+    ];
+
+    pub fn print_test_table_header() {
+        println!(
+            "{:<24} {:>8} {:>8} {:>8} {:>8} {:>8}  Result",
+            "Access method", "TCP", "Proxy", "TLS", "HTTP", "Total"
+        );
+    }
+
+    /// Print the outcome of testing an access method as a row of the table started by
+    /// [`print_test_table_header`]. Stages which were not run are shown as `-`.
+    pub fn print_test_table_row(
+        api_access_method: &AccessMethodSetting,
+        report: &AccessMethodTestReport,
+    ) {
+        let timings = TEST_TABLE_STAGES.map(|stage| {
+            report
+                .timings
+                .iter()
+                .find(|(completed_stage, _)| *completed_stage == stage)
+                .map(|(_, duration)| format_duration(*duration))
+                .unwrap_or_else(|| "-".to_string())
+        });
+        let result = match &report.failure {
+            None => "OK".to_string(),
+            Some((stage, error)) => format!("{stage} failed: {error}"),
+        };
+        println!(
+            "{:<24} {:>8} {:>8} {:>8} {:>8} {:>8}  {result}",
+            api_access_method.get_name(),
+            timings[0],
+            timings[1],
+            timings[2],
+            timings[3],
+            format_duration(report.total()),
+        );
+    }
+
+    fn format_duration(duration: Duration) -> String {
+        format!("{} ms", duration.as_millis())
+    }
+
+    fn capitalize(stage: &ConnectionStage) -> String {
+        let stage = stage.to_string();
+        let mut chars = stage.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => stage,
+        }
+    }
+
+    /// Print how well an access method has worked since the daemon started.
+    pub fn print_stats(stats: &AccessMethodStats) {
+        use crate::print_option;
+
+        let now = SystemTime::now();
+        let reachability = match (stats.reachable(), stats.cooldown_until) {
+            (_, Some(cooldown_until)) => {
+                let retry_in = cooldown_until.duration_since(now).unwrap_or_default();
+                format!("unreachable, retried in {}s", retry_in.as_secs())
+            }
+            (Some(true), None) => "reachable".to_string(),
+            (Some(false), None) => "unreachable".to_string(),
+            (None, None) => "unknown".to_string(),
+        };
+        print_option!("Reachability", reachability);
+        print_option!(
+            "Requests",
+            format!("{} succeeded, {} failed", stats.successes, stats.failures)
+        );
+        if let Some(last_used) = stats.last_used {
+            print_option!("Last used", format_time(last_used));
+        }
+        if let Some(last_error) = &stats.last_error {
+            print_option!("Last error", last_error);
+        }
+    }
+
+    fn format_time(time: SystemTime) -> String {
+        chrono::DateTime::<chrono::Local>::from(time)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    }
 
     pub struct ApiAccessMethodFormatter<'a> {
         api_access_method: &'a AccessMethodSetting,
@@ -541,6 +857,13 @@ mod pp {
                     write!(f, "")
                 }
             };
+            let write_name = |f: &mut std::fmt::Formatter<'_>| -> std::fmt::Result {
+                write!(f, "{}", self.api_access_method.get_name())?;
+                if self.api_access_method.imported {
+                    write!(f, " (imported)")?;
+                }
+                Ok(())
+            };
 
             match &self.api_access_method.access_method {
                 AccessMethod::BuiltIn(method) => {
@@ -552,7 +875,7 @@ mod pp {
                 }
                 AccessMethod::Custom(method) => match &method {
                     CustomAccessMethod::Shadowsocks(shadowsocks) => {
-                        write!(f, "{}", self.api_access_method.get_name())?;
+                        write_name(f)?;
                         if self.settings.write_enabled {
                             write_status(f, self.api_access_method.enabled())?;
                         }
@@ -564,7 +887,7 @@ mod pp {
                     }
                     CustomAccessMethod::Socks5(socks) => match socks {
                         Socks5::Remote(remote) => {
-                            write!(f, "{}", self.api_access_method.get_name())?;
+                            write_name(f)?;
                             if self.settings.write_enabled {
                                 write_status(f, self.api_access_method.enabled())?;
                             }
@@ -581,7 +904,7 @@ mod pp {
                             Ok(())
                         }
                         Socks5::Local(local) => {
-                            write!(f, "{}", self.api_access_method.get_name())?;
+                            write_name(f)?;
                             if self.settings.write_enabled {
                                 write_status(f, self.api_access_method.enabled())?;
                             }
@@ -592,8 +915,52 @@ mod pp {
                             Ok(())
                         }
                     },
+                    CustomAccessMethod::HttpProxy(http) => {
+                        write_name(f)?;
+                        if self.settings.write_enabled {
+                            write_status(f, self.api_access_method.enabled())?;
+                        }
+                        writeln!(f)?;
+                        print_option!("Protocol", "HTTP proxy");
+                        print_option!("Peer", http.peer);
+                        if let Some(auth) = &http.authentication {
+                            print_option!("Username", auth.username);
+                            print_option!("Password", "<hidden>");
+                        }
+                        Ok(())
+                    }
                 },
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::access_method::BuiltInAccessMethod;
+
+    #[test]
+    fn test_list_json() {
+        let method = AccessMethodSetting::new(
+            "Direct".to_owned(),
+            true,
+            AccessMethod::BuiltIn(BuiltInAccessMethod::Direct),
+        );
+        let entry = ListEntry {
+            method: &method,
+            stats: None,
+        };
+        assert_eq!(
+            serde_json::to_value(entry).unwrap(),
+            serde_json::json!({
+                "id": method.get_id(),
+                "name": "Direct",
+                "enabled": true,
+                "access_method": { "BuiltIn": "Direct" },
+                "imported": false,
+                "stats": null,
+            })
+        );
+    }
+}
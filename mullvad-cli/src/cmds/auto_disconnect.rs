@@ -0,0 +1,49 @@
+use anyhow::Result;
+use clap::Subcommand;
+use mullvad_management_interface::MullvadProxyClient;
+use std::time::Duration;
+
+#[derive(Subcommand, Debug)]
+pub enum AutoDisconnect {
+    /// Display how long the tunnel must be idle to be disconnected
+    Get,
+    /// Disconnect the tunnel once next to no traffic has passed through it for MINUTES. It is not
+    /// connected again until you connect it. Only WireGuard tunnels are disconnected
+    Set {
+        #[arg(value_parser = clap::value_parser!(u64).range(1..=24 * 60))]
+        minutes: u64,
+    },
+    /// Never disconnect the tunnel for being idle
+    Off,
+}
+
+impl AutoDisconnect {
+    pub async fn handle(self) -> Result<()> {
+        match self {
+            AutoDisconnect::Get => Self::get().await,
+            AutoDisconnect::Set { minutes } => {
+                Self::set(Some(Duration::from_secs(minutes * 60))).await
+            }
+            AutoDisconnect::Off => Self::set(None).await,
+        }
+    }
+
+    async fn set(timeout: Option<Duration>) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        rpc.set_auto_disconnect_idle(timeout).await?;
+        println!("Changed idle auto-disconnect setting");
+        Ok(())
+    }
+
+    async fn get() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        match rpc.get_settings().await?.auto_disconnect_idle {
+            Some(timeout) => println!(
+                "Disconnect after being idle for: {} minutes",
+                timeout.as_secs() / 60
+            ),
+            None => println!("Disconnect after being idle: off"),
+        }
+        Ok(())
+    }
+}
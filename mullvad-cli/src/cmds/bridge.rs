@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Subcommand;
 use mullvad_management_interface::MullvadProxyClient;
 use mullvad_types::{
@@ -8,7 +8,10 @@ use mullvad_types::{
     },
     relay_list::RelayEndpointData,
 };
-use std::net::{IpAddr, SocketAddr};
+use std::{
+    io::BufRead,
+    net::{IpAddr, SocketAddr},
+};
 use talpid_types::net::openvpn::{self, SHADOWSOCKS_CIPHERS};
 
 use super::{relay::find_relay_by_hostname, relay_constraints::LocationArgs};
@@ -117,12 +120,18 @@ pub enum SetCustomCommands {
         /// The port of the remote proxy server
         remote_port: u16,
 
-        /// Username for authentication
+        /// Username for authentication. Prefer --auth-stdin, since arguments end up in the shell
+        /// history
         #[arg(requires = "password")]
         username: Option<String>,
-        /// Password for authentication
+        /// Password for authentication. Prefer --auth-stdin, since arguments end up in the shell
+        /// history
         #[arg(requires = "username")]
         password: Option<String>,
+
+        /// Read the credentials from standard input, formatted as 'username:password'
+        #[arg(long, conflicts_with_all = ["username", "password"])]
+        auth_stdin: bool,
     },
 
     /// Configure bundled Shadowsocks proxy
@@ -218,11 +227,13 @@ impl Bridge {
                 remote_port,
                 username,
                 password,
+                auth_stdin,
             } => {
                 let auth = match (username, password) {
                     (Some(username), Some(password)) => {
                         Some(openvpn::ProxyAuth { username, password })
                     }
+                    _ if auth_stdin => Some(Self::read_proxy_auth_from_stdin().await?),
                     _ => None,
                 };
                 let proxy = openvpn::RemoteProxySettings {
@@ -305,12 +316,25 @@ impl Bridge {
 
         if let Some(ref auth) = proxy.auth {
             println!("  auth username: {}", auth.username);
-            println!("  auth password: {}", auth.password);
+            println!("  auth password: <hidden>");
         } else {
             println!("  auth: none");
         }
     }
 
+    async fn read_proxy_auth_from_stdin() -> Result<openvpn::ProxyAuth> {
+        println!("Reading 'username:password' from standard input");
+
+        let line = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line).map(|_| line)
+        })
+        .await
+        .unwrap()?;
+
+        parse_proxy_auth(&line)
+    }
+
     fn print_shadowsocks_proxy(proxy: &openvpn::ShadowsocksProxySettings) {
         println!("proxy: Shadowsocks");
         println!("  peer address: {}", proxy.peer);
@@ -410,3 +434,68 @@ impl Bridge {
         Ok(())
     }
 }
+
+/// Parse SOCKS5 credentials formatted as `username:password`. Only the first colon separates the
+/// two parts, so the password may contain colons.
+fn parse_proxy_auth(line: &str) -> Result<openvpn::ProxyAuth> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (username, password) = line
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Expected credentials formatted as 'username:password'"))?;
+    if username.is_empty() || password.is_empty() {
+        return Err(anyhow!("The username and password must not be empty"));
+    }
+    Ok(openvpn::ProxyAuth {
+        username: username.to_owned(),
+        password: password.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser, Debug)]
+    struct TestCli {
+        #[clap(subcommand)]
+        cmd: SetCustomCommands,
+    }
+
+    #[test]
+    fn test_parse_proxy_auth() {
+        let auth = parse_proxy_auth("user:pass:word\n").unwrap();
+        assert_eq!(auth.username, "user");
+        assert_eq!(auth.password, "pass:word");
+
+        assert!(parse_proxy_auth("user\n").is_err());
+        assert!(parse_proxy_auth(":pass\n").is_err());
+        assert!(parse_proxy_auth("user:\n").is_err());
+    }
+
+    #[test]
+    fn test_remote_auth_stdin_args() {
+        let cli =
+            TestCli::try_parse_from(["test", "remote", "1.2.3.4", "1080", "--auth-stdin"]).unwrap();
+        assert!(matches!(
+            cli.cmd,
+            SetCustomCommands::Remote {
+                username: None,
+                password: None,
+                auth_stdin: true,
+                ..
+            }
+        ));
+
+        let result = TestCli::try_parse_from([
+            "test",
+            "remote",
+            "1.2.3.4",
+            "1080",
+            "user",
+            "pass",
+            "--auth-stdin",
+        ]);
+        assert!(result.is_err());
+    }
+}
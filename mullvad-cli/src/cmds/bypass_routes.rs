@@ -0,0 +1,80 @@
+use anyhow::Result;
+use clap::Subcommand;
+use ipnetwork::IpNetwork;
+use mullvad_management_interface::MullvadProxyClient;
+
+/// Manage networks that are routed outside the tunnel. Traffic to these networks is not protected
+/// by the VPN.
+#[derive(Subcommand, Debug)]
+pub enum BypassRoutes {
+    /// List the networks that are routed outside the tunnel
+    Get,
+
+    /// Route a network outside the tunnel
+    Add { network: IpNetwork },
+
+    /// Route a network through the tunnel again
+    Remove { network: IpNetwork },
+
+    /// Route all networks through the tunnel again
+    Clear,
+}
+
+impl BypassRoutes {
+    pub async fn handle(self) -> Result<()> {
+        match self {
+            BypassRoutes::Get => Self::get().await,
+            BypassRoutes::Add { network } => Self::add(network).await,
+            BypassRoutes::Remove { network } => Self::remove(network).await,
+            BypassRoutes::Clear => Self::set(vec![]).await,
+        }
+    }
+
+    async fn get() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let routes = rpc.get_settings().await?.bypass_routes;
+        if routes.is_empty() {
+            println!("No networks are routed outside the tunnel");
+        } else {
+            println!("Networks routed outside the tunnel:");
+            for network in routes {
+                println!("{network}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn add(network: IpNetwork) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let mut routes = rpc.get_settings().await?.bypass_routes;
+        if routes.contains(&network) {
+            println!("{network} is already routed outside the tunnel");
+            return Ok(());
+        }
+        routes.push(network);
+        rpc.set_bypass_routes(routes).await?;
+        println!("Routing {network} outside the tunnel");
+        Ok(())
+    }
+
+    async fn remove(network: IpNetwork) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let mut routes = rpc.get_settings().await?.bypass_routes;
+        let count = routes.len();
+        routes.retain(|route| *route != network);
+        if routes.len() == count {
+            println!("{network} is not routed outside the tunnel");
+            return Ok(());
+        }
+        rpc.set_bypass_routes(routes).await?;
+        println!("Routing {network} through the tunnel");
+        Ok(())
+    }
+
+    async fn set(routes: Vec<IpNetwork>) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        rpc.set_bypass_routes(routes).await?;
+        println!("Routing all networks through the tunnel");
+        Ok(())
+    }
+}
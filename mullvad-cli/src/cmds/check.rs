@@ -0,0 +1,227 @@
+use crate::{exit_code::ExitCode, format};
+use anyhow::{anyhow, Result};
+use mullvad_management_interface::{Error, MullvadProxyClient};
+use mullvad_types::{connection_check::ConnectionCheck, dns_leak::DnsLeakVerdict};
+use std::fmt;
+
+/// Returned after the result of a check that found traffic outside the tunnel has been printed.
+#[derive(Debug)]
+pub struct NotViaMullvad;
+
+impl NotViaMullvad {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::NotViaMullvad
+    }
+}
+
+impl fmt::Display for NotViaMullvad {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The tunnel is connected, but traffic does not go through it")
+    }
+}
+
+impl std::error::Error for NotViaMullvad {}
+
+pub async fn handle(allow_disconnected: bool, json: bool) -> Result<()> {
+    let mut rpc = MullvadProxyClient::new().await?;
+    if !json {
+        println!("Checking the connection...");
+    }
+    let check = match rpc.check_connection(allow_disconnected).await {
+        Err(Error::ConnectionCheckNotConnected) => {
+            return Err(anyhow!(
+                "The tunnel is not connected. Pass --allow-disconnected to check the connection \
+                 outside of it"
+            ))
+        }
+        result => result?,
+    };
+
+    if json {
+        format::print_json(&check)?;
+    } else {
+        print!("{}", format_check(&check));
+    }
+    if check.bypasses_tunnel() {
+        return Err(NotViaMullvad.into());
+    }
+    Ok(())
+}
+
+fn summary(check: &ConnectionCheck) -> &'static str {
+    let dns_leaking = check
+        .dns_leak_test
+        .as_ref()
+        .map(|result| result.verdict == DnsLeakVerdict::Leaking)
+        .unwrap_or(false);
+    match check {
+        ConnectionCheck {
+            connected: false,
+            mullvad_exit_ip: false,
+            ..
+        } => "You are not connected to Mullvad",
+        ConnectionCheck {
+            connected: false, ..
+        } => "The tunnel is not connected, but traffic goes through Mullvad",
+        ConnectionCheck {
+            mullvad_exit_ip: false,
+            ..
+        } => "Traffic does not go through Mullvad, although the tunnel is connected",
+        ConnectionCheck {
+            via_connected_relay: Some(false),
+            ..
+        } => "Traffic goes through another Mullvad relay than the one the tunnel is connected to",
+        _ if dns_leaking => "Traffic goes through Mullvad, but DNS lookups leak outside the tunnel",
+        _ => "You are connected to Mullvad",
+    }
+}
+
+fn format_check(check: &ConnectionCheck) -> String {
+    let mut lines = vec![summary(check).to_owned()];
+    let mut field = |name: &str, value: &dyn fmt::Display| {
+        lines.push(format!("{:<4}{:<16}{value}", "", format!("{name}:")));
+    };
+
+    if let Some(ipv4) = check.ipv4 {
+        field("Exit IPv4", &ipv4);
+    }
+    if let Some(ipv6) = check.ipv6 {
+        field("Exit IPv6", &ipv6);
+    }
+    match &check.city {
+        Some(city) => field("Location", &format!("{city}, {}", check.country)),
+        None => field("Location", &check.country),
+    }
+    if let Some(relay) = &check.exit_relay {
+        let relay = match check.via_connected_relay {
+            Some(true) => format!("{relay} (the connected relay)"),
+            Some(false) => format!("{relay} (not the connected relay)"),
+            None => relay.clone(),
+        };
+        field("Exit relay", &relay);
+    }
+    let dns = match &check.dns_leak_test {
+        Some(result) => match result.verdict {
+            DnsLeakVerdict::NoLeak => "no leak".to_owned(),
+            DnsLeakVerdict::Leaking => {
+                let resolvers: Vec<_> = result
+                    .leaking_resolvers
+                    .iter()
+                    .map(|resolver| resolver.to_string())
+                    .collect();
+                format!("leaking to {}", resolvers.join(", "))
+            }
+            DnsLeakVerdict::Inconclusive => "inconclusive".to_owned(),
+        },
+        None if check.connected => "failed".to_owned(),
+        None => "only run while connected".to_owned(),
+    };
+    field("DNS leak test", &dns);
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::dns_leak::DnsLeakTestResult;
+
+    fn connected_check() -> ConnectionCheck {
+        ConnectionCheck {
+            connected: true,
+            ipv4: Some("192.0.2.1".parse().unwrap()),
+            ipv6: None,
+            country: "Sweden".to_owned(),
+            city: Some("Gothenburg".to_owned()),
+            mullvad_exit_ip: true,
+            exit_relay: Some("se-got-wg-001".to_owned()),
+            via_connected_relay: Some(true),
+            dns_leak_test: Some(DnsLeakTestResult {
+                verdict: DnsLeakVerdict::NoLeak,
+                observed_resolvers: vec![],
+                leaking_resolvers: vec![],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_format_check() {
+        assert_eq!(
+            format_check(&connected_check()),
+            "You are connected to Mullvad
+    Exit IPv4:      192.0.2.1
+    Location:       Gothenburg, Sweden
+    Exit relay:     se-got-wg-001 (the connected relay)
+    DNS leak test:  no leak
+"
+        );
+
+        let disconnected = ConnectionCheck {
+            connected: false,
+            ipv4: None,
+            ipv6: Some("2001:db8::1".parse().unwrap()),
+            city: None,
+            mullvad_exit_ip: false,
+            exit_relay: None,
+            via_connected_relay: None,
+            dns_leak_test: None,
+            ..connected_check()
+        };
+        assert_eq!(
+            format_check(&disconnected),
+            "You are not connected to Mullvad
+    Exit IPv6:      2001:db8::1
+    Location:       Sweden
+    DNS leak test:  only run while connected
+"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(summary(&connected_check()), "You are connected to Mullvad");
+
+        let leaking = ConnectionCheck {
+            dns_leak_test: Some(DnsLeakTestResult {
+                verdict: DnsLeakVerdict::Leaking,
+                observed_resolvers: vec![],
+                leaking_resolvers: vec!["198.51.100.53".parse().unwrap()],
+            }),
+            ..connected_check()
+        };
+        assert_eq!(
+            summary(&leaking),
+            "Traffic goes through Mullvad, but DNS lookups leak outside the tunnel"
+        );
+        assert!(format_check(&leaking).contains("leaking to 198.51.100.53"));
+
+        let other_relay = ConnectionCheck {
+            via_connected_relay: Some(false),
+            ..connected_check()
+        };
+        assert!(format_check(&other_relay).contains("se-got-wg-001 (not the connected relay)"));
+
+        let outside = ConnectionCheck {
+            mullvad_exit_ip: false,
+            exit_relay: None,
+            via_connected_relay: Some(false),
+            ..connected_check()
+        };
+        assert_eq!(
+            summary(&outside),
+            "Traffic does not go through Mullvad, although the tunnel is connected"
+        );
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let json = serde_json::to_value(connected_check()).unwrap();
+        assert_eq!(json["connected"], true);
+        assert_eq!(json["ipv4"], "192.0.2.1");
+        assert_eq!(json["ipv6"], serde_json::Value::Null);
+        assert_eq!(json["mullvad_exit_ip"], true);
+        assert_eq!(json["exit_relay"], "se-got-wg-001");
+        assert_eq!(json["via_connected_relay"], true);
+        assert_eq!(json["dns_leak_test"]["verdict"], "no_leak");
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use clap::Subcommand;
+use mullvad_management_interface::MullvadProxyClient;
+
+use super::BooleanOption;
+
+#[derive(Subcommand, Debug)]
+pub enum CoexistenceMode {
+    /// Display the current coexistence mode setting
+    Get,
+    /// Change coexistence mode setting. When it is on, the default route is left to other VPNs,
+    /// and traffic is routed into the tunnel using two more specific routes instead. The firewall
+    /// still blocks traffic outside the tunnel.
+    Set { policy: BooleanOption },
+}
+
+impl CoexistenceMode {
+    pub async fn handle(self) -> Result<()> {
+        match self {
+            CoexistenceMode::Get => Self::get().await,
+            CoexistenceMode::Set { policy } => Self::set(policy).await,
+        }
+    }
+
+    async fn set(policy: BooleanOption) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        rpc.set_coexistence_mode(*policy).await?;
+        println!("Changed coexistence mode setting");
+        Ok(())
+    }
+
+    async fn get() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let coexistence_mode = BooleanOption::from(rpc.get_settings().await?.coexistence_mode);
+        println!("Coexistence mode: {coexistence_mode}");
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+//! Completion candidates that depend on the daemon, such as locations and custom list names. The
+//! shell completion scripts call the hidden `__complete` subcommand to get them, and fall back to
+//! the static completions if it prints nothing.
+
+use anyhow::Result;
+use mullvad_management_interface::MullvadProxyClient;
+use mullvad_types::relay_list::{RelayEndpointData, RelayList, RelayListCountry};
+use std::{future::Future, time::Duration};
+
+/// How long to wait for the daemon. Completion must not hang the shell if the daemon is slow.
+const DAEMON_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What the word that is being completed refers to.
+#[derive(Debug, PartialEq, Eq)]
+enum Context<'a> {
+    /// Location arguments, i.e. a country, city and hostname. `args` are the ones that have
+    /// already been given.
+    Location {
+        bridges: bool,
+        args: Vec<&'a str>,
+    },
+    CustomList,
+}
+
+/// Prints the candidates for the last of `words`, one per line. `words` are the words that follow
+/// `mullvad` on the command line. Nothing is printed if there are no dynamic candidates, or if
+/// the daemon cannot be reached.
+pub async fn handle(words: Vec<String>) -> Result<()> {
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let Some((current, preceding)) = words.split_last() else {
+        return Ok(());
+    };
+    let Some(context) = context(preceding) else {
+        return Ok(());
+    };
+    let candidates = match context {
+        Context::Location { bridges, args } => {
+            match query(|mut rpc| async move { rpc.get_relay_locations().await }).await {
+                Some(relay_list) => location_candidates(&relay_list, bridges, &args),
+                None => vec![],
+            }
+        }
+        Context::CustomList => {
+            match query(|mut rpc| async move { rpc.get_settings().await }).await {
+                Some(settings) => settings
+                    .custom_lists
+                    .into_iter()
+                    .map(|list| list.name)
+                    .collect(),
+                None => vec![],
+            }
+        }
+    };
+    for candidate in filter(candidates, current) {
+        println!("{candidate}");
+    }
+    Ok(())
+}
+
+/// Runs `request` against the daemon. Returns `None` if it fails or takes too long.
+async fn query<T, F>(request: impl FnOnce(MullvadProxyClient) -> F) -> Option<T>
+where
+    F: Future<Output = std::result::Result<T, mullvad_management_interface::Error>>,
+{
+    let request = async {
+        let rpc = MullvadProxyClient::new().await.ok()?;
+        request(rpc).await.ok()
+    };
+    tokio::time::timeout(DAEMON_TIMEOUT, request)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Returns what the next word refers to, given the `words` before it.
+fn context<'a>(words: &[&'a str]) -> Option<Context<'a>> {
+    let positional: Vec<&str> = words
+        .iter()
+        .copied()
+        .filter(|word| !word.starts_with('-'))
+        .collect();
+    match positional.as_slice() {
+        ["relay", "set", "location", args @ ..] => Some(Context::Location {
+            bridges: false,
+            args: args.to_vec(),
+        }),
+        ["bridge", "set", "location", args @ ..] => Some(Context::Location {
+            bridges: true,
+            args: args.to_vec(),
+        }),
+        ["relay", "set", "custom-list"]
+        | ["bridge", "set", "custom-list"]
+        | ["custom-list", "list" | "delete"]
+        | ["custom-list", "edit", "add" | "remove" | "rename"] => Some(Context::CustomList),
+        ["custom-list", "edit", "add" | "remove", _name, args @ ..] => Some(Context::Location {
+            bridges: false,
+            args: args.to_vec(),
+        }),
+        // The WireGuard options take values, so only look at what follows the subcommand
+        ["relay", "set", "tunnel", "wireguard", rest @ ..] => {
+            match rest.iter().rposition(|word| *word == "entry-location") {
+                Some(index) => Some(Context::Location {
+                    bridges: false,
+                    args: rest[index + 1..].to_vec(),
+                }),
+                None if rest.last() == Some(&"custom-list") => Some(Context::CustomList),
+                None => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the candidates for the next location argument, given the ones in `args`. The first
+/// argument may be a country code or a hostname.
+fn location_candidates(relay_list: &RelayList, bridges: bool, args: &[&str]) -> Vec<String> {
+    let countries: Vec<RelayListCountry> = relay_list
+        .countries
+        .iter()
+        .cloned()
+        .filter_map(|mut country| {
+            country.cities.retain_mut(|city| {
+                city.relays.retain(|relay| {
+                    relay.active && (relay.endpoint_data == RelayEndpointData::Bridge) == bridges
+                });
+                !city.relays.is_empty()
+            });
+            (!country.cities.is_empty()).then_some(country)
+        })
+        .collect();
+    let find_country = |code: &str| {
+        countries
+            .iter()
+            .find(|country| country.code.eq_ignore_ascii_case(code))
+    };
+
+    match args {
+        [] => {
+            let hostnames = countries
+                .iter()
+                .flat_map(|country| &country.cities)
+                .flat_map(|city| &city.relays)
+                .map(|relay| relay.hostname.clone());
+            countries
+                .iter()
+                .map(|country| country.code.clone())
+                .chain(hostnames)
+                .collect()
+        }
+        [country] => find_country(country)
+            .map(|country| {
+                country
+                    .cities
+                    .iter()
+                    .map(|city| city.code.clone())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        [country, city] => find_country(country)
+            .and_then(|country| {
+                country
+                    .cities
+                    .iter()
+                    .find(|candidate| candidate.code.eq_ignore_ascii_case(city))
+            })
+            .map(|city| {
+                city.relays
+                    .iter()
+                    .map(|relay| relay.hostname.clone())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        _ => vec![],
+    }
+}
+
+/// Returns the candidates that start with `prefix`, ignoring case.
+fn filter(candidates: Vec<String>, prefix: &str) -> impl Iterator<Item = String> {
+    let prefix = prefix.to_lowercase();
+    candidates
+        .into_iter()
+        .filter(move |candidate| candidate.to_lowercase().starts_with(&prefix))
+}
+
+/// Writes the completion script for `shell` to `dir`. The scripts for bash, zsh and fish also ask
+/// `__complete` for dynamic candidates.
+#[cfg(all(unix, not(target_os = "android")))]
+pub fn write_script(
+    shell: clap_complete::Shell,
+    cmd: &mut clap::Command,
+    bin_name: &str,
+    dir: &std::path::Path,
+) -> Result<std::path::PathBuf> {
+    use clap_complete::{Generator, Shell};
+
+    let mut script = vec![];
+    clap_complete::generate(shell, cmd, bin_name, &mut script);
+    let script = String::from_utf8(script)?;
+    let script = match shell {
+        Shell::Bash => script + &BASH_DYNAMIC.replace("BIN", bin_name),
+        Shell::Fish => script + &FISH_DYNAMIC.replace("BIN", bin_name),
+        Shell::Zsh => with_zsh_dynamic(&script, bin_name)
+            .ok_or_else(|| anyhow::anyhow!("Unexpected layout of the zsh completion script"))?,
+        _ => script,
+    };
+    let path = dir.join(shell.file_name(bin_name));
+    std::fs::write(&path, script)?;
+    Ok(path)
+}
+
+#[cfg(all(unix, not(target_os = "android")))]
+const BASH_DYNAMIC: &str = r#"
+# Complete locations and custom list names by asking the daemon
+_BIN_dynamic() {
+    local candidates
+    mapfile -t candidates < <(BIN __complete "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null)
+    if [[ ${#candidates[@]} -gt 0 ]]; then
+        COMPREPLY=("${candidates[@]}")
+        return 0
+    fi
+    _BIN "$@"
+}
+
+complete -F _BIN_dynamic -o bashdefault -o default BIN
+"#;
+
+#[cfg(all(unix, not(target_os = "android")))]
+const FISH_DYNAMIC: &str = r#"
+# Complete locations and custom list names by asking the daemon
+complete -c BIN -f -a '(BIN __complete (commandline -opc)[2..] (commandline -ct) 2>/dev/null)'
+"#;
+
+#[cfg(all(unix, not(target_os = "android")))]
+const ZSH_DYNAMIC: &str = r#"# Complete locations and custom list names by asking the daemon
+_BIN() {
+    local -a candidates
+    candidates=(${(f)"$(BIN __complete "${(@)words[2,CURRENT]}" 2>/dev/null)"})
+    if (( ${#candidates} )); then
+        compadd -a candidates
+    else
+        _BIN_static "$@"
+    fi
+}
+
+"#;
+
+/// Renames the generated completion function, and puts one which tries `__complete` first in its
+/// place. Returns `None` if the script does not look as expected.
+#[cfg(all(unix, not(target_os = "android")))]
+fn with_zsh_dynamic(script: &str, bin_name: &str) -> Option<String> {
+    let definition = format!("\n_{bin_name}() {{\n");
+    let dispatch = format!("if [ \"$funcstack[1]\" = \"_{bin_name}\" ]; then");
+    if !script.contains(&definition) || !script.contains(&dispatch) {
+        return None;
+    }
+    let script = script.replacen(&definition, &format!("\n_{bin_name}_static() {{\n"), 1);
+    let dynamic = ZSH_DYNAMIC.replace("BIN", bin_name);
+    Some(script.replacen(&dispatch, &format!("{dynamic}{dispatch}"), 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::relay_list::{Relay, RelayListCity};
+
+    fn relay(hostname: &str, endpoint_data: RelayEndpointData) -> Relay {
+        Relay {
+            hostname: hostname.to_owned(),
+            ipv4_addr_in: "10.0.0.1".parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: "provider".to_owned(),
+            weight: 1,
+            endpoint_data,
+            location: None,
+        }
+    }
+
+    fn city(code: &str, relays: Vec<Relay>) -> RelayListCity {
+        RelayListCity {
+            name: code.to_owned(),
+            code: code.to_owned(),
+            latitude: 0.0,
+            longitude: 0.0,
+            relays,
+        }
+    }
+
+    fn relay_list() -> RelayList {
+        let mut inactive = relay("se-sto-ovpn-001", RelayEndpointData::Openvpn);
+        inactive.active = false;
+        RelayList {
+            countries: vec![
+                RelayListCountry {
+                    name: "Sweden".to_owned(),
+                    code: "se".to_owned(),
+                    cities: vec![
+                        city(
+                            "got",
+                            vec![
+                                relay("se-got-ovpn-001", RelayEndpointData::Openvpn),
+                                relay("se-got-br-001", RelayEndpointData::Bridge),
+                            ],
+                        ),
+                        city("sto", vec![inactive]),
+                    ],
+                },
+                RelayListCountry {
+                    name: "Germany".to_owned(),
+                    code: "de".to_owned(),
+                    cities: vec![city(
+                        "ber",
+                        vec![relay("de-ber-ovpn-001", RelayEndpointData::Openvpn)],
+                    )],
+                },
+            ],
+            ..RelayList::empty()
+        }
+    }
+
+    #[test]
+    fn test_context() {
+        assert_eq!(
+            context(&["relay", "set", "location", "se"]),
+            Some(Context::Location {
+                bridges: false,
+                args: vec!["se"]
+            })
+        );
+        assert_eq!(
+            context(&["bridge", "set", "location"]),
+            Some(Context::Location {
+                bridges: true,
+                args: vec![]
+            })
+        );
+        assert_eq!(
+            context(&["custom-list", "edit", "add", "work", "se"]),
+            Some(Context::Location {
+                bridges: false,
+                args: vec!["se"]
+            })
+        );
+        assert_eq!(
+            context(&[
+                "relay",
+                "set",
+                "tunnel",
+                "wireguard",
+                "--port",
+                "53",
+                "entry-location"
+            ]),
+            Some(Context::Location {
+                bridges: false,
+                args: vec![]
+            })
+        );
+        assert_eq!(
+            context(&["custom-list", "edit", "rename"]),
+            Some(Context::CustomList)
+        );
+        assert_eq!(
+            context(&["relay", "set", "tunnel", "wireguard", "custom-list"]),
+            Some(Context::CustomList)
+        );
+        assert_eq!(context(&["custom-list", "edit", "rename", "work"]), None);
+        assert_eq!(context(&["relay", "set"]), None);
+    }
+
+    #[test]
+    fn test_location_candidates() {
+        let relay_list = relay_list();
+        // Cities without active relays of the right kind are left out
+        assert_eq!(
+            location_candidates(&relay_list, false, &[]),
+            vec!["se", "de", "se-got-ovpn-001", "de-ber-ovpn-001"]
+        );
+        assert_eq!(
+            location_candidates(&relay_list, false, &["SE"]),
+            vec!["got"]
+        );
+        assert_eq!(
+            location_candidates(&relay_list, false, &["se", "got"]),
+            vec!["se-got-ovpn-001"]
+        );
+        assert_eq!(
+            location_candidates(&relay_list, true, &["se", "got"]),
+            vec!["se-got-br-001"]
+        );
+        assert!(location_candidates(&relay_list, false, &["se", "sto"]).is_empty());
+        assert!(
+            location_candidates(&relay_list, false, &["se", "got", "se-got-ovpn-001"]).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let candidates = location_candidates(&relay_list(), false, &[]);
+        assert_eq!(
+            filter(candidates, "Se").collect::<Vec<_>>(),
+            vec!["se", "se-got-ovpn-001"]
+        );
+    }
+
+    #[cfg(all(unix, not(target_os = "android")))]
+    #[test]
+    fn test_zsh_script() {
+        let mut script = vec![];
+        let mut cmd = clap::Command::new("mullvad").subcommand(clap::Command::new("relay"));
+        clap_complete::generate(clap_complete::Shell::Zsh, &mut cmd, "mullvad", &mut script);
+        let script = with_zsh_dynamic(&String::from_utf8(script).unwrap(), "mullvad").unwrap();
+        assert!(script.contains("\n_mullvad_static() {\n"));
+        assert!(script.contains("mullvad __complete"));
+    }
+}
@@ -2,13 +2,18 @@ use super::{
     relay::{find_relay_by_hostname, get_filtered_relays},
     relay_constraints::LocationArgs,
 };
-use anyhow::{anyhow, Result};
+use crate::exit_code::UsageError;
+use anyhow::{anyhow, Context, Result};
 use clap::Subcommand;
 use mullvad_management_interface::MullvadProxyClient;
 use mullvad_types::{
     relay_constraints::{Constraint, GeographicLocationConstraint},
     relay_list::RelayList,
 };
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 #[derive(Subcommand, Debug)]
 pub enum CustomList {
@@ -20,8 +25,6 @@ pub enum CustomList {
 
     /// Show all custom lists or retrieve a specific custom list
     List {
-        // TODO: Would be cool to provide dynamic auto-completion:
-        // https://github.com/clap-rs/clap/issues/1232
         /// A custom list. If omitted, all custom lists are shown
         name: Option<String>,
     },
@@ -35,6 +38,26 @@ pub enum CustomList {
         /// A custom list
         name: String,
     },
+
+    /// Write the locations of a custom list to a file, one per line
+    Export {
+        /// A custom list
+        name: String,
+        /// File to write to. If omitted, the locations are printed
+        file: Option<PathBuf>,
+    },
+
+    /// Replace the locations of a custom list with those in a file, creating the list if needed
+    ///
+    /// Each line holds a country code, a country and city code, a country, city and hostname,
+    /// or only a hostname. Empty lines and anything after '#' are ignored. Locations that are
+    /// not in the relay list are imported anyway, with a warning.
+    Import {
+        /// A custom list
+        name: String,
+        /// File to read from. If omitted, the locations are read from standard input
+        file: Option<PathBuf>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -43,8 +66,11 @@ pub enum EditCommand {
     Add {
         /// A custom list
         name: String,
+        /// Read any number of locations from standard input, in the format of `import`
+        #[arg(long, conflicts_with = "country")]
+        many: bool,
         #[command(flatten)]
-        location: LocationArgs,
+        location: Option<LocationArgs>,
     },
 
     /// Remove a location from some custom list
@@ -71,8 +97,21 @@ impl CustomList {
             CustomList::List { name: Some(name) } => Self::get(name).await,
             CustomList::New { name } => Self::create_list(name).await,
             CustomList::Delete { name } => Self::delete_list(name).await,
+            CustomList::Export { name, file } => Self::export_list(name, file.as_deref()).await,
+            CustomList::Import { name, file } => Self::import_list(name, file.as_deref()).await,
             CustomList::Edit(cmd) => match cmd {
-                EditCommand::Add { name, location } => Self::add_location(name, location).await,
+                EditCommand::Add {
+                    name, many: true, ..
+                } => Self::add_many_locations(name).await,
+                EditCommand::Add {
+                    name,
+                    location: Some(location),
+                    ..
+                } => Self::add_location(name, location).await,
+                EditCommand::Add { location: None, .. } => Err(UsageError(
+                    "Specify a location, or pass --many to read them from standard input",
+                )
+                .into()),
                 EditCommand::Rename { name, new_name } => Self::rename_list(name, new_name).await,
                 EditCommand::Remove { name, location } => {
                     Self::remove_location(name, location).await
@@ -123,6 +162,22 @@ impl CustomList {
         Ok(())
     }
 
+    async fn add_many_locations(name: String) -> Result<()> {
+        let input = read_input(None)?;
+        let mut rpc = MullvadProxyClient::new().await?;
+        let relay_list = rpc.get_relay_locations().await?;
+        let locations = parse_locations(&input, &relay_list)?;
+        print_unknown_locations(&locations, &relay_list);
+
+        let mut list = find_list_by_name(&mut rpc, &name).await?;
+        let count = locations.len();
+        list.locations.extend(locations);
+        rpc.update_custom_list(list).await?;
+
+        println!("Added {count} location(s) to {name}");
+        Ok(())
+    }
+
     async fn remove_location(name: String, location_args: LocationArgs) -> Result<()> {
         let location = Constraint::<GeographicLocationConstraint>::from(location_args)
             .option()
@@ -144,6 +199,39 @@ impl CustomList {
         Ok(())
     }
 
+    async fn export_list(name: String, file: Option<&Path>) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let list = find_list_by_name(&mut rpc, &name).await?;
+        let document = format_locations(&list.locations);
+        match file {
+            Some(path) => {
+                std::fs::write(path, document)
+                    .with_context(|| format!("Failed to write to {}", path.display()))?;
+                println!(
+                    "Wrote {} location(s) to {}",
+                    list.locations.len(),
+                    path.display()
+                );
+            }
+            None => print!("{document}"),
+        }
+        Ok(())
+    }
+
+    async fn import_list(name: String, file: Option<&Path>) -> Result<()> {
+        let input = read_input(file)?;
+        let mut rpc = MullvadProxyClient::new().await?;
+        let relay_list = rpc.get_relay_locations().await?;
+        let locations = parse_locations(&input, &relay_list)?;
+        print_unknown_locations(&locations, &relay_list);
+
+        let count = locations.len();
+        rpc.replace_custom_list(name.clone(), locations).await?;
+
+        println!("Imported {count} location(s) into {name}");
+        Ok(())
+    }
+
     async fn rename_list(name: String, new_name: String) -> Result<()> {
         let mut rpc = MullvadProxyClient::new().await?;
 
@@ -234,6 +322,137 @@ impl<'a> std::fmt::Display for GeographicLocationConstraintFormatter<'a> {
     }
 }
 
+/// Formats locations in the format read by [`parse_locations`], one per line.
+fn format_locations<'a>(
+    locations: impl IntoIterator<Item = &'a GeographicLocationConstraint>,
+) -> String {
+    locations
+        .into_iter()
+        .map(|location| match location {
+            GeographicLocationConstraint::Country(country) => format!("{country}\n"),
+            GeographicLocationConstraint::City(country, city) => format!("{country} {city}\n"),
+            GeographicLocationConstraint::Hostname(country, city, hostname) => {
+                format!("{country} {city} {hostname}\n")
+            }
+        })
+        .collect()
+}
+
+/// Parses one location per line. A line holds a country code, a country and city code, a
+/// country, city and hostname, or only a hostname. The country and city of a lone hostname are
+/// looked up in `relay_list`, or taken from the hostname if it is not in the relay list.
+fn parse_locations(
+    input: &str,
+    relay_list: &RelayList,
+) -> Result<Vec<GeographicLocationConstraint>> {
+    let mut locations = vec![];
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let location = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [] => continue,
+            [code] if code.eq_ignore_ascii_case("any") => {
+                return Err(anyhow!(
+                    "Line {line_number}: \"any\" is not a valid location"
+                ));
+            }
+            [hostname] if hostname.contains('-') => {
+                find_relay_by_hostname(&relay_list.countries, hostname)
+                    .or_else(|| location_from_hostname(hostname))
+                    .ok_or_else(|| {
+                        anyhow!("Line {line_number}: \"{hostname}\" is not a valid hostname")
+                    })?
+            }
+            [country] => GeographicLocationConstraint::Country(country.to_lowercase()),
+            [country, city] => {
+                GeographicLocationConstraint::City(country.to_lowercase(), city.to_lowercase())
+            }
+            [country, city, hostname] => GeographicLocationConstraint::Hostname(
+                country.to_lowercase(),
+                city.to_lowercase(),
+                hostname.to_lowercase(),
+            ),
+            _ => {
+                return Err(anyhow!(
+                    "Line {line_number}: Expected a country, city and hostname, found \"{}\"",
+                    line.trim()
+                ));
+            }
+        };
+        locations.push(location);
+    }
+    Ok(locations)
+}
+
+/// Derives the location of a relay from its hostname, such as "se-got-wg-101".
+fn location_from_hostname(hostname: &str) -> Option<GeographicLocationConstraint> {
+    let hostname = hostname.to_lowercase();
+    let mut parts = hostname.split('-');
+    let country = parts.next().filter(|part| !part.is_empty())?.to_owned();
+    let city = parts.next().filter(|part| !part.is_empty())?.to_owned();
+    parts.next()?;
+    Some(GeographicLocationConstraint::Hostname(
+        country, city, hostname,
+    ))
+}
+
+/// Returns the locations that do not match any relay in `relay_list`.
+fn unknown_locations<'a>(
+    locations: &'a [GeographicLocationConstraint],
+    relay_list: &RelayList,
+) -> Vec<&'a GeographicLocationConstraint> {
+    locations
+        .iter()
+        .filter(|location| !is_in_relay_list(location, relay_list))
+        .collect()
+}
+
+fn is_in_relay_list(location: &GeographicLocationConstraint, relay_list: &RelayList) -> bool {
+    let lookup_city = |country: &str, city: &str| {
+        relay_list
+            .lookup_country(country.to_owned())
+            .and_then(|country| country.lookup_city(city.to_owned()))
+    };
+    match location {
+        GeographicLocationConstraint::Country(country) => {
+            relay_list.lookup_country(country.clone()).is_some()
+        }
+        GeographicLocationConstraint::City(country, city) => lookup_city(country, city).is_some(),
+        GeographicLocationConstraint::Hostname(country, city, hostname) => {
+            match lookup_city(country, city) {
+                Some(city) => city.relays.iter().any(|relay| &relay.hostname == hostname),
+                None => false,
+            }
+        }
+    }
+}
+
+/// Warns about locations that are not in the relay list. They are kept, since relays are added
+/// and removed over time.
+fn print_unknown_locations(locations: &[GeographicLocationConstraint], relay_list: &RelayList) {
+    for location in unknown_locations(locations, relay_list) {
+        eprintln!(
+            "Warning: {} is not in the relay list",
+            GeographicLocationConstraintFormatter::from_constraint(location, relay_list)
+        );
+    }
+}
+
+/// Reads the whole file, or standard input if there is no file.
+fn read_input(file: Option<&Path>) -> Result<String> {
+    match file {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display())),
+        None => {
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .context("Failed to read from standard input")?;
+            Ok(input)
+        }
+    }
+}
+
 pub async fn find_list_by_name(
     rpc: &mut MullvadProxyClient,
     name: &str,
@@ -245,3 +464,136 @@ pub async fn find_list_by_name(
         .find(|list| list.name == name)
         .ok_or(anyhow!("List not found"))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::{
+        location::Location,
+        relay_list::{Relay, RelayEndpointData, RelayListCity, RelayListCountry},
+    };
+    use std::net::Ipv4Addr;
+
+    fn relay_list() -> RelayList {
+        let relay = Relay {
+            hostname: "se-got-ovpn-001".to_owned(),
+            ipv4_addr_in: Ipv4Addr::new(10, 0, 0, 1),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: "31173".to_owned(),
+            weight: 1,
+            endpoint_data: RelayEndpointData::Openvpn,
+            location: Some(Location {
+                country: "Sweden".to_owned(),
+                country_code: "se".to_owned(),
+                city: "Gothenburg".to_owned(),
+                city_code: "got".to_owned(),
+                latitude: 0.0,
+                longitude: 0.0,
+            }),
+        };
+        RelayList {
+            countries: vec![RelayListCountry {
+                name: "Sweden".to_owned(),
+                code: "se".to_owned(),
+                cities: vec![RelayListCity {
+                    name: "Gothenburg".to_owned(),
+                    code: "got".to_owned(),
+                    latitude: 0.0,
+                    longitude: 0.0,
+                    relays: vec![relay],
+                }],
+            }],
+            ..RelayList::empty()
+        }
+    }
+
+    fn country(country: &str) -> GeographicLocationConstraint {
+        GeographicLocationConstraint::Country(country.to_owned())
+    }
+
+    fn city(country: &str, city: &str) -> GeographicLocationConstraint {
+        GeographicLocationConstraint::City(country.to_owned(), city.to_owned())
+    }
+
+    fn hostname(country: &str, city: &str, hostname: &str) -> GeographicLocationConstraint {
+        GeographicLocationConstraint::Hostname(
+            country.to_owned(),
+            city.to_owned(),
+            hostname.to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_locations_round_trip() {
+        let locations = [
+            country("se"),
+            city("de", "ber"),
+            hostname("se", "got", "se-got-ovpn-001"),
+            hostname("us", "nyc", "us-nyc-wg-999"),
+        ];
+        let document = format_locations(&locations);
+        assert_eq!(
+            document,
+            "se\nde ber\nse got se-got-ovpn-001\nus nyc us-nyc-wg-999\n"
+        );
+        assert_eq!(
+            parse_locations(&document, &relay_list()).unwrap(),
+            locations
+        );
+    }
+
+    #[test]
+    fn test_parse_locations() {
+        let input = "\
+# Exported from another machine
+SE
+
+se got   # Gothenburg
+se-got-ovpn-001
+us-nyc-wg-999
+";
+        assert_eq!(
+            parse_locations(input, &relay_list()).unwrap(),
+            [
+                country("se"),
+                city("se", "got"),
+                hostname("se", "got", "se-got-ovpn-001"),
+                hostname("us", "nyc", "us-nyc-wg-999"),
+            ]
+        );
+
+        let error = |input| {
+            parse_locations(input, &relay_list())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("se\nany\n"),
+            "Line 2: \"any\" is not a valid location"
+        );
+        assert_eq!(
+            error("se got se-got-ovpn-001 extra"),
+            "Line 1: Expected a country, city and hostname, found \"se got se-got-ovpn-001 extra\""
+        );
+        assert_eq!(error("se-"), "Line 1: \"se-\" is not a valid hostname");
+    }
+
+    #[test]
+    fn test_unknown_locations() {
+        let locations = [
+            country("se"),
+            country("de"),
+            city("se", "got"),
+            city("se", "sto"),
+            hostname("se", "got", "se-got-ovpn-001"),
+            hostname("se", "got", "se-got-wg-999"),
+        ];
+        assert_eq!(
+            unknown_locations(&locations, &relay_list()),
+            [&locations[1], &locations[3], &locations[5]]
+        );
+    }
+}
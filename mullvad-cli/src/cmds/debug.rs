@@ -0,0 +1,288 @@
+use anyhow::Result;
+use clap::Subcommand;
+use mullvad_management_interface::MullvadProxyClient;
+use mullvad_types::{
+    metrics,
+    obfuscation_scores::ObfuscationScores,
+    routes::{RouteDiff, RouteInfo},
+    settings::LogRotationSettings,
+};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use talpid_types::tunnel::MockTunnelScript;
+
+use super::debug_dump;
+
+/// Show information that helps debug connection problems
+#[derive(Subcommand, Debug)]
+pub enum Debug {
+    /// Show the routes that the daemon applies, compared to the routing table where possible.
+    /// Routes prefixed with '-' are missing from the routing table, and routes prefixed with '+'
+    /// are found in the routing table but were not added by the daemon.
+    Routes,
+    /// Show how well each obfuscation type has worked on the networks that have been used
+    /// recently. In auto mode, the types are tried in order of their best score on the current
+    /// network. Scores range from 0 to 1, where 0.5 means that nothing is known.
+    ObfuscationScores,
+    /// Show health metrics of the daemon, such as the current tunnel state and the number of
+    /// reconnects since the daemon started
+    Metrics {
+        /// Print the metrics in the Prometheus text format, e.g. for the textfile collector of
+        /// node_exporter
+        #[arg(long)]
+        prometheus: bool,
+    },
+    /// Show the most recent significant events in the daemon, such as tunnel state transitions,
+    /// relay selections and changes to the firewall and DNS. Addresses and account numbers are
+    /// redacted. The events are also included in problem reports.
+    Events,
+    /// Show the most recent connections, with the relay and parameters that were used, how long
+    /// it took to connect and how each connection ended. The history is kept across restarts and
+    /// included in problem reports.
+    History,
+    /// Write a single document with the version, tunnel state, settings, firewall, DNS, routes,
+    /// connection history and recent events, for attaching to support requests. Addresses,
+    /// account numbers and credentials are redacted. Sections that the daemon does not support
+    /// are marked as unavailable.
+    Dump {
+        /// Write the document to this file instead of printing it
+        #[arg(long, short = 'o')]
+        output: Option<PathBuf>,
+        /// Format of the document
+        #[arg(long, value_enum, default_value_t = debug_dump::Format::Json)]
+        format: debug_dump::Format,
+    },
+    /// Show or change how large the daemon and tunnel logs may grow before they are compressed
+    /// into archives, and how many archives are kept of each. The most recent archives are
+    /// included in problem reports. The MULLVAD_LOG_MAX_SIZE_MIB and MULLVAD_LOG_ARCHIVES
+    /// environment variables of the daemon take precedence over these settings.
+    LogRotation {
+        /// Size in MiB that logs are rotated at
+        #[arg(long)]
+        max_size_mib: Option<u32>,
+        /// Number of archives to keep of each log. If 0, logs are emptied when they are rotated
+        #[arg(long)]
+        archives: Option<u32>,
+        /// Restore the default log rotation
+        #[arg(long, conflicts_with_all = ["max_size_mib", "archives"])]
+        reset: bool,
+    },
+    /// Replace the host and address of the API, e.g. to use a staging environment. This is only
+    /// supported by development builds of the daemon. The override is kept across restarts until
+    /// it is cleared.
+    #[clap(subcommand)]
+    ApiEndpoint(ApiEndpoint),
+    /// Make the mock tunnel simulate failures. This is only supported by daemons that are built
+    /// with the mock-tunnel feature and started with MULLVAD_MOCK_TUNNEL=1. Each call replaces
+    /// the previous script.
+    MockTunnel {
+        /// Number of connection attempts to fail before one succeeds
+        #[arg(long, default_value_t = 0)]
+        fail_connects: u32,
+        /// Time in milliseconds that each connection attempt takes
+        #[arg(long)]
+        connect_delay_ms: Option<u64>,
+        /// Drop the tunnel if it is connected, as if the connection was lost
+        #[arg(long)]
+        drop_tunnel: bool,
+        /// Pretend that the host goes offline or comes back online
+        #[arg(long)]
+        offline: Option<bool>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ApiEndpoint {
+    /// Send API requests to HOST, and connect to it at ADDRESS
+    Set {
+        host: String,
+        /// IP address and port, such as 192.0.2.1:443
+        address: SocketAddr,
+    },
+    /// Go back to the default API endpoint
+    Clear,
+}
+
+impl Debug {
+    pub async fn handle(self) -> Result<()> {
+        match self {
+            Debug::Routes => Self::routes().await,
+            Debug::ObfuscationScores => Self::obfuscation_scores().await,
+            Debug::Metrics { prometheus } => Self::metrics(prometheus).await,
+            Debug::Events => Self::events().await,
+            Debug::History => Self::history().await,
+            Debug::Dump { output, format } => debug_dump::handle(output.as_deref(), format).await,
+            Debug::LogRotation {
+                max_size_mib,
+                archives,
+                reset,
+            } => Self::log_rotation(max_size_mib, archives, reset).await,
+            Debug::ApiEndpoint(cmd) => Self::api_endpoint(cmd).await,
+            Debug::MockTunnel {
+                fail_connects,
+                connect_delay_ms,
+                drop_tunnel,
+                offline,
+            } => {
+                Self::mock_tunnel(MockTunnelScript {
+                    fail_connects,
+                    connect_delay: connect_delay_ms.map(Duration::from_millis),
+                    drop_tunnel,
+                    offline,
+                })
+                .await
+            }
+        }
+    }
+
+    async fn log_rotation(
+        max_size_mib: Option<u32>,
+        archives: Option<u32>,
+        reset: bool,
+    ) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let current = rpc.get_settings().await?.log_rotation;
+        let rotation = if reset {
+            LogRotationSettings::default()
+        } else {
+            LogRotationSettings {
+                max_size_mib: max_size_mib.unwrap_or(current.max_size_mib),
+                max_archives: archives.unwrap_or(current.max_archives),
+            }
+        };
+        if rotation != current {
+            rpc.set_log_rotation_settings(rotation).await?;
+        }
+        println!(
+            "Logs are rotated at {} MiB, keeping {} archives",
+            rotation.max_size_mib, rotation.max_archives
+        );
+        Ok(())
+    }
+
+    async fn api_endpoint(cmd: ApiEndpoint) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        match cmd {
+            ApiEndpoint::Set { host, address } => {
+                rpc.set_api_endpoint_override(host.clone(), address).await?;
+                println!("Using API at {host} ({address})");
+            }
+            ApiEndpoint::Clear => {
+                rpc.clear_api_endpoint_override().await?;
+                println!("Using the default API endpoint");
+            }
+        }
+        Ok(())
+    }
+
+    async fn mock_tunnel(script: MockTunnelScript) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        rpc.script_mock_tunnel(script).await?;
+        println!("Updated the mock tunnel script");
+        Ok(())
+    }
+
+    async fn routes() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let dump = rpc.get_routes().await?;
+
+        match dump.diff() {
+            Some(diff) => {
+                if diff.is_empty() {
+                    println!("No routes are applied");
+                }
+                for (diff, route) in diff {
+                    let prefix = match diff {
+                        RouteDiff::Present => ' ',
+                        RouteDiff::Missing => '-',
+                        RouteDiff::Unexpected => '+',
+                    };
+                    println!("{prefix} {}", format_route(route));
+                }
+            }
+            None => {
+                if dump.desired.is_empty() {
+                    println!("No routes are applied");
+                }
+                for route in &dump.desired {
+                    println!("{}", format_route(route));
+                }
+                println!("The routing table cannot be listed on this platform");
+            }
+        }
+        Ok(())
+    }
+
+    async fn obfuscation_scores() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        print_obfuscation_scores(&rpc.get_obfuscation_scores().await?);
+        Ok(())
+    }
+
+    async fn metrics(prometheus: bool) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let metrics = rpc.get_metrics().await?;
+        if prometheus {
+            print!("{}", metrics::to_prometheus(&metrics));
+        } else {
+            for metric in &metrics {
+                println!("{} {}", metric.series(), metric.value);
+            }
+        }
+        Ok(())
+    }
+
+    async fn events() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let events = rpc.get_recent_events().await?;
+        if events.is_empty() {
+            println!("No events have been recorded");
+        }
+        for event in events {
+            println!("{event}");
+        }
+        Ok(())
+    }
+
+    async fn history() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let connections = rpc.get_connection_history().await?;
+        if connections.is_empty() {
+            println!("No connections have been recorded");
+        }
+        for connection in connections {
+            println!("{connection}");
+        }
+        Ok(())
+    }
+}
+
+fn print_obfuscation_scores(scores: &ObfuscationScores) {
+    if scores.networks.is_empty() {
+        println!("No connection attempts have been recorded");
+    }
+    let now = SystemTime::now();
+    for network in &scores.networks {
+        println!("{}", network.network);
+        for score in &network.scores {
+            println!(
+                "\t{:<8} {:<18} successes: {:.1}, failures: {:.1}, score: {:.2}",
+                score.obfuscation_type,
+                score.port.to_string(),
+                score.successes,
+                score.failures,
+                score.score(now),
+            );
+        }
+    }
+}
+
+fn format_route(route: &RouteInfo) -> String {
+    match &route.purpose {
+        Some(purpose) => format!("[{purpose}] {route}"),
+        None => route.to_string(),
+    }
+}
@@ -0,0 +1,347 @@
+//! A single document with the state of the daemon, for attaching to support requests. Every
+//! string in it is redacted the same way as the logs in a problem report, and credentials are
+//! left out entirely.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use mullvad_management_interface::{Code, MullvadProxyClient};
+use mullvad_types::{
+    connection_history::Connection, recent_events::Event, routes::RouteDump, settings::Settings,
+    states::TunnelState, version::AppVersionInfo,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+use talpid_types::tunnel::ActionAfterDisconnect;
+
+/// Version of the document layout. Increased when sections are removed or change meaning.
+const FORMAT_VERSION: u32 = 1;
+
+/// Object keys whose values are replaced entirely, since they hold credentials.
+const SECRET_KEYS: &[&str] = &["password", "username", "private_key"];
+
+const REDACTED: &str = "[REDACTED]";
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Format {
+    #[default]
+    Json,
+    Text,
+}
+
+/// Responses of the RPCs that the dump is made from.
+struct Responses {
+    daemon_version: RpcResult<String>,
+    version_info: RpcResult<AppVersionInfo>,
+    tunnel_state: RpcResult<TunnelState>,
+    settings: RpcResult<Settings>,
+    routes: RpcResult<RouteDump>,
+    connection_history: RpcResult<Vec<Connection>>,
+    recent_events: RpcResult<Vec<Event>>,
+}
+
+type RpcResult<T> = std::result::Result<T, mullvad_management_interface::Error>;
+
+/// The document that `mullvad debug dump` writes.
+#[derive(Debug, Serialize)]
+struct DebugDump {
+    format_version: u32,
+    generated_at: DateTime<Utc>,
+    cli_version: String,
+    daemon_version: Section,
+    version_info: Section,
+    tunnel_state: Section,
+    settings: Section,
+    firewall: Section,
+    dns: Section,
+    routes: Section,
+    connection_history: Section,
+    recent_events: Section,
+}
+
+/// Part of the dump. Sections are unavailable if the daemon does not support the RPC that they
+/// come from, e.g. because it is older than the CLI, or if the RPC fails.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+enum Section {
+    Available { content: Value },
+    Unavailable { reason: String },
+}
+
+/// What is known about the firewall policy. The daemon does not expose the policy itself, so
+/// this is derived from the tunnel state and the settings.
+#[derive(Debug, Serialize)]
+struct Firewall {
+    /// Whether traffic outside the tunnel is blocked.
+    blocking: bool,
+    allow_lan: bool,
+    lockdown_mode: bool,
+}
+
+impl Firewall {
+    fn new(state: &TunnelState, settings: &Settings) -> Self {
+        let lockdown_mode = settings.block_when_disconnected;
+        let blocking = match state {
+            TunnelState::Disconnected => lockdown_mode,
+            TunnelState::Connecting { .. } | TunnelState::Connected { .. } => true,
+            TunnelState::Disconnecting(ActionAfterDisconnect::Nothing) => lockdown_mode,
+            TunnelState::Disconnecting(_) => true,
+            TunnelState::Error(error_state) => error_state.is_blocking(),
+        };
+        Firewall {
+            blocking,
+            allow_lan: settings.allow_lan,
+            lockdown_mode,
+        }
+    }
+}
+
+impl Section {
+    fn available(content: impl Serialize) -> Self {
+        match serde_json::to_value(content) {
+            Ok(mut content) => {
+                redact_value(&mut content);
+                Section::Available { content }
+            }
+            Err(error) => Section::unavailable(format!("Failed to serialize: {error}")),
+        }
+    }
+
+    fn unavailable(reason: impl Into<String>) -> Self {
+        Section::Unavailable {
+            reason: mullvad_problem_report::redact(&reason.into()),
+        }
+    }
+
+    fn from_response<T: Serialize>(response: &RpcResult<T>) -> Self {
+        use mullvad_management_interface::Error;
+
+        match response {
+            Ok(content) => Section::available(content),
+            Err(Error::Rpc(status)) if status.code() == Code::Unimplemented => {
+                Section::unavailable("Not supported by the daemon")
+            }
+            Err(error @ Error::Rpc(status)) => {
+                Section::unavailable(format!("{error}: {}", status.message()))
+            }
+            Err(error) => Section::unavailable(error.to_string()),
+        }
+    }
+}
+
+impl DebugDump {
+    async fn collect(rpc: &mut MullvadProxyClient) -> Self {
+        let responses = Responses {
+            daemon_version: rpc.get_current_version().await,
+            version_info: rpc.get_version_info().await,
+            tunnel_state: rpc.get_tunnel_state().await,
+            settings: rpc.get_settings().await,
+            routes: rpc.get_routes().await,
+            connection_history: rpc.get_connection_history().await,
+            recent_events: rpc.get_recent_events().await,
+        };
+        DebugDump::new(Utc::now(), responses)
+    }
+
+    fn new(generated_at: DateTime<Utc>, responses: Responses) -> Self {
+        let firewall = match (&responses.tunnel_state, &responses.settings) {
+            (Ok(state), Ok(settings)) => Section::available(Firewall::new(state, settings)),
+            _ => Section::unavailable("The tunnel state and settings are unavailable"),
+        };
+        let dns = match &responses.tunnel_state {
+            Ok(TunnelState::Connected { effective_dns, .. }) => Section::available(effective_dns),
+            Ok(_) => Section::unavailable("Only known while connected"),
+            Err(_) => Section::unavailable("The tunnel state is unavailable"),
+        };
+        DebugDump {
+            format_version: FORMAT_VERSION,
+            generated_at,
+            cli_version: mullvad_version::VERSION.to_owned(),
+            daemon_version: Section::from_response(&responses.daemon_version),
+            version_info: Section::from_response(&responses.version_info),
+            tunnel_state: Section::from_response(&responses.tunnel_state),
+            settings: Section::from_response(&responses.settings),
+            firewall,
+            dns,
+            routes: Section::from_response(&responses.routes),
+            connection_history: Section::from_response(&responses.connection_history),
+            recent_events: Section::from_response(&responses.recent_events),
+        }
+    }
+
+    fn sections(&self) -> [(&'static str, &Section); 9] {
+        [
+            ("Daemon version", &self.daemon_version),
+            ("Version info", &self.version_info),
+            ("Tunnel state", &self.tunnel_state),
+            ("Settings", &self.settings),
+            ("Firewall", &self.firewall),
+            ("DNS", &self.dns),
+            ("Routes", &self.routes),
+            ("Connection history", &self.connection_history),
+            ("Recent events", &self.recent_events),
+        ]
+    }
+
+    fn to_text(&self) -> Result<String> {
+        let mut text = format!(
+            "Mullvad debug dump, format {}\nGenerated at: {}\nCLI version: {}\n",
+            self.format_version,
+            self.generated_at.to_rfc3339(),
+            self.cli_version
+        );
+        for (title, section) in self.sections() {
+            text.push_str(&format!("\n== {title} ==\n"));
+            match section {
+                Section::Available { content } => {
+                    text.push_str(&serde_json::to_string_pretty(content)?);
+                    text.push('\n');
+                }
+                Section::Unavailable { reason } => {
+                    text.push_str(&format!("Unavailable: {reason}\n"));
+                }
+            }
+        }
+        Ok(text)
+    }
+}
+
+/// Replaces credentials and redacts every string in `value`.
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::String(string) => *string = mullvad_problem_report::redact(string),
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) && !value.is_null() {
+                    *value = Value::String(REDACTED.to_owned());
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => (),
+    }
+}
+
+/// Writes the dump to `output`, or prints it if there is no output file.
+pub async fn handle(output: Option<&Path>, format: Format) -> Result<()> {
+    let mut rpc = MullvadProxyClient::new().await?;
+    let dump = DebugDump::collect(&mut rpc).await;
+    let document = match format {
+        Format::Json => serde_json::to_string_pretty(&dump)? + "\n",
+        Format::Text => dump.to_text()?,
+    };
+    match output {
+        Some(path) => {
+            std::fs::write(path, document)?;
+            println!("Wrote the debug dump to {}", path.display());
+        }
+        None => print!("{document}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use mullvad_management_interface::{Error, Status};
+    use mullvad_types::{
+        access_method::{AccessMethod, AccessMethodSetting, CustomAccessMethod, Shadowsocks},
+        custom_list::CustomList,
+    };
+
+    fn unimplemented<T>() -> RpcResult<T> {
+        Err(Error::Rpc(Status::unimplemented("")))
+    }
+
+    fn generated_at() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_dump_structure() {
+        let responses = Responses {
+            daemon_version: Ok("2024.1".to_owned()),
+            version_info: unimplemented(),
+            tunnel_state: Ok(TunnelState::Disconnected),
+            settings: Ok(Settings::default()),
+            routes: unimplemented(),
+            connection_history: unimplemented(),
+            recent_events: Ok(vec![]),
+        };
+        let dump = serde_json::to_value(DebugDump::new(generated_at(), responses)).unwrap();
+
+        let unsupported = serde_json::json!({
+            "status": "unavailable",
+            "reason": "Not supported by the daemon",
+        });
+        assert_eq!(dump["format_version"], 1);
+        assert_eq!(dump["generated_at"], "2024-03-01T12:00:00Z");
+        assert_eq!(
+            dump["daemon_version"],
+            serde_json::json!({ "status": "available", "content": "2024.1" })
+        );
+        assert_eq!(dump["version_info"], unsupported);
+        assert_eq!(
+            dump["tunnel_state"],
+            serde_json::json!({ "status": "available", "content": { "state": "disconnected" } })
+        );
+        assert_eq!(dump["settings"]["status"], "available");
+        assert_eq!(
+            dump["firewall"],
+            serde_json::json!({
+                "status": "available",
+                "content": { "blocking": false, "allow_lan": false, "lockdown_mode": false },
+            })
+        );
+        assert_eq!(
+            dump["dns"],
+            serde_json::json!({ "status": "unavailable", "reason": "Only known while connected" })
+        );
+        assert_eq!(dump["routes"], unsupported);
+        assert_eq!(dump["connection_history"], unsupported);
+        assert_eq!(
+            dump["recent_events"],
+            serde_json::json!({ "status": "available", "content": [] })
+        );
+    }
+
+    #[test]
+    fn test_dump_redaction() {
+        let mut settings = Settings::default();
+        settings
+            .custom_lists
+            .add(CustomList::new("work 1234123412341234".to_owned()));
+        settings.api_access_methods.append(AccessMethodSetting::new(
+            "proxy".to_owned(),
+            true,
+            AccessMethod::Custom(CustomAccessMethod::Shadowsocks(Shadowsocks::new(
+                "192.0.2.7:443".parse().unwrap(),
+                "aes-256-gcm".to_owned(),
+                "hunter2".to_owned(),
+            ))),
+        ));
+        let responses = Responses {
+            daemon_version: Ok("2024.1".to_owned()),
+            version_info: unimplemented(),
+            tunnel_state: Err(Error::Rpc(Status::internal("no route to 198.51.100.1"))),
+            settings: Ok(settings),
+            routes: unimplemented(),
+            connection_history: unimplemented(),
+            recent_events: unimplemented(),
+        };
+        let dump = DebugDump::new(generated_at(), responses);
+        let json = serde_json::to_string(&dump).unwrap();
+        let text = dump.to_text().unwrap();
+
+        for document in [json, text] {
+            for secret in ["1234123412341234", "hunter2", "192.0.2.7", "198.51.100.1"] {
+                assert!(!document.contains(secret), "{secret} was not redacted");
+            }
+            assert!(document.contains("aes-256-gcm"));
+        }
+    }
+}
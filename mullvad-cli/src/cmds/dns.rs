@@ -1,8 +1,15 @@
 use anyhow::Result;
 use clap::Subcommand;
 use mullvad_management_interface::MullvadProxyClient;
-use mullvad_types::settings::{CustomDnsOptions, DefaultDnsOptions, DnsOptions, DnsState};
+use mullvad_types::{
+    dns_leak::DnsLeakVerdict,
+    settings::{CustomDnsOptions, DefaultDnsOptions, DnsOptions, DnsState, Settings},
+};
 use std::net::IpAddr;
+use talpid_types::net::TlsDnsServer;
+
+use super::BooleanOption;
+use crate::format;
 
 #[derive(Subcommand, Debug)]
 pub enum Dns {
@@ -14,6 +21,21 @@ pub enum Dns {
         #[clap(subcommand)]
         cmd: DnsSet,
     },
+
+    /// Also use the search domains of other network interfaces for lookups through the tunnel.
+    /// Only applies when DNS is managed by systemd-resolved
+    PreserveSearchDomains { policy: BooleanOption },
+
+    /// Allow DNS requests to custom DNS servers on the LAN while traffic is blocked.
+    /// Only applies when local network sharing is enabled. Not supported on Windows
+    AllowLanDnsWhenBlocked { policy: BooleanOption },
+
+    /// Keep using the custom DNS servers while disconnected, and block DNS requests to other
+    /// servers. Only applies when lockdown mode is disabled. Not supported on Windows
+    KeepCustomDnsWhileDisconnected { policy: BooleanOption },
+
+    /// Check whether DNS lookups leak outside the tunnel
+    LeakTest,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -52,12 +74,20 @@ pub enum DnsSet {
         #[arg(required(true), num_args = 1..)]
         servers: Vec<IpAddr>,
     },
+
+    /// Set a list of custom DNS-over-TLS servers. Queries are sent through the tunnel, and
+    /// fail if a server's certificate is not valid for its hostname
+    CustomTls {
+        /// One or more servers, each given as <ADDRESS>#<HOSTNAME>, e.g. 9.9.9.9#dns.quad9.net
+        #[arg(required(true), num_args = 1.., value_parser = parse_tls_server)]
+        servers: Vec<TlsDnsServer>,
+    },
 }
 
 impl Dns {
-    pub async fn handle(self) -> Result<()> {
+    pub async fn handle(self, json: bool) -> Result<()> {
         match self {
-            Dns::Get => Self::get().await,
+            Dns::Get => Self::get(json).await,
             Dns::Set {
                 cmd:
                     DnsSet::Default {
@@ -82,12 +112,28 @@ impl Dns {
             Dns::Set {
                 cmd: DnsSet::Custom { servers },
             } => Self::set_custom(servers).await,
+            Dns::Set {
+                cmd: DnsSet::CustomTls { servers },
+            } => Self::set_custom_tls(servers).await,
+            Dns::PreserveSearchDomains { policy } => {
+                Self::set_preserve_search_domains(*policy).await
+            }
+            Dns::AllowLanDnsWhenBlocked { policy } => {
+                Self::set_allow_lan_dns_when_blocked(*policy).await
+            }
+            Dns::KeepCustomDnsWhileDisconnected { policy } => {
+                Self::set_keep_custom_dns_while_disconnected(*policy).await
+            }
+            Dns::LeakTest => Self::leak_test().await,
         }
     }
 
-    async fn get() -> Result<()> {
+    async fn get(json: bool) -> Result<()> {
         let mut rpc = MullvadProxyClient::new().await?;
         let options = rpc.get_settings().await?.tunnel_options.dns_options;
+        if json {
+            return format::print_json(&options);
+        }
 
         match options.state {
             DnsState::Default => {
@@ -110,8 +156,23 @@ impl Dns {
                 for server in &options.custom_options.addresses {
                     println!("{server}");
                 }
+                for server in &options.custom_options.tls_servers {
+                    println!("{server} (DNS over TLS)");
+                }
             }
         }
+        println!(
+            "Preserve search domains: {}",
+            BooleanOption::from(options.preserve_search_domains)
+        );
+        println!(
+            "Allow LAN DNS when blocked: {}",
+            BooleanOption::from(options.allow_lan_dns_when_blocked)
+        );
+        println!(
+            "Keep custom DNS while disconnected: {}",
+            BooleanOption::from(options.keep_custom_dns_while_disconnected)
+        );
 
         Ok(())
     }
@@ -148,11 +209,223 @@ impl Dns {
         let settings = rpc.get_settings().await?;
         rpc.set_dns_options(DnsOptions {
             state: DnsState::Custom,
-            custom_options: CustomDnsOptions { addresses: servers },
+            custom_options: CustomDnsOptions {
+                addresses: servers,
+                tls_servers: vec![],
+            },
+            ..settings.tunnel_options.dns_options
+        })
+        .await?;
+        println!("Updated DNS settings");
+        if let Some(warning) = unreachable_lan_dns_warning(&rpc.get_settings().await?) {
+            eprintln!("{warning}");
+        }
+        Ok(())
+    }
+
+    async fn set_custom_tls(servers: Vec<TlsDnsServer>) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let settings = rpc.get_settings().await?;
+        rpc.set_dns_options(DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: vec![],
+                tls_servers: servers,
+            },
+            ..settings.tunnel_options.dns_options
+        })
+        .await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    async fn set_preserve_search_domains(preserve_search_domains: bool) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let settings = rpc.get_settings().await?;
+        rpc.set_dns_options(DnsOptions {
+            preserve_search_domains,
+            ..settings.tunnel_options.dns_options
+        })
+        .await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    async fn set_allow_lan_dns_when_blocked(allow_lan_dns_when_blocked: bool) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let settings = rpc.get_settings().await?;
+        rpc.set_dns_options(DnsOptions {
+            allow_lan_dns_when_blocked,
             ..settings.tunnel_options.dns_options
         })
         .await?;
         println!("Updated DNS settings");
         Ok(())
     }
+
+    async fn set_keep_custom_dns_while_disconnected(
+        keep_custom_dns_while_disconnected: bool,
+    ) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let settings = rpc.get_settings().await?;
+        rpc.set_dns_options(DnsOptions {
+            keep_custom_dns_while_disconnected,
+            ..settings.tunnel_options.dns_options
+        })
+        .await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    async fn leak_test() -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        println!("Running DNS leak test...");
+        let result = rpc.run_dns_leak_test().await?;
+
+        match result.verdict {
+            DnsLeakVerdict::NoLeak => println!("Result: no leak"),
+            DnsLeakVerdict::Leaking => println!("Result: leaking"),
+            DnsLeakVerdict::Inconclusive => println!("Result: inconclusive"),
+        }
+
+        println!("Observed resolvers:");
+        for resolver in &result.observed_resolvers {
+            let organization = resolver.organization.as_deref().unwrap_or("unknown");
+            let operator = if resolver.mullvad_dns {
+                "Mullvad"
+            } else {
+                "not Mullvad"
+            };
+            println!("\t{} ({organization}, {operator})", resolver.ip);
+        }
+
+        if !result.leaking_resolvers.is_empty() {
+            println!("Leaking resolvers:");
+            for resolver in &result.leaking_resolvers {
+                println!("\t{resolver}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a DNS-over-TLS server given as `<ADDRESS>#<HOSTNAME>`.
+fn parse_tls_server(server: &str) -> Result<TlsDnsServer, String> {
+    let (address, hostname) = server
+        .split_once('#')
+        .ok_or_else(|| format!("Expected <ADDRESS>#<HOSTNAME>, got \"{server}\""))?;
+    let address = address
+        .parse()
+        .map_err(|_| format!("Invalid IP address: \"{address}\""))?;
+    if hostname.is_empty() {
+        return Err("The hostname must not be empty".to_owned());
+    }
+    Ok(TlsDnsServer {
+        address,
+        hostname: hostname.to_owned(),
+    })
+}
+
+/// Returns a warning if any custom DNS server is on the LAN while local network sharing is
+/// disabled, since such servers cannot be reached while traffic is blocked.
+pub fn unreachable_lan_dns_warning(settings: &Settings) -> Option<String> {
+    let servers = settings.unreachable_lan_dns_servers();
+    if servers.is_empty() {
+        return None;
+    }
+    let servers = servers
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(format!(
+        "Warning: The custom DNS servers {servers} are on the local network and cannot be \
+         reached while traffic is blocked unless local network sharing is enabled. Run \
+         `mullvad lan set allow` to enable it"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(allow_lan: bool, servers: &[&str]) -> Settings {
+        let mut settings = Settings {
+            allow_lan,
+            ..Default::default()
+        };
+        settings.tunnel_options.dns_options = DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: servers
+                    .iter()
+                    .map(|server| server.parse().unwrap())
+                    .collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        settings
+    }
+
+    #[test]
+    fn test_warn_about_unreachable_lan_dns() {
+        let warning = unreachable_lan_dns_warning(&settings(false, &["192.168.1.2", "9.9.9.9"]))
+            .expect("expected a warning");
+        assert!(warning.contains("192.168.1.2"));
+        assert!(!warning.contains("9.9.9.9"));
+    }
+
+    #[test]
+    fn test_no_warning_when_lan_dns_is_reachable() {
+        assert_eq!(
+            unreachable_lan_dns_warning(&settings(true, &["192.168.1.2"])),
+            None
+        );
+        assert_eq!(
+            unreachable_lan_dns_warning(&settings(false, &["9.9.9.9"])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_tls_server() {
+        assert_eq!(
+            parse_tls_server("9.9.9.9#dns.quad9.net"),
+            Ok(TlsDnsServer {
+                address: "9.9.9.9".parse().unwrap(),
+                hostname: "dns.quad9.net".to_owned(),
+            })
+        );
+        assert!(parse_tls_server("9.9.9.9").is_err());
+        assert!(parse_tls_server("9.9.9.9#").is_err());
+        assert!(parse_tls_server("dns.quad9.net#9.9.9.9").is_err());
+    }
+
+    #[test]
+    fn test_dns_json() {
+        let options = settings(false, &["9.9.9.9"]).tunnel_options.dns_options;
+        assert_eq!(
+            serde_json::to_value(options).unwrap(),
+            serde_json::json!({
+                "state": "custom",
+                "default_options": {
+                    "block_ads": false,
+                    "block_trackers": false,
+                    "block_malware": false,
+                    "block_adult_content": false,
+                    "block_gambling": false,
+                    "block_social_media": false,
+                },
+                "custom_options": {
+                    "addresses": ["9.9.9.9"],
+                    "tls_servers": [],
+                },
+                "preserve_search_domains": false,
+                "allow_lan_dns_when_blocked": false,
+                "keep_custom_dns_while_disconnected": false,
+            })
+        );
+    }
 }
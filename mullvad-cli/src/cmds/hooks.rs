@@ -0,0 +1,78 @@
+use anyhow::Result;
+use clap::{Subcommand, ValueEnum};
+use mullvad_management_interface::MullvadProxyClient;
+use mullvad_types::settings::HookSettings;
+use std::path::{Path, PathBuf};
+
+/// Manage scripts that the daemon runs when the tunnel connects, disconnects or fails. Details of
+/// the tunnel, such as MULLVAD_TUNNEL_INTERFACE, MULLVAD_TUNNEL_IPV4 and MULLVAD_RELAY_HOSTNAME,
+/// are passed in environment variables. Hooks that run for longer than 30 seconds are killed
+#[derive(Subcommand, Debug)]
+pub enum Hooks {
+    /// Display the configured hooks
+    Get,
+
+    /// Set the script to run on an event. On Linux and macOS, it must be owned by root and not be
+    /// writable by all users
+    Set {
+        event: HookEvent,
+
+        /// Absolute path of the script
+        path: PathBuf,
+    },
+
+    /// Stop running a script on an event
+    Clear { event: HookEvent },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum HookEvent {
+    /// The tunnel has connected
+    #[value(name = "on-connect")]
+    Connect,
+    /// The tunnel has disconnected
+    #[value(name = "on-disconnect")]
+    Disconnect,
+    /// The tunnel has entered the error state
+    #[value(name = "on-error")]
+    Error,
+}
+
+impl Hooks {
+    pub async fn handle(self) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let mut hooks = rpc.get_settings().await?.hooks;
+        match self {
+            Hooks::Get => {
+                print_hook("On connect", hooks.on_connect.as_deref());
+                print_hook("On disconnect", hooks.on_disconnect.as_deref());
+                print_hook("On error", hooks.on_error.as_deref());
+                return Ok(());
+            }
+            Hooks::Set { event, path } => {
+                *hook_mut(&mut hooks, event) = Some(path);
+            }
+            Hooks::Clear { event } => {
+                *hook_mut(&mut hooks, event) = None;
+            }
+        }
+        rpc.set_hook_settings(&hooks).await?;
+        println!("Updated hooks");
+        Ok(())
+    }
+}
+
+fn hook_mut(hooks: &mut HookSettings, event: HookEvent) -> &mut Option<PathBuf> {
+    match event {
+        HookEvent::Connect => &mut hooks.on_connect,
+        HookEvent::Disconnect => &mut hooks.on_disconnect,
+        HookEvent::Error => &mut hooks.on_error,
+    }
+}
+
+fn print_hook(event: &str, path: Option<&Path>) {
+    match path {
+        Some(path) => println!("{event}: {}", path.display()),
+        None => println!("{event}: none"),
+    }
+}
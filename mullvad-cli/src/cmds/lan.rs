@@ -2,7 +2,7 @@ use anyhow::Result;
 use clap::Subcommand;
 use mullvad_management_interface::MullvadProxyClient;
 
-use super::BooleanOption;
+use super::{dns::unreachable_lan_dns_warning, BooleanOption};
 
 #[derive(Subcommand, Debug)]
 pub enum Lan {
@@ -28,6 +28,9 @@ impl Lan {
         let mut rpc = MullvadProxyClient::new().await?;
         rpc.set_allow_lan(*policy).await?;
         println!("Changed local network sharing setting");
+        if let Some(warning) = unreachable_lan_dns_warning(&rpc.get_settings().await?) {
+            eprintln!("{warning}");
+        }
         Ok(())
     }
 
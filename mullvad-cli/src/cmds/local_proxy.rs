@@ -0,0 +1,68 @@
+use anyhow::Result;
+use clap::Subcommand;
+use mullvad_management_interface::MullvadProxyClient;
+use mullvad_types::{access_method::SocksAuth, settings::LocalProxySettings};
+
+/// Manage a SOCKS5 proxy on localhost that other applications can use to connect through the
+/// tunnel, even if they are excluded from it. It only accepts connections while connected.
+#[derive(Subcommand, Debug)]
+pub enum LocalProxy {
+    /// Display the local proxy settings
+    Get,
+
+    /// Enable the local proxy
+    Set {
+        /// Port to listen on, on 127.0.0.1
+        port: u16,
+
+        /// Username that clients must authenticate with
+        #[arg(long, requires = "password")]
+        username: Option<String>,
+
+        /// Password that clients must authenticate with
+        #[arg(long, requires = "username")]
+        password: Option<String>,
+    },
+
+    /// Disable the local proxy
+    Disable,
+}
+
+impl LocalProxy {
+    pub async fn handle(self) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        match self {
+            LocalProxy::Get => {
+                let settings = rpc.get_settings().await?.local_proxy;
+                match settings.port {
+                    Some(port) => println!("Local proxy: 127.0.0.1:{port}"),
+                    None => println!("Local proxy: off"),
+                }
+                if let Some(authentication) = settings.authentication {
+                    println!("Username: {}", authentication.username);
+                }
+            }
+            LocalProxy::Set {
+                port,
+                username,
+                password,
+            } => {
+                let authentication = username
+                    .zip(password)
+                    .map(|(username, password)| SocksAuth { username, password });
+                rpc.set_local_proxy_settings(&LocalProxySettings {
+                    port: Some(port),
+                    authentication,
+                })
+                .await?;
+                println!("Local proxy will listen on 127.0.0.1:{port} while connected");
+            }
+            LocalProxy::Disable => {
+                rpc.set_local_proxy_settings(&LocalProxySettings::default())
+                    .await?;
+                println!("Disabled local proxy");
+            }
+        }
+        Ok(())
+    }
+}
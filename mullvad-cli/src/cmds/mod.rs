@@ -4,19 +4,33 @@ use std::ops::Deref;
 pub mod account;
 pub mod api_access;
 pub mod auto_connect;
+pub mod auto_disconnect;
 pub mod beta_program;
 pub mod bridge;
+pub mod bypass_routes;
+pub mod check;
+#[cfg(target_os = "macos")]
+pub mod coexistence_mode;
+pub mod complete;
 pub mod custom_list;
+pub mod debug;
+pub mod debug_dump;
 pub mod dns;
+pub mod hooks;
 pub mod lan;
+pub mod local_proxy;
 pub mod lockdown;
 pub mod obfuscation;
+pub mod profile;
 pub mod relay;
 pub mod relay_constraints;
 pub mod reset;
+pub mod settings;
 pub mod split_tunnel;
 pub mod status;
+pub mod trust;
 pub mod tunnel;
+pub mod tunnel_monitor;
 pub mod tunnel_state;
 pub mod version;
 
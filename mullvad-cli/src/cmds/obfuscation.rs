@@ -1,9 +1,14 @@
-use anyhow::Result;
-use clap::Subcommand;
+use anyhow::{anyhow, Result};
+use clap::{Args, Subcommand};
 use mullvad_management_interface::MullvadProxyClient;
-use mullvad_types::relay_constraints::{
-    Constraint, ObfuscationSettings, SelectedObfuscation, Udp2TcpObfuscationSettings,
+use mullvad_types::{
+    network_profiles::NetworkId,
+    relay_constraints::{
+        Constraint, ObfuscationSettings, ObfuscationType, SelectedObfuscation,
+        TlsObfuscationSettings, Udp2TcpObfuscationSettings,
+    },
 };
+use talpid_types::net::proxy::{SocksAuth, SocksProxy};
 
 #[derive(Subcommand, Debug)]
 pub enum Obfuscation {
@@ -13,6 +18,61 @@ pub enum Obfuscation {
     /// Set obfuscation settings
     #[clap(subcommand)]
     Set(SetCommands),
+
+    /// Manage obfuscation settings that are used instead of the global ones on specific networks
+    #[clap(subcommand)]
+    Profile(ProfileCommands),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ProfileCommands {
+    /// List the network obfuscation profiles, and which one is used on the current network
+    List,
+
+    /// Add a profile for a network, or replace the existing one. Settings that are not given
+    /// are copied from the global obfuscation settings
+    Add {
+        #[command(flatten)]
+        network: NetworkTarget,
+
+        /// Obfuscation mode to use on the network
+        #[arg(long)]
+        mode: Option<SelectedObfuscation>,
+
+        /// udp2tcp port to use on the network, or 'any'
+        #[arg(long, short = 'p')]
+        port: Option<Constraint<u16>>,
+    },
+
+    /// Remove the profile of a network
+    Remove(NetworkTarget),
+
+    /// Remove all network obfuscation profiles
+    Clear,
+}
+
+/// A network given by the SSID of a Wi-Fi network, or the name of an interface. Settings for a
+/// Wi-Fi network take precedence over those for its interface. SSIDs are only detected on Linux
+#[derive(Args, Debug, Clone)]
+#[group(required = true, multiple = false)]
+pub struct NetworkTarget {
+    /// SSID of the Wi-Fi network
+    #[arg(long)]
+    ssid: Option<String>,
+
+    /// Name of the interface of the default route
+    #[arg(long)]
+    interface: Option<String>,
+}
+
+impl From<NetworkTarget> for NetworkId {
+    fn from(target: NetworkTarget) -> Self {
+        match (target.ssid, target.interface) {
+            (Some(ssid), _) => NetworkId::Ssid(ssid),
+            (None, Some(interface)) => NetworkId::Interface(interface),
+            (None, None) => unreachable!("clap requires a network"),
+        }
+    }
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -21,11 +81,63 @@ pub enum SetCommands {
     /// And if so, what obfuscation protocol it should use.
     Mode { mode: SelectedObfuscation },
 
+    /// Specifies the order in which obfuscation types are tried in auto mode. Each type is
+    /// used for two attempts before the next one is tried. Include 'none' to also try
+    /// connecting without obfuscation
+    Priority {
+        #[arg(required = true, num_args = 1..)]
+        types: Vec<ObfuscationType>,
+    },
+
     /// Specifies the config for the udp2tcp obfuscator.
     Udp2tcp {
         /// Port to use, or 'any'
         #[arg(long, short = 'p')]
-        port: Constraint<u16>,
+        port: Option<Constraint<u16>>,
+
+        /// Send a keepalive over the TCP connection after this many seconds without outgoing
+        /// traffic. Use 0 to disable keepalives
+        #[arg(long)]
+        keepalive_interval: Option<u16>,
+
+        /// Reconnect if nothing is received for this many seconds while WireGuard handshakes
+        /// are unanswered. Use 0 to disable the timeout
+        #[arg(long)]
+        idle_timeout: Option<u16>,
+    },
+
+    /// Specifies the config for the QUIC obfuscator. The server name and application
+    /// protocols are visible to observers, and can be set to blend in with other traffic
+    Quic {
+        /// Server name to send in the TLS handshake, or 'relay' to use the name of the relay
+        #[arg(long)]
+        server_name: Option<String>,
+
+        /// Comma-separated application protocols to negotiate, such as 'h3'
+        #[arg(long, value_delimiter = ',')]
+        alpn: Option<Vec<String>>,
+    },
+
+    /// Specifies the config for the TLS obfuscator. The server name is visible to observers,
+    /// and can be set to that of a common website
+    Tls {
+        /// Server name to send in the TLS handshake, or 'relay' to use the name of the relay
+        #[arg(long)]
+        server_name: String,
+    },
+
+    /// Connect to the relay through a SOCKS5 proxy when using udp2tcp or TLS obfuscation
+    UpstreamProxy {
+        /// Address and port of the proxy, or 'none' to connect directly
+        endpoint: String,
+
+        /// Username to authenticate with
+        #[arg(long, short, requires = "password")]
+        username: Option<String>,
+
+        /// Password to authenticate with
+        #[arg(long, short, requires = "username")]
+        password: Option<String>,
     },
 }
 
@@ -39,11 +151,92 @@ impl Obfuscation {
                     "Obfuscation mode: {}",
                     obfuscation_settings.selected_obfuscation
                 );
+                println!(
+                    "Auto mode priority: {}",
+                    format_priority(obfuscation_settings.obfuscation_priority())
+                );
                 println!("udp2tcp settings: {}", obfuscation_settings.udp2tcp);
+                println!("QUIC settings: {}", obfuscation_settings.quic);
+                println!("TLS settings: {}", obfuscation_settings.tls);
+                println!(
+                    "Upstream proxy: {}",
+                    format_upstream_proxy(obfuscation_settings.upstream_proxy.as_ref())
+                );
                 Ok(())
             }
             Obfuscation::Set(subcmd) => Self::set(subcmd).await,
+            Obfuscation::Profile(subcmd) => Self::profile(subcmd).await,
+        }
+    }
+
+    async fn profile(subcmd: ProfileCommands) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let settings = rpc.get_settings().await?;
+        let mut profiles = settings.network_obfuscation_profiles;
+
+        match subcmd {
+            ProfileCommands::List => {
+                let active = rpc.get_active_obfuscation_profile().await?;
+                if profiles.is_empty() {
+                    println!("No network obfuscation profiles");
+                }
+                for (network, obfuscation_settings) in &profiles {
+                    let marker = if active.as_ref() == Some(network) {
+                        " (active)"
+                    } else {
+                        ""
+                    };
+                    println!("{network}{marker}");
+                    println!(
+                        "    Obfuscation mode: {}",
+                        obfuscation_settings.selected_obfuscation
+                    );
+                    println!(
+                        "    Auto mode priority: {}",
+                        format_priority(obfuscation_settings.obfuscation_priority())
+                    );
+                    println!("    udp2tcp settings: {}", obfuscation_settings.udp2tcp);
+                    println!("    QUIC settings: {}", obfuscation_settings.quic);
+                    println!("    TLS settings: {}", obfuscation_settings.tls);
+                    println!(
+                        "    Upstream proxy: {}",
+                        format_upstream_proxy(obfuscation_settings.upstream_proxy.as_ref())
+                    );
+                }
+                return Ok(());
+            }
+            ProfileCommands::Add {
+                network,
+                mode,
+                port,
+            } => {
+                let network = NetworkId::from(network);
+                let mut obfuscation_settings = settings.obfuscation_settings;
+                if let Some(mode) = mode {
+                    obfuscation_settings.selected_obfuscation = mode;
+                }
+                if let Some(port) = port {
+                    obfuscation_settings.udp2tcp.port = port;
+                }
+                match profiles.iter_mut().find(|(id, _)| id == &network) {
+                    Some((_, existing)) => *existing = obfuscation_settings,
+                    None => profiles.push((network, obfuscation_settings)),
+                }
+            }
+            ProfileCommands::Remove(network) => {
+                let network = NetworkId::from(network);
+                let len = profiles.len();
+                profiles.retain(|(id, _)| id != &network);
+                if profiles.len() == len {
+                    return Err(anyhow!("There is no obfuscation profile for {network}"));
+                }
+            }
+            ProfileCommands::Clear => profiles.clear(),
         }
+
+        rpc.set_network_obfuscation_profiles(&profiles).await?;
+        println!("Updated network obfuscation profiles");
+        Ok(())
     }
 
     async fn set(subcmd: SetCommands) -> Result<()> {
@@ -58,9 +251,84 @@ impl Obfuscation {
                 })
                 .await?;
             }
-            SetCommands::Udp2tcp { port } => {
+            SetCommands::Priority { types } => {
+                rpc.set_obfuscation_settings(ObfuscationSettings {
+                    obfuscation_priority: types,
+                    ..current_settings
+                })
+                .await?;
+            }
+            SetCommands::Udp2tcp {
+                port,
+                keepalive_interval,
+                idle_timeout,
+            } => {
+                let current = &current_settings.udp2tcp;
+                // Zero disables the setting, and omitted settings are left unchanged
+                let secs = |new: Option<u16>, current: Option<u16>| match new {
+                    Some(0) => None,
+                    Some(secs) => Some(secs),
+                    None => current,
+                };
+                let udp2tcp = Udp2TcpObfuscationSettings {
+                    port: port.unwrap_or(current.port),
+                    keepalive_interval_secs: secs(
+                        keepalive_interval,
+                        current.keepalive_interval_secs,
+                    ),
+                    idle_timeout_secs: secs(idle_timeout, current.idle_timeout_secs),
+                };
+                rpc.set_obfuscation_settings(ObfuscationSettings {
+                    udp2tcp,
+                    ..current_settings
+                })
+                .await?;
+            }
+            SetCommands::Quic { server_name, alpn } => {
+                let mut quic = current_settings.quic.clone();
+                if let Some(server_name) = server_name {
+                    quic.server_name = Some(server_name).filter(|name| name != "relay");
+                }
+                if let Some(alpn) = alpn {
+                    quic.alpn = alpn;
+                }
+                rpc.set_obfuscation_settings(ObfuscationSettings {
+                    quic,
+                    ..current_settings
+                })
+                .await?;
+            }
+            SetCommands::Tls { server_name } => {
+                let tls = TlsObfuscationSettings {
+                    server_name: Some(server_name).filter(|name| name != "relay"),
+                };
                 rpc.set_obfuscation_settings(ObfuscationSettings {
-                    udp2tcp: Udp2TcpObfuscationSettings { port },
+                    tls,
+                    ..current_settings
+                })
+                .await?;
+            }
+            SetCommands::UpstreamProxy {
+                endpoint,
+                username,
+                password,
+            } => {
+                let upstream_proxy = if endpoint == "none" {
+                    None
+                } else {
+                    let endpoint = endpoint
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid proxy address: {endpoint}"))?;
+                    let authentication = username
+                        .zip(password)
+                        .map(|(username, password)| SocksAuth { username, password });
+                    Some(SocksProxy {
+                        endpoint,
+                        authentication,
+                    })
+                };
+                rpc.set_obfuscation_settings(ObfuscationSettings {
+                    upstream_proxy,
                     ..current_settings
                 })
                 .await?;
@@ -72,3 +340,17 @@ impl Obfuscation {
         Ok(())
     }
 }
+
+fn format_priority(priority: &[ObfuscationType]) -> String {
+    priority
+        .iter()
+        .map(ObfuscationType::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_upstream_proxy(proxy: Option<&SocksProxy>) -> String {
+    proxy
+        .map(SocksProxy::to_string)
+        .unwrap_or_else(|| "none".to_owned())
+}
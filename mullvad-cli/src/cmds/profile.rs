@@ -0,0 +1,50 @@
+use anyhow::Result;
+use clap::Subcommand;
+use mullvad_management_interface::MullvadProxyClient;
+
+/// Save the settings as named profiles and switch between them. The account and device are not
+/// part of a profile
+#[derive(Subcommand, Debug)]
+pub enum Profile {
+    /// Save the current settings as a profile, replacing any profile with the same name
+    Save { name: String },
+
+    /// List the saved profiles
+    List,
+
+    /// Replace the settings with those of a profile
+    Apply { name: String },
+
+    /// Delete a profile
+    Delete { name: String },
+}
+
+impl Profile {
+    pub async fn handle(self) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        match self {
+            Profile::Save { name } => {
+                rpc.save_profile(name.clone()).await?;
+                println!("Saved the settings as profile \"{name}\"");
+            }
+            Profile::List => {
+                let names = rpc.list_profiles().await?;
+                if names.is_empty() {
+                    println!("No profiles saved");
+                }
+                for name in names {
+                    println!("{name}");
+                }
+            }
+            Profile::Apply { name } => {
+                rpc.apply_profile(name.clone()).await?;
+                println!("Applied profile \"{name}\"");
+            }
+            Profile::Delete { name } => {
+                rpc.delete_profile(name.clone()).await?;
+                println!("Deleted profile \"{name}\"");
+            }
+        }
+        Ok(())
+    }
+}
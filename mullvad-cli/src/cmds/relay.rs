@@ -1,19 +1,21 @@
 use anyhow::{anyhow, Context, Result};
-use clap::Subcommand;
+use clap::{Args, Subcommand};
 use itertools::Itertools;
 use mullvad_management_interface::MullvadProxyClient;
 use mullvad_types::{
-    location::Location,
+    location::{CityCode, CountryCode, Location},
     relay_constraints::{
         Constraint, GeographicLocationConstraint, LocationConstraint, LocationConstraintFormatter,
-        Match, OpenVpnConstraints, Ownership, Provider, Providers, RelayConstraintsUpdate,
-        RelaySettings, RelaySettingsUpdate, TransportPort, WireguardConstraints,
+        Match, MatchingRelays, OpenVpnConstraints, Ownership, Provider, Providers,
+        RelayConstraints, RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate,
+        TransportPort, WireguardConstraints,
     },
-    relay_list::{RelayEndpointData, RelayListCountry},
+    relay_list::{RelayEndpointData, RelayList, RelayListCountry},
+    settings::Settings,
     ConnectionConfig, CustomTunnelEndpoint,
 };
 use std::{
-    io::BufRead,
+    io::{BufRead, IsTerminal},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
 };
 use talpid_types::net::{
@@ -21,7 +23,10 @@ use talpid_types::net::{
 };
 
 use super::{relay_constraints::LocationArgs, BooleanOption};
-use crate::print_option;
+use crate::{
+    exit_code::{InteractionRequired, UsageError},
+    format, print_option,
+};
 
 #[derive(Subcommand, Debug)]
 pub enum Relay {
@@ -29,23 +34,278 @@ pub enum Relay {
     Get,
 
     /// Set relay constraints, such as location and port
-    #[clap(subcommand)]
-    Set(SetCommands),
+    Set(SetArgs),
 
     /// List available relays
-    List,
+    List(ListArgs),
 
     /// Update the relay list
     Update,
 }
 
+/// Filters and output options of `mullvad relay list`. The filters use the same predicates as the
+/// relay selector, and a relay is only listed if it matches all of them.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ListArgs {
+    /// Only list relays in this country, given as a two-letter country code
+    #[arg(long)]
+    country: Option<CountryCode>,
+
+    /// Only list relays in this city, given as a three-letter city code
+    #[arg(long, requires = "country")]
+    city: Option<CityCode>,
+
+    /// Only list relays that support this tunnel protocol: 'wireguard' or 'openvpn'
+    #[arg(long)]
+    protocol: Option<TunnelType>,
+
+    /// Only list relays with this ownership
+    #[arg(long)]
+    ownership: Option<Ownership>,
+
+    /// Only list relays hosted by one of these providers
+    #[arg(long = "provider", num_args = 1..)]
+    providers: Vec<Provider>,
+
+    /// Leave out relays that are not currently in use
+    #[arg(long)]
+    active_only: bool,
+
+    /// Only print the hostnames of the relays, one per line
+    #[arg(long)]
+    hostnames_only: bool,
+}
+
+impl ListArgs {
+    fn matches(&self, relay: &mullvad_types::relay_list::Relay) -> bool {
+        let location = match (&self.country, &self.city) {
+            (Some(country), Some(city)) => Some(GeographicLocationConstraint::City(
+                country.to_lowercase(),
+                city.to_lowercase(),
+            )),
+            (Some(country), None) => Some(GeographicLocationConstraint::Country(
+                country.to_lowercase(),
+            )),
+            (None, _) => None,
+        };
+        let protocol_matches = match (self.protocol, &relay.endpoint_data) {
+            (None, _) => true,
+            (Some(TunnelType::OpenVpn), RelayEndpointData::Openvpn) => true,
+            (Some(TunnelType::Wireguard), RelayEndpointData::Wireguard(_)) => true,
+            (Some(_), _) => false,
+        };
+        relay.endpoint_data != RelayEndpointData::Bridge
+            && (relay.active || !self.active_only)
+            && protocol_matches
+            && location.map_or(true, |location| location.matches_with_opts(relay, true))
+            && self
+                .ownership
+                .map_or(true, |ownership| ownership.matches(relay))
+            && Providers::new(self.providers.iter().cloned())
+                .map_or(true, |providers| providers.matches(relay))
+    }
+
+    /// Returns the countries, cities and relays in `relay_list` that match the filters, sorted by
+    /// name. Countries and cities without matching relays are left out.
+    fn filter(&self, relay_list: RelayList) -> Vec<RelayListCountry> {
+        let mut countries: Vec<_> = relay_list
+            .countries
+            .into_iter()
+            .filter_map(|mut country| {
+                country.cities.retain_mut(|city| {
+                    city.relays.retain(|relay| self.matches(relay));
+                    city.relays
+                        .sort_by(|r1, r2| natord::compare_ignore_case(&r1.hostname, &r2.hostname));
+                    !city.relays.is_empty()
+                });
+                country
+                    .cities
+                    .sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+                (!country.cities.is_empty()).then_some(country)
+            })
+            .collect();
+        countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+        countries
+    }
+}
+
+/// Either a subcommand that sets one kind of constraint, or flags that set any combination of
+/// constraints in a single update. A single update only reconnects once.
+#[derive(Args, Debug, Clone)]
+#[command(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
+pub struct SetArgs {
+    #[command(subcommand)]
+    cmd: Option<SetCommands>,
+
+    #[command(flatten)]
+    constraints: ConstraintArgs,
+}
+
+/// Relay constraints that are set by `mullvad relay set` without a subcommand. Constraints that
+/// are not given are left unchanged.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ConstraintArgs {
+    /// Select relays using a country, a city in it and a relay in that city, or only the hostname
+    /// of a relay, a city code or a country code. 'any' selects relays in any location.
+    #[arg(long, num_args = 1..=3, value_names = ["COUNTRY", "CITY", "HOSTNAME"])]
+    location: Option<Vec<String>>,
+
+    /// Select relays from a custom list
+    #[arg(long, conflicts_with = "location")]
+    custom_list: Option<String>,
+
+    /// Multihop entry location, given like --location. This enables multihop.
+    #[arg(long, num_args = 1..=3, value_names = ["COUNTRY", "CITY", "HOSTNAME"])]
+    entry_location: Option<Vec<String>>,
+
+    /// Select multihop entry relays from a custom list. This enables multihop.
+    #[arg(long, conflicts_with = "entry_location")]
+    entry_custom_list: Option<String>,
+
+    /// Whether to enable multihop. Only supported with WireGuard.
+    #[arg(long)]
+    multihop: Option<BooleanOption>,
+
+    /// Hosting providers to select relays from, or 'any'
+    #[arg(long, num_args = 1..)]
+    providers: Option<Vec<Provider>>,
+
+    /// Servers to select from: 'any', 'owned', or 'rented'
+    #[arg(long)]
+    ownership: Option<Constraint<Ownership>>,
+
+    /// Tunnel protocol to use: 'any', 'wireguard', or 'openvpn'
+    #[arg(long)]
+    protocol: Option<Constraint<TunnelType>>,
+
+    /// Port to use with the tunnel protocol given with --protocol, or 'any'
+    #[arg(long, requires = "protocol")]
+    port: Option<Constraint<u16>>,
+
+    /// OpenVPN transport protocol to use, or 'any'
+    #[arg(long)]
+    transport_protocol: Option<Constraint<TransportProtocol>>,
+
+    /// WireGuard IP version to use, or 'any'
+    #[arg(long)]
+    ip_version: Option<Constraint<IpVersion>>,
+
+    /// Print how many relays match the new constraints, without applying them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl ConstraintArgs {
+    /// Rejects flags that cannot be used together.
+    fn validate(&self) -> std::result::Result<(), UsageError> {
+        let openvpn = self.protocol == Some(Constraint::Only(TunnelType::OpenVpn));
+        let wireguard = self.protocol == Some(Constraint::Only(TunnelType::Wireguard));
+        let entry_location = self.entry_location.is_some() || self.entry_custom_list.is_some();
+        let multihop = self.multihop.map(|multihop| *multihop);
+
+        if openvpn && (entry_location || multihop == Some(true)) {
+            return Err(UsageError("Multihop is only supported with WireGuard"));
+        }
+        if openvpn && self.ip_version.is_some() {
+            return Err(UsageError("--ip-version is only used with WireGuard"));
+        }
+        if wireguard && self.transport_protocol.is_some() {
+            return Err(UsageError("--transport-protocol is only used with OpenVPN"));
+        }
+        if entry_location && multihop == Some(false) {
+            return Err(UsageError("An entry location requires multihop"));
+        }
+        if self.port.is_some() && self.protocol == Some(Constraint::Any) {
+            return Err(UsageError("--port requires a specific tunnel protocol"));
+        }
+        let has_constraints = self.location.is_some()
+            || self.custom_list.is_some()
+            || entry_location
+            || multihop.is_some()
+            || self.providers.is_some()
+            || self.ownership.is_some()
+            || self.protocol.is_some()
+            || self.transport_protocol.is_some()
+            || self.ip_version.is_some();
+        if !has_constraints {
+            return Err(UsageError("No relay constraints were given"));
+        }
+        Ok(())
+    }
+
+    /// Returns the update that sets the constraints. The OpenVPN and WireGuard constraints that
+    /// are not given are kept from `settings`.
+    fn update(
+        &self,
+        settings: &Settings,
+        relay_list: &RelayList,
+    ) -> Result<RelayConstraintsUpdate> {
+        let current = match &settings.relay_settings {
+            RelaySettings::Normal(constraints) => constraints.clone(),
+            RelaySettings::CustomTunnelEndpoint(_) => RelayConstraints::default(),
+        };
+        let location = match (&self.location, &self.custom_list) {
+            (Some(location), _) => Some(location_constraint(location, &relay_list.countries)?),
+            (None, Some(name)) => Some(custom_list_constraint(settings, name)?),
+            (None, None) => None,
+        };
+        let mut wireguard_constraints = current.wireguard_constraints;
+        let mut wireguard_changed = false;
+        if self.protocol == Some(Constraint::Only(TunnelType::Wireguard)) {
+            if let Some(port) = self.port {
+                wireguard_constraints.port = validate_wireguard_port(port, relay_list)?;
+                wireguard_changed = true;
+            }
+        }
+        if let Some(ip_version) = self.ip_version {
+            wireguard_constraints.ip_version = ip_version;
+            wireguard_changed = true;
+        }
+        if let Some(multihop) = self.multihop {
+            wireguard_constraints.use_multihop = *multihop;
+            wireguard_changed = true;
+        }
+        let entry_location = match (&self.entry_location, &self.entry_custom_list) {
+            (Some(location), _) => Some(location_constraint(location, &relay_list.countries)?),
+            (None, Some(name)) => Some(custom_list_constraint(settings, name)?),
+            (None, None) => None,
+        };
+        if let Some(entry_location) = entry_location {
+            wireguard_constraints.entry_location = entry_location;
+            wireguard_constraints.use_multihop = true;
+            wireguard_changed = true;
+        }
+
+        let openvpn_port = self
+            .port
+            .filter(|_| self.protocol == Some(Constraint::Only(TunnelType::OpenVpn)));
+        let openvpn_constraints = (openvpn_port.is_some() || self.transport_protocol.is_some())
+            .then(|| OpenVpnConstraints {
+                port: parse_transport_port(
+                    openvpn_port,
+                    self.transport_protocol,
+                    &current.openvpn_constraints.port,
+                ),
+            });
+
+        Ok(RelayConstraintsUpdate {
+            location,
+            providers: self.providers.clone().map(providers_constraint),
+            ownership: self.ownership,
+            tunnel_protocol: self.protocol,
+            wireguard_constraints: wireguard_changed.then_some(wireguard_constraints),
+            openvpn_constraints,
+        })
+    }
+}
+
 #[derive(Subcommand, Debug, Clone)]
 pub enum SetCommands {
     /// Select a relay using country, city or hostname.
     /// The 'mullvad relay list' command shows the available relays and their
     /// geographical location.
     #[command(
-        override_usage = "mullvad relay set location <COUNTRY> [CITY] [HOSTNAME] | <HOSTNAME>
+        override_usage = "mullvad relay set location <COUNTRY> [CITY] [HOSTNAME] | <HOSTNAME> | <CITY>
 
   Select relay using a country:
 
@@ -61,7 +321,11 @@ pub enum SetCommands {
 
   Select relay using only its hostname:
 
-\tmullvad relay set location se-got-wg-004"
+\tmullvad relay set location se-got-wg-004
+
+  Select relay using only a city code:
+
+\tmullvad relay set location got"
     )]
     Location(LocationArgs),
 
@@ -138,7 +402,7 @@ pub enum EntryLocation {
     /// Entry endpoint to use. This can be 'any' or any location that is valid with 'set location',
     /// such as 'se got'.
     #[command(
-        override_usage = "mullvad relay set tunnel wireguard entry-location <COUNTRY> [CITY] [HOSTNAME] | <HOSTNAME>
+        override_usage = "mullvad relay set tunnel wireguard entry-location <COUNTRY> [CITY] [HOSTNAME] | <HOSTNAME> | <CITY>
 
   Select entry location using a country:
 
@@ -202,18 +466,24 @@ pub enum SetCustomCommands {
 }
 
 impl Relay {
-    pub async fn handle(self) -> Result<()> {
+    pub async fn handle(self, json: bool, non_interactive: bool) -> Result<()> {
         match self {
-            Relay::Get => Self::get().await,
-            Relay::List => Self::list().await,
+            Relay::Get => Self::get(json).await,
+            Relay::List(args) => Self::list(args, json).await,
             Relay::Update => Self::update().await,
-            Relay::Set(subcmd) => Self::set(subcmd).await,
+            Relay::Set(args) => match args.cmd {
+                Some(subcmd) => Self::set(subcmd, non_interactive).await,
+                None => Self::set_constraints(args.constraints).await,
+            },
         }
     }
 
-    async fn get() -> Result<()> {
+    async fn get(json: bool) -> Result<()> {
         let mut rpc = MullvadProxyClient::new().await?;
         let settings = rpc.get_settings().await?;
+        if json {
+            return format::print_json(&settings.relay_settings);
+        }
         let relay_settings = settings.relay_settings;
 
         match relay_settings {
@@ -284,17 +554,29 @@ impl Relay {
         Ok(())
     }
 
-    async fn list() -> Result<()> {
-        let mut countries = get_filtered_relays().await?;
-        countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
-        for mut country in countries {
-            country
-                .cities
-                .sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+    async fn list(args: ListArgs, json: bool) -> Result<()> {
+        let relay_list = MullvadProxyClient::new()
+            .await?
+            .get_relay_locations()
+            .await?;
+        let countries = args.filter(relay_list);
+        if json {
+            return format::print_json(&countries);
+        }
+        if args.hostnames_only {
+            for relay in countries
+                .iter()
+                .flat_map(|country| &country.cities)
+                .flat_map(|city| &city.relays)
+            {
+                println!("{}", relay.hostname);
+            }
+            return Ok(());
+        }
+
+        for country in countries {
             println!("{} ({})", country.name, country.code);
-            for mut city in country.cities {
-                city.relays
-                    .sort_by(|r1, r2| natord::compare_ignore_case(&r1.hostname, &r2.hostname));
+            for city in country.cities {
                 println!(
                     "\t{} ({}) @ {:.5}°N, {:.5}°W",
                     city.name, city.code, city.latitude, city.longitude
@@ -314,8 +596,9 @@ impl Relay {
                     if let Some(ipv6_addr) = relay.ipv6_addr_in {
                         addresses.push(ipv6_addr.into());
                     }
+                    let inactive = if relay.active { "" } else { ", inactive" };
                     println!(
-                        "\t\t{} ({}) - {}, hosted by {} ({ownership})",
+                        "\t\t{} ({}) - {}, hosted by {} ({ownership}){inactive}",
                         relay.hostname,
                         addresses.iter().join(", "),
                         support_msg,
@@ -346,9 +629,28 @@ impl Relay {
         Ok(())
     }
 
-    async fn set(subcmd: SetCommands) -> Result<()> {
+    /// Sets the constraints in a single update, or prints how many relays match them.
+    async fn set_constraints(args: ConstraintArgs) -> Result<()> {
+        args.validate()?;
+        let mut rpc = MullvadProxyClient::new().await?;
+        let settings = rpc.get_settings().await?;
+        let relay_list = rpc.get_relay_locations().await?;
+        let update = RelaySettingsUpdate::Normal(args.update(&settings, &relay_list)?);
+
+        if args.dry_run {
+            let matching_relays = rpc.preview_relay_settings(update).await?;
+            println!("{}", format_matching_relays(&matching_relays));
+            println!("The relay constraints were not changed");
+            return Ok(());
+        }
+        rpc.update_relay_settings(update).await?;
+        println!("Relay constraints updated");
+        Ok(())
+    }
+
+    async fn set(subcmd: SetCommands, non_interactive: bool) -> Result<()> {
         match subcmd {
-            SetCommands::Custom(subcmd) => Self::set_custom(subcmd).await,
+            SetCommands::Custom(subcmd) => Self::set_custom(subcmd, non_interactive).await,
             SetCommands::Location(location) => Self::set_location(location).await,
             SetCommands::CustomList { custom_list_name } => {
                 Self::set_custom_list(custom_list_name).await
@@ -378,7 +680,7 @@ impl Relay {
         }
     }
 
-    async fn set_custom(subcmd: SetCustomCommands) -> Result<()> {
+    async fn set_custom(subcmd: SetCustomCommands, non_interactive: bool) -> Result<()> {
         let custom_endpoint = match subcmd {
             SetCustomCommands::Openvpn {
                 host,
@@ -404,6 +706,7 @@ impl Relay {
                     tunnel_ip,
                     v4_gateway,
                     v6_gateway,
+                    non_interactive,
                 )
                 .await?
             }
@@ -438,7 +741,12 @@ impl Relay {
         tunnel_ip: Vec<IpAddr>,
         ipv4_gateway: Ipv4Addr,
         ipv6_gateway: Option<Ipv6Addr>,
+        non_interactive: bool,
     ) -> Result<CustomTunnelEndpoint> {
+        // Reading from a pipe is fine, but a terminal would wait for the user
+        if non_interactive && std::io::stdin().is_terminal() {
+            return Err(InteractionRequired("Pipe the private key to standard input").into());
+        }
         println!("Reading private key from standard input");
 
         let private_key_str = tokio::task::spawn_blocking(|| {
@@ -481,34 +789,21 @@ impl Relay {
 
     async fn set_location(location_constraint_args: LocationArgs) -> Result<()> {
         let countries = get_filtered_relays().await?;
-        let constraint =
-            if let Some(relay) =
-                // The country field is assumed to be hostname due to CLI argument parsing
-                find_relay_by_hostname(&countries, &location_constraint_args.country)
-            {
-                Constraint::Only(LocationConstraint::Location(relay))
-            } else {
-                let location_constraint: Constraint<GeographicLocationConstraint> =
-                    Constraint::from(location_constraint_args);
-                match &location_constraint {
-                    Constraint::Any => (),
-                    Constraint::Only(constraint) => {
-                        let found = countries
-                            .into_iter()
-                            .flat_map(|country| country.cities)
-                            .flat_map(|city| city.relays)
-                            .any(|relay| constraint.matches(&relay));
-
-                        if !found {
-                            eprintln!("Warning: No matching relay was found.");
-                        }
-                    }
-                }
-                location_constraint.map(LocationConstraint::Location)
-            };
+        let location_constraint = resolve_location(&countries, location_constraint_args)?;
+        if let Constraint::Only(constraint) = &location_constraint {
+            let found = countries
+                .into_iter()
+                .flat_map(|country| country.cities)
+                .flat_map(|city| city.relays)
+                .any(|relay| constraint.matches(&relay));
+
+            if !found {
+                eprintln!("Warning: No matching relay was found.");
+            }
+        }
 
         Self::update_constraints(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
-            location: Some(constraint),
+            location: Some(location_constraint.map(LocationConstraint::Location)),
             ..Default::default()
         }))
         .await
@@ -528,13 +823,8 @@ impl Relay {
     }
 
     async fn set_providers(providers: Vec<String>) -> Result<()> {
-        let providers = if providers[0].eq_ignore_ascii_case("any") {
-            Constraint::Any
-        } else {
-            Constraint::Only(Providers::new(providers.into_iter()).unwrap())
-        };
         Self::update_constraints(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
-            providers: Some(providers),
+            providers: Some(providers_constraint(providers)),
             ..Default::default()
         }))
         .await
@@ -582,23 +872,11 @@ impl Relay {
         entry_location: Option<EntryLocation>,
     ) -> Result<()> {
         let mut rpc = MullvadProxyClient::new().await?;
-        let wireguard = rpc.get_relay_locations().await?.wireguard;
+        let relay_list = rpc.get_relay_locations().await?;
         let mut wireguard_constraints = Self::get_wireguard_constraints(&mut rpc).await?;
 
         if let Some(port) = port {
-            wireguard_constraints.port = match port {
-                Constraint::Any => Constraint::Any,
-                Constraint::Only(specific_port) => {
-                    let is_valid_port = wireguard
-                        .port_ranges
-                        .into_iter()
-                        .any(|(first, last)| first <= specific_port && specific_port <= last);
-                    if !is_valid_port {
-                        return Err(anyhow!("The specified port is invalid"));
-                    }
-                    Constraint::Only(specific_port)
-                }
-            }
+            wireguard_constraints.port = validate_wireguard_port(port, &relay_list)?;
         }
 
         if let Some(ipv) = ip_version {
@@ -610,13 +888,8 @@ impl Relay {
         match entry_location {
             Some(EntryLocation::EntryLocation(entry)) => {
                 let countries = get_filtered_relays().await?;
-                // The country field is assumed to be hostname due to CLI argument parsing
                 wireguard_constraints.entry_location =
-                    if let Some(relay) = find_relay_by_hostname(&countries, &entry.country) {
-                        Constraint::Only(LocationConstraint::Location(relay))
-                    } else {
-                        Constraint::from(entry)
-                    };
+                    resolve_location(&countries, entry)?.map(LocationConstraint::Location);
             }
             Some(EntryLocation::CustomList { custom_list_name }) => {
                 let list_id = super::custom_list::find_list_by_name(&mut rpc, &custom_list_name)
@@ -656,6 +929,154 @@ impl Relay {
     }
 }
 
+/// Returns the location constraint for the arguments of `--location` or `--entry-location`. Like
+/// with `set location`, a single argument may be a hostname or a city code.
+fn location_constraint(
+    location: &[String],
+    countries: &[RelayListCountry],
+) -> Result<Constraint<LocationConstraint>> {
+    let location = LocationArgs {
+        country: location[0].clone(),
+        city: location.get(1).cloned(),
+        hostname: location.get(2).cloned(),
+    };
+    Ok(resolve_location(countries, location)?.map(LocationConstraint::Location))
+}
+
+/// Returns the constraint for a location given as a country, city and hostname. A single
+/// argument is matched against the hostnames of the relays first, then against city codes and
+/// last against country codes, so that e.g. 'se-got-wg-004' or 'got' is enough.
+fn resolve_location(
+    countries: &[RelayListCountry],
+    location: LocationArgs,
+) -> Result<Constraint<GeographicLocationConstraint>> {
+    if location.city.is_some() || location.country.eq_ignore_ascii_case("any") {
+        return Ok(Constraint::from(location));
+    }
+    let name = location.country;
+    if let Some(relay) = find_relay_by_hostname(countries, &name) {
+        return Ok(Constraint::Only(relay));
+    }
+
+    let cities: Vec<_> = countries
+        .iter()
+        .flat_map(|country| {
+            country
+                .cities
+                .iter()
+                .filter(|city| city.code.eq_ignore_ascii_case(&name))
+                .map(move |city| (country, city))
+        })
+        .collect();
+    match cities.as_slice() {
+        [] => (),
+        [(country, city)] => {
+            return Ok(Constraint::Only(GeographicLocationConstraint::City(
+                country.code.clone(),
+                city.code.clone(),
+            )));
+        }
+        cities => {
+            let candidates = cities
+                .iter()
+                .map(|(country, city)| {
+                    format!(
+                        "    {} {} ({}, {})",
+                        country.code, city.code, city.name, country.name
+                    )
+                })
+                .join("\n");
+            return Err(anyhow!(
+                "'{name}' matches several cities, give the country code as well:\n{candidates}"
+            ));
+        }
+    }
+
+    if let Some(country) = countries
+        .iter()
+        .find(|country| country.code.eq_ignore_ascii_case(&name))
+    {
+        return Ok(Constraint::Only(GeographicLocationConstraint::Country(
+            country.code.clone(),
+        )));
+    }
+
+    // The relay may have been removed, so suggest the other relays in the same city
+    let prefix = name.to_lowercase().splitn(3, '-').take(2).join("-") + "-";
+    let similar = countries
+        .iter()
+        .flat_map(|country| &country.cities)
+        .flat_map(|city| &city.relays)
+        .filter(|relay| relay.hostname.to_lowercase().starts_with(&prefix))
+        .map(|relay| format!("    {}", relay.hostname))
+        .join("\n");
+    if name.contains('-') && !similar.is_empty() {
+        return Err(anyhow!(
+            "No relay called '{name}' was found. These relays are in the same location:\n{similar}"
+        ));
+    }
+    Err(anyhow!(
+        "No relay, city or country called '{name}' was found. The 'mullvad relay list' command \
+         shows the available locations"
+    ))
+}
+
+fn custom_list_constraint(
+    settings: &Settings,
+    name: &str,
+) -> Result<Constraint<LocationConstraint>> {
+    let list = settings
+        .custom_lists
+        .iter()
+        .find(|list| list.name == name)
+        .ok_or(anyhow!("List not found"))?;
+    Ok(Constraint::Only(LocationConstraint::CustomList {
+        list_id: list.id,
+    }))
+}
+
+fn providers_constraint(providers: Vec<Provider>) -> Constraint<Providers> {
+    if providers[0].eq_ignore_ascii_case("any") {
+        Constraint::Any
+    } else {
+        Constraint::Only(Providers::new(providers.into_iter()).unwrap())
+    }
+}
+
+/// Returns `port` if the WireGuard relays in `relay_list` accept it.
+fn validate_wireguard_port(
+    port: Constraint<u16>,
+    relay_list: &RelayList,
+) -> Result<Constraint<u16>> {
+    if let Constraint::Only(specific_port) = port {
+        let is_valid_port = relay_list
+            .wireguard
+            .port_ranges
+            .iter()
+            .any(|&(first, last)| first <= specific_port && specific_port <= last);
+        if !is_valid_port {
+            return Err(anyhow!("The specified port is invalid"));
+        }
+    }
+    Ok(port)
+}
+
+fn format_matching_relays(matching_relays: &MatchingRelays) -> String {
+    let relays = |count: usize| match count {
+        1 => "1 relay".to_owned(),
+        count => format!("{count} relays"),
+    };
+    match (matching_relays.relays, matching_relays.entry_relays) {
+        (1, None) => "1 relay matches the new constraints".to_owned(),
+        (count, None) => format!("{count} relays match the new constraints"),
+        (exit_relays, Some(entry_relays)) => format!(
+            "{} match as exit and {} as entry with the new constraints",
+            relays(exit_relays),
+            relays(entry_relays)
+        ),
+    }
+}
+
 fn parse_transport_port(
     port: Option<Constraint<u16>>,
     protocol: Option<Constraint<TransportProtocol>>,
@@ -734,3 +1155,477 @@ pub async fn get_filtered_relays() -> Result<Vec<RelayListCountry>> {
 
     Ok(countries)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+    use mullvad_types::relay_list::{Relay, RelayListCity, WireguardRelayEndpointData};
+
+    #[derive(Parser, Debug)]
+    struct TestCli {
+        #[clap(flatten)]
+        args: ListArgs,
+    }
+
+    fn list_args(args: &[&str]) -> ListArgs {
+        TestCli::try_parse_from(std::iter::once("test").chain(args.iter().copied()))
+            .unwrap()
+            .args
+    }
+
+    fn relay(hostname: &str, provider: &str, owned: bool, active: bool) -> Relay {
+        let mut parts = hostname.split('-');
+        let country_code = parts.next().unwrap().to_owned();
+        let city_code = parts.next().unwrap().to_owned();
+        let endpoint_data = match parts.next().unwrap() {
+            "wg" => RelayEndpointData::Wireguard(WireguardRelayEndpointData {
+                public_key: wireguard::PrivateKey::new_from_random().public_key(),
+                quic: None,
+                tls: None,
+            }),
+            "br" => RelayEndpointData::Bridge,
+            _ => RelayEndpointData::Openvpn,
+        };
+        Relay {
+            hostname: hostname.to_owned(),
+            ipv4_addr_in: Ipv4Addr::new(10, 0, 0, 1),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active,
+            owned,
+            provider: provider.to_owned(),
+            weight: 1,
+            endpoint_data,
+            location: Some(Location {
+                country: country_code.clone(),
+                country_code,
+                city: city_code.clone(),
+                city_code,
+                latitude: 0.0,
+                longitude: 0.0,
+            }),
+        }
+    }
+
+    fn relay_list() -> RelayList {
+        let city = |code: &str, relays| RelayListCity {
+            name: code.to_owned(),
+            code: code.to_owned(),
+            latitude: 0.0,
+            longitude: 0.0,
+            relays,
+        };
+        RelayList {
+            countries: vec![
+                RelayListCountry {
+                    name: "Sweden".to_owned(),
+                    code: "se".to_owned(),
+                    cities: vec![city(
+                        "got",
+                        vec![
+                            relay("se-got-wg-010", "31173", true, true),
+                            relay("se-got-wg-002", "31173", true, false),
+                            relay("se-got-ovpn-001", "31173", true, true),
+                            relay("se-got-br-001", "31173", true, true),
+                        ],
+                    )],
+                },
+                RelayListCountry {
+                    name: "Germany".to_owned(),
+                    code: "de".to_owned(),
+                    cities: vec![
+                        city("fra", vec![relay("de-fra-wg-001", "M247", false, true)]),
+                        city(
+                            "ber",
+                            vec![
+                                relay("de-ber-wg-001", "DataPacket", false, true),
+                                relay("de-ber-wg-002", "xtom", true, true),
+                            ],
+                        ),
+                    ],
+                },
+            ],
+            ..RelayList::empty()
+        }
+    }
+
+    #[derive(Parser, Debug)]
+    struct RelayCli {
+        #[command(subcommand)]
+        relay: super::Relay,
+    }
+
+    fn set_args(args: &[&str]) -> std::result::Result<SetArgs, clap::Error> {
+        let cli = RelayCli::try_parse_from(["test", "set"].iter().chain(args))?;
+        match cli.relay {
+            super::Relay::Set(args) => Ok(args),
+            relay => panic!("unexpected command: {relay:?}"),
+        }
+    }
+
+    fn constraint_args(args: &[&str]) -> ConstraintArgs {
+        let args = set_args(args).unwrap();
+        assert!(args.cmd.is_none());
+        args.constraints
+    }
+
+    fn constraints_update(args: &[&str], settings: &Settings) -> Result<RelayConstraintsUpdate> {
+        let args = constraint_args(args);
+        args.validate()?;
+        let mut relay_list = relay_list();
+        relay_list.wireguard.port_ranges = vec![(53, 53), (4000, 33433), (51820, 51820)];
+        args.update(settings, &relay_list)
+    }
+
+    fn location(country: &str) -> Constraint<LocationConstraint> {
+        Constraint::Only(LocationConstraint::Location(
+            GeographicLocationConstraint::Country(country.to_owned()),
+        ))
+    }
+
+    fn hostnames(args: &[&str]) -> Vec<String> {
+        list_args(args)
+            .filter(relay_list())
+            .into_iter()
+            .flat_map(|country| country.cities)
+            .flat_map(|city| city.relays)
+            .map(|relay| relay.hostname)
+            .collect()
+    }
+
+    #[test]
+    fn test_list_sorting() {
+        // Bridges are never listed. Countries and cities are sorted by name, and relays by
+        // hostname.
+        assert_eq!(
+            hostnames(&[]),
+            vec![
+                "de-ber-wg-001",
+                "de-ber-wg-002",
+                "de-fra-wg-001",
+                "se-got-ovpn-001",
+                "se-got-wg-002",
+                "se-got-wg-010",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_filters() {
+        assert_eq!(
+            hostnames(&["--country", "DE", "--city", "ber"]),
+            vec!["de-ber-wg-001", "de-ber-wg-002"]
+        );
+        assert_eq!(
+            hostnames(&["--protocol", "openvpn"]),
+            vec!["se-got-ovpn-001"]
+        );
+        assert_eq!(
+            hostnames(&["--provider", "M247", "xtom"]),
+            vec!["de-ber-wg-002", "de-fra-wg-001"]
+        );
+        assert_eq!(
+            hostnames(&["--active-only", "--country", "se"]),
+            vec!["se-got-ovpn-001", "se-got-wg-010"]
+        );
+        // The filters compose
+        assert_eq!(
+            hostnames(&[
+                "--country",
+                "de",
+                "--protocol",
+                "wireguard",
+                "--ownership",
+                "mullvad-owned"
+            ]),
+            vec!["de-ber-wg-002"]
+        );
+        assert!(hostnames(&["--country", "se", "--ownership", "rented"]).is_empty());
+        assert!(TestCli::try_parse_from(["test", "--city", "got"]).is_err());
+    }
+
+    #[test]
+    fn test_set_constraints() {
+        let update = constraints_update(
+            &[
+                "--location",
+                "se",
+                "--entry-location",
+                "de",
+                "ber",
+                "--providers",
+                "31173",
+                "--ownership",
+                "owned",
+                "--protocol",
+                "wireguard",
+                "--port",
+                "51820",
+            ],
+            &Settings::default(),
+        )
+        .unwrap();
+
+        assert_eq!(update.location, Some(location("se")));
+        assert_eq!(
+            update.providers,
+            Some(Constraint::Only(
+                Providers::new(std::iter::once("31173".to_owned())).unwrap()
+            ))
+        );
+        assert_eq!(
+            update.ownership,
+            Some(Constraint::Only(Ownership::MullvadOwned))
+        );
+        assert_eq!(
+            update.tunnel_protocol,
+            Some(Constraint::Only(TunnelType::Wireguard))
+        );
+        let wireguard_constraints = update.wireguard_constraints.unwrap();
+        assert_eq!(wireguard_constraints.port, Constraint::Only(51820));
+        assert!(wireguard_constraints.use_multihop);
+        assert_eq!(
+            wireguard_constraints.entry_location,
+            Constraint::Only(LocationConstraint::Location(
+                GeographicLocationConstraint::City("de".to_owned(), "ber".to_owned())
+            ))
+        );
+        assert_eq!(update.openvpn_constraints, None);
+    }
+
+    #[test]
+    fn test_set_constraints_keeps_others() {
+        let mut settings = Settings::default();
+        let RelaySettings::Normal(constraints) = &mut settings.relay_settings else {
+            unreachable!();
+        };
+        constraints.wireguard_constraints.ip_version = Constraint::Only(IpVersion::V6);
+        constraints.openvpn_constraints.port = Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Tcp,
+            port: Constraint::Only(443),
+        });
+
+        // Only the given constraints are changed
+        let update = constraints_update(&["--location", "se-got-wg-010"], &settings).unwrap();
+        assert_eq!(
+            update.location,
+            Some(Constraint::Only(LocationConstraint::Location(
+                GeographicLocationConstraint::Hostname(
+                    "se".to_owned(),
+                    "got".to_owned(),
+                    "se-got-wg-010".to_owned()
+                )
+            )))
+        );
+        assert!(update.providers.is_none());
+        assert!(update.ownership.is_none());
+        assert!(update.tunnel_protocol.is_none());
+        assert_eq!(update.wireguard_constraints, None);
+        assert_eq!(update.openvpn_constraints, None);
+
+        // The OpenVPN and WireGuard constraints that are not given are kept from the settings
+        let update =
+            constraints_update(&["--protocol", "openvpn", "--port", "80"], &settings).unwrap();
+        assert_eq!(
+            update.openvpn_constraints.unwrap().port,
+            Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Only(80),
+            })
+        );
+        assert_eq!(update.wireguard_constraints, None);
+
+        let update = constraints_update(&["--multihop", "on"], &settings).unwrap();
+        let wireguard_constraints = update.wireguard_constraints.unwrap();
+        assert!(wireguard_constraints.use_multihop);
+        assert_eq!(
+            wireguard_constraints.ip_version,
+            Constraint::Only(IpVersion::V6)
+        );
+
+        let update =
+            constraints_update(&["--location", "any", "--providers", "any"], &settings).unwrap();
+        assert_eq!(update.location, Some(Constraint::Any));
+        assert_eq!(update.providers, Some(Constraint::Any));
+    }
+
+    /// Resolves `args` against the relay list, with a city code that is used in two countries.
+    fn resolve(args: &[&str]) -> Result<Constraint<GeographicLocationConstraint>> {
+        let mut countries = relay_list().countries;
+        countries.push(RelayListCountry {
+            name: "USA".to_owned(),
+            code: "us".to_owned(),
+            cities: vec![RelayListCity {
+                name: "Berlin, NH".to_owned(),
+                code: "ber".to_owned(),
+                latitude: 0.0,
+                longitude: 0.0,
+                relays: vec![relay("us-ber-wg-001", "M247", false, true)],
+            }],
+        });
+        let location = LocationArgs {
+            country: args[0].to_owned(),
+            city: args.get(1).map(|city| city.to_string()),
+            hostname: args.get(2).map(|hostname| hostname.to_string()),
+        };
+        resolve_location(&countries, location)
+    }
+
+    #[test]
+    fn test_resolve_location() {
+        let hostname = Constraint::Only(GeographicLocationConstraint::Hostname(
+            "se".to_owned(),
+            "got".to_owned(),
+            "se-got-wg-010".to_owned(),
+        ));
+        assert_eq!(resolve(&["se-got-wg-010"]).unwrap(), hostname);
+        assert_eq!(resolve(&["SE-GOT-WG-010"]).unwrap(), hostname);
+        assert_eq!(resolve(&["se", "got", "se-got-wg-010"]).unwrap(), hostname);
+
+        let gothenburg = Constraint::Only(GeographicLocationConstraint::City(
+            "se".to_owned(),
+            "got".to_owned(),
+        ));
+        assert_eq!(resolve(&["got"]).unwrap(), gothenburg);
+        assert_eq!(resolve(&["GOT"]).unwrap(), gothenburg);
+        assert_eq!(resolve(&["se", "got"]).unwrap(), gothenburg);
+
+        assert_eq!(
+            resolve(&["de"]).unwrap(),
+            Constraint::Only(GeographicLocationConstraint::Country("de".to_owned()))
+        );
+        assert_eq!(resolve(&["any"]).unwrap(), Constraint::Any);
+
+        // Both arguments are kept when the country is given, even if the city is not in it
+        assert_eq!(
+            resolve(&["us", "fra"]).unwrap(),
+            Constraint::Only(GeographicLocationConstraint::City(
+                "us".to_owned(),
+                "fra".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_resolve_location_errors() {
+        // City codes that are used in several countries are ambiguous
+        let error = resolve(&["ber"]).unwrap_err().to_string();
+        assert!(error.contains("'ber' matches several cities"), "{error}");
+        assert!(error.contains("de ber (ber, Germany)"), "{error}");
+        assert!(error.contains("us ber (Berlin, NH, USA)"), "{error}");
+        assert_eq!(
+            resolve(&["us", "ber"]).unwrap(),
+            Constraint::Only(GeographicLocationConstraint::City(
+                "us".to_owned(),
+                "ber".to_owned()
+            ))
+        );
+
+        // Relays that are no longer in the list are not taken as countries
+        let error = resolve(&["se-got-wg-004"]).unwrap_err().to_string();
+        assert!(error.contains("No relay called 'se-got-wg-004'"), "{error}");
+        assert!(error.contains("se-got-wg-010"), "{error}");
+        assert!(!error.contains("de-ber-wg-001"), "{error}");
+
+        for name in ["xyz", "nl-ams-wg-001"] {
+            let error = resolve(&[name]).unwrap_err().to_string();
+            assert!(
+                error.starts_with("No relay, city or country called"),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_constraints_location_shorthand() {
+        let update = constraints_update(
+            &["--location", "fra", "--entry-location", "got"],
+            &Settings::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            update.location,
+            Some(Constraint::Only(LocationConstraint::Location(
+                GeographicLocationConstraint::City("de".to_owned(), "fra".to_owned())
+            )))
+        );
+        assert_eq!(
+            update.wireguard_constraints.unwrap().entry_location,
+            Constraint::Only(LocationConstraint::Location(
+                GeographicLocationConstraint::City("se".to_owned(), "got".to_owned())
+            ))
+        );
+        assert!(constraints_update(&["--location", "xyz"], &Settings::default()).is_err());
+    }
+
+    #[test]
+    fn test_set_constraints_conflicts() {
+        let settings = Settings::default();
+        let error = |args: &[&str]| {
+            constraints_update(args, &settings)
+                .unwrap_err()
+                .downcast::<UsageError>()
+                .unwrap()
+                .0
+        };
+
+        assert_eq!(
+            error(&["--protocol", "openvpn", "--entry-location", "se"]),
+            "Multihop is only supported with WireGuard"
+        );
+        assert_eq!(
+            error(&["--protocol", "openvpn", "--multihop", "on"]),
+            "Multihop is only supported with WireGuard"
+        );
+        assert_eq!(
+            error(&["--protocol", "openvpn", "--ip-version", "v6"]),
+            "--ip-version is only used with WireGuard"
+        );
+        assert_eq!(
+            error(&["--protocol", "wireguard", "--transport-protocol", "tcp"]),
+            "--transport-protocol is only used with OpenVPN"
+        );
+        assert_eq!(
+            error(&["--entry-location", "se", "--multihop", "off"]),
+            "An entry location requires multihop"
+        );
+        assert_eq!(
+            error(&["--protocol", "any", "--port", "53"]),
+            "--port requires a specific tunnel protocol"
+        );
+        assert_eq!(error(&["--dry-run"]), "No relay constraints were given");
+
+        // Ports that the relays do not accept are rejected
+        assert!(
+            constraints_update(&["--protocol", "wireguard", "--port", "80"], &settings).is_err()
+        );
+
+        // Rejected while parsing
+        assert!(set_args(&[]).is_err());
+        assert!(set_args(&["--port", "53"]).is_err());
+        assert!(set_args(&["--location", "se", "--custom-list", "work"]).is_err());
+        assert!(set_args(&["--location", "se", "got", "se-got-wg-010", "x"]).is_err());
+        assert!(set_args(&["location", "se", "--protocol", "wireguard"]).is_err());
+        assert!(set_args(&["--protocol", "wireguard", "location", "se"]).is_err());
+        assert!(set_args(&["location", "se"]).unwrap().cmd.is_some());
+    }
+
+    #[test]
+    fn test_format_matching_relays() {
+        assert_eq!(
+            format_matching_relays(&MatchingRelays {
+                relays: 1,
+                entry_relays: None
+            }),
+            "1 relay matches the new constraints"
+        );
+        assert_eq!(
+            format_matching_relays(&MatchingRelays {
+                relays: 12,
+                entry_relays: Some(0)
+            }),
+            "12 relays match as exit and 0 relays as entry with the new constraints"
+        );
+    }
+}
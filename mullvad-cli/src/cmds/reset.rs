@@ -1,19 +1,92 @@
+use crate::exit_code::InteractionRequired;
 use anyhow::Result;
+use clap::Args;
 use mullvad_management_interface::MullvadProxyClient;
+use mullvad_types::settings::ResetScopes;
 use std::io::stdin;
 
-pub async fn handle() -> Result<()> {
-    if receive_confirmation().await {
+#[derive(Args, Debug, Clone)]
+pub struct ResetArgs {
+    /// Do not ask for confirmation
+    #[arg(long, short = 'y')]
+    yes: bool,
+
+    /// Restore the default settings, but stay logged in
+    #[arg(long)]
+    settings: bool,
+
+    /// Remove the cached relay list and API addresses, and fetch them again
+    #[arg(long)]
+    cache: bool,
+
+    /// Forget saved accounts and the connection history
+    #[arg(long)]
+    history: bool,
+
+    /// Log out, and remove all settings, caches, history and logs. This is the default if no
+    /// other scope is given
+    #[arg(long, conflicts_with_all = ["settings", "cache", "history"])]
+    all: bool,
+}
+
+impl ResetArgs {
+    fn scopes(&self) -> ResetScopes {
+        if self.all {
+            return ResetScopes::all();
+        }
+        ResetScopes {
+            settings: self.settings,
+            cache: self.cache,
+            history: self.history,
+        }
+    }
+}
+
+pub async fn handle(args: ResetArgs, non_interactive: bool) -> Result<()> {
+    let scopes = args.scopes();
+    if !args.yes && non_interactive {
+        return Err(InteractionRequired("Pass --yes to reset the settings").into());
+    }
+    if args.yes || receive_confirmation(scopes).await {
         let mut rpc = MullvadProxyClient::new().await?;
-        rpc.factory_reset().await?;
-        #[cfg(target_os = "linux")]
-        println!("If you're running systemd, to remove all logs, you must use journalctl");
+        rpc.factory_reset(scopes).await?;
+        if scopes.is_all() {
+            #[cfg(target_os = "linux")]
+            println!("If you're running systemd, to remove all logs, you must use journalctl");
+        } else {
+            println!("Reset {}", describe_scopes(scopes).join(", "));
+        }
     }
     Ok(())
 }
 
-async fn receive_confirmation() -> bool {
-    println!("Are you sure you want to disconnect, log out, delete all settings, logs and cache files for the Mullvad VPN system service? [Yes/No (default)]");
+/// Describes what is reset, for the confirmation prompt and the output.
+fn describe_scopes(scopes: ResetScopes) -> Vec<&'static str> {
+    if scopes.is_all() {
+        return vec!["the account login, all settings, logs and cache files"];
+    }
+    let mut parts = vec![];
+    if scopes.settings {
+        parts.push("the settings");
+    }
+    if scopes.cache {
+        parts.push("the relay list and API address cache");
+    }
+    if scopes.history {
+        parts.push("the saved accounts and connection history");
+    }
+    parts
+}
+
+async fn receive_confirmation(scopes: ResetScopes) -> bool {
+    if scopes.is_all() {
+        println!("Are you sure you want to disconnect, log out, delete all settings, logs and cache files for the Mullvad VPN system service? [Yes/No (default)]");
+    } else {
+        println!(
+            "Are you sure you want to reset {}? [Yes/No (default)]",
+            describe_scopes(scopes).join(", ")
+        );
+    }
 
     tokio::task::spawn_blocking(|| loop {
         let mut buf = String::new();
@@ -30,3 +103,63 @@ async fn receive_confirmation() -> bool {
     .await
     .unwrap()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        args: ResetArgs,
+    }
+
+    fn parse(args: &[&str]) -> clap::error::Result<ResetArgs> {
+        TestCli::try_parse_from(["factory-reset"].iter().chain(args)).map(|cli| cli.args)
+    }
+
+    #[test]
+    fn test_reset_scopes() {
+        assert!(parse(&[]).unwrap().scopes().is_all());
+        assert!(parse(&["--all", "--yes"]).unwrap().scopes().is_all());
+
+        let settings = parse(&["--settings"]).unwrap().scopes();
+        assert_eq!(
+            settings,
+            ResetScopes {
+                settings: true,
+                cache: false,
+                history: false,
+            }
+        );
+        assert!(!settings.is_all());
+
+        let scopes = parse(&["--cache", "--history", "-y"]).unwrap();
+        assert!(scopes.yes);
+        assert_eq!(
+            scopes.scopes(),
+            ResetScopes {
+                settings: false,
+                cache: true,
+                history: true,
+            }
+        );
+
+        assert!(parse(&["--all", "--settings"]).is_err());
+        assert!(parse(&["--all", "--history"]).is_err());
+    }
+
+    #[test]
+    fn test_describe_scopes() {
+        assert_eq!(
+            describe_scopes(ResetScopes {
+                settings: true,
+                cache: false,
+                history: true,
+            }),
+            ["the settings", "the saved accounts and connection history"]
+        );
+        assert_eq!(describe_scopes(ResetScopes::all()).len(), 1);
+    }
+}
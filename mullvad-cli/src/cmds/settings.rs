@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use mullvad_management_interface::MullvadProxyClient;
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Move the settings to another machine, or change several of them at once. The settings are
+/// exported as a JSON document, which can be imported by the same or a later version of the app
+#[derive(Subcommand, Debug)]
+pub enum Settings {
+    /// Export all settings. Passwords and private keys are left out by default, and settings
+    /// that need them cannot be imported
+    Export {
+        /// File to write the settings to. They are printed if no file is given
+        file: Option<PathBuf>,
+
+        /// Also export passwords, private keys and the account and device that is logged in.
+        /// Anyone who has the file can use the account
+        #[arg(long)]
+        include_secrets: bool,
+    },
+
+    /// Replace all settings with exported settings. Nothing is changed if any of them are
+    /// invalid. An exported device is only used if no account is logged in
+    Import {
+        /// File to read the settings from, or "-" to read them from standard input
+        file: PathBuf,
+    },
+
+    /// Change several settings at once, so that the tunnel reconnects at most once. The patch is
+    /// a JSON merge patch against the "settings" of an exported document: objects are merged,
+    /// null removes a field and anything else replaces it. Nothing is changed if the patched
+    /// settings are invalid
+    Apply {
+        /// File to read the patch from, or "-" to read it from standard input
+        file: PathBuf,
+    },
+}
+
+impl Settings {
+    pub async fn handle(self) -> Result<()> {
+        match self {
+            Settings::Export {
+                file,
+                include_secrets,
+            } => Self::export(file, include_secrets).await,
+            Settings::Import { file } => Self::import(&file).await,
+            Settings::Apply { file } => Self::apply(&file).await,
+        }
+    }
+
+    async fn export(file: Option<PathBuf>, include_secrets: bool) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let document = rpc.export_settings(include_secrets).await?;
+        match file {
+            Some(file) => {
+                write_document(&file, &document, include_secrets)
+                    .with_context(|| format!("Failed to write {}", file.display()))?;
+                println!("Exported settings to {}", file.display());
+            }
+            None => println!("{document}"),
+        }
+        Ok(())
+    }
+
+    async fn import(file: &Path) -> Result<()> {
+        let document = read_document(file)?;
+        let mut rpc = MullvadProxyClient::new().await?;
+        rpc.import_settings(document).await?;
+        println!("Imported settings");
+        Ok(())
+    }
+
+    async fn apply(file: &Path) -> Result<()> {
+        let patch = read_document(file)?;
+        let mut rpc = MullvadProxyClient::new().await?;
+        rpc.apply_settings_patch(patch).await?;
+        println!("Applied settings patch");
+        Ok(())
+    }
+}
+
+/// Reads `file`, or standard input if it is "-".
+fn read_document(file: &Path) -> Result<String> {
+    if file == Path::new("-") {
+        let mut document = String::new();
+        io::stdin()
+            .read_to_string(&mut document)
+            .context("Failed to read standard input")?;
+        Ok(document)
+    } else {
+        std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))
+    }
+}
+
+fn write_document(file: &Path, document: &str, include_secrets: bool) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Keep other users from reading the secrets
+    #[cfg(unix)]
+    if include_secrets {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = include_secrets;
+    options.open(file)?.write_all(document.as_bytes())
+}
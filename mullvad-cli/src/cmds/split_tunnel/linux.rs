@@ -1,35 +1,282 @@
+use super::super::BooleanOption;
 use anyhow::Result;
-use clap::Subcommand;
-use mullvad_management_interface::MullvadProxyClient;
+use clap::{Args, Subcommand, ValueEnum};
+use futures::StreamExt;
+use ipnetwork::IpNetwork;
+use mullvad_management_interface::{
+    client::{DaemonEvent, EventCategory, EventFilter},
+    MullvadProxyClient,
+};
+use std::collections::BTreeMap;
+use talpid_types::{
+    net::TransportProtocol,
+    split_tunnel::{
+        disabled_ip_forwarding, ExcludedDestination, ExcludedProcess, PortRange, SplitTunnelMode,
+    },
+};
 
 /// Manage split tunneling. To launch applications outside the tunnel, use the program
 /// 'mullvad-exclude' instead of this command
 #[derive(Subcommand, Debug)]
 pub enum SplitTunnel {
-    /// List all processes that are excluded from the tunnel
-    List,
+    /// List all processes that are excluded from the tunnel, and why they are excluded
+    List {
+        /// Keep running and print processes as they start or stop being excluded
+        #[arg(long)]
+        watch: bool,
+    },
     /// Add a PID to exclude from the tunnel
     Add { pid: i32 },
     /// Stop excluding a PID from the tunnel
     Delete { pid: i32 },
     /// Stop excluding all processes from the tunnel
     Clear,
+    /// Manage users whose processes are excluded from the tunnel
+    #[clap(subcommand)]
+    Uid(Owner),
+    /// Manage groups whose processes are excluded from the tunnel
+    #[clap(subcommand)]
+    Gid(Owner),
+    /// Choose whether the selected processes are excluded from the tunnel, or are the only ones
+    /// that use it
+    #[clap(subcommand)]
+    Mode(Mode),
+    /// Manage destinations whose traffic is excluded from the tunnel, regardless of which process
+    /// sends it
+    #[clap(subcommand)]
+    Dest(Destination),
+    /// Manage interfaces whose forwarded traffic is excluded from the tunnel, such as the
+    /// bridges of containers
+    #[clap(subcommand)]
+    Interface(Interface),
+    /// Manage local ports whose traffic is excluded from the tunnel, so that services that listen
+    /// on them are reached, and reply, outside the tunnel
+    #[clap(subcommand)]
+    Port(LocalPort),
+    /// Choose whether excluded processes may reach the local network when local network sharing
+    /// is disabled
+    #[clap(subcommand)]
+    Lan(Lan),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Lan {
+    /// Display whether excluded processes may reach the local network
+    Get,
+    /// Allow or block local network access for excluded processes. Other processes are only
+    /// allowed to reach the local network if local network sharing is enabled
+    Set {
+        #[arg(value_parser = BooleanOption::custom_parser("allow", "block"))]
+        policy: BooleanOption,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Interface {
+    /// List the interfaces that are excluded from the tunnel
+    List,
+    /// Exclude traffic that is forwarded from an interface from the tunnel
+    Add {
+        /// Name of the interface, such as docker0. End it with '*' to match all interfaces that
+        /// start with the rest of the name, such as br-* for all Docker networks
+        name: String,
+    },
+    /// Stop excluding traffic from an interface
+    Remove { name: String },
+    /// Stop excluding traffic from all interfaces
+    Clear,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LocalPort {
+    /// List the local ports that are excluded from the tunnel
+    List,
+    /// Exclude traffic to and from a local port from the tunnel
+    Add(LocalPortArgs),
+    /// Stop excluding traffic of a local port
+    Remove(LocalPortArgs),
+    /// Stop excluding traffic of all local ports
+    Clear,
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct LocalPortArgs {
+    /// Port that the service listens on, such as 51820. Ports that the system assigns to outgoing
+    /// connections cannot be excluded
+    port: u16,
+    /// Transport protocol of the service
+    #[arg(long)]
+    protocol: TransportProtocol,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Destination {
+    /// List the destinations that are excluded from the tunnel
+    List,
+    /// Exclude traffic to a destination from the tunnel
+    Add(DestinationArgs),
+    /// Stop excluding traffic to a destination
+    Remove(DestinationArgs),
+    /// Stop excluding traffic to all destinations
+    Clear,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DestinationArgs {
+    /// Network to exclude, such as 10.0.0.0/8
+    network: IpNetwork,
+    /// Destination port, such as 445, or range of ports, such as 8000-8100. All ports are
+    /// excluded if this is not given
+    #[arg(long)]
+    port: Option<PortRange>,
+    /// Transport protocol to exclude
+    #[arg(long)]
+    protocol: TransportProtocol,
+}
+
+impl From<DestinationArgs> for ExcludedDestination {
+    fn from(args: DestinationArgs) -> Self {
+        ExcludedDestination {
+            network: args.network,
+            ports: args.port,
+            protocol: args.protocol,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Mode {
+    /// Display the split tunnel mode
+    Get,
+    /// Set the split tunnel mode. In include mode, only the selected processes use the tunnel.
+    /// All other traffic is sent outside the tunnel while connected, and is blocked while
+    /// connecting, in the error state, and while disconnected if lockdown mode is enabled
+    Set { mode: ModeArg },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ModeArg {
+    Exclude,
+    Include,
+}
+
+impl From<ModeArg> for SplitTunnelMode {
+    fn from(mode: ModeArg) -> Self {
+        match mode {
+            ModeArg::Exclude => SplitTunnelMode::Exclude,
+            ModeArg::Include => SplitTunnelMode::Include,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Owner {
+    /// List the IDs that are excluded from the tunnel
+    List,
+    /// Exclude all processes running with an ID from the tunnel
+    Add { id: u32 },
+    /// Stop excluding the processes running with an ID
+    Remove { id: u32 },
+    /// Stop excluding the processes running with any ID
+    Clear,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum OwnerKind {
+    User,
+    Group,
+}
+
+impl OwnerKind {
+    fn name(self) -> &'static str {
+        match self {
+            OwnerKind::User => "user",
+            OwnerKind::Group => "group",
+        }
+    }
 }
 
 impl SplitTunnel {
     pub async fn handle(self) -> Result<()> {
+        let changes_settings = self.changes_settings();
+        self.run().await?;
+        if changes_settings {
+            super::print_config_warnings().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the command changes the settings, rather than only displaying them or
+    /// changing the processes that are excluded at runtime.
+    fn changes_settings(&self) -> bool {
         match self {
-            SplitTunnel::List => {
-                let pids = MullvadProxyClient::new()
-                    .await?
-                    .get_split_tunnel_processes()
-                    .await?;
+            SplitTunnel::List { .. }
+            | SplitTunnel::Add { .. }
+            | SplitTunnel::Delete { .. }
+            | SplitTunnel::Clear
+            | SplitTunnel::Uid(Owner::List)
+            | SplitTunnel::Gid(Owner::List)
+            | SplitTunnel::Mode(Mode::Get)
+            | SplitTunnel::Dest(Destination::List)
+            | SplitTunnel::Interface(Interface::List)
+            | SplitTunnel::Port(LocalPort::List)
+            | SplitTunnel::Lan(Lan::Get) => false,
+            SplitTunnel::Uid(_)
+            | SplitTunnel::Gid(_)
+            | SplitTunnel::Mode(Mode::Set { .. })
+            | SplitTunnel::Dest(_)
+            | SplitTunnel::Interface(_)
+            | SplitTunnel::Port(_)
+            | SplitTunnel::Lan(Lan::Set { .. }) => true,
+        }
+    }
+
+    async fn run(self) -> Result<()> {
+        match self {
+            SplitTunnel::List { watch } => {
+                let mut rpc = MullvadProxyClient::new().await?;
+                // Listen before listing the processes, so that no changes are missed
+                let events = if watch {
+                    Some(
+                        rpc.events_listen_filtered(EventFilter {
+                            categories: vec![EventCategory::Other],
+                            replay: 0,
+                        })
+                        .await?,
+                    )
+                } else {
+                    None
+                };
+                let mut processes = excluded_processes(&mut rpc).await?;
 
-                println!("Excluded PIDs:");
-                for pid in &pids {
-                    println!("{pid}");
+                println!("Excluded processes:");
+                for process in processes.values() {
+                    print_process("", process);
                 }
+                let Some(mut events) = events else {
+                    return Ok(());
+                };
 
+                while let Some(event) = events.next().await {
+                    let DaemonEvent::ExcludedProcesses(current) = event? else {
+                        continue;
+                    };
+                    let current: BTreeMap<_, _> = current
+                        .into_iter()
+                        .map(|process| (process.pid, process))
+                        .collect();
+                    for (pid, process) in &processes {
+                        if current.get(pid) != Some(process) {
+                            print_process("- ", process);
+                        }
+                    }
+                    for (pid, process) in &current {
+                        if processes.get(pid) != Some(process) {
+                            print_process("+ ", process);
+                        }
+                    }
+                    processes = current;
+                }
                 Ok(())
             }
             SplitTunnel::Add { pid } => {
@@ -56,6 +303,259 @@ impl SplitTunnel {
                 println!("Stopped excluding all processes");
                 Ok(())
             }
+            SplitTunnel::Uid(owner) => owner.handle(OwnerKind::User).await,
+            SplitTunnel::Gid(owner) => owner.handle(OwnerKind::Group).await,
+            SplitTunnel::Mode(Mode::Get) => {
+                let settings = MullvadProxyClient::new().await?.get_settings().await?;
+                println!("Split tunnel mode: {}", settings.split_tunnel.mode);
+                Ok(())
+            }
+            SplitTunnel::Mode(Mode::Set { mode }) => {
+                let mode = SplitTunnelMode::from(mode);
+                MullvadProxyClient::new()
+                    .await?
+                    .set_split_tunnel_mode(mode)
+                    .await?;
+                println!("Split tunnel mode: {mode}");
+                Ok(())
+            }
+            SplitTunnel::Dest(destination) => destination.handle().await,
+            SplitTunnel::Interface(interface) => interface.handle().await,
+            SplitTunnel::Port(port) => port.handle().await,
+            SplitTunnel::Lan(Lan::Get) => {
+                let settings = MullvadProxyClient::new().await?.get_settings().await?;
+                let policy = BooleanOption::with_labels(
+                    settings.split_tunnel.excluded_apps_allow_lan,
+                    "allow",
+                    "block",
+                );
+                println!("Local network access for excluded processes: {policy}");
+                Ok(())
+            }
+            SplitTunnel::Lan(Lan::Set { policy }) => {
+                MullvadProxyClient::new()
+                    .await?
+                    .set_excluded_apps_allow_lan(*policy)
+                    .await?;
+                println!("Changed local network access for excluded processes");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns the excluded processes, ordered by PID.
+async fn excluded_processes(
+    rpc: &mut MullvadProxyClient,
+) -> Result<BTreeMap<u32, ExcludedProcess>> {
+    Ok(rpc
+        .get_excluded_processes()
+        .await?
+        .into_iter()
+        .map(|process| (process.pid, process))
+        .collect())
+}
+
+fn print_process(prefix: &str, process: &ExcludedProcess) {
+    println!(
+        "{prefix}{:<8} {:<10} {}",
+        process.pid,
+        process.reason.to_string(),
+        process.image.display()
+    );
+}
+
+impl Destination {
+    async fn handle(self) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let mut destinations = rpc.get_settings().await?.split_tunnel.destinations;
+
+        match self {
+            Destination::List => {
+                if destinations.is_empty() {
+                    println!("No destinations are excluded from the tunnel");
+                } else {
+                    println!("Excluded destinations:");
+                    for destination in &destinations {
+                        println!("{destination}");
+                    }
+                }
+                return Ok(());
+            }
+            Destination::Add(args) => {
+                let destination = ExcludedDestination::from(args);
+                if destinations.contains(&destination) {
+                    println!("{destination} is already excluded from the tunnel");
+                    return Ok(());
+                }
+                destinations.push(destination);
+                rpc.set_split_tunnel_destinations(destinations).await?;
+                println!("Excluding {destination}");
+            }
+            Destination::Remove(args) => {
+                let destination = ExcludedDestination::from(args);
+                let count = destinations.len();
+                destinations.retain(|excluded| *excluded != destination);
+                if destinations.len() == count {
+                    println!("{destination} is not excluded from the tunnel");
+                    return Ok(());
+                }
+                rpc.set_split_tunnel_destinations(destinations).await?;
+                println!("Stopped excluding {destination}");
+            }
+            Destination::Clear => {
+                rpc.set_split_tunnel_destinations(vec![]).await?;
+                println!("Stopped excluding all destinations");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Interface {
+    async fn handle(self) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let mut interfaces = rpc.get_settings().await?.split_tunnel.interfaces;
+
+        match self {
+            Interface::List => {
+                if interfaces.is_empty() {
+                    println!("No interfaces are excluded from the tunnel");
+                } else {
+                    println!("Excluded interfaces:");
+                    for interface in &interfaces {
+                        println!("{interface}");
+                    }
+                }
+                return Ok(());
+            }
+            Interface::Add { name } => {
+                if interfaces.contains(&name) {
+                    println!("{name} is already excluded from the tunnel");
+                    return Ok(());
+                }
+                interfaces.push(name.clone());
+                rpc.set_split_tunnel_interfaces(interfaces).await?;
+                println!("Excluding traffic from {name}");
+                for sysctl in disabled_ip_forwarding().unwrap_or_default() {
+                    eprintln!(
+                        "Warning: IP forwarding is disabled ({sysctl} = 0), so some traffic from \
+                         {name} is not forwarded"
+                    );
+                }
+            }
+            Interface::Remove { name } => {
+                let count = interfaces.len();
+                interfaces.retain(|interface| *interface != name);
+                if interfaces.len() == count {
+                    println!("{name} is not excluded from the tunnel");
+                    return Ok(());
+                }
+                rpc.set_split_tunnel_interfaces(interfaces).await?;
+                println!("Stopped excluding traffic from {name}");
+            }
+            Interface::Clear => {
+                rpc.set_split_tunnel_interfaces(vec![]).await?;
+                println!("Stopped excluding traffic from all interfaces");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LocalPort {
+    async fn handle(self) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let mut ports = rpc.get_settings().await?.split_tunnel.local_ports;
+
+        match self {
+            LocalPort::List => {
+                if ports.is_empty() {
+                    println!("No local ports are excluded from the tunnel");
+                } else {
+                    println!("Excluded local ports:");
+                    for (protocol, port) in &ports {
+                        println!("{port}/{protocol}");
+                    }
+                }
+                return Ok(());
+            }
+            LocalPort::Add(LocalPortArgs { port, protocol }) => {
+                if ports.contains(&(protocol, port)) {
+                    println!("{port}/{protocol} is already excluded from the tunnel");
+                    return Ok(());
+                }
+                ports.push((protocol, port));
+                rpc.set_split_tunnel_local_ports(ports).await?;
+                println!("Excluding traffic of {port}/{protocol}");
+            }
+            LocalPort::Remove(LocalPortArgs { port, protocol }) => {
+                let count = ports.len();
+                ports.retain(|excluded| *excluded != (protocol, port));
+                if ports.len() == count {
+                    println!("{port}/{protocol} is not excluded from the tunnel");
+                    return Ok(());
+                }
+                rpc.set_split_tunnel_local_ports(ports).await?;
+                println!("Stopped excluding traffic of {port}/{protocol}");
+            }
+            LocalPort::Clear => {
+                rpc.set_split_tunnel_local_ports(vec![]).await?;
+                println!("Stopped excluding traffic of all local ports");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Owner {
+    async fn handle(self, kind: OwnerKind) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let settings = rpc.get_settings().await?;
+        let (mut users, mut groups) = (settings.split_tunnel.users, settings.split_tunnel.groups);
+        let ids = match kind {
+            OwnerKind::User => &mut users,
+            OwnerKind::Group => &mut groups,
+        };
+        let name = kind.name();
+
+        match self {
+            Owner::List => {
+                if ids.is_empty() {
+                    println!("No {name}s are excluded from the tunnel");
+                } else {
+                    println!("Excluded {name}s:");
+                    for id in ids.iter() {
+                        println!("{id}");
+                    }
+                }
+                return Ok(());
+            }
+            Owner::Add { id } => {
+                if ids.contains(&id) {
+                    println!("The {name} {id} is already excluded from the tunnel");
+                    return Ok(());
+                }
+                ids.push(id);
+                rpc.set_split_tunnel_owners(users, groups).await?;
+                println!("Excluding the processes of {name} {id}");
+            }
+            Owner::Remove { id } => {
+                let count = ids.len();
+                ids.retain(|excluded| *excluded != id);
+                if ids.len() == count {
+                    println!("The {name} {id} is not excluded from the tunnel");
+                    return Ok(());
+                }
+                rpc.set_split_tunnel_owners(users, groups).await?;
+                println!("Stopped excluding the processes of {name} {id}");
+            }
+            Owner::Clear => {
+                ids.clear();
+                rpc.set_split_tunnel_owners(users, groups).await?;
+                println!("Stopped excluding the processes of all {name}s");
+            }
         }
+        Ok(())
     }
 }
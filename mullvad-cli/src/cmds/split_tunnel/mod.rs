@@ -8,3 +8,17 @@ mod imp;
 
 #[cfg(any(target_os = "linux", windows))]
 pub use imp::*;
+
+/// Prints the problems with the split tunnel configuration, such as excluded apps that still use
+/// the DNS servers in the tunnel. This is run after the settings are changed.
+#[cfg(any(target_os = "linux", windows))]
+async fn print_config_warnings() -> anyhow::Result<()> {
+    use mullvad_management_interface::MullvadProxyClient;
+
+    let mut rpc = MullvadProxyClient::new().await?;
+    let settings = rpc.get_settings().await?;
+    for warning in rpc.validate_split_tunnel_config(&settings).await? {
+        eprintln!("Warning: {warning}");
+    }
+    Ok(())
+}
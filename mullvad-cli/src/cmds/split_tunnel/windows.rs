@@ -4,8 +4,10 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use clap::Subcommand;
+use clap::{Args, Subcommand};
 use mullvad_management_interface::MullvadProxyClient;
+use mullvad_types::settings::SplitApp;
+use talpid_types::split_tunnel::{AppExclusionState, ExcludedApp};
 
 use super::super::BooleanOption;
 
@@ -15,8 +17,8 @@ pub enum SplitTunnel {
     /// Display the split tunnel status and apps
     Get {
         /// List processes that are currently being excluded, as well as whether they are
-        /// excluded because of their executable paths or because they're subprocesses of
-        /// such processes
+        /// excluded because of their executable paths or publishers, or because they're
+        /// subprocesses of such processes
         #[arg(long)]
         list_processes: bool,
     },
@@ -31,13 +33,42 @@ pub enum SplitTunnel {
 
 #[derive(Subcommand, Debug)]
 pub enum App {
-    Add { path: PathBuf },
-    Remove { path: PathBuf },
+    /// List the excluded applications, and whether they are currently being excluded
+    List,
+    Add(AppTarget),
+    Remove(AppTarget),
     Clear,
 }
 
+/// An application given by its path, or all applications signed by a publisher
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+pub struct AppTarget {
+    /// Path to the executable
+    path: Option<PathBuf>,
+
+    /// Name of the publisher that signed the executables, such as "Mozilla Corporation".
+    /// Append "/<product name>" to only match a single product of the publisher
+    #[arg(long)]
+    publisher: Option<String>,
+}
+
 impl SplitTunnel {
     pub async fn handle(self) -> Result<()> {
+        let changes_settings = self.changes_settings();
+        self.run().await?;
+        if changes_settings {
+            super::print_config_warnings().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the command changes the settings, rather than only displaying them.
+    fn changes_settings(&self) -> bool {
+        !matches!(self, SplitTunnel::Get { .. } | SplitTunnel::App(App::List))
+    }
+
+    async fn run(self) -> Result<()> {
         match self {
             SplitTunnel::Get { list_processes } => {
                 let mut rpc = MullvadProxyClient::new().await?;
@@ -48,8 +79,8 @@ impl SplitTunnel {
                 println!("Split tunneling state: {enable_exclusions}");
 
                 println!("Excluded applications:");
-                for path in &settings.apps {
-                    println!("{}", path.display());
+                for app in &settings.apps {
+                    println!("{app}");
                 }
 
                 if list_processes {
@@ -57,12 +88,13 @@ impl SplitTunnel {
                     for process in &processes {
                         let subproc = if process.inherited { "subprocess" } else { "" };
                         println!(
-                            "{:<7}{subproc:<12}{}",
+                            "{:<7}{subproc:<12}{:<32}{}",
                             process.pid,
                             Path::new(&process.image)
                                 .file_name()
                                 .unwrap_or(OsStr::new("unknown"))
-                                .to_string_lossy()
+                                .to_string_lossy(),
+                            process.reason,
                         );
                     }
                 }
@@ -81,18 +113,64 @@ impl SplitTunnel {
 
     async fn app(subcmd: App) -> Result<()> {
         match subcmd {
-            App::Add { path } => {
+            App::List => {
+                let mut rpc = MullvadProxyClient::new().await?;
+                let settings = rpc.get_settings().await?.split_tunnel;
+                let statuses = rpc.get_split_tunnel_app_status().await?;
+
+                for app in &settings.apps {
+                    let app = match app {
+                        SplitApp::Path(path) => ExcludedApp::Path(path.clone()),
+                        SplitApp::Publisher(publisher) => ExcludedApp::Publisher(publisher.clone()),
+                    };
+                    let state = statuses
+                        .iter()
+                        .find(|status| status.app == app)
+                        .map(|status| status.state.to_string());
+                    let state = match state {
+                        _ if !settings.enable_exclusions => "split tunneling disabled".to_owned(),
+                        Some(state) => state,
+                        // The daemon has not applied the app yet
+                        None => AppExclusionState::Pending.to_string(),
+                    };
+                    println!("{app} ({state})");
+                }
+                Ok(())
+            }
+            App::Add(AppTarget {
+                publisher: Some(publisher),
+                ..
+            }) => {
+                MullvadProxyClient::new()
+                    .await?
+                    .add_split_tunnel_publisher(publisher)
+                    .await?;
+                println!("Added publisher to excluded apps list");
+                Ok(())
+            }
+            App::Add(AppTarget { path, .. }) => {
                 MullvadProxyClient::new()
                     .await?
-                    .add_split_tunnel_app(path)
+                    .add_split_tunnel_app(path.expect("path or publisher is required"))
                     .await?;
                 println!("Added path to excluded apps list");
                 Ok(())
             }
-            App::Remove { path } => {
+            App::Remove(AppTarget {
+                publisher: Some(publisher),
+                ..
+            }) => {
+                MullvadProxyClient::new()
+                    .await?
+                    .remove_split_tunnel_publisher(publisher)
+                    .await?;
+                println!("Stopped excluding apps of publisher from tunnel");
+                Ok(())
+            }
+            App::Remove(AppTarget { path, .. }) => {
                 MullvadProxyClient::new()
                     .await?
-                    .remove_split_tunnel_app(path)
+                    .remove_split_tunnel_app(path.expect("path or publisher is required"))
                     .await?;
                 println!("Stopped excluding app from tunnel");
                 Ok(())
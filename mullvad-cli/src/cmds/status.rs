@@ -1,20 +1,94 @@
 use anyhow::Result;
 use clap::{Args, Subcommand};
 use futures::StreamExt;
-use mullvad_management_interface::{client::DaemonEvent, MullvadProxyClient};
-use mullvad_types::{device::DeviceState, states::TunnelState};
+use mullvad_management_interface::{
+    client::{DaemonEvent, EventCategory, EventFilter},
+    MullvadProxyClient,
+};
+use mullvad_types::{
+    access_method::ApiConnectivity,
+    account::AccountExpiryWarning,
+    device::DeviceState,
+    features::{compute_feature_indicators, FeatureIndicator},
+    location::GeoIpLocation,
+    network_profiles::NetworkId,
+    routes::RoutesUpdate,
+    settings::Settings,
+    states::{IdleDisconnect, NetworkChange, TunnelState},
+};
+use serde::Serialize;
 
 use crate::format;
 
 #[derive(Subcommand, Debug, PartialEq)]
 pub enum Status {
     /// Listen for tunnel state changes
-    Listen,
+    Listen(ListenArgs),
+}
+
+/// Without any of the filter flags, all events are printed. With one or more of them, only events
+/// of the selected categories are.
+#[derive(Args, Debug, PartialEq)]
+pub struct ListenArgs {
+    /// Also print the routes that are added and removed when connecting, disconnecting, or
+    /// when the default route changes. Only supported on Linux.
+    #[arg(long)]
+    routes: bool,
+
+    /// Print tunnel state changes
+    #[arg(long)]
+    states: bool,
+
+    /// Print settings changes
+    #[arg(long)]
+    settings: bool,
+
+    /// Print device and account events
+    #[arg(long)]
+    devices: bool,
+
+    /// Print relay list updates
+    #[arg(long)]
+    relays: bool,
+
+    /// Print network events, such as network changes, routes, and API connectivity
+    #[arg(long)]
+    network: bool,
+
+    /// First print up to this many of the most recent events, oldest first. The daemon keeps
+    /// the last 50 events.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    replay: u32,
+}
+
+impl ListenArgs {
+    fn filter(&self) -> EventFilter {
+        let categories = [
+            (self.states, EventCategory::TunnelState),
+            (self.settings, EventCategory::Settings),
+            (self.devices, EventCategory::Device),
+            (self.relays, EventCategory::RelayList),
+            (self.network, EventCategory::Network),
+        ]
+        .into_iter()
+        .filter_map(|(selected, category)| selected.then_some(category))
+        .collect();
+        EventFilter {
+            categories,
+            replay: self.replay,
+        }
+    }
+
+    /// Returns whether the events include tunnel state changes.
+    fn wants_states(&self) -> bool {
+        let filter = self.filter();
+        filter.categories.is_empty() || filter.categories.contains(&EventCategory::TunnelState)
+    }
 }
 
 #[derive(Args, Debug)]
 pub struct StatusArgs {
-    /// Enable verbose output
+    /// Enable verbose output, including the features that are in effect
     #[arg(long, short = 'v')]
     verbose: bool,
 
@@ -27,29 +101,233 @@ pub struct StatusArgs {
     debug: bool,
 }
 
+/// What `mullvad status` shows. It is also what `--json` prints, so the field names must not
+/// change.
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    tunnel_state: TunnelState,
+    /// Only used to warn about connecting without being logged in.
+    #[serde(skip)]
+    device: DeviceState,
+    /// Only set with `--verbose`.
+    features: Option<Features>,
+    /// Only set with `--verbose`.
+    api_connectivity: Option<ApiConnectivity>,
+    /// Only set with `--location`, and only if the location is known.
+    location: Option<GeoIpLocation>,
+}
+
+/// The features that are in effect, and the network whose obfuscation profile is used, if any.
+#[derive(Debug, Serialize)]
+struct Features {
+    features: Vec<FeatureIndicator>,
+    obfuscation_profile: Option<NetworkId>,
+}
+
+/// Event that `mullvad status listen --json` prints, one per line. The field names must not
+/// change.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case", tag = "event", content = "details")]
+enum ListenEvent<'a> {
+    TunnelState(&'a TunnelState),
+    Features(&'a Features),
+    Location(&'a GeoIpLocation),
+    DnsTampered,
+    NetworkChanged(&'a NetworkChange),
+    ForeignTunnel { interface: Option<&'a str> },
+    RoutesUpdated(&'a RoutesUpdate),
+    ProblemReportSent { id: &'a str },
+    AccountExpiryWarning(&'a AccountExpiryWarning),
+    IdleDisconnect(&'a IdleDisconnect),
+    ApiConnectivity(&'a ApiConnectivity),
+}
+
+impl StatusReport {
+    async fn fetch(rpc: &mut MullvadProxyClient, args: &StatusArgs) -> Result<Self> {
+        let tunnel_state = rpc.get_tunnel_state().await?;
+        let device = rpc.get_device().await?;
+        let (features, api_connectivity) = if args.verbose {
+            let settings = rpc.get_settings().await?;
+            let features = Features::fetch(rpc, &settings, &tunnel_state).await?;
+            (Some(features), Some(rpc.get_api_connectivity().await?))
+        } else {
+            (None, None)
+        };
+        let location = if args.location {
+            fetch_location(rpc).await?
+        } else {
+            None
+        };
+        Ok(StatusReport {
+            tunnel_state,
+            device,
+            features,
+            api_connectivity,
+            location,
+        })
+    }
+
+    fn print(&self, args: &StatusArgs) {
+        print_account_loggedout(&self.tunnel_state, &self.device);
+        if args.debug {
+            println!("Tunnel state: {:#?}", self.tunnel_state);
+        } else {
+            format::print_state(&self.tunnel_state, args.verbose);
+            if let Some(features) = &self.features {
+                features.print();
+            }
+            if let Some(api_connectivity) = &self.api_connectivity {
+                format::print_api_connectivity(api_connectivity);
+            }
+        }
+        if args.location {
+            print_location(self.location.as_ref());
+        }
+    }
+}
+
+impl Features {
+    /// Returns the active features. While connected, the features of the tunnel in `state` are
+    /// included.
+    async fn fetch(
+        rpc: &mut MullvadProxyClient,
+        settings: &Settings,
+        state: &TunnelState,
+    ) -> Result<Self> {
+        let endpoint = match state {
+            TunnelState::Connected { endpoint, .. } => Some(endpoint),
+            _ => None,
+        };
+        let mut features = compute_feature_indicators(settings, endpoint);
+        let obfuscation_profile = rpc.get_active_obfuscation_profile().await?;
+        if obfuscation_profile.is_some() {
+            features.push(FeatureIndicator::NetworkObfuscationProfile);
+        }
+        Ok(Features {
+            features,
+            obfuscation_profile,
+        })
+    }
+
+    fn print(&self) {
+        format::print_feature_indicators(&self.features);
+        if let Some(network) = &self.obfuscation_profile {
+            println!("Obfuscation profile: {network}");
+        }
+    }
+}
+
 impl Status {
-    pub async fn listen(mut rpc: MullvadProxyClient, args: StatusArgs) -> Result<()> {
-        while let Some(event) = rpc.events_listen().await?.next().await {
+    /// Prints events as newline-delimited JSON. Unless events are replayed or tunnel states are
+    /// filtered out, the current tunnel state is printed first.
+    async fn listen_json(
+        mut rpc: MullvadProxyClient,
+        args: StatusArgs,
+        listen_args: ListenArgs,
+    ) -> Result<()> {
+        let routes = listen_args.routes;
+        let mut tunnel_state = rpc.get_tunnel_state().await?;
+        if listen_args.wants_states() && listen_args.replay == 0 {
+            format::print_json_line(&ListenEvent::TunnelState(&tunnel_state))?;
+        }
+        let mut events = rpc.events_listen_filtered(listen_args.filter()).await?;
+        while let Some(event) = events.next().await {
+            match event? {
+                DaemonEvent::TunnelState(new_state) => {
+                    format::print_json_line(&ListenEvent::TunnelState(&new_state))?;
+                    if args.location
+                        && matches!(
+                            new_state,
+                            TunnelState::Connected { .. } | TunnelState::Disconnected
+                        )
+                    {
+                        if let Some(location) = fetch_location(&mut rpc).await? {
+                            format::print_json_line(&ListenEvent::Location(&location))?;
+                        }
+                    }
+                    if args.verbose && new_state.is_connected() {
+                        let settings = rpc.get_settings().await?;
+                        let features = Features::fetch(&mut rpc, &settings, &new_state).await?;
+                        format::print_json_line(&ListenEvent::Features(&features))?;
+                    }
+                    tunnel_state = new_state;
+                }
+                DaemonEvent::Settings(settings) if args.verbose => {
+                    let features = Features::fetch(&mut rpc, &settings, &tunnel_state).await?;
+                    format::print_json_line(&ListenEvent::Features(&features))?;
+                }
+                DaemonEvent::DnsTampered => format::print_json_line(&ListenEvent::DnsTampered)?,
+                DaemonEvent::NetworkChanged(change) => {
+                    format::print_json_line(&ListenEvent::NetworkChanged(&change))?
+                }
+                DaemonEvent::ForeignTunnel(interface) => {
+                    format::print_json_line(&ListenEvent::ForeignTunnel {
+                        interface: interface.as_deref(),
+                    })?
+                }
+                DaemonEvent::RoutesUpdated(update) if routes => {
+                    format::print_json_line(&ListenEvent::RoutesUpdated(&update))?
+                }
+                DaemonEvent::ProblemReportSent(id) => {
+                    format::print_json_line(&ListenEvent::ProblemReportSent { id: &id })?
+                }
+                DaemonEvent::AccountExpiryWarning(warning) => {
+                    format::print_json_line(&ListenEvent::AccountExpiryWarning(&warning))?
+                }
+                DaemonEvent::IdleDisconnect(event) => {
+                    format::print_json_line(&ListenEvent::IdleDisconnect(&event))?
+                }
+                DaemonEvent::ApiConnectivity(status) => {
+                    format::print_json_line(&ListenEvent::ApiConnectivity(&status))?
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn listen(
+        mut rpc: MullvadProxyClient,
+        args: StatusArgs,
+        listen_args: ListenArgs,
+    ) -> Result<()> {
+        let routes = listen_args.routes;
+        let mut tunnel_state = rpc.get_tunnel_state().await?;
+        let mut events = rpc.events_listen_filtered(listen_args.filter()).await?;
+        while let Some(event) = events.next().await {
             match event? {
                 DaemonEvent::TunnelState(new_state) => {
                     if args.debug {
                         println!("New tunnel state: {new_state:#?}");
                     } else {
                         format::print_state(&new_state, args.verbose);
+                        format::print_connection_attempt(&new_state);
                     }
 
                     match new_state {
                         TunnelState::Connected { .. } | TunnelState::Disconnected => {
                             if args.location {
-                                print_location(&mut rpc).await?;
+                                print_location(fetch_location(&mut rpc).await?.as_ref());
                             }
                         }
                         _ => {}
                     }
+                    // The features in use are only known once connected
+                    if !args.debug && args.verbose && new_state.is_connected() {
+                        let settings = rpc.get_settings().await?;
+                        Features::fetch(&mut rpc, &settings, &new_state)
+                            .await?
+                            .print();
+                    }
+                    tunnel_state = new_state;
                 }
                 DaemonEvent::Settings(settings) => {
                     if args.debug {
                         println!("New settings: {settings:#?}");
+                    } else if args.verbose {
+                        Features::fetch(&mut rpc, &settings, &tunnel_state)
+                            .await?
+                            .print();
                     }
                 }
                 DaemonEvent::RelayList(relay_list) => {
@@ -72,45 +350,117 @@ impl Status {
                         println!("Remove device event: {device:#?}");
                     }
                 }
+                DaemonEvent::DnsTampered => {
+                    println!("DNS settings were changed by another program and have been restored");
+                }
+                DaemonEvent::NetworkChanged(change) => {
+                    println!("Network changed: {change}");
+                }
+                DaemonEvent::ForeignTunnel(Some(interface)) => {
+                    println!(
+                        "Warning: The default route uses {interface}, which belongs to another VPN"
+                    );
+                }
+                DaemonEvent::ForeignTunnel(None) => {
+                    println!("The default route no longer uses the interface of another VPN");
+                }
+                DaemonEvent::RoutesUpdated(update) => {
+                    if routes {
+                        print_routes_update(&update);
+                    }
+                }
+                DaemonEvent::SplitTunnelAppStatus(statuses) => {
+                    println!("Excluded applications:");
+                    for status in &statuses {
+                        println!("    {}: {}", status.app, status.state);
+                    }
+                }
+                DaemonEvent::ExcludedProcesses(processes) => {
+                    if args.verbose {
+                        println!("Number of excluded processes: {}", processes.len());
+                    }
+                }
+                DaemonEvent::ProblemReportSent(id) => {
+                    println!("Queued problem report {id} was sent");
+                }
+                DaemonEvent::AccountExpiryWarning(warning) => {
+                    println!(
+                        "Warning: The account runs out of time at {}",
+                        warning.expiry.with_timezone(&chrono::Local)
+                    );
+                }
+                DaemonEvent::IdleDisconnect(event) => {
+                    println!(
+                        "Disconnected after being idle for {} minutes",
+                        event.idle_for.as_secs() / 60
+                    );
+                    if event.locked_down {
+                        println!("Traffic is blocked by lockdown mode until you connect again");
+                    }
+                }
+                DaemonEvent::ApiConnectivity(status) => {
+                    if args.verbose || args.debug {
+                        format::print_api_connectivity(&status);
+                    }
+                }
             }
         }
         Ok(())
     }
 }
 
-pub async fn handle(cmd: Option<Status>, args: StatusArgs) -> Result<()> {
+pub async fn handle(cmd: Option<Status>, args: StatusArgs, json: bool) -> Result<()> {
     let mut rpc = MullvadProxyClient::new().await?;
-    let state = rpc.get_tunnel_state().await?;
-    let device = rpc.get_device().await?;
-
-    print_account_loggedout(&state, &device);
-
-    if args.debug {
-        println!("Tunnel state: {state:#?}");
-    } else {
-        format::print_state(&state, args.verbose);
+    match cmd {
+        Some(Status::Listen(listen_args)) if json => {
+            Status::listen_json(rpc, args, listen_args).await
+        }
+        cmd => {
+            let report = StatusReport::fetch(&mut rpc, &args).await?;
+            if json {
+                return format::print_json(&report);
+            }
+            match cmd {
+                Some(Status::Listen(listen_args)) => {
+                    // Like with `--json`, the current state is only printed when it is followed
+                    // by the tunnel state changes
+                    if listen_args.wants_states() && listen_args.replay == 0 {
+                        report.print(&args);
+                    }
+                    Status::listen(rpc, args, listen_args).await
+                }
+                None => {
+                    report.print(&args);
+                    Ok(())
+                }
+            }
+        }
     }
+}
 
-    if args.location {
-        print_location(&mut rpc).await?;
+fn print_routes_update(update: &RoutesUpdate) {
+    println!("Routes updated:");
+    for route in &update.removed {
+        println!("    - {route}");
+    }
+    for route in &update.added {
+        println!("    + {route}");
     }
+}
 
-    if cmd == Some(Status::Listen) {
-        Status::listen(rpc, args).await?;
+/// Returns the current location, or `None` if it is unknown.
+async fn fetch_location(rpc: &mut MullvadProxyClient) -> Result<Option<GeoIpLocation>> {
+    match rpc.get_current_location().await {
+        Ok(location) => Ok(Some(location)),
+        Err(mullvad_management_interface::Error::NoLocationData) => Ok(None),
+        Err(error) => Err(error.into()),
     }
-    Ok(())
 }
 
-async fn print_location(rpc: &mut MullvadProxyClient) -> Result<()> {
-    let location = match rpc.get_current_location().await {
-        Ok(location) => location,
-        Err(error) => match &error {
-            mullvad_management_interface::Error::NoLocationData => {
-                println!("Location data unavailable");
-                return Ok(());
-            }
-            _ => return Err(error.into()),
-        },
+fn print_location(location: Option<&GeoIpLocation>) {
+    let Some(location) = location else {
+        println!("Location data unavailable");
+        return;
     };
     if let Some(ipv4) = location.ipv4 {
         println!("IPv4: {ipv4}");
@@ -123,7 +473,6 @@ async fn print_location(rpc: &mut MullvadProxyClient) -> Result<()> {
         "Position: {:.5}°N, {:.5}°W",
         location.latitude, location.longitude
     );
-    Ok(())
 }
 
 fn print_account_loggedout(state: &TunnelState, device: &DeviceState) {
@@ -140,3 +489,100 @@ fn print_account_loggedout(state: &TunnelState, device: &DeviceState) {
         TunnelState::Disconnected | TunnelState::Disconnecting(_) => (),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+    use serde_json::json;
+
+    #[test]
+    fn test_status_json() {
+        let report = StatusReport {
+            tunnel_state: TunnelState::Disconnected,
+            device: DeviceState::LoggedOut,
+            features: Some(Features {
+                features: vec![FeatureIndicator::NetworkObfuscationProfile],
+                obfuscation_profile: Some(NetworkId::Ssid("Office".to_owned())),
+            }),
+            api_connectivity: Some(ApiConnectivity::Reachable),
+            location: None,
+        };
+        assert_eq!(
+            serde_json::to_value(report).unwrap(),
+            json!({
+                "tunnel_state": { "state": "disconnected" },
+                "features": {
+                    "features": ["network_obfuscation_profile"],
+                    "obfuscation_profile": { "ssid": "Office" },
+                },
+                "api_connectivity": { "status": "reachable" },
+                "location": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_listen_filter() {
+        #[derive(Debug, Parser)]
+        struct TestCli {
+            #[command(subcommand)]
+            cmd: Status,
+        }
+
+        let parse = |args: &[&str]| {
+            let cli = TestCli::try_parse_from(["status", "listen"].iter().chain(args)).unwrap();
+            let Status::Listen(listen_args) = cli.cmd;
+            (listen_args.filter(), listen_args.wants_states())
+        };
+
+        let (filter, wants_states) = parse(&[]);
+        assert!(filter.categories.is_empty());
+        assert_eq!(filter.replay, 0);
+        assert!(wants_states);
+
+        let (filter, wants_states) = parse(&["--routes"]);
+        assert!(filter.categories.is_empty());
+        assert!(wants_states);
+
+        let (filter, wants_states) = parse(&["--settings", "--replay", "5"]);
+        assert_eq!(filter.categories, vec![EventCategory::Settings]);
+        assert_eq!(filter.replay, 5);
+        assert!(!wants_states);
+
+        let (filter, wants_states) = parse(&["--network", "--states", "--devices", "--relays"]);
+        assert_eq!(
+            filter.categories,
+            vec![
+                EventCategory::TunnelState,
+                EventCategory::Device,
+                EventCategory::RelayList,
+                EventCategory::Network,
+            ]
+        );
+        assert!(wants_states);
+    }
+
+    #[test]
+    fn test_listen_event_json() {
+        assert_eq!(
+            serde_json::to_value(ListenEvent::TunnelState(&TunnelState::Disconnected)).unwrap(),
+            json!({ "event": "tunnel_state", "details": { "state": "disconnected" } })
+        );
+        assert_eq!(
+            serde_json::to_value(ListenEvent::DnsTampered).unwrap(),
+            json!({ "event": "dns_tampered" })
+        );
+        assert_eq!(
+            serde_json::to_value(ListenEvent::ForeignTunnel {
+                interface: Some("wg0")
+            })
+            .unwrap(),
+            json!({ "event": "foreign_tunnel", "details": { "interface": "wg0" } })
+        );
+        assert_eq!(
+            serde_json::to_value(ListenEvent::ProblemReportSent { id: "abc" }).unwrap(),
+            json!({ "event": "problem_report_sent", "details": { "id": "abc" } })
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use clap::{Subcommand, ValueEnum};
+use mullvad_management_interface::MullvadProxyClient;
+use mullvad_types::{
+    network_profiles::NetworkId,
+    network_trust::{NetworkTrustSettings, TrustPolicy},
+};
+
+use super::{obfuscation::NetworkTarget, BooleanOption};
+
+#[derive(Subcommand, Debug)]
+pub enum Trust {
+    /// Display the network trust rules and settings
+    Get,
+
+    /// Add a rule for a Wi-Fi network, or replace the existing one. The tunnel is connected
+    /// automatically on untrusted networks. SSIDs are only detected on Linux
+    AddSsid {
+        /// SSID of the Wi-Fi network
+        ssid: String,
+
+        /// Mark the network as untrusted instead of trusted
+        #[arg(long)]
+        untrusted: bool,
+    },
+
+    /// Add a rule for the network of an interface, or replace the existing one. Rules for a
+    /// Wi-Fi network take precedence over those for its interface
+    AddInterface {
+        /// Name of the interface of the default route
+        interface: String,
+
+        /// Mark the network as untrusted instead of trusted
+        #[arg(long)]
+        untrusted: bool,
+    },
+
+    /// Remove the rule of a network
+    Remove(NetworkTarget),
+
+    /// Remove all network trust rules
+    Clear,
+
+    /// Set the policy of networks that no rule matches
+    SetDefault { policy: DefaultPolicy },
+
+    /// Control whether to disconnect when joining a trusted network. When it is off, trusted
+    /// networks only prevent the tunnel from being connected automatically
+    DisconnectOnTrusted { policy: BooleanOption },
+
+    /// Control whether lockdown mode keeps blocking traffic while disconnected on a trusted
+    /// network
+    LockdownOnTrusted { policy: BooleanOption },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum DefaultPolicy {
+    Trusted,
+    Untrusted,
+    /// Neither connect nor disconnect automatically
+    None,
+}
+
+impl From<DefaultPolicy> for Option<TrustPolicy> {
+    fn from(policy: DefaultPolicy) -> Self {
+        match policy {
+            DefaultPolicy::Trusted => Some(TrustPolicy::Trusted),
+            DefaultPolicy::Untrusted => Some(TrustPolicy::Untrusted),
+            DefaultPolicy::None => None,
+        }
+    }
+}
+
+impl Trust {
+    pub async fn handle(self) -> Result<()> {
+        let mut rpc = MullvadProxyClient::new().await?;
+        let mut settings = rpc.get_settings().await?.network_trust;
+
+        match self {
+            Trust::Get => {
+                print_settings(&settings);
+                return Ok(());
+            }
+            Trust::AddSsid { ssid, untrusted } => {
+                add_rule(&mut settings, NetworkId::Ssid(ssid), untrusted)
+            }
+            Trust::AddInterface {
+                interface,
+                untrusted,
+            } => add_rule(&mut settings, NetworkId::Interface(interface), untrusted),
+            Trust::Remove(network) => {
+                let network = NetworkId::from(network);
+                let len = settings.rules.len();
+                settings.rules.retain(|(id, _)| id != &network);
+                if settings.rules.len() == len {
+                    return Err(anyhow!("There is no trust rule for {network}"));
+                }
+            }
+            Trust::Clear => settings.rules.clear(),
+            Trust::SetDefault { policy } => settings.default_policy = policy.into(),
+            Trust::DisconnectOnTrusted { policy } => settings.disconnect_on_trusted = *policy,
+            Trust::LockdownOnTrusted { policy } => settings.lockdown_on_trusted = *policy,
+        }
+
+        rpc.set_network_trust_settings(&settings).await?;
+        println!("Updated network trust settings");
+        Ok(())
+    }
+}
+
+fn add_rule(settings: &mut NetworkTrustSettings, network: NetworkId, untrusted: bool) {
+    let policy = if untrusted {
+        TrustPolicy::Untrusted
+    } else {
+        TrustPolicy::Trusted
+    };
+    match settings.rules.iter_mut().find(|(id, _)| id == &network) {
+        Some((_, existing)) => *existing = policy,
+        None => settings.rules.push((network, policy)),
+    }
+}
+
+fn print_settings(settings: &NetworkTrustSettings) {
+    if settings.rules.is_empty() {
+        println!("No network trust rules");
+    }
+    for (network, policy) in &settings.rules {
+        println!("{network}: {policy}");
+    }
+    match settings.default_policy {
+        Some(policy) => println!("Other networks: {policy}"),
+        None => println!("Other networks: no policy"),
+    }
+    println!(
+        "Disconnect on trusted networks: {}",
+        BooleanOption::from(settings.disconnect_on_trusted)
+    );
+    println!(
+        "Lockdown mode on trusted networks: {}",
+        BooleanOption::from(settings.lockdown_on_trusted)
+    );
+}
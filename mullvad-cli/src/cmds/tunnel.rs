@@ -5,6 +5,8 @@ use mullvad_types::{
     relay_constraints::Constraint,
     wireguard::{QuantumResistantState, RotationInterval, DEFAULT_ROTATION_INTERVAL},
 };
+use std::time::Duration;
+use talpid_types::tunnel::RetryBackoff;
 
 use super::BooleanOption;
 use crate::print_option;
@@ -17,6 +19,19 @@ pub enum Tunnel {
     /// Set tunnel options
     #[clap(subcommand)]
     Set(TunnelOptions),
+
+    /// Show the state, relay and throughput of the tunnel, updated continuously until Ctrl-C is
+    /// pressed. One line is printed per update if the output is not a terminal
+    Monitor {
+        /// Seconds between updates
+        #[arg(
+            long,
+            short = 'i',
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        interval: u64,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -49,6 +64,30 @@ pub enum TunnelOptions {
     /// Enable or disable IPv6 in the tunnel
     #[clap(arg_required_else_help = true)]
     Ipv6 { state: BooleanOption },
+
+    /// Configure how long consecutive connection attempts are spaced apart. The delay grows by
+    /// the multiplier after every failed attempt, up to the maximum delay
+    #[clap(arg_required_else_help = true)]
+    RetryBackoff {
+        /// Delay before the first retry, in milliseconds
+        #[arg(long)]
+        initial_ms: Option<u64>,
+        /// Factor that the delay grows by after every failed attempt
+        #[arg(long)]
+        multiplier: Option<f64>,
+        /// Upper bound of the delay, in milliseconds
+        #[arg(long)]
+        max_ms: Option<u64>,
+        /// Seconds that the tunnel must stay connected for the delay to start over
+        #[arg(long)]
+        reset_after_secs: Option<u64>,
+        /// Restore the default backoff
+        #[arg(
+            long,
+            conflicts_with_all = ["initial_ms", "multiplier", "max_ms", "reset_after_secs"]
+        )]
+        reset: bool,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -62,12 +101,16 @@ impl Tunnel {
         match self {
             Tunnel::Get => Self::get().await,
             Tunnel::Set(options) => Self::set(options).await,
+            Tunnel::Monitor { interval } => {
+                super::tunnel_monitor::handle(Duration::from_secs(interval)).await
+            }
         }
     }
 
     async fn get() -> Result<()> {
         let mut rpc = MullvadProxyClient::new().await?;
-        let tunnel_options = rpc.get_settings().await?.tunnel_options;
+        let settings = rpc.get_settings().await?;
+        let tunnel_options = settings.tunnel_options;
 
         println!("OpenVPN options");
 
@@ -120,6 +163,11 @@ impl Tunnel {
             }
         );
 
+        print_option!(
+            "Retry backoff",
+            format_retry_backoff(&settings.retry_backoff),
+        );
+
         Ok(())
     }
 
@@ -135,6 +183,33 @@ impl Tunnel {
                 Self::handle_wireguard(mtu, quantum_resistant, rotation_interval, rotate_key).await
             }
             TunnelOptions::Ipv6 { state } => Self::handle_ipv6(state).await,
+            TunnelOptions::RetryBackoff {
+                initial_ms,
+                multiplier,
+                max_ms,
+                reset_after_secs,
+                reset,
+            } => {
+                let mut rpc = MullvadProxyClient::new().await?;
+                let backoff = if reset {
+                    RetryBackoff::default()
+                } else {
+                    let current = rpc.get_settings().await?.retry_backoff;
+                    RetryBackoff {
+                        initial: initial_ms
+                            .map(Duration::from_millis)
+                            .unwrap_or(current.initial),
+                        multiplier: multiplier.unwrap_or(current.multiplier),
+                        max: max_ms.map(Duration::from_millis).unwrap_or(current.max),
+                        reset_after_connected_for: reset_after_secs
+                            .map(Duration::from_secs)
+                            .unwrap_or(current.reset_after_connected_for),
+                    }
+                };
+                rpc.set_retry_backoff(backoff).await?;
+                println!("Retry backoff: {}", format_retry_backoff(&backoff));
+                Ok(())
+            }
         }
     }
 
@@ -198,3 +273,13 @@ impl Tunnel {
         Ok(())
     }
 }
+
+fn format_retry_backoff(backoff: &RetryBackoff) -> String {
+    format!(
+        "{} ms, multiplied by {} up to {} ms, reset after {} s connected",
+        backoff.initial.as_millis(),
+        backoff.multiplier,
+        backoff.max.as_millis(),
+        backoff.reset_after_connected_for.as_secs()
+    )
+}
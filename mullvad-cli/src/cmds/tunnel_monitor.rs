@@ -0,0 +1,442 @@
+//! `mullvad tunnel monitor`, a view of the tunnel that is redrawn every time the daemon sends new
+//! traffic counters.
+
+use anyhow::Result;
+use futures::StreamExt;
+use mullvad_management_interface::{
+    client::{DaemonEvent, EventCategory, EventFilter},
+    MullvadProxyClient,
+};
+use mullvad_types::states::TunnelState;
+use std::{
+    io::{IsTerminal, Write},
+    time::{Duration, Instant},
+};
+use talpid_types::tunnel::TrafficStats;
+
+/// Moves the cursor to the top left corner and clears the screen.
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+/// Moves the cursor to the top left corner without clearing the screen, to avoid flickering.
+const CURSOR_HOME: &str = "\x1b[H";
+/// Clears the rest of the line.
+const CLEAR_LINE: &str = "\x1b[K";
+/// Clears everything below the cursor.
+const CLEAR_BELOW: &str = "\x1b[J";
+
+/// What the monitor shows, updated from tunnel state events and traffic counters.
+#[derive(Debug)]
+struct Monitor {
+    state: TunnelState,
+    /// Number of times that the tunnel has been reconnected since the monitor started.
+    reconnects: u32,
+    /// The most recent traffic counters, and when they were received.
+    sample: Option<(Instant, TrafficStats)>,
+    /// Bytes per second since the previous sample.
+    throughput: Option<TrafficStats>,
+}
+
+impl Monitor {
+    fn new(state: TunnelState) -> Self {
+        Monitor {
+            state,
+            reconnects: 0,
+            sample: None,
+            throughput: None,
+        }
+    }
+
+    fn update_state(&mut self, state: TunnelState) {
+        if self.state.is_connected() && matches!(state, TunnelState::Connecting { .. }) {
+            self.reconnects += 1;
+        }
+        if !state.is_connected() {
+            // The counters of the next tunnel start over
+            self.sample = None;
+            self.throughput = None;
+        }
+        self.state = state;
+    }
+
+    fn update_stats(&mut self, now: Instant, stats: Option<TrafficStats>) {
+        let Some(stats) = stats else {
+            self.sample = None;
+            self.throughput = None;
+            return;
+        };
+        self.throughput = self
+            .sample
+            .and_then(|(then, previous)| throughput(previous, stats, now.duration_since(then)));
+        self.sample = Some((now, stats));
+    }
+
+    /// Returns the lines of the full screen view.
+    fn lines(&self) -> Vec<String> {
+        let (rx_rate, tx_rate) = match self.throughput {
+            Some(rate) => (format_rate(rate.rx_bytes), format_rate(rate.tx_bytes)),
+            None => ("-".to_owned(), "-".to_owned()),
+        };
+        let (rx_total, tx_total) = match self.sample {
+            Some((_, stats)) => (format_bytes(stats.rx_bytes), format_bytes(stats.tx_bytes)),
+            None => ("-".to_owned(), "-".to_owned()),
+        };
+        vec![
+            "Mullvad tunnel monitor (press Ctrl-C to exit)".to_owned(),
+            String::new(),
+            format!("{:<14}{}", "State:", format_state(&self.state)),
+            format!(
+                "{:<14}{}",
+                "Relay:",
+                format_relay(&self.state).unwrap_or_else(|| "-".to_owned())
+            ),
+            format!(
+                "{:<14}{}",
+                "Obfuscation:",
+                format_obfuscation(&self.state).unwrap_or_else(|| "-".to_owned())
+            ),
+            format!("{:<14}down {rx_rate}, up {tx_rate}", "Throughput:"),
+            format!("{:<14}down {rx_total}, up {tx_total}", "Transferred:"),
+            format!("{:<14}{}", "Reconnects:", self.reconnects),
+        ]
+    }
+
+    /// Returns a single line summary, for when the output is not a terminal.
+    fn line(&self) -> String {
+        let mut parts = vec![format_state(&self.state).to_owned()];
+        parts.extend(format_relay(&self.state));
+        if let Some(rate) = self.throughput {
+            parts.push(format!(
+                "down {}, up {}",
+                format_rate(rate.rx_bytes),
+                format_rate(rate.tx_bytes)
+            ));
+        }
+        if let Some(obfuscation) = format_obfuscation(&self.state) {
+            parts.push(format!("obfuscation: {obfuscation}"));
+        }
+        parts.push(format!("reconnects: {}", self.reconnects));
+        parts.join(" | ")
+    }
+}
+
+/// Returns the bytes per second that were received and sent between two samples that were
+/// `elapsed` apart, or `None` if the counters belong to different tunnels.
+fn throughput(
+    previous: TrafficStats,
+    current: TrafficStats,
+    elapsed: Duration,
+) -> Option<TrafficStats> {
+    if current.rx_bytes < previous.rx_bytes || current.tx_bytes < previous.tx_bytes {
+        return None;
+    }
+    let elapsed_ms = elapsed.as_millis();
+    if elapsed_ms == 0 {
+        return None;
+    }
+    let per_second =
+        |bytes: u64| u64::try_from(u128::from(bytes) * 1000 / elapsed_ms).unwrap_or(u64::MAX);
+    Some(TrafficStats {
+        rx_bytes: per_second(current.rx_bytes - previous.rx_bytes),
+        tx_bytes: per_second(current.tx_bytes - previous.tx_bytes),
+    })
+}
+
+/// Formats a number of bytes with decimal units, e.g. "1.5 MB".
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["kB", "MB", "GB", "TB", "PB"];
+
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 999.95 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn format_rate(bytes_per_second: u64) -> String {
+    format!("{}/s", format_bytes(bytes_per_second))
+}
+
+fn format_state(state: &TunnelState) -> &'static str {
+    match state {
+        TunnelState::Connected { .. } => "Connected",
+        TunnelState::Connecting { .. } => "Connecting",
+        TunnelState::Disconnected => "Disconnected",
+        TunnelState::Disconnecting(_) => "Disconnecting",
+        TunnelState::Error(error) if error.is_blocking() => "Blocked",
+        TunnelState::Error(_) => "Error",
+    }
+}
+
+/// Returns the exit relay, and the entry relay if multihop is used.
+fn format_relay(state: &TunnelState) -> Option<String> {
+    let (TunnelState::Connected {
+        endpoint, location, ..
+    }
+    | TunnelState::Connecting {
+        endpoint, location, ..
+    }) = state
+    else {
+        return None;
+    };
+    let exit = location
+        .as_ref()
+        .and_then(|location| location.hostname.clone())
+        .unwrap_or_else(|| endpoint.endpoint.address.ip().to_string());
+    let entry = location
+        .as_ref()
+        .and_then(|location| location.entry_hostname.clone())
+        .or_else(|| {
+            endpoint
+                .entry_endpoint
+                .as_ref()
+                .map(|entry| entry.address.ip().to_string())
+        });
+    Some(match entry {
+        Some(entry) => format!("{exit} via {entry}"),
+        None => exit,
+    })
+}
+
+fn format_obfuscation(state: &TunnelState) -> Option<String> {
+    let (TunnelState::Connected { endpoint, .. } | TunnelState::Connecting { endpoint, .. }) =
+        state
+    else {
+        return None;
+    };
+    Some(match &endpoint.obfuscation {
+        Some(obfuscation) => format!(
+            "{} ({})",
+            obfuscation.obfuscation_type, obfuscation.endpoint.address
+        ),
+        None => "none".to_owned(),
+    })
+}
+
+/// Returns what to print to redraw the screen with `lines`.
+fn redraw(lines: &[String], first: bool) -> String {
+    let mut screen = String::from(if first { CLEAR_SCREEN } else { CURSOR_HOME });
+    for line in lines {
+        screen.push_str(line);
+        screen.push_str(CLEAR_LINE);
+        screen.push('\n');
+    }
+    screen.push_str(CLEAR_BELOW);
+    screen
+}
+
+/// Shows the tunnel until Ctrl-C is pressed or the daemon shuts down. The view is redrawn every
+/// `interval`, and whenever the tunnel state changes.
+pub async fn handle(interval: Duration) -> Result<()> {
+    let mut rpc = MullvadProxyClient::new().await?;
+    let mut events = rpc
+        .events_listen_filtered(EventFilter {
+            categories: vec![EventCategory::TunnelState],
+            replay: 0,
+        })
+        .await?;
+    let mut stats = rpc.tunnel_stats_listen(interval).await?;
+    let mut monitor = Monitor::new(rpc.get_tunnel_state().await?);
+
+    let terminal = std::io::stdout().is_terminal();
+    let mut first = true;
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(event) => match event? {
+                    DaemonEvent::TunnelState(state) => monitor.update_state(state),
+                    _ => continue,
+                },
+                None => break,
+            },
+            update = stats.next() => match update {
+                Some(update) => monitor.update_stats(Instant::now(), update?),
+                None => break,
+            },
+            _ = &mut ctrl_c => break,
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        if terminal {
+            write!(stdout, "{}", redraw(&monitor.lines(), first))?;
+        } else {
+            writeln!(stdout, "{}", monitor.line())?;
+        }
+        stdout.flush()?;
+        first = false;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cmds::tunnel_state::test::{connected, connecting, endpoint};
+    use mullvad_types::location::GeoIpLocation;
+    use talpid_types::net::{Endpoint, ObfuscationEndpoint, ObfuscationType, TransportProtocol};
+
+    fn stats(rx_bytes: u64, tx_bytes: u64) -> TrafficStats {
+        TrafficStats { rx_bytes, tx_bytes }
+    }
+
+    fn location() -> GeoIpLocation {
+        GeoIpLocation {
+            ipv4: None,
+            ipv6: None,
+            country: "Sweden".to_owned(),
+            city: Some("Gothenburg".to_owned()),
+            latitude: 0.0,
+            longitude: 0.0,
+            mullvad_exit_ip: true,
+            hostname: Some("se-got-wg-001".to_owned()),
+            bridge_hostname: None,
+            entry_hostname: None,
+            obfuscator_hostname: None,
+        }
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(999), "999 B");
+        assert_eq!(format_bytes(1000), "1.0 kB");
+        assert_eq!(format_bytes(1_560), "1.6 kB");
+        assert_eq!(format_bytes(999_999), "1.0 MB");
+        assert_eq!(format_bytes(12_345_678), "12.3 MB");
+        assert_eq!(format_bytes(4_000_000_000_000), "4.0 TB");
+        assert_eq!(format_bytes(u64::MAX), "18446.7 PB");
+        assert_eq!(format_rate(2_500), "2.5 kB/s");
+    }
+
+    #[test]
+    fn test_throughput() {
+        assert_eq!(
+            throughput(stats(0, 0), stats(4000, 500), Duration::from_secs(2)),
+            Some(stats(2000, 250))
+        );
+        assert_eq!(
+            throughput(stats(100, 100), stats(100, 350), Duration::from_millis(500)),
+            Some(stats(0, 500))
+        );
+        // The counters start over when a new tunnel is created
+        assert_eq!(
+            throughput(stats(5000, 5000), stats(10, 5000), Duration::from_secs(1)),
+            None
+        );
+        assert_eq!(throughput(stats(0, 0), stats(1, 1), Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_format_relay() {
+        assert_eq!(format_relay(&TunnelState::Disconnected), None);
+        assert_eq!(
+            format_relay(&connected(endpoint(), None)).as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(
+            format_relay(&connected(endpoint(), Some(location()))).as_deref(),
+            Some("se-got-wg-001")
+        );
+
+        let mut multihop = endpoint();
+        multihop.entry_endpoint = Some(Endpoint::new([10, 0, 0, 2], 51820, TransportProtocol::Udp));
+        assert_eq!(
+            format_relay(&connected(multihop.clone(), None)).as_deref(),
+            Some("10.0.0.1 via 10.0.0.2")
+        );
+        let location = GeoIpLocation {
+            entry_hostname: Some("se-sto-wg-002".to_owned()),
+            ..location()
+        };
+        assert_eq!(
+            format_relay(&connected(multihop, Some(location))).as_deref(),
+            Some("se-got-wg-001 via se-sto-wg-002")
+        );
+    }
+
+    #[test]
+    fn test_format_obfuscation() {
+        assert_eq!(format_obfuscation(&TunnelState::Disconnected), None);
+        assert_eq!(
+            format_obfuscation(&connected(endpoint(), None)).as_deref(),
+            Some("none")
+        );
+        let mut obfuscated = endpoint();
+        obfuscated.obfuscation = Some(ObfuscationEndpoint {
+            endpoint: Endpoint::new([10, 0, 0, 1], 443, TransportProtocol::Tcp),
+            obfuscation_type: ObfuscationType::Udp2Tcp,
+            upstream_proxy: None,
+        });
+        assert_eq!(
+            format_obfuscation(&connected(obfuscated, None)).as_deref(),
+            Some("Udp2Tcp (10.0.0.1:443)")
+        );
+    }
+
+    #[test]
+    fn test_reconnects() {
+        let mut monitor = Monitor::new(connecting());
+        monitor.update_state(connected(endpoint(), None));
+        assert_eq!(monitor.reconnects, 0);
+        monitor.update_state(connecting());
+        monitor.update_state(connecting());
+        monitor.update_state(connected(endpoint(), None));
+        assert_eq!(monitor.reconnects, 1);
+        monitor.update_state(TunnelState::Disconnected);
+        monitor.update_state(connecting());
+        assert_eq!(monitor.reconnects, 1);
+    }
+
+    #[test]
+    fn test_monitor_lines() {
+        let start = Instant::now();
+        let mut monitor = Monitor::new(connected(endpoint(), Some(location())));
+        monitor.update_stats(start, Some(stats(1000, 1000)));
+        assert_eq!(monitor.throughput, None);
+        assert_eq!(
+            monitor.line(),
+            "Connected | se-got-wg-001 | obfuscation: none | reconnects: 0"
+        );
+
+        monitor.update_stats(
+            start + Duration::from_secs(1),
+            Some(stats(2_501_000, 11_000)),
+        );
+        assert_eq!(
+            monitor.lines()[2..],
+            [
+                "State:        Connected",
+                "Relay:        se-got-wg-001",
+                "Obfuscation:  none",
+                "Throughput:   down 2.5 MB/s, up 10.0 kB/s",
+                "Transferred:  down 2.5 MB, up 11.0 kB",
+                "Reconnects:   0",
+            ]
+        );
+        assert_eq!(
+            monitor.line(),
+            "Connected | se-got-wg-001 | down 2.5 MB/s, up 10.0 kB/s | obfuscation: none \
+             | reconnects: 0"
+        );
+
+        monitor.update_state(TunnelState::Disconnected);
+        assert_eq!(monitor.line(), "Disconnected | reconnects: 0");
+        assert_eq!(monitor.lines()[5], "Throughput:   down -, up -");
+    }
+
+    #[test]
+    fn test_redraw() {
+        let lines = ["a".to_owned(), "b".to_owned()];
+        assert_eq!(
+            redraw(&lines, true),
+            "\x1b[H\x1b[2Ja\x1b[K\nb\x1b[K\n\x1b[J"
+        );
+        assert!(redraw(&lines, false).starts_with("\x1b[Ha\x1b[K\n"));
+    }
+}
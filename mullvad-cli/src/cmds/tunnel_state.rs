@@ -1,93 +1,233 @@
-use crate::format;
+use crate::{exit_code::ExitCode, format};
 use anyhow::{anyhow, Result};
+use clap::Args;
 use futures::{Stream, StreamExt};
 use mullvad_management_interface::{client::DaemonEvent, MullvadProxyClient};
-use mullvad_types::{device::DeviceState, states::TunnelState};
+use mullvad_types::{
+    device::DeviceState,
+    relay_constraints::{RelayOverrides, SelectedObfuscation},
+    states::TunnelState,
+};
+use std::{fmt, time::Duration};
+use talpid_types::{
+    net::TunnelType,
+    tunnel::{ActionAfterDisconnect, ErrorCode},
+};
 
-pub async fn connect(wait: bool) -> Result<()> {
+/// Parameters that are used instead of the settings for the next connection attempt only.
+/// Later attempts select the relay according to the settings again.
+#[derive(Args, Debug)]
+pub struct RelayOverrideArgs {
+    /// Connect to this relay, regardless of the location and filters in the settings
+    #[arg(long)]
+    relay: Option<String>,
+
+    /// Connect to this port
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// Obfuscation to use. 'none' disables it
+    #[arg(long)]
+    obfuscation: Option<SelectedObfuscation>,
+
+    /// Tunnel protocol to use: 'wireguard' or 'openvpn'
+    #[arg(long)]
+    protocol: Option<TunnelType>,
+}
+
+impl From<RelayOverrideArgs> for RelayOverrides {
+    fn from(args: RelayOverrideArgs) -> Self {
+        RelayOverrides {
+            hostname: args.relay,
+            port: args.port,
+            obfuscation: args.obfuscation,
+            tunnel_protocol: args.protocol,
+        }
+    }
+}
+
+/// Options for waiting until the tunnel has reached the requested state.
+#[derive(Args, Debug)]
+pub struct WaitArgs {
+    /// Wait until the tunnel has reached the requested state before exiting. Exits with 2 if the
+    /// tunnel enters the error state, 3 on timeout, and 4 if another client changes the target
+    /// state in the meantime
+    #[arg(long, short = 'w')]
+    wait: bool,
+
+    /// Give up waiting after this many seconds
+    #[arg(long, requires = "wait", value_name = "SECS")]
+    timeout: Option<u64>,
+}
+
+impl WaitArgs {
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+}
+
+/// Reason that waiting for a tunnel state failed. Each reason exits with its own code, so that
+/// scripts can tell them apart.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WaitError {
+    /// The tunnel entered the error state.
+    TunnelError(ErrorCode),
+    /// The state was not reached in time.
+    TimedOut,
+    /// Another client changed the target state.
+    Superseded,
+}
+
+impl WaitError {
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            WaitError::TunnelError(_) => ExitCode::WaitTunnelError,
+            WaitError::TimedOut => ExitCode::WaitTimedOut,
+            WaitError::Superseded => ExitCode::WaitSuperseded,
+        }
+    }
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::TunnelError(code) => write!(f, "The tunnel entered the error state: {code}"),
+            WaitError::TimedOut => f.write_str("Timed out"),
+            WaitError::Superseded => f.write_str("Superseded by another client"),
+        }
+    }
+}
+
+impl std::error::Error for WaitError {}
+
+/// The tunnel state that is waited for.
+#[derive(Clone, Copy, Debug)]
+enum Goal {
+    Connected,
+    Disconnected,
+}
+
+impl Goal {
+    /// Returns the outcome of waiting if `state` ends the wait, or `None` to keep waiting.
+    fn outcome(self, state: &TunnelState) -> Option<std::result::Result<(), WaitError>> {
+        match (self, state) {
+            (Goal::Connected, TunnelState::Connected { .. }) => Some(Ok(())),
+            (Goal::Connected, TunnelState::Error(error_state)) => Some(Err(
+                WaitError::TunnelError(error_state.cause().error_code()),
+            )),
+            (
+                Goal::Connected,
+                TunnelState::Disconnecting(ActionAfterDisconnect::Nothing)
+                | TunnelState::Disconnected,
+            ) => Some(Err(WaitError::Superseded)),
+            (Goal::Disconnected, TunnelState::Disconnected) => Some(Ok(())),
+            (
+                Goal::Disconnected,
+                TunnelState::Connecting { .. } | TunnelState::Connected { .. },
+            ) => Some(Err(WaitError::Superseded)),
+            _ => None,
+        }
+    }
+}
+
+pub async fn connect(wait: WaitArgs) -> Result<()> {
     let mut rpc = MullvadProxyClient::new().await?;
 
     let device_state = rpc.get_device().await?;
     print_account_loggedout(&device_state);
 
-    let listener = if wait {
+    let listener = if wait.wait {
         Some(rpc.events_listen().await?)
     } else {
         None
     };
 
-    if rpc.connect_tunnel().await? {
-        if let Some(receiver) = listener {
-            wait_for_tunnel_state(receiver, |state| match state {
-                TunnelState::Connected { .. } => Ok(true),
-                TunnelState::Error(_) => Err(anyhow!("Failed to connect")),
-                _ => Ok(false),
-            })
-            .await?;
+    let changed = rpc.connect_tunnel().await?;
+    if let Some(receiver) = listener {
+        // The tunnel may already be connected, or have failed to connect, if the target state
+        // was already secured
+        let state = rpc.get_tunnel_state().await?;
+        match Goal::Connected.outcome(&state) {
+            Some(Ok(())) => return Ok(()),
+            Some(Err(error)) if !changed => return Err(error.into()),
+            _ => wait_for_tunnel_state(receiver, Goal::Connected, wait.timeout()).await?,
         }
     }
 
     Ok(())
 }
 
-pub async fn disconnect(wait: bool) -> Result<()> {
+pub async fn disconnect(wait: WaitArgs) -> Result<()> {
     let mut rpc = MullvadProxyClient::new().await?;
 
-    let listener = if wait {
+    let listener = if wait.wait {
         Some(rpc.events_listen().await?)
     } else {
         None
     };
 
-    if rpc.disconnect_tunnel().await? {
-        if let Some(receiver) = listener {
-            wait_for_tunnel_state(receiver, |state| Ok(state.is_disconnected())).await?;
+    rpc.disconnect_tunnel().await?;
+    if let Some(receiver) = listener {
+        if !rpc.get_tunnel_state().await?.is_disconnected() {
+            wait_for_tunnel_state(receiver, Goal::Disconnected, wait.timeout()).await?;
         }
     }
 
     Ok(())
 }
 
-pub async fn reconnect(wait: bool) -> Result<()> {
+pub async fn reconnect(wait: WaitArgs, overrides: RelayOverrideArgs) -> Result<()> {
     let mut rpc = MullvadProxyClient::new().await?;
 
     let device_state = rpc.get_device().await?;
     print_account_loggedout(&device_state);
 
-    let listener = if wait {
+    let listener = if wait.wait {
         Some(rpc.events_listen().await?)
     } else {
         None
     };
 
-    if rpc.reconnect_tunnel().await? {
+    let overrides = RelayOverrides::from(overrides);
+    let reconnecting = if overrides.is_empty() {
+        rpc.reconnect_tunnel().await?
+    } else {
+        rpc.reconnect_with_overrides(overrides).await?
+    };
+    // The current state cannot be checked first, since it may still be the old tunnel
+    if reconnecting {
         if let Some(receiver) = listener {
-            wait_for_tunnel_state(receiver, |state| match state {
-                TunnelState::Connected { .. } => Ok(true),
-                TunnelState::Error(_) => Err(anyhow!("Failed to reconnect")),
-                _ => Ok(false),
-            })
-            .await?;
+            wait_for_tunnel_state(receiver, Goal::Connected, wait.timeout()).await?;
         }
     }
 
     Ok(())
 }
 
+/// Prints every tunnel state in `event_stream` until one of them ends the wait for `goal`.
 async fn wait_for_tunnel_state(
     mut event_stream: impl Stream<Item = std::result::Result<DaemonEvent, mullvad_management_interface::Error>>
         + Unpin,
-    matches_event: impl Fn(&TunnelState) -> Result<bool>,
+    goal: Goal,
+    timeout: Option<Duration>,
 ) -> Result<()> {
-    while let Some(state) = event_stream.next().await {
-        if let DaemonEvent::TunnelState(new_state) = state? {
-            format::print_state(&new_state, false);
-            if matches_event(&new_state)? {
-                return Ok(());
+    let wait = async {
+        while let Some(state) = event_stream.next().await {
+            if let DaemonEvent::TunnelState(new_state) = state? {
+                format::print_state(&new_state, false);
+                if let Some(outcome) = goal.outcome(&new_state) {
+                    return outcome.map_err(anyhow::Error::from);
+                }
             }
         }
+        Err(anyhow!("Failed to wait for expected tunnel state"))
+    };
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, wait)
+            .await
+            .unwrap_or_else(|_| Err(WaitError::TimedOut.into())),
+        None => wait.await,
     }
-    Err(anyhow!("Failed to wait for expected tunnel state"))
 }
 
 /// Checks the if the user is logged in. If not, we print a warning to get their
@@ -113,3 +253,116 @@ fn print_account_loggedout(state: &DeviceState) {
 For more information, try 'mullvad account -h' or 'mullvad disconnect -h'"
     );
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use mullvad_types::location::GeoIpLocation;
+    use talpid_types::{
+        net::{Endpoint, TransportProtocol, TunnelEndpoint},
+        tunnel::{ErrorState, ErrorStateCause},
+    };
+
+    /// Returns an endpoint of a WireGuard relay.
+    pub(crate) fn endpoint() -> TunnelEndpoint {
+        TunnelEndpoint {
+            endpoint: Endpoint::new([10, 0, 0, 1], 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            quantum_resistant: false,
+            proxy: None,
+            obfuscation: None,
+            entry_endpoint: None,
+            tunnel_interface: None,
+        }
+    }
+
+    pub(crate) fn connecting() -> TunnelState {
+        TunnelState::Connecting {
+            endpoint: endpoint(),
+            location: None,
+            phase: Default::default(),
+            attempt: Default::default(),
+        }
+    }
+
+    /// Returns the state of a tunnel that is connected to `endpoint`.
+    pub(crate) fn connected(
+        endpoint: TunnelEndpoint,
+        location: Option<GeoIpLocation>,
+    ) -> TunnelState {
+        TunnelState::Connected {
+            endpoint,
+            location,
+            effective_dns: Default::default(),
+            first_hop: None,
+        }
+    }
+
+    async fn wait(
+        states: Vec<TunnelState>,
+        goal: Goal,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<(), WaitError> {
+        let events = futures::stream::iter(
+            states
+                .into_iter()
+                .map(|state| Ok(DaemonEvent::TunnelState(state))),
+        )
+        .chain(futures::stream::pending());
+        wait_for_tunnel_state(events, goal, timeout)
+            .await
+            .map_err(|error| error.downcast::<WaitError>().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_connected() {
+        let reconnect = vec![
+            TunnelState::Disconnecting(ActionAfterDisconnect::Reconnect),
+            connecting(),
+            connected(endpoint(), None),
+        ];
+        assert_eq!(wait(reconnect, Goal::Connected, None).await, Ok(()));
+
+        let error = TunnelState::Error(ErrorState::new(ErrorStateCause::StartTunnelError, None));
+        let result = wait(vec![connecting(), error], Goal::Connected, None).await;
+        assert_eq!(
+            result,
+            Err(WaitError::TunnelError(ErrorCode::StartTunnelFailed))
+        );
+        assert_eq!(result.unwrap_err().exit_code(), ExitCode::WaitTunnelError);
+
+        let disconnected = vec![
+            connecting(),
+            TunnelState::Disconnecting(ActionAfterDisconnect::Nothing),
+        ];
+        assert_eq!(
+            wait(disconnected, Goal::Connected, None).await,
+            Err(WaitError::Superseded)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_disconnected() {
+        let disconnect = vec![
+            TunnelState::Disconnecting(ActionAfterDisconnect::Nothing),
+            TunnelState::Disconnected,
+        ];
+        assert_eq!(wait(disconnect, Goal::Disconnected, None).await, Ok(()));
+        assert_eq!(
+            wait(vec![connecting()], Goal::Disconnected, None).await,
+            Err(WaitError::Superseded)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_timeout() {
+        let result = wait(
+            vec![connecting()],
+            Goal::Connected,
+            Some(Duration::from_millis(10)),
+        )
+        .await;
+        assert_eq!(result, Err(WaitError::TimedOut));
+        assert_eq!(result.unwrap_err().exit_code(), ExitCode::WaitTimedOut);
+    }
+}
@@ -1,5 +1,47 @@
+use crate::exit_code::ExitCode;
 use anyhow::{Context, Result};
+use clap::Subcommand;
 use mullvad_management_interface::MullvadProxyClient;
+use mullvad_types::version::AppVersionInfo;
+use std::fmt;
+
+#[derive(Subcommand, Debug)]
+pub enum Version {
+    /// Fetch the version info now and show whether the installed version is still supported.
+    /// Exits with code 8 if it is not
+    Check {
+        /// Show the newest beta version instead of the newest stable version
+        #[arg(long)]
+        beta: bool,
+    },
+}
+
+/// Returned after the result of a version check that found the version unsupported has been
+/// printed.
+#[derive(Debug)]
+pub struct VersionUnsupported;
+
+impl VersionUnsupported {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::VersionUnsupported
+    }
+}
+
+impl fmt::Display for VersionUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("This version of the app is no longer supported")
+    }
+}
+
+impl std::error::Error for VersionUnsupported {}
+
+impl Version {
+    pub async fn handle(self) -> Result<()> {
+        match self {
+            Version::Check { beta } => check(beta).await,
+        }
+    }
+}
 
 pub async fn print() -> Result<()> {
     println!("{:22}: {}", "Current version", mullvad_version::VERSION);
@@ -46,3 +88,77 @@ pub async fn print() -> Result<()> {
 
     Ok(())
 }
+
+async fn check(beta: bool) -> Result<()> {
+    let mut rpc = MullvadProxyClient::new()
+        .await
+        .context("Failed to connect to mullvad-daemon")?;
+
+    // The daemon is what the API reports on, so its version is the one that matters
+    let daemon_version = rpc
+        .get_current_version()
+        .await
+        .context("Failed to get current mullvad-daemon version")?;
+    let version_info = rpc
+        .check_version(beta)
+        .await
+        .context("Failed to check for new versions")?;
+
+    print!("{}", format_check(&daemon_version, &version_info, beta));
+    if !version_info.supported {
+        return Err(VersionUnsupported.into());
+    }
+    Ok(())
+}
+
+/// Formats the result of `mullvad version check` for the beta or the stable channel.
+fn format_check(current_version: &str, version_info: &AppVersionInfo, beta: bool) -> String {
+    let (channel, latest) = if beta {
+        ("Latest beta version", &version_info.latest_beta)
+    } else {
+        ("Latest stable version", &version_info.latest_stable)
+    };
+    let supported = if version_info.supported { "yes" } else { "no" };
+    let suggested_upgrade = version_info.suggested_upgrade.as_deref().unwrap_or("none");
+    format!(
+        "{:22}: {current_version}\n{:22}: {supported}\n{channel:22}: {latest}\n{:22}: \
+         {suggested_upgrade}\n",
+        "Current version", "Is supported", "Suggested upgrade"
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn version_info(supported: bool, suggested_upgrade: Option<&str>) -> AppVersionInfo {
+        AppVersionInfo {
+            supported,
+            latest_stable: "2024.2".to_owned(),
+            latest_beta: "2024.3-beta1".to_owned(),
+            suggested_upgrade: suggested_upgrade.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_format_check() {
+        assert_eq!(
+            format_check("2024.2", &version_info(true, None), false),
+            "\
+Current version       : 2024.2
+Is supported          : yes
+Latest stable version : 2024.2
+Suggested upgrade     : none
+"
+        );
+        assert_eq!(
+            format_check("2023.1", &version_info(false, Some("2024.3-beta1")), true),
+            "\
+Current version       : 2023.1
+Is supported          : no
+Latest beta version   : 2024.3-beta1
+Suggested upgrade     : 2024.3-beta1
+"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Exit codes of the CLI. Scripts rely on them, so a code must never change its meaning.
+
+use crate::cmds::{check::NotViaMullvad, tunnel_state::WaitError, version::VersionUnsupported};
+use mullvad_management_interface::Code;
+use std::fmt;
+
+/// Environment variable that enables non-interactive mode, like `--non-interactive`.
+pub const NON_INTERACTIVE_ENV: &str = "MULLVAD_NONINTERACTIVE";
+
+/// Shown at the end of `mullvad --help`.
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  Success
+  1  The operation failed
+  2  The tunnel entered the error state while waiting (--wait)
+  3  Timed out waiting for the tunnel state (--wait --timeout)
+  4  Another client changed the target tunnel state while waiting (--wait)
+  5  Invalid arguments
+  6  The daemon could not be reached
+  7  The tunnel is connected, but traffic does not go through it (check)
+  8  This version of the app is no longer supported (version check)
+  9  Input would have been needed, but the CLI runs non-interactively";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    OperationFailed = 1,
+    WaitTunnelError = 2,
+    WaitTimedOut = 3,
+    WaitSuperseded = 4,
+    Usage = 5,
+    DaemonUnreachable = 6,
+    NotViaMullvad = 7,
+    VersionUnsupported = 8,
+    InteractionRequired = 9,
+}
+
+impl ExitCode {
+    /// Returns the code to exit with after failing with `error`.
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        use mullvad_management_interface::Error;
+
+        if let Some(error) = error.downcast_ref::<WaitError>() {
+            return error.exit_code();
+        }
+        if let Some(error) = error.downcast_ref::<NotViaMullvad>() {
+            return error.exit_code();
+        }
+        if let Some(error) = error.downcast_ref::<VersionUnsupported>() {
+            return error.exit_code();
+        }
+        if error.is::<InteractionRequired>() {
+            return ExitCode::InteractionRequired;
+        }
+        if error.is::<UsageError>() {
+            return ExitCode::Usage;
+        }
+        match error.downcast_ref::<Error>() {
+            Some(Error::GrpcTransportError(_)) => ExitCode::DaemonUnreachable,
+            Some(Error::Rpc(status)) if status.code() == Code::Unavailable => {
+                ExitCode::DaemonUnreachable
+            }
+            _ => ExitCode::OperationFailed,
+        }
+    }
+
+    /// Returns the code to exit with when the arguments could not be parsed. Printing the help or
+    /// the version is also reported as an error by clap, but is successful.
+    pub fn from_clap_error(error: &clap::Error) -> Self {
+        if error.use_stderr() {
+            ExitCode::Usage
+        } else {
+            ExitCode::Success
+        }
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        std::process::ExitCode::from(code as u8)
+    }
+}
+
+/// The arguments are valid on their own, but not together.
+#[derive(Debug)]
+pub struct UsageError(pub &'static str);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Returned instead of asking the user for input in non-interactive mode. The message should say
+/// which argument to pass instead.
+#[derive(Debug)]
+pub struct InteractionRequired(pub &'static str);
+
+impl fmt::Display for InteractionRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for InteractionRequired {}
+
+/// Returns whether the value of `MULLVAD_NONINTERACTIVE` enables non-interactive mode. Any value
+/// except for an empty one, "0" and "false" does.
+pub fn is_non_interactive_env(value: Option<&str>) -> bool {
+    match value {
+        Some(value) => !matches!(value.trim(), "" | "0" | "false"),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+    use mullvad_management_interface::Status;
+
+    #[derive(Debug, Parser)]
+    struct TestCli {
+        #[arg(long)]
+        flag: bool,
+    }
+
+    #[test]
+    fn test_exit_code_from_error() {
+        use mullvad_management_interface::Error;
+
+        let code = |error: anyhow::Error| ExitCode::from_error(&error);
+
+        assert_eq!(
+            code(Error::Rpc(Status::unavailable("")).into()),
+            ExitCode::DaemonUnreachable
+        );
+        assert_eq!(
+            code(anyhow::Error::from(Error::Rpc(Status::unavailable(""))).context("Failed")),
+            ExitCode::DaemonUnreachable
+        );
+        assert_eq!(
+            code(Error::Rpc(Status::internal("")).into()),
+            ExitCode::OperationFailed
+        );
+        assert_eq!(
+            code(Error::TooManyDevices.into()),
+            ExitCode::OperationFailed
+        );
+        assert_eq!(
+            code(anyhow::anyhow!("Log in or specify an account")),
+            ExitCode::OperationFailed
+        );
+        assert_eq!(
+            code(InteractionRequired("Pass --yes").into()),
+            ExitCode::InteractionRequired
+        );
+        assert_eq!(code(UsageError("No").into()), ExitCode::Usage);
+        assert_eq!(
+            code(WaitError::TunnelError(talpid_types::tunnel::ErrorCode::StartTunnelFailed).into()),
+            ExitCode::WaitTunnelError
+        );
+        assert_eq!(code(WaitError::TimedOut.into()), ExitCode::WaitTimedOut);
+        assert_eq!(code(WaitError::Superseded.into()), ExitCode::WaitSuperseded);
+        assert_eq!(code(NotViaMullvad.into()), ExitCode::NotViaMullvad);
+        assert_eq!(
+            code(VersionUnsupported.into()),
+            ExitCode::VersionUnsupported
+        );
+    }
+
+    #[test]
+    fn test_exit_code_from_clap_error() {
+        let code =
+            |args: &[&str]| ExitCode::from_clap_error(&TestCli::try_parse_from(args).unwrap_err());
+
+        assert_eq!(code(&["test", "--help"]), ExitCode::Success);
+        assert_eq!(code(&["test", "--unknown"]), ExitCode::Usage);
+        assert_eq!(code(&["test", "--flag=maybe"]), ExitCode::Usage);
+    }
+
+    #[test]
+    fn test_non_interactive_env() {
+        assert!(!is_non_interactive_env(None));
+        assert!(!is_non_interactive_env(Some("")));
+        assert!(!is_non_interactive_env(Some("0")));
+        assert!(!is_non_interactive_env(Some("false")));
+        assert!(is_non_interactive_env(Some("1")));
+        assert!(is_non_interactive_env(Some("true")));
+    }
+}
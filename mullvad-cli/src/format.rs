@@ -1,7 +1,13 @@
-use mullvad_types::{auth_failed::AuthFailed, location::GeoIpLocation, states::TunnelState};
+use anyhow::Result;
+use mullvad_types::{
+    access_method::ApiConnectivity, auth_failed::AuthFailed, features::FeatureIndicator,
+    location::GeoIpLocation, states::TunnelState,
+};
+use serde::Serialize;
+use std::net::IpAddr;
 use talpid_types::{
-    net::{Endpoint, TunnelEndpoint},
-    tunnel::ErrorState,
+    net::{Endpoint, TunnelEndpoint, TunnelType},
+    tunnel::{ConnectingPhase, ErrorState},
 };
 
 #[macro_export]
@@ -14,26 +20,70 @@ macro_rules! print_option {
     }};
 }
 
+/// Prints `value` as JSON, for `--json`. Scripts depend on the output, so the field names of the
+/// printed types must not change.
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Prints `value` as JSON on a single line, for streams of newline-delimited JSON.
+pub fn print_json_line<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
 pub fn print_state(state: &TunnelState, verbose: bool) {
     use TunnelState::*;
 
     match state {
-        Error(error) => print_error_state(error),
-        Connected { endpoint, location } => {
+        Error(error) => print_error_state(error, verbose),
+        Connected {
+            endpoint,
+            location,
+            effective_dns,
+            first_hop,
+        } => {
             println!(
                 "Connected to {}",
                 format_relay_connection(endpoint, location.as_ref(), verbose)
             );
             if verbose {
                 if let Some(tunnel_interface) = &endpoint.tunnel_interface {
-                    println!("Tunnel interface: {tunnel_interface}")
+                    println!("Tunnel interface: {tunnel_interface}");
+                    #[cfg(windows)]
+                    print_interface_metrics(tunnel_interface);
+                }
+                println!(
+                    "DNS servers ({}): {}",
+                    effective_dns.source,
+                    format_servers(&effective_dns.servers)
+                );
+                if effective_dns.tampered {
+                    println!("DNS config was overwritten by another program and has been restored");
+                }
+                if let Some(first_hop) = first_hop {
+                    println!("First hop: {first_hop}");
                 }
             }
         }
-        Connecting { endpoint, location } => {
+        Connecting {
+            endpoint,
+            location,
+            phase,
+            ..
+        } => {
             let ellipsis = if !verbose { "..." } else { "" };
+            let action = match phase {
+                ConnectingPhase::EstablishingTunnel => "Connecting to",
+                ConnectingPhase::WaitingForNetwork => "Waiting for network before connecting to",
+                ConnectingPhase::WaitingForStableNetwork => {
+                    "Waiting for a stable network before connecting to"
+                }
+                ConnectingPhase::WaitingToRetry => "Waiting to retry connecting to",
+            };
             println!(
-                "Connecting to {}{ellipsis}",
+                "{action} {}{ellipsis}",
                 format_relay_connection(endpoint, location.as_ref(), verbose)
             );
         }
@@ -42,6 +92,111 @@ pub fn print_state(state: &TunnelState, verbose: bool) {
     }
 }
 
+/// Prints the attempt that is in progress if `state` is connecting, e.g.
+/// "attempt 4: se-got-wg-002 via udp2tcp:443, next retry in 8s".
+pub fn print_connection_attempt(state: &TunnelState) {
+    let TunnelState::Connecting {
+        endpoint,
+        location,
+        attempt,
+        ..
+    } = state
+    else {
+        return;
+    };
+    if attempt.attempt == 0 {
+        // The daemon does not report attempts
+        return;
+    }
+
+    let first_hop = endpoint
+        .entry_endpoint
+        .as_ref()
+        .unwrap_or(&endpoint.endpoint);
+    let relay = location
+        .as_ref()
+        .and_then(|l| l.entry_hostname.as_ref().or(l.hostname.as_ref()))
+        .cloned()
+        .unwrap_or_else(|| first_hop.address.ip().to_string());
+    let transport = match &endpoint.obfuscation {
+        Some(obfuscator) => format!(
+            "{}:{}",
+            obfuscator.obfuscation_type,
+            obfuscator.endpoint.address.port()
+        ),
+        None => format!("{}:{}", first_hop.protocol, first_hop.address.port()),
+    };
+    let next_retry = attempt
+        .retry_timeout
+        .map(|timeout| format!(", next retry in {}s", timeout.as_secs()))
+        .unwrap_or_default();
+    println!(
+        "attempt {}: {relay} via {}{next_retry}",
+        attempt.attempt,
+        transport.to_lowercase()
+    );
+}
+
+pub fn print_feature_indicators(features: &[FeatureIndicator]) {
+    if features.is_empty() {
+        println!("Active features: none");
+        return;
+    }
+    let features = features
+        .iter()
+        .map(|feature| feature.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!("Active features: {features}");
+}
+
+pub fn print_api_connectivity(status: &ApiConnectivity) {
+    match status {
+        ApiConnectivity::Unknown => println!("API: unknown"),
+        ApiConnectivity::Reachable => println!("API: reachable"),
+        ApiConnectivity::Unreachable { since, last_error } => {
+            let since = chrono::DateTime::<chrono::Local>::from(*since).format("%Y-%m-%d %H:%M:%S");
+            println!("API: unreachable since {since} ({last_error})");
+        }
+    }
+}
+
+/// Prints the IPv4 and IPv6 metrics of a network interface.
+#[cfg(windows)]
+fn print_interface_metrics(interface: &str) {
+    use talpid_windows_net::{get_ip_interface_entry, luid_from_alias, AddressFamily};
+
+    let Ok(luid) = luid_from_alias(interface) else {
+        return;
+    };
+    let metrics = [(AddressFamily::Ipv4, "IPv4"), (AddressFamily::Ipv6, "IPv6")]
+        .into_iter()
+        .filter_map(|(family, name)| {
+            let row = get_ip_interface_entry(family, &luid).ok()?;
+            let automatic = if row.UseAutomaticMetric != 0 {
+                " (automatic)"
+            } else {
+                ""
+            };
+            Some(format!("{name} {}{automatic}", row.Metric))
+        })
+        .collect::<Vec<_>>();
+    if !metrics.is_empty() {
+        println!("Tunnel interface metrics: {}", metrics.join(", "));
+    }
+}
+
+fn format_servers(servers: &[IpAddr]) -> String {
+    if servers.is_empty() {
+        return "none".to_owned();
+    }
+    servers
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 fn format_relay_connection(
     endpoint: &TunnelEndpoint,
     location: Option<&GeoIpLocation>,
@@ -149,6 +304,9 @@ fn format_relay_connection(
         }
         if let Some(obfuscator) = &endpoint.obfuscation {
             obfuscator_type = format!("\nObfuscator: {}", obfuscator.obfuscation_type);
+        } else if endpoint.tunnel_type == TunnelType::Wireguard {
+            // Auto mode may also try connecting without obfuscation
+            obfuscator_type = "\nObfuscator: none".to_string();
         }
     }
 
@@ -169,7 +327,7 @@ fn format_endpoint(hostname: Option<&str>, endpoint: &Endpoint, verbose: bool) -
     }
 }
 
-fn print_error_state(error_state: &ErrorState) {
+fn print_error_state(error_state: &ErrorState, verbose: bool) {
     if error_state.block_failure().is_some() {
         eprintln!("Mullvad daemon failed to setup firewall rules!");
         eprintln!("Daemon cannot block traffic from flowing, non-local traffic will leak");
@@ -187,8 +345,17 @@ fn print_error_state(error_state: &ErrorState) {
                 get_auth_failed_message(AuthFailed::from(auth_failed.as_str()))
             );
         }
+        talpid_types::tunnel::ErrorStateCause::AccountExpired => {
+            println!(
+                "Blocked: {}",
+                get_auth_failed_message(AuthFailed::ExpiredAccount)
+            );
+        }
         cause => println!("Blocked: {cause}"),
     }
+    if verbose {
+        println!("Error code: {}", error_state.cause().error_code());
+    }
 }
 
 const fn get_auth_failed_message(auth_failed: AuthFailed) -> &'static str {
@@ -3,9 +3,11 @@
 #[cfg(all(unix, not(target_os = "android")))]
 use anyhow::anyhow;
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use exit_code::{ExitCode, UsageError};
 
 mod cmds;
+mod exit_code;
 mod format;
 use cmds::*;
 
@@ -13,8 +15,26 @@ pub const BIN_NAME: &str = env!("CARGO_BIN_NAME");
 
 #[derive(Debug, Parser)]
 #[command(author, version = mullvad_version::VERSION, about, long_about = None)]
-#[command(propagate_version = true)]
-enum Cli {
+#[command(propagate_version = true, after_help = exit_code::EXIT_CODES_HELP)]
+struct Cli {
+    /// Print the output as JSON, for use in scripts. The field names are kept stable between
+    /// releases. Supported by `status`, `status listen`, `check`, `relay get`, `relay list`,
+    /// `account get`, `dns get` and `api-access list`. `status listen` prints one JSON object
+    /// per line
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Never ask for input. Commands that would have to fail with exit code 9 instead, and
+    /// confirmations must be given with --yes. Also enabled by setting MULLVAD_NONINTERACTIVE
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
     /// Control and display information about your Mullvad account
     #[clap(subcommand)]
     Account(account::Account),
@@ -23,6 +43,10 @@ enum Cli {
     #[clap(subcommand)]
     AutoConnect(auto_connect::AutoConnect),
 
+    /// Disconnect the tunnel when next to no traffic has passed through it for a while
+    #[clap(subcommand)]
+    AutoDisconnect(auto_disconnect::AutoDisconnect),
+
     /// Receive notifications about beta updates
     #[clap(subcommand)]
     BetaProgram(beta_program::BetaProgram),
@@ -39,25 +63,42 @@ enum Cli {
     #[clap(subcommand)]
     Lan(lan::Lan),
 
+    /// Route networks outside the tunnel
+    #[clap(subcommand)]
+    BypassRoutes(bypass_routes::BypassRoutes),
+
+    /// Manage a SOCKS5 proxy on localhost whose connections use the tunnel
+    #[clap(subcommand)]
+    LocalProxy(local_proxy::LocalProxy),
+
+    /// Run scripts when the tunnel connects, disconnects or fails
+    #[clap(subcommand)]
+    Hooks(hooks::Hooks),
+
+    /// Control whether to leave the default route to other VPNs
+    #[cfg(target_os = "macos")]
+    #[clap(subcommand)]
+    CoexistenceMode(coexistence_mode::CoexistenceMode),
+
     /// Connect to a VPN relay
     Connect {
-        /// Wait until connected before exiting
-        #[arg(long, short = 'w')]
-        wait: bool,
+        #[clap(flatten)]
+        wait: tunnel_state::WaitArgs,
     },
 
     /// Disconnect from the VPN
     Disconnect {
-        /// Wait until disconnected before exiting
-        #[arg(long, short = 'w')]
-        wait: bool,
+        #[clap(flatten)]
+        wait: tunnel_state::WaitArgs,
     },
 
     /// Reconnect to any matching VPN relay
     Reconnect {
-        /// Wait until connected before exiting
-        #[arg(long, short = 'w')]
-        wait: bool,
+        #[clap(flatten)]
+        wait: tunnel_state::WaitArgs,
+
+        #[clap(flatten)]
+        overrides: tunnel_state::RelayOverrideArgs,
     },
 
     /// Manage use of bridges, socks proxies and Shadowsocks for OpenVPN.
@@ -98,6 +139,12 @@ enum Cli {
     #[clap(subcommand)]
     SplitTunnel(split_tunnel::SplitTunnel),
 
+    /// Connect or disconnect automatically depending on the network. Untrusted networks connect
+    /// the tunnel, and trusted networks can disconnect it. A connect or disconnect command is
+    /// respected until the host joins another network
+    #[clap(subcommand)]
+    Trust(trust::Trust),
+
     /// Return the state of the VPN tunnel
     Status {
         #[clap(subcommand)]
@@ -107,13 +154,32 @@ enum Cli {
         args: status::StatusArgs,
     },
 
+    /// Check that traffic goes through Mullvad, like the connection check on the Mullvad website.
+    /// Exits with code 7 if the tunnel is connected but traffic does not go through it
+    Check {
+        /// Check the connection even if the tunnel is not connected
+        #[arg(long)]
+        allow_disconnected: bool,
+    },
+
     /// Manage tunnel options
     #[clap(subcommand)]
     Tunnel(tunnel::Tunnel),
 
     /// Show information about the current Mullvad version
     /// and available versions
-    Version,
+    Version {
+        #[clap(subcommand)]
+        cmd: Option<version::Version>,
+    },
+
+    /// Print completion candidates that depend on the daemon. Used by the completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        /// The words after 'mullvad', ending with the word that is being completed
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
 
     /// Generate completion scripts for the specified shell
     #[cfg(all(unix, not(target_os = "android")))]
@@ -127,47 +193,119 @@ enum Cli {
         dir: std::path::PathBuf,
     },
 
-    /// Reset settings, caches, and logs
-    FactoryReset,
+    /// Reset settings, caches, history and logs. Everything is reset unless scopes are given
+    FactoryReset(reset::ResetArgs),
+
+    /// Export the settings, or import settings exported on another machine
+    #[clap(subcommand)]
+    Settings(settings::Settings),
+
+    /// Save the settings as named profiles and switch between them
+    #[clap(subcommand)]
+    Profile(profile::Profile),
 
     /// Manage custom lists
     #[clap(subcommand)]
     CustomList(custom_list::CustomList),
+
+    /// Show information that helps debug connection problems
+    #[clap(subcommand)]
+    Debug(debug::Debug),
+}
+
+impl Command {
+    /// Returns whether the command can print its output as JSON.
+    fn supports_json(&self) -> bool {
+        matches!(
+            self,
+            Command::Status { .. }
+                | Command::Check { .. }
+                | Command::Relay(relay::Relay::Get | relay::Relay::List(_))
+                | Command::Account(account::Account::Get { .. })
+                | Command::Dns(dns::Dns::Get)
+                | Command::ApiAccess(api_access::ApiAccess::List { .. })
+        )
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> std::process::ExitCode {
     env_logger::init();
 
-    match Cli::parse() {
-        Cli::Account(cmd) => cmd.handle().await,
-        Cli::Bridge(cmd) => cmd.handle().await,
-        Cli::Connect { wait } => tunnel_state::connect(wait).await,
-        Cli::Reconnect { wait } => tunnel_state::reconnect(wait).await,
-        Cli::Disconnect { wait } => tunnel_state::disconnect(wait).await,
-        Cli::AutoConnect(cmd) => cmd.handle().await,
-        Cli::BetaProgram(cmd) => cmd.handle().await,
-        Cli::LockdownMode(cmd) => cmd.handle().await,
-        Cli::Dns(cmd) => cmd.handle().await,
-        Cli::Lan(cmd) => cmd.handle().await,
-        Cli::Obfuscation(cmd) => cmd.handle().await,
-        Cli::ApiAccess(cmd) => cmd.handle().await,
-        Cli::Version => version::print().await,
-        Cli::FactoryReset => reset::handle().await,
-        Cli::Relay(cmd) => cmd.handle().await,
-        Cli::Tunnel(cmd) => cmd.handle().await,
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(error) => {
+            let _ = error.print();
+            return ExitCode::from_clap_error(&error).into();
+        }
+    };
+    match run(cli).await {
+        Ok(()) => ExitCode::Success.into(),
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+            ExitCode::from_error(&error).into()
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let Cli {
+        json,
+        non_interactive,
+        command,
+    } = cli;
+    let non_interactive = non_interactive
+        || exit_code::is_non_interactive_env(
+            std::env::var(exit_code::NON_INTERACTIVE_ENV)
+                .ok()
+                .as_deref(),
+        );
+    if json && !command.supports_json() {
+        return Err(UsageError("This command does not support --json").into());
+    }
+
+    match command {
+        Command::Account(cmd) => cmd.handle(json, non_interactive).await,
+        Command::Bridge(cmd) => cmd.handle().await,
+        Command::Connect { wait } => tunnel_state::connect(wait).await,
+        Command::Reconnect { wait, overrides } => tunnel_state::reconnect(wait, overrides).await,
+        Command::Disconnect { wait } => tunnel_state::disconnect(wait).await,
+        Command::AutoConnect(cmd) => cmd.handle().await,
+        Command::AutoDisconnect(cmd) => cmd.handle().await,
+        Command::BetaProgram(cmd) => cmd.handle().await,
+        Command::LockdownMode(cmd) => cmd.handle().await,
+        Command::Dns(cmd) => cmd.handle(json).await,
+        Command::Lan(cmd) => cmd.handle().await,
+        Command::BypassRoutes(cmd) => cmd.handle().await,
+        Command::LocalProxy(cmd) => cmd.handle().await,
+        Command::Hooks(cmd) => cmd.handle().await,
+        #[cfg(target_os = "macos")]
+        Command::CoexistenceMode(cmd) => cmd.handle().await,
+        Command::Obfuscation(cmd) => cmd.handle().await,
+        Command::ApiAccess(cmd) => cmd.handle(json).await,
+        Command::Version { cmd: None } => version::print().await,
+        Command::Version { cmd: Some(cmd) } => cmd.handle().await,
+        Command::FactoryReset(args) => reset::handle(args, non_interactive).await,
+        Command::Settings(cmd) => cmd.handle().await,
+        Command::Profile(cmd) => cmd.handle().await,
+        Command::Relay(cmd) => cmd.handle(json, non_interactive).await,
+        Command::Tunnel(cmd) => cmd.handle().await,
         #[cfg(any(target_os = "windows", target_os = "linux"))]
-        Cli::SplitTunnel(cmd) => cmd.handle().await,
-        Cli::Status { cmd, args } => status::handle(cmd, args).await,
-        Cli::CustomList(cmd) => cmd.handle().await,
+        Command::SplitTunnel(cmd) => cmd.handle().await,
+        Command::Status { cmd, args } => status::handle(cmd, args, json).await,
+        Command::Check { allow_disconnected } => check::handle(allow_disconnected, json).await,
+        Command::Trust(cmd) => cmd.handle().await,
+        Command::CustomList(cmd) => cmd.handle().await,
+        Command::Debug(cmd) => cmd.handle().await,
+        Command::Complete { words } => complete::handle(words).await,
 
         #[cfg(all(unix, not(target_os = "android")))]
-        Cli::ShellCompletions { shell, dir } => {
+        Command::ShellCompletions { shell, dir } => {
             use clap::CommandFactory;
 
             // FIXME: The shell completions include hidden commands (including "shell-completions")
             println!("Generating shell completions to {}", dir.display());
-            clap_complete::generate_to(shell, &mut Cli::command(), BIN_NAME, dir)
+            complete::write_script(shell, &mut Cli::command(), BIN_NAME, &dir)
                 .map_err(|_| anyhow!("Failed to generate shell completions"))?;
             Ok(())
         }
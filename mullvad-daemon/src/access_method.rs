@@ -1,9 +1,11 @@
 use crate::{
+    api,
     settings::{self, MadeChanges},
     Daemon, EventListener,
 };
+use futures::Future;
 use mullvad_types::{
-    access_method::{self, AccessMethod, AccessMethodSetting},
+    access_method::{self, share, AccessMethod, AccessMethodSetting, AccessMethodTestReport},
     settings::Settings,
 };
 
@@ -28,6 +30,12 @@ pub enum Error {
     /// Access methods settings error
     #[error(display = "Settings error")]
     Settings(#[error(source)] settings::Error),
+    /// Can not import a shared access method
+    #[error(display = "Cannot import access method")]
+    Import(#[error(source, no_from)] share::Error),
+    /// Can not export an access method
+    #[error(display = "Cannot export access method")]
+    Export(#[error(source, no_from)] share::Error),
 }
 
 /// A tiny datastructure used for signaling whether the daemon should force a
@@ -67,6 +75,37 @@ where
             .map_err(Error::Settings)
     }
 
+    /// Add an access method which has been shared as a string by [`export_access_method`]. The
+    /// new [`AccessMethodSetting`] is disabled and marked as imported.
+    ///
+    /// [`export_access_method`]: Self::export_access_method
+    pub async fn import_access_method(
+        &mut self,
+        encoded: &str,
+    ) -> Result<access_method::Id, Error> {
+        let access_method_setting = share::decode(encoded)
+            .map_err(Error::Import)?
+            .into_setting();
+        let id = access_method_setting.get_id();
+        self.settings
+            .update(|settings| settings.api_access_methods.append(access_method_setting))
+            .await
+            .map(|did_change| self.notify_on_change(did_change))
+            .map(|_| id)
+            .map_err(Error::Settings)
+    }
+
+    /// Encode a custom [`AccessMethodSetting`] as a string which can be imported by other
+    /// users. The string contains any credentials of the access method.
+    pub fn export_access_method(&self, access_method: access_method::Id) -> Result<String, Error> {
+        let access_method_setting = self
+            .settings
+            .api_access_methods
+            .find(&access_method)
+            .ok_or(Error::NoSuchMethod(access_method))?;
+        share::encode(access_method_setting).map_err(Error::Export)
+    }
+
     /// Remove a [`AccessMethodSetting`] from the daemon's saved settings.
     ///
     /// If the [`AccessMethodSetting`] which is currently in use happens to be
@@ -158,6 +197,36 @@ where
             .await
     }
 
+    /// Return a future which tests how long each stage of reaching the API takes using an
+    /// [`AccessMethodSetting`], regardless of whether it is enabled. The access method in use is
+    /// not changed, but the firewall allows traffic to the endpoint under test while the test
+    /// runs.
+    pub fn test_api_access_method(
+        &self,
+        access_method: access_method::Id,
+    ) -> Result<impl Future<Output = AccessMethodTestReport> + Send + 'static, Error> {
+        let access_method = self
+            .settings
+            .api_access_methods
+            .find(&access_method)
+            .ok_or(Error::NoSuchMethod(access_method))?;
+        let connection_mode =
+            api::resolve_connection_mode(&self.relay_selector, access_method.access_method.clone());
+        let test = self
+            .api_runtime
+            .test_connection_mode(connection_mode.clone());
+        let endpoint_updater = self.endpoint_updater.clone();
+        let address_cache = self.api_runtime.address_cache.clone();
+
+        Ok(async move {
+            let api_address = address_cache.get_address().await;
+            let endpoint = connection_mode.get_endpoint().unwrap_or(api_address);
+            endpoint_updater
+                .with_endpoint_allowed(endpoint, api_address, test)
+                .await
+        })
+    }
+
     /// Return the [`AccessMethodSetting`] which is currently used to access the
     /// Mullvad API.
     pub fn get_current_access_method(&self) -> Result<AccessMethodSetting, Error> {
@@ -178,26 +247,37 @@ where
             })
     }
 
+    /// Use the access methods in the settings after all of them have been replaced, and select
+    /// the API endpoint again.
+    pub async fn reload_access_methods(&mut self) -> Result<(), Error> {
+        self.update_connection_modes();
+        self.force_api_endpoint_rotation().await
+    }
+
     /// If settings were changed due to an update, notify all listeners.
     fn notify_on_change(&mut self, settings_changed: MadeChanges) -> &mut Self {
         if settings_changed {
             self.event_listener
                 .notify_settings(self.settings.to_settings());
-
-            let mut connection_modes = self.connection_modes.lock().unwrap();
-            connection_modes.update_access_methods(
-                self.settings
-                    .api_access_methods
-                    .access_method_settings
-                    .iter()
-                    .filter(|api_access_method| api_access_method.enabled())
-                    .cloned()
-                    .collect(),
-            )
+            self.update_connection_modes();
         };
         self
     }
 
+    /// Make the enabled access methods in the settings available for accessing the API.
+    fn update_connection_modes(&self) {
+        let mut connection_modes = self.connection_modes.lock().unwrap();
+        connection_modes.update_access_methods(
+            self.settings
+                .api_access_methods
+                .access_method_settings
+                .iter()
+                .filter(|api_access_method| api_access_method.enabled())
+                .cloned()
+                .collect(),
+        )
+    }
+
     /// The semantics of the [`Command`] datastructure.
     async fn process_command(&mut self, command: Command) -> Result<(), Error> {
         match command {
@@ -0,0 +1,289 @@
+//! Selection of the API access method to use next.
+//!
+//! The outcome of every API request is recorded for the access method that was in use. A method
+//! which fails is put in a cooldown, which grows exponentially with the number of consecutive
+//! failures, before it is selected again. This module does not keep track of time itself, so
+//! that the selection logic is easy to test.
+use mullvad_types::access_method::{
+    AccessMethod, AccessMethodSetting, AccessMethodStats, BuiltInAccessMethod, Id,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+/// Cooldown after the first failure of an access method.
+const INITIAL_COOLDOWN: Duration = Duration::from_secs(10);
+/// Upper bound on the cooldown of an access method.
+const MAX_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+pub struct AccessMethodSelector {
+    access_methods: Vec<AccessMethodSetting>,
+    /// Set by the user, and returned by the next call to `next` regardless of its cooldown.
+    next: Option<AccessMethodSetting>,
+    current: AccessMethodSetting,
+    health: HashMap<Id, Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    successes: u32,
+    failures: u32,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    last_failure: Option<SystemTime>,
+    last_success: Option<SystemTime>,
+    last_used: Option<SystemTime>,
+    cooldown_until: Option<SystemTime>,
+}
+
+impl Health {
+    fn is_cooling_down(&self, now: SystemTime) -> bool {
+        self.cooldown_until
+            .map(|cooldown_until| now < cooldown_until)
+            .unwrap_or(false)
+    }
+}
+
+impl AccessMethodSelector {
+    /// Create a selector which picks among `access_methods`, starting with the first one. If
+    /// there are none, [`BuiltInAccessMethod::Direct`] is used.
+    pub fn new(access_methods: Vec<AccessMethodSetting>) -> Self {
+        let current = access_methods.first().cloned().unwrap_or_else(direct);
+        Self {
+            access_methods,
+            next: None,
+            current,
+            health: HashMap::new(),
+        }
+    }
+
+    /// Set the next [`AccessMethodSetting`] to be returned by [`Self::next`], even if it is
+    /// cooling down.
+    pub fn set_access_method(&mut self, next: AccessMethodSetting) {
+        self.next = Some(next);
+    }
+
+    /// Update the collection of [`AccessMethodSetting`]s to pick from. The health of the methods
+    /// that are still present is kept.
+    pub fn update_access_methods(&mut self, access_methods: Vec<AccessMethodSetting>) {
+        self.health.retain(|id, _| {
+            access_methods
+                .iter()
+                .any(|access_method| access_method.get_id() == *id)
+        });
+        self.access_methods = access_methods;
+    }
+
+    /// Look at the currently active [`AccessMethodSetting`].
+    pub fn peek(&self) -> AccessMethodSetting {
+        self.current.clone()
+    }
+
+    /// Select the access method to use next. This is the first method after the current one
+    /// which is not cooling down. If all of them are, the one whose cooldown ends first is used.
+    pub fn next(&mut self, now: SystemTime) -> AccessMethodSetting {
+        let next = self.next.take().unwrap_or_else(|| self.select(now));
+        self.health.entry(next.get_id()).or_default().last_used = Some(now);
+        self.current = next.clone();
+        next
+    }
+
+    fn select(&self, now: SystemTime) -> AccessMethodSetting {
+        if self.access_methods.is_empty() {
+            return direct();
+        }
+        let current_index = self
+            .access_methods
+            .iter()
+            .position(|access_method| access_method.get_id() == self.current.get_id());
+        let start = current_index.map(|index| index + 1).unwrap_or(0);
+        let candidates = || {
+            self.access_methods
+                .iter()
+                .cycle()
+                .skip(start)
+                .take(self.access_methods.len())
+        };
+
+        candidates()
+            .find(|access_method| {
+                self.health
+                    .get(&access_method.get_id())
+                    .map(|health| !health.is_cooling_down(now))
+                    .unwrap_or(true)
+            })
+            .or_else(|| {
+                candidates().min_by_key(|access_method| {
+                    self.health
+                        .get(&access_method.get_id())
+                        .and_then(|health| health.cooldown_until)
+                })
+            })
+            .cloned()
+            .unwrap_or_else(direct)
+    }
+
+    /// Record that the current access method reached the API.
+    pub fn record_success(&mut self, now: SystemTime) {
+        let health = self.health.entry(self.current.get_id()).or_default();
+        health.successes = health.successes.saturating_add(1);
+        health.consecutive_failures = 0;
+        health.last_success = Some(now);
+        health.cooldown_until = None;
+    }
+
+    /// Record that the current access method failed to reach the API, and put it in a cooldown.
+    pub fn record_failure(&mut self, error: String, now: SystemTime) {
+        let health = self.health.entry(self.current.get_id()).or_default();
+        health.failures = health.failures.saturating_add(1);
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+        health.last_error = Some(error);
+        health.last_failure = Some(now);
+        health.cooldown_until = Some(now + cooldown(health.consecutive_failures));
+    }
+
+    /// Return the health of every configured access method, in order.
+    pub fn stats(&self, now: SystemTime) -> Vec<AccessMethodStats> {
+        self.access_methods
+            .iter()
+            .map(|access_method| {
+                let id = access_method.get_id();
+                match self.health.get(&id) {
+                    Some(health) => AccessMethodStats {
+                        id,
+                        successes: health.successes,
+                        failures: health.failures,
+                        last_error: health.last_error.clone(),
+                        last_failure: health.last_failure,
+                        last_success: health.last_success,
+                        last_used: health.last_used,
+                        cooldown_until: health
+                            .cooldown_until
+                            .filter(|_| health.is_cooling_down(now)),
+                    },
+                    None => AccessMethodStats::new(id),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Return the cooldown after `consecutive_failures` failures in a row, which doubles for every
+/// failure up to [`MAX_COOLDOWN`].
+fn cooldown(consecutive_failures: u32) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(16);
+    INITIAL_COOLDOWN
+        .saturating_mul(1 << exponent)
+        .min(MAX_COOLDOWN)
+}
+
+fn direct() -> AccessMethodSetting {
+    let direct = BuiltInAccessMethod::Direct;
+    AccessMethodSetting::new(direct.canonical_name(), true, AccessMethod::from(direct))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn access_methods() -> Vec<AccessMethodSetting> {
+        [BuiltInAccessMethod::Direct, BuiltInAccessMethod::Bridge]
+            .into_iter()
+            .map(|method| {
+                AccessMethodSetting::new(method.canonical_name(), true, AccessMethod::from(method))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cooldown_is_exponential_and_capped() {
+        assert_eq!(cooldown(1), INITIAL_COOLDOWN);
+        assert_eq!(cooldown(2), INITIAL_COOLDOWN * 2);
+        assert_eq!(cooldown(3), INITIAL_COOLDOWN * 4);
+        assert_eq!(cooldown(100), MAX_COOLDOWN);
+    }
+
+    #[test]
+    fn test_rotate_in_order() {
+        let methods = access_methods();
+        let mut selector = AccessMethodSelector::new(methods.clone());
+        let now = SystemTime::UNIX_EPOCH;
+
+        assert_eq!(selector.peek(), methods[0]);
+        assert_eq!(selector.next(now), methods[1]);
+        assert_eq!(selector.next(now), methods[0]);
+    }
+
+    #[test]
+    fn test_skip_methods_in_cooldown() {
+        let methods = access_methods();
+        let mut selector = AccessMethodSelector::new(methods.clone());
+        let now = SystemTime::UNIX_EPOCH;
+
+        // The bridge fails, so rotating from direct should not pick it again until its cooldown
+        // has passed.
+        assert_eq!(selector.next(now), methods[1]);
+        selector.record_failure("timed out".to_owned(), now);
+        assert_eq!(selector.next(now), methods[0]);
+        assert_eq!(selector.next(now), methods[0]);
+
+        let later = now + INITIAL_COOLDOWN;
+        assert_eq!(selector.next(later), methods[1]);
+    }
+
+    #[test]
+    fn test_all_methods_in_cooldown() {
+        let methods = access_methods();
+        let mut selector = AccessMethodSelector::new(methods.clone());
+        let now = SystemTime::UNIX_EPOCH;
+
+        // Direct fails twice, so its cooldown ends after the one of the bridge.
+        selector.record_failure("timed out".to_owned(), now);
+        selector.record_failure("timed out".to_owned(), now);
+        assert_eq!(selector.next(now), methods[1]);
+        selector.record_failure("timed out".to_owned(), now);
+
+        assert_eq!(selector.next(now), methods[1]);
+    }
+
+    #[test]
+    fn test_forced_method_ignores_cooldown() {
+        let methods = access_methods();
+        let mut selector = AccessMethodSelector::new(methods.clone());
+        let now = SystemTime::UNIX_EPOCH;
+
+        selector.record_failure("timed out".to_owned(), now);
+        selector.set_access_method(methods[0].clone());
+        assert_eq!(selector.next(now), methods[0]);
+    }
+
+    #[test]
+    fn test_stats() {
+        let methods = access_methods();
+        let mut selector = AccessMethodSelector::new(methods.clone());
+        let now = SystemTime::UNIX_EPOCH;
+
+        selector.record_success(now);
+        selector.record_failure("timed out".to_owned(), now);
+        let stats = selector.stats(now);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].id, methods[0].get_id());
+        assert_eq!(stats[0].successes, 1);
+        assert_eq!(stats[0].failures, 1);
+        assert_eq!(stats[0].last_error.as_deref(), Some("timed out"));
+        assert_eq!(stats[0].last_success, Some(now));
+        assert_eq!(stats[0].last_failure, Some(now));
+        assert_eq!(stats[0].cooldown_until, Some(now + INITIAL_COOLDOWN));
+        assert_eq!(stats[1], AccessMethodStats::new(methods[1].get_id()));
+
+        // A success ends the cooldown and resets its length.
+        selector.record_success(now);
+        selector.record_failure("timed out".to_owned(), now);
+        assert_eq!(
+            selector.stats(now)[0].cooldown_until,
+            Some(now + INITIAL_COOLDOWN)
+        );
+        assert_eq!(selector.stats(now + MAX_COOLDOWN)[0].cooldown_until, None);
+    }
+}
@@ -0,0 +1,228 @@
+//! Tracks when the account runs out of time. Warnings are emitted as the expiry draws closer, at
+//! the thresholds in the settings, and the daemon is told when the expiry has passed so that it
+//! can block rather than let the tunnel fail to authenticate over and over.
+
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Something that the daemon should act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryEvent {
+    /// The account expires within `threshold`.
+    Warning {
+        expiry: DateTime<Utc>,
+        threshold: Duration,
+    },
+    /// The account has run out of time.
+    Expired,
+}
+
+#[derive(Debug)]
+pub struct ExpiryTracker {
+    /// Longest first.
+    thresholds: Vec<Duration>,
+    expiry: Option<DateTime<Utc>>,
+    /// Number of thresholds, counting from the longest, that have been passed and reported.
+    reported_thresholds: usize,
+    reported_expired: bool,
+}
+
+impl ExpiryTracker {
+    pub fn new(thresholds: &[Duration]) -> Self {
+        let mut tracker = Self {
+            thresholds: vec![],
+            expiry: None,
+            reported_thresholds: 0,
+            reported_expired: false,
+        };
+        tracker.set_thresholds(thresholds);
+        tracker
+    }
+
+    /// Changes the thresholds. Thresholds that have already passed are not reported.
+    pub fn set_thresholds(&mut self, thresholds: &[Duration]) {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_unstable_by(|a, b| b.cmp(a));
+        thresholds.dedup();
+        self.thresholds = thresholds;
+        self.reported_thresholds = self.passed_thresholds(Utc::now());
+    }
+
+    /// Records the expiry of the account that is logged in. The thresholds that pass after `now`
+    /// are reported again if time was added, e.g. by redeeming a voucher.
+    pub fn set_expiry(&mut self, expiry: DateTime<Utc>, now: DateTime<Utc>) {
+        if self.expiry == Some(expiry) {
+            return;
+        }
+        self.expiry = Some(expiry);
+        if expiry > now {
+            self.reported_expired = false;
+        }
+        // The thresholds that have already passed for the new expiry are summed up in a single
+        // warning, for the shortest of them
+        self.reported_thresholds = self.passed_thresholds(now).saturating_sub(1);
+    }
+
+    /// Forgets the expiry, e.g. because the user logged out.
+    pub fn clear(&mut self) {
+        self.expiry = None;
+        self.reported_thresholds = 0;
+        self.reported_expired = false;
+    }
+
+    /// Returns when [`Self::poll`] should be called next.
+    pub fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        let expiry = self.expiry?;
+        match self.thresholds.get(self.reported_thresholds) {
+            Some(threshold) => Some(warning_time(expiry, *threshold)),
+            None if !self.reported_expired => Some(expiry),
+            None => None,
+        }
+    }
+
+    /// Returns what has happened since the last call, if anything.
+    pub fn poll(&mut self, now: DateTime<Utc>) -> Option<ExpiryEvent> {
+        let expiry = self.expiry?;
+        if now >= expiry {
+            self.reported_thresholds = self.thresholds.len();
+            if self.reported_expired {
+                return None;
+            }
+            self.reported_expired = true;
+            return Some(ExpiryEvent::Expired);
+        }
+
+        let passed = self.passed_thresholds(now);
+        if passed <= self.reported_thresholds {
+            return None;
+        }
+        self.reported_thresholds = passed;
+        Some(ExpiryEvent::Warning {
+            expiry,
+            threshold: self.thresholds[passed - 1],
+        })
+    }
+
+    /// Returns the number of thresholds that have been passed at `now`.
+    fn passed_thresholds(&self, now: DateTime<Utc>) -> usize {
+        let Some(expiry) = self.expiry else {
+            return 0;
+        };
+        self.thresholds
+            .iter()
+            .take_while(|threshold| warning_time(expiry, **threshold) <= now)
+            .count()
+    }
+}
+
+/// Returns when the warning for `threshold` is due.
+fn warning_time(expiry: DateTime<Utc>, threshold: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(threshold)
+        .ok()
+        .and_then(|threshold| expiry.checked_sub_signed(threshold))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn tracker() -> ExpiryTracker {
+        ExpiryTracker::new(&[HOUR, 3 * DAY, DAY])
+    }
+
+    fn at(expiry: DateTime<Utc>, before: Duration) -> DateTime<Utc> {
+        warning_time(expiry, before)
+    }
+
+    fn after(time: DateTime<Utc>, duration: Duration) -> DateTime<Utc> {
+        time + chrono::Duration::from_std(duration).unwrap()
+    }
+
+    #[test]
+    fn test_warnings_are_emitted_in_order() {
+        let now = Utc::now();
+        let expiry = after(now, 7 * DAY);
+        let mut tracker = tracker();
+        tracker.set_expiry(expiry, now);
+
+        assert_eq!(tracker.poll(now), None);
+        assert_eq!(tracker.next_deadline(), Some(at(expiry, 3 * DAY)));
+
+        for threshold in [3 * DAY, DAY, HOUR] {
+            let deadline = tracker.next_deadline().unwrap();
+            assert_eq!(deadline, at(expiry, threshold));
+            assert_eq!(
+                tracker.poll(deadline),
+                Some(ExpiryEvent::Warning { expiry, threshold })
+            );
+            assert_eq!(tracker.poll(deadline), None);
+        }
+
+        assert_eq!(tracker.next_deadline(), Some(expiry));
+        assert_eq!(tracker.poll(expiry), Some(ExpiryEvent::Expired));
+        assert_eq!(tracker.poll(expiry), None);
+        assert_eq!(tracker.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_passed_thresholds_are_summed_up() {
+        let now = Utc::now();
+        let expiry = after(now, 2 * HOUR);
+        let mut tracker = tracker();
+        tracker.set_expiry(expiry, now);
+
+        // Only the shortest threshold that has passed is reported
+        assert_eq!(
+            tracker.poll(now),
+            Some(ExpiryEvent::Warning {
+                expiry,
+                threshold: DAY
+            })
+        );
+        assert_eq!(tracker.next_deadline(), Some(at(expiry, HOUR)));
+    }
+
+    #[test]
+    fn test_expiry_while_connected() {
+        let now = Utc::now();
+        let expiry = after(now, HOUR / 2);
+        let mut tracker = tracker();
+        tracker.set_expiry(expiry, now);
+        assert!(matches!(
+            tracker.poll(now),
+            Some(ExpiryEvent::Warning { .. })
+        ));
+
+        // The expiry passes without a new refresh of the account data
+        assert_eq!(tracker.next_deadline(), Some(expiry));
+        let later = expiry + chrono::Duration::seconds(1);
+        assert_eq!(tracker.poll(later), Some(ExpiryEvent::Expired));
+
+        // A refresh that still shows the account as expired does not block again
+        tracker.set_expiry(expiry, later);
+        assert_eq!(tracker.poll(later), None);
+
+        // Time is added, and the warnings start over for the new expiry
+        let new_expiry = after(later, 30 * DAY);
+        tracker.set_expiry(new_expiry, later);
+        assert_eq!(tracker.poll(later), None);
+        assert_eq!(tracker.next_deadline(), Some(at(new_expiry, 3 * DAY)));
+        assert_eq!(tracker.poll(new_expiry), Some(ExpiryEvent::Expired));
+    }
+
+    #[test]
+    fn test_expired_when_logging_in() {
+        let now = Utc::now();
+        let mut tracker = tracker();
+        tracker.set_expiry(now - chrono::Duration::days(1), now);
+        assert_eq!(tracker.poll(now), Some(ExpiryEvent::Expired));
+
+        tracker.clear();
+        assert_eq!(tracker.poll(now), None);
+        assert_eq!(tracker.next_deadline(), None);
+    }
+}
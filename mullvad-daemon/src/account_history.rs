@@ -1,6 +1,7 @@
-use mullvad_types::account::AccountToken;
+use mullvad_types::account::{AccountToken, SavedAccount};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
 use talpid_types::ErrorExt;
 use tokio::{
@@ -28,9 +29,19 @@ pub enum Error {
 
 static ACCOUNT_HISTORY_FILE: &str = "account-history.json";
 
+/// The number of accounts to remember. The least recently used account is forgotten first.
+const MAX_SAVED_ACCOUNTS: usize = 10;
+
 pub struct AccountHistory {
     file: io::BufWriter<fs::File>,
-    token: Option<AccountToken>,
+    /// The most recently used account comes first.
+    accounts: Vec<SavedAccount>,
+}
+
+/// Contents of the history file. Older versions only contain the last used account number.
+#[derive(Serialize, Deserialize)]
+struct HistoryFile {
+    saved_accounts: Vec<SavedAccount>,
 }
 
 static ACCOUNT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[0-9]+$").unwrap());
@@ -63,18 +74,23 @@ impl AccountHistory {
             .map_err(Error::Read)?;
 
         let mut buffer = String::new();
-        let (token, should_save): (Option<AccountToken>, bool) =
-            match reader.read_to_string(&mut buffer).await {
-                Ok(_) if ACCOUNT_REGEX.is_match(&buffer) => (Some(buffer), false),
-                Ok(0) => (current_token, true),
-                Ok(_) | Err(_) => {
+        let saved_accounts = match reader.read_to_string(&mut buffer).await {
+            Ok(0) => None,
+            result => {
+                let accounts = result.ok().and_then(|_| parse_history(&buffer));
+                if accounts.is_none() {
                     log::warn!("Failed to parse account history");
-                    (current_token, true)
                 }
-            };
+                accounts
+            }
+        };
+        let should_save = saved_accounts.is_none();
+        let accounts = saved_accounts
+            .unwrap_or_else(|| current_token.map(SavedAccount::new).into_iter().collect());
+        log::debug!("Loaded {} saved accounts", accounts.len());
 
         let file = io::BufWriter::new(reader.into_inner());
-        let mut history = AccountHistory { file, token };
+        let mut history = AccountHistory { file, accounts };
         if should_save {
             if let Err(error) = history.save_to_disk().await {
                 log::error!(
@@ -86,20 +102,94 @@ impl AccountHistory {
         Ok(history)
     }
 
-    /// Gets the account token in the history
+    /// Gets the account token that was used last
     pub fn get(&self) -> Option<AccountToken> {
-        self.token.clone()
+        self.accounts
+            .first()
+            .map(|account| account.account_token.clone())
+    }
+
+    /// Returns the saved accounts, the most recently used first
+    pub fn saved_accounts(&self) -> &[SavedAccount] {
+        &self.accounts
+    }
+
+    /// Finds a saved account by its label, ignoring case, or by its account number
+    pub fn find(&self, label_or_token: &str) -> Option<&SavedAccount> {
+        self.accounts
+            .iter()
+            .find(|account| {
+                account
+                    .label
+                    .as_ref()
+                    .is_some_and(|label| label.eq_ignore_ascii_case(label_or_token))
+            })
+            .or_else(|| {
+                self.accounts
+                    .iter()
+                    .find(|account| account.account_token == label_or_token)
+            })
     }
 
-    /// Replace the account token in the history
-    pub async fn set(&mut self, new_entry: AccountToken) -> Result<()> {
-        self.token = Some(new_entry);
+    /// Makes `token` the most recently used account, and remembers the device that it is used
+    /// with. The label is kept if the account was already saved
+    pub async fn set(&mut self, token: AccountToken, device_name: Option<String>) -> Result<()> {
+        self.record_use(token, device_name);
         self.save_to_disk().await
     }
 
-    /// Remove account history
+    fn record_use(&mut self, token: AccountToken, device_name: Option<String>) {
+        let mut account = match self
+            .accounts
+            .iter()
+            .position(|account| account.account_token == token)
+        {
+            Some(index) => self.accounts.remove(index),
+            None => SavedAccount::new(token),
+        };
+        if device_name.is_some() {
+            account.device_name = device_name;
+        }
+        self.accounts.insert(0, account);
+        self.accounts.truncate(MAX_SAVED_ACCOUNTS);
+    }
+
+    /// Sets or removes the label of a saved account. Labels are unique, so any other account with
+    /// the same label loses it. Returns false if the account is not saved
+    pub async fn set_label(&mut self, token: &str, label: Option<String>) -> Result<bool> {
+        if !self.apply_label(token, label) {
+            return Ok(false);
+        }
+        self.save_to_disk().await?;
+        Ok(true)
+    }
+
+    fn apply_label(&mut self, token: &str, label: Option<String>) -> bool {
+        if !self
+            .accounts
+            .iter()
+            .any(|account| account.account_token == token)
+        {
+            return false;
+        }
+        let label = label
+            .map(|label| label.trim().to_owned())
+            .filter(|label| !label.is_empty());
+        for account in &mut self.accounts {
+            if account.account_token == token {
+                account.label = label.clone();
+            } else if let (Some(other), Some(label)) = (&account.label, &label) {
+                if other.eq_ignore_ascii_case(label) {
+                    account.label = None;
+                }
+            }
+        }
+        true
+    }
+
+    /// Remove all saved accounts
     pub async fn clear(&mut self) -> Result<()> {
-        self.token = None;
+        self.accounts.clear();
         self.save_to_disk().await
     }
 
@@ -109,13 +199,159 @@ impl AccountHistory {
             .seek(io::SeekFrom::Start(0))
             .await
             .map_err(Error::Write)?;
-        if let Some(ref token) = self.token {
-            self.file
-                .write_all(token.as_bytes())
-                .await
-                .map_err(Error::Write)?;
+        if !self.accounts.is_empty() {
+            let contents = serde_json::to_vec(&HistoryFile {
+                saved_accounts: self.accounts.clone(),
+            })
+            .map_err(Error::Serialize)?;
+            self.file.write_all(&contents).await.map_err(Error::Write)?;
         }
         self.file.flush().await.map_err(Error::Write)?;
         self.file.get_mut().sync_all().await.map_err(Error::Write)
     }
 }
+
+/// Parses the history file, which is either a single account number or a list of saved accounts.
+fn parse_history(contents: &str) -> Option<Vec<SavedAccount>> {
+    if ACCOUNT_REGEX.is_match(contents) {
+        return Some(vec![SavedAccount::new(contents.to_owned())]);
+    }
+    serde_json::from_str::<HistoryFile>(contents)
+        .ok()
+        .map(|file| file.saved_accounts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PERSONAL: &str = "1234123412341234";
+    const WORK: &str = "5678567856785678";
+
+    async fn open_history(dir: &Path) -> AccountHistory {
+        AccountHistory::new(dir, None).await.unwrap()
+    }
+
+    #[test]
+    fn test_parse_history() {
+        assert_eq!(
+            parse_history(PERSONAL),
+            Some(vec![SavedAccount::new(PERSONAL.to_owned())])
+        );
+        assert_eq!(
+            parse_history(
+                r#"{"saved_accounts": [{
+                    "account_token": "1234123412341234",
+                    "label": "personal",
+                    "device_name": "happy seal"
+                }]}"#
+            ),
+            Some(vec![SavedAccount {
+                account_token: PERSONAL.to_owned(),
+                label: Some("personal".to_owned()),
+                device_name: Some("happy seal".to_owned()),
+            }])
+        );
+        assert_eq!(parse_history(r#"{"accounts": ["1234"]}"#), None);
+    }
+
+    #[tokio::test]
+    async fn test_switch_between_saved_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = open_history(dir.path()).await;
+
+        history
+            .set(PERSONAL.to_owned(), Some("happy seal".to_owned()))
+            .await
+            .unwrap();
+        assert!(history
+            .set_label(PERSONAL, Some("Personal".to_owned()))
+            .await
+            .unwrap());
+        history
+            .set(WORK.to_owned(), Some("busy otter".to_owned()))
+            .await
+            .unwrap();
+        assert!(history
+            .set_label(WORK, Some("work".to_owned()))
+            .await
+            .unwrap());
+
+        // Switching back keeps the label, and a new device replaces the old one
+        let personal = history.find("personal").unwrap().account_token.clone();
+        assert_eq!(personal, PERSONAL);
+        history
+            .set(personal, Some("quiet fox".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(history.get().as_deref(), Some(PERSONAL));
+        assert_eq!(
+            history.saved_accounts()[0],
+            SavedAccount {
+                account_token: PERSONAL.to_owned(),
+                label: Some("Personal".to_owned()),
+                device_name: Some("quiet fox".to_owned()),
+            }
+        );
+        assert_eq!(history.find(WORK).unwrap().label.as_deref(), Some("work"));
+        assert!(history.find("home").is_none());
+
+        // The accounts are kept when the daemon restarts
+        let saved = history.saved_accounts().to_vec();
+        drop(history);
+        assert_eq!(open_history(dir.path()).await.saved_accounts(), saved);
+    }
+
+    #[tokio::test]
+    async fn test_labels() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = open_history(dir.path()).await;
+        history.set(PERSONAL.to_owned(), None).await.unwrap();
+        history.set(WORK.to_owned(), None).await.unwrap();
+
+        assert!(!history
+            .set_label("1111222233334444", Some("other".to_owned()))
+            .await
+            .unwrap());
+        history
+            .set_label(PERSONAL, Some("work".to_owned()))
+            .await
+            .unwrap();
+        history
+            .set_label(WORK, Some(" Work ".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(history.find(PERSONAL).unwrap().label, None);
+        assert_eq!(history.find("work").unwrap().account_token, WORK);
+
+        history.set_label(WORK, Some(" ".to_owned())).await.unwrap();
+        assert_eq!(history.find(WORK).unwrap().label, None);
+    }
+
+    #[tokio::test]
+    async fn test_forget_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = open_history(dir.path()).await;
+        for i in 0..=MAX_SAVED_ACCOUNTS {
+            history.set(format!("{i:016}"), None).await.unwrap();
+        }
+        assert_eq!(history.saved_accounts().len(), MAX_SAVED_ACCOUNTS);
+        assert!(history.find(&format!("{:016}", 0)).is_none());
+
+        history.clear().await.unwrap();
+        assert_eq!(history.get(), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = open_history(dir.path()).await;
+        history.set(PERSONAL.to_owned(), None).await.unwrap();
+
+        let metadata = std::fs::metadata(dir.path().join(ACCOUNT_HISTORY_FILE)).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::{
+    access_method_selector::AccessMethodSelector, api_connectivity::ApiConnectivityMonitor,
+    recent_events::RecentEvents,
+};
 #[cfg(target_os = "android")]
 use crate::{DaemonCommand, DaemonEventSender};
 use futures::{
@@ -6,23 +10,32 @@ use futures::{
 };
 use mullvad_api::{
     availability::ApiAvailabilityHandle,
-    proxy::{ApiConnectionMode, ProxyConfig},
-    ApiEndpointUpdateCallback,
+    nat64,
+    proxy::{ApiConnectionMode, ConnectionModeOutcome, ProxyConfig},
+    rest::RequestServiceHandle,
+    AddressCache, ApiEndpointUpdateCallback,
 };
 use mullvad_relay_selector::RelaySelector;
-use mullvad_types::access_method::{AccessMethod, AccessMethodSetting, BuiltInAccessMethod};
+use mullvad_types::{
+    access_method::{AccessMethod, AccessMethodSetting, BuiltInAccessMethod},
+    recent_events::EventKind,
+    settings::Settings,
+    states::TunnelState,
+    wireguard::AssociatedAddresses,
+};
 use std::{
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
     pin::Pin,
     sync::{Arc, Mutex, Weak},
     task::Poll,
+    time::SystemTime,
 };
 #[cfg(target_os = "android")]
 use talpid_core::mpsc::Sender;
 use talpid_core::tunnel_state_machine::TunnelCommand;
 use talpid_types::{
-    net::{openvpn::ProxySettings, AllowedEndpoint, Endpoint, TransportProtocol},
+    net::{openvpn::ProxySettings, AllowedEndpoint, Endpoint, TransportProtocol, TunnelType},
     ErrorExt,
 };
 
@@ -34,13 +47,14 @@ use talpid_types::{
 /// or via any supported custom proxy protocol ([`api_access_methods::ObfuscationProtocol`]).
 ///
 /// The strategy for determining the next [`ApiConnectionMode`] is handled by
-/// [`ConnectionModesIterator`].
+/// [`AccessMethodSelector`].
 pub struct ApiConnectionModeProvider {
     cache_dir: PathBuf,
     /// Used for selecting a Bridge when the `Mullvad Bridges` access method is used.
     relay_selector: RelaySelector,
     current_task: Option<Pin<Box<dyn Future<Output = ApiConnectionMode> + Send>>>,
-    connection_modes: Arc<Mutex<ConnectionModesIterator>>,
+    connection_modes: Arc<Mutex<AccessMethodSelector>>,
+    recent_events: RecentEvents,
 }
 
 impl Stream for ApiConnectionModeProvider {
@@ -83,21 +97,23 @@ impl ApiConnectionModeProvider {
         cache_dir: PathBuf,
         relay_selector: RelaySelector,
         connection_modes: Vec<AccessMethodSetting>,
+        recent_events: RecentEvents,
     ) -> Self {
-        let connection_modes_iterator = ConnectionModesIterator::new(connection_modes);
+        let selector = AccessMethodSelector::new(connection_modes);
         Self {
             cache_dir,
             relay_selector,
             current_task: None,
-            connection_modes: Arc::new(Mutex::new(connection_modes_iterator)),
+            connection_modes: Arc::new(Mutex::new(selector)),
+            recent_events,
         }
     }
 
-    /// Return a pointer to the underlying iterator over [`AccessMethod`].
-    /// Having access to this iterator allow you to influence , e.g. by calling
-    /// [`ConnectionModesIterator::set_access_method()`] or
-    /// [`ConnectionModesIterator::update_access_methods()`].
-    pub(crate) fn handle(&self) -> Arc<Mutex<ConnectionModesIterator>> {
+    /// Return a pointer to the underlying [`AccessMethodSelector`].
+    /// Having access to the selector allow you to influence it, e.g. by calling
+    /// [`AccessMethodSelector::set_access_method()`] or
+    /// [`AccessMethodSelector::update_access_methods()`].
+    pub(crate) fn handle(&self) -> Arc<Mutex<AccessMethodSelector>> {
         self.connection_modes.clone()
     }
 
@@ -106,131 +122,82 @@ impl ApiConnectionModeProvider {
         log::debug!("Rotating Access mode!");
         let access_method = {
             let mut access_methods_picker = self.connection_modes.lock().unwrap();
-            access_methods_picker
-                .next()
-                .map(|access_method_setting| access_method_setting.access_method)
-                .unwrap_or(AccessMethod::from(BuiltInAccessMethod::Direct))
+            access_methods_picker.next(SystemTime::now()).access_method
         };
 
-        let connection_mode = self.from(access_method);
+        let connection_mode = resolve_connection_mode(&self.relay_selector, access_method);
         log::info!("New API connection mode selected: {}", connection_mode);
+        self.recent_events.push(
+            EventKind::ApiConnectivity,
+            format!("Selected API connection mode: {connection_mode}"),
+        );
         connection_mode
     }
-
-    /// Ad-hoc version of [`std::convert::From::from`], but since some
-    /// [`ApiConnectionMode`]s require extra logic/data from
-    /// [`ApiConnectionModeProvider`] the standard [`std::convert::From`] trait
-    /// can not be implemented.
-    fn from(&mut self, access_method: AccessMethod) -> ApiConnectionMode {
-        use mullvad_types::access_method;
-        match access_method {
-            AccessMethod::BuiltIn(access_method) => match access_method {
-                BuiltInAccessMethod::Direct => ApiConnectionMode::Direct,
-                BuiltInAccessMethod::Bridge => self
-                    .relay_selector
-                    .get_bridge_forced()
-                    .and_then(|settings| match settings {
-                        ProxySettings::Shadowsocks(ss_settings) => {
-                            let ss_settings: access_method::Shadowsocks =
-                                access_method::Shadowsocks::new(
-                                    ss_settings.peer,
-                                    ss_settings.cipher,
-                                    ss_settings.password,
-                                );
-                            Some(ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(
-                                ss_settings,
-                            )))
-                        }
-                        _ => {
-                            log::error!("Received unexpected proxy settings type");
-                            None
-                        }
-                    })
-                    .unwrap_or(ApiConnectionMode::Direct),
-            },
-            AccessMethod::Custom(access_method) => match access_method {
-                access_method::CustomAccessMethod::Shadowsocks(shadowsocks_config) => {
-                    ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(shadowsocks_config))
-                }
-                access_method::CustomAccessMethod::Socks5(socks_config) => {
-                    ApiConnectionMode::Proxied(ProxyConfig::Socks(socks_config))
-                }
-            },
-        }
-    }
 }
 
-/// An iterator which will always produce an [`AccessMethod`].
-///
-/// Safety: It is always safe to [`unwrap`] after calling [`next`] on a
-/// [`std::iter::Cycle`], so thereby it is safe to always call [`unwrap`] on a
-/// [`ConnectionModesIterator`].
-///
-/// [`unwrap`]: Option::unwrap
-/// [`next`]: std::iter::Iterator::next
-pub struct ConnectionModesIterator {
-    available_modes: Box<dyn Iterator<Item = AccessMethodSetting> + Send>,
-    next: Option<AccessMethodSetting>,
-    current: AccessMethodSetting,
-}
-
-impl ConnectionModesIterator {
-    pub fn new(access_methods: Vec<AccessMethodSetting>) -> ConnectionModesIterator {
-        let mut iterator = Self::cycle(access_methods);
-        Self {
-            next: None,
-            current: iterator.next().unwrap(),
-            available_modes: iterator,
-        }
-    }
-
-    /// Set the next [`AccessMethod`] to be returned from this iterator.
-    pub fn set_access_method(&mut self, next: AccessMethodSetting) {
-        self.next = Some(next);
-    }
-    /// Update the collection of [`AccessMethod`] which this iterator will
-    /// return.
-    pub fn update_access_methods(&mut self, access_methods: Vec<AccessMethodSetting>) {
-        self.available_modes = Self::cycle(access_methods)
-    }
-
-    fn cycle(
-        access_methods: Vec<AccessMethodSetting>,
-    ) -> Box<dyn Iterator<Item = AccessMethodSetting> + Send> {
-        Box::new(access_methods.into_iter().cycle())
-    }
-
-    /// Look at the currently active [`AccessMethod`]
-    pub fn peek(&self) -> AccessMethodSetting {
-        self.current.clone()
-    }
-}
-
-impl Iterator for ConnectionModesIterator {
-    type Item = AccessMethodSetting;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let next = self
-            .next
-            .take()
-            .or_else(|| self.available_modes.next())
-            .unwrap();
-        self.current = next.clone();
-        Some(next)
+/// Ad-hoc version of [`std::convert::From::from`], but since some [`ApiConnectionMode`]s require
+/// extra logic/data from the [`RelaySelector`] the standard [`std::convert::From`] trait can not be
+/// implemented.
+pub(crate) fn resolve_connection_mode(
+    relay_selector: &RelaySelector,
+    access_method: AccessMethod,
+) -> ApiConnectionMode {
+    use mullvad_types::access_method;
+    match access_method {
+        AccessMethod::BuiltIn(access_method) => match access_method {
+            BuiltInAccessMethod::Direct => ApiConnectionMode::Direct,
+            BuiltInAccessMethod::Bridge => relay_selector
+                .get_bridge_forced()
+                .and_then(|settings| match settings {
+                    ProxySettings::Shadowsocks(ss_settings) => {
+                        let ss_settings: access_method::Shadowsocks =
+                            access_method::Shadowsocks::new(
+                                ss_settings.peer,
+                                ss_settings.cipher,
+                                ss_settings.password,
+                            );
+                        Some(ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(
+                            ss_settings,
+                        )))
+                    }
+                    _ => {
+                        log::error!("Received unexpected proxy settings type");
+                        None
+                    }
+                })
+                .unwrap_or(ApiConnectionMode::Direct),
+        },
+        AccessMethod::Custom(access_method) => match access_method {
+            access_method::CustomAccessMethod::Shadowsocks(shadowsocks_config) => {
+                ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(shadowsocks_config))
+            }
+            access_method::CustomAccessMethod::Socks5(socks_config) => {
+                ApiConnectionMode::Proxied(ProxyConfig::Socks(socks_config))
+            }
+            access_method::CustomAccessMethod::HttpProxy(http_config) => {
+                ApiConnectionMode::Proxied(ProxyConfig::HttpProxy(http_config))
+            }
+        },
     }
 }
 
 /// Notifies the tunnel state machine that the API (real or proxied) endpoint has
 /// changed. [ApiEndpointUpdaterHandle::callback()] creates a callback that may
 /// be passed to the `mullvad-api` runtime.
+#[derive(Clone)]
 pub(super) struct ApiEndpointUpdaterHandle {
     tunnel_cmd_tx: Arc<Mutex<Option<Weak<mpsc::UnboundedSender<TunnelCommand>>>>>,
+    /// The endpoint most recently selected by the `mullvad-api` runtime.
+    current_endpoint: Arc<Mutex<Option<SocketAddr>>>,
+    recent_events: RecentEvents,
 }
 
 impl ApiEndpointUpdaterHandle {
-    pub fn new() -> Self {
+    pub fn new(recent_events: RecentEvents) -> Self {
         Self {
             tunnel_cmd_tx: Arc::new(Mutex::new(None)),
+            current_endpoint: Arc::new(Mutex::new(None)),
+            recent_events,
         }
     }
 
@@ -239,27 +206,73 @@ impl ApiEndpointUpdaterHandle {
     }
 
     pub fn callback(&self) -> impl ApiEndpointUpdateCallback {
-        let tunnel_tx = self.tunnel_cmd_tx.clone();
+        let handle = self.clone();
         move |address: SocketAddr| {
-            let inner_tx = tunnel_tx.clone();
+            let handle = handle.clone();
             async move {
-                let tunnel_tx = if let Some(tunnel_tx) = { inner_tx.lock().unwrap().as_ref() }
-                    .and_then(|tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>| tx.upgrade())
-                {
-                    tunnel_tx
-                } else {
-                    log::error!("Rejecting allowed endpoint: Tunnel state machine is not running");
-                    return false;
-                };
-                let (result_tx, result_rx) = oneshot::channel();
-                let _ = tunnel_tx.unbounded_send(TunnelCommand::AllowEndpoint(
-                    get_allowed_endpoint(address),
-                    result_tx,
-                ));
-                // Wait for the firewall policy to be updated.
-                let _ = result_rx.await;
-                log::debug!("API endpoint: {}", address);
-                true
+                *handle.current_endpoint.lock().unwrap() = Some(address);
+                handle.allow_endpoint(address).await
+            }
+        }
+    }
+
+    /// Run `test` while the firewall allows traffic to `address` instead of the current API
+    /// endpoint. The current endpoint is allowed again afterwards. If none has been selected yet,
+    /// `initial_endpoint` is used.
+    pub async fn with_endpoint_allowed<T>(
+        &self,
+        address: SocketAddr,
+        initial_endpoint: SocketAddr,
+        test: impl Future<Output = T>,
+    ) -> T {
+        self.allow_endpoint(address).await;
+        let result = test.await;
+        let current_endpoint = { *self.current_endpoint.lock().unwrap() };
+        self.allow_endpoint(current_endpoint.unwrap_or(initial_endpoint))
+            .await;
+        result
+    }
+
+    /// Make the firewall allow traffic to `address`. Returns whether the policy was updated.
+    async fn allow_endpoint(&self, address: SocketAddr) -> bool {
+        let tunnel_tx = if let Some(tunnel_tx) = { self.tunnel_cmd_tx.lock().unwrap().as_ref() }
+            .and_then(|tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>| tx.upgrade())
+        {
+            tunnel_tx
+        } else {
+            log::error!("Rejecting allowed endpoint: Tunnel state machine is not running");
+            return false;
+        };
+        let (result_tx, result_rx) = oneshot::channel();
+        let _ = tunnel_tx.unbounded_send(TunnelCommand::AllowEndpoint(
+            get_allowed_endpoint(address),
+            result_tx,
+        ));
+        // Wait for the firewall policy to be updated.
+        let _ = result_rx.await;
+        log::debug!("API endpoint: {}", address);
+        self.recent_events.push(
+            EventKind::Firewall,
+            format!("Allowed traffic to the API endpoint {address}"),
+        );
+        true
+    }
+}
+
+/// Returns a callback which records the outcome of API requests for the access method in use, so
+/// that failing methods are put in a cooldown. The outcome also updates the status of the
+/// connectivity to the API.
+pub(crate) fn outcome_recorder(
+    connection_modes: Arc<Mutex<AccessMethodSelector>>,
+    connectivity: ApiConnectivityMonitor,
+) -> impl Fn(ConnectionModeOutcome) + Send + Sync + 'static {
+    move |outcome| {
+        connectivity.record(&outcome, SystemTime::now());
+        let mut connection_modes = connection_modes.lock().unwrap();
+        match outcome {
+            ConnectionModeOutcome::Success => connection_modes.record_success(SystemTime::now()),
+            ConnectionModeOutcome::Failure(error) => {
+                connection_modes.record_failure(error, SystemTime::now())
             }
         }
     }
@@ -286,22 +299,81 @@ pub(super) fn get_allowed_endpoint(api_address: SocketAddr) -> AllowedEndpoint {
     }
 }
 
+/// Forwards the offline state to the API runtime. Whenever the host comes online, it is also
+/// checked whether the network uses NAT64.
 pub(crate) fn forward_offline_state(
     api_availability: ApiAvailabilityHandle,
+    address_cache: AddressCache,
+    api_service: RequestServiceHandle,
+    relay_selector: RelaySelector,
+    recent_events: RecentEvents,
     mut offline_state_rx: mpsc::UnboundedReceiver<bool>,
 ) {
+    let record = move |is_offline| {
+        let message = if is_offline {
+            "The host is offline, pausing API requests"
+        } else {
+            "The host is online"
+        };
+        recent_events.push(EventKind::ApiConnectivity, message);
+    };
     tokio::spawn(async move {
         let initial_state = offline_state_rx
             .next()
             .await
             .expect("missing initial offline state");
         api_availability.set_offline(initial_state);
+        record(initial_state);
+        if !initial_state {
+            tokio::spawn(detect_nat64(
+                address_cache.clone(),
+                api_service.clone(),
+                relay_selector.clone(),
+            ));
+        }
         while let Some(is_offline) = offline_state_rx.next().await {
             api_availability.set_offline(is_offline);
+            record(is_offline);
+            if !is_offline {
+                tokio::spawn(detect_nat64(
+                    address_cache.clone(),
+                    api_service.clone(),
+                    relay_selector.clone(),
+                ));
+            }
         }
     });
 }
 
+/// Looks for a NAT64 prefix. If one is found, the API is reached through it and IPv6 relay
+/// endpoints are preferred. The previous result is kept if the lookup fails, e.g. because DNS
+/// requests are blocked by the firewall.
+async fn detect_nat64(
+    address_cache: AddressCache,
+    api_service: RequestServiceHandle,
+    relay_selector: RelaySelector,
+) {
+    match nat64::discover().await {
+        Ok(prefix) => {
+            relay_selector.set_prefer_ipv6_endpoints(prefix.is_some());
+            if address_cache.set_nat64_prefix(prefix).await {
+                // The firewall must allow the new API address, and open connections to the old
+                // address have to be closed
+                if let Err(error) = api_service.address_changed().await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to switch to the new API address")
+                    );
+                }
+            }
+        }
+        Err(error) => log::debug!(
+            "{}",
+            error.display_chain_with_msg("Failed to look up the NAT64 prefix")
+        ),
+    }
+}
+
 #[cfg(target_os = "android")]
 pub(crate) fn create_bypass_tx(
     event_sender: &DaemonEventSender,
@@ -321,3 +393,127 @@ pub(crate) fn create_bypass_tx(
     });
     Some(bypass_tx)
 }
+
+/// Returns the tunnel addresses that API connections should be bound to, or `None` if API
+/// connections should be made the usual way, i.e. using the selected access method.
+///
+/// API traffic is only sent over the tunnel if the user prefers it and the tunnel is up. An empty
+/// list means that the tunnel is up but its addresses are unknown, in which case the connector
+/// still connects directly but lets the routing table pick the source address.
+pub(crate) fn api_tunnel_addresses(
+    settings: &Settings,
+    tunnel_state: &TunnelState,
+    device_addresses: Option<&AssociatedAddresses>,
+) -> Option<Vec<IpAddr>> {
+    if !settings.prefer_api_in_tunnel {
+        return None;
+    }
+    tunnel_addresses(settings, tunnel_state, device_addresses)
+}
+
+/// Returns the tunnel addresses that connections must be bound to in order to be sent through the
+/// tunnel, or `None` if the tunnel is not up. See [`api_tunnel_addresses`].
+pub(crate) fn tunnel_addresses(
+    settings: &Settings,
+    tunnel_state: &TunnelState,
+    device_addresses: Option<&AssociatedAddresses>,
+) -> Option<Vec<IpAddr>> {
+    let TunnelState::Connected { endpoint, .. } = tunnel_state else {
+        return None;
+    };
+    let mut addresses = vec![];
+    if let (TunnelType::Wireguard, Some(device_addresses)) =
+        (endpoint.tunnel_type, device_addresses)
+    {
+        addresses.push(IpAddr::V4(device_addresses.ipv4_address.ip()));
+        if settings.tunnel_options.generic.enable_ipv6 {
+            addresses.push(IpAddr::V6(device_addresses.ipv6_address.ip()));
+        }
+    }
+    Some(addresses)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{connected_state, tunnel_endpoint};
+
+    fn device_addresses() -> AssociatedAddresses {
+        AssociatedAddresses {
+            ipv4_address: "10.64.0.2/32".parse().unwrap(),
+            ipv6_address: "fc00:bbbb:bbbb:bb01::2/128".parse().unwrap(),
+        }
+    }
+
+    fn connected(tunnel_type: TunnelType) -> TunnelState {
+        connected_state(tunnel_endpoint(tunnel_type), None)
+    }
+
+    #[test]
+    fn test_api_tunnel_addresses() {
+        let mut settings = Settings {
+            prefer_api_in_tunnel: true,
+            ..Default::default()
+        };
+        settings.tunnel_options.generic.enable_ipv6 = false;
+        let addresses = device_addresses();
+
+        // Switch to the tunnel once connected
+        assert_eq!(
+            api_tunnel_addresses(
+                &settings,
+                &connected(TunnelType::Wireguard),
+                Some(&addresses)
+            ),
+            Some(vec!["10.64.0.2".parse().unwrap()])
+        );
+
+        settings.tunnel_options.generic.enable_ipv6 = true;
+        assert_eq!(
+            api_tunnel_addresses(
+                &settings,
+                &connected(TunnelType::Wireguard),
+                Some(&addresses)
+            ),
+            Some(vec![
+                "10.64.0.2".parse().unwrap(),
+                "fc00:bbbb:bbbb:bb01::2".parse().unwrap()
+            ])
+        );
+
+        // The OpenVPN tunnel addresses are not known in advance
+        assert_eq!(
+            api_tunnel_addresses(&settings, &connected(TunnelType::OpenVpn), Some(&addresses)),
+            Some(vec![])
+        );
+
+        // Fall back to the access methods while not connected
+        assert_eq!(
+            api_tunnel_addresses(&settings, &TunnelState::Disconnected, Some(&addresses)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_api_tunnel_addresses_disabled() {
+        let settings = Settings::default();
+        assert!(!settings.prefer_api_in_tunnel);
+        assert_eq!(
+            api_tunnel_addresses(
+                &settings,
+                &connected(TunnelType::Wireguard),
+                Some(&device_addresses())
+            ),
+            None
+        );
+        // Connection checks are still sent through the tunnel
+        assert_eq!(
+            tunnel_addresses(
+                &settings,
+                &connected(TunnelType::Wireguard),
+                Some(&device_addresses())
+            ),
+            Some(vec!["10.64.0.2".parse().unwrap()])
+        );
+    }
+}
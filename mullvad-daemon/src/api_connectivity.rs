@@ -0,0 +1,190 @@
+//! Keeps track of whether the API can be reached, judging by the outcome of the requests that are
+//! sent to it. Requests often fail one at a time while the access methods are rotated, so the API
+//! is only considered unreachable after several failures in a row. A single request that reaches
+//! the API is enough to make it reachable again.
+
+use mullvad_api::proxy::ConnectionModeOutcome;
+use mullvad_types::access_method::ApiConnectivity;
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::sync::watch;
+
+/// Number of requests in a row that must fail before the API is considered unreachable.
+const FAILURES_BEFORE_UNREACHABLE: u32 = 3;
+
+/// Shared handle to the connectivity status. Every clone refers to the same status.
+#[derive(Clone)]
+pub struct ApiConnectivityMonitor {
+    failures: Arc<Mutex<Failures>>,
+    status_tx: Arc<watch::Sender<ApiConnectivity>>,
+}
+
+/// Failures since the last request that reached the API.
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    first: Option<SystemTime>,
+}
+
+impl ApiConnectivityMonitor {
+    pub fn new() -> Self {
+        let (status_tx, _) = watch::channel(ApiConnectivity::Unknown);
+        ApiConnectivityMonitor {
+            failures: Arc::new(Mutex::new(Failures::default())),
+            status_tx: Arc::new(status_tx),
+        }
+    }
+
+    /// Records the outcome of a request that finished at `now`. Subscribers are only notified if
+    /// the status changes.
+    pub fn record(&self, outcome: &ConnectionModeOutcome, now: SystemTime) {
+        let mut failures = self.failures.lock().unwrap();
+        let status = match outcome {
+            ConnectionModeOutcome::Success => {
+                *failures = Failures::default();
+                ApiConnectivity::Reachable
+            }
+            ConnectionModeOutcome::Failure(error) => {
+                failures.count = failures.count.saturating_add(1);
+                let since = *failures.first.get_or_insert(now);
+                if failures.count < FAILURES_BEFORE_UNREACHABLE {
+                    return;
+                }
+                ApiConnectivity::Unreachable {
+                    since,
+                    last_error: error.clone(),
+                }
+            }
+        };
+        self.status_tx.send_if_modified(|current| {
+            // Only the first failure that makes the API unreachable is announced. The errors of
+            // the ones that follow are kept without notifying anyone.
+            let changed = match (&*current, &status) {
+                (ApiConnectivity::Unreachable { .. }, ApiConnectivity::Unreachable { .. }) => false,
+                (current, status) => current != status,
+            };
+            *current = status;
+            changed
+        });
+    }
+
+    pub fn status(&self) -> ApiConnectivity {
+        self.status_tx.borrow().clone()
+    }
+
+    pub fn is_unreachable(&self) -> bool {
+        self.status_tx.borrow().is_unreachable()
+    }
+
+    /// Returns a receiver which is notified whenever the status changes.
+    pub fn subscribe(&self) -> watch::Receiver<ApiConnectivity> {
+        self.status_tx.subscribe()
+    }
+
+    /// Waits until the API is no longer known to be unreachable. Returns immediately unless it is.
+    pub async fn wait_until_not_unreachable(&self) {
+        let mut status_rx = self.subscribe();
+        while status_rx.borrow_and_update().is_unreachable() {
+            if status_rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn failure(error: &str) -> ConnectionModeOutcome {
+        ConnectionModeOutcome::Failure(error.to_owned())
+    }
+
+    #[test]
+    fn test_single_failure_does_not_flap() {
+        let monitor = ApiConnectivityMonitor::new();
+        let mut status_rx = monitor.subscribe();
+        let now = SystemTime::now();
+
+        monitor.record(&ConnectionModeOutcome::Success, now);
+        assert!(status_rx.has_changed().unwrap());
+        assert_eq!(*status_rx.borrow_and_update(), ApiConnectivity::Reachable);
+
+        // Failures that are interrupted by a success never make the API unreachable
+        for _ in 0..10 {
+            for _ in 1..FAILURES_BEFORE_UNREACHABLE {
+                monitor.record(&failure("timed out"), now);
+            }
+            monitor.record(&ConnectionModeOutcome::Success, now);
+        }
+        assert!(!status_rx.has_changed().unwrap());
+        assert_eq!(monitor.status(), ApiConnectivity::Reachable);
+    }
+
+    #[test]
+    fn test_unreachable_after_consecutive_failures() {
+        let monitor = ApiConnectivityMonitor::new();
+        let mut status_rx = monitor.subscribe();
+        let start = SystemTime::now();
+
+        for attempt in 1..FAILURES_BEFORE_UNREACHABLE {
+            monitor.record(
+                &failure("timed out"),
+                start + Duration::from_secs(attempt.into()),
+            );
+            assert!(!status_rx.has_changed().unwrap());
+            assert_eq!(monitor.status(), ApiConnectivity::Unknown);
+        }
+
+        let last = start + Duration::from_secs(60);
+        monitor.record(&failure("connection refused"), last);
+        assert!(status_rx.has_changed().unwrap());
+        // The API has been unreachable since the first failure of the streak
+        assert_eq!(
+            *status_rx.borrow_and_update(),
+            ApiConnectivity::Unreachable {
+                since: start + Duration::from_secs(1),
+                last_error: "connection refused".to_owned(),
+            }
+        );
+
+        // More failures update the error without notifying anyone
+        monitor.record(&failure("no route to host"), last);
+        assert!(!status_rx.has_changed().unwrap());
+        assert!(matches!(
+            monitor.status(),
+            ApiConnectivity::Unreachable { last_error, .. } if last_error == "no route to host"
+        ));
+
+        monitor.record(&ConnectionModeOutcome::Success, last);
+        assert!(status_rx.has_changed().unwrap());
+        assert_eq!(*status_rx.borrow_and_update(), ApiConnectivity::Reachable);
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_not_unreachable() {
+        let monitor = ApiConnectivityMonitor::new();
+        // Unknown does not block
+        monitor.wait_until_not_unreachable().await;
+
+        let now = SystemTime::now();
+        for _ in 0..FAILURES_BEFORE_UNREACHABLE {
+            monitor.record(&failure("timed out"), now);
+        }
+        let waiter = tokio::spawn({
+            let monitor = monitor.clone();
+            async move { monitor.wait_until_not_unreachable().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        monitor.record(&ConnectionModeOutcome::Success, now);
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
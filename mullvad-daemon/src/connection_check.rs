@@ -0,0 +1,372 @@
+//! Checks where traffic leaves for the internet, like the connection check on the Mullvad website.
+//!
+//! The connection check endpoint is asked which IP address it sees requests from over IPv4 and,
+//! if enabled, IPv6, and whether those addresses belong to Mullvad relays. While connected, the
+//! requests are sent through the tunnel, and a DNS leak test is run at the same time.
+
+use futures::{
+    future::{self, BoxFuture},
+    join, Future, FutureExt,
+};
+use mullvad_api::rest::{self, RequestServiceHandle};
+use mullvad_types::{
+    connection_check::ConnectionCheck, dns_leak::DnsLeakTestResult, location::AmIMullvad,
+};
+use std::net::IpAddr;
+use talpid_types::{net::IpVersion, ErrorExt};
+
+use crate::geoip::{self, MULLVAD_CONNCHECK_HOST};
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "The connection can only be checked while connected")]
+    NotConnected,
+
+    #[error(display = "Failed to query the connection check endpoint")]
+    Request(#[error(source)] rest::Error),
+}
+
+/// Performs the requests of a connection check.
+pub trait CheckBackend {
+    /// Asks the connection check endpoint where requests over `ip_version` come from.
+    fn am_i_mullvad(
+        &self,
+        ip_version: IpVersion,
+    ) -> BoxFuture<'static, Result<AmIMullvad, rest::Error>>;
+}
+
+/// Check backend that queries the Mullvad connection check API.
+pub struct ApiCheckBackend {
+    service: RequestServiceHandle,
+}
+
+impl ApiCheckBackend {
+    pub fn new(service: RequestServiceHandle) -> Self {
+        ApiCheckBackend { service }
+    }
+}
+
+impl CheckBackend for ApiCheckBackend {
+    fn am_i_mullvad(
+        &self,
+        ip_version: IpVersion,
+    ) -> BoxFuture<'static, Result<AmIMullvad, rest::Error>> {
+        let service = self.service.clone();
+        let subdomain = match ip_version {
+            IpVersion::V4 => "ipv4",
+            IpVersion::V6 => "ipv6",
+        };
+        async move {
+            let uri = format!("https://{subdomain}.{}/json", *MULLVAD_CONNCHECK_HOST);
+            geoip::send_location_request_internal(&uri, service).await
+        }
+        .boxed()
+    }
+}
+
+/// The tunnel that the check is made through.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectedTunnel {
+    /// Hostname of the exit relay, if it is known.
+    pub relay_hostname: Option<String>,
+}
+
+/// Runs a connection check. `tunnel` is the connected tunnel, or `None` if the check is made
+/// outside of the tunnel. `dns_leak_test` is run at the same time as the check, if it is given.
+pub async fn run<F>(
+    backend: &impl CheckBackend,
+    tunnel: Option<ConnectedTunnel>,
+    use_ipv6: bool,
+    dns_leak_test: Option<F>,
+) -> Result<ConnectionCheck, Error>
+where
+    F: Future<Output = Result<DnsLeakTestResult, crate::dns_leak::Error>>,
+{
+    let v4_future = backend.am_i_mullvad(IpVersion::V4);
+    let v6_future = if use_ipv6 {
+        backend.am_i_mullvad(IpVersion::V6).map(Some).boxed()
+    } else {
+        future::ready(None).boxed()
+    };
+    let dns_leak_future = async move {
+        match dns_leak_test?.await {
+            Ok(result) => Some(result),
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("DNS leak test of connection check failed")
+                );
+                None
+            }
+        }
+    };
+    let (v4_result, v6_result, dns_leak_test) = join!(v4_future, v6_future, dns_leak_future);
+
+    let responses = match (v4_result, v6_result) {
+        (Ok(v4), Some(Ok(v6))) => vec![v4, v6],
+        (Ok(v4), None) => vec![v4],
+        (Ok(v4), Some(Err(error))) => {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("IPv6 connection check failed")
+            );
+            vec![v4]
+        }
+        (Err(error), Some(Ok(v6))) => {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("IPv4 connection check failed")
+            );
+            vec![v6]
+        }
+        (Err(error), _) => return Err(Error::Request(error)),
+    };
+
+    Ok(evaluate(responses, tunnel, dns_leak_test))
+}
+
+/// Combines the responses for each IP version. Traffic only goes through Mullvad if it does over
+/// every IP version.
+fn evaluate(
+    responses: Vec<AmIMullvad>,
+    tunnel: Option<ConnectedTunnel>,
+    dns_leak_test: Option<DnsLeakTestResult>,
+) -> ConnectionCheck {
+    let first = &responses[0];
+    let via_connected_relay = tunnel.as_ref().and_then(|tunnel| {
+        let matches: Vec<_> = responses
+            .iter()
+            .map(|response| matches_relay(response, tunnel.relay_hostname.as_deref()))
+            .collect();
+        if matches.contains(&Some(false)) {
+            Some(false)
+        } else if matches.iter().all(|matches| *matches == Some(true)) {
+            Some(true)
+        } else {
+            None
+        }
+    });
+
+    ConnectionCheck {
+        connected: tunnel.is_some(),
+        ipv4: responses.iter().find_map(|response| match response.ip {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        }),
+        ipv6: responses.iter().find_map(|response| match response.ip {
+            IpAddr::V6(ip) => Some(ip),
+            IpAddr::V4(_) => None,
+        }),
+        country: first.country.clone(),
+        city: first.city.clone(),
+        mullvad_exit_ip: responses.iter().all(|response| response.mullvad_exit_ip),
+        exit_relay: responses
+            .iter()
+            .find_map(|response| response.mullvad_exit_ip_hostname.clone()),
+        via_connected_relay,
+        dns_leak_test,
+    }
+}
+
+/// Returns whether `response` shows that traffic leaves through the relay `relay_hostname`, or
+/// `None` if that cannot be told.
+fn matches_relay(response: &AmIMullvad, relay_hostname: Option<&str>) -> Option<bool> {
+    if !response.mullvad_exit_ip {
+        return Some(false);
+    }
+    let exit_hostname = response.mullvad_exit_ip_hostname.as_deref()?;
+    Some(exit_hostname.eq_ignore_ascii_case(relay_hostname?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future::Ready;
+    use mullvad_types::dns_leak::DnsLeakVerdict;
+    use std::sync::{Arc, Mutex};
+
+    type DnsLeakResult = Result<DnsLeakTestResult, crate::dns_leak::Error>;
+
+    /// Responds like the connection check endpoint would, or with an error if there is no
+    /// response for the IP version.
+    #[derive(Default)]
+    struct MockBackend {
+        v4: Option<AmIMullvad>,
+        v6: Option<AmIMullvad>,
+        requests: Arc<Mutex<Vec<IpVersion>>>,
+    }
+
+    impl CheckBackend for MockBackend {
+        fn am_i_mullvad(
+            &self,
+            ip_version: IpVersion,
+        ) -> BoxFuture<'static, Result<AmIMullvad, rest::Error>> {
+            self.requests.lock().unwrap().push(ip_version);
+            let response = match ip_version {
+                IpVersion::V4 => self.v4.clone(),
+                IpVersion::V6 => self.v6.clone(),
+            };
+            future::ready(response.ok_or(rest::Error::ReceiveError)).boxed()
+        }
+    }
+
+    fn response(ip: &str, hostname: Option<&str>) -> AmIMullvad {
+        AmIMullvad {
+            ip: ip.parse().unwrap(),
+            country: "Sweden".to_owned(),
+            city: Some("Gothenburg".to_owned()),
+            latitude: 57.7,
+            longitude: 11.97,
+            mullvad_exit_ip: hostname.is_some(),
+            mullvad_exit_ip_hostname: hostname.map(str::to_owned),
+        }
+    }
+
+    fn tunnel(relay_hostname: &str) -> Option<ConnectedTunnel> {
+        Some(ConnectedTunnel {
+            relay_hostname: Some(relay_hostname.to_owned()),
+        })
+    }
+
+    fn no_leak() -> Option<Ready<DnsLeakResult>> {
+        Some(future::ready(Ok(DnsLeakTestResult {
+            verdict: DnsLeakVerdict::NoLeak,
+            observed_resolvers: vec![],
+            leaking_resolvers: vec![],
+        })))
+    }
+
+    async fn check(
+        backend: &MockBackend,
+        tunnel: Option<ConnectedTunnel>,
+        use_ipv6: bool,
+    ) -> Result<ConnectionCheck, Error> {
+        run(backend, tunnel, use_ipv6, None::<Ready<DnsLeakResult>>).await
+    }
+
+    #[tokio::test]
+    async fn test_via_connected_relay() {
+        let backend = MockBackend {
+            v4: Some(response("192.0.2.1", Some("se-got-wg-001"))),
+            v6: Some(response("2001:db8::1", Some("se-got-wg-001"))),
+            ..Default::default()
+        };
+        let result = run(&backend, tunnel("se-got-wg-001"), true, no_leak())
+            .await
+            .unwrap();
+
+        assert!(result.connected);
+        assert_eq!(result.ipv4, Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(result.ipv6, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(result.country, "Sweden");
+        assert!(result.mullvad_exit_ip);
+        assert_eq!(result.exit_relay.as_deref(), Some("se-got-wg-001"));
+        assert_eq!(result.via_connected_relay, Some(true));
+        assert_eq!(
+            result.dns_leak_test.map(|result| result.verdict),
+            Some(DnsLeakVerdict::NoLeak)
+        );
+        assert!(!ConnectionCheck {
+            dns_leak_test: None,
+            ..result
+        }
+        .bypasses_tunnel());
+    }
+
+    #[tokio::test]
+    async fn test_other_relay() {
+        let backend = MockBackend {
+            v4: Some(response("192.0.2.2", Some("se-sto-wg-002"))),
+            ..Default::default()
+        };
+        let result = check(&backend, tunnel("se-got-wg-001"), false)
+            .await
+            .unwrap();
+        assert!(result.mullvad_exit_ip);
+        assert_eq!(result.via_connected_relay, Some(false));
+        assert!(result.bypasses_tunnel());
+    }
+
+    #[tokio::test]
+    async fn test_not_via_mullvad() {
+        // IPv6 traffic that leaks outside the tunnel is detected even if IPv4 traffic does not.
+        let backend = MockBackend {
+            v4: Some(response("192.0.2.1", Some("se-got-wg-001"))),
+            v6: Some(response("2001:db8::99", None)),
+            ..Default::default()
+        };
+        let result = check(&backend, tunnel("se-got-wg-001"), true)
+            .await
+            .unwrap();
+        assert!(!result.mullvad_exit_ip);
+        assert_eq!(result.exit_relay.as_deref(), Some("se-got-wg-001"));
+        assert_eq!(result.via_connected_relay, Some(false));
+        assert!(result.bypasses_tunnel());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_relay() {
+        let backend = MockBackend {
+            v4: Some(response("192.0.2.1", Some("se-got-wg-001"))),
+            ..Default::default()
+        };
+        let result = check(&backend, Some(ConnectedTunnel::default()), false)
+            .await
+            .unwrap();
+        assert_eq!(result.via_connected_relay, None);
+        assert!(!result.bypasses_tunnel());
+    }
+
+    #[tokio::test]
+    async fn test_disconnected() {
+        let backend = MockBackend {
+            v4: Some(response("198.51.100.1", None)),
+            ..Default::default()
+        };
+        let result = check(&backend, None, true).await.unwrap();
+        assert!(!result.connected);
+        assert!(!result.mullvad_exit_ip);
+        assert_eq!(result.via_connected_relay, None);
+        assert!(!result.bypasses_tunnel());
+        assert_eq!(
+            *backend.requests.lock().unwrap(),
+            [IpVersion::V4, IpVersion::V6]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_requests() {
+        let backend = MockBackend {
+            v6: Some(response("2001:db8::1", Some("se-got-wg-001"))),
+            ..Default::default()
+        };
+        let result = check(&backend, tunnel("se-got-wg-001"), true)
+            .await
+            .unwrap();
+        assert_eq!(result.ipv4, None);
+        assert_eq!(result.via_connected_relay, Some(true));
+
+        assert!(matches!(
+            check(&backend, tunnel("se-got-wg-001"), false).await,
+            Err(Error::Request(_))
+        ));
+        assert_eq!(
+            *backend.requests.lock().unwrap(),
+            [IpVersion::V4, IpVersion::V6, IpVersion::V4]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_dns_leak_test() {
+        let backend = MockBackend {
+            v4: Some(response("192.0.2.1", Some("se-got-wg-001"))),
+            ..Default::default()
+        };
+        let leak_test = future::ready(Err(crate::dns_leak::Error::Timeout));
+        let result = run(&backend, tunnel("se-got-wg-001"), false, Some(leak_test))
+            .await
+            .unwrap();
+        assert_eq!(result.dns_leak_test, None);
+        assert_eq!(result.via_connected_relay, Some(true));
+    }
+}
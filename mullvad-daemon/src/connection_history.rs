@@ -0,0 +1,538 @@
+//! Keeps a bounded history of the most recent connections, with the parameters that were used and
+//! how each connection ended. The history is persisted to the cache directory, so that it survives
+//! restarts and can be included in problem reports.
+
+use chrono::{DateTime, Utc};
+use mullvad_types::{
+    connection_history::{Connection, ConnectionOutcome, ConnectionParameters, CAPACITY},
+    location::GeoIpLocation,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
+use talpid_types::{
+    net::TunnelEndpoint,
+    tunnel::{ActionAfterDisconnect, ErrorCode, TunnelStateTransition},
+    ErrorExt,
+};
+use tokio::{fs, io};
+
+/// Name of the file in the cache directory that the history is stored in. Problem reports look
+/// for it by this name.
+pub const CONNECTION_HISTORY_FILE: &str = mullvad_problem_report::CONNECTION_HISTORY_FILE;
+
+/// Version of the format that the history is stored in. It must be increased whenever the format
+/// is changed in a way that older versions cannot read.
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to parse the connection history")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unsupported connection history version: {}", _0)]
+    UnsupportedVersion(u32),
+}
+
+/// Format of the history file.
+#[derive(Serialize, Deserialize)]
+struct HistoryFile {
+    version: u32,
+    /// Oldest first.
+    connections: Vec<Connection>,
+}
+
+/// Only the version of the history file, which is read before the rest.
+#[derive(Deserialize)]
+struct HistoryVersion {
+    version: u32,
+}
+
+/// The most recent connections, oldest first.
+#[derive(Debug)]
+pub struct ConnectionHistory {
+    connections: VecDeque<Connection>,
+    capacity: usize,
+    /// Number of the latest attempt of the ongoing connection, as reported by the tunnel state
+    /// machine.
+    last_attempt: u32,
+}
+
+impl ConnectionHistory {
+    pub fn new(capacity: usize) -> Self {
+        ConnectionHistory {
+            connections: VecDeque::with_capacity(capacity),
+            capacity,
+            last_attempt: 0,
+        }
+    }
+
+    /// Parses a history file. Connections that were ongoing when the file was written are marked
+    /// as interrupted, since the daemon must have stopped before they ended.
+    pub fn parse(content: &str, capacity: usize) -> Result<Self, Error> {
+        let HistoryVersion { version } = serde_json::from_str(content).map_err(Error::Parse)?;
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let file: HistoryFile = serde_json::from_str(content).map_err(Error::Parse)?;
+        let mut history = Self::new(capacity);
+        for mut connection in file.connections {
+            connection
+                .outcome
+                .get_or_insert(ConnectionOutcome::Interrupted);
+            history.push(connection);
+        }
+        Ok(history)
+    }
+
+    pub fn serialize(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(&HistoryFile {
+            version: FORMAT_VERSION,
+            connections: self.connections(),
+        })
+    }
+
+    /// Adds a connection, dropping the oldest one if the history is full.
+    fn push(&mut self, connection: Connection) {
+        if self.connections.len() == self.capacity {
+            self.connections.pop_front();
+        }
+        self.connections.push_back(connection);
+    }
+
+    pub fn connections(&self) -> Vec<Connection> {
+        self.connections.iter().cloned().collect()
+    }
+
+    fn ongoing(&mut self) -> Option<&mut Connection> {
+        self.connections
+            .back_mut()
+            .filter(|connection| connection.outcome.is_none())
+    }
+
+    /// Ends the ongoing connection, if there is one.
+    fn end(&mut self, outcome: ConnectionOutcome, now: DateTime<Utc>) {
+        if let Some(connection) = self.ongoing() {
+            connection.ended_at = Some(now);
+            connection.outcome = Some(outcome);
+        }
+    }
+
+    /// Updates the history with a transition that happened at `now`. `location` is where the
+    /// tunnel leads, if it is known. Returns whether the history changed.
+    pub fn handle_state_transition(
+        &mut self,
+        transition: &TunnelStateTransition,
+        location: Option<&GeoIpLocation>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let before = self.connections.back().cloned();
+        match transition {
+            TunnelStateTransition::Connecting(endpoint, _, attempt) => {
+                // A tunnel that went down is replaced by a new connection
+                if self
+                    .ongoing()
+                    .is_some_and(|connection| connection.connected_at.is_some())
+                {
+                    self.end(ConnectionOutcome::ConnectionLost, now);
+                }
+                let relay = location.and_then(|location| location.hostname.clone());
+                let entry_relay = location.and_then(|location| location.entry_hostname.clone());
+                let last_attempt = self.last_attempt;
+                self.last_attempt = attempt.attempt;
+                match self.ongoing() {
+                    Some(connection) => {
+                        // Phase changes are reported as transitions within the same attempt
+                        if attempt.attempt != last_attempt {
+                            connection.attempts = connection.attempts.saturating_add(1);
+                        }
+                        connection.relay = relay;
+                        connection.entry_relay = entry_relay;
+                        connection.parameters = parameters(endpoint);
+                    }
+                    None => self.push(Connection {
+                        started_at: now,
+                        attempts: 1,
+                        relay,
+                        entry_relay,
+                        parameters: parameters(endpoint),
+                        connected_at: None,
+                        ended_at: None,
+                        outcome: None,
+                    }),
+                }
+            }
+            TunnelStateTransition::Connected(..) => {
+                if let Some(connection) = self.ongoing() {
+                    connection.connected_at.get_or_insert(now);
+                }
+            }
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Nothing)
+            | TunnelStateTransition::Disconnected => self.end(ConnectionOutcome::Disconnected, now),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Reconnect) => {
+                self.end(ConnectionOutcome::Reconnected, now)
+            }
+            // The error state that follows tells why
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Block) => (),
+            TunnelStateTransition::Error(error_state) => {
+                let cause = error_state.cause();
+                let outcome = match cause.error_code() {
+                    ErrorCode::Offline => ConnectionOutcome::Offline,
+                    code => ConnectionOutcome::Error {
+                        code: code.code(),
+                        description: mullvad_problem_report::redact(&cause.to_string()),
+                    },
+                };
+                self.end(outcome, now);
+            }
+        }
+        self.connections.back() != before.as_ref()
+    }
+}
+
+/// Returns the parameters of a connection to `endpoint`. The port and protocol are those of the
+/// first hop, since that is what the network sees.
+fn parameters(endpoint: &TunnelEndpoint) -> ConnectionParameters {
+    let first_hop = endpoint
+        .proxy
+        .as_ref()
+        .map(|proxy| proxy.endpoint)
+        .or_else(|| {
+            endpoint
+                .obfuscation
+                .as_ref()
+                .map(|obfuscation| obfuscation.endpoint)
+        })
+        .or(endpoint.entry_endpoint)
+        .unwrap_or(endpoint.endpoint);
+    ConnectionParameters {
+        tunnel_type: endpoint.tunnel_type,
+        protocol: first_hop.protocol,
+        port: first_hop.address.port(),
+        obfuscation: endpoint
+            .obfuscation
+            .as_ref()
+            .map(|obfuscation| obfuscation.obfuscation_type),
+        quantum_resistant: endpoint.quantum_resistant,
+    }
+}
+
+/// Records connections in a [`ConnectionHistory`], which is persisted to the cache directory.
+pub struct ConnectionHistoryStore {
+    history: ConnectionHistory,
+    cache_path: PathBuf,
+}
+
+impl ConnectionHistoryStore {
+    /// Loads the history from the cache directory. If there is none, or if it cannot be read, the
+    /// history starts out empty.
+    pub async fn load(cache_dir: &Path) -> Self {
+        let cache_path = cache_dir.join(CONNECTION_HISTORY_FILE);
+        let history = match fs::read_to_string(&cache_path).await {
+            Ok(content) => ConnectionHistory::parse(&content, CAPACITY).unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to load the connection history")
+                );
+                ConnectionHistory::new(CAPACITY)
+            }),
+            Err(error) => {
+                if error.kind() == io::ErrorKind::NotFound {
+                    log::debug!("No connection history to load");
+                } else {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read the connection history")
+                    );
+                }
+                ConnectionHistory::new(CAPACITY)
+            }
+        };
+        ConnectionHistoryStore {
+            history,
+            cache_path,
+        }
+    }
+
+    pub fn connections(&self) -> Vec<Connection> {
+        self.history.connections()
+    }
+
+    /// Removes all connections, including the ongoing one, and the history file.
+    pub async fn clear(&mut self) -> io::Result<()> {
+        self.history = ConnectionHistory::new(CAPACITY);
+        match fs::remove_file(&self.cache_path).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a transition, and saves the history if it changed.
+    pub async fn handle_state_transition(
+        &mut self,
+        transition: &TunnelStateTransition,
+        location: Option<&GeoIpLocation>,
+    ) {
+        if self
+            .history
+            .handle_state_transition(transition, location, Utc::now())
+        {
+            self.save().await;
+        }
+    }
+
+    async fn save(&self) {
+        log::trace!("Saving connection history to {}", self.cache_path.display());
+        match self.history.serialize() {
+            Ok(data) => {
+                if let Err(error) = fs::write(&self.cache_path, data).await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to write the connection history")
+                    );
+                }
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to serialize the connection history")
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::tunnel_endpoint;
+    use chrono::{Duration, TimeZone};
+    use talpid_types::{
+        net::{TransportProtocol, TunnelType},
+        tunnel::{ConnectingPhase, ConnectionAttempt, ErrorState, ErrorStateCause},
+    };
+
+    fn time(second: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap() + Duration::seconds(second)
+    }
+
+    fn connecting(attempt: u32) -> TunnelStateTransition {
+        TunnelStateTransition::Connecting(
+            tunnel_endpoint(TunnelType::Wireguard),
+            ConnectingPhase::EstablishingTunnel,
+            ConnectionAttempt {
+                attempt,
+                retry_timeout: None,
+            },
+        )
+    }
+
+    fn connected() -> TunnelStateTransition {
+        TunnelStateTransition::Connected(tunnel_endpoint(TunnelType::Wireguard), Default::default())
+    }
+
+    fn location(hostname: &str) -> GeoIpLocation {
+        GeoIpLocation {
+            ipv4: None,
+            ipv6: None,
+            country: "Sweden".to_owned(),
+            city: Some("Gothenburg".to_owned()),
+            latitude: 0.0,
+            longitude: 0.0,
+            mullvad_exit_ip: true,
+            hostname: Some(hostname.to_owned()),
+            bridge_hostname: None,
+            entry_hostname: None,
+            obfuscator_hostname: None,
+        }
+    }
+
+    /// Records a connection to `hostname` that starts at `start` and is disconnected on request.
+    fn record_connection(history: &mut ConnectionHistory, start: i64, hostname: &str) {
+        let location = location(hostname);
+        history.handle_state_transition(&connecting(1), Some(&location), time(start));
+        history.handle_state_transition(&connected(), None, time(start + 1));
+        history.handle_state_transition(
+            &TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Nothing),
+            None,
+            time(start + 10),
+        );
+        history.handle_state_transition(
+            &TunnelStateTransition::Disconnected,
+            None,
+            time(start + 11),
+        );
+    }
+
+    #[test]
+    fn test_oldest_connections_are_dropped() {
+        let mut history = ConnectionHistory::new(3);
+        for i in 0..5 {
+            record_connection(&mut history, i * 100, &format!("se-got-wg-00{i}"));
+        }
+        let relays: Vec<_> = history
+            .connections()
+            .into_iter()
+            .map(|connection| connection.relay.unwrap())
+            .collect();
+        assert_eq!(relays, ["se-got-wg-002", "se-got-wg-003", "se-got-wg-004"]);
+    }
+
+    #[test]
+    fn test_connection_outcomes() {
+        let mut history = ConnectionHistory::new(CAPACITY);
+        let location = location("se-got-wg-001");
+
+        // Retries before the tunnel is up belong to the same connection
+        assert!(history.handle_state_transition(&connecting(1), Some(&location), time(0)));
+        assert!(!history.handle_state_transition(&connecting(1), Some(&location), time(1)));
+        assert!(history.handle_state_transition(&connecting(2), Some(&location), time(5)));
+        assert!(history.handle_state_transition(&connected(), None, time(7)));
+        // The tunnel goes down
+        assert!(history.handle_state_transition(&connecting(3), Some(&location), time(60)));
+        history.handle_state_transition(
+            &TunnelStateTransition::Error(ErrorState::new(ErrorStateCause::IsOffline, None)),
+            None,
+            time(61),
+        );
+        history.handle_state_transition(&connecting(1), Some(&location), time(120));
+        history.handle_state_transition(
+            &TunnelStateTransition::Error(ErrorState::new(ErrorStateCause::StartTunnelError, None)),
+            None,
+            time(121),
+        );
+
+        let connections = history.connections();
+        assert_eq!(connections.len(), 3);
+        assert_eq!(connections[0].attempts, 2);
+        assert_eq!(connections[0].relay.as_deref(), Some("se-got-wg-001"));
+        assert_eq!(connections[0].time_to_connect(), Some(Duration::seconds(7)));
+        assert_eq!(connections[0].ended_at, Some(time(60)));
+        assert_eq!(
+            connections[0].outcome,
+            Some(ConnectionOutcome::ConnectionLost)
+        );
+        assert_eq!(connections[1].outcome, Some(ConnectionOutcome::Offline));
+        assert_eq!(
+            connections[2].outcome,
+            Some(ConnectionOutcome::Error {
+                code: ErrorCode::StartTunnelFailed.code(),
+                description: ErrorStateCause::StartTunnelError.to_string(),
+            })
+        );
+        assert_eq!(connections[2].time_to_connect(), None);
+    }
+
+    #[test]
+    fn test_parameters_are_recorded_without_addresses() {
+        let mut history = ConnectionHistory::new(CAPACITY);
+        history.handle_state_transition(&connecting(1), None, time(0));
+        assert_eq!(
+            history.connections()[0].parameters,
+            ConnectionParameters {
+                tunnel_type: TunnelType::Wireguard,
+                protocol: TransportProtocol::Udp,
+                port: 51820,
+                obfuscation: None,
+                quantum_resistant: false,
+            }
+        );
+        let content = history.serialize().unwrap();
+        assert!(!content.contains("192.0.2.1"));
+    }
+
+    #[test]
+    fn test_error_descriptions_are_redacted() {
+        let mut history = ConnectionHistory::new(CAPACITY);
+        history.handle_state_transition(&connecting(1), None, time(0));
+        history.handle_state_transition(
+            &TunnelStateTransition::Error(ErrorState::new(
+                ErrorStateCause::AuthFailed(Some("Rejected 1234123412341234".to_owned())),
+                None,
+            )),
+            None,
+            time(1),
+        );
+        let Some(ConnectionOutcome::Error { description, .. }) = &history.connections()[0].outcome
+        else {
+            panic!("Expected an error outcome");
+        };
+        assert!(!description.contains("1234123412341234"));
+        assert!(description.contains("[REDACTED ACCOUNT NUMBER]"));
+    }
+
+    #[test]
+    fn test_serialization_round_trip() {
+        let mut history = ConnectionHistory::new(CAPACITY);
+        record_connection(&mut history, 0, "se-got-wg-001");
+        // The daemon stops while this connection is ongoing
+        history.handle_state_transition(&connecting(1), None, time(100));
+
+        let content = history.serialize().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json["version"], FORMAT_VERSION);
+
+        let parsed = ConnectionHistory::parse(&content, CAPACITY).unwrap();
+        let connections = parsed.connections();
+        assert_eq!(connections[0], history.connections()[0]);
+        assert_eq!(connections[1].outcome, Some(ConnectionOutcome::Interrupted));
+        assert_eq!(connections[1].ended_at, None);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let content = r#"{ "version": 2, "connections": [{ "format": "unknown" }] }"#;
+        assert!(matches!(
+            ConnectionHistory::parse(content, CAPACITY),
+            Err(Error::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            ConnectionHistory::parse(r#"{ "connections": [] }"#, CAPACITY),
+            Err(Error::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_history_is_truncated_when_loaded() {
+        let mut history = ConnectionHistory::new(5);
+        for i in 0..5 {
+            record_connection(&mut history, i * 100, &format!("se-got-wg-00{i}"));
+        }
+        let parsed = ConnectionHistory::parse(&history.serialize().unwrap(), 2).unwrap();
+        let relays: Vec<_> = parsed
+            .connections()
+            .into_iter()
+            .map(|connection| connection.relay.unwrap())
+            .collect();
+        assert_eq!(relays, ["se-got-wg-003", "se-got-wg-004"]);
+    }
+
+    #[tokio::test]
+    async fn test_clear_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let other_file = dir.path().join("relays.json");
+        std::fs::write(&other_file, "{}").unwrap();
+        let mut store = ConnectionHistoryStore::load(dir.path()).await;
+        store
+            .handle_state_transition(&connecting(1), Some(&location("se-got-wg-001")))
+            .await;
+        assert!(dir.path().join(CONNECTION_HISTORY_FILE).exists());
+
+        store.clear().await.unwrap();
+        assert!(store.connections().is_empty());
+        assert!(!dir.path().join(CONNECTION_HISTORY_FILE).exists());
+        assert_eq!(std::fs::read_to_string(&other_file).unwrap(), "{}");
+        assert!(ConnectionHistoryStore::load(dir.path())
+            .await
+            .connections()
+            .is_empty());
+
+        // Connections are recorded again afterwards.
+        store.handle_state_transition(&connecting(1), None).await;
+        assert_eq!(store.connections().len(), 1);
+        store.clear().await.unwrap();
+    }
+}
@@ -1,10 +1,12 @@
-use crate::{new_selector_config, Daemon, Error, EventListener};
+use crate::{Daemon, Error, EventListener};
 use mullvad_types::{
-    custom_list::{CustomList, Id},
+    custom_list::{CustomList, CustomListsSettings, Id},
     relay_constraints::{
-        BridgeSettings, BridgeState, Constraint, LocationConstraint, RelaySettings,
+        BridgeSettings, BridgeState, Constraint, GeographicLocationConstraint, LocationConstraint,
+        RelaySettings,
     },
 };
+use std::collections::BTreeSet;
 use talpid_types::net::TunnelType;
 
 impl<L> Daemon<L>
@@ -24,6 +26,7 @@ where
         let new_list = CustomList::new(name);
         let id = new_list.id;
 
+        let old_lists = self.settings.custom_lists.clone();
         let settings_changed = self
             .settings
             .update(|settings| {
@@ -35,8 +38,7 @@ where
         if let Ok(true) = settings_changed {
             self.event_listener
                 .notify_settings(self.settings.to_settings());
-            self.relay_selector
-                .set_config(new_selector_config(&self.settings));
+            self.apply_custom_lists(&old_lists);
         }
 
         settings_changed?;
@@ -52,6 +54,7 @@ where
         else {
             return Err(Error::CustomListNotFound);
         };
+        let old_lists = self.settings.custom_lists.clone();
         let settings_changed = self
             .settings
             .update(|settings| {
@@ -65,11 +68,8 @@ where
         if let Ok(true) = settings_changed {
             self.event_listener
                 .notify_settings(self.settings.to_settings());
-            self.relay_selector
-                .set_config(new_selector_config(&self.settings));
-
-            if self.change_should_cause_reconnect(id) {
-                log::info!("Initiating tunnel restart because a selected custom list was deleted");
+            if self.apply_custom_lists(&old_lists) {
+                log::info!("Initiating tunnel restart because a selected custom list changed");
                 self.reconnect_tunnel();
             }
         }
@@ -88,7 +88,6 @@ where
         else {
             return Err(Error::CustomListNotFound);
         };
-        let id = old_list.id;
 
         if old_list.name != new_list.name
             && self
@@ -100,6 +99,7 @@ where
             return Err(Error::CustomListExists);
         }
 
+        let old_lists = self.settings.custom_lists.clone();
         let settings_changed = self
             .settings
             .update(|settings| {
@@ -111,10 +111,7 @@ where
         if let Ok(true) = settings_changed {
             self.event_listener
                 .notify_settings(self.settings.to_settings());
-            self.relay_selector
-                .set_config(new_selector_config(&self.settings));
-
-            if self.change_should_cause_reconnect(id) {
+            if self.apply_custom_lists(&old_lists) {
                 log::info!("Initiating tunnel restart because a selected custom list changed");
                 self.reconnect_tunnel();
             }
@@ -124,6 +121,59 @@ where
         Ok(())
     }
 
+    /// Replaces the locations of the custom list called `name`, or creates the list if there is
+    /// none. This is done in a single settings update, so that clients never see a list that is
+    /// only partially imported.
+    pub async fn replace_custom_list(
+        &mut self,
+        name: String,
+        locations: BTreeSet<GeographicLocationConstraint>,
+    ) -> Result<Id, Error> {
+        let list_index = self
+            .settings
+            .custom_lists
+            .iter()
+            .position(|list| list.name == name);
+        let mut new_list = match list_index {
+            Some(list_index) => self.settings.custom_lists[list_index].clone(),
+            None => CustomList::new(name),
+        };
+        let id = new_list.id;
+        new_list.locations = locations;
+
+        let old_lists = self.settings.custom_lists.clone();
+        let settings_changed = self
+            .settings
+            .update(|settings| match list_index {
+                Some(list_index) => settings.custom_lists[list_index] = new_list,
+                None => settings.custom_lists.add(new_list),
+            })
+            .await
+            .map_err(Error::SettingsError);
+
+        if let Ok(true) = settings_changed {
+            self.event_listener
+                .notify_settings(self.settings.to_settings());
+            if self.apply_custom_lists(&old_lists) {
+                log::info!("Initiating tunnel restart because a selected custom list changed");
+                self.reconnect_tunnel();
+            }
+        }
+
+        settings_changed?;
+        Ok(id)
+    }
+
+    /// Applies custom lists that replaced `old_lists`. Returns whether the tunnel must reconnect,
+    /// which is the case if a list that it uses was changed or removed.
+    pub(crate) fn apply_custom_lists(&mut self, old_lists: &CustomListsSettings) -> bool {
+        self.update_relay_selector_config();
+        old_lists
+            .iter()
+            .filter(|old_list| !self.settings.custom_lists.contains(old_list))
+            .any(|old_list| self.change_should_cause_reconnect(old_list.id))
+    }
+
     fn change_should_cause_reconnect(&self, custom_list_id: Id) -> bool {
         use mullvad_types::states::TunnelState;
         let mut need_to_reconnect = false;
@@ -138,10 +188,14 @@ where
             if let TunnelState::Connecting {
                 endpoint,
                 location: _,
+                phase: _,
+                attempt: _,
             }
             | TunnelState::Connected {
                 endpoint,
                 location: _,
+                effective_dns: _,
+                first_hop: _,
             } = &self.tunnel_state
             {
                 match endpoint.tunnel_type {
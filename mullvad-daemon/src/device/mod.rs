@@ -4,6 +4,7 @@ use futures::{
     stream::StreamExt,
 };
 
+use crate::api_connectivity::ApiConnectivityMonitor;
 use mullvad_api::rest;
 use mullvad_types::{
     account::{AccountToken, VoucherSubmission},
@@ -22,7 +23,11 @@ use std::{
     time::{Duration, SystemTime},
 };
 use talpid_core::mpsc::Sender;
-use talpid_types::{net::TunnelType, tunnel::TunnelStateTransition, ErrorExt};
+use talpid_types::{
+    net::TunnelType,
+    tunnel::{ConnectingPhase, TunnelStateTransition},
+    ErrorExt,
+};
 use tokio::{
     fs,
     io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
@@ -59,6 +64,8 @@ pub enum Error {
     InvalidVoucher,
     #[error(display = "The voucher has already been used")]
     UsedVoucher,
+    #[error(display = "Too many requests were sent to the API")]
+    RateLimited(Option<Duration>),
     #[error(display = "Failed to read or write device cache")]
     DeviceIoError(#[error(source)] io::Error),
     #[error(display = "Failed parse device cache")]
@@ -298,6 +305,7 @@ type ResponseTx<T> = oneshot::Sender<Result<T, Error>>;
 enum AccountManagerCommand {
     Login(AccountToken, ResponseTx<()>),
     Logout(ResponseTx<()>),
+    DeviceRemoved(DeviceId, ResponseTx<()>),
     SetData(PrivateAccountAndDevice, ResponseTx<()>),
     GetData(ResponseTx<PrivateDeviceState>),
     GetDataAfterLogin(ResponseTx<PrivateDeviceState>),
@@ -326,6 +334,27 @@ impl AccountManagerHandle {
         self.send_command(AccountManagerCommand::Logout).await
     }
 
+    /// Log in to `token` unless the current device already belongs to it. Like any login, this
+    /// removes the current device and creates a new device and WireGuard key.
+    pub async fn switch_account(&self, token: AccountToken) -> Result<(), Error> {
+        let current_account = self
+            .data()
+            .await?
+            .into_device()
+            .map(|device| device.account_token);
+        if current_account.as_ref() == Some(&token) {
+            return Ok(());
+        }
+        self.login(token).await
+    }
+
+    /// Tell the account manager that `device_id` has been removed from its account. If it is the
+    /// current device, this logs out without trying to remove the device again.
+    pub async fn device_removed(&self, device_id: DeviceId) -> Result<(), Error> {
+        self.send_command(|tx| AccountManagerCommand::DeviceRemoved(device_id, tx))
+            .await
+    }
+
     pub async fn set(&self, data: PrivateAccountAndDevice) -> Result<(), Error> {
         self.send_command(|tx| AccountManagerCommand::SetData(data, tx))
             .await
@@ -389,6 +418,7 @@ pub(crate) struct AccountManager {
     device_service: DeviceService,
     data: PrivateDeviceState,
     rotation_interval: RotationInterval,
+    api_connectivity: ApiConnectivityMonitor,
     listeners: Vec<Box<dyn Sender<AccountEvent> + Send>>,
     last_validation: Option<SystemTime>,
     validation_requests: Vec<ResponseTx<()>>,
@@ -404,6 +434,7 @@ impl AccountManager {
         rest_handle: rest::MullvadRestHandle,
         settings_dir: &Path,
         initial_rotation_interval: RotationInterval,
+        api_connectivity: ApiConnectivityMonitor,
         listener_tx: impl Sender<AccountEvent> + Send + 'static,
     ) -> Result<(AccountManagerHandle, PrivateDeviceState), Error> {
         let (cacher, data) = DeviceCacher::new(settings_dir).await?;
@@ -421,6 +452,7 @@ impl AccountManager {
             device_service: device_service.clone(),
             data: data.clone(),
             rotation_interval: initial_rotation_interval,
+            api_connectivity,
             listeners: vec![Box::new(listener_tx)],
             last_validation: None,
             validation_requests: vec![],
@@ -469,6 +501,24 @@ impl AccountManager {
                             current_api_call.clear();
                             self.logout(tx).await;
                         }
+                        Some(AccountManagerCommand::DeviceRemoved(device_id, tx)) => {
+                            let is_current_device = self
+                                .data
+                                .device()
+                                .map(|data| data.device.id == device_id)
+                                .unwrap_or(false);
+                            if is_current_device {
+                                log::debug!("The current device was removed from the account");
+                                current_api_call.clear();
+                                Self::drain_requests(
+                                    &mut self.data_requests,
+                                    || Err(Error::AccountChange),
+                                );
+                                let _ = tx.send(self.set_logged_out().await.map(|_| ()));
+                            } else {
+                                let _ = tx.send(Ok(()));
+                            }
+                        }
                         Some(AccountManagerCommand::SetData(data, tx)) => {
                             let _ = tx.send(self.set(PrivateDeviceEvent::Login(data)).await);
                         }
@@ -822,11 +872,18 @@ impl AccountManager {
         let key_rotation_timer = self.key_rotation_timer(config.device.wg_data.created);
 
         let device_service = self.device_service.clone();
+        let api_connectivity = self.api_connectivity.clone();
         let account_token = config.account_token.clone();
         let device_id = config.device.id.clone();
 
         Some(async move {
             key_rotation_timer.await;
+            // Requests that are sent while the API is unreachable are likely to fail, and are
+            // retried a day later. Other requests will tell when the API can be reached again.
+            if api_connectivity.is_unreachable() {
+                log::debug!("Postponing key rotation until the API is reachable");
+                api_connectivity.wait_until_not_unreachable().await;
+            }
             device_service
                 .rotate_key_with_backoff(account_token, device_id)
                 .await
@@ -861,18 +918,13 @@ impl AccountManager {
             let _ = tx.send(Ok(()));
             return;
         }
-        if let Err(err) = self.cacher.write(&PrivateDeviceState::LoggedOut).await {
-            let _ = tx.send(Err(err));
-            return;
-        }
-
-        let old_config = self.data.logout();
-
-        self.listeners.retain(|listener| {
-            listener
-                .send(AccountEvent::Device(PrivateDeviceEvent::Logout))
-                .is_ok()
-        });
+        let old_config = match self.set_logged_out().await {
+            Ok(old_config) => old_config,
+            Err(err) => {
+                let _ = tx.send(Err(err));
+                return;
+            }
+        };
 
         if let Some(old_config) = old_config {
             let logout_call = tokio::spawn(Box::pin(self.logout_api_call(old_config)));
@@ -887,6 +939,22 @@ impl AccountManager {
         }
     }
 
+    /// Enters the logged out state without removing the device from the account. Returns the
+    /// device that was logged in, if any.
+    async fn set_logged_out(&mut self) -> Result<Option<PrivateAccountAndDevice>, Error> {
+        self.cacher.write(&PrivateDeviceState::LoggedOut).await?;
+
+        let old_config = self.data.logout();
+
+        self.listeners.retain(|listener| {
+            listener
+                .send(AccountEvent::Device(PrivateDeviceEvent::Logout))
+                .is_ok()
+        });
+
+        Ok(old_config)
+    }
+
     fn logout_api_call(&self, data: PrivateAccountAndDevice) -> impl Future<Output = ()> + 'static {
         let service = self.device_service.clone();
 
@@ -1113,14 +1181,16 @@ impl DeviceCacher {
 /// after multiple attempts.
 pub(crate) struct TunnelStateChangeHandler {
     manager: AccountManagerHandle,
+    api_connectivity: ApiConnectivityMonitor,
     check_validity: Arc<AtomicBool>,
     wg_retry_attempt: usize,
 }
 
 impl TunnelStateChangeHandler {
-    pub fn new(manager: AccountManagerHandle) -> Self {
+    pub fn new(manager: AccountManagerHandle, api_connectivity: ApiConnectivityMonitor) -> Self {
         Self {
             manager,
+            api_connectivity,
             check_validity: Arc::new(AtomicBool::new(true)),
             wg_retry_attempt: 0,
         }
@@ -1128,12 +1198,19 @@ impl TunnelStateChangeHandler {
 
     pub fn handle_state_transition(&mut self, new_state: &TunnelStateTransition) {
         match new_state {
-            TunnelStateTransition::Connecting(endpoint) => {
-                if endpoint.tunnel_type != TunnelType::Wireguard {
+            TunnelStateTransition::Connecting(endpoint, phase, _) => {
+                // Attempts that wait for the network are counted once they proceed
+                if endpoint.tunnel_type != TunnelType::Wireguard
+                    || *phase != ConnectingPhase::EstablishingTunnel
+                {
                     return;
                 }
                 self.wg_retry_attempt = self.wg_retry_attempt.wrapping_add(1);
-                if self.wg_retry_attempt % WG_DEVICE_CHECK_THRESHOLD == 0 {
+                // The check cannot succeed while the API is unreachable, so it is left for a
+                // later attempt
+                if self.wg_retry_attempt % WG_DEVICE_CHECK_THRESHOLD == 0
+                    && !self.api_connectivity.is_unreachable()
+                {
                     let handle = self.manager.clone();
                     let check_validity = self.check_validity.clone();
                     tokio::spawn(async move {
@@ -1155,7 +1232,7 @@ impl TunnelStateChangeHandler {
                 }
             }
             TunnelStateTransition::Error(_)
-            | TunnelStateTransition::Connected(_)
+            | TunnelStateTransition::Connected(..)
             | TunnelStateTransition::Disconnected => {
                 self.check_validity.store(true, Ordering::SeqCst);
                 self.wg_retry_attempt = 0;
@@ -1169,3 +1246,326 @@ impl TunnelStateChangeHandler {
         handle.check_expiry().await.map(|_expiry| ())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account_history::AccountHistory;
+    use mullvad_api::proxy::ApiConnectionMode;
+    use mullvad_types::wireguard::AssociatedAddresses;
+    use once_cell::sync::Lazy;
+    use serde_json::json;
+    use std::sync::Mutex;
+    use talpid_types::net::wireguard::{PrivateKey, PublicKey};
+    use tokio::io::AsyncBufReadExt;
+
+    const ACCOUNT: &str = "1234123412341234";
+    const OTHER_ACCOUNT: &str = "5678567856785678";
+
+    /// Requests that changed devices on the mock API.
+    #[derive(Default)]
+    struct MockApiLog {
+        /// Devices that were created, with their accounts and keys.
+        created: Vec<(AccountToken, DeviceId, PublicKey)>,
+        /// Devices that were removed, with their accounts.
+        removed: Vec<(AccountToken, DeviceId)>,
+    }
+
+    /// Serves the token, device and account endpoints of the API over plain HTTP. The API
+    /// endpoint is global, so all tests share this server and tell their requests apart by
+    /// account number.
+    static MOCK_API: Lazy<Arc<Mutex<MockApiLog>>> = Lazy::new(|| {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        mullvad_api::API
+            .override_init(mullvad_api::ApiEndpoint {
+                host: "api.mullvad.net".to_owned(),
+                addr: listener.local_addr().unwrap(),
+                disable_address_cache: true,
+                disable_tls: true,
+                force_direct_connection: true,
+            })
+            .expect("the API endpoint was used before the mock API was started");
+
+        let log = Arc::new(Mutex::new(MockApiLog::default()));
+        let server_log = log.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio::spawn(serve_mock_api(stream, server_log.clone()));
+                }
+            });
+        });
+        log
+    });
+
+    async fn serve_mock_api(stream: tokio::net::TcpStream, log: Arc<Mutex<MockApiLog>>) {
+        let mut stream = io::BufReader::new(stream);
+        loop {
+            let mut request_line = String::new();
+            if stream.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let mut content_length = 0;
+            let mut account = None;
+            loop {
+                let mut header = String::new();
+                stream.read_line(&mut header).await.unwrap();
+                let Some((name, value)) = header.trim_end().split_once(':') else {
+                    break;
+                };
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                } else if name.eq_ignore_ascii_case("authorization") {
+                    account = value
+                        .trim()
+                        .strip_prefix("Bearer access-")
+                        .map(str::to_owned);
+                }
+            }
+            let mut body = vec![0; content_length];
+            stream.read_exact(&mut body).await.unwrap();
+
+            let mut request_line = request_line.split_whitespace();
+            let method = request_line.next().unwrap();
+            let path = request_line.next().unwrap();
+            let (status, response) =
+                respond_mock_api(method, path, account, &body, &mut log.lock().unwrap());
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{response}",
+                response.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    fn respond_mock_api(
+        method: &str,
+        path: &str,
+        account: Option<AccountToken>,
+        body: &[u8],
+        log: &mut MockApiLog,
+    ) -> (&'static str, String) {
+        let request: serde_json::Value = serde_json::from_slice(body).unwrap_or_default();
+        match (method, path) {
+            ("POST", "/auth/v1/token") => {
+                let account = request["account_number"].as_str().unwrap();
+                let response = json!({
+                    "access_token": format!("access-{account}"),
+                    "expiry": "2100-01-01T00:00:00Z",
+                });
+                ("200 OK", response.to_string())
+            }
+            ("POST", "/accounts/v1/devices") => {
+                let number = log.created.len() + 1;
+                let id = format!("mock-device-{number}");
+                let pubkey = serde_json::from_value(request["pubkey"].clone()).unwrap();
+                log.created.push((account.unwrap(), id.clone(), pubkey));
+                let response = json!({
+                    "id": id,
+                    "name": format!("mock device {number}"),
+                    "pubkey": request["pubkey"],
+                    "ipv4_address": "10.64.0.2/32",
+                    "ipv6_address": "fc00:bbbb:bbbb:bb01::2/128",
+                    "hijack_dns": false,
+                    "created": "2024-01-01T00:00:00Z",
+                });
+                ("201 Created", response.to_string())
+            }
+            ("DELETE", path) if path.starts_with("/accounts/v1/devices/") => {
+                let id = path.trim_start_matches("/accounts/v1/devices/").to_owned();
+                log.removed.push((account.unwrap(), id));
+                ("204 No Content", String::new())
+            }
+            ("GET", "/accounts/v1/accounts/me") => {
+                let response = json!({ "expiry": "2100-01-01T00:00:00Z" });
+                ("200 OK", response.to_string())
+            }
+            _ => ("404 Not Found", json!({ "code": "NOT_FOUND" }).to_string()),
+        }
+    }
+
+    /// Returns an API runtime whose requests go to the mock API.
+    fn mock_api_runtime() -> mullvad_api::Runtime {
+        Lazy::force(&MOCK_API);
+        mullvad_api::Runtime::new(tokio::runtime::Handle::current()).unwrap()
+    }
+
+    fn mock_api_requests_for(account: &str) -> (Vec<DeviceId>, Vec<DeviceId>) {
+        let log = MOCK_API.lock().unwrap();
+        let created = log.created.iter().filter(|(token, ..)| token == account);
+        let removed = log.removed.iter().filter(|(token, _)| token == account);
+        (
+            created.map(|(_, id, _)| id.clone()).collect(),
+            removed.map(|(_, id)| id.clone()).collect(),
+        )
+    }
+
+    fn logged_in_device(id: &str) -> PrivateAccountAndDevice {
+        PrivateAccountAndDevice {
+            account_token: ACCOUNT.to_owned(),
+            device: PrivateDevice {
+                id: id.to_owned(),
+                name: "test device".to_owned(),
+                wg_data: WireguardData {
+                    private_key: PrivateKey::new_from_random(),
+                    addresses: AssociatedAddresses {
+                        ipv4_address: "10.64.0.1/32".parse().unwrap(),
+                        ipv6_address: "fc00:bbbb:bbbb:bb01::1/128".parse().unwrap(),
+                    },
+                    created: Utc::now(),
+                },
+                hijack_dns: false,
+                created: Utc::now(),
+            },
+        }
+    }
+
+    /// Spawns an account manager which is logged in on `device`. `runtime` should be created by
+    /// [`mock_api_runtime`].
+    async fn spawn_account_manager(
+        runtime: &mullvad_api::Runtime,
+        settings_dir: &Path,
+        device: PrivateAccountAndDevice,
+    ) -> (AccountManagerHandle, mpsc::UnboundedReceiver<AccountEvent>) {
+        let rest_handle = runtime
+            .mullvad_rest_handle(
+                ApiConnectionMode::Direct.into_repeat(),
+                |_| async { true },
+                |_| (),
+            )
+            .await;
+        let (event_tx, event_rx) = mpsc::unbounded();
+        let (handle, _) = AccountManager::spawn(
+            rest_handle,
+            settings_dir,
+            RotationInterval::default(),
+            ApiConnectivityMonitor::new(),
+            event_tx,
+        )
+        .await
+        .unwrap();
+        handle.set(device).await.unwrap();
+        (handle, event_rx)
+    }
+
+    fn received_logout(event_rx: &mut mpsc::UnboundedReceiver<AccountEvent>) -> bool {
+        std::iter::from_fn(|| event_rx.try_next().ok().flatten())
+            .any(|event| matches!(event, AccountEvent::Device(PrivateDeviceEvent::Logout)))
+    }
+
+    #[tokio::test]
+    async fn test_remove_current_device() {
+        let runtime = mock_api_runtime();
+        let settings_dir = tempfile::tempdir().unwrap();
+        let (handle, mut event_rx) =
+            spawn_account_manager(&runtime, settings_dir.path(), logged_in_device("current")).await;
+
+        handle.device_removed("current".to_owned()).await.unwrap();
+
+        assert_eq!(handle.data().await.unwrap(), PrivateDeviceState::LoggedOut);
+        assert!(received_logout(&mut event_rx));
+
+        // The logged out state is also saved to disk.
+        handle.shutdown().await;
+        let (_cacher, state) = DeviceCacher::new(settings_dir.path()).await.unwrap();
+        assert_eq!(state, PrivateDeviceState::LoggedOut);
+    }
+
+    #[tokio::test]
+    async fn test_remove_other_device() {
+        let runtime = mock_api_runtime();
+        let settings_dir = tempfile::tempdir().unwrap();
+        let device = logged_in_device("current");
+        let (handle, mut event_rx) =
+            spawn_account_manager(&runtime, settings_dir.path(), device.clone()).await;
+
+        handle.device_removed("other".to_owned()).await.unwrap();
+
+        assert_eq!(
+            handle.data().await.unwrap(),
+            PrivateDeviceState::LoggedIn(device)
+        );
+        assert!(!received_logout(&mut event_rx));
+    }
+
+    #[tokio::test]
+    async fn test_switch_account() {
+        let runtime = mock_api_runtime();
+        let settings_dir = tempfile::tempdir().unwrap();
+        let device = logged_in_device("switched-from");
+        let (handle, mut event_rx) =
+            spawn_account_manager(&runtime, settings_dir.path(), device.clone()).await;
+
+        let mut history = AccountHistory::new(settings_dir.path(), Some(ACCOUNT.to_owned()))
+            .await
+            .unwrap();
+        history.set(OTHER_ACCOUNT.to_owned(), None).await.unwrap();
+        history
+            .set_label(OTHER_ACCOUNT, Some("work".to_owned()))
+            .await
+            .unwrap();
+        let account = history.find("work").unwrap().account_token.clone();
+
+        handle.switch_account(account).await.unwrap();
+
+        let new_device = handle.data().await.unwrap().into_device().unwrap();
+        assert_eq!(new_device.account_token, OTHER_ACCOUNT);
+        assert_ne!(new_device.device.id, device.device.id);
+        let (created, _) = mock_api_requests_for(OTHER_ACCOUNT);
+        assert_eq!(created, vec![new_device.device.id.clone()]);
+        assert!(MOCK_API
+            .lock()
+            .unwrap()
+            .created
+            .iter()
+            .any(|(_, id, pubkey)| {
+                id == &new_device.device.id
+                    && pubkey == &new_device.device.wg_data.private_key.public_key()
+            }));
+
+        // The old device is removed in the background
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !mock_api_requests_for(ACCOUNT).1.contains(&device.device.id) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("the old device was not removed");
+
+        let logged_in = std::iter::from_fn(|| event_rx.try_next().ok().flatten()).any(|event| {
+            matches!(event, AccountEvent::Device(PrivateDeviceEvent::Login(login)) if login == new_device)
+        });
+        assert!(logged_in);
+    }
+
+    #[tokio::test]
+    async fn test_switch_to_current_account() {
+        let runtime = mock_api_runtime();
+        let settings_dir = tempfile::tempdir().unwrap();
+        let device = logged_in_device("not-switched");
+        let (handle, mut event_rx) =
+            spawn_account_manager(&runtime, settings_dir.path(), device.clone()).await;
+        // Skip the login on `device`
+        while event_rx.try_next().ok().flatten().is_some() {}
+
+        handle.switch_account(ACCOUNT.to_owned()).await.unwrap();
+
+        assert_eq!(
+            handle.data().await.unwrap(),
+            PrivateDeviceState::LoggedIn(device.clone())
+        );
+        let (created, removed) = mock_api_requests_for(ACCOUNT);
+        assert!(created.is_empty());
+        assert!(!removed.contains(&device.device.id));
+        assert!(std::iter::from_fn(|| event_rx.try_next().ok().flatten())
+            .next()
+            .is_none());
+    }
+}
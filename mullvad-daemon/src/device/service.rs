@@ -10,14 +10,13 @@ use mullvad_types::{
 use talpid_types::net::wireguard::PrivateKey;
 
 use super::{Error, PrivateAccountAndDevice, PrivateDevice};
+use mullvad_api::retry::{retry_request, RetryPolicy};
 use mullvad_api::{
     availability::ApiAvailabilityHandle,
     rest::{self, Error as RestError, MullvadRestHandle},
     AccountsProxy, DevicesProxy,
 };
-use talpid_core::future_retry::{retry_future, ConstantInterval, ExponentialBackoff, Jittered};
-/// Retry strategy used for user-initiated actions that require immediate feedback
-const RETRY_ACTION_STRATEGY: ConstantInterval = ConstantInterval::new(Duration::ZERO, Some(3));
+use talpid_core::future_retry::{retry_future, ExponentialBackoff, Jittered};
 /// Retry strategy used for background tasks
 const RETRY_BACKOFF_STRATEGY: Jittered<ExponentialBackoff> = Jittered::jitter(
     ExponentialBackoff::new(Duration::from_secs(4), 5)
@@ -50,10 +49,12 @@ impl DeviceService {
         let api_handle = self.api_availability.clone();
         let token_copy = account_token.clone();
         async move {
-            let (device, addresses) = retry_future(
+            // Creating a device is not idempotent. If the response to a successful request is
+            // lost, a retry would add a second device to the account.
+            let (device, addresses) = retry_request(
+                RetryPolicy::NO_RETRY,
                 move || proxy.create(token_copy.clone(), pubkey.clone()),
                 move |result| should_retry(result, &api_handle),
-                RETRY_ACTION_STRATEGY,
             )
             .await
             .map_err(map_rest_error)?;
@@ -120,10 +121,10 @@ impl DeviceService {
     ) -> Result<(), Error> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        retry_future(
+        retry_request(
+            RetryPolicy::INTERACTIVE,
             move || proxy.remove(token.clone(), device.clone()),
             move |result| should_retry(result, &api_handle),
-            RETRY_ACTION_STRATEGY,
         )
         .await
         .map_err(map_rest_error)?;
@@ -161,10 +162,10 @@ impl DeviceService {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
         let pubkey = private_key.public_key();
-        let addresses = retry_future(
+        let addresses = retry_request(
+            RetryPolicy::INTERACTIVE,
             move || proxy.replace_wg_key(token.clone(), device.clone(), pubkey.clone()),
             move |result| should_retry(result, &api_handle),
-            RETRY_ACTION_STRATEGY,
         )
         .await
         .map_err(map_rest_error)?;
@@ -213,10 +214,10 @@ impl DeviceService {
     pub async fn list_devices(&self, token: AccountToken) -> Result<Vec<Device>, Error> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        retry_future(
+        retry_request(
+            RetryPolicy::INTERACTIVE,
             move || proxy.list(token.clone()),
             move |result| should_retry(result, &api_handle),
-            RETRY_ACTION_STRATEGY,
         )
         .await
         .map_err(map_rest_error)
@@ -241,10 +242,10 @@ impl DeviceService {
     pub async fn get(&self, token: AccountToken, device: DeviceId) -> Result<Device, Error> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        retry_future(
+        retry_request(
+            RetryPolicy::INTERACTIVE,
             move || proxy.get(token.clone(), device.clone()),
             move |result| should_retry(result, &api_handle),
-            RETRY_ACTION_STRATEGY,
         )
         .await
         .map_err(map_rest_error)
@@ -262,10 +263,12 @@ impl AccountService {
     pub fn create_account(&self) -> impl Future<Output = Result<AccountToken, rest::Error>> {
         let mut proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        retry_future(
+        // Creating an account is not idempotent. A retry after a lost response would create a
+        // second account.
+        retry_request(
+            RetryPolicy::NO_RETRY,
             move || proxy.create_account(),
             move |result| should_retry(result, &api_handle),
-            RETRY_ACTION_STRATEGY,
         )
     }
 
@@ -275,20 +278,20 @@ impl AccountService {
     ) -> impl Future<Output = Result<String, rest::Error>> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        retry_future(
+        retry_request(
+            RetryPolicy::INTERACTIVE,
             move || proxy.get_www_auth_token(account.clone()),
             move |result| should_retry(result, &api_handle),
-            RETRY_ACTION_STRATEGY,
         )
     }
 
     pub async fn check_expiry(&self, token: AccountToken) -> Result<DateTime<Utc>, rest::Error> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        let result = retry_future(
+        let result = retry_request(
+            RetryPolicy::INTERACTIVE,
             move || proxy.get_expiry(token.clone()),
             move |result| should_retry(result, &api_handle),
-            RETRY_ACTION_STRATEGY,
         )
         .await;
         if handle_expiry_result_inner(&result, &self.api_availability) {
@@ -308,10 +311,12 @@ impl AccountService {
     ) -> Result<VoucherSubmission, Error> {
         let mut proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        let result = retry_future(
+        // A voucher can only be used once, so a retry after a lost response would report it as
+        // used.
+        let result = retry_request(
+            RetryPolicy::NO_RETRY,
             move || proxy.submit_voucher(account_token.clone(), voucher.clone()),
             move |result| should_retry(result, &api_handle),
-            RETRY_ACTION_STRATEGY,
         )
         .await;
         if result.is_ok() {
@@ -416,6 +421,7 @@ fn map_rest_error(error: rest::Error) -> Error {
             mullvad_api::VOUCHER_USED => Error::UsedVoucher,
             _ => Error::OtherRestError(error),
         },
+        RestError::RateLimited(retry_after) => Error::RateLimited(retry_after),
         error => Error::OtherRestError(error),
     }
 }
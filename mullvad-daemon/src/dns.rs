@@ -1,5 +1,6 @@
-use mullvad_types::settings::{DnsOptions, DnsState};
-use std::net::{IpAddr, Ipv4Addr};
+use mullvad_types::settings::{DnsOptions, DnsState, Settings};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use talpid_types::net::{DnsSource, TlsDnsServer};
 
 /// When we want to block certain contents with the help of DNS server side,
 /// we compute the resolver IP to use based on these constants. The last
@@ -12,6 +13,12 @@ const DNS_ADULT_BLOCKING_IP_BIT: u8 = 1 << 3; // 0b00001000
 const DNS_GAMBLING_BLOCKING_IP_BIT: u8 = 1 << 4; // 0b00010000
 const DNS_SOCIAL_MEDIA_BLOCKING_IP_BIT: u8 = 1 << 5; // 0b00100000
 
+/// Resolver at the tunnel gateway, which is used when no other resolvers are requested.
+pub const TUNNEL_GATEWAY_RESOLVER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1));
+/// IPv6 address of the resolver at the tunnel gateway.
+const TUNNEL_GATEWAY_RESOLVER_V6: IpAddr =
+    IpAddr::V6(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1));
+
 /// Return the resolvers as a vector of `IpAddr`s. Returns `None` when no special resolvers
 /// are requested and the tunnel default gateway should be used.
 pub fn addresses_from_options(options: &DnsOptions) -> Option<Vec<IpAddr>> {
@@ -57,3 +64,70 @@ pub fn addresses_from_options(options: &DnsOptions) -> Option<Vec<IpAddr>> {
         }
     }
 }
+
+/// Returns whether `address` is one of the content blocking resolvers that
+/// [`addresses_from_options`] computes. These can only be reached through the tunnel.
+fn is_content_blocking_address(address: &IpAddr) -> bool {
+    const ALL_BLOCKING_BITS: u8 = DNS_AD_BLOCKING_IP_BIT
+        | DNS_TRACKER_BLOCKING_IP_BIT
+        | DNS_MALWARE_BLOCKING_IP_BIT
+        | DNS_ADULT_BLOCKING_IP_BIT
+        | DNS_GAMBLING_BLOCKING_IP_BIT
+        | DNS_SOCIAL_MEDIA_BLOCKING_IP_BIT;
+
+    match address {
+        IpAddr::V4(address) => {
+            let [first, second, third, last_byte] = address.octets();
+            let base = DNS_BLOCKING_IP_BASE.octets();
+            [first, second, third] == base[..3]
+                && last_byte != 0
+                && last_byte & !ALL_BLOCKING_BITS == 0
+        }
+        IpAddr::V6(_) => false,
+    }
+}
+
+/// Returns whether `address` can only be reached through the tunnel, either because it is the
+/// resolver at the tunnel gateway or one of the content blocking resolvers.
+pub fn is_tunnel_only_address(address: &IpAddr) -> bool {
+    *address == TUNNEL_GATEWAY_RESOLVER
+        || *address == TUNNEL_GATEWAY_RESOLVER_V6
+        || is_content_blocking_address(address)
+}
+
+/// Return the DNS-over-TLS servers to use. These take precedence over the resolvers returned by
+/// [`addresses_from_options`].
+pub fn tls_servers_from_options(options: &DnsOptions) -> Vec<TlsDnsServer> {
+    match options.state {
+        DnsState::Custom => options.custom_options.tls_servers.clone(),
+        DnsState::Default => vec![],
+    }
+}
+
+/// Log a warning if any custom DNS server is on the LAN while LAN traffic is blocked, since such
+/// servers cannot be reached in the blocked states.
+pub fn warn_about_unreachable_lan_servers(settings: &Settings) {
+    let servers = settings.unreachable_lan_dns_servers();
+    if !servers.is_empty() {
+        log::warn!(
+            "Custom DNS servers on the LAN are unreachable in the blocked states while local \
+             network sharing is disabled: {}",
+            servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
+
+/// Return where the resolvers returned by [`addresses_from_options`] come from.
+pub fn source_from_options(options: &DnsOptions) -> DnsSource {
+    match options.state {
+        DnsState::Custom => DnsSource::Custom,
+        DnsState::Default if addresses_from_options(options).is_some() => {
+            DnsSource::ContentBlockers
+        }
+        DnsState::Default => DnsSource::Default,
+    }
+}
@@ -0,0 +1,406 @@
+//! A self-test that checks whether DNS lookups are leaking outside the tunnel.
+//!
+//! The test sends uniquely labelled lookups of a Mullvad-controlled test zone through the system
+//! resolver as well as directly to the in-tunnel resolvers. It then asks the API which resolvers
+//! the test zone observed queries from.
+
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
+use mullvad_api::rest::{self, RequestServiceHandle};
+use mullvad_types::dns_leak::{DnsLeakTestResult, DnsLeakVerdict, ObservedResolver};
+use std::{io, net::IpAddr, time::Duration};
+use talpid_types::ErrorExt;
+use trust_dns_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
+};
+
+use crate::geoip::MULLVAD_CONNCHECK_HOST;
+
+/// Number of uniquely labelled lookups to send through each resolver.
+const LOOKUPS_PER_RESOLVER: usize = 3;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "The DNS leak test can only be run while connected")]
+    NotConnected,
+
+    #[error(display = "Failed to fetch the resolvers observed by the test zone")]
+    FetchResolvers(#[error(source)] rest::Error),
+
+    #[error(display = "Timed out while fetching the resolvers observed by the test zone")]
+    Timeout,
+}
+
+/// Timeouts used by the DNS leak test.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Maximum time to wait for a single lookup. Lookups of the test zone are not expected to
+    /// succeed, so this only bounds how long an unresponsive resolver can stall the test.
+    pub lookup: Duration,
+    /// Time given to the test zone to register the lookups before asking which resolvers it saw.
+    pub registration_delay: Duration,
+    /// Maximum time to wait for the API to report the observed resolvers.
+    pub api: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            lookup: Duration::from_secs(5),
+            registration_delay: Duration::from_secs(1),
+            api: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Performs the network operations of a DNS leak test.
+pub trait LeakTestBackend {
+    /// Looks up `hostname` using the system resolver.
+    fn lookup_system(&self, hostname: String) -> BoxFuture<'static, io::Result<()>>;
+
+    /// Looks up `hostname` by querying `resolver` directly.
+    fn lookup_direct(
+        &self,
+        resolver: IpAddr,
+        hostname: String,
+    ) -> BoxFuture<'static, io::Result<()>>;
+
+    /// Fetches the resolvers that the test zone observed lookups from for `test_id`.
+    fn observed_resolvers(
+        &self,
+        test_id: String,
+    ) -> BoxFuture<'static, Result<Vec<ObservedResolver>, rest::Error>>;
+}
+
+/// Leak test backend that queries real resolvers and the Mullvad connection check API.
+pub struct ApiLeakTestBackend {
+    service: RequestServiceHandle,
+    lookup_timeout: Duration,
+}
+
+impl ApiLeakTestBackend {
+    pub fn new(service: RequestServiceHandle, timeouts: Timeouts) -> Self {
+        ApiLeakTestBackend {
+            service,
+            lookup_timeout: timeouts.lookup,
+        }
+    }
+}
+
+impl LeakTestBackend for ApiLeakTestBackend {
+    fn lookup_system(&self, hostname: String) -> BoxFuture<'static, io::Result<()>> {
+        async move {
+            tokio::net::lookup_host((hostname.as_str(), 0))
+                .await
+                .map(|_| ())
+        }
+        .boxed()
+    }
+
+    fn lookup_direct(
+        &self,
+        resolver: IpAddr,
+        hostname: String,
+    ) -> BoxFuture<'static, io::Result<()>> {
+        let mut options = ResolverOpts::default();
+        options.attempts = 1;
+        options.cache_size = 0;
+        options.timeout = self.lookup_timeout;
+        let config = ResolverConfig::from_parts(
+            None,
+            vec![],
+            NameServerConfigGroup::from_ips_clear(&[resolver], 53, true),
+        );
+
+        async move {
+            TokioAsyncResolver::tokio(config, options)
+                .lookup_ip(hostname)
+                .await
+                .map(|_| ())
+                .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+        }
+        .boxed()
+    }
+
+    fn observed_resolvers(
+        &self,
+        test_id: String,
+    ) -> BoxFuture<'static, Result<Vec<ObservedResolver>, rest::Error>> {
+        let service = self.service.clone();
+        async move {
+            let uri = format!("https://{}/dnsleak/{test_id}", *MULLVAD_CONNCHECK_HOST);
+            let request = rest::RestRequest::get(&uri)?;
+            let response = service.request(request).await?;
+            rest::deserialize_body(response).await
+        }
+        .boxed()
+    }
+}
+
+/// Returns a new random identifier for a leak test.
+pub fn new_test_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Runs a DNS leak test. `tunnel_resolvers` are the resolvers that lookups are expected to be
+/// sent to. If `custom_dns` is set, lookups are forwarded by resolvers that aren't operated by
+/// Mullvad, so the result is inconclusive unless only Mullvad resolvers were observed.
+pub async fn run(
+    backend: &impl LeakTestBackend,
+    test_id: String,
+    tunnel_resolvers: &[IpAddr],
+    custom_dns: bool,
+    timeouts: Timeouts,
+) -> Result<DnsLeakTestResult, Error> {
+    let mut lookups = vec![];
+    for n in 0..LOOKUPS_PER_RESOLVER {
+        let hostname = test_hostname(&test_id, &format!("s{n}"));
+        lookups.push(bounded_lookup(
+            backend.lookup_system(hostname.clone()),
+            hostname,
+            timeouts.lookup,
+        ));
+        for (i, resolver) in tunnel_resolvers.iter().enumerate() {
+            let hostname = test_hostname(&test_id, &format!("t{i}-{n}"));
+            lookups.push(bounded_lookup(
+                backend.lookup_direct(*resolver, hostname.clone()),
+                hostname,
+                timeouts.lookup,
+            ));
+        }
+    }
+    future::join_all(lookups).await;
+
+    tokio::time::sleep(timeouts.registration_delay).await;
+
+    let observed = tokio::time::timeout(timeouts.api, backend.observed_resolvers(test_id))
+        .await
+        .map_err(|_elapsed| Error::Timeout)?
+        .map_err(Error::FetchResolvers)?;
+
+    Ok(evaluate(observed, custom_dns))
+}
+
+fn test_hostname(test_id: &str, label: &str) -> String {
+    format!("{test_id}-{label}.dnsleak.{}", *MULLVAD_CONNCHECK_HOST)
+}
+
+async fn bounded_lookup(
+    lookup: BoxFuture<'static, io::Result<()>>,
+    hostname: String,
+    timeout: Duration,
+) {
+    // The test zone does not necessarily answer lookups, so failures are expected here.
+    match tokio::time::timeout(timeout, lookup).await {
+        Ok(Ok(())) => (),
+        Ok(Err(error)) => log::trace!(
+            "{}",
+            error.display_chain_with_msg(&format!("Leak test lookup of {hostname} failed"))
+        ),
+        Err(_elapsed) => log::debug!("Leak test lookup of {hostname} timed out"),
+    }
+}
+
+fn evaluate(observed_resolvers: Vec<ObservedResolver>, custom_dns: bool) -> DnsLeakTestResult {
+    let non_mullvad_resolvers: Vec<IpAddr> = observed_resolvers
+        .iter()
+        .filter(|resolver| !resolver.mullvad_dns)
+        .map(|resolver| resolver.ip)
+        .collect();
+
+    let (verdict, leaking_resolvers) = if observed_resolvers.is_empty() {
+        (DnsLeakVerdict::Inconclusive, vec![])
+    } else if non_mullvad_resolvers.is_empty() {
+        (DnsLeakVerdict::NoLeak, vec![])
+    } else if custom_dns {
+        (DnsLeakVerdict::Inconclusive, vec![])
+    } else {
+        (DnsLeakVerdict::Leaking, non_mullvad_resolvers)
+    };
+
+    DnsLeakTestResult {
+        verdict,
+        observed_resolvers,
+        leaking_resolvers,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        net::Ipv4Addr,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Default)]
+    struct MockBackend {
+        system_lookups: Arc<Mutex<Vec<String>>>,
+        direct_lookups: Arc<Mutex<Vec<(IpAddr, String)>>>,
+        observed: Vec<ObservedResolver>,
+        hang_lookups: bool,
+        hang_api: bool,
+    }
+
+    impl LeakTestBackend for MockBackend {
+        fn lookup_system(&self, hostname: String) -> BoxFuture<'static, io::Result<()>> {
+            self.system_lookups.lock().unwrap().push(hostname);
+            self.lookup_result()
+        }
+
+        fn lookup_direct(
+            &self,
+            resolver: IpAddr,
+            hostname: String,
+        ) -> BoxFuture<'static, io::Result<()>> {
+            self.direct_lookups
+                .lock()
+                .unwrap()
+                .push((resolver, hostname));
+            self.lookup_result()
+        }
+
+        fn observed_resolvers(
+            &self,
+            _test_id: String,
+        ) -> BoxFuture<'static, Result<Vec<ObservedResolver>, rest::Error>> {
+            if self.hang_api {
+                return future::pending().boxed();
+            }
+            future::ready(Ok(self.observed.clone())).boxed()
+        }
+    }
+
+    impl MockBackend {
+        fn lookup_result(&self) -> BoxFuture<'static, io::Result<()>> {
+            if self.hang_lookups {
+                future::pending().boxed()
+            } else {
+                future::ready(Err(io::Error::new(io::ErrorKind::NotFound, "NXDOMAIN"))).boxed()
+            }
+        }
+    }
+
+    fn short_timeouts() -> Timeouts {
+        Timeouts {
+            lookup: Duration::from_millis(50),
+            registration_delay: Duration::from_millis(0),
+            api: Duration::from_millis(50),
+        }
+    }
+
+    fn resolver(last_octet: u8, mullvad_dns: bool) -> ObservedResolver {
+        ObservedResolver {
+            ip: IpAddr::V4(Ipv4Addr::new(193, 138, 218, last_octet)),
+            mullvad_dns,
+            organization: None,
+        }
+    }
+
+    const TUNNEL_RESOLVER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1));
+
+    #[tokio::test]
+    async fn test_unique_lookups() {
+        let backend = MockBackend {
+            observed: vec![resolver(1, true)],
+            ..Default::default()
+        };
+        let result = run(
+            &backend,
+            "abc".to_owned(),
+            &[TUNNEL_RESOLVER],
+            false,
+            short_timeouts(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.verdict, DnsLeakVerdict::NoLeak);
+
+        let system_lookups = backend.system_lookups.lock().unwrap().clone();
+        let direct_lookups = backend.direct_lookups.lock().unwrap().clone();
+        assert_eq!(system_lookups.len(), LOOKUPS_PER_RESOLVER);
+        assert_eq!(direct_lookups.len(), LOOKUPS_PER_RESOLVER);
+        assert!(direct_lookups
+            .iter()
+            .all(|(resolver, _)| *resolver == TUNNEL_RESOLVER));
+
+        let mut hostnames: Vec<_> = system_lookups
+            .into_iter()
+            .chain(direct_lookups.into_iter().map(|(_, hostname)| hostname))
+            .collect();
+        assert!(hostnames
+            .iter()
+            .all(|hostname| hostname.starts_with("abc-")));
+        hostnames.sort();
+        hostnames.dedup();
+        assert_eq!(hostnames.len(), 2 * LOOKUPS_PER_RESOLVER);
+    }
+
+    #[tokio::test]
+    async fn test_leaking_resolver() {
+        let backend = MockBackend {
+            observed: vec![resolver(1, true), resolver(2, false)],
+            ..Default::default()
+        };
+        let result = run(&backend, "abc".to_owned(), &[], false, short_timeouts())
+            .await
+            .unwrap();
+        assert_eq!(result.verdict, DnsLeakVerdict::Leaking);
+        assert_eq!(result.leaking_resolvers, vec![resolver(2, false).ip]);
+        assert_eq!(result.observed_resolvers.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_custom_dns_is_inconclusive() {
+        let backend = MockBackend {
+            observed: vec![resolver(2, false)],
+            ..Default::default()
+        };
+        let result = run(&backend, "abc".to_owned(), &[], true, short_timeouts())
+            .await
+            .unwrap();
+        assert_eq!(result.verdict, DnsLeakVerdict::Inconclusive);
+        assert!(result.leaking_resolvers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_no_observed_resolvers() {
+        let backend = MockBackend::default();
+        let result = run(&backend, "abc".to_owned(), &[], false, short_timeouts())
+            .await
+            .unwrap();
+        assert_eq!(result.verdict, DnsLeakVerdict::Inconclusive);
+    }
+
+    #[tokio::test]
+    async fn test_hanging_lookups_time_out() {
+        let backend = MockBackend {
+            observed: vec![resolver(1, true)],
+            hang_lookups: true,
+            ..Default::default()
+        };
+        let result = run(
+            &backend,
+            "abc".to_owned(),
+            &[TUNNEL_RESOLVER],
+            false,
+            short_timeouts(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.verdict, DnsLeakVerdict::NoLeak);
+    }
+
+    #[tokio::test]
+    async fn test_api_timeout() {
+        let backend = MockBackend {
+            hang_api: true,
+            ..Default::default()
+        };
+        let result = run(&backend, "abc".to_owned(), &[], false, short_timeouts()).await;
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
+}
@@ -16,7 +16,7 @@ use talpid_types::ErrorExt;
 // production build, a warning will be logged and the env variable *won´t* have
 // any effect on the api call. The default host name `am.i.mullvad.net` will
 // always be used in release mode.
-static MULLVAD_CONNCHECK_HOST: Lazy<String> = Lazy::new(|| {
+pub(crate) static MULLVAD_CONNCHECK_HOST: Lazy<String> = Lazy::new(|| {
     const DEFAULT_CONNCHECK_HOST: &str = "am.i.mullvad.net";
     let conncheck_host_var = std::env::var("MULLVAD_CONNCHECK_HOST").ok();
     let host = if cfg!(feature = "api-override") {
@@ -78,7 +78,7 @@ pub async fn send_location_request(
     }
 }
 
-async fn send_location_request_internal(
+pub(crate) async fn send_location_request_internal(
     uri: &str,
     service: RequestServiceHandle,
 ) -> Result<AmIMullvad, Error> {
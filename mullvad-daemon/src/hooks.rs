@@ -0,0 +1,297 @@
+//! Runs the scripts that the user has configured to run when the tunnel connects, disconnects or
+//! fails. Hooks run in the background, and their outcome never affects the tunnel.
+
+use mullvad_types::{settings::HookSettings, states::TunnelState, wireguard::AssociatedAddresses};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Output, Stdio},
+    time::Duration,
+};
+use talpid_types::{net::TunnelType, ErrorExt};
+use tokio::process::Command;
+
+/// Hooks that run for longer than this are killed.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to run the hook")]
+    Run(#[error(source)] io::Error),
+
+    #[error(display = "The hook did not finish within {:?}", _0)]
+    Timeout(Duration),
+}
+
+/// Returns the hook to run when entering `tunnel_state`, if any.
+pub fn hook_for_state<'a>(hooks: &'a HookSettings, tunnel_state: &TunnelState) -> Option<&'a Path> {
+    match tunnel_state {
+        TunnelState::Connected { .. } => hooks.on_connect.as_deref(),
+        TunnelState::Disconnected => hooks.on_disconnect.as_deref(),
+        TunnelState::Error(_) => hooks.on_error.as_deref(),
+        TunnelState::Connecting { .. } | TunnelState::Disconnecting(_) => None,
+    }
+}
+
+/// Returns the environment variables that describe `tunnel_state` to a hook. The in-tunnel
+/// addresses are only known for WireGuard tunnels, which use the addresses of the device.
+pub fn environment(
+    tunnel_state: &TunnelState,
+    device_addresses: Option<&AssociatedAddresses>,
+) -> Vec<(&'static str, String)> {
+    let mut env = vec![];
+    match tunnel_state {
+        TunnelState::Connected {
+            endpoint, location, ..
+        } => {
+            env.push(("MULLVAD_TUNNEL_STATE", "connected".to_owned()));
+            env.push(("MULLVAD_TUNNEL_TYPE", endpoint.tunnel_type.to_string()));
+            if let Some(interface) = &endpoint.tunnel_interface {
+                env.push(("MULLVAD_TUNNEL_INTERFACE", interface.clone()));
+            }
+            if let (TunnelType::Wireguard, Some(addresses)) =
+                (endpoint.tunnel_type, device_addresses)
+            {
+                env.push((
+                    "MULLVAD_TUNNEL_IPV4",
+                    addresses.ipv4_address.ip().to_string(),
+                ));
+                env.push((
+                    "MULLVAD_TUNNEL_IPV6",
+                    addresses.ipv6_address.ip().to_string(),
+                ));
+            }
+            env.push((
+                "MULLVAD_RELAY_ADDRESS",
+                endpoint.endpoint.address.ip().to_string(),
+            ));
+            if let Some(hostname) = location
+                .as_ref()
+                .and_then(|location| location.hostname.clone())
+            {
+                env.push(("MULLVAD_RELAY_HOSTNAME", hostname));
+            }
+        }
+        TunnelState::Disconnected => {
+            env.push(("MULLVAD_TUNNEL_STATE", "disconnected".to_owned()));
+        }
+        TunnelState::Error(error_state) => {
+            env.push(("MULLVAD_TUNNEL_STATE", "error".to_owned()));
+            env.push(("MULLVAD_ERROR_CAUSE", error_state.cause().to_string()));
+        }
+        TunnelState::Connecting { .. } | TunnelState::Disconnecting(_) => (),
+    }
+    env
+}
+
+/// Runs `hook` in the background, and logs its output at debug level.
+pub fn spawn(hook: PathBuf, env: Vec<(&'static str, String)>) {
+    tokio::spawn(async move {
+        // The file may have been changed since the hook was set
+        if let Err(error) = crate::settings::validate_hook_file(&hook) {
+            log::error!("{}", error.display_chain_with_msg("Not running the hook"));
+            return;
+        }
+        log::debug!("Running hook {}", hook.display());
+        match run(&hook, env, HOOK_TIMEOUT).await {
+            Ok(output) => {
+                if !output.status.success() {
+                    log::warn!("Hook {} failed: {}", hook.display(), output.status);
+                }
+                for (name, data) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+                    if !data.is_empty() {
+                        log::debug!(
+                            "{name} of hook {}:\n{}",
+                            hook.display(),
+                            String::from_utf8_lossy(data).trim_end()
+                        );
+                    }
+                }
+            }
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!("Hook {} failed", hook.display()))
+            ),
+        }
+    });
+}
+
+/// Runs `hook` with `env` added to the environment of the daemon, and kills it if it has not
+/// exited after `timeout`. On Unix, the hook runs in its own process group, so that any processes
+/// it has started are killed along with it.
+async fn run(
+    hook: &Path,
+    env: Vec<(&'static str, String)>,
+    timeout: Duration,
+) -> Result<Output, Error> {
+    let mut command = std::process::Command::new(hook);
+    command
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    let child = Command::from(command)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(Error::Run)?;
+    #[cfg(unix)]
+    let process_group = child.id();
+
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => output.map_err(Error::Run),
+        Err(_) => {
+            #[cfg(unix)]
+            if let Some(process_group) = process_group {
+                kill_process_group(process_group);
+            }
+            Err(Error::Timeout(timeout))
+        }
+    }
+}
+
+/// Kills all processes that remain in the process group of a hook.
+#[cfg(unix)]
+fn kill_process_group(process_group: u32) {
+    use nix::{
+        errno::Errno,
+        sys::signal::{killpg, Signal},
+        unistd::Pid,
+    };
+
+    match killpg(Pid::from_raw(process_group as i32), Signal::SIGKILL) {
+        // All processes of the hook have already exited
+        Ok(()) | Err(Errno::ESRCH) => (),
+        Err(error) => log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to kill the processes started by the hook")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{connected_state, tunnel_endpoint};
+    use mullvad_types::location::GeoIpLocation;
+    use talpid_types::{
+        net::TunnelEndpoint,
+        tunnel::{ErrorState, ErrorStateCause},
+    };
+
+    fn connected(tunnel_type: TunnelType) -> TunnelState {
+        connected_state(
+            TunnelEndpoint {
+                tunnel_interface: Some("wg0-mullvad".to_owned()),
+                ..tunnel_endpoint(tunnel_type)
+            },
+            Some(GeoIpLocation {
+                ipv4: None,
+                ipv6: None,
+                country: "Sweden".to_owned(),
+                city: None,
+                latitude: 0.0,
+                longitude: 0.0,
+                mullvad_exit_ip: true,
+                hostname: Some("se-got-wg-001".to_owned()),
+                bridge_hostname: None,
+                entry_hostname: None,
+                obfuscator_hostname: None,
+            }),
+        )
+    }
+
+    fn get<'a>(env: &'a [(&'static str, String)], name: &str) -> Option<&'a str> {
+        env.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn test_environment() {
+        let addresses = AssociatedAddresses {
+            ipv4_address: "10.64.0.2/32".parse().unwrap(),
+            ipv6_address: "fc00:bbbb:bbbb:bb01::2/128".parse().unwrap(),
+        };
+
+        let env = environment(&connected(TunnelType::Wireguard), Some(&addresses));
+        assert_eq!(get(&env, "MULLVAD_TUNNEL_STATE"), Some("connected"));
+        assert_eq!(get(&env, "MULLVAD_TUNNEL_INTERFACE"), Some("wg0-mullvad"));
+        assert_eq!(get(&env, "MULLVAD_TUNNEL_IPV4"), Some("10.64.0.2"));
+        assert_eq!(
+            get(&env, "MULLVAD_TUNNEL_IPV6"),
+            Some("fc00:bbbb:bbbb:bb01::2")
+        );
+        assert_eq!(get(&env, "MULLVAD_RELAY_ADDRESS"), Some("192.0.2.1"));
+        assert_eq!(get(&env, "MULLVAD_RELAY_HOSTNAME"), Some("se-got-wg-001"));
+
+        // The device addresses are not used by OpenVPN
+        let env = environment(&connected(TunnelType::OpenVpn), Some(&addresses));
+        assert_eq!(get(&env, "MULLVAD_TUNNEL_IPV4"), None);
+
+        let env = environment(
+            &TunnelState::Error(ErrorState::new(ErrorStateCause::IsOffline, None)),
+            None,
+        );
+        assert_eq!(get(&env, "MULLVAD_TUNNEL_STATE"), Some("error"));
+        assert!(get(&env, "MULLVAD_ERROR_CAUSE").is_some());
+        assert_eq!(get(&env, "MULLVAD_RELAY_ADDRESS"), None);
+    }
+
+    #[test]
+    fn test_hook_for_state() {
+        let hooks = HookSettings {
+            on_connect: Some(PathBuf::from("/usr/local/bin/on-connect")),
+            on_disconnect: None,
+            on_error: Some(PathBuf::from("/usr/local/bin/on-error")),
+        };
+        assert_eq!(
+            hook_for_state(&hooks, &connected(TunnelType::Wireguard)),
+            Some(Path::new("/usr/local/bin/on-connect"))
+        );
+        assert_eq!(hook_for_state(&hooks, &TunnelState::Disconnected), None);
+    }
+
+    #[cfg(unix)]
+    fn write_script(dir: &Path, content: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("hook.sh");
+        std::fs::write(&path, content).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_passes_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let hook = write_script(dir.path(), "#!/bin/sh\necho \"$MULLVAD_TUNNEL_STATE\"\n");
+
+        let env = vec![("MULLVAD_TUNNEL_STATE", "connected".to_owned())];
+        let output = run(&hook, env, Duration::from_secs(10)).await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"connected\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let hook = write_script(
+            dir.path(),
+            "#!/bin/sh\n(sleep 1; touch \"$MARKER\") &\nwait\n",
+        );
+        let marker = dir.path().join("marker");
+
+        let env = vec![("MARKER", marker.to_string_lossy().into_owned())];
+        let result = run(&hook, env, Duration::from_millis(200)).await;
+        assert!(matches!(result, Err(Error::Timeout(_))));
+
+        // The hook and the processes it has started are killed rather than left running
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(!marker.exists());
+    }
+}
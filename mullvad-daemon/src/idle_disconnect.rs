@@ -0,0 +1,155 @@
+//! Decides when the tunnel has been idle for long enough to be disconnected. The tunnel is idle
+//! while next to no traffic passes through it in either direction, as judged by its traffic
+//! counters. The counters are only available for WireGuard tunnels, so other tunnels are never
+//! considered idle.
+
+use std::time::{Duration, Instant};
+use talpid_types::tunnel::TrafficStats;
+
+/// How often the traffic counters are checked while the tunnel is connected.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of bytes that may pass in either direction between two checks without counting as
+/// activity. Keepalives and the pings of the connectivity monitor stay well below this.
+const IDLE_TRAFFIC_THRESHOLD: u64 = 4 * 1024;
+
+pub struct IdleTracker {
+    /// Counters that were read at the last check.
+    last_stats: Option<TrafficStats>,
+    /// When the tunnel was last seen to be in use.
+    active_at: Instant,
+}
+
+impl IdleTracker {
+    pub fn new(now: Instant) -> Self {
+        IdleTracker {
+            last_stats: None,
+            active_at: now,
+        }
+    }
+
+    /// Starts over, as if the tunnel was in use at `now`.
+    pub fn reset(&mut self, now: Instant) {
+        *self = Self::new(now);
+    }
+
+    /// Records the counters that were read at `now`, and returns how long the tunnel has been
+    /// idle. The tunnel counts as in use when the counters are unavailable.
+    pub fn update(&mut self, now: Instant, stats: Option<TrafficStats>) -> Duration {
+        let active = match (self.last_stats, stats) {
+            (Some(last), Some(stats)) => {
+                // The counters start over when the tunnel is replaced
+                stats.rx_bytes < last.rx_bytes
+                    || stats.tx_bytes < last.tx_bytes
+                    || stats.rx_bytes - last.rx_bytes > IDLE_TRAFFIC_THRESHOLD
+                    || stats.tx_bytes - last.tx_bytes > IDLE_TRAFFIC_THRESHOLD
+            }
+            _ => true,
+        };
+        self.last_stats = stats;
+        if active {
+            self.active_at = now;
+        }
+        now.saturating_duration_since(self.active_at)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+    fn stats(rx_bytes: u64, tx_bytes: u64) -> Option<TrafficStats> {
+        Some(TrafficStats { rx_bytes, tx_bytes })
+    }
+
+    /// Feeds `tracker` with counters that grow by `rx_step` and `tx_step` at every check, until
+    /// `TIMEOUT` has passed. Returns how long the tunnel was idle at the last check.
+    fn run_checks(
+        tracker: &mut IdleTracker,
+        start: Instant,
+        rx_step: u64,
+        tx_step: u64,
+    ) -> Duration {
+        let checks = (TIMEOUT.as_secs() / CHECK_INTERVAL.as_secs()) as u32;
+        let mut idle_for = Duration::ZERO;
+        for check in 0..=checks {
+            let bytes = u64::from(check);
+            idle_for = tracker.update(
+                start + CHECK_INTERVAL * check,
+                stats(bytes * rx_step, bytes * tx_step),
+            );
+        }
+        idle_for
+    }
+
+    #[test]
+    fn test_idle_with_keepalives() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::new(start);
+        // A ping and its reply at every check
+        assert!(run_checks(&mut tracker, start, 128, 128) >= TIMEOUT);
+    }
+
+    #[test]
+    fn test_traffic_in_one_direction_is_activity() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::new(start);
+        assert_eq!(
+            run_checks(&mut tracker, start, 0, IDLE_TRAFFIC_THRESHOLD + 1),
+            Duration::ZERO
+        );
+
+        let mut tracker = IdleTracker::new(start);
+        assert_eq!(
+            run_checks(&mut tracker, start, IDLE_TRAFFIC_THRESHOLD + 1, 0),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_activity_restarts_the_period() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::new(start);
+        assert_eq!(tracker.update(start, stats(1000, 1000)), Duration::ZERO);
+        assert_eq!(
+            tracker.update(start + 4 * CHECK_INTERVAL, stats(1000, 1000)),
+            4 * CHECK_INTERVAL
+        );
+        let active_at = start + 5 * CHECK_INTERVAL;
+        assert_eq!(
+            tracker.update(active_at, stats(1_000_000, 1000)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            tracker.update(active_at + CHECK_INTERVAL, stats(1_000_000, 1000)),
+            CHECK_INTERVAL
+        );
+    }
+
+    #[test]
+    fn test_unavailable_counters_are_never_idle() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::new(start);
+        for check in 0..100 {
+            assert_eq!(
+                tracker.update(start + CHECK_INTERVAL * check, None),
+                Duration::ZERO
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_tunnel_is_activity() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::new(start);
+        tracker.update(start, stats(1_000_000, 1_000_000));
+        tracker.update(start + CHECK_INTERVAL, stats(1_000_000, 1_000_000));
+        // The counters of the replacing tunnel start from zero
+        assert_eq!(
+            tracker.update(start + 2 * CHECK_INTERVAL, stats(0, 0)),
+            Duration::ZERO
+        );
+    }
+}
@@ -2,25 +2,42 @@
 #![recursion_limit = "512"]
 
 mod access_method;
+mod access_method_selector;
+mod account_expiry;
 pub mod account_history;
 mod api;
+mod api_connectivity;
 #[cfg(not(target_os = "android"))]
 mod cleanup;
+mod connection_check;
+mod connection_history;
 mod custom_list;
 pub mod device;
 mod dns;
+mod dns_leak;
 pub mod exception_logging;
 mod geoip;
+mod hooks;
+mod idle_disconnect;
+mod local_proxy;
 pub mod logging;
 #[cfg(target_os = "macos")]
 mod macos;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
+mod metrics;
 mod migrations;
+mod network_identity;
+mod network_trust;
+mod obfuscation_scores;
+mod profiles;
+mod recent_events;
+mod routes;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
 pub mod settings;
+mod settings_transfer;
 pub mod shutdown;
 mod target_state;
 mod tunnel;
@@ -28,42 +45,71 @@ pub mod version;
 mod version_check;
 
 use crate::target_state::PersistentTargetState;
+use account_expiry::ExpiryEvent;
 use device::{AccountEvent, PrivateAccountAndDevice, PrivateDeviceEvent};
 use futures::{
     channel::{mpsc, oneshot},
     future::{abortable, AbortHandle, Future, LocalBoxFuture},
     StreamExt,
 };
+use ipnetwork::IpNetwork;
+use mullvad_problem_report::outbox::{Outbox, QueuedReport};
 use mullvad_relay_selector::{
     updater::{RelayListUpdater, RelayListUpdaterHandle},
     RelaySelector, SelectorConfig,
 };
+#[cfg(not(target_os = "android"))]
+use mullvad_types::settings::ResetScopes;
+#[cfg(target_os = "windows")]
+use mullvad_types::settings::SplitApp;
 use mullvad_types::{
-    access_method::{AccessMethod, AccessMethodSetting},
-    account::{AccountData, AccountToken, VoucherSubmission},
-    auth_failed::AuthFailed,
+    access_method::{
+        AccessMethod, AccessMethodSetting, AccessMethodStats, AccessMethodTestReport,
+        ApiConnectivity,
+    },
+    account::{AccountData, AccountExpiryWarning, AccountToken, SavedAccount, VoucherSubmission},
+    connection_check::ConnectionCheck,
+    connection_history::Connection,
     custom_list::CustomList,
     device::{Device, DeviceEvent, DeviceEventCause, DeviceId, DeviceState, RemoveDeviceEvent},
+    dns_leak::DnsLeakTestResult,
+    features,
     location::GeoIpLocation,
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
+    metrics::Metric,
+    network_profiles::{self, CurrentNetwork, NetworkId},
+    network_trust::NetworkTrustSettings,
+    obfuscation_scores::ObfuscationScores,
+    problem_report::{ProblemReportSendResult, QueuedProblemReport},
+    recent_events::{Event as RecentEvent, EventKind},
+    relay_constraints::{
+        BridgeSettings, BridgeState, GeographicLocationConstraint, MatchingRelays,
+        ObfuscationSettings, RelayOverrides, RelaySettings, RelaySettingsUpdate,
+    },
     relay_list::RelayList,
-    settings::{DnsOptions, Settings},
-    states::{TargetState, TunnelState},
+    routes::{RouteDump, RoutesUpdate},
+    settings::{
+        AccountExpiryWarnings, DnsOptions, DnsState, HookSettings, LocalProxySettings,
+        LogRotationSettings, Settings, TunnelOptions,
+    },
+    states::{IdleDisconnect, NetworkChange, TargetState, TunnelState},
     version::{AppVersion, AppVersionInfo},
-    wireguard::{PublicKey, QuantumResistantState, RotationInterval},
+    wireguard::{AssociatedAddresses, PublicKey, QuantumResistantState, RotationInterval},
 };
+use once_cell::sync::Lazy;
 use settings::SettingsPersister;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
 #[cfg(target_os = "windows")]
 use std::{collections::HashSet, ffi::OsString};
 use std::{
+    collections::{BTreeSet, HashMap},
     marker::PhantomData,
     mem,
+    net::IpAddr,
     path::PathBuf,
     pin::Pin,
     sync::{Arc, Mutex, Weak},
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 #[cfg(any(target_os = "linux", windows))]
 use talpid_core::split_tunnel;
@@ -73,11 +119,18 @@ use talpid_core::{
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
-#[cfg(target_os = "windows")]
-use talpid_types::split_tunnel::ExcludedProcess;
+#[cfg(target_os = "linux")]
+use talpid_types::{
+    net::TransportProtocol,
+    split_tunnel::{ExcludedDestination, SplitTunnelMode},
+};
 use talpid_types::{
-    net::{TunnelEndpoint, TunnelType},
-    tunnel::{ErrorStateCause, TunnelStateTransition},
+    net::{EffectiveDns, TunnelEndpoint, TunnelType},
+    split_tunnel::{AppExclusionStatus, ExcludedProcess},
+    tunnel::{
+        ErrorCode, ErrorStateCause, MockTunnelScript, RetryBackoff, TrafficStats,
+        TunnelStateTransition,
+    },
     ErrorExt,
 };
 #[cfg(any(target_os = "macos", target_os = "linux"))]
@@ -87,6 +140,21 @@ use tokio::io;
 /// Delay between generating a new WireGuard key and reconnecting
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(4 * 60);
 
+/// How often to check for problem reports that have been queued to be sent
+const PROBLEM_REPORT_OUTBOX_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Longest time between checks of the account expiry. The timer does not advance while the host
+/// is suspended, so a single long timer could fire long after the expiry.
+const ACCOUNT_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How often the account data is refreshed while blocking because the account is out of time.
+/// Time that is added outside of the app is only noticed this way.
+const ACCOUNT_EXPIRED_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long to collect process events before listing the excluded processes again.
+#[cfg(target_os = "linux")]
+const PROCESS_EVENTS_DELAY: Duration = Duration::from_millis(500);
+
 pub type ResponseTx<T, E> = oneshot::Sender<Result<T, E>>;
 
 #[derive(err_derive::Error, Debug)]
@@ -151,6 +219,9 @@ pub enum Error {
     #[error(display = "No account history available for the token")]
     NoAccountTokenHistory,
 
+    #[error(display = "There is no saved account with that label or account number")]
+    SavedAccountNotFound,
+
     #[error(display = "Settings error")]
     SettingsError(#[error(source)] settings::Error),
 
@@ -175,6 +246,45 @@ pub enum Error {
     #[error(display = "Access method error")]
     AccessMethodError(#[error(source)] access_method::Error),
 
+    #[error(display = "DNS leak test failed")]
+    DnsLeakTestError(#[error(source)] dns_leak::Error),
+
+    #[error(display = "Connection check failed")]
+    ConnectionCheckError(#[error(source)] connection_check::Error),
+
+    #[error(display = "Version check failed")]
+    VersionCheckError(#[error(source)] version_check::Error),
+
+    #[error(display = "The API endpoint can only be overridden in development builds")]
+    ApiEndpointOverrideUnavailable,
+
+    #[error(display = "Failed to store the API endpoint override")]
+    ApiEndpointOverrideError(#[error(source)] io::Error),
+
+    #[error(display = "The mock tunnel is not enabled")]
+    MockTunnelUnavailable,
+
+    #[error(display = "Failed to obtain the routes from the route manager")]
+    GetRoutesError(#[error(source)] talpid_routing::Error),
+
+    #[error(display = "Failed to access the queued problem reports")]
+    ProblemReportOutboxError(#[error(source)] io::Error),
+
+    #[error(display = "Failed to export the settings")]
+    ExportSettings(#[error(source)] settings_transfer::Error),
+
+    #[error(display = "Cannot import the settings")]
+    ImportSettings(#[error(source)] settings_transfer::Error),
+
+    #[error(display = "Cannot apply the settings patch")]
+    ApplySettingsPatch(#[error(source)] settings_transfer::Error),
+
+    #[error(display = "Cannot connect with the given parameters")]
+    RelayOverrides(#[error(source)] mullvad_relay_selector::Error),
+
+    #[error(display = "Settings profile error")]
+    ProfileError(#[error(source)] profiles::Error),
+
     #[cfg(target_os = "macos")]
     #[error(display = "Failed to set exclusion group")]
     GroupIdError(#[error(source)] io::Error),
@@ -186,8 +296,13 @@ pub enum DaemonCommand {
     SetTargetState(oneshot::Sender<bool>, TargetState),
     /// Reconnect the tunnel, if one is connecting/connected.
     Reconnect(oneshot::Sender<bool>),
+    /// Reconnect the tunnel, if one is connecting/connected, using the given parameters instead of
+    /// the relay constraints. Only the next connection attempt uses them.
+    ReconnectWithOverrides(ResponseTx<bool, Error>, RelayOverrides),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
+    /// Get the traffic counters of the current tunnel, if it has any
+    GetTunnelStats(oneshot::Sender<Option<TrafficStats>>),
     /// Get the current geographical location.
     GetCurrentLocation(oneshot::Sender<Option<GeoIpLocation>>),
     CreateNewAccount(ResponseTx<String, Error>),
@@ -202,8 +317,15 @@ pub enum DaemonCommand {
     SubmitVoucher(ResponseTx<VoucherSubmission, Error>, String),
     /// Request account history
     GetAccountHistory(oneshot::Sender<Option<AccountToken>>),
-    /// Remove the last used account, if there is one
+    /// Remove all saved accounts, including the last used one
     ClearAccountHistory(ResponseTx<(), Error>),
+    /// Get the accounts that have been used on this device, the most recently used first
+    ListSavedAccounts(oneshot::Sender<Vec<SavedAccount>>),
+    /// Set or remove the label of a saved account
+    SetSavedAccountLabel(ResponseTx<(), Error>, AccountToken, Option<String>),
+    /// Log in to a saved account, given its label or account number. The device of the current
+    /// account is removed, and a new device is created for the saved account.
+    SwitchAccount(ResponseTx<(), Error>, String),
     /// Get the list of countries and cities where there are relays.
     GetRelayLocations(oneshot::Sender<RelayList>),
     /// Trigger an asynchronous relay list update. This returns before the relay list is actually
@@ -223,14 +345,25 @@ pub enum DaemonCommand {
     RemoveDevice(ResponseTx<(), Error>, AccountToken, DeviceId),
     /// Place constraints on the type of tunnel and relay
     UpdateRelaySettings(ResponseTx<(), settings::Error>, RelaySettingsUpdate),
+    /// Count the relays that would match the relay constraints after an update, without
+    /// applying it. Returns `None` if the update results in a custom relay.
+    PreviewRelaySettings(oneshot::Sender<Option<MatchingRelays>>, RelaySettingsUpdate),
     /// Set the allow LAN setting.
     SetAllowLan(ResponseTx<(), settings::Error>, bool),
+    /// Set the networks that are routed outside the tunnel.
+    SetBypassRoutes(ResponseTx<(), settings::Error>, Vec<IpNetwork>),
+    /// Set whether to leave the default route to other VPNs.
+    SetCoexistenceMode(ResponseTx<(), settings::Error>, bool),
+    /// Set whether to send API traffic inside the tunnel while connected.
+    SetPreferApiInTunnel(ResponseTx<(), settings::Error>, bool),
     /// Set the beta program setting.
     SetShowBetaReleases(ResponseTx<(), settings::Error>, bool),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(ResponseTx<(), settings::Error>, bool),
     /// Set the auto-connect setting.
     SetAutoConnect(ResponseTx<(), settings::Error>, bool),
+    /// Set how long the tunnel must be idle to be disconnected, or `None` to never disconnect it
+    SetAutoDisconnectIdle(ResponseTx<(), settings::Error>, Option<Duration>),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(ResponseTx<(), settings::Error>, Option<u16>),
     /// Set proxy details for OpenVPN
@@ -243,6 +376,28 @@ pub enum DaemonCommand {
     SetQuantumResistantTunnel(ResponseTx<(), settings::Error>, QuantumResistantState),
     /// Set DNS options or servers to use
     SetDnsOptions(ResponseTx<(), settings::Error>, DnsOptions),
+    /// Check whether DNS lookups are leaking outside the tunnel
+    RunDnsLeakTest(ResponseTx<DnsLeakTestResult, Error>),
+    /// Check where traffic leaves for the internet. The check is made through the tunnel, and
+    /// only while connected unless the flag is set
+    CheckConnection(ResponseTx<ConnectionCheck, Error>, bool),
+    /// Get the routes that the route manager applies, and the routes in the routing table
+    GetRoutes(ResponseTx<RouteDump, Error>),
+    /// Get the recorded outcomes of connection attempts, decayed to what they are now
+    GetObfuscationScores(oneshot::Sender<ObfuscationScores>),
+    /// Get health metrics of the daemon
+    GetMetrics(oneshot::Sender<Vec<Metric>>),
+    /// Get the most recent significant events, oldest first
+    GetRecentEvents(oneshot::Sender<Vec<RecentEvent>>),
+    /// Get the most recent connections, oldest first
+    GetConnectionHistory(oneshot::Sender<Vec<Connection>>),
+    /// Queue a collected problem report to be sent once the API can be reached. The arguments
+    /// are the email, message and redacted report. Returns the ID of the queued report
+    QueueProblemReport(ResponseTx<String, Error>, String, String, String),
+    /// Get the problem reports that are queued to be sent, oldest first
+    GetQueuedProblemReports(ResponseTx<Vec<QueuedProblemReport>, Error>),
+    /// Try to send every queued problem report once
+    FlushQueuedProblemReports(ResponseTx<Vec<ProblemReportSendResult>, Error>),
     /// Toggle macOS network check leak
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
@@ -260,6 +415,13 @@ pub enum DaemonCommand {
     DeleteCustomList(ResponseTx<(), Error>, mullvad_types::custom_list::Id),
     /// Update a custom list with a given id
     UpdateCustomList(ResponseTx<(), Error>, CustomList),
+    /// Replace the locations of the custom list with a given name, or create it if it doesn't
+    /// exist
+    ReplaceCustomList(
+        ResponseTx<mullvad_types::custom_list::Id, Error>,
+        String,
+        BTreeSet<GeographicLocationConstraint>,
+    ),
     /// Get API access methods
     GetApiAccessMethods(ResponseTx<Vec<AccessMethodSetting>, Error>),
     /// Add API access methods
@@ -277,20 +439,64 @@ pub enum DaemonCommand {
     UpdateApiAccessMethod(ResponseTx<(), Error>, AccessMethodSetting),
     /// Get the currently used API access method
     GetCurrentAccessMethod(ResponseTx<AccessMethodSetting, Error>),
+    /// Get the number of successful and failed API requests of each enabled API access method
+    GetApiAccessMethodStats(oneshot::Sender<Vec<AccessMethodStats>>),
+    /// Get whether the API can currently be reached
+    GetApiConnectivity(oneshot::Sender<ApiConnectivity>),
+    /// Measure how long each stage of reaching the API takes using an API access method, without
+    /// switching to it
+    TestApiAccessMethod(
+        ResponseTx<AccessMethodTestReport, Error>,
+        mullvad_types::access_method::Id,
+    ),
+    /// Add an API access method which has been shared as a string
+    ImportApiAccessMethod(ResponseTx<mullvad_types::access_method::Id, Error>, String),
+    /// Encode an API access method as a string which can be shared
+    ExportApiAccessMethod(ResponseTx<String, Error>, mullvad_types::access_method::Id),
     /// Get the addresses of all known API endpoints
     GetApiAddresses(ResponseTx<Vec<std::net::SocketAddr>, Error>),
+    /// Replace the API host and address, or go back to the default ones if `None` is given. Only
+    /// available in builds with the `api-override` feature.
+    SetApiEndpointOverride(
+        ResponseTx<(), Error>,
+        Option<mullvad_api::ApiEndpointOverride>,
+    ),
+    /// Change the failures that the mock tunnel simulates. Only available in builds with the
+    /// `mock-tunnel` feature, when the mock tunnel is enabled.
+    ScriptMockTunnel(ResponseTx<(), Error>, MockTunnelScript),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<Option<AppVersionInfo>>),
+    /// Fetch the version info now. The upgrade is suggested from the beta channel if the flag is
+    /// set, and from the stable channel otherwise
+    CheckVersion(ResponseTx<AppVersionInfo, Error>, bool),
     /// Return whether the daemon is performing post-upgrade tasks
     IsPerformingPostUpgrade(oneshot::Sender<bool>),
     /// Get current version of the app
     GetCurrentVersion(oneshot::Sender<AppVersion>),
-    /// Remove settings and clear the cache
+    /// Reset the selected parts of the state. If everything is reset, the account is logged out,
+    /// settings, caches and logs are removed, and the daemon stops.
     #[cfg(not(target_os = "android"))]
-    FactoryReset(ResponseTx<(), Error>),
+    FactoryReset(ResponseTx<(), Error>, ResetScopes),
+    /// Export the settings as a JSON document, including secrets if the flag is set
+    ExportSettings(ResponseTx<String, Error>, bool),
+    /// Replace the settings with those in a JSON document created by `ExportSettings`
+    ImportSettings(ResponseTx<(), Error>, String),
+    /// Change several settings at once with a JSON merge patch against the exported format
+    ApplySettingsPatch(ResponseTx<(), Error>, String),
+    /// Save the current settings as a named profile
+    SaveProfile(ResponseTx<(), Error>, String),
+    /// Request the names of the saved settings profiles
+    ListProfiles(oneshot::Sender<Vec<String>>),
+    /// Replace the settings with those of a saved profile
+    ApplyProfile(ResponseTx<(), Error>, String),
+    /// Remove a saved settings profile
+    DeleteProfile(ResponseTx<(), Error>, String),
     /// Request list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     GetSplitTunnelProcesses(ResponseTx<Vec<i32>, split_tunnel::Error>),
+    /// Returns all processes currently being excluded from the tunnel, and why they are excluded
+    #[cfg(target_os = "linux")]
+    GetExcludedProcesses(ResponseTx<Vec<ExcludedProcess>, split_tunnel::Error>),
     /// Exclude traffic of a process (PID) from the tunnel
     #[cfg(target_os = "linux")]
     AddSplitTunnelProcess(ResponseTx<(), split_tunnel::Error>, i32),
@@ -300,12 +506,43 @@ pub enum DaemonCommand {
     /// Clear list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     ClearSplitTunnelProcesses(ResponseTx<(), split_tunnel::Error>),
+    /// Set users and groups whose processes are excluded from the tunnel
+    #[cfg(target_os = "linux")]
+    SetSplitTunnelOwners(ResponseTx<(), settings::Error>, Vec<u32>, Vec<u32>),
+    /// Set whether the selected apps are excluded from the tunnel or are the only ones using it
+    #[cfg(target_os = "linux")]
+    SetSplitTunnelMode(ResponseTx<(), settings::Error>, SplitTunnelMode),
+    /// Set destinations whose traffic is excluded from the tunnel regardless of the process
+    #[cfg(target_os = "linux")]
+    SetSplitTunnelDestinations(ResponseTx<(), settings::Error>, Vec<ExcludedDestination>),
+    /// Set interfaces whose forwarded traffic is excluded from the tunnel
+    #[cfg(target_os = "linux")]
+    SetSplitTunnelInterfaces(ResponseTx<(), settings::Error>, Vec<String>),
+    /// Set whether excluded apps may reach the local network when it is otherwise blocked
+    #[cfg(target_os = "linux")]
+    SetExcludedAppsAllowLan(ResponseTx<(), settings::Error>, bool),
+    /// Set local ports whose traffic is excluded from the tunnel
+    #[cfg(target_os = "linux")]
+    SetSplitTunnelLocalPorts(
+        ResponseTx<(), settings::Error>,
+        Vec<(TransportProtocol, u16)>,
+    ),
+    /// Set the SOCKS5 server on localhost that relays connections through the tunnel
+    SetLocalProxySettings(ResponseTx<(), settings::Error>, LocalProxySettings),
+    /// Set the scripts to run when the tunnel state changes
+    SetHookSettings(ResponseTx<(), settings::Error>, HookSettings),
+    /// Set how long consecutive connection attempts are spaced apart
+    SetRetryBackoff(ResponseTx<(), settings::Error>, RetryBackoff),
+    /// Set how long before the account expires that warnings are sent
+    SetAccountExpiryWarnings(ResponseTx<(), settings::Error>, AccountExpiryWarnings),
+    /// Set when the daemon and tunnel logs are rotated
+    SetLogRotationSettings(ResponseTx<(), settings::Error>, LogRotationSettings),
     /// Exclude traffic of an application from the tunnel
     #[cfg(windows)]
-    AddSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
+    AddSplitTunnelApp(ResponseTx<(), Error>, SplitApp),
     /// Remove application from list of apps to exclude from the tunnel
     #[cfg(windows)]
-    RemoveSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
+    RemoveSplitTunnelApp(ResponseTx<(), Error>, SplitApp),
     /// Clear list of apps to exclude from the tunnel
     #[cfg(windows)]
     ClearSplitTunnelApps(ResponseTx<(), Error>),
@@ -315,11 +552,23 @@ pub enum DaemonCommand {
     /// Returns all processes currently being excluded from the tunnel
     #[cfg(windows)]
     GetSplitTunnelProcesses(ResponseTx<Vec<ExcludedProcess>, split_tunnel::Error>),
+    /// Returns whether the exclusion of each configured app has been applied
+    #[cfg(windows)]
+    GetSplitTunnelAppStatus(ResponseTx<Vec<AppExclusionStatus>, split_tunnel::Error>),
     /// Notify the split tunnel monitor that a volume was mounted or dismounted
     #[cfg(target_os = "windows")]
     CheckVolumes(ResponseTx<(), Error>),
     /// Register settings for WireGuard obfuscator
     SetObfuscationSettings(ResponseTx<(), settings::Error>, ObfuscationSettings),
+    /// Set the obfuscation settings to use instead of the global ones on specific networks
+    SetNetworkObfuscationProfiles(
+        ResponseTx<(), settings::Error>,
+        Vec<(NetworkId, ObfuscationSettings)>,
+    ),
+    /// Return the network of the obfuscation profile that is used on the current network
+    GetActiveObfuscationProfile(oneshot::Sender<Option<NetworkId>>),
+    /// Set the rules for connecting and disconnecting automatically on specific networks
+    SetNetworkTrustSettings(ResponseTx<(), settings::Error>, NetworkTrustSettings),
     /// Saves the target tunnel state and enters a blocking state. The state is restored
     /// upon restart.
     PrepareRestart,
@@ -347,12 +596,31 @@ pub(crate) enum InternalDaemonEvent {
     /// The split tunnel paths or state were updated.
     #[cfg(target_os = "windows")]
     ExcludedPathsEvent(ExcludedPathsUpdate, oneshot::Sender<Result<(), Error>>),
+    /// Another program overwrote the DNS config, which has been restored.
+    DnsTampered,
+    /// The DNS config was changed without leaving the connected state.
+    EffectiveDnsChanged(EffectiveDns),
+    /// The route manager added or removed routes.
+    #[cfg(target_os = "linux")]
+    RoutesUpdated(talpid_routing::RoutesUpdate),
+    /// The network of the default route was identified after a network change.
+    NetworkIdentified(CurrentNetwork),
+    /// A threshold or the expiry of the account may have been passed.
+    AccountExpiryCheck,
+    /// The traffic counters of the tunnel should be checked for idleness.
+    IdleCheck,
+    /// The API became reachable or unreachable.
+    ApiConnectivityChanged(ApiConnectivity),
+    /// Processes were created, exited or changed owner, so the excluded processes may have
+    /// changed.
+    #[cfg(target_os = "linux")]
+    ProcessesChanged,
 }
 
 #[cfg(target_os = "windows")]
 pub(crate) enum ExcludedPathsUpdate {
     SetState(bool),
-    SetPaths(HashSet<PathBuf>),
+    SetPaths(HashSet<SplitApp>),
 }
 
 impl From<TunnelStateTransition> for InternalDaemonEvent {
@@ -379,6 +647,19 @@ impl From<AccountEvent> for InternalDaemonEvent {
     }
 }
 
+#[cfg(target_os = "linux")]
+impl From<talpid_routing::RoutesUpdate> for InternalDaemonEvent {
+    fn from(update: talpid_routing::RoutesUpdate) -> Self {
+        InternalDaemonEvent::RoutesUpdated(update)
+    }
+}
+
+impl From<CurrentNetwork> for InternalDaemonEvent {
+    fn from(network: CurrentNetwork) -> Self {
+        InternalDaemonEvent::NetworkIdentified(network)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum DaemonExecutionState {
     Running,
@@ -541,6 +822,66 @@ where
     }
 }
 
+/// Returns a listener that forwards route updates to the daemon. They are sent on the same channel
+/// as the tunnel state transitions, so that they are handled in the order that they happen.
+#[cfg(target_os = "linux")]
+fn routes_update_listener(
+    internal_event_tx: &DaemonEventSender,
+) -> impl Fn(talpid_routing::RoutesUpdate) + Send + 'static {
+    let routes_update_tx = internal_event_tx.to_specialized_sender();
+    move |update| {
+        let _ = routes_update_tx.send(update);
+    }
+}
+
+/// Notifies the daemon when processes change, until the daemon goes away. Events that are received
+/// within `PROCESS_EVENTS_DELAY` of each other are reported once.
+#[cfg(target_os = "linux")]
+fn forward_process_events(
+    events: split_tunnel::ProcessEvents,
+    internal_event_tx: DaemonEventSender,
+) {
+    loop {
+        if let Err(error) = events.wait() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to receive process events")
+            );
+            return;
+        }
+        std::thread::sleep(PROCESS_EVENTS_DELAY);
+        if let Err(error) = events.drain() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to receive process events")
+            );
+            return;
+        }
+        if internal_event_tx
+            .send(InternalDaemonEvent::ProcessesChanged)
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Forwards changes of the API connectivity to the daemon until either side goes away.
+async fn forward_api_connectivity(
+    mut status_rx: tokio::sync::watch::Receiver<ApiConnectivity>,
+    internal_event_tx: DaemonEventSender,
+) {
+    while status_rx.changed().await.is_ok() {
+        let status = status_rx.borrow_and_update().clone();
+        if internal_event_tx
+            .send(InternalDaemonEvent::ApiConnectivityChanged(status))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
 /// Trait representing something that can broadcast daemon events.
 pub trait EventListener {
     /// Notify that the tunnel state changed.
@@ -561,6 +902,40 @@ pub trait EventListener {
 
     /// Notify that a device was revoked using `RemoveDevice`.
     fn notify_remove_device_event(&self, event: RemoveDeviceEvent);
+
+    /// Notify that the system DNS config was overwritten by another program, and that the desired
+    /// config has been restored.
+    fn notify_dns_tampered(&self);
+
+    /// Notify that the default route or the interface it uses changed.
+    fn notify_network_changed(&self, change: NetworkChange);
+
+    /// Notify that routes were added or removed by connecting, disconnecting, or following a new
+    /// default route.
+    fn notify_routes_updated(&self, update: RoutesUpdate);
+
+    /// Notify that the default route started using the tunnel interface of another VPN, or
+    /// stopped using it if `interface` is `None`.
+    fn notify_foreign_tunnel(&self, interface: Option<String>);
+
+    /// Notify that the exclusion of a configured split tunnel app was applied, became pending, or
+    /// failed. The state of every configured app is included.
+    fn notify_split_tunnel_app_status(&self, statuses: Vec<AppExclusionStatus>);
+
+    /// Notify that a process started or stopped being excluded from the tunnel.
+    fn notify_excluded_processes(&self, processes: Vec<ExcludedProcess>);
+
+    /// Notify that a problem report which had been queued was sent.
+    fn notify_problem_report_sent(&self, id: String);
+
+    /// Notify that the account expires within one of the thresholds in the settings.
+    fn notify_account_expiry_warning(&self, warning: AccountExpiryWarning);
+
+    /// Notify that the tunnel was disconnected because it had been idle for too long.
+    fn notify_idle_disconnect(&self, event: IdleDisconnect);
+
+    /// Notify that the API became reachable or unreachable.
+    fn notify_api_connectivity(&self, status: ApiConnectivity);
 }
 
 pub struct Daemon<L: EventListener> {
@@ -569,21 +944,52 @@ pub struct Daemon<L: EventListener> {
     state: DaemonExecutionState,
     #[cfg(target_os = "linux")]
     exclude_pids: split_tunnel::PidManager,
+    /// Processes that are excluded from the tunnel, as last reported to the event listener.
+    #[cfg(target_os = "linux")]
+    excluded_processes: Vec<ExcludedProcess>,
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    /// Expiry of the account that is logged in, and which warnings have been sent for it.
+    account_expiry: account_expiry::ExpiryTracker,
+    account_expiry_job: Option<AbortHandle>,
+    /// How long the connected tunnel has been idle, and the job that regularly checks it.
+    idle_tracker: idle_disconnect::IdleTracker,
+    idle_check_job: Option<AbortHandle>,
     event_listener: L,
     migration_complete: migrations::MigrationComplete,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
     device_checker: device::TunnelStateChangeHandler,
     account_manager: device::AccountManagerHandle,
-    connection_modes: Arc<Mutex<api::ConnectionModesIterator>>,
+    connection_modes: Arc<Mutex<access_method_selector::AccessMethodSelector>>,
+    api_connectivity: api_connectivity::ApiConnectivityMonitor,
+    endpoint_updater: api::ApiEndpointUpdaterHandle,
     api_runtime: mullvad_api::Runtime,
     api_handle: mullvad_api::rest::MullvadRestHandle,
     version_updater_handle: version_check::VersionUpdaterHandle,
     relay_selector: RelaySelector,
     relay_list_updater: RelayListUpdaterHandle,
+    /// Network of the default route, which decides what network obfuscation profile is used.
+    current_network: CurrentNetwork,
+    /// Decides when the network trust rules may change the target state.
+    network_trust: network_trust::NetworkTrustArbiter,
+    /// Outcomes of connection attempts, which decide the order of obfuscation types in auto mode.
+    obfuscation_scores: obfuscation_scores::ObfuscationScoreStore,
+    /// Named copies of the settings that the user can switch between.
+    profiles: profiles::ProfileStore,
+    /// Tunnel type that last connected on each network during this session, if the tunnel
+    /// protocol is automatic. It is tried first for as long as the daemon runs.
+    session_tunnel_types: HashMap<NetworkId, TunnelType>,
+    metrics: metrics::MetricsRegistry,
+    /// Significant events, kept for debugging regardless of the log level.
+    recent_events: recent_events::RecentEvents,
+    /// The most recent connections and how they ended.
+    connection_history: connection_history::ConnectionHistoryStore,
+    /// Problem reports which could not be sent by `mullvad-problem-report`.
+    problem_report_outbox: Outbox,
+    /// SOCKS5 server on localhost, which only runs while connected.
+    local_proxy: Option<local_proxy::LocalProxy>,
     parameters_generator: tunnel::ParametersGenerator,
     app_version_info: Option<AppVersionInfo>,
     shutdown_tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>,
@@ -624,7 +1030,8 @@ where
         let api_availability = api_runtime.availability_handle();
         api_availability.suspend();
 
-        let endpoint_updater = api::ApiEndpointUpdaterHandle::new();
+        let recent_events = recent_events::RecentEvents::new(log_dir.clone());
+        let endpoint_updater = api::ApiEndpointUpdaterHandle::new(recent_events.clone());
 
         let migration_data = migrations::migrate_all(&cache_dir, &settings_dir)
             .await
@@ -636,9 +1043,21 @@ where
                 None
             });
         let settings = SettingsPersister::load(&settings_dir).await;
+        talpid_core::logging::set_rotation_config(settings::log_rotation_config(
+            &settings.log_rotation,
+        ));
+        let profiles = profiles::ProfileStore::load(&settings_dir).await;
         let app_version_info = version_check::load_cache(&cache_dir).await;
 
-        let initial_selector_config = new_selector_config(&settings);
+        let obfuscation_scores = obfuscation_scores::ObfuscationScoreStore::load(&cache_dir).await;
+        let connection_history = connection_history::ConnectionHistoryStore::load(&cache_dir).await;
+
+        let initial_selector_config = new_selector_config(
+            &settings,
+            &CurrentNetwork::default(),
+            obfuscation_scores.scores(),
+            &HashMap::new(),
+        );
         let relay_selector = RelaySelector::new(initial_selector_config, &resource_dir, &cache_dir);
 
         let proxy_provider = api::ApiConnectionModeProvider::new(
@@ -652,12 +1071,22 @@ where
                 .filter(|api_access_method| api_access_method.enabled())
                 .cloned()
                 .collect(),
+            recent_events.clone(),
         );
 
         let connection_modes = proxy_provider.handle();
+        let api_connectivity = api_connectivity::ApiConnectivityMonitor::new();
+        tokio::spawn(forward_api_connectivity(
+            api_connectivity.subscribe(),
+            internal_event_tx.clone(),
+        ));
 
         let api_handle = api_runtime
-            .mullvad_rest_handle(proxy_provider, endpoint_updater.callback())
+            .mullvad_rest_handle(
+                proxy_provider,
+                endpoint_updater.callback(),
+                api::outcome_recorder(connection_modes.clone(), api_connectivity.clone()),
+            )
             .await;
 
         let migration_complete = if let Some(migration_data) = migration_data {
@@ -678,6 +1107,7 @@ where
                 .wireguard
                 .rotation_interval
                 .unwrap_or_default(),
+            api_connectivity.clone(),
             internal_event_tx.to_specialized_sender(),
         )
         .await
@@ -698,15 +1128,10 @@ where
         };
 
         #[cfg(windows)]
-        let exclude_paths = if settings.split_tunnel.enable_exclusions {
-            settings
-                .split_tunnel
-                .apps
-                .iter()
-                .map(OsString::from)
-                .collect()
+        let exclude_apps = if settings.split_tunnel.enable_exclusions {
+            excluded_apps(&settings.split_tunnel.apps)
         } else {
-            vec![]
+            split_tunnel::ExcludedApps::default()
         };
 
         let initial_api_endpoint =
@@ -715,33 +1140,78 @@ where
             account_manager.clone(),
             relay_selector.clone(),
             settings.tunnel_options.clone(),
+            recent_events.clone(),
         );
         let (offline_state_tx, offline_state_rx) = mpsc::unbounded();
         #[cfg(target_os = "windows")]
         let (volume_update_tx, volume_update_rx) = mpsc::unbounded();
+        #[cfg(target_os = "windows")]
+        let (app_status_tx, mut app_status_rx) = mpsc::unbounded();
+        #[cfg(target_os = "windows")]
+        let (excluded_processes_tx, mut excluded_processes_rx) = mpsc::unbounded();
+        let (dns_tampered_tx, mut dns_tampered_rx) = mpsc::unbounded();
+        let (effective_dns_tx, mut effective_dns_rx) = mpsc::unbounded();
+        let (network_change_tx, mut network_change_rx) = mpsc::unbounded();
         let tunnel_state_machine_handle = tunnel_state_machine::spawn(
             tunnel_state_machine::InitialTunnelState {
                 allow_lan: settings.allow_lan,
+                bypass_routes: settings.bypass_routes.clone(),
                 block_when_disconnected: settings.block_when_disconnected,
                 dns_servers: dns::addresses_from_options(&settings.tunnel_options.dns_options),
+                dns_source: dns::source_from_options(&settings.tunnel_options.dns_options),
+                tls_dns_servers: dns::tls_servers_from_options(
+                    &settings.tunnel_options.dns_options,
+                ),
+                preserve_search_domains: settings
+                    .tunnel_options
+                    .dns_options
+                    .preserve_search_domains,
+                allow_lan_dns_when_blocked: settings
+                    .tunnel_options
+                    .dns_options
+                    .allow_lan_dns_when_blocked,
+                keep_custom_dns_while_disconnected: settings
+                    .tunnel_options
+                    .dns_options
+                    .keep_custom_dns_while_disconnected,
+                retry_backoff: settings.retry_backoff,
+                // Auto-connect usually happens at boot, before the network is up
+                wait_for_network_at_startup: settings.auto_connect,
                 allowed_endpoint: initial_api_endpoint,
                 reset_firewall: *target_state != TargetState::Secured,
+                #[cfg(target_os = "linux")]
+                excluded_owners: excluded_owners(&settings),
+                #[cfg(target_os = "linux")]
+                split_tunnel: split_tunnel_config(&settings),
                 #[cfg(windows)]
-                exclude_paths,
+                exclude_apps,
             },
             parameters_generator.clone(),
             log_dir,
             resource_dir.clone(),
             internal_event_tx.to_specialized_sender(),
             offline_state_tx,
+            dns_tampered_tx,
+            effective_dns_tx,
+            network_change_tx,
             #[cfg(target_os = "windows")]
             volume_update_rx,
+            #[cfg(target_os = "windows")]
+            app_status_tx,
+            #[cfg(target_os = "windows")]
+            excluded_processes_tx,
             #[cfg(target_os = "android")]
             android_context,
             #[cfg(target_os = "linux")]
-            tunnel_state_machine::LinuxNetworkingIdentifiers {
-                fwmark: mullvad_types::TUNNEL_FWMARK,
-                table_id: mullvad_types::TUNNEL_TABLE_ID,
+            cache_dir.clone(),
+            #[cfg(target_os = "linux")]
+            {
+                let routing = settings::routing_settings(&settings.routing);
+                tunnel_state_machine::LinuxNetworkingIdentifiers {
+                    fwmark: mullvad_types::TUNNEL_FWMARK,
+                    table_id: routing.route_table,
+                    rule_priority: routing.rule_priority,
+                }
             },
         )
         .await
@@ -750,7 +1220,103 @@ where
         endpoint_updater
             .set_tunnel_command_tx(Arc::downgrade(tunnel_state_machine_handle.command_tx()));
 
-        api::forward_offline_state(api_availability.clone(), offline_state_rx);
+        #[cfg(target_os = "linux")]
+        if let Err(error) = tunnel_state_machine_handle
+            .route_manager()
+            .add_routes_update_listener(routes_update_listener(&internal_event_tx))
+        {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to listen for route updates")
+            );
+        }
+
+        api::forward_offline_state(
+            api_availability.clone(),
+            api_runtime.address_cache.clone(),
+            api_handle.service(),
+            relay_selector.clone(),
+            recent_events.clone(),
+            offline_state_rx,
+        );
+
+        let dns_tampered_tx = internal_event_tx.clone();
+        tokio::spawn(async move {
+            while dns_tampered_rx.next().await.is_some() {
+                if dns_tampered_tx
+                    .send(InternalDaemonEvent::DnsTampered)
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let effective_dns_event_tx = internal_event_tx.clone();
+        tokio::spawn(async move {
+            while let Some(effective_dns) = effective_dns_rx.next().await {
+                if effective_dns_event_tx
+                    .send(InternalDaemonEvent::EffectiveDnsChanged(effective_dns))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let network_change_listener = event_listener.clone();
+        let network_identity_tx = internal_event_tx.to_specialized_sender();
+        tokio::spawn(async move {
+            while let Some(change) = network_change_rx.next().await {
+                let interface = change.interface.clone();
+                network_change_listener.notify_network_changed(change);
+                let network = network_identity::identify(interface).await;
+                if network_identity_tx.send(network).is_err() {
+                    break;
+                }
+            }
+        });
+
+        #[cfg(target_os = "windows")]
+        {
+            let app_status_listener = event_listener.clone();
+            tokio::spawn(async move {
+                while let Some(statuses) = app_status_rx.next().await {
+                    app_status_listener.notify_split_tunnel_app_status(statuses);
+                }
+            });
+            let excluded_processes_listener = event_listener.clone();
+            tokio::spawn(async move {
+                while let Some(processes) = excluded_processes_rx.next().await {
+                    excluded_processes_listener.notify_excluded_processes(processes);
+                }
+            });
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            let route_manager = tunnel_state_machine_handle.route_manager().clone();
+            if let Err(error) = route_manager.set_coexistence_mode(settings.coexistence_mode) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set coexistence mode")
+                );
+            }
+            let foreign_tunnel_listener = event_listener.clone();
+            tokio::spawn(async move {
+                match route_manager.foreign_tunnel_listener().await {
+                    Ok(mut events) => {
+                        while let Some(interface) = events.next().await {
+                            foreign_tunnel_listener.notify_foreign_tunnel(interface);
+                        }
+                    }
+                    Err(error) => log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to listen for other VPNs")
+                    ),
+                }
+            });
+        }
 
         let relay_list_listener = event_listener.clone();
         let on_relay_list_update = move |relay_list: &RelayList| {
@@ -774,6 +1340,39 @@ where
         );
         tokio::spawn(version_updater.run());
 
+        let problem_report_outbox = Outbox::new(&cache_dir);
+        let problem_report_proxy = mullvad_api::ProblemReportProxy::new(api_handle.clone());
+        let problem_report_listener = event_listener.clone();
+        tokio::spawn(mullvad_problem_report::outbox::run_sender(
+            problem_report_outbox.clone(),
+            api_availability.clone(),
+            mullvad_api::retry::RetryPolicy::background(),
+            PROBLEM_REPORT_OUTBOX_INTERVAL,
+            move |report| {
+                problem_report_proxy.problem_report(
+                    &report.user_email,
+                    &report.user_message,
+                    &report.report,
+                    &report.metadata,
+                )
+            },
+            move |report| problem_report_listener.notify_problem_report_sent(report.id.clone()),
+        ));
+
+        #[cfg(target_os = "linux")]
+        match split_tunnel::ProcessEvents::new() {
+            Ok(events) => {
+                let internal_event_tx = internal_event_tx.clone();
+                std::thread::spawn(move || forward_process_events(events, internal_event_tx));
+            }
+            Err(error) => log::warn!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to listen for process events. Excluded processes are not reported"
+                )
+            ),
+        }
+
         // Attempt to download a fresh relay list
         relay_list_updater.update().await;
 
@@ -783,21 +1382,44 @@ where
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
             exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
+            #[cfg(target_os = "linux")]
+            excluded_processes: vec![],
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
+            account_expiry: account_expiry::ExpiryTracker::new(
+                &settings.account_expiry_warnings.thresholds,
+            ),
+            account_expiry_job: None,
+            idle_tracker: idle_disconnect::IdleTracker::new(Instant::now()),
+            idle_check_job: None,
             event_listener,
             migration_complete,
             settings,
             account_history,
-            device_checker: device::TunnelStateChangeHandler::new(account_manager.clone()),
+            device_checker: device::TunnelStateChangeHandler::new(
+                account_manager.clone(),
+                api_connectivity.clone(),
+            ),
             account_manager,
             connection_modes,
+            api_connectivity,
+            endpoint_updater,
             api_runtime,
             api_handle,
             version_updater_handle,
             relay_selector,
             relay_list_updater,
+            current_network: CurrentNetwork::default(),
+            network_trust: network_trust::NetworkTrustArbiter::new(),
+            obfuscation_scores,
+            profiles,
+            session_tunnel_types: HashMap::new(),
+            metrics: metrics::MetricsRegistry::new(Instant::now()),
+            recent_events,
+            connection_history,
+            problem_report_outbox,
+            local_proxy: None,
             parameters_generator,
             app_version_info,
             shutdown_tasks: vec![],
@@ -817,6 +1439,7 @@ where
         if *self.target_state == TargetState::Secured {
             self.connect_tunnel();
         }
+        self.refresh_account_expiry();
 
         while let Some(event) = self.rx.next().await {
             self.handle_event(event).await;
@@ -895,6 +1518,18 @@ where
             DeviceMigrationEvent(event) => self.handle_device_migration_event(event),
             #[cfg(windows)]
             ExcludedPathsEvent(update, tx) => self.handle_new_excluded_paths(update, tx).await,
+            DnsTampered => self.handle_dns_tampered(),
+            EffectiveDnsChanged(effective_dns) => self.handle_effective_dns_changed(effective_dns),
+            #[cfg(target_os = "linux")]
+            RoutesUpdated(update) => self
+                .event_listener
+                .notify_routes_updated(routes::routes_update(update)),
+            NetworkIdentified(network) => self.handle_network_identified(network).await,
+            AccountExpiryCheck => self.handle_account_expiry_check(),
+            IdleCheck => self.handle_idle_check().await,
+            ApiConnectivityChanged(status) => self.handle_api_connectivity_changed(status),
+            #[cfg(target_os = "linux")]
+            ProcessesChanged => self.handle_processes_changed(),
         }
     }
 
@@ -905,16 +1540,38 @@ where
         self.reset_rpc_sockets_on_tunnel_state_transition(&tunnel_state_transition);
         self.device_checker
             .handle_state_transition(&tunnel_state_transition);
+        self.handle_obfuscation_score_transition(&tunnel_state_transition)
+            .await;
+        self.metrics
+            .handle_state_transition(&tunnel_state_transition, Instant::now());
+        for (kind, message) in recent_events::describe_transition(&tunnel_state_transition) {
+            self.recent_events.push(kind, message);
+        }
+        let location = match tunnel_state_transition {
+            TunnelStateTransition::Connecting(..) => {
+                self.parameters_generator.get_last_location().await
+            }
+            _ => None,
+        };
+        self.connection_history
+            .handle_state_transition(&tunnel_state_transition, location.as_ref())
+            .await;
 
         let tunnel_state = match tunnel_state_transition {
             TunnelStateTransition::Disconnected => TunnelState::Disconnected,
-            TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
-                endpoint,
-                location: self.parameters_generator.get_last_location().await,
-            },
-            TunnelStateTransition::Connected(endpoint) => TunnelState::Connected {
+            TunnelStateTransition::Connecting(endpoint, phase, attempt) => {
+                TunnelState::Connecting {
+                    endpoint,
+                    location: self.parameters_generator.get_last_location().await,
+                    phase,
+                    attempt,
+                }
+            }
+            TunnelStateTransition::Connected(endpoint, effective_dns) => TunnelState::Connected {
+                first_hop: Some(features::first_hop(&endpoint)),
                 endpoint,
                 location: self.parameters_generator.get_last_location().await,
+                effective_dns,
             },
             TunnelStateTransition::Disconnecting(after_disconnect) => {
                 TunnelState::Disconnecting(after_disconnect)
@@ -958,26 +1615,180 @@ where
                     );
                 }
 
-                if let ErrorStateCause::AuthFailed(_) = error_state.cause() {
-                    // If time is added outside of the app, no notifications
-                    // are received. So we must continually try to reconnect.
-                    self.schedule_reconnect(Duration::from_secs(60))
+                match error_state.cause() {
+                    ErrorStateCause::AuthFailed(_) => {
+                        // If time is added outside of the app, no notifications
+                        // are received. So we must continually try to reconnect.
+                        self.schedule_reconnect(Duration::from_secs(60));
+                        if error_state.cause().error_code() == ErrorCode::AuthFailedExpiredAccount {
+                            // Block until time has been added if the account data agrees
+                            self.refresh_account_expiry();
+                        }
+                    }
+                    ErrorStateCause::AccountExpired => self.unschedule_reconnect(),
+                    _ => (),
                 }
             }
             _ => {}
         }
 
+        let was_disconnected = self.tunnel_state.is_disconnected();
         self.tunnel_state = tunnel_state.clone();
+        if self.is_blocked_by_account_expiry() {
+            // Refresh the account data until time has been added
+            self.schedule_account_expiry_check();
+        }
+        self.update_idle_check();
+        if !(was_disconnected && tunnel_state.is_disconnected()) {
+            self.run_hook().await;
+        }
+        self.update_api_tunnel_addresses().await;
+        self.update_local_proxy(false).await;
         self.event_listener.notify_new_state(tunnel_state);
     }
 
+    fn handle_dns_tampered(&mut self) {
+        self.event_listener.notify_dns_tampered();
+        if mark_dns_tampered(&mut self.tunnel_state) {
+            self.event_listener
+                .notify_new_state(self.tunnel_state.clone());
+        }
+    }
+
+    fn handle_effective_dns_changed(&mut self, effective_dns: EffectiveDns) {
+        if update_effective_dns(&mut self.tunnel_state, effective_dns) {
+            self.event_listener
+                .notify_new_state(self.tunnel_state.clone());
+        }
+    }
+
+    /// Runs the hook of the current tunnel state, if there is one.
+    async fn run_hook(&self) {
+        if let Some(hook) = hooks::hook_for_state(&self.settings.hooks, &self.tunnel_state) {
+            let env =
+                hooks::environment(&self.tunnel_state, self.device_addresses().await.as_ref());
+            hooks::spawn(hook.to_owned(), env);
+        }
+    }
+
+    /// Returns the in-tunnel addresses of the current device, if logged in.
+    async fn device_addresses(&self) -> Option<AssociatedAddresses> {
+        self.account_manager
+            .data()
+            .await
+            .ok()
+            .and_then(|state| state.into_device())
+            .map(|device| device.device.wg_data.addresses)
+    }
+
+    /// Records the outcome of the connection attempt that the transition ends, if any. The order of
+    /// obfuscation types is only updated once the tunnel settles, so that the retry schedule of a
+    /// connection attempt is not upset by each failure.
+    async fn handle_obfuscation_score_transition(&mut self, transition: &TunnelStateTransition) {
+        if let TunnelStateTransition::Connected(endpoint, _) = transition {
+            self.record_session_tunnel_type(endpoint.tunnel_type);
+        }
+        self.obfuscation_scores
+            .handle_state_transition(transition, &self.current_network)
+            .await;
+        if matches!(
+            transition,
+            TunnelStateTransition::Connected(..)
+                | TunnelStateTransition::Disconnected
+                | TunnelStateTransition::Error(_)
+        ) {
+            self.relay_selector.set_config(new_selector_config(
+                &self.settings,
+                &self.current_network,
+                self.obfuscation_scores.scores(),
+                &self.session_tunnel_types,
+            ));
+        }
+    }
+
+    /// Remembers that `tunnel_type` connected on the current network, if the tunnel protocol is
+    /// automatic. Explicitly selected tunnel protocols are never affected.
+    fn record_session_tunnel_type(&mut self, tunnel_type: TunnelType) {
+        let automatic_protocol = matches!(
+            &self.settings.relay_settings,
+            RelaySettings::Normal(constraints) if constraints.tunnel_protocol.is_any()
+        );
+        let Some(network) = self.current_network.id() else {
+            return;
+        };
+        if automatic_protocol && self.session_tunnel_types.get(&network) != Some(&tunnel_type) {
+            log::info!("Preferring {tunnel_type} on {network} for the rest of the session");
+            self.session_tunnel_types.insert(network, tunnel_type);
+        }
+    }
+
+    /// Tells the API connector whether, and from which addresses, to send API traffic inside the
+    /// tunnel.
+    async fn update_api_tunnel_addresses(&self) {
+        let addresses = api::api_tunnel_addresses(
+            &self.settings,
+            &self.tunnel_state,
+            self.device_addresses().await.as_ref(),
+        );
+        self.api_handle.service().set_tunnel_addresses(addresses);
+    }
+
+    /// Starts the local proxy if it is enabled and the tunnel is connected, and stops it
+    /// otherwise. Connections made through the proxy would not use the tunnel in other states, or
+    /// in include mode.
+    /// If `restart` is set, a running proxy is restarted so that new settings take effect.
+    async fn update_local_proxy(&mut self, restart: bool) {
+        let port = match self.settings.local_proxy.port {
+            Some(port) if self.tunnel_state.is_connected() => {
+                if local_proxy::is_supported(&self.settings) {
+                    Some(port)
+                } else {
+                    log::warn!(
+                        "Not running the local proxy, since only the selected processes use the \
+                         tunnel"
+                    );
+                    None
+                }
+            }
+            _ => None,
+        };
+        let Some(port) = port else {
+            if let Some(mut proxy) = self.local_proxy.take() {
+                proxy.stop().await;
+                log::debug!("Stopped local proxy");
+            }
+            return;
+        };
+        if let Some(proxy) = &mut self.local_proxy {
+            if !restart {
+                return;
+            }
+            proxy.stop().await;
+            self.local_proxy = None;
+        }
+
+        let authentication = self.settings.local_proxy.authentication.clone();
+        match local_proxy::LocalProxy::start(port, authentication, self.settings.allow_lan).await {
+            Ok(proxy) => {
+                log::info!("Local proxy listening on {}", proxy.local_addr());
+                self.local_proxy = Some(proxy);
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start local proxy")
+                );
+            }
+        }
+    }
+
     fn reset_rpc_sockets_on_tunnel_state_transition(
         &mut self,
         tunnel_state_transition: &TunnelStateTransition,
     ) {
         match (&self.tunnel_state, &tunnel_state_transition) {
             // Only reset the API sockets when entering or leaving the connected state
-            (&TunnelState::Connected { .. }, _) | (_, &TunnelStateTransition::Connected(_)) => {
+            (&TunnelState::Connected { .. }, _) | (_, &TunnelStateTransition::Connected(..)) => {
                 self.api_handle.service().reset();
             }
             _ => (),
@@ -1007,46 +1818,237 @@ where
         }
     }
 
-    async fn handle_command(&mut self, command: DaemonCommand) {
-        use self::DaemonCommand::*;
-        if !self.state.is_running() {
-            log::trace!("Dropping daemon command because the daemon is shutting down",);
-            return;
-        }
+    /// Returns whether the tunnel is blocking because the account is out of time.
+    fn is_blocked_by_account_expiry(&self) -> bool {
+        matches!(
+            &self.tunnel_state,
+            TunnelState::Error(state) if matches!(state.cause(), ErrorStateCause::AccountExpired)
+        )
+    }
 
-        if self.tunnel_state.is_disconnected() {
-            self.api_handle.availability.reset_inactivity_timer();
+    /// Fetches the account expiry in the background. The result is received as an
+    /// [`AccountEvent::Expiry`].
+    fn refresh_account_expiry(&self) {
+        let account_manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            if let Err(error) = account_manager.check_expiry().await {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to refresh the account expiry")
+                );
+            }
+        });
+    }
+
+    /// Schedules an [`InternalDaemonEvent::AccountExpiryCheck`] for when the next warning is due
+    /// or the account expires, replacing any check that was scheduled before.
+    fn schedule_account_expiry_check(&mut self) {
+        if let Some(job) = self.account_expiry_job.take() {
+            job.abort();
         }
 
-        match command {
-            SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
-            Reconnect(tx) => self.on_reconnect(tx),
-            GetState(tx) => self.on_get_state(tx),
-            GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
-            CreateNewAccount(tx) => self.on_create_new_account(tx),
-            GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token),
-            GetWwwAuthToken(tx) => self.on_get_www_auth_token(tx).await,
-            SubmitVoucher(tx, voucher) => self.on_submit_voucher(tx, voucher),
-            GetRelayLocations(tx) => self.on_get_relay_locations(tx),
-            UpdateRelayLocations => self.on_update_relay_locations().await,
-            LoginAccount(tx, account_token) => self.on_login_account(tx, account_token),
-            LogoutAccount(tx) => self.on_logout_account(tx),
-            GetDevice(tx) => self.on_get_device(tx),
-            UpdateDevice(tx) => self.on_update_device(tx),
-            ListDevices(tx, account_token) => self.on_list_devices(tx, account_token),
-            RemoveDevice(tx, account_token, device_id) => {
-                self.on_remove_device(tx, account_token, device_id)
-            }
+        let until_deadline = self.account_expiry.next_deadline().map(|deadline| {
+            (deadline - chrono::Utc::now())
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+                .min(ACCOUNT_EXPIRY_CHECK_INTERVAL)
+        });
+        let delay = if self.is_blocked_by_account_expiry() {
+            until_deadline
+                .unwrap_or(ACCOUNT_EXPIRED_REFRESH_INTERVAL)
+                .min(ACCOUNT_EXPIRED_REFRESH_INTERVAL)
+        } else {
+            match until_deadline {
+                Some(delay) => delay,
+                None => return,
+            }
+        };
+
+        let daemon_tx = self.tx.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            let _ = daemon_tx.send(InternalDaemonEvent::AccountExpiryCheck);
+        }));
+        tokio::spawn(future);
+        self.account_expiry_job = Some(abort_handle);
+    }
+
+    fn handle_api_connectivity_changed(&mut self, status: ApiConnectivity) {
+        let message = match &status {
+            ApiConnectivity::Unknown => return,
+            ApiConnectivity::Reachable => "The API is reachable".to_owned(),
+            ApiConnectivity::Unreachable { last_error, .. } => {
+                format!("The API is unreachable: {last_error}")
+            }
+        };
+        log::info!("{message}");
+        self.recent_events.push(EventKind::ApiConnectivity, message);
+        self.event_listener.notify_api_connectivity(status);
+    }
+
+    fn handle_account_expiry_check(&mut self) {
+        self.account_expiry_job = None;
+
+        let event = self.account_expiry.poll(chrono::Utc::now());
+        match event {
+            Some(ExpiryEvent::Warning { expiry, threshold }) => {
+                self.notify_account_expiry_warning(expiry, threshold);
+            }
+            Some(ExpiryEvent::Expired) => {
+                log::info!("The account has run out of time");
+                if *self.target_state == TargetState::Secured
+                    && !self.is_blocked_by_account_expiry()
+                {
+                    self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::AccountExpired));
+                }
+            }
+            None => (),
+        }
+
+        // Time may have been added since the expiry was fetched
+        if event.is_some() || self.is_blocked_by_account_expiry() {
+            self.refresh_account_expiry();
+        }
+        self.schedule_account_expiry_check();
+    }
+
+    /// Checks the traffic counters of the tunnel regularly while it is connected and should be
+    /// disconnected when idle, and stops checking them otherwise. The tunnel counts as in use
+    /// when it connects.
+    fn update_idle_check(&mut self) {
+        if self.settings.auto_disconnect_idle.is_none() || !self.tunnel_state.is_connected() {
+            if let Some(job) = self.idle_check_job.take() {
+                job.abort();
+            }
+            return;
+        }
+        if self.idle_check_job.is_some() {
+            return;
+        }
+
+        self.idle_tracker.reset(Instant::now());
+        let daemon_tx = self.tx.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            let mut interval = tokio::time::interval(idle_disconnect::CHECK_INTERVAL);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if daemon_tx.send(InternalDaemonEvent::IdleCheck).is_err() {
+                    break;
+                }
+            }
+        }));
+        tokio::spawn(future);
+        self.idle_check_job = Some(abort_handle);
+    }
+
+    async fn handle_idle_check(&mut self) {
+        let Some(timeout) = self.settings.auto_disconnect_idle else {
+            return;
+        };
+        if !self.tunnel_state.is_connected() || *self.target_state != TargetState::Secured {
+            return;
+        }
+        let stats = self.tunnel_state_machine_handle.traffic_counters().get();
+        let idle_for = self.idle_tracker.update(Instant::now(), stats);
+        if idle_for < timeout {
+            return;
+        }
+
+        // The firewall keeps blocking in lockdown mode, so the user must connect again to get
+        // online. The event lets the frontends explain why.
+        let locked_down = self.block_when_disconnected();
+        log::info!(
+            "Disconnecting since the tunnel has been idle for {} seconds",
+            idle_for.as_secs()
+        );
+        self.recent_events.push(
+            EventKind::TunnelState,
+            format!(
+                "Disconnecting after being idle for {} seconds",
+                idle_for.as_secs()
+            ),
+        );
+        // Like a disconnect by the user, this is not undone by the network trust rules until
+        // another network is joined. Auto-connect only connects when the daemon starts.
+        self.network_trust.user_set_target_state();
+        self.set_target_state(TargetState::Unsecured).await;
+        self.event_listener.notify_idle_disconnect(IdleDisconnect {
+            idle_for,
+            locked_down,
+        });
+    }
+
+    fn notify_account_expiry_warning(
+        &self,
+        expiry: chrono::DateTime<chrono::Utc>,
+        threshold: Duration,
+    ) {
+        log::info!("The account expires at {expiry}");
+        self.event_listener
+            .notify_account_expiry_warning(AccountExpiryWarning { expiry, threshold });
+    }
+
+    async fn handle_command(&mut self, command: DaemonCommand) {
+        use self::DaemonCommand::*;
+        if !self.state.is_running() {
+            log::trace!("Dropping daemon command because the daemon is shutting down",);
+            return;
+        }
+
+        if self.tunnel_state.is_disconnected() {
+            self.api_handle.availability.reset_inactivity_timer();
+        }
+
+        match command {
+            SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
+            Reconnect(tx) => self.on_reconnect(tx),
+            ReconnectWithOverrides(tx, overrides) => {
+                self.on_reconnect_with_overrides(tx, overrides).await
+            }
+            GetState(tx) => self.on_get_state(tx),
+            GetTunnelStats(tx) => self.on_get_tunnel_stats(tx),
+            GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
+            CreateNewAccount(tx) => self.on_create_new_account(tx),
+            GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token),
+            GetWwwAuthToken(tx) => self.on_get_www_auth_token(tx).await,
+            SubmitVoucher(tx, voucher) => self.on_submit_voucher(tx, voucher),
+            GetRelayLocations(tx) => self.on_get_relay_locations(tx),
+            UpdateRelayLocations => self.on_update_relay_locations().await,
+            LoginAccount(tx, account_token) => self.on_login_account(tx, account_token),
+            LogoutAccount(tx) => self.on_logout_account(tx),
+            GetDevice(tx) => self.on_get_device(tx),
+            UpdateDevice(tx) => self.on_update_device(tx),
+            ListDevices(tx, account_token) => self.on_list_devices(tx, account_token),
+            RemoveDevice(tx, account_token, device_id) => {
+                self.on_remove_device(tx, account_token, device_id)
+            }
             GetAccountHistory(tx) => self.on_get_account_history(tx),
             ClearAccountHistory(tx) => self.on_clear_account_history(tx).await,
+            ListSavedAccounts(tx) => self.on_list_saved_accounts(tx),
+            SetSavedAccountLabel(tx, account_token, label) => {
+                self.on_set_saved_account_label(tx, account_token, label)
+                    .await
+            }
+            SwitchAccount(tx, label_or_token) => self.on_switch_account(tx, label_or_token),
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update).await,
+            PreviewRelaySettings(tx, update) => self.on_preview_relay_settings(tx, update),
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan).await,
+            SetBypassRoutes(tx, routes) => self.on_set_bypass_routes(tx, routes).await,
+            SetCoexistenceMode(tx, enabled) => self.on_set_coexistence_mode(tx, enabled).await,
+            SetPreferApiInTunnel(tx, enabled) => {
+                self.on_set_prefer_api_in_tunnel(tx, enabled).await
+            }
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled).await,
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
                     .await
             }
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect).await,
+            SetAutoDisconnectIdle(tx, timeout) => {
+                self.on_set_auto_disconnect_idle(tx, timeout).await
+            }
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg).await,
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings).await
@@ -1058,6 +2060,20 @@ where
                     .await
             }
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
+            RunDnsLeakTest(tx) => self.on_run_dns_leak_test(tx).await,
+            CheckConnection(tx, allow_disconnected) => {
+                self.on_check_connection(tx, allow_disconnected).await
+            }
+            GetRoutes(tx) => self.on_get_routes(tx),
+            GetObfuscationScores(tx) => self.on_get_obfuscation_scores(tx),
+            GetMetrics(tx) => self.on_get_metrics(tx),
+            GetRecentEvents(tx) => self.on_get_recent_events(tx),
+            GetConnectionHistory(tx) => self.on_get_connection_history(tx),
+            QueueProblemReport(tx, email, message, report) => {
+                self.on_queue_problem_report(tx, email, message, report)
+            }
+            GetQueuedProblemReports(tx) => self.on_get_queued_problem_reports(tx),
+            FlushQueuedProblemReports(tx) => self.on_flush_queued_problem_reports(tx),
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
@@ -1068,7 +2084,11 @@ where
             CreateCustomList(tx, name) => self.on_create_custom_list(tx, name).await,
             DeleteCustomList(tx, id) => self.on_delete_custom_list(tx, id).await,
             UpdateCustomList(tx, update) => self.on_update_custom_list(tx, update).await,
+            ReplaceCustomList(tx, name, locations) => {
+                self.on_replace_custom_list(tx, name, locations).await
+            }
             GetVersionInfo(tx) => self.on_get_version_info(tx),
+            CheckVersion(tx, beta) => self.on_check_version(tx, beta),
             GetApiAccessMethods(tx) => self.on_get_api_access_methods(tx),
             AddApiAccessMethod(tx, name, enabled, access_method) => {
                 self.on_add_access_method(tx, name, enabled, access_method)
@@ -1077,35 +2097,101 @@ where
             RemoveApiAccessMethod(tx, method) => self.on_remove_api_access_method(tx, method).await,
             UpdateApiAccessMethod(tx, method) => self.on_update_api_access_method(tx, method).await,
             GetCurrentAccessMethod(tx) => self.on_get_current_api_access_method(tx),
+            GetApiAccessMethodStats(tx) => self.on_get_api_access_method_stats(tx),
+            GetApiConnectivity(tx) => self.on_get_api_connectivity(tx),
+            TestApiAccessMethod(tx, method) => self.on_test_api_access_method(tx, method),
+            ImportApiAccessMethod(tx, encoded) => {
+                self.on_import_api_access_method(tx, encoded).await
+            }
+            ExportApiAccessMethod(tx, method) => self.on_export_api_access_method(tx, method),
             SetApiAccessMethod(tx, method) => self.on_set_api_access_method(tx, method).await,
             GetApiAddresses(tx) => self.on_get_api_addresses(tx).await,
+            SetApiEndpointOverride(tx, endpoint_override) => {
+                self.on_set_api_endpoint_override(tx, endpoint_override)
+                    .await
+            }
+            ScriptMockTunnel(tx, script) => self.on_script_mock_tunnel(tx, script),
             IsPerformingPostUpgrade(tx) => self.on_is_performing_post_upgrade(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
             #[cfg(not(target_os = "android"))]
-            FactoryReset(tx) => self.on_factory_reset(tx).await,
+            FactoryReset(tx, scopes) => self.on_factory_reset(tx, scopes).await,
+            ExportSettings(tx, include_secrets) => {
+                self.on_export_settings(tx, include_secrets).await
+            }
+            ImportSettings(tx, document) => self.on_import_settings(tx, document).await,
+            ApplySettingsPatch(tx, patch) => self.on_apply_settings_patch(tx, patch).await,
+            SaveProfile(tx, name) => self.on_save_profile(tx, name).await,
+            ListProfiles(tx) => self.on_list_profiles(tx),
+            ApplyProfile(tx, name) => self.on_apply_profile(tx, name).await,
+            DeleteProfile(tx, name) => self.on_delete_profile(tx, name).await,
             #[cfg(target_os = "linux")]
             GetSplitTunnelProcesses(tx) => self.on_get_split_tunnel_processes(tx),
             #[cfg(target_os = "linux")]
+            GetExcludedProcesses(tx) => self.on_get_excluded_processes(tx),
+            #[cfg(target_os = "linux")]
             AddSplitTunnelProcess(tx, pid) => self.on_add_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
+            #[cfg(target_os = "linux")]
+            SetSplitTunnelOwners(tx, users, groups) => {
+                self.on_set_split_tunnel_owners(tx, users, groups).await
+            }
+            #[cfg(target_os = "linux")]
+            SetSplitTunnelMode(tx, mode) => self.on_set_split_tunnel_mode(tx, mode).await,
+            #[cfg(target_os = "linux")]
+            SetSplitTunnelDestinations(tx, destinations) => {
+                self.on_set_split_tunnel_destinations(tx, destinations)
+                    .await
+            }
+            #[cfg(target_os = "linux")]
+            SetSplitTunnelInterfaces(tx, interfaces) => {
+                self.on_set_split_tunnel_interfaces(tx, interfaces).await
+            }
+            #[cfg(target_os = "linux")]
+            SetExcludedAppsAllowLan(tx, allow) => {
+                self.on_set_excluded_apps_allow_lan(tx, allow).await
+            }
+            #[cfg(target_os = "linux")]
+            SetSplitTunnelLocalPorts(tx, ports) => {
+                self.on_set_split_tunnel_local_ports(tx, ports).await
+            }
+            SetLocalProxySettings(tx, settings) => {
+                self.on_set_local_proxy_settings(tx, settings).await
+            }
+            SetHookSettings(tx, hooks) => self.on_set_hook_settings(tx, hooks).await,
+            SetRetryBackoff(tx, backoff) => self.on_set_retry_backoff(tx, backoff).await,
+            SetAccountExpiryWarnings(tx, warnings) => {
+                self.on_set_account_expiry_warnings(tx, warnings).await
+            }
+            SetLogRotationSettings(tx, rotation) => {
+                self.on_set_log_rotation_settings(tx, rotation).await
+            }
             #[cfg(windows)]
-            AddSplitTunnelApp(tx, path) => self.on_add_split_tunnel_app(tx, path),
+            AddSplitTunnelApp(tx, app) => self.on_add_split_tunnel_app(tx, app),
             #[cfg(windows)]
-            RemoveSplitTunnelApp(tx, path) => self.on_remove_split_tunnel_app(tx, path),
+            RemoveSplitTunnelApp(tx, app) => self.on_remove_split_tunnel_app(tx, app),
             #[cfg(windows)]
             ClearSplitTunnelApps(tx) => self.on_clear_split_tunnel_apps(tx),
             #[cfg(windows)]
             SetSplitTunnelState(tx, enabled) => self.on_set_split_tunnel_state(tx, enabled),
             #[cfg(windows)]
             GetSplitTunnelProcesses(tx) => self.on_get_split_tunnel_processes(tx),
+            #[cfg(windows)]
+            GetSplitTunnelAppStatus(tx) => self.on_get_split_tunnel_app_status(tx),
             #[cfg(target_os = "windows")]
             CheckVolumes(tx) => self.on_check_volumes(tx),
             SetObfuscationSettings(tx, settings) => {
                 self.on_set_obfuscation_settings(tx, settings).await
             }
+            SetNetworkObfuscationProfiles(tx, profiles) => {
+                self.on_set_network_obfuscation_profiles(tx, profiles).await
+            }
+            GetActiveObfuscationProfile(tx) => self.on_get_active_obfuscation_profile(tx),
+            SetNetworkTrustSettings(tx, network_trust) => {
+                self.on_set_network_trust_settings(tx, network_trust).await
+            }
             PrepareRestart => self.on_prepare_restart(),
             #[cfg(target_os = "android")]
             BypassSocket(fd, tx) => self.on_bypass_socket(fd, tx),
@@ -1114,15 +2200,112 @@ where
 
     fn handle_new_app_version_info(&mut self, app_version_info: AppVersionInfo) {
         self.app_version_info = Some(app_version_info.clone());
-        self.relay_selector
-            .set_config(new_selector_config(&self.settings));
+        self.relay_selector.set_config(new_selector_config(
+            &self.settings,
+            &self.current_network,
+            self.obfuscation_scores.scores(),
+            &self.session_tunnel_types,
+        ));
         self.event_listener.notify_app_version(app_version_info);
     }
 
+    async fn handle_network_identified(&mut self, network: CurrentNetwork) {
+        if network == self.current_network {
+            return;
+        }
+        let old_obfuscation_settings =
+            network_profiles::effective_obfuscation_settings(&self.settings, &self.current_network)
+                .clone();
+        let old_block_when_disconnected = self.block_when_disconnected();
+        self.current_network = network;
+        self.update_block_when_disconnected(old_block_when_disconnected);
+
+        let obfuscation_changed =
+            network_profiles::effective_obfuscation_settings(&self.settings, &self.current_network)
+                != &old_obfuscation_settings;
+        if obfuscation_changed {
+            match self.active_obfuscation_profile() {
+                Some(network) => log::info!("Using the obfuscation profile of {network}"),
+                None => log::info!("Using the global obfuscation settings"),
+            }
+            self.relay_selector.set_config(new_selector_config(
+                &self.settings,
+                &self.current_network,
+                self.obfuscation_scores.scores(),
+                &self.session_tunnel_types,
+            ));
+        }
+
+        let trusted_state = self
+            .network_trust
+            .network_changed(&self.settings.network_trust, &self.current_network);
+        let state_change_initiated = match trusted_state {
+            Some(target_state) => self.set_network_trust_target_state(target_state).await,
+            None => false,
+        };
+        if obfuscation_changed && !state_change_initiated {
+            log::info!("Initiating tunnel restart because the obfuscation settings changed");
+            self.reconnect_tunnel();
+        }
+    }
+
+    /// Sets the target state that the network trust rules selected. Unlike a target state set by
+    /// the user, this does nothing if the target state is already the same.
+    async fn set_network_trust_target_state(&mut self, target_state: TargetState) -> bool {
+        if target_state == *self.target_state || !self.state.is_running() {
+            return false;
+        }
+        log::info!(
+            "Setting target state to {} because of the trust policy of the current network",
+            target_state
+        );
+        self.set_target_state(target_state).await
+    }
+
+    /// Whether the tunnel should block traffic while disconnected. Lockdown mode may be lifted on
+    /// trusted networks, see [`NetworkTrustSettings::lifts_lockdown`].
+    fn block_when_disconnected(&self) -> bool {
+        self.settings.block_when_disconnected
+            && !self
+                .settings
+                .network_trust
+                .lifts_lockdown(&self.current_network)
+    }
+
+    /// Tells the tunnel whether to block traffic while disconnected, if it has changed from
+    /// `old_block_when_disconnected`.
+    fn update_block_when_disconnected(&mut self, old_block_when_disconnected: bool) {
+        let block_when_disconnected = self.block_when_disconnected();
+        if block_when_disconnected != old_block_when_disconnected {
+            if !block_when_disconnected {
+                log::info!("Lifting lockdown mode on trusted network");
+            }
+            self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                block_when_disconnected,
+            ));
+        }
+    }
+
+    /// Returns the network of the obfuscation profile that is used on the current network.
+    fn active_obfuscation_profile(&self) -> Option<NetworkId> {
+        network_profiles::select_obfuscation_profile(
+            &self.settings.network_obfuscation_profiles,
+            &self.current_network,
+        )
+        .map(|(network, _)| network.clone())
+    }
+
     async fn handle_device_event(&mut self, event: AccountEvent) {
         match &event {
             AccountEvent::Device(PrivateDeviceEvent::Login(device)) => {
-                if let Err(error) = self.account_history.set(device.account_token.clone()).await {
+                if let Err(error) = self
+                    .account_history
+                    .set(
+                        device.account_token.clone(),
+                        Some(device.device.name.clone()),
+                    )
+                    .await
+                {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Failed to update account history")
@@ -1132,10 +2315,14 @@ where
                     log::debug!("Initiating tunnel restart because the account token changed");
                     self.reconnect_tunnel();
                 }
+                self.account_expiry.clear();
+                self.refresh_account_expiry();
             }
             AccountEvent::Device(PrivateDeviceEvent::Logout) => {
                 log::info!("Disconnecting because account token was cleared");
                 self.set_target_state(TargetState::Unsecured).await;
+                self.account_expiry.clear();
+                self.schedule_account_expiry_check();
             }
             AccountEvent::Device(PrivateDeviceEvent::Revoked) => {
                 // If we're currently in a secured state, reconnect to make sure we immediately
@@ -1149,20 +2336,35 @@ where
                     self.schedule_reconnect(WG_RECONNECT_DELAY);
                 }
             }
-            AccountEvent::Expiry(expiry) if *self.target_state == TargetState::Secured => {
-                if expiry >= &chrono::Utc::now() {
-                    if let TunnelState::Error(ref state) = self.tunnel_state {
-                        if matches!(state.cause(), ErrorStateCause::AuthFailed(_)) {
+            AccountEvent::Expiry(expiry) => {
+                let now = chrono::Utc::now();
+                self.account_expiry.set_expiry(*expiry, now);
+                if let Some(ExpiryEvent::Warning { expiry, threshold }) =
+                    self.account_expiry.poll(now)
+                {
+                    self.notify_account_expiry_warning(expiry, threshold);
+                }
+
+                if *self.target_state == TargetState::Secured {
+                    let is_blocked = self.is_blocked_by_account_expiry();
+                    let is_auth_failed = matches!(
+                        &self.tunnel_state,
+                        TunnelState::Error(state)
+                            if matches!(state.cause(), ErrorStateCause::AuthFailed(_))
+                    );
+                    if *expiry >= now {
+                        if is_blocked || is_auth_failed {
                             log::debug!("Reconnecting since the account has time on it");
                             self.connect_tunnel();
                         }
+                    } else if !is_blocked {
+                        log::debug!("Entering blocking state since the account is out of time");
+                        self.send_tunnel_command(TunnelCommand::Block(
+                            ErrorStateCause::AccountExpired,
+                        ));
                     }
-                } else if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
-                    log::debug!("Entering blocking state since the account is out of time");
-                    self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::AuthFailed(
-                        Some(AuthFailed::ExpiredAccount.as_str().to_string()),
-                    )))
                 }
+                self.schedule_account_expiry_check();
             }
             _ => (),
         }
@@ -1243,6 +2445,7 @@ where
         new_target_state: TargetState,
     ) {
         if self.state.is_running() {
+            self.network_trust.user_set_target_state();
             let state_change_initated = self.set_target_state(new_target_state).await;
             Self::oneshot_send(tx, state_change_initated, "state change initiated");
         } else {
@@ -1260,10 +2463,40 @@ where
         }
     }
 
+    async fn on_reconnect_with_overrides(
+        &mut self,
+        tx: ResponseTx<bool, Error>,
+        overrides: RelayOverrides,
+    ) {
+        if *self.target_state != TargetState::Secured && !self.tunnel_state.is_in_error_state() {
+            log::debug!("Ignoring reconnect command. Currently not in secured state");
+            Self::oneshot_send(tx, Ok(false), "reconnect issued");
+            return;
+        }
+        // Reject parameters that cannot be used before giving up the current tunnel
+        if let Err(error) = self.relay_selector.get_relay_with_overrides(&overrides, 0) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Cannot reconnect with the given parameters")
+            );
+            Self::oneshot_send(tx, Err(Error::RelayOverrides(error)), "reconnect issued");
+            return;
+        }
+        log::info!("Reconnecting with overridden parameters: {overrides:?}");
+        self.parameters_generator.set_overrides(overrides).await;
+        self.connect_tunnel();
+        Self::oneshot_send(tx, Ok(true), "reconnect issued");
+    }
+
     fn on_get_state(&self, tx: oneshot::Sender<TunnelState>) {
         Self::oneshot_send(tx, self.tunnel_state.clone(), "current state");
     }
 
+    fn on_get_tunnel_stats(&self, tx: oneshot::Sender<Option<TrafficStats>>) {
+        let stats = self.tunnel_state_machine_handle.traffic_counters().get();
+        Self::oneshot_send(tx, stats, "tunnel stats");
+    }
+
     fn on_is_performing_post_upgrade(&self, tx: oneshot::Sender<bool>) {
         let performing_post_upgrade = !self.migration_complete.is_complete();
         Self::oneshot_send(tx, performing_post_upgrade, "performing post upgrade");
@@ -1496,21 +2729,30 @@ where
         account_token: AccountToken,
         device_id: DeviceId,
     ) {
-        let device_service = self.account_manager.device_service.clone();
+        let account_manager = self.account_manager.clone();
         let event_listener = self.event_listener.clone();
 
         tokio::spawn(async move {
-            let result = device_service
-                .remove_device(account_token.clone(), device_id)
-                .await
-                .map(move |new_devices| {
-                    // FIXME: We should be able to get away with only returning the removed ID,
-                    //        and not have to request the list from the API.
-                    event_listener.notify_remove_device_event(RemoveDeviceEvent {
-                        account_token,
-                        new_devices,
-                    });
+            let result = account_manager
+                .device_service
+                .remove_device(account_token.clone(), device_id.clone())
+                .await;
+            if result.is_ok() {
+                if let Err(error) = account_manager.device_removed(device_id).await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to log out of the removed device")
+                    );
+                }
+            }
+            let result = result.map(move |new_devices| {
+                // FIXME: We should be able to get away with only returning the removed ID,
+                //        and not have to request the list from the API.
+                event_listener.notify_remove_device_event(RemoveDeviceEvent {
+                    account_token,
+                    new_devices,
                 });
+            });
             Self::oneshot_send(
                 tx,
                 result.map_err(Error::RemoveDeviceError),
@@ -1536,6 +2778,74 @@ where
         Self::oneshot_send(tx, result, "clear_account_history response");
     }
 
+    fn on_list_saved_accounts(&mut self, tx: oneshot::Sender<Vec<SavedAccount>>) {
+        Self::oneshot_send(
+            tx,
+            self.account_history.saved_accounts().to_vec(),
+            "list_saved_accounts response",
+        );
+    }
+
+    async fn on_set_saved_account_label(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        account_token: AccountToken,
+        label: Option<String>,
+    ) {
+        let result = match self.account_history.set_label(&account_token, label).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::SavedAccountNotFound),
+            Err(error) => Err(Error::AccountHistory(error)),
+        };
+        Self::oneshot_send(tx, result, "set_saved_account_label response");
+    }
+
+    fn on_switch_account(&mut self, tx: ResponseTx<(), Error>, label_or_token: String) {
+        let Some(account) = self.account_history.find(&label_or_token) else {
+            Self::oneshot_send(
+                tx,
+                Err(Error::SavedAccountNotFound),
+                "switch_account response",
+            );
+            return;
+        };
+        let account_token = account.account_token.clone();
+        let account_manager = self.account_manager.clone();
+        let availability = self.api_runtime.availability_handle();
+        tokio::spawn(async move {
+            let result = async {
+                // Logging in replaces the current device, so the tunnel is not disconnected
+                account_manager
+                    .switch_account(account_token)
+                    .await
+                    .map_err(|error| {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Switching account failed")
+                        );
+                        Error::LoginError(error)
+                    })?;
+                availability.resume_background();
+                Ok(())
+            };
+            Self::oneshot_send(tx, result.await, "switch_account response");
+        });
+    }
+
+    fn on_check_version(&mut self, tx: ResponseTx<AppVersionInfo, Error>, beta: bool) {
+        let mut handle = self.version_updater_handle.clone();
+        tokio::spawn(async move {
+            let result = handle.check_version(beta).await.map_err(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Error running version check")
+                );
+                Error::VersionCheckError(error)
+            });
+            Self::oneshot_send(tx, result, "check_version response");
+        });
+    }
+
     fn on_get_version_info(&mut self, tx: oneshot::Sender<Option<AppVersionInfo>>) {
         if self.app_version_info.is_none() {
             log::debug!("No version cache found. Fetching new info");
@@ -1574,7 +2884,13 @@ where
     }
 
     #[cfg(not(target_os = "android"))]
-    async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
+    async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>, scopes: ResetScopes) {
+        if !scopes.is_all() {
+            let result = self.reset_scopes(scopes).await;
+            Self::oneshot_send(tx, result, "factory_reset response");
+            return;
+        }
+
         let mut last_error = Ok(());
 
         if let Err(error) = self.account_manager.logout().await {
@@ -1614,44 +2930,825 @@ where
         }));
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_get_split_tunnel_processes(&mut self, tx: ResponseTx<Vec<i32>, split_tunnel::Error>) {
-        let result = self.exclude_pids.list().map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to obtain PIDs"));
-            error
-        });
-        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
-    }
-
-    #[cfg(target_os = "linux")]
-    fn on_add_split_tunnel_process(&mut self, tx: ResponseTx<(), split_tunnel::Error>, pid: i32) {
-        let result = self.exclude_pids.add(pid).map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to add PID"));
-            error
-        });
-        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
-    }
+    /// Resets the selected parts of the state, without logging out or stopping the daemon. All
+    /// selected scopes are reset even if one of them fails.
+    #[cfg(not(target_os = "android"))]
+    async fn reset_scopes(&mut self, scopes: ResetScopes) -> Result<(), Error> {
+        let mut last_error = Ok(());
 
-    #[cfg(target_os = "linux")]
-    fn on_remove_split_tunnel_process(
-        &mut self,
-        tx: ResponseTx<(), split_tunnel::Error>,
-        pid: i32,
-    ) {
-        let result = self.exclude_pids.remove(pid).map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to remove PID"));
-            error
-        });
-        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
-    }
+        if scopes.settings {
+            // The settings are replaced all at once, so no mix of old and default settings is
+            // ever applied. Like when importing settings, excluded apps are kept on Windows.
+            match self
+                .replace_settings(SettingsPersister::default_settings())
+                .await
+            {
+                Ok(_) => log::info!("Reset the settings"),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to reset settings")
+                    );
+                    last_error = Err(Error::FactoryResetError("Failed to reset settings"));
+                }
+            }
+        }
 
-    #[cfg(target_os = "linux")]
-    fn on_clear_split_tunnel_processes(&mut self, tx: ResponseTx<(), split_tunnel::Error>) {
-        let result = self.exclude_pids.clear().map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to clear PIDs"));
-            error
+        if scopes.cache {
+            self.relay_list_updater.clear_cache().await;
+            if let Err(error) = self.api_runtime.address_cache.clear().await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to clear the API address cache")
+                );
+                last_error = Err(Error::FactoryResetError(
+                    "Failed to clear the API address cache",
+                ));
+            }
+        }
+
+        if scopes.history {
+            if let Err(error) = self.account_history.clear().await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to clear account history")
+                );
+                last_error = Err(Error::FactoryResetError("Failed to clear account history"));
+            }
+            if let Err(error) = self.connection_history.clear().await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to clear the connection history")
+                );
+                last_error = Err(Error::FactoryResetError(
+                    "Failed to clear the connection history",
+                ));
+            }
+        }
+
+        last_error
+    }
+
+    async fn on_export_settings(&self, tx: ResponseTx<String, Error>, include_secrets: bool) {
+        let device = if include_secrets {
+            self.account_manager
+                .data()
+                .await
+                .ok()
+                .and_then(|state| state.into_device())
+        } else {
+            None
+        };
+        let result = settings_transfer::export(&self.settings, device, include_secrets)
+            .map_err(Error::ExportSettings);
+        Self::oneshot_send(tx, result, "export_settings response");
+    }
+
+    async fn on_import_settings(&mut self, tx: ResponseTx<(), Error>, document: String) {
+        let result = self.import_settings(&document).await;
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to import settings")
+            );
+        }
+        Self::oneshot_send(tx, result, "import_settings response");
+    }
+
+    /// Replaces all settings with those in an exported document. Nothing is changed unless all of
+    /// the imported settings are valid. The imported device is only used if no account is logged
+    /// in.
+    async fn import_settings(&mut self, document: &str) -> Result<(), Error> {
+        let imported = settings_transfer::import(document).map_err(Error::ImportSettings)?;
+        if self.replace_settings(imported.settings).await? {
+            log::info!("Imported settings");
+        }
+
+        if let Some(device) = imported.device {
+            match self.account_manager.data().await {
+                Ok(state) if state.logged_in() => {
+                    log::info!("Keeping the current device instead of the imported one")
+                }
+                _ => {
+                    log::info!("Using the imported device");
+                    self.account_manager
+                        .set(device)
+                        .await
+                        .map_err(Error::LoginError)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_apply_settings_patch(&mut self, tx: ResponseTx<(), Error>, patch: String) {
+        let result = self.apply_settings_patch(&patch).await;
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to apply settings patch")
+            );
+        }
+        Self::oneshot_send(tx, result, "apply_settings_patch response");
+    }
+
+    /// Changes several settings at once. The patch is applied to the settings as they are when
+    /// the command is handled, so changes made by other commands to settings that are not in the
+    /// patch are kept. Nothing is changed unless the patched settings are valid as a whole, and
+    /// the settings are saved and applied once, so that the tunnel reconnects at most once.
+    async fn apply_settings_patch(&mut self, patch: &str) -> Result<(), Error> {
+        let new_settings = settings_transfer::apply_patch(&self.settings, patch)
+            .map_err(Error::ApplySettingsPatch)?;
+        if self.replace_settings(new_settings).await? {
+            log::info!("Applied settings patch");
+        }
+        Ok(())
+    }
+
+    async fn on_save_profile(&mut self, tx: ResponseTx<(), Error>, name: String) {
+        let result = self
+            .profiles
+            .save(&name, &self.settings)
+            .await
+            .map_err(Error::ProfileError);
+        match &result {
+            Ok(()) => log::info!("Saved settings profile {}", name.trim()),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save settings profile")
+            ),
+        }
+        Self::oneshot_send(tx, result, "save_profile response");
+    }
+
+    fn on_list_profiles(&self, tx: oneshot::Sender<Vec<String>>) {
+        Self::oneshot_send(tx, self.profiles.names(), "list_profiles response");
+    }
+
+    async fn on_apply_profile(&mut self, tx: ResponseTx<(), Error>, name: String) {
+        let result = self.apply_profile(&name).await;
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to apply settings profile")
+            );
+        }
+        Self::oneshot_send(tx, result, "apply_profile response");
+    }
+
+    /// Replaces the settings with those of a profile, in the same way as a settings patch.
+    async fn apply_profile(&mut self, name: &str) -> Result<(), Error> {
+        let new_settings = self.profiles.get(name).map_err(Error::ProfileError)?;
+        if self.replace_settings(new_settings).await? {
+            log::info!("Applied settings profile {}", name.trim());
+        }
+        Ok(())
+    }
+
+    async fn on_delete_profile(&mut self, tx: ResponseTx<(), Error>, name: String) {
+        let result = self
+            .profiles
+            .remove(&name)
+            .await
+            .map_err(Error::ProfileError);
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to delete settings profile")
+            );
+        }
+        Self::oneshot_send(tx, result, "delete_profile response");
+    }
+
+    /// Replaces all settings at once. Nothing is changed unless `new_settings` are valid as a
+    /// whole, and the settings are saved and applied once, so that the tunnel reconnects at most
+    /// once. Returns whether any setting changed.
+    async fn replace_settings(&mut self, new_settings: Settings) -> Result<bool, Error> {
+        settings::validate_settings(&new_settings, &relay_addresses(&self.tunnel_state))
+            .map_err(Error::SettingsError)?;
+
+        let old_settings = self.settings.to_settings();
+        let old_block_when_disconnected = self.block_when_disconnected();
+        let settings_changed = self
+            .settings
+            .update(move |settings| {
+                // Excluded apps are only applied when they are set, and their paths are specific
+                // to this machine
+                #[cfg(windows)]
+                let new_settings = Settings {
+                    split_tunnel: settings.split_tunnel.clone(),
+                    ..new_settings
+                };
+                *settings = new_settings;
+            })
+            .await
+            .map_err(Error::SettingsError)?;
+        if settings_changed {
+            self.apply_replaced_settings(&old_settings, old_block_when_disconnected)
+                .await;
+        }
+        Ok(settings_changed)
+    }
+
+    /// Applies settings that replaced `old_settings` all at once. Each setting that changed is
+    /// applied like its own handler applies it, except that the tunnel reconnects at most once.
+    async fn apply_replaced_settings(
+        &mut self,
+        old_settings: &Settings,
+        old_block_when_disconnected: bool,
+    ) {
+        let settings = self.settings.to_settings();
+        self.event_listener.notify_settings(settings.clone());
+
+        let mut reconnect = false;
+        if settings.relay_settings != old_settings.relay_settings
+            || settings.bridge_state != old_settings.bridge_state
+            || settings.obfuscation_settings != old_settings.obfuscation_settings
+        {
+            reconnect |= self.apply_relay_selector_settings();
+        }
+        if settings.bridge_settings != old_settings.bridge_settings {
+            reconnect |= self.apply_bridge_settings().await;
+        }
+        if settings.network_obfuscation_profiles != old_settings.network_obfuscation_profiles {
+            let old_obfuscation_settings = network_profiles::effective_obfuscation_settings(
+                old_settings,
+                &self.current_network,
+            );
+            reconnect |= self.apply_network_obfuscation_profiles(old_obfuscation_settings);
+        }
+        if settings.custom_lists != old_settings.custom_lists {
+            reconnect |= self.apply_custom_lists(&old_settings.custom_lists);
+        }
+        if settings.tunnel_options != old_settings.tunnel_options {
+            reconnect |= self
+                .apply_tunnel_options(&old_settings.tunnel_options)
+                .await;
+        }
+        if settings.coexistence_mode != old_settings.coexistence_mode {
+            reconnect |= self.apply_coexistence_mode();
+        }
+
+        if settings.allow_lan != old_settings.allow_lan {
+            self.apply_allow_lan();
+        }
+        if settings.bypass_routes != old_settings.bypass_routes {
+            self.apply_bypass_routes();
+        }
+        if settings.retry_backoff != old_settings.retry_backoff {
+            self.apply_retry_backoff();
+        }
+        if settings.account_expiry_warnings != old_settings.account_expiry_warnings {
+            self.apply_account_expiry_warnings();
+        }
+        if settings.log_rotation != old_settings.log_rotation {
+            self.apply_log_rotation();
+        }
+        if settings.auto_disconnect_idle != old_settings.auto_disconnect_idle {
+            self.update_idle_check();
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if excluded_owners(&settings) != excluded_owners(old_settings) {
+                self.apply_split_tunnel_owners();
+            }
+            if split_tunnel_config(&settings) != split_tunnel_config(old_settings) {
+                self.apply_split_tunnel_config();
+            }
+            if settings.split_tunnel.mode != old_settings.split_tunnel.mode {
+                self.update_local_proxy(false).await;
+            }
+            if settings.routing != old_settings.routing {
+                Self::apply_routing_settings();
+            }
+        }
+        if settings.api_access_methods != old_settings.api_access_methods {
+            if let Err(error) = self.reload_access_methods().await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to use the new access methods")
+                );
+            }
+        }
+        if settings.prefer_api_in_tunnel != old_settings.prefer_api_in_tunnel {
+            self.update_api_tunnel_addresses().await;
+        }
+        if settings.local_proxy != old_settings.local_proxy {
+            self.update_local_proxy(true).await;
+        }
+        if settings.show_beta_releases != old_settings.show_beta_releases {
+            self.apply_show_beta_releases().await;
+        }
+
+        self.update_block_when_disconnected(old_block_when_disconnected);
+        let state_change_initiated = settings.network_trust != old_settings.network_trust
+            && self.apply_network_trust().await;
+        if reconnect && !state_change_initiated {
+            log::info!("Initiating tunnel restart because the settings changed");
+            self.reconnect_tunnel();
+        }
+    }
+
+    /// Tells the relay selector about settings that affect which relays are selected.
+    fn update_relay_selector_config(&mut self) {
+        self.relay_selector.set_config(new_selector_config(
+            &self.settings,
+            &self.current_network,
+            self.obfuscation_scores.scores(),
+            &self.session_tunnel_types,
+        ));
+    }
+
+    /// Applies changed relay settings, bridge state or obfuscation settings. Returns whether the
+    /// tunnel must reconnect to use them.
+    fn apply_relay_selector_settings(&mut self) -> bool {
+        self.update_relay_selector_config();
+        true
+    }
+
+    /// Applies changed bridge settings. Returns whether the tunnel must reconnect to use them.
+    async fn apply_bridge_settings(&mut self) -> bool {
+        self.update_relay_selector_config();
+        if let Err(error) = self.api_handle.service().next_api_endpoint().await {
+            log::error!("Failed to rotate API endpoint: {}", error);
+        }
+        true
+    }
+
+    /// Applies changed network obfuscation profiles, given the obfuscation settings that were
+    /// used on the current network before. Returns whether the tunnel must reconnect to use them.
+    fn apply_network_obfuscation_profiles(
+        &mut self,
+        old_obfuscation_settings: &ObfuscationSettings,
+    ) -> bool {
+        if network_profiles::effective_obfuscation_settings(&self.settings, &self.current_network)
+            == old_obfuscation_settings
+        {
+            return false;
+        }
+        self.update_relay_selector_config();
+        true
+    }
+
+    /// Applies tunnel options that replaced `old_options`. Returns whether the tunnel must
+    /// reconnect to use them.
+    async fn apply_tunnel_options(&mut self, old_options: &TunnelOptions) -> bool {
+        let options = self.settings.tunnel_options.clone();
+        self.parameters_generator.set_tunnel_options(&options).await;
+        if options.dns_options != old_options.dns_options {
+            self.apply_dns_options();
+        }
+        if options.wireguard.rotation_interval != old_options.wireguard.rotation_interval {
+            if let Err(error) = self
+                .account_manager
+                .set_rotation_interval(options.wireguard.rotation_interval.unwrap_or_default())
+                .await
+            {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to update rotation interval")
+                );
+            }
+        }
+
+        let target_tunnel_type = self.get_target_tunnel_type();
+        options.generic.enable_ipv6 != old_options.generic.enable_ipv6
+            || (options.openvpn.mssfix != old_options.openvpn.mssfix
+                && target_tunnel_type == Some(TunnelType::OpenVpn))
+            || (options.wireguard.quantum_resistant != old_options.wireguard.quantum_resistant
+                && target_tunnel_type == Some(TunnelType::Wireguard))
+            || (options.wireguard.mtu != old_options.wireguard.mtu
+                && self.get_connected_tunnel_type() == Some(TunnelType::Wireguard))
+    }
+
+    fn apply_dns_options(&mut self) {
+        dns::warn_about_unreachable_lan_servers(&self.settings);
+        let dns_options = self.settings.tunnel_options.dns_options.clone();
+        self.send_tunnel_command(TunnelCommand::PreserveSearchDomains(
+            dns_options.preserve_search_domains,
+        ));
+        self.send_tunnel_command(TunnelCommand::AllowLanDnsWhenBlocked(
+            dns_options.allow_lan_dns_when_blocked,
+        ));
+        self.send_tunnel_command(TunnelCommand::KeepCustomDnsWhileDisconnected(
+            dns_options.keep_custom_dns_while_disconnected,
+        ));
+        self.send_tunnel_command(TunnelCommand::TlsDnsServers(dns::tls_servers_from_options(
+            &dns_options,
+        )));
+        self.send_tunnel_command(TunnelCommand::Dns(
+            dns::addresses_from_options(&dns_options),
+            dns::source_from_options(&dns_options),
+        ));
+    }
+
+    /// Applies the coexistence mode. Returns whether the tunnel must reconnect for it to take
+    /// effect.
+    fn apply_coexistence_mode(&mut self) -> bool {
+        #[cfg(target_os = "macos")]
+        if let Err(error) = self
+            .tunnel_state_machine_handle
+            .route_manager()
+            .set_coexistence_mode(self.settings.coexistence_mode)
+        {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set coexistence mode")
+            );
+        }
+        // The routes are only changed when they are applied again
+        cfg!(target_os = "macos")
+    }
+
+    fn apply_allow_lan(&mut self) {
+        dns::warn_about_unreachable_lan_servers(&self.settings);
+        self.send_tunnel_command(TunnelCommand::AllowLan(self.settings.allow_lan));
+        if let Some(proxy) = &self.local_proxy {
+            proxy.set_allow_lan(self.settings.allow_lan);
+        }
+    }
+
+    fn apply_bypass_routes(&mut self) {
+        self.send_tunnel_command(TunnelCommand::BypassRoutes(
+            self.settings.bypass_routes.clone(),
+        ));
+    }
+
+    fn apply_retry_backoff(&mut self) {
+        self.send_tunnel_command(TunnelCommand::RetryBackoff(self.settings.retry_backoff));
+    }
+
+    fn apply_account_expiry_warnings(&mut self) {
+        self.account_expiry
+            .set_thresholds(&self.settings.account_expiry_warnings.thresholds);
+        self.schedule_account_expiry_check();
+    }
+
+    fn apply_log_rotation(&self) {
+        talpid_core::logging::set_rotation_config(settings::log_rotation_config(
+            &self.settings.log_rotation,
+        ));
+    }
+
+    async fn apply_show_beta_releases(&mut self) {
+        let mut handle = self.version_updater_handle.clone();
+        handle
+            .set_show_beta_releases(self.settings.show_beta_releases)
+            .await;
+    }
+
+    #[cfg(target_os = "linux")]
+    fn apply_split_tunnel_owners(&mut self) {
+        self.send_tunnel_command(TunnelCommand::ExcludedOwners(excluded_owners(
+            &self.settings,
+        )));
+        self.handle_processes_changed();
+    }
+
+    #[cfg(target_os = "linux")]
+    fn apply_split_tunnel_config(&mut self) {
+        self.send_tunnel_command(TunnelCommand::SplitTunnelConfig(split_tunnel_config(
+            &self.settings,
+        )));
+    }
+
+    /// The routing table and rule priorities are only read when the daemon starts, since the
+    /// route manager cannot move its routes and rules while it is running.
+    #[cfg(target_os = "linux")]
+    fn apply_routing_settings() {
+        log::warn!("The new routing settings are used when the daemon is restarted");
+    }
+
+    /// Applies changed network trust settings. Returns whether they changed the target state.
+    async fn apply_network_trust(&mut self) -> bool {
+        match self
+            .network_trust
+            .rules_changed(&self.settings.network_trust, &self.current_network)
+        {
+            Some(target_state) => self.set_network_trust_target_state(target_state).await,
+            None => false,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_split_tunnel_processes(&mut self, tx: ResponseTx<Vec<i32>, split_tunnel::Error>) {
+        let result = self.exclude_pids.list().map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to obtain PIDs"));
+            error
+        });
+        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_excluded_processes(
+        &mut self,
+        tx: ResponseTx<Vec<ExcludedProcess>, split_tunnel::Error>,
+    ) {
+        let result = self
+            .exclude_pids
+            .excluded_processes(&excluded_owners(&self.settings))
+            .map_err(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to list excluded processes")
+                );
+                error
+            });
+        Self::oneshot_send(tx, result, "get_excluded_processes response");
+    }
+
+    /// Lists the excluded processes, and notifies the event listener if they have changed.
+    #[cfg(target_os = "linux")]
+    fn handle_processes_changed(&mut self) {
+        let mut processes = match self
+            .exclude_pids
+            .excluded_processes(&excluded_owners(&self.settings))
+        {
+            Ok(processes) => processes,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to list excluded processes")
+                );
+                return;
+            }
+        };
+        processes.sort_by_key(|process| process.pid);
+        if processes != self.excluded_processes {
+            self.excluded_processes = processes.clone();
+            self.event_listener.notify_excluded_processes(processes);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_add_split_tunnel_process(&mut self, tx: ResponseTx<(), split_tunnel::Error>, pid: i32) {
+        let result = self.exclude_pids.add(pid).map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to add PID"));
+            error
+        });
+        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+        self.handle_processes_changed();
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_remove_split_tunnel_process(
+        &mut self,
+        tx: ResponseTx<(), split_tunnel::Error>,
+        pid: i32,
+    ) {
+        let result = self.exclude_pids.remove(pid).map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to remove PID"));
+            error
+        });
+        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+        self.handle_processes_changed();
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_clear_split_tunnel_processes(&mut self, tx: ResponseTx<(), split_tunnel::Error>) {
+        let result = self.exclude_pids.clear().map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to clear PIDs"));
+            error
         });
         Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
+        self.handle_processes_changed();
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_split_tunnel_owners(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        users: Vec<u32>,
+        groups: Vec<u32>,
+    ) {
+        if let Err(error) = settings::validate_split_tunnel_owners(&users, &groups) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid split tunnel users or groups")
+            );
+            Self::oneshot_send(tx, Err(error), "set_split_tunnel_owners response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| {
+                settings.split_tunnel.users = users;
+                settings.split_tunnel.groups = groups;
+            })
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_tunnel_owners response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_split_tunnel_owners();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_split_tunnel_owners response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_split_tunnel_mode(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        mode: SplitTunnelMode,
+    ) {
+        if let Err(error) = settings::validate_split_tunnel_mode(mode) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid split tunnel mode")
+            );
+            Self::oneshot_send(tx, Err(error), "set_split_tunnel_mode response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.split_tunnel.mode = mode)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_tunnel_mode response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_split_tunnel_config();
+                    self.update_local_proxy(false).await;
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_split_tunnel_mode response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_split_tunnel_destinations(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        destinations: Vec<ExcludedDestination>,
+    ) {
+        if let Err(error) = settings::validate_split_tunnel_destinations(
+            &destinations,
+            &self.settings.tunnel_options.dns_options,
+            &relay_addresses(&self.tunnel_state),
+            self.settings.allow_lan,
+        ) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid excluded destinations")
+            );
+            Self::oneshot_send(tx, Err(error), "set_split_tunnel_destinations response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.split_tunnel.destinations = destinations)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_tunnel_destinations response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_split_tunnel_config();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_split_tunnel_destinations response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_split_tunnel_interfaces(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interfaces: Vec<String>,
+    ) {
+        if let Err(error) = settings::validate_split_tunnel_interfaces(&interfaces) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid excluded interfaces")
+            );
+            Self::oneshot_send(tx, Err(error), "set_split_tunnel_interfaces response");
+            return;
+        }
+        if !interfaces.is_empty() {
+            match talpid_types::split_tunnel::disabled_ip_forwarding() {
+                Ok(disabled) => {
+                    for sysctl in disabled {
+                        log::warn!(
+                            "IP forwarding is disabled ({sysctl} = 0), so some traffic from \
+                             excluded interfaces will not be forwarded"
+                        );
+                    }
+                }
+                Err(error) => {
+                    log::warn!("Failed to check whether IP forwarding is enabled: {error}")
+                }
+            }
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.split_tunnel.interfaces = interfaces)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_tunnel_interfaces response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_split_tunnel_config();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_split_tunnel_interfaces response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_excluded_apps_allow_lan(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        allow: bool,
+    ) {
+        if let Err(error) = settings::validate_excluded_apps_allow_lan(allow) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid LAN access for excluded apps")
+            );
+            Self::oneshot_send(tx, Err(error), "set_excluded_apps_allow_lan response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.split_tunnel.excluded_apps_allow_lan = allow)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_excluded_apps_allow_lan response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_split_tunnel_config();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_excluded_apps_allow_lan response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_split_tunnel_local_ports(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        ports: Vec<(TransportProtocol, u16)>,
+    ) {
+        if let Err(error) =
+            settings::validate_split_tunnel_local_ports(&ports, settings::ephemeral_port_range())
+        {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid excluded local ports")
+            );
+            Self::oneshot_send(tx, Err(error), "set_split_tunnel_local_ports response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.split_tunnel.local_ports = ports)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_tunnel_local_ports response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_split_tunnel_config();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_split_tunnel_local_ports response");
+            }
+        }
     }
 
     /// Update the split app paths in both the settings and tunnel
@@ -1686,9 +3783,9 @@ where
 
         if new_state || new_state != settings.split_tunnel.enable_exclusions {
             let tunnel_list = if new_state {
-                new_list.map(OsString::from).collect()
+                excluded_apps(new_list)
             } else {
-                vec![]
+                split_tunnel::ExcludedApps::default()
             };
 
             let (result_tx, result_rx) = oneshot::channel();
@@ -1722,11 +3819,11 @@ where
     }
 
     #[cfg(windows)]
-    fn on_add_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
+    fn on_add_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, app: SplitApp) {
         let settings = self.settings.to_settings();
 
         let mut new_list = settings.split_tunnel.apps.clone();
-        new_list.insert(path);
+        new_list.insert(app);
 
         self.set_split_tunnel_paths(
             tx,
@@ -1737,11 +3834,11 @@ where
     }
 
     #[cfg(windows)]
-    fn on_remove_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
+    fn on_remove_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, app: SplitApp) {
         let settings = self.settings.to_settings();
 
         let mut new_list = settings.split_tunnel.apps.clone();
-        new_list.remove(&path);
+        new_list.remove(&app);
 
         self.set_split_tunnel_paths(
             tx,
@@ -1788,58 +3885,176 @@ where
         );
     }
 
-    #[cfg(windows)]
-    fn on_check_volumes(&mut self, tx: ResponseTx<(), Error>) {
-        if self.volume_update_tx.unbounded_send(()).is_ok() {
-            let _ = tx.send(Ok(()));
+    #[cfg(windows)]
+    fn on_get_split_tunnel_app_status(
+        &self,
+        tx: ResponseTx<Vec<AppExclusionStatus>, split_tunnel::Error>,
+    ) {
+        Self::oneshot_send(
+            tx,
+            self.tunnel_state_machine_handle
+                .split_tunnel()
+                .get_app_statuses(),
+            "get_split_tunnel_app_status response",
+        );
+    }
+
+    #[cfg(windows)]
+    fn on_check_volumes(&mut self, tx: ResponseTx<(), Error>) {
+        if self.volume_update_tx.unbounded_send(()).is_ok() {
+            let _ = tx.send(Ok(()));
+        }
+    }
+
+    async fn on_update_relay_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        update: RelaySettingsUpdate,
+    ) {
+        match self
+            .settings
+            .update(move |settings| settings.update_relay_settings(update))
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.apply_relay_selector_settings() {
+                        log::info!("Initiating tunnel restart because the relay settings changed");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
+            }
+        }
+    }
+
+    fn on_preview_relay_settings(
+        &self,
+        tx: oneshot::Sender<Option<MatchingRelays>>,
+        update: RelaySettingsUpdate,
+    ) {
+        let matching_relays = match self.settings.relay_settings.merge(update) {
+            RelaySettings::Normal(constraints) => {
+                Some(self.relay_selector.count_matching_relays(&constraints))
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => None,
+        };
+        Self::oneshot_send(tx, matching_relays, "preview_relay_settings response");
+    }
+
+    async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
+        match self
+            .settings
+            .update(move |settings| settings.allow_lan = allow_lan)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_allow_lan();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+            }
+        }
+    }
+
+    async fn on_set_bypass_routes(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        routes: Vec<IpNetwork>,
+    ) {
+        if let Err(error) = settings::validate_bypass_routes(
+            &routes,
+            &self.settings.tunnel_options.dns_options,
+            &relay_addresses(&self.tunnel_state),
+        ) {
+            log::error!("{}", error.display_chain_with_msg("Invalid bypass routes"));
+            Self::oneshot_send(tx, Err(error), "set_bypass_routes response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.bypass_routes = routes)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_bypass_routes response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_bypass_routes();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_bypass_routes response");
+            }
         }
     }
 
-    async fn on_update_relay_settings(
+    async fn on_set_prefer_api_in_tunnel(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        update: RelaySettingsUpdate,
+        enabled: bool,
     ) {
         match self
             .settings
-            .update(move |settings| settings.update_relay_settings(update))
+            .update(move |settings| settings.prefer_api_in_tunnel = enabled)
             .await
         {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
+                Self::oneshot_send(tx, Ok(()), "set_prefer_api_in_tunnel response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.relay_selector
-                        .set_config(new_selector_config(&self.settings));
-                    log::info!("Initiating tunnel restart because the relay settings changed");
-                    self.reconnect_tunnel();
+                    self.update_api_tunnel_addresses().await;
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
+                Self::oneshot_send(tx, Err(e), "set_prefer_api_in_tunnel response");
             }
         }
     }
 
-    async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
+    async fn on_set_coexistence_mode(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
         match self
             .settings
-            .update(move |settings| settings.allow_lan = allow_lan)
+            .update(move |settings| settings.coexistence_mode = enabled)
             .await
         {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
+                Self::oneshot_send(tx, Ok(()), "set_coexistence_mode response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
+                    if self.apply_coexistence_mode() {
+                        log::info!(
+                            "Initiating tunnel restart because the coexistence mode changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+                Self::oneshot_send(tx, Err(e), "set_coexistence_mode response");
             }
         }
     }
@@ -1859,8 +4074,7 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    let mut handle = self.version_updater_handle.clone();
-                    handle.set_show_beta_releases(enabled).await;
+                    self.apply_show_beta_releases().await;
                 }
             }
             Err(e) => {
@@ -1875,6 +4089,7 @@ where
         tx: ResponseTx<(), settings::Error>,
         block_when_disconnected: bool,
     ) {
+        let old_block_when_disconnected = self.block_when_disconnected();
         match self
             .settings
             .update(move |settings| settings.block_when_disconnected = block_when_disconnected)
@@ -1885,9 +4100,7 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
-                        block_when_disconnected,
-                    ));
+                    self.update_block_when_disconnected(old_block_when_disconnected);
                 }
             }
             Err(e) => {
@@ -1921,11 +4134,46 @@ where
         }
     }
 
+    async fn on_set_auto_disconnect_idle(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        timeout: Option<Duration>,
+    ) {
+        if let Err(error) = settings::validate_auto_disconnect_idle(timeout) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid idle auto-disconnect timeout")
+            );
+            Self::oneshot_send(tx, Err(error), "set_auto_disconnect_idle response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.auto_disconnect_idle = timeout)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_auto_disconnect_idle response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.update_idle_check();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_auto_disconnect_idle response");
+            }
+        }
+    }
+
     async fn on_set_openvpn_mssfix(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
         mssfix: Option<u16>,
     ) {
+        let old_tunnel_options = self.settings.tunnel_options.clone();
         match self
             .settings
             .update(move |settings| settings.tunnel_options.openvpn.mssfix = mssfix)
@@ -1934,12 +4182,9 @@ where
             Ok(settings_changed) => {
                 Self::oneshot_send(tx, Ok(()), "set_openvpn_mssfix response");
                 if settings_changed {
-                    self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
-                        .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    if self.get_target_tunnel_type() == Some(TunnelType::OpenVpn) {
+                    if self.apply_tunnel_options(&old_tunnel_options).await {
                         log::info!(
                             "Initiating tunnel restart because the OpenVPN mssfix setting changed"
                         );
@@ -1968,56 +4213,319 @@ where
                 if settings_changes {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.relay_selector
-                        .set_config(new_selector_config(&self.settings));
-                    if let Err(error) = self.api_handle.service().next_api_endpoint().await {
-                        log::error!("Failed to rotate API endpoint: {}", error);
+                    if self.apply_bridge_settings().await {
+                        log::info!("Initiating tunnel restart because the bridge settings changed");
+                        self.reconnect_tunnel();
                     }
-                    self.reconnect_tunnel();
                 };
                 Self::oneshot_send(tx, Ok(()), "set_bridge_settings");
             }
-
-            Err(e) => {
+
+            Err(e) => {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to set new bridge settings")
+                );
+                Self::oneshot_send(tx, Err(e), "set_bridge_settings");
+            }
+        }
+    }
+
+    async fn on_set_obfuscation_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        new_settings: ObfuscationSettings,
+    ) {
+        if let Err(error) = settings::validate_obfuscation_settings(&new_settings) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid obfuscation settings")
+            );
+            Self::oneshot_send(tx, Err(error), "set_obfuscation_settings");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.obfuscation_settings = new_settings)
+            .await
+        {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.apply_relay_selector_settings() {
+                        log::info!(
+                            "Initiating tunnel restart because the obfuscation settings changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+                Self::oneshot_send(tx, Ok(()), "set_obfuscation_settings");
+            }
+            Err(err) => {
+                log::error!(
+                    "{}",
+                    err.display_chain_with_msg("Failed to set obfuscation settings")
+                );
+                Self::oneshot_send(tx, Err(err), "set_obfuscation_settings");
+            }
+        }
+    }
+
+    async fn on_set_local_proxy_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        local_proxy: LocalProxySettings,
+    ) {
+        if let Err(error) = settings::validate_local_proxy(&local_proxy) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid local proxy settings")
+            );
+            Self::oneshot_send(tx, Err(error), "set_local_proxy_settings response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.local_proxy = local_proxy)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_local_proxy_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.update_local_proxy(true).await;
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_local_proxy_settings response");
+            }
+        }
+    }
+
+    async fn on_set_hook_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        hooks: HookSettings,
+    ) {
+        if let Err(error) = settings::validate_hooks(&hooks) {
+            log::error!("{}", error.display_chain_with_msg("Invalid hook settings"));
+            Self::oneshot_send(tx, Err(error), "set_hook_settings response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.hooks = hooks)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_hook_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_hook_settings response");
+            }
+        }
+    }
+
+    async fn on_set_retry_backoff(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        backoff: RetryBackoff,
+    ) {
+        if let Err(error) = settings::validate_retry_backoff(&backoff) {
+            log::error!("{}", error.display_chain_with_msg("Invalid retry backoff"));
+            Self::oneshot_send(tx, Err(error), "set_retry_backoff response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.retry_backoff = backoff)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_retry_backoff response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_retry_backoff();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_retry_backoff response");
+            }
+        }
+    }
+
+    async fn on_set_account_expiry_warnings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        warnings: AccountExpiryWarnings,
+    ) {
+        if let Err(error) = settings::validate_account_expiry_warnings(&warnings) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid account expiry warnings")
+            );
+            Self::oneshot_send(tx, Err(error), "set_account_expiry_warnings response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.account_expiry_warnings = warnings)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_account_expiry_warnings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_account_expiry_warnings();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_account_expiry_warnings response");
+            }
+        }
+    }
+
+    async fn on_set_log_rotation_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        rotation: LogRotationSettings,
+    ) {
+        if let Err(error) = settings::validate_log_rotation(&rotation) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid log rotation settings")
+            );
+            Self::oneshot_send(tx, Err(error), "set_log_rotation_settings response");
+            return;
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.log_rotation = rotation)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_log_rotation_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_log_rotation();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_log_rotation_settings response");
+            }
+        }
+    }
+
+    async fn on_set_network_obfuscation_profiles(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        profiles: Vec<(NetworkId, ObfuscationSettings)>,
+    ) {
+        if let Err(error) = settings::validate_network_obfuscation_profiles(&profiles) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid network obfuscation profiles")
+            );
+            Self::oneshot_send(tx, Err(error), "set_network_obfuscation_profiles");
+            return;
+        }
+
+        let old_obfuscation_settings =
+            network_profiles::effective_obfuscation_settings(&self.settings, &self.current_network)
+                .clone();
+        match self
+            .settings
+            .update(move |settings| settings.network_obfuscation_profiles = profiles)
+            .await
+        {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.apply_network_obfuscation_profiles(&old_obfuscation_settings) {
+                        log::info!(
+                            "Initiating tunnel restart because the obfuscation profile of the \
+                             current network changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+                Self::oneshot_send(tx, Ok(()), "set_network_obfuscation_profiles");
+            }
+            Err(err) => {
                 log::error!(
                     "{}",
-                    e.display_chain_with_msg("Failed to set new bridge settings")
+                    err.display_chain_with_msg("Failed to set network obfuscation profiles")
                 );
-                Self::oneshot_send(tx, Err(e), "set_bridge_settings");
+                Self::oneshot_send(tx, Err(err), "set_network_obfuscation_profiles");
             }
         }
     }
 
-    async fn on_set_obfuscation_settings(
+    async fn on_set_network_trust_settings(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        new_settings: ObfuscationSettings,
+        network_trust: NetworkTrustSettings,
     ) {
+        if let Err(error) = settings::validate_network_trust_settings(&network_trust) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Invalid network trust settings")
+            );
+            Self::oneshot_send(tx, Err(error), "set_network_trust_settings response");
+            return;
+        }
+
+        let old_block_when_disconnected = self.block_when_disconnected();
         match self
             .settings
-            .update(move |settings| settings.obfuscation_settings = new_settings)
+            .update(move |settings| settings.network_trust = network_trust)
             .await
         {
             Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_network_trust_settings response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.relay_selector
-                        .set_config(new_selector_config(&self.settings));
-                    self.reconnect_tunnel();
+                    self.update_block_when_disconnected(old_block_when_disconnected);
+                    self.apply_network_trust().await;
                 }
-                Self::oneshot_send(tx, Ok(()), "set_obfuscation_settings");
             }
-            Err(err) => {
-                log::error!(
-                    "{}",
-                    err.display_chain_with_msg("Failed to set obfuscation settings")
-                );
-                Self::oneshot_send(tx, Err(err), "set_obfuscation_settings");
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_network_trust_settings response");
             }
         }
     }
 
+    fn on_get_active_obfuscation_profile(&self, tx: oneshot::Sender<Option<NetworkId>>) {
+        Self::oneshot_send(
+            tx,
+            self.active_obfuscation_profile(),
+            "get_active_obfuscation_profile response",
+        );
+    }
+
     async fn on_set_bridge_state(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2032,10 +4540,10 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.relay_selector
-                        .set_config(new_selector_config(&self.settings));
-                    log::info!("Initiating tunnel restart because bridge state changed");
-                    self.reconnect_tunnel();
+                    if self.apply_relay_selector_settings() {
+                        log::info!("Initiating tunnel restart because bridge state changed");
+                        self.reconnect_tunnel();
+                    }
                 }
                 Ok(())
             }
@@ -2051,6 +4559,14 @@ where
     }
 
     async fn on_set_enable_ipv6(&mut self, tx: ResponseTx<(), settings::Error>, enable_ipv6: bool) {
+        let dns_options = &self.settings.tunnel_options.dns_options;
+        if let Err(error) = settings::validate_dns_options(dns_options, enable_ipv6) {
+            log::error!("{}", error.display_chain_with_msg("Cannot disable IPv6"));
+            Self::oneshot_send(tx, Err(error), "set_enable_ipv6 response");
+            return;
+        }
+
+        let old_tunnel_options = self.settings.tunnel_options.clone();
         match self
             .settings
             .update(|settings| settings.tunnel_options.generic.enable_ipv6 = enable_ipv6)
@@ -2059,13 +4575,14 @@ where
             Ok(settings_changed) => {
                 Self::oneshot_send(tx, Ok(()), "set_enable_ipv6 response");
                 if settings_changed {
-                    self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
-                        .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    log::info!("Initiating tunnel restart because the enable IPv6 setting changed");
-                    self.reconnect_tunnel();
+                    if self.apply_tunnel_options(&old_tunnel_options).await {
+                        log::info!(
+                            "Initiating tunnel restart because the enable IPv6 setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
                 }
             }
             Err(e) => {
@@ -2080,6 +4597,7 @@ where
         tx: ResponseTx<(), settings::Error>,
         quantum_resistant: QuantumResistantState,
     ) {
+        let old_tunnel_options = self.settings.tunnel_options.clone();
         match self
             .settings
             .update(|settings| {
@@ -2090,12 +4608,9 @@ where
             Ok(settings_changed) => {
                 Self::oneshot_send(tx, Ok(()), "set_quantum_resistant_tunnel response");
                 if settings_changed {
-                    self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
-                        .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
+                    if self.apply_tunnel_options(&old_tunnel_options).await {
                         log::info!("Reconnecting because the PQ safety setting changed");
                         self.reconnect_tunnel();
                     }
@@ -2108,11 +4623,289 @@ where
         }
     }
 
+    async fn on_run_dns_leak_test(&mut self, tx: ResponseTx<DnsLeakTestResult, Error>) {
+        if !matches!(self.tunnel_state, TunnelState::Connected { .. }) {
+            Self::oneshot_send(
+                tx,
+                Err(Error::DnsLeakTestError(dns_leak::Error::NotConnected)),
+                "run_dns_leak_test response",
+            );
+            return;
+        }
+
+        let leak_test = self.dns_leak_test().await;
+        tokio::spawn(async move {
+            let result = leak_test.await.map_err(|error| {
+                log::error!("{}", error.display_chain_with_msg("DNS leak test failed"));
+                Error::DnsLeakTestError(error)
+            });
+            Self::oneshot_send(tx, result, "run_dns_leak_test response");
+        });
+    }
+
+    /// Returns a DNS leak test of the resolvers that are currently used in the tunnel.
+    async fn dns_leak_test(
+        &mut self,
+    ) -> impl Future<Output = Result<DnsLeakTestResult, dns_leak::Error>> {
+        let dns_options = &self.settings.tunnel_options.dns_options;
+        let custom_dns = dns_options.state == DnsState::Custom;
+        let tls_servers = dns::tls_servers_from_options(dns_options);
+        let tunnel_resolvers = if !tls_servers.is_empty() {
+            tls_servers.iter().map(|server| server.address).collect()
+        } else {
+            match dns::addresses_from_options(dns_options) {
+                Some(resolvers) => resolvers,
+                None => self
+                    .parameters_generator
+                    .get_last_wireguard_gateway()
+                    .await
+                    .map(|gateway| vec![IpAddr::V4(gateway)])
+                    .unwrap_or_default(),
+            }
+        };
+
+        let timeouts = dns_leak::Timeouts::default();
+        let backend =
+            dns_leak::ApiLeakTestBackend::new(self.api_runtime.rest_handle().await, timeouts);
+        async move {
+            dns_leak::run(
+                &backend,
+                dns_leak::new_test_id(),
+                &tunnel_resolvers,
+                custom_dns,
+                timeouts,
+            )
+            .await
+        }
+    }
+
+    async fn on_check_connection(
+        &mut self,
+        tx: ResponseTx<ConnectionCheck, Error>,
+        allow_disconnected: bool,
+    ) {
+        let tunnel = match &self.tunnel_state {
+            TunnelState::Connected { location, .. } => Some(connection_check::ConnectedTunnel {
+                relay_hostname: location
+                    .as_ref()
+                    .and_then(|location| location.hostname.clone()),
+            }),
+            _ if allow_disconnected => None,
+            _ => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::ConnectionCheckError(
+                        connection_check::Error::NotConnected,
+                    )),
+                    "check_connection response",
+                );
+                return;
+            }
+        };
+
+        let service = self.api_runtime.rest_handle().await;
+        if tunnel.is_some() {
+            // Bind the requests to the tunnel addresses, so that they are sent through the tunnel
+            // regardless of how API traffic is routed.
+            service.set_tunnel_addresses(api::tunnel_addresses(
+                &self.settings,
+                &self.tunnel_state,
+                self.device_addresses().await.as_ref(),
+            ));
+        }
+        let dns_leak_test = match tunnel {
+            Some(_) => Some(self.dns_leak_test().await),
+            None => None,
+        };
+        let use_ipv6 = self.settings.tunnel_options.generic.enable_ipv6;
+        let backend = connection_check::ApiCheckBackend::new(service);
+        tokio::spawn(async move {
+            let result = connection_check::run(&backend, tunnel, use_ipv6, dns_leak_test)
+                .await
+                .map_err(|error| {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Connection check failed")
+                    );
+                    Error::ConnectionCheckError(error)
+                });
+            Self::oneshot_send(tx, result, "check_connection response");
+        });
+    }
+
+    fn on_get_routes(&self, tx: ResponseTx<RouteDump, Error>) {
+        let route_manager = self.tunnel_state_machine_handle.route_manager().clone();
+        tokio::spawn(async move {
+            let result = route_manager
+                .get_routes()
+                .await
+                .map(routes::route_dump)
+                .map_err(Error::GetRoutesError);
+            Self::oneshot_send(tx, result, "get_routes response");
+        });
+    }
+
+    fn on_get_obfuscation_scores(&self, tx: oneshot::Sender<ObfuscationScores>) {
+        let scores = self.obfuscation_scores.scores().decayed(SystemTime::now());
+        Self::oneshot_send(tx, scores, "get_obfuscation_scores response");
+    }
+
+    fn on_get_metrics(&self, tx: oneshot::Sender<Vec<Metric>>) {
+        let now = Instant::now();
+        let registry = self.metrics.clone();
+        let relay = match &self.tunnel_state {
+            TunnelState::Connecting { location, .. } | TunnelState::Connected { location, .. } => {
+                location
+                    .as_ref()
+                    .and_then(|location| location.hostname.clone())
+            }
+            _ => None,
+        };
+        let api_available = !self.api_handle.availability.get_state().is_offline();
+        let account_manager = self.account_manager.clone();
+
+        tokio::spawn(async move {
+            let key_age = match account_manager.data().await {
+                Ok(state) => state.into_device().and_then(|device| {
+                    (chrono::Utc::now() - device.device.wg_data.created)
+                        .to_std()
+                        .ok()
+                }),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read the device for metrics")
+                    );
+                    None
+                }
+            };
+            let status = metrics::Status {
+                relay,
+                api_available,
+                key_age,
+            };
+            Self::oneshot_send(tx, registry.metrics(now, &status), "get_metrics response");
+        });
+    }
+
+    fn on_get_recent_events(&self, tx: oneshot::Sender<Vec<RecentEvent>>) {
+        Self::oneshot_send(
+            tx,
+            self.recent_events.events(),
+            "get_recent_events response",
+        );
+    }
+
+    fn on_get_connection_history(&self, tx: oneshot::Sender<Vec<Connection>>) {
+        Self::oneshot_send(
+            tx,
+            self.connection_history.connections(),
+            "get_connection_history response",
+        );
+    }
+
+    fn on_queue_problem_report(
+        &self,
+        tx: ResponseTx<String, Error>,
+        email: String,
+        message: String,
+        report: String,
+    ) {
+        let outbox = self.problem_report_outbox.clone();
+        // Collecting the metadata of a report which lacks it runs external commands
+        tokio::task::spawn_blocking(move || {
+            let report = QueuedReport::new(&email, &message, report);
+            let result = outbox
+                .push(&report)
+                .map(|()| report.id)
+                .map_err(Error::ProblemReportOutboxError);
+            if let Ok(id) = &result {
+                log::info!("Queued problem report {id}");
+            }
+            Self::oneshot_send(tx, result, "queue_problem_report response");
+        });
+    }
+
+    fn on_get_queued_problem_reports(&self, tx: ResponseTx<Vec<QueuedProblemReport>, Error>) {
+        let result = self
+            .problem_report_outbox
+            .list()
+            .map(|reports| {
+                reports
+                    .into_iter()
+                    .map(|report| QueuedProblemReport {
+                        size: report.report.len() as u64,
+                        queued_at: report.queued_at.into(),
+                        id: report.id,
+                    })
+                    .collect()
+            })
+            .map_err(Error::ProblemReportOutboxError);
+        Self::oneshot_send(tx, result, "get_queued_problem_reports response");
+    }
+
+    fn on_flush_queued_problem_reports(&self, tx: ResponseTx<Vec<ProblemReportSendResult>, Error>) {
+        let outbox = self.problem_report_outbox.clone();
+        let proxy = mullvad_api::ProblemReportProxy::new(self.api_handle.clone());
+        let event_listener = self.event_listener.clone();
+        tokio::spawn(async move {
+            let result = outbox
+                .flush(|report| {
+                    proxy.problem_report(
+                        &report.user_email,
+                        &report.user_message,
+                        &report.report,
+                        &report.metadata,
+                    )
+                })
+                .await
+                .map(|results| {
+                    results
+                        .into_iter()
+                        .map(|(report, result)| {
+                            if result.is_ok() {
+                                event_listener.notify_problem_report_sent(report.id.clone());
+                            }
+                            ProblemReportSendResult {
+                                id: report.id,
+                                error: result.err().map(|error| error.display_chain()),
+                            }
+                        })
+                        .collect()
+                })
+                .map_err(Error::ProblemReportOutboxError);
+            Self::oneshot_send(tx, result, "flush_queued_problem_reports response");
+        });
+    }
+
     async fn on_set_dns_options(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
         dns_options: DnsOptions,
     ) {
+        let enable_ipv6 = self.settings.tunnel_options.generic.enable_ipv6;
+        let result = settings::validate_dns_options(&dns_options, enable_ipv6).and_then(|()| {
+            settings::validate_bypass_routes(
+                &self.settings.bypass_routes,
+                &dns_options,
+                &relay_addresses(&self.tunnel_state),
+            )?;
+            #[cfg(target_os = "linux")]
+            settings::validate_split_tunnel_destinations(
+                &self.settings.split_tunnel.destinations,
+                &dns_options,
+                &relay_addresses(&self.tunnel_state),
+                self.settings.allow_lan,
+            )?;
+            Ok(())
+        });
+        if let Err(error) = result {
+            log::error!("{}", error.display_chain_with_msg("Invalid DNS options"));
+            Self::oneshot_send(tx, Err(error), "set_dns_options response");
+            return;
+        }
+
+        let old_tunnel_options = self.settings.tunnel_options.clone();
         match self
             .settings
             .update(move |settings| settings.tunnel_options.dns_options = dns_options)
@@ -2121,14 +4914,9 @@ where
             Ok(settings_changed) => {
                 Self::oneshot_send(tx, Ok(()), "set_dns_options response");
                 if settings_changed {
-                    let settings = self.settings.to_settings();
-                    let resolvers =
-                        dns::addresses_from_options(&settings.tunnel_options.dns_options);
-                    self.parameters_generator
-                        .set_tunnel_options(&settings.tunnel_options)
-                        .await;
-                    self.event_listener.notify_settings(settings);
-                    self.send_tunnel_command(TunnelCommand::Dns(resolvers));
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_tunnel_options(&old_tunnel_options).await;
                 }
             }
             Err(e) => {
@@ -2143,6 +4931,7 @@ where
         tx: ResponseTx<(), settings::Error>,
         mtu: Option<u16>,
     ) {
+        let old_tunnel_options = self.settings.tunnel_options.clone();
         match self
             .settings
             .update(move |settings| settings.tunnel_options.wireguard.mtu = mtu)
@@ -2151,12 +4940,9 @@ where
             Ok(settings_changed) => {
                 Self::oneshot_send(tx, Ok(()), "set_wireguard_mtu response");
                 if settings_changed {
-                    self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
-                        .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                    if self.apply_tunnel_options(&old_tunnel_options).await {
                         log::info!(
                             "Initiating tunnel restart because the WireGuard MTU setting changed"
                         );
@@ -2176,6 +4962,7 @@ where
         tx: ResponseTx<(), settings::Error>,
         interval: Option<RotationInterval>,
     ) {
+        let old_tunnel_options = self.settings.tunnel_options.clone();
         match self
             .settings
             .update(move |settings| settings.tunnel_options.wireguard.rotation_interval = interval)
@@ -2184,21 +4971,9 @@ where
             Ok(settings_changed) => {
                 Self::oneshot_send(tx, Ok(()), "set_wireguard_rotation_interval response");
                 if settings_changed {
-                    if let Err(error) = self
-                        .account_manager
-                        .set_rotation_interval(interval.unwrap_or_default())
-                        .await
-                    {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg("Failed to update rotation interval")
-                        );
-                    }
-                    self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
-                        .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
+                    self.apply_tunnel_options(&old_tunnel_options).await;
                 }
             }
             Err(e) => {
@@ -2253,6 +5028,16 @@ where
         Self::oneshot_send(tx, result, "update_custom_list response");
     }
 
+    async fn on_replace_custom_list(
+        &mut self,
+        tx: ResponseTx<mullvad_types::custom_list::Id, Error>,
+        name: String,
+        locations: BTreeSet<GeographicLocationConstraint>,
+    ) {
+        let result = self.replace_custom_list(name, locations).await;
+        Self::oneshot_send(tx, result, "replace_custom_list response");
+    }
+
     fn on_get_api_access_methods(&mut self, tx: ResponseTx<Vec<AccessMethodSetting>, Error>) {
         let result = Ok(self.settings.api_access_methods.cloned());
         Self::oneshot_send(tx, result, "get_api_access_methods response");
@@ -2272,6 +5057,29 @@ where
         Self::oneshot_send(tx, result, "add_api_access_method response");
     }
 
+    async fn on_import_api_access_method(
+        &mut self,
+        tx: ResponseTx<mullvad_types::access_method::Id, Error>,
+        encoded: String,
+    ) {
+        let result = self
+            .import_access_method(&encoded)
+            .await
+            .map_err(Error::AccessMethodError);
+        Self::oneshot_send(tx, result, "import_api_access_method response");
+    }
+
+    fn on_export_api_access_method(
+        &self,
+        tx: ResponseTx<String, Error>,
+        access_method: mullvad_types::access_method::Id,
+    ) {
+        let result = self
+            .export_access_method(access_method)
+            .map_err(Error::AccessMethodError);
+        Self::oneshot_send(tx, result, "export_api_access_method response");
+    }
+
     async fn on_remove_api_access_method(
         &mut self,
         tx: ResponseTx<(), Error>,
@@ -2315,6 +5123,42 @@ where
         Self::oneshot_send(tx, result, "get_current_api_access_method response");
     }
 
+    fn on_get_api_access_method_stats(&self, tx: oneshot::Sender<Vec<AccessMethodStats>>) {
+        let stats = self
+            .connection_modes
+            .lock()
+            .unwrap()
+            .stats(SystemTime::now());
+        Self::oneshot_send(tx, stats, "get_api_access_method_stats response");
+    }
+
+    fn on_get_api_connectivity(&self, tx: oneshot::Sender<ApiConnectivity>) {
+        Self::oneshot_send(
+            tx,
+            self.api_connectivity.status(),
+            "get_api_connectivity response",
+        );
+    }
+
+    fn on_test_api_access_method(
+        &self,
+        tx: ResponseTx<AccessMethodTestReport, Error>,
+        access_method: mullvad_types::access_method::Id,
+    ) {
+        match self.test_api_access_method(access_method) {
+            Ok(test) => {
+                tokio::spawn(async move {
+                    Self::oneshot_send(tx, Ok(test.await), "test_api_access_method response");
+                });
+            }
+            Err(error) => Self::oneshot_send(
+                tx,
+                Err(Error::AccessMethodError(error)),
+                "test_api_access_method response",
+            ),
+        }
+    }
+
     async fn on_get_api_addresses(&mut self, tx: ResponseTx<Vec<std::net::SocketAddr>, Error>) {
         let api_proxy = mullvad_api::ApiProxy::new(self.api_handle.clone());
         let result = api_proxy.get_api_addrs().await.map_err(Error::RestError);
@@ -2322,6 +5166,61 @@ where
         Self::oneshot_send(tx, result, "on_get_api_adressess response");
     }
 
+    async fn on_set_api_endpoint_override(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        endpoint_override: Option<mullvad_api::ApiEndpointOverride>,
+    ) {
+        #[cfg(feature = "api-override")]
+        let result = self.set_api_endpoint_override(endpoint_override).await;
+        #[cfg(not(feature = "api-override"))]
+        let result = {
+            let _ = endpoint_override;
+            Err(Error::ApiEndpointOverrideUnavailable)
+        };
+        Self::oneshot_send(tx, result, "set_api_endpoint_override response");
+    }
+
+    #[cfg(feature = "api-override")]
+    async fn set_api_endpoint_override(
+        &mut self,
+        endpoint_override: Option<mullvad_api::ApiEndpointOverride>,
+    ) -> Result<(), Error> {
+        self.api_runtime
+            .set_endpoint_override(endpoint_override)
+            .await
+            .map_err(Error::ApiEndpointOverrideError)?;
+        // Select the current access method again. This closes the connections to the previous
+        // endpoint and makes the firewall allow the new one.
+        {
+            let mut connection_modes = self.connection_modes.lock().unwrap();
+            let current = connection_modes.peek();
+            connection_modes.set_access_method(current);
+        }
+        self.api_handle
+            .service()
+            .next_api_endpoint()
+            .await
+            .map_err(Error::RestError)
+    }
+
+    fn on_script_mock_tunnel(&self, tx: ResponseTx<(), Error>, script: MockTunnelScript) {
+        #[cfg(feature = "mock-tunnel")]
+        let result = match self.tunnel_state_machine_handle.mock_tunnel() {
+            Some(mock_tunnel) => {
+                mock_tunnel.run_script(script);
+                Ok(())
+            }
+            None => Err(Error::MockTunnelUnavailable),
+        };
+        #[cfg(not(feature = "mock-tunnel"))]
+        let result = {
+            let _ = script;
+            Err(Error::MockTunnelUnavailable)
+        };
+        Self::oneshot_send(tx, result, "script_mock_tunnel response");
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.to_settings(), "get_settings response");
     }
@@ -2431,6 +5330,9 @@ where
     }
 
     fn send_tunnel_command(&self, command: TunnelCommand) {
+        if let Some((kind, message)) = recent_events::describe_command(&command) {
+            self.recent_events.push(kind, message);
+        }
         self.tunnel_state_machine_handle
             .command_tx()
             .unbounded_send(command)
@@ -2457,15 +5359,219 @@ impl DaemonShutdownHandle {
     }
 }
 
-fn new_selector_config(settings: &Settings) -> SelectorConfig {
-    let default_tunnel_type = TunnelType::Wireguard;
+/// Returns the addresses of the relays, proxies and obfuscators that the tunnel connects to.
+fn relay_addresses(tunnel_state: &TunnelState) -> Vec<IpAddr> {
+    let endpoint = match tunnel_state {
+        TunnelState::Connecting { endpoint, .. } | TunnelState::Connected { endpoint, .. } => {
+            endpoint
+        }
+        _ => return vec![],
+    };
+    std::iter::once(&endpoint.endpoint)
+        .chain(endpoint.entry_endpoint.as_ref())
+        .chain(endpoint.proxy.as_ref().map(|proxy| &proxy.endpoint))
+        .chain(endpoint.obfuscation.as_ref().map(|obfs| &obfs.endpoint))
+        .map(|endpoint| endpoint.address.ip())
+        .collect()
+}
+
+/// Returns the users and groups in `settings` whose processes are excluded from the tunnel.
+#[cfg(target_os = "linux")]
+fn excluded_owners(settings: &Settings) -> split_tunnel::ExcludedOwners {
+    split_tunnel::ExcludedOwners {
+        users: settings.split_tunnel.users.clone(),
+        groups: settings.split_tunnel.groups.clone(),
+    }
+}
+
+/// Returns the split tunnel settings in `settings` that only affect the firewall rules.
+#[cfg(target_os = "linux")]
+fn split_tunnel_config(settings: &Settings) -> split_tunnel::Config {
+    split_tunnel::Config {
+        mode: settings.split_tunnel.mode,
+        destinations: settings.split_tunnel.destinations.clone(),
+        interfaces: settings.split_tunnel.interfaces.clone(),
+        excluded_apps_allow_lan: settings.split_tunnel.excluded_apps_allow_lan,
+        local_ports: settings.split_tunnel.local_ports.clone(),
+    }
+}
+
+/// Returns the paths and publishers of applications that are excluded from the tunnel.
+#[cfg(windows)]
+fn excluded_apps<'a>(apps: impl IntoIterator<Item = &'a SplitApp>) -> split_tunnel::ExcludedApps {
+    let mut excluded = split_tunnel::ExcludedApps::default();
+    for app in apps {
+        match app {
+            SplitApp::Path(path) => excluded.paths.push(OsString::from(path)),
+            SplitApp::Publisher(publisher) => excluded.publishers.push(publisher.clone()),
+        }
+    }
+    excluded
+}
+
+/// Overrides the number of WireGuard attempts that are made before falling back to OpenVPN when
+/// the tunnel protocol is automatic.
+static WIREGUARD_ATTEMPTS_BEFORE_OPENVPN: Lazy<u32> = Lazy::new(|| {
+    std::env::var("MULLVAD_WIREGUARD_ATTEMPTS_BEFORE_OPENVPN")
+        .ok()
+        .and_then(|attempts| attempts.parse().ok())
+        .unwrap_or(mullvad_relay_selector::DEFAULT_WIREGUARD_ATTEMPTS_BEFORE_OPENVPN)
+});
+
+fn new_selector_config(
+    settings: &Settings,
+    network: &CurrentNetwork,
+    scores: &ObfuscationScores,
+    session_tunnel_types: &HashMap<NetworkId, TunnelType>,
+) -> SelectorConfig {
+    // Prefer the tunnel type that worked on this network earlier in the session
+    let default_tunnel_type = network
+        .id()
+        .and_then(|network| session_tunnel_types.get(&network).copied())
+        .unwrap_or(TunnelType::Wireguard);
+
+    let mut obfuscation_settings =
+        network_profiles::effective_obfuscation_settings(settings, network).clone();
+    if let Some(network) = network.id() {
+        // Try the obfuscation types that have worked on this network before first
+        obfuscation_settings.obfuscation_priority = scores.order(
+            &network,
+            obfuscation_settings.obfuscation_priority(),
+            SystemTime::now(),
+        );
+    }
 
     SelectorConfig {
         relay_settings: settings.relay_settings.clone(),
         bridge_state: settings.bridge_state,
         bridge_settings: settings.bridge_settings.clone(),
-        obfuscation_settings: settings.obfuscation_settings.clone(),
+        obfuscation_settings,
         default_tunnel_type,
+        wireguard_attempts_before_openvpn: *WIREGUARD_ATTEMPTS_BEFORE_OPENVPN,
         custom_lists: settings.custom_lists.clone(),
     }
 }
+
+/// Records in the connected state that another program has overwritten the DNS config. Returns
+/// whether `tunnel_state` changed.
+fn mark_dns_tampered(tunnel_state: &mut TunnelState) -> bool {
+    match tunnel_state {
+        TunnelState::Connected { effective_dns, .. } if !effective_dns.tampered => {
+            effective_dns.tampered = true;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Replaces the DNS config of the connected state, without treating it as a new state. Returns
+/// whether `tunnel_state` changed.
+fn update_effective_dns(tunnel_state: &mut TunnelState, new_dns: EffectiveDns) -> bool {
+    match tunnel_state {
+        TunnelState::Connected { effective_dns, .. } if *effective_dns != new_dns => {
+            *effective_dns = new_dns;
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use talpid_types::net::{DnsSource, Endpoint, TransportProtocol};
+
+    /// Returns an endpoint of a relay that uses `tunnel_type`.
+    pub(crate) fn tunnel_endpoint(tunnel_type: TunnelType) -> TunnelEndpoint {
+        TunnelEndpoint {
+            endpoint: Endpoint::new([192, 0, 2, 1], 51820, TransportProtocol::Udp),
+            tunnel_type,
+            quantum_resistant: false,
+            proxy: None,
+            obfuscation: None,
+            entry_endpoint: None,
+            tunnel_interface: None,
+        }
+    }
+
+    /// Returns the state of a tunnel that is connected to `endpoint`.
+    pub(crate) fn connected_state(
+        endpoint: TunnelEndpoint,
+        location: Option<GeoIpLocation>,
+    ) -> TunnelState {
+        TunnelState::Connected {
+            endpoint,
+            location,
+            effective_dns: Default::default(),
+            first_hop: None,
+        }
+    }
+
+    fn connected_with_dns(dns: EffectiveDns) -> TunnelState {
+        let mut state = connected_state(tunnel_endpoint(TunnelType::Wireguard), None);
+        if let TunnelState::Connected { effective_dns, .. } = &mut state {
+            *effective_dns = dns;
+        }
+        state
+    }
+
+    #[test]
+    fn test_dns_tampered_updates_connected_state() {
+        let effective_dns = EffectiveDns {
+            servers: vec!["10.64.0.1".parse().unwrap()],
+            source: DnsSource::Default,
+            tampered: false,
+        };
+        let mut tunnel_state = connected_with_dns(effective_dns.clone());
+
+        assert!(mark_dns_tampered(&mut tunnel_state));
+        let TunnelState::Connected {
+            effective_dns: tampered_dns,
+            ..
+        } = &tunnel_state
+        else {
+            panic!("expected the connected state, got {tunnel_state:?}");
+        };
+        assert_eq!(
+            tampered_dns,
+            &EffectiveDns {
+                tampered: true,
+                ..effective_dns
+            }
+        );
+        // The state is only broadcast again the first time
+        assert!(!mark_dns_tampered(&mut tunnel_state));
+
+        let mut tunnel_state = TunnelState::Disconnected;
+        assert!(!mark_dns_tampered(&mut tunnel_state));
+        assert!(tunnel_state.is_disconnected());
+    }
+
+    #[test]
+    fn test_effective_dns_changed_updates_connected_state() {
+        let gateway_dns = EffectiveDns {
+            servers: vec!["10.64.0.1".parse().unwrap()],
+            source: DnsSource::Default,
+            tampered: true,
+        };
+        let custom_dns = EffectiveDns {
+            servers: vec!["192.0.2.53".parse().unwrap()],
+            source: DnsSource::Custom,
+            tampered: false,
+        };
+        let mut tunnel_state = connected_with_dns(gateway_dns);
+
+        assert!(update_effective_dns(&mut tunnel_state, custom_dns.clone()));
+        let TunnelState::Connected { effective_dns, .. } = &tunnel_state else {
+            panic!("expected the connected state, got {tunnel_state:?}");
+        };
+        assert_eq!(effective_dns, &custom_dns);
+        // Nothing is broadcast if the config is unchanged
+        assert!(!update_effective_dns(&mut tunnel_state, custom_dns.clone()));
+
+        // Other states are left alone
+        let mut tunnel_state = TunnelState::Disconnected;
+        assert!(!update_effective_dns(&mut tunnel_state, custom_dns));
+        assert!(tunnel_state.is_disconnected());
+    }
+}
@@ -0,0 +1,529 @@
+//! A SOCKS5 server on localhost that lets other applications make TCP connections through the
+//! tunnel, even if they are excluded from it. The connections are made by the daemon, whose
+//! traffic uses the tunnel, so the proxy is only run while connected. Connections to loopback
+//! and LAN destinations are refused unless LAN traffic is allowed.
+//!
+//! In include mode on Linux, all traffic except that of the selected processes is sent outside the
+//! tunnel, including that of the daemon, so the proxy is not run at all.
+
+use mullvad_types::{access_method::SocksAuth, settings::Settings};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+#[cfg(target_os = "linux")]
+use talpid_types::split_tunnel::SplitTunnelMode;
+use talpid_types::ErrorExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::{JoinHandle, JoinSet},
+};
+
+const SOCKS_VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const COMMAND_CONNECT: u8 = 0x01;
+
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_NOT_ALLOWED: u8 = 0x02;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_CONNECTION_REFUSED: u8 = 0x05;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Maximum time a client may take to authenticate and send its request.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+enum Error {
+    #[error(display = "Failed to communicate with the client")]
+    Client(#[error(source)] io::Error),
+
+    #[error(display = "The client did not complete the handshake in time")]
+    HandshakeTimeout,
+
+    #[error(display = "Unsupported SOCKS version {}", _0)]
+    UnsupportedVersion(u8),
+
+    #[error(display = "The client does not support any accepted authentication method")]
+    NoAcceptableMethod,
+
+    #[error(display = "The client gave invalid credentials")]
+    InvalidCredentials,
+
+    #[error(display = "Unsupported command {}", _0)]
+    UnsupportedCommand(u8),
+
+    #[error(display = "Unsupported address type {}", _0)]
+    UnsupportedAddressType(u8),
+
+    #[error(display = "Connections to local destinations are not allowed")]
+    LocalDestination,
+
+    #[error(display = "Failed to connect to the destination")]
+    Connect(#[error(source)] io::Error),
+}
+
+/// Settings that apply to every client of the proxy.
+struct Config {
+    authentication: Option<SocksAuth>,
+    allow_lan: AtomicBool,
+}
+
+/// Returns whether the connections that are made by the proxy would use the tunnel with
+/// `settings`.
+pub fn is_supported(settings: &Settings) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        settings.split_tunnel.mode != SplitTunnelMode::Include
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = settings;
+        true
+    }
+}
+
+/// A running proxy. It stops listening and closes all of its connections when dropped.
+pub struct LocalProxy {
+    local_addr: SocketAddr,
+    config: Arc<Config>,
+    server: JoinHandle<()>,
+}
+
+impl LocalProxy {
+    /// Starts listening on `port` on the IPv4 loopback address. If `authentication` is set,
+    /// clients must authenticate with that username and password. Loopback and LAN destinations
+    /// can only be reached if `allow_lan` is set.
+    pub async fn start(
+        port: u16,
+        authentication: Option<SocksAuth>,
+        allow_lan: bool,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+        let local_addr = listener.local_addr()?;
+        let config = Arc::new(Config {
+            authentication,
+            allow_lan: AtomicBool::new(allow_lan),
+        });
+        let server = tokio::spawn(serve(listener, config.clone()));
+        Ok(Self {
+            local_addr,
+            config,
+            server,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Sets whether loopback and LAN destinations can be reached. Only affects new connections.
+    pub fn set_allow_lan(&self, allow_lan: bool) {
+        self.config.allow_lan.store(allow_lan, Ordering::Relaxed);
+    }
+
+    /// Stops the proxy, and waits until the port has been released.
+    pub async fn stop(&mut self) {
+        self.server.abort();
+        let _ = (&mut self.server).await;
+    }
+}
+
+impl Drop for LocalProxy {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn serve(listener: TcpListener, config: Arc<Config>) {
+    // Connections are aborted along with the set when the server is stopped
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            result = listener.accept() => match result {
+                Ok((client, _)) => {
+                    connections.spawn(handle_client(client, config.clone()));
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to accept local proxy connection")
+                    );
+                }
+            },
+            Some(_) = connections.join_next() => (),
+        }
+    }
+}
+
+async fn handle_client(mut client: TcpStream, config: Arc<Config>) {
+    let result =
+        match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(&mut client, &config)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::HandshakeTimeout),
+        };
+    let mut destination = match result {
+        Ok(destination) => destination,
+        Err(error) => {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Local proxy request failed")
+            );
+            return;
+        }
+    };
+    if let Err(error) = tokio::io::copy_bidirectional(&mut client, &mut destination).await {
+        log::trace!(
+            "{}",
+            error.display_chain_with_msg("Local proxy connection closed")
+        );
+    }
+}
+
+/// Negotiates the authentication method, authenticates the client, and connects to the
+/// destination of its request. The client is told whether the connection succeeded.
+async fn handshake(client: &mut TcpStream, config: &Config) -> Result<TcpStream, Error> {
+    let version = client.read_u8().await.map_err(Error::Client)?;
+    if version != SOCKS_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let num_methods = client.read_u8().await.map_err(Error::Client)?;
+    let mut methods = vec![0u8; usize::from(num_methods)];
+    client
+        .read_exact(&mut methods)
+        .await
+        .map_err(Error::Client)?;
+
+    let method = match config.authentication {
+        Some(_) => METHOD_USERNAME_PASSWORD,
+        None => METHOD_NO_AUTH,
+    };
+    if !methods.contains(&method) {
+        client
+            .write_all(&[SOCKS_VERSION, METHOD_NONE_ACCEPTABLE])
+            .await
+            .map_err(Error::Client)?;
+        return Err(Error::NoAcceptableMethod);
+    }
+    client
+        .write_all(&[SOCKS_VERSION, method])
+        .await
+        .map_err(Error::Client)?;
+    if let Some(authentication) = &config.authentication {
+        authenticate(client, authentication).await?;
+    }
+
+    let [version, command, _reserved, address_type] = read_array(client).await?;
+    if version != SOCKS_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let mut addresses = match address_type {
+        ADDRESS_IPV4 => {
+            let ip = Ipv4Addr::from(read_array::<4>(client).await?);
+            vec![SocketAddr::new(IpAddr::V4(ip), read_port(client).await?)]
+        }
+        ADDRESS_IPV6 => {
+            let ip = Ipv6Addr::from(read_array::<16>(client).await?);
+            vec![SocketAddr::new(IpAddr::V6(ip), read_port(client).await?)]
+        }
+        ADDRESS_DOMAIN => {
+            let domain = read_string(client).await?;
+            let port = read_port(client).await?;
+            let lookup = tokio::net::lookup_host((domain.as_str(), port)).await;
+            match lookup {
+                Ok(addresses) => addresses.collect(),
+                Err(error) => {
+                    send_reply(client, REPLY_HOST_UNREACHABLE).await?;
+                    return Err(Error::Connect(error));
+                }
+            }
+        }
+        address_type => {
+            send_reply(client, REPLY_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Err(Error::UnsupportedAddressType(address_type));
+        }
+    };
+    if command != COMMAND_CONNECT {
+        send_reply(client, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(Error::UnsupportedCommand(command));
+    }
+    if !config.allow_lan.load(Ordering::Relaxed) {
+        addresses.retain(|address| !is_local_destination(address.ip()));
+        if addresses.is_empty() {
+            send_reply(client, REPLY_NOT_ALLOWED).await?;
+            return Err(Error::LocalDestination);
+        }
+    }
+
+    match TcpStream::connect(&addresses[..]).await {
+        Ok(destination) => {
+            send_reply(client, REPLY_SUCCEEDED).await?;
+            Ok(destination)
+        }
+        Err(error) => {
+            let reply = match error.kind() {
+                io::ErrorKind::ConnectionRefused => REPLY_CONNECTION_REFUSED,
+                _ => REPLY_GENERAL_FAILURE,
+            };
+            send_reply(client, reply).await?;
+            Err(Error::Connect(error))
+        }
+    }
+}
+
+/// Performs username and password authentication as described in RFC 1929.
+async fn authenticate(client: &mut TcpStream, authentication: &SocksAuth) -> Result<(), Error> {
+    let [version] = read_array(client).await?;
+    if version != AUTH_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let username = read_string(client).await?;
+    let password = read_string(client).await?;
+    // Both fields are always compared, so that the time taken does not reveal which was wrong
+    let valid = constant_time_eq(username.as_bytes(), authentication.username.as_bytes())
+        & constant_time_eq(password.as_bytes(), authentication.password.as_bytes());
+    client
+        .write_all(&[AUTH_VERSION, u8::from(!valid)])
+        .await
+        .map_err(Error::Client)?;
+    if !valid {
+        return Err(Error::InvalidCredentials);
+    }
+    Ok(())
+}
+
+/// Compares two byte strings in a time that only depends on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Returns whether `address` is on the loopback interface or the LAN, where connections made by
+/// the daemon would not go through the tunnel. IPv4-mapped addresses are checked as IPv4, and
+/// the unspecified address is treated as loopback, since connecting to it reaches this machine.
+fn is_local_destination(address: IpAddr) -> bool {
+    let address = match address {
+        IpAddr::V6(address) => address
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(address)),
+        address => address,
+    };
+    address.is_unspecified() || talpid_core::firewall::is_local_address(&address)
+}
+
+/// Sends a reply to a request. The bound address is not used by clients of CONNECT requests, so
+/// the unspecified address is always sent.
+async fn send_reply(client: &mut TcpStream, reply: u8) -> Result<(), Error> {
+    client
+        .write_all(&[SOCKS_VERSION, reply, 0, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+        .map_err(Error::Client)
+}
+
+async fn read_array<const N: usize>(client: &mut TcpStream) -> Result<[u8; N], Error> {
+    let mut buffer = [0u8; N];
+    client
+        .read_exact(&mut buffer)
+        .await
+        .map_err(Error::Client)?;
+    Ok(buffer)
+}
+
+async fn read_port(client: &mut TcpStream) -> Result<u16, Error> {
+    Ok(u16::from_be_bytes(read_array(client).await?))
+}
+
+/// Reads a string that is prefixed by its length.
+async fn read_string(client: &mut TcpStream) -> Result<String, Error> {
+    let len = client.read_u8().await.map_err(Error::Client)?;
+    let mut buffer = vec![0u8; usize::from(len)];
+    client
+        .read_exact(&mut buffer)
+        .await
+        .map_err(Error::Client)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Starts a server that echoes everything it receives.
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Sends a CONNECT request for `destination` through the proxy, and returns the reply code.
+    async fn connect(
+        proxy: SocketAddr,
+        authentication: Option<(&str, &str)>,
+        destination: SocketAddr,
+    ) -> io::Result<(TcpStream, u8)> {
+        let mut stream = TcpStream::connect(proxy).await?;
+        let method = match authentication {
+            Some(_) => METHOD_USERNAME_PASSWORD,
+            None => METHOD_NO_AUTH,
+        };
+        stream.write_all(&[SOCKS_VERSION, 1, method]).await?;
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await?;
+        assert_eq!(response, [SOCKS_VERSION, method]);
+
+        if let Some((username, password)) = authentication {
+            let mut request = vec![AUTH_VERSION, username.len() as u8];
+            request.extend(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend(password.as_bytes());
+            stream.write_all(&request).await?;
+            stream.read_exact(&mut response).await?;
+            if response[1] != 0 {
+                return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+            }
+        }
+
+        let SocketAddr::V4(destination) = destination else {
+            unreachable!("the test servers use IPv4");
+        };
+        let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0, ADDRESS_IPV4];
+        request.extend(destination.ip().octets());
+        request.extend(destination.port().to_be_bytes());
+        stream.write_all(&request).await?;
+        let mut reply = [0u8; 10];
+        stream.read_exact(&mut reply).await?;
+        Ok((stream, reply[1]))
+    }
+
+    #[tokio::test]
+    async fn test_connect_relay() {
+        let echo = echo_server().await;
+        let proxy = LocalProxy::start(0, None, true).await.unwrap();
+
+        let (mut stream, reply) = connect(proxy.local_addr(), None, echo).await.unwrap();
+        assert_eq!(reply, REPLY_SUCCEEDED);
+        stream.write_all(b"through the tunnel").await.unwrap();
+        let mut buffer = [0u8; 18];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"through the tunnel");
+
+        // Nothing is listening on the port of a dropped listener
+        let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let (_, reply) = connect(proxy.local_addr(), None, closed_addr)
+            .await
+            .unwrap();
+        assert_eq!(reply, REPLY_CONNECTION_REFUSED);
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let echo = echo_server().await;
+        let authentication = SocksAuth {
+            username: "user".to_owned(),
+            password: "secret".to_owned(),
+        };
+        let proxy = LocalProxy::start(0, Some(authentication), true)
+            .await
+            .unwrap();
+
+        let (_, reply) = connect(proxy.local_addr(), Some(("user", "secret")), echo)
+            .await
+            .unwrap();
+        assert_eq!(reply, REPLY_SUCCEEDED);
+        assert!(connect(proxy.local_addr(), Some(("user", "wrong")), echo)
+            .await
+            .is_err());
+        assert!(connect(proxy.local_addr(), Some(("use", "secret")), echo)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_destinations() {
+        let echo = echo_server().await;
+        let proxy = LocalProxy::start(0, None, false).await.unwrap();
+
+        let (_, reply) = connect(proxy.local_addr(), None, echo).await.unwrap();
+        assert_eq!(reply, REPLY_NOT_ALLOWED);
+
+        proxy.set_allow_lan(true);
+        let (_, reply) = connect(proxy.local_addr(), None, echo).await.unwrap();
+        assert_eq!(reply, REPLY_SUCCEEDED);
+    }
+
+    #[test]
+    fn test_is_supported() {
+        assert!(is_supported(&Settings::default()));
+    }
+
+    /// In include mode, the connections of the daemon are sent outside the tunnel.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_not_supported_in_include_mode() {
+        let mut settings = Settings::default();
+        settings.split_tunnel.mode = SplitTunnelMode::Include;
+        settings.local_proxy.port = Some(1080);
+        assert!(!is_supported(&settings));
+    }
+
+    #[test]
+    fn test_is_local_destination() {
+        for address in [
+            "127.0.0.1",
+            "0.0.0.0",
+            "192.168.1.1",
+            "10.0.0.1",
+            "::1",
+            "::",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:192.168.1.1",
+        ] {
+            assert!(is_local_destination(address.parse().unwrap()), "{address}");
+        }
+        for address in ["1.1.1.1", "2001:db8::1", "::ffff:1.1.1.1"] {
+            assert!(!is_local_destination(address.parse().unwrap()), "{address}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop_closes_connections() {
+        let echo = echo_server().await;
+        let mut proxy = LocalProxy::start(0, None, true).await.unwrap();
+        let proxy_addr = proxy.local_addr();
+        let (mut stream, _) = connect(proxy_addr, None, echo).await.unwrap();
+
+        proxy.stop().await;
+        // Open connections are closed, and new connections are refused
+        let mut buffer = [0u8; 1];
+        assert_eq!(stream.read(&mut buffer).await.unwrap_or(0), 0);
+        assert!(TcpStream::connect(proxy_addr).await.is_err());
+    }
+}
@@ -3,19 +3,11 @@ use fern::{
     Output,
 };
 use std::{fmt, io, path::PathBuf};
-use talpid_core::logging::rotate_log;
+use talpid_core::logging::RotatingLogWriter;
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
-    /// Unable to open log file for writing
-    #[error(display = "Unable to open log file for writing: {}", path)]
-    WriteFile {
-        path: String,
-        #[error(source)]
-        source: io::Error,
-    },
-
-    #[error(display = "Unable to rotate daemon log file")]
+    #[error(display = "Unable to open daemon log file")]
     RotateLog(#[error(source)] talpid_core::logging::RotateLogError),
 
     #[error(display = "Unable to set logger")]
@@ -87,19 +79,15 @@ pub fn init_logger(
         .chain(io::stdout());
     top_dispatcher = top_dispatcher.chain(stdout_dispatcher);
 
-    if let Some(ref log_file) = log_file {
-        rotate_log(log_file).map_err(Error::RotateLog)?;
+    if let Some(log_file) = log_file {
+        let writer = RotatingLogWriter::open(log_file).map_err(Error::RotateLog)?;
         let file_formatter = Formatter {
             output_timestamp: true,
             output_color: false,
         };
-        let f = fern::log_file(log_file).map_err(|source| Error::WriteFile {
-            path: log_file.display().to_string(),
-            source,
-        })?;
         let file_dispatcher = fern::Dispatch::new()
             .format(move |out, message, record| file_formatter.output_msg(out, message, record))
-            .chain(Output::file(f, LINE_SEPARATOR));
+            .chain(Output::writer(Box::new(writer), LINE_SEPARATOR));
         top_dispatcher = top_dispatcher.chain(file_dispatcher);
     }
     #[cfg(all(target_os = "android", debug_assertions))]
@@ -1,34 +1,61 @@
-use crate::{account_history, device, settings, DaemonCommand, DaemonCommandSender, EventListener};
+use crate::{
+    access_method, account_history, device, settings, DaemonCommand, DaemonCommandSender,
+    EventListener,
+};
 use futures::{
     channel::{mpsc, oneshot},
     StreamExt,
 };
+use ipnetwork::IpNetwork;
 use mullvad_api::{rest::Error as RestError, StatusCode};
 use mullvad_management_interface::{
-    types::{self, daemon_event, management_service_server::ManagementService},
+    types::{
+        self, daemon_event, events_listen_request::Category as EventCategory,
+        management_service_server::ManagementService,
+    },
     Code, Request, Response, Status,
 };
 use mullvad_paths;
 #[cfg(not(target_os = "android"))]
 use mullvad_types::settings::DnsOptions;
+#[cfg(windows)]
+use mullvad_types::settings::SplitApp;
 use mullvad_types::{
-    account::AccountToken,
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
+    access_method::ApiConnectivity,
+    account::{AccountExpiryWarning, AccountToken},
+    network_profiles::NetworkId,
+    network_trust::NetworkTrustSettings,
+    relay_constraints::{
+        BridgeSettings, BridgeState, GeographicLocationConstraint, ObfuscationSettings,
+        RelayOverrides, RelaySettingsUpdate,
+    },
     relay_list::RelayList,
-    settings::Settings,
-    states::{TargetState, TunnelState},
+    routes::RoutesUpdate,
+    settings::{
+        AccountExpiryWarnings, HookSettings, LocalProxySettings, LogRotationSettings, Settings,
+    },
+    split_tunnel::validate_split_tunnel_config,
+    states::{IdleDisconnect, NetworkChange, TargetState, TunnelState},
     version,
     wireguard::{RotationInterval, RotationIntervalError},
 };
 #[cfg(windows)]
 use std::path::PathBuf;
 use std::{
+    collections::{BTreeSet, VecDeque},
     convert::{TryFrom, TryInto},
     str::FromStr,
     sync::{Arc, Mutex},
     time::Duration,
 };
-use talpid_types::ErrorExt;
+#[cfg(target_os = "linux")]
+use talpid_types::split_tunnel::ExcludedDestination;
+use talpid_types::{
+    net::TransportProtocol,
+    split_tunnel::{AppExclusionStatus, ExcludedProcess, SplitTunnelMode},
+    tunnel::{MockTunnelScript, RetryBackoff},
+    ErrorExt,
+};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
 #[derive(err_derive::Error, Debug)]
@@ -41,13 +68,92 @@ pub enum Error {
 
 struct ManagementServiceImpl {
     daemon_tx: DaemonCommandSender,
-    subscriptions: Arc<Mutex<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
 }
 
 pub type ServiceResult<T> = std::result::Result<Response<T>, Status>;
 type EventsListenerReceiver = UnboundedReceiverStream<Result<types::DaemonEvent, Status>>;
 type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
 
+/// The number of events that are kept for clients that ask for them to be replayed.
+const REPLAYED_EVENTS_LIMIT: usize = 50;
+
+/// How often tunnel statistics are sent if the client does not ask for an interval.
+const DEFAULT_TUNNEL_STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// Tunnel statistics are never sent more often than this.
+const MIN_TUNNEL_STATS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The clients that listen for events, and the most recent events. They share a lock so that a
+/// client that subscribes neither misses nor receives an event twice.
+#[derive(Default)]
+struct Subscriptions {
+    listeners: Vec<EventsListener>,
+    recent_events: VecDeque<types::DaemonEvent>,
+}
+
+struct EventsListener {
+    tx: EventsListenerSender,
+    /// The categories of events to send, or `None` to send all events.
+    categories: Option<Vec<EventCategory>>,
+}
+
+impl EventsListener {
+    fn wants(&self, event: &types::DaemonEvent) -> bool {
+        match (&self.categories, &event.event) {
+            (None, _) => true,
+            (Some(categories), Some(event)) => categories.contains(&event.category()),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl Subscriptions {
+    /// Adds a listener, and sends it the most recent events that it asks to be replayed.
+    fn subscribe(&mut self, request: types::EventsListenRequest) -> EventsListenerReceiver {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        // Categories that this daemon does not know of are ignored. If the client only asked for
+        // such categories, it gets no events at all rather than every event.
+        let categories = (!request.categories.is_empty()).then(|| {
+            request
+                .categories
+                .iter()
+                .filter_map(|&category| EventCategory::try_from(category).ok())
+                .collect()
+        });
+        let listener = EventsListener { tx, categories };
+
+        let mut replayed: Vec<_> = self
+            .recent_events
+            .iter()
+            .rev()
+            .filter(|event| listener.wants(event))
+            .take(usize::try_from(request.replay).unwrap_or(usize::MAX))
+            .collect();
+        replayed.reverse();
+        for event in replayed {
+            let _ = listener.tx.send(Ok(event.clone()));
+        }
+
+        self.listeners.push(listener);
+        UnboundedReceiverStream::new(rx)
+    }
+
+    fn notify(&mut self, event: types::DaemonEvent) {
+        if self.recent_events.len() >= REPLAYED_EVENTS_LIMIT {
+            self.recent_events.pop_front();
+        }
+        self.recent_events.push_back(event.clone());
+
+        self.listeners.retain(|listener| {
+            if listener.wants(&event) {
+                listener.tx.send(Ok(event.clone())).is_ok()
+            } else {
+                !listener.tx.is_closed()
+            }
+        });
+    }
+}
+
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 
@@ -55,6 +161,7 @@ const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 impl ManagementService for ManagementServiceImpl {
     type GetSplitTunnelProcessesStream = UnboundedReceiverStream<Result<i32, Status>>;
     type EventsListenStream = EventsListenerReceiver;
+    type TunnelStatsListenStream = UnboundedReceiverStream<Result<types::TunnelStats, Status>>;
 
     // Control and get the tunnel state
     //
@@ -85,6 +192,19 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(reconnect_issued))
     }
 
+    async fn reconnect_with_overrides(
+        &self,
+        request: Request<types::RelayOverrides>,
+    ) -> ServiceResult<bool> {
+        log::debug!("reconnect_with_overrides");
+        let overrides =
+            RelayOverrides::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ReconnectWithOverrides(tx, overrides))?;
+        let reconnect_issued = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(reconnect_issued))
+    }
+
     async fn get_tunnel_state(&self, _: Request<()>) -> ServiceResult<types::TunnelState> {
         log::debug!("get_tunnel_state");
         let (tx, rx) = oneshot::channel();
@@ -93,16 +213,52 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(types::TunnelState::from(state)))
     }
 
-    // Control the daemon and receive events
-    //
+    async fn tunnel_stats_listen(
+        &self,
+        request: Request<types::TunnelStatsRequest>,
+    ) -> ServiceResult<Self::TunnelStatsListenStream> {
+        let interval_ms = request.into_inner().interval_ms;
+        log::debug!("tunnel_stats_listen({interval_ms})");
+        let interval = tunnel_stats_interval(interval_ms);
 
-    async fn events_listen(&self, _: Request<()>) -> ServiceResult<Self::EventsListenStream> {
+        let daemon_tx = self.daemon_tx.clone();
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => (),
+                    _ = tx.closed() => break,
+                }
+                let (stats_tx, stats_rx) = oneshot::channel();
+                if daemon_tx
+                    .send(DaemonCommand::GetTunnelStats(stats_tx))
+                    .is_err()
+                {
+                    break;
+                }
+                // The daemon drops the sender if it is shutting down, which ends the stream
+                let Ok(stats) = stats_rx.await else {
+                    break;
+                };
+                if tx.send(Ok(types::TunnelStats::from(stats))).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
 
-        let mut subscriptions = self.subscriptions.lock().unwrap();
-        subscriptions.push(tx);
+    // Control the daemon and receive events
+    //
 
-        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    async fn events_listen(
+        &self,
+        request: Request<types::EventsListenRequest>,
+    ) -> ServiceResult<Self::EventsListenStream> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        Ok(Response::new(subscriptions.subscribe(request.into_inner())))
     }
 
     async fn prepare_restart(&self, _: Request<()>) -> ServiceResult<()> {
@@ -111,12 +267,16 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
-    async fn factory_reset(&self, _: Request<()>) -> ServiceResult<()> {
+    async fn factory_reset(
+        &self,
+        request: Request<types::FactoryResetRequest>,
+    ) -> ServiceResult<()> {
         #[cfg(not(target_os = "android"))]
         {
-            log::debug!("factory_reset");
+            let scopes = mullvad_types::settings::ResetScopes::from(request.into_inner());
+            log::debug!("factory_reset({:?})", scopes);
             let (tx, rx) = oneshot::channel();
-            self.send_command_to_daemon(DaemonCommand::FactoryReset(tx))?;
+            self.send_command_to_daemon(DaemonCommand::FactoryReset(tx, scopes))?;
             self.wait_for_result(rx)
                 .await?
                 .map(Response::new)
@@ -124,10 +284,86 @@ impl ManagementService for ManagementServiceImpl {
         }
         #[cfg(target_os = "android")]
         {
+            let _ = request;
             Ok(Response::new(()))
         }
     }
 
+    async fn export_settings(
+        &self,
+        request: Request<types::ExportSettingsRequest>,
+    ) -> ServiceResult<String> {
+        let include_secrets = request.into_inner().include_secrets;
+        log::debug!("export_settings({include_secrets})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportSettings(tx, include_secrets))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn import_settings(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("import_settings");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ImportSettings(tx, request.into_inner()))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn apply_settings_patch(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("apply_settings_patch");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ApplySettingsPatch(tx, request.into_inner()))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn save_profile(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        log::debug!("save_profile({name})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SaveProfile(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn list_profiles(&self, _: Request<()>) -> ServiceResult<types::ProfileList> {
+        log::debug!("list_profiles");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ListProfiles(tx))?;
+        let names = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ProfileList { names }))
+    }
+
+    async fn apply_profile(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        log::debug!("apply_profile({name})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ApplyProfile(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn delete_profile(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        log::debug!("delete_profile({name})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::DeleteProfile(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     async fn get_current_version(&self, _: Request<()>) -> ServiceResult<String> {
         log::debug!("get_current_version");
         let (tx, rx) = oneshot::channel();
@@ -148,6 +384,20 @@ impl ManagementService for ManagementServiceImpl {
             .map(Response::new)
     }
 
+    async fn check_version(
+        &self,
+        request: Request<types::CheckVersionRequest>,
+    ) -> ServiceResult<types::AppVersionInfo> {
+        let beta = request.into_inner().beta;
+        log::debug!("check_version({beta})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::CheckVersion(tx, beta))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|version_info| Response::new(types::AppVersionInfo::from(version_info)))
+            .map_err(map_daemon_error)
+    }
+
     async fn is_performing_post_upgrade(&self, _: Request<()>) -> ServiceResult<bool> {
         log::debug!("is_performing_post_upgrade");
         let (tx, rx) = oneshot::channel();
@@ -181,6 +431,26 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn preview_relay_settings(
+        &self,
+        request: Request<types::RelaySettingsUpdate>,
+    ) -> ServiceResult<types::MatchingRelays> {
+        log::debug!("preview_relay_settings");
+        let (tx, rx) = oneshot::channel();
+        let update =
+            RelaySettingsUpdate::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+
+        self.send_command_to_daemon(DaemonCommand::PreviewRelaySettings(tx, update))?;
+        match self.wait_for_result(rx).await? {
+            Some(matching_relays) => {
+                Ok(Response::new(types::MatchingRelays::from(matching_relays)))
+            }
+            None => Err(Status::invalid_argument(
+                "Custom relays are not selected by the relay selector",
+            )),
+        }
+    }
+
     async fn get_relay_locations(&self, _: Request<()>) -> ServiceResult<types::RelayList> {
         log::debug!("get_relay_locations");
 
@@ -234,6 +504,49 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_network_obfuscation_profiles(
+        &self,
+        request: Request<types::NetworkObfuscationProfiles>,
+    ) -> ServiceResult<()> {
+        let profiles = Vec::<(NetworkId, ObfuscationSettings)>::try_from(request.into_inner())
+            .map_err(map_protobuf_type_err)?;
+        log::debug!("set_network_obfuscation_profiles({:?})", profiles);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetNetworkObfuscationProfiles(tx, profiles))?;
+        let settings_result = self.wait_for_result(rx).await?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_network_trust_settings(
+        &self,
+        request: Request<types::NetworkTrustSettings>,
+    ) -> ServiceResult<()> {
+        let settings =
+            NetworkTrustSettings::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_network_trust_settings({:?})", settings);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetNetworkTrustSettings(tx, settings))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_active_obfuscation_profile(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ActiveObfuscationProfile> {
+        log::debug!("get_active_obfuscation_profile");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetActiveObfuscationProfile(tx))?;
+        let network = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ActiveObfuscationProfile {
+            network: network.as_ref().map(types::NetworkId::from),
+        }))
+    }
+
     async fn set_bridge_state(&self, request: Request<types::BridgeState>) -> ServiceResult<()> {
         let bridge_state =
             BridgeState::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
@@ -270,6 +583,38 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_excluded_apps_allow_lan(&self, request: Request<bool>) -> ServiceResult<()> {
+        let allow = request.into_inner();
+        log::debug!("set_excluded_apps_allow_lan({allow})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetExcludedAppsAllowLan(tx, allow))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_excluded_apps_allow_lan(&self, request: Request<bool>) -> ServiceResult<()> {
+        let allow = request.into_inner();
+        log::debug!("set_excluded_apps_allow_lan({allow})");
+        // Excluded apps can always reach the local network on this platform
+        settings::validate_excluded_apps_allow_lan(allow)
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_bypass_routes(&self, request: Request<types::BypassRoutes>) -> ServiceResult<()> {
+        let routes = Vec::<IpNetwork>::try_from(request.into_inner())?;
+        log::debug!("set_bypass_routes({:?})", routes);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetBypassRoutes(tx, routes))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
         let enabled = request.into_inner();
         log::debug!("set_show_beta_releases({})", enabled);
@@ -295,6 +640,17 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_coexistence_mode(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_coexistence_mode({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetCoexistenceMode(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_auto_connect(&self, request: Request<bool>) -> ServiceResult<()> {
         let auto_connect = request.into_inner();
         log::debug!("set_auto_connect({})", auto_connect);
@@ -379,6 +735,30 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    async fn run_dns_leak_test(&self, _: Request<()>) -> ServiceResult<types::DnsLeakTestResult> {
+        log::debug!("run_dns_leak_test");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RunDnsLeakTest(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|result| Response::new(types::DnsLeakTestResult::from(result)))
+            .map_err(map_daemon_error)
+    }
+
+    async fn check_connection(
+        &self,
+        request: Request<types::CheckConnectionRequest>,
+    ) -> ServiceResult<types::ConnectionCheck> {
+        let allow_disconnected = request.into_inner().allow_disconnected;
+        log::debug!("check_connection({allow_disconnected})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::CheckConnection(tx, allow_disconnected))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|result| Response::new(types::ConnectionCheck::from(result)))
+            .map_err(map_daemon_error)
+    }
+
     // Account management
     //
 
@@ -452,6 +832,49 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
     }
 
+    async fn list_saved_accounts(&self, _: Request<()>) -> ServiceResult<types::SavedAccounts> {
+        log::debug!("list_saved_accounts");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ListSavedAccounts(tx))?;
+        self.wait_for_result(rx).await.map(|accounts| {
+            Response::new(types::SavedAccounts {
+                accounts: accounts
+                    .into_iter()
+                    .map(types::SavedAccount::from)
+                    .collect(),
+            })
+        })
+    }
+
+    async fn set_saved_account_label(
+        &self,
+        request: Request<types::SavedAccountLabel>,
+    ) -> ServiceResult<()> {
+        log::debug!("set_saved_account_label");
+        let request = request.into_inner();
+        let label = Some(request.label).filter(|label| !label.is_empty());
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSavedAccountLabel(
+            tx,
+            request.account_token,
+            label,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn switch_account(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("switch_account");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SwitchAccount(tx, request.into_inner()))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     async fn get_www_auth_token(&self, _: Request<()>) -> ServiceResult<String> {
         log::debug!("get_www_auth_token");
         let (tx, rx) = oneshot::channel();
@@ -619,6 +1042,29 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
     }
 
+    async fn replace_custom_list(
+        &self,
+        request: Request<types::ReplaceCustomListRequest>,
+    ) -> ServiceResult<String> {
+        log::debug!("replace_custom_list");
+        let request = request.into_inner();
+        let locations = request
+            .locations
+            .into_iter()
+            .map(GeographicLocationConstraint::try_from)
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ReplaceCustomList(
+            tx,
+            request.name,
+            locations,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|response| Response::new(response.to_string()))
+            .map_err(map_daemon_error)
+    }
+
     // Access Methods
 
     async fn add_api_access_method(
@@ -700,31 +1146,166 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
     }
 
-    async fn get_api_addresses(&self, _: Request<()>) -> ServiceResult<types::ApiAddresses> {
-        log::debug!("get_api_addresses");
+    async fn get_api_access_method_stats(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ApiAccessMethodStats> {
+        log::debug!("get_api_access_method_stats");
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::GetApiAddresses(tx))?;
+        self.send_command_to_daemon(DaemonCommand::GetApiAccessMethodStats(tx))?;
+        let stats = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ApiAccessMethodStats::from(
+            stats.as_slice(),
+        )))
+    }
+
+    async fn get_api_connectivity(&self, _: Request<()>) -> ServiceResult<types::ApiConnectivity> {
+        log::debug!("get_api_connectivity");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetApiConnectivity(tx))?;
+        let status = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ApiConnectivity::from(status)))
+    }
+
+    async fn test_api_access_method(
+        &self,
+        request: Request<types::Uuid>,
+    ) -> ServiceResult<types::ApiAccessMethodTestResult> {
+        log::debug!("test_api_access_method");
+        let api_access_method = mullvad_types::access_method::Id::try_from(request.into_inner())?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::TestApiAccessMethod(tx, api_access_method))?;
         self.wait_for_result(rx)
             .await?
-            .map(types::ApiAddresses::from)
-            .map(Response::new)
+            .map(|report| Response::new(types::ApiAccessMethodTestResult::from(report)))
             .map_err(map_daemon_error)
     }
 
-    // Split tunneling
-    //
+    async fn import_api_access_method(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<types::Uuid> {
+        log::debug!("import_api_access_method");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ImportApiAccessMethod(
+            tx,
+            request.into_inner(),
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(types::Uuid::from)
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
 
-    async fn get_split_tunnel_processes(
+    async fn export_api_access_method(
         &self,
-        _: Request<()>,
-    ) -> ServiceResult<Self::GetSplitTunnelProcessesStream> {
-        #[cfg(target_os = "linux")]
-        {
-            log::debug!("get_split_tunnel_processes");
-            let (tx, rx) = oneshot::channel();
-            self.send_command_to_daemon(DaemonCommand::GetSplitTunnelProcesses(tx))?;
-            let pids = self
-                .wait_for_result(rx)
+        request: Request<types::Uuid>,
+    ) -> ServiceResult<String> {
+        log::debug!("export_api_access_method");
+        let api_access_method = mullvad_types::access_method::Id::try_from(request.into_inner())?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportApiAccessMethod(tx, api_access_method))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn set_prefer_api_in_tunnel(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_prefer_api_in_tunnel({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetPreferApiInTunnel(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_api_addresses(&self, _: Request<()>) -> ServiceResult<types::ApiAddresses> {
+        log::debug!("get_api_addresses");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetApiAddresses(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(types::ApiAddresses::from)
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn set_api_endpoint_override(
+        &self,
+        request: Request<types::ApiEndpointOverride>,
+    ) -> ServiceResult<()> {
+        log::debug!("set_api_endpoint_override");
+        let request = request.into_inner();
+        let endpoint_override = mullvad_api::ApiEndpointOverride {
+            host: request.host,
+            addr: request
+                .address
+                .parse()
+                .map_err(|_| Status::invalid_argument("invalid API address"))?,
+        };
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiEndpointOverride(
+            tx,
+            Some(endpoint_override),
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn clear_api_endpoint_override(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("clear_api_endpoint_override");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiEndpointOverride(tx, None))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn script_mock_tunnel(
+        &self,
+        request: Request<types::MockTunnelScript>,
+    ) -> ServiceResult<()> {
+        let request = request.into_inner();
+        let script = MockTunnelScript {
+            fail_connects: request.fail_connects,
+            connect_delay: request
+                .connect_delay
+                .map(Duration::try_from)
+                .transpose()
+                .map_err(|_| Status::invalid_argument("unexpected negative connect delay"))?,
+            drop_tunnel: request.drop_tunnel,
+            offline: request.offline,
+        };
+        log::debug!("script_mock_tunnel({:?})", script);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ScriptMockTunnel(tx, script))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    // Split tunneling
+    //
+
+    async fn get_split_tunnel_processes(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::GetSplitTunnelProcessesStream> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("get_split_tunnel_processes");
+            let (tx, rx) = oneshot::channel();
+            self.send_command_to_daemon(DaemonCommand::GetSplitTunnelProcesses(tx))?;
+            let pids = self
+                .wait_for_result(rx)
                 .await?
                 .map_err(|error| Status::failed_precondition(error.to_string()))?;
 
@@ -793,12 +1374,276 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_split_tunnel_owners(
+        &self,
+        request: Request<types::SplitTunnelOwners>,
+    ) -> ServiceResult<()> {
+        let owners = request.into_inner();
+        log::debug!(
+            "set_split_tunnel_owners({:?}, {:?})",
+            owners.users,
+            owners.groups
+        );
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSplitTunnelOwners(
+            tx,
+            owners.users,
+            owners.groups,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_split_tunnel_owners(
+        &self,
+        _: Request<types::SplitTunnelOwners>,
+    ) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_split_tunnel_mode(
+        &self,
+        request: Request<types::SplitTunnelMode>,
+    ) -> ServiceResult<()> {
+        let mode = SplitTunnelMode::try_from(request.into_inner())?;
+        log::debug!("set_split_tunnel_mode({mode})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSplitTunnelMode(tx, mode))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_split_tunnel_mode(
+        &self,
+        request: Request<types::SplitTunnelMode>,
+    ) -> ServiceResult<()> {
+        let mode = SplitTunnelMode::try_from(request.into_inner())?;
+        log::debug!("set_split_tunnel_mode({mode})");
+        // Only the default mode is supported on this platform, so there is nothing to save
+        settings::validate_split_tunnel_mode(mode)
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_split_tunnel_destinations(
+        &self,
+        request: Request<types::SplitTunnelDestinations>,
+    ) -> ServiceResult<()> {
+        let destinations = request
+            .into_inner()
+            .destinations
+            .into_iter()
+            .map(ExcludedDestination::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        log::debug!("set_split_tunnel_destinations({destinations:?})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSplitTunnelDestinations(tx, destinations))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_split_tunnel_destinations(
+        &self,
+        request: Request<types::SplitTunnelDestinations>,
+    ) -> ServiceResult<()> {
+        let destinations = request.into_inner().destinations;
+        log::debug!(
+            "set_split_tunnel_destinations({} destinations)",
+            destinations.len()
+        );
+        if destinations.is_empty() {
+            return Ok(Response::new(()));
+        }
+        Err(map_settings_error(
+            settings::Error::SplitTunnelDestinationsNotSupported,
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_split_tunnel_interfaces(
+        &self,
+        request: Request<types::SplitTunnelInterfaces>,
+    ) -> ServiceResult<()> {
+        let interfaces = request.into_inner().interfaces;
+        log::debug!("set_split_tunnel_interfaces({interfaces:?})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSplitTunnelInterfaces(tx, interfaces))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_split_tunnel_interfaces(
+        &self,
+        request: Request<types::SplitTunnelInterfaces>,
+    ) -> ServiceResult<()> {
+        let interfaces = request.into_inner().interfaces;
+        log::debug!("set_split_tunnel_interfaces({interfaces:?})");
+        settings::validate_split_tunnel_interfaces(&interfaces)
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_split_tunnel_local_ports(
+        &self,
+        request: Request<types::SplitTunnelLocalPorts>,
+    ) -> ServiceResult<()> {
+        let ports = request
+            .into_inner()
+            .ports
+            .into_iter()
+            .map(<(TransportProtocol, u16)>::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        log::debug!("set_split_tunnel_local_ports({ports:?})");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSplitTunnelLocalPorts(tx, ports))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_split_tunnel_local_ports(
+        &self,
+        request: Request<types::SplitTunnelLocalPorts>,
+    ) -> ServiceResult<()> {
+        let ports = request
+            .into_inner()
+            .ports
+            .into_iter()
+            .map(<(TransportProtocol, u16)>::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        log::debug!("set_split_tunnel_local_ports({ports:?})");
+        settings::validate_split_tunnel_local_ports(&ports, settings::ephemeral_port_range())
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_local_proxy_settings(
+        &self,
+        request: Request<types::LocalProxySettings>,
+    ) -> ServiceResult<()> {
+        let settings =
+            LocalProxySettings::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        // The credentials are not logged
+        log::debug!(
+            "set_local_proxy_settings(port: {:?}, authentication: {})",
+            settings.port,
+            settings.authentication.is_some()
+        );
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLocalProxySettings(tx, settings))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_hook_settings(&self, request: Request<types::HookSettings>) -> ServiceResult<()> {
+        let settings = HookSettings::from(request.into_inner());
+        log::debug!("set_hook_settings({:?})", settings);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetHookSettings(tx, settings))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_account_expiry_warnings(
+        &self,
+        request: Request<types::AccountExpiryWarnings>,
+    ) -> ServiceResult<()> {
+        let warnings =
+            AccountExpiryWarnings::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_account_expiry_warnings({:?})", warnings);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAccountExpiryWarnings(tx, warnings))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_log_rotation_settings(
+        &self,
+        request: Request<types::LogRotationSettings>,
+    ) -> ServiceResult<()> {
+        let rotation = LogRotationSettings::from(request.into_inner());
+        log::debug!("set_log_rotation_settings({:?})", rotation);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLogRotationSettings(tx, rotation))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_auto_disconnect_idle(
+        &self,
+        request: Request<types::Duration>,
+    ) -> ServiceResult<()> {
+        let timeout = Duration::try_from(request.into_inner())
+            .map_err(|_| Status::invalid_argument("unexpected negative idle timeout"))?;
+        log::debug!("set_auto_disconnect_idle({:?})", timeout);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAutoDisconnectIdle(tx, Some(timeout)))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn clear_auto_disconnect_idle(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("clear_auto_disconnect_idle");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAutoDisconnectIdle(tx, None))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_retry_backoff(&self, request: Request<types::RetryBackoff>) -> ServiceResult<()> {
+        let backoff =
+            RetryBackoff::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_retry_backoff({:?})", backoff);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetRetryBackoff(tx, backoff))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn validate_split_tunnel_config(
+        &self,
+        request: Request<types::Settings>,
+    ) -> ServiceResult<types::SplitTunnelWarnings> {
+        log::debug!("validate_split_tunnel_config");
+        // Only the settings that are given are checked, so the daemon is not involved
+        let settings = Settings::try_from(request.into_inner())?;
+        let warnings = validate_split_tunnel_config(&settings);
+        Ok(Response::new(types::SplitTunnelWarnings::from(warnings)))
+    }
+
     #[cfg(windows)]
     async fn add_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("add_split_tunnel_app");
-        let path = PathBuf::from(request.into_inner());
+        let app = SplitApp::Path(PathBuf::from(request.into_inner()));
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::AddSplitTunnelApp(tx, path))?;
+        self.send_command_to_daemon(DaemonCommand::AddSplitTunnelApp(tx, app))?;
         self.wait_for_result(rx)
             .await?
             .map_err(map_daemon_error)
@@ -812,9 +1657,9 @@ impl ManagementService for ManagementServiceImpl {
     #[cfg(windows)]
     async fn remove_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("remove_split_tunnel_app");
-        let path = PathBuf::from(request.into_inner());
+        let app = SplitApp::Path(PathBuf::from(request.into_inner()));
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::RemoveSplitTunnelApp(tx, path))?;
+        self.send_command_to_daemon(DaemonCommand::RemoveSplitTunnelApp(tx, app))?;
         self.wait_for_result(rx)
             .await?
             .map_err(map_daemon_error)
@@ -825,6 +1670,38 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    #[cfg(windows)]
+    async fn add_split_tunnel_publisher(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("add_split_tunnel_publisher");
+        let app = SplitApp::Publisher(request.into_inner());
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddSplitTunnelApp(tx, app))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+    #[cfg(not(windows))]
+    async fn add_split_tunnel_publisher(&self, _: Request<String>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(windows)]
+    async fn remove_split_tunnel_publisher(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("remove_split_tunnel_publisher");
+        let app = SplitApp::Publisher(request.into_inner());
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveSplitTunnelApp(tx, app))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+    #[cfg(not(windows))]
+    async fn remove_split_tunnel_publisher(&self, _: Request<String>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
     #[cfg(windows)]
     async fn clear_split_tunnel_apps(&self, _: Request<()>) -> ServiceResult<()> {
         log::debug!("clear_split_tunnel_apps");
@@ -877,7 +1754,27 @@ impl ManagementService for ManagementServiceImpl {
             })
     }
 
-    #[cfg(not(windows))]
+    #[cfg(target_os = "linux")]
+    async fn get_excluded_processes(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ExcludedProcessList> {
+        log::debug!("get_excluded_processes");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetExcludedProcesses(tx))?;
+        let processes = self
+            .wait_for_result(rx)
+            .await?
+            .map_err(|error| Status::failed_precondition(error.to_string()))?;
+        Ok(Response::new(types::ExcludedProcessList {
+            processes: processes
+                .into_iter()
+                .map(types::ExcludedProcess::from)
+                .collect(),
+        }))
+    }
+
+    #[cfg(not(any(windows, target_os = "linux")))]
     async fn get_excluded_processes(
         &self,
         _: Request<()>,
@@ -887,6 +1784,29 @@ impl ManagementService for ManagementServiceImpl {
         }))
     }
 
+    #[cfg(windows)]
+    async fn get_split_tunnel_app_status(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::AppExclusionStatusList> {
+        log::debug!("get_split_tunnel_app_status");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetSplitTunnelAppStatus(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_split_tunnel_error)
+            .map(|statuses| Response::new(types::AppExclusionStatusList::from(statuses)))
+    }
+    #[cfg(not(windows))]
+    async fn get_split_tunnel_app_status(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::AppExclusionStatusList> {
+        Ok(Response::new(types::AppExclusionStatusList {
+            apps: vec![],
+        }))
+    }
+
     #[cfg(windows)]
     async fn check_volumes(&self, _: Request<()>) -> ServiceResult<()> {
         log::debug!("check_volumes");
@@ -902,6 +1822,125 @@ impl ManagementService for ManagementServiceImpl {
     async fn check_volumes(&self, _: Request<()>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
+
+    // Problem reports
+    //
+
+    async fn queue_problem_report(
+        &self,
+        request: Request<types::QueueProblemReportRequest>,
+    ) -> ServiceResult<String> {
+        // The report is not logged, since it contains the email and logs of the user
+        log::debug!("queue_problem_report");
+        let types::QueueProblemReportRequest {
+            email,
+            message,
+            report,
+        } = request.into_inner();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::QueueProblemReport(
+            tx, email, message, report,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+
+    async fn get_queued_problem_reports(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::QueuedProblemReportList> {
+        log::debug!("get_queued_problem_reports");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetQueuedProblemReports(tx))?;
+        let reports = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(types::QueuedProblemReportList {
+            reports: reports
+                .into_iter()
+                .map(types::QueuedProblemReport::from)
+                .collect(),
+        }))
+    }
+
+    async fn flush_queued_problem_reports(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ProblemReportSendResultList> {
+        log::debug!("flush_queued_problem_reports");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::FlushQueuedProblemReports(tx))?;
+        let results = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(types::ProblemReportSendResultList {
+            results: results
+                .into_iter()
+                .map(types::ProblemReportSendResult::from)
+                .collect(),
+        }))
+    }
+
+    // Debugging
+    //
+
+    async fn get_routes(&self, _: Request<()>) -> ServiceResult<types::RouteDump> {
+        log::debug!("get_routes");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetRoutes(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|dump| Response::new(types::RouteDump::from(dump)))
+            .map_err(map_daemon_error)
+    }
+
+    async fn get_obfuscation_scores(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ObfuscationScores> {
+        log::debug!("get_obfuscation_scores");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetObfuscationScores(tx))?;
+        let scores = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ObfuscationScores::from(&scores)))
+    }
+
+    async fn get_metrics(&self, _: Request<()>) -> ServiceResult<types::Metrics> {
+        log::debug!("get_metrics");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetMetrics(tx))?;
+        let metrics = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::Metrics {
+            metrics: metrics.iter().map(types::metrics::Metric::from).collect(),
+        }))
+    }
+
+    async fn get_recent_events(&self, _: Request<()>) -> ServiceResult<types::RecentEvents> {
+        log::debug!("get_recent_events");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetRecentEvents(tx))?;
+        let events = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::RecentEvents {
+            events: events
+                .into_iter()
+                .map(types::recent_events::Event::from)
+                .collect(),
+        }))
+    }
+
+    async fn get_connection_history(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ConnectionHistory> {
+        log::debug!("get_connection_history");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetConnectionHistory(tx))?;
+        let connections = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ConnectionHistory {
+            connections: connections
+                .into_iter()
+                .map(types::connection_history::Connection::from)
+                .collect(),
+        }))
+    }
 }
 
 impl ManagementServiceImpl {
@@ -923,7 +1962,7 @@ impl ManagementInterfaceServer {
     pub fn start(
         tunnel_tx: DaemonCommandSender,
     ) -> Result<(String, ManagementInterfaceEventBroadcaster), Error> {
-        let subscriptions = Arc::<Mutex<Vec<EventsListenerSender>>>::default();
+        let subscriptions = Arc::<Mutex<Subscriptions>>::default();
 
         let socket_path = mullvad_paths::get_rpc_socket_path()
             .to_string_lossy()
@@ -959,7 +1998,7 @@ impl ManagementInterfaceServer {
 /// A handle that allows broadcasting messages to all subscribers of the management interface.
 #[derive(Clone)]
 pub struct ManagementInterfaceEventBroadcaster {
-    subscriptions: Arc<Mutex<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
     _close_handle: mpsc::Sender<()>,
 }
 
@@ -1019,22 +2058,118 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
             )),
         })
     }
+
+    fn notify_dns_tampered(&self) {
+        log::debug!("Broadcasting DNS tampered event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::DnsTampered(())),
+        })
+    }
+
+    fn notify_network_changed(&self, change: NetworkChange) {
+        log::debug!("Broadcasting network change event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::NetworkChanged(
+                types::NetworkChange::from(change),
+            )),
+        })
+    }
+
+    fn notify_foreign_tunnel(&self, interface: Option<String>) {
+        log::debug!("Broadcasting foreign tunnel event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ForeignTunnel(types::ForeignTunnel {
+                interface: interface.unwrap_or_default(),
+            })),
+        })
+    }
+
+    fn notify_routes_updated(&self, update: RoutesUpdate) {
+        log::debug!("Broadcasting routes update event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::RoutesUpdated(
+                types::RoutesUpdate::from(update),
+            )),
+        })
+    }
+
+    fn notify_split_tunnel_app_status(&self, statuses: Vec<AppExclusionStatus>) {
+        log::debug!("Broadcasting split tunnel app status event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::SplitTunnelAppStatus(
+                types::AppExclusionStatusList::from(statuses),
+            )),
+        })
+    }
+
+    fn notify_excluded_processes(&self, processes: Vec<ExcludedProcess>) {
+        log::debug!("Broadcasting excluded processes event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ExcludedProcesses(
+                types::ExcludedProcessList::from(processes),
+            )),
+        })
+    }
+
+    fn notify_problem_report_sent(&self, id: String) {
+        log::debug!("Broadcasting problem report sent event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ProblemReportSent(
+                types::ProblemReportSent { id },
+            )),
+        })
+    }
+
+    fn notify_account_expiry_warning(&self, warning: AccountExpiryWarning) {
+        log::debug!("Broadcasting account expiry warning event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::AccountExpiryWarning(
+                types::AccountExpiryWarning::from(warning),
+            )),
+        })
+    }
+
+    fn notify_idle_disconnect(&self, event: IdleDisconnect) {
+        log::debug!("Broadcasting idle disconnect event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::IdleDisconnect(
+                types::IdleDisconnect::from(event),
+            )),
+        })
+    }
+
+    fn notify_api_connectivity(&self, status: ApiConnectivity) {
+        log::debug!("Broadcasting API connectivity");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ApiConnectivity(
+                types::ApiConnectivity::from(status),
+            )),
+        })
+    }
 }
 
 impl ManagementInterfaceEventBroadcaster {
     fn notify(&self, value: types::DaemonEvent) {
-        let mut subscriptions = self.subscriptions.lock().unwrap();
-        subscriptions.retain(|tx| tx.send(Ok(value.clone())).is_ok());
+        self.subscriptions.lock().unwrap().notify(value);
     }
 }
 
 /// Converts [`mullvad_daemon::Error`] into a tonic status.
+/// Returns how often to send tunnel statistics when a client asks for them every `interval_ms`.
+fn tunnel_stats_interval(interval_ms: u32) -> Duration {
+    match interval_ms {
+        0 => DEFAULT_TUNNEL_STATS_INTERVAL,
+        interval_ms => Duration::from_millis(u64::from(interval_ms)).max(MIN_TUNNEL_STATS_INTERVAL),
+    }
+}
+
 fn map_daemon_error(error: crate::Error) -> Status {
     use crate::Error as DaemonError;
 
     match error {
         DaemonError::RestError(error) => map_rest_error(&error),
         DaemonError::SettingsError(error) => map_settings_error(error),
+        DaemonError::ProfileError(error) => map_profile_error(error),
         DaemonError::AlreadyLoggedIn => Status::already_exists(error.to_string()),
         DaemonError::LoginError(error) => map_device_error(&error),
         DaemonError::LogoutError(error) => map_device_error(&error),
@@ -1049,6 +2184,7 @@ fn map_daemon_error(error: crate::Error) -> Status {
         DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => {
             Status::unauthenticated(error.to_string())
         }
+        DaemonError::SavedAccountNotFound => Status::not_found(error.to_string()),
         DaemonError::CustomListExists => Status::with_details(
             Code::AlreadyExists,
             error.to_string(),
@@ -1059,10 +2195,62 @@ fn map_daemon_error(error: crate::Error) -> Status {
             error.to_string(),
             mullvad_management_interface::CUSTOM_LIST_LIST_NOT_FOUND_DETAILS.into(),
         ),
+        DaemonError::DnsLeakTestError(error) => map_dns_leak_test_error(error),
+        DaemonError::ConnectionCheckError(error) => map_connection_check_error(error),
+        DaemonError::VersionCheckError(error) => Status::unknown(error.display_chain()),
+        DaemonError::ApiEndpointOverrideUnavailable | DaemonError::MockTunnelUnavailable => {
+            Status::unimplemented(error.to_string())
+        }
+        // Include the reason, since it tells the user what is wrong with the shared string.
+        DaemonError::AccessMethodError(
+            access_method::Error::Import(ref reason) | access_method::Error::Export(ref reason),
+        ) => Status::invalid_argument(format!("{error}: {reason}")),
+        DaemonError::ImportSettings(ref reason) | DaemonError::ApplySettingsPatch(ref reason) => {
+            Status::invalid_argument(format!("{error}: {reason}"))
+        }
+        DaemonError::RelayOverrides(ref reason) => {
+            Status::invalid_argument(format!("{error}: {reason}"))
+        }
         error => Status::unknown(error.to_string()),
     }
 }
 
+/// Converts [`crate::profiles::Error`] into a tonic status.
+fn map_profile_error(error: crate::profiles::Error) -> Status {
+    use crate::profiles::Error;
+
+    match error {
+        Error::NotFound(_) => Status::not_found(error.to_string()),
+        Error::InvalidName | Error::TooManyProfiles => Status::invalid_argument(error.to_string()),
+        // Include the reason, since the profile may have been saved by a newer version of the app
+        Error::Load(_, ref reason) => Status::failed_precondition(format!("{error}: {reason}")),
+        Error::Serialize(_) | Error::Write(..) => Status::unknown(error.to_string()),
+    }
+}
+
+/// Converts [`crate::dns_leak::Error`] into a tonic status.
+fn map_dns_leak_test_error(error: crate::dns_leak::Error) -> Status {
+    use crate::dns_leak::Error;
+
+    match error {
+        Error::NotConnected => Status::failed_precondition(error.to_string()),
+        Error::Timeout => Status::deadline_exceeded(error.to_string()),
+        Error::FetchResolvers(error) => map_rest_error(&error),
+    }
+}
+
+/// Converts [`crate::connection_check::Error`] into a tonic status.
+fn map_connection_check_error(error: crate::connection_check::Error) -> Status {
+    use crate::connection_check::Error;
+
+    match error {
+        Error::NotConnected => Status::failed_precondition(error.to_string()),
+        // The endpoint not being reachable is a result of the check, not a sign that the daemon is
+        // unavailable, so this is not mapped like other REST errors.
+        Error::Request(_) => Status::unknown(error.display_chain()),
+    }
+}
+
 #[cfg(windows)]
 /// Converts [`talpid_core::split_tunnel::Error`] into a tonic status.
 fn map_split_tunnel_error(error: talpid_core::split_tunnel::Error) -> Status {
@@ -1105,6 +2293,58 @@ fn map_settings_error(error: settings::Error) -> Status {
         settings::Error::SerializeError(..) | settings::Error::ParseError(..) => {
             Status::new(Code::Internal, error.to_string())
         }
+        settings::Error::Ipv6DnsServerWithoutIpv6(..)
+        | settings::Error::MixedPlainAndTlsDnsServers
+        | settings::Error::KeepTunnelOnlyDnsServer(..)
+        | settings::Error::KeepDefaultDnsServers
+        | settings::Error::KeepDnsWhileDisconnectedNotSupported
+        | settings::Error::BypassRouteMatchesAll(..)
+        | settings::Error::BypassRouteContainsRelay(..)
+        | settings::Error::BypassRouteContainsDnsServer(..)
+        | settings::Error::SplitTunnelRootUser
+        | settings::Error::SplitTunnelRootGroup
+        | settings::Error::SplitTunnelModeNotSupported
+        | settings::Error::SplitTunnelDestinationsNotSupported
+        | settings::Error::SplitTunnelDestinationMatchesAll(..)
+        | settings::Error::SplitTunnelDestinationContainsRelay(..)
+        | settings::Error::SplitTunnelDestinationContainsDnsServer(..)
+        | settings::Error::SplitTunnelDestinationAllowedLan(..)
+        | settings::Error::SplitTunnelInterfacesNotSupported
+        | settings::Error::InvalidSplitTunnelInterface(..)
+        | settings::Error::SplitTunnelLoopbackInterface
+        | settings::Error::ExcludedAppsBlockLanNotSupported
+        | settings::Error::SplitTunnelLocalPortsNotSupported
+        | settings::Error::InvalidSplitTunnelLocalPort
+        | settings::Error::SplitTunnelLocalPortEphemeral(..)
+        | settings::Error::EmptyNetworkId
+        | settings::Error::DuplicateNetworkObfuscationProfile(..)
+        | settings::Error::DuplicateNetworkTrustRule(..)
+        | settings::Error::EmptyObfuscationPriority
+        | settings::Error::DuplicateObfuscationType(..)
+        | settings::Error::InvalidQuicServerName(..)
+        | settings::Error::InvalidQuicAlpn
+        | settings::Error::InvalidTlsServerName(..)
+        | settings::Error::InvalidUpstreamProxyPort
+        | settings::Error::InvalidUpstreamProxyCredentials
+        | settings::Error::InvalidLocalProxyPort
+        | settings::Error::InvalidLocalProxyCredentials
+        | settings::Error::RelativeHookPath(..)
+        | settings::Error::HookNotAFile(..)
+        | settings::Error::HookNotOwnedByRoot(..)
+        | settings::Error::HookWritableByOthers(..)
+        | settings::Error::HookNotAdminOnly(..)
+        | settings::Error::InsecureHookDirectory(..)
+        | settings::Error::InvalidRetryDelay
+        | settings::Error::InvalidRetryMultiplier
+        | settings::Error::InvalidRetryResetAfter
+        | settings::Error::InvalidAccountExpiryWarning
+        | settings::Error::TooManyAccountExpiryWarnings
+        | settings::Error::InvalidLogMaxSize
+        | settings::Error::TooManyLogArchives
+        | settings::Error::InvalidAutoDisconnectIdle => {
+            Status::new(Code::InvalidArgument, error.to_string())
+        }
+        settings::Error::ReadHookError(..) => Status::new(Code::NotFound, error.to_string()),
     }
 }
 
@@ -1118,6 +2358,20 @@ fn map_device_error(error: &device::Error) -> Status {
         }
         device::Error::InvalidVoucher => Status::new(Code::NotFound, INVALID_VOUCHER_MESSAGE),
         device::Error::UsedVoucher => Status::new(Code::ResourceExhausted, USED_VOUCHER_MESSAGE),
+        device::Error::RateLimited(retry_after) => {
+            let mut status = Status::with_details(
+                Code::ResourceExhausted,
+                error.to_string(),
+                mullvad_management_interface::RATE_LIMITED_DETAILS.into(),
+            );
+            if let Some(retry_after) = retry_after {
+                status.metadata_mut().insert(
+                    mullvad_management_interface::RETRY_AFTER_METADATA_KEY,
+                    retry_after.as_secs().into(),
+                );
+            }
+            status
+        }
         device::Error::DeviceIoError(ref _error) => {
             Status::new(Code::Unavailable, error.to_string())
         }
@@ -1144,3 +2398,140 @@ fn map_protobuf_type_err(err: types::FromProtobufTypeError) -> Status {
         types::FromProtobufTypeError::InvalidArgument(err) => Status::invalid_argument(err),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+
+    fn event(event: daemon_event::Event) -> types::DaemonEvent {
+        types::DaemonEvent { event: Some(event) }
+    }
+
+    fn settings_event() -> types::DaemonEvent {
+        event(daemon_event::Event::Settings(types::Settings::default()))
+    }
+
+    fn tunnel_state_event() -> types::DaemonEvent {
+        event(daemon_event::Event::TunnelState(
+            types::TunnelState::default(),
+        ))
+    }
+
+    fn version_event(suggested_upgrade: &str) -> types::DaemonEvent {
+        event(daemon_event::Event::VersionInfo(types::AppVersionInfo {
+            suggested_upgrade: suggested_upgrade.to_owned(),
+            ..Default::default()
+        }))
+    }
+
+    fn request(categories: &[EventCategory], replay: u32) -> types::EventsListenRequest {
+        types::EventsListenRequest {
+            categories: categories.iter().copied().map(i32::from).collect(),
+            replay,
+        }
+    }
+
+    /// Returns the events that have been sent to `rx` so far.
+    fn received(rx: &mut EventsListenerReceiver) -> Vec<types::DaemonEvent> {
+        let mut events = vec![];
+        while let Some(Some(event)) = rx.next().now_or_never() {
+            events.push(event.unwrap());
+        }
+        events
+    }
+
+    #[test]
+    fn test_event_filters() {
+        let mut subscriptions = Subscriptions::default();
+        let mut all = subscriptions.subscribe(request(&[], 0));
+        let mut settings = subscriptions.subscribe(request(&[EventCategory::Settings], 0));
+        let mut states_and_settings = subscriptions.subscribe(request(
+            &[EventCategory::TunnelState, EventCategory::Settings],
+            0,
+        ));
+        let mut unknown = subscriptions.subscribe(types::EventsListenRequest {
+            categories: vec![i32::MAX],
+            replay: 0,
+        });
+
+        subscriptions.notify(tunnel_state_event());
+        subscriptions.notify(settings_event());
+        subscriptions.notify(version_event("1"));
+
+        assert_eq!(
+            received(&mut all),
+            vec![tunnel_state_event(), settings_event(), version_event("1")]
+        );
+        assert_eq!(received(&mut settings), vec![settings_event()]);
+        assert_eq!(
+            received(&mut states_and_settings),
+            vec![tunnel_state_event(), settings_event()]
+        );
+        assert_eq!(received(&mut unknown), vec![]);
+    }
+
+    #[test]
+    fn test_closed_listeners_are_removed() {
+        let mut subscriptions = Subscriptions::default();
+        let all = subscriptions.subscribe(request(&[], 0));
+        let settings = subscriptions.subscribe(request(&[EventCategory::Settings], 0));
+        drop(all);
+        drop(settings);
+
+        subscriptions.notify(tunnel_state_event());
+        assert!(subscriptions.listeners.is_empty());
+    }
+
+    #[test]
+    fn test_event_replay() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.notify(version_event("1"));
+        subscriptions.notify(settings_event());
+        subscriptions.notify(version_event("2"));
+        subscriptions.notify(version_event("3"));
+
+        // The most recent events are replayed, oldest first, before any new events
+        let mut rx = subscriptions.subscribe(request(&[EventCategory::Other], 2));
+        subscriptions.notify(version_event("4"));
+        assert_eq!(
+            received(&mut rx),
+            vec![version_event("2"), version_event("3"), version_event("4")]
+        );
+
+        let mut rx = subscriptions.subscribe(request(&[], 10));
+        assert_eq!(
+            received(&mut rx),
+            vec![
+                version_event("1"),
+                settings_event(),
+                version_event("2"),
+                version_event("3"),
+                version_event("4"),
+            ]
+        );
+
+        let mut rx = subscriptions.subscribe(request(&[], 0));
+        assert_eq!(received(&mut rx), vec![]);
+    }
+
+    #[test]
+    fn test_event_replay_limit() {
+        let mut subscriptions = Subscriptions::default();
+        for version in 0..REPLAYED_EVENTS_LIMIT + 5 {
+            subscriptions.notify(version_event(&version.to_string()));
+        }
+
+        let mut rx = subscriptions.subscribe(request(&[], u32::MAX));
+        let events = received(&mut rx);
+        assert_eq!(events.len(), REPLAYED_EVENTS_LIMIT);
+        assert_eq!(events[0], version_event("5"));
+    }
+
+    #[test]
+    fn test_tunnel_stats_interval() {
+        assert_eq!(tunnel_stats_interval(0), DEFAULT_TUNNEL_STATS_INTERVAL);
+        assert_eq!(tunnel_stats_interval(1), MIN_TUNNEL_STATS_INTERVAL);
+        assert_eq!(tunnel_stats_interval(2500), Duration::from_millis(2500));
+    }
+}
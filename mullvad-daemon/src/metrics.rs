@@ -0,0 +1,266 @@
+//! Counters and gauges describing the health of the daemon, returned by the `get_metrics` RPC.
+
+use mullvad_types::metrics::Metric;
+use std::time::{Duration, Instant};
+use talpid_types::tunnel::{ErrorCode, TunnelStateTransition};
+
+/// Names of the tunnel states, as used in the `state` label.
+const STATES: [&str; 5] = [
+    "disconnected",
+    "connecting",
+    "connected",
+    "disconnecting",
+    "error",
+];
+
+/// Values that are read from other parts of the daemon when the metrics are requested.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Hostname of the relay that is used while connecting or connected.
+    pub relay: Option<String>,
+    pub api_available: bool,
+    /// Age of the WireGuard key of the device, if logged in.
+    pub key_age: Option<Duration>,
+}
+
+/// Counts tunnel state transitions since the daemon started.
+#[derive(Debug, Clone)]
+pub struct MetricsRegistry {
+    started: Instant,
+    state: &'static str,
+    state_since: Instant,
+    /// Number of the current connection attempt, used to tell a new attempt from a change of
+    /// phase within the same attempt.
+    attempt: Option<u32>,
+    connection_attempts: u64,
+    reconnects: u64,
+    connects: u64,
+    errors: u64,
+    last_error_code: Option<ErrorCode>,
+}
+
+impl MetricsRegistry {
+    pub fn new(now: Instant) -> Self {
+        MetricsRegistry {
+            started: now,
+            state: "disconnected",
+            state_since: now,
+            attempt: None,
+            connection_attempts: 0,
+            reconnects: 0,
+            connects: 0,
+            errors: 0,
+            last_error_code: None,
+        }
+    }
+
+    pub fn handle_state_transition(&mut self, transition: &TunnelStateTransition, now: Instant) {
+        let state = match transition {
+            TunnelStateTransition::Disconnected => "disconnected",
+            TunnelStateTransition::Connecting(_, _, attempt) => {
+                let is_new_attempt =
+                    self.state != "connecting" || self.attempt != Some(attempt.attempt);
+                if is_new_attempt {
+                    self.connection_attempts += 1;
+                    // Every attempt that does not follow a disconnected tunnel replaces a tunnel
+                    // that failed or was torn down
+                    if self.state != "disconnected" {
+                        self.reconnects += 1;
+                    }
+                }
+                self.attempt = Some(attempt.attempt);
+                "connecting"
+            }
+            TunnelStateTransition::Connected(..) => {
+                self.connects += 1;
+                "connected"
+            }
+            TunnelStateTransition::Disconnecting(_) => "disconnecting",
+            TunnelStateTransition::Error(error_state) => {
+                self.errors += 1;
+                self.last_error_code = Some(error_state.cause().error_code());
+                "error"
+            }
+        };
+        if state != "connecting" {
+            self.attempt = None;
+        }
+        if state != self.state {
+            self.state = state;
+            self.state_since = now;
+        }
+    }
+
+    /// Returns the metrics at `now`, with metrics of the same name next to each other.
+    pub fn metrics(&self, now: Instant, status: &Status) -> Vec<Metric> {
+        let mut metrics = vec![Metric::gauge(
+            "mullvad_daemon_uptime_seconds",
+            now.saturating_duration_since(self.started).as_secs_f64(),
+        )];
+        metrics.extend(STATES.iter().map(|state| {
+            Metric::gauge("mullvad_tunnel_state", f64::from(*state == self.state))
+                .with_label("state", *state)
+        }));
+        metrics.extend([
+            Metric::gauge(
+                "mullvad_tunnel_state_seconds",
+                now.saturating_duration_since(self.state_since)
+                    .as_secs_f64(),
+            ),
+            Metric::counter(
+                "mullvad_connection_attempts_total",
+                self.connection_attempts,
+            ),
+            Metric::counter("mullvad_reconnects_total", self.reconnects),
+            Metric::counter("mullvad_connects_total", self.connects),
+            Metric::counter("mullvad_errors_total", self.errors),
+            // Zero if the daemon has not entered the error state
+            Metric::gauge(
+                "mullvad_last_error_code",
+                self.last_error_code
+                    .map(|code| f64::from(code.code()))
+                    .unwrap_or(0.0),
+            ),
+            Metric::gauge("mullvad_api_available", f64::from(status.api_available)),
+        ]);
+        if let Some(relay) = &status.relay {
+            metrics.push(Metric::gauge("mullvad_relay_info", 1.0).with_label("hostname", relay));
+        }
+        if let Some(key_age) = status.key_age {
+            metrics.push(Metric::gauge(
+                "mullvad_wireguard_key_age_seconds",
+                key_age.as_secs_f64(),
+            ));
+        }
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::tunnel_endpoint;
+    use talpid_types::{
+        net::TunnelType,
+        tunnel::{
+            ActionAfterDisconnect, ConnectingPhase, ConnectionAttempt, ErrorState, ErrorStateCause,
+        },
+    };
+
+    fn connecting(attempt: u32, phase: ConnectingPhase) -> TunnelStateTransition {
+        TunnelStateTransition::Connecting(
+            tunnel_endpoint(TunnelType::Wireguard),
+            phase,
+            ConnectionAttempt {
+                attempt,
+                retry_timeout: None,
+            },
+        )
+    }
+
+    fn value(metrics: &[Metric], series: &str) -> f64 {
+        metrics
+            .iter()
+            .find(|metric| metric.series() == series)
+            .unwrap_or_else(|| panic!("missing metric {series}"))
+            .value
+    }
+
+    #[test]
+    fn test_scripted_sequence() {
+        let start = Instant::now();
+        let mut registry = MetricsRegistry::new(start);
+        let status = Status::default();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let script = [
+            (1, connecting(1, ConnectingPhase::EstablishingTunnel)),
+            // A change of phase is not a new attempt
+            (2, connecting(1, ConnectingPhase::WaitingForNetwork)),
+            (3, connecting(2, ConnectingPhase::EstablishingTunnel)),
+            (
+                4,
+                TunnelStateTransition::Connected(
+                    tunnel_endpoint(TunnelType::Wireguard),
+                    Default::default(),
+                ),
+            ),
+            (
+                10,
+                TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Nothing),
+            ),
+            (11, TunnelStateTransition::Disconnected),
+        ];
+        for (secs, transition) in &script {
+            registry.handle_state_transition(transition, at(*secs));
+        }
+
+        let metrics = registry.metrics(at(15), &status);
+        assert_eq!(value(&metrics, "mullvad_daemon_uptime_seconds"), 15.0);
+        assert_eq!(
+            value(&metrics, "mullvad_tunnel_state{state=\"disconnected\"}"),
+            1.0
+        );
+        assert_eq!(
+            value(&metrics, "mullvad_tunnel_state{state=\"connected\"}"),
+            0.0
+        );
+        assert_eq!(value(&metrics, "mullvad_tunnel_state_seconds"), 4.0);
+        assert_eq!(value(&metrics, "mullvad_connection_attempts_total"), 2.0);
+        assert_eq!(value(&metrics, "mullvad_reconnects_total"), 1.0);
+        assert_eq!(value(&metrics, "mullvad_connects_total"), 1.0);
+        assert_eq!(value(&metrics, "mullvad_errors_total"), 0.0);
+        assert_eq!(value(&metrics, "mullvad_last_error_code"), 0.0);
+
+        let error = ErrorState::new(ErrorStateCause::IsOffline, None);
+        let script = [
+            (20, connecting(1, ConnectingPhase::EstablishingTunnel)),
+            (21, TunnelStateTransition::Error(error)),
+            (30, connecting(1, ConnectingPhase::EstablishingTunnel)),
+        ];
+        for (secs, transition) in &script {
+            registry.handle_state_transition(transition, at(*secs));
+        }
+
+        let metrics = registry.metrics(at(35), &status);
+        assert_eq!(
+            value(&metrics, "mullvad_tunnel_state{state=\"connecting\"}"),
+            1.0
+        );
+        assert_eq!(value(&metrics, "mullvad_tunnel_state_seconds"), 5.0);
+        assert_eq!(value(&metrics, "mullvad_connection_attempts_total"), 4.0);
+        assert_eq!(value(&metrics, "mullvad_reconnects_total"), 2.0);
+        assert_eq!(value(&metrics, "mullvad_errors_total"), 1.0);
+        assert_eq!(
+            value(&metrics, "mullvad_last_error_code"),
+            f64::from(ErrorCode::Offline.code())
+        );
+    }
+
+    #[test]
+    fn test_status_metrics() {
+        let now = Instant::now();
+        let registry = MetricsRegistry::new(now);
+        let status = Status {
+            relay: Some("se-got-wg-001".to_owned()),
+            api_available: true,
+            key_age: Some(Duration::from_secs(3600)),
+        };
+        let metrics = registry.metrics(now, &status);
+        assert_eq!(value(&metrics, "mullvad_api_available"), 1.0);
+        assert_eq!(
+            value(&metrics, "mullvad_relay_info{hostname=\"se-got-wg-001\"}"),
+            1.0
+        );
+        assert_eq!(value(&metrics, "mullvad_wireguard_key_age_seconds"), 3600.0);
+
+        // Metrics that are unknown are left out
+        let metrics = registry.metrics(now, &Status::default());
+        assert!(!metrics
+            .iter()
+            .any(|metric| metric.name == "mullvad_relay_info"));
+        assert!(!metrics
+            .iter()
+            .any(|metric| metric.name == "mullvad_wireguard_key_age_seconds"));
+    }
+}
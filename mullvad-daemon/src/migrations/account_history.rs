@@ -59,7 +59,7 @@ pub async fn migrate_formats(settings_dir: &Path, settings: &mut serde_json::Val
         .await
         .map_err(Error::ReadHistory)?;
 
-    if is_format_v3(&bytes) {
+    if is_format_v3(&bytes) || is_format_v4(&bytes) {
         return Ok(());
     }
     write_format_v3(file, migrate_formats_inner(&bytes, settings)?).await
@@ -90,6 +90,13 @@ fn is_format_v3(bytes: &[u8]) -> bool {
     }
 }
 
+/// The current format, which lists the saved accounts. It is read by `AccountHistory`.
+fn is_format_v4(bytes: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(bytes)
+        .map(|history| history.get("saved_accounts").is_some())
+        .unwrap_or(false)
+}
+
 async fn write_format_v3(mut file: File, token: Option<AccountToken>) -> Result<()> {
     file.set_len(0).await.map_err(Error::WriteHistory)?;
     file.seek(io::SeekFrom::Start(0))
@@ -168,6 +175,8 @@ mod test {
 ]"#;
     pub const ACCOUNT_HISTORY_V2_EMPTY: &str = r#"[]"#;
     pub const ACCOUNT_HISTORY_V3: &str = r#"123456"#;
+    pub const ACCOUNT_HISTORY_V4: &str =
+        r#"{"saved_accounts": [{"account_token": "123456", "label": null, "device_name": null}]}"#;
 
     pub const OLD_SETTINGS: &str = r#"
 {
@@ -326,11 +335,20 @@ mod test {
 "#;
 
     // Test whether the current format is parsed correctly
+    #[test]
+    fn test_v4() {
+        assert!(!super::is_format_v4(ACCOUNT_HISTORY_V1.as_bytes()));
+        assert!(!super::is_format_v4(ACCOUNT_HISTORY_V2.as_bytes()));
+        assert!(!super::is_format_v4(ACCOUNT_HISTORY_V3.as_bytes()));
+        assert!(super::is_format_v4(ACCOUNT_HISTORY_V4.as_bytes()));
+    }
+
     #[test]
     fn test_v3() {
         assert!(!super::is_format_v3(ACCOUNT_HISTORY_V1.as_bytes()));
         assert!(!super::is_format_v3(ACCOUNT_HISTORY_V2.as_bytes()));
         assert!(super::is_format_v3(ACCOUNT_HISTORY_V3.as_bytes()));
+        assert!(!super::is_format_v3(ACCOUNT_HISTORY_V4.as_bytes()));
     }
 
     #[test]
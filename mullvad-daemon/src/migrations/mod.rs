@@ -173,6 +173,24 @@ pub async fn migrate_all(cache_dir: &Path, settings_dir: &Path) -> Result<Option
     Ok(migration_data)
 }
 
+/// Migrates settings that were exported by another daemon to the current format. Unlike
+/// [`migrate_all`], nothing is read from or written to disk, so data that older versions stored in
+/// the settings, such as the account number, is discarded.
+pub fn migrate_exported(settings: &mut serde_json::Value) -> Result<()> {
+    if !settings.is_object() {
+        return Err(Error::InvalidSettingsContent);
+    }
+
+    v1::migrate(settings)?;
+    v2::migrate(settings)?;
+    v3::migrate(settings)?;
+    v4::migrate(settings)?;
+    let _ = v5::migrate(settings)?;
+    v6::migrate(settings)?;
+
+    Ok(())
+}
+
 pub(crate) fn migrate_device(
     migration_data: MigrationData,
     rest_handle: mullvad_api::rest::MullvadRestHandle,
@@ -0,0 +1,195 @@
+//! Identifies the network of the default route, so that network obfuscation profiles can be
+//! matched against it.
+
+use mullvad_types::network_profiles::CurrentNetwork;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use talpid_types::ErrorExt;
+
+/// Returns the network that `interface` is connected to. The SSID is obtained from NetworkManager
+/// on Linux, if it is running, from `networksetup` on macOS and from the WLAN service on Windows.
+pub async fn identify(interface: Option<String>) -> CurrentNetwork {
+    let ssid = match &interface {
+        Some(interface) => wifi_ssid(interface.clone()).await,
+        None => None,
+    };
+    CurrentNetwork { interface, ssid }
+}
+
+#[cfg(target_os = "linux")]
+async fn wifi_ssid(interface: String) -> Option<String> {
+    use talpid_dbus::network_manager::NetworkManager;
+
+    let result = tokio::task::spawn_blocking(move || {
+        NetworkManager::new().and_then(|manager| manager.get_wifi_ssid(&interface))
+    })
+    .await;
+    match result {
+        Ok(Ok(ssid)) => ssid,
+        Ok(Err(error)) => {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Failed to look up the SSID of the network")
+            );
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+async fn wifi_ssid(interface: String) -> Option<String> {
+    let output = tokio::process::Command::new("/usr/sbin/networksetup")
+        .args(["-getairportnetwork", &interface])
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => {
+            parse_airport_network(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(output) => {
+            log::debug!(
+                "Failed to look up the SSID of the network: networksetup exited with {}",
+                output.status
+            );
+            None
+        }
+        Err(error) => {
+            log::debug!("Failed to run networksetup: {error}");
+            None
+        }
+    }
+}
+
+/// Returns the SSID in the output of `networksetup -getairportnetwork <interface>`. The output is
+/// `Current Wi-Fi Network: <SSID>` if the interface is connected to a Wi-Fi network.
+#[cfg(any(target_os = "macos", test))]
+fn parse_airport_network(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("Current Wi-Fi Network: "))
+        .map(|ssid| ssid.to_owned())
+}
+
+#[cfg(target_os = "windows")]
+async fn wifi_ssid(interface: String) -> Option<String> {
+    let result = tokio::task::spawn_blocking(move || wlan::current_ssid(&interface)).await;
+    match result {
+        Ok(Ok(ssid)) => ssid,
+        Ok(Err(error)) => {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Failed to look up the SSID of the network")
+            );
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod wlan {
+    use std::{ffi::c_void, io, ptr};
+    use windows_sys::Win32::{
+        Foundation::{ERROR_INVALID_STATE, ERROR_NOT_FOUND, ERROR_SUCCESS, HANDLE},
+        NetworkManagement::WiFi::{
+            wlan_intf_opcode_current_connection, WlanCloseHandle, WlanFreeMemory, WlanOpenHandle,
+            WlanQueryInterface, WLAN_CONNECTION_ATTRIBUTES,
+        },
+    };
+
+    /// Version of the WLAN API that was introduced in Windows Vista.
+    const WLAN_API_VERSION: u32 = 2;
+
+    /// Returns the SSID of the network that the interface with the alias `alias` is connected to,
+    /// or `None` if it is not a wireless interface that is connected.
+    pub fn current_ssid(alias: &str) -> io::Result<Option<String>> {
+        let luid = talpid_windows_net::luid_from_alias(alias)?;
+        let guid = talpid_windows_net::guid_from_luid(&luid)?;
+
+        let client = Client::open()?;
+        let mut size = 0;
+        let mut data: *mut c_void = ptr::null_mut();
+        let status = unsafe {
+            WlanQueryInterface(
+                client.0,
+                &guid,
+                wlan_intf_opcode_current_connection,
+                ptr::null(),
+                &mut size,
+                &mut data,
+                ptr::null_mut(),
+            )
+        };
+        match status {
+            ERROR_SUCCESS => (),
+            // The interface is not connected, or is not a wireless interface
+            ERROR_INVALID_STATE | ERROR_NOT_FOUND => return Ok(None),
+            status => return Err(io::Error::from_raw_os_error(status as i32)),
+        }
+
+        // SAFETY: The data of this opcode is a `WLAN_CONNECTION_ATTRIBUTES`
+        let ssid = unsafe {
+            let attributes = &*(data as *const WLAN_CONNECTION_ATTRIBUTES);
+            let ssid = &attributes.wlanAssociationAttributes.dot11Ssid;
+            let len = (ssid.uSSIDLength as usize).min(ssid.ucSSID.len());
+            String::from_utf8_lossy(&ssid.ucSSID[..len]).into_owned()
+        };
+        unsafe { WlanFreeMemory(data) };
+
+        Ok(Some(ssid).filter(|ssid| !ssid.is_empty()))
+    }
+
+    /// Handle to the WLAN service, which is closed when dropped.
+    struct Client(HANDLE);
+
+    impl Client {
+        fn open() -> io::Result<Self> {
+            let mut negotiated_version = 0;
+            let mut handle = 0;
+            let status = unsafe {
+                WlanOpenHandle(
+                    WLAN_API_VERSION,
+                    ptr::null(),
+                    &mut negotiated_version,
+                    &mut handle,
+                )
+            };
+            if status != ERROR_SUCCESS {
+                return Err(io::Error::from_raw_os_error(status as i32));
+            }
+            Ok(Client(handle))
+        }
+    }
+
+    impl Drop for Client {
+        fn drop(&mut self) {
+            unsafe { WlanCloseHandle(self.0, ptr::null()) };
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+async fn wifi_ssid(_interface: String) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_airport_network() {
+        assert_eq!(
+            parse_airport_network("Current Wi-Fi Network: Home Network\n"),
+            Some("Home Network".to_owned())
+        );
+        assert_eq!(
+            parse_airport_network("You are not associated with an AirPort network.\n"),
+            None
+        );
+        assert_eq!(
+            parse_airport_network("en0 is not a Wi-Fi interface.\n"),
+            None
+        );
+    }
+}
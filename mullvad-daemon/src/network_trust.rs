@@ -0,0 +1,162 @@
+//! Decides when the network trust rules may change the target state. The rules are applied when
+//! the host joins another network, but never override a target state that the user has chosen
+//! since then.
+
+use mullvad_types::{
+    network_profiles::{CurrentNetwork, NetworkId},
+    network_trust::{NetworkTrustSettings, TrustPolicy},
+    states::TargetState,
+};
+
+#[derive(Debug, Default)]
+pub struct NetworkTrustArbiter {
+    /// The last network that the host was known to be connected to.
+    network: Option<NetworkId>,
+    /// Whether the user has set the target state since `network` was joined.
+    overridden: bool,
+}
+
+impl NetworkTrustArbiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the target state that the rules select for `network`, or `None` if the target
+    /// state should be left alone.
+    ///
+    /// Losing connectivity does not count as leaving the network, so that briefly dropping off a
+    /// network does not undo a choice that the user made on it.
+    pub fn network_changed(
+        &mut self,
+        settings: &NetworkTrustSettings,
+        network: &CurrentNetwork,
+    ) -> Option<TargetState> {
+        let id = network.id()?;
+        if self.network.as_ref() == Some(&id) {
+            return None;
+        }
+        self.network = Some(id);
+        self.overridden = false;
+        target_state(settings, network)
+    }
+
+    /// Returns the target state that the rules select for `network` after they have been
+    /// changed, unless the user has set the target state since the network was joined.
+    pub fn rules_changed(
+        &self,
+        settings: &NetworkTrustSettings,
+        network: &CurrentNetwork,
+    ) -> Option<TargetState> {
+        if self.overridden {
+            return None;
+        }
+        target_state(settings, network)
+    }
+
+    /// Records that the user has set the target state, which the rules must not override until
+    /// another network is joined.
+    pub fn user_set_target_state(&mut self) {
+        self.overridden = true;
+    }
+}
+
+fn target_state(settings: &NetworkTrustSettings, network: &CurrentNetwork) -> Option<TargetState> {
+    match settings.policy(network)? {
+        TrustPolicy::Untrusted => Some(TargetState::Secured),
+        TrustPolicy::Trusted if settings.disconnect_on_trusted => Some(TargetState::Unsecured),
+        TrustPolicy::Trusted => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(disconnect_on_trusted: bool) -> NetworkTrustSettings {
+        NetworkTrustSettings {
+            rules: vec![(NetworkId::Ssid("HomeNet".to_owned()), TrustPolicy::Trusted)],
+            default_policy: Some(TrustPolicy::Untrusted),
+            disconnect_on_trusted,
+            lockdown_on_trusted: true,
+        }
+    }
+
+    fn wifi(ssid: &str) -> CurrentNetwork {
+        CurrentNetwork {
+            interface: Some("wlan0".to_owned()),
+            ssid: Some(ssid.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_apply_rules_on_network_change() {
+        let settings = settings(true);
+        let mut arbiter = NetworkTrustArbiter::new();
+
+        assert_eq!(
+            arbiter.network_changed(&settings, &wifi("Cafe")),
+            Some(TargetState::Secured)
+        );
+        assert_eq!(
+            arbiter.network_changed(&settings, &wifi("HomeNet")),
+            Some(TargetState::Unsecured)
+        );
+        // Being identified again is not a change
+        assert_eq!(arbiter.network_changed(&settings, &wifi("HomeNet")), None);
+
+        // Trusted networks only prevent connecting unless disconnecting is enabled
+        let mut arbiter = NetworkTrustArbiter::new();
+        assert_eq!(
+            arbiter.network_changed(&self::settings(false), &wifi("HomeNet")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_user_command_after_change() {
+        let settings = settings(true);
+        let mut arbiter = NetworkTrustArbiter::new();
+
+        assert_eq!(
+            arbiter.network_changed(&settings, &wifi("Cafe")),
+            Some(TargetState::Secured)
+        );
+        arbiter.user_set_target_state();
+
+        // Neither editing the rules nor a brief loss of connectivity overrides the user
+        assert_eq!(arbiter.rules_changed(&settings, &wifi("Cafe")), None);
+        assert_eq!(
+            arbiter.network_changed(&settings, &CurrentNetwork::default()),
+            None
+        );
+        assert_eq!(arbiter.network_changed(&settings, &wifi("Cafe")), None);
+
+        // Joining another network applies the rules again
+        assert_eq!(
+            arbiter.network_changed(&settings, &wifi("HomeNet")),
+            Some(TargetState::Unsecured)
+        );
+        assert_eq!(
+            arbiter.rules_changed(&self::settings(false), &wifi("HomeNet")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rules_changed() {
+        let mut settings = settings(true);
+        let mut arbiter = NetworkTrustArbiter::new();
+        assert_eq!(
+            arbiter.network_changed(&settings, &wifi("Office")),
+            Some(TargetState::Secured)
+        );
+
+        settings
+            .rules
+            .push((NetworkId::Ssid("Office".to_owned()), TrustPolicy::Trusted));
+        assert_eq!(
+            arbiter.rules_changed(&settings, &wifi("Office")),
+            Some(TargetState::Unsecured)
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use mullvad_types::{
+    network_profiles::{CurrentNetwork, NetworkId},
+    obfuscation_scores::{ObfuscationScores, PortBucket},
+    relay_constraints::ObfuscationType,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use talpid_types::{
+    net::{self, TunnelEndpoint, TunnelType},
+    tunnel::{ConnectingPhase, TunnelStateTransition},
+    ErrorExt,
+};
+use tokio::{fs, io};
+
+const OBFUSCATION_SCORES_FILE: &str = "obfuscation-scores.json";
+
+/// A WireGuard connection attempt whose outcome has not been recorded yet.
+struct Attempt {
+    network: NetworkId,
+    obfuscation_type: ObfuscationType,
+    port: PortBucket,
+}
+
+/// Records the outcomes of connection attempts in [`ObfuscationScores`], which are persisted to
+/// the cache directory.
+pub struct ObfuscationScoreStore {
+    scores: ObfuscationScores,
+    cache_path: PathBuf,
+    pending_attempt: Option<Attempt>,
+}
+
+impl ObfuscationScoreStore {
+    /// Loads the scores from the cache directory. If there are none, or if they cannot be read,
+    /// the store starts out empty.
+    pub async fn load(cache_dir: &Path) -> Self {
+        let cache_path = cache_dir.join(OBFUSCATION_SCORES_FILE);
+        let scores = match fs::read_to_string(&cache_path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse cached obfuscation scores")
+                );
+                ObfuscationScores::default()
+            }),
+            Err(error) => {
+                if error.kind() == io::ErrorKind::NotFound {
+                    log::debug!("No cached obfuscation scores to load");
+                } else {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read cached obfuscation scores")
+                    );
+                }
+                ObfuscationScores::default()
+            }
+        };
+        ObfuscationScoreStore {
+            scores,
+            cache_path,
+            pending_attempt: None,
+        }
+    }
+
+    pub fn scores(&self) -> &ObfuscationScores {
+        &self.scores
+    }
+
+    /// Records the outcome of the pending connection attempt, if the transition ends it. An
+    /// attempt fails if the tunnel enters the error state or starts another attempt before it
+    /// connects. Attempts that are cancelled by disconnecting are not recorded.
+    pub async fn handle_state_transition(
+        &mut self,
+        transition: &TunnelStateTransition,
+        network: &CurrentNetwork,
+    ) {
+        match transition {
+            TunnelStateTransition::Connecting(endpoint, phase, _) => {
+                self.record(false).await;
+                // The attempt does not start until the network is usable
+                if *phase == ConnectingPhase::EstablishingTunnel {
+                    self.pending_attempt = attempt(endpoint, network);
+                }
+            }
+            TunnelStateTransition::Connected(..) => self.record(true).await,
+            TunnelStateTransition::Error(_) => self.record(false).await,
+            TunnelStateTransition::Disconnecting(_) | TunnelStateTransition::Disconnected => {
+                self.pending_attempt = None;
+            }
+        }
+    }
+
+    async fn record(&mut self, success: bool) {
+        let attempt = match self.pending_attempt.take() {
+            Some(attempt) => attempt,
+            None => return,
+        };
+        log::debug!(
+            "Recording {} of {} on {} using {}",
+            if success { "success" } else { "failure" },
+            attempt.obfuscation_type,
+            attempt.network,
+            attempt.port,
+        );
+        self.scores.record(
+            &attempt.network,
+            attempt.obfuscation_type,
+            attempt.port,
+            success,
+            SystemTime::now(),
+        );
+        self.save().await;
+    }
+
+    async fn save(&self) {
+        log::trace!("Saving obfuscation scores to {}", self.cache_path.display());
+        match serde_json::to_string(&self.scores) {
+            Ok(data) => {
+                if let Err(error) = fs::write(&self.cache_path, data).await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to write obfuscation scores")
+                    );
+                }
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to serialize obfuscation scores")
+                )
+            }
+        }
+    }
+}
+
+/// Returns the attempt to connect to `endpoint`, if it is one that scores are kept for. Only the
+/// obfuscation of WireGuard tunnels is scored.
+fn attempt(endpoint: &TunnelEndpoint, network: &CurrentNetwork) -> Option<Attempt> {
+    if endpoint.tunnel_type != TunnelType::Wireguard {
+        return None;
+    }
+    let network = network.id()?;
+    let (obfuscation_type, port) = match &endpoint.obfuscation {
+        Some(obfuscation) => {
+            let obfuscation_type = match obfuscation.obfuscation_type {
+                net::ObfuscationType::Udp2Tcp => ObfuscationType::Udp2Tcp,
+                net::ObfuscationType::Quic => ObfuscationType::Quic,
+                net::ObfuscationType::Tls => ObfuscationType::Tls,
+            };
+            (obfuscation_type, obfuscation.endpoint.address.port())
+        }
+        None => {
+            let entry = endpoint.entry_endpoint.unwrap_or(endpoint.endpoint);
+            (ObfuscationType::None, entry.address.port())
+        }
+    };
+    Some(Attempt {
+        network,
+        obfuscation_type,
+        port: PortBucket::from(port),
+    })
+}
@@ -0,0 +1,286 @@
+//! Named copies of the settings that can be switched between at runtime. Profiles are stored next
+//! to the settings file, in the same versioned format, and are migrated along with it when the
+//! daemon starts, so that profiles saved by older versions of the app keep working. The account
+//! and device are not part of the settings, and so never part of a profile.
+
+use crate::settings_transfer;
+use mullvad_types::settings::Settings;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use talpid_types::ErrorExt;
+use tokio::{
+    fs,
+    io::{self, AsyncWriteExt},
+};
+
+const PROFILES_FILE: &str = "settings-profiles.json";
+
+/// Bounds of the profiles. Names are shown in lists, and every profile is a full copy of the
+/// settings.
+const MAX_PROFILE_NAME_LEN: usize = 64;
+const MAX_PROFILES: usize = 32;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Profile names must be between 1 and 64 characters long")]
+    InvalidName,
+
+    #[error(display = "There is no profile named {}", _0)]
+    NotFound(String),
+
+    #[error(display = "At most 32 profiles can be saved")]
+    TooManyProfiles,
+
+    #[error(display = "Unable to serialize the profiles")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to write the profiles to {}", _0)]
+    Write(String, #[error(source)] io::Error),
+
+    #[error(display = "The profile {} cannot be read", _0)]
+    Load(String, #[error(source)] settings_transfer::Error),
+}
+
+/// Persists the profiles to the settings directory.
+pub struct ProfileStore {
+    /// The settings of each profile, in the format of the settings file.
+    profiles: BTreeMap<String, Value>,
+    path: PathBuf,
+}
+
+impl ProfileStore {
+    /// Loads the profiles from the settings directory and migrates them to the current settings
+    /// version. If they cannot be read, the store starts out empty.
+    pub async fn load(settings_dir: &Path) -> Self {
+        let path = settings_dir.join(PROFILES_FILE);
+        let profiles = match fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse settings profiles")
+                );
+                BTreeMap::new()
+            }),
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read settings profiles")
+                    );
+                }
+                BTreeMap::new()
+            }
+        };
+        let mut store = ProfileStore { profiles, path };
+        if store.migrate() {
+            if let Err(error) = store.persist(&store.profiles).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to save migrated settings profiles")
+                );
+            }
+        }
+        store
+    }
+
+    /// Migrates the profiles that were saved by older versions. Profiles that cannot be migrated
+    /// are kept as they are, and fail when they are applied. Returns whether any profile changed.
+    fn migrate(&mut self) -> bool {
+        let mut changed = false;
+        for (name, settings) in &mut self.profiles {
+            let mut migrated = settings.clone();
+            match settings_transfer::migrate(&mut migrated) {
+                Ok(()) if migrated != *settings => {
+                    log::info!("Migrated settings profile {name}");
+                    *settings = migrated;
+                    changed = true;
+                }
+                Ok(()) => (),
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to migrate settings profile {name}"
+                    ))
+                ),
+            }
+        }
+        changed
+    }
+
+    /// Returns the names of the profiles in alphabetical order.
+    pub fn names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    /// Returns the settings of the profile named `name`.
+    pub fn get(&self, name: &str) -> Result<Settings, Error> {
+        let name = name.trim();
+        let mut settings = self
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(name.to_owned()))?;
+        let load_error = |error| Error::Load(name.to_owned(), error);
+        settings_transfer::migrate(&mut settings).map_err(load_error)?;
+        serde_json::from_value(settings)
+            .map_err(|error| load_error(settings_transfer::Error::Deserialize(error)))
+    }
+
+    /// Saves `settings` as the profile named `name`, replacing any profile with the same name.
+    pub async fn save(&mut self, name: &str, settings: &Settings) -> Result<(), Error> {
+        let name = validate_name(name)?;
+        if !self.profiles.contains_key(name) && self.profiles.len() >= MAX_PROFILES {
+            return Err(Error::TooManyProfiles);
+        }
+        let mut profiles = self.profiles.clone();
+        profiles.insert(
+            name.to_owned(),
+            serde_json::to_value(settings).map_err(Error::Serialize)?,
+        );
+        self.persist(&profiles).await?;
+        self.profiles = profiles;
+        Ok(())
+    }
+
+    /// Removes the profile named `name`.
+    pub async fn remove(&mut self, name: &str) -> Result<(), Error> {
+        let name = name.trim();
+        if !self.profiles.contains_key(name) {
+            return Err(Error::NotFound(name.to_owned()));
+        }
+        let mut profiles = self.profiles.clone();
+        profiles.remove(name);
+        self.persist(&profiles).await?;
+        self.profiles = profiles;
+        Ok(())
+    }
+
+    async fn persist(&self, profiles: &BTreeMap<String, Value>) -> Result<(), Error> {
+        log::debug!("Writing settings profiles to {}", self.path.display());
+        let write_error = |error| Error::Write(self.path.display().to_string(), error);
+        let buffer = serde_json::to_string_pretty(profiles).map_err(Error::Serialize)?;
+        let mut file = mullvad_fs::AtomicFile::new(&self.path)
+            .await
+            .map_err(write_error)?;
+        file.write_all(buffer.as_bytes())
+            .await
+            .map_err(write_error)?;
+        file.finalize().await.map_err(write_error)
+    }
+}
+
+/// Returns `name` without surrounding whitespace, or an error if it is not a valid profile name.
+fn validate_name(name: &str) -> Result<&str, Error> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_PROFILE_NAME_LEN {
+        return Err(Error::InvalidName);
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::{
+        relay_constraints::{
+            Constraint, GeographicLocationConstraint, LocationConstraint, RelaySettings,
+        },
+        settings::CURRENT_SETTINGS_VERSION,
+    };
+
+    #[tokio::test]
+    async fn test_save_and_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = ProfileStore::load(dir.path()).await;
+        assert!(store.names().is_empty());
+
+        let mut work = Settings {
+            allow_lan: true,
+            ..Default::default()
+        };
+        work.tunnel_options.dns_options.default_options.block_ads = true;
+        store.save(" work ", &work).await.unwrap();
+        // The default settings contain randomly generated access method IDs
+        let home = Settings::default();
+        store.save("home", &home).await.unwrap();
+
+        // The profiles are read back from disk
+        let mut store = ProfileStore::load(dir.path()).await;
+        assert_eq!(store.names(), ["home", "work"]);
+        assert_eq!(store.get("work").unwrap(), work);
+        assert_eq!(store.get("home").unwrap(), home);
+
+        store.remove("home").await.unwrap();
+        assert!(matches!(store.get("home"), Err(Error::NotFound(_))));
+        assert!(matches!(
+            store.remove("home").await,
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(ProfileStore::load(dir.path()).await.names(), ["work"]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = ProfileStore::load(dir.path()).await;
+        let settings = Settings::default();
+        for name in ["", "   ", &"x".repeat(MAX_PROFILE_NAME_LEN + 1)] {
+            assert!(matches!(
+                store.save(name, &settings).await,
+                Err(Error::InvalidName)
+            ));
+        }
+        assert!(store.names().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_old_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        // Saved by a version 6 daemon, which stored locations without the custom list variant
+        let profiles = r#"{
+          "travel": {
+            "relay_settings": {
+              "normal": {
+                "location": { "only": { "city": ["se", "got"] } },
+                "tunnel_protocol": "any",
+                "wireguard_constraints": { "port": "any" },
+                "openvpn_constraints": { "port": "any" }
+              }
+            },
+            "settings_version": 6
+          }
+        }"#;
+        fs::write(dir.path().join(PROFILES_FILE), profiles)
+            .await
+            .unwrap();
+
+        let store = ProfileStore::load(dir.path()).await;
+        let settings = store.get("travel").unwrap();
+        assert_eq!(settings.settings_version, CURRENT_SETTINGS_VERSION);
+        let RelaySettings::Normal(constraints) = &settings.relay_settings else {
+            panic!("expected normal relay settings");
+        };
+        assert_eq!(
+            constraints.location,
+            Constraint::Only(LocationConstraint::Location(
+                GeographicLocationConstraint::City("se".to_owned(), "got".to_owned())
+            ))
+        );
+
+        // The migrated profile was saved, so that it is not migrated again
+        let saved: BTreeMap<String, Value> = serde_json::from_str(
+            &fs::read_to_string(dir.path().join(PROFILES_FILE))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            saved["travel"]["settings_version"],
+            CURRENT_SETTINGS_VERSION as u64
+        );
+    }
+}
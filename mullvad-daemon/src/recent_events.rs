@@ -0,0 +1,257 @@
+//! A bounded buffer of significant events in the daemon, such as tunnel state transitions and
+//! relay selections, that is kept regardless of the log level.
+//!
+//! Descriptions of events are redacted like the logs in a problem report before they are stored.
+//! The buffer is also written to a file in the log directory whenever it changes, so that it is
+//! included in problem reports.
+
+use chrono::Utc;
+use mullvad_types::recent_events::{Event, EventKind};
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use talpid_core::tunnel_state_machine::TunnelCommand;
+use talpid_types::{tunnel::TunnelStateTransition, ErrorExt};
+use tokio::sync::watch;
+
+/// Number of events that are kept.
+pub const CAPACITY: usize = 500;
+
+/// Name of the file in the log directory that the events are written to.
+const LOG_FILENAME: &str = "recent-events.log";
+
+/// The most recent events, oldest first.
+#[derive(Debug)]
+pub struct EventBuffer {
+    events: VecDeque<Event>,
+    capacity: usize,
+}
+
+impl EventBuffer {
+    pub fn new(capacity: usize) -> Self {
+        EventBuffer {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Adds an event with a redacted `message`, dropping the oldest event if the buffer is full.
+    pub fn push(&mut self, event: Event) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event {
+            message: mullvad_problem_report::redact(&event.message),
+            ..event
+        });
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.iter().cloned().collect()
+    }
+
+    /// Returns the events as text, one per line.
+    pub fn to_log(&self) -> String {
+        self.events.iter().fold(String::new(), |mut log, event| {
+            let _ = writeln!(log, "{event}");
+            log
+        })
+    }
+}
+
+/// Handle to the buffer of recent events, which can be shared with other parts of the daemon.
+#[derive(Clone)]
+pub struct RecentEvents {
+    buffer: Arc<Mutex<EventBuffer>>,
+    log_tx: Option<Arc<watch::Sender<String>>>,
+}
+
+impl RecentEvents {
+    /// Creates an empty buffer. If there is a log directory, the events are written to it
+    /// whenever they change.
+    pub fn new(log_dir: Option<PathBuf>) -> Self {
+        let log_tx = log_dir.map(|log_dir| {
+            let (log_tx, log_rx) = watch::channel(String::new());
+            tokio::spawn(write_log(log_dir.join(LOG_FILENAME), log_rx));
+            Arc::new(log_tx)
+        });
+        RecentEvents {
+            buffer: Arc::new(Mutex::new(EventBuffer::new(CAPACITY))),
+            log_tx,
+        }
+    }
+
+    /// Records an event that happens now.
+    pub fn push(&self, kind: EventKind, message: impl Into<String>) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.push(Event {
+            time: Utc::now(),
+            kind,
+            message: message.into(),
+        });
+        if let Some(log_tx) = &self.log_tx {
+            log_tx.send_replace(buffer.to_log());
+        }
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.buffer.lock().unwrap().events()
+    }
+}
+
+/// Returns the events that `transition` is recorded as.
+pub fn describe_transition(transition: &TunnelStateTransition) -> Vec<(EventKind, String)> {
+    match transition {
+        TunnelStateTransition::Disconnected => {
+            vec![(EventKind::TunnelState, "Disconnected".to_owned())]
+        }
+        TunnelStateTransition::Connecting(endpoint, phase, attempt) => vec![(
+            EventKind::TunnelState,
+            format!(
+                "Connecting to {endpoint}, attempt {}, phase {phase:?}",
+                attempt.attempt
+            ),
+        )],
+        TunnelStateTransition::Connected(endpoint, effective_dns) => vec![
+            (EventKind::TunnelState, format!("Connected to {endpoint}")),
+            (
+                EventKind::Dns,
+                format!(
+                    "Using {} DNS servers {:?}",
+                    effective_dns.source, effective_dns.servers
+                ),
+            ),
+        ],
+        TunnelStateTransition::Disconnecting(after_disconnect) => vec![(
+            EventKind::TunnelState,
+            format!("Disconnecting, action afterwards: {after_disconnect:?}"),
+        )],
+        TunnelStateTransition::Error(error_state) => {
+            let cause = error_state.cause();
+            let mut events = vec![(
+                EventKind::TunnelState,
+                format!("Entered the error state ({}): {cause}", cause.error_code()),
+            )];
+            if let Some(error) = error_state.block_failure() {
+                events.push((
+                    EventKind::Firewall,
+                    error.display_chain_with_msg("Failed to block all traffic"),
+                ));
+            }
+            events
+        }
+    }
+}
+
+/// Returns the event that `command` is recorded as, if it changes the firewall policy, the DNS
+/// configuration or the target state.
+pub fn describe_command(command: &TunnelCommand) -> Option<(EventKind, String)> {
+    let event = match command {
+        TunnelCommand::AllowLan(allow_lan) => {
+            (EventKind::Firewall, format!("Set allow LAN to {allow_lan}"))
+        }
+        TunnelCommand::AllowLanDnsWhenBlocked(allow) => (
+            EventKind::Firewall,
+            format!("Set allow LAN DNS when blocked to {allow}"),
+        ),
+        TunnelCommand::BlockWhenDisconnected(block) => (
+            EventKind::Firewall,
+            format!("Set block when disconnected to {block}"),
+        ),
+        TunnelCommand::BypassRoutes(routes) => {
+            (EventKind::Firewall, format!("Set bypass routes {routes:?}"))
+        }
+        TunnelCommand::Dns(Some(servers), source) => (
+            EventKind::Dns,
+            format!("Set {source} DNS servers {servers:?}"),
+        ),
+        TunnelCommand::Dns(None, _) => {
+            (EventKind::Dns, "Set DNS servers to the default".to_owned())
+        }
+        TunnelCommand::TlsDnsServers(servers) => {
+            let servers: Vec<_> = servers.iter().map(ToString::to_string).collect();
+            (
+                EventKind::Dns,
+                format!("Set DNS-over-TLS servers {servers:?}"),
+            )
+        }
+        TunnelCommand::Connect => (EventKind::TunnelState, "Requested connect".to_owned()),
+        TunnelCommand::Disconnect => (EventKind::TunnelState, "Requested disconnect".to_owned()),
+        TunnelCommand::Block(cause) => {
+            (EventKind::TunnelState, format!("Requested block: {cause}"))
+        }
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// Writes the latest text in `log_rx` to `path`. Versions that are replaced while the file is
+/// being written are skipped.
+async fn write_log(path: PathBuf, mut log_rx: watch::Receiver<String>) {
+    while log_rx.changed().await.is_ok() {
+        let log = log_rx.borrow_and_update().clone();
+        if let Err(error) = tokio::fs::write(&path, log).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to write the recent events")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn event(second: i64, message: &str) -> Event {
+        Event {
+            time: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap() + Duration::seconds(second),
+            kind: EventKind::TunnelState,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_oldest_events_are_dropped() {
+        let mut buffer = EventBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(event(i, &format!("event {i}")));
+        }
+        let messages: Vec<_> = buffer
+            .events()
+            .into_iter()
+            .map(|event| event.message)
+            .collect();
+        assert_eq!(messages, ["event 2", "event 3", "event 4"]);
+    }
+
+    #[test]
+    fn test_events_are_redacted() {
+        let mut buffer = EventBuffer::new(CAPACITY);
+        buffer.push(event(
+            0,
+            "Connecting to 192.0.2.1 with account 1234123412341234",
+        ));
+        assert_eq!(
+            buffer.events()[0].message,
+            "Connecting to [REDACTED] with account [REDACTED ACCOUNT NUMBER]"
+        );
+    }
+
+    #[test]
+    fn test_log() {
+        let mut buffer = EventBuffer::new(CAPACITY);
+        assert_eq!(buffer.to_log(), "");
+        buffer.push(event(0, "Connecting"));
+        buffer.push(event(1, "Connected"));
+        assert_eq!(
+            buffer.to_log(),
+            "2024-03-01T12:00:00.000Z [tunnel state] Connecting\n\
+             2024-03-01T12:00:01.000Z [tunnel state] Connected\n"
+        );
+    }
+}
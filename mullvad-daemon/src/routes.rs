@@ -0,0 +1,65 @@
+//! Conversion of the routes reported by the route manager.
+
+#[cfg(target_os = "linux")]
+use mullvad_types::routes::RoutesUpdate;
+use mullvad_types::routes::{RouteDump, RouteInfo, RoutePurpose};
+use talpid_routing::NetNode;
+
+pub fn route_dump(dump: talpid_routing::RouteDump) -> RouteDump {
+    RouteDump {
+        desired: dump.desired.into_iter().map(desired_route).collect(),
+        kernel: dump
+            .kernel
+            .map(|routes| routes.iter().map(kernel_route).collect()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn routes_update(update: talpid_routing::RoutesUpdate) -> RoutesUpdate {
+    RoutesUpdate {
+        added: update.added.iter().map(kernel_route).collect(),
+        removed: update.removed.iter().map(kernel_route).collect(),
+    }
+}
+
+fn desired_route(route: talpid_routing::DesiredRoute) -> RouteInfo {
+    let (gateway, interface) = match &route.node {
+        NetNode::RealNode(node) => (node.get_address(), node.get_device().map(str::to_owned)),
+        NetNode::DefaultNode => (None, None),
+    };
+    RouteInfo {
+        destination: route.prefix,
+        gateway,
+        interface,
+        via_default_route: route.node == NetNode::DefaultNode,
+        table: route.table,
+        metric: None,
+        purpose: Some(route_purpose(route.purpose)),
+    }
+}
+
+fn kernel_route(route: &talpid_routing::Route) -> RouteInfo {
+    let node = route.get_node();
+    RouteInfo {
+        destination: route.get_prefix(),
+        gateway: node.get_address(),
+        interface: node.get_device().map(str::to_owned),
+        via_default_route: false,
+        #[cfg(target_os = "linux")]
+        table: Some(route.get_table_id()),
+        #[cfg(not(target_os = "linux"))]
+        table: None,
+        metric: route.get_metric(),
+        purpose: None,
+    }
+}
+
+fn route_purpose(purpose: talpid_routing::RoutePurpose) -> RoutePurpose {
+    match purpose {
+        talpid_routing::RoutePurpose::RelayExclusion => RoutePurpose::RelayExclusion,
+        talpid_routing::RoutePurpose::TunnelDefault => RoutePurpose::TunnelDefault,
+        talpid_routing::RoutePurpose::Tunnel => RoutePurpose::Tunnel,
+        talpid_routing::RoutePurpose::Bypass => RoutePurpose::Bypass,
+        talpid_routing::RoutePurpose::Other => RoutePurpose::Other,
+    }
+}
@@ -1,16 +1,37 @@
 #[cfg(not(target_os = "android"))]
 use futures::TryFutureExt;
+use ipnetwork::IpNetwork;
+#[cfg(target_os = "linux")]
+use mullvad_types::settings::RoutingSettings;
 use mullvad_types::{
-    relay_constraints::{RelayConstraints, RelaySettings, WireguardConstraints},
-    settings::{DnsState, Settings},
+    access_method::SocksAuth,
+    network_profiles::NetworkId,
+    network_trust::NetworkTrustSettings,
+    relay_constraints::{
+        ObfuscationSettings, ObfuscationType, RelayConstraints, RelaySettings, WireguardConstraints,
+    },
+    settings::{
+        AccountExpiryWarnings, DnsOptions, DnsState, HookSettings, LocalProxySettings,
+        LogRotationSettings, Settings,
+    },
 };
 use std::{
     fmt::{self, Display},
-    ops::Deref,
+    net::IpAddr,
+    ops::{Deref, RangeInclusive},
     path::{Path, PathBuf},
+    time::Duration,
+};
+use talpid_core::{
+    firewall::{is_allowed_lan_network, is_local_address},
+    logging::RotationConfig,
+};
+use talpid_types::{
+    net::TransportProtocol,
+    split_tunnel::{ExcludedDestination, PortRange, SplitTunnelMode},
+    tunnel::RetryBackoff,
+    ErrorExt,
 };
-use talpid_core::firewall::is_local_address;
-use talpid_types::ErrorExt;
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
@@ -18,6 +39,17 @@ use tokio::{
 
 const SETTINGS_FILE: &str = "settings.json";
 
+/// Overrides the routing table in the settings.
+#[cfg(target_os = "linux")]
+const ROUTE_TABLE_ENV_VAR: &str = "MULLVAD_ROUTE_TABLE";
+/// Overrides the routing rule priority in the settings.
+#[cfg(target_os = "linux")]
+const RULE_PRIORITY_ENV_VAR: &str = "MULLVAD_RULE_PRIORITY";
+/// Overrides the size in MiB that logs are rotated at in the settings.
+const LOG_MAX_SIZE_ENV_VAR: &str = "MULLVAD_LOG_MAX_SIZE_MIB";
+/// Overrides the number of log archives that are kept in the settings.
+const LOG_ARCHIVES_ENV_VAR: &str = "MULLVAD_LOG_ARCHIVES";
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -36,6 +68,1037 @@ pub enum Error {
 
     #[error(display = "Unable to write settings to {}", _0)]
     WriteError(String, #[error(source)] io::Error),
+
+    #[error(
+        display = "The custom DNS server {} requires IPv6 to be enabled in the tunnel",
+        _0
+    )]
+    Ipv6DnsServerWithoutIpv6(IpAddr),
+
+    #[error(display = "Plain and DNS-over-TLS custom DNS servers cannot be combined")]
+    MixedPlainAndTlsDnsServers,
+
+    #[error(
+        display = "The DNS server {} is only reachable through the tunnel and cannot be kept while \
+                   disconnected",
+        _0
+    )]
+    KeepTunnelOnlyDnsServer(IpAddr),
+
+    #[error(
+        display = "The default DNS servers are only reachable through the tunnel and cannot be \
+                   kept while disconnected"
+    )]
+    KeepDefaultDnsServers,
+
+    #[error(
+        display = "Keeping custom DNS servers while disconnected is only supported on Linux and \
+                   macOS"
+    )]
+    KeepDnsWhileDisconnectedNotSupported,
+
+    #[error(
+        display = "The bypass route {} would route all traffic outside the tunnel",
+        _0
+    )]
+    BypassRouteMatchesAll(IpNetwork),
+
+    #[error(
+        display = "The bypass route {} contains the relay {} that the tunnel connects to",
+        _0,
+        _1
+    )]
+    BypassRouteContainsRelay(IpNetwork, IpAddr),
+
+    #[error(display = "The bypass route {} contains the DNS server {}", _0, _1)]
+    BypassRouteContainsDnsServer(IpNetwork, IpAddr),
+
+    #[error(display = "The root user cannot be excluded from the tunnel")]
+    SplitTunnelRootUser,
+
+    #[error(display = "The root group cannot be excluded from the tunnel")]
+    SplitTunnelRootGroup,
+
+    #[error(display = "Split tunneling in include mode is only supported on Linux")]
+    SplitTunnelModeNotSupported,
+
+    #[error(display = "Excluding destinations from the tunnel is only supported on Linux")]
+    SplitTunnelDestinationsNotSupported,
+
+    #[error(
+        display = "The excluded destination {} would exclude all traffic from the tunnel",
+        _0
+    )]
+    SplitTunnelDestinationMatchesAll(ExcludedDestination),
+
+    #[error(
+        display = "The excluded destination {} contains the relay {} that the tunnel connects to",
+        _0,
+        _1
+    )]
+    SplitTunnelDestinationContainsRelay(ExcludedDestination, IpAddr),
+
+    #[error(
+        display = "The excluded destination {} contains the DNS server {}",
+        _0,
+        _1
+    )]
+    SplitTunnelDestinationContainsDnsServer(ExcludedDestination, IpAddr),
+
+    #[error(
+        display = "The excluded destination {} is already reachable on the local network",
+        _0
+    )]
+    SplitTunnelDestinationAllowedLan(ExcludedDestination),
+
+    #[error(display = "Excluding interfaces from the tunnel is only supported on Linux")]
+    SplitTunnelInterfacesNotSupported,
+
+    #[error(display = "{:?} is not a valid interface name", _0)]
+    InvalidSplitTunnelInterface(String),
+
+    #[error(display = "The loopback interface cannot be excluded from the tunnel")]
+    SplitTunnelLoopbackInterface,
+
+    #[error(display = "Blocking excluded apps from the local network is only supported on Linux")]
+    ExcludedAppsBlockLanNotSupported,
+
+    #[error(display = "Excluding local ports from the tunnel is only supported on Linux")]
+    SplitTunnelLocalPortsNotSupported,
+
+    #[error(display = "Port 0 cannot be excluded from the tunnel")]
+    InvalidSplitTunnelLocalPort,
+
+    #[error(
+        display = "The local port {}/{} may be used by the tunnel or by other connections, since \
+                   it is in the ephemeral port range {}",
+        _1,
+        _0,
+        _2
+    )]
+    SplitTunnelLocalPortEphemeral(TransportProtocol, u16, PortRange),
+
+    #[error(display = "A network obfuscation profile or trust rule must name a network")]
+    EmptyNetworkId,
+
+    #[error(display = "There is more than one obfuscation profile for {}", _0)]
+    DuplicateNetworkObfuscationProfile(NetworkId),
+
+    #[error(display = "There is more than one trust rule for {}", _0)]
+    DuplicateNetworkTrustRule(NetworkId),
+
+    #[error(display = "The obfuscation priority must contain at least one obfuscation type")]
+    EmptyObfuscationPriority,
+
+    #[error(
+        display = "The obfuscation type {} occurs more than once in the priority",
+        _0
+    )]
+    DuplicateObfuscationType(ObfuscationType),
+
+    #[error(display = "\"{}\" is not a valid server name for QUIC obfuscation", _0)]
+    InvalidQuicServerName(String),
+
+    #[error(display = "QUIC application protocols must be 1 to 255 bytes long")]
+    InvalidQuicAlpn,
+
+    #[error(display = "\"{}\" is not a valid server name for TLS obfuscation", _0)]
+    InvalidTlsServerName(String),
+
+    #[error(display = "The upstream proxy cannot be reached on port 0")]
+    InvalidUpstreamProxyPort,
+
+    #[error(
+        display = "The username and password of the upstream proxy must be 1 to 255 bytes long"
+    )]
+    InvalidUpstreamProxyCredentials,
+
+    #[error(display = "The local proxy cannot listen on port 0")]
+    InvalidLocalProxyPort,
+
+    #[error(display = "The username and password of the local proxy must be 1 to 255 bytes long")]
+    InvalidLocalProxyCredentials,
+
+    #[error(display = "The hook {} is not an absolute path", _0)]
+    RelativeHookPath(String),
+
+    #[error(display = "Unable to read the hook {}", _0)]
+    ReadHookError(String, #[error(source)] std::io::Error),
+
+    #[error(display = "The hook {} is not a file", _0)]
+    HookNotAFile(String),
+
+    /// Hooks are run as root, so only root may be able to change them.
+    #[error(display = "The hook {} is not owned by root", _0)]
+    HookNotOwnedByRoot(String),
+
+    #[error(display = "The hook {} is writable by users other than root", _0)]
+    HookWritableByOthers(String),
+
+    /// Hooks are run as SYSTEM, so only administrators may be able to change them.
+    #[error(
+        display = "The hook {} can be changed by users other than administrators",
+        _0
+    )]
+    HookNotAdminOnly(String),
+
+    /// Another user could replace the hook by changing the directory.
+    #[error(
+        display = "The hook {} is in {}, which can be changed by unprivileged users",
+        _0,
+        _1
+    )]
+    InsecureHookDirectory(String, String),
+
+    #[error(
+        display = "The retry delays must be between 100 ms and 10 minutes, and the initial delay \
+                   may not exceed the maximum delay"
+    )]
+    InvalidRetryDelay,
+
+    #[error(display = "The retry backoff multiplier must be between 1 and 10")]
+    InvalidRetryMultiplier,
+
+    #[error(display = "The retry backoff must be reset after between 1 second and 24 hours")]
+    InvalidRetryResetAfter,
+
+    #[error(display = "Account expiry warnings must be between 1 minute and 30 days")]
+    InvalidAccountExpiryWarning,
+
+    #[error(display = "At most 10 account expiry warnings can be set")]
+    TooManyAccountExpiryWarnings,
+
+    #[error(display = "Logs must be rotated at between 1 and 1024 MiB")]
+    InvalidLogMaxSize,
+
+    #[error(display = "At most 20 log archives can be kept")]
+    TooManyLogArchives,
+
+    #[error(display = "The tunnel must be idle for between 1 minute and 24 hours to disconnect")]
+    InvalidAutoDisconnectIdle,
+}
+
+/// Bounds of the delays between connection attempts. Shorter delays could make the daemon spin
+/// on a network that never works, and longer ones would leave the user blocked for too long.
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+const MAX_RETRY_MULTIPLIER: f64 = 10.0;
+const MIN_RETRY_RESET_AFTER: Duration = Duration::from_secs(1);
+const MAX_RETRY_RESET_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Bounds of the account expiry warnings. Accounts are never topped up for much longer than a
+/// month at a time, and warnings that are closer together than a minute would not be noticed.
+const MIN_ACCOUNT_EXPIRY_WARNING: Duration = Duration::from_secs(60);
+const MAX_ACCOUNT_EXPIRY_WARNING: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const MAX_ACCOUNT_EXPIRY_WARNINGS: usize = 10;
+
+/// Bounds of the log rotation. The archives of all logs must fit comfortably on disk.
+const MIN_LOG_MAX_SIZE_MIB: u32 = 1;
+const MAX_LOG_MAX_SIZE_MIB: u32 = 1024;
+const MAX_LOG_ARCHIVES: u32 = 20;
+
+/// Bounds of how long the tunnel must be idle to be disconnected. Idleness is only checked every
+/// so often, so shorter periods would not be honored.
+const MIN_AUTO_DISCONNECT_IDLE: Duration = Duration::from_secs(60);
+const MAX_AUTO_DISCONNECT_IDLE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns an error if `options` contain both plain and DNS-over-TLS custom DNS servers, or a
+/// custom IPv6 DNS server that can only be reached through the tunnel while IPv6 is disabled in
+/// the tunnel. The default DNS servers, the resolver at the tunnel gateway and the content blocking
+/// servers are only reachable through the tunnel, so they may also not be kept in the disconnected
+/// state. DNS servers can only be kept while disconnected on Linux and macOS.
+pub fn validate_dns_options(options: &DnsOptions, enable_ipv6: bool) -> Result<(), Error> {
+    if options.keep_custom_dns_while_disconnected {
+        if !cfg!(any(target_os = "linux", target_os = "macos")) {
+            return Err(Error::KeepDnsWhileDisconnectedNotSupported);
+        }
+        if options.state != DnsState::Custom {
+            return Err(Error::KeepDefaultDnsServers);
+        }
+        if let Some(server) = options
+            .custom_options
+            .addresses
+            .iter()
+            .find(|server| crate::dns::is_tunnel_only_address(server))
+        {
+            return Err(Error::KeepTunnelOnlyDnsServer(*server));
+        }
+    }
+    if options.state != DnsState::Custom {
+        return Ok(());
+    }
+    let custom_options = &options.custom_options;
+    if !custom_options.addresses.is_empty() && !custom_options.tls_servers.is_empty() {
+        return Err(Error::MixedPlainAndTlsDnsServers);
+    }
+    if enable_ipv6 {
+        return Ok(());
+    }
+    let tls_addresses = custom_options
+        .tls_servers
+        .iter()
+        .map(|server| &server.address);
+    match custom_options
+        .addresses
+        .iter()
+        .chain(tls_addresses)
+        .find(|addr| addr.is_ipv6() && !is_local_address(addr))
+    {
+        Some(addr) => Err(Error::Ipv6DnsServerWithoutIpv6(*addr)),
+        None => Ok(()),
+    }
+}
+
+/// Returns an error if any of the bypass `routes` would route all traffic outside the tunnel, or
+/// contains one of `relays` or a DNS server used by `dns_options`. DNS requests to such a server
+/// would otherwise leak outside the tunnel.
+pub fn validate_bypass_routes(
+    routes: &[IpNetwork],
+    dns_options: &DnsOptions,
+    relays: &[IpAddr],
+) -> Result<(), Error> {
+    let dns_servers = crate::dns::addresses_from_options(dns_options)
+        .unwrap_or_else(|| vec![crate::dns::TUNNEL_GATEWAY_RESOLVER]);
+    let tls_servers = crate::dns::tls_servers_from_options(dns_options);
+    let dns_servers = dns_servers
+        .into_iter()
+        .chain(tls_servers.into_iter().map(|server| server.address));
+
+    for route in routes {
+        if route.prefix() == 0 {
+            return Err(Error::BypassRouteMatchesAll(*route));
+        }
+        if let Some(relay) = relays.iter().find(|relay| route.contains(**relay)) {
+            return Err(Error::BypassRouteContainsRelay(*route, *relay));
+        }
+    }
+    for server in dns_servers {
+        if let Some(route) = routes.iter().find(|route| route.contains(server)) {
+            return Err(Error::BypassRouteContainsDnsServer(*route, server));
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if the root user or group is among the `users` and `groups` to exclude from
+/// the tunnel. The daemon runs as root, so its own traffic would otherwise be excluded.
+pub fn validate_split_tunnel_owners(users: &[u32], groups: &[u32]) -> Result<(), Error> {
+    if users.contains(&0) {
+        return Err(Error::SplitTunnelRootUser);
+    }
+    if groups.contains(&0) {
+        return Err(Error::SplitTunnelRootGroup);
+    }
+    Ok(())
+}
+
+/// Returns an error if `mode` is not supported on this platform. The split tunnel driver on
+/// Windows can only exclude applications, so include mode is only available on Linux.
+pub fn validate_split_tunnel_mode(mode: SplitTunnelMode) -> Result<(), Error> {
+    if mode == SplitTunnelMode::Include && !cfg!(target_os = "linux") {
+        return Err(Error::SplitTunnelModeNotSupported);
+    }
+    Ok(())
+}
+
+/// Returns an error if any of the excluded `destinations` would exclude all traffic, or contains
+/// one of `relays` or a DNS server used by `dns_options`. Destinations that are only on the local
+/// network are rejected while `allow_lan` is enabled, since that traffic is already allowed
+/// outside the tunnel.
+pub fn validate_split_tunnel_destinations(
+    destinations: &[ExcludedDestination],
+    dns_options: &DnsOptions,
+    relays: &[IpAddr],
+    allow_lan: bool,
+) -> Result<(), Error> {
+    if !destinations.is_empty() && !cfg!(target_os = "linux") {
+        return Err(Error::SplitTunnelDestinationsNotSupported);
+    }
+
+    let dns_servers = crate::dns::addresses_from_options(dns_options)
+        .unwrap_or_else(|| vec![crate::dns::TUNNEL_GATEWAY_RESOLVER]);
+    let dns_endpoints = dns_servers
+        .into_iter()
+        .flat_map(|server| {
+            [
+                (server, TransportProtocol::Udp, 53),
+                (server, TransportProtocol::Tcp, 53),
+            ]
+        })
+        .chain(
+            crate::dns::tls_servers_from_options(dns_options)
+                .into_iter()
+                .map(|server| (server.address, TransportProtocol::Tcp, 853)),
+        )
+        .collect::<Vec<_>>();
+
+    for destination in destinations {
+        if destination.network.prefix() == 0 && destination.ports.is_none() {
+            return Err(Error::SplitTunnelDestinationMatchesAll(*destination));
+        }
+        if let Some(relay) = relays
+            .iter()
+            .find(|relay| destination.network.contains(**relay))
+        {
+            return Err(Error::SplitTunnelDestinationContainsRelay(
+                *destination,
+                *relay,
+            ));
+        }
+        if let Some((server, ..)) = dns_endpoints.iter().find(|(server, protocol, port)| {
+            destination.network.contains(*server) && destination.contains_port(*protocol, *port)
+        }) {
+            return Err(Error::SplitTunnelDestinationContainsDnsServer(
+                *destination,
+                *server,
+            ));
+        }
+        if allow_lan && is_allowed_lan_network(&destination.network) {
+            return Err(Error::SplitTunnelDestinationAllowedLan(*destination));
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if any of the `interfaces` whose forwarded traffic is excluded is not an
+/// interface name, or a prefix followed by `*`, or if it matches the loopback interface.
+pub fn validate_split_tunnel_interfaces(interfaces: &[String]) -> Result<(), Error> {
+    /// Maximum length of an interface name, excluding the null terminator.
+    const MAX_INTERFACE_NAME_LEN: usize = 15;
+
+    if !interfaces.is_empty() && !cfg!(target_os = "linux") {
+        return Err(Error::SplitTunnelInterfacesNotSupported);
+    }
+    for interface in interfaces {
+        let name = interface.strip_suffix('*').unwrap_or(interface);
+        if name.is_empty()
+            || name.len() > MAX_INTERFACE_NAME_LEN
+            || name
+                .chars()
+                .any(|c| c == '/' || c == '*' || c == ':' || c == '\0' || c.is_whitespace())
+        {
+            return Err(Error::InvalidSplitTunnelInterface(interface.clone()));
+        }
+        if "lo".starts_with(name) && (name == "lo" || interface.ends_with('*')) {
+            return Err(Error::SplitTunnelLoopbackInterface);
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if excluded apps would be blocked from the local network on a platform where
+/// that is not supported. The split tunnel driver on Windows permits all traffic of excluded apps,
+/// so they can always reach the local network.
+pub fn validate_excluded_apps_allow_lan(allow: bool) -> Result<(), Error> {
+    if !allow && !cfg!(target_os = "linux") {
+        return Err(Error::ExcludedAppsBlockLanNotSupported);
+    }
+    Ok(())
+}
+
+/// Returns an error if any of the local `ports` whose traffic is excluded is 0, or is in
+/// `ephemeral_ports`. The kernel assigns ports in that range to sockets that are not bound to a
+/// specific port, such as that of the WireGuard tunnel, so their traffic could leak.
+pub fn validate_split_tunnel_local_ports(
+    ports: &[(TransportProtocol, u16)],
+    ephemeral_ports: PortRange,
+) -> Result<(), Error> {
+    if !ports.is_empty() && !cfg!(target_os = "linux") {
+        return Err(Error::SplitTunnelLocalPortsNotSupported);
+    }
+    for (protocol, port) in ports {
+        if *port == 0 {
+            return Err(Error::InvalidSplitTunnelLocalPort);
+        }
+        if ephemeral_ports.contains(*port) {
+            return Err(Error::SplitTunnelLocalPortEphemeral(
+                *protocol,
+                *port,
+                ephemeral_ports,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `server_name` is a host name that can be sent in a TLS handshake.
+fn is_valid_server_name(server_name: &str) -> bool {
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    server_name.len() <= 253 && server_name.split('.').all(valid_label)
+}
+
+/// Returns an error if the obfuscation priority of `settings` is empty or contains an obfuscation
+/// type more than once, if the QUIC or TLS server name or the QUIC application protocols cannot be
+/// sent in a TLS handshake, or if the upstream proxy credentials cannot be sent in a SOCKS5
+/// request.
+pub fn validate_obfuscation_settings(settings: &ObfuscationSettings) -> Result<(), Error> {
+    if let Some(server_name) = &settings.quic.server_name {
+        if !is_valid_server_name(server_name) {
+            return Err(Error::InvalidQuicServerName(server_name.clone()));
+        }
+    }
+    if let Some(server_name) = &settings.tls.server_name {
+        if !is_valid_server_name(server_name) {
+            return Err(Error::InvalidTlsServerName(server_name.clone()));
+        }
+    }
+    if settings
+        .quic
+        .alpn
+        .iter()
+        .any(|protocol| !(1..=usize::from(u8::MAX)).contains(&protocol.len()))
+    {
+        return Err(Error::InvalidQuicAlpn);
+    }
+    if let Some(proxy) = &settings.upstream_proxy {
+        if proxy.endpoint.port() == 0 {
+            return Err(Error::InvalidUpstreamProxyPort);
+        }
+        if let Some(auth) = &proxy.authentication {
+            let valid_len = |value: &str| (1..=usize::from(u8::MAX)).contains(&value.len());
+            if !valid_len(&auth.username) || !valid_len(&auth.password) {
+                return Err(Error::InvalidUpstreamProxyCredentials);
+            }
+        }
+    }
+
+    let priority = &settings.obfuscation_priority;
+    if priority.is_empty() {
+        return Err(Error::EmptyObfuscationPriority);
+    }
+    for (i, obfuscation_type) in priority.iter().enumerate() {
+        if priority[..i].contains(obfuscation_type) {
+            return Err(Error::DuplicateObfuscationType(*obfuscation_type));
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if any of the obfuscation `profiles` has an empty SSID or interface name, or
+/// invalid obfuscation settings, or if there is more than one profile for the same network.
+pub fn validate_network_obfuscation_profiles(
+    profiles: &[(NetworkId, ObfuscationSettings)],
+) -> Result<(), Error> {
+    for (i, (network, obfuscation_settings)) in profiles.iter().enumerate() {
+        validate_obfuscation_settings(obfuscation_settings)?;
+        let (NetworkId::Ssid(name) | NetworkId::Interface(name)) = network;
+        if name.is_empty() {
+            return Err(Error::EmptyNetworkId);
+        }
+        if profiles[..i].iter().any(|(other, _)| other == network) {
+            return Err(Error::DuplicateNetworkObfuscationProfile(network.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if any of the trust rules has an empty SSID or interface name, or if there is
+/// more than one rule for the same network.
+pub fn validate_network_trust_settings(settings: &NetworkTrustSettings) -> Result<(), Error> {
+    for (i, (network, _)) in settings.rules.iter().enumerate() {
+        let (NetworkId::Ssid(name) | NetworkId::Interface(name)) = network;
+        if name.is_empty() {
+            return Err(Error::EmptyNetworkId);
+        }
+        if settings.rules[..i]
+            .iter()
+            .any(|(other, _)| other == network)
+        {
+            return Err(Error::DuplicateNetworkTrustRule(network.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if the local proxy would listen on port 0, or if its username or password
+/// cannot be sent in a SOCKS5 authentication request.
+pub fn validate_local_proxy(settings: &LocalProxySettings) -> Result<(), Error> {
+    if settings.port == Some(0) {
+        return Err(Error::InvalidLocalProxyPort);
+    }
+    if let Some(SocksAuth { username, password }) = &settings.authentication {
+        let valid_len = |value: &str| (1..=usize::from(u8::MAX)).contains(&value.len());
+        if !valid_len(username) || !valid_len(password) {
+            return Err(Error::InvalidLocalProxyCredentials);
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if any of the `hooks` is not an absolute path to a file. See
+/// [`validate_hook_file`].
+pub fn validate_hooks(hooks: &HookSettings) -> Result<(), Error> {
+    let paths = [&hooks.on_connect, &hooks.on_disconnect, &hooks.on_error];
+    for path in paths.into_iter().flatten() {
+        validate_hook_file(path)?;
+    }
+    Ok(())
+}
+
+/// Returns an error unless `path` is an absolute path to a file that only root can change, since
+/// the daemon runs hooks as root. The file must be owned by root and not be writable by other
+/// users, other than through a group that only root is in, and so must every directory on the
+/// way to it, both as given and with symlinks resolved. On Windows, where hooks are run as
+/// SYSTEM, the file and directories must be owned by and only be writable by administrators.
+///
+/// This is checked when the hooks are set, and again right before a hook is run.
+pub fn validate_hook_file(path: &Path) -> Result<(), Error> {
+    validate_hook(
+        path,
+        #[cfg(unix)]
+        0,
+    )
+}
+
+fn validate_hook(path: &Path, #[cfg(unix)] owner_uid: u32) -> Result<(), Error> {
+    let name = || path.display().to_string();
+    if !path.is_absolute() {
+        return Err(Error::RelativeHookPath(name()));
+    }
+    let metadata = std::fs::metadata(path).map_err(|error| Error::ReadHookError(name(), error))?;
+    if !metadata.is_file() {
+        return Err(Error::HookNotAFile(name()));
+    }
+    let resolved =
+        std::fs::canonicalize(path).map_err(|error| Error::ReadHookError(name(), error))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if metadata.uid() != owner_uid {
+            return Err(Error::HookNotOwnedByRoot(name()));
+        }
+        if writable_by_others(&metadata) {
+            return Err(Error::HookWritableByOthers(name()));
+        }
+        validate_hook_directories(path, path, owner_uid)?;
+        validate_hook_directories(path, &resolved, owner_uid)?;
+    }
+    #[cfg(windows)]
+    {
+        windows::validate_hook_security(path, path)?;
+        windows::validate_hook_security(path, &resolved)?;
+    }
+    Ok(())
+}
+
+/// Returns whether users other than the owner of a file can write to it. Group write permission
+/// is allowed for the root group, which only privileged users are in.
+#[cfg(unix)]
+fn writable_by_others(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.mode() & 0o002 != 0 || (metadata.mode() & 0o020 != 0 && metadata.gid() != 0)
+}
+
+/// Returns an error if a user other than root or `owner_uid` can change what `path` refers to.
+/// Every directory on the way must be owned by one of them, and must not be writable by other
+/// users unless it has the sticky bit set, like `/tmp`, and the entry in it is owned by one of
+/// them.
+#[cfg(unix)]
+fn validate_hook_directories(hook: &Path, path: &Path, owner_uid: u32) -> Result<(), Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let trusted = |uid| uid == 0 || uid == owner_uid;
+    let insecure = |dir: &Path| {
+        Error::InsecureHookDirectory(hook.display().to_string(), dir.display().to_string())
+    };
+    let read_error = |path: &Path, error| Error::ReadHookError(path.display().to_string(), error);
+
+    let mut entry = path;
+    while let Some(dir) = entry.parent() {
+        let metadata = std::fs::metadata(dir).map_err(|error| read_error(dir, error))?;
+        if !trusted(metadata.uid()) {
+            return Err(insecure(dir));
+        }
+        if writable_by_others(&metadata) {
+            let sticky = metadata.mode() & 0o1000 != 0;
+            // The entry itself, rather than what it links to, must not be replaceable
+            let entry_owner = std::fs::symlink_metadata(entry)
+                .map_err(|error| read_error(entry, error))?
+                .uid();
+            if !sticky || !trusted(entry_owner) {
+                return Err(insecure(dir));
+            }
+        }
+        entry = dir;
+    }
+    Ok(())
+}
+
+/// Checks the security descriptors of hooks on Windows.
+#[cfg(windows)]
+mod windows {
+    use super::Error;
+    use std::{ffi::c_void, io, os::windows::ffi::OsStrExt, path::Path, ptr};
+    use windows_sys::Win32::{
+        Foundation::{ERROR_SUCCESS, HANDLE, PSID},
+        Security::{
+            Authorization::{ConvertStringSidToSidW, GetNamedSecurityInfoW, SE_FILE_OBJECT},
+            EqualSid, GetAce, IsWellKnownSid, WinBuiltinAdministratorsSid, WinLocalSystemSid,
+            ACCESS_ALLOWED_ACE, ACE_HEADER, ACL, DACL_SECURITY_INFORMATION,
+            OWNER_SECURITY_INFORMATION, WELL_KNOWN_SID_TYPE,
+        },
+        System::Memory::LocalFree,
+    };
+
+    /// The SID of `NT SERVICE\TrustedInstaller`, which owns most system files.
+    const TRUSTED_INSTALLER_SID: &str =
+        "S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464";
+
+    const ACCESS_ALLOWED_ACE_TYPE: u8 = 0x0;
+    const ACCESS_ALLOWED_CALLBACK_ACE_TYPE: u8 = 0x9;
+    const ACCESS_DENIED_ACE_TYPE: u8 = 0x1;
+    const ACCESS_DENIED_CALLBACK_ACE_TYPE: u8 = 0xa;
+    const INHERIT_ONLY_ACE: u8 = 0x8;
+
+    const DELETE: u32 = 0x0001_0000;
+    const WRITE_DAC: u32 = 0x0004_0000;
+    const WRITE_OWNER: u32 = 0x0008_0000;
+    const GENERIC_ALL: u32 = 0x1000_0000;
+    const GENERIC_WRITE: u32 = 0x4000_0000;
+    const FILE_WRITE_DATA: u32 = 0x0002;
+    const FILE_APPEND_DATA: u32 = 0x0004;
+    const FILE_DELETE_CHILD: u32 = 0x0040;
+
+    /// Access rights that allow changing a file.
+    const FILE_WRITE_RIGHTS: u32 = DELETE
+        | WRITE_DAC
+        | WRITE_OWNER
+        | GENERIC_ALL
+        | GENERIC_WRITE
+        | FILE_WRITE_DATA
+        | FILE_APPEND_DATA;
+    /// Access rights that allow replacing the entries of a directory. Creating new entries does
+    /// not affect the entries that are already in it.
+    const DIRECTORY_WRITE_RIGHTS: u32 =
+        DELETE | WRITE_DAC | WRITE_OWNER | GENERIC_ALL | FILE_DELETE_CHILD;
+
+    /// Returns an error unless only administrators can change the hook at `path`, or replace it
+    /// by changing one of the directories on the way to it. `hook` is the path to report.
+    pub fn validate_hook_security(hook: &Path, path: &Path) -> Result<(), Error> {
+        let name = || hook.display().to_string();
+        let trusted_installer = StringSid::new(TRUSTED_INSTALLER_SID)
+            .map_err(|error| Error::ReadHookError(name(), error))?;
+        let is_trusted = |sid: PSID| {
+            is_well_known_sid(sid, WinLocalSystemSid)
+                || is_well_known_sid(sid, WinBuiltinAdministratorsSid)
+                || unsafe { EqualSid(sid, trusted_installer.0) } != 0
+        };
+
+        let security = SecurityInformation::from_file(path)
+            .map_err(|error| Error::ReadHookError(name(), error))?;
+        if !security.only_writable_by(is_trusted, FILE_WRITE_RIGHTS) {
+            return Err(Error::HookNotAdminOnly(name()));
+        }
+
+        let mut entry = path;
+        while let Some(dir) = entry.parent() {
+            let security = SecurityInformation::from_file(dir)
+                .map_err(|error| Error::ReadHookError(dir.display().to_string(), error))?;
+            if !security.only_writable_by(is_trusted, DIRECTORY_WRITE_RIGHTS) {
+                return Err(Error::InsecureHookDirectory(
+                    name(),
+                    dir.display().to_string(),
+                ));
+            }
+            entry = dir;
+        }
+        Ok(())
+    }
+
+    /// The owner and DACL of a file.
+    struct SecurityInformation {
+        security_descriptor: *mut c_void,
+        owner: PSID,
+        dacl: *mut ACL,
+    }
+
+    impl SecurityInformation {
+        fn from_file(path: &Path) -> io::Result<Self> {
+            let mut u16_path: Vec<u16> = path.as_os_str().encode_wide().collect();
+            u16_path.push(0u16);
+
+            let mut security_descriptor = ptr::null_mut();
+            let mut owner = ptr::null_mut();
+            let mut dacl = ptr::null_mut();
+
+            let status = unsafe {
+                GetNamedSecurityInfoW(
+                    u16_path.as_ptr(),
+                    SE_FILE_OBJECT,
+                    OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION,
+                    &mut owner,
+                    ptr::null_mut(),
+                    &mut dacl,
+                    ptr::null_mut(),
+                    &mut security_descriptor,
+                )
+            };
+            if status != ERROR_SUCCESS {
+                return Err(io::Error::from_raw_os_error(status as i32));
+            }
+
+            Ok(SecurityInformation {
+                security_descriptor,
+                owner,
+                dacl,
+            })
+        }
+
+        /// Returns whether the owner is trusted, and no other account is allowed any of the
+        /// `rights`. Access that is denied is ignored, since allowing it may still grant it
+        /// through another group.
+        fn only_writable_by(&self, is_trusted: impl Fn(PSID) -> bool, rights: u32) -> bool {
+            // The owner can always change the DACL
+            if self.owner.is_null() || !is_trusted(self.owner) {
+                return false;
+            }
+            // A missing DACL allows everyone full access
+            let Some(dacl) = (unsafe { self.dacl.as_ref() }) else {
+                return false;
+            };
+            for index in 0..u32::from(dacl.AceCount) {
+                let mut ace: *mut c_void = ptr::null_mut();
+                if unsafe { GetAce(self.dacl, index, &mut ace) } == 0 {
+                    return false;
+                }
+                let header = unsafe { &*(ace as *const ACE_HEADER) };
+                if header.AceFlags & INHERIT_ONLY_ACE != 0 {
+                    // Only applies to the children of a directory
+                    continue;
+                }
+                match header.AceType {
+                    ACCESS_DENIED_ACE_TYPE | ACCESS_DENIED_CALLBACK_ACE_TYPE => continue,
+                    ACCESS_ALLOWED_ACE_TYPE | ACCESS_ALLOWED_CALLBACK_ACE_TYPE => {
+                        let ace = unsafe { &*(ace as *const ACCESS_ALLOWED_ACE) };
+                        let sid = &ace.SidStart as *const u32 as PSID;
+                        if ace.Mask & rights != 0 && !is_trusted(sid) {
+                            return false;
+                        }
+                    }
+                    // Object and compound ACEs are not expected on files
+                    _ => return false,
+                }
+            }
+            true
+        }
+    }
+
+    impl Drop for SecurityInformation {
+        fn drop(&mut self) {
+            unsafe { LocalFree(self.security_descriptor as HANDLE) };
+        }
+    }
+
+    /// A SID that is parsed from its string form.
+    struct StringSid(PSID);
+
+    impl StringSid {
+        fn new(sid: &str) -> io::Result<Self> {
+            let mut u16_sid: Vec<u16> = sid.encode_utf16().collect();
+            u16_sid.push(0u16);
+
+            let mut psid = ptr::null_mut();
+            if unsafe { ConvertStringSidToSidW(u16_sid.as_ptr(), &mut psid) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(StringSid(psid))
+        }
+    }
+
+    impl Drop for StringSid {
+        fn drop(&mut self) {
+            unsafe { LocalFree(self.0 as HANDLE) };
+        }
+    }
+
+    fn is_well_known_sid(sid: PSID, well_known_sid_type: WELL_KNOWN_SID_TYPE) -> bool {
+        unsafe { IsWellKnownSid(sid, well_known_sid_type) != 0 }
+    }
+}
+
+/// Returns an error if the delays, multiplier or reset time of `backoff` are out of bounds.
+pub fn validate_retry_backoff(backoff: &RetryBackoff) -> Result<(), Error> {
+    let delays = MIN_RETRY_DELAY..=MAX_RETRY_DELAY;
+    if !delays.contains(&backoff.initial)
+        || !delays.contains(&backoff.max)
+        || backoff.initial > backoff.max
+    {
+        return Err(Error::InvalidRetryDelay);
+    }
+    // Also rejects NaN
+    if !(1.0..=MAX_RETRY_MULTIPLIER).contains(&backoff.multiplier) {
+        return Err(Error::InvalidRetryMultiplier);
+    }
+    if !(MIN_RETRY_RESET_AFTER..=MAX_RETRY_RESET_AFTER).contains(&backoff.reset_after_connected_for)
+    {
+        return Err(Error::InvalidRetryResetAfter);
+    }
+    Ok(())
+}
+
+/// Returns an error if any of the thresholds in `warnings` is out of bounds, or if there are too
+/// many of them.
+pub fn validate_account_expiry_warnings(warnings: &AccountExpiryWarnings) -> Result<(), Error> {
+    if warnings.thresholds.len() > MAX_ACCOUNT_EXPIRY_WARNINGS {
+        return Err(Error::TooManyAccountExpiryWarnings);
+    }
+    let bounds = MIN_ACCOUNT_EXPIRY_WARNING..=MAX_ACCOUNT_EXPIRY_WARNING;
+    if !warnings
+        .thresholds
+        .iter()
+        .all(|threshold| bounds.contains(threshold))
+    {
+        return Err(Error::InvalidAccountExpiryWarning);
+    }
+    Ok(())
+}
+
+/// Returns an error if logs would be rotated at a size that is out of bounds, or if too many
+/// archives would be kept.
+pub fn validate_log_rotation(settings: &LogRotationSettings) -> Result<(), Error> {
+    if !(MIN_LOG_MAX_SIZE_MIB..=MAX_LOG_MAX_SIZE_MIB).contains(&settings.max_size_mib) {
+        return Err(Error::InvalidLogMaxSize);
+    }
+    if settings.max_archives > MAX_LOG_ARCHIVES {
+        return Err(Error::TooManyLogArchives);
+    }
+    Ok(())
+}
+
+/// Returns an error if the tunnel would be disconnected after being idle for a period that is out
+/// of bounds.
+pub fn validate_auto_disconnect_idle(timeout: Option<Duration>) -> Result<(), Error> {
+    match timeout {
+        Some(timeout)
+            if !(MIN_AUTO_DISCONNECT_IDLE..=MAX_AUTO_DISCONNECT_IDLE).contains(&timeout) =>
+        {
+            Err(Error::InvalidAutoDisconnectIdle)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns an error if any part of `settings` is invalid, as if each setting had been set on its
+/// own. `relays` are the relays that the tunnel uses.
+pub fn validate_settings(settings: &Settings, relays: &[IpAddr]) -> Result<(), Error> {
+    let dns_options = &settings.tunnel_options.dns_options;
+    validate_dns_options(dns_options, settings.tunnel_options.generic.enable_ipv6)?;
+    validate_bypass_routes(&settings.bypass_routes, dns_options, relays)?;
+    #[cfg(target_os = "linux")]
+    {
+        let split_tunnel = &settings.split_tunnel;
+        validate_split_tunnel_owners(&split_tunnel.users, &split_tunnel.groups)?;
+        validate_split_tunnel_mode(split_tunnel.mode)?;
+        validate_split_tunnel_destinations(
+            &split_tunnel.destinations,
+            dns_options,
+            relays,
+            settings.allow_lan,
+        )?;
+        validate_split_tunnel_interfaces(&split_tunnel.interfaces)?;
+        validate_excluded_apps_allow_lan(split_tunnel.excluded_apps_allow_lan)?;
+        validate_split_tunnel_local_ports(&split_tunnel.local_ports, ephemeral_port_range())?;
+    }
+    validate_obfuscation_settings(&settings.obfuscation_settings)?;
+    validate_network_obfuscation_profiles(&settings.network_obfuscation_profiles)?;
+    validate_network_trust_settings(&settings.network_trust)?;
+    validate_local_proxy(&settings.local_proxy)?;
+    validate_hooks(&settings.hooks)?;
+    validate_retry_backoff(&settings.retry_backoff)?;
+    validate_account_expiry_warnings(&settings.account_expiry_warnings)?;
+    validate_log_rotation(&settings.log_rotation)?;
+    validate_auto_disconnect_idle(settings.auto_disconnect_idle)
+}
+
+/// Returns the range of local ports that are assigned to sockets that are not bound to a specific
+/// port. If it cannot be read, the default range of the kernel is returned.
+pub fn ephemeral_port_range() -> PortRange {
+    /// Default value of `net.ipv4.ip_local_port_range`.
+    const DEFAULT_EPHEMERAL_PORTS: (u16, u16) = (32768, 60999);
+
+    #[cfg(target_os = "linux")]
+    match talpid_types::split_tunnel::ephemeral_port_range() {
+        Ok(ports) => return ports,
+        Err(error) => log::warn!(
+            "{}",
+            error.display_chain_with_msg("Failed to read the ephemeral port range")
+        ),
+    }
+    PortRange::new(DEFAULT_EPHEMERAL_PORTS.0, DEFAULT_EPHEMERAL_PORTS.1)
+        .expect("invalid default port range")
+}
+
+/// Returns how logs should be rotated, with any overrides from the environment applied.
+pub fn log_rotation_config(settings: &LogRotationSettings) -> RotationConfig {
+    let mut settings = *settings;
+    let max_size_bounds = MIN_LOG_MAX_SIZE_MIB..=MAX_LOG_MAX_SIZE_MIB;
+    if let Some(max_size_mib) = log_rotation_from_env(LOG_MAX_SIZE_ENV_VAR, max_size_bounds) {
+        settings.max_size_mib = max_size_mib;
+    }
+    if let Some(max_archives) = log_rotation_from_env(LOG_ARCHIVES_ENV_VAR, 0..=MAX_LOG_ARCHIVES) {
+        settings.max_archives = max_archives;
+    }
+    RotationConfig {
+        max_size: u64::from(settings.max_size_mib) * 1024 * 1024,
+        max_archives: settings.max_archives as usize,
+    }
+}
+
+fn log_rotation_from_env(var: &str, bounds: RangeInclusive<u32>) -> Option<u32> {
+    let value = std::env::var(var).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) if bounds.contains(&parsed) => {
+            log::info!("Using {var}={parsed}");
+            Some(parsed)
+        }
+        _ => {
+            log::warn!("Ignoring invalid value of {var}: {value}");
+            None
+        }
+    }
+}
+
+/// Returns the routing settings to use, with any overrides from the environment applied.
+#[cfg(target_os = "linux")]
+pub fn routing_settings(settings: &RoutingSettings) -> RoutingSettings {
+    let mut settings = *settings;
+    if let Some(route_table) = routing_id_from_env(ROUTE_TABLE_ENV_VAR) {
+        settings.route_table = route_table;
+    }
+    if let Some(rule_priority) = routing_id_from_env(RULE_PRIORITY_ENV_VAR) {
+        settings.rule_priority = Some(rule_priority);
+    }
+    settings
+}
+
+#[cfg(target_os = "linux")]
+fn routing_id_from_env(var: &str) -> Option<u32> {
+    let value = std::env::var(var).ok()?;
+    match parse_routing_id(&value) {
+        Some(id) => {
+            log::info!("Using {var}={id}");
+            Some(id)
+        }
+        None => {
+            log::warn!("Ignoring invalid value of {var}: {value}");
+            None
+        }
+    }
+}
+
+/// Parses a decimal or "0x"-prefixed hexadecimal number.
+#[cfg(target_os = "linux")]
+fn parse_routing_id(value: &str) -> Option<u32> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
 }
 
 #[derive(Debug)]
@@ -159,7 +1222,7 @@ impl SettingsPersister {
 
     /// Modifies `Settings::default()` somewhat, e.g. depending on whether a beta version
     /// is being run or not.
-    fn default_settings() -> Settings {
+    pub fn default_settings() -> Settings {
         let mut settings = Settings::default();
 
         if crate::version::is_beta_version() {
@@ -289,6 +1352,17 @@ impl<'a> Display for SettingsSummary<'a> {
                 }
                 write!(f, "{}", content.join(" "))?;
             }
+            DnsState::Custom
+                if !self
+                    .settings
+                    .tunnel_options
+                    .dns_options
+                    .custom_options
+                    .tls_servers
+                    .is_empty() =>
+            {
+                f.write_str("custom, tls")?
+            }
             DnsState::Custom => {
                 // NOTE: Technically inaccurate, as the gateway IP is a local IP but isn't treated
                 // as one.
@@ -333,9 +1407,32 @@ impl<'a> SettingsSummary<'a> {
 
 #[cfg(test)]
 mod test {
-    use super::SettingsPersister;
-    use mullvad_types::settings::SettingsVersion;
+    use super::{
+        validate_account_expiry_warnings, validate_auto_disconnect_idle, validate_bypass_routes,
+        validate_dns_options, validate_hooks, validate_local_proxy, validate_log_rotation,
+        validate_network_obfuscation_profiles, validate_network_trust_settings,
+        validate_obfuscation_settings, validate_retry_backoff, validate_settings,
+        validate_split_tunnel_destinations, validate_split_tunnel_interfaces,
+        validate_split_tunnel_local_ports, validate_split_tunnel_mode,
+        validate_split_tunnel_owners, Error, SettingsPersister,
+    };
+    use mullvad_types::{
+        access_method::SocksAuth,
+        network_profiles::NetworkId,
+        network_trust::{NetworkTrustSettings, TrustPolicy},
+        relay_constraints::{ObfuscationSettings, ObfuscationType},
+        settings::{
+            AccountExpiryWarnings, CustomDnsOptions, DefaultDnsOptions, DnsOptions, DnsState,
+            HookSettings, LocalProxySettings, LogRotationSettings, Settings, SettingsVersion,
+        },
+    };
     use serde_json;
+    use std::time::Duration;
+    use talpid_types::{
+        net::{TlsDnsServer, TransportProtocol},
+        split_tunnel::{ExcludedDestination, PortRange, SplitTunnelMode},
+        tunnel::RetryBackoff,
+    };
 
     #[test]
     #[should_panic]
@@ -362,6 +1459,186 @@ mod test {
         assert_eq!(s, "2");
     }
 
+    #[test]
+    fn test_ipv6_dns_requires_ipv6() {
+        let options = DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: vec![
+                    "9.9.9.9".parse().unwrap(),
+                    "fd00::53".parse().unwrap(),
+                    "2620:fe::fe".parse().unwrap(),
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let public_server: std::net::IpAddr = "2620:fe::fe".parse().unwrap();
+        assert!(matches!(
+            validate_dns_options(&options, false),
+            Err(Error::Ipv6DnsServerWithoutIpv6(addr)) if addr == public_server
+        ));
+        assert!(validate_dns_options(&options, true).is_ok());
+
+        let default_options = DnsOptions {
+            state: DnsState::Default,
+            ..options
+        };
+        assert!(validate_dns_options(&default_options, false).is_ok());
+    }
+
+    #[test]
+    fn test_tls_dns_servers() {
+        let tls_server = TlsDnsServer {
+            address: "2620:fe::fe".parse().unwrap(),
+            hostname: "dns.quad9.net".to_owned(),
+        };
+        let options = DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                tls_servers: vec![tls_server.clone()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_dns_options(&options, false),
+            Err(Error::Ipv6DnsServerWithoutIpv6(addr)) if addr == tls_server.address
+        ));
+        assert!(validate_dns_options(&options, true).is_ok());
+
+        let mixed_options = DnsOptions {
+            custom_options: CustomDnsOptions {
+                addresses: vec!["9.9.9.9".parse().unwrap()],
+                tls_servers: vec![tls_server],
+            },
+            ..options
+        };
+        assert!(matches!(
+            validate_dns_options(&mixed_options, true),
+            Err(Error::MixedPlainAndTlsDnsServers)
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_routing_id() {
+        use super::parse_routing_id;
+
+        assert_eq!(parse_routing_id("1000"), Some(1000));
+        assert_eq!(parse_routing_id(" 0x6d6f6c65\n"), Some(0x6d6f6c65));
+        assert_eq!(parse_routing_id("-1"), None);
+        assert_eq!(parse_routing_id("0x"), None);
+        assert_eq!(parse_routing_id("mole"), None);
+    }
+
+    #[test]
+    fn test_custom_dns_with_content_blockers() {
+        // The content blockers are kept, but not used, while custom DNS is enabled
+        let options = DnsOptions {
+            state: DnsState::Custom,
+            default_options: DefaultDnsOptions {
+                block_ads: true,
+                block_trackers: true,
+                ..Default::default()
+            },
+            custom_options: CustomDnsOptions {
+                addresses: vec!["9.9.9.9".parse().unwrap()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(validate_dns_options(&options, true).is_ok());
+        assert_eq!(
+            crate::dns::addresses_from_options(&options),
+            Some(vec!["9.9.9.9".parse().unwrap()])
+        );
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn test_keep_dns_while_disconnected() {
+        let options = DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: vec!["9.9.9.9".parse().unwrap()],
+                ..Default::default()
+            },
+            keep_custom_dns_while_disconnected: true,
+            ..Default::default()
+        };
+        assert!(validate_dns_options(&options, true).is_ok());
+
+        // The resolver at the tunnel gateway and the content blockers are only reachable through
+        // the tunnel
+        for server in [
+            "10.64.0.1",
+            "fc00:bbbb:bbbb:bb01::1",
+            "100.64.0.1",
+            "100.64.0.7",
+            "100.64.0.63",
+        ] {
+            let server: std::net::IpAddr = server.parse().unwrap();
+            let options = DnsOptions {
+                custom_options: CustomDnsOptions {
+                    addresses: vec!["9.9.9.9".parse().unwrap(), server],
+                    ..Default::default()
+                },
+                ..options.clone()
+            };
+            assert!(matches!(
+                validate_dns_options(&options, true),
+                Err(Error::KeepTunnelOnlyDnsServer(addr)) if addr == server
+            ));
+        }
+
+        // Other addresses in the shared address space are not content blockers
+        for server in ["100.64.0.64", "100.65.0.1"] {
+            let options = DnsOptions {
+                custom_options: CustomDnsOptions {
+                    addresses: vec![server.parse().unwrap()],
+                    ..Default::default()
+                },
+                ..options.clone()
+            };
+            assert!(validate_dns_options(&options, true).is_ok());
+        }
+
+        // The default resolvers are only reachable through the tunnel
+        let default_options = DnsOptions {
+            state: DnsState::Default,
+            ..options.clone()
+        };
+        assert!(matches!(
+            validate_dns_options(&default_options, true),
+            Err(Error::KeepDefaultDnsServers)
+        ));
+        let default_options = DnsOptions {
+            keep_custom_dns_while_disconnected: false,
+            ..default_options
+        };
+        assert!(validate_dns_options(&default_options, true).is_ok());
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn test_keep_dns_while_disconnected_not_supported() {
+        let options = DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: vec!["9.9.9.9".parse().unwrap()],
+                ..Default::default()
+            },
+            keep_custom_dns_while_disconnected: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_dns_options(&options, true),
+            Err(Error::KeepDnsWhileDisconnectedNotSupported)
+        ));
+    }
+
     #[test]
     fn test_deserialization() {
         let settings = br#"{
@@ -417,4 +1694,618 @@ mod test {
 
         let _ = SettingsPersister::load_from_bytes(settings).unwrap();
     }
+
+    #[test]
+    fn test_validate_bypass_routes() {
+        let relay: std::net::IpAddr = "185.213.154.68".parse().unwrap();
+        let routes = vec![
+            "10.10.0.0/16".parse().unwrap(),
+            "2001:db8::/32".parse().unwrap(),
+        ];
+        assert!(validate_bypass_routes(&routes, &DnsOptions::default(), &[relay]).is_ok());
+
+        let all_v6 = "::/0".parse().unwrap();
+        assert!(matches!(
+            validate_bypass_routes(&[all_v6], &DnsOptions::default(), &[]),
+            Err(Error::BypassRouteMatchesAll(route)) if route == all_v6
+        ));
+
+        let relay_route = "185.213.154.0/24".parse().unwrap();
+        assert!(matches!(
+            validate_bypass_routes(&[relay_route], &DnsOptions::default(), &[relay]),
+            Err(Error::BypassRouteContainsRelay(route, addr)) if route == relay_route && addr == relay
+        ));
+    }
+
+    #[test]
+    fn test_bypass_routes_may_not_contain_dns_servers() {
+        let gateway_route = "10.64.0.0/10".parse().unwrap();
+        assert!(matches!(
+            validate_bypass_routes(&[gateway_route], &DnsOptions::default(), &[]),
+            Err(Error::BypassRouteContainsDnsServer(route, _)) if route == gateway_route
+        ));
+
+        let custom_route = "9.9.9.0/24".parse().unwrap();
+        let options = DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: vec!["9.9.9.9".parse().unwrap()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_bypass_routes(&[custom_route], &options, &[]),
+            Err(Error::BypassRouteContainsDnsServer(route, _)) if route == custom_route
+        ));
+        assert!(validate_bypass_routes(&[gateway_route], &options, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_split_tunnel_owners_may_not_be_root() {
+        assert!(validate_split_tunnel_owners(&[1001, 1002], &[100]).is_ok());
+        assert!(matches!(
+            validate_split_tunnel_owners(&[1001, 0], &[]),
+            Err(Error::SplitTunnelRootUser)
+        ));
+        assert!(matches!(
+            validate_split_tunnel_owners(&[], &[0]),
+            Err(Error::SplitTunnelRootGroup)
+        ));
+    }
+
+    #[test]
+    fn test_split_tunnel_include_mode_requires_linux() {
+        assert!(validate_split_tunnel_mode(SplitTunnelMode::Exclude).is_ok());
+        assert_eq!(
+            validate_split_tunnel_mode(SplitTunnelMode::Include).is_ok(),
+            cfg!(target_os = "linux")
+        );
+    }
+
+    fn destination(network: &str, port: Option<u16>) -> ExcludedDestination {
+        ExcludedDestination {
+            network: network.parse().unwrap(),
+            ports: port.map(|port| PortRange::new(port, port).unwrap()),
+            protocol: TransportProtocol::Tcp,
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_validate_split_tunnel_destinations() {
+        let relay: std::net::IpAddr = "185.213.154.68".parse().unwrap();
+        let nas = destination("10.0.0.0/8", Some(445));
+        let options = DnsOptions::default();
+
+        assert!(validate_split_tunnel_destinations(&[nas], &options, &[relay], false).is_ok());
+        assert!(matches!(
+            validate_split_tunnel_destinations(&[nas], &options, &[relay], true),
+            Err(Error::SplitTunnelDestinationAllowedLan(_))
+        ));
+
+        assert!(matches!(
+            validate_split_tunnel_destinations(
+                &[destination("0.0.0.0/0", None)],
+                &options,
+                &[],
+                false
+            ),
+            Err(Error::SplitTunnelDestinationMatchesAll(_))
+        ));
+        assert!(matches!(
+            validate_split_tunnel_destinations(
+                &[destination("185.213.154.0/24", Some(443))],
+                &options,
+                &[relay],
+                false
+            ),
+            Err(Error::SplitTunnelDestinationContainsRelay(_, addr)) if addr == relay
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_split_tunnel_destinations_may_not_contain_dns_servers() {
+        let options = DnsOptions::default();
+        // The default resolver is only reached on port 53
+        assert!(matches!(
+            validate_split_tunnel_destinations(
+                &[destination("10.64.0.0/10", None)],
+                &options,
+                &[],
+                false
+            ),
+            Err(Error::SplitTunnelDestinationContainsDnsServer(..))
+        ));
+        assert!(matches!(
+            validate_split_tunnel_destinations(
+                &[destination("10.64.0.0/10", Some(53))],
+                &options,
+                &[],
+                false
+            ),
+            Err(Error::SplitTunnelDestinationContainsDnsServer(..))
+        ));
+        assert!(validate_split_tunnel_destinations(
+            &[destination("10.64.0.0/10", Some(445))],
+            &options,
+            &[],
+            false
+        )
+        .is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_validate_split_tunnel_interfaces() {
+        let interfaces = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(validate_split_tunnel_interfaces(&interfaces(&["docker0", "br-*"])).is_ok());
+        for invalid in [
+            "",
+            "*",
+            "br-*-x",
+            "eth0:1",
+            "docker bridge",
+            "a-name-that-is-too-long",
+        ] {
+            assert!(
+                matches!(
+                    validate_split_tunnel_interfaces(&interfaces(&[invalid])),
+                    Err(Error::InvalidSplitTunnelInterface(name)) if name == invalid
+                ),
+                "{invalid:?} was accepted"
+            );
+        }
+        for loopback in ["lo", "l*"] {
+            assert!(matches!(
+                validate_split_tunnel_interfaces(&interfaces(&[loopback])),
+                Err(Error::SplitTunnelLoopbackInterface)
+            ));
+        }
+        assert!(validate_split_tunnel_interfaces(&interfaces(&["lxcbr0"])).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_validate_split_tunnel_local_ports() {
+        let ephemeral_ports = PortRange::new(32768, 60999).unwrap();
+
+        assert!(validate_split_tunnel_local_ports(
+            &[
+                (TransportProtocol::Udp, 27015),
+                (TransportProtocol::Tcp, 25565)
+            ],
+            ephemeral_ports
+        )
+        .is_ok());
+        assert!(matches!(
+            validate_split_tunnel_local_ports(&[(TransportProtocol::Tcp, 0)], ephemeral_ports),
+            Err(Error::InvalidSplitTunnelLocalPort)
+        ));
+        // The WireGuard tunnel is bound to a port in the ephemeral range
+        for port in [32768, 45000, 60999] {
+            assert!(matches!(
+                validate_split_tunnel_local_ports(&[(TransportProtocol::Udp, port)], ephemeral_ports),
+                Err(Error::SplitTunnelLocalPortEphemeral(TransportProtocol::Udp, p, _)) if p == port
+            ));
+        }
+    }
+
+    #[test]
+    fn test_validate_obfuscation_settings() {
+        let settings = |obfuscation_priority: Vec<ObfuscationType>| ObfuscationSettings {
+            obfuscation_priority,
+            ..Default::default()
+        };
+
+        assert!(validate_obfuscation_settings(&ObfuscationSettings::default()).is_ok());
+        assert!(validate_obfuscation_settings(&settings(vec![ObfuscationType::Udp2Tcp])).is_ok());
+        assert!(matches!(
+            validate_obfuscation_settings(&settings(vec![])),
+            Err(Error::EmptyObfuscationPriority)
+        ));
+        assert!(matches!(
+            validate_obfuscation_settings(&settings(vec![
+                ObfuscationType::None,
+                ObfuscationType::Udp2Tcp,
+                ObfuscationType::None,
+            ])),
+            Err(Error::DuplicateObfuscationType(ObfuscationType::None))
+        ));
+
+        let mut quic_settings = ObfuscationSettings::default();
+        quic_settings.quic.server_name = Some("cdn.example.com".to_owned());
+        assert!(validate_obfuscation_settings(&quic_settings).is_ok());
+        quic_settings.quic.server_name = Some("not a hostname".to_owned());
+        assert!(matches!(
+            validate_obfuscation_settings(&quic_settings),
+            Err(Error::InvalidQuicServerName(_))
+        ));
+        quic_settings.quic.server_name = None;
+        quic_settings.quic.alpn = vec![String::new()];
+        assert!(matches!(
+            validate_obfuscation_settings(&quic_settings),
+            Err(Error::InvalidQuicAlpn)
+        ));
+
+        let mut tls_settings = ObfuscationSettings::default();
+        tls_settings.tls.server_name = Some("www.example.com".to_owned());
+        assert!(validate_obfuscation_settings(&tls_settings).is_ok());
+        tls_settings.tls.server_name = Some("example..com".to_owned());
+        assert!(matches!(
+            validate_obfuscation_settings(&tls_settings),
+            Err(Error::InvalidTlsServerName(_))
+        ));
+
+        let proxy_settings = |endpoint: &str, username: &str| ObfuscationSettings {
+            upstream_proxy: Some(talpid_types::net::proxy::SocksProxy {
+                endpoint: endpoint.parse().unwrap(),
+                authentication: Some(talpid_types::net::proxy::SocksAuth {
+                    username: username.to_owned(),
+                    password: "secret".to_owned(),
+                }),
+            }),
+            ..Default::default()
+        };
+        assert!(validate_obfuscation_settings(&proxy_settings("10.0.0.1:1080", "user")).is_ok());
+        assert!(matches!(
+            validate_obfuscation_settings(&proxy_settings("10.0.0.1:0", "user")),
+            Err(Error::InvalidUpstreamProxyPort)
+        ));
+        assert!(matches!(
+            validate_obfuscation_settings(&proxy_settings("10.0.0.1:1080", "")),
+            Err(Error::InvalidUpstreamProxyCredentials)
+        ));
+    }
+
+    #[test]
+    fn test_validate_network_obfuscation_profiles() {
+        let profile = |network: NetworkId| (network, ObfuscationSettings::default());
+
+        assert!(validate_network_obfuscation_profiles(&[
+            profile(NetworkId::Ssid("wlan0".to_owned())),
+            profile(NetworkId::Interface("wlan0".to_owned())),
+        ])
+        .is_ok());
+        assert!(matches!(
+            validate_network_obfuscation_profiles(&[profile(NetworkId::Ssid(String::new()))]),
+            Err(Error::EmptyNetworkId)
+        ));
+        assert!(matches!(
+            validate_network_obfuscation_profiles(&[
+                profile(NetworkId::Interface("eth0".to_owned())),
+                profile(NetworkId::Interface("eth0".to_owned())),
+            ]),
+            Err(Error::DuplicateNetworkObfuscationProfile(NetworkId::Interface(name)))
+                if name == "eth0"
+        ));
+    }
+
+    #[test]
+    fn test_validate_network_trust_settings() {
+        let settings = |rules: &[(NetworkId, TrustPolicy)]| NetworkTrustSettings {
+            rules: rules.to_vec(),
+            ..Default::default()
+        };
+
+        assert!(validate_network_trust_settings(&settings(&[
+            (NetworkId::Ssid("HomeNet".to_owned()), TrustPolicy::Trusted),
+            (
+                NetworkId::Interface("eth0".to_owned()),
+                TrustPolicy::Untrusted
+            ),
+        ]))
+        .is_ok());
+        assert!(matches!(
+            validate_network_trust_settings(&settings(&[(
+                NetworkId::Interface(String::new()),
+                TrustPolicy::Trusted
+            )])),
+            Err(Error::EmptyNetworkId)
+        ));
+        assert!(matches!(
+            validate_network_trust_settings(&settings(&[
+                (NetworkId::Ssid("HomeNet".to_owned()), TrustPolicy::Trusted),
+                (NetworkId::Ssid("HomeNet".to_owned()), TrustPolicy::Untrusted),
+            ])),
+            Err(Error::DuplicateNetworkTrustRule(NetworkId::Ssid(name))) if name == "HomeNet"
+        ));
+    }
+
+    #[test]
+    fn test_validate_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let relative = HookSettings {
+            on_connect: Some("hooks/on-connect.sh".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_hooks(&relative),
+            Err(Error::RelativeHookPath(_))
+        ));
+
+        let missing = HookSettings {
+            on_error: Some(dir.path().join("missing.sh")),
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_hooks(&missing),
+            Err(Error::ReadHookError(..))
+        ));
+
+        let directory = HookSettings {
+            on_disconnect: Some(dir.path().to_owned()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_hooks(&directory),
+            Err(Error::HookNotAFile(_))
+        ));
+
+        assert!(validate_hooks(&HookSettings::default()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_settings() {
+        assert!(validate_settings(&Settings::default(), &[]).is_ok());
+
+        #[cfg(target_os = "linux")]
+        {
+            let settings = Settings {
+                split_tunnel: mullvad_types::settings::SplitTunnelSettings {
+                    users: vec![0],
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            assert!(matches!(
+                validate_settings(&settings, &[]),
+                Err(Error::SplitTunnelRootUser)
+            ));
+        }
+
+        // Traffic to the relay that the tunnel uses may not be routed outside it
+        let relay: std::net::IpAddr = "185.213.154.68".parse().unwrap();
+        let settings = Settings {
+            bypass_routes: vec!["185.213.154.0/24".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_settings(&settings, &[relay]),
+            Err(Error::BypassRouteContainsRelay(..))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_hook_permissions() {
+        use super::validate_hook;
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let hook = dir.path().join("hook.sh");
+        std::fs::write(&hook, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let owner = std::fs::metadata(&hook).unwrap().uid();
+
+        assert!(validate_hook(&hook, owner).is_ok());
+        assert!(matches!(
+            validate_hook(&hook, owner.wrapping_add(1)),
+            Err(Error::HookNotOwnedByRoot(_))
+        ));
+
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o757)).unwrap();
+        assert!(matches!(
+            validate_hook(&hook, owner),
+            Err(Error::HookWritableByOthers(_))
+        ));
+
+        // Only the root group may be able to write to it
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o775)).unwrap();
+        let group_writable = validate_hook(&hook, owner);
+        if std::fs::metadata(&hook).unwrap().gid() == 0 {
+            assert!(group_writable.is_ok());
+        } else {
+            assert!(matches!(
+                group_writable,
+                Err(Error::HookWritableByOthers(_))
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_hook_directories() {
+        use super::validate_hook;
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let writable_dir = dir.path().join("writable");
+        std::fs::create_dir(&writable_dir).unwrap();
+        let hook = writable_dir.join("hook.sh");
+        std::fs::write(&hook, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let owner = std::fs::metadata(&hook).unwrap().uid();
+        assert!(validate_hook(&hook, owner).is_ok());
+
+        // Anyone could replace the hook
+        std::fs::set_permissions(&writable_dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(matches!(
+            validate_hook(&hook, owner),
+            Err(Error::InsecureHookDirectory(_, insecure)) if std::path::Path::new(&insecure) == writable_dir
+        ));
+
+        // The sticky bit keeps others from replacing it
+        std::fs::set_permissions(&writable_dir, std::fs::Permissions::from_mode(0o1777)).unwrap();
+        assert!(validate_hook(&hook, owner).is_ok());
+
+        // Members of the group could replace the hook, unless it is the root group
+        std::fs::set_permissions(&writable_dir, std::fs::Permissions::from_mode(0o775)).unwrap();
+        if std::fs::metadata(&writable_dir).unwrap().gid() != 0 {
+            assert!(matches!(
+                validate_hook(&hook, owner),
+                Err(Error::InsecureHookDirectory(..))
+            ));
+        }
+        std::fs::set_permissions(&writable_dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+        // Symlinks are resolved
+        let link = dir.path().join("link.sh");
+        std::os::unix::fs::symlink(&hook, &link).unwrap();
+        assert!(matches!(
+            validate_hook(&link, owner),
+            Err(Error::InsecureHookDirectory(..))
+        ));
+    }
+
+    #[test]
+    fn test_validate_retry_backoff() {
+        assert!(validate_retry_backoff(&RetryBackoff::default()).is_ok());
+
+        let exponential = RetryBackoff {
+            initial: Duration::from_millis(500),
+            multiplier: 2.0,
+            max: Duration::from_secs(60),
+            reset_after_connected_for: Duration::from_secs(30),
+        };
+        assert!(validate_retry_backoff(&exponential).is_ok());
+
+        let invalid_delays = [
+            (Duration::ZERO, Duration::from_secs(1)),
+            (Duration::from_secs(1), Duration::from_secs(3600)),
+            (Duration::from_secs(10), Duration::from_secs(5)),
+        ];
+        for (initial, max) in invalid_delays {
+            let backoff = RetryBackoff {
+                initial,
+                max,
+                ..exponential
+            };
+            assert!(matches!(
+                validate_retry_backoff(&backoff),
+                Err(Error::InvalidRetryDelay)
+            ));
+        }
+
+        for multiplier in [0.5, 11.0, f64::NAN, f64::INFINITY] {
+            let backoff = RetryBackoff {
+                multiplier,
+                ..exponential
+            };
+            assert!(matches!(
+                validate_retry_backoff(&backoff),
+                Err(Error::InvalidRetryMultiplier)
+            ));
+        }
+
+        let backoff = RetryBackoff {
+            reset_after_connected_for: Duration::ZERO,
+            ..exponential
+        };
+        assert!(matches!(
+            validate_retry_backoff(&backoff),
+            Err(Error::InvalidRetryResetAfter)
+        ));
+    }
+
+    #[test]
+    fn test_validate_account_expiry_warnings() {
+        assert!(validate_account_expiry_warnings(&AccountExpiryWarnings::default()).is_ok());
+        assert!(
+            validate_account_expiry_warnings(&AccountExpiryWarnings { thresholds: vec![] }).is_ok()
+        );
+
+        for threshold in [
+            Duration::from_secs(59),
+            Duration::from_secs(31 * 24 * 60 * 60),
+        ] {
+            let warnings = AccountExpiryWarnings {
+                thresholds: vec![Duration::from_secs(3600), threshold],
+            };
+            assert!(matches!(
+                validate_account_expiry_warnings(&warnings),
+                Err(Error::InvalidAccountExpiryWarning)
+            ));
+        }
+
+        let warnings = AccountExpiryWarnings {
+            thresholds: (1..=11)
+                .map(|hours| Duration::from_secs(hours * 3600))
+                .collect(),
+        };
+        assert!(matches!(
+            validate_account_expiry_warnings(&warnings),
+            Err(Error::TooManyAccountExpiryWarnings)
+        ));
+    }
+
+    #[test]
+    fn test_validate_log_rotation() {
+        assert!(validate_log_rotation(&LogRotationSettings::default()).is_ok());
+        let rotation = |max_size_mib, max_archives| LogRotationSettings {
+            max_size_mib,
+            max_archives,
+        };
+        assert!(validate_log_rotation(&rotation(1, 0)).is_ok());
+        assert!(validate_log_rotation(&rotation(1024, 20)).is_ok());
+
+        for max_size_mib in [0, 1025] {
+            assert!(matches!(
+                validate_log_rotation(&rotation(max_size_mib, 5)),
+                Err(Error::InvalidLogMaxSize)
+            ));
+        }
+        assert!(matches!(
+            validate_log_rotation(&rotation(10, 21)),
+            Err(Error::TooManyLogArchives)
+        ));
+    }
+
+    #[test]
+    fn test_validate_auto_disconnect_idle() {
+        const MINUTE: Duration = Duration::from_secs(60);
+        assert!(validate_auto_disconnect_idle(None).is_ok());
+        assert!(validate_auto_disconnect_idle(Some(MINUTE)).is_ok());
+        assert!(validate_auto_disconnect_idle(Some(24 * 60 * MINUTE)).is_ok());
+        for timeout in [Duration::ZERO, MINUTE / 2, 24 * 60 * MINUTE + MINUTE] {
+            assert!(matches!(
+                validate_auto_disconnect_idle(Some(timeout)),
+                Err(Error::InvalidAutoDisconnectIdle)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_validate_local_proxy() {
+        let proxy = |port: u16, username: &str, password: &str| LocalProxySettings {
+            port: Some(port),
+            authentication: Some(SocksAuth {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            }),
+        };
+
+        assert!(validate_local_proxy(&LocalProxySettings::default()).is_ok());
+        assert!(validate_local_proxy(&proxy(1080, "user", "secret")).is_ok());
+        assert!(matches!(
+            validate_local_proxy(&proxy(0, "user", "secret")),
+            Err(Error::InvalidLocalProxyPort)
+        ));
+        assert!(matches!(
+            validate_local_proxy(&proxy(1080, "", "secret")),
+            Err(Error::InvalidLocalProxyCredentials)
+        ));
+        assert!(matches!(
+            validate_local_proxy(&proxy(1080, "user", &"a".repeat(256))),
+            Err(Error::InvalidLocalProxyCredentials)
+        ));
+    }
 }
@@ -0,0 +1,519 @@
+//! Export of the settings to a JSON document that can be imported on another machine.
+//!
+//! The document contains the settings in the same format as the settings file, including the
+//! settings version, so that documents exported by older versions of the app are migrated when
+//! they are imported. Passwords and private keys are replaced by `null` unless the secrets are
+//! exported, in which case the account number and device are included as well. A document whose
+//! secrets were left out cannot be imported if any of its settings need them.
+//!
+//! Parts of the settings can also be changed at once with a JSON merge patch (RFC 7386) against
+//! the same format. Objects in the patch are merged into the current settings, `null` removes a
+//! field, and anything else replaces it. Since enums are stored as objects with a single field,
+//! switching to another variant requires the current one to be removed in the same patch.
+
+use crate::{device::PrivateAccountAndDevice, migrations};
+use mullvad_types::settings::{Settings, CURRENT_SETTINGS_VERSION};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Names of the fields in the settings that hold secrets.
+const SECRET_FIELDS: [&str; 2] = ["password", "private_key"];
+
+/// Top-level settings that a patch may not change. The settings version is managed by the daemon,
+/// and on Windows, the excluded apps must be set one at a time so that each of them is checked.
+#[cfg(windows)]
+const UNPATCHABLE_FIELDS: [&str; 2] = ["settings_version", "split_tunnel"];
+#[cfg(not(windows))]
+const UNPATCHABLE_FIELDS: [&str; 1] = ["settings_version"];
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to serialize the settings")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "The document does not contain exported settings")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "The exported settings have no settings version")]
+    MissingVersion,
+
+    /// The settings were exported by a newer version of the app
+    #[error(
+        display = "Settings version {} is not supported, try updating the app",
+        _0
+    )]
+    UnsupportedVersion(u64),
+
+    #[error(display = "Unable to migrate the exported settings")]
+    Migrate(#[error(source)] migrations::Error),
+
+    #[error(display = "The exported settings are malformed")]
+    Deserialize(#[error(source)] serde_json::Error),
+
+    #[error(
+        display = "The secret {} was left out when the settings were exported",
+        _0
+    )]
+    RedactedSecret(String),
+
+    #[error(display = "The settings patch is not a JSON object")]
+    PatchNotAnObject,
+
+    #[error(display = "The settings patch cannot change {}", _0)]
+    UnpatchableField(String),
+
+    #[error(display = "The settings patch contains the unknown setting {}", _0)]
+    UnknownField(String),
+
+    #[error(display = "The patched settings are malformed")]
+    InvalidPatch(#[error(source)] serde_json::Error),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Document {
+    settings: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<PrivateAccountAndDevice>,
+}
+
+/// Settings read from an exported document.
+#[derive(Debug)]
+pub struct ImportedSettings {
+    pub settings: Settings,
+    /// The account and device that were logged in when the settings were exported, if the
+    /// secrets were exported.
+    pub device: Option<PrivateAccountAndDevice>,
+}
+
+/// Returns the document that [`import`] reads `settings` from. The secrets are left out unless
+/// `include_secrets` is set, in which case `device` is included as well.
+pub fn export(
+    settings: &Settings,
+    device: Option<PrivateAccountAndDevice>,
+    include_secrets: bool,
+) -> Result<String, Error> {
+    let mut settings = serde_json::to_value(settings).map_err(Error::Serialize)?;
+    let device = if include_secrets {
+        device
+    } else {
+        redact_secrets(&mut settings);
+        None
+    };
+    serde_json::to_string_pretty(&Document { settings, device }).map_err(Error::Serialize)
+}
+
+/// Reads, migrates and deserializes the settings in a document created by [`export`]. The
+/// settings are not validated.
+pub fn import(document: &str) -> Result<ImportedSettings, Error> {
+    let Document {
+        mut settings,
+        device,
+    } = serde_json::from_str(document).map_err(Error::Parse)?;
+    migrate(&mut settings)?;
+
+    if let Some(path) = find_redacted_secret(&settings, "") {
+        return Err(Error::RedactedSecret(path));
+    }
+    let settings = serde_json::from_value(settings).map_err(Error::Deserialize)?;
+    Ok(ImportedSettings { settings, device })
+}
+
+/// Migrates settings in the format of the settings file, of any version that this version of the
+/// app can read, to the current version.
+pub fn migrate(settings: &mut Value) -> Result<(), Error> {
+    match settings.get("settings_version").and_then(Value::as_u64) {
+        None => return Err(Error::MissingVersion),
+        Some(version) if version > CURRENT_SETTINGS_VERSION as u64 => {
+            return Err(Error::UnsupportedVersion(version))
+        }
+        Some(_) => (),
+    }
+    migrations::migrate_exported(settings).map_err(Error::Migrate)
+}
+
+/// Returns `settings` with the JSON merge patch `patch` applied. The result is not validated.
+pub fn apply_patch(settings: &Settings, patch: &str) -> Result<Settings, Error> {
+    let patch: Value = serde_json::from_str(patch).map_err(Error::Parse)?;
+    let Some(fields) = patch.as_object() else {
+        return Err(Error::PatchNotAnObject);
+    };
+    if let Some(name) = UNPATCHABLE_FIELDS
+        .iter()
+        .find(|name| fields.contains_key(**name))
+    {
+        return Err(Error::UnpatchableField(name.to_string()));
+    }
+
+    let mut merged = serde_json::to_value(settings).map_err(Error::Serialize)?;
+    merge_patch(&mut merged, &patch);
+    let patched: Settings = serde_json::from_value(merged).map_err(Error::InvalidPatch)?;
+
+    // Unknown fields are ignored when deserializing, so a misspelled setting would otherwise be
+    // dropped without notice
+    let reserialized = serde_json::to_value(&patched).map_err(Error::Serialize)?;
+    if let Some(path) = find_unknown_field(&patch, &reserialized, "") {
+        return Err(Error::UnknownField(path));
+    }
+    Ok(patched)
+}
+
+/// Applies `patch` to `target` as described in RFC 7386.
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch_fields) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let Value::Object(target_fields) = target else {
+        unreachable!("target was just made an object");
+    };
+    for (name, value) in patch_fields {
+        if value.is_null() {
+            target_fields.remove(name);
+        } else {
+            merge_patch(
+                target_fields.entry(name.clone()).or_insert(Value::Null),
+                value,
+            );
+        }
+    }
+}
+
+/// Returns the path of the first field that is set in `patch` but missing from `settings`.
+fn find_unknown_field(patch: &Value, settings: &Value, path: &str) -> Option<String> {
+    let (Value::Object(patch_fields), Value::Object(fields)) = (patch, settings) else {
+        return None;
+    };
+    patch_fields
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .find_map(|(name, value)| {
+            let path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{path}.{name}")
+            };
+            match fields.get(name) {
+                Some(field) => find_unknown_field(value, field, &path),
+                None => Some(path),
+            }
+        })
+}
+
+fn redact_secrets(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&name.as_str()) {
+                    *value = Value::Null;
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_secrets),
+        _ => (),
+    }
+}
+
+/// Returns the path of the first secret in `value` that was redacted by [`redact_secrets`].
+fn find_redacted_secret(value: &Value, path: &str) -> Option<String> {
+    match value {
+        Value::Object(fields) => fields.iter().find_map(|(name, value)| {
+            let path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{path}.{name}")
+            };
+            if SECRET_FIELDS.contains(&name.as_str()) && value.is_null() {
+                Some(path)
+            } else {
+                find_redacted_secret(value, &path)
+            }
+        }),
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .find_map(|(i, value)| find_redacted_secret(value, &format!("{path}[{i}]"))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::{
+        access_method::SocksAuth,
+        relay_constraints::{
+            Constraint, GeographicLocationConstraint, LocationConstraint, RelaySettings,
+            SelectedObfuscation,
+        },
+        wireguard::QuantumResistantState,
+    };
+
+    /// Settings exported by a version 5 daemon, which still stored the account number and
+    /// WireGuard key in the settings.
+    const V5_DOCUMENT: &str = r#"{
+      "settings": {
+        "account_token": "1234123412341234",
+        "wireguard": {
+          "private_key": "mAdSb0hehTBc/dwS8wWH6K3vJZltD7lyOhm5fdCHRGY="
+        },
+        "relay_settings": {
+          "normal": {
+            "location": {
+              "only": {
+                "country": "se"
+              }
+            },
+            "tunnel_protocol": "any",
+            "wireguard_constraints": {
+              "port": {
+                "only": {
+                  "protocol": "tcp",
+                  "port": "any"
+                }
+              }
+            },
+            "openvpn_constraints": {
+              "port": "any"
+            }
+          }
+        },
+        "allow_lan": true,
+        "settings_version": 5
+      }
+    }"#;
+
+    const V6_DOCUMENT: &str = r#"{
+      "settings": {
+        "relay_settings": {
+          "normal": {
+            "location": {
+              "only": {
+                "city": ["se", "got"]
+              }
+            },
+            "tunnel_protocol": "any",
+            "wireguard_constraints": {
+              "port": "any"
+            },
+            "openvpn_constraints": {
+              "port": "any"
+            }
+          }
+        },
+        "tunnel_options": {
+          "wireguard": {
+            "mtu": 1380,
+            "rotation_interval": null,
+            "use_pq_safe_psk": true
+          }
+        },
+        "settings_version": 6
+      }
+    }"#;
+
+    fn location(settings: &Settings) -> &Constraint<LocationConstraint> {
+        match &settings.relay_settings {
+            RelaySettings::Normal(constraints) => &constraints.location,
+            RelaySettings::CustomTunnelEndpoint(_) => panic!("expected normal relay settings"),
+        }
+    }
+
+    fn with_proxy_password() -> Settings {
+        let mut settings = Settings::default();
+        settings.local_proxy.port = Some(1080);
+        settings.local_proxy.authentication = Some(SocksAuth {
+            username: "user".to_owned(),
+            password: "hunter2".to_owned(),
+        });
+        settings
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let settings = Settings::default();
+        let document = export(&settings, None, false).unwrap();
+        assert_eq!(import(&document).unwrap().settings, settings);
+
+        let settings = with_proxy_password();
+        let document = export(&settings, None, true).unwrap();
+        let imported = import(&document).unwrap();
+        assert_eq!(imported.settings, settings);
+        assert!(imported.device.is_none());
+    }
+
+    #[test]
+    fn test_redacted_secrets() {
+        let document = export(&with_proxy_password(), None, false).unwrap();
+        assert!(!document.contains("hunter2"));
+
+        // The proxy would not work without the password, so the import is refused
+        assert!(matches!(
+            import(&document),
+            Err(Error::RedactedSecret(path)) if path == "local_proxy.authentication.password"
+        ));
+    }
+
+    #[test]
+    fn test_import_v5() {
+        let imported = import(V5_DOCUMENT).unwrap();
+        let settings = imported.settings;
+        assert_eq!(settings.settings_version, CURRENT_SETTINGS_VERSION);
+        assert_eq!(
+            location(&settings),
+            &Constraint::Only(LocationConstraint::Location(
+                GeographicLocationConstraint::Country("se".to_owned())
+            ))
+        );
+        // WireGuard over TCP became an obfuscation setting in version 6
+        assert_eq!(
+            settings.obfuscation_settings.selected_obfuscation,
+            SelectedObfuscation::Udp2Tcp
+        );
+        // The account number and key in old settings are discarded rather than imported
+        assert!(imported.device.is_none());
+    }
+
+    #[test]
+    fn test_import_v6() {
+        let settings = import(V6_DOCUMENT).unwrap().settings;
+        assert_eq!(settings.settings_version, CURRENT_SETTINGS_VERSION);
+        assert_eq!(
+            location(&settings),
+            &Constraint::Only(LocationConstraint::Location(
+                GeographicLocationConstraint::City("se".to_owned(), "got".to_owned())
+            ))
+        );
+        assert_eq!(settings.tunnel_options.wireguard.mtu, Some(1380));
+        assert_eq!(
+            settings.tunnel_options.wireguard.quantum_resistant,
+            QuantumResistantState::On
+        );
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut target = serde_json::json!({
+            "a": { "b": 1, "c": 2 },
+            "d": [1, 2],
+            "e": "kept"
+        });
+        merge_patch(
+            &mut target,
+            &serde_json::json!({
+                "a": { "b": null, "c": 3 },
+                "d": [3],
+                "f": { "g": true }
+            }),
+        );
+        assert_eq!(
+            target,
+            serde_json::json!({
+                "a": { "c": 3 },
+                "d": [3],
+                "e": "kept",
+                "f": { "g": true }
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let settings = with_proxy_password();
+        let patch = r#"{
+          "allow_lan": true,
+          "relay_settings": {
+            "normal": { "location": { "only": { "location": { "country": "se" } } } }
+          },
+          "tunnel_options": { "dns_options": { "default_options": { "block_ads": true } } }
+        }"#;
+        let patched = apply_patch(&settings, patch).unwrap();
+
+        assert!(patched.allow_lan);
+        assert_eq!(
+            location(&patched),
+            &Constraint::Only(LocationConstraint::Location(
+                GeographicLocationConstraint::Country("se".to_owned())
+            ))
+        );
+        assert!(patched.tunnel_options.dns_options.default_options.block_ads);
+        // Everything that is not in the patch is left as it was, including the secrets
+        assert_eq!(patched.local_proxy, settings.local_proxy);
+        assert_eq!(
+            patched
+                .tunnel_options
+                .dns_options
+                .default_options
+                .block_malware,
+            settings
+                .tunnel_options
+                .dns_options
+                .default_options
+                .block_malware
+        );
+    }
+
+    #[test]
+    fn test_invalid_patches() {
+        let settings = Settings::default();
+        assert!(matches!(
+            apply_patch(&settings, "[]"),
+            Err(Error::PatchNotAnObject)
+        ));
+        assert!(matches!(
+            apply_patch(&settings, r#"{ "settings_version": 1 }"#),
+            Err(Error::UnpatchableField(name)) if name == "settings_version"
+        ));
+        assert!(matches!(
+            apply_patch(&settings, r#"{ "tunnel_options": { "dns_optoins": {} } }"#),
+            Err(Error::UnknownField(path)) if path == "tunnel_options.dns_optoins"
+        ));
+        assert!(matches!(
+            apply_patch(&settings, r#"{ "allow_lan": "yes" }"#),
+            Err(Error::InvalidPatch(_))
+        ));
+        // A patch that sets another variant without removing the current one is ambiguous
+        assert!(matches!(
+            apply_patch(
+                &settings,
+                r#"{ "relay_settings": { "custom_tunnel_endpoint": {} } }"#
+            ),
+            Err(Error::InvalidPatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_patched_settings_are_validated_together() {
+        let settings = Settings::default();
+        // Each change is valid on its own, but DNS requests to the custom server would bypass the
+        // tunnel
+        let patch = r#"{
+          "bypass_routes": ["192.0.2.0/24"],
+          "tunnel_options": {
+            "dns_options": {
+              "state": "custom",
+              "custom_options": { "addresses": ["192.0.2.1"] }
+            }
+          }
+        }"#;
+        let patched = apply_patch(&settings, patch).unwrap();
+        assert!(crate::settings::validate_settings(&patched, &[]).is_err());
+    }
+
+    #[test]
+    fn test_import_invalid_version() {
+        assert!(matches!(
+            import(r#"{ "settings": { "allow_lan": true } }"#),
+            Err(Error::MissingVersion)
+        ));
+        assert!(matches!(
+            import(r#"{ "settings": { "settings_version": 1000 } }"#),
+            Err(Error::UnsupportedVersion(1000))
+        ));
+    }
+}
@@ -10,12 +10,13 @@ use tokio::sync::Mutex;
 
 use mullvad_relay_selector::{RelaySelector, SelectedBridge, SelectedObfuscator, SelectedRelay};
 use mullvad_types::{
-    endpoint::MullvadEndpoint, location::GeoIpLocation, relay_list::Relay, settings::TunnelOptions,
+    endpoint::MullvadEndpoint, location::GeoIpLocation, recent_events::EventKind,
+    relay_constraints::RelayOverrides, relay_list::Relay, settings::TunnelOptions,
 };
 use once_cell::sync::Lazy;
 use talpid_core::tunnel_state_machine::TunnelParametersGenerator;
 use talpid_types::{
-    net::{wireguard, TunnelParameters},
+    net::{wireguard, IpVersion, TunnelParameters},
     tunnel::ParameterGenerationError,
     ErrorExt,
 };
@@ -23,7 +24,10 @@ use talpid_types::{
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn;
 
-use crate::device::{AccountManagerHandle, PrivateAccountAndDevice};
+use crate::{
+    device::{AccountManagerHandle, PrivateAccountAndDevice},
+    recent_events::RecentEvents,
+};
 
 /// The IP-addresses that the client uses when it connects to a server that supports the
 /// "Same IP" functionality. This means all clients have the same in-tunnel IP on these
@@ -48,6 +52,9 @@ pub enum Error {
     #[error(display = "No bridge available")]
     NoBridgeAvailable,
 
+    #[error(display = "No bridge with an {} address available", _0)]
+    NoBridgeWithIpVersion(IpVersion),
+
     #[error(display = "Failed to resolve hostname for custom relay")]
     ResolveCustomHostname,
 }
@@ -59,8 +66,14 @@ struct InnerParametersGenerator {
     relay_selector: RelaySelector,
     tunnel_options: TunnelOptions,
     account_manager: AccountManagerHandle,
+    recent_events: RecentEvents,
+
+    /// Parameters to use instead of the relay constraints for the next attempt only.
+    overrides: Option<RelayOverrides>,
 
     last_generated_relays: Option<LastSelectedRelays>,
+    /// IPv4 gateway of the last generated WireGuard tunnel parameters.
+    last_wireguard_gateway: Option<Ipv4Addr>,
 }
 
 impl ParametersGenerator {
@@ -69,14 +82,18 @@ impl ParametersGenerator {
         account_manager: AccountManagerHandle,
         relay_selector: RelaySelector,
         tunnel_options: TunnelOptions,
+        recent_events: RecentEvents,
     ) -> Self {
         Self(Arc::new(Mutex::new(InnerParametersGenerator {
             tunnel_options,
             relay_selector,
 
             account_manager,
+            recent_events,
 
+            overrides: None,
             last_generated_relays: None,
+            last_wireguard_gateway: None,
         })))
     }
 
@@ -85,6 +102,17 @@ impl ParametersGenerator {
         self.0.lock().await.tunnel_options = tunnel_options.clone();
     }
 
+    /// Sets parameters that replace the relay constraints for the next generated tunnel
+    /// parameters. The attempts after that use the constraints again.
+    pub async fn set_overrides(&self, overrides: RelayOverrides) {
+        self.0.lock().await.overrides = Some(overrides);
+    }
+
+    /// Gets the in-tunnel IPv4 gateway of the last generated WireGuard tunnel parameters.
+    pub async fn get_last_wireguard_gateway(&self) -> Option<Ipv4Addr> {
+        self.0.lock().await.last_wireguard_gateway
+    }
+
     /// Gets the location associated with the last generated tunnel parameters.
     pub async fn get_last_location(&self) -> Option<GeoIpLocation> {
         let inner = self.0.lock().await;
@@ -140,9 +168,16 @@ impl ParametersGenerator {
 impl InnerParametersGenerator {
     async fn generate(&mut self, retry_attempt: u32) -> Result<TunnelParameters, Error> {
         let _data = self.device().await?;
-        match self.relay_selector.get_relay(retry_attempt) {
+        let selected_relay = match self.overrides.take() {
+            Some(overrides) => self
+                .relay_selector
+                .get_relay_with_overrides(&overrides, retry_attempt),
+            None => self.relay_selector.get_relay(retry_attempt),
+        };
+        match selected_relay {
             Ok((SelectedRelay::Custom(custom_relay), _bridge, _obfsucator)) => {
                 self.last_generated_relays = None;
+                self.last_wireguard_gateway = None;
                 custom_relay
                     // TODO: generate proxy settings for custom tunnels
                     .to_tunnel_parameters(self.tunnel_options.clone(), None)
@@ -162,6 +197,9 @@ impl InnerParametersGenerator {
                 .await
             }
             Err(mullvad_relay_selector::Error::NoBridge) => Err(Error::NoBridgeAvailable),
+            Err(mullvad_relay_selector::Error::NoBridgeWithIpVersion(ip_version)) => {
+                Err(Error::NoBridgeWithIpVersion(ip_version))
+            }
             Err(_error) => Err(Error::NoRelayAvailable),
         }
     }
@@ -191,6 +229,7 @@ impl InnerParametersGenerator {
                     relay: relay.clone(),
                     bridge: bridge_relay,
                 });
+                self.last_wireguard_gateway = None;
 
                 Ok(openvpn::TunnelParameters {
                     config: openvpn::ConnectionConfig::new(
@@ -234,6 +273,7 @@ impl InnerParametersGenerator {
                     wg_exit: relay.clone(),
                     obfuscator: obfuscator_relay,
                 });
+                self.last_wireguard_gateway = Some(endpoint.ipv4_gateway);
 
                 Ok(wireguard::TunnelParameters {
                     connection: wireguard::ConnectionConfig {
@@ -258,6 +298,50 @@ impl InnerParametersGenerator {
         }
     }
 
+    /// Records the relays that were selected for a connection attempt, or why there were none.
+    fn record_selection(&self, retry_attempt: u32, result: &Result<TunnelParameters, Error>) {
+        let parameters = match result {
+            Ok(parameters) => parameters,
+            Err(error) => {
+                self.recent_events.push(
+                    EventKind::RelaySelection,
+                    format!("No relay selected for attempt {retry_attempt}: {error}"),
+                );
+                return;
+            }
+        };
+        let hostname = |role: &str, relay: &Option<Relay>| {
+            relay
+                .as_ref()
+                .map(|relay| format!(", {role} {}", relay.hostname))
+                .unwrap_or_default()
+        };
+        let relays = match &self.last_generated_relays {
+            Some(LastSelectedRelays::WireGuard {
+                wg_entry,
+                wg_exit,
+                obfuscator,
+            }) => format!(
+                "{}{}{}",
+                wg_exit.hostname,
+                hostname("entry", wg_entry),
+                hostname("obfuscator", obfuscator)
+            ),
+            #[cfg(not(target_os = "android"))]
+            Some(LastSelectedRelays::OpenVpn { relay, bridge }) => {
+                format!("{}{}", relay.hostname, hostname("bridge", bridge))
+            }
+            None => "custom relay".to_owned(),
+        };
+        self.recent_events.push(
+            EventKind::RelaySelection,
+            format!(
+                "Selected {relays} for attempt {retry_attempt}: {}",
+                parameters.get_tunnel_endpoint()
+            ),
+        );
+    }
+
     async fn device(&self) -> Result<PrivateAccountAndDevice, Error> {
         self.account_manager
             .data()
@@ -277,22 +361,25 @@ impl TunnelParametersGenerator for ParametersGenerator {
         let generator = self.0.clone();
         Box::pin(async move {
             let mut inner = generator.lock().await;
-            inner
-                .generate(retry_attempt)
-                .await
-                .map_err(|error| match error {
-                    Error::NoBridgeAvailable => ParameterGenerationError::NoMatchingBridgeRelay,
-                    Error::ResolveCustomHostname => {
-                        ParameterGenerationError::CustomTunnelHostResultionError
-                    }
-                    error => {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg("Failed to generate tunnel parameters")
-                        );
-                        ParameterGenerationError::NoMatchingRelay
-                    }
-                })
+            let result = inner.generate(retry_attempt).await;
+            inner.record_selection(retry_attempt, &result);
+            result.map_err(|error| match error {
+                Error::NoBridgeAvailable => ParameterGenerationError::NoMatchingBridgeRelay,
+                Error::NoBridgeWithIpVersion(_) => {
+                    log::error!("{}", error);
+                    ParameterGenerationError::NoMatchingBridgeRelay
+                }
+                Error::ResolveCustomHostname => {
+                    ParameterGenerationError::CustomTunnelHostResultionError
+                }
+                error => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to generate tunnel parameters")
+                    );
+                    ParameterGenerationError::NoMatchingRelay
+                }
+            })
         })
     }
 }
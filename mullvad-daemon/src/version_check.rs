@@ -4,7 +4,11 @@ use futures::{
     stream::FusedStream,
     FutureExt, SinkExt, StreamExt, TryFutureExt,
 };
-use mullvad_api::{availability::ApiAvailabilityHandle, rest::MullvadRestHandle, AppVersionProxy};
+use mullvad_api::{
+    availability::ApiAvailabilityHandle,
+    rest::{MullvadRestHandle, RequestClass},
+    AppVersionProxy,
+};
 use mullvad_types::version::{AppVersionInfo, ParsedAppVersion};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
@@ -25,8 +29,6 @@ static APP_VERSION: Lazy<ParsedAppVersion> =
     Lazy::new(|| ParsedAppVersion::from_str(mullvad_version::VERSION).unwrap());
 static IS_DEV_BUILD: Lazy<bool> = Lazy::new(|| APP_VERSION.is_dev());
 
-const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(15);
-
 /// Wait this long until next check after a successful check
 const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
 /// Wait this long until next try if an update failed
@@ -99,7 +101,7 @@ pub(crate) struct VersionUpdater {
     show_beta_releases: bool,
     rx: Option<mpsc::Receiver<VersionUpdaterCommand>>,
     availability_handle: ApiAvailabilityHandle,
-    internal_done_tx: Option<oneshot::Sender<AppVersionInfo>>,
+    internal_done_tx: Option<oneshot::Sender<Result<AppVersionInfo, Error>>>,
 }
 
 #[derive(Clone)]
@@ -109,7 +111,7 @@ pub(crate) struct VersionUpdaterHandle {
 
 enum VersionUpdaterCommand {
     SetShowBetaReleases(bool),
-    RunVersionCheck(oneshot::Sender<AppVersionInfo>),
+    RunVersionCheck(oneshot::Sender<Result<AppVersionInfo, Error>>),
 }
 
 impl VersionUpdaterHandle {
@@ -136,9 +138,36 @@ impl VersionUpdaterHandle {
         {
             Err(Error::VersionUpdaterDown)
         } else {
-            done_rx.await.map_err(|_| Error::UpdateAborted)
+            done_rx.await.map_err(|_| Error::UpdateAborted)?
         }
     }
+
+    /// Fetches the version info now, regardless of when it was last fetched. The suggested
+    /// upgrade is the newest version in the beta channel if `beta` is set, and the newest stable
+    /// version otherwise.
+    pub async fn check_version(&mut self, beta: bool) -> Result<AppVersionInfo, Error> {
+        let version_info = self.run_version_check().await?;
+        Ok(version_info_for_channel(version_info, &APP_VERSION, beta))
+    }
+}
+
+/// Returns `version_info` with the upgrade that is suggested for `current_version` in the beta
+/// channel, or in the stable channel.
+fn version_info_for_channel(
+    version_info: AppVersionInfo,
+    current_version: &ParsedAppVersion,
+    beta: bool,
+) -> AppVersionInfo {
+    let suggested_upgrade = VersionUpdater::suggested_upgrade(
+        current_version,
+        &Some(version_info.latest_stable.clone()),
+        &version_info.latest_beta,
+        beta,
+    );
+    AppVersionInfo {
+        suggested_upgrade,
+        ..version_info
+    }
 }
 
 impl VersionUpdater {
@@ -150,7 +179,7 @@ impl VersionUpdater {
         last_app_version_info: Option<AppVersionInfo>,
         show_beta_releases: bool,
     ) -> (Self, VersionUpdaterHandle) {
-        api_handle.factory.timeout = DOWNLOAD_TIMEOUT;
+        api_handle.factory.class = RequestClass::Background;
         let version_proxy = AppVersionProxy::new(api_handle);
         let cache_path = cache_dir.join(VERSION_INFO_FILENAME);
         let (tx, rx) = mpsc::channel(1);
@@ -174,7 +203,7 @@ impl VersionUpdater {
 
     fn create_update_future(
         &mut self,
-        done_tx: oneshot::Sender<AppVersionInfo>,
+        done_tx: oneshot::Sender<Result<AppVersionInfo, Error>>,
     ) -> std::pin::Pin<
         Box<dyn Future<Output = Result<mullvad_api::AppVersionResponse, Error>> + Send + 'static>,
     > {
@@ -311,7 +340,7 @@ impl VersionUpdater {
 
     async fn update_version_info(&mut self, new_version_info: AppVersionInfo) {
         if let Some(done_tx) = self.internal_done_tx.take() {
-            let _ = done_tx.send(new_version_info.clone());
+            let _ = done_tx.send(Ok(new_version_info.clone()));
         }
 
         // if daemon can't be reached, return immediately
@@ -337,7 +366,7 @@ impl VersionUpdater {
             while let Some(cmd) = rx.next().await {
                 if let VersionUpdaterCommand::RunVersionCheck(done_tx) = cmd {
                     log::info!("Version check is disabled in dev builds");
-                    let _ = done_tx.send(dev_version_cache());
+                    let _ = done_tx.send(Ok(dev_version_cache()));
                 }
             }
             return;
@@ -407,7 +436,9 @@ impl VersionUpdater {
                         },
                         Err(err) => {
                             log::error!("Failed to fetch version info: {}", err);
-                            self.internal_done_tx = None;
+                            if let Some(done_tx) = self.internal_done_tx.take() {
+                                let _ = done_tx.send(Err(err));
+                            }
                         },
                     }
 
@@ -528,4 +559,54 @@ mod test {
             None
         );
     }
+
+    #[test]
+    fn test_version_info_for_channel() {
+        // Version info as returned by the API
+        let version_info = |supported: bool, latest_beta: &str| AppVersionInfo {
+            supported,
+            latest_stable: "2020.4".to_owned(),
+            latest_beta: latest_beta.to_owned(),
+            suggested_upgrade: Some("2019.1".to_owned()),
+        };
+        let suggestion = |latest_beta: &str, current_version: &str, beta: bool| {
+            let current_version = ParsedAppVersion::from_str(current_version).unwrap();
+            version_info_for_channel(version_info(true, latest_beta), &current_version, beta)
+                .suggested_upgrade
+        };
+
+        assert_eq!(suggestion("2020.5-beta3", "2020.4", false), None);
+        assert_eq!(
+            suggestion("2020.5-beta3", "2020.4", true).as_deref(),
+            Some("2020.5-beta3")
+        );
+        assert_eq!(
+            suggestion("2020.5-beta3", "2020.3", false).as_deref(),
+            Some("2020.4")
+        );
+        assert_eq!(suggestion("2020.5-beta3", "2020.5-beta3", false), None);
+        assert_eq!(
+            suggestion("2020.5-beta3", "2020.5-beta2", true).as_deref(),
+            Some("2020.5-beta3")
+        );
+        // When the newest release is stable, the beta channel suggests it too
+        assert_eq!(suggestion("2020.4", "2020.4", true), None);
+        assert_eq!(
+            suggestion("2020.3-beta1", "2020.3", true).as_deref(),
+            Some("2020.4")
+        );
+
+        // Only the suggestion depends on the channel
+        let current_version = ParsedAppVersion::from_str("2020.1").unwrap();
+        for beta in [false, true] {
+            let result = version_info_for_channel(
+                version_info(false, "2020.5-beta3"),
+                &current_version,
+                beta,
+            );
+            assert!(!result.supported);
+            assert_eq!(result.latest_stable, "2020.4");
+            assert_eq!(result.latest_beta, "2020.5-beta3");
+        }
+    }
 }
@@ -10,15 +10,32 @@ use std::{
     ffi::{CString, NulError},
     fs,
     io::{self, BufWriter, Write},
-    os::unix::ffi::OsStrExt,
+    os::unix::{ffi::OsStrExt, net::UnixStream},
+    path::{Path, PathBuf},
 };
 
 #[cfg(target_os = "linux")]
-use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
+use talpid_types::cgroup::{
+    find_cgroup2_mount, find_net_cls_mount, invocation_cgroup_name, net_cls_cgroup_of,
+    remove_empty_invocation_cgroups, SPLIT_TUNNEL_CGROUP_NAME,
+};
 
 #[cfg(target_os = "linux")]
 const PROGRAM_NAME: &str = "mullvad-exclude";
 
+/// Exit code for invalid arguments and errors that do not have a more specific exit code.
+#[cfg(target_os = "linux")]
+const EXIT_FAILURE: i32 = 1;
+/// Exit code returned when the daemon is not running.
+#[cfg(target_os = "linux")]
+const EXIT_DAEMON_NOT_RUNNING: i32 = 2;
+/// Exit code returned when the system does not support split tunneling.
+#[cfg(target_os = "linux")]
+const EXIT_SPLIT_TUNNELING_UNSUPPORTED: i32 = 3;
+/// Exit code returned when the process could not be placed in the split tunnel cgroup.
+#[cfg(target_os = "linux")]
+const EXIT_PLACEMENT_FAILED: i32 = 4;
+
 #[cfg(target_os = "linux")]
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -26,8 +43,44 @@ enum Error {
     #[error(display = "Invalid arguments")]
     InvalidArguments,
 
-    #[error(display = "Cannot set the cgroup")]
-    AddProcToCGroup(#[error(source)] io::Error),
+    #[error(
+        display = "The Mullvad VPN daemon is not running. Failed to connect to {:?}",
+        _0
+    )]
+    DaemonNotRunning(PathBuf, #[error(source)] io::Error),
+
+    #[error(
+        display = "The split tunnel cgroup {:?} does not exist. Is the daemon running?",
+        _0
+    )]
+    NoExclusionCGroup(PathBuf),
+
+    #[error(display = "Failed to find net_cls controller")]
+    FindNetClsController(#[error(source)] io::Error),
+
+    #[error(display = "No net_cls controller")]
+    NoNetClsController,
+
+    #[error(
+        display = "No net_cls controller. Only cgroup v2 is mounted, at {:?}",
+        _0
+    )]
+    OnlyCGroup2(PathBuf),
+
+    #[error(display = "Cannot create the cgroup {:?}", _0)]
+    CreateCGroup(PathBuf, #[error(source)] io::Error),
+
+    #[error(display = "Cannot set the class ID of the cgroup {:?}", _0)]
+    SetCGroupClassId(PathBuf, #[error(source)] io::Error),
+
+    #[error(display = "Cannot add the process to the cgroup {:?}", _0)]
+    AddProcToCGroup(PathBuf, #[error(source)] io::Error),
+
+    #[error(display = "Failed to read the cgroup of the process")]
+    ReadProcCGroup(#[error(source)] io::Error),
+
+    #[error(display = "The process is in the cgroup {:?} instead of {:?}", _1, _0)]
+    WrongCGroup(PathBuf, Option<PathBuf>),
 
     #[error(display = "Failed to drop root user privileges for the process")]
     DropRootUid(#[error(source)] nix::Error),
@@ -40,12 +93,22 @@ enum Error {
 
     #[error(display = "An argument contains interior nul bytes")]
     ArgumentNulError(#[error(source)] NulError),
+}
 
-    #[error(display = "Failed to find net_cls controller")]
-    FindNetClsController(#[error(source)] io::Error),
-
-    #[error(display = "No net_cls controller")]
-    NoNetClsController,
+#[cfg(target_os = "linux")]
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Error::DaemonNotRunning(..) | Error::NoExclusionCGroup(..) => EXIT_DAEMON_NOT_RUNNING,
+            Error::NoNetClsController | Error::OnlyCGroup2(..) => EXIT_SPLIT_TUNNELING_UNSUPPORTED,
+            Error::CreateCGroup(..)
+            | Error::SetCGroupClassId(..)
+            | Error::AddProcToCGroup(..)
+            | Error::ReadProcCGroup(..)
+            | Error::WrongCGroup(..) => EXIT_PLACEMENT_FAILED,
+            _ => EXIT_FAILURE,
+        }
+    }
 }
 
 fn main() {
@@ -55,7 +118,7 @@ fn main() {
             let mut args = env::args();
             let program = args.next().unwrap_or_else(|| PROGRAM_NAME.to_string());
             eprintln!("Usage: {program} COMMAND [ARGS]");
-            std::process::exit(1);
+            std::process::exit(EXIT_FAILURE);
         }
         Err(e) => {
             let mut s = format!("{e}");
@@ -66,7 +129,7 @@ fn main() {
             }
             eprintln!("{s}");
 
-            std::process::exit(1);
+            std::process::exit(e.exit_code());
         }
         _ => unreachable!("execv returned unexpectedly"),
     }
@@ -84,23 +147,49 @@ fn run() -> Result<Infallible, Error> {
         .collect::<Result<Vec<CString>, NulError>>()
         .map_err(Error::ArgumentNulError)?;
 
-    let cgroup_dir = find_net_cls_mount()
-        .map_err(Error::FindNetClsController)?
-        .ok_or(Error::NoNetClsController)?;
+    let socket_path = mullvad_paths::get_rpc_socket_path();
+    if let Err(error) = UnixStream::connect(&socket_path) {
+        return Err(Error::DaemonNotRunning(socket_path, error));
+    }
 
-    let procs_path = cgroup_dir
-        .join(SPLIT_TUNNEL_CGROUP_NAME)
-        .join("cgroup.procs");
+    let cgroup_dir = match find_net_cls_mount().map_err(Error::FindNetClsController)? {
+        Some(cgroup_dir) => cgroup_dir,
+        None => match find_cgroup2_mount().map_err(Error::FindNetClsController)? {
+            Some(cgroup2_dir) => return Err(Error::OnlyCGroup2(cgroup2_dir)),
+            None => return Err(Error::NoNetClsController),
+        },
+    };
+
+    // The daemon creates the split tunnel cgroup when it starts
+    let exclusions_dir = cgroup_dir.join(SPLIT_TUNNEL_CGROUP_NAME);
+    if !exclusions_dir.is_dir() {
+        return Err(Error::NoExclusionCGroup(exclusions_dir));
+    }
+    let pid = getpid().as_raw() as u32;
+    let invocation_name = invocation_cgroup_name(pid);
+    let invocation_dir = exclusions_dir.join(&invocation_name);
+    match add_to_invocation_cgroup(&exclusions_dir, &invocation_dir, pid) {
+        // Another invocation may remove the cgroup before this process is added to it
+        Err(Error::AddProcToCGroup(_, error)) if error.kind() == io::ErrorKind::NotFound => {
+            add_to_invocation_cgroup(&exclusions_dir, &invocation_dir, pid)?
+        }
+        result => result?,
+    }
 
-    let file = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(procs_path)
-        .map_err(Error::AddProcToCGroup)?;
+    // The cgroup is kept when the program is executed, so the placement can be verified here
+    let expected = Path::new(SPLIT_TUNNEL_CGROUP_NAME).join(&invocation_name);
+    let actual = net_cls_cgroup_of(pid).map_err(Error::ReadProcCGroup)?;
+    if !actual
+        .as_ref()
+        .map(|actual| actual.ends_with(&expected))
+        .unwrap_or(false)
+    {
+        return Err(Error::WrongCGroup(invocation_dir, actual));
+    }
 
-    BufWriter::new(file)
-        .write_all(getpid().to_string().as_bytes())
-        .map_err(Error::AddProcToCGroup)?;
+    if let Err(error) = remove_empty_invocation_cgroups(&exclusions_dir) {
+        eprintln!("Failed to remove unused cgroups: {error}");
+    }
 
     // Drop root privileges
     let real_uid = getuid();
@@ -111,3 +200,43 @@ fn run() -> Result<Infallible, Error> {
     // Launch the process
     execvp(&program, &args).map_err(Error::Exec)
 }
+
+/// Adds the process `pid` to a cgroup for this invocation in the split tunnel cgroup. All of its
+/// descendants are placed in the same cgroup, even if they daemonize.
+#[cfg(target_os = "linux")]
+fn add_to_invocation_cgroup(
+    exclusions_dir: &Path,
+    invocation_dir: &Path,
+    pid: u32,
+) -> Result<(), Error> {
+    create_invocation_cgroup(exclusions_dir, invocation_dir)?;
+
+    let procs_path = invocation_dir.join("cgroup.procs");
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(&procs_path)
+        .map_err(|error| Error::AddProcToCGroup(procs_path.clone(), error))?;
+
+    BufWriter::new(file)
+        .write_all(pid.to_string().as_bytes())
+        .map_err(|error| Error::AddProcToCGroup(procs_path, error))
+}
+
+/// Creates a cgroup for this invocation in the split tunnel cgroup, with the same class ID.
+#[cfg(target_os = "linux")]
+fn create_invocation_cgroup(exclusions_dir: &Path, invocation_dir: &Path) -> Result<(), Error> {
+    match fs::create_dir(invocation_dir) {
+        Ok(()) => (),
+        // Left behind by an earlier process with the same PID
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(error) => return Err(Error::CreateCGroup(invocation_dir.to_owned(), error)),
+    }
+
+    // New net_cls cgroups inherit the class ID of their parent, but it is set explicitly in case
+    // the split tunnel cgroup was reconfigured after this cgroup was created
+    let classid_path = exclusions_dir.join("net_cls.classid");
+    let classid =
+        fs::read(&classid_path).map_err(|error| Error::SetCGroupClassId(classid_path, error))?;
+    let classid_path = invocation_dir.join("net_cls.classid");
+    fs::write(&classid_path, classid).map_err(|error| Error::SetCGroupClassId(classid_path, error))
+}
@@ -0,0 +1,91 @@
+//! These tests launch programs using the `mullvad-exclude` binary. The ignored tests must be run as
+//! root, with the daemon running, using `cargo test -p mullvad-exclude -- --ignored`.
+
+#![cfg(target_os = "linux")]
+
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::Path,
+    process::{Command, Stdio},
+};
+use talpid_types::cgroup::{
+    find_net_cls_mount, invocation_cgroup_name, net_cls_cgroup_of, SPLIT_TUNNEL_CGROUP_NAME,
+};
+
+const EXCLUDE_BIN: &str = env!("CARGO_BIN_EXE_mullvad-exclude");
+
+/// Prints the PIDs of a background child, of a grandchild that is orphaned when its parent exits,
+/// and of the shell itself, and keeps running until it is killed.
+const FORKING_SCRIPT: &str = r#"
+sleep 60 &
+echo $!
+sh -c 'sleep 60 & echo $!'
+echo $$
+exec sleep 60
+"#;
+
+fn kill(pid: u32) {
+    let _ = Command::new("kill").arg(pid.to_string()).status();
+}
+
+#[test]
+#[ignore]
+fn test_descendants_are_excluded() {
+    let mut child = Command::new(EXCLUDE_BIN)
+        .args(["sh", "-c", FORKING_SCRIPT])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to launch mullvad-exclude");
+
+    let pids: Vec<u32> = BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .take(3)
+        .map(|line| line.unwrap().trim().parse().unwrap())
+        .collect();
+    assert_eq!(pids.len(), 3, "the test program exited early");
+    // mullvad-exclude executes the program, so the shell keeps its PID
+    assert_eq!(pids[2], child.id());
+
+    let expected = Path::new(SPLIT_TUNNEL_CGROUP_NAME).join(invocation_cgroup_name(child.id()));
+    let results: Vec<_> = pids
+        .iter()
+        .map(|pid| (*pid, net_cls_cgroup_of(*pid)))
+        .collect();
+    for pid in &pids {
+        kill(*pid);
+    }
+    let _ = child.wait();
+
+    for (pid, cgroup) in results {
+        let cgroup = cgroup.unwrap();
+        assert!(
+            matches!(&cgroup, Some(cgroup) if cgroup.ends_with(&expected)),
+            "process {pid} is in the cgroup {cgroup:?}"
+        );
+    }
+
+    // Traffic is marked using the class ID of the split tunnel cgroup
+    let exclusions_dir = find_net_cls_mount()
+        .unwrap()
+        .expect("no net_cls controller")
+        .join(SPLIT_TUNNEL_CGROUP_NAME);
+    let classid = fs::read_to_string(exclusions_dir.join("net_cls.classid")).unwrap();
+    let invocation_classid = fs::read_to_string(
+        exclusions_dir
+            .join(invocation_cgroup_name(child.id()))
+            .join("net_cls.classid"),
+    );
+    // The cgroup may already have been removed by the daemon after the processes exited
+    if let Ok(invocation_classid) = invocation_classid {
+        assert_eq!(invocation_classid, classid);
+    }
+}
+
+#[test]
+fn test_missing_program_fails() {
+    let status = Command::new(EXCLUDE_BIN)
+        .status()
+        .expect("failed to launch mullvad-exclude");
+    assert_eq!(status.code(), Some(1));
+}
@@ -7,14 +7,20 @@ use jnix::{
 };
 use mullvad_daemon::EventListener;
 use mullvad_types::{
+    access_method::ApiConnectivity,
+    account::AccountExpiryWarning,
     device::{DeviceEvent, RemoveDeviceEvent},
     relay_list::RelayList,
+    routes::RoutesUpdate,
     settings::Settings,
-    states::TunnelState,
+    states::{IdleDisconnect, NetworkChange, TunnelState},
     version::AppVersionInfo,
 };
 use std::{sync::mpsc, thread};
-use talpid_types::ErrorExt;
+use talpid_types::{
+    split_tunnel::{AppExclusionStatus, ExcludedProcess},
+    ErrorExt,
+};
 
 #[derive(Debug, err_derive::Error)]
 #[error(no_from)]
@@ -71,6 +77,46 @@ impl EventListener for JniEventListener {
     fn notify_remove_device_event(&self, event: RemoveDeviceEvent) {
         let _ = self.0.send(Event::RemoveDeviceEvent(event));
     }
+
+    fn notify_dns_tampered(&self) {
+        // DNS is never monitored for changes on Android.
+    }
+
+    fn notify_network_changed(&self, _change: NetworkChange) {
+        // Network changes are not reported on Android.
+    }
+
+    fn notify_foreign_tunnel(&self, _interface: Option<String>) {
+        // Other VPNs are not detected on Android.
+    }
+
+    fn notify_routes_updated(&self, _update: RoutesUpdate) {
+        // Route updates are not reported on Android.
+    }
+
+    fn notify_split_tunnel_app_status(&self, _statuses: Vec<AppExclusionStatus>) {
+        // Apps are not excluded by the daemon on Android.
+    }
+
+    fn notify_excluded_processes(&self, _processes: Vec<ExcludedProcess>) {
+        // Excluded apps are handled by the app on Android.
+    }
+
+    fn notify_problem_report_sent(&self, _id: String) {
+        // The app is not notified about queued problem reports being sent.
+    }
+
+    fn notify_account_expiry_warning(&self, _warning: AccountExpiryWarning) {
+        // The app reminds the user of the expiry on its own.
+    }
+
+    fn notify_idle_disconnect(&self, _event: IdleDisconnect) {
+        // The app cannot enable disconnecting idle tunnels.
+    }
+
+    fn notify_api_connectivity(&self, _status: ApiConnectivity) {
+        // The app is not told whether the API can be reached.
+    }
 }
 
 struct JniEventHandler<'env> {
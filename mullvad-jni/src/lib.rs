@@ -1227,7 +1227,11 @@ pub extern "system" fn Java_net_mullvad_mullvadvpn_service_MullvadDaemon_setObfu
 
     // SAFETY: The address points to an instance valid for the duration of this function call
     if let Some(daemon_interface) = unsafe { get_daemon_interface(daemon_interface_address) } {
-        let settings = FromJava::from_java(&env, obfuscationSettings);
+        let mut settings: mullvad_types::relay_constraints::ObfuscationSettings =
+            FromJava::from_java(&env, obfuscationSettings);
+        // The app does not configure the order in which obfuscation types are tried
+        settings.obfuscation_priority =
+            mullvad_types::relay_constraints::DEFAULT_OBFUSCATION_PRIORITY.to_vec();
 
         if let Err(error) = daemon_interface.set_obfuscation_settings(settings) {
             log::error!(
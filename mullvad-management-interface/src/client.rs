@@ -2,24 +2,49 @@
 
 use crate::types;
 use futures::{Stream, StreamExt};
+use ipnetwork::IpNetwork;
 use mullvad_types::{
-    access_method::{self, AccessMethod, AccessMethodSetting},
-    account::{AccountData, AccountToken, VoucherSubmission},
+    access_method::{
+        self, AccessMethod, AccessMethodSetting, AccessMethodStats, AccessMethodTestReport,
+        ApiConnectivity,
+    },
+    account::{AccountData, AccountExpiryWarning, AccountToken, SavedAccount, VoucherSubmission},
+    connection_check::ConnectionCheck,
+    connection_history::Connection,
     custom_list::{CustomList, Id},
     device::{Device, DeviceEvent, DeviceId, DeviceState, RemoveDeviceEvent},
+    dns_leak::DnsLeakTestResult,
     location::GeoIpLocation,
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
+    metrics::Metric,
+    network_profiles::NetworkId,
+    network_trust::NetworkTrustSettings,
+    obfuscation_scores::ObfuscationScores,
+    problem_report::{ProblemReportSendResult, QueuedProblemReport},
+    recent_events::Event as RecentEvent,
+    relay_constraints::{
+        BridgeSettings, BridgeState, GeographicLocationConstraint, MatchingRelays,
+        ObfuscationSettings, RelayOverrides, RelaySettingsUpdate,
+    },
     relay_list::RelayList,
-    settings::{DnsOptions, Settings},
-    states::TunnelState,
+    routes::{RouteDump, RoutesUpdate},
+    settings::{
+        AccountExpiryWarnings, DnsOptions, HookSettings, LocalProxySettings, LogRotationSettings,
+        ResetScopes, Settings,
+    },
+    split_tunnel::SplitTunnelWarning,
+    states::{IdleDisconnect, NetworkChange, TunnelState},
     version::AppVersionInfo,
     wireguard::{PublicKey, QuantumResistantState, RotationInterval},
 };
 #[cfg(target_os = "windows")]
 use std::path::Path;
 use std::str::FromStr;
-#[cfg(target_os = "windows")]
-use talpid_types::split_tunnel::ExcludedProcess;
+use std::time::Duration;
+use talpid_types::{
+    net::TransportProtocol,
+    split_tunnel::{AppExclusionStatus, ExcludedDestination, ExcludedProcess, SplitTunnelMode},
+    tunnel::{MockTunnelScript, RetryBackoff, TrafficStats},
+};
 use tonic::{Code, Status};
 
 type Error = super::Error;
@@ -36,6 +61,42 @@ pub enum DaemonEvent {
     AppVersionInfo(AppVersionInfo),
     Device(DeviceEvent),
     RemoveDevice(RemoveDeviceEvent),
+    /// The system DNS config was overwritten by another program and has been restored.
+    DnsTampered,
+    /// The default route or the interface it uses changed.
+    NetworkChanged(NetworkChange),
+    /// Routes were added or removed by connecting, disconnecting, or following a new default
+    /// route.
+    RoutesUpdated(RoutesUpdate),
+    /// The default route started using the tunnel interface of another VPN, or stopped using it
+    /// if the interface is `None`.
+    ForeignTunnel(Option<String>),
+    /// The exclusion of a configured split tunnel app was applied, became pending, or failed.
+    /// The state of every configured app is included.
+    SplitTunnelAppStatus(Vec<AppExclusionStatus>),
+    /// A process started or stopped being excluded from the tunnel. Every process that is
+    /// excluded is included.
+    ExcludedProcesses(Vec<ExcludedProcess>),
+    /// A problem report which had been queued was sent. This contains the ID of the report.
+    ProblemReportSent(String),
+    /// The account expires within one of the thresholds in the settings.
+    AccountExpiryWarning(AccountExpiryWarning),
+    /// The tunnel was disconnected because it had been idle for too long.
+    IdleDisconnect(IdleDisconnect),
+    /// The API became reachable or unreachable.
+    ApiConnectivity(ApiConnectivity),
+}
+
+pub use types::events_listen_request::Category as EventCategory;
+
+/// The events to receive from [`MullvadProxyClient::events_listen_filtered`].
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    /// Only receive events in these categories. All events are received if this is empty.
+    pub categories: Vec<EventCategory>,
+    /// Receive up to this many of the events that the daemon has buffered before any new events,
+    /// oldest first.
+    pub replay: u32,
 }
 
 impl TryFrom<types::daemon_event::Event> for DaemonEvent {
@@ -61,6 +122,40 @@ impl TryFrom<types::daemon_event::Event> for DaemonEvent {
             types::daemon_event::Event::RemoveDevice(event) => RemoveDeviceEvent::try_from(event)
                 .map(DaemonEvent::RemoveDevice)
                 .map_err(Error::InvalidResponse),
+            types::daemon_event::Event::DnsTampered(()) => Ok(DaemonEvent::DnsTampered),
+            types::daemon_event::Event::NetworkChanged(change) => NetworkChange::try_from(change)
+                .map(DaemonEvent::NetworkChanged)
+                .map_err(Error::InvalidResponse),
+            types::daemon_event::Event::RoutesUpdated(update) => RoutesUpdate::try_from(update)
+                .map(DaemonEvent::RoutesUpdated)
+                .map_err(Error::InvalidResponse),
+            types::daemon_event::Event::ForeignTunnel(tunnel) => Ok(DaemonEvent::ForeignTunnel(
+                (!tunnel.interface.is_empty()).then_some(tunnel.interface),
+            )),
+            types::daemon_event::Event::SplitTunnelAppStatus(statuses) => {
+                Vec::<AppExclusionStatus>::try_from(statuses)
+                    .map(DaemonEvent::SplitTunnelAppStatus)
+                    .map_err(Error::InvalidResponse)
+            }
+            types::daemon_event::Event::ExcludedProcesses(processes) => {
+                Ok(DaemonEvent::ExcludedProcesses(Vec::from(processes)))
+            }
+            types::daemon_event::Event::ProblemReportSent(sent) => {
+                Ok(DaemonEvent::ProblemReportSent(sent.id))
+            }
+            types::daemon_event::Event::AccountExpiryWarning(warning) => {
+                AccountExpiryWarning::try_from(warning)
+                    .map(DaemonEvent::AccountExpiryWarning)
+                    .map_err(Error::InvalidResponse)
+            }
+            types::daemon_event::Event::IdleDisconnect(event) => IdleDisconnect::try_from(event)
+                .map(DaemonEvent::IdleDisconnect)
+                .map_err(Error::InvalidResponse),
+            types::daemon_event::Event::ApiConnectivity(status) => {
+                ApiConnectivity::try_from(status)
+                    .map(DaemonEvent::ApiConnectivity)
+                    .map_err(Error::InvalidResponse)
+            }
         }
     }
 }
@@ -98,6 +193,15 @@ impl MullvadProxyClient {
             .into_inner())
     }
 
+    pub async fn reconnect_with_overrides(&mut self, overrides: RelayOverrides) -> Result<bool> {
+        Ok(self
+            .0
+            .reconnect_with_overrides(types::RelayOverrides::from(overrides))
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner())
+    }
+
     pub async fn get_tunnel_state(&mut self) -> Result<TunnelState> {
         let state = self
             .0
@@ -108,10 +212,39 @@ impl MullvadProxyClient {
         TunnelState::try_from(state).map_err(Error::InvalidResponse)
     }
 
+    /// Receive the traffic counters of the current tunnel every `interval`. An item is `None`
+    /// while there is no tunnel. The stream ends when the daemon shuts down.
+    pub async fn tunnel_stats_listen(
+        &mut self,
+        interval: Duration,
+    ) -> Result<impl Stream<Item = Result<Option<TrafficStats>>>> {
+        let interval_ms = u32::try_from(interval.as_millis()).unwrap_or(u32::MAX);
+        let listener = self
+            .0
+            .tunnel_stats_listen(types::TunnelStatsRequest { interval_ms })
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+
+        Ok(listener.map(|item| item.map(Option::from).map_err(Error::Rpc)))
+    }
+
     pub async fn events_listen(&mut self) -> Result<impl Stream<Item = Result<DaemonEvent>>> {
+        self.events_listen_filtered(EventFilter::default()).await
+    }
+
+    /// Listen for the events that match `filter`. The filter is applied by the daemon, so other
+    /// events are never sent.
+    pub async fn events_listen_filtered(
+        &mut self,
+        filter: EventFilter,
+    ) -> Result<impl Stream<Item = Result<DaemonEvent>>> {
         let listener = self
             .0
-            .events_listen(())
+            .events_listen(types::EventsListenRequest {
+                categories: filter.categories.into_iter().map(i32::from).collect(),
+                replay: filter.replay,
+            })
             .await
             .map_err(Error::Rpc)?
             .into_inner();
@@ -130,8 +263,65 @@ impl MullvadProxyClient {
         Ok(())
     }
 
-    pub async fn factory_reset(&mut self) -> Result<()> {
-        self.0.factory_reset(()).await.map_err(Error::Rpc)?;
+    /// Reset the parts of the daemon's state that are selected in `scopes`. If everything is
+    /// reset, the daemon stops afterwards.
+    pub async fn factory_reset(&mut self, scopes: ResetScopes) -> Result<()> {
+        self.0
+            .factory_reset(types::FactoryResetRequest::from(scopes))
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    /// Export the settings as a JSON document that [`Self::import_settings`] accepts. Passwords
+    /// and private keys are left out unless `include_secrets` is set.
+    pub async fn export_settings(&mut self, include_secrets: bool) -> Result<String> {
+        Ok(self
+            .0
+            .export_settings(types::ExportSettingsRequest { include_secrets })
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner())
+    }
+
+    /// Replace all settings with those in a document created by [`Self::export_settings`].
+    pub async fn import_settings(&mut self, document: String) -> Result<()> {
+        self.0.import_settings(document).await.map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    /// Change several settings at once. `patch` is a JSON merge patch against the settings in
+    /// the format of [`Self::export_settings`].
+    pub async fn apply_settings_patch(&mut self, patch: String) -> Result<()> {
+        self.0
+            .apply_settings_patch(patch)
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn save_profile(&mut self, name: String) -> Result<()> {
+        self.0.save_profile(name).await.map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn list_profiles(&mut self) -> Result<Vec<String>> {
+        Ok(self
+            .0
+            .list_profiles(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner()
+            .names)
+    }
+
+    pub async fn apply_profile(&mut self, name: String) -> Result<()> {
+        self.0.apply_profile(name).await.map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn delete_profile(&mut self, name: String) -> Result<()> {
+        self.0.delete_profile(name).await.map_err(Error::Rpc)?;
         Ok(())
     }
 
@@ -154,6 +344,18 @@ impl MullvadProxyClient {
         Ok(AppVersionInfo::from(version_info))
     }
 
+    /// Make the daemon fetch the version info now. The suggested upgrade is the newest version in
+    /// the beta channel if `beta` is set, and the newest stable version otherwise.
+    pub async fn check_version(&mut self, beta: bool) -> Result<AppVersionInfo> {
+        let version_info = self
+            .0
+            .check_version(types::CheckVersionRequest { beta })
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        Ok(AppVersionInfo::from(version_info))
+    }
+
     pub async fn get_relay_locations(&mut self) -> Result<RelayList> {
         let list = self
             .0
@@ -202,6 +404,107 @@ impl MullvadProxyClient {
             })
     }
 
+    pub async fn get_api_access_method_stats(&mut self) -> Result<Vec<AccessMethodStats>> {
+        let stats = self
+            .0
+            .get_api_access_method_stats(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        Vec::<AccessMethodStats>::try_from(stats).map_err(Error::InvalidResponse)
+    }
+
+    /// Returns whether the daemon is currently able to reach the API.
+    pub async fn get_api_connectivity(&mut self) -> Result<ApiConnectivity> {
+        let status = self
+            .0
+            .get_api_connectivity(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        ApiConnectivity::try_from(status).map_err(Error::InvalidResponse)
+    }
+
+    /// Measure how long each stage of reaching the API takes using an access method, without
+    /// switching to it.
+    pub async fn test_api_access_method(
+        &mut self,
+        api_access_method: access_method::Id,
+    ) -> Result<AccessMethodTestReport> {
+        let result = self
+            .0
+            .test_api_access_method(types::Uuid::from(api_access_method))
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        AccessMethodTestReport::try_from(result).map_err(Error::InvalidResponse)
+    }
+
+    /// Add an access method which has been shared as a string, such as a Shadowsocks URI.
+    pub async fn import_api_access_method(&mut self, encoded: String) -> Result<access_method::Id> {
+        let id = self
+            .0
+            .import_api_access_method(encoded)
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        access_method::Id::try_from(id).map_err(Error::InvalidResponse)
+    }
+
+    /// Encode a custom access method as a string which can be shared and imported by others.
+    pub async fn export_api_access_method(
+        &mut self,
+        api_access_method: access_method::Id,
+    ) -> Result<String> {
+        Ok(self
+            .0
+            .export_api_access_method(types::Uuid::from(api_access_method))
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner())
+    }
+
+    /// Replace the API host and address. This fails unless the daemon is a development build.
+    pub async fn set_api_endpoint_override(
+        &mut self,
+        host: String,
+        address: std::net::SocketAddr,
+    ) -> Result<()> {
+        self.0
+            .set_api_endpoint_override(types::ApiEndpointOverride {
+                host,
+                address: address.to_string(),
+            })
+            .await
+            .map_err(Error::Rpc)
+            .map(drop)
+    }
+
+    pub async fn clear_api_endpoint_override(&mut self) -> Result<()> {
+        self.0
+            .clear_api_endpoint_override(())
+            .await
+            .map_err(Error::Rpc)
+            .map(drop)
+    }
+
+    /// Change the failures that the mock tunnel simulates. This fails unless the daemon is built
+    /// with the mock tunnel, and it is enabled.
+    pub async fn script_mock_tunnel(&mut self, script: MockTunnelScript) -> Result<()> {
+        self.0
+            .script_mock_tunnel(types::MockTunnelScript {
+                fail_connects: script.fail_connects,
+                connect_delay: script
+                    .connect_delay
+                    .and_then(|delay| types::Duration::try_from(delay).ok()),
+                drop_tunnel: script.drop_tunnel,
+                offline: script.offline,
+            })
+            .await
+            .map_err(Error::Rpc)
+            .map(drop)
+    }
+
     pub async fn get_api_addresses(&mut self) -> Result<Vec<std::net::SocketAddr>> {
         self.0
             .get_api_addresses(())
@@ -230,6 +533,22 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    /// Count the relays that would match the relay constraints after `update`, without applying
+    /// it.
+    pub async fn preview_relay_settings(
+        &mut self,
+        update: RelaySettingsUpdate,
+    ) -> Result<MatchingRelays> {
+        let update = types::RelaySettingsUpdate::from(update);
+        let matching_relays = self
+            .0
+            .preview_relay_settings(update)
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        Ok(MatchingRelays::from(matching_relays))
+    }
+
     pub async fn get_current_location(&mut self) -> Result<GeoIpLocation> {
         let location = self
             .0
@@ -264,6 +583,44 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    pub async fn set_network_obfuscation_profiles(
+        &mut self,
+        profiles: &[(NetworkId, ObfuscationSettings)],
+    ) -> Result<()> {
+        let profiles = types::NetworkObfuscationProfiles::from(profiles);
+        self.0
+            .set_network_obfuscation_profiles(profiles)
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_network_trust_settings(
+        &mut self,
+        settings: &NetworkTrustSettings,
+    ) -> Result<()> {
+        let settings = types::NetworkTrustSettings::from(settings);
+        self.0
+            .set_network_trust_settings(settings)
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn get_active_obfuscation_profile(&mut self) -> Result<Option<NetworkId>> {
+        let profile = self
+            .0
+            .get_active_obfuscation_profile(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        profile
+            .network
+            .map(NetworkId::try_from)
+            .transpose()
+            .map_err(Error::InvalidResponse)
+    }
+
     pub async fn get_settings(&mut self) -> Result<Settings> {
         let settings = self
             .0
@@ -279,6 +636,22 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    pub async fn set_excluded_apps_allow_lan(&mut self, allow: bool) -> Result<()> {
+        self.0
+            .set_excluded_apps_allow_lan(allow)
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_bypass_routes(&mut self, routes: Vec<IpNetwork>) -> Result<()> {
+        self.0
+            .set_bypass_routes(types::BypassRoutes::from(&routes[..]))
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
     pub async fn set_show_beta_releases(&mut self, state: bool) -> Result<()> {
         self.0
             .set_show_beta_releases(state)
@@ -295,6 +668,22 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    pub async fn set_coexistence_mode(&mut self, enabled: bool) -> Result<()> {
+        self.0
+            .set_coexistence_mode(enabled)
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_prefer_api_in_tunnel(&mut self, enabled: bool) -> Result<()> {
+        self.0
+            .set_prefer_api_in_tunnel(enabled)
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
     pub async fn set_auto_connect(&mut self, state: bool) -> Result<()> {
         self.0.set_auto_connect(state).await.map_err(Error::Rpc)?;
         Ok(())
@@ -339,6 +728,31 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    pub async fn run_dns_leak_test(&mut self) -> Result<DnsLeakTestResult> {
+        let result = self
+            .0
+            .run_dns_leak_test(())
+            .await
+            .map_err(map_dns_leak_test_error)?
+            .into_inner();
+        DnsLeakTestResult::try_from(result).map_err(Error::InvalidResponse)
+    }
+
+    /// Check where traffic leaves for the internet. This fails unless connected, or unless
+    /// `allow_disconnected` is set.
+    pub async fn check_connection(&mut self, allow_disconnected: bool) -> Result<ConnectionCheck> {
+        let result = self
+            .0
+            .check_connection(types::CheckConnectionRequest { allow_disconnected })
+            .await
+            .map_err(|status| match status.code() {
+                Code::FailedPrecondition => Error::ConnectionCheckNotConnected,
+                _other => Error::Rpc(status),
+            })?
+            .into_inner();
+        ConnectionCheck::try_from(result).map_err(Error::InvalidResponse)
+    }
+
     pub async fn create_new_account(&mut self) -> Result<AccountToken> {
         Ok(self
             .0
@@ -386,6 +800,44 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    pub async fn list_saved_accounts(&mut self) -> Result<Vec<SavedAccount>> {
+        let accounts = self
+            .0
+            .list_saved_accounts(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        Ok(accounts
+            .accounts
+            .into_iter()
+            .map(SavedAccount::from)
+            .collect())
+    }
+
+    pub async fn set_saved_account_label(
+        &mut self,
+        account: AccountToken,
+        label: Option<String>,
+    ) -> Result<()> {
+        self.0
+            .set_saved_account_label(types::SavedAccountLabel {
+                account_token: account,
+                label: label.unwrap_or_default(),
+            })
+            .await
+            .map_err(map_saved_account_error)?;
+        Ok(())
+    }
+
+    /// Log in to a saved account, given its label or account number.
+    pub async fn switch_account(&mut self, label_or_account: String) -> Result<()> {
+        self.0
+            .switch_account(label_or_account)
+            .await
+            .map_err(map_saved_account_error)?;
+        Ok(())
+    }
+
     // get_www_auth_token
 
     pub async fn submit_voucher(&mut self, voucher: String) -> Result<VoucherSubmission> {
@@ -395,6 +847,16 @@ impl MullvadProxyClient {
             .await
             .map_err(|error| match error.code() {
                 Code::NotFound => Error::InvalidVoucher,
+                Code::ResourceExhausted if error.details() == crate::RATE_LIMITED_DETAILS => {
+                    Error::VoucherRateLimited(
+                        error
+                            .metadata()
+                            .get(crate::RETRY_AFTER_METADATA_KEY)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(|value| value.parse().ok())
+                            .map(Duration::from_secs),
+                    )
+                }
                 Code::ResourceExhausted => Error::UsedVoucher,
                 _other => Error::Rpc(error),
             })?
@@ -507,6 +969,29 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    /// Replaces all locations of the custom list called `name` at once, creating the list if it
+    /// does not exist.
+    pub async fn replace_custom_list(
+        &mut self,
+        name: String,
+        locations: impl IntoIterator<Item = GeographicLocationConstraint>,
+    ) -> Result<Id> {
+        let request = types::ReplaceCustomListRequest {
+            name,
+            locations: locations
+                .into_iter()
+                .map(types::RelayLocation::from)
+                .collect(),
+        };
+        let id = self
+            .0
+            .replace_custom_list(request)
+            .await
+            .map_err(map_custom_list_error)?
+            .into_inner();
+        Id::from_str(&id).map_err(|_| Error::CustomListListNotFound)
+    }
+
     pub async fn add_access_method(
         &mut self,
         name: String,
@@ -604,6 +1089,137 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    pub async fn set_split_tunnel_owners(
+        &mut self,
+        users: Vec<u32>,
+        groups: Vec<u32>,
+    ) -> Result<()> {
+        self.0
+            .set_split_tunnel_owners(types::SplitTunnelOwners { users, groups })
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_split_tunnel_mode(&mut self, mode: SplitTunnelMode) -> Result<()> {
+        self.0
+            .set_split_tunnel_mode(types::SplitTunnelMode::from(mode))
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_split_tunnel_destinations(
+        &mut self,
+        destinations: Vec<ExcludedDestination>,
+    ) -> Result<()> {
+        let destinations = destinations
+            .into_iter()
+            .map(types::SplitTunnelDestination::from)
+            .collect();
+        self.0
+            .set_split_tunnel_destinations(types::SplitTunnelDestinations { destinations })
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_split_tunnel_interfaces(&mut self, interfaces: Vec<String>) -> Result<()> {
+        self.0
+            .set_split_tunnel_interfaces(types::SplitTunnelInterfaces { interfaces })
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_split_tunnel_local_ports(
+        &mut self,
+        ports: Vec<(TransportProtocol, u16)>,
+    ) -> Result<()> {
+        let ports = ports
+            .into_iter()
+            .map(types::SplitTunnelLocalPort::from)
+            .collect();
+        self.0
+            .set_split_tunnel_local_ports(types::SplitTunnelLocalPorts { ports })
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_local_proxy_settings(&mut self, settings: &LocalProxySettings) -> Result<()> {
+        self.0
+            .set_local_proxy_settings(types::LocalProxySettings::from(settings))
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_hook_settings(&mut self, settings: &HookSettings) -> Result<()> {
+        self.0
+            .set_hook_settings(types::HookSettings::from(settings))
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_account_expiry_warnings(
+        &mut self,
+        warnings: AccountExpiryWarnings,
+    ) -> Result<()> {
+        self.0
+            .set_account_expiry_warnings(types::AccountExpiryWarnings::from(&warnings))
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_log_rotation_settings(&mut self, rotation: LogRotationSettings) -> Result<()> {
+        self.0
+            .set_log_rotation_settings(types::LogRotationSettings::from(&rotation))
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    /// Disconnect the tunnel once it has been idle for `timeout`, or never if it is `None`.
+    pub async fn set_auto_disconnect_idle(&mut self, timeout: Option<Duration>) -> Result<()> {
+        match timeout {
+            Some(timeout) => {
+                let duration =
+                    types::Duration::try_from(timeout).map_err(|_| Error::DurationTooLarge)?;
+                self.0.set_auto_disconnect_idle(duration).await
+            }
+            None => self.0.clear_auto_disconnect_idle(()).await,
+        }
+        .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    pub async fn set_retry_backoff(&mut self, backoff: RetryBackoff) -> Result<()> {
+        self.0
+            .set_retry_backoff(types::RetryBackoff::from(&backoff))
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    /// Returns the split tunnel configurations in `settings` that may not work as expected. The
+    /// settings are not applied.
+    pub async fn validate_split_tunnel_config(
+        &mut self,
+        settings: &Settings,
+    ) -> Result<Vec<SplitTunnelWarning>> {
+        let warnings = self
+            .0
+            .validate_split_tunnel_config(types::Settings::from(settings))
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        Ok(Vec::<SplitTunnelWarning>::from(warnings))
+    }
+
     #[cfg(target_os = "windows")]
     pub async fn add_split_tunnel_app<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
         let path = path.as_ref().to_str().ok_or(Error::PathMustBeUtf8)?;
@@ -624,6 +1240,24 @@ impl MullvadProxyClient {
         Ok(())
     }
 
+    #[cfg(target_os = "windows")]
+    pub async fn add_split_tunnel_publisher(&mut self, publisher: String) -> Result<()> {
+        self.0
+            .add_split_tunnel_publisher(publisher)
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    pub async fn remove_split_tunnel_publisher(&mut self, publisher: String) -> Result<()> {
+        self.0
+            .remove_split_tunnel_publisher(publisher)
+            .await
+            .map_err(Error::Rpc)?;
+        Ok(())
+    }
+
     #[cfg(target_os = "windows")]
     pub async fn clear_split_tunnel_apps(&mut self) -> Result<()> {
         self.0
@@ -642,7 +1276,7 @@ impl MullvadProxyClient {
         Ok(())
     }
 
-    #[cfg(target_os = "windows")]
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
     pub async fn get_excluded_processes(&mut self) -> Result<Vec<ExcludedProcess>> {
         let procs = self
             .0
@@ -650,14 +1284,133 @@ impl MullvadProxyClient {
             .await
             .map_err(Error::Rpc)?
             .into_inner();
-        Ok(procs
-            .processes
-            .into_iter()
-            .map(ExcludedProcess::from)
-            .collect::<Vec<_>>())
+        Ok(Vec::<ExcludedProcess>::from(procs))
+    }
+
+    #[cfg(target_os = "windows")]
+    pub async fn get_split_tunnel_app_status(&mut self) -> Result<Vec<AppExclusionStatus>> {
+        let statuses = self
+            .0
+            .get_split_tunnel_app_status(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        Vec::<AppExclusionStatus>::try_from(statuses).map_err(Error::InvalidResponse)
     }
 
     // check_volumes
+
+    /// Queues a collected problem report so that the daemon sends it once the API can be reached.
+    /// Returns the ID of the queued report.
+    pub async fn queue_problem_report(
+        &mut self,
+        email: String,
+        message: String,
+        report: String,
+    ) -> Result<String> {
+        Ok(self
+            .0
+            .queue_problem_report(types::QueueProblemReportRequest {
+                email,
+                message,
+                report,
+            })
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner())
+    }
+
+    pub async fn get_queued_problem_reports(&mut self) -> Result<Vec<QueuedProblemReport>> {
+        self.0
+            .get_queued_problem_reports(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner()
+            .reports
+            .into_iter()
+            .map(QueuedProblemReport::try_from)
+            .collect::<std::result::Result<_, _>>()
+            .map_err(Error::InvalidResponse)
+    }
+
+    /// Tries to send every queued problem report once.
+    pub async fn flush_queued_problem_reports(&mut self) -> Result<Vec<ProblemReportSendResult>> {
+        Ok(self
+            .0
+            .flush_queued_problem_reports(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner()
+            .results
+            .into_iter()
+            .map(ProblemReportSendResult::from)
+            .collect())
+    }
+
+    pub async fn get_routes(&mut self) -> Result<RouteDump> {
+        let dump = self
+            .0
+            .get_routes(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        RouteDump::try_from(dump).map_err(Error::InvalidResponse)
+    }
+
+    pub async fn get_obfuscation_scores(&mut self) -> Result<ObfuscationScores> {
+        let scores = self
+            .0
+            .get_obfuscation_scores(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        ObfuscationScores::try_from(scores).map_err(Error::InvalidResponse)
+    }
+
+    pub async fn get_metrics(&mut self) -> Result<Vec<Metric>> {
+        let metrics = self
+            .0
+            .get_metrics(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        metrics
+            .metrics
+            .into_iter()
+            .map(Metric::try_from)
+            .collect::<std::result::Result<_, _>>()
+            .map_err(Error::InvalidResponse)
+    }
+
+    pub async fn get_recent_events(&mut self) -> Result<Vec<RecentEvent>> {
+        let events = self
+            .0
+            .get_recent_events(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        events
+            .events
+            .into_iter()
+            .map(RecentEvent::try_from)
+            .collect::<std::result::Result<_, _>>()
+            .map_err(Error::InvalidResponse)
+    }
+
+    pub async fn get_connection_history(&mut self) -> Result<Vec<Connection>> {
+        let history = self
+            .0
+            .get_connection_history(())
+            .await
+            .map_err(Error::Rpc)?
+            .into_inner();
+        history
+            .connections
+            .into_iter()
+            .map(Connection::try_from)
+            .collect::<std::result::Result<_, _>>()
+            .map_err(Error::InvalidResponse)
+    }
 }
 
 fn map_device_error(status: Status) -> Error {
@@ -670,6 +1423,13 @@ fn map_device_error(status: Status) -> Error {
     }
 }
 
+fn map_saved_account_error(status: Status) -> Error {
+    match status.code() {
+        Code::NotFound => Error::SavedAccountNotFound,
+        _other => map_device_error(status),
+    }
+}
+
 fn map_location_error(status: Status) -> Error {
     match status.code() {
         Code::NotFound => Error::NoLocationData,
@@ -677,6 +1437,14 @@ fn map_location_error(status: Status) -> Error {
     }
 }
 
+fn map_dns_leak_test_error(status: Status) -> Error {
+    match status.code() {
+        Code::FailedPrecondition => Error::DnsLeakTestNotConnected,
+        Code::DeadlineExceeded => Error::DnsLeakTestTimeout,
+        _other => Error::Rpc(status),
+    }
+}
+
 fn map_custom_list_error(status: Status) -> Error {
     match status.code() {
         Code::NotFound => {
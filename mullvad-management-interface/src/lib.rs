@@ -9,6 +9,7 @@ use std::{
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tonic::transport::{server::Connected, Endpoint, Server, Uri};
@@ -28,6 +29,9 @@ static MULLVAD_MANAGEMENT_SOCKET_GROUP: Lazy<Option<String>> =
 
 pub const CUSTOM_LIST_LIST_NOT_FOUND_DETAILS: &[u8] = b"custom_list_list_not_found";
 pub const CUSTOM_LIST_LIST_EXISTS_DETAILS: &[u8] = b"custom_list_list_exists";
+pub const RATE_LIMITED_DETAILS: &[u8] = b"rate_limited";
+/// Metadata key for the number of seconds to wait before retrying a rate limited request.
+pub const RETRY_AFTER_METADATA_KEY: &str = "retry-after";
 
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -77,6 +81,9 @@ pub enum Error {
     #[error(display = "This voucher code has already been used")]
     UsedVoucher,
 
+    #[error(display = "Too many attempts to redeem a voucher")]
+    VoucherRateLimited(Option<Duration>),
+
     #[error(display = "There are too many devices on the account. One must be revoked to log in")]
     TooManyDevices,
 
@@ -89,6 +96,9 @@ pub enum Error {
     #[error(display = "There is no such device")]
     DeviceNotFound,
 
+    #[error(display = "There is no saved account with that label or account number")]
+    SavedAccountNotFound,
+
     #[error(display = "Location data is unavailable")]
     NoLocationData,
 
@@ -109,6 +119,15 @@ pub enum Error {
 
     #[error(display = "An access method with that id does not exist")]
     ApiAccessMethodNotFound,
+
+    #[error(display = "The DNS leak test can only be run while connected")]
+    DnsLeakTestNotConnected,
+
+    #[error(display = "The DNS leak test timed out")]
+    DnsLeakTestTimeout,
+
+    #[error(display = "The connection can only be checked while connected")]
+    ConnectionCheckNotConnected,
 }
 
 #[deprecated(note = "Prefer MullvadProxyClient")]
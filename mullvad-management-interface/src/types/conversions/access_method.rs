@@ -44,8 +44,8 @@ mod settings {
 mod data {
     use crate::types::{proto, FromProtobufTypeError};
     use mullvad_types::access_method::{
-        AccessMethod, AccessMethodSetting, BuiltInAccessMethod, CustomAccessMethod, Id,
-        Shadowsocks, Socks5, Socks5Local, Socks5Remote, SocksAuth,
+        AccessMethod, AccessMethodSetting, BuiltInAccessMethod, CustomAccessMethod, HttpProxy,
+        HttpProxyAuth, Id, Shadowsocks, Socks5, Socks5Local, Socks5Remote, SocksAuth,
     };
 
     impl TryFrom<proto::AccessMethodSetting> for AccessMethodSetting {
@@ -67,12 +67,9 @@ mod data {
                 ))
                 .and_then(AccessMethod::try_from)?;
 
-            Ok(AccessMethodSetting::with_id(
-                id,
-                name,
-                enabled,
-                access_method,
-            ))
+            let mut setting = AccessMethodSetting::with_id(id, name, enabled, access_method);
+            setting.imported = value.imported;
+            Ok(setting)
         }
     }
 
@@ -86,6 +83,7 @@ mod data {
                 name,
                 enabled,
                 access_method: Some(proto::AccessMethod::from(value.access_method)),
+                imported: value.imported,
             }
         }
     }
@@ -113,6 +111,9 @@ mod data {
                 proto::access_method::AccessMethod::Shadowsocks(shadowsocks) => {
                     AccessMethod::try_from(shadowsocks)?
                 }
+                proto::access_method::AccessMethod::HttpProxy(http_proxy) => {
+                    AccessMethod::try_from(http_proxy)?
+                }
             })
         }
     }
@@ -187,6 +188,19 @@ mod data {
         }
     }
 
+    impl TryFrom<proto::access_method::HttpProxy> for AccessMethod {
+        type Error = FromProtobufTypeError;
+
+        fn try_from(value: proto::access_method::HttpProxy) -> Result<Self, Self::Error> {
+            let authentication = value.authentication.map(HttpProxyAuth::from);
+            HttpProxy::from_args(value.ip, value.port as u16, authentication)
+                .ok_or(FromProtobufTypeError::InvalidArgument(
+                    "Could not parse HTTP proxy message from protobuf",
+                ))
+                .map(AccessMethod::from)
+        }
+    }
+
     impl From<BuiltInAccessMethod> for proto::AccessMethod {
         fn from(value: BuiltInAccessMethod) -> Self {
             let access_method = match value {
@@ -235,6 +249,17 @@ mod data {
                         authentication: authentication.map(proto::access_method::SocksAuth::from),
                     },
                 ),
+                CustomAccessMethod::HttpProxy(HttpProxy {
+                    peer,
+                    authentication,
+                }) => {
+                    proto::access_method::AccessMethod::HttpProxy(proto::access_method::HttpProxy {
+                        ip: peer.ip().to_string(),
+                        port: peer.port() as u32,
+                        authentication: authentication
+                            .map(proto::access_method::HttpProxyAuth::from),
+                    })
+                }
             };
 
             proto::AccessMethod {
@@ -279,6 +304,24 @@ mod data {
         }
     }
 
+    impl From<HttpProxyAuth> for proto::access_method::HttpProxyAuth {
+        fn from(value: HttpProxyAuth) -> Self {
+            proto::access_method::HttpProxyAuth {
+                username: value.username,
+                password: value.password,
+            }
+        }
+    }
+
+    impl From<proto::access_method::HttpProxyAuth> for HttpProxyAuth {
+        fn from(value: proto::access_method::HttpProxyAuth) -> Self {
+            Self {
+                username: value.username,
+                password: value.password,
+            }
+        }
+    }
+
     impl TryFrom<&proto::AccessMethodSetting> for AccessMethodSetting {
         type Error = FromProtobufTypeError;
 
@@ -287,3 +330,207 @@ mod data {
         }
     }
 }
+
+/// Implements conversions for the [`crate::types::proto::ApiAccessMethodStats`] type to a list
+/// of [`mullvad_types::access_method::AccessMethodStats`].
+mod stats {
+    use crate::types::{proto, FromProtobufTypeError};
+    use mullvad_types::access_method::{AccessMethodStats, Id};
+    use prost_types::Timestamp;
+    use std::time::SystemTime;
+
+    impl From<&[AccessMethodStats]> for proto::ApiAccessMethodStats {
+        fn from(stats: &[AccessMethodStats]) -> Self {
+            proto::ApiAccessMethodStats {
+                methods: stats
+                    .iter()
+                    .map(|stats| proto::api_access_method_stats::Method {
+                        id: Some(proto::Uuid::from(stats.id.clone())),
+                        successes: stats.successes,
+                        failures: stats.failures,
+                        last_error: stats.last_error.clone().unwrap_or_default(),
+                        last_failure: stats.last_failure.map(Timestamp::from),
+                        last_success: stats.last_success.map(Timestamp::from),
+                        last_used: stats.last_used.map(Timestamp::from),
+                        cooldown_until: stats.cooldown_until.map(Timestamp::from),
+                    })
+                    .collect(),
+            }
+        }
+    }
+
+    impl TryFrom<proto::ApiAccessMethodStats> for Vec<AccessMethodStats> {
+        type Error = FromProtobufTypeError;
+
+        fn try_from(stats: proto::ApiAccessMethodStats) -> Result<Self, Self::Error> {
+            let time_from_proto = |timestamp: Option<Timestamp>| {
+                timestamp
+                    .map(SystemTime::try_from)
+                    .transpose()
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid timestamp"))
+            };
+
+            stats
+                .methods
+                .into_iter()
+                .map(|method| {
+                    let id = method
+                        .id
+                        .ok_or(FromProtobufTypeError::InvalidArgument(
+                            "missing access method id",
+                        ))
+                        .and_then(Id::try_from)?;
+                    Ok(AccessMethodStats {
+                        id,
+                        successes: method.successes,
+                        failures: method.failures,
+                        last_error: Some(method.last_error).filter(|error| !error.is_empty()),
+                        last_failure: time_from_proto(method.last_failure)?,
+                        last_success: time_from_proto(method.last_success)?,
+                        last_used: time_from_proto(method.last_used)?,
+                        cooldown_until: time_from_proto(method.cooldown_until)?,
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+/// Implements conversions for the [`crate::types::proto::ApiConnectivity`] type.
+mod connectivity {
+    use crate::types::{proto, FromProtobufTypeError};
+    use mullvad_types::access_method::ApiConnectivity;
+    use prost_types::Timestamp;
+    use proto::api_connectivity::Status;
+    use std::time::SystemTime;
+
+    impl From<ApiConnectivity> for proto::ApiConnectivity {
+        fn from(connectivity: ApiConnectivity) -> Self {
+            match connectivity {
+                ApiConnectivity::Unknown => proto::ApiConnectivity {
+                    status: i32::from(Status::Unknown),
+                    unreachable_since: None,
+                    last_error: String::new(),
+                },
+                ApiConnectivity::Reachable => proto::ApiConnectivity {
+                    status: i32::from(Status::Reachable),
+                    unreachable_since: None,
+                    last_error: String::new(),
+                },
+                ApiConnectivity::Unreachable { since, last_error } => proto::ApiConnectivity {
+                    status: i32::from(Status::Unreachable),
+                    unreachable_since: Some(Timestamp::from(since)),
+                    last_error,
+                },
+            }
+        }
+    }
+
+    impl TryFrom<proto::ApiConnectivity> for ApiConnectivity {
+        type Error = FromProtobufTypeError;
+
+        fn try_from(connectivity: proto::ApiConnectivity) -> Result<Self, Self::Error> {
+            match Status::try_from(connectivity.status) {
+                Ok(Status::Unknown) => Ok(ApiConnectivity::Unknown),
+                Ok(Status::Reachable) => Ok(ApiConnectivity::Reachable),
+                Ok(Status::Unreachable) => {
+                    let since = connectivity
+                        .unreachable_since
+                        .ok_or(FromProtobufTypeError::InvalidArgument(
+                            "missing time since the API was unreachable",
+                        ))
+                        .and_then(|since| {
+                            SystemTime::try_from(since).map_err(|_| {
+                                FromProtobufTypeError::InvalidArgument("invalid timestamp")
+                            })
+                        })?;
+                    Ok(ApiConnectivity::Unreachable {
+                        since,
+                        last_error: connectivity.last_error,
+                    })
+                }
+                Err(_) => Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid API connectivity status",
+                )),
+            }
+        }
+    }
+}
+
+/// Implements conversions for the [`crate::types::proto::ApiAccessMethodTestResult`] type.
+mod test_result {
+    use crate::types::{proto, FromProtobufTypeError};
+    use mullvad_types::access_method::{AccessMethodTestReport, ConnectionStage};
+    use proto::api_access_method_test_result::{Failure, Stage, Timing};
+
+    impl From<ConnectionStage> for Stage {
+        fn from(stage: ConnectionStage) -> Self {
+            match stage {
+                ConnectionStage::TcpConnect => Stage::TcpConnect,
+                ConnectionStage::ProxyHandshake => Stage::ProxyHandshake,
+                ConnectionStage::TlsHandshake => Stage::TlsHandshake,
+                ConnectionStage::HttpRoundTrip => Stage::HttpRoundTrip,
+            }
+        }
+    }
+
+    fn try_stage_from_i32(stage: i32) -> Result<ConnectionStage, FromProtobufTypeError> {
+        match Stage::try_from(stage) {
+            Ok(Stage::TcpConnect) => Ok(ConnectionStage::TcpConnect),
+            Ok(Stage::ProxyHandshake) => Ok(ConnectionStage::ProxyHandshake),
+            Ok(Stage::TlsHandshake) => Ok(ConnectionStage::TlsHandshake),
+            Ok(Stage::HttpRoundTrip) => Ok(ConnectionStage::HttpRoundTrip),
+            Err(_) => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid connection stage",
+            )),
+        }
+    }
+
+    impl From<AccessMethodTestReport> for proto::ApiAccessMethodTestResult {
+        fn from(report: AccessMethodTestReport) -> Self {
+            proto::ApiAccessMethodTestResult {
+                timings: report
+                    .timings
+                    .into_iter()
+                    .map(|(stage, duration)| Timing {
+                        stage: i32::from(Stage::from(stage)),
+                        duration: prost_types::Duration::try_from(duration).ok(),
+                    })
+                    .collect(),
+                failure: report.failure.map(|(stage, error)| Failure {
+                    stage: i32::from(Stage::from(stage)),
+                    error,
+                }),
+            }
+        }
+    }
+
+    impl TryFrom<proto::ApiAccessMethodTestResult> for AccessMethodTestReport {
+        type Error = FromProtobufTypeError;
+
+        fn try_from(result: proto::ApiAccessMethodTestResult) -> Result<Self, Self::Error> {
+            let timings = result
+                .timings
+                .into_iter()
+                .map(|timing| {
+                    let duration = timing
+                        .duration
+                        .map(std::time::Duration::try_from)
+                        .ok_or(FromProtobufTypeError::InvalidArgument(
+                            "missing stage duration",
+                        ))?
+                        .map_err(|_| {
+                            FromProtobufTypeError::InvalidArgument("invalid stage duration")
+                        })?;
+                    Ok((try_stage_from_i32(timing.stage)?, duration))
+                })
+                .collect::<Result<_, Self::Error>>()?;
+            let failure = result
+                .failure
+                .map(|failure| Ok((try_stage_from_i32(failure.stage)?, failure.error)))
+                .transpose()?;
+
+            Ok(AccessMethodTestReport { timings, failure })
+        }
+    }
+}
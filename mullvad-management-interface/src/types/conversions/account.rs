@@ -1,8 +1,8 @@
 use crate::types;
 use chrono::TimeZone;
-use mullvad_types::account::{AccountData, VoucherSubmission};
+use mullvad_types::account::{AccountData, AccountExpiryWarning, SavedAccount, VoucherSubmission};
 
-use super::FromProtobufTypeError;
+use super::{option_from_proto_string, FromProtobufTypeError};
 
 impl From<VoucherSubmission> for types::VoucherSubmission {
     fn from(submission: VoucherSubmission) -> Self {
@@ -60,3 +60,59 @@ impl TryFrom<types::AccountData> for AccountData {
         })
     }
 }
+
+impl From<AccountExpiryWarning> for types::AccountExpiryWarning {
+    fn from(warning: AccountExpiryWarning) -> Self {
+        types::AccountExpiryWarning {
+            expiry: Some(types::Timestamp {
+                seconds: warning.expiry.timestamp(),
+                nanos: 0,
+            }),
+            threshold: prost_types::Duration::try_from(warning.threshold).ok(),
+        }
+    }
+}
+
+impl TryFrom<types::AccountExpiryWarning> for AccountExpiryWarning {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(warning: types::AccountExpiryWarning) -> Result<Self, FromProtobufTypeError> {
+        let expiry = warning
+            .expiry
+            .ok_or(FromProtobufTypeError::InvalidArgument("missing expiry"))?;
+        let ndt = chrono::NaiveDateTime::from_timestamp_opt(expiry.seconds, expiry.nanos as u32)
+            .ok_or(FromProtobufTypeError::InvalidArgument("invalid expiry"))?;
+        let threshold = warning
+            .threshold
+            .ok_or(FromProtobufTypeError::InvalidArgument("missing threshold"))
+            .and_then(|threshold| {
+                std::time::Duration::try_from(threshold)
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid threshold"))
+            })?;
+
+        Ok(AccountExpiryWarning {
+            expiry: chrono::Utc.from_utc_datetime(&ndt),
+            threshold,
+        })
+    }
+}
+
+impl From<SavedAccount> for types::SavedAccount {
+    fn from(account: SavedAccount) -> Self {
+        types::SavedAccount {
+            account_token: account.account_token,
+            label: account.label.unwrap_or_default(),
+            device_name: account.device_name.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<types::SavedAccount> for SavedAccount {
+    fn from(account: types::SavedAccount) -> Self {
+        SavedAccount {
+            account_token: account.account_token,
+            label: option_from_proto_string(account.label),
+            device_name: option_from_proto_string(account.device_name),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::types::{
+    conversions::{arg_from_str, option_from_proto_string},
+    proto::{self, connection_check::RelayMatch},
+    FromProtobufTypeError,
+};
+use mullvad_types::{connection_check::ConnectionCheck, dns_leak::DnsLeakTestResult};
+
+impl From<ConnectionCheck> for proto::ConnectionCheck {
+    fn from(check: ConnectionCheck) -> Self {
+        let relay_match = match check.via_connected_relay {
+            None => RelayMatch::Unknown,
+            Some(true) => RelayMatch::ConnectedRelay,
+            Some(false) => RelayMatch::Other,
+        };
+        proto::ConnectionCheck {
+            connected: check.connected,
+            ipv4: check.ipv4.map(|ip| ip.to_string()).unwrap_or_default(),
+            ipv6: check.ipv6.map(|ip| ip.to_string()).unwrap_or_default(),
+            country: check.country,
+            city: check.city.unwrap_or_default(),
+            mullvad_exit_ip: check.mullvad_exit_ip,
+            exit_relay: check.exit_relay.unwrap_or_default(),
+            relay_match: i32::from(relay_match),
+            dns_leak_test: check.dns_leak_test.map(proto::DnsLeakTestResult::from),
+        }
+    }
+}
+
+impl TryFrom<proto::ConnectionCheck> for ConnectionCheck {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(check: proto::ConnectionCheck) -> Result<Self, Self::Error> {
+        let via_connected_relay = match RelayMatch::try_from(check.relay_match) {
+            Ok(RelayMatch::Unknown) => None,
+            Ok(RelayMatch::ConnectedRelay) => Some(true),
+            Ok(RelayMatch::Other) => Some(false),
+            Err(_) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid relay match",
+                ))
+            }
+        };
+        Ok(ConnectionCheck {
+            connected: check.connected,
+            ipv4: option_from_proto_string(check.ipv4)
+                .map(|addr| arg_from_str(&addr, "invalid IPv4 address"))
+                .transpose()?,
+            ipv6: option_from_proto_string(check.ipv6)
+                .map(|addr| arg_from_str(&addr, "invalid IPv6 address"))
+                .transpose()?,
+            country: check.country,
+            city: option_from_proto_string(check.city),
+            mullvad_exit_ip: check.mullvad_exit_ip,
+            exit_relay: option_from_proto_string(check.exit_relay),
+            via_connected_relay,
+            dns_leak_test: check
+                .dns_leak_test
+                .map(DnsLeakTestResult::try_from)
+                .transpose()?,
+        })
+    }
+}
@@ -0,0 +1,154 @@
+use super::net::{try_transport_protocol_from_i32, try_tunnel_type_from_i32};
+use crate::types::{
+    conversions::{option_from_proto_string, to_timestamp, try_from_timestamp},
+    proto,
+    proto::connection_history::outcome::Reason,
+    FromProtobufTypeError,
+};
+use mullvad_types::connection_history::{Connection, ConnectionOutcome, ConnectionParameters};
+use talpid_types::net::ObfuscationType;
+
+impl From<Connection> for proto::connection_history::Connection {
+    fn from(connection: Connection) -> Self {
+        proto::connection_history::Connection {
+            started_at: Some(to_timestamp(connection.started_at)),
+            attempts: connection.attempts,
+            relay: connection.relay.unwrap_or_default(),
+            entry_relay: connection.entry_relay.unwrap_or_default(),
+            parameters: Some(proto::connection_history::Parameters::from(
+                connection.parameters,
+            )),
+            connected_at: connection.connected_at.map(to_timestamp),
+            ended_at: connection.ended_at.map(to_timestamp),
+            outcome: connection
+                .outcome
+                .map(proto::connection_history::Outcome::from),
+        }
+    }
+}
+
+impl TryFrom<proto::connection_history::Connection> for Connection {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(connection: proto::connection_history::Connection) -> Result<Self, Self::Error> {
+        Ok(Connection {
+            started_at: connection
+                .started_at
+                .ok_or(FromProtobufTypeError::InvalidArgument(
+                    "missing connection start time",
+                ))
+                .and_then(try_from_timestamp)?,
+            attempts: connection.attempts,
+            relay: option_from_proto_string(connection.relay),
+            entry_relay: option_from_proto_string(connection.entry_relay),
+            parameters: connection
+                .parameters
+                .ok_or(FromProtobufTypeError::InvalidArgument(
+                    "missing connection parameters",
+                ))
+                .and_then(ConnectionParameters::try_from)?,
+            connected_at: connection
+                .connected_at
+                .map(try_from_timestamp)
+                .transpose()?,
+            ended_at: connection.ended_at.map(try_from_timestamp).transpose()?,
+            outcome: connection
+                .outcome
+                .map(ConnectionOutcome::try_from)
+                .transpose()?,
+        })
+    }
+}
+
+impl From<ConnectionParameters> for proto::connection_history::Parameters {
+    fn from(parameters: ConnectionParameters) -> Self {
+        use proto::connection_history::parameters::Obfuscation;
+
+        proto::connection_history::Parameters {
+            tunnel_type: i32::from(proto::TunnelType::from(parameters.tunnel_type)),
+            protocol: i32::from(proto::TransportProtocol::from(parameters.protocol)),
+            port: u32::from(parameters.port),
+            obfuscation: parameters.obfuscation.map(|obfuscation| Obfuscation {
+                obfuscation_type: i32::from(match obfuscation {
+                    ObfuscationType::Udp2Tcp => proto::ObfuscationType::Udp2tcp,
+                    ObfuscationType::Quic => proto::ObfuscationType::Quic,
+                    ObfuscationType::Tls => proto::ObfuscationType::Tls,
+                }),
+            }),
+            quantum_resistant: parameters.quantum_resistant,
+        }
+    }
+}
+
+impl TryFrom<proto::connection_history::Parameters> for ConnectionParameters {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(parameters: proto::connection_history::Parameters) -> Result<Self, Self::Error> {
+        let obfuscation = parameters
+            .obfuscation
+            .map(|obfuscation| {
+                match proto::ObfuscationType::try_from(obfuscation.obfuscation_type) {
+                    Ok(proto::ObfuscationType::Udp2tcp) => Ok(ObfuscationType::Udp2Tcp),
+                    Ok(proto::ObfuscationType::Quic) => Ok(ObfuscationType::Quic),
+                    Ok(proto::ObfuscationType::Tls) => Ok(ObfuscationType::Tls),
+                    Err(_) => Err(FromProtobufTypeError::InvalidArgument(
+                        "unknown obfuscation type",
+                    )),
+                }
+            })
+            .transpose()?;
+        Ok(ConnectionParameters {
+            tunnel_type: try_tunnel_type_from_i32(parameters.tunnel_type)?,
+            protocol: try_transport_protocol_from_i32(parameters.protocol)?,
+            port: u16::try_from(parameters.port)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))?,
+            obfuscation,
+            quantum_resistant: parameters.quantum_resistant,
+        })
+    }
+}
+
+impl From<ConnectionOutcome> for proto::connection_history::Outcome {
+    fn from(outcome: ConnectionOutcome) -> Self {
+        let reason = |reason: Reason| proto::connection_history::Outcome {
+            reason: i32::from(reason),
+            error_code: 0,
+            error_description: String::new(),
+        };
+        match outcome {
+            ConnectionOutcome::Disconnected => reason(Reason::Disconnected),
+            ConnectionOutcome::Reconnected => reason(Reason::Reconnected),
+            ConnectionOutcome::ConnectionLost => reason(Reason::ConnectionLost),
+            ConnectionOutcome::Offline => reason(Reason::Offline),
+            ConnectionOutcome::Error { code, description } => proto::connection_history::Outcome {
+                reason: i32::from(Reason::Error),
+                error_code: code,
+                error_description: description,
+            },
+            ConnectionOutcome::Interrupted => reason(Reason::Interrupted),
+        }
+    }
+}
+
+impl TryFrom<proto::connection_history::Outcome> for ConnectionOutcome {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(
+        outcome: proto::connection_history::Outcome,
+    ) -> Result<Self, FromProtobufTypeError> {
+        match Reason::try_from(outcome.reason) {
+            Ok(Reason::Disconnected) => Ok(ConnectionOutcome::Disconnected),
+            Ok(Reason::Reconnected) => Ok(ConnectionOutcome::Reconnected),
+            Ok(Reason::ConnectionLost) => Ok(ConnectionOutcome::ConnectionLost),
+            Ok(Reason::Offline) => Ok(ConnectionOutcome::Offline),
+            Ok(Reason::Error) => Ok(ConnectionOutcome::Error {
+                code: outcome.error_code,
+                description: outcome.error_description,
+            }),
+            Ok(Reason::Interrupted) => Ok(ConnectionOutcome::Interrupted),
+            Err(_) => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid connection outcome",
+            )),
+        }
+    }
+}
@@ -194,3 +194,58 @@ impl From<Vec<mullvad_types::device::Device>> for proto::DeviceList {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::wireguard::PrivateKey;
+
+    fn device(id: &str) -> mullvad_types::device::Device {
+        mullvad_types::device::Device {
+            id: id.to_owned(),
+            name: "test device".to_owned(),
+            pubkey: PrivateKey::new_from_random().public_key(),
+            hijack_dns: false,
+            created: chrono::Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_device_list_conversion() {
+        let devices = vec![device("a"), device("b")];
+        let list = proto::DeviceList::from(devices.clone());
+        assert_eq!(list.devices.len(), 2);
+
+        let converted = list
+            .devices
+            .into_iter()
+            .map(mullvad_types::device::Device::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(converted, devices);
+    }
+
+    #[test]
+    fn test_remove_device_event_conversion() {
+        let event = mullvad_types::device::RemoveDeviceEvent {
+            account_token: "1234123412341234".to_owned(),
+            new_devices: vec![device("remaining")],
+        };
+        let converted = mullvad_types::device::RemoveDeviceEvent::try_from(
+            proto::RemoveDeviceEvent::from(event.clone()),
+        )
+        .unwrap();
+        assert_eq!(converted, event);
+    }
+
+    #[test]
+    fn test_invalid_device() {
+        let mut invalid = proto::Device::from(device("a"));
+        invalid.created = None;
+        assert!(mullvad_types::device::Device::try_from(invalid).is_err());
+
+        let mut invalid = proto::Device::from(device("a"));
+        invalid.pubkey = vec![0; 3];
+        assert!(mullvad_types::device::Device::try_from(invalid).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::types::{
+    conversions::{arg_from_str, option_from_proto_string},
+    proto, FromProtobufTypeError,
+};
+use mullvad_types::dns_leak::{DnsLeakTestResult, DnsLeakVerdict, ObservedResolver};
+
+impl From<ObservedResolver> for proto::ObservedResolver {
+    fn from(resolver: ObservedResolver) -> Self {
+        proto::ObservedResolver {
+            ip: resolver.ip.to_string(),
+            mullvad_dns: resolver.mullvad_dns,
+            organization: resolver.organization.unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<proto::ObservedResolver> for ObservedResolver {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(resolver: proto::ObservedResolver) -> Result<Self, Self::Error> {
+        Ok(ObservedResolver {
+            ip: arg_from_str(&resolver.ip, "invalid resolver IP address")?,
+            mullvad_dns: resolver.mullvad_dns,
+            organization: option_from_proto_string(resolver.organization),
+        })
+    }
+}
+
+impl From<DnsLeakTestResult> for proto::DnsLeakTestResult {
+    fn from(result: DnsLeakTestResult) -> Self {
+        use proto::dns_leak_test_result::Verdict;
+
+        let verdict = match result.verdict {
+            DnsLeakVerdict::NoLeak => Verdict::NoLeak,
+            DnsLeakVerdict::Leaking => Verdict::Leaking,
+            DnsLeakVerdict::Inconclusive => Verdict::Inconclusive,
+        };
+
+        proto::DnsLeakTestResult {
+            verdict: i32::from(verdict),
+            observed_resolvers: result
+                .observed_resolvers
+                .into_iter()
+                .map(proto::ObservedResolver::from)
+                .collect(),
+            leaking_resolvers: result
+                .leaking_resolvers
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::DnsLeakTestResult> for DnsLeakTestResult {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(result: proto::DnsLeakTestResult) -> Result<Self, Self::Error> {
+        use proto::dns_leak_test_result::Verdict;
+
+        let verdict = match Verdict::try_from(result.verdict) {
+            Ok(Verdict::NoLeak) => DnsLeakVerdict::NoLeak,
+            Ok(Verdict::Leaking) => DnsLeakVerdict::Leaking,
+            Ok(Verdict::Inconclusive) => DnsLeakVerdict::Inconclusive,
+            Err(_) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid DNS leak test verdict",
+                ))
+            }
+        };
+
+        Ok(DnsLeakTestResult {
+            verdict,
+            observed_resolvers: result
+                .observed_resolvers
+                .into_iter()
+                .map(ObservedResolver::try_from)
+                .collect::<Result<_, _>>()?,
+            leaking_resolvers: result
+                .leaking_resolvers
+                .iter()
+                .map(|ip| arg_from_str(ip, "invalid resolver IP address"))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
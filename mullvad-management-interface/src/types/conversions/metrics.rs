@@ -0,0 +1,50 @@
+use crate::types::{proto, FromProtobufTypeError};
+use mullvad_types::metrics::{Metric, MetricKind};
+
+impl From<&Metric> for proto::metrics::Metric {
+    fn from(metric: &Metric) -> Self {
+        let kind = match metric.kind {
+            MetricKind::Counter => proto::metrics::Kind::Counter,
+            MetricKind::Gauge => proto::metrics::Kind::Gauge,
+        };
+        proto::metrics::Metric {
+            name: metric.name.clone(),
+            kind: i32::from(kind),
+            labels: metric
+                .labels
+                .iter()
+                .map(|(name, value)| proto::metrics::Label {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            value: metric.value,
+        }
+    }
+}
+
+impl TryFrom<proto::metrics::Metric> for Metric {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(metric: proto::metrics::Metric) -> Result<Self, Self::Error> {
+        let kind = match proto::metrics::Kind::try_from(metric.kind) {
+            Ok(proto::metrics::Kind::Counter) => MetricKind::Counter,
+            Ok(proto::metrics::Kind::Gauge) => MetricKind::Gauge,
+            Err(_) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid metric kind",
+                ))
+            }
+        };
+        Ok(Metric {
+            name: metric.name,
+            kind,
+            labels: metric
+                .labels
+                .into_iter()
+                .map(|label| (label.name, label.value))
+                .collect(),
+            value: metric.value,
+        })
+    }
+}
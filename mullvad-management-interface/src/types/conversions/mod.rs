@@ -1,16 +1,27 @@
+use chrono::{DateTime, TimeZone, Utc};
+use prost_types::Timestamp;
 use std::str::FromStr;
 
 mod access_method;
 mod account;
+mod connection_check;
+mod connection_history;
 mod custom_list;
 mod custom_tunnel;
 mod device;
+mod dns_leak;
 mod location;
+mod metrics;
 mod net;
+mod network_profiles;
+mod network_trust;
+mod obfuscation_scores;
+mod problem_report;
+mod recent_events;
 pub mod relay_constraints;
 mod relay_list;
+mod routes;
 mod settings;
-#[cfg(target_os = "windows")]
 mod split_tunnel;
 mod states;
 mod version;
@@ -53,6 +64,19 @@ fn option_from_proto_string(s: String) -> Option<String> {
     }
 }
 
+fn to_timestamp(time: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn try_from_timestamp(time: Timestamp) -> Result<DateTime<Utc>, FromProtobufTypeError> {
+    chrono::NaiveDateTime::from_timestamp_opt(time.seconds, time.nanos as u32)
+        .map(|time| Utc.from_utc_datetime(&time))
+        .ok_or(FromProtobufTypeError::InvalidArgument("invalid timestamp"))
+}
+
 fn arg_from_str<T: FromStr<Err = E>, E>(
     s: &str,
     invalid_arg_msg: &'static str,
@@ -1,4 +1,5 @@
 use crate::types::{conversions::arg_from_str, proto, FromProtobufTypeError};
+use ipnetwork::IpNetwork;
 use mullvad_types::relay_constraints::Constraint;
 use std::net::SocketAddr;
 
@@ -31,7 +32,15 @@ impl From<talpid_types::net::TunnelEndpoint> for proto::TunnelEndpoint {
                     )),
                     obfuscation_type: match obfuscation_endpoint.obfuscation_type {
                         net::ObfuscationType::Udp2Tcp => i32::from(proto::ObfuscationType::Udp2tcp),
+                        net::ObfuscationType::Quic => i32::from(proto::ObfuscationType::Quic),
+                        net::ObfuscationType::Tls => i32::from(proto::ObfuscationType::Tls),
                     },
+                    upstream_proxy: obfuscation_endpoint.upstream_proxy.map(|proxy| {
+                        proto::Endpoint {
+                            address: proxy.address.to_string(),
+                            protocol: i32::from(proto::TransportProtocol::from(proxy.protocol)),
+                        }
+                    }),
                 }
             }),
             entry_endpoint: endpoint.entry_endpoint.map(|entry| proto::Endpoint {
@@ -103,12 +112,26 @@ impl TryFrom<proto::TunnelEndpoint> for talpid_types::net::TunnelEndpoint {
                             Ok(proto::ObfuscationType::Udp2tcp) => {
                                 talpid_net::ObfuscationType::Udp2Tcp
                             }
+                            Ok(proto::ObfuscationType::Quic) => talpid_net::ObfuscationType::Quic,
+                            Ok(proto::ObfuscationType::Tls) => talpid_net::ObfuscationType::Tls,
                             Err(_) => {
                                 return Err(FromProtobufTypeError::InvalidArgument(
                                     "unknown obfuscation type",
                                 ))
                             }
                         },
+                        upstream_proxy: obfs_ep
+                            .upstream_proxy
+                            .map(|proxy| {
+                                Ok(talpid_net::Endpoint {
+                                    address: arg_from_str(
+                                        &proxy.address,
+                                        "invalid upstream proxy address",
+                                    )?,
+                                    protocol: try_transport_protocol_from_i32(proxy.protocol)?,
+                                })
+                            })
+                            .transpose()?,
                     })
                 })
                 .transpose()?,
@@ -146,6 +169,49 @@ impl From<talpid_types::net::IpVersion> for proto::IpVersion {
     }
 }
 
+impl From<talpid_types::net::NetworkChange> for proto::NetworkChange {
+    fn from(change: talpid_types::net::NetworkChange) -> Self {
+        proto::NetworkChange {
+            interface: change.interface.unwrap_or_default(),
+            gateway: change
+                .gateway
+                .map(|gateway| gateway.to_string())
+                .unwrap_or_default(),
+            families: change
+                .families
+                .into_iter()
+                .map(|family| i32::from(proto::IpVersion::from(family)))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::NetworkChange> for talpid_types::net::NetworkChange {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(change: proto::NetworkChange) -> Result<Self, Self::Error> {
+        let gateway = if change.gateway.is_empty() {
+            None
+        } else {
+            Some(arg_from_str(&change.gateway, "invalid gateway address")?)
+        };
+        let families = change
+            .families
+            .into_iter()
+            .map(|family| match proto::IpVersion::try_from(family) {
+                Ok(proto::IpVersion::V4) => Ok(talpid_types::net::IpVersion::V4),
+                Ok(proto::IpVersion::V6) => Ok(talpid_types::net::IpVersion::V6),
+                Err(_) => Err(FromProtobufTypeError::InvalidArgument("invalid IP version")),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(talpid_types::net::NetworkChange {
+            interface: (!change.interface.is_empty()).then_some(change.interface),
+            gateway,
+            families,
+        })
+    }
+}
+
 impl From<proto::TransportProtocol> for talpid_types::net::TransportProtocol {
     fn from(protocol: proto::TransportProtocol) -> Self {
         match protocol {
@@ -155,6 +221,15 @@ impl From<proto::TransportProtocol> for talpid_types::net::TransportProtocol {
     }
 }
 
+impl From<talpid_types::net::TunnelType> for proto::TunnelType {
+    fn from(tunnel_type: talpid_types::net::TunnelType) -> Self {
+        match tunnel_type {
+            talpid_types::net::TunnelType::Wireguard => proto::TunnelType::Wireguard,
+            talpid_types::net::TunnelType::OpenVpn => proto::TunnelType::Openvpn,
+        }
+    }
+}
+
 impl TryFrom<proto::TunnelTypeConstraint> for Constraint<talpid_types::net::TunnelType> {
     type Error = FromProtobufTypeError;
 
@@ -195,6 +270,31 @@ impl From<Vec<SocketAddr>> for proto::ApiAddresses {
     }
 }
 
+impl From<&[IpNetwork]> for proto::BypassRoutes {
+    fn from(networks: &[IpNetwork]) -> Self {
+        Self {
+            networks: networks.iter().map(IpNetwork::to_string).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::BypassRoutes> for Vec<IpNetwork> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(routes: proto::BypassRoutes) -> Result<Self, Self::Error> {
+        try_networks_from_strings(&routes.networks)
+    }
+}
+
+pub fn try_networks_from_strings(
+    networks: &[String],
+) -> Result<Vec<IpNetwork>, FromProtobufTypeError> {
+    networks
+        .iter()
+        .map(|network| arg_from_str(network, "invalid network"))
+        .collect()
+}
+
 pub fn try_tunnel_type_from_i32(
     tunnel_type: i32,
 ) -> Result<talpid_types::net::TunnelType, FromProtobufTypeError> {
@@ -214,3 +314,34 @@ pub fn try_transport_protocol_from_i32(
         .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid transport protocol"))?
         .into())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::{IpVersion, NetworkChange};
+
+    #[test]
+    fn test_network_change_roundtrip() {
+        let changes = [
+            NetworkChange {
+                interface: Some("eth0".to_owned()),
+                gateway: Some("192.168.1.1".parse().unwrap()),
+                families: vec![IpVersion::V4, IpVersion::V6],
+            },
+            NetworkChange::default(),
+        ];
+        for change in changes {
+            let proto_change = proto::NetworkChange::from(change.clone());
+            assert_eq!(NetworkChange::try_from(proto_change).unwrap(), change);
+        }
+    }
+
+    #[test]
+    fn test_invalid_network_change() {
+        let change = proto::NetworkChange {
+            gateway: "not an address".to_owned(),
+            ..Default::default()
+        };
+        assert!(NetworkChange::try_from(change).is_err());
+    }
+}
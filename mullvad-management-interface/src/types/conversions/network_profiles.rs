@@ -0,0 +1,113 @@
+use crate::types::{proto, FromProtobufTypeError};
+use mullvad_types::{network_profiles::NetworkId, relay_constraints::ObfuscationSettings};
+
+impl From<&NetworkId> for proto::NetworkId {
+    fn from(network: &NetworkId) -> Self {
+        use proto::network_id::Id;
+
+        let id = match network {
+            NetworkId::Ssid(ssid) => Id::Ssid(ssid.clone()),
+            NetworkId::Interface(interface) => Id::Interface(interface.clone()),
+        };
+        proto::NetworkId { id: Some(id) }
+    }
+}
+
+impl TryFrom<proto::NetworkId> for NetworkId {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(network: proto::NetworkId) -> Result<Self, Self::Error> {
+        use proto::network_id::Id;
+
+        match network.id {
+            Some(Id::Ssid(ssid)) => Ok(NetworkId::Ssid(ssid)),
+            Some(Id::Interface(interface)) => Ok(NetworkId::Interface(interface)),
+            None => Err(FromProtobufTypeError::InvalidArgument("missing network id")),
+        }
+    }
+}
+
+impl From<&(NetworkId, ObfuscationSettings)> for proto::NetworkObfuscationProfile {
+    fn from((network, obfuscation_settings): &(NetworkId, ObfuscationSettings)) -> Self {
+        proto::NetworkObfuscationProfile {
+            network: Some(proto::NetworkId::from(network)),
+            obfuscation_settings: Some(proto::ObfuscationSettings::from(obfuscation_settings)),
+        }
+    }
+}
+
+impl TryFrom<proto::NetworkObfuscationProfile> for (NetworkId, ObfuscationSettings) {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(profile: proto::NetworkObfuscationProfile) -> Result<Self, Self::Error> {
+        let network = profile
+            .network
+            .ok_or(FromProtobufTypeError::InvalidArgument("missing network id"))?;
+        let obfuscation_settings =
+            profile
+                .obfuscation_settings
+                .ok_or(FromProtobufTypeError::InvalidArgument(
+                    "missing obfuscation settings",
+                ))?;
+        Ok((
+            NetworkId::try_from(network)?,
+            ObfuscationSettings::try_from(obfuscation_settings)?,
+        ))
+    }
+}
+
+impl From<&[(NetworkId, ObfuscationSettings)]> for proto::NetworkObfuscationProfiles {
+    fn from(profiles: &[(NetworkId, ObfuscationSettings)]) -> Self {
+        proto::NetworkObfuscationProfiles {
+            profiles: profiles
+                .iter()
+                .map(proto::NetworkObfuscationProfile::from)
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::NetworkObfuscationProfiles> for Vec<(NetworkId, ObfuscationSettings)> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(profiles: proto::NetworkObfuscationProfiles) -> Result<Self, Self::Error> {
+        profiles
+            .profiles
+            .into_iter()
+            .map(<(NetworkId, ObfuscationSettings)>::try_from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::relay_constraints::SelectedObfuscation;
+
+    #[test]
+    fn test_network_obfuscation_profiles_roundtrip() {
+        let profiles = vec![
+            (
+                NetworkId::Ssid("eduroam".to_owned()),
+                ObfuscationSettings {
+                    selected_obfuscation: SelectedObfuscation::Udp2Tcp,
+                    ..Default::default()
+                },
+            ),
+            (
+                NetworkId::Interface("eth0".to_owned()),
+                ObfuscationSettings {
+                    selected_obfuscation: SelectedObfuscation::Off,
+                    ..Default::default()
+                },
+            ),
+        ];
+        let converted = Vec::<(NetworkId, ObfuscationSettings)>::try_from(
+            proto::NetworkObfuscationProfiles::from(&profiles[..]),
+        )
+        .unwrap();
+        assert_eq!(converted, profiles);
+
+        assert!(NetworkId::try_from(proto::NetworkId { id: None }).is_err());
+    }
+}
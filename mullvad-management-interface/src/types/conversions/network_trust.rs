@@ -0,0 +1,120 @@
+use crate::types::{proto, FromProtobufTypeError};
+use mullvad_types::{
+    network_profiles::NetworkId,
+    network_trust::{NetworkTrustSettings, TrustPolicy},
+};
+
+impl From<TrustPolicy> for proto::TrustPolicy {
+    fn from(policy: TrustPolicy) -> Self {
+        match policy {
+            TrustPolicy::Trusted => proto::TrustPolicy::Trusted,
+            TrustPolicy::Untrusted => proto::TrustPolicy::Untrusted,
+        }
+    }
+}
+
+fn try_trust_policy_from_i32(policy: i32) -> Result<TrustPolicy, FromProtobufTypeError> {
+    match proto::TrustPolicy::try_from(policy) {
+        Ok(proto::TrustPolicy::Trusted) => Ok(TrustPolicy::Trusted),
+        Ok(proto::TrustPolicy::Untrusted) => Ok(TrustPolicy::Untrusted),
+        Err(_) => Err(FromProtobufTypeError::InvalidArgument(
+            "invalid trust policy",
+        )),
+    }
+}
+
+impl From<&NetworkTrustSettings> for proto::NetworkTrustSettings {
+    fn from(settings: &NetworkTrustSettings) -> Self {
+        use proto::network_trust_settings::DefaultPolicy;
+
+        let default_policy = match settings.default_policy {
+            None => DefaultPolicy::NoPolicy,
+            Some(TrustPolicy::Trusted) => DefaultPolicy::Trusted,
+            Some(TrustPolicy::Untrusted) => DefaultPolicy::Untrusted,
+        };
+        proto::NetworkTrustSettings {
+            rules: settings
+                .rules
+                .iter()
+                .map(|(network, policy)| proto::NetworkTrustRule {
+                    network: Some(proto::NetworkId::from(network)),
+                    policy: i32::from(proto::TrustPolicy::from(*policy)),
+                })
+                .collect(),
+            default_policy: i32::from(default_policy),
+            disconnect_on_trusted: settings.disconnect_on_trusted,
+            lockdown_on_trusted: settings.lockdown_on_trusted,
+        }
+    }
+}
+
+impl TryFrom<proto::NetworkTrustSettings> for NetworkTrustSettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: proto::NetworkTrustSettings) -> Result<Self, Self::Error> {
+        use proto::network_trust_settings::DefaultPolicy;
+
+        let rules = settings
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let network = rule
+                    .network
+                    .ok_or(FromProtobufTypeError::InvalidArgument(
+                        "missing network of trust rule",
+                    ))
+                    .and_then(NetworkId::try_from)?;
+                Ok((network, try_trust_policy_from_i32(rule.policy)?))
+            })
+            .collect::<Result<_, FromProtobufTypeError>>()?;
+        let default_policy = match DefaultPolicy::try_from(settings.default_policy) {
+            Ok(DefaultPolicy::NoPolicy) => None,
+            Ok(DefaultPolicy::Trusted) => Some(TrustPolicy::Trusted),
+            Ok(DefaultPolicy::Untrusted) => Some(TrustPolicy::Untrusted),
+            Err(_) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid default trust policy",
+                ))
+            }
+        };
+        Ok(NetworkTrustSettings {
+            rules,
+            default_policy,
+            disconnect_on_trusted: settings.disconnect_on_trusted,
+            lockdown_on_trusted: settings.lockdown_on_trusted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_network_trust_settings_roundtrip() {
+        let settings = NetworkTrustSettings {
+            rules: vec![
+                (NetworkId::Ssid("HomeNet".to_owned()), TrustPolicy::Trusted),
+                (
+                    NetworkId::Interface("eth0".to_owned()),
+                    TrustPolicy::Untrusted,
+                ),
+            ],
+            default_policy: Some(TrustPolicy::Untrusted),
+            disconnect_on_trusted: true,
+            lockdown_on_trusted: false,
+        };
+        let converted =
+            NetworkTrustSettings::try_from(proto::NetworkTrustSettings::from(&settings)).unwrap();
+        assert_eq!(converted, settings);
+
+        let settings = NetworkTrustSettings::default();
+        let converted =
+            NetworkTrustSettings::try_from(proto::NetworkTrustSettings::from(&settings)).unwrap();
+        assert_eq!(converted, settings);
+
+        let mut proto_settings = proto::NetworkTrustSettings::from(&settings);
+        proto_settings.default_policy = 10;
+        assert!(NetworkTrustSettings::try_from(proto_settings).is_err());
+    }
+}
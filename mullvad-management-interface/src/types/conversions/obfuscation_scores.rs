@@ -0,0 +1,101 @@
+use crate::types::{proto, FromProtobufTypeError};
+use mullvad_types::{
+    network_profiles::NetworkId,
+    obfuscation_scores::{NetworkScores, ObfuscationScore, ObfuscationScores, PortBucket},
+    relay_constraints::ObfuscationType,
+};
+use std::time::SystemTime;
+
+impl From<&ObfuscationScores> for proto::ObfuscationScores {
+    fn from(scores: &ObfuscationScores) -> Self {
+        use proto::obfuscation_settings::ObfuscationType as IpcObfuscationType;
+
+        let networks = scores
+            .networks
+            .iter()
+            .map(|network| proto::obfuscation_scores::Network {
+                network: Some(proto::NetworkId::from(&network.network)),
+                scores: network
+                    .scores
+                    .iter()
+                    .map(|score| proto::obfuscation_scores::Score {
+                        obfuscation_type: i32::from(match score.obfuscation_type {
+                            ObfuscationType::None => IpcObfuscationType::None,
+                            ObfuscationType::Udp2Tcp => IpcObfuscationType::Udp2tcpObfuscation,
+                            ObfuscationType::Quic => IpcObfuscationType::QuicObfuscation,
+                            ObfuscationType::Tls => IpcObfuscationType::TlsObfuscation,
+                        }),
+                        port: match score.port {
+                            PortBucket::WellKnown(port) => u32::from(port),
+                            PortBucket::High => 0,
+                        },
+                        successes: score.successes,
+                        failures: score.failures,
+                    })
+                    .collect(),
+            })
+            .collect();
+        proto::ObfuscationScores { networks }
+    }
+}
+
+/// The counts are taken to be up to date, since they are decayed before they are sent.
+impl TryFrom<proto::ObfuscationScores> for ObfuscationScores {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(scores: proto::ObfuscationScores) -> Result<Self, Self::Error> {
+        use proto::obfuscation_settings::ObfuscationType as IpcObfuscationType;
+
+        let now = SystemTime::now();
+        let score_from_proto = |score: proto::obfuscation_scores::Score| {
+            let obfuscation_type = match IpcObfuscationType::try_from(score.obfuscation_type) {
+                Ok(IpcObfuscationType::None) => ObfuscationType::None,
+                Ok(IpcObfuscationType::Udp2tcpObfuscation) => ObfuscationType::Udp2Tcp,
+                Ok(IpcObfuscationType::QuicObfuscation) => ObfuscationType::Quic,
+                Ok(IpcObfuscationType::TlsObfuscation) => ObfuscationType::Tls,
+                Err(_) => {
+                    return Err(FromProtobufTypeError::InvalidArgument(
+                        "invalid obfuscation type",
+                    ))
+                }
+            };
+            let port = match score.port {
+                0 => PortBucket::High,
+                port => u16::try_from(port)
+                    .ok()
+                    .filter(|port| *port < 1024)
+                    .map(PortBucket::WellKnown)
+                    .ok_or(FromProtobufTypeError::InvalidArgument(
+                        "invalid obfuscation score port",
+                    ))?,
+            };
+            Ok(ObfuscationScore {
+                obfuscation_type,
+                port,
+                successes: score.successes,
+                failures: score.failures,
+                updated: now,
+            })
+        };
+
+        let networks = scores
+            .networks
+            .into_iter()
+            .map(|network| {
+                Ok(NetworkScores {
+                    network: NetworkId::try_from(
+                        network
+                            .network
+                            .ok_or(FromProtobufTypeError::InvalidArgument("missing network"))?,
+                    )?,
+                    scores: network
+                        .scores
+                        .into_iter()
+                        .map(score_from_proto)
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<Result<_, FromProtobufTypeError>>()?;
+        Ok(ObfuscationScores { networks })
+    }
+}
@@ -0,0 +1,50 @@
+use crate::types::{
+    conversions::{option_from_proto_string, to_timestamp, try_from_timestamp},
+    proto, FromProtobufTypeError,
+};
+use mullvad_types::problem_report::{ProblemReportSendResult, QueuedProblemReport};
+
+impl From<QueuedProblemReport> for proto::QueuedProblemReport {
+    fn from(report: QueuedProblemReport) -> Self {
+        proto::QueuedProblemReport {
+            id: report.id,
+            queued_at: Some(to_timestamp(report.queued_at)),
+            size: report.size,
+        }
+    }
+}
+
+impl TryFrom<proto::QueuedProblemReport> for QueuedProblemReport {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(report: proto::QueuedProblemReport) -> Result<Self, Self::Error> {
+        Ok(QueuedProblemReport {
+            id: report.id,
+            queued_at: report
+                .queued_at
+                .ok_or(FromProtobufTypeError::InvalidArgument(
+                    "missing problem report queue time",
+                ))
+                .and_then(try_from_timestamp)?,
+            size: report.size,
+        })
+    }
+}
+
+impl From<ProblemReportSendResult> for proto::ProblemReportSendResult {
+    fn from(result: ProblemReportSendResult) -> Self {
+        proto::ProblemReportSendResult {
+            id: result.id,
+            error: result.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<proto::ProblemReportSendResult> for ProblemReportSendResult {
+    fn from(result: proto::ProblemReportSendResult) -> Self {
+        ProblemReportSendResult {
+            id: result.id,
+            error: option_from_proto_string(result.error),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::types::{proto, FromProtobufTypeError};
+use chrono::TimeZone;
+use mullvad_types::recent_events::{Event, EventKind};
+use prost_types::Timestamp;
+
+impl From<Event> for proto::recent_events::Event {
+    fn from(event: Event) -> Self {
+        use proto::recent_events::Kind;
+
+        let kind = match event.kind {
+            EventKind::TunnelState => Kind::TunnelState,
+            EventKind::RelaySelection => Kind::RelaySelection,
+            EventKind::Firewall => Kind::Firewall,
+            EventKind::Dns => Kind::Dns,
+            EventKind::ApiConnectivity => Kind::ApiConnectivity,
+        };
+        proto::recent_events::Event {
+            time: Some(Timestamp {
+                seconds: event.time.timestamp(),
+                nanos: event.time.timestamp_subsec_nanos() as i32,
+            }),
+            kind: i32::from(kind),
+            message: event.message,
+        }
+    }
+}
+
+impl TryFrom<proto::recent_events::Event> for Event {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(event: proto::recent_events::Event) -> Result<Self, Self::Error> {
+        use proto::recent_events::Kind;
+
+        let time = event
+            .time
+            .and_then(|time| {
+                chrono::NaiveDateTime::from_timestamp_opt(time.seconds, time.nanos as u32)
+            })
+            .ok_or(FromProtobufTypeError::InvalidArgument(
+                "missing or invalid event time",
+            ))?;
+        let kind = match Kind::try_from(event.kind) {
+            Ok(Kind::TunnelState) => EventKind::TunnelState,
+            Ok(Kind::RelaySelection) => EventKind::RelaySelection,
+            Ok(Kind::Firewall) => EventKind::Firewall,
+            Ok(Kind::Dns) => EventKind::Dns,
+            Ok(Kind::ApiConnectivity) => EventKind::ApiConnectivity,
+            Err(_) => return Err(FromProtobufTypeError::InvalidArgument("invalid event kind")),
+        };
+        Ok(Event {
+            time: chrono::Utc.from_utc_datetime(&time),
+            kind,
+            message: event.message,
+        })
+    }
+}
@@ -1,7 +1,10 @@
+use crate::types::conversions::net::try_tunnel_type_from_i32;
 use crate::types::{conversions::option_from_proto_string, proto, FromProtobufTypeError};
 use mullvad_types::{
     custom_list::Id,
-    relay_constraints::{Constraint, RelaySettingsUpdate},
+    relay_constraints::{
+        Constraint, MatchingRelays, RelayOverrides, RelaySettingsUpdate, SelectedObfuscation,
+    },
 };
 use std::str::FromStr;
 use talpid_types::net::TunnelType;
@@ -332,17 +335,47 @@ impl From<mullvad_types::relay_constraints::BridgeState> for proto::BridgeState
 
 impl From<&mullvad_types::relay_constraints::ObfuscationSettings> for proto::ObfuscationSettings {
     fn from(settings: &mullvad_types::relay_constraints::ObfuscationSettings) -> Self {
-        use mullvad_types::relay_constraints::SelectedObfuscation;
-        let selected_obfuscation = i32::from(match settings.selected_obfuscation {
-            SelectedObfuscation::Auto => proto::obfuscation_settings::SelectedObfuscation::Auto,
-            SelectedObfuscation::Off => proto::obfuscation_settings::SelectedObfuscation::Off,
-            SelectedObfuscation::Udp2Tcp => {
-                proto::obfuscation_settings::SelectedObfuscation::Udp2tcp
-            }
-        });
+        use mullvad_types::relay_constraints::ObfuscationType;
+        use proto::obfuscation_settings::ObfuscationType as IpcObfuscationType;
+        let selected_obfuscation = i32::from(
+            proto::obfuscation_settings::SelectedObfuscation::from(settings.selected_obfuscation),
+        );
+        let obfuscation_priority = settings
+            .obfuscation_priority
+            .iter()
+            .map(|obfuscation_type| {
+                i32::from(match obfuscation_type {
+                    ObfuscationType::None => IpcObfuscationType::None,
+                    ObfuscationType::Udp2Tcp => IpcObfuscationType::Udp2tcpObfuscation,
+                    ObfuscationType::Quic => IpcObfuscationType::QuicObfuscation,
+                    ObfuscationType::Tls => IpcObfuscationType::TlsObfuscation,
+                })
+            })
+            .collect();
         Self {
             selected_obfuscation,
             udp2tcp: Some(proto::Udp2TcpObfuscationSettings::from(&settings.udp2tcp)),
+            obfuscation_priority,
+            quic: Some(proto::QuicObfuscationSettings {
+                server_name: settings.quic.server_name.clone().unwrap_or_default(),
+                alpn: settings.quic.alpn.clone(),
+            }),
+            tls: Some(proto::TlsObfuscationSettings {
+                server_name: settings.tls.server_name.clone().unwrap_or_default(),
+            }),
+            upstream_proxy: settings
+                .upstream_proxy
+                .as_ref()
+                .map(|proxy| proto::UpstreamProxy {
+                    ip: proxy.endpoint.ip().to_string(),
+                    port: u32::from(proxy.endpoint.port()),
+                    authentication: proxy.authentication.as_ref().map(|auth| {
+                        proto::access_method::SocksAuth {
+                            username: auth.username.clone(),
+                            password: auth.password.clone(),
+                        }
+                    }),
+                }),
         }
     }
 }
@@ -353,6 +386,8 @@ impl From<&mullvad_types::relay_constraints::Udp2TcpObfuscationSettings>
     fn from(settings: &mullvad_types::relay_constraints::Udp2TcpObfuscationSettings) -> Self {
         Self {
             port: u32::from(settings.port.unwrap_or(0)),
+            keepalive_interval_secs: u32::from(settings.keepalive_interval_secs.unwrap_or(0)),
+            idle_timeout_secs: u32::from(settings.idle_timeout_secs.unwrap_or(0)),
         }
     }
 }
@@ -660,19 +695,15 @@ impl TryFrom<proto::ObfuscationSettings> for mullvad_types::relay_constraints::O
     type Error = FromProtobufTypeError;
 
     fn try_from(settings: proto::ObfuscationSettings) -> Result<Self, Self::Error> {
-        use mullvad_types::relay_constraints::SelectedObfuscation;
-        use proto::obfuscation_settings::SelectedObfuscation as IpcSelectedObfuscation;
+        use mullvad_types::relay_constraints::{
+            ObfuscationType, QuicObfuscationSettings, TlsObfuscationSettings,
+            DEFAULT_OBFUSCATION_PRIORITY,
+        };
+        use proto::obfuscation_settings::ObfuscationType as IpcObfuscationType;
+        use std::net::SocketAddr;
+        use talpid_types::net::proxy::{SocksAuth, SocksProxy};
         let selected_obfuscation =
-            match IpcSelectedObfuscation::try_from(settings.selected_obfuscation) {
-                Ok(IpcSelectedObfuscation::Auto) => SelectedObfuscation::Auto,
-                Ok(IpcSelectedObfuscation::Off) => SelectedObfuscation::Off,
-                Ok(IpcSelectedObfuscation::Udp2tcp) => SelectedObfuscation::Udp2Tcp,
-                Err(_) => {
-                    return Err(FromProtobufTypeError::InvalidArgument(
-                        "invalid selected obfuscator",
-                    ));
-                }
-            };
+            try_selected_obfuscation_from_i32(settings.selected_obfuscation)?;
 
         let udp2tcp = match settings.udp2tcp {
             Some(settings) => {
@@ -685,9 +716,70 @@ impl TryFrom<proto::ObfuscationSettings> for mullvad_types::relay_constraints::O
             }
         };
 
+        // Older clients do not set the priority
+        let obfuscation_priority = if settings.obfuscation_priority.is_empty() {
+            DEFAULT_OBFUSCATION_PRIORITY.to_vec()
+        } else {
+            settings
+                .obfuscation_priority
+                .into_iter()
+                .map(
+                    |obfuscation_type| match IpcObfuscationType::try_from(obfuscation_type) {
+                        Ok(IpcObfuscationType::None) => Ok(ObfuscationType::None),
+                        Ok(IpcObfuscationType::Udp2tcpObfuscation) => Ok(ObfuscationType::Udp2Tcp),
+                        Ok(IpcObfuscationType::QuicObfuscation) => Ok(ObfuscationType::Quic),
+                        Ok(IpcObfuscationType::TlsObfuscation) => Ok(ObfuscationType::Tls),
+                        Err(_) => Err(FromProtobufTypeError::InvalidArgument(
+                            "invalid obfuscation type",
+                        )),
+                    },
+                )
+                .collect::<Result<_, _>>()?
+        };
+
+        // Older clients do not set the QUIC settings
+        let quic = settings
+            .quic
+            .map(|quic| QuicObfuscationSettings {
+                server_name: Some(quic.server_name).filter(|name| !name.is_empty()),
+                alpn: quic.alpn,
+            })
+            .unwrap_or_default();
+
+        // Older clients do not set the TLS settings
+        let tls = settings
+            .tls
+            .map(|tls| TlsObfuscationSettings {
+                server_name: Some(tls.server_name).filter(|name| !name.is_empty()),
+            })
+            .unwrap_or_default();
+
+        let upstream_proxy = settings
+            .upstream_proxy
+            .map(|proxy| {
+                let ip = proxy.ip.parse().map_err(|_| {
+                    FromProtobufTypeError::InvalidArgument("invalid upstream proxy address")
+                })?;
+                let port = u16::try_from(proxy.port).map_err(|_| {
+                    FromProtobufTypeError::InvalidArgument("invalid upstream proxy port")
+                })?;
+                Ok(SocksProxy {
+                    endpoint: SocketAddr::new(ip, port),
+                    authentication: proxy.authentication.map(|auth| SocksAuth {
+                        username: auth.username,
+                        password: auth.password,
+                    }),
+                })
+            })
+            .transpose()?;
+
         Ok(Self {
             selected_obfuscation,
             udp2tcp,
+            quic,
+            tls,
+            obfuscation_priority,
+            upstream_proxy,
         })
     }
 }
@@ -698,12 +790,26 @@ impl TryFrom<&proto::Udp2TcpObfuscationSettings>
     type Error = FromProtobufTypeError;
 
     fn try_from(settings: &proto::Udp2TcpObfuscationSettings) -> Result<Self, Self::Error> {
+        let optional_secs = |secs: u32, error| match secs {
+            0 => Ok(None),
+            secs => u16::try_from(secs)
+                .map(Some)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument(error)),
+        };
         Ok(Self {
             port: if settings.port == 0 {
                 Constraint::Any
             } else {
                 Constraint::Only(settings.port as u16)
             },
+            keepalive_interval_secs: optional_secs(
+                settings.keepalive_interval_secs,
+                "invalid udp2tcp keepalive interval",
+            )?,
+            idle_timeout_secs: optional_secs(
+                settings.idle_timeout_secs,
+                "invalid udp2tcp idle timeout",
+            )?,
         })
     }
 }
@@ -744,6 +850,108 @@ impl TryFrom<proto::TransportPort> for mullvad_types::relay_constraints::Transpo
     }
 }
 
+impl From<SelectedObfuscation> for proto::obfuscation_settings::SelectedObfuscation {
+    fn from(obfuscation: SelectedObfuscation) -> Self {
+        use proto::obfuscation_settings::SelectedObfuscation as IpcSelectedObfuscation;
+        match obfuscation {
+            SelectedObfuscation::Auto => IpcSelectedObfuscation::Auto,
+            SelectedObfuscation::Off => IpcSelectedObfuscation::Off,
+            SelectedObfuscation::Udp2Tcp => IpcSelectedObfuscation::Udp2tcp,
+            SelectedObfuscation::Quic => IpcSelectedObfuscation::Quic,
+            SelectedObfuscation::Tls => IpcSelectedObfuscation::Tls,
+        }
+    }
+}
+
+pub fn try_selected_obfuscation_from_i32(
+    obfuscation: i32,
+) -> Result<SelectedObfuscation, FromProtobufTypeError> {
+    use proto::obfuscation_settings::SelectedObfuscation as IpcSelectedObfuscation;
+    match IpcSelectedObfuscation::try_from(obfuscation) {
+        Ok(IpcSelectedObfuscation::Auto) => Ok(SelectedObfuscation::Auto),
+        Ok(IpcSelectedObfuscation::Off) => Ok(SelectedObfuscation::Off),
+        Ok(IpcSelectedObfuscation::Udp2tcp) => Ok(SelectedObfuscation::Udp2Tcp),
+        Ok(IpcSelectedObfuscation::Quic) => Ok(SelectedObfuscation::Quic),
+        Ok(IpcSelectedObfuscation::Tls) => Ok(SelectedObfuscation::Tls),
+        Err(_) => Err(FromProtobufTypeError::InvalidArgument(
+            "invalid selected obfuscator",
+        )),
+    }
+}
+
+impl From<RelayOverrides> for proto::RelayOverrides {
+    fn from(overrides: RelayOverrides) -> Self {
+        proto::RelayOverrides {
+            hostname: overrides.hostname.unwrap_or_default(),
+            port: overrides.port.map(u32::from),
+            obfuscation: overrides.obfuscation.map(|obfuscation| {
+                proto::relay_overrides::Obfuscation {
+                    selected_obfuscation: i32::from(
+                        proto::obfuscation_settings::SelectedObfuscation::from(obfuscation),
+                    ),
+                }
+            }),
+            tunnel_type: overrides
+                .tunnel_protocol
+                .map(|tunnel_type| proto::TunnelTypeConstraint {
+                    tunnel_type: i32::from(proto::TunnelType::from(tunnel_type)),
+                }),
+        }
+    }
+}
+
+impl TryFrom<proto::RelayOverrides> for RelayOverrides {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(overrides: proto::RelayOverrides) -> Result<Self, Self::Error> {
+        let port = overrides
+            .port
+            .map(|port| {
+                u16::try_from(port)
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))
+            })
+            .transpose()?;
+        let obfuscation = overrides
+            .obfuscation
+            .map(|obfuscation| try_selected_obfuscation_from_i32(obfuscation.selected_obfuscation))
+            .transpose()?;
+        let tunnel_protocol = overrides
+            .tunnel_type
+            .map(|tunnel_type| try_tunnel_type_from_i32(tunnel_type.tunnel_type))
+            .transpose()?;
+        Ok(RelayOverrides {
+            hostname: option_from_proto_string(overrides.hostname),
+            port,
+            obfuscation,
+            tunnel_protocol,
+        })
+    }
+}
+
+impl From<MatchingRelays> for proto::MatchingRelays {
+    fn from(matching_relays: MatchingRelays) -> Self {
+        proto::MatchingRelays {
+            relays: u32::try_from(matching_relays.relays).unwrap_or(u32::MAX),
+            multihop: matching_relays.entry_relays.is_some(),
+            entry_relays: matching_relays
+                .entry_relays
+                .map(|count| u32::try_from(count).unwrap_or(u32::MAX))
+                .unwrap_or(0),
+        }
+    }
+}
+
+impl From<proto::MatchingRelays> for MatchingRelays {
+    fn from(matching_relays: proto::MatchingRelays) -> Self {
+        MatchingRelays {
+            relays: matching_relays.relays as usize,
+            entry_relays: matching_relays
+                .multihop
+                .then_some(matching_relays.entry_relays as usize),
+        }
+    }
+}
+
 pub fn try_providers_constraint_from_proto(
     providers: &[String],
 ) -> Result<Constraint<mullvad_types::relay_constraints::Providers>, FromProtobufTypeError> {
@@ -125,6 +125,14 @@ impl From<mullvad_types::relay_list::Relay> for proto::Relay {
                     "mullvad_daemon.management_interface/WireguardRelayEndpointData",
                     proto::WireguardRelayEndpointData {
                         public_key: data.public_key.as_bytes().to_vec(),
+                        quic: data.quic.map(|quic| proto::QuicEndpointData {
+                            port: u32::from(quic.port),
+                            server_name: quic.server_name.unwrap_or_default(),
+                        }),
+                        tls: data.tls.map(|tls| proto::TlsEndpointData {
+                            port: u32::from(tls.port),
+                            server_name: tls.server_name.unwrap_or_default(),
+                        }),
                     },
                 )),
                 _ => None,
@@ -239,6 +247,38 @@ impl TryFrom<proto::Relay> for mullvad_types::relay_list::Relay {
                 MullvadEndpointData::Wireguard(
                     mullvad_types::relay_list::WireguardRelayEndpointData {
                         public_key: bytes_to_pubkey(&data.public_key)?,
+                        quic: data
+                            .quic
+                            .map(|quic| {
+                                Ok::<_, FromProtobufTypeError>(
+                                    mullvad_types::relay_list::QuicEndpointData {
+                                        port: u16::try_from(quic.port).map_err(|_| {
+                                            FromProtobufTypeError::InvalidArgument(
+                                                "invalid QUIC port",
+                                            )
+                                        })?,
+                                        server_name: Some(quic.server_name)
+                                            .filter(|name| !name.is_empty()),
+                                    },
+                                )
+                            })
+                            .transpose()?,
+                        tls: data
+                            .tls
+                            .map(|tls| {
+                                Ok::<_, FromProtobufTypeError>(
+                                    mullvad_types::relay_list::TlsEndpointData {
+                                        port: u16::try_from(tls.port).map_err(|_| {
+                                            FromProtobufTypeError::InvalidArgument(
+                                                "invalid TLS port",
+                                            )
+                                        })?,
+                                        server_name: Some(tls.server_name)
+                                            .filter(|name| !name.is_empty()),
+                                    },
+                                )
+                            })
+                            .transpose()?,
                     },
                 )
             }
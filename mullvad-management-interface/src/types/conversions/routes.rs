@@ -0,0 +1,126 @@
+use crate::types::{
+    conversions::{arg_from_str, option_from_proto_string},
+    proto, FromProtobufTypeError,
+};
+use mullvad_types::routes::{RouteDump, RouteInfo, RoutePurpose, RoutesUpdate};
+
+impl From<RouteInfo> for proto::Route {
+    fn from(route: RouteInfo) -> Self {
+        use proto::route::Purpose;
+
+        let purpose = match route.purpose {
+            Some(RoutePurpose::RelayExclusion) => Purpose::RelayExclusion,
+            Some(RoutePurpose::TunnelDefault) => Purpose::TunnelDefault,
+            Some(RoutePurpose::Tunnel) => Purpose::Tunnel,
+            Some(RoutePurpose::Bypass) => Purpose::Bypass,
+            Some(RoutePurpose::Other) | None => Purpose::Other,
+        };
+
+        proto::Route {
+            destination: route.destination.to_string(),
+            gateway: route
+                .gateway
+                .map(|gateway| gateway.to_string())
+                .unwrap_or_default(),
+            interface: route.interface.unwrap_or_default(),
+            via_default_route: route.via_default_route,
+            table: route.table.unwrap_or_default(),
+            metric: route.metric,
+            purpose: i32::from(purpose),
+        }
+    }
+}
+
+/// Converts a route, which has a purpose if it is required by the daemon.
+fn route_from_proto(
+    route: proto::Route,
+    desired: bool,
+) -> Result<RouteInfo, FromProtobufTypeError> {
+    use proto::route::Purpose;
+
+    let purpose = match Purpose::try_from(route.purpose) {
+        Ok(Purpose::RelayExclusion) => RoutePurpose::RelayExclusion,
+        Ok(Purpose::TunnelDefault) => RoutePurpose::TunnelDefault,
+        Ok(Purpose::Tunnel) => RoutePurpose::Tunnel,
+        Ok(Purpose::Bypass) => RoutePurpose::Bypass,
+        Ok(Purpose::Other) => RoutePurpose::Other,
+        Err(_) => {
+            return Err(FromProtobufTypeError::InvalidArgument(
+                "invalid route purpose",
+            ))
+        }
+    };
+
+    Ok(RouteInfo {
+        destination: arg_from_str(&route.destination, "invalid route destination")?,
+        gateway: option_from_proto_string(route.gateway)
+            .map(|gateway| arg_from_str(&gateway, "invalid route gateway"))
+            .transpose()?,
+        interface: option_from_proto_string(route.interface),
+        via_default_route: route.via_default_route,
+        table: (route.table != 0).then_some(route.table),
+        metric: route.metric,
+        purpose: desired.then_some(purpose),
+    })
+}
+
+impl From<RouteDump> for proto::RouteDump {
+    fn from(dump: RouteDump) -> Self {
+        proto::RouteDump {
+            desired: dump.desired.into_iter().map(proto::Route::from).collect(),
+            kernel_listed: dump.kernel.is_some(),
+            kernel: dump
+                .kernel
+                .unwrap_or_default()
+                .into_iter()
+                .map(proto::Route::from)
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::RouteDump> for RouteDump {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(dump: proto::RouteDump) -> Result<Self, Self::Error> {
+        let kernel = dump
+            .kernel
+            .into_iter()
+            .map(|route| route_from_proto(route, false))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RouteDump {
+            desired: dump
+                .desired
+                .into_iter()
+                .map(|route| route_from_proto(route, true))
+                .collect::<Result<_, _>>()?,
+            kernel: dump.kernel_listed.then_some(kernel),
+        })
+    }
+}
+
+impl From<RoutesUpdate> for proto::RoutesUpdate {
+    fn from(update: RoutesUpdate) -> Self {
+        proto::RoutesUpdate {
+            added: update.added.into_iter().map(proto::Route::from).collect(),
+            removed: update.removed.into_iter().map(proto::Route::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::RoutesUpdate> for RoutesUpdate {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(update: proto::RoutesUpdate) -> Result<Self, Self::Error> {
+        let routes = |routes: Vec<proto::Route>| {
+            routes
+                .into_iter()
+                .map(|route| route_from_proto(route, false))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(RoutesUpdate {
+            added: routes(update.added)?,
+            removed: routes(update.removed)?,
+        })
+    }
+}
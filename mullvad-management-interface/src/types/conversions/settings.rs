@@ -1,36 +1,79 @@
-use crate::types::{proto, FromProtobufTypeError};
+use crate::types::{
+    conversions::{
+        arg_from_str,
+        net::{try_networks_from_strings, try_transport_protocol_from_i32},
+    },
+    proto, FromProtobufTypeError,
+};
 use mullvad_types::settings::CURRENT_SETTINGS_VERSION;
-use talpid_types::ErrorExt;
+use std::{path::PathBuf, time::Duration};
+use talpid_types::{
+    net::TransportProtocol,
+    split_tunnel::{ExcludedDestination, PortRange, SplitTunnelMode},
+    tunnel::RetryBackoff,
+    ErrorExt,
+};
 
 impl From<&mullvad_types::settings::Settings> for proto::Settings {
     fn from(settings: &mullvad_types::settings::Settings) -> Self {
         #[cfg(windows)]
         let split_tunnel = {
+            use mullvad_types::settings::SplitApp;
+
             let mut converted_list = vec![];
-            for path in settings.split_tunnel.apps.clone().iter() {
-                match path.as_path().as_os_str().to_str() {
-                    Some(path) => converted_list.push(path.to_string()),
-                    None => {
-                        log::error!("failed to convert OS string: {:?}", path);
-                    }
+            let mut publishers = vec![];
+            for app in settings.split_tunnel.apps.iter() {
+                match app {
+                    SplitApp::Path(path) => match path.as_path().as_os_str().to_str() {
+                        Some(path) => converted_list.push(path.to_string()),
+                        None => {
+                            log::error!("failed to convert OS string: {:?}", path);
+                        }
+                    },
+                    SplitApp::Publisher(publisher) => publishers.push(publisher.clone()),
                 }
             }
 
             Some(proto::SplitTunnelSettings {
                 enable_exclusions: settings.split_tunnel.enable_exclusions,
                 apps: converted_list,
+                publishers,
             })
         };
         #[cfg(not(windows))]
         let split_tunnel = None;
 
-        Self {
+        #[cfg(target_os = "linux")]
+        let routing = Some(proto::RoutingSettings::from(settings.routing));
+        #[cfg(not(target_os = "linux"))]
+        let routing = None;
+
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let mut converted = Self {
             relay_settings: Some(proto::RelaySettings::from(settings.get_relay_settings())),
             bridge_settings: Some(proto::BridgeSettings::from(
                 settings.bridge_settings.clone(),
             )),
             bridge_state: Some(proto::BridgeState::from(settings.bridge_state)),
             allow_lan: settings.allow_lan,
+            bypass_routes: settings
+                .bypass_routes
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+            coexistence_mode: settings.coexistence_mode,
+            local_proxy: Some(proto::LocalProxySettings::from(&settings.local_proxy)),
+            prefer_api_in_tunnel: settings.prefer_api_in_tunnel,
+            network_trust: Some(proto::NetworkTrustSettings::from(&settings.network_trust)),
+            hooks: Some(proto::HookSettings::from(&settings.hooks)),
+            retry_backoff: Some(proto::RetryBackoff::from(&settings.retry_backoff)),
+            account_expiry_warnings: Some(proto::AccountExpiryWarnings::from(
+                &settings.account_expiry_warnings,
+            )),
+            log_rotation: Some(proto::LogRotationSettings::from(&settings.log_rotation)),
+            auto_disconnect_idle: settings
+                .auto_disconnect_idle
+                .and_then(|idle| prost_types::Duration::try_from(idle).ok()),
             block_when_disconnected: settings.block_when_disconnected,
             auto_connect: settings.auto_connect,
             tunnel_options: Some(proto::TunnelOptions::from(&settings.tunnel_options)),
@@ -38,14 +81,44 @@ impl From<&mullvad_types::settings::Settings> for proto::Settings {
             obfuscation_settings: Some(proto::ObfuscationSettings::from(
                 &settings.obfuscation_settings,
             )),
+            network_obfuscation_profiles: settings
+                .network_obfuscation_profiles
+                .iter()
+                .map(proto::NetworkObfuscationProfile::from)
+                .collect(),
             split_tunnel,
+            routing,
             custom_lists: Some(proto::CustomListSettings::from(
                 settings.custom_lists.clone(),
             )),
             api_access_methods: Some(proto::ApiAccessMethodSettings::from(
                 &settings.api_access_methods,
             )),
+            ..Default::default()
+        };
+
+        // The Linux split tunnel settings are sent as top-level fields
+        #[cfg(target_os = "linux")]
+        {
+            let split_tunnel = &settings.split_tunnel;
+            converted.split_tunnel_users = split_tunnel.users.clone();
+            converted.split_tunnel_groups = split_tunnel.groups.clone();
+            converted.split_tunnel_mode = Some(proto::SplitTunnelMode::from(split_tunnel.mode));
+            converted.split_tunnel_destinations = split_tunnel
+                .destinations
+                .iter()
+                .map(|destination| proto::SplitTunnelDestination::from(*destination))
+                .collect();
+            converted.split_tunnel_interfaces = split_tunnel.interfaces.clone();
+            converted.excluded_apps_allow_lan = Some(split_tunnel.excluded_apps_allow_lan);
+            converted.split_tunnel_local_ports = split_tunnel
+                .local_ports
+                .iter()
+                .map(|port| proto::SplitTunnelLocalPort::from(*port))
+                .collect();
         }
+
+        converted
     }
 }
 
@@ -73,7 +146,19 @@ impl From<&mullvad_types::settings::DnsOptions> for proto::DnsOptions {
                     .iter()
                     .map(|addr| addr.to_string())
                     .collect(),
+                tls_servers: options
+                    .custom_options
+                    .tls_servers
+                    .iter()
+                    .map(|server| proto::TlsDnsServer {
+                        address: server.address.to_string(),
+                        hostname: server.hostname.clone(),
+                    })
+                    .collect(),
             }),
+            preserve_search_domains: options.preserve_search_domains,
+            allow_lan_dns_when_blocked: options.allow_lan_dns_when_blocked,
+            keep_custom_dns_while_disconnected: options.keep_custom_dns_while_disconnected,
         }
     }
 }
@@ -152,9 +237,40 @@ impl TryFrom<proto::Settings> for mullvad_types::settings::Settings {
         #[cfg(windows)]
         let split_tunnel = settings
             .split_tunnel
+            .map(mullvad_types::settings::SplitTunnelSettings::from)
             .ok_or(FromProtobufTypeError::InvalidArgument(
                 "missing split tunnel options",
             ))?;
+        #[cfg(target_os = "linux")]
+        let split_tunnel = mullvad_types::settings::SplitTunnelSettings {
+            users: settings.split_tunnel_users,
+            groups: settings.split_tunnel_groups,
+            // Missing in settings from older daemons
+            mode: settings
+                .split_tunnel_mode
+                .map(SplitTunnelMode::try_from)
+                .transpose()?
+                .unwrap_or_default(),
+            destinations: settings
+                .split_tunnel_destinations
+                .into_iter()
+                .map(ExcludedDestination::try_from)
+                .collect::<Result<_, _>>()?,
+            interfaces: settings.split_tunnel_interfaces,
+            // Missing in settings from older daemons, which always allowed it
+            excluded_apps_allow_lan: settings.excluded_apps_allow_lan.unwrap_or(true),
+            local_ports: settings
+                .split_tunnel_local_ports
+                .into_iter()
+                .map(<(TransportProtocol, u16)>::try_from)
+                .collect::<Result<_, _>>()?,
+        };
+        #[cfg(target_os = "linux")]
+        let routing = settings
+            .routing
+            .ok_or(FromProtobufTypeError::InvalidArgument(
+                "missing routing settings",
+            ))?;
 
         Ok(Self {
             relay_settings: mullvad_types::relay_constraints::RelaySettings::try_from(
@@ -165,15 +281,35 @@ impl TryFrom<proto::Settings> for mullvad_types::settings::Settings {
             )?,
             bridge_state,
             allow_lan: settings.allow_lan,
+            bypass_routes: try_networks_from_strings(&settings.bypass_routes)?,
+            coexistence_mode: settings.coexistence_mode,
+            // Missing in settings from older daemons, which have no local proxy
+            local_proxy: settings
+                .local_proxy
+                .map(mullvad_types::settings::LocalProxySettings::try_from)
+                .transpose()?
+                .unwrap_or_default(),
             block_when_disconnected: settings.block_when_disconnected,
             auto_connect: settings.auto_connect,
+            auto_disconnect_idle: settings
+                .auto_disconnect_idle
+                .map(std::time::Duration::try_from)
+                .transpose()
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid idle duration"))?,
             tunnel_options: mullvad_types::settings::TunnelOptions::try_from(tunnel_options)?,
             show_beta_releases: settings.show_beta_releases,
-            #[cfg(windows)]
-            split_tunnel: mullvad_types::settings::SplitTunnelSettings::from(split_tunnel),
+            #[cfg(any(windows, target_os = "linux"))]
+            split_tunnel,
+            #[cfg(target_os = "linux")]
+            routing: mullvad_types::settings::RoutingSettings::from(routing),
             obfuscation_settings: mullvad_types::relay_constraints::ObfuscationSettings::try_from(
                 obfuscation_settings,
             )?,
+            network_obfuscation_profiles: settings
+                .network_obfuscation_profiles
+                .into_iter()
+                .map(TryFrom::try_from)
+                .collect::<Result<_, _>>()?,
             // NOTE: This field is set based on mullvad-types. It's not based on the actual settings
             // version.
             settings_version: CURRENT_SETTINGS_VERSION,
@@ -183,6 +319,35 @@ impl TryFrom<proto::Settings> for mullvad_types::settings::Settings {
             api_access_methods: mullvad_types::access_method::Settings::try_from(
                 api_access_methods_settings,
             )?,
+            prefer_api_in_tunnel: settings.prefer_api_in_tunnel,
+            // Missing in settings from older daemons, which have no trust rules
+            network_trust: settings
+                .network_trust
+                .map(mullvad_types::network_trust::NetworkTrustSettings::try_from)
+                .transpose()?
+                .unwrap_or_default(),
+            // Missing in settings from older daemons, which have no hooks
+            hooks: settings
+                .hooks
+                .map(mullvad_types::settings::HookSettings::from)
+                .unwrap_or_default(),
+            // Missing in settings from older daemons, which used the default backoff
+            retry_backoff: settings
+                .retry_backoff
+                .map(RetryBackoff::try_from)
+                .transpose()?
+                .unwrap_or_default(),
+            // Missing in settings from older daemons, which sent no warnings
+            account_expiry_warnings: settings
+                .account_expiry_warnings
+                .map(mullvad_types::settings::AccountExpiryWarnings::try_from)
+                .transpose()?
+                .unwrap_or_default(),
+            // Missing in settings from older daemons, which always used the defaults
+            log_rotation: settings
+                .log_rotation
+                .map(mullvad_types::settings::LogRotationSettings::from)
+                .unwrap_or_default(),
         })
     }
 }
@@ -204,15 +369,277 @@ pub fn try_bridge_state_from_i32(
     }
 }
 
+#[cfg(target_os = "linux")]
+impl From<mullvad_types::settings::RoutingSettings> for proto::RoutingSettings {
+    fn from(settings: mullvad_types::settings::RoutingSettings) -> Self {
+        Self {
+            route_table: settings.route_table,
+            rule_priority: settings.rule_priority.unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<proto::RoutingSettings> for mullvad_types::settings::RoutingSettings {
+    fn from(settings: proto::RoutingSettings) -> Self {
+        Self {
+            route_table: settings.route_table,
+            rule_priority: (settings.rule_priority != 0).then_some(settings.rule_priority),
+        }
+    }
+}
+
+impl From<SplitTunnelMode> for proto::SplitTunnelMode {
+    fn from(mode: SplitTunnelMode) -> Self {
+        Self {
+            mode: i32::from(match mode {
+                SplitTunnelMode::Exclude => proto::split_tunnel_mode::Mode::Exclude,
+                SplitTunnelMode::Include => proto::split_tunnel_mode::Mode::Include,
+            }),
+        }
+    }
+}
+
+impl TryFrom<proto::SplitTunnelMode> for SplitTunnelMode {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(mode: proto::SplitTunnelMode) -> Result<Self, Self::Error> {
+        match proto::split_tunnel_mode::Mode::try_from(mode.mode) {
+            Ok(proto::split_tunnel_mode::Mode::Exclude) => Ok(SplitTunnelMode::Exclude),
+            Ok(proto::split_tunnel_mode::Mode::Include) => Ok(SplitTunnelMode::Include),
+            Err(_) => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid split tunnel mode",
+            )),
+        }
+    }
+}
+
+impl From<ExcludedDestination> for proto::SplitTunnelDestination {
+    fn from(destination: ExcludedDestination) -> Self {
+        Self {
+            network: destination.network.to_string(),
+            ports: destination.ports.map(|ports| proto::PortRange {
+                first: u32::from(ports.start),
+                last: u32::from(ports.end),
+            }),
+            protocol: i32::from(proto::TransportProtocol::from(destination.protocol)),
+        }
+    }
+}
+
+impl TryFrom<proto::SplitTunnelDestination> for ExcludedDestination {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(destination: proto::SplitTunnelDestination) -> Result<Self, Self::Error> {
+        let ports = destination
+            .ports
+            .map(|ports| {
+                let port = |port: u32| {
+                    u16::try_from(port)
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))
+                };
+                PortRange::new(port(ports.first)?, port(ports.last)?)
+                    .ok_or(FromProtobufTypeError::InvalidArgument("invalid port range"))
+            })
+            .transpose()?;
+        Ok(ExcludedDestination {
+            network: arg_from_str(&destination.network, "invalid network")?,
+            ports,
+            protocol: try_transport_protocol_from_i32(destination.protocol)?,
+        })
+    }
+}
+
+impl From<(TransportProtocol, u16)> for proto::SplitTunnelLocalPort {
+    fn from((protocol, port): (TransportProtocol, u16)) -> Self {
+        Self {
+            protocol: i32::from(proto::TransportProtocol::from(protocol)),
+            port: u32::from(port),
+        }
+    }
+}
+
+impl TryFrom<proto::SplitTunnelLocalPort> for (TransportProtocol, u16) {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(port: proto::SplitTunnelLocalPort) -> Result<Self, Self::Error> {
+        Ok((
+            try_transport_protocol_from_i32(port.protocol)?,
+            u16::try_from(port.port)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))?,
+        ))
+    }
+}
+
+impl From<&mullvad_types::settings::LocalProxySettings> for proto::LocalProxySettings {
+    fn from(settings: &mullvad_types::settings::LocalProxySettings) -> Self {
+        Self {
+            port: settings.port.map(u32::from).unwrap_or(0),
+            authentication: settings
+                .authentication
+                .clone()
+                .map(proto::access_method::SocksAuth::from),
+        }
+    }
+}
+
+impl TryFrom<proto::LocalProxySettings> for mullvad_types::settings::LocalProxySettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: proto::LocalProxySettings) -> Result<Self, Self::Error> {
+        let port = match settings.port {
+            0 => None,
+            port => Some(
+                u16::try_from(port)
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))?,
+            ),
+        };
+        Ok(Self {
+            port,
+            authentication: settings
+                .authentication
+                .map(mullvad_types::access_method::SocksAuth::from),
+        })
+    }
+}
+
+impl From<&mullvad_types::settings::HookSettings> for proto::HookSettings {
+    fn from(settings: &mullvad_types::settings::HookSettings) -> Self {
+        let path = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        Self {
+            on_connect: path(&settings.on_connect),
+            on_disconnect: path(&settings.on_disconnect),
+            on_error: path(&settings.on_error),
+        }
+    }
+}
+
+impl From<proto::HookSettings> for mullvad_types::settings::HookSettings {
+    fn from(settings: proto::HookSettings) -> Self {
+        let path = |path: String| (!path.is_empty()).then(|| PathBuf::from(path));
+        Self {
+            on_connect: path(settings.on_connect),
+            on_disconnect: path(settings.on_disconnect),
+            on_error: path(settings.on_error),
+        }
+    }
+}
+
+impl From<&RetryBackoff> for proto::RetryBackoff {
+    fn from(backoff: &RetryBackoff) -> Self {
+        let duration = |duration: Duration| prost_types::Duration::try_from(duration).ok();
+        Self {
+            initial: duration(backoff.initial),
+            multiplier: backoff.multiplier,
+            max: duration(backoff.max),
+            reset_after_connected_for: duration(backoff.reset_after_connected_for),
+        }
+    }
+}
+
+impl TryFrom<proto::RetryBackoff> for RetryBackoff {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(backoff: proto::RetryBackoff) -> Result<Self, Self::Error> {
+        let duration = |duration: Option<prost_types::Duration>| {
+            duration
+                .ok_or(FromProtobufTypeError::InvalidArgument("missing duration"))
+                .and_then(|duration| {
+                    Duration::try_from(duration)
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid duration"))
+                })
+        };
+        Ok(Self {
+            initial: duration(backoff.initial)?,
+            multiplier: backoff.multiplier,
+            max: duration(backoff.max)?,
+            reset_after_connected_for: duration(backoff.reset_after_connected_for)?,
+        })
+    }
+}
+
+impl From<&mullvad_types::settings::AccountExpiryWarnings> for proto::AccountExpiryWarnings {
+    fn from(warnings: &mullvad_types::settings::AccountExpiryWarnings) -> Self {
+        Self {
+            thresholds: warnings
+                .thresholds
+                .iter()
+                .filter_map(|threshold| prost_types::Duration::try_from(*threshold).ok())
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::AccountExpiryWarnings> for mullvad_types::settings::AccountExpiryWarnings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(warnings: proto::AccountExpiryWarnings) -> Result<Self, Self::Error> {
+        let thresholds = warnings
+            .thresholds
+            .into_iter()
+            .map(|threshold| {
+                Duration::try_from(threshold)
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid threshold"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { thresholds })
+    }
+}
+
+impl From<&mullvad_types::settings::LogRotationSettings> for proto::LogRotationSettings {
+    fn from(settings: &mullvad_types::settings::LogRotationSettings) -> Self {
+        Self {
+            max_size_mib: settings.max_size_mib,
+            max_archives: settings.max_archives,
+        }
+    }
+}
+
+impl From<proto::LogRotationSettings> for mullvad_types::settings::LogRotationSettings {
+    fn from(settings: proto::LogRotationSettings) -> Self {
+        Self {
+            max_size_mib: settings.max_size_mib,
+            max_archives: settings.max_archives,
+        }
+    }
+}
+
+impl From<mullvad_types::settings::ResetScopes> for proto::FactoryResetRequest {
+    fn from(scopes: mullvad_types::settings::ResetScopes) -> Self {
+        Self {
+            settings: scopes.settings,
+            cache: scopes.cache,
+            history: scopes.history,
+        }
+    }
+}
+
+impl From<proto::FactoryResetRequest> for mullvad_types::settings::ResetScopes {
+    fn from(request: proto::FactoryResetRequest) -> Self {
+        Self {
+            settings: request.settings,
+            cache: request.cache,
+            history: request.history,
+        }
+    }
+}
+
 #[cfg(windows)]
 impl From<proto::SplitTunnelSettings> for mullvad_types::settings::SplitTunnelSettings {
     fn from(value: proto::SplitTunnelSettings) -> Self {
+        use mullvad_types::settings::SplitApp;
+
         mullvad_types::settings::SplitTunnelSettings {
             enable_exclusions: value.enable_exclusions,
             apps: value
                 .apps
                 .into_iter()
-                .map(std::path::PathBuf::from)
+                .map(|path| SplitApp::Path(std::path::PathBuf::from(path)))
+                .chain(value.publishers.into_iter().map(SplitApp::Publisher))
                 .collect(),
         }
     }
@@ -342,7 +769,257 @@ impl TryFrom<proto::DnsOptions> for mullvad_types::settings::DnsOptions {
                         })
                     })
                     .collect::<Result<Vec<_>, _>>()?,
+                tls_servers: custom_options
+                    .tls_servers
+                    .into_iter()
+                    .map(|server| {
+                        Ok::<_, FromProtobufTypeError>(talpid_types::net::TlsDnsServer {
+                            address: server.address.parse().map_err(|_| {
+                                FromProtobufTypeError::InvalidArgument("invalid IP address")
+                            })?,
+                            hostname: server.hostname,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
             },
+            preserve_search_domains: options.preserve_search_domains,
+            allow_lan_dns_when_blocked: options.allow_lan_dns_when_blocked,
+            keep_custom_dns_while_disconnected: options.keep_custom_dns_while_disconnected,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::settings::{CustomDnsOptions, DefaultDnsOptions, DnsOptions, DnsState};
+
+    #[test]
+    fn test_dns_options_roundtrip() {
+        let options = DnsOptions {
+            state: DnsState::Default,
+            default_options: DefaultDnsOptions {
+                block_ads: true,
+                block_trackers: true,
+                block_social_media: true,
+                ..Default::default()
+            },
+            custom_options: CustomDnsOptions {
+                addresses: vec!["9.9.9.9".parse().unwrap(), "2620:fe::fe".parse().unwrap()],
+                tls_servers: vec![talpid_types::net::TlsDnsServer {
+                    address: "9.9.9.9".parse().unwrap(),
+                    hostname: "dns.quad9.net".to_owned(),
+                }],
+            },
+            preserve_search_domains: true,
+            allow_lan_dns_when_blocked: true,
+            keep_custom_dns_while_disconnected: false,
+        };
+
+        let proto_options = proto::DnsOptions::from(&options);
+        assert_eq!(DnsOptions::try_from(proto_options).unwrap(), options);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_split_tunnel_owners_roundtrip() {
+        let settings = mullvad_types::settings::Settings {
+            split_tunnel: mullvad_types::settings::SplitTunnelSettings {
+                users: vec![1001, 1002],
+                groups: vec![100],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let proto_settings = proto::Settings::from(&settings);
+        assert_eq!(proto_settings.split_tunnel_users, vec![1001, 1002]);
+        assert_eq!(proto_settings.split_tunnel_groups, vec![100]);
+
+        let converted = mullvad_types::settings::Settings::try_from(proto_settings).unwrap();
+        assert_eq!(converted.split_tunnel, settings.split_tunnel);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_split_tunnel_mode_roundtrip() {
+        let settings = mullvad_types::settings::Settings {
+            split_tunnel: mullvad_types::settings::SplitTunnelSettings {
+                mode: SplitTunnelMode::Include,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut proto_settings = proto::Settings::from(&settings);
+        let converted =
+            mullvad_types::settings::Settings::try_from(proto_settings.clone()).unwrap();
+        assert_eq!(converted.split_tunnel.mode, SplitTunnelMode::Include);
+
+        proto_settings.split_tunnel_mode = None;
+        let converted = mullvad_types::settings::Settings::try_from(proto_settings).unwrap();
+        assert_eq!(converted.split_tunnel.mode, SplitTunnelMode::Exclude);
+    }
+
+    #[test]
+    fn test_split_tunnel_destinations_roundtrip() {
+        let destinations = vec![
+            ExcludedDestination {
+                network: "10.0.0.0/8".parse().unwrap(),
+                ports: Some(PortRange::new(445, 445).unwrap()),
+                protocol: TransportProtocol::Tcp,
+            },
+            ExcludedDestination {
+                network: "fd00::/8".parse().unwrap(),
+                ports: None,
+                protocol: TransportProtocol::Udp,
+            },
+        ];
+        #[cfg(target_os = "linux")]
+        {
+            let settings = mullvad_types::settings::Settings {
+                split_tunnel: mullvad_types::settings::SplitTunnelSettings {
+                    destinations: destinations.clone(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            let proto_settings = proto::Settings::from(&settings);
+            let converted = mullvad_types::settings::Settings::try_from(proto_settings).unwrap();
+            assert_eq!(converted.split_tunnel.destinations, destinations);
+        }
+
+        let mut destination = proto::SplitTunnelDestination::from(destinations[0]);
+        destination.ports = Some(proto::PortRange {
+            first: 8100,
+            last: 8000,
+        });
+        assert!(ExcludedDestination::try_from(destination).is_err());
+    }
+
+    #[test]
+    fn test_split_tunnel_local_ports_roundtrip() {
+        let ports = vec![
+            (TransportProtocol::Udp, 51820),
+            (TransportProtocol::Tcp, 25565),
+        ];
+        #[cfg(target_os = "linux")]
+        {
+            let settings = mullvad_types::settings::Settings {
+                split_tunnel: mullvad_types::settings::SplitTunnelSettings {
+                    local_ports: ports.clone(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            let proto_settings = proto::Settings::from(&settings);
+            let converted = mullvad_types::settings::Settings::try_from(proto_settings).unwrap();
+            assert_eq!(converted.split_tunnel.local_ports, ports);
+        }
+
+        let mut port = proto::SplitTunnelLocalPort::from(ports[0]);
+        port.port = 70000;
+        assert!(<(TransportProtocol, u16)>::try_from(port).is_err());
+    }
+
+    #[test]
+    fn test_local_proxy_roundtrip() {
+        let local_proxy = mullvad_types::settings::LocalProxySettings {
+            port: Some(1080),
+            authentication: Some(mullvad_types::access_method::SocksAuth {
+                username: "user".to_owned(),
+                password: "secret".to_owned(),
+            }),
+        };
+        let settings = mullvad_types::settings::Settings {
+            local_proxy: local_proxy.clone(),
+            ..Default::default()
+        };
+
+        let mut proto_settings = proto::Settings::from(&settings);
+        let converted =
+            mullvad_types::settings::Settings::try_from(proto_settings.clone()).unwrap();
+        assert_eq!(converted.local_proxy, local_proxy);
+
+        // Older daemons do not send the setting
+        proto_settings.local_proxy = None;
+        let converted = mullvad_types::settings::Settings::try_from(proto_settings).unwrap();
+        assert_eq!(converted.local_proxy.port, None);
+    }
+
+    #[test]
+    fn test_hooks_roundtrip() {
+        let hooks = mullvad_types::settings::HookSettings {
+            on_connect: Some(PathBuf::from("/usr/local/bin/update-ddns")),
+            on_disconnect: None,
+            on_error: Some(PathBuf::from("/usr/local/bin/notify")),
+        };
+        let proto_hooks = proto::HookSettings::from(&hooks);
+        assert_eq!(proto_hooks.on_disconnect, "");
+        assert_eq!(
+            mullvad_types::settings::HookSettings::from(proto_hooks),
+            hooks
+        );
+    }
+
+    #[test]
+    fn test_retry_backoff_roundtrip() {
+        let backoff = RetryBackoff {
+            initial: Duration::from_millis(500),
+            multiplier: 1.5,
+            max: Duration::from_secs(30),
+            reset_after_connected_for: Duration::from_secs(120),
+        };
+        let proto_backoff = proto::RetryBackoff::from(&backoff);
+        assert_eq!(RetryBackoff::try_from(proto_backoff).unwrap(), backoff);
+    }
+
+    #[test]
+    fn test_account_expiry_warnings_roundtrip() {
+        let warnings = mullvad_types::settings::AccountExpiryWarnings {
+            thresholds: vec![
+                Duration::from_secs(2 * 24 * 60 * 60),
+                Duration::from_secs(600),
+            ],
+        };
+        let proto_warnings = proto::AccountExpiryWarnings::from(&warnings);
+        assert_eq!(
+            mullvad_types::settings::AccountExpiryWarnings::try_from(proto_warnings).unwrap(),
+            warnings
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_excluded_apps_allow_lan_defaults_to_allowed() {
+        let settings = mullvad_types::settings::Settings {
+            split_tunnel: mullvad_types::settings::SplitTunnelSettings {
+                excluded_apps_allow_lan: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut proto_settings = proto::Settings::from(&settings);
+        assert_eq!(proto_settings.excluded_apps_allow_lan, Some(false));
+        let converted =
+            mullvad_types::settings::Settings::try_from(proto_settings.clone()).unwrap();
+        assert!(!converted.split_tunnel.excluded_apps_allow_lan);
+
+        proto_settings.excluded_apps_allow_lan = None;
+        let converted = mullvad_types::settings::Settings::try_from(proto_settings).unwrap();
+        assert!(converted.split_tunnel.excluded_apps_allow_lan);
+    }
+
+    #[test]
+    fn test_invalid_dns_options() {
+        let mut proto_options = proto::DnsOptions::from(&DnsOptions::default());
+        proto_options.state = -1;
+        assert!(DnsOptions::try_from(proto_options).is_err());
+
+        let mut proto_options = proto::DnsOptions::from(&DnsOptions::default());
+        proto_options.default_options = None;
+        assert!(DnsOptions::try_from(proto_options).is_err());
+    }
+}
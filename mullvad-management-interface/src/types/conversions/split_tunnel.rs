@@ -1,23 +1,256 @@
-use crate::types;
+use crate::types::{self, FromProtobufTypeError};
+use mullvad_types::split_tunnel::SplitTunnelWarning;
 use std::path::PathBuf;
-use talpid_types::split_tunnel::ExcludedProcess;
+use talpid_types::split_tunnel::{
+    AppExclusionState, AppExclusionStatus, ExcludedApp, ExcludedProcess, ExclusionReason,
+};
 
 impl From<ExcludedProcess> for types::ExcludedProcess {
     fn from(value: ExcludedProcess) -> Self {
+        use types::excluded_process::Reason;
+
+        let (reason, owner_id, publisher) = match value.reason {
+            ExclusionReason::Path => (Reason::Path, 0, String::new()),
+            ExclusionReason::Publisher(publisher) => (Reason::Publisher, 0, publisher),
+            ExclusionReason::CGroup => (Reason::Cgroup, 0, String::new()),
+            ExclusionReason::User(uid) => (Reason::User, uid, String::new()),
+            ExclusionReason::Group(gid) => (Reason::Group, gid, String::new()),
+        };
         types::ExcludedProcess {
             image: value.image.to_string_lossy().into_owned(),
             inherited: value.inherited,
             pid: value.pid,
+            reason: i32::from(reason),
+            owner_id,
+            publisher,
         }
     }
 }
 
 impl From<types::ExcludedProcess> for ExcludedProcess {
     fn from(value: types::ExcludedProcess) -> Self {
+        use types::excluded_process::Reason;
+
+        let reason = match Reason::try_from(value.reason) {
+            Ok(Reason::Publisher) => ExclusionReason::Publisher(value.publisher),
+            Ok(Reason::Cgroup) => ExclusionReason::CGroup,
+            Ok(Reason::User) => ExclusionReason::User(value.owner_id),
+            Ok(Reason::Group) => ExclusionReason::Group(value.owner_id),
+            // Older daemons only exclude processes by path
+            Ok(Reason::Path) | Err(_) => ExclusionReason::Path,
+        };
         ExcludedProcess {
             image: PathBuf::from(value.image),
             inherited: value.inherited,
             pid: value.pid,
+            reason,
+        }
+    }
+}
+
+impl From<Vec<ExcludedProcess>> for types::ExcludedProcessList {
+    fn from(processes: Vec<ExcludedProcess>) -> Self {
+        types::ExcludedProcessList {
+            processes: processes
+                .into_iter()
+                .map(types::ExcludedProcess::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<types::ExcludedProcessList> for Vec<ExcludedProcess> {
+    fn from(list: types::ExcludedProcessList) -> Self {
+        list.processes
+            .into_iter()
+            .map(ExcludedProcess::from)
+            .collect()
+    }
+}
+
+impl From<AppExclusionStatus> for types::AppExclusionStatus {
+    fn from(status: AppExclusionStatus) -> Self {
+        use types::app_exclusion_status::{App, State};
+
+        let app = match status.app {
+            ExcludedApp::Path(path) => App::Path(path.to_string_lossy().into_owned()),
+            ExcludedApp::Publisher(publisher) => App::Publisher(publisher),
+        };
+        let (state, error) = match status.state {
+            AppExclusionState::Applied => (State::Applied, String::new()),
+            AppExclusionState::Pending => (State::Pending, String::new()),
+            AppExclusionState::Failed(error) => (State::Failed, error),
+        };
+        types::AppExclusionStatus {
+            app: Some(app),
+            state: i32::from(state),
+            error,
+        }
+    }
+}
+
+impl TryFrom<types::AppExclusionStatus> for AppExclusionStatus {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(status: types::AppExclusionStatus) -> Result<Self, Self::Error> {
+        use types::app_exclusion_status::{App, State};
+
+        let app = match status.app {
+            Some(App::Path(path)) => ExcludedApp::Path(PathBuf::from(path)),
+            Some(App::Publisher(publisher)) => ExcludedApp::Publisher(publisher),
+            None => return Err(FromProtobufTypeError::InvalidArgument("missing app")),
+        };
+        let state = match State::try_from(status.state) {
+            Ok(State::Applied) => AppExclusionState::Applied,
+            Ok(State::Pending) => AppExclusionState::Pending,
+            Ok(State::Failed) => AppExclusionState::Failed(status.error),
+            Err(_) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid app exclusion state",
+                ))
+            }
+        };
+        Ok(AppExclusionStatus { app, state })
+    }
+}
+
+impl From<Vec<AppExclusionStatus>> for types::AppExclusionStatusList {
+    fn from(statuses: Vec<AppExclusionStatus>) -> Self {
+        types::AppExclusionStatusList {
+            apps: statuses
+                .into_iter()
+                .map(types::AppExclusionStatus::from)
+                .collect(),
         }
     }
 }
+
+impl TryFrom<types::AppExclusionStatusList> for Vec<AppExclusionStatus> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(list: types::AppExclusionStatusList) -> Result<Self, Self::Error> {
+        list.apps
+            .into_iter()
+            .map(AppExclusionStatus::try_from)
+            .collect()
+    }
+}
+
+impl From<Vec<SplitTunnelWarning>> for types::SplitTunnelWarnings {
+    fn from(warnings: Vec<SplitTunnelWarning>) -> Self {
+        use types::split_tunnel_warnings::Warning;
+
+        types::SplitTunnelWarnings {
+            warnings: warnings
+                .into_iter()
+                .map(|warning| {
+                    i32::from(match warning {
+                        SplitTunnelWarning::ExcludedAppsUseTunnelDns => {
+                            Warning::ExcludedAppsUseTunnelDns
+                        }
+                        SplitTunnelWarning::LockdownBlocksUnselectedTraffic => {
+                            Warning::LockdownBlocksUnselectedTraffic
+                        }
+                        SplitTunnelWarning::NoSelectedApps => Warning::NoSelectedApps,
+                        SplitTunnelWarning::LocalProxyNotRun => Warning::LocalProxyNotRun,
+                        SplitTunnelWarning::ExclusionsDisabled => Warning::ExclusionsDisabled,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<types::SplitTunnelWarnings> for Vec<SplitTunnelWarning> {
+    fn from(warnings: types::SplitTunnelWarnings) -> Self {
+        use types::split_tunnel_warnings::Warning;
+
+        warnings
+            .warnings
+            .into_iter()
+            // Warnings that are only known by newer daemons are skipped
+            .filter_map(|warning| match Warning::try_from(warning) {
+                Ok(Warning::ExcludedAppsUseTunnelDns) => {
+                    Some(SplitTunnelWarning::ExcludedAppsUseTunnelDns)
+                }
+                Ok(Warning::LockdownBlocksUnselectedTraffic) => {
+                    Some(SplitTunnelWarning::LockdownBlocksUnselectedTraffic)
+                }
+                Ok(Warning::NoSelectedApps) => Some(SplitTunnelWarning::NoSelectedApps),
+                Ok(Warning::LocalProxyNotRun) => Some(SplitTunnelWarning::LocalProxyNotRun),
+                Ok(Warning::ExclusionsDisabled) => Some(SplitTunnelWarning::ExclusionsDisabled),
+                Err(_) => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_excluded_process_roundtrip() {
+        for reason in [
+            ExclusionReason::Path,
+            ExclusionReason::Publisher("Mozilla Corporation".to_owned()),
+            ExclusionReason::CGroup,
+            ExclusionReason::User(1001),
+            ExclusionReason::Group(100),
+        ] {
+            let process = ExcludedProcess {
+                pid: 1234,
+                image: PathBuf::from("/usr/bin/transmission-gtk"),
+                inherited: false,
+                reason,
+            };
+            let converted = ExcludedProcess::from(types::ExcludedProcess::from(process.clone()));
+            assert_eq!(converted, process);
+        }
+    }
+
+    #[test]
+    fn test_app_exclusion_status_roundtrip() {
+        let statuses = vec![
+            AppExclusionStatus {
+                app: ExcludedApp::Path(PathBuf::from(r"C:\app.exe")),
+                state: AppExclusionState::Applied,
+            },
+            AppExclusionStatus {
+                app: ExcludedApp::Publisher("Mozilla Corporation".to_owned()),
+                state: AppExclusionState::Pending,
+            },
+            AppExclusionStatus {
+                app: ExcludedApp::Path(PathBuf::from(r"C:\rejected.exe")),
+                state: AppExclusionState::Failed("access denied".to_owned()),
+            },
+        ];
+        let converted = Vec::<AppExclusionStatus>::try_from(types::AppExclusionStatusList::from(
+            statuses.clone(),
+        ))
+        .unwrap();
+        assert_eq!(converted, statuses);
+    }
+
+    #[test]
+    fn test_split_tunnel_warnings_roundtrip() {
+        let warnings = vec![
+            SplitTunnelWarning::ExcludedAppsUseTunnelDns,
+            SplitTunnelWarning::LockdownBlocksUnselectedTraffic,
+            SplitTunnelWarning::NoSelectedApps,
+            SplitTunnelWarning::LocalProxyNotRun,
+            SplitTunnelWarning::ExclusionsDisabled,
+        ];
+        let converted =
+            Vec::<SplitTunnelWarning>::from(types::SplitTunnelWarnings::from(warnings.clone()));
+        assert_eq!(converted, warnings);
+
+        let unknown = types::SplitTunnelWarnings {
+            warnings: vec![1, 100],
+        };
+        assert_eq!(
+            Vec::<SplitTunnelWarning>::from(unknown),
+            vec![SplitTunnelWarning::LockdownBlocksUnselectedTraffic]
+        );
+    }
+}
@@ -37,22 +37,38 @@ impl From<mullvad_types::states::TunnelState> for proto::TunnelState {
             MullvadTunnelState::Disconnected => {
                 proto::tunnel_state::State::Disconnected(proto::tunnel_state::Disconnected {})
             }
-            MullvadTunnelState::Connecting { endpoint, location } => {
-                proto::tunnel_state::State::Connecting(proto::tunnel_state::Connecting {
-                    relay_info: Some(proto::TunnelStateRelayInfo {
-                        tunnel_endpoint: Some(proto::TunnelEndpoint::from(endpoint)),
-                        location: location.map(proto::GeoIpLocation::from),
-                    }),
-                })
-            }
-            MullvadTunnelState::Connected { endpoint, location } => {
-                proto::tunnel_state::State::Connected(proto::tunnel_state::Connected {
-                    relay_info: Some(proto::TunnelStateRelayInfo {
-                        tunnel_endpoint: Some(proto::TunnelEndpoint::from(endpoint)),
-                        location: location.map(proto::GeoIpLocation::from),
-                    }),
-                })
-            }
+            MullvadTunnelState::Connecting {
+                endpoint,
+                location,
+                phase,
+                attempt,
+            } => proto::tunnel_state::State::Connecting(proto::tunnel_state::Connecting {
+                relay_info: Some(proto::TunnelStateRelayInfo {
+                    tunnel_endpoint: Some(proto::TunnelEndpoint::from(endpoint)),
+                    location: location.map(proto::GeoIpLocation::from),
+                }),
+                waiting_for_network: phase == talpid_tunnel::ConnectingPhase::WaitingForNetwork,
+                waiting_for_stable_network: phase
+                    == talpid_tunnel::ConnectingPhase::WaitingForStableNetwork,
+                waiting_to_retry: phase == talpid_tunnel::ConnectingPhase::WaitingToRetry,
+                attempt: attempt.attempt,
+                retry_timeout: attempt
+                    .retry_timeout
+                    .and_then(|timeout| prost_types::Duration::try_from(timeout).ok()),
+            }),
+            MullvadTunnelState::Connected {
+                endpoint,
+                location,
+                effective_dns,
+                first_hop,
+            } => proto::tunnel_state::State::Connected(proto::tunnel_state::Connected {
+                relay_info: Some(proto::TunnelStateRelayInfo {
+                    tunnel_endpoint: Some(proto::TunnelEndpoint::from(endpoint)),
+                    location: location.map(proto::GeoIpLocation::from),
+                }),
+                effective_dns: Some(proto::EffectiveDns::from(effective_dns)),
+                first_hop: first_hop.map(proto::FirstHop::from),
+            }),
             MullvadTunnelState::Disconnecting(after_disconnect) => {
                 proto::tunnel_state::State::Disconnecting(proto::tunnel_state::Disconnecting {
                     after_disconnect: match after_disconnect {
@@ -93,6 +109,9 @@ impl From<mullvad_types::states::TunnelState> for proto::TunnelState {
                             talpid_tunnel::ErrorStateCause::IsOffline => {
                                 i32::from(Cause::IsOffline)
                             }
+                            talpid_tunnel::ErrorStateCause::AccountExpired => {
+                                i32::from(Cause::AccountExpired)
+                            }
                             #[cfg(target_os = "android")]
                             talpid_tunnel::ErrorStateCause::VpnPermissionDenied => {
                                 i32::from(Cause::VpnPermissionDenied)
@@ -140,6 +159,9 @@ impl From<mullvad_types::states::TunnelState> for proto::TunnelState {
                             } else {
                                 None
                             },
+                        error_code: error_state.cause().error_code().code(),
+                        error_id: error_state.cause().error_code().as_str().to_owned(),
+                        description: error_state.cause().to_string(),
                     }),
                 })
             }
@@ -198,11 +220,32 @@ impl TryFrom<proto::TunnelState> for mullvad_types::states::TunnelState {
                         tunnel_endpoint: Some(tunnel_endpoint),
                         location,
                     }),
+                waiting_for_network,
+                waiting_for_stable_network,
+                waiting_to_retry,
+                attempt,
+                retry_timeout,
             })) => MullvadState::Connecting {
                 endpoint: talpid_net::TunnelEndpoint::try_from(tunnel_endpoint)?,
                 location: location
                     .map(mullvad_types::location::GeoIpLocation::try_from)
                     .transpose()?,
+                phase: if waiting_for_stable_network {
+                    talpid_tunnel::ConnectingPhase::WaitingForStableNetwork
+                } else if waiting_to_retry {
+                    talpid_tunnel::ConnectingPhase::WaitingToRetry
+                } else if waiting_for_network {
+                    talpid_tunnel::ConnectingPhase::WaitingForNetwork
+                } else {
+                    talpid_tunnel::ConnectingPhase::EstablishingTunnel
+                },
+                attempt: talpid_tunnel::ConnectionAttempt {
+                    attempt,
+                    retry_timeout: retry_timeout
+                        .map(std::time::Duration::try_from)
+                        .transpose()
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid duration"))?,
+                },
             },
             Some(proto::tunnel_state::State::Connected(proto::tunnel_state::Connected {
                 relay_info:
@@ -210,11 +253,20 @@ impl TryFrom<proto::TunnelState> for mullvad_types::states::TunnelState {
                         tunnel_endpoint: Some(tunnel_endpoint),
                         location,
                     }),
+                effective_dns,
+                first_hop,
             })) => MullvadState::Connected {
                 endpoint: talpid_net::TunnelEndpoint::try_from(tunnel_endpoint)?,
                 location: location
                     .map(mullvad_types::location::GeoIpLocation::try_from)
                     .transpose()?,
+                effective_dns: effective_dns
+                    .map(talpid_net::EffectiveDns::try_from)
+                    .transpose()?
+                    .unwrap_or_default(),
+                first_hop: first_hop
+                    .map(mullvad_types::features::FirstHop::try_from)
+                    .transpose()?,
             },
             Some(proto::tunnel_state::State::Disconnecting(
                 proto::tunnel_state::Disconnecting { after_disconnect },
@@ -244,6 +296,7 @@ impl TryFrom<proto::TunnelState> for mullvad_types::states::TunnelState {
                         auth_failed_error,
                         parameter_error,
                         policy_error,
+                        ..
                     }),
             })) => {
                 let cause = match proto::error_state::Cause::try_from(cause) {
@@ -259,6 +312,9 @@ impl TryFrom<proto::TunnelState> for mullvad_types::states::TunnelState {
                     Ok(proto::error_state::Cause::IsOffline) => {
                         talpid_tunnel::ErrorStateCause::IsOffline
                     }
+                    Ok(proto::error_state::Cause::AccountExpired) => {
+                        talpid_tunnel::ErrorStateCause::AccountExpired
+                    }
                     Ok(proto::error_state::Cause::SetDnsError) => {
                         talpid_tunnel::ErrorStateCause::SetDnsError
                     }
@@ -353,3 +409,200 @@ fn try_firewall_policy_error_from_i32(
         )),
     }
 }
+
+impl From<talpid_types::net::EffectiveDns> for proto::EffectiveDns {
+    fn from(effective_dns: talpid_types::net::EffectiveDns) -> Self {
+        use proto::effective_dns::DnsSource;
+        use talpid_types::net::DnsSource as TalpidDnsSource;
+
+        let source = match effective_dns.source {
+            TalpidDnsSource::Default => DnsSource::Default,
+            TalpidDnsSource::Custom => DnsSource::Custom,
+            TalpidDnsSource::ContentBlockers => DnsSource::ContentBlockers,
+        };
+
+        proto::EffectiveDns {
+            servers: effective_dns
+                .servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect(),
+            source: i32::from(source),
+            tampered: effective_dns.tampered,
+        }
+    }
+}
+
+impl TryFrom<proto::EffectiveDns> for talpid_types::net::EffectiveDns {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(effective_dns: proto::EffectiveDns) -> Result<Self, Self::Error> {
+        use proto::effective_dns::DnsSource;
+        use talpid_types::net::DnsSource as TalpidDnsSource;
+
+        let source = match DnsSource::try_from(effective_dns.source) {
+            Ok(DnsSource::Default) => TalpidDnsSource::Default,
+            Ok(DnsSource::Custom) => TalpidDnsSource::Custom,
+            Ok(DnsSource::ContentBlockers) => TalpidDnsSource::ContentBlockers,
+            Err(_) => return Err(FromProtobufTypeError::InvalidArgument("invalid DNS source")),
+        };
+        let servers = effective_dns
+            .servers
+            .iter()
+            .map(|server| {
+                server
+                    .parse()
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid DNS server"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(talpid_types::net::EffectiveDns {
+            servers,
+            source,
+            tampered: effective_dns.tampered,
+        })
+    }
+}
+
+impl From<mullvad_types::features::FirstHop> for proto::FirstHop {
+    fn from(first_hop: mullvad_types::features::FirstHop) -> Self {
+        use mullvad_types::features::FirstHopKind;
+        use proto::first_hop::Kind;
+        use talpid_types::net::{proxy::ProxyType, ObfuscationType};
+
+        let mut proto_first_hop = proto::FirstHop {
+            endpoint: Some(proto::Endpoint {
+                address: first_hop.endpoint.address.to_string(),
+                protocol: i32::from(proto::TransportProtocol::from(first_hop.endpoint.protocol)),
+            }),
+            ..Default::default()
+        };
+        let kind = match first_hop.kind {
+            FirstHopKind::Relay => Kind::Relay,
+            FirstHopKind::EntryRelay => Kind::EntryRelay,
+            FirstHopKind::Bridge(proxy_type) => {
+                proto_first_hop.proxy_type = i32::from(match proxy_type {
+                    ProxyType::Shadowsocks => proto::ProxyType::Shadowsocks,
+                    ProxyType::Custom => proto::ProxyType::Custom,
+                });
+                Kind::Bridge
+            }
+            FirstHopKind::Obfuscator(obfuscation_type) => {
+                proto_first_hop.obfuscation_type = i32::from(match obfuscation_type {
+                    ObfuscationType::Udp2Tcp => proto::ObfuscationType::Udp2tcp,
+                    ObfuscationType::Quic => proto::ObfuscationType::Quic,
+                    ObfuscationType::Tls => proto::ObfuscationType::Tls,
+                });
+                Kind::Obfuscator
+            }
+        };
+        proto_first_hop.kind = i32::from(kind);
+        proto_first_hop
+    }
+}
+
+impl TryFrom<proto::FirstHop> for mullvad_types::features::FirstHop {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(first_hop: proto::FirstHop) -> Result<Self, Self::Error> {
+        use crate::types::conversions::{arg_from_str, net::try_transport_protocol_from_i32};
+        use mullvad_types::features::FirstHopKind;
+        use proto::first_hop::Kind;
+        use talpid_types::net::{proxy::ProxyType, Endpoint, ObfuscationType};
+
+        let endpoint = first_hop
+            .endpoint
+            .ok_or(FromProtobufTypeError::InvalidArgument(
+                "missing first hop endpoint",
+            ))?;
+        let endpoint = Endpoint {
+            address: arg_from_str(&endpoint.address, "invalid first hop address")?,
+            protocol: try_transport_protocol_from_i32(endpoint.protocol)?,
+        };
+        let kind = match Kind::try_from(first_hop.kind) {
+            Ok(Kind::Relay) => FirstHopKind::Relay,
+            Ok(Kind::EntryRelay) => FirstHopKind::EntryRelay,
+            Ok(Kind::Bridge) => {
+                FirstHopKind::Bridge(match proto::ProxyType::try_from(first_hop.proxy_type) {
+                    Ok(proto::ProxyType::Shadowsocks) => ProxyType::Shadowsocks,
+                    Ok(proto::ProxyType::Custom) => ProxyType::Custom,
+                    Err(_) => {
+                        return Err(FromProtobufTypeError::InvalidArgument("unknown proxy type"))
+                    }
+                })
+            }
+            Ok(Kind::Obfuscator) => FirstHopKind::Obfuscator(
+                match proto::ObfuscationType::try_from(first_hop.obfuscation_type) {
+                    Ok(proto::ObfuscationType::Udp2tcp) => ObfuscationType::Udp2Tcp,
+                    Ok(proto::ObfuscationType::Quic) => ObfuscationType::Quic,
+                    Ok(proto::ObfuscationType::Tls) => ObfuscationType::Tls,
+                    Err(_) => {
+                        return Err(FromProtobufTypeError::InvalidArgument(
+                            "unknown obfuscation type",
+                        ))
+                    }
+                },
+            ),
+            Err(_) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid first hop kind",
+                ))
+            }
+        };
+
+        Ok(mullvad_types::features::FirstHop { endpoint, kind })
+    }
+}
+
+impl From<mullvad_types::states::IdleDisconnect> for proto::IdleDisconnect {
+    fn from(event: mullvad_types::states::IdleDisconnect) -> Self {
+        proto::IdleDisconnect {
+            idle_for: prost_types::Duration::try_from(event.idle_for).ok(),
+            locked_down: event.locked_down,
+        }
+    }
+}
+
+impl TryFrom<proto::IdleDisconnect> for mullvad_types::states::IdleDisconnect {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(event: proto::IdleDisconnect) -> Result<Self, FromProtobufTypeError> {
+        let idle_for = event
+            .idle_for
+            .ok_or(FromProtobufTypeError::InvalidArgument(
+                "missing idle duration",
+            ))
+            .and_then(|idle_for| {
+                std::time::Duration::try_from(idle_for)
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid idle duration"))
+            })?;
+        Ok(mullvad_types::states::IdleDisconnect {
+            idle_for,
+            locked_down: event.locked_down,
+        })
+    }
+}
+
+impl From<Option<talpid_types::tunnel::TrafficStats>> for proto::TunnelStats {
+    fn from(stats: Option<talpid_types::tunnel::TrafficStats>) -> Self {
+        match stats {
+            Some(stats) => proto::TunnelStats {
+                available: true,
+                rx_bytes: stats.rx_bytes,
+                tx_bytes: stats.tx_bytes,
+            },
+            None => proto::TunnelStats::default(),
+        }
+    }
+}
+
+impl From<proto::TunnelStats> for Option<talpid_types::tunnel::TrafficStats> {
+    fn from(stats: proto::TunnelStats) -> Self {
+        stats
+            .available
+            .then_some(talpid_types::tunnel::TrafficStats {
+                rx_bytes: stats.rx_bytes,
+                tx_bytes: stats.tx_bytes,
+            })
+    }
+}
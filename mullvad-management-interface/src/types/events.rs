@@ -0,0 +1,24 @@
+use super::{daemon_event::Event, events_listen_request::Category};
+
+impl Event {
+    /// Returns the category that the event is filtered by in `EventsListen`.
+    pub fn category(&self) -> Category {
+        match self {
+            Event::TunnelState(_) | Event::IdleDisconnect(_) => Category::TunnelState,
+            Event::Settings(_) => Category::Settings,
+            Event::Device(_) | Event::RemoveDevice(_) | Event::AccountExpiryWarning(_) => {
+                Category::Device
+            }
+            Event::RelayList(_) => Category::RelayList,
+            Event::NetworkChanged(_)
+            | Event::RoutesUpdated(_)
+            | Event::ForeignTunnel(_)
+            | Event::DnsTampered(_)
+            | Event::ApiConnectivity(_) => Category::Network,
+            Event::VersionInfo(_)
+            | Event::SplitTunnelAppStatus(_)
+            | Event::ExcludedProcesses(_)
+            | Event::ProblemReportSent(_) => Category::Other,
+        }
+    }
+}
@@ -3,6 +3,7 @@ mod proto {
     tonic::include_proto!("mullvad_daemon.management_interface");
 }
 mod conversions;
+mod events;
 
 pub use prost_types::{Duration, Timestamp};
 
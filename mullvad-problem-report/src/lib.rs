@@ -1,5 +1,6 @@
 #![deny(rust_2018_idioms)]
 
+use flate2::read::GzDecoder;
 use mullvad_api::proxy::ApiConnectionMode;
 use once_cell::sync::Lazy;
 use regex::Regex;
@@ -15,12 +16,15 @@ use std::{
 use talpid_types::ErrorExt;
 
 pub mod metadata;
+pub mod outbox;
 
 /// Maximum number of bytes to read from each log file
 const LOG_MAX_READ_BYTES: usize = 128 * 1024;
+/// Maximum number of bytes to read from all compressed log archives together
+const ARCHIVES_MAX_READ_BYTES: usize = 256 * 1024;
 const EXTRA_BYTES: usize = 32 * 1024;
-/// Fit five logs plus some system information in the report.
-const REPORT_MAX_SIZE: usize = (5 * LOG_MAX_READ_BYTES) + EXTRA_BYTES;
+/// Fit five logs, the most recent log archives and some system information in the report.
+const REPORT_MAX_SIZE: usize = (5 * LOG_MAX_READ_BYTES) + ARCHIVES_MAX_READ_BYTES + EXTRA_BYTES;
 
 /// Field delimiter in generated problem report
 const LOG_DELIMITER: &str = "====================";
@@ -34,6 +38,10 @@ const LINE_SEPARATOR: &str = "\r\n";
 
 const MAX_SEND_ATTEMPTS: usize = 3;
 
+/// Name of the file in the cache directory where the daemon keeps its history of recent
+/// connections. It is included in problem reports.
+pub const CONNECTION_HISTORY_FILE: &str = "connection-history.json";
+
 /// Custom macro to write a line to an output formatter that uses platform-specific newline
 /// character sequences.
 macro_rules! write_line {
@@ -77,6 +85,18 @@ pub enum Error {
 
     #[error(display = "Unable to find cache directory")]
     ObtainCacheDirectory(#[error(source)] mullvad_paths::Error),
+
+    #[error(display = "Failed to connect to the daemon")]
+    DaemonConnectionError(#[error(source)] mullvad_management_interface::Error),
+
+    #[error(display = "Failed to queue the problem report")]
+    QueueProblemReportError(#[error(source)] mullvad_management_interface::Error),
+
+    #[error(display = "Failed to read queued problem reports")]
+    ReadQueueError(#[error(source)] mullvad_management_interface::Error),
+
+    #[error(display = "Failed to send queued problem reports")]
+    FlushQueueError(#[error(source)] mullvad_management_interface::Error),
 }
 
 /// These are errors that can happen during problem report collection.
@@ -125,9 +145,12 @@ pub fn collect_report<P: AsRef<Path>>(
         }
     };
 
-    let daemon_logs = daemon_logs_dir.and_then(list_logs);
+    let daemon_logs = daemon_logs_dir.and_then(|dir| {
+        let archives = list_log_archives(&dir);
+        list_logs(dir).map(|logs| (logs, archives))
+    });
     match daemon_logs {
-        Ok(daemon_logs) => {
+        Ok((daemon_logs, archives)) => {
             let mut other_logs = Vec::new();
             for log in daemon_logs {
                 match log {
@@ -144,6 +167,10 @@ pub fn collect_report<P: AsRef<Path>>(
             for other_log in other_logs {
                 problem_report.add_log(&other_log);
             }
+            match archives {
+                Ok(archives) => problem_report.add_log_archives(&archives),
+                Err(error) => problem_report.add_error("Failed to list log archives", &error),
+            }
         }
         Err(error) => {
             problem_report.add_error("Failed to list logs in daemon log directory", &error)
@@ -163,6 +190,16 @@ pub fn collect_report<P: AsRef<Path>>(
         }
         None => {}
     }
+    #[cfg(not(target_os = "android"))]
+    match mullvad_paths::get_cache_dir() {
+        Ok(cache_dir) => {
+            let history_path = cache_dir.join(CONNECTION_HISTORY_FILE);
+            if history_path.exists() {
+                problem_report.add_log(&history_path);
+            }
+        }
+        Err(error) => problem_report.add_error("Failed to find the cache directory", &error),
+    }
     #[cfg(target_os = "android")]
     match write_logcat_to_file(android_log_dir) {
         Ok(logcat_path) => problem_report.add_log(&logcat_path),
@@ -239,6 +276,34 @@ fn frontend_log_dir() -> Option<Result<PathBuf, LogError>> {
     }
 }
 
+/// Returns the compressed archives of the logs in the given directory, which are named like
+/// `daemon.1.log.gz`. The most recent archive of each log comes first.
+fn list_log_archives(log_dir: &Path) -> Result<Vec<PathBuf>, LogError> {
+    let list_error = |source| LogError::ListLogDir {
+        path: log_dir.display().to_string(),
+        source,
+    };
+    let mut archives = vec![];
+    for dir_entry in fs::read_dir(log_dir).map_err(list_error)? {
+        let path = dir_entry.map_err(list_error)?.path();
+        if let Some(index) = log_archive_index(&path) {
+            archives.push((index, path));
+        }
+    }
+    archives.sort();
+    Ok(archives.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Returns how many rotations old the log archive at `path` is.
+fn log_archive_index(path: &Path) -> Option<usize> {
+    let name = path.file_name()?.to_str()?.strip_suffix(".log.gz")?;
+    let (_, index) = name.rsplit_once('.')?;
+    if index.is_empty() || !index.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    index.parse().ok()
+}
+
 fn is_tunnel_log(path: &Path) -> bool {
     match path.file_name() {
         Some(file_name) => file_name.to_string_lossy().contains("openvpn"),
@@ -263,21 +328,8 @@ pub fn send_problem_report(
     report_path: &Path,
     cache_dir: &Path,
 ) -> Result<(), Error> {
-    let report_content = normalize_newlines(
-        read_file_lossy(report_path, REPORT_MAX_SIZE).map_err(|source| {
-            Error::ReadProblemReportError {
-                path: report_path.display().to_string(),
-                source,
-            }
-        })?,
-    );
-
-    let runtime = tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(2)
-        .enable_all()
-        .build()
-        .map_err(Error::CreateRuntime)?;
-    runtime.block_on(send_problem_report_inner(
+    let report_content = read_report(report_path)?;
+    create_runtime()?.block_on(send_problem_report_inner(
         user_email,
         user_message,
         &report_content,
@@ -285,13 +337,29 @@ pub fn send_problem_report(
     ))
 }
 
-async fn send_problem_report_inner(
-    user_email: &str,
-    user_message: &str,
-    report_content: &str,
+/// Read the report at `report_path`, the same way as it is read when it is sent.
+pub fn read_report(report_path: &Path) -> Result<String, Error> {
+    read_file_lossy(report_path, REPORT_MAX_SIZE)
+        .map(normalize_newlines)
+        .map_err(|source| Error::ReadProblemReportError {
+            path: report_path.display().to_string(),
+            source,
+        })
+}
+
+pub fn create_runtime() -> Result<tokio::runtime::Runtime, Error> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .map_err(Error::CreateRuntime)
+}
+
+/// Create a client for sending problem reports. The returned runtime must be kept for as long as
+/// the client is used.
+async fn create_api_client(
     cache_dir: &Path,
-) -> Result<(), Error> {
-    let metadata = ProblemReport::parse_metadata(report_content).unwrap_or_else(metadata::collect);
+) -> Result<(mullvad_api::Runtime, mullvad_api::ProblemReportProxy), Error> {
     let api_runtime = mullvad_api::Runtime::with_cache(
         cache_dir,
         false,
@@ -308,9 +376,21 @@ async fn send_problem_report_inner(
                     .await
                     .into_repeat(),
                 |_| async { true },
+                |_| (),
             )
             .await,
     );
+    Ok((api_runtime, api_client))
+}
+
+async fn send_problem_report_inner(
+    user_email: &str,
+    user_message: &str,
+    report_content: &str,
+    cache_dir: &Path,
+) -> Result<(), Error> {
+    let metadata = ProblemReport::parse_metadata(report_content).unwrap_or_else(metadata::collect);
+    let (_api_runtime, api_client) = create_api_client(cache_dir).await?;
 
     for _attempt in 0..MAX_SEND_ATTEMPTS {
         match api_client
@@ -382,17 +462,35 @@ impl ProblemReport {
     /// Attach a file log to this report. This method adds the error chain instead of the log
     /// contents if an error occurs while reading the log file.
     pub fn add_log(&mut self, path: &Path) {
+        self.add_log_contents(path, |path| read_file_lossy(path, LOG_MAX_READ_BYTES));
+    }
+
+    /// Attach the end of each compressed log archive in `paths`, in order, until
+    /// [`ARCHIVES_MAX_READ_BYTES`] have been read in total.
+    pub fn add_log_archives(&mut self, paths: &[PathBuf]) {
+        let mut remaining = ARCHIVES_MAX_READ_BYTES;
+        for path in paths {
+            if remaining == 0 {
+                break;
+            }
+            self.add_log_contents(path, |path| {
+                let contents = read_archive_tail(path, min(remaining, LOG_MAX_READ_BYTES))?;
+                remaining -= contents.len();
+                Ok(String::from_utf8_lossy(&contents).into_owned())
+            });
+        }
+    }
+
+    fn add_log_contents(&mut self, path: &Path, read: impl FnOnce(&Path) -> io::Result<String>) {
         let expanded_path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
         if self.log_paths.insert(expanded_path.clone()) {
             let redacted_path = self.redact(&expanded_path.to_string_lossy());
-            let content = self.redact(&read_file_lossy(path, LOG_MAX_READ_BYTES).unwrap_or_else(
-                |error| {
-                    error.display_chain_with_msg(&format!(
-                        "Error reading the contents of log file: {}",
-                        expanded_path.display()
-                    ))
-                },
-            ));
+            let content = self.redact(&read(path).unwrap_or_else(|error| {
+                error.display_chain_with_msg(&format!(
+                    "Error reading the contents of log file: {}",
+                    expanded_path.display()
+                ))
+            }));
             self.logs.push((redacted_path, content));
             log::info!("Adding {}", expanded_path.display());
         }
@@ -405,11 +503,7 @@ impl ProblemReport {
     }
 
     fn redact(&self, input: &str) -> String {
-        let out1 = Self::redact_account_number(input);
-        let out2 = Self::redact_home_dir(&out1);
-        let out3 = Self::redact_network_info(&out2);
-        let out4 = Self::redact_guids(&out3);
-        self.redact_custom_strings(&out4).to_string()
+        self.redact_custom_strings(&redact(input)).to_string()
     }
 
     fn redact_account_number(input: &str) -> Cow<'_, str> {
@@ -496,6 +590,15 @@ impl ProblemReport {
     }
 }
 
+/// Removes account numbers, the home directory, IP and MAC addresses and GUIDs from `input`, the
+/// same way as they are removed from the logs in a problem report.
+pub fn redact(input: &str) -> String {
+    let out1 = ProblemReport::redact_account_number(input);
+    let out2 = ProblemReport::redact_home_dir(&out1);
+    let out3 = ProblemReport::redact_network_info(&out2);
+    ProblemReport::redact_guids(&out3).into_owned()
+}
+
 fn redact_home_dir_inner(input: &str, home_dir: Option<PathBuf>) -> Cow<'_, str> {
     match home_dir {
         Some(home) => {
@@ -593,6 +696,30 @@ fn read_file_lossy(path: &Path, max_bytes: usize) -> io::Result<String> {
     Ok(String::from_utf8_lossy(&buffer).into_owned())
 }
 
+/// Decompresses the archive at `path` and returns the last `max_bytes` of it. Archives can be much
+/// larger than what is kept, so they are not decompressed into memory in full.
+fn read_archive_tail(path: &Path, max_bytes: usize) -> io::Result<Vec<u8>> {
+    let mut decoder = GzDecoder::new(io::BufReader::new(File::open(path)?));
+    let mut tail = Vec::with_capacity(2 * max_bytes);
+    let mut chunk = [0u8; 8 * 1024];
+    loop {
+        let read = match decoder.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        tail.extend_from_slice(&chunk[..read]);
+        if tail.len() > 2 * max_bytes {
+            tail.drain(..tail.len() - max_bytes);
+        }
+    }
+    if tail.len() > max_bytes {
+        tail.drain(..tail.len() - max_bytes);
+    }
+    Ok(tail)
+}
+
 #[cfg(not(windows))]
 fn normalize_newlines(text: String) -> String {
     text
@@ -678,6 +805,54 @@ mod tests {
         assert_does_not_redact("09:47:59");
     }
 
+    #[test]
+    fn includes_most_recent_archives() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let dir = tempfile::tempdir().unwrap();
+        let write_archive = |name: &str, contents: &[u8]| {
+            let mut encoder = GzEncoder::new(
+                File::create(dir.path().join(name)).unwrap(),
+                Compression::default(),
+            );
+            encoder.write_all(contents).unwrap();
+            encoder.finish().unwrap();
+        };
+        write_archive("daemon.2.log.gz", &vec![b'b'; LOG_MAX_READ_BYTES]);
+        write_archive("daemon.1.log.gz", &vec![b'a'; 2 * LOG_MAX_READ_BYTES]);
+        write_archive("openvpn.1.log.gz", &vec![b'o'; LOG_MAX_READ_BYTES]);
+        write_archive("daemon.3.log.gz", b"oldest");
+        File::create(dir.path().join("daemon.log")).unwrap();
+        File::create(dir.path().join("daemon.1.log.gz.partial")).unwrap();
+
+        let archives = list_log_archives(dir.path()).unwrap();
+        let names: Vec<_> = archives
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "daemon.1.log.gz",
+                "openvpn.1.log.gz",
+                "daemon.2.log.gz",
+                "daemon.3.log.gz"
+            ]
+        );
+
+        // Only the end of each archive is read, and the oldest archives are left out once the cap
+        // has been reached
+        let mut report = ProblemReport::new(vec![]);
+        report.add_log_archives(&archives);
+        let lengths: Vec<_> = report
+            .logs
+            .iter()
+            .map(|(_, contents)| contents.len())
+            .collect();
+        assert_eq!(lengths, [LOG_MAX_READ_BYTES, LOG_MAX_READ_BYTES]);
+        assert!(report.logs[0].1.bytes().all(|byte| byte == b'a'));
+    }
+
     fn assert_redacts(input: &str) {
         let report = ProblemReport::new(vec![]);
         let actual = report.redact(&format!("pre {input} post"));
@@ -1,11 +1,13 @@
 #![deny(rust_2018_idioms)]
 
 use clap::Parser;
+use mullvad_management_interface::MullvadProxyClient;
 use mullvad_problem_report::{collect_report, Error};
 use std::{
     env,
     path::{Path, PathBuf},
     process,
+    time::SystemTime,
 };
 use talpid_types::ErrorExt;
 
@@ -50,7 +52,23 @@ enum Cli {
         /// Message to include in the problem report
         #[arg(long, short = 'm')]
         message: Option<String>,
+        /// Queue the report if it cannot be sent, so that it is sent by the daemon once the API
+        /// can be reached
+        #[arg(long)]
+        queue_on_failure: bool,
     },
+
+    /// Manage problem reports which are queued to be sent
+    #[command(subcommand)]
+    Queue(Queue),
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Queue {
+    /// List the queued problem reports
+    List,
+    /// Try to send the queued problem reports now
+    Flush,
 }
 
 fn run() -> Result<(), Error> {
@@ -73,13 +91,17 @@ fn run() -> Result<(), Error> {
             report,
             email,
             message,
+            queue_on_failure,
         } => {
             send_problem_report(
                 &email.unwrap_or_default(),
                 &message.unwrap_or_default(),
                 &report,
+                queue_on_failure,
             )?;
         }
+        Cli::Queue(Queue::List) => list_queued_reports()?,
+        Cli::Queue(Queue::Flush) => flush_queued_reports()?,
     }
 
     Ok(())
@@ -89,14 +111,91 @@ fn send_problem_report(
     user_email: &str,
     user_message: &str,
     report_path: &Path,
+    queue_on_failure: bool,
 ) -> Result<(), Error> {
     let cache_dir = mullvad_paths::get_cache_dir().map_err(Error::ObtainCacheDirectory)?;
-    mullvad_problem_report::send_problem_report(user_email, user_message, report_path, &cache_dir)
-        .map_err(|error| {
-            eprintln!("{}", error.display_chain());
-            error
-        })?;
+    let result = mullvad_problem_report::send_problem_report(
+        user_email,
+        user_message,
+        report_path,
+        &cache_dir,
+    )
+    .map_err(|error| {
+        eprintln!("{}", error.display_chain());
+        error
+    });
+
+    match result {
+        Ok(()) => println!("Problem report sent"),
+        Err(Error::SendFailedTooManyTimes) if queue_on_failure => {
+            // The outbox is owned by the daemon, so the report is handed over to it
+            let report = mullvad_problem_report::read_report(report_path)?;
+            let id = mullvad_problem_report::create_runtime()?.block_on(async {
+                connect_daemon()
+                    .await?
+                    .queue_problem_report(user_email.to_owned(), user_message.to_owned(), report)
+                    .await
+                    .map_err(Error::QueueProblemReportError)
+            })?;
+            println!(
+                "Problem report queued as {}. It will be sent once the API can be reached",
+                id
+            );
+        }
+        Err(error) => return Err(error),
+    }
+    Ok(())
+}
 
-    println!("Problem report sent");
+fn list_queued_reports() -> Result<(), Error> {
+    let reports = mullvad_problem_report::create_runtime()?.block_on(async {
+        connect_daemon()
+            .await?
+            .get_queued_problem_reports()
+            .await
+            .map_err(Error::ReadQueueError)
+    })?;
+    if reports.is_empty() {
+        println!("No queued problem reports");
+    }
+    let now = SystemTime::now();
+    for report in reports {
+        let age = now
+            .duration_since(SystemTime::from(report.queued_at))
+            .unwrap_or_default()
+            .as_secs();
+        println!(
+            "{}: queued {} min ago, {} bytes",
+            report.id,
+            age / 60,
+            report.size
+        );
+    }
+    Ok(())
+}
+
+fn flush_queued_reports() -> Result<(), Error> {
+    let results = mullvad_problem_report::create_runtime()?.block_on(async {
+        connect_daemon()
+            .await?
+            .flush_queued_problem_reports()
+            .await
+            .map_err(Error::FlushQueueError)
+    })?;
+    if results.is_empty() {
+        println!("No queued problem reports");
+    }
+    for result in results {
+        match result.error {
+            None => println!("{}: sent", result.id),
+            Some(error) => println!("{}: {}", result.id, error),
+        }
+    }
     Ok(())
 }
+
+async fn connect_daemon() -> Result<MullvadProxyClient, Error> {
+    MullvadProxyClient::new()
+        .await
+        .map_err(Error::DaemonConnectionError)
+}
@@ -0,0 +1,470 @@
+//! Problem reports which could not be sent, kept in the cache directory until they can be sent.
+//!
+//! The outbox is only accessed by the daemon, since the cache directory is owned by it. Reports
+//! are stored one per file after they have been redacted, so nothing is collected again when they
+//! are sent. On Unix, the files can only be read by the owner, since they contain the email and
+//! logs of the user. Reports older than [`MAX_AGE`] are deleted, and the oldest reports are
+//! deleted when the combined size of the queue would exceed [`MAX_TOTAL_SIZE`].
+use mullvad_api::{
+    availability::ApiAvailabilityHandle,
+    rest,
+    retry::{retry_request, RetryPolicy},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use talpid_types::ErrorExt;
+
+const OUTBOX_DIR: &str = "problem-report-outbox";
+
+/// Queued reports older than this are deleted without being sent.
+pub const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Maximum combined size of the queued reports, in bytes.
+pub const MAX_TOTAL_SIZE: u64 = 4 * super::REPORT_MAX_SIZE as u64;
+
+/// A problem report waiting to be sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedReport {
+    pub id: String,
+    pub user_email: String,
+    pub user_message: String,
+    /// The redacted contents of the report.
+    pub report: String,
+    pub metadata: BTreeMap<String, String>,
+    pub queued_at: SystemTime,
+}
+
+impl QueuedReport {
+    pub fn new(user_email: &str, user_message: &str, report: String) -> Self {
+        let metadata =
+            super::ProblemReport::parse_metadata(&report).unwrap_or_else(super::metadata::collect);
+        QueuedReport {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_email: user_email.to_owned(),
+            user_message: user_message.to_owned(),
+            report,
+            metadata,
+            queued_at: SystemTime::now(),
+        }
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        now.duration_since(self.queued_at)
+            .map(|age| age > MAX_AGE)
+            .unwrap_or(false)
+    }
+}
+
+/// The directory where queued reports are stored.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    dir: PathBuf,
+}
+
+impl Outbox {
+    pub fn new(cache_dir: &Path) -> Self {
+        Outbox {
+            dir: cache_dir.join(OUTBOX_DIR),
+        }
+    }
+
+    /// Store `report`, deleting the oldest queued reports if there is not enough room for it.
+    pub fn push(&self, report: &QueuedReport) -> io::Result<()> {
+        let contents = serde_json::to_vec(report)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let size = contents.len() as u64;
+        if size > MAX_TOTAL_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the report is too large to be queued",
+            ));
+        }
+
+        create_private_dir(&self.dir)?;
+        let mut entries = self.entries()?;
+        let mut total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+        // `entries` is sorted with the oldest report first.
+        entries.reverse();
+        while total_size + size > MAX_TOTAL_SIZE {
+            let Some(oldest) = entries.pop() else {
+                break;
+            };
+            log::debug!(
+                "Deleting queued problem report {} to make room",
+                oldest.report.id
+            );
+            remove_file(&oldest.path)?;
+            total_size -= oldest.size;
+        }
+
+        let path = self.report_path(&report.id);
+        let temp_path = path.with_extension("tmp");
+        write_private_file(&temp_path, &contents)?;
+        fs::rename(temp_path, path)
+    }
+
+    /// Return the queued reports, oldest first. Expired reports, and files which cannot be read,
+    /// are deleted.
+    pub fn list(&self) -> io::Result<Vec<QueuedReport>> {
+        match self.entries() {
+            Ok(entries) => Ok(entries.into_iter().map(|entry| entry.report).collect()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Delete the queued report with the given ID.
+    pub fn remove(&self, id: &str) -> io::Result<()> {
+        remove_file(&self.report_path(id))
+    }
+
+    /// Send every queued report with `send`, and delete those which are sent. Reports which are
+    /// rejected by the API are deleted as well, since sending them again would not help. The
+    /// result of sending each report is returned.
+    pub async fn flush<F, O>(
+        &self,
+        mut send: F,
+    ) -> io::Result<Vec<(QueuedReport, Result<(), rest::Error>)>>
+    where
+        F: FnMut(&QueuedReport) -> O,
+        O: Future<Output = Result<(), rest::Error>>,
+    {
+        let mut results = vec![];
+        for report in self.list()? {
+            let result = send(&report).await;
+            self.handle_send_result(&report, &result)?;
+            results.push((report, result));
+        }
+        Ok(results)
+    }
+
+    fn handle_send_result(
+        &self,
+        report: &QueuedReport,
+        result: &Result<(), rest::Error>,
+    ) -> io::Result<()> {
+        match result {
+            Ok(()) => self.remove(&report.id),
+            Err(error) if error.is_network_error() => Ok(()),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Deleting queued problem report {} rejected by the API",
+                        report.id
+                    ))
+                );
+                self.remove(&report.id)
+            }
+        }
+    }
+
+    fn report_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    fn entries(&self) -> io::Result<Vec<Entry>> {
+        let now = SystemTime::now();
+        let mut entries = vec![];
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            if path.extension() != Some(OsStr::new("json")) {
+                continue;
+            }
+            let report = fs::read(&path).and_then(|contents| {
+                serde_json::from_slice::<QueuedReport>(&contents)
+                    .map(|report| (report, contents.len() as u64))
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+            });
+            match report {
+                Ok((report, _)) if report.is_expired(now) => {
+                    log::debug!("Deleting expired problem report {}", report.id);
+                    remove_file(&path)?;
+                }
+                Ok((report, size)) => entries.push(Entry { path, size, report }),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Deleting unreadable problem report {}",
+                            path.display()
+                        ))
+                    );
+                    remove_file(&path)?;
+                }
+            }
+        }
+        entries.sort_by_key(|entry| entry.report.queued_at);
+        Ok(entries)
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    report: QueuedReport,
+}
+
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+        // The directory may have been created with broader permissions by an older version
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+    }
+    #[cfg(not(unix))]
+    fs::create_dir_all(dir)
+}
+
+fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Send the reports in `outbox` whenever the API is reachable. Reports which fail to be sent due
+/// to network errors are retried according to `policy`. The outbox is checked again every
+/// `check_interval`, and `on_sent` is called for every report that is sent.
+pub async fn run_sender<F, O>(
+    outbox: Outbox,
+    availability: ApiAvailabilityHandle,
+    policy: RetryPolicy,
+    check_interval: Duration,
+    mut send: F,
+    on_sent: impl Fn(&QueuedReport),
+) where
+    F: FnMut(&QueuedReport) -> O,
+    O: Future<Output = Result<(), rest::Error>>,
+{
+    loop {
+        if availability.wait_online().await.is_err() {
+            return;
+        }
+
+        let reports = outbox.list().unwrap_or_else(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to read queued problem reports")
+            );
+            vec![]
+        });
+        for report in reports {
+            let result = retry_request(
+                policy,
+                || availability.when_online(send(&report)),
+                |result| matches!(result, Err(error) if error.is_network_error()),
+            )
+            .await;
+            if let Err(error) = outbox.handle_send_result(&report, &result) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to delete queued problem report")
+                );
+            }
+            match result {
+                Ok(()) => {
+                    log::info!("Sent queued problem report {}", report.id);
+                    on_sent(&report);
+                }
+                Err(error) => log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to send queued problem report")
+                ),
+            }
+        }
+
+        talpid_time::sleep(check_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_api::availability::{ApiAvailability, State};
+    use std::sync::{Arc, Mutex};
+
+    const TEST_POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: Some(3),
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(10),
+        factor: 2,
+        jitter: 0.0,
+    };
+
+    fn report(contents: &str) -> QueuedReport {
+        QueuedReport::new("user@example.com", "message", contents.to_owned())
+    }
+
+    async fn network_error() -> rest::Error {
+        let elapsed = tokio::time::timeout(Duration::ZERO, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        rest::Error::TimeoutError(elapsed)
+    }
+
+    #[test]
+    fn test_persistence() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::new(cache_dir.path());
+        assert!(outbox.list().unwrap().is_empty());
+
+        let first = report("first");
+        let mut second = report("second");
+        second.queued_at = first.queued_at + Duration::from_secs(1);
+        outbox.push(&second).unwrap();
+        outbox.push(&first).unwrap();
+        assert_eq!(outbox.list().unwrap(), [first.clone(), second.clone()]);
+
+        outbox.remove(&first.id).unwrap();
+        assert_eq!(outbox.list().unwrap(), [second]);
+    }
+
+    #[test]
+    fn test_age_cap() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::new(cache_dir.path());
+
+        let mut expired = report("expired");
+        expired.queued_at -= MAX_AGE + Duration::from_secs(1);
+        let recent = report("recent");
+        outbox.push(&expired).unwrap();
+        outbox.push(&recent).unwrap();
+
+        assert_eq!(outbox.list().unwrap(), [recent]);
+        assert!(!outbox.report_path(&expired.id).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reports_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::new(cache_dir.path());
+        let report = report("contents");
+        outbox.push(&report).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&outbox.dir), 0o700);
+        assert_eq!(mode(&outbox.report_path(&report.id)), 0o600);
+    }
+
+    #[test]
+    fn test_size_cap() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::new(cache_dir.path());
+
+        let large = "a".repeat(MAX_TOTAL_SIZE as usize / 4);
+        let mut reports = vec![];
+        for i in 0..4 {
+            let mut report = report(&large);
+            report.queued_at += Duration::from_secs(i);
+            outbox.push(&report).unwrap();
+            reports.push(report);
+        }
+        // Only three reports fit, so the oldest one is deleted.
+        assert_eq!(outbox.list().unwrap(), reports[1..]);
+
+        let too_large = report(&"a".repeat(MAX_TOTAL_SIZE as usize + 1));
+        assert!(outbox.push(&too_large).is_err());
+        assert_eq!(outbox.list().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_flush_keeps_reports_after_network_errors() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::new(cache_dir.path());
+        let unsent = report("unsent");
+        let sent = report("sent");
+        outbox.push(&unsent).unwrap();
+        outbox.push(&sent).unwrap();
+
+        let results = outbox
+            .flush(|report| {
+                let is_sent = report.id == sent.id;
+                async move {
+                    if is_sent {
+                        Ok(())
+                    } else {
+                        Err(network_error().await)
+                    }
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(outbox.list().unwrap(), [unsent]);
+    }
+
+    #[tokio::test]
+    async fn test_send_when_online() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::new(cache_dir.path());
+        let queued = report("report");
+        outbox.push(&queued).unwrap();
+
+        let availability = ApiAvailability::new(State::default());
+        let availability_handle = availability.handle();
+        availability_handle.set_offline(true);
+
+        let attempts = Arc::new(Mutex::new(0));
+        let sent = Arc::new(Mutex::new(vec![]));
+        let attempts_copy = attempts.clone();
+        let sent_copy = sent.clone();
+        tokio::spawn(run_sender(
+            outbox.clone(),
+            availability_handle.clone(),
+            TEST_POLICY,
+            Duration::from_secs(60),
+            move |_report| {
+                let attempt = {
+                    let mut attempts = attempts_copy.lock().unwrap();
+                    *attempts += 1;
+                    *attempts
+                };
+                async move {
+                    // The first attempt after coming online fails.
+                    if attempt == 1 {
+                        Err(network_error().await)
+                    } else {
+                        Ok(())
+                    }
+                }
+            },
+            move |report| sent_copy.lock().unwrap().push(report.id.clone()),
+        ));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*attempts.lock().unwrap(), 0);
+        assert_eq!(outbox.list().unwrap().len(), 1);
+
+        availability_handle.set_offline(false);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*attempts.lock().unwrap(), 2);
+        assert_eq!(*sent.lock().unwrap(), [queued.id]);
+        assert!(outbox.list().unwrap().is_empty());
+    }
+}
@@ -8,26 +8,30 @@ use mullvad_types::{
     endpoint::{MullvadEndpoint, MullvadWireguardEndpoint},
     location::{Coordinates, Location},
     relay_constraints::{
-        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint,
-        Match, ObfuscationSettings, OpenVpnConstraints, Ownership, Providers, RelayConstraints,
-        RelayConstraintsFormatter, RelaySettings, ResolvedLocationConstraint, SelectedObfuscation,
-        Set, TransportPort, Udp2TcpObfuscationSettings,
+        BridgeSettings, BridgeState, Constraint, GeographicLocationConstraint,
+        InternalBridgeConstraints, LocationConstraint, Match, MatchingRelays, ObfuscationSettings,
+        ObfuscationType, OpenVpnConstraints, Ownership, Providers, QuicObfuscationSettings,
+        RelayConstraints, RelayConstraintsFormatter, RelayOverrides, RelaySettings,
+        ResolvedLocationConstraint, SelectedObfuscation, Set, TransportPort,
     },
     relay_list::{BridgeEndpointData, Relay, RelayEndpointData, RelayList},
     CustomTunnelEndpoint,
 };
-use parking_lot::{Mutex, MutexGuard};
+use parking_lot::Mutex;
 use rand::{seq::SliceRandom, Rng};
 use std::{
     io,
     net::{IpAddr, SocketAddr},
     path::Path,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{self, SystemTime},
 };
 use talpid_types::{
     net::{
-        obfuscation::ObfuscatorConfig, openvpn::ProxySettings, wireguard, IpVersion,
+        obfuscation::ObfuscatorConfig, openvpn::ProxySettings, wireguard, Endpoint, IpVersion,
         TransportProtocol, TunnelType,
     },
     ErrorExt,
@@ -46,6 +50,23 @@ const WIREGUARD_EXIT_IP_VERSION: Constraint<IpVersion> = Constraint::Only(IpVers
 
 const UDP2TCP_PORTS: [u16; 2] = [80, 5001];
 
+/// Number of consecutive attempts that use the same obfuscation type in auto mode.
+const AUTO_OBFUSCATION_ATTEMPTS_PER_TYPE: u32 = 2;
+
+/// Number of WireGuard attempts that are made before falling back to OpenVPN when the tunnel
+/// protocol is automatic. With the default obfuscation order, this covers two attempts without
+/// obfuscation and two with udp2tcp.
+pub const DEFAULT_WIREGUARD_ATTEMPTS_BEFORE_OPENVPN: u32 = 4;
+
+/// Number of consecutive OpenVPN attempts that follow the WireGuard attempts when the tunnel
+/// protocol is automatic.
+const OPENVPN_FALLBACK_ATTEMPTS: u32 = 2;
+
+/// Number of WireGuard attempts that used to precede all OpenVPN attempts when the tunnel protocol
+/// is automatic. Bridges are still chosen as if that were the case, so that they are used on the
+/// same OpenVPN attempts as before.
+const OPENVPN_FALLBACK_BRIDGE_OFFSET: u32 = 2;
+
 /// Minimum number of bridges to keep for selection when filtering by distance.
 const MIN_BRIDGE_COUNT: usize = 5;
 
@@ -67,6 +88,12 @@ pub enum Error {
     #[error(display = "No bridges matching current constraints")]
     NoBridge,
 
+    #[error(
+        display = "None of the bridges matching current constraints have an {} address",
+        _0
+    )]
+    NoBridgeWithIpVersion(IpVersion),
+
     #[error(display = "No obfuscators matching current constraints")]
     NoObfuscator,
 
@@ -75,6 +102,12 @@ pub enum Error {
 
     #[error(display = "Downloader already shut down")]
     DownloaderShutDown,
+
+    #[error(display = "There is no relay named {}", _0)]
+    UnknownRelay(String),
+
+    #[error(display = "The parameters cannot be used: {}", _0)]
+    InvalidOverride(String),
 }
 
 struct ParsedRelays {
@@ -169,6 +202,9 @@ pub struct SelectorConfig {
     pub bridge_settings: BridgeSettings,
     pub obfuscation_settings: ObfuscationSettings,
     pub default_tunnel_type: TunnelType,
+    /// Number of WireGuard attempts before OpenVPN is tried, if the tunnel protocol is automatic
+    /// and WireGuard is the default. At least one WireGuard attempt is always made.
+    pub wireguard_attempts_before_openvpn: u32,
     pub custom_lists: CustomListsSettings,
 }
 
@@ -176,6 +212,7 @@ pub struct SelectorConfig {
 pub struct RelaySelector {
     config: Arc<Mutex<SelectorConfig>>,
     parsed_relays: Arc<Mutex<ParsedRelays>>,
+    prefer_ipv6_endpoints: Arc<AtomicBool>,
 }
 
 impl RelaySelector {
@@ -201,6 +238,7 @@ impl RelaySelector {
         RelaySelector {
             config: Arc::new(Mutex::new(config)),
             parsed_relays: Arc::new(Mutex::new(unsynchronized_parsed_relays)),
+            prefer_ipv6_endpoints: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -208,12 +246,74 @@ impl RelaySelector {
         *self.config.lock() = config;
     }
 
+    /// Sets whether to prefer the IPv6 address of WireGuard relays when no IP version has been
+    /// specified, and to only use bridges that have an IPv6 address. This is done on IPv6-only
+    /// networks, where IPv4 addresses can only be reached through NAT64.
+    pub fn set_prefer_ipv6_endpoints(&self, prefer_ipv6_endpoints: bool) {
+        self.prefer_ipv6_endpoints
+            .store(prefer_ipv6_endpoints, Ordering::Relaxed);
+    }
+
     /// Returns all countries and cities. The cities in the object returned does not have any
     /// relays in them.
     pub fn get_locations(&mut self) -> RelayList {
         self.parsed_relays.lock().locations().clone()
     }
 
+    /// Returns how many relays match `relay_constraints`, without selecting any of them. With
+    /// multihop, the exit and entry relays are counted separately, and only WireGuard relays are
+    /// counted.
+    pub fn count_matching_relays(&self, relay_constraints: &RelayConstraints) -> MatchingRelays {
+        let custom_lists = self.config.lock().custom_lists.clone();
+        let (openvpn_data, wireguard_data, relays) = {
+            let parsed_relays = self.parsed_relays.lock();
+            (
+                parsed_relays.locations.openvpn.clone(),
+                parsed_relays.locations.wireguard.clone(),
+                parsed_relays.relays().clone(),
+            )
+        };
+        let mut matcher = RelayMatcher::new(
+            relay_constraints.clone(),
+            openvpn_data,
+            wireguard_data,
+            &custom_lists,
+        );
+        // Only the first hop is connected to through the obfuscator
+        matcher.endpoint_matcher.wireguard.require_quic = self.entry_requires_quic();
+        matcher.endpoint_matcher.wireguard.require_tls = self.entry_requires_tls();
+
+        let use_multihop = relay_constraints.wireguard_constraints.use_multihop
+            && relay_constraints.tunnel_protocol != Constraint::Only(TunnelType::OpenVpn);
+        if !use_multihop {
+            return MatchingRelays {
+                relays: matcher.filter_matching_relay_list(&relays).len(),
+                entry_relays: None,
+            };
+        }
+
+        let entry_matcher = RelayMatcher {
+            locations: ResolvedLocationConstraint::from_constraint(
+                relay_constraints
+                    .wireguard_constraints
+                    .entry_location
+                    .clone(),
+                &custom_lists,
+            ),
+            providers: relay_constraints.providers.clone(),
+            ownership: relay_constraints.ownership,
+            endpoint_matcher: matcher.endpoint_matcher.clone(),
+        }
+        .into_wireguard_matcher();
+        matcher.endpoint_matcher.wireguard = self.wireguard_exit_matcher();
+        let exit_matcher = matcher.into_wireguard_matcher();
+
+        MatchingRelays {
+            relays: exit_matcher.filter_matching_relay_list(&relays).len(),
+            entry_relays: Some(entry_matcher.filter_matching_relay_list(&relays).len()),
+        }
+    }
+
     /// Returns a random relay and relay endpoint matching the current constraints.
     pub fn get_relay(
         &self,
@@ -226,12 +326,15 @@ impl RelaySelector {
         ),
         Error,
     > {
-        let config = self.config.lock();
+        // The lock is not held while selecting, since the selection reads the obfuscation settings
+        let config = self.config.lock().clone();
         match &config.relay_settings {
             RelaySettings::CustomTunnelEndpoint(custom_relay) => {
                 Ok((SelectedRelay::Custom(custom_relay.clone()), None, None))
             }
             RelaySettings::Normal(constraints) => {
+                let type_attempt = tunnel_type_attempt(&config, constraints, retry_attempt);
+                let bridge_attempt = bridge_attempt(&config, constraints, retry_attempt);
                 let relay = self.get_tunnel_endpoint(
                     constraints,
                     config.bridge_state,
@@ -248,7 +351,12 @@ impl RelaySelector {
                             .location
                             .as_ref()
                             .expect("Relay has no location set");
-                        self.get_bridge_for(&config, location, retry_attempt, &config.custom_lists)?
+                        self.get_bridge_for(
+                            &config,
+                            location,
+                            bridge_attempt,
+                            &config.custom_lists,
+                        )?
                     }
                     _ => None,
                 };
@@ -260,7 +368,7 @@ impl RelaySelector {
                             &config,
                             obfuscator_relay,
                             endpoint,
-                            retry_attempt,
+                            type_attempt,
                         )?
                     }
                     _ => None,
@@ -270,6 +378,148 @@ impl RelaySelector {
         }
     }
 
+    /// Returns a relay and relay endpoint for a single connection attempt, where `overrides` take
+    /// precedence over the constraints in the settings. A relay that is named by the overrides is
+    /// used even if the location and filters in the settings exclude it, but it has to exist and
+    /// support the other overrides.
+    pub fn get_relay_with_overrides(
+        &self,
+        overrides: &RelayOverrides,
+        retry_attempt: u32,
+    ) -> Result<
+        (
+            SelectedRelay,
+            Option<SelectedBridge>,
+            Option<SelectedObfuscator>,
+        ),
+        Error,
+    > {
+        let mut config = self.config.lock().clone();
+        self.apply_overrides(&mut config, overrides)?;
+        // The selection reads the config in several places, so it is made by a selector that
+        // shares the relays but has its own config.
+        let selector = RelaySelector {
+            config: Arc::new(Mutex::new(config)),
+            parsed_relays: self.parsed_relays.clone(),
+            prefer_ipv6_endpoints: self.prefer_ipv6_endpoints.clone(),
+        };
+        selector.get_relay(retry_attempt)
+    }
+
+    /// Replaces the constraints in `config` with `overrides`, after checking that the relays
+    /// support them.
+    fn apply_overrides(
+        &self,
+        config: &mut SelectorConfig,
+        overrides: &RelayOverrides,
+    ) -> Result<(), Error> {
+        let invalid = |reason: String| Err(Error::InvalidOverride(reason));
+        let RelaySettings::Normal(constraints) = &mut config.relay_settings else {
+            return invalid("a custom relay is used".to_owned());
+        };
+        let parsed_relays = self.parsed_relays.lock();
+
+        if let Some(tunnel_protocol) = overrides.tunnel_protocol {
+            constraints.tunnel_protocol = Constraint::Only(tunnel_protocol);
+        }
+
+        let mut relay = None;
+        if let Some(hostname) = &overrides.hostname {
+            let found = parsed_relays
+                .relays()
+                .iter()
+                .find(|relay| relay.hostname.eq_ignore_ascii_case(hostname))
+                .ok_or_else(|| Error::UnknownRelay(hostname.clone()))?;
+            if !found.active {
+                return invalid(format!("{} is not active", found.hostname));
+            }
+            let tunnel_protocol = match found.endpoint_data {
+                RelayEndpointData::Wireguard(_) => TunnelType::Wireguard,
+                RelayEndpointData::Openvpn => TunnelType::OpenVpn,
+                RelayEndpointData::Bridge => {
+                    return invalid(format!("{} is a bridge", found.hostname));
+                }
+            };
+            if let Constraint::Only(requested) = constraints.tunnel_protocol {
+                if requested != tunnel_protocol {
+                    return invalid(format!("{} does not support {requested}", found.hostname));
+                }
+            }
+            let location = found.location.as_ref().expect("Relay has no location set");
+            constraints.location = Constraint::Only(LocationConstraint::from(
+                GeographicLocationConstraint::Hostname(
+                    location.country_code.clone(),
+                    location.city_code.clone(),
+                    found.hostname.clone(),
+                ),
+            ));
+            constraints.tunnel_protocol = Constraint::Only(tunnel_protocol);
+            constraints.providers = Constraint::Any;
+            constraints.ownership = Constraint::Any;
+            constraints.wireguard_constraints.use_multihop = false;
+            relay = Some(found);
+        }
+
+        if let Some(port) = overrides.port {
+            let wireguard_ports = &parsed_relays.locations().wireguard.port_ranges;
+            let supports_wireguard = wireguard_ports
+                .iter()
+                .any(|(first, last)| (*first..=*last).contains(&port));
+            let openvpn_protocol = parsed_relays
+                .locations()
+                .openvpn
+                .ports
+                .iter()
+                .find(|endpoint| endpoint.port == port)
+                .map(|endpoint| endpoint.protocol);
+            // Without a tunnel protocol, the port decides which one is used
+            let tunnel_protocol = match constraints.tunnel_protocol {
+                Constraint::Only(tunnel_protocol) => tunnel_protocol,
+                Constraint::Any if supports_wireguard => TunnelType::Wireguard,
+                Constraint::Any => TunnelType::OpenVpn,
+            };
+            match (tunnel_protocol, openvpn_protocol) {
+                (TunnelType::Wireguard, _) if supports_wireguard => {
+                    constraints.wireguard_constraints.port = Constraint::Only(port);
+                }
+                (TunnelType::OpenVpn, Some(protocol)) => {
+                    constraints.openvpn_constraints.port = Constraint::Only(TransportPort {
+                        protocol,
+                        port: Constraint::Only(port),
+                    });
+                }
+                (tunnel_protocol, _) => {
+                    return invalid(format!("{tunnel_protocol} does not support port {port}"));
+                }
+            }
+            constraints.tunnel_protocol = Constraint::Only(tunnel_protocol);
+        }
+
+        if let Some(obfuscation) = overrides.obfuscation {
+            if obfuscation != SelectedObfuscation::Off
+                && constraints.tunnel_protocol == Constraint::Only(TunnelType::OpenVpn)
+            {
+                return invalid("only WireGuard can be obfuscated".to_owned());
+            }
+            if let Some(relay) = relay {
+                let supported = match (&relay.endpoint_data, obfuscation) {
+                    (RelayEndpointData::Wireguard(data), SelectedObfuscation::Quic) => {
+                        data.quic.is_some()
+                    }
+                    (RelayEndpointData::Wireguard(data), SelectedObfuscation::Tls) => {
+                        data.tls.is_some()
+                    }
+                    _ => true,
+                };
+                if !supported {
+                    return invalid(format!("{} does not support {obfuscation}", relay.hostname));
+                }
+            }
+            config.obfuscation_settings.selected_obfuscation = obfuscation;
+        }
+        Ok(())
+    }
+
     /// Returns a random relay and relay endpoint matching the given constraints and with
     /// preferences applied.
     fn get_tunnel_endpoint(
@@ -473,7 +723,7 @@ impl RelaySelector {
         // If using multihop then location is the exit constraint and
         // `wireguard_constraints.entry_location` is set as the entry location constraint.
         if !relay_constraints.wireguard_constraints.use_multihop {
-            let relay_matcher = RelayMatcher {
+            let mut relay_matcher = RelayMatcher {
                 locations: ResolvedLocationConstraint::from_constraint(
                     relay_constraints.location.clone(),
                     custom_lists,
@@ -485,6 +735,8 @@ impl RelaySelector {
                     wg_endpoint_data,
                 ),
             };
+            relay_matcher.endpoint_matcher.require_quic = self.entry_requires_quic();
+            relay_matcher.endpoint_matcher.require_tls = self.entry_requires_tls();
 
             // Nightly clippy seems wrong about this being a redundant clone
             #[allow(clippy::redundant_clone)]
@@ -493,6 +745,10 @@ impl RelaySelector {
                 .endpoint_matcher
                 .port
                 .or(Self::preferred_wireguard_port(retry_attempt));
+            preferred_matcher.endpoint_matcher.ip_version = preferred_matcher
+                .endpoint_matcher
+                .ip_version
+                .or(self.preferred_wireguard_ip_version());
 
             self.get_tunnel_endpoint_internal(&preferred_matcher)
                 .or_else(|_| self.get_tunnel_endpoint_internal(&relay_matcher))
@@ -516,6 +772,8 @@ impl RelaySelector {
                 .endpoint_matcher
                 .port
                 .or(Self::preferred_wireguard_port(retry_attempt));
+            entry_relay_matcher.endpoint_matcher.require_quic = self.entry_requires_quic();
+            entry_relay_matcher.endpoint_matcher.require_tls = self.entry_requires_tls();
 
             self.get_wireguard_multi_hop_endpoint(
                 entry_relay_matcher,
@@ -544,6 +802,9 @@ impl RelaySelector {
             wireguard_data,
             custom_lists,
         );
+        // Only the first hop is connected to through the obfuscator
+        matcher.endpoint_matcher.wireguard.require_quic = self.entry_requires_quic();
+        matcher.endpoint_matcher.wireguard.require_tls = self.entry_requires_tls();
 
         let mut selected_entry_relay = None;
         let mut selected_entry_endpoint = None;
@@ -741,6 +1002,11 @@ impl RelaySelector {
             }
         };
 
+        relay_constraints.wireguard_constraints.ip_version = relay_constraints
+            .wireguard_constraints
+            .ip_version
+            .or(self.preferred_wireguard_ip_version());
+
         relay_constraints
     }
 
@@ -776,7 +1042,7 @@ impl RelaySelector {
 
     fn get_bridge_for(
         &self,
-        config: &MutexGuard<'_, SelectorConfig>,
+        config: &SelectorConfig,
         location: &mullvad_types::location::Location,
         retry_attempt: u32,
         custom_lists: &CustomListsSettings,
@@ -789,12 +1055,15 @@ impl RelaySelector {
                     ownership: settings.ownership,
                     // FIXME: This is temporary while talpid-core only supports TCP proxies
                     transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+                    ip_version: self.preferred_bridge_ip_version(),
                 };
                 match config.bridge_state {
                     BridgeState::On => {
                         let (settings, relay) = self
                             .get_proxy_settings(&bridge_constraints, Some(location), custom_lists)
-                            .ok_or(Error::NoBridge)?;
+                            .ok_or_else(|| {
+                                self.no_bridge_error(&bridge_constraints, location, custom_lists)
+                            })?;
                         Ok(Some(SelectedBridge::Normal(NormalSelectedBridge {
                             settings,
                             relay,
@@ -835,12 +1104,14 @@ impl RelaySelector {
                 providers: settings.providers.clone(),
                 ownership: settings.ownership,
                 transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+                ip_version: self.preferred_bridge_ip_version(),
             },
             BridgeSettings::Custom(_bridge_settings) => InternalBridgeConstraints {
                 location: Constraint::Any,
                 providers: Constraint::Any,
                 ownership: Constraint::Any,
                 transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+                ip_version: self.preferred_bridge_ip_version(),
             },
         };
 
@@ -848,6 +1119,27 @@ impl RelaySelector {
             .map(|(settings, _relay)| settings)
     }
 
+    /// Returns the error to report when no bridge matches `constraints`. Only the IP version is
+    /// to blame if a bridge would have matched without it.
+    fn no_bridge_error(
+        &self,
+        constraints: &InternalBridgeConstraints,
+        location: &mullvad_types::location::Location,
+        custom_lists: &CustomListsSettings,
+    ) -> Error {
+        let Constraint::Only(ip_version) = constraints.ip_version else {
+            return Error::NoBridge;
+        };
+        let any_ip_version = InternalBridgeConstraints {
+            ip_version: Constraint::Any,
+            ..constraints.clone()
+        };
+        match self.get_proxy_settings(&any_ip_version, Some(location), custom_lists) {
+            Some(_) => Error::NoBridgeWithIpVersion(ip_version),
+            None => Error::NoBridge,
+        }
+    }
+
     fn should_use_bridge(retry_attempt: u32) -> bool {
         // shouldn't use a bridge for the first 3 times
         retry_attempt > 3 &&
@@ -871,7 +1163,9 @@ impl RelaySelector {
             ),
             providers: constraints.providers.clone(),
             ownership: constraints.ownership,
-            endpoint_matcher: BridgeMatcher(()),
+            endpoint_matcher: BridgeMatcher {
+                ip_version: constraints.ip_version,
+            },
         };
         let matching_relays: Vec<Relay> =
             matcher.filter_matching_relay_list(self.parsed_relays.lock().relays());
@@ -924,14 +1218,18 @@ impl RelaySelector {
             self.pick_random_relay(&matching_relays).cloned()
         };
         relay.and_then(|relay| {
-            self.pick_random_bridge(&self.parsed_relays.lock().locations.bridge, &relay)
-                .map(|bridge| (bridge, relay.clone()))
+            self.pick_random_bridge(
+                &self.parsed_relays.lock().locations.bridge,
+                &relay,
+                constraints.ip_version,
+            )
+            .map(|bridge| (bridge, relay.clone()))
         })
     }
 
     fn get_obfuscator_inner(
         &self,
-        config: &MutexGuard<'_, SelectorConfig>,
+        config: &SelectorConfig,
         relay: &Relay,
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
@@ -946,13 +1244,21 @@ impl RelaySelector {
             SelectedObfuscation::Off => Ok(None),
             SelectedObfuscation::Udp2Tcp => Ok(Some(
                 self.get_udp2tcp_obfuscator(
-                    &config.obfuscation_settings.udp2tcp,
+                    &config.obfuscation_settings,
                     relay,
                     endpoint,
                     retry_attempt,
                 )
                 .ok_or(Error::NoObfuscator)?,
             )),
+            SelectedObfuscation::Quic => Ok(Some(
+                self.get_quic_obfuscator(&config.obfuscation_settings.quic, relay, endpoint)
+                    .ok_or(Error::NoObfuscator)?,
+            )),
+            SelectedObfuscation::Tls => Ok(Some(
+                self.get_tls_obfuscator(&config.obfuscation_settings, relay, endpoint)
+                    .ok_or(Error::NoObfuscator)?,
+            )),
         }
     }
 
@@ -963,40 +1269,30 @@ impl RelaySelector {
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
     ) -> Option<SelectedObfuscator> {
-        if !self.should_use_auto_obfuscator(retry_attempt) {
-            return None;
-        }
-        // TODO FIX: The third obfuscator entry will never be chosen
-        // Because get_auto_obfuscator_retry_attempt() returns [0, 1]
-        // And the udp2tcp endpoints are defined in a vector with entries [0, 1, 2]
-        self.get_udp2tcp_obfuscator(
-            &obfuscation_settings.udp2tcp,
-            relay,
-            endpoint,
-            self.get_auto_obfuscator_retry_attempt(retry_attempt)
-                .unwrap(),
-        )
-    }
-
-    fn should_use_auto_obfuscator(&self, retry_attempt: u32) -> bool {
-        self.get_auto_obfuscator_retry_attempt(retry_attempt)
-            .is_some()
-    }
-
-    fn get_auto_obfuscator_retry_attempt(&self, retry_attempt: u32) -> Option<u32> {
-        match retry_attempt % 4 {
-            0 | 1 => None,
-            filtered_retry => Some(filtered_retry - 2),
+        let (obfuscation_type, type_attempt) =
+            auto_obfuscation_attempt(obfuscation_settings.obfuscation_priority(), retry_attempt);
+        match obfuscation_type {
+            ObfuscationType::None => None,
+            ObfuscationType::Udp2Tcp => {
+                self.get_udp2tcp_obfuscator(obfuscation_settings, relay, endpoint, type_attempt)
+            }
+            // Relays that do not accept QUIC are connected to without obfuscation
+            ObfuscationType::Quic => {
+                self.get_quic_obfuscator(&obfuscation_settings.quic, relay, endpoint)
+            }
+            // Relays that do not accept TLS are connected to without obfuscation
+            ObfuscationType::Tls => self.get_tls_obfuscator(obfuscation_settings, relay, endpoint),
         }
     }
 
     fn get_udp2tcp_obfuscator(
         &self,
-        obfuscation_settings: &Udp2TcpObfuscationSettings,
+        settings: &ObfuscationSettings,
         relay: &Relay,
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
     ) -> Option<SelectedObfuscator> {
+        let obfuscation_settings = &settings.udp2tcp;
         let udp2tcp_ports = &self.parsed_relays.lock().locations.wireguard.udp2tcp_ports;
         let udp2tcp_endpoint = if obfuscation_settings.port.is_only() {
             udp2tcp_ports
@@ -1008,6 +1304,9 @@ impl RelaySelector {
         udp2tcp_endpoint
             .map(|udp2tcp_endpoint| ObfuscatorConfig::Udp2Tcp {
                 endpoint: SocketAddr::new(endpoint.peer.endpoint.ip(), *udp2tcp_endpoint),
+                keepalive_interval: obfuscation_settings.keepalive_interval(),
+                idle_timeout: obfuscation_settings.idle_timeout(),
+                upstream_proxy: settings.upstream_proxy.clone(),
             })
             .map(|config| SelectedObfuscator {
                 config,
@@ -1015,6 +1314,57 @@ impl RelaySelector {
             })
     }
 
+    fn get_quic_obfuscator(
+        &self,
+        obfuscation_settings: &QuicObfuscationSettings,
+        relay: &Relay,
+        endpoint: &MullvadWireguardEndpoint,
+    ) -> Option<SelectedObfuscator> {
+        let RelayEndpointData::Wireguard(data) = &relay.endpoint_data else {
+            return None;
+        };
+        let quic = data.quic.as_ref()?;
+        let server_name = obfuscation_settings
+            .server_name
+            .clone()
+            .or_else(|| quic.server_name.clone())
+            .unwrap_or_else(|| relay.hostname.clone());
+        Some(SelectedObfuscator {
+            config: ObfuscatorConfig::Quic {
+                endpoint: SocketAddr::new(endpoint.peer.endpoint.ip(), quic.port),
+                server_name,
+                alpn: obfuscation_settings.alpn.clone(),
+            },
+            relay: relay.clone(),
+        })
+    }
+
+    fn get_tls_obfuscator(
+        &self,
+        settings: &ObfuscationSettings,
+        relay: &Relay,
+        endpoint: &MullvadWireguardEndpoint,
+    ) -> Option<SelectedObfuscator> {
+        let RelayEndpointData::Wireguard(data) = &relay.endpoint_data else {
+            return None;
+        };
+        let tls = data.tls.as_ref()?;
+        let server_name = settings
+            .tls
+            .server_name
+            .clone()
+            .or_else(|| tls.server_name.clone())
+            .unwrap_or_else(|| relay.hostname.clone());
+        Some(SelectedObfuscator {
+            config: ObfuscatorConfig::Tls {
+                endpoint: SocketAddr::new(endpoint.peer.endpoint.ip(), tls.port),
+                server_name,
+                upstream_proxy: settings.upstream_proxy.clone(),
+            },
+            relay: relay.clone(),
+        })
+    }
+
     /// Returns preferred constraints
     #[allow(unused_variables)]
     fn preferred_tunnel_constraints(
@@ -1062,28 +1412,41 @@ impl RelaySelector {
             }
         }
 
-        // Try out WireGuard in the first two connection attempts, first with any port,
-        // afterwards on port 53. Afterwards, connect through OpenVPN alternating between UDP
-        // on any port twice and TCP on port 443 once.
-        match retry_attempt {
-            0 => (
-                Constraint::Any,
-                TransportProtocol::Udp,
-                TunnelType::Wireguard,
-            ),
-            1 => (
-                Constraint::Only(53),
+        // Try WireGuard a number of times, with and without obfuscation, and then fall back to
+        // OpenVPN. The OpenVPN attempts are counted separately, so they go through the same
+        // protocols as when OpenVPN is the default, including TCP port 443, which gets through
+        // many networks that block UDP.
+        let wireguard_attempts = self.config.lock().wireguard_attempts_before_openvpn;
+        match auto_tunnel_attempt(wireguard_attempts, retry_attempt) {
+            (TunnelType::Wireguard, type_attempt) => (
+                Self::preferred_wireguard_port(type_attempt),
                 TransportProtocol::Udp,
                 TunnelType::Wireguard,
             ),
-            _ => {
+            (TunnelType::OpenVpn, type_attempt) => {
                 let (preferred_port, preferred_protocol) =
-                    Self::preferred_openvpn_constraints(retry_attempt - 2);
+                    Self::preferred_openvpn_constraints(type_attempt);
                 (preferred_port, preferred_protocol, TunnelType::OpenVpn)
             }
         }
     }
 
+    fn preferred_wireguard_ip_version(&self) -> Constraint<IpVersion> {
+        if self.prefer_ipv6_endpoints.load(Ordering::Relaxed) {
+            Constraint::Only(IpVersion::V6)
+        } else {
+            Constraint::Any
+        }
+    }
+
+    fn preferred_bridge_ip_version(&self) -> Constraint<IpVersion> {
+        if self.prefer_ipv6_endpoints.load(Ordering::Relaxed) {
+            Constraint::Only(IpVersion::V6)
+        } else {
+            Constraint::Any
+        }
+    }
+
     fn preferred_wireguard_port(retry_attempt: u32) -> Constraint<u16> {
         // This ensures that if after the first 2 failed attempts the daemon does not
         // connect, then afterwards 2 of each 4 successive attempts will try to connect
@@ -1170,27 +1533,34 @@ impl RelaySelector {
         }
     }
 
-    /// Picks a random bridge from a relay.
+    /// Picks a random bridge from a relay, at the address of the relay with the given IP version.
     fn pick_random_bridge(
         &self,
         data: &BridgeEndpointData,
         relay: &Relay,
+        ip_version: Constraint<IpVersion>,
     ) -> Option<ProxySettings> {
         if relay.endpoint_data != RelayEndpointData::Bridge {
             return None;
         }
+        let addr_in = match ip_version {
+            Constraint::Any | Constraint::Only(IpVersion::V4) => IpAddr::from(relay.ipv4_addr_in),
+            Constraint::Only(IpVersion::V6) => IpAddr::from(relay.ipv6_addr_in?),
+        };
         data.shadowsocks
             .choose(&mut rand::thread_rng())
             .map(|shadowsocks_endpoint| {
                 log::info!(
-                    "Selected Shadowsocks bridge {} at {}:{}/{}",
+                    "Selected Shadowsocks bridge {} at {}",
                     relay.hostname,
-                    relay.ipv4_addr_in,
-                    shadowsocks_endpoint.port,
-                    shadowsocks_endpoint.protocol
+                    Endpoint::new(
+                        addr_in,
+                        shadowsocks_endpoint.port,
+                        shadowsocks_endpoint.protocol
+                    ),
                 );
                 shadowsocks_endpoint.to_proxy_settings(
-                    relay.ipv4_addr_in.into(),
+                    addr_in,
                     #[cfg(target_os = "linux")]
                     mullvad_types::TUNNEL_FWMARK,
                 )
@@ -1224,6 +1594,18 @@ impl RelaySelector {
         }
     }
 
+    /// Returns whether the first WireGuard hop must accept QUIC connections, which is the case
+    /// when QUIC obfuscation is selected.
+    fn entry_requires_quic(&self) -> bool {
+        self.config.lock().obfuscation_settings.selected_obfuscation == SelectedObfuscation::Quic
+    }
+
+    /// Returns whether the first WireGuard hop must accept TLS connections, which is the case
+    /// when TLS obfuscation is selected.
+    fn entry_requires_tls(&self) -> bool {
+        self.config.lock().obfuscation_settings.selected_obfuscation == SelectedObfuscation::Tls
+    }
+
     fn wireguard_exit_matcher(&self) -> WireguardMatcher {
         let mut tunnel =
             WireguardMatcher::from_endpoint(self.parsed_relays.lock().locations.wireguard.clone());
@@ -1286,18 +1668,86 @@ impl NormalSelectedRelay {
     }
 }
 
+/// Returns the tunnel type to use for `retry_attempt` when the tunnel protocol is automatic, along
+/// with the number of earlier attempts that used the same type. `wireguard_attempts` WireGuard
+/// attempts, but at least one, are followed by `OPENVPN_FALLBACK_ATTEMPTS` OpenVPN attempts, after
+/// which the ladder starts over.
+fn auto_tunnel_attempt(wireguard_attempts: u32, retry_attempt: u32) -> (TunnelType, u32) {
+    let wireguard_attempts = wireguard_attempts.max(1);
+    let ladder_length = wireguard_attempts + OPENVPN_FALLBACK_ATTEMPTS;
+    let completed_ladders = retry_attempt / ladder_length;
+    let step = retry_attempt % ladder_length;
+    if step < wireguard_attempts {
+        (
+            TunnelType::Wireguard,
+            completed_ladders * wireguard_attempts + step,
+        )
+    } else {
+        (
+            TunnelType::OpenVpn,
+            completed_ladders * OPENVPN_FALLBACK_ATTEMPTS + step - wireguard_attempts,
+        )
+    }
+}
+
+/// Returns the attempt number that the ports and obfuscation of `retry_attempt` are chosen by. When the tunnel protocol is automatic, attempts are only counted for the tunnel
+/// type that they use, so that falling back to OpenVPN does not skip steps for WireGuard.
+fn tunnel_type_attempt(
+    config: &SelectorConfig,
+    constraints: &RelayConstraints,
+    retry_attempt: u32,
+) -> u32 {
+    if constraints.tunnel_protocol.is_any() && config.default_tunnel_type == TunnelType::Wireguard {
+        auto_tunnel_attempt(config.wireguard_attempts_before_openvpn, retry_attempt).1
+    } else {
+        retry_attempt
+    }
+}
+
+/// Returns the attempt number that the bridge of `retry_attempt` is chosen by. Bridges are only
+/// used with OpenVPN, so when the tunnel protocol is automatic, only the OpenVPN attempts are
+/// counted, offset by `OPENVPN_FALLBACK_BRIDGE_OFFSET`.
+fn bridge_attempt(
+    config: &SelectorConfig,
+    constraints: &RelayConstraints,
+    retry_attempt: u32,
+) -> u32 {
+    if constraints.tunnel_protocol.is_any() && config.default_tunnel_type == TunnelType::Wireguard {
+        tunnel_type_attempt(config, constraints, retry_attempt) + OPENVPN_FALLBACK_BRIDGE_OFFSET
+    } else {
+        retry_attempt
+    }
+}
+
+/// Returns the obfuscation type to use for `retry_attempt` in auto mode, along with the number of
+/// earlier attempts that used the same type. Each type in `priority` is used for
+/// `AUTO_OBFUSCATION_ATTEMPTS_PER_TYPE` consecutive attempts, and the list starts over once
+/// every type has been tried.
+fn auto_obfuscation_attempt(
+    priority: &[ObfuscationType],
+    retry_attempt: u32,
+) -> (ObfuscationType, u32) {
+    let rounds = retry_attempt / AUTO_OBFUSCATION_ATTEMPTS_PER_TYPE;
+    let obfuscation_type = priority[rounds as usize % priority.len()];
+    let completed_cycles = rounds / priority.len() as u32;
+    let type_attempt = completed_cycles * AUTO_OBFUSCATION_ATTEMPTS_PER_TYPE
+        + retry_attempt % AUTO_OBFUSCATION_ATTEMPTS_PER_TYPE;
+    (obfuscation_type, type_attempt)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use mullvad_types::{
         custom_list::CustomListsSettings,
         relay_constraints::{
-            BridgeConstraints, GeographicLocationConstraint, RelayConstraints,
-            RelayConstraintsUpdate, RelaySettingsUpdate, WireguardConstraints,
+            BridgeConstraints, RelayConstraints, RelayConstraintsUpdate, RelaySettingsUpdate,
+            TlsObfuscationSettings, WireguardConstraints, DEFAULT_OBFUSCATION_PRIORITY,
         },
         relay_list::{
-            OpenVpnEndpoint, OpenVpnEndpointData, Relay, RelayListCity, RelayListCountry,
-            ShadowsocksEndpointData, WireguardEndpointData, WireguardRelayEndpointData,
+            OpenVpnEndpoint, OpenVpnEndpointData, QuicEndpointData, Relay, RelayListCity,
+            RelayListCountry, ShadowsocksEndpointData, TlsEndpointData, WireguardEndpointData,
+            WireguardRelayEndpointData,
         },
     };
     use once_cell::sync::Lazy;
@@ -1340,6 +1790,11 @@ mod test {
                                 "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
                             )
                             .unwrap(),
+                            quic: Some(QuicEndpointData {
+                                port: 443,
+                                server_name: None,
+                            }),
+                            tls: None,
                         }),
                         location: None,
                     },
@@ -1357,6 +1812,11 @@ mod test {
                                 "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
                             )
                             .unwrap(),
+                            quic: None,
+                            tls: Some(TlsEndpointData {
+                                port: 443,
+                                server_name: Some("www.example.com".to_owned()),
+                            }),
                         }),
                         location: None,
                     },
@@ -1473,8 +1933,10 @@ mod test {
                 },
                 bridge_state: BridgeState::Auto,
                 default_tunnel_type: default_tunnel_type(),
+                wireguard_attempts_before_openvpn: DEFAULT_WIREGUARD_ATTEMPTS_BEFORE_OPENVPN,
                 custom_lists: CustomListsSettings::default(),
             })),
+            prefer_ipv6_endpoints: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -1482,6 +1944,93 @@ mod test {
         new_relay_selector_with_relays(RELAYS.clone())
     }
 
+    #[test]
+    fn test_count_matching_relays() {
+        let relay_selector = new_relay_selector();
+        let count =
+            |constraints: RelayConstraints| relay_selector.count_matching_relays(&constraints);
+
+        // Bridges are never counted
+        assert_eq!(
+            count(RelayConstraints::default()),
+            MatchingRelays {
+                relays: 4,
+                entry_relays: None
+            }
+        );
+        assert_eq!(
+            count(RelayConstraints {
+                tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+                ownership: Constraint::Only(Ownership::MullvadOwned),
+                ..RelayConstraints::default()
+            })
+            .relays,
+            1
+        );
+        assert_eq!(
+            count(RelayConstraints {
+                tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+                providers: Constraint::Only(
+                    Providers::new(std::iter::once("provider0".to_owned())).unwrap()
+                ),
+                ..RelayConstraints::default()
+            })
+            .relays,
+            1
+        );
+        assert_eq!(
+            count(RelayConstraints {
+                location: Constraint::Only(LocationConstraint::from(
+                    GeographicLocationConstraint::Country("de".to_owned()),
+                )),
+                ..RelayConstraints::default()
+            })
+            .relays,
+            0
+        );
+
+        // With multihop, the entry and exit relays are counted separately
+        let multihop = |entry_location| RelayConstraints {
+            wireguard_constraints: WireguardConstraints {
+                use_multihop: true,
+                entry_location,
+                ..WireguardConstraints::default()
+            },
+            ..RelayConstraints::default()
+        };
+        assert_eq!(
+            count(multihop(Constraint::Any)),
+            MatchingRelays {
+                relays: 2,
+                entry_relays: Some(2)
+            }
+        );
+        assert_eq!(
+            count(multihop(Constraint::Only(LocationConstraint::from(
+                GeographicLocationConstraint::Hostname(
+                    "se".to_owned(),
+                    "got".to_owned(),
+                    "se9-wireguard".to_owned()
+                ),
+            )))),
+            MatchingRelays {
+                relays: 2,
+                entry_relays: Some(1)
+            }
+        );
+        // Multihop is ignored with OpenVPN
+        assert_eq!(
+            count(RelayConstraints {
+                tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+                ..multihop(Constraint::Any)
+            }),
+            MatchingRelays {
+                relays: 2,
+                entry_relays: None
+            }
+        );
+    }
+
     #[test]
     fn test_preferred_tunnel_protocol() {
         let relay_selector = new_relay_selector();
@@ -1709,6 +2258,49 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_prefer_ipv6_endpoints() {
+        fn endpoint_ip(relay_selector: &RelaySelector, constraints: &RelayConstraints) -> IpAddr {
+            relay_selector
+                .get_tunnel_endpoint(
+                    constraints,
+                    BridgeState::Off,
+                    0,
+                    TunnelType::Wireguard,
+                    &CustomListsSettings::default(),
+                )
+                .expect("Failed to select relay")
+                .endpoint
+                .to_endpoint()
+                .address
+                .ip()
+        }
+
+        let relay_selector = new_relay_selector();
+        let mut relay_constraints = RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        assert!(endpoint_ip(&relay_selector, &relay_constraints).is_ipv4());
+
+        relay_selector.set_prefer_ipv6_endpoints(true);
+        assert!(endpoint_ip(&relay_selector, &relay_constraints).is_ipv6());
+
+        // Fall back on IPv4 if no relay has an IPv6 address
+        let mut relays = RELAYS.clone();
+        for relay in &mut relays.countries[0].cities[0].relays {
+            relay.ipv6_addr_in = None;
+        }
+        let ipv4_only_selector = new_relay_selector_with_relays(relays);
+        ipv4_only_selector.set_prefer_ipv6_endpoints(true);
+        assert!(endpoint_ip(&ipv4_only_selector, &relay_constraints).is_ipv4());
+
+        // An explicit IP version takes precedence
+        relay_constraints.wireguard_constraints.ip_version = Constraint::Only(IpVersion::V4);
+        assert!(endpoint_ip(&relay_selector, &relay_constraints).is_ipv4());
+    }
+
     #[test]
     fn test_openvpn_constraints() -> Result<(), String> {
         let relay_selector = new_relay_selector();
@@ -1921,10 +2513,46 @@ mod test {
                 Constraint::Only(TunnelType::Wireguard)
             );
         }
+        // WireGuard is tried a number of times before falling back on OpenVPN
+        let preferred = relay_selector.preferred_constraints(
+            &relay_constraints,
+            BridgeState::On,
+            DEFAULT_WIREGUARD_ATTEMPTS_BEFORE_OPENVPN - 1,
+            TunnelType::Wireguard,
+            &CustomListsSettings::default(),
+        );
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::Wireguard)
+        );
+        let preferred = relay_selector.preferred_constraints(
+            &relay_constraints,
+            BridgeState::On,
+            DEFAULT_WIREGUARD_ATTEMPTS_BEFORE_OPENVPN,
+            TunnelType::Wireguard,
+            &CustomListsSettings::default(),
+        );
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::OpenVpn)
+        );
+        assert_eq!(
+            preferred.openvpn_constraints.port,
+            Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Any,
+            })
+        );
+
+        // The fallback happens sooner when fewer WireGuard attempts are configured
+        relay_selector
+            .config
+            .lock()
+            .wireguard_attempts_before_openvpn = 1;
         let preferred = relay_selector.preferred_constraints(
             &relay_constraints,
             BridgeState::On,
-            2,
+            1,
             TunnelType::Wireguard,
             &CustomListsSettings::default(),
         );
@@ -2040,10 +2668,34 @@ mod test {
         assert!(matches!(
             obfs_config,
             SelectedObfuscator {
-                config: ObfuscatorConfig::Udp2Tcp { .. },
+                config: ObfuscatorConfig::Udp2Tcp {
+                    upstream_proxy: None,
+                    ..
+                },
                 ..
             }
         ));
+
+        // The upstream proxy is passed on to the obfuscator
+        let proxy = talpid_types::net::proxy::SocksProxy {
+            endpoint: "192.168.1.1:1080".parse().unwrap(),
+            authentication: None,
+        };
+        relay_selector
+            .config
+            .lock()
+            .obfuscation_settings
+            .upstream_proxy = Some(proxy.clone());
+        let obfs_config = relay_selector
+            .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 0)
+            .unwrap()
+            .unwrap();
+        match obfs_config.config {
+            ObfuscatorConfig::Udp2Tcp { upstream_proxy, .. } => {
+                assert_eq!(upstream_proxy, Some(proxy))
+            }
+            config => panic!("Unexpected obfuscator: {config:?}"),
+        }
     }
 
     #[test]
@@ -2075,6 +2727,286 @@ mod test {
             .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 2,)
             .unwrap()
             .is_some());
+
+        // A custom order is honored
+        relay_selector
+            .config
+            .lock()
+            .obfuscation_settings
+            .obfuscation_priority = vec![ObfuscationType::Udp2Tcp, ObfuscationType::None];
+
+        assert!(relay_selector
+            .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 0,)
+            .unwrap()
+            .is_some());
+
+        assert!(relay_selector
+            .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 2,)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_quic_obfuscation_selects_quic_relays() {
+        let relay_selector = new_relay_selector();
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings = RelaySettings::Normal(WIREGUARD_SINGLEHOP_CONSTRAINTS);
+            config.obfuscation_settings = ObfuscationSettings {
+                selected_obfuscation: SelectedObfuscation::Quic,
+                ..ObfuscationSettings::default()
+            };
+        }
+
+        // Only se9-wireguard accepts QUIC
+        for attempt in 0..10 {
+            let (relay, _bridge, obfuscator) = relay_selector.get_relay(attempt).unwrap();
+            let SelectedRelay::Normal(relay) = relay else {
+                panic!("expected a normal relay");
+            };
+            assert_eq!(relay.exit_relay.hostname, "se9-wireguard");
+            let Some(SelectedObfuscator {
+                config:
+                    ObfuscatorConfig::Quic {
+                        endpoint,
+                        server_name,
+                        alpn,
+                    },
+                ..
+            }) = obfuscator
+            else {
+                panic!("expected a QUIC obfuscator");
+            };
+            assert_eq!(endpoint, "185.213.154.68:443".parse().unwrap());
+            assert_eq!(server_name, "se9-wireguard");
+            assert_eq!(alpn, vec!["h3".to_owned()]);
+        }
+
+        // Relays that do not accept QUIC are used without obfuscation in auto mode
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Auto,
+            obfuscation_priority: vec![ObfuscationType::Quic],
+            ..ObfuscationSettings::default()
+        };
+        let relays = relay_selector.parsed_relays.lock().relays().clone();
+        let se10 = relays
+            .iter()
+            .find(|relay| relay.hostname == "se10-wireguard")
+            .unwrap();
+        let endpoint = WireguardMatcher::from_endpoint(
+            relay_selector
+                .parsed_relays
+                .lock()
+                .locations
+                .wireguard
+                .clone(),
+        )
+        .mullvad_endpoint(se10)
+        .unwrap();
+        assert!(relay_selector
+            .get_obfuscator(se10, endpoint.unwrap_wireguard(), 0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_tls_obfuscation_selects_tls_relays() {
+        let relay_selector = new_relay_selector();
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings = RelaySettings::Normal(WIREGUARD_SINGLEHOP_CONSTRAINTS);
+            config.obfuscation_settings = ObfuscationSettings {
+                selected_obfuscation: SelectedObfuscation::Tls,
+                ..ObfuscationSettings::default()
+            };
+        }
+
+        // Only se10-wireguard accepts TLS, and the relay list sets its server name
+        for attempt in 0..10 {
+            let (relay, _bridge, obfuscator) = relay_selector.get_relay(attempt).unwrap();
+            let SelectedRelay::Normal(relay) = relay else {
+                panic!("expected a normal relay");
+            };
+            assert_eq!(relay.exit_relay.hostname, "se10-wireguard");
+            let Some(SelectedObfuscator {
+                config:
+                    ObfuscatorConfig::Tls {
+                        endpoint,
+                        server_name,
+                        upstream_proxy,
+                    },
+                ..
+            }) = obfuscator
+            else {
+                panic!("expected a TLS obfuscator");
+            };
+            assert_eq!(endpoint, "185.213.154.69:443".parse().unwrap());
+            assert_eq!(server_name, "www.example.com");
+            assert_eq!(upstream_proxy, None);
+        }
+
+        // The server name in the settings takes precedence
+        relay_selector.config.lock().obfuscation_settings.tls = TlsObfuscationSettings {
+            server_name: Some("cdn.example.net".to_owned()),
+        };
+        let (_, _, obfuscator) = relay_selector.get_relay(0).unwrap();
+        assert!(matches!(
+            obfuscator,
+            Some(SelectedObfuscator {
+                config: ObfuscatorConfig::Tls { server_name, .. },
+                ..
+            }) if server_name == "cdn.example.net"
+        ));
+    }
+
+    #[test]
+    fn test_auto_obfuscation_attempts() {
+        use ObfuscationType::*;
+
+        let attempts = |priority: &[ObfuscationType]| {
+            (0..8)
+                .map(|attempt| auto_obfuscation_attempt(priority, attempt))
+                .collect::<Vec<_>>()
+        };
+
+        // The default order keeps the behavior of trying without obfuscation first, and cycles
+        // through all udp2tcp ports
+        assert_eq!(
+            attempts(&DEFAULT_OBFUSCATION_PRIORITY),
+            vec![
+                (None, 0),
+                (None, 1),
+                (Udp2Tcp, 0),
+                (Udp2Tcp, 1),
+                (None, 2),
+                (None, 3),
+                (Udp2Tcp, 2),
+                (Udp2Tcp, 3),
+            ]
+        );
+
+        // No obfuscation can be tried last
+        assert_eq!(
+            attempts(&[Udp2Tcp, None]),
+            vec![
+                (Udp2Tcp, 0),
+                (Udp2Tcp, 1),
+                (None, 0),
+                (None, 1),
+                (Udp2Tcp, 2),
+                (Udp2Tcp, 3),
+                (None, 2),
+                (None, 3),
+            ]
+        );
+
+        assert!(attempts(&[Udp2Tcp])
+            .into_iter()
+            .enumerate()
+            .all(|(attempt, selected)| selected == (Udp2Tcp, attempt as u32)));
+    }
+
+    #[test]
+    fn test_auto_tunnel_attempts() {
+        use TunnelType::*;
+
+        let attempts = |wireguard_attempts: u32| {
+            (0..9)
+                .map(|attempt| auto_tunnel_attempt(wireguard_attempts, attempt))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            attempts(4),
+            vec![
+                (Wireguard, 0),
+                (Wireguard, 1),
+                (Wireguard, 2),
+                (Wireguard, 3),
+                (OpenVpn, 0),
+                (OpenVpn, 1),
+                (Wireguard, 4),
+                (Wireguard, 5),
+                (Wireguard, 6),
+            ]
+        );
+        assert_eq!(
+            attempts(1),
+            vec![
+                (Wireguard, 0),
+                (OpenVpn, 0),
+                (OpenVpn, 1),
+                (Wireguard, 1),
+                (OpenVpn, 2),
+                (OpenVpn, 3),
+                (Wireguard, 2),
+                (OpenVpn, 4),
+                (OpenVpn, 5),
+            ]
+        );
+        // OpenVPN is always fallen back to
+        assert_eq!(attempts(0), attempts(1));
+    }
+
+    /// Verify that WireGuard falls back to OpenVPN over UDP and TCP in auto mode, with and without
+    /// obfuscation, but never when WireGuard has been selected explicitly.
+    #[test]
+    fn test_openvpn_fallback() {
+        let relay_selector = new_relay_selector();
+        {
+            let mut config = relay_selector.config.lock();
+            config.default_tunnel_type = TunnelType::Wireguard;
+            config.obfuscation_settings.selected_obfuscation = SelectedObfuscation::Auto;
+        }
+
+        let attempts = |relay_selector: &RelaySelector| {
+            (0..12)
+                .map(|attempt| {
+                    let (relay, _bridge, obfuscator) = relay_selector.get_relay(attempt).unwrap();
+                    let SelectedRelay::Normal(relay) = relay else {
+                        panic!("expected a normal relay");
+                    };
+                    // OpenVPN attempts are identified by their protocol, and whether TCP is used
+                    // on port 443
+                    match relay.endpoint {
+                        MullvadEndpoint::OpenVpn(endpoint) => (
+                            Some(endpoint.protocol),
+                            endpoint.protocol == TransportProtocol::Tcp
+                                && endpoint.address.port() == 443,
+                        ),
+                        MullvadEndpoint::Wireguard(_) => (None, obfuscator.is_some()),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let wireguard = (None, false);
+        let udp2tcp = (None, true);
+        let openvpn_udp = (Some(TransportProtocol::Udp), false);
+        let openvpn_tcp = (Some(TransportProtocol::Tcp), true);
+        assert_eq!(
+            attempts(&relay_selector),
+            vec![
+                wireguard,
+                wireguard,
+                udp2tcp,
+                udp2tcp,
+                openvpn_udp,
+                openvpn_udp,
+                wireguard,
+                wireguard,
+                udp2tcp,
+                udp2tcp,
+                openvpn_tcp,
+                openvpn_tcp,
+            ]
+        );
+
+        relay_selector.config.lock().relay_settings =
+            RelaySettings::Normal(WIREGUARD_SINGLEHOP_CONSTRAINTS);
+        assert!(attempts(&relay_selector)
+            .into_iter()
+            .all(|(protocol, _)| protocol.is_none()));
     }
 
     #[test]
@@ -2109,18 +3041,13 @@ mod test {
                 .unwrap()
                 .expect("Failed to get Tcp2Udp endpoint");
 
-            assert!(matches!(
-                obfs_config,
-                SelectedObfuscator {
-                    config: ObfuscatorConfig::Udp2Tcp { .. },
-                    ..
-                }
-            ));
-
             let SelectedObfuscator {
-                config: ObfuscatorConfig::Udp2Tcp { endpoint },
+                config: ObfuscatorConfig::Udp2Tcp { endpoint, .. },
                 ..
-            } = obfs_config;
+            } = obfs_config
+            else {
+                panic!("expected udp2tcp obfuscation, got {obfs_config:?}");
+            };
             assert!(TCP2UDP_PORTS.contains(&endpoint.port()));
         }
     }
@@ -2255,6 +3182,7 @@ mod test {
         {
             let mut config = relay_selector.config.lock();
             config.bridge_state = BridgeState::Auto;
+            config.default_tunnel_type = TunnelType::OpenVpn;
         }
 
         const ATTEMPT_SHOULD_USE_BRIDGE: [bool; 5] = [false, false, false, false, true];
@@ -2264,6 +3192,26 @@ mod test {
             assert_eq!(*should_use_bridge, bridge.is_some());
         }
 
+        // When falling back from WireGuard, bridges are used on the same OpenVPN attempts as when
+        // WireGuard was only tried twice
+        relay_selector.config.lock().default_tunnel_type = TunnelType::Wireguard;
+        let openvpn_attempts_use_bridge = (0..18)
+            .filter_map(|attempt| {
+                let (relay, bridge, _obfs) = relay_selector.get_relay(attempt).unwrap();
+                match relay {
+                    SelectedRelay::Normal(NormalSelectedRelay {
+                        endpoint: MullvadEndpoint::OpenVpn(_),
+                        ..
+                    }) => Some(bridge.is_some()),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            openvpn_attempts_use_bridge,
+            vec![false, false, true, true, false, false]
+        );
+
         // Verify that bridges are ignored when tunnel protocol is WireGuard
         {
             let mut config = relay_selector.config.lock();
@@ -2281,6 +3229,60 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_bridge_ip_version() {
+        fn bridge_ip(relay_selector: &RelaySelector) -> Result<IpAddr, Error> {
+            let (_relay, bridge, _obfs) = relay_selector.get_relay(0)?;
+            match bridge {
+                Some(SelectedBridge::Normal(NormalSelectedBridge {
+                    settings: ProxySettings::Shadowsocks(settings),
+                    ..
+                })) => Ok(settings.peer.ip()),
+                bridge => panic!("Unexpected bridge: {bridge:?}"),
+            }
+        }
+        fn use_openvpn_bridges(relay_selector: &RelaySelector) {
+            let mut config = relay_selector.config.lock();
+            config.bridge_state = BridgeState::On;
+            config.relay_settings =
+                config
+                    .relay_settings
+                    .merge(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                        tunnel_protocol: Some(Constraint::Only(TunnelType::OpenVpn)),
+                        ..Default::default()
+                    }));
+        }
+
+        let mut relays = RELAYS.clone();
+        for relay in &mut relays.countries[0].cities[0].relays {
+            if relay.endpoint_data == RelayEndpointData::Bridge {
+                relay.ipv6_addr_in = Some("2001:db8::1337".parse().unwrap());
+            }
+        }
+        let relay_selector = new_relay_selector_with_relays(relays);
+        use_openvpn_bridges(&relay_selector);
+
+        assert_eq!(
+            bridge_ip(&relay_selector).unwrap(),
+            "1.3.3.7".parse::<IpAddr>().unwrap()
+        );
+        relay_selector.set_prefer_ipv6_endpoints(true);
+        assert_eq!(
+            bridge_ip(&relay_selector).unwrap(),
+            "2001:db8::1337".parse::<IpAddr>().unwrap()
+        );
+
+        // Fail rather than pick an IPv4 address that can't be reached
+        let ipv4_only_selector = new_relay_selector();
+        use_openvpn_bridges(&ipv4_only_selector);
+        assert!(bridge_ip(&ipv4_only_selector).unwrap().is_ipv4());
+        ipv4_only_selector.set_prefer_ipv6_endpoints(true);
+        assert!(matches!(
+            bridge_ip(&ipv4_only_selector),
+            Err(Error::NoBridgeWithIpVersion(IpVersion::V6))
+        ));
+    }
+
     /// Ensure that `include_in_country` is ignored if all relays have it set to false (i.e., some
     /// relay is returned). Also ensure that `include_in_country` is respected if some relays
     /// have it set to true (i.e., that relay is never returned)
@@ -2312,6 +3314,8 @@ mod test {
                                         "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
                                     )
                                     .unwrap(),
+                                    quic: None,
+                                    tls: None,
                                 },
                             ),
                             location: None,
@@ -2331,6 +3335,8 @@ mod test {
                                         "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
                                     )
                                     .unwrap(),
+                                    quic: None,
+                                    tls: None,
                                 },
                             ),
                             location: None,
@@ -2391,4 +3397,133 @@ mod test {
             }) if hostname == expected_relay.hostname
         ))
     }
+
+    #[test]
+    fn test_relay_overrides() {
+        let relay_selector = new_relay_selector();
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings = RelaySettings::Normal(RelayConstraints {
+                location: Constraint::Only(LocationConstraint::from(
+                    GeographicLocationConstraint::Country("se".to_owned()),
+                )),
+                ownership: Constraint::Only(Ownership::Rented),
+                tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+                ..RelayConstraints::default()
+            });
+            config.obfuscation_settings.selected_obfuscation = SelectedObfuscation::Tls;
+        }
+
+        // The relay is used even though it is owned, and without the obfuscation in the settings
+        let overrides = RelayOverrides {
+            hostname: Some("SE9-wireguard".to_owned()),
+            port: Some(53),
+            obfuscation: Some(SelectedObfuscation::Off),
+            tunnel_protocol: None,
+        };
+        for attempt in 0..5 {
+            let (relay, _bridge, obfuscator) = relay_selector
+                .get_relay_with_overrides(&overrides, attempt)
+                .unwrap();
+            let SelectedRelay::Normal(relay) = relay else {
+                panic!("expected a normal relay");
+            };
+            assert_eq!(relay.exit_relay.hostname, "se9-wireguard");
+            assert_eq!(relay.endpoint.to_endpoint().address.port(), 53);
+            assert!(obfuscator.is_none());
+        }
+
+        // The settings are left as they were for the attempts that follow
+        let (relay, _bridge, obfuscator) = relay_selector.get_relay(0).unwrap();
+        let SelectedRelay::Normal(relay) = relay else {
+            panic!("expected a normal relay");
+        };
+        assert_eq!(relay.exit_relay.hostname, "se10-wireguard");
+        assert!(matches!(
+            obfuscator,
+            Some(SelectedObfuscator {
+                config: ObfuscatorConfig::Tls { .. },
+                ..
+            })
+        ));
+
+        // Without a tunnel protocol, the port decides which one is used
+        let overrides = RelayOverrides {
+            port: Some(443),
+            ..RelayOverrides::default()
+        };
+        relay_selector.config.lock().relay_settings =
+            RelaySettings::Normal(RelayConstraints::default());
+        let (relay, ..) = relay_selector
+            .get_relay_with_overrides(&overrides, 0)
+            .unwrap();
+        let SelectedRelay::Normal(relay) = relay else {
+            panic!("expected a normal relay");
+        };
+        let MullvadEndpoint::OpenVpn(endpoint) = relay.endpoint else {
+            panic!("expected an OpenVPN endpoint");
+        };
+        assert_eq!(endpoint.protocol, TransportProtocol::Tcp);
+        assert_eq!(endpoint.address.port(), 443);
+    }
+
+    #[test]
+    fn test_invalid_relay_overrides() {
+        let relay_selector = new_relay_selector();
+        let get_relay = |overrides: RelayOverrides| {
+            relay_selector
+                .get_relay_with_overrides(&overrides, 0)
+                .map(|_| ())
+        };
+        let relay = |hostname: &str| RelayOverrides {
+            hostname: Some(hostname.to_owned()),
+            ..RelayOverrides::default()
+        };
+
+        assert!(matches!(
+            get_relay(relay("se-nonexistent-wg-001")),
+            Err(Error::UnknownRelay(_))
+        ));
+        assert!(matches!(
+            get_relay(relay("se-got-br-001")),
+            Err(Error::InvalidOverride(_))
+        ));
+        assert!(matches!(
+            get_relay(RelayOverrides {
+                tunnel_protocol: Some(TunnelType::Wireguard),
+                ..relay("se-got-001")
+            }),
+            Err(Error::InvalidOverride(_))
+        ));
+        // WireGuard does not accept port 1194
+        assert!(matches!(
+            get_relay(RelayOverrides {
+                port: Some(1194),
+                ..relay("se9-wireguard")
+            }),
+            Err(Error::InvalidOverride(_))
+        ));
+        // se9-wireguard does not accept TLS connections
+        assert!(matches!(
+            get_relay(RelayOverrides {
+                obfuscation: Some(SelectedObfuscation::Tls),
+                ..relay("se9-wireguard")
+            }),
+            Err(Error::InvalidOverride(_))
+        ));
+        assert!(matches!(
+            get_relay(RelayOverrides {
+                obfuscation: Some(SelectedObfuscation::Udp2Tcp),
+                ..relay("se-got-001")
+            }),
+            Err(Error::InvalidOverride(_))
+        ));
+
+        assert!(get_relay(relay("se-got-001")).is_ok());
+        assert!(get_relay(RelayOverrides {
+            obfuscation: Some(SelectedObfuscation::Quic),
+            ..relay("se9-wireguard")
+        })
+        .is_ok());
+    }
 }
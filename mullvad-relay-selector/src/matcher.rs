@@ -219,6 +219,10 @@ pub struct WireguardMatcher {
     pub peer: Option<Relay>,
     pub port: Constraint<u16>,
     pub ip_version: Constraint<IpVersion>,
+    /// Only match relays that accept QUIC obfuscation.
+    pub require_quic: bool,
+    /// Only match relays that accept TLS obfuscation.
+    pub require_tls: bool,
 
     pub data: WireguardEndpointData,
 }
@@ -229,6 +233,8 @@ impl WireguardMatcher {
             peer: None,
             port: constraints.port,
             ip_version: constraints.ip_version,
+            require_quic: false,
+            require_tls: false,
             data,
         }
     }
@@ -317,7 +323,14 @@ impl EndpointMatcher for WireguardMatcher {
             .as_ref()
             .map(|peer_relay| peer_relay.hostname == relay.hostname)
             .unwrap_or(false)
-            && matches!(relay.endpoint_data, RelayEndpointData::Wireguard(..))
+            && match &relay.endpoint_data {
+                RelayEndpointData::Wireguard(data) => {
+                    (!self.require_quic || data.quic.is_some())
+                        && (!self.require_tls || data.tls.is_some())
+                }
+                _ => false,
+            }
+            && (self.ip_version != Constraint::Only(IpVersion::V6) || relay.ipv6_addr_in.is_some())
     }
 
     fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint> {
@@ -329,11 +342,14 @@ impl EndpointMatcher for WireguardMatcher {
 }
 
 #[derive(Clone)]
-pub struct BridgeMatcher(pub ());
+pub struct BridgeMatcher {
+    pub ip_version: Constraint<IpVersion>,
+}
 
 impl EndpointMatcher for BridgeMatcher {
     fn is_matching_relay(&self, relay: &Relay) -> bool {
         matches!(relay.endpoint_data, RelayEndpointData::Bridge)
+            && (self.ip_version != Constraint::Only(IpVersion::V6) || relay.ipv6_addr_in.is_some())
     }
 
     fn mullvad_endpoint(&self, _relay: &Relay) -> Option<MullvadEndpoint> {
@@ -4,7 +4,12 @@ use futures::{
     future::{Fuse, FusedFuture},
     Future, FutureExt, SinkExt, StreamExt,
 };
-use mullvad_api::{availability::ApiAvailabilityHandle, rest::MullvadRestHandle, RelayListProxy};
+use mullvad_api::{
+    availability::ApiAvailabilityHandle,
+    rest::MullvadRestHandle,
+    retry::{retry_request, RetryPolicy},
+    RelayListProxy,
+};
 use mullvad_types::relay_list::RelayList;
 use parking_lot::Mutex;
 use std::{
@@ -12,7 +17,6 @@ use std::{
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use talpid_core::future_retry::{retry_future, ExponentialBackoff, Jittered};
 use talpid_types::ErrorExt;
 use tokio::fs::File;
 
@@ -23,21 +27,33 @@ const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 15);
 /// How old the cached relays need to be to trigger an update
 const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
-const DOWNLOAD_RETRY_STRATEGY: Jittered<ExponentialBackoff> = Jittered::jitter(
-    ExponentialBackoff::new(Duration::from_secs(16), 8)
-        .max_delay(Some(Duration::from_secs(2 * 60 * 60))),
-);
+enum UpdaterCommand {
+    /// Download the relay list if it has changed.
+    Update,
+    /// Remove the cached relay list and download it again, even if it has not changed.
+    ClearCache,
+}
 
 #[derive(Clone)]
 pub struct RelayListUpdaterHandle {
-    tx: mpsc::Sender<()>,
+    tx: mpsc::Sender<UpdaterCommand>,
 }
 
 impl RelayListUpdaterHandle {
     pub async fn update(&mut self) {
+        self.send_command(UpdaterCommand::Update).await
+    }
+
+    /// Removes the relay list cache file and downloads the relay list again. The current relay
+    /// list is used until the download has finished.
+    pub async fn clear_cache(&mut self) {
+        self.send_command(UpdaterCommand::ClearCache).await
+    }
+
+    async fn send_command(&mut self, command: UpdaterCommand) {
         if let Err(error) = self
             .tx
-            .send(())
+            .send(command)
             .await
             .map_err(|_| Error::DownloaderShutDown)
         {
@@ -82,7 +98,7 @@ impl RelayListUpdater {
         RelayListUpdaterHandle { tx }
     }
 
-    async fn run(mut self, mut cmd_rx: mpsc::Receiver<()>) {
+    async fn run(mut self, mut cmd_rx: mpsc::Receiver<UpdaterCommand>) {
         let mut download_future = Box::pin(Fuse::terminated());
         loop {
             let next_check = tokio::time::sleep(UPDATE_CHECK_INTERVAL).fuse();
@@ -103,11 +119,17 @@ impl RelayListUpdater {
 
                 cmd = cmd_rx.next() => {
                     match cmd {
-                        Some(()) => {
+                        Some(UpdaterCommand::Update) => {
                             let tag = self.parsed_relays.lock().tag().map(|tag| tag.to_string());
                             download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag).fuse());
                             self.last_check = SystemTime::now();
                         },
+                        Some(UpdaterCommand::ClearCache) => {
+                            Self::remove_cache(self.cache_path.clone()).await;
+                            // Without a tag, the relay list is downloaded even if it is unchanged
+                            download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), None).fuse());
+                            self.last_check = SystemTime::now();
+                        },
                         None => {
                             log::trace!("Relay list updater shutting down");
                             return;
@@ -163,11 +185,9 @@ impl RelayListUpdater {
             }
         };
 
-        retry_future(
-            download_futures,
-            |result| result.is_err(),
-            DOWNLOAD_RETRY_STRATEGY,
-        )
+        retry_request(RetryPolicy::background(), download_futures, |result| {
+            result.is_err()
+        })
     }
 
     async fn update_cache(&mut self, new_relay_list: RelayList) -> Result<(), Error> {
@@ -190,6 +210,19 @@ impl RelayListUpdater {
         Ok(())
     }
 
+    /// Remove the relay cache file, if it exists.
+    async fn remove_cache(cache_path: PathBuf) {
+        log::debug!("Removing relays cache {}", cache_path.display());
+        match tokio::fs::remove_file(&cache_path).await {
+            Ok(()) => (),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => (),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove the relay cache")
+            ),
+        }
+    }
+
     /// Write a `RelayList` to the cache file.
     async fn cache_relays(cache_path: &Path, relays: &RelayList) -> Result<(), Error> {
         log::debug!("Writing relays cache to {}", cache_path.display());
@@ -166,6 +166,7 @@ async fn remove_device() -> Result<(), Error> {
                         .await
                         .into_repeat(),
                     |_| async { true },
+                    |_| (),
                 )
                 .await,
         );
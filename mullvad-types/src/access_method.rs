@@ -1,7 +1,12 @@
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::{Duration, SystemTime},
+};
+
+pub mod share;
 
 /// Daemon settings for API access methods.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -79,9 +84,13 @@ pub struct AccessMethodSetting {
     pub name: String,
     pub enabled: bool,
     pub access_method: AccessMethod,
+    /// Whether the access method was imported from a string created by someone else, see
+    /// [`share`].
+    #[serde(default)]
+    pub imported: bool,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Id(uuid::Uuid);
 
 impl Id {
@@ -102,6 +111,114 @@ impl std::fmt::Display for Id {
     }
 }
 
+/// Whether the daemon is able to reach the API, judging by the requests that have been sent to it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum ApiConnectivity {
+    /// No request has finished since the daemon started.
+    #[default]
+    Unknown,
+    Reachable,
+    /// Every request has failed since `since`. `last_error` describes the most recent failure.
+    Unreachable {
+        since: SystemTime,
+        last_error: String,
+    },
+}
+
+impl ApiConnectivity {
+    pub fn is_unreachable(&self) -> bool {
+        matches!(self, ApiConnectivity::Unreachable { .. })
+    }
+}
+
+/// How well an access method has worked since the daemon started.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AccessMethodStats {
+    pub id: Id,
+    /// Number of API requests which reached the API.
+    pub successes: u32,
+    /// Number of API requests which failed due to network errors.
+    pub failures: u32,
+    pub last_error: Option<String>,
+    pub last_failure: Option<SystemTime>,
+    pub last_success: Option<SystemTime>,
+    /// When the access method was last selected.
+    pub last_used: Option<SystemTime>,
+    /// Set while the access method is not retried due to failing recently.
+    pub cooldown_until: Option<SystemTime>,
+}
+
+impl AccessMethodStats {
+    /// Returns whether the most recent request reached the API, or `None` if there have been no
+    /// requests.
+    pub fn reachable(&self) -> Option<bool> {
+        match (self.last_success, self.last_failure) {
+            (None, None) => None,
+            (Some(_), None) => Some(true),
+            (None, Some(_)) => Some(false),
+            (Some(success), Some(failure)) => Some(success >= failure),
+        }
+    }
+
+    /// Stats for an access method which has not been used yet.
+    pub fn new(id: Id) -> Self {
+        Self {
+            id,
+            successes: 0,
+            failures: 0,
+            last_error: None,
+            last_failure: None,
+            last_success: None,
+            last_used: None,
+            cooldown_until: None,
+        }
+    }
+}
+
+/// A stage of reaching the API using an access method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConnectionStage {
+    /// Opening a TCP connection to the API, or to the proxy if there is one.
+    TcpConnect,
+    /// Asking the proxy to relay the connection to the API.
+    ProxyHandshake,
+    TlsHandshake,
+    /// Sending a request to the API and receiving the response header.
+    HttpRoundTrip,
+}
+
+impl std::fmt::Display for ConnectionStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionStage::TcpConnect => f.write_str("TCP connect"),
+            ConnectionStage::ProxyHandshake => f.write_str("proxy handshake"),
+            ConnectionStage::TlsHandshake => f.write_str("TLS handshake"),
+            ConnectionStage::HttpRoundTrip => f.write_str("HTTP round trip"),
+        }
+    }
+}
+
+/// The outcome of testing whether an access method can reach the API.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessMethodTestReport {
+    /// How long each stage which completed took, in order.
+    pub timings: Vec<(ConnectionStage, Duration)>,
+    /// The stage which failed and the reason, if the API could not be reached.
+    pub failure: Option<(ConnectionStage, String)>,
+}
+
+impl AccessMethodTestReport {
+    pub fn succeeded(&self) -> bool {
+        self.failure.is_none()
+    }
+
+    /// Time spent on all stages which completed.
+    pub fn total(&self) -> Duration {
+        self.timings.iter().map(|(_, duration)| *duration).sum()
+    }
+}
+
 /// Access Method datastructure.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Hash)]
 pub enum AccessMethod {
@@ -116,6 +233,7 @@ impl AccessMethodSetting {
             name,
             enabled,
             access_method,
+            imported: false,
         }
     }
 
@@ -134,6 +252,7 @@ impl AccessMethodSetting {
             name,
             enabled,
             access_method,
+            imported: false,
         }
     }
 
@@ -180,6 +299,7 @@ pub enum BuiltInAccessMethod {
 pub enum CustomAccessMethod {
     Shadowsocks(Shadowsocks),
     Socks5(Socks5),
+    HttpProxy(HttpProxy),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -212,12 +332,47 @@ pub struct Socks5Remote {
     pub authentication: Option<SocksAuth>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+/// Credentials for username/password authentication against a SOCKS5 proxy. The password is left
+/// out of the `Debug` output.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct SocksAuth {
     pub username: String,
     pub password: String,
 }
 
+impl std::fmt::Debug for SocksAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SocksAuth")
+            .field("username", &self.username)
+            .field("password", &"[REDACTED]")
+            .finish()
+    }
+}
+
+/// An HTTP proxy which tunnels the API traffic using the `CONNECT` method.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct HttpProxy {
+    pub peer: SocketAddr,
+    pub authentication: Option<HttpProxyAuth>,
+}
+
+/// Credentials for Basic authentication against an [`HttpProxy`]. The password is left out of the
+/// `Debug` output.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct HttpProxyAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for HttpProxyAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpProxyAuth")
+            .field("username", &self.username)
+            .field("password", &"[REDACTED]")
+            .finish()
+    }
+}
+
 impl AccessMethod {
     pub fn as_custom(&self) -> Option<&CustomAccessMethod> {
         match self {
@@ -296,6 +451,23 @@ impl Socks5Remote {
     }
 }
 
+impl HttpProxy {
+    pub fn new(peer: SocketAddr, authentication: Option<HttpProxyAuth>) -> Self {
+        Self {
+            peer,
+            authentication,
+        }
+    }
+
+    /// Like [new()], but tries to parse `ip` and `port` into a [`std::net::SocketAddr`] for you.
+    /// If `ip` or `port` are valid [`Some(HttpProxy)`] is returned, otherwise [`None`].
+    pub fn from_args(ip: String, port: u16, authentication: Option<HttpProxyAuth>) -> Option<Self> {
+        let peer_ip = IpAddr::from_str(&ip).ok()?;
+        let peer = SocketAddr::new(peer_ip, port);
+        Some(Self::new(peer, authentication))
+    }
+}
+
 impl From<BuiltInAccessMethod> for AccessMethod {
     fn from(value: BuiltInAccessMethod) -> Self {
         AccessMethod::BuiltIn(value)
@@ -332,6 +504,12 @@ impl From<Socks5Local> for AccessMethod {
     }
 }
 
+impl From<HttpProxy> for AccessMethod {
+    fn from(value: HttpProxy) -> Self {
+        CustomAccessMethod::HttpProxy(value).into()
+    }
+}
+
 impl From<Socks5Remote> for Socks5 {
     fn from(value: Socks5Remote) -> Self {
         Socks5::Remote(value)
@@ -343,3 +521,24 @@ impl From<Socks5Local> for Socks5 {
         Socks5::Local(value)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_http_proxy_debug_is_redacted() {
+        let proxy = HttpProxy::from_args(
+            "10.0.0.1".to_owned(),
+            3128,
+            Some(HttpProxyAuth {
+                username: "user".to_owned(),
+                password: "hunter2".to_owned(),
+            }),
+        )
+        .unwrap();
+        let debug = format!("{:?}", AccessMethod::from(proxy));
+        assert!(debug.contains("user"));
+        assert!(!debug.contains("hunter2"));
+    }
+}
@@ -0,0 +1,566 @@
+//! Encoding of custom access methods as strings which can be shared with other users.
+//!
+//! Access methods are encoded as `mullvad-access://` followed by the URL-safe base64 encoding of
+//! a JSON object which has a `version` field. Shadowsocks proxies are instead encoded as `ss://`
+//! URIs ([SIP002]), which other Shadowsocks clients understand as well. Both the SIP002 and the
+//! older base64-encoded `ss://` format can be imported.
+//!
+//! Encoded strings come from other users, so they are validated before anything is imported.
+//! Local SOCKS5 proxies cannot be shared, since the firewall lets them reach any peer, and
+//! imported access methods are disabled until the user enables them.
+//!
+//! [SIP002]: https://shadowsocks.org/doc/sip002.html
+use super::{AccessMethod, AccessMethodSetting, CustomAccessMethod, Shadowsocks, Socks5};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use talpid_types::net::openvpn::SHADOWSOCKS_CIPHERS;
+
+/// Prefix of access methods encoded by this module.
+pub const SCHEME: &str = "mullvad-access://";
+/// Prefix of Shadowsocks URIs.
+pub const SHADOWSOCKS_SCHEME: &str = "ss://";
+/// The version written by [`encode`]. [`decode`] rejects any other version.
+pub const VERSION: u32 = 1;
+
+/// Length limit of the username and password of SOCKS5 proxies.
+const MAX_SOCKS_CREDENTIAL_LEN: usize = 255;
+
+#[derive(err_derive::Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Built-in access methods are available to everyone and cannot be exported
+    #[error(display = "Built-in access methods cannot be exported")]
+    BuiltIn,
+
+    /// Local SOCKS5 proxies make the firewall allow traffic to their peer, so they are never
+    /// imported from strings created by someone else
+    #[error(display = "Local SOCKS5 proxies cannot be shared")]
+    LocalProxy,
+
+    #[error(
+        display = "Expected a string starting with \"{}\" or \"{}\"",
+        SCHEME,
+        SHADOWSOCKS_SCHEME
+    )]
+    UnknownScheme,
+
+    #[error(display = "The access method is not valid base64")]
+    InvalidBase64,
+
+    #[error(display = "The access method is malformed: {}", _0)]
+    Malformed(String),
+
+    /// The string was created by a newer version of the app
+    #[error(
+        display = "Unsupported access method version {}. Only version {} is supported, \
+        try updating the app",
+        _0,
+        VERSION
+    )]
+    UnsupportedVersion(u32),
+
+    #[error(display = "The access method has no name")]
+    EmptyName,
+
+    #[error(display = "Invalid proxy address {}", _0)]
+    InvalidAddress(String),
+
+    #[error(display = "Unsupported Shadowsocks cipher \"{}\"", _0)]
+    UnsupportedCipher(String),
+
+    #[error(display = "Shadowsocks plugins are not supported")]
+    UnsupportedPlugin,
+
+    #[error(display = "Invalid credentials: {}", _0)]
+    InvalidCredentials(&'static str),
+}
+
+/// An access method read by [`decode`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedAccessMethod {
+    pub name: String,
+    pub access_method: CustomAccessMethod,
+}
+
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EncodedV1 {
+    version: u32,
+    name: String,
+    access_method: CustomAccessMethod,
+}
+
+/// Encodes a custom access method as a string for [`decode`]. Shadowsocks proxies are encoded
+/// as `ss://` URIs.
+pub fn encode(setting: &AccessMethodSetting) -> Result<String, Error> {
+    let access_method = setting.as_custom().ok_or(Error::BuiltIn)?;
+    if let CustomAccessMethod::Socks5(Socks5::Local(_)) = access_method {
+        return Err(Error::LocalProxy);
+    }
+    if let CustomAccessMethod::Shadowsocks(shadowsocks) = access_method {
+        return Ok(encode_shadowsocks(&setting.name, shadowsocks));
+    }
+    let encoded = EncodedV1 {
+        version: VERSION,
+        name: setting.name.clone(),
+        access_method: access_method.clone(),
+    };
+    let json = serde_json::to_vec(&encoded).map_err(|error| Error::Malformed(error.to_string()))?;
+    Ok(format!(
+        "{SCHEME}{}",
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    ))
+}
+
+/// Decodes and validates a string created by [`encode`], or a Shadowsocks URI.
+pub fn decode(encoded: &str) -> Result<SharedAccessMethod, Error> {
+    let encoded = encoded.trim();
+    let shared = if let Some(data) = encoded.strip_prefix(SCHEME) {
+        decode_versioned(data)?
+    } else if let Some(uri) = encoded.strip_prefix(SHADOWSOCKS_SCHEME) {
+        decode_shadowsocks(uri)?
+    } else {
+        return Err(Error::UnknownScheme);
+    };
+    validate(&shared)?;
+    Ok(shared)
+}
+
+impl SharedAccessMethod {
+    /// Creates the setting which is added to the daemon settings. It is marked as imported so
+    /// that it can be told apart from access methods the user added, and is disabled until the
+    /// user enables it.
+    pub fn into_setting(self) -> AccessMethodSetting {
+        let mut setting =
+            AccessMethodSetting::new(self.name, false, AccessMethod::from(self.access_method));
+        setting.imported = true;
+        setting
+    }
+}
+
+fn decode_versioned(data: &str) -> Result<SharedAccessMethod, Error> {
+    let json = decode_base64(data)?;
+    // Check the version first, since later versions may have a different layout.
+    let Versioned { version } =
+        serde_json::from_slice(&json).map_err(|error| Error::Malformed(error.to_string()))?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let encoded: EncodedV1 =
+        serde_json::from_slice(&json).map_err(|error| Error::Malformed(error.to_string()))?;
+    Ok(SharedAccessMethod {
+        name: encoded.name,
+        access_method: encoded.access_method,
+    })
+}
+
+fn encode_shadowsocks(name: &str, shadowsocks: &Shadowsocks) -> String {
+    let userinfo = base64::encode_config(
+        format!("{}:{}", shadowsocks.cipher, shadowsocks.password),
+        base64::URL_SAFE_NO_PAD,
+    );
+    format!(
+        "{SHADOWSOCKS_SCHEME}{userinfo}@{}#{}",
+        shadowsocks.peer,
+        percent_encode(name)
+    )
+}
+
+fn decode_shadowsocks(uri: &str) -> Result<SharedAccessMethod, Error> {
+    let (uri, name) = match uri.split_once('#') {
+        Some((uri, tag)) => (uri, percent_decode(tag)?),
+        None => (uri, String::new()),
+    };
+    // The query only carries plugin options.
+    let uri = match uri.split_once('?') {
+        Some((_, query)) if query.split('&').any(|param| param.starts_with("plugin=")) => {
+            return Err(Error::UnsupportedPlugin);
+        }
+        Some((uri, _)) => uri,
+        None => uri,
+    };
+
+    let (userinfo, address) = match uri.rsplit_once('@') {
+        // SIP002: the user info is either base64 or percent-encoded.
+        Some((userinfo, address)) => {
+            let address = address.strip_suffix('/').unwrap_or(address).to_owned();
+            if userinfo.contains(':') || userinfo.contains('%') {
+                (percent_decode(userinfo)?, address)
+            } else {
+                (decode_base64_string(userinfo)?, address)
+            }
+        }
+        // The legacy format encodes everything but the tag.
+        None => {
+            let decoded = decode_base64_string(uri)?;
+            let (userinfo, address) = decoded
+                .rsplit_once('@')
+                .ok_or_else(|| Error::Malformed("missing proxy address".to_owned()))?;
+            (userinfo.to_owned(), address.to_owned())
+        }
+    };
+    let (cipher, password) = userinfo
+        .split_once(':')
+        .ok_or_else(|| Error::Malformed("missing Shadowsocks password".to_owned()))?;
+    let peer = parse_address(&address)?;
+
+    let name = if name.trim().is_empty() {
+        format!("Shadowsocks {peer}")
+    } else {
+        name
+    };
+    Ok(SharedAccessMethod {
+        name,
+        access_method: CustomAccessMethod::Shadowsocks(Shadowsocks::new(
+            peer,
+            cipher.to_owned(),
+            password.to_owned(),
+        )),
+    })
+}
+
+/// Parses `<ip>:<port>`, where IPv6 addresses are enclosed in brackets. Hostnames are rejected,
+/// since resolving them would have to be done without access to the API.
+fn parse_address(address: &str) -> Result<SocketAddr, Error> {
+    address
+        .parse()
+        .map_err(|_| Error::InvalidAddress(address.to_owned()))
+}
+
+fn validate(shared: &SharedAccessMethod) -> Result<(), Error> {
+    if shared.name.trim().is_empty() {
+        return Err(Error::EmptyName);
+    }
+    let peer = match &shared.access_method {
+        CustomAccessMethod::Shadowsocks(shadowsocks) => {
+            if !SHADOWSOCKS_CIPHERS.contains(&shadowsocks.cipher.as_str()) {
+                return Err(Error::UnsupportedCipher(shadowsocks.cipher.clone()));
+            }
+            shadowsocks.peer
+        }
+        CustomAccessMethod::Socks5(Socks5::Local(_)) => return Err(Error::LocalProxy),
+        CustomAccessMethod::Socks5(Socks5::Remote(remote)) => {
+            if let Some(auth) = &remote.authentication {
+                if auth.username.is_empty() {
+                    return Err(Error::InvalidCredentials("the username is empty"));
+                }
+                if auth.username.len() > MAX_SOCKS_CREDENTIAL_LEN
+                    || auth.password.len() > MAX_SOCKS_CREDENTIAL_LEN
+                {
+                    return Err(Error::InvalidCredentials(
+                        "SOCKS5 credentials are at most 255 bytes long",
+                    ));
+                }
+            }
+            remote.peer
+        }
+        CustomAccessMethod::HttpProxy(http) => {
+            if let Some(auth) = &http.authentication {
+                if auth.username.is_empty() {
+                    return Err(Error::InvalidCredentials("the username is empty"));
+                }
+                if auth.username.contains(':') {
+                    return Err(Error::InvalidCredentials("the username contains ':'"));
+                }
+            }
+            http.peer
+        }
+    };
+    if peer.port() == 0 || peer.ip().is_unspecified() {
+        return Err(Error::InvalidAddress(peer.to_string()));
+    }
+    Ok(())
+}
+
+/// Decodes base64 with or without padding, using either the standard or the URL-safe alphabet.
+fn decode_base64(data: &str) -> Result<Vec<u8>, Error> {
+    let data = data.trim_end_matches('=');
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD)
+        .or_else(|_| base64::decode_config(data, base64::STANDARD_NO_PAD))
+        .map_err(|_| Error::InvalidBase64)
+}
+
+fn decode_base64_string(data: &str) -> Result<String, Error> {
+    String::from_utf8(decode_base64(data)?)
+        .map_err(|_| Error::Malformed("the decoded data is not UTF-8".to_owned()))
+}
+
+/// Percent-encodes everything except unreserved characters (RFC 3986).
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(char::from(byte))
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Result<String, Error> {
+    let malformed = || Error::Malformed(format!("invalid percent-encoding in \"{s}\""));
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [
+                bytes.next().ok_or_else(malformed)?,
+                bytes.next().ok_or_else(malformed)?,
+            ];
+            let hex = std::str::from_utf8(&hex).map_err(|_| malformed())?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| malformed())?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| malformed())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::access_method::{
+        BuiltInAccessMethod, HttpProxy, HttpProxyAuth, Socks5Local, Socks5Remote, SocksAuth,
+    };
+
+    fn setting(name: &str, access_method: impl Into<AccessMethod>) -> AccessMethodSetting {
+        AccessMethodSetting::new(name.to_owned(), true, access_method.into())
+    }
+
+    fn all_methods() -> Vec<AccessMethodSetting> {
+        vec![
+            setting(
+                "Shadowsocks #1 (åäö)",
+                Shadowsocks::new(
+                    "192.0.2.1:443".parse().unwrap(),
+                    "aes-256-gcm".to_owned(),
+                    "p@ss:w#rd/?".to_owned(),
+                ),
+            ),
+            setting(
+                "Shadowsocks IPv6",
+                Shadowsocks::new(
+                    "[2001:db8::1]:8388".parse().unwrap(),
+                    "chacha20-ietf-poly1305".to_owned(),
+                    String::new(),
+                ),
+            ),
+            setting(
+                "Remote SOCKS5",
+                Socks5Remote::new("198.51.100.7:1080".parse().unwrap()),
+            ),
+            setting(
+                "Remote SOCKS5 with credentials",
+                Socks5Remote {
+                    peer: "[2001:db8::3]:1080".parse().unwrap(),
+                    authentication: Some(SocksAuth {
+                        username: "user name".to_owned(),
+                        password: "pässword\"{}".to_owned(),
+                    }),
+                },
+            ),
+            setting(
+                "HTTP proxy",
+                HttpProxy::new("203.0.113.9:3128".parse().unwrap(), None),
+            ),
+            setting(
+                "HTTP proxy with credentials",
+                HttpProxy::new(
+                    "[2001:db8::4]:8080".parse().unwrap(),
+                    Some(HttpProxyAuth {
+                        username: "user".to_owned(),
+                        password: "secret:with:colons".to_owned(),
+                    }),
+                ),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for setting in all_methods() {
+            let encoded = encode(&setting).unwrap();
+            let expected_scheme = match setting.as_custom().unwrap() {
+                CustomAccessMethod::Shadowsocks(_) => SHADOWSOCKS_SCHEME,
+                _ => SCHEME,
+            };
+            assert!(encoded.starts_with(expected_scheme), "{encoded}");
+            assert_eq!(
+                decode(&encoded).unwrap(),
+                SharedAccessMethod {
+                    name: setting.name.clone(),
+                    access_method: setting.as_custom().unwrap().clone(),
+                },
+                "{encoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_imported_setting() {
+        let shared = decode(&encode(&all_methods()[0]).unwrap()).unwrap();
+        let setting = shared.clone().into_setting();
+        assert!(setting.imported);
+        assert!(!setting.enabled);
+        assert_eq!(setting.name, shared.name);
+        assert_eq!(setting.as_custom(), Some(&shared.access_method));
+    }
+
+    #[test]
+    fn test_local_proxy_is_not_shared() {
+        let local = setting(
+            "Local SOCKS5",
+            Socks5Local::new("[2001:db8::2]:1080".parse().unwrap(), 1081),
+        );
+        assert_eq!(encode(&local), Err(Error::LocalProxy));
+
+        let json = serde_json::to_vec(&EncodedV1 {
+            version: VERSION,
+            name: local.name.clone(),
+            access_method: local.as_custom().unwrap().clone(),
+        })
+        .unwrap();
+        let encoded = format!(
+            "{SCHEME}{}",
+            base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+        );
+        assert_eq!(decode(&encoded), Err(Error::LocalProxy));
+    }
+
+    #[test]
+    fn test_built_in_is_not_exported() {
+        let direct = setting("Direct", BuiltInAccessMethod::Direct);
+        assert_eq!(encode(&direct), Err(Error::BuiltIn));
+    }
+
+    #[test]
+    fn test_shadowsocks_uris() {
+        // Examples from SIP002.
+        let sip002 = decode("ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888#Example1").unwrap();
+        assert_eq!(
+            sip002,
+            SharedAccessMethod {
+                name: "Example1".to_owned(),
+                access_method: CustomAccessMethod::Shadowsocks(Shadowsocks::new(
+                    "192.168.100.1:8888".parse().unwrap(),
+                    "aes-128-gcm".to_owned(),
+                    "test".to_owned(),
+                )),
+            }
+        );
+        let plain = decode("ss://aes-128-gcm:test@192.168.100.1:8888/#Example%201").unwrap();
+        assert_eq!(plain.name, "Example 1");
+        assert_eq!(plain.access_method, sip002.access_method);
+
+        // Legacy format, with standard base64 and padding.
+        let legacy = decode(&format!(
+            "ss://{}#legacy",
+            base64::encode("aes-128-gcm:test@[2001:db8::1]:8888")
+        ))
+        .unwrap();
+        assert_eq!(legacy.name, "legacy");
+        assert_eq!(
+            legacy.access_method,
+            CustomAccessMethod::Shadowsocks(Shadowsocks::new(
+                "[2001:db8::1]:8888".parse().unwrap(),
+                "aes-128-gcm".to_owned(),
+                "test".to_owned(),
+            ))
+        );
+
+        let unnamed = decode("ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888").unwrap();
+        assert_eq!(unnamed.name, "Shadowsocks 192.168.100.1:8888");
+    }
+
+    #[test]
+    fn test_invalid_shadowsocks_uris() {
+        assert_eq!(
+            decode("ss://YmYtY2ZiOnRlc3Q@192.168.100.1:8888#Example2"),
+            Err(Error::UnsupportedCipher("bf-cfb".to_owned()))
+        );
+        assert_eq!(
+            decode("ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888/?plugin=obfs-local#Example3"),
+            Err(Error::UnsupportedPlugin)
+        );
+        assert_eq!(
+            decode("ss://YWVzLTEyOC1nY206dGVzdA@example.com:8888"),
+            Err(Error::InvalidAddress("example.com:8888".to_owned()))
+        );
+        assert_eq!(
+            decode("ss://YWVzLTEyOC1nY206dGVzdA@2001:db8::1:8888"),
+            Err(Error::InvalidAddress("2001:db8::1:8888".to_owned()))
+        );
+        assert_eq!(
+            decode("ss://YWVzLTEyOC1nY206dGVzdA@0.0.0.0:8888"),
+            Err(Error::InvalidAddress("0.0.0.0:8888".to_owned()))
+        );
+        assert_eq!(
+            decode("ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:0"),
+            Err(Error::InvalidAddress("192.168.100.1:0".to_owned()))
+        );
+        assert_eq!(
+            decode("ss://not*base64@192.168.100.1:8888"),
+            Err(Error::InvalidBase64)
+        );
+        assert!(matches!(
+            decode("ss://aes-128-gcm:%ZZ@192.168.100.1:8888"),
+            Err(Error::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let encoded = |json: &str| {
+            format!(
+                "{SCHEME}{}",
+                base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+            )
+        };
+        assert_eq!(
+            decode(&encoded(r#"{"version":2,"something":"new"}"#)),
+            Err(Error::UnsupportedVersion(2))
+        );
+        assert!(matches!(
+            decode(&encoded(r#"{"name":"no version"}"#)),
+            Err(Error::Malformed(_))
+        ));
+        assert!(matches!(
+            decode(&encoded(
+                r#"{"version":1,"name":"x","access_method":{},"extra":true}"#
+            )),
+            Err(Error::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_strings() {
+        assert_eq!(decode("https://mullvad.net"), Err(Error::UnknownScheme));
+        assert_eq!(decode(""), Err(Error::UnknownScheme));
+        assert_eq!(decode(&format!("{SCHEME}!!!")), Err(Error::InvalidBase64));
+
+        let mut unnamed = all_methods().remove(3);
+        unnamed.name = " ".to_owned();
+        assert_eq!(decode(&encode(&unnamed).unwrap()), Err(Error::EmptyName));
+
+        let no_username = setting(
+            "No username",
+            HttpProxy::new(
+                "203.0.113.9:3128".parse().unwrap(),
+                Some(HttpProxyAuth {
+                    username: String::new(),
+                    password: "secret".to_owned(),
+                }),
+            ),
+        );
+        assert!(matches!(
+            decode(&encode(&no_username).unwrap()),
+            Err(Error::InvalidCredentials(_))
+        ));
+    }
+}
@@ -2,6 +2,7 @@ use chrono::{offset::Utc, DateTime};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// Identifier used to identify a Mullvad account.
 pub type AccountToken = String;
@@ -39,6 +40,34 @@ pub struct VoucherSubmission {
     pub new_expiry: DateTime<Utc>,
 }
 
+/// An account that has been used on this device, and that can be switched back to.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SavedAccount {
+    pub account_token: AccountToken,
+    /// Name that the user gave the account.
+    pub label: Option<String>,
+    /// Name of the device that the account was last used with.
+    pub device_name: Option<String>,
+}
+
+impl SavedAccount {
+    pub fn new(account_token: AccountToken) -> Self {
+        SavedAccount {
+            account_token,
+            label: None,
+            device_name: None,
+        }
+    }
+}
+
+/// Emitted when the account expires within one of the thresholds in the settings.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AccountExpiryWarning {
+    pub expiry: DateTime<Utc>,
+    /// The threshold that was passed.
+    pub threshold: Duration,
+}
+
 /// Token used for authentication in the API.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct AccessTokenData {
@@ -0,0 +1,81 @@
+use crate::dns_leak::{DnsLeakTestResult, DnsLeakVerdict};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Where traffic leaves for the internet, as observed by the Mullvad connection check endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionCheck {
+    /// Whether the tunnel was connected when the check was made.
+    pub connected: bool,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub country: String,
+    pub city: Option<String>,
+    /// Whether all observed exit IPs belong to Mullvad relays.
+    pub mullvad_exit_ip: bool,
+    /// Hostname of the relay that the exit IP belongs to, if it is a Mullvad relay.
+    pub exit_relay: Option<String>,
+    /// Whether the exit IPs belong to the relay that the tunnel is connected to. `None` if the
+    /// tunnel is not connected, or if the relay could not be identified.
+    pub via_connected_relay: Option<bool>,
+    /// Result of a DNS leak test, which is only run while connected. `None` if it was not run or
+    /// if it failed.
+    pub dns_leak_test: Option<DnsLeakTestResult>,
+}
+
+impl ConnectionCheck {
+    /// Returns true if the tunnel is connected, but traffic or DNS lookups are observed to not go
+    /// through it.
+    pub fn bypasses_tunnel(&self) -> bool {
+        let dns_leaking = self
+            .dns_leak_test
+            .as_ref()
+            .map(|result| result.verdict == DnsLeakVerdict::Leaking)
+            .unwrap_or(false);
+        self.connected
+            && (!self.mullvad_exit_ip || self.via_connected_relay == Some(false) || dns_leaking)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(connected: bool, mullvad_exit_ip: bool) -> ConnectionCheck {
+        ConnectionCheck {
+            connected,
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ipv6: None,
+            country: "Sweden".to_owned(),
+            city: Some("Gothenburg".to_owned()),
+            mullvad_exit_ip,
+            exit_relay: None,
+            via_connected_relay: None,
+            dns_leak_test: None,
+        }
+    }
+
+    #[test]
+    fn test_bypasses_tunnel() {
+        assert!(!check(true, true).bypasses_tunnel());
+        assert!(check(true, false).bypasses_tunnel());
+        // Not going through Mullvad is expected while disconnected.
+        assert!(!check(false, false).bypasses_tunnel());
+
+        let other_relay = ConnectionCheck {
+            via_connected_relay: Some(false),
+            ..check(true, true)
+        };
+        assert!(other_relay.bypasses_tunnel());
+
+        let dns_leak = ConnectionCheck {
+            dns_leak_test: Some(DnsLeakTestResult {
+                verdict: DnsLeakVerdict::Leaking,
+                observed_resolvers: vec![],
+                leaking_resolvers: vec![],
+            }),
+            ..check(true, true)
+        };
+        assert!(dns_leak.bypasses_tunnel());
+    }
+}
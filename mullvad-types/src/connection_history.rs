@@ -0,0 +1,206 @@
+//! Record of the most recent connections and how they ended, kept for troubleshooting.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use talpid_types::net::{ObfuscationType, TransportProtocol, TunnelType};
+
+/// Number of connections that are kept.
+pub const CAPACITY: usize = 50;
+
+/// A connection, from its first attempt until it ended. Retries that happen before the tunnel is
+/// up belong to the same connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Connection {
+    /// When the first attempt started.
+    pub started_at: DateTime<Utc>,
+    /// Number of attempts that were made.
+    pub attempts: u32,
+    /// Hostname of the relay that the latest attempt connected to.
+    pub relay: Option<String>,
+    /// Hostname of the entry relay of the latest attempt, when multihop is used.
+    pub entry_relay: Option<String>,
+    /// Parameters of the latest attempt.
+    pub parameters: ConnectionParameters,
+    /// When the tunnel was established, if it ever was.
+    pub connected_at: Option<DateTime<Utc>>,
+    /// When the connection ended, if it has.
+    pub ended_at: Option<DateTime<Utc>>,
+    /// How the connection ended. This is `None` while it is ongoing.
+    pub outcome: Option<ConnectionOutcome>,
+}
+
+impl Connection {
+    /// Returns how long it took to establish the tunnel, counting from the first attempt.
+    pub fn time_to_connect(&self) -> Option<chrono::Duration> {
+        self.connected_at
+            .map(|connected_at| connected_at - self.started_at)
+    }
+}
+
+/// Tunnel parameters of a connection attempt. Addresses are left out, since they would be
+/// redacted from problem reports anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionParameters {
+    pub tunnel_type: TunnelType,
+    pub protocol: TransportProtocol,
+    pub port: u16,
+    pub obfuscation: Option<ObfuscationType>,
+    pub quantum_resistant: bool,
+}
+
+/// The reason that a connection ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "reason")]
+pub enum ConnectionOutcome {
+    /// The tunnel was disconnected on request.
+    Disconnected,
+    /// The tunnel was replaced on request, e.g. because the settings changed.
+    Reconnected,
+    /// The tunnel went down and another one was started.
+    ConnectionLost,
+    /// The host lost its connection to the internet.
+    Offline,
+    /// The tunnel entered the error state. `description` has been redacted like the logs in a
+    /// problem report.
+    Error { code: u32, description: String },
+    /// The daemon stopped before the connection ended.
+    Interrupted,
+}
+
+impl fmt::Display for ConnectionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionOutcome::Disconnected => f.write_str("disconnected"),
+            ConnectionOutcome::Reconnected => f.write_str("reconnected"),
+            ConnectionOutcome::ConnectionLost => f.write_str("connection lost"),
+            ConnectionOutcome::Offline => f.write_str("offline"),
+            ConnectionOutcome::Error { code, description } => {
+                match talpid_types::tunnel::ErrorCode::from_code(*code) {
+                    Some(code) => write!(f, "error {code}: {description}"),
+                    None => write!(f, "error ({code}): {description}"),
+                }
+            }
+            ConnectionOutcome::Interrupted => f.write_str("interrupted"),
+        }
+    }
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parameters = &self.parameters;
+        write!(
+            f,
+            "{} {}",
+            self.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.relay.as_deref().unwrap_or("unknown relay"),
+        )?;
+        if let Some(entry_relay) = &self.entry_relay {
+            write!(f, " via {entry_relay}")?;
+        }
+        write!(
+            f,
+            " - {} {}/{}",
+            parameters.tunnel_type, parameters.port, parameters.protocol
+        )?;
+        if let Some(obfuscation) = parameters.obfuscation {
+            write!(f, ", {obfuscation}")?;
+        }
+        if parameters.quantum_resistant {
+            write!(f, ", quantum resistant")?;
+        }
+        write!(
+            f,
+            ", {} attempt{}",
+            self.attempts,
+            if self.attempts == 1 { "" } else { "s" }
+        )?;
+        if let Some(time_to_connect) = self.time_to_connect() {
+            write!(
+                f,
+                ", connected in {:.1} s",
+                time_to_connect.num_milliseconds() as f64 / 1000.0
+            )?;
+        }
+        match (&self.outcome, self.ended_at) {
+            (Some(outcome), Some(ended_at)) => write!(
+                f,
+                ", ended {}: {outcome}",
+                ended_at.to_rfc3339_opts(SecondsFormat::Millis, true)
+            ),
+            (Some(outcome), None) => write!(f, ", ended: {outcome}"),
+            (None, _) => write!(f, ", ongoing"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn connection() -> Connection {
+        let started_at = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        Connection {
+            started_at,
+            attempts: 2,
+            relay: Some("se-got-wg-001".to_owned()),
+            entry_relay: None,
+            parameters: ConnectionParameters {
+                tunnel_type: TunnelType::Wireguard,
+                protocol: TransportProtocol::Udp,
+                port: 51820,
+                obfuscation: None,
+                quantum_resistant: false,
+            },
+            connected_at: Some(started_at + chrono::Duration::milliseconds(2500)),
+            ended_at: Some(started_at + chrono::Duration::minutes(30)),
+            outcome: Some(ConnectionOutcome::Error {
+                code: 10,
+                description: "Failed to start the tunnel".to_owned(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_serialization() {
+        let json = serde_json::to_value(connection()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "started_at": "2024-03-01T12:00:00Z",
+                "attempts": 2,
+                "relay": "se-got-wg-001",
+                "entry_relay": null,
+                "parameters": {
+                    "tunnel_type": "wireguard",
+                    "protocol": "udp",
+                    "port": 51820,
+                    "obfuscation": null,
+                    "quantum_resistant": false,
+                },
+                "connected_at": "2024-03-01T12:00:02.500Z",
+                "ended_at": "2024-03-01T12:30:00Z",
+                "outcome": {
+                    "reason": "error",
+                    "code": 10,
+                    "description": "Failed to start the tunnel",
+                },
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<Connection>(json).unwrap(),
+            connection()
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            connection().to_string(),
+            "2024-03-01T12:00:00.000Z se-got-wg-001 - WireGuard 51820/UDP, 2 attempts, \
+             connected in 2.5 s, ended 2024-03-01T12:30:00.000Z: error start_tunnel_failed (10): \
+             Failed to start the tunnel"
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// A resolver that was observed by the DNS leak test zone, as returned by the API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObservedResolver {
+    pub ip: IpAddr,
+    /// Whether the resolver belongs to Mullvad.
+    pub mullvad_dns: bool,
+    /// Name of the organization that operates the resolver, if known.
+    #[serde(default)]
+    pub organization: Option<String>,
+}
+
+/// Outcome of a DNS leak test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsLeakVerdict {
+    /// All observed resolvers were reached through the tunnel.
+    NoLeak,
+    /// At least one lookup was handled by a resolver outside the tunnel.
+    Leaking,
+    /// The test could not determine whether DNS is leaking.
+    Inconclusive,
+}
+
+/// Result of a DNS leak test.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsLeakTestResult {
+    pub verdict: DnsLeakVerdict,
+    /// All resolvers observed by the test zone.
+    pub observed_resolvers: Vec<ObservedResolver>,
+    /// Resolvers that handled lookups outside the tunnel.
+    pub leaking_resolvers: Vec<IpAddr>,
+}
@@ -0,0 +1,525 @@
+//! Indicators of settings and tunnel parameters that affect the connection, so that frontends can
+//! show which features are in effect, and how the tunnel is reached.
+
+use crate::{
+    relay_constraints::{Constraint, RelaySettings, SelectedObfuscation},
+    settings::{DnsOptions, DnsState, Settings},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use talpid_types::net::{proxy::ProxyType, Endpoint, ObfuscationType, TunnelEndpoint, TunnelType};
+#[cfg(target_os = "linux")]
+use talpid_types::split_tunnel::SplitTunnelMode;
+
+/// A feature that is in effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureIndicator {
+    BlockAds,
+    BlockTrackers,
+    BlockMalware,
+    BlockAdultContent,
+    BlockGambling,
+    BlockSocialMedia,
+    CustomDns,
+    BypassRoutes,
+    InverseSplitTunneling,
+    ExcludedDestinations,
+    ExcludedAppsAllowLan,
+    Udp2TcpObfuscation,
+    QuicObfuscation,
+    TlsObfuscation,
+    /// OpenVPN traffic is sent through a bridge.
+    Bridge,
+    /// WireGuard traffic enters through another relay than the one it exits from.
+    Multihop,
+    /// The obfuscation settings of a network profile are used instead of the global ones. This
+    /// depends on the current network, so it is not computed from the settings.
+    NetworkObfuscationProfile,
+    /// The tunnel protocol is automatic, and OpenVPN is used because WireGuard could not connect.
+    OpenVpnFallback,
+}
+
+impl fmt::Display for FeatureIndicator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let feature = match self {
+            FeatureIndicator::BlockAds => "Block ads",
+            FeatureIndicator::BlockTrackers => "Block trackers",
+            FeatureIndicator::BlockMalware => "Block malware",
+            FeatureIndicator::BlockAdultContent => "Block adult content",
+            FeatureIndicator::BlockGambling => "Block gambling",
+            FeatureIndicator::BlockSocialMedia => "Block social media",
+            FeatureIndicator::CustomDns => "Custom DNS",
+            FeatureIndicator::BypassRoutes => "Bypass routes",
+            FeatureIndicator::InverseSplitTunneling => "Inverse split tunneling",
+            FeatureIndicator::ExcludedDestinations => "Excluded destinations",
+            FeatureIndicator::ExcludedAppsAllowLan => "Local network sharing for excluded apps",
+            FeatureIndicator::Udp2TcpObfuscation => "Udp2Tcp obfuscation",
+            FeatureIndicator::QuicObfuscation => "QUIC obfuscation",
+            FeatureIndicator::TlsObfuscation => "TLS obfuscation",
+            FeatureIndicator::Bridge => "Bridge",
+            FeatureIndicator::Multihop => "Multihop",
+            FeatureIndicator::NetworkObfuscationProfile => "Network obfuscation profile",
+            FeatureIndicator::OpenVpnFallback => "OpenVPN fallback",
+        };
+        f.write_str(feature)
+    }
+}
+
+/// How the first hop of a tunnel is reached. This is the only host that the local network sees
+/// traffic being sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FirstHopKind {
+    /// The relay that the tunnel exits from is connected to directly.
+    Relay,
+    /// The entry relay of a multihop tunnel is connected to directly.
+    EntryRelay,
+    /// The relay is connected to through a bridge.
+    Bridge(ProxyType),
+    /// The relay is connected to through an obfuscator.
+    Obfuscator(ObfuscationType),
+}
+
+impl fmt::Display for FirstHopKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FirstHopKind::Relay => f.write_str("relay"),
+            FirstHopKind::EntryRelay => f.write_str("entry relay"),
+            FirstHopKind::Bridge(proxy_type) => write!(f, "{proxy_type} bridge"),
+            FirstHopKind::Obfuscator(obfuscation_type) => {
+                write!(f, "{obfuscation_type} obfuscation")
+            }
+        }
+    }
+}
+
+/// The first host that traffic of a tunnel is sent to, and how it is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FirstHop {
+    pub endpoint: Endpoint,
+    pub kind: FirstHopKind,
+}
+
+impl fmt::Display for FirstHop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.endpoint, self.kind)
+    }
+}
+
+/// Returns the first hop of the tunnel to `endpoint`. An obfuscator or bridge is connected to
+/// before any relay, and the entry relay of a multihop tunnel before the exit relay. An obfuscator
+/// that connects through an upstream proxy only sends traffic to the proxy.
+pub fn first_hop(endpoint: &TunnelEndpoint) -> FirstHop {
+    if let Some(obfuscation) = &endpoint.obfuscation {
+        FirstHop {
+            endpoint: obfuscation.upstream_proxy.unwrap_or(obfuscation.endpoint),
+            kind: FirstHopKind::Obfuscator(obfuscation.obfuscation_type),
+        }
+    } else if let Some(proxy) = &endpoint.proxy {
+        FirstHop {
+            endpoint: proxy.endpoint,
+            kind: FirstHopKind::Bridge(proxy.proxy_type),
+        }
+    } else if let Some(entry_endpoint) = endpoint.entry_endpoint {
+        FirstHop {
+            endpoint: entry_endpoint,
+            kind: FirstHopKind::EntryRelay,
+        }
+    } else {
+        FirstHop {
+            endpoint: endpoint.endpoint,
+            kind: FirstHopKind::Relay,
+        }
+    }
+}
+
+/// Returns the features that are in effect given `settings`, in a stable order. If the tunnel is
+/// connected to `endpoint`, the obfuscation, bridge and multihop indicators are computed from it,
+/// since the settings only say what may be used. Otherwise, an indicator is shown for the
+/// obfuscation types that are always used when selected.
+pub fn compute_feature_indicators(
+    settings: &Settings,
+    endpoint: Option<&TunnelEndpoint>,
+) -> Vec<FeatureIndicator> {
+    let mut features = dns_feature_indicators(&settings.tunnel_options.dns_options);
+    if !settings.bypass_routes.is_empty() {
+        features.push(FeatureIndicator::BypassRoutes);
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // Most traffic is sent outside the tunnel while connected. Lockdown mode still blocks all
+        // traffic in the other states, so the indicator is shown whether or not it is enabled
+        if settings.split_tunnel.mode == SplitTunnelMode::Include {
+            features.push(FeatureIndicator::InverseSplitTunneling);
+        }
+        if !settings.split_tunnel.destinations.is_empty() {
+            features.push(FeatureIndicator::ExcludedDestinations);
+        }
+        // Excluded apps can reach the local network even though other apps cannot. Processes
+        // that are excluded at runtime, such as those launched with mullvad-exclude, are not
+        // counted
+        if !settings.allow_lan
+            && settings.split_tunnel.excluded_apps_allow_lan
+            && excludes_apps(settings)
+        {
+            features.push(FeatureIndicator::ExcludedAppsAllowLan);
+        }
+    }
+    match endpoint {
+        Some(endpoint) => {
+            features.extend(tunnel_feature_indicators(endpoint));
+            // WireGuard is always tried first when the tunnel protocol is automatic
+            let automatic_protocol = matches!(
+                &settings.relay_settings,
+                RelaySettings::Normal(constraints) if constraints.tunnel_protocol == Constraint::Any
+            );
+            if automatic_protocol && endpoint.tunnel_type == TunnelType::OpenVpn {
+                features.push(FeatureIndicator::OpenVpnFallback);
+            }
+        }
+        None => match settings.obfuscation_settings.selected_obfuscation {
+            SelectedObfuscation::Quic => features.push(FeatureIndicator::QuicObfuscation),
+            SelectedObfuscation::Tls => features.push(FeatureIndicator::TlsObfuscation),
+            _ => (),
+        },
+    }
+    features
+}
+
+/// Returns the features that are in effect on the tunnel to `endpoint`.
+pub fn tunnel_feature_indicators(endpoint: &TunnelEndpoint) -> Vec<FeatureIndicator> {
+    let mut features = vec![];
+    if let Some(obfuscation) = &endpoint.obfuscation {
+        features.push(match obfuscation.obfuscation_type {
+            ObfuscationType::Udp2Tcp => FeatureIndicator::Udp2TcpObfuscation,
+            ObfuscationType::Quic => FeatureIndicator::QuicObfuscation,
+            ObfuscationType::Tls => FeatureIndicator::TlsObfuscation,
+        });
+    }
+    if endpoint.proxy.is_some() {
+        features.push(FeatureIndicator::Bridge);
+    }
+    if endpoint.entry_endpoint.is_some() {
+        features.push(FeatureIndicator::Multihop);
+    }
+    features
+}
+
+/// Returns whether `settings` select any apps to be sent outside the tunnel. In include mode, most
+/// traffic is sent outside the tunnel, which is already shown by its own indicator.
+pub(crate) fn excludes_apps(settings: &Settings) -> bool {
+    #[cfg(windows)]
+    {
+        settings.split_tunnel.enable_exclusions && !settings.split_tunnel.apps.is_empty()
+    }
+    #[cfg(target_os = "linux")]
+    {
+        settings.split_tunnel.mode == SplitTunnelMode::Exclude
+            && (!settings.split_tunnel.users.is_empty() || !settings.split_tunnel.groups.is_empty())
+    }
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        let _ = settings;
+        false
+    }
+}
+
+/// Returns the features that are in effect given `options`. The content blockers are only used
+/// together with the default DNS servers, so they are ignored when custom DNS is enabled.
+pub fn dns_feature_indicators(options: &DnsOptions) -> Vec<FeatureIndicator> {
+    match options.state {
+        DnsState::Custom => vec![FeatureIndicator::CustomDns],
+        DnsState::Default => {
+            let blockers = &options.default_options;
+            [
+                (blockers.block_ads, FeatureIndicator::BlockAds),
+                (blockers.block_trackers, FeatureIndicator::BlockTrackers),
+                (blockers.block_malware, FeatureIndicator::BlockMalware),
+                (
+                    blockers.block_adult_content,
+                    FeatureIndicator::BlockAdultContent,
+                ),
+                (blockers.block_gambling, FeatureIndicator::BlockGambling),
+                (
+                    blockers.block_social_media,
+                    FeatureIndicator::BlockSocialMedia,
+                ),
+            ]
+            .into_iter()
+            .filter_map(|(enabled, feature)| enabled.then_some(feature))
+            .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::DefaultDnsOptions;
+    #[cfg(target_os = "linux")]
+    use crate::settings::SplitTunnelSettings;
+    use talpid_types::net::{proxy::ProxyEndpoint, ObfuscationEndpoint, TransportProtocol};
+    #[cfg(target_os = "linux")]
+    use talpid_types::split_tunnel::{ExcludedDestination, PortRange};
+
+    #[test]
+    fn test_dns_feature_indicators() {
+        let mut options = DnsOptions {
+            default_options: DefaultDnsOptions {
+                block_ads: true,
+                block_gambling: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            dns_feature_indicators(&options),
+            vec![FeatureIndicator::BlockAds, FeatureIndicator::BlockGambling]
+        );
+
+        options.state = DnsState::Custom;
+        assert_eq!(
+            dns_feature_indicators(&options),
+            vec![FeatureIndicator::CustomDns]
+        );
+
+        assert!(dns_feature_indicators(&DnsOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_bypass_routes_feature_indicator() {
+        let mut settings = Settings::default();
+        assert!(compute_feature_indicators(&settings, None).is_empty());
+
+        settings.bypass_routes = vec!["10.10.0.0/16".parse().unwrap()];
+        settings.tunnel_options.dns_options.state = DnsState::Custom;
+        assert_eq!(
+            compute_feature_indicators(&settings, None),
+            vec![FeatureIndicator::CustomDns, FeatureIndicator::BypassRoutes]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_inverse_split_tunneling_feature_indicator() {
+        let settings = Settings {
+            split_tunnel: SplitTunnelSettings {
+                mode: SplitTunnelMode::Include,
+                ..Default::default()
+            },
+            block_when_disconnected: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            compute_feature_indicators(&settings, None),
+            vec![FeatureIndicator::InverseSplitTunneling]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_excluded_destinations_feature_indicator() {
+        let settings = Settings {
+            split_tunnel: SplitTunnelSettings {
+                destinations: vec![ExcludedDestination {
+                    network: "10.0.0.0/8".parse().unwrap(),
+                    ports: Some(PortRange::new(445, 445).unwrap()),
+                    protocol: TransportProtocol::Tcp,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            compute_feature_indicators(&settings, None),
+            vec![FeatureIndicator::ExcludedDestinations]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_excluded_apps_allow_lan_feature_indicator() {
+        let mut settings = Settings {
+            split_tunnel: SplitTunnelSettings {
+                users: vec![1001],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            compute_feature_indicators(&settings, None),
+            vec![FeatureIndicator::ExcludedAppsAllowLan]
+        );
+
+        // The indicator is only shown when it makes a difference
+        settings.allow_lan = true;
+        assert!(compute_feature_indicators(&settings, None).is_empty());
+        settings.allow_lan = false;
+        settings.split_tunnel.excluded_apps_allow_lan = false;
+        assert!(compute_feature_indicators(&settings, None).is_empty());
+    }
+
+    #[test]
+    fn test_obfuscation_feature_indicators() {
+        let mut settings = Settings::default();
+        settings.obfuscation_settings.selected_obfuscation = SelectedObfuscation::Quic;
+        assert_eq!(
+            compute_feature_indicators(&settings, None),
+            vec![FeatureIndicator::QuicObfuscation]
+        );
+        settings.obfuscation_settings.selected_obfuscation = SelectedObfuscation::Tls;
+        assert_eq!(
+            compute_feature_indicators(&settings, None),
+            vec![FeatureIndicator::TlsObfuscation]
+        );
+    }
+
+    fn wireguard_endpoint() -> TunnelEndpoint {
+        TunnelEndpoint {
+            endpoint: Endpoint::new([185, 213, 154, 68], 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            quantum_resistant: false,
+            proxy: None,
+            obfuscation: None,
+            entry_endpoint: None,
+            tunnel_interface: None,
+        }
+    }
+
+    #[test]
+    fn test_tunnel_feature_indicators() {
+        let settings = Settings::default();
+
+        let plain = wireguard_endpoint();
+        assert!(compute_feature_indicators(&settings, Some(&plain)).is_empty());
+        assert_eq!(
+            first_hop(&plain),
+            FirstHop {
+                endpoint: plain.endpoint,
+                kind: FirstHopKind::Relay,
+            }
+        );
+
+        let obfuscator = Endpoint::new([185, 213, 154, 68], 443, TransportProtocol::Tcp);
+        let udp2tcp = TunnelEndpoint {
+            obfuscation: Some(ObfuscationEndpoint {
+                endpoint: obfuscator,
+                obfuscation_type: ObfuscationType::Udp2Tcp,
+                upstream_proxy: None,
+            }),
+            ..wireguard_endpoint()
+        };
+        assert_eq!(
+            compute_feature_indicators(&settings, Some(&udp2tcp)),
+            vec![FeatureIndicator::Udp2TcpObfuscation]
+        );
+        assert_eq!(
+            first_hop(&udp2tcp).to_string(),
+            "185.213.154.68:443/TCP (Udp2Tcp obfuscation)"
+        );
+
+        let bridge = Endpoint::new([185, 65, 134, 115], 443, TransportProtocol::Tcp);
+        let openvpn = TunnelEndpoint {
+            endpoint: Endpoint::new([185, 213, 154, 70], 1194, TransportProtocol::Tcp),
+            tunnel_type: TunnelType::OpenVpn,
+            proxy: Some(ProxyEndpoint {
+                endpoint: bridge,
+                proxy_type: ProxyType::Shadowsocks,
+            }),
+            ..wireguard_endpoint()
+        };
+        assert_eq!(
+            compute_feature_indicators(&settings, Some(&openvpn)),
+            vec![FeatureIndicator::Bridge, FeatureIndicator::OpenVpnFallback]
+        );
+        assert_eq!(
+            first_hop(&openvpn),
+            FirstHop {
+                endpoint: bridge,
+                kind: FirstHopKind::Bridge(ProxyType::Shadowsocks),
+            }
+        );
+
+        let entry = Endpoint::new([185, 204, 1, 203], 51820, TransportProtocol::Udp);
+        let mut multihop = TunnelEndpoint {
+            entry_endpoint: Some(entry),
+            ..wireguard_endpoint()
+        };
+        assert_eq!(
+            compute_feature_indicators(&settings, Some(&multihop)),
+            vec![FeatureIndicator::Multihop]
+        );
+        assert_eq!(first_hop(&multihop).kind, FirstHopKind::EntryRelay);
+
+        // The obfuscator runs in front of the entry relay
+        multihop.obfuscation = udp2tcp.obfuscation;
+        assert_eq!(
+            compute_feature_indicators(&settings, Some(&multihop)),
+            vec![
+                FeatureIndicator::Udp2TcpObfuscation,
+                FeatureIndicator::Multihop
+            ]
+        );
+        assert_eq!(first_hop(&multihop).endpoint, obfuscator);
+
+        // Only the proxy is connected to when the obfuscator goes through one
+        let upstream_proxy = Endpoint::new([192, 168, 1, 10], 1080, TransportProtocol::Tcp);
+        let mut proxied = udp2tcp.clone();
+        proxied.obfuscation.as_mut().unwrap().upstream_proxy = Some(upstream_proxy);
+        assert_eq!(
+            first_hop(&proxied),
+            FirstHop {
+                endpoint: upstream_proxy,
+                kind: FirstHopKind::Obfuscator(ObfuscationType::Udp2Tcp),
+            }
+        );
+    }
+
+    #[test]
+    fn test_openvpn_fallback_feature_indicator() {
+        fn with_tunnel_protocol(tunnel_protocol: Constraint<TunnelType>) -> Settings {
+            let mut settings = Settings::default();
+            if let RelaySettings::Normal(constraints) = &mut settings.relay_settings {
+                constraints.tunnel_protocol = tunnel_protocol;
+            }
+            settings
+        }
+        let openvpn = TunnelEndpoint {
+            endpoint: Endpoint::new([185, 213, 154, 70], 443, TransportProtocol::Tcp),
+            tunnel_type: TunnelType::OpenVpn,
+            ..wireguard_endpoint()
+        };
+
+        let settings = with_tunnel_protocol(Constraint::Any);
+        assert_eq!(
+            compute_feature_indicators(&settings, Some(&openvpn)),
+            vec![FeatureIndicator::OpenVpnFallback]
+        );
+        assert!(compute_feature_indicators(&settings, Some(&wireguard_endpoint())).is_empty());
+
+        // OpenVPN is not a fallback if it has been selected
+        let settings = with_tunnel_protocol(Constraint::Only(TunnelType::OpenVpn));
+        assert!(compute_feature_indicators(&settings, Some(&openvpn)).is_empty());
+    }
+
+    #[test]
+    fn test_obfuscation_feature_indicators_follow_tunnel() {
+        // The selected mode is not shown once the tunnel parameters are known
+        let mut settings = Settings::default();
+        settings.obfuscation_settings.selected_obfuscation = SelectedObfuscation::Quic;
+        let endpoint = TunnelEndpoint {
+            obfuscation: Some(ObfuscationEndpoint {
+                endpoint: Endpoint::new([185, 213, 154, 68], 443, TransportProtocol::Udp),
+                obfuscation_type: ObfuscationType::Quic,
+                upstream_proxy: None,
+            }),
+            ..wireguard_endpoint()
+        };
+        assert_eq!(
+            compute_feature_indicators(&settings, Some(&endpoint)),
+            vec![FeatureIndicator::QuicObfuscation]
+        );
+        assert!(compute_feature_indicators(&settings, Some(&wireguard_endpoint())).is_empty());
+    }
+}
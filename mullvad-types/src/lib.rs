@@ -3,13 +3,25 @@
 pub mod access_method;
 pub mod account;
 pub mod auth_failed;
+pub mod connection_check;
+pub mod connection_history;
 pub mod custom_list;
 pub mod device;
+pub mod dns_leak;
 pub mod endpoint;
+pub mod features;
 pub mod location;
+pub mod metrics;
+pub mod network_profiles;
+pub mod network_trust;
+pub mod obfuscation_scores;
+pub mod problem_report;
+pub mod recent_events;
 pub mod relay_constraints;
 pub mod relay_list;
+pub mod routes;
 pub mod settings;
+pub mod split_tunnel;
 pub mod states;
 pub mod version;
 pub mod wireguard;
@@ -124,7 +124,7 @@ fn haversine_dist_rad(lat: f64, lon: f64, other_lat: f64, other_lon: f64) -> f64
 }
 
 /// The response from the am.i.mullvad.net location service.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct AmIMullvad {
     pub ip: IpAddr,
     pub country: String,
@@ -132,6 +132,9 @@ pub struct AmIMullvad {
     pub latitude: f64,
     pub longitude: f64,
     pub mullvad_exit_ip: bool,
+    /// Hostname of the relay that `ip` belongs to, if it is a Mullvad relay.
+    #[serde(default)]
+    pub mullvad_exit_ip_hostname: Option<String>,
 }
 
 /// GeoIP information exposed from the daemon to frontends.
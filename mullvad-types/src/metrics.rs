@@ -0,0 +1,110 @@
+//! Health metrics of the daemon, for monitoring many machines that run it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Whether a metric only ever grows, or can go up and down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+/// The value of a metric. Metrics with the same name are told apart by their labels.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metric {
+    pub name: String,
+    pub kind: MetricKind,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+impl Metric {
+    pub fn counter(name: &str, value: u64) -> Self {
+        Self::new(name, MetricKind::Counter, value as f64)
+    }
+
+    pub fn gauge(name: &str, value: f64) -> Self {
+        Self::new(name, MetricKind::Gauge, value)
+    }
+
+    fn new(name: &str, kind: MetricKind, value: f64) -> Self {
+        Metric {
+            name: name.to_owned(),
+            kind,
+            labels: vec![],
+            value,
+        }
+    }
+
+    pub fn with_label(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.labels.push((name.to_owned(), value.into()));
+        self
+    }
+
+    /// Returns the name of the metric followed by its labels, e.g. `name{label="value"}`.
+    pub fn series(&self) -> String {
+        if self.labels.is_empty() {
+            return self.name.clone();
+        }
+        let labels = self
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{name}=\"{}\"", escape_label_value(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("{}{{{labels}}}", self.name)
+    }
+}
+
+/// Returns `metrics` in the Prometheus text format, as read by the textfile collector of
+/// node_exporter. Metrics with the same name must be adjacent.
+pub fn to_prometheus(metrics: &[Metric]) -> String {
+    let mut text = String::new();
+    let mut previous_name = None;
+    for metric in metrics {
+        if previous_name != Some(&metric.name) {
+            let kind = match metric.kind {
+                MetricKind::Counter => "counter",
+                MetricKind::Gauge => "gauge",
+            };
+            let _ = writeln!(text, "# TYPE {} {kind}", metric.name);
+            previous_name = Some(&metric.name);
+        }
+        let _ = writeln!(text, "{} {}", metric.series(), metric.value);
+    }
+    text
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_format() {
+        let metrics = [
+            Metric::counter("mullvad_connects_total", 3),
+            Metric::gauge("mullvad_tunnel_state", 1.0).with_label("state", "connected"),
+            Metric::gauge("mullvad_tunnel_state", 0.0).with_label("state", "error"),
+            Metric::gauge("mullvad_relay_info", 1.0).with_label("hostname", "se-\"got\"\\1"),
+        ];
+        assert_eq!(
+            to_prometheus(&metrics),
+            "# TYPE mullvad_connects_total counter\n\
+             mullvad_connects_total 3\n\
+             # TYPE mullvad_tunnel_state gauge\n\
+             mullvad_tunnel_state{state=\"connected\"} 1\n\
+             mullvad_tunnel_state{state=\"error\"} 0\n\
+             # TYPE mullvad_relay_info gauge\n\
+             mullvad_relay_info{hostname=\"se-\\\"got\\\"\\\\1\"} 1\n"
+        );
+    }
+}
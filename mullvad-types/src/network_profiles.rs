@@ -0,0 +1,159 @@
+//! Obfuscation settings that are used instead of the global ones on specific networks.
+
+use crate::{relay_constraints::ObfuscationSettings, settings::Settings};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Identifies the network that the host is connected to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkId {
+    /// Name of the Wi-Fi network of the default route.
+    Ssid(String),
+    /// Name of the interface of the default route.
+    Interface(String),
+}
+
+impl fmt::Display for NetworkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkId::Ssid(ssid) => write!(f, "Wi-Fi network \"{ssid}\""),
+            NetworkId::Interface(interface) => write!(f, "interface {interface}"),
+        }
+    }
+}
+
+/// The network that the host is currently connected to, as far as it can be determined.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CurrentNetwork {
+    /// Interface of the default route.
+    pub interface: Option<String>,
+    /// SSID of the Wi-Fi network that `interface` is connected to. Only detected on Linux.
+    pub ssid: Option<String>,
+}
+
+impl CurrentNetwork {
+    /// Returns the most specific identifier of the network, which is the SSID if it is known.
+    pub fn id(&self) -> Option<NetworkId> {
+        self.ssid
+            .clone()
+            .map(NetworkId::Ssid)
+            .or_else(|| self.interface.clone().map(NetworkId::Interface))
+    }
+
+    fn matches(&self, network: &NetworkId) -> bool {
+        match network {
+            NetworkId::Ssid(ssid) => self.ssid.as_ref() == Some(ssid),
+            NetworkId::Interface(interface) => self.interface.as_ref() == Some(interface),
+        }
+    }
+}
+
+/// Returns the entry in `entries` whose network matches `network`. Entries that match the SSID
+/// take precedence over those that match the interface, since several Wi-Fi networks may be
+/// joined using the same interface. Otherwise, the first matching entry is used.
+pub fn select_network_entry<'a, T>(
+    entries: &'a [(NetworkId, T)],
+    network: &CurrentNetwork,
+) -> Option<&'a (NetworkId, T)> {
+    let matching = |ssid: bool| {
+        entries
+            .iter()
+            .find(|(id, _)| matches!(id, NetworkId::Ssid(_)) == ssid && network.matches(id))
+    };
+    matching(true).or_else(|| matching(false))
+}
+
+/// Returns the profile in `profiles` that matches `network`. See [`select_network_entry`].
+pub fn select_obfuscation_profile<'a>(
+    profiles: &'a [(NetworkId, ObfuscationSettings)],
+    network: &CurrentNetwork,
+) -> Option<&'a (NetworkId, ObfuscationSettings)> {
+    select_network_entry(profiles, network)
+}
+
+/// Returns the obfuscation settings to use on `network`, which are those of the matching profile
+/// if there is one, or the global obfuscation settings otherwise.
+pub fn effective_obfuscation_settings<'a>(
+    settings: &'a Settings,
+    network: &CurrentNetwork,
+) -> &'a ObfuscationSettings {
+    select_obfuscation_profile(&settings.network_obfuscation_profiles, network)
+        .map(|(_, obfuscation_settings)| obfuscation_settings)
+        .unwrap_or(&settings.obfuscation_settings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::relay_constraints::SelectedObfuscation;
+
+    fn obfuscation(selected_obfuscation: SelectedObfuscation) -> ObfuscationSettings {
+        ObfuscationSettings {
+            selected_obfuscation,
+            ..Default::default()
+        }
+    }
+
+    fn network(interface: &str, ssid: Option<&str>) -> CurrentNetwork {
+        CurrentNetwork {
+            interface: Some(interface.to_owned()),
+            ssid: ssid.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_profile_precedence() {
+        let profiles = vec![
+            (
+                NetworkId::Interface("wlan0".to_owned()),
+                obfuscation(SelectedObfuscation::Off),
+            ),
+            (
+                NetworkId::Ssid("eduroam".to_owned()),
+                obfuscation(SelectedObfuscation::Udp2Tcp),
+            ),
+            (
+                NetworkId::Interface("wlan0".to_owned()),
+                obfuscation(SelectedObfuscation::Auto),
+            ),
+        ];
+
+        // The SSID is preferred over an interface profile that comes first
+        let (id, _) =
+            select_obfuscation_profile(&profiles, &network("wlan0", Some("eduroam"))).unwrap();
+        assert_eq!(id, &NetworkId::Ssid("eduroam".to_owned()));
+
+        // The first matching interface profile is used if no SSID matches
+        let (_, selected) =
+            select_obfuscation_profile(&profiles, &network("wlan0", Some("home"))).unwrap();
+        assert_eq!(selected.selected_obfuscation, SelectedObfuscation::Off);
+
+        assert!(select_obfuscation_profile(&profiles, &network("eth0", None)).is_none());
+        assert!(select_obfuscation_profile(&profiles, &CurrentNetwork::default()).is_none());
+    }
+
+    #[test]
+    fn test_effective_obfuscation_settings() {
+        let settings = Settings {
+            obfuscation_settings: obfuscation(SelectedObfuscation::Auto),
+            network_obfuscation_profiles: vec![(
+                NetworkId::Ssid("eduroam".to_owned()),
+                obfuscation(SelectedObfuscation::Udp2Tcp),
+            )],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            effective_obfuscation_settings(&settings, &network("wlan0", Some("eduroam")))
+                .selected_obfuscation,
+            SelectedObfuscation::Udp2Tcp
+        );
+        // Falls back to the global settings
+        assert_eq!(
+            effective_obfuscation_settings(&settings, &network("wlan0", Some("home")))
+                .selected_obfuscation,
+            SelectedObfuscation::Auto
+        );
+    }
+}
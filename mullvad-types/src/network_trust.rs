@@ -0,0 +1,127 @@
+//! Rules for connecting and disconnecting automatically depending on the network that the host is
+//! connected to.
+
+use crate::network_profiles::{self, CurrentNetwork, NetworkId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Whether a network is trusted to carry traffic outside the tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustPolicy {
+    /// The tunnel is not connected automatically on the network, and is disconnected if
+    /// [`NetworkTrustSettings::disconnect_on_trusted`] is set.
+    Trusted,
+    /// The tunnel is connected automatically on the network.
+    Untrusted,
+}
+
+impl fmt::Display for TrustPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrustPolicy::Trusted => f.write_str("trusted"),
+            TrustPolicy::Untrusted => f.write_str("untrusted"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkTrustSettings {
+    /// Policies of specific networks. Rules are selected the same way as network obfuscation
+    /// profiles, see [`network_profiles::select_network_entry`].
+    pub rules: Vec<(NetworkId, TrustPolicy)>,
+    /// Policy of networks that no rule matches. If `None`, the tunnel is neither connected nor
+    /// disconnected automatically on those networks.
+    pub default_policy: Option<TrustPolicy>,
+    /// Whether to disconnect when joining a trusted network. Otherwise, trusted networks only
+    /// prevent the tunnel from being connected automatically.
+    pub disconnect_on_trusted: bool,
+    /// Whether lockdown mode keeps blocking traffic while disconnected on a trusted network.
+    pub lockdown_on_trusted: bool,
+}
+
+impl Default for NetworkTrustSettings {
+    fn default() -> Self {
+        NetworkTrustSettings {
+            rules: vec![],
+            default_policy: None,
+            disconnect_on_trusted: false,
+            lockdown_on_trusted: true,
+        }
+    }
+}
+
+impl NetworkTrustSettings {
+    /// Returns the policy of `network`, or `None` if it has no policy or cannot be identified,
+    /// e.g. because the host is offline.
+    pub fn policy(&self, network: &CurrentNetwork) -> Option<TrustPolicy> {
+        network.id()?;
+        network_profiles::select_network_entry(&self.rules, network)
+            .map(|(_, policy)| *policy)
+            .or(self.default_policy)
+    }
+
+    /// Returns whether lockdown mode should stop blocking traffic on `network`.
+    pub fn lifts_lockdown(&self, network: &CurrentNetwork) -> bool {
+        !self.lockdown_on_trusted && self.policy(network) == Some(TrustPolicy::Trusted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn network(interface: &str, ssid: Option<&str>) -> CurrentNetwork {
+        CurrentNetwork {
+            interface: Some(interface.to_owned()),
+            ssid: ssid.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        let mut settings = NetworkTrustSettings {
+            rules: vec![
+                (
+                    NetworkId::Interface("wlan0".to_owned()),
+                    TrustPolicy::Untrusted,
+                ),
+                (NetworkId::Ssid("HomeNet".to_owned()), TrustPolicy::Trusted),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            settings.policy(&network("wlan0", Some("HomeNet"))),
+            Some(TrustPolicy::Trusted)
+        );
+        assert_eq!(
+            settings.policy(&network("wlan0", Some("Cafe"))),
+            Some(TrustPolicy::Untrusted)
+        );
+        assert_eq!(settings.policy(&network("eth0", None)), None);
+
+        settings.default_policy = Some(TrustPolicy::Untrusted);
+        assert_eq!(
+            settings.policy(&network("eth0", None)),
+            Some(TrustPolicy::Untrusted)
+        );
+        // The default policy is not applied while offline
+        assert_eq!(settings.policy(&CurrentNetwork::default()), None);
+    }
+
+    #[test]
+    fn test_lifts_lockdown() {
+        let mut settings = NetworkTrustSettings {
+            rules: vec![(NetworkId::Ssid("HomeNet".to_owned()), TrustPolicy::Trusted)],
+            ..Default::default()
+        };
+        let home = network("wlan0", Some("HomeNet"));
+
+        assert!(!settings.lifts_lockdown(&home));
+        settings.lockdown_on_trusted = false;
+        assert!(settings.lifts_lockdown(&home));
+        assert!(!settings.lifts_lockdown(&network("wlan0", Some("Cafe"))));
+    }
+}
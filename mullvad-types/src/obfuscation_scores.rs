@@ -0,0 +1,305 @@
+//! Scores of how well each obfuscation type and port has worked on the networks that the host has
+//! been connected to. They are used to try the obfuscation types in auto mode in order of how
+//! likely they are to work.
+
+use crate::{network_profiles::NetworkId, relay_constraints::ObfuscationType};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+/// Number of networks that scores are kept for. When scores are recorded for another network, the
+/// scores of the least recently used network are dropped.
+pub const MAX_NETWORKS: usize = 32;
+/// Time after which an outcome counts half as much as when it was recorded.
+pub const SCORE_HALF_LIFE: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+/// Score of combinations that have no recorded outcomes.
+const NEUTRAL_SCORE: f64 = 0.5;
+
+/// Group of ports that outcomes are recorded for. Ports below 1024 are often treated specially by
+/// firewalls, so they are kept apart, while all other ports share a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortBucket {
+    WellKnown(u16),
+    High,
+}
+
+impl From<u16> for PortBucket {
+    fn from(port: u16) -> Self {
+        if port < 1024 {
+            PortBucket::WellKnown(port)
+        } else {
+            PortBucket::High
+        }
+    }
+}
+
+impl fmt::Display for PortBucket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortBucket::WellKnown(port) => write!(f, "port {port}"),
+            PortBucket::High => write!(f, "ports 1024 and up"),
+        }
+    }
+}
+
+/// Outcomes of connection attempts using an obfuscation type and port bucket. The counts decay
+/// over time, so that old outcomes matter less than recent ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObfuscationScore {
+    pub obfuscation_type: ObfuscationType,
+    pub port: PortBucket,
+    pub successes: f64,
+    pub failures: f64,
+    /// When the counts were last decayed.
+    pub updated: SystemTime,
+}
+
+impl ObfuscationScore {
+    fn new(obfuscation_type: ObfuscationType, port: PortBucket, now: SystemTime) -> Self {
+        ObfuscationScore {
+            obfuscation_type,
+            port,
+            successes: 0.0,
+            failures: 0.0,
+            updated: now,
+        }
+    }
+
+    /// Decays the counts to what they are at `now`.
+    fn decay(&mut self, now: SystemTime) {
+        let elapsed = now.duration_since(self.updated).unwrap_or_default();
+        let factor = 0.5f64.powf(elapsed.as_secs_f64() / SCORE_HALF_LIFE.as_secs_f64());
+        self.successes *= factor;
+        self.failures *= factor;
+        self.updated = now;
+    }
+
+    /// Returns the estimated probability that a connection attempt succeeds, between 0 and 1.
+    /// Without any outcomes, this is [`NEUTRAL_SCORE`].
+    pub fn score(&self, now: SystemTime) -> f64 {
+        let mut decayed = self.clone();
+        decayed.decay(now);
+        (decayed.successes + 1.0) / (decayed.successes + decayed.failures + 2.0)
+    }
+}
+
+/// Scores recorded on a network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkScores {
+    pub network: NetworkId,
+    pub scores: Vec<ObfuscationScore>,
+}
+
+/// Scores of the most recently used networks, most recently used first.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObfuscationScores {
+    pub networks: Vec<NetworkScores>,
+}
+
+impl ObfuscationScores {
+    /// Records the outcome of a connection attempt on `network` using `obfuscation_type` and
+    /// `port`. This makes `network` the most recently used network.
+    pub fn record(
+        &mut self,
+        network: &NetworkId,
+        obfuscation_type: ObfuscationType,
+        port: PortBucket,
+        success: bool,
+        now: SystemTime,
+    ) {
+        let network_scores = match self.networks.iter().position(|n| &n.network == network) {
+            Some(index) => self.networks.remove(index),
+            None => NetworkScores {
+                network: network.clone(),
+                scores: vec![],
+            },
+        };
+        self.networks.insert(0, network_scores);
+        self.networks.truncate(MAX_NETWORKS);
+
+        let scores = &mut self.networks[0].scores;
+        let score = match scores
+            .iter()
+            .position(|s| s.obfuscation_type == obfuscation_type && s.port == port)
+        {
+            Some(index) => &mut scores[index],
+            None => {
+                scores.push(ObfuscationScore::new(obfuscation_type, port, now));
+                scores.last_mut().unwrap()
+            }
+        };
+        score.decay(now);
+        if success {
+            score.successes += 1.0;
+        } else {
+            score.failures += 1.0;
+        }
+    }
+
+    /// Returns `priority` ordered by the best score of each obfuscation type on `network`, over
+    /// all ports. Types with equal scores, such as those without any outcomes, keep their order.
+    pub fn order(
+        &self,
+        network: &NetworkId,
+        priority: &[ObfuscationType],
+        now: SystemTime,
+    ) -> Vec<ObfuscationType> {
+        let scores = self
+            .networks
+            .iter()
+            .find(|n| &n.network == network)
+            .map(|n| &n.scores[..])
+            .unwrap_or(&[]);
+        let best_score = |obfuscation_type: &ObfuscationType| {
+            scores
+                .iter()
+                .filter(|score| &score.obfuscation_type == obfuscation_type)
+                .map(|score| score.score(now))
+                .reduce(f64::max)
+                .unwrap_or(NEUTRAL_SCORE)
+        };
+
+        let mut ordered = priority.to_vec();
+        // The sort is stable, so ties keep the configured order
+        ordered.sort_by(|a, b| best_score(b).total_cmp(&best_score(a)));
+        ordered
+    }
+
+    /// Returns the scores with all counts decayed to what they are at `now`.
+    pub fn decayed(&self, now: SystemTime) -> Self {
+        let mut decayed = self.clone();
+        for score in decayed
+            .networks
+            .iter_mut()
+            .flat_map(|network| network.scores.iter_mut())
+        {
+            score.decay(now);
+        }
+        decayed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn network(ssid: &str) -> NetworkId {
+        NetworkId::Ssid(ssid.to_owned())
+    }
+
+    #[test]
+    fn test_decay() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let mut scores = ObfuscationScores::default();
+        for _ in 0..4 {
+            scores.record(
+                &network("home"),
+                ObfuscationType::Udp2Tcp,
+                PortBucket::from(80),
+                true,
+                start,
+            );
+        }
+        let score = &scores.networks[0].scores[0];
+        assert_eq!(score.score(start), 5.0 / 6.0);
+
+        // After one half-life, the successes count half as much
+        assert_eq!(score.score(start + SCORE_HALF_LIFE), 3.0 / 4.0);
+        let decayed = scores.decayed(start + SCORE_HALF_LIFE);
+        assert_eq!(decayed.networks[0].scores[0].successes, 2.0);
+
+        // Old outcomes are eventually forgotten
+        let forgotten = score.score(start + 100 * SCORE_HALF_LIFE);
+        assert!((forgotten - NEUTRAL_SCORE).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let now = SystemTime::now();
+        let mut scores = ObfuscationScores::default();
+        let mut record = |ssid: &str| {
+            scores.record(
+                &network(ssid),
+                ObfuscationType::None,
+                PortBucket::High,
+                true,
+                now,
+            )
+        };
+        for i in 0..MAX_NETWORKS {
+            record(&i.to_string());
+        }
+        // Using the oldest network makes it the most recently used one
+        record("0");
+        record("new");
+
+        assert_eq!(scores.networks.len(), MAX_NETWORKS);
+        assert_eq!(scores.networks[0].network, network("new"));
+        assert_eq!(scores.networks[1].network, network("0"));
+        assert!(!scores.networks.iter().any(|n| n.network == network("1")));
+        // The outcomes of a network are kept when it is used again
+        assert_eq!(scores.networks[1].scores[0].successes, 2.0);
+    }
+
+    #[test]
+    fn test_order() {
+        let now = SystemTime::now();
+        let priority = [
+            ObfuscationType::None,
+            ObfuscationType::Udp2Tcp,
+            ObfuscationType::Quic,
+        ];
+        let mut scores = ObfuscationScores::default();
+        assert_eq!(scores.order(&network("office"), &priority, now), priority);
+
+        // Multiple ports of a type are tried, and the best one counts
+        let mut record = |obfuscation_type, port: u16, success| {
+            scores.record(
+                &network("office"),
+                obfuscation_type,
+                PortBucket::from(port),
+                success,
+                now,
+            )
+        };
+        record(ObfuscationType::None, 51820, false);
+        record(ObfuscationType::Udp2Tcp, 80, false);
+        record(ObfuscationType::Udp2Tcp, 5001, true);
+        assert_eq!(
+            scores.order(&network("office"), &priority, now),
+            [
+                ObfuscationType::Udp2Tcp,
+                ObfuscationType::Quic,
+                ObfuscationType::None,
+            ]
+        );
+
+        // Other networks are not affected
+        assert_eq!(scores.order(&network("home"), &priority, now), priority);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut scores = ObfuscationScores::default();
+        scores.record(
+            &NetworkId::Interface("eth0".to_owned()),
+            ObfuscationType::Quic,
+            PortBucket::from(443),
+            false,
+            SystemTime::now(),
+        );
+        let serialized = serde_json::to_string(&scores).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ObfuscationScores>(&serialized).unwrap(),
+            scores
+        );
+        assert_eq!(
+            serde_json::from_str::<ObfuscationScores>("{}").unwrap(),
+            ObfuscationScores::default()
+        );
+    }
+}
@@ -0,0 +1,19 @@
+use chrono::{DateTime, Utc};
+
+/// A problem report which could not be sent, and which the daemon sends once the API can be
+/// reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedProblemReport {
+    pub id: String,
+    pub queued_at: DateTime<Utc>,
+    /// Size of the report in bytes.
+    pub size: u64,
+}
+
+/// The outcome of trying to send a queued problem report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProblemReportSendResult {
+    pub id: String,
+    /// Why the report could not be sent, or `None` if it was sent.
+    pub error: Option<String>,
+}
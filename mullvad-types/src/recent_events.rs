@@ -0,0 +1,84 @@
+//! Significant events in the daemon, kept for debugging problems after the fact.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Part of the daemon that an event concerns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    TunnelState,
+    RelaySelection,
+    Firewall,
+    Dns,
+    ApiConnectivity,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EventKind::TunnelState => "tunnel state",
+            EventKind::RelaySelection => "relay selection",
+            EventKind::Firewall => "firewall",
+            EventKind::Dns => "dns",
+            EventKind::ApiConnectivity => "api connectivity",
+        })
+    }
+}
+
+/// An event with a description that has had addresses and account data removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub time: DateTime<Utc>,
+    pub kind: EventKind,
+    pub message: String,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}",
+            self.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.kind,
+            self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn event() -> Event {
+        Event {
+            time: Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap(),
+            kind: EventKind::RelaySelection,
+            message: "Selected se-got-wg-001".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_serialization() {
+        let json = serde_json::to_value(event()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "time": "2024-03-01T12:30:05Z",
+                "kind": "relay_selection",
+                "message": "Selected se-got-wg-001",
+            })
+        );
+        assert_eq!(serde_json::from_value::<Event>(json).unwrap(), event());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            event().to_string(),
+            "2024-03-01T12:30:05.000Z [relay selection] Selected se-got-wg-001"
+        );
+    }
+}
@@ -10,8 +10,10 @@ use crate::{
 #[cfg(target_os = "android")]
 use jnix::{jni::objects::JObject, FromJava, IntoJava, JnixEnv};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, fmt, str::FromStr};
-use talpid_types::net::{openvpn::ProxySettings, IpVersion, TransportProtocol, TunnelType};
+use std::{collections::HashSet, fmt, str::FromStr, time::Duration};
+use talpid_types::net::{
+    openvpn::ProxySettings, proxy::SocksProxy, IpVersion, TransportProtocol, TunnelType,
+};
 
 pub trait Match<T> {
     fn matches(&self, other: &T) -> bool;
@@ -798,9 +800,14 @@ pub enum BridgeSettings {
 pub enum SelectedObfuscation {
     Auto,
     #[default]
+    #[cfg_attr(feature = "clap", clap(alias = "none"))]
     Off,
     #[cfg_attr(feature = "clap", clap(name = "udp2tcp"))]
     Udp2Tcp,
+    /// Only relays that accept QUIC connections are used.
+    Quic,
+    /// Only relays that accept TLS connections are used.
+    Tls,
 }
 
 impl fmt::Display for SelectedObfuscation {
@@ -809,10 +816,41 @@ impl fmt::Display for SelectedObfuscation {
             SelectedObfuscation::Auto => "auto".fmt(f),
             SelectedObfuscation::Off => "off".fmt(f),
             SelectedObfuscation::Udp2Tcp => "udp2tcp".fmt(f),
+            SelectedObfuscation::Quic => "quic".fmt(f),
+            SelectedObfuscation::Tls => "tls".fmt(f),
         }
     }
 }
 
+/// A way to obfuscate WireGuard traffic, or none, that can be tried in auto mode.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum ObfuscationType {
+    None,
+    #[cfg_attr(feature = "clap", clap(name = "udp2tcp"))]
+    Udp2Tcp,
+    /// Skipped for relays that do not accept QUIC connections.
+    Quic,
+    /// Skipped for relays that do not accept TLS connections.
+    Tls,
+}
+
+impl fmt::Display for ObfuscationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObfuscationType::None => "none".fmt(f),
+            ObfuscationType::Udp2Tcp => "udp2tcp".fmt(f),
+            ObfuscationType::Quic => "quic".fmt(f),
+            ObfuscationType::Tls => "tls".fmt(f),
+        }
+    }
+}
+
+/// Order in which obfuscation types are tried in auto mode, unless it is configured.
+pub const DEFAULT_OBFUSCATION_PRIORITY: [ObfuscationType; 2] =
+    [ObfuscationType::None, ObfuscationType::Udp2Tcp];
+
 #[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
@@ -823,6 +861,27 @@ pub struct Udp2TcpObfuscationSettings {
         jnix(map = "|constraint| constraint.map(|v| v as i32)")
     )]
     pub port: Constraint<u16>,
+    /// Seconds without outgoing traffic after which a keepalive is sent over the TCP connection.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub keepalive_interval_secs: Option<u16>,
+    /// Seconds without incoming traffic, while WireGuard handshakes are unanswered, after which
+    /// the TCP connection is considered stalled and the tunnel is reconnected.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u16>,
+}
+
+impl Udp2TcpObfuscationSettings {
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        self.keepalive_interval_secs
+            .map(|secs| Duration::from_secs(u64::from(secs)))
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs
+            .map(|secs| Duration::from_secs(u64::from(secs)))
+    }
 }
 
 #[cfg(target_os = "android")]
@@ -848,6 +907,7 @@ where
 
         Udp2TcpObfuscationSettings {
             port: port.map(|port| port as u16),
+            ..Default::default()
         }
     }
 }
@@ -855,14 +915,78 @@ where
 impl fmt::Display for Udp2TcpObfuscationSettings {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.port {
-            Constraint::Any => write!(f, "any port"),
-            Constraint::Only(port) => write!(f, "port {port}"),
+            Constraint::Any => write!(f, "any port")?,
+            Constraint::Only(port) => write!(f, "port {port}")?,
+        }
+        if let Some(interval) = self.keepalive_interval_secs {
+            write!(f, ", keepalive every {interval} s")?;
+        }
+        if let Some(timeout) = self.idle_timeout_secs {
+            write!(f, ", idle timeout {timeout} s")?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings for the QUIC obfuscator. The server name and application protocols are sent
+/// unencrypted in the handshake, so they can be chosen to make the connection look like other
+/// common traffic.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct QuicObfuscationSettings {
+    /// Server name to send in the TLS handshake. If unset, the name suggested by the relay list
+    /// is used, or the hostname of the relay.
+    pub server_name: Option<String>,
+    /// Application protocols to offer in the TLS handshake.
+    pub alpn: Vec<String>,
+}
+
+impl Default for QuicObfuscationSettings {
+    fn default() -> Self {
+        QuicObfuscationSettings {
+            server_name: None,
+            alpn: vec!["h3".to_owned()],
+        }
+    }
+}
+
+impl fmt::Display for QuicObfuscationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.server_name {
+            Some(server_name) => write!(f, "server name {server_name}")?,
+            None => write!(f, "server name of the relay")?,
+        }
+        if self.alpn.is_empty() {
+            write!(f, ", no ALPN")
+        } else {
+            write!(f, ", ALPN {}", self.alpn.join(","))
+        }
+    }
+}
+
+/// Settings for the TLS obfuscator. The server name is sent unencrypted in the handshake, so it
+/// can be chosen to make the connection look like it is made to a common website.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct TlsObfuscationSettings {
+    /// Server name to send in the TLS handshake. If unset, the name suggested by the relay list
+    /// is used, or the hostname of the relay.
+    pub server_name: Option<String>,
+}
+
+impl fmt::Display for TlsObfuscationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.server_name {
+            Some(server_name) => write!(f, "server name {server_name}"),
+            None => write!(f, "server name of the relay"),
         }
     }
 }
 
 /// Contains obfuscation settings
-#[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[cfg_attr(target_os = "android", derive(FromJava, IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 #[serde(rename_all = "snake_case")]
@@ -870,6 +994,42 @@ impl fmt::Display for Udp2TcpObfuscationSettings {
 pub struct ObfuscationSettings {
     pub selected_obfuscation: SelectedObfuscation,
     pub udp2tcp: Udp2TcpObfuscationSettings,
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub quic: QuicObfuscationSettings,
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub tls: TlsObfuscationSettings,
+    /// Order in which obfuscation types are tried in auto mode. Each type is used for a couple
+    /// of attempts before the next one is tried. An empty list means the default order.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub obfuscation_priority: Vec<ObfuscationType>,
+    /// SOCKS5 server that TCP-based obfuscators connect to the relay through. Not supported on
+    /// Android.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub upstream_proxy: Option<SocksProxy>,
+}
+
+impl Default for ObfuscationSettings {
+    fn default() -> Self {
+        ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::default(),
+            udp2tcp: Udp2TcpObfuscationSettings::default(),
+            quic: QuicObfuscationSettings::default(),
+            tls: TlsObfuscationSettings::default(),
+            obfuscation_priority: DEFAULT_OBFUSCATION_PRIORITY.to_vec(),
+            upstream_proxy: None,
+        }
+    }
+}
+
+impl ObfuscationSettings {
+    /// Returns the order in which obfuscation types are tried in auto mode.
+    pub fn obfuscation_priority(&self) -> &[ObfuscationType] {
+        if self.obfuscation_priority.is_empty() {
+            &DEFAULT_OBFUSCATION_PRIORITY
+        } else {
+            &self.obfuscation_priority
+        }
+    }
 }
 
 /// Limits the set of bridge servers to use in `mullvad-daemon`.
@@ -944,6 +1104,9 @@ pub struct InternalBridgeConstraints {
     pub providers: Constraint<Providers>,
     pub ownership: Constraint<Ownership>,
     pub transport_protocol: Constraint<TransportProtocol>,
+    /// IP version of the bridge address to connect to. Bridges without an address of this
+    /// version do not match.
+    pub ip_version: Constraint<IpVersion>,
 }
 
 /// Used to update the [`RelaySettings`] used in `mullvad-daemon`.
@@ -995,3 +1158,29 @@ pub struct RelayConstraintsUpdate {
     #[cfg_attr(target_os = "android", jnix(default))]
     pub openvpn_constraints: Option<OpenVpnConstraints>,
 }
+
+/// Parameters that take precedence over the relay settings for a single connection attempt.
+/// Parameters that are `None` are selected according to the settings.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct RelayOverrides {
+    /// Relay to connect to, regardless of the location and filters in the settings.
+    pub hostname: Option<Hostname>,
+    pub port: Option<u16>,
+    pub obfuscation: Option<SelectedObfuscation>,
+    pub tunnel_protocol: Option<TunnelType>,
+}
+
+impl RelayOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The number of relays that the relay selector could pick from with some relay constraints.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct MatchingRelays {
+    /// The relays that could be used as the only hop, or as the exit hop with multihop.
+    pub relays: usize,
+    /// The relays that could be used as the entry hop. Only set with multihop.
+    pub entry_relays: Option<usize>,
+}
@@ -176,6 +176,32 @@ struct PortRange {
 pub struct WireguardRelayEndpointData {
     /// Public key used by the relay peer
     pub public_key: wireguard::PublicKey,
+    /// QUIC obfuscation endpoint of the relay, if it has one
+    #[serde(default)]
+    pub quic: Option<QuicEndpointData>,
+    /// TLS obfuscation endpoint of the relay, if it has one
+    #[serde(default)]
+    pub tls: Option<TlsEndpointData>,
+}
+
+/// Data needed to connect to the QUIC obfuscation endpoint of a relay.
+#[derive(Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Debug)]
+pub struct QuicEndpointData {
+    /// UDP port that the relay accepts QUIC connections on
+    pub port: u16,
+    /// Server name that the relay suggests sending in the TLS handshake
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+/// Data needed to connect to the TLS obfuscation endpoint of a relay.
+#[derive(Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Debug)]
+pub struct TlsEndpointData {
+    /// TCP port that the relay accepts TLS connections on, usually 443
+    pub port: u16,
+    /// Server name that the relay suggests sending in the TLS handshake
+    #[serde(default)]
+    pub server_name: Option<String>,
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
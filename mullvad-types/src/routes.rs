@@ -0,0 +1,189 @@
+//! Routes applied by the daemon, used to debug connectivity problems.
+
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr};
+
+/// Reason why the daemon applies a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutePurpose {
+    /// Routes traffic to the relay outside the tunnel.
+    RelayExclusion,
+    /// Routes all other traffic into the tunnel.
+    TunnelDefault,
+    /// Any other route through the tunnel.
+    Tunnel,
+    /// Routes a bypass route outside the tunnel.
+    Bypass,
+    Other,
+}
+
+impl fmt::Display for RoutePurpose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let purpose = match self {
+            RoutePurpose::RelayExclusion => "relay-exclusion",
+            RoutePurpose::TunnelDefault => "tunnel-default",
+            RoutePurpose::Tunnel => "tunnel",
+            RoutePurpose::Bypass => "bypass",
+            RoutePurpose::Other => "other",
+        };
+        f.write_str(purpose)
+    }
+}
+
+/// A route, either required by the daemon or found in the routing table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteInfo {
+    pub destination: IpNetwork,
+    pub gateway: Option<IpAddr>,
+    pub interface: Option<String>,
+    /// Whether the route uses the current default route outside the tunnel, rather than a
+    /// specific gateway or interface.
+    pub via_default_route: bool,
+    /// Routing table of the route, on platforms with multiple routing tables.
+    pub table: Option<u32>,
+    pub metric: Option<u32>,
+    /// Why the daemon applies the route. Not known for routes found in the routing table.
+    pub purpose: Option<RoutePurpose>,
+}
+
+impl RouteInfo {
+    fn is_same_route(&self, other: &RouteInfo) -> bool {
+        self.destination == other.destination && self.table == other.table
+    }
+}
+
+impl fmt::Display for RouteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.destination)?;
+        if self.via_default_route {
+            write!(f, " via default route")?;
+        }
+        if let Some(gateway) = &self.gateway {
+            write!(f, " via {gateway}")?;
+        }
+        if let Some(interface) = &self.interface {
+            write!(f, " dev {interface}")?;
+        }
+        if let Some(metric) = &self.metric {
+            write!(f, " metric {metric}")?;
+        }
+        if let Some(table) = &self.table {
+            write!(f, " table {table}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Routes that the daemon has asked the route manager to apply, along with the routes that are
+/// actually found in the routing tables.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteDump {
+    pub desired: Vec<RouteInfo>,
+    /// Routes found in the routing tables used by the daemon, or `None` if they cannot be listed
+    /// on this platform.
+    pub kernel: Option<Vec<RouteInfo>>,
+}
+
+/// Routes that were added and removed by a single operation of the daemon, such as connecting,
+/// disconnecting, or following a new default route.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutesUpdate {
+    pub added: Vec<RouteInfo>,
+    pub removed: Vec<RouteInfo>,
+}
+
+/// How a route compares to the routing table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteDiff {
+    /// The desired route is found in the routing table.
+    Present,
+    /// The desired route is not found in the routing table.
+    Missing,
+    /// The route is found in the routing table but is not desired.
+    Unexpected,
+}
+
+impl RouteDump {
+    /// Compares the desired routes to the routes in the routing table, or returns `None` if the
+    /// routing table could not be listed. Routes are matched by destination and table, since
+    /// routes via the default route do not specify a gateway.
+    pub fn diff(&self) -> Option<Vec<(RouteDiff, &RouteInfo)>> {
+        let kernel = self.kernel.as_ref()?;
+
+        let desired = self.desired.iter().map(|route| {
+            if kernel.iter().any(|found| found.is_same_route(route)) {
+                (RouteDiff::Present, route)
+            } else {
+                (RouteDiff::Missing, route)
+            }
+        });
+        let unexpected = kernel
+            .iter()
+            .filter(|found| !self.desired.iter().any(|route| route.is_same_route(found)))
+            .map(|route| (RouteDiff::Unexpected, route));
+
+        Some(desired.chain(unexpected).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn route(destination: &str, table: u32, purpose: Option<RoutePurpose>) -> RouteInfo {
+        RouteInfo {
+            destination: destination.parse().unwrap(),
+            gateway: None,
+            interface: Some("wg0-mullvad".to_owned()),
+            via_default_route: false,
+            table: Some(table),
+            metric: None,
+            purpose,
+        }
+    }
+
+    #[test]
+    fn test_route_diff() {
+        let tunnel_default = route("0.0.0.0/0", 1, Some(RoutePurpose::TunnelDefault));
+        let bypass = route("10.10.0.0/16", 1, Some(RoutePurpose::Bypass));
+        let stale = route("10.64.0.1/32", 1, None);
+        let dump = RouteDump {
+            desired: vec![tunnel_default.clone(), bypass.clone()],
+            kernel: Some(vec![
+                // The gateway of the kernel route does not matter
+                RouteInfo {
+                    gateway: Some("10.64.0.1".parse().unwrap()),
+                    purpose: None,
+                    ..tunnel_default.clone()
+                },
+                // Same destination in another table
+                route("10.10.0.0/16", 254, None),
+                stale.clone(),
+            ]),
+        };
+
+        let diff: Vec<_> = dump
+            .diff()
+            .unwrap()
+            .into_iter()
+            .map(|(diff, route)| (diff, route.destination, route.table))
+            .collect();
+        assert_eq!(
+            diff,
+            vec![
+                (
+                    RouteDiff::Present,
+                    tunnel_default.destination,
+                    tunnel_default.table
+                ),
+                (RouteDiff::Missing, bypass.destination, bypass.table),
+                (RouteDiff::Unexpected, bypass.destination, Some(254)),
+                (RouteDiff::Unexpected, stale.destination, stale.table),
+            ]
+        );
+
+        assert_eq!(RouteDump::default().diff(), None);
+    }
+}
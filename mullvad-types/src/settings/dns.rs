@@ -2,6 +2,7 @@
 use jnix::{FromJava, IntoJava};
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
+use talpid_types::net::TlsDnsServer;
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "snake_case")]
@@ -22,6 +23,35 @@ pub struct DnsOptions {
     pub state: DnsState,
     pub default_options: DefaultDnsOptions,
     pub custom_options: CustomDnsOptions,
+    /// Also complete single-label names with the search domains of other network interfaces when
+    /// they are looked up through the tunnel. Names under those domains are then also resolved via
+    /// the tunnel. Only used with systemd-resolved.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub preserve_search_domains: bool,
+    /// Allow DNS requests to custom DNS servers on the LAN in the blocked states. Only used if
+    /// LAN traffic is allowed.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub allow_lan_dns_when_blocked: bool,
+    /// Keep using the custom DNS servers in the disconnected state, and block DNS requests to
+    /// other servers. Only used if "block when disconnected" is disabled.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub keep_custom_dns_while_disconnected: bool,
+}
+
+impl DnsOptions {
+    /// Returns the custom DNS servers that are on the LAN, and thus only reachable if LAN traffic
+    /// is allowed.
+    pub fn lan_dns_servers(&self) -> Vec<IpAddr> {
+        if self.state != DnsState::Custom {
+            return vec![];
+        }
+        self.custom_options
+            .addresses
+            .iter()
+            .filter(|address| is_lan_address(address))
+            .copied()
+            .collect()
+    }
 }
 
 /// Default DNS config
@@ -44,4 +74,57 @@ pub struct DefaultDnsOptions {
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub struct CustomDnsOptions {
     pub addresses: Vec<IpAddr>,
+    /// DNS-over-TLS servers. Queries are relayed to these by a local stub resolver.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub tls_servers: Vec<TlsDnsServer>,
+}
+
+/// Returns whether `address` belongs to one of the networks that the firewall allows when LAN
+/// traffic is allowed.
+fn is_lan_address(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => address.is_private() || address.is_link_local(),
+        IpAddr::V6(address) => {
+            let first_segment = address.segments()[0];
+            // fe80::/10 or fc00::/7
+            (first_segment & 0xffc0) == 0xfe80 || (first_segment & 0xfe00) == 0xfc00
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lan_dns_servers() {
+        let options = DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: vec![
+                    "192.168.1.2".parse().unwrap(),
+                    "9.9.9.9".parse().unwrap(),
+                    "127.0.0.1".parse().unwrap(),
+                    "fd00::53".parse().unwrap(),
+                    "2001:db8::53".parse().unwrap(),
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            options.lan_dns_servers(),
+            vec![
+                "192.168.1.2".parse::<IpAddr>().unwrap(),
+                "fd00::53".parse::<IpAddr>().unwrap(),
+            ]
+        );
+
+        let options = DnsOptions {
+            state: DnsState::Default,
+            ..options
+        };
+        assert!(options.lan_dns_servers().is_empty());
+    }
 }
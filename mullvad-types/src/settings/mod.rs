@@ -1,6 +1,8 @@
 use crate::{
     access_method,
     custom_list::CustomListsSettings,
+    network_profiles::NetworkId,
+    network_trust::NetworkTrustSettings,
     relay_constraints::{
         BridgeConstraints, BridgeSettings, BridgeState, Constraint, GeographicLocationConstraint,
         LocationConstraint, ObfuscationSettings, RelayConstraints, RelaySettings,
@@ -8,12 +10,22 @@ use crate::{
     },
     wireguard,
 };
+use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 #[cfg(target_os = "windows")]
-use std::{collections::HashSet, path::PathBuf};
-use talpid_types::net::{openvpn, GenericTunnelOptions};
+use std::{collections::HashSet, fmt};
+use std::{path::PathBuf, time::Duration};
+#[cfg(target_os = "linux")]
+use talpid_types::{
+    net::TransportProtocol,
+    split_tunnel::{ExcludedDestination, SplitTunnelMode},
+};
+use talpid_types::{
+    net::{openvpn, GenericTunnelOptions},
+    tunnel::RetryBackoff,
+};
 
 mod dns;
 
@@ -72,6 +84,13 @@ pub struct Settings {
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub bridge_settings: BridgeSettings,
     pub obfuscation_settings: ObfuscationSettings,
+    /// Obfuscation settings that are used instead of `obfuscation_settings` on specific
+    /// networks. See [`crate::network_profiles`] for how a profile is selected.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub network_obfuscation_profiles: Vec<(NetworkId, ObfuscationSettings)>,
+    /// Whether to connect or disconnect automatically when joining specific networks.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub network_trust: NetworkTrustSettings,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub bridge_state: BridgeState,
     /// All of the custom relay lists
@@ -80,22 +99,57 @@ pub struct Settings {
     /// API access methods.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub api_access_methods: access_method::Settings,
+    /// Whether to send API traffic inside the tunnel while connected, instead of using the API
+    /// access methods. API traffic is still allowed outside the tunnel in all other states.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub prefer_api_in_tunnel: bool,
     /// If the daemon should allow communication with private (LAN) networks.
     pub allow_lan: bool,
+    /// Networks that are routed outside the tunnel, via the default route of the physical
+    /// interface. Traffic to these networks is not protected by the tunnel.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub bypass_routes: Vec<IpNetwork>,
+    /// Whether to leave the default route to other VPNs, and route traffic into the tunnel using
+    /// two routes that are more specific instead. Only used on macOS.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub coexistence_mode: bool,
+    /// SOCKS5 server on localhost that other applications can use to connect through the tunnel.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub local_proxy: LocalProxySettings,
+    /// Scripts that the daemon runs when the tunnel state changes.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub hooks: HookSettings,
+    /// How long consecutive connection attempts are spaced apart.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub retry_backoff: RetryBackoff,
+    /// When to warn that the account is about to run out of time.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub account_expiry_warnings: AccountExpiryWarnings,
+    /// When the daemon and tunnel logs are rotated.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub log_rotation: LogRotationSettings,
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub block_when_disconnected: bool,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     pub auto_connect: bool,
+    /// Disconnect the tunnel once next to no traffic has passed through it for this long. It stays
+    /// disconnected until it is connected again. `auto_connect` only connects it when the daemon
+    /// starts.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub auto_disconnect_idle: Option<Duration>,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
     /// might be located.
     pub tunnel_options: TunnelOptions,
     /// Whether to notify users of beta updates.
     pub show_beta_releases: bool,
     /// Split tunneling settings
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     pub split_tunnel: SplitTunnelSettings,
+    /// Routing table and rule priorities used to route traffic into the tunnel
+    #[cfg(target_os = "linux")]
+    pub routing: RoutingSettings,
     /// Specifies settings schema version
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub settings_version: SettingsVersion,
@@ -107,7 +161,170 @@ pub struct SplitTunnelSettings {
     /// Toggles split tunneling on or off
     pub enable_exclusions: bool,
     /// List of applications to exclude from the tunnel.
-    pub apps: HashSet<PathBuf>,
+    pub apps: HashSet<SplitApp>,
+}
+
+/// An application to exclude from the tunnel.
+#[cfg(windows)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum SplitApp {
+    /// The executable at a path. It is stored as a plain string, as in earlier versions.
+    Path(PathBuf),
+    /// Executables signed by a publisher, which are matched against the subject of the signing
+    /// certificate. The publisher may be followed by `/` and a product name to only match images
+    /// with that product name in their version info.
+    Publisher(#[serde(with = "publisher_app")] String),
+}
+
+#[cfg(windows)]
+impl fmt::Display for SplitApp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitApp::Path(path) => write!(f, "{}", path.display()),
+            SplitApp::Publisher(publisher) => write!(f, "publisher: {publisher}"),
+        }
+    }
+}
+
+/// Stores publishers as `{ "publisher": "..." }`, to tell them apart from paths.
+#[cfg(windows)]
+mod publisher_app {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize, Serialize)]
+    struct PublisherApp<T> {
+        publisher: T,
+    }
+
+    pub fn serialize<S: Serializer>(publisher: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        PublisherApp { publisher }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        PublisherApp::<String>::deserialize(deserializer).map(|app| app.publisher)
+    }
+}
+
+/// Traffic that is sent outside the tunnel, or the only traffic that is sent through it.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct SplitTunnelSettings {
+    /// Users whose processes are excluded from the tunnel.
+    pub users: Vec<u32>,
+    /// Groups whose processes are excluded from the tunnel.
+    pub groups: Vec<u32>,
+    /// Whether the selected processes are excluded from the tunnel, or are the only ones using
+    /// it.
+    pub mode: SplitTunnelMode,
+    /// Destinations whose traffic is excluded from the tunnel, regardless of which process sends
+    /// it.
+    pub destinations: Vec<ExcludedDestination>,
+    /// Interfaces whose forwarded traffic, such as that of containers on a bridge, is excluded
+    /// from the tunnel. A name that ends with `*` matches all interfaces that start with the rest
+    /// of it.
+    pub interfaces: Vec<String>,
+    /// Whether processes that are excluded from the tunnel may connect to the local network even
+    /// when `allow_lan` is disabled. Other processes are still blocked from the local network, and
+    /// connections from it are not accepted. When disabled, excluded processes follow `allow_lan`.
+    pub excluded_apps_allow_lan: bool,
+    /// Local ports whose traffic is excluded from the tunnel, so that services that listen on
+    /// them, such as game servers, are reached and reply outside the tunnel.
+    pub local_ports: Vec<(TransportProtocol, u16)>,
+}
+
+#[cfg(target_os = "linux")]
+impl Default for SplitTunnelSettings {
+    fn default() -> Self {
+        SplitTunnelSettings {
+            users: vec![],
+            groups: vec![],
+            mode: SplitTunnelMode::Exclude,
+            destinations: vec![],
+            interfaces: vec![],
+            excluded_apps_allow_lan: true,
+            local_ports: vec![],
+        }
+    }
+}
+
+/// Settings for the SOCKS5 server that relays connections of other applications through the
+/// tunnel. It only accepts connections from the host itself, and only runs while connected.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct LocalProxySettings {
+    /// Port on the loopback address to listen on. The proxy is disabled if this is `None`.
+    pub port: Option<u16>,
+    /// Username and password that clients must authenticate with, if any.
+    pub authentication: Option<access_method::SocksAuth>,
+}
+
+/// Executables that the daemon runs after the tunnel has connected, disconnected or failed, e.g.
+/// to update a dynamic DNS record. Details of the tunnel are passed in environment variables.
+/// Hooks that fail never affect the tunnel.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HookSettings {
+    pub on_connect: Option<PathBuf>,
+    pub on_disconnect: Option<PathBuf>,
+    pub on_error: Option<PathBuf>,
+}
+
+/// How long before the account runs out of time that the daemon emits warnings.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AccountExpiryWarnings {
+    pub thresholds: Vec<Duration>,
+}
+
+impl Default for AccountExpiryWarnings {
+    fn default() -> Self {
+        const HOUR: Duration = Duration::from_secs(60 * 60);
+        AccountExpiryWarnings {
+            thresholds: vec![72 * HOUR, 24 * HOUR, HOUR],
+        }
+    }
+}
+
+/// How large the daemon and tunnel logs may grow before they are compressed into archives, and how
+/// many archives are kept of each.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct LogRotationSettings {
+    pub max_size_mib: u32,
+    pub max_archives: u32,
+}
+
+impl Default for LogRotationSettings {
+    fn default() -> Self {
+        LogRotationSettings {
+            max_size_mib: 10,
+            max_archives: 5,
+        }
+    }
+}
+
+/// Advanced routing settings. These are only read when the daemon starts.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RoutingSettings {
+    /// Routing table that holds the tunnel routes.
+    pub route_table: u32,
+    /// Priority of the routing rules. The rules use this priority and the one following it. If
+    /// `None`, the priorities are assigned by the kernel.
+    pub rule_priority: Option<u32>,
+}
+
+#[cfg(target_os = "linux")]
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        RoutingSettings {
+            route_table: crate::TUNNEL_TABLE_ID,
+            rule_priority: None,
+        }
+    }
 }
 
 impl Default for Settings {
@@ -130,17 +347,30 @@ impl Default for Settings {
                 selected_obfuscation: SelectedObfuscation::Off,
                 ..Default::default()
             },
+            network_obfuscation_profiles: vec![],
+            network_trust: NetworkTrustSettings::default(),
             bridge_state: BridgeState::Auto,
             allow_lan: false,
+            bypass_routes: vec![],
+            coexistence_mode: false,
+            local_proxy: LocalProxySettings::default(),
+            hooks: HookSettings::default(),
+            retry_backoff: RetryBackoff::default(),
+            account_expiry_warnings: AccountExpiryWarnings::default(),
+            log_rotation: LogRotationSettings::default(),
             block_when_disconnected: false,
             auto_connect: false,
+            auto_disconnect_idle: None,
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
             split_tunnel: SplitTunnelSettings::default(),
+            #[cfg(target_os = "linux")]
+            routing: RoutingSettings::default(),
             settings_version: CURRENT_SETTINGS_VERSION,
             custom_lists: CustomListsSettings::default(),
             api_access_methods: access_method::Settings::default(),
+            prefer_api_in_tunnel: false,
         }
     }
 }
@@ -173,6 +403,38 @@ impl Settings {
             self.relay_settings = new_settings;
         }
     }
+
+    /// Returns the custom DNS servers on the LAN that cannot be reached while traffic is blocked,
+    /// since LAN traffic is not allowed.
+    pub fn unreachable_lan_dns_servers(&self) -> Vec<std::net::IpAddr> {
+        if self.allow_lan {
+            return vec![];
+        }
+        self.tunnel_options.dns_options.lan_dns_servers()
+    }
+}
+
+/// What a factory reset removes. If no scope is selected, everything is removed: the account is
+/// logged out, and all settings, caches, history and logs are deleted before the daemon stops.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResetScopes {
+    /// Restore the default settings. The account and device stay logged in.
+    pub settings: bool,
+    /// Remove the cached relay list and API addresses, so that they are fetched again.
+    pub cache: bool,
+    /// Remove the saved accounts and the connection history.
+    pub history: bool,
+}
+
+impl ResetScopes {
+    /// Reset everything, like the factory reset did before it had scopes.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn is_all(&self) -> bool {
+        !(self.settings || self.cache || self.history)
+    }
 }
 
 /// TunnelOptions holds configuration data that applies to all kinds of tunnels.
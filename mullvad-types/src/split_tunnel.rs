@@ -0,0 +1,213 @@
+//! Checks for split tunnel configurations that are accepted but may not work as expected, such as
+//! excluded apps that still resolve names through the tunnel.
+
+use crate::{
+    features::excludes_apps,
+    settings::{DnsOptions, DnsState, Settings},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+#[cfg(target_os = "linux")]
+use talpid_types::split_tunnel::SplitTunnelMode;
+
+/// A combination of settings that may make split tunneling behave differently than expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitTunnelWarning {
+    /// Apps are excluded, but the DNS servers are only reached through the tunnel. Lookups that
+    /// are made by the system resolver on behalf of excluded apps are sent through the tunnel,
+    /// so the apps may be given addresses that are close to the relay rather than to the user.
+    ExcludedAppsUseTunnelDns,
+    /// Only the selected processes use the tunnel, and lockdown mode is enabled, so all other
+    /// traffic is blocked while disconnected. Only used on Linux.
+    LockdownBlocksUnselectedTraffic,
+    /// Only the selected processes use the tunnel, but no users or groups are selected, so only
+    /// processes that are added while the daemon is running use it. Only used on Linux.
+    NoSelectedApps,
+    /// Only the selected processes use the tunnel, so the local proxy is not run, since the
+    /// connections that it makes would be sent outside the tunnel. Only used on Linux.
+    LocalProxyNotRun,
+    /// Apps are listed for exclusion, but split tunneling is disabled, so they use the tunnel.
+    /// Only used on Windows.
+    ExclusionsDisabled,
+}
+
+impl fmt::Display for SplitTunnelWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let warning = match self {
+            SplitTunnelWarning::ExcludedAppsUseTunnelDns => {
+                "Excluded apps that look up names through the system resolver use the DNS servers \
+                 in the tunnel, so they may be sent to servers near the relay"
+            }
+            SplitTunnelWarning::LockdownBlocksUnselectedTraffic => {
+                "Lockdown mode blocks all traffic except that of the selected processes while \
+                 disconnected"
+            }
+            SplitTunnelWarning::NoSelectedApps => {
+                "No users or groups are selected, so only processes that are added at runtime \
+                 use the tunnel"
+            }
+            SplitTunnelWarning::LocalProxyNotRun => {
+                "The local proxy is not run, since its connections would be sent outside the \
+                 tunnel"
+            }
+            SplitTunnelWarning::ExclusionsDisabled => {
+                "Split tunneling is disabled, so the listed apps use the tunnel"
+            }
+        };
+        f.write_str(warning)
+    }
+}
+
+/// Returns the problems with the split tunnel configuration in `settings`, in a stable order. This
+/// only looks at the settings, so it can be used to check settings before they are applied.
+pub fn validate_split_tunnel_config(settings: &Settings) -> Vec<SplitTunnelWarning> {
+    let mut warnings = vec![];
+    if excludes_apps(settings) && uses_tunnel_dns(&settings.tunnel_options.dns_options) {
+        warnings.push(SplitTunnelWarning::ExcludedAppsUseTunnelDns);
+    }
+    #[cfg(target_os = "linux")]
+    if settings.split_tunnel.mode == SplitTunnelMode::Include {
+        if settings.block_when_disconnected {
+            warnings.push(SplitTunnelWarning::LockdownBlocksUnselectedTraffic);
+        }
+        if settings.split_tunnel.users.is_empty() && settings.split_tunnel.groups.is_empty() {
+            warnings.push(SplitTunnelWarning::NoSelectedApps);
+        }
+        if settings.local_proxy.port.is_some() {
+            warnings.push(SplitTunnelWarning::LocalProxyNotRun);
+        }
+    }
+    #[cfg(windows)]
+    if !settings.split_tunnel.enable_exclusions && !settings.split_tunnel.apps.is_empty() {
+        warnings.push(SplitTunnelWarning::ExclusionsDisabled);
+    }
+    warnings
+}
+
+/// Returns whether any of the DNS servers in `options` is only reached through the tunnel. This is
+/// the case for the default resolvers, DNS-over-TLS servers, and custom servers that are neither
+/// on the LAN nor on the host itself.
+fn uses_tunnel_dns(options: &DnsOptions) -> bool {
+    match options.state {
+        DnsState::Default => true,
+        DnsState::Custom => {
+            let custom = &options.custom_options;
+            let lan_servers = options.lan_dns_servers();
+            // The tunnel gateway is used if no custom servers are given
+            !custom.tls_servers.is_empty()
+                || custom.addresses.is_empty()
+                || custom
+                    .addresses
+                    .iter()
+                    .any(|address| !address.is_loopback() && !lan_servers.contains(address))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::CustomDnsOptions;
+    #[cfg(target_os = "linux")]
+    use crate::settings::SplitTunnelSettings;
+
+    fn custom_dns(addresses: &[&str]) -> DnsOptions {
+        DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: addresses
+                    .iter()
+                    .map(|address| address.parse().unwrap())
+                    .collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_uses_tunnel_dns() {
+        assert!(uses_tunnel_dns(&DnsOptions::default()));
+        assert!(uses_tunnel_dns(&custom_dns(&[])));
+        assert!(uses_tunnel_dns(&custom_dns(&["192.168.1.1", "9.9.9.9"])));
+        assert!(!uses_tunnel_dns(&custom_dns(&["192.168.1.1", "fd00::53"])));
+        assert!(!uses_tunnel_dns(&custom_dns(&["127.0.0.1"])));
+    }
+
+    #[test]
+    fn test_no_warnings_by_default() {
+        assert!(validate_split_tunnel_config(&Settings::default()).is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_excluded_apps_use_tunnel_dns() {
+        let mut settings = Settings {
+            split_tunnel: SplitTunnelSettings {
+                users: vec![1001],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_split_tunnel_config(&settings),
+            vec![SplitTunnelWarning::ExcludedAppsUseTunnelDns]
+        );
+
+        // Excluded apps reach resolvers on the LAN directly
+        settings.tunnel_options.dns_options = custom_dns(&["192.168.1.1"]);
+        assert!(validate_split_tunnel_config(&settings).is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_include_mode_warnings() {
+        let mut settings = Settings {
+            split_tunnel: SplitTunnelSettings {
+                mode: SplitTunnelMode::Include,
+                ..Default::default()
+            },
+            block_when_disconnected: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_split_tunnel_config(&settings),
+            vec![
+                SplitTunnelWarning::LockdownBlocksUnselectedTraffic,
+                SplitTunnelWarning::NoSelectedApps
+            ]
+        );
+
+        // The selected processes use the tunnel, so the DNS warning does not apply
+        settings.split_tunnel.groups = vec![1001];
+        settings.block_when_disconnected = false;
+        assert!(validate_split_tunnel_config(&settings).is_empty());
+
+        settings.local_proxy.port = Some(1080);
+        assert_eq!(
+            validate_split_tunnel_config(&settings),
+            vec![SplitTunnelWarning::LocalProxyNotRun]
+        );
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_exclusions_disabled() {
+        use crate::settings::SplitApp;
+
+        let mut settings = Settings::default();
+        settings.tunnel_options.dns_options = custom_dns(&["192.168.1.1"]);
+        settings
+            .split_tunnel
+            .apps
+            .insert(SplitApp::Path(r"C:\app.exe".into()));
+        assert_eq!(
+            validate_split_tunnel_config(&settings),
+            vec![SplitTunnelWarning::ExclusionsDisabled]
+        );
+
+        settings.split_tunnel.enable_exclusions = true;
+        assert!(validate_split_tunnel_config(&settings).is_empty());
+    }
+}
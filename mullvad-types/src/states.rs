@@ -1,13 +1,15 @@
-use crate::location::GeoIpLocation;
+use crate::{features::FirstHop, location::GeoIpLocation};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{fmt, time::Duration};
 use talpid_types::{
-    net::TunnelEndpoint,
-    tunnel::{ActionAfterDisconnect, ErrorState},
+    net::{EffectiveDns, TunnelEndpoint},
+    tunnel::{ActionAfterDisconnect, ConnectingPhase, ConnectionAttempt, ErrorState},
 };
 
+pub use talpid_types::net::NetworkChange;
+
 /// Represents the state the client strives towards.
 /// When in `Secured`, the client should keep the computer from leaking and try to
 /// establish a VPN tunnel if it is not up.
@@ -38,10 +40,27 @@ pub enum TunnelState {
     Connecting {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        /// Whether the tunnel is being established, or the attempt is waiting for the network.
+        #[serde(default)]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        phase: ConnectingPhase,
+        /// The attempt that is in progress, and when the next attempt is made.
+        #[serde(default)]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        attempt: ConnectionAttempt,
     },
     Connected {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        /// The DNS configuration that is in use.
+        #[serde(default)]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        effective_dns: EffectiveDns,
+        /// The host that traffic is first sent to. Only missing if the state was received from
+        /// an older daemon.
+        #[serde(default)]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        first_hop: Option<FirstHop>,
     },
     Disconnecting(ActionAfterDisconnect),
     Error(ErrorState),
@@ -63,3 +82,13 @@ impl TunnelState {
         matches!(self, TunnelState::Disconnected)
     }
 }
+
+/// Emitted when the tunnel is disconnected because next to no traffic has passed through it for
+/// as long as the `auto_disconnect_idle` setting allows.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct IdleDisconnect {
+    /// How long the tunnel was idle.
+    pub idle_for: Duration,
+    /// Whether traffic is still blocked, because lockdown mode is enabled.
+    pub locked_down: bool,
+}
@@ -10,7 +10,7 @@ pub struct DnsMonitor;
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new() -> Result<Self, Self::Error> {
+    fn new(_tampered_tx: super::DnsTamperedSender) -> Result<Self, Self::Error> {
         Ok(DnsMonitor)
     }
 
@@ -7,11 +7,35 @@ use self::{
     network_manager::NetworkManager, resolvconf::Resolvconf, static_resolv_conf::StaticResolvConf,
     systemd_resolved::SystemdResolved,
 };
-use std::{env, fmt, net::IpAddr};
+use super::{
+    watchdog::{TamperNotifier, POLL_INTERVAL},
+    DnsTamperedSender, OriginalResolvers,
+};
+use crate::tunnel_state_machine::TunnelCommand;
+use futures::channel::mpsc;
+use std::{
+    env, fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::{mpsc as sync_mpsc, Weak},
+    thread,
+};
+use talpid_dbus::systemd_resolved::SystemdResolved as DbusInterface;
 use talpid_routing::RouteManagerHandle;
+use talpid_types::ErrorExt;
+
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// Public addresses used to find the interfaces that traffic outside the tunnel is routed through.
+const PUBLIC_INTERNET_ADDRESS_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(193, 138, 218, 78));
+const PUBLIC_INTERNET_ADDRESS_V6: IpAddr =
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6));
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Resolvers paired with the name of the interface they are configured for.
+type InterfaceResolvers = Vec<(String, IpAddr)>;
+
 /// Errors that can happen in the Linux DNS monitor
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -38,33 +62,83 @@ pub enum Error {
 
 pub struct DnsMonitor {
     route_manager: RouteManagerHandle,
+    fwmark: u32,
     handle: tokio::runtime::Handle,
+    cache_dir: PathBuf,
     inner: Option<DnsMonitorHolder>,
+    preserve_search_domains: bool,
+    tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    notifier: TamperNotifier,
+    original_resolvers: OriginalResolvers,
+    original_resolvers_watcher: Option<OriginalResolversWatcher>,
+    tunnel_interface: Option<String>,
 }
 
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(handle: tokio::runtime::Handle, route_manager: RouteManagerHandle) -> Result<Self> {
+    fn new(
+        handle: tokio::runtime::Handle,
+        route_manager: RouteManagerHandle,
+        fwmark: u32,
+        cache_dir: PathBuf,
+        tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+        tampered_tx: DnsTamperedSender,
+    ) -> Result<Self> {
         Ok(DnsMonitor {
             route_manager,
+            fwmark,
             handle,
+            cache_dir,
             inner: None,
+            preserve_search_domains: false,
+            tx: tx.clone(),
+            notifier: TamperNotifier::new(tx, tampered_tx),
+            original_resolvers: OriginalResolvers::default(),
+            original_resolvers_watcher: None,
+            tunnel_interface: None,
         })
     }
 
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
         self.reset()?;
         // Creating a new DNS monitor for each set, in case the system changed how it manages DNS.
-        let mut inner = DnsMonitorHolder::new()?;
+        let mut inner = DnsMonitorHolder::new(&self.cache_dir, &self.notifier)?;
         if !servers.is_empty() {
-            inner.set(&self.handle, &self.route_manager, interface, servers)?;
+            self.tunnel_interface = Some(interface.to_owned());
+            self.capture_original_resolvers(&inner, interface);
+            inner.set(
+                &self.handle,
+                &self.route_manager,
+                interface,
+                servers,
+                self.preserve_search_domains,
+                &self.notifier,
+            )?;
+            if let DnsMonitorHolder::SystemdResolved(ref systemd_resolved) = inner {
+                self.original_resolvers_watcher = Some(OriginalResolversWatcher::start(
+                    systemd_resolved.dbus_interface.clone(),
+                    self.handle.clone(),
+                    self.route_manager.clone(),
+                    self.fwmark,
+                    interface.to_owned(),
+                    self.original_resolvers.clone(),
+                    self.tx.clone(),
+                ));
+            }
             self.inner = Some(inner);
         }
         Ok(())
     }
 
+    fn set_preserve_search_domains(&mut self, preserve_search_domains: bool) {
+        self.preserve_search_domains = preserve_search_domains;
+    }
+
     fn reset(&mut self) -> Result<()> {
+        self.original_resolvers_watcher = None;
+        self.tunnel_interface = None;
+        self.original_resolvers.clear();
         if let Some(mut inner) = self.inner.take() {
             inner.reset(&self.handle)?;
         }
@@ -72,6 +146,59 @@ impl super::DnsMonitorT for DnsMonitor {
     }
 }
 
+impl DnsMonitor {
+    pub fn original_resolvers(&self) -> &OriginalResolvers {
+        &self.original_resolvers
+    }
+
+    /// Only systemd-resolved keeps the resolvers of other interfaces around while the tunnel DNS
+    /// is set. For other DNS managers, the resolvers are only captured before overriding them,
+    /// and which interfaces traffic is routed through does not matter since the resolvers don't
+    /// belong to any interface.
+    pub fn refresh_original_resolvers(&mut self) -> bool {
+        match (self.inner.take(), self.tunnel_interface.clone()) {
+            (Some(inner @ DnsMonitorHolder::SystemdResolved(_)), Some(tunnel_interface)) => {
+                let changed = self.capture_original_resolvers(&inner, &tunnel_interface);
+                self.inner = Some(inner);
+                changed
+            }
+            (inner, _) => {
+                self.inner = inner;
+                false
+            }
+        }
+    }
+
+    /// Records the resolvers that are currently in use. Returns whether they changed.
+    fn capture_original_resolvers(
+        &mut self,
+        holder: &DnsMonitorHolder,
+        tunnel_interface: &str,
+    ) -> bool {
+        let (interface_servers, global_servers) = match holder.current_resolvers() {
+            Ok(resolvers) => resolvers,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain the original resolvers")
+                );
+                return false;
+            }
+        };
+        let default_interfaces = default_interfaces(&self.handle, &self.route_manager, self.fwmark);
+        let changed = self.original_resolvers.update(
+            tunnel_interface,
+            default_interfaces,
+            interface_servers,
+            global_servers,
+        );
+        if changed {
+            log::debug!("Original resolvers: {:?}", self.original_resolvers);
+        }
+        changed
+    }
+}
+
 pub enum DnsMonitorHolder {
     SystemdResolved(SystemdResolved),
     NetworkManager(NetworkManager),
@@ -93,26 +220,29 @@ impl fmt::Display for DnsMonitorHolder {
 }
 
 impl DnsMonitorHolder {
-    fn new() -> Result<Self> {
+    fn new(cache_dir: &Path, notifier: &TamperNotifier) -> Result<Self> {
         let dns_module = env::var_os("TALPID_DNS_MODULE");
 
         let manager = match dns_module.as_ref().and_then(|value| value.to_str()) {
-            Some("static-file") => DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new()?),
+            Some("static-file") => DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new(
+                cache_dir,
+                notifier.clone(),
+            )?),
             Some("resolvconf") => DnsMonitorHolder::Resolvconf(Resolvconf::new()?),
             Some("systemd") => DnsMonitorHolder::SystemdResolved(SystemdResolved::new()?),
             Some("network-manager") => DnsMonitorHolder::NetworkManager(NetworkManager::new()?),
-            Some(_) | None => Self::with_detected_dns_manager()?,
+            Some(_) | None => Self::with_detected_dns_manager(cache_dir, notifier)?,
         };
         log::debug!("Managing DNS via {}", manager);
         Ok(manager)
     }
 
-    fn with_detected_dns_manager() -> Result<Self> {
+    fn with_detected_dns_manager(cache_dir: &Path, notifier: &TamperNotifier) -> Result<Self> {
         SystemdResolved::new()
             .map(DnsMonitorHolder::SystemdResolved)
             .or_else(|err| {
                 match err {
-                    systemd_resolved::Error::SystemdResolvedError(
+                    systemd_resolved::Error::SystemdResolved(
                         systemd_resolved::SystemdDbusError::NoSystemdResolved(_),
                     ) => (),
                     other_error => {
@@ -122,7 +252,10 @@ impl DnsMonitorHolder {
                 NetworkManager::new().map(DnsMonitorHolder::NetworkManager)
             })
             .or_else(|_| Resolvconf::new().map(DnsMonitorHolder::Resolvconf))
-            .or_else(|_| StaticResolvConf::new().map(DnsMonitorHolder::StaticResolvConf))
+            .or_else(|_| {
+                StaticResolvConf::new(cache_dir, notifier.clone())
+                    .map(DnsMonitorHolder::StaticResolvConf)
+            })
             .map_err(|_| Error::NoDnsMonitor)
     }
 
@@ -132,6 +265,8 @@ impl DnsMonitorHolder {
         route_manager: &RouteManagerHandle,
         interface: &str,
         servers: &[IpAddr],
+        preserve_search_domains: bool,
+        notifier: &TamperNotifier,
     ) -> Result<()> {
         use self::DnsMonitorHolder::*;
         match self {
@@ -139,15 +274,34 @@ impl DnsMonitorHolder {
             StaticResolvConf(ref mut static_resolv_conf) => {
                 static_resolv_conf.set_dns(servers.to_vec())?
             }
-            SystemdResolved(ref mut systemd_resolved) => handle
-                .block_on(systemd_resolved.set_dns(route_manager.clone(), interface, servers))?,
+            SystemdResolved(ref mut systemd_resolved) => {
+                handle.block_on(systemd_resolved.set_dns(
+                    route_manager.clone(),
+                    interface,
+                    servers,
+                    preserve_search_domains,
+                    notifier,
+                ))?
+            }
             NetworkManager(ref mut network_manager) => {
-                network_manager.set_dns(interface, servers)?
+                network_manager.set_dns(interface, servers, notifier)?
             }
         }
         Ok(())
     }
 
+    /// Returns the resolvers that are currently configured for each interface, and those that
+    /// don't belong to any interface.
+    fn current_resolvers(&self) -> io::Result<(InterfaceResolvers, Vec<IpAddr>)> {
+        match self {
+            DnsMonitorHolder::SystemdResolved(systemd_resolved) => {
+                systemd_resolved_resolvers(&systemd_resolved.dbus_interface)
+            }
+            // The other DNS managers only expose the resolvers listed in /etc/resolv.conf
+            _ => Ok((vec![], resolv_conf_nameservers()?)),
+        }
+    }
+
     fn reset(&mut self, handle: &tokio::runtime::Handle) -> Result<()> {
         use self::DnsMonitorHolder::*;
         match self {
@@ -162,6 +316,124 @@ impl DnsMonitorHolder {
     }
 }
 
+/// Periodically checks whether the resolvers used by excluded processes have changed, and asks the
+/// tunnel state machine to refresh them if so. Only systemd-resolved can be checked while the tunnel
+/// DNS is set. The watcher stops once dropped.
+struct OriginalResolversWatcher {
+    _cancel_tx: sync_mpsc::Sender<()>,
+}
+
+impl OriginalResolversWatcher {
+    fn start(
+        dbus_interface: DbusInterface,
+        handle: tokio::runtime::Handle,
+        route_manager: RouteManagerHandle,
+        fwmark: u32,
+        tunnel_interface: String,
+        mut original_resolvers: OriginalResolvers,
+        tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    ) -> Self {
+        let (cancel_tx, cancel_rx) = sync_mpsc::channel();
+        thread::spawn(move || {
+            while let Err(sync_mpsc::RecvTimeoutError::Timeout) =
+                cancel_rx.recv_timeout(POLL_INTERVAL)
+            {
+                let (interface_servers, global_servers) =
+                    match systemd_resolved_resolvers(&dbus_interface) {
+                        Ok(resolvers) => resolvers,
+                        Err(error) => {
+                            log::trace!("Failed to obtain the original resolvers: {}", error);
+                            continue;
+                        }
+                    };
+                let default_interfaces = default_interfaces(&handle, &route_manager, fwmark);
+                if !original_resolvers.update(
+                    &tunnel_interface,
+                    default_interfaces,
+                    interface_servers,
+                    global_servers,
+                ) {
+                    continue;
+                }
+                match tx.upgrade() {
+                    Some(tx) => {
+                        let _ = tx.unbounded_send(TunnelCommand::RefreshOriginalResolvers);
+                    }
+                    None => break,
+                }
+            }
+        });
+        Self {
+            _cancel_tx: cancel_tx,
+        }
+    }
+}
+
+/// Returns the resolvers that systemd-resolved has configured for each interface, and those that
+/// don't belong to any interface.
+fn systemd_resolved_resolvers(
+    dbus_interface: &DbusInterface,
+) -> io::Result<(InterfaceResolvers, Vec<IpAddr>)> {
+    let servers = dbus_interface
+        .get_all_dns()
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+    let mut interface_servers = vec![];
+    let mut global_servers = vec![];
+    for (interface_index, server) in servers {
+        if interface_index == 0 {
+            global_servers.push(server);
+            continue;
+        }
+        match crate::linux::iface_name(interface_index as u32) {
+            Ok(interface) => interface_servers.push((interface, server)),
+            Err(error) => log::debug!(
+                "Ignoring resolver of unknown interface {}: {}",
+                interface_index,
+                error
+            ),
+        }
+    }
+    Ok((interface_servers, global_servers))
+}
+
+/// Returns the interfaces that traffic outside the tunnel is routed through.
+fn default_interfaces(
+    handle: &tokio::runtime::Handle,
+    route_manager: &RouteManagerHandle,
+    fwmark: u32,
+) -> Vec<String> {
+    let mut interfaces = vec![];
+    for address in [PUBLIC_INTERNET_ADDRESS_V4, PUBLIC_INTERNET_ADDRESS_V6] {
+        match handle.block_on(route_manager.get_destination_route(address, Some(fwmark))) {
+            Ok(Some(route)) => {
+                if let Some(device) = route.get_node().get_device() {
+                    interfaces.push(device.to_owned());
+                }
+            }
+            Ok(None) => (),
+            Err(error) => log::debug!(
+                "{}",
+                error.display_chain_with_msg("Failed to find the default route")
+            ),
+        }
+    }
+    interfaces
+}
+
+fn resolv_conf_nameservers() -> io::Result<Vec<IpAddr>> {
+    let contents = fs::read(RESOLV_CONF_PATH)?;
+    let config = resolv_conf::Config::parse(contents)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    Ok(config
+        .nameservers
+        .into_iter()
+        .map(|server| match server {
+            resolv_conf::ScopedIp::V4(address) => IpAddr::V4(address),
+            resolv_conf::ScopedIp::V6(address, _) => IpAddr::V6(address),
+        })
+        .collect())
+}
+
 /// Returns true if DnsMonitor will use NetworkManager to manage DNS.
 pub fn will_use_nm() -> bool {
     crate::dns::imp::SystemdResolved::new().is_err()
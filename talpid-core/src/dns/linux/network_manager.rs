@@ -1,4 +1,5 @@
-use std::net::IpAddr;
+use crate::dns::watchdog::{same_servers, PolledDnsConfig, PollingWatchdog, TamperNotifier};
+use std::{io, net::IpAddr};
 pub use talpid_dbus::network_manager::Error;
 use talpid_dbus::network_manager::{self, DeviceConfig, NetworkManager as DBus};
 
@@ -8,6 +9,7 @@ pub struct NetworkManager {
     pub connection: DBus,
     device: Option<String>,
     settings_backup: Option<DeviceConfig>,
+    watchdog: Option<PollingWatchdog>,
 }
 
 impl NetworkManager {
@@ -18,18 +20,37 @@ impl NetworkManager {
             connection,
             device: None,
             settings_backup: None,
+            watchdog: None,
         };
         Ok(manager)
     }
 
-    pub fn set_dns(&mut self, interface_name: &str, servers: &[IpAddr]) -> Result<()> {
+    /// Sets the DNS servers of the tunnel device. They are restored whenever another program
+    /// overwrites the nameservers in resolv.conf.
+    pub fn set_dns(
+        &mut self,
+        interface_name: &str,
+        servers: &[IpAddr],
+        notifier: &TamperNotifier,
+    ) -> Result<()> {
         let old_settings = self.connection.set_dns(interface_name, servers)?;
         self.settings_backup = Some(old_settings);
         self.device = Some(interface_name.to_string());
+        let config = DeviceDnsConfig {
+            connection: DBus::new()?,
+            interface_name: interface_name.to_owned(),
+            servers: servers.to_vec(),
+        };
+        self.watchdog = Some(PollingWatchdog::start(
+            "NetworkManager DNS config",
+            config,
+            notifier.clone(),
+        ));
         Ok(())
     }
 
     pub fn reset(&mut self) -> Result<()> {
+        self.watchdog = None;
         if let Some(settings_backup) = self.settings_backup.take() {
             let device = match self.device.take() {
                 Some(device) => device,
@@ -51,3 +72,31 @@ impl NetworkManager {
         Ok(())
     }
 }
+
+/// The DNS config of the tunnel device, which is enforced by a [`PollingWatchdog`]. NetworkManager
+/// writes the nameservers of the device to resolv.conf, so any other nameservers there mean that
+/// the config has been overwritten.
+struct DeviceDnsConfig {
+    connection: DBus,
+    interface_name: String,
+    servers: Vec<IpAddr>,
+}
+
+impl PolledDnsConfig for DeviceDnsConfig {
+    type Error = io::Error;
+
+    fn check(&mut self) -> io::Result<Option<String>> {
+        let nameservers = super::resolv_conf_nameservers()?;
+        if same_servers(&nameservers, &self.servers) {
+            return Ok(None);
+        }
+        Ok(Some(format!("nameservers {nameservers:?}")))
+    }
+
+    fn restore(&mut self) -> io::Result<()> {
+        self.connection
+            .set_dns(&self.interface_name, &self.servers)
+            .map(|_| ())
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+    }
+}
@@ -1,13 +1,39 @@
+use crate::dns::watchdog::{Decision, RestoreThrottle, TamperNotifier};
 use futures::StreamExt;
 use inotify::{Inotify, WatchMask};
 use parking_lot::Mutex;
 use resolv_conf::{Config, ScopedIp};
-use std::{fs, io, net::IpAddr, sync::Arc};
+use std::{
+    ffi::OsString,
+    fs, io,
+    net::IpAddr,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 use talpid_types::ErrorExt;
 use triggered::{trigger, Listener, Trigger};
 
-const RESOLV_CONF_BACKUP_PATH: &str = "/etc/resolv.conf.mullvadbackup";
 const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+/// Location of the backup before it was moved to the cache directory.
+const LEGACY_RESOLV_CONF_BACKUP_PATH: &str = "/etc/resolv.conf.mullvadbackup";
+const RESOLV_CONF_BACKUP_FILENAME: &str = "resolv.conf.mullvadbackup";
+const RESOLV_CONF_LINK_BACKUP_FILENAME: &str = "resolv.conf.mullvadlink";
+
+/// Flag set on files that have been made immutable with `chattr +i`. See `linux/fs.h`.
+const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+
+// `FS_IOC_GETFLAGS` is defined in terms of a `long`, but the kernel only ever reads and writes an
+// `int`.
+nix::ioctl_read_bad!(
+    get_inode_flags,
+    nix::request_code_read!(b'f', 1, std::mem::size_of::<libc::c_long>()),
+    libc::c_int
+);
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -17,32 +43,121 @@ pub enum Error {
     WatchResolvConf(#[error(source)] std::io::Error),
 
     #[error(display = "Failed to write to {}", _0)]
-    WriteResolvConf(&'static str, #[error(source)] io::Error),
+    WriteResolvConf(String, #[error(source)] io::Error),
 
     #[error(display = "Failed to read from {}", _0)]
-    ReadResolvConf(&'static str, #[error(source)] io::Error),
+    ReadResolvConf(String, #[error(source)] io::Error),
 
     #[error(display = "resolv.conf at {} could not be parsed", _0)]
-    Parse(&'static str, #[error(source)] resolv_conf::ParseError),
+    Parse(String, #[error(source)] resolv_conf::ParseError),
 
     #[error(display = "Failed to remove stale resolv.conf backup at {}", _0)]
-    RemoveBackup(&'static str, #[error(source)] io::Error),
+    RemoveBackup(String, #[error(source)] io::Error),
+
+    #[error(display = "Failed to read the file attributes of {}", _0)]
+    ReadAttributes(String, #[error(source)] io::Error),
+
+    #[error(
+        display = "{} is immutable. Run 'chattr -i {}' to allow DNS to be managed",
+        _0,
+        _0
+    )]
+    Immutable(String),
+
+    #[error(display = "Failed to restore {} as a symlink to {}", _0, _1)]
+    RestoreSymlink(String, String, #[error(source)] io::Error),
+}
+
+/// Locations of resolv.conf and of the files that record its original state, so that it can be
+/// restored after a crash.
+#[derive(Debug, Clone)]
+struct Paths {
+    resolv_conf: PathBuf,
+    /// Contents of resolv.conf before DNS was set.
+    backup: PathBuf,
+    /// Target of the symlink that resolv.conf was, if it was one.
+    link_backup: PathBuf,
+    /// Backup written by older versions.
+    legacy_backup: PathBuf,
+}
+
+impl Paths {
+    fn new(cache_dir: &Path) -> Self {
+        Paths {
+            resolv_conf: PathBuf::from(RESOLV_CONF_PATH),
+            backup: cache_dir.join(RESOLV_CONF_BACKUP_FILENAME),
+            link_backup: cache_dir.join(RESOLV_CONF_LINK_BACKUP_FILENAME),
+            legacy_backup: PathBuf::from(LEGACY_RESOLV_CONF_BACKUP_PATH),
+        }
+    }
+}
+
+/// What resolv.conf looks like before it is managed by us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Layout {
+    Missing,
+    File {
+        immutable: bool,
+    },
+    /// resolv.conf is a symlink, typically into a file owned by another program such as
+    /// NetworkManager. Instead of writing to that file, the link itself is replaced while DNS is
+    /// set, and recreated afterwards.
+    Symlink {
+        target: PathBuf,
+    },
+}
+
+impl Layout {
+    fn inspect(path: &Path) -> Result<Self> {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Layout::Missing),
+            Err(error) => return Err(Error::ReadResolvConf(display(path), error)),
+        };
+
+        if metadata.file_type().is_symlink() {
+            let target =
+                fs::read_link(path).map_err(|error| Error::ReadResolvConf(display(path), error))?;
+            return Ok(Layout::Symlink { target });
+        }
+
+        let immutable =
+            is_immutable(path).map_err(|error| Error::ReadAttributes(display(path), error))?;
+        Ok(Layout::File { immutable })
+    }
+
+    /// Fails with an error describing what has to be changed if resolv.conf cannot be managed.
+    fn check_manageable(&self, path: &Path) -> Result<()> {
+        match self {
+            Layout::File { immutable: true } => Err(Error::Immutable(display(path))),
+            _ => Ok(()),
+        }
+    }
 }
 
 pub struct StaticResolvConf {
+    paths: Arc<Paths>,
     state: Arc<Mutex<Option<State>>>,
+    notifier: TamperNotifier,
     _watcher: DnsWatcher,
 }
 
 impl StaticResolvConf {
-    pub fn new() -> Result<Self> {
-        restore_from_backup()?;
+    pub fn new(cache_dir: &Path, notifier: TamperNotifier) -> Result<Self> {
+        Self::with_paths(Paths::new(cache_dir), notifier)
+    }
 
+    fn with_paths(paths: Paths, notifier: TamperNotifier) -> Result<Self> {
+        restore_from_backup(&paths)?;
+
+        let paths = Arc::new(paths);
         let state = Arc::new(Mutex::new(None));
-        let watcher = DnsWatcher::start(state.clone())?;
+        let watcher = DnsWatcher::start(paths.clone(), state.clone(), notifier.clone())?;
 
         Ok(StaticResolvConf {
+            paths,
             state,
+            notifier,
             _watcher: watcher,
         })
     }
@@ -51,17 +166,34 @@ impl StaticResolvConf {
         let mut state = self.state.lock();
         let new_state = match state.take() {
             None => {
-                let backup = read_config()?;
-                write_backup(&backup)?;
+                let layout = Layout::inspect(&self.paths.resolv_conf)?;
+                layout.check_manageable(&self.paths.resolv_conf)?;
+
+                let backup = read_config(&self.paths)?;
+                write_backup(&self.paths, &backup)?;
+
+                let original_link = match layout {
+                    Layout::Symlink { target } => {
+                        log::debug!(
+                            "{} is a symlink to {}, replacing it while DNS is set",
+                            self.paths.resolv_conf.display(),
+                            target.display()
+                        );
+                        write_link_backup(&self.paths, &target)?;
+                        Some(target)
+                    }
+                    Layout::Missing | Layout::File { .. } => None,
+                };
 
                 State {
                     backup,
+                    original_link,
                     desired_dns: servers,
                 }
             }
             Some(previous_state) => State {
-                backup: previous_state.backup,
                 desired_dns: servers,
+                ..previous_state
             },
         };
 
@@ -69,13 +201,28 @@ impl StaticResolvConf {
 
         *state = Some(new_state);
 
-        write_config(&new_config)
+        if write_config(&self.paths, &new_config)? {
+            // The watch was added to the symlink, which no longer exists
+            drop(state);
+            self._watcher = DnsWatcher::start(
+                self.paths.clone(),
+                self.state.clone(),
+                self.notifier.clone(),
+            )?;
+        }
+        Ok(())
     }
 
     pub fn reset(&mut self) -> Result<()> {
         if let Some(state) = self.state.lock().take() {
-            write_config(&state.backup)?;
-            let _ = fs::remove_file(RESOLV_CONF_BACKUP_PATH);
+            match state.original_link {
+                Some(target) => restore_link(&self.paths, &target)?,
+                None => {
+                    write_config(&self.paths, &state.backup)?;
+                }
+            }
+            let _ = fs::remove_file(&self.paths.backup);
+            let _ = fs::remove_file(&self.paths.link_backup);
         }
 
         Ok(())
@@ -84,20 +231,27 @@ impl StaticResolvConf {
 
 struct State {
     backup: Config,
+    /// Target of the symlink that resolv.conf was replaced with, if it was one.
+    original_link: Option<PathBuf>,
     desired_dns: Vec<IpAddr>,
 }
-
 impl State {
     fn desired_config(&self) -> Config {
         let mut config = self.backup.clone();
+        config.nameservers = self.desired_nameservers();
+        config
+    }
 
-        config.nameservers = self
-            .desired_dns
+    fn desired_nameservers(&self) -> Vec<ScopedIp> {
+        self.desired_dns
             .iter()
             .map(|&address| ScopedIp::from(address))
-            .collect();
+            .collect()
+    }
 
-        config
+    /// Returns whether `config` uses other nameservers than the desired ones.
+    fn diverges_from(&self, config: &Config) -> bool {
+        config.nameservers != self.desired_nameservers()
     }
 }
 
@@ -112,7 +266,11 @@ impl Drop for DnsWatcher {
 }
 
 impl DnsWatcher {
-    fn start(state: Arc<Mutex<Option<State>>>) -> Result<Self> {
+    fn start(
+        paths: Arc<Paths>,
+        state: Arc<Mutex<Option<State>>>,
+        notifier: TamperNotifier,
+    ) -> Result<Self> {
         let watcher = Inotify::init().map_err(Error::WatchResolvConf)?;
         let mut mask = WatchMask::empty();
         // Documentation for the meaning of these masks can be found in `man inotify`
@@ -123,15 +281,24 @@ impl DnsWatcher {
         // DELETE_SELF is generated if the file watched is itself deleted
         mask.insert(WatchMask::DELETE_SELF);
         mask.insert(WatchMask::MOVE_SELF);
+        // If resolv.conf is a symlink, watch the link rather than the file owned by another
+        // program.
+        mask.insert(WatchMask::DONT_FOLLOW);
 
         watcher
             .watches()
-            .add(RESOLV_CONF_PATH, mask)
+            .add(&paths.resolv_conf, mask)
             .map_err(Error::WatchResolvConf)?;
 
         let (cancel_trigger, cancel_listener) = trigger();
 
-        tokio::spawn(async move { Self::event_loop(watcher, cancel_listener, &state).await });
+        tokio::spawn(Self::event_loop(
+            watcher,
+            cancel_listener,
+            paths,
+            state,
+            notifier,
+        ));
 
         Ok(DnsWatcher { cancel_trigger })
     }
@@ -139,13 +306,16 @@ impl DnsWatcher {
     async fn event_loop(
         watcher: Inotify,
         mut cancel_listener: Listener,
-        state: &Arc<Mutex<Option<State>>>,
+        paths: Arc<Paths>,
+        state: Arc<Mutex<Option<State>>>,
+        notifier: TamperNotifier,
     ) {
         const EVENT_BUFFER_SIZE: usize = 1024;
         let mut buffer = [0; EVENT_BUFFER_SIZE];
         let mut events = watcher
             .into_event_stream(&mut buffer)
             .expect("Could not read events for resolv.conf");
+        let mut throttle = RestoreThrottle::new();
 
         loop {
             tokio::select! {
@@ -153,85 +323,523 @@ impl DnsWatcher {
                     break;
                 },
                 Some(_) = events.next() => {
-                    let mut locked_state = state.lock();
-                    if let Err(error) = Self::update(locked_state.as_mut()) {
+                    let tampered = Self::check(&paths, state.lock().as_mut()).unwrap_or_else(|error| {
                         log::error!(
                             "{}",
                             error.display_chain_with_msg(
                                 "Failed to update DNS state after DNS settings changed"
                             )
                         );
+                        false
+                    });
+                    if !tampered {
+                        continue;
+                    }
+
+                    let delay = match throttle.register(Instant::now()) {
+                        Decision::Restore { delay } => delay,
+                        Decision::GiveUp => {
+                            log::error!(
+                                "{} keeps being overwritten, assuming DNS can't be set properly",
+                                paths.resolv_conf.display()
+                            );
+                            notifier.give_up();
+                            break;
+                        }
+                    };
+                    if !delay.is_zero() {
+                        log::debug!("Restoring {} in {:?}", paths.resolv_conf.display(), delay);
+                        tokio::select! {
+                            _ = &mut cancel_listener => break,
+                            _ = tokio::time::sleep(delay) => (),
+                        }
+                    }
+
+                    match Self::restore(&paths, state.lock().as_mut()) {
+                        Ok(true) => notifier.restored(),
+                        Ok(false) => (),
+                        Err(error) => {
+                            log::error!(
+                                "{}",
+                                error.display_chain_with_msg(&format!(
+                                    "Failed to restore {}",
+                                    paths.resolv_conf.display()
+                                ))
+                            );
+                        }
                     }
                 }
             }
         }
     }
 
-    fn update(state: Option<&mut State>) -> Result<()> {
-        if let Some(state) = state {
-            let mut new_config = read_config()?;
-            let desired_nameservers = state
-                .desired_dns
-                .iter()
-                .map(|&address| ScopedIp::from(address))
-                .collect();
-
-            if new_config.nameservers != desired_nameservers {
-                state.backup = new_config.clone();
-                new_config.nameservers = desired_nameservers;
+    /// Returns whether resolv.conf has been changed to use other nameservers. If not, any other
+    /// changes are kept and included in the backup.
+    fn check(paths: &Paths, state: Option<&mut State>) -> Result<bool> {
+        let state = match state {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        let (contents, mut new_config) = read_contents_and_config(paths)?;
+
+        if state.diverges_from(&new_config) {
+            log::warn!(
+                "{} was overwritten by another program. New contents:\n{}",
+                paths.resolv_conf.display(),
+                contents
+            );
+            Ok(true)
+        } else {
+            new_config.nameservers.clear();
+            new_config.nameservers.append(&mut state.backup.nameservers);
+            state.backup = new_config;
 
-                write_config(&new_config)
-            } else {
-                new_config.nameservers.clear();
-                new_config.nameservers.append(&mut state.backup.nameservers);
-                state.backup = new_config;
+            write_backup(paths, &state.backup)?;
+            Ok(false)
+        }
+    }
 
-                write_backup(&state.backup)
-            }
-        } else {
-            Ok(())
+    /// Re-applies the desired nameservers, unless resolv.conf has reverted to them in the
+    /// meantime. The overwritten config becomes the new backup. Returns whether the config had to
+    /// be restored.
+    fn restore(paths: &Paths, state: Option<&mut State>) -> Result<bool> {
+        let state = match state {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        let mut new_config = read_config(paths)?;
+        if !state.diverges_from(&new_config) {
+            return Ok(false);
         }
+
+        state.backup = new_config.clone();
+        new_config.nameservers = state.desired_nameservers();
+        write_config(paths, &new_config)?;
+        Ok(true)
     }
 }
 
-fn read_config() -> Result<Config> {
-    if !std::path::Path::new(RESOLV_CONF_PATH).exists() {
-        return Ok(Config::new());
+fn read_config(paths: &Paths) -> Result<Config> {
+    read_contents_and_config(paths).map(|(_, config)| config)
+}
+
+fn read_contents_and_config(paths: &Paths) -> Result<(String, Config)> {
+    let path = &paths.resolv_conf;
+    if !path.exists() {
+        return Ok((String::new(), Config::new()));
     }
 
-    let contents = fs::read_to_string(RESOLV_CONF_PATH)
-        .map_err(|e| Error::ReadResolvConf(RESOLV_CONF_PATH, e))?;
-    let config = Config::parse(contents).map_err(|e| Error::Parse(RESOLV_CONF_PATH, e))?;
+    let contents = fs::read_to_string(path).map_err(|e| Error::ReadResolvConf(display(path), e))?;
+    let config = Config::parse(&contents).map_err(|e| Error::Parse(display(path), e))?;
 
-    Ok(config)
+    Ok((contents, config))
 }
 
-fn write_config(config: &Config) -> Result<()> {
-    fs::write(RESOLV_CONF_PATH, config.to_string().as_bytes())
-        .map_err(|e| Error::WriteResolvConf(RESOLV_CONF_PATH, e))
+/// Writes `config` to resolv.conf. If resolv.conf is a symlink, the link itself is replaced with
+/// a regular file instead of writing to the file that it points to. Returns whether a symlink was
+/// replaced.
+fn write_config(paths: &Paths, config: &Config) -> Result<bool> {
+    let path = &paths.resolv_conf;
+    let contents = config.to_string();
+    let is_symlink = fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+
+    let result = if is_symlink {
+        let temp_path = temp_path(path);
+        let _ = fs::remove_file(&temp_path);
+        fs::write(&temp_path, contents.as_bytes()).and_then(|()| fs::rename(&temp_path, path))
+    } else {
+        fs::write(path, contents.as_bytes())
+    };
+    result
+        .map(|()| is_symlink)
+        .map_err(|e| Error::WriteResolvConf(display(path), e))
 }
 
-fn write_backup(backup: &Config) -> Result<()> {
-    fs::write(RESOLV_CONF_BACKUP_PATH, backup.to_string().as_bytes())
-        .map_err(|e| Error::WriteResolvConf(RESOLV_CONF_BACKUP_PATH, e))
+fn write_backup(paths: &Paths, backup: &Config) -> Result<()> {
+    fs::write(&paths.backup, backup.to_string().as_bytes())
+        .map_err(|e| Error::WriteResolvConf(display(&paths.backup), e))
 }
 
-fn restore_from_backup() -> Result<()> {
-    match fs::read_to_string(RESOLV_CONF_BACKUP_PATH) {
-        Ok(backup) => {
-            log::info!("Restoring DNS state from backup");
-            let config =
-                Config::parse(backup).map_err(|e| Error::Parse(RESOLV_CONF_BACKUP_PATH, e))?;
+fn write_link_backup(paths: &Paths, target: &Path) -> Result<()> {
+    fs::write(&paths.link_backup, target.as_os_str().as_bytes())
+        .map_err(|e| Error::WriteResolvConf(display(&paths.link_backup), e))
+}
 
-            write_config(&config)?;
+/// Atomically replaces resolv.conf with a symlink to `target`.
+fn restore_link(paths: &Paths, target: &Path) -> Result<()> {
+    let path = &paths.resolv_conf;
+    let temp_path = temp_path(path);
+    let _ = fs::remove_file(&temp_path);
+    std::os::unix::fs::symlink(target, &temp_path)
+        .and_then(|()| fs::rename(&temp_path, path))
+        .map_err(|e| Error::RestoreSymlink(display(path), display(target), e))
+}
 
-            fs::remove_file(RESOLV_CONF_BACKUP_PATH)
-                .map_err(|e| Error::RemoveBackup(RESOLV_CONF_BACKUP_PATH, e))
+fn restore_from_backup(paths: &Paths) -> Result<()> {
+    match fs::read(&paths.link_backup) {
+        Ok(target) => {
+            let target = PathBuf::from(OsString::from_vec(target));
+            log::info!(
+                "Restoring {} as a symlink to {}",
+                paths.resolv_conf.display(),
+                target.display()
+            );
+            restore_link(paths, &target)?;
+
+            let _ = fs::remove_file(&paths.backup);
+            return fs::remove_file(&paths.link_backup)
+                .map_err(|e| Error::RemoveBackup(display(&paths.link_backup), e));
         }
-        Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
-            log::debug!("No DNS state backup to restore");
-            Ok(())
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => return Err(Error::ReadResolvConf(display(&paths.link_backup), error)),
+    }
+
+    for backup_path in [&paths.backup, &paths.legacy_backup] {
+        match fs::read_to_string(backup_path) {
+            Ok(backup) => {
+                log::info!("Restoring DNS state from backup");
+                let config =
+                    Config::parse(backup).map_err(|e| Error::Parse(display(backup_path), e))?;
+
+                write_config(paths, &config)?;
+
+                return fs::remove_file(backup_path)
+                    .map_err(|e| Error::RemoveBackup(display(backup_path), e));
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(Error::ReadResolvConf(display(backup_path), error)),
         }
-        Err(error) => Err(Error::ReadResolvConf(RESOLV_CONF_BACKUP_PATH, error)),
+    }
+
+    log::debug!("No DNS state backup to restore");
+    Ok(())
+}
+
+/// Returns whether the immutable attribute is set on the file at `path`.
+fn is_immutable(path: &Path) -> io::Result<bool> {
+    let file = fs::File::open(path)?;
+    let mut flags: libc::c_int = 0;
+    // SAFETY: `file` is open for as long as the call lasts, and `flags` is large enough to hold
+    // the result.
+    match unsafe { get_inode_flags(file.as_raw_fd(), &mut flags) } {
+        Ok(_) => Ok(flags & FS_IMMUTABLE_FL != 0),
+        // The file system does not support inode flags, so the file cannot be immutable
+        Err(nix::errno::Errno::ENOTTY) | Err(nix::errno::Errno::EOPNOTSUPP) => Ok(false),
+        Err(errno) => Err(io::Error::from_raw_os_error(errno as i32)),
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".mullvadtmp");
+    path.with_file_name(file_name)
+}
+
+fn display(path: &Path) -> String {
+    path.display().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::mpsc;
+    use std::{net::Ipv4Addr, sync::Weak, time::Duration};
+    use tempfile::TempDir;
+
+    const ORIGINAL: &str = "nameserver 192.168.1.1\nsearch lan\n";
+
+    fn state() -> State {
+        State {
+            backup: Config::parse(ORIGINAL).unwrap(),
+            original_link: None,
+            desired_dns: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+        }
+    }
+
+    const DESIRED: &str = "nameserver 10.64.0.1\nsearch lan\n";
+
+    /// Simulates `/etc` and a cache directory inside a temporary directory.
+    fn paths(dir: &TempDir) -> Paths {
+        fs::create_dir(dir.path().join("etc")).unwrap();
+        fs::create_dir(dir.path().join("cache")).unwrap();
+        Paths {
+            resolv_conf: dir.path().join("etc/resolv.conf"),
+            backup: dir.path().join("cache").join(RESOLV_CONF_BACKUP_FILENAME),
+            link_backup: dir
+                .path()
+                .join("cache")
+                .join(RESOLV_CONF_LINK_BACKUP_FILENAME),
+            legacy_backup: dir.path().join("etc/resolv.conf.mullvadbackup"),
+        }
+    }
+
+    /// Creates a file owned by "another program" that resolv.conf can link to.
+    fn foreign_file(dir: &TempDir) -> PathBuf {
+        let foreign_dir = dir.path().join("run/NetworkManager");
+        fs::create_dir_all(&foreign_dir).unwrap();
+        let target = foreign_dir.join("resolv.conf");
+        fs::write(&target, ORIGINAL).unwrap();
+        target
+    }
+
+    fn notifier() -> TamperNotifier {
+        let (tampered_tx, _) = mpsc::unbounded();
+        TamperNotifier::new(Weak::new(), tampered_tx)
+    }
+
+    fn config_of(contents: &str) -> Config {
+        Config::parse(contents).unwrap()
+    }
+
+    fn config(path: &Path) -> Config {
+        config_of(&fs::read_to_string(path).unwrap())
+    }
+
+    fn nameservers(path: &Path) -> Vec<ScopedIp> {
+        config(path).nameservers
+    }
+
+    fn no_backups(paths: &Paths) -> bool {
+        !paths.backup.exists() && !paths.link_backup.exists() && !paths.legacy_backup.exists()
+    }
+
+    #[test]
+    fn test_inspect_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = paths(&dir);
+        assert_eq!(
+            Layout::inspect(&paths.resolv_conf).unwrap(),
+            Layout::Missing
+        );
+
+        fs::write(&paths.resolv_conf, ORIGINAL).unwrap();
+        assert_eq!(
+            Layout::inspect(&paths.resolv_conf).unwrap(),
+            Layout::File { immutable: false }
+        );
+
+        let target = foreign_file(&dir);
+        fs::remove_file(&paths.resolv_conf).unwrap();
+        std::os::unix::fs::symlink(&target, &paths.resolv_conf).unwrap();
+        assert_eq!(
+            Layout::inspect(&paths.resolv_conf).unwrap(),
+            Layout::Symlink {
+                target: target.clone()
+            }
+        );
+
+        // A dangling symlink is still a symlink
+        fs::remove_file(&target).unwrap();
+        assert_eq!(
+            Layout::inspect(&paths.resolv_conf).unwrap(),
+            Layout::Symlink { target }
+        );
+    }
+
+    #[test]
+    fn test_immutable_file_is_not_manageable() {
+        let path = Path::new("/etc/resolv.conf");
+        assert!(matches!(
+            Layout::File { immutable: true }.check_manageable(path),
+            Err(Error::Immutable(_))
+        ));
+        for layout in [
+            Layout::Missing,
+            Layout::File { immutable: false },
+            Layout::Symlink {
+                target: PathBuf::from("/run/NetworkManager/resolv.conf"),
+            },
+        ] {
+            assert!(layout.check_manageable(path).is_ok(), "{layout:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_and_reset_regular_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = paths(&dir);
+        fs::write(&paths.resolv_conf, ORIGINAL).unwrap();
+
+        let mut resolv_conf = StaticResolvConf::with_paths(paths.clone(), notifier()).unwrap();
+        resolv_conf.set_dns(state().desired_dns).unwrap();
+        assert_eq!(config(&paths.resolv_conf), config_of(DESIRED));
+        assert_eq!(config(&paths.backup), config_of(ORIGINAL));
+        assert!(!paths.link_backup.exists());
+
+        resolv_conf.reset().unwrap();
+        assert_eq!(config(&paths.resolv_conf), config_of(ORIGINAL));
+        assert!(no_backups(&paths));
+    }
+
+    #[tokio::test]
+    async fn test_set_and_reset_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = paths(&dir);
+        let target = foreign_file(&dir);
+        std::os::unix::fs::symlink(&target, &paths.resolv_conf).unwrap();
+
+        let mut resolv_conf = StaticResolvConf::with_paths(paths.clone(), notifier()).unwrap();
+        resolv_conf.set_dns(state().desired_dns).unwrap();
+
+        // The link is replaced, and the file of the other program is left alone
+        assert_eq!(
+            Layout::inspect(&paths.resolv_conf).unwrap(),
+            Layout::File { immutable: false }
+        );
+        assert_eq!(config(&paths.resolv_conf), config_of(DESIRED));
+        assert_eq!(fs::read_to_string(&target).unwrap(), ORIGINAL);
+        assert_eq!(
+            fs::read(&paths.link_backup).unwrap(),
+            target.as_os_str().as_bytes()
+        );
+
+        // Updating the servers keeps the original link around
+        let servers = vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))];
+        resolv_conf.set_dns(servers.clone()).unwrap();
+        assert_eq!(
+            nameservers(&paths.resolv_conf),
+            vec![ScopedIp::from(servers[0])]
+        );
+
+        resolv_conf.reset().unwrap();
+        assert_eq!(
+            Layout::inspect(&paths.resolv_conf).unwrap(),
+            Layout::Symlink {
+                target: target.clone()
+            }
+        );
+        assert_eq!(fs::read_to_string(&target).unwrap(), ORIGINAL);
+        assert!(no_backups(&paths));
+    }
+
+    #[tokio::test]
+    async fn test_set_dangling_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = paths(&dir);
+        let target = dir.path().join("run/NetworkManager/resolv.conf");
+        std::os::unix::fs::symlink(&target, &paths.resolv_conf).unwrap();
+
+        let mut resolv_conf = StaticResolvConf::with_paths(paths.clone(), notifier()).unwrap();
+        resolv_conf.set_dns(state().desired_dns).unwrap();
+        assert_eq!(
+            nameservers(&paths.resolv_conf),
+            state().desired_nameservers()
+        );
+
+        resolv_conf.reset().unwrap();
+        assert_eq!(
+            Layout::inspect(&paths.resolv_conf).unwrap(),
+            Layout::Symlink { target }
+        );
+        assert!(no_backups(&paths));
+    }
+
+    #[tokio::test]
+    async fn test_recover_symlink_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = paths(&dir);
+        let target = foreign_file(&dir);
+        std::os::unix::fs::symlink(&target, &paths.resolv_conf).unwrap();
+
+        let mut resolv_conf = StaticResolvConf::with_paths(paths.clone(), notifier()).unwrap();
+        resolv_conf.set_dns(state().desired_dns).unwrap();
+        // Simulate a crash by not resetting
+        drop(resolv_conf);
+
+        let _resolv_conf = StaticResolvConf::with_paths(paths.clone(), notifier()).unwrap();
+        assert_eq!(
+            Layout::inspect(&paths.resolv_conf).unwrap(),
+            Layout::Symlink { target }
+        );
+        assert!(no_backups(&paths));
+    }
+
+    #[test]
+    fn test_recover_file_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = paths(&dir);
+        fs::write(&paths.resolv_conf, DESIRED).unwrap();
+        fs::write(&paths.backup, ORIGINAL).unwrap();
+
+        restore_from_backup(&paths).unwrap();
+        assert_eq!(config(&paths.resolv_conf), config_of(ORIGINAL));
+        assert!(no_backups(&paths));
+    }
+
+    /// Backups written by older versions are still restored.
+    #[test]
+    fn test_recover_from_legacy_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = paths(&dir);
+        fs::write(&paths.resolv_conf, DESIRED).unwrap();
+        fs::write(&paths.legacy_backup, ORIGINAL).unwrap();
+
+        restore_from_backup(&paths).unwrap();
+        assert_eq!(config(&paths.resolv_conf), config_of(ORIGINAL));
+        assert!(no_backups(&paths));
+    }
+
+    #[test]
+    fn test_desired_config_does_not_diverge() {
+        let state = state();
+        assert!(!state.diverges_from(&state.desired_config()));
+    }
+
+    #[test]
+    fn test_other_changes_do_not_diverge() {
+        let state = state();
+        let config =
+            Config::parse("nameserver 10.64.0.1\nsearch example.com\noptions ndots:2\n").unwrap();
+        assert!(!state.diverges_from(&config));
+    }
+
+    #[test]
+    fn test_overwritten_nameservers_diverge() {
+        let state = state();
+        for contents in [
+            ORIGINAL,
+            "",
+            "nameserver 10.64.0.1\nnameserver 1.1.1.1\n",
+            "nameserver 127.0.0.53\n",
+        ] {
+            let config = Config::parse(contents).unwrap();
+            assert!(state.diverges_from(&config), "{contents:?}");
+        }
+    }
+
+    /// Simulate another program that rewrites resolv.conf every second, with our own
+    /// restorations in between. The watchdog should eventually give up.
+    #[test]
+    fn test_fighting_program_gives_up() {
+        let state = state();
+        let restored = state.desired_config().to_string();
+        let mut throttle = RestoreThrottle::new();
+        let start = Instant::now();
+
+        let mut restorations = 0;
+        let mut decision = None;
+        for second in 0..60 {
+            for contents in [ORIGINAL, restored.as_str()] {
+                let config = Config::parse(contents).unwrap();
+                if !state.diverges_from(&config) {
+                    continue;
+                }
+                match throttle.register(start + Duration::from_secs(second)) {
+                    Decision::Restore { .. } => restorations += 1,
+                    Decision::GiveUp => {
+                        decision = Some(Decision::GiveUp);
+                        break;
+                    }
+                }
+            }
+            if decision.is_some() {
+                break;
+            }
+        }
+
+        assert_eq!(decision, Some(Decision::GiveUp));
+        assert_eq!(restorations, 5);
     }
 }
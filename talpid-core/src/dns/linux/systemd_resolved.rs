@@ -1,6 +1,9 @@
-use crate::linux::{iface_index, IfaceIndexLookupError};
+use crate::{
+    dns::watchdog::{PolledDnsConfig, PollingWatchdog, TamperNotifier},
+    linux::{iface_index, IfaceIndexLookupError},
+};
 use std::net::IpAddr;
-use talpid_dbus::systemd_resolved::{AsyncHandle, SystemdResolved as DbusInterface};
+use talpid_dbus::systemd_resolved::SystemdResolved as DbusInterface;
 use talpid_routing::RouteManagerHandle;
 use talpid_types::ErrorExt;
 
@@ -8,75 +11,664 @@ pub(crate) use talpid_dbus::systemd_resolved::Error as SystemdDbusError;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The routing domain that matches all lookups. systemd-resolved represents `~.` as the domain
+/// `.` with the routing-only flag set.
+const ROUTE_ALL_DOMAIN: &str = ".";
+
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
     #[error(display = "systemd-resolved operation failed")]
-    SystemdResolvedError(#[error(source)] SystemdDbusError),
+    SystemdResolved(#[error(source)] SystemdDbusError),
 
     #[error(display = "Failed to resolve interface index with error {}", _0)]
-    InterfaceNameError(#[error(source)] IfaceIndexLookupError),
+    InterfaceName(#[error(source)] IfaceIndexLookupError),
+
+    #[error(display = "Async systemd-resolved task failed")]
+    AsyncTask(#[error(source)] tokio::task::JoinError),
+}
+
+/// The subset of the systemd-resolved D-Bus API that is used to configure the tunnel link.
+trait ResolvedBackend {
+    fn disable_dot(&self, interface_index: u32) -> std::result::Result<(), SystemdDbusError>;
+
+    fn set_domains(
+        &self,
+        interface_index: u32,
+        domains: &[(&str, bool)],
+    ) -> std::result::Result<(), SystemdDbusError>;
+
+    fn set_default_route(
+        &self,
+        interface_index: u32,
+        default_route: bool,
+    ) -> std::result::Result<(), SystemdDbusError>;
+
+    fn set_dns(
+        &self,
+        interface_index: u32,
+        servers: &[IpAddr],
+    ) -> std::result::Result<(), SystemdDbusError>;
+
+    fn get_all_domains(&self) -> std::result::Result<Vec<(i32, String, bool)>, SystemdDbusError>;
+
+    fn get_all_dns(&self) -> std::result::Result<Vec<(i32, IpAddr)>, SystemdDbusError>;
+
+    /// Returns `None` if the default route setting is not supported.
+    fn get_default_route(
+        &self,
+        interface_index: u32,
+    ) -> std::result::Result<Option<bool>, SystemdDbusError>;
+
+    /// Returns `false` if reverting links is not supported.
+    fn revert_link(&self, interface_index: u32) -> std::result::Result<bool, SystemdDbusError>;
+}
+
+impl ResolvedBackend for DbusInterface {
+    fn disable_dot(&self, interface_index: u32) -> std::result::Result<(), SystemdDbusError> {
+        self.link_disable_dns_over_tls(interface_index)
+    }
+
+    fn set_domains(
+        &self,
+        interface_index: u32,
+        domains: &[(&str, bool)],
+    ) -> std::result::Result<(), SystemdDbusError> {
+        DbusInterface::set_domains(self, interface_index, domains)
+    }
+
+    fn set_default_route(
+        &self,
+        interface_index: u32,
+        default_route: bool,
+    ) -> std::result::Result<(), SystemdDbusError> {
+        DbusInterface::set_default_route(self, interface_index, default_route)
+    }
+
+    fn set_dns(
+        &self,
+        interface_index: u32,
+        servers: &[IpAddr],
+    ) -> std::result::Result<(), SystemdDbusError> {
+        DbusInterface::set_dns(self, interface_index, servers.to_vec()).map(|_| ())
+    }
+
+    fn get_all_domains(&self) -> std::result::Result<Vec<(i32, String, bool)>, SystemdDbusError> {
+        DbusInterface::get_all_domains(self)
+    }
+
+    fn get_all_dns(&self) -> std::result::Result<Vec<(i32, IpAddr)>, SystemdDbusError> {
+        DbusInterface::get_all_dns(self)
+    }
+
+    fn get_default_route(
+        &self,
+        interface_index: u32,
+    ) -> std::result::Result<Option<bool>, SystemdDbusError> {
+        DbusInterface::get_default_route(self, interface_index)
+    }
+
+    fn revert_link(&self, interface_index: u32) -> std::result::Result<bool, SystemdDbusError> {
+        self.revert_link_by_index(interface_index)
+    }
 }
 
 pub struct SystemdResolved {
-    pub dbus_interface: AsyncHandle,
-    tunnel_index: u32,
+    pub dbus_interface: DbusInterface,
+    tunnel_index: Option<u32>,
+    original_config: LinkConfig,
+    watchdog: Option<PollingWatchdog>,
 }
 
 impl SystemdResolved {
     pub fn new() -> Result<Self> {
-        let dbus_interface = DbusInterface::new()?.async_handle();
+        let dbus_interface = DbusInterface::new()?;
 
         let systemd_resolved = SystemdResolved {
             dbus_interface,
-            tunnel_index: 0,
+            tunnel_index: None,
+            original_config: LinkConfig::default(),
+            watchdog: None,
         };
 
         Ok(systemd_resolved)
     }
 
+    /// Configures the servers on the tunnel link and makes it the only link used for lookups
+    /// that don't match a more specific routing domain, using `~.` as its only routing domain.
+    /// Domains of other links are never modified, so they keep being resolved by the resolvers of
+    /// those links. If `preserve_search_domains` is set, the search domains of other links are also
+    /// added as search domains to the tunnel link, so that single-label names keep being completed
+    /// with them. Routing-only domains of other links are not added. Since systemd-resolved also
+    /// routes lookups by search domains, names under a preserved search domain are then resolved
+    /// via the tunnel as well as via the link that the domain belongs to. The config is restored
+    /// whenever another program changes it.
+    ///
+    /// The link does not have to be a tunnel. If it already has a config, such as one obtained
+    /// via DHCP, that config is put back by [`Self::reset`].
     pub async fn set_dns(
         &mut self,
         _route_manager: RouteManagerHandle,
         interface_name: &str,
         servers: &[IpAddr],
+        preserve_search_domains: bool,
+        notifier: &TamperNotifier,
     ) -> Result<()> {
         let tunnel_index = iface_index(interface_name)?;
-        self.tunnel_index = tunnel_index;
+        self.tunnel_index = Some(tunnel_index);
 
-        if let Err(error) = self.dbus_interface.disable_dot(self.tunnel_index).await {
-            log::error!("Failed to disable DoT: {}", error.display_chain());
+        let mut config = TunnelLinkConfig {
+            dbus_interface: self.dbus_interface.clone(),
+            tunnel_index,
+            servers: servers.to_vec(),
+            preserve_search_domains,
+        };
+        let (original_config, config) = tokio::task::spawn_blocking(move || {
+            let original_config = link_config(&config.dbus_interface, tunnel_index)?;
+            config.restore().map(|()| (original_config, config))
+        })
+        .await??;
+        self.original_config = original_config;
+        self.watchdog = Some(PollingWatchdog::start(
+            "systemd-resolved tunnel link config",
+            config,
+            notifier.clone(),
+        ));
+        Ok(())
+    }
+
+    pub async fn reset(&mut self) -> Result<()> {
+        self.watchdog = None;
+        let tunnel_index = match self.tunnel_index.take() {
+            Some(tunnel_index) => tunnel_index,
+            None => return Ok(()),
+        };
+
+        let dbus_interface = self.dbus_interface.clone();
+        let original_config = std::mem::take(&mut self.original_config);
+        tokio::task::spawn_blocking(move || {
+            reset_link_config(&dbus_interface, tunnel_index, &original_config)
+        })
+        .await?
+    }
+}
+
+/// The DNS config that a link had before it was overridden by [`apply_tunnel_config`].
+#[derive(Debug, Default, Clone, PartialEq)]
+struct LinkConfig {
+    servers: Vec<IpAddr>,
+    domains: Vec<(String, bool)>,
+    default_route: Option<bool>,
+}
+
+impl LinkConfig {
+    /// Returns whether no other program has configured DNS for the link.
+    fn is_empty(&self) -> bool {
+        self.servers.is_empty() && self.domains.is_empty()
+    }
+}
+
+fn link_config(backend: &impl ResolvedBackend, interface_index: u32) -> Result<LinkConfig> {
+    let servers = backend
+        .get_all_dns()?
+        .into_iter()
+        .filter(|(index, _)| *index == interface_index as i32)
+        .map(|(_, server)| server)
+        .collect();
+    let domains = backend
+        .get_all_domains()?
+        .into_iter()
+        .filter(|(index, ..)| *index == interface_index as i32)
+        .map(|(_, domain, routing_only)| (domain, routing_only))
+        .collect();
+    let default_route = backend
+        .get_default_route(interface_index)
+        .unwrap_or_else(|error| {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Failed to obtain the default route setting")
+            );
+            None
+        });
+    Ok(LinkConfig {
+        servers,
+        domains,
+        default_route,
+    })
+}
+
+/// Undoes [`apply_tunnel_config`]. If another program had configured DNS for the link, such as
+/// the DHCP client of a physical link, its config is put back rather than reverting the link,
+/// since reverting would remove that config as well.
+fn reset_link_config(
+    backend: &impl ResolvedBackend,
+    interface_index: u32,
+    original_config: &LinkConfig,
+) -> Result<()> {
+    if original_config.is_empty() {
+        return revert_tunnel_config(backend, interface_index);
+    }
+
+    let domains: Vec<(&str, bool)> = original_config
+        .domains
+        .iter()
+        .map(|(domain, routing_only)| (domain.as_str(), *routing_only))
+        .collect();
+    backend.set_domains(interface_index, &domains)?;
+    if let Some(default_route) = original_config.default_route {
+        if let Err(error) = backend.set_default_route(interface_index, default_route) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to restore the default DNS route")
+            );
         }
+    }
+    backend.set_dns(interface_index, &original_config.servers)?;
 
-        if let Err(error) = self
-            .dbus_interface
-            .set_domains(tunnel_index, &[(".", true)])
-            .await
-        {
-            log::error!("Failed to set search domains: {}", error.display_chain());
+    Ok(())
+}
+
+/// The config of the tunnel link, which is enforced by a [`PollingWatchdog`].
+struct TunnelLinkConfig {
+    dbus_interface: DbusInterface,
+    tunnel_index: u32,
+    servers: Vec<IpAddr>,
+    preserve_search_domains: bool,
+}
+
+impl PolledDnsConfig for TunnelLinkConfig {
+    type Error = Error;
+
+    fn check(&mut self) -> Result<Option<String>> {
+        overwritten_tunnel_config(&self.dbus_interface, self.tunnel_index, &self.servers)
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        apply_tunnel_config(
+            &self.dbus_interface,
+            self.tunnel_index,
+            &self.servers,
+            self.preserve_search_domains,
+        )
+    }
+}
+
+fn apply_tunnel_config(
+    backend: &impl ResolvedBackend,
+    tunnel_index: u32,
+    servers: &[IpAddr],
+    preserve_search_domains: bool,
+) -> Result<()> {
+    if let Err(error) = backend.disable_dot(tunnel_index) {
+        log::error!("Failed to disable DoT: {}", error.display_chain());
+    }
+
+    let mut domains = vec![(ROUTE_ALL_DOMAIN.to_owned(), true)];
+    if preserve_search_domains {
+        match backend.get_all_domains() {
+            Ok(all_domains) => {
+                domains.extend(
+                    other_link_domains(tunnel_index, all_domains)
+                        .into_iter()
+                        .map(|domain| (domain, false)),
+                );
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain the domains of other links")
+                );
+            }
         }
+    }
+    let domains: Vec<(&str, bool)> = domains
+        .iter()
+        .map(|(domain, routing_only)| (domain.as_str(), *routing_only))
+        .collect();
+    backend.set_domains(tunnel_index, &domains)?;
 
-        let _ = self
-            .dbus_interface
-            .set_dns(self.tunnel_index, servers.to_vec())
-            .await?;
+    if let Err(error) = backend.set_default_route(tunnel_index, true) {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to make tunnel the default DNS route")
+        );
+    }
 
-        Ok(())
+    backend.set_dns(tunnel_index, servers)?;
+
+    Ok(())
+}
+
+fn revert_tunnel_config(backend: &impl ResolvedBackend, tunnel_index: u32) -> Result<()> {
+    if backend.revert_link(tunnel_index)? {
+        return Ok(());
     }
 
-    pub async fn reset(&mut self) -> Result<()> {
-        if let Err(error) = self
-            .dbus_interface
-            .set_domains(self.tunnel_index, &[])
-            .await
-        {
-            log::error!("Failed to set search domains: {}", error.display_chain());
+    // Older versions of systemd-resolved can't revert links, so undo the changes manually.
+    if let Err(error) = backend.set_domains(tunnel_index, &[]) {
+        log::error!("Failed to set search domains: {}", error.display_chain());
+    }
+    backend.set_dns(tunnel_index, &[])?;
+
+    Ok(())
+}
+
+/// Returns a description of the config of the tunnel link if it no longer uses `servers`, or no
+/// longer routes all lookups, as set by [`apply_tunnel_config`].
+fn overwritten_tunnel_config(
+    backend: &impl ResolvedBackend,
+    tunnel_index: u32,
+    servers: &[IpAddr],
+) -> Result<Option<String>> {
+    let link_servers: Vec<IpAddr> = backend
+        .get_all_dns()?
+        .into_iter()
+        .filter(|(interface_index, _)| *interface_index == tunnel_index as i32)
+        .map(|(_, server)| server)
+        .collect();
+    let routes_all_domains =
+        backend
+            .get_all_domains()?
+            .into_iter()
+            .any(|(interface_index, domain, routing_only)| {
+                interface_index == tunnel_index as i32 && routing_only && domain == ROUTE_ALL_DOMAIN
+            });
+    if link_servers == servers && routes_all_domains {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "servers {link_servers:?}, routing all lookups: {routes_all_domains}"
+    )))
+}
+
+/// Returns the deduplicated search domains of all links except the tunnel link, including global
+/// ones. Routing-only domains are skipped, since they are not used to complete names.
+fn other_link_domains(tunnel_index: u32, all_domains: Vec<(i32, String, bool)>) -> Vec<String> {
+    let mut domains: Vec<String> = vec![];
+    for (interface_index, domain, routing_only) in all_domains {
+        if routing_only || interface_index == tunnel_index as i32 {
+            continue;
         }
+        let domain = domain.trim_end_matches('.');
+        if domain.is_empty() || domains.iter().any(|existing| existing == domain) {
+            continue;
+        }
+        domains.push(domain.to_owned());
+    }
+    domains
+}
 
-        let _ = self
-            .dbus_interface
-            .set_dns(self.tunnel_index, vec![])
-            .await?;
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{cell::RefCell, net::Ipv4Addr};
 
-        Ok(())
+    const TUNNEL_INDEX: u32 = 10;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        DisableDot(u32),
+        SetDomains(u32, Vec<(String, bool)>),
+        SetDefaultRoute(u32, bool),
+        SetDns(u32, Vec<IpAddr>),
+        GetAllDomains,
+        GetAllDns,
+        GetDefaultRoute(u32),
+        RevertLink(u32),
+    }
+
+    #[derive(Default)]
+    struct MockBackend {
+        calls: RefCell<Vec<Call>>,
+        domains: Vec<(i32, String, bool)>,
+        dns: Vec<(i32, IpAddr)>,
+        default_route: Option<bool>,
+        supports_revert: bool,
+    }
+
+    impl ResolvedBackend for MockBackend {
+        fn disable_dot(&self, interface_index: u32) -> std::result::Result<(), SystemdDbusError> {
+            self.calls
+                .borrow_mut()
+                .push(Call::DisableDot(interface_index));
+            Ok(())
+        }
+
+        fn set_domains(
+            &self,
+            interface_index: u32,
+            domains: &[(&str, bool)],
+        ) -> std::result::Result<(), SystemdDbusError> {
+            self.calls.borrow_mut().push(Call::SetDomains(
+                interface_index,
+                domains
+                    .iter()
+                    .map(|(domain, routing)| (domain.to_string(), *routing))
+                    .collect(),
+            ));
+            Ok(())
+        }
+
+        fn set_default_route(
+            &self,
+            interface_index: u32,
+            default_route: bool,
+        ) -> std::result::Result<(), SystemdDbusError> {
+            self.calls
+                .borrow_mut()
+                .push(Call::SetDefaultRoute(interface_index, default_route));
+            Ok(())
+        }
+
+        fn set_dns(
+            &self,
+            interface_index: u32,
+            servers: &[IpAddr],
+        ) -> std::result::Result<(), SystemdDbusError> {
+            self.calls
+                .borrow_mut()
+                .push(Call::SetDns(interface_index, servers.to_vec()));
+            Ok(())
+        }
+
+        fn get_all_domains(
+            &self,
+        ) -> std::result::Result<Vec<(i32, String, bool)>, SystemdDbusError> {
+            self.calls.borrow_mut().push(Call::GetAllDomains);
+            Ok(self.domains.clone())
+        }
+
+        fn get_all_dns(&self) -> std::result::Result<Vec<(i32, IpAddr)>, SystemdDbusError> {
+            self.calls.borrow_mut().push(Call::GetAllDns);
+            Ok(self.dns.clone())
+        }
+
+        fn get_default_route(
+            &self,
+            interface_index: u32,
+        ) -> std::result::Result<Option<bool>, SystemdDbusError> {
+            self.calls
+                .borrow_mut()
+                .push(Call::GetDefaultRoute(interface_index));
+            Ok(self.default_route)
+        }
+
+        fn revert_link(&self, interface_index: u32) -> std::result::Result<bool, SystemdDbusError> {
+            self.calls
+                .borrow_mut()
+                .push(Call::RevertLink(interface_index));
+            Ok(self.supports_revert)
+        }
+    }
+
+    fn split_dns_backend() -> MockBackend {
+        MockBackend {
+            domains: vec![
+                (2, "corp.example".to_owned(), false),
+                (2, "lan".to_owned(), true),
+                (3, "corp.example.".to_owned(), true),
+                (TUNNEL_INDEX as i32, "stale.example".to_owned(), true),
+                (0, ".".to_owned(), true),
+            ],
+            supports_revert: true,
+            ..Default::default()
+        }
+    }
+
+    fn servers() -> Vec<IpAddr> {
+        vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))]
+    }
+
+    #[test]
+    fn test_only_route_all_domain() {
+        let backend = split_dns_backend();
+        apply_tunnel_config(&backend, TUNNEL_INDEX, &servers(), false).unwrap();
+
+        assert_eq!(
+            backend.calls.into_inner(),
+            vec![
+                Call::DisableDot(TUNNEL_INDEX),
+                Call::SetDomains(TUNNEL_INDEX, vec![(".".to_owned(), true)]),
+                Call::SetDefaultRoute(TUNNEL_INDEX, true),
+                Call::SetDns(TUNNEL_INDEX, servers()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preserve_search_domains() {
+        let backend = split_dns_backend();
+        apply_tunnel_config(&backend, TUNNEL_INDEX, &servers(), true).unwrap();
+
+        assert_eq!(
+            backend.calls.into_inner(),
+            vec![
+                Call::DisableDot(TUNNEL_INDEX),
+                Call::GetAllDomains,
+                Call::SetDomains(
+                    TUNNEL_INDEX,
+                    vec![(".".to_owned(), true), ("corp.example".to_owned(), false)]
+                ),
+                Call::SetDefaultRoute(TUNNEL_INDEX, true),
+                Call::SetDns(TUNNEL_INDEX, servers()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_overwritten_tunnel_config() {
+        let mut backend = split_dns_backend();
+        backend
+            .domains
+            .push((TUNNEL_INDEX as i32, ".".to_owned(), true));
+        backend.dns = vec![
+            (2, "192.168.1.1".parse().unwrap()),
+            (TUNNEL_INDEX as i32, servers()[0]),
+        ];
+        assert_eq!(
+            overwritten_tunnel_config(&backend, TUNNEL_INDEX, &servers()).unwrap(),
+            None
+        );
+
+        // Other servers on the tunnel link
+        let mut overwritten = split_dns_backend();
+        overwritten.domains = backend.domains.clone();
+        overwritten.dns = vec![(TUNNEL_INDEX as i32, "1.1.1.1".parse().unwrap())];
+        assert!(
+            overwritten_tunnel_config(&overwritten, TUNNEL_INDEX, &servers())
+                .unwrap()
+                .is_some()
+        );
+
+        // The tunnel link no longer routes all lookups
+        let mut overwritten = split_dns_backend();
+        overwritten.dns = backend.dns.clone();
+        overwritten
+            .domains
+            .push((TUNNEL_INDEX as i32, ".".to_owned(), false));
+        assert!(
+            overwritten_tunnel_config(&overwritten, TUNNEL_INDEX, &servers())
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_revert_link() {
+        let backend = split_dns_backend();
+        revert_tunnel_config(&backend, TUNNEL_INDEX).unwrap();
+
+        assert_eq!(
+            backend.calls.into_inner(),
+            vec![Call::RevertLink(TUNNEL_INDEX)]
+        );
+    }
+
+    #[test]
+    fn test_revert_link_unsupported() {
+        let backend = MockBackend {
+            supports_revert: false,
+            ..Default::default()
+        };
+        revert_tunnel_config(&backend, TUNNEL_INDEX).unwrap();
+
+        assert_eq!(
+            backend.calls.into_inner(),
+            vec![
+                Call::RevertLink(TUNNEL_INDEX),
+                Call::SetDomains(TUNNEL_INDEX, vec![]),
+                Call::SetDns(TUNNEL_INDEX, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_restore_config_of_other_program() {
+        const LINK_INDEX: u32 = 2;
+        let backend = MockBackend {
+            dns: vec![
+                (LINK_INDEX as i32, "192.168.1.1".parse().unwrap()),
+                (3, "10.0.0.1".parse().unwrap()),
+            ],
+            default_route: Some(true),
+            ..split_dns_backend()
+        };
+        let original_config = link_config(&backend, LINK_INDEX).unwrap();
+        assert_eq!(
+            original_config,
+            LinkConfig {
+                servers: vec!["192.168.1.1".parse().unwrap()],
+                domains: vec![("corp.example".to_owned(), false), ("lan".to_owned(), true)],
+                default_route: Some(true),
+            }
+        );
+        backend.calls.borrow_mut().clear();
+
+        // The link is not reverted, since that would remove the config of the other program
+        reset_link_config(&backend, LINK_INDEX, &original_config).unwrap();
+        assert_eq!(
+            backend.calls.into_inner(),
+            vec![
+                Call::SetDomains(
+                    LINK_INDEX,
+                    vec![("corp.example".to_owned(), false), ("lan".to_owned(), true)]
+                ),
+                Call::SetDefaultRoute(LINK_INDEX, true),
+                Call::SetDns(LINK_INDEX, vec!["192.168.1.1".parse().unwrap()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_revert_unconfigured_link() {
+        let backend = split_dns_backend();
+        let original_config = link_config(&backend, TUNNEL_INDEX + 1).unwrap();
+        assert!(original_config.is_empty());
+        backend.calls.borrow_mut().clear();
+
+        reset_link_config(&backend, TUNNEL_INDEX + 1, &original_config).unwrap();
+        assert_eq!(
+            backend.calls.into_inner(),
+            vec![Call::RevertLink(TUNNEL_INDEX + 1)]
+        );
     }
 }
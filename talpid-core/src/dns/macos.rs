@@ -21,8 +21,8 @@ use system_configuration::{
     sys::schema_definitions::{kSCPropNetDNSServerAddresses, kSCPropNetInterfaceDeviceName},
 };
 use talpid_time::Instant;
-use talpid_types::tunnel::ErrorStateCause;
 
+use super::{watchdog::TamperNotifier, DnsTamperedSender};
 use crate::tunnel_state_machine::TunnelCommand;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -62,8 +62,8 @@ type ServicePath = String;
 type DnsServer = String;
 
 struct State {
-    /// Channel to signal to the TSM that something has gone wrong
-    tsm_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    /// Used to signal to the TSM that something has gone wrong, and to report restorations
+    notifier: TamperNotifier,
     /// Change counter to fail a tunnel if setting DNS
     change_counter: ChangeCounter,
     /// The settings this monitor is currently enforcing as active settings.
@@ -73,9 +73,9 @@ struct State {
 }
 
 impl State {
-    fn new(tsm_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>) -> Self {
+    fn new(notifier: TamperNotifier) -> Self {
         Self {
-            tsm_tx,
+            notifier,
             dns_settings: None,
             change_counter: ChangeCounter::new(),
             backup: HashMap::new(),
@@ -126,7 +126,7 @@ impl State {
                     Some(new_settings) => {
                         if new_settings.address_set() != expected_settings.address_set() {
                             let servers = new_settings.server_addresses().join(",");
-                            log::debug!("Detected DNS change [{}] for {}", servers, *path);
+                            log::warn!("Detected DNS change [{}] for {}", servers, *path);
                             self.backup.insert(path.to_string(), Some(new_settings));
                             true
                         } else {
@@ -137,19 +137,17 @@ impl State {
                 };
                 if should_set_dns {
                     if self.change_counter.increment() {
-                        if let Some(tx) = self.tsm_tx.upgrade() {
-                            log::error!("A burst of DNS changes has been detected, assuming can't set DNS config properly");
-                            let _ = tx
-                                .unbounded_send(TunnelCommand::Block(ErrorStateCause::SetDnsError));
-                        }
+                        log::error!("A burst of DNS changes has been detected, assuming can't set DNS config properly");
+                        self.notifier.give_up();
 
                         if let Err(err) = self.reset(&store) {
                             log::error!("Failed to reset DNS after detecting a burst: {}", err);
                         }
                         return;
                     }
-                    if let Err(e) = expected_settings.save(&store, path.clone()) {
-                        log::error!("Failed changing DNS for {}: {}", *path, e);
+                    match expected_settings.save(&store, path.clone()) {
+                        Ok(()) => self.notifier.restored(),
+                        Err(e) => log::error!("Failed changing DNS for {}: {}", *path, e),
                     }
                     // If we changed a "state" entry, also set the corresponding "setup" entry.
                     if let Some(setup_path_str) = state_to_setup_path(&path.to_string()) {
@@ -341,8 +339,12 @@ impl super::DnsMonitorT for DnsMonitor {
     /// DNS settings for all network interfaces. If any changes occur it will instantly reset
     /// the DNS settings for that interface back to the last server list set to this instance
     /// with `set_dns`.
-    fn new(tx: Weak<mpsc::UnboundedSender<TunnelCommand>>) -> Result<Self> {
-        let state = Arc::new(Mutex::new(State::new(tx)));
+    fn new(
+        tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+        tampered_tx: DnsTamperedSender,
+    ) -> Result<Self> {
+        let notifier = TamperNotifier::new(tx, tampered_tx);
+        let state = Arc::new(Mutex::new(State::new(notifier)));
         Self::spawn(state.clone())?;
         Ok(DnsMonitor {
             store: SCDynamicStoreBuilder::new("mullvad-dns").build(),
@@ -0,0 +1,19 @@
+//! DNS monitor that only pretends to set DNS. It lets the tunnel state machine run without the
+//! privileges that are needed to change the system DNS.
+
+#[cfg(target_os = "linux")]
+use super::OriginalResolvers;
+
+#[derive(Default)]
+pub struct DnsMonitor {
+    /// The system DNS is never changed, so no resolvers are ever replaced.
+    #[cfg(target_os = "linux")]
+    original_resolvers: OriginalResolvers,
+}
+
+impl DnsMonitor {
+    #[cfg(target_os = "linux")]
+    pub fn original_resolvers(&self) -> &OriginalResolvers {
+        &self.original_resolvers
+    }
+}
@@ -1,12 +1,21 @@
+use futures::channel::mpsc::UnboundedSender;
 use std::net::IpAddr;
 #[cfg(target_os = "linux")]
-use talpid_routing::RouteManagerHandle;
+use {std::path::PathBuf, talpid_routing::RouteManagerHandle};
 
-#[cfg(target_os = "macos")]
-use {
-    crate::tunnel_state_machine::TunnelCommand, futures::channel::mpsc::UnboundedSender,
-    std::sync::Weak,
-};
+#[cfg(not(target_os = "android"))]
+use {crate::tunnel_state_machine::TunnelCommand, std::sync::Weak};
+
+#[cfg(not(target_os = "android"))]
+mod watchdog;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) mod tls_forwarder;
+
+#[cfg(target_os = "linux")]
+mod original_resolvers;
+#[cfg(target_os = "linux")]
+pub use original_resolvers::OriginalResolvers;
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -27,11 +36,28 @@ mod imp;
 #[path = "android.rs"]
 mod imp;
 
+#[cfg(feature = "mock-tunnel")]
+mod mock;
+
 pub use self::imp::Error;
 
+/// Channel used to notify that the system DNS config was overwritten by another program, and that
+/// the desired config has been restored.
+pub type DnsTamperedSender = UnboundedSender<()>;
+
 /// Sets and monitors system DNS settings. Makes sure the desired DNS servers are being used.
+/// Whenever another program overwrites the settings, they are restored and a notification is sent
+/// on the `DnsTamperedSender`. This is done on all platforms except Android, and on Linux unless DNS
+/// is managed by resolvconf, which merges the servers of every interface into `/etc/resolv.conf`.
 pub struct DnsMonitor {
-    inner: imp::DnsMonitor,
+    inner: Inner,
+}
+
+enum Inner {
+    System(imp::DnsMonitor),
+    /// Never changes the system DNS, for testing without privileges.
+    #[cfg(feature = "mock-tunnel")]
+    Mock(mock::DnsMonitor),
 }
 
 impl DnsMonitor {
@@ -39,25 +65,46 @@ impl DnsMonitor {
     pub fn new(
         #[cfg(target_os = "linux")] handle: tokio::runtime::Handle,
         #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
-        #[cfg(target_os = "macos")] tx: Weak<UnboundedSender<TunnelCommand>>,
+        #[cfg(target_os = "linux")] fwmark: u32,
+        #[cfg(target_os = "linux")] cache_dir: PathBuf,
+        #[cfg(not(target_os = "android"))] tx: Weak<UnboundedSender<TunnelCommand>>,
+        tampered_tx: DnsTamperedSender,
     ) -> Result<Self, Error> {
         Ok(DnsMonitor {
-            inner: imp::DnsMonitor::new(
+            inner: Inner::System(imp::DnsMonitor::new(
                 #[cfg(target_os = "linux")]
                 handle,
                 #[cfg(target_os = "linux")]
                 route_manager,
-                #[cfg(target_os = "macos")]
+                #[cfg(target_os = "linux")]
+                fwmark,
+                #[cfg(target_os = "linux")]
+                cache_dir,
+                #[cfg(not(target_os = "android"))]
                 tx,
-            )?,
+                tampered_tx,
+            )?),
         })
     }
 
+    /// Returns a `DnsMonitor` that only logs the DNS servers that it is given, and never touches
+    /// the system DNS.
+    #[cfg(feature = "mock-tunnel")]
+    pub fn mock() -> Self {
+        DnsMonitor {
+            inner: Inner::Mock(mock::DnsMonitor::default()),
+        }
+    }
+
     /// Returns a map of interfaces and respective list of resolvers that don't contain our
     /// changes.
     #[cfg(target_os = "macos")]
     pub fn get_system_config(&self) -> Result<Option<(String, Vec<IpAddr>)>, Error> {
-        self.inner.get_system_config()
+        match &self.inner {
+            Inner::System(inner) => inner.get_system_config(),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock(_) => Ok(None),
+        }
     }
 
     /// Set DNS to the given servers. And start monitoring the system for changes.
@@ -70,14 +117,55 @@ impl DnsMonitor {
                 .collect::<Vec<String>>()
                 .join(", ")
         );
-        self.inner.set(interface, servers)
+        match &mut self.inner {
+            Inner::System(inner) => inner.set(interface, servers),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock(_) => Ok(()),
+        }
+    }
+
+    /// Set whether the search domains of other interfaces should also be used for lookups through
+    /// the tunnel. This takes effect the next time DNS is set. Currently, this only affects
+    /// systemd-resolved on Linux.
+    pub fn set_preserve_search_domains(&mut self, preserve_search_domains: bool) {
+        match &mut self.inner {
+            Inner::System(inner) => inner.set_preserve_search_domains(preserve_search_domains),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock(_) => (),
+        }
+    }
+
+    /// Returns the resolvers that were in use before DNS was last set. DNS requests from excluded
+    /// processes are sent to these.
+    #[cfg(target_os = "linux")]
+    pub fn original_resolvers(&self) -> &OriginalResolvers {
+        match &self.inner {
+            Inner::System(inner) => inner.original_resolvers(),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock(inner) => inner.original_resolvers(),
+        }
+    }
+
+    /// Updates the resolvers returned by [`Self::original_resolvers`] after a network change, if
+    /// they can still be determined while DNS is set. Returns whether they changed.
+    #[cfg(target_os = "linux")]
+    pub fn refresh_original_resolvers(&mut self) -> bool {
+        match &mut self.inner {
+            Inner::System(inner) => inner.refresh_original_resolvers(),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock(_) => false,
+        }
     }
 
     /// Reset system DNS settings to what it was before being set by this instance.
     /// This succeeds if the interface does not exist.
     pub fn reset(&mut self) -> Result<(), Error> {
         log::info!("Resetting DNS");
-        self.inner.reset()
+        match &mut self.inner {
+            Inner::System(inner) => inner.reset(),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock(_) => Ok(()),
+        }
     }
 
     /// Reset DNS settings to what they were before being set by this instance.
@@ -85,7 +173,11 @@ impl DnsMonitor {
     /// as the interface will be destroyed.
     pub fn reset_before_interface_removal(&mut self) -> Result<(), Error> {
         log::info!("Resetting DNS");
-        self.inner.reset_before_interface_removal()
+        match &mut self.inner {
+            Inner::System(inner) => inner.reset_before_interface_removal(),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock(_) => Ok(()),
+        }
     }
 }
 
@@ -95,11 +187,16 @@ trait DnsMonitorT: Sized {
     fn new(
         #[cfg(target_os = "linux")] handle: tokio::runtime::Handle,
         #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
-        #[cfg(target_os = "macos")] tx: Weak<UnboundedSender<TunnelCommand>>,
+        #[cfg(target_os = "linux")] fwmark: u32,
+        #[cfg(target_os = "linux")] cache_dir: PathBuf,
+        #[cfg(not(target_os = "android"))] tx: Weak<UnboundedSender<TunnelCommand>>,
+        tampered_tx: DnsTamperedSender,
     ) -> Result<Self, Self::Error>;
 
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
 
+    fn set_preserve_search_domains(&mut self, _preserve_search_domains: bool) {}
+
     fn reset(&mut self) -> Result<(), Self::Error>;
 
     fn reset_before_interface_removal(&mut self) -> Result<(), Self::Error> {
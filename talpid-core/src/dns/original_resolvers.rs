@@ -0,0 +1,216 @@
+//! Bookkeeping of the resolvers that were in use before the DNS config of the tunnel was applied.
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+/// The resolvers that the system used before the DNS config of the tunnel was applied, per
+/// network interface. Resolvers that don't belong to a specific interface, such as those listed in
+/// a static `/etc/resolv.conf`, are kept separately.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OriginalResolvers {
+    interfaces: BTreeMap<String, Vec<IpAddr>>,
+    global: Vec<IpAddr>,
+    /// Interfaces that traffic outside the tunnel is routed through.
+    default_interfaces: Vec<String>,
+}
+
+impl OriginalResolvers {
+    /// Replaces the recorded resolvers. Resolvers of `tunnel_interface`, as well as local stub
+    /// resolvers, are ignored since excluded traffic cannot use them. The resolvers of
+    /// `default_interfaces` are preferred over those of other interfaces. Returns whether the
+    /// recorded resolvers changed.
+    pub fn update(
+        &mut self,
+        tunnel_interface: &str,
+        default_interfaces: impl IntoIterator<Item = String>,
+        interface_servers: impl IntoIterator<Item = (String, IpAddr)>,
+        global_servers: impl IntoIterator<Item = IpAddr>,
+    ) -> bool {
+        let mut resolvers = OriginalResolvers::default();
+        for interface in default_interfaces {
+            if interface != tunnel_interface && !resolvers.default_interfaces.contains(&interface) {
+                resolvers.default_interfaces.push(interface);
+            }
+        }
+        for (interface, server) in interface_servers {
+            if interface != tunnel_interface {
+                push_unique(resolvers.interfaces.entry(interface).or_default(), server);
+            }
+        }
+        for server in global_servers {
+            push_unique(&mut resolvers.global, server);
+        }
+        resolvers
+            .interfaces
+            .retain(|_, servers| !servers.is_empty());
+
+        let changed = *self != resolvers;
+        *self = resolvers;
+        changed
+    }
+
+    /// Forgets all recorded resolvers. Returns whether any were recorded.
+    pub fn clear(&mut self) -> bool {
+        let changed = !self.is_empty();
+        *self = OriginalResolvers::default();
+        changed
+    }
+
+    /// Returns the resolvers recorded for `interface`.
+    pub fn interface_servers(&self, interface: &str) -> &[IpAddr] {
+        self.interfaces
+            .get(interface)
+            .map(|servers| &servers[..])
+            .unwrap_or(&[])
+    }
+
+    /// Returns all recorded resolvers in order of preference: those of the interfaces that
+    /// traffic outside the tunnel is routed through, then those that don't belong to any
+    /// interface, and finally those of other interfaces.
+    pub fn servers(&self) -> Vec<IpAddr> {
+        let default_servers = self
+            .default_interfaces
+            .iter()
+            .flat_map(|interface| self.interface_servers(interface));
+        let other_servers = self
+            .interfaces
+            .iter()
+            .filter(|(interface, _)| !self.default_interfaces.contains(interface))
+            .flat_map(|(_, servers)| servers);
+
+        let mut servers = vec![];
+        for server in default_servers.chain(&self.global).chain(other_servers) {
+            push_unique(&mut servers, *server);
+        }
+        servers
+    }
+
+    /// Returns whether no resolvers have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.interfaces.is_empty() && self.global.is_empty()
+    }
+}
+
+fn push_unique(servers: &mut Vec<IpAddr>, server: IpAddr) {
+    if !server.is_loopback() && !server.is_unspecified() && !servers.contains(&server) {
+        servers.push(server);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TUNNEL_INTERFACE: &str = "wg0-mullvad";
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn interface_servers() -> Vec<(String, IpAddr)> {
+        vec![
+            ("eth0".to_owned(), ip("192.168.1.1")),
+            ("eth0".to_owned(), ip("fe80::1")),
+            ("eth0".to_owned(), ip("192.168.1.1")),
+            ("wlan0".to_owned(), ip("10.0.0.1")),
+            (TUNNEL_INTERFACE.to_owned(), ip("10.64.0.1")),
+        ]
+    }
+
+    #[test]
+    fn test_record_per_interface() {
+        let mut resolvers = OriginalResolvers::default();
+        assert!(resolvers.update(
+            TUNNEL_INTERFACE,
+            vec![],
+            interface_servers(),
+            vec![ip("9.9.9.9")]
+        ));
+
+        assert_eq!(
+            resolvers.interface_servers("eth0"),
+            &[ip("192.168.1.1"), ip("fe80::1")]
+        );
+        assert_eq!(resolvers.interface_servers("wlan0"), &[ip("10.0.0.1")]);
+        assert!(resolvers.interface_servers(TUNNEL_INTERFACE).is_empty());
+        assert_eq!(
+            resolvers.servers(),
+            vec![
+                ip("9.9.9.9"),
+                ip("192.168.1.1"),
+                ip("fe80::1"),
+                ip("10.0.0.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prefer_default_interfaces() {
+        let mut resolvers = OriginalResolvers::default();
+        resolvers.update(
+            TUNNEL_INTERFACE,
+            vec!["wlan0".to_owned(), TUNNEL_INTERFACE.to_owned()],
+            interface_servers(),
+            vec![ip("9.9.9.9")],
+        );
+        assert_eq!(
+            resolvers.servers(),
+            vec![
+                ip("10.0.0.1"),
+                ip("9.9.9.9"),
+                ip("192.168.1.1"),
+                ip("fe80::1"),
+            ]
+        );
+
+        // Traffic is now routed through eth0
+        assert!(resolvers.update(
+            TUNNEL_INTERFACE,
+            vec!["eth0".to_owned()],
+            interface_servers(),
+            vec![ip("9.9.9.9")],
+        ));
+        assert_eq!(
+            resolvers.servers(),
+            vec![
+                ip("192.168.1.1"),
+                ip("fe80::1"),
+                ip("9.9.9.9"),
+                ip("10.0.0.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ignore_stub_resolvers() {
+        let mut resolvers = OriginalResolvers::default();
+        assert!(!resolvers.update(
+            TUNNEL_INTERFACE,
+            vec![],
+            vec![("lo".to_owned(), ip("127.0.0.53"))],
+            vec![ip("127.0.0.53"), ip("::1"), ip("0.0.0.0")],
+        ));
+        assert!(resolvers.is_empty());
+    }
+
+    #[test]
+    fn test_refresh() {
+        let mut resolvers = OriginalResolvers::default();
+        resolvers.update(TUNNEL_INTERFACE, vec![], interface_servers(), vec![]);
+
+        // Nothing changed
+        assert!(!resolvers.update(TUNNEL_INTERFACE, vec![], interface_servers(), vec![]));
+
+        // wlan0 went away and eth0 got a new resolver
+        assert!(resolvers.update(
+            TUNNEL_INTERFACE,
+            vec![],
+            vec![("eth0".to_owned(), ip("192.168.2.1"))],
+            vec![],
+        ));
+        assert_eq!(resolvers.servers(), vec![ip("192.168.2.1")]);
+        assert!(resolvers.interface_servers("wlan0").is_empty());
+
+        assert!(resolvers.clear());
+        assert!(!resolvers.clear());
+    }
+}
@@ -0,0 +1,506 @@
+//! Local DNS stub that forwards queries to DNS-over-TLS servers.
+//!
+//! The stub listens for plaintext queries on port 53 of a local address, and relays each query
+//! over TLS (RFC 7858) to the first upstream server that answers. The certificate presented by an
+//! upstream must be valid for its configured hostname. Queries are never sent in plaintext to an
+//! upstream, not even when the TLS handshake fails.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use talpid_types::{net::TlsDnsServer, ErrorExt};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    runtime::Handle,
+    sync::Mutex,
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{self, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+/// Port that DNS-over-TLS servers listen on.
+const DOT_PORT: u16 = 853;
+/// Port that the stub listens on.
+const DNS_PORT: u16 = 53;
+/// Maximum number of idle connections to keep open per upstream server.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+/// Maximum time to wait for an upstream server to answer a query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Size of the DNS message header.
+const HEADER_LEN: usize = 12;
+/// Response code indicating that the server failed to process the query.
+const RCODE_SERVFAIL: u8 = 2;
+
+/// Errors that can occur while forwarding DNS queries over TLS.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to bind the local DNS stub.
+    #[error(display = "Failed to bind DNS stub to {}", _0)]
+    Bind(SocketAddr, #[error(source)] io::Error),
+
+    /// The hostname of an upstream server is not a valid DNS name.
+    #[error(display = "Invalid DNS-over-TLS hostname: {}", _0)]
+    InvalidHostname(String),
+
+    /// Failed to establish a connection to an upstream server.
+    #[error(display = "Failed to connect to DNS-over-TLS server")]
+    Connect(#[error(source)] io::Error),
+
+    /// The certificate of an upstream server could not be validated.
+    #[error(display = "Invalid certificate for DNS-over-TLS server {}", _0)]
+    InvalidCertificate(String, #[error(source)] rustls::Error),
+
+    /// Failed to exchange a message with an upstream server.
+    #[error(display = "Failed to query DNS-over-TLS server")]
+    Query(#[error(source)] io::Error),
+}
+
+/// Callback invoked when the certificate of an upstream server could not be validated.
+pub type CertificateErrorCallback = Box<dyn Fn(&Error) + Send + Sync>;
+
+/// A running DNS stub. The stub is stopped when this is dropped.
+pub struct TlsForwarder {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl TlsForwarder {
+    /// Starts a DNS stub on port 53 of `bind_address` that forwards queries to `servers`.
+    /// `on_certificate_error` is invoked the first time an upstream server presents a certificate
+    /// that is not valid for its hostname. Queries are served on `runtime`.
+    pub fn start(
+        runtime: &Handle,
+        bind_address: IpAddr,
+        servers: &[TlsDnsServer],
+        on_certificate_error: CertificateErrorCallback,
+    ) -> Result<Self, Error> {
+        Self::start_with_config(
+            runtime,
+            SocketAddr::new(bind_address, DNS_PORT),
+            servers,
+            DOT_PORT,
+            default_client_config(),
+            on_certificate_error,
+        )
+    }
+
+    fn start_with_config(
+        runtime: &Handle,
+        bind_addr: SocketAddr,
+        servers: &[TlsDnsServer],
+        upstream_port: u16,
+        config: Arc<ClientConfig>,
+        on_certificate_error: CertificateErrorCallback,
+    ) -> Result<Self, Error> {
+        let upstreams = servers
+            .iter()
+            .map(|server| Upstream::new(server, upstream_port))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The socket is bound synchronously, so that the caller does not have to block on the
+        // runtime
+        let socket = std::net::UdpSocket::bind(bind_addr)
+            .and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                let _guard = runtime.enter();
+                UdpSocket::from_std(socket)
+            })
+            .map_err(|error| Error::Bind(bind_addr, error))?;
+        let local_addr = socket
+            .local_addr()
+            .map_err(|error| Error::Bind(bind_addr, error))?;
+
+        let forwarder = Arc::new(Forwarder {
+            connector: TlsConnector::from(config),
+            upstreams,
+            on_certificate_error,
+            reported_certificate_error: AtomicBool::new(false),
+        });
+        let task = runtime.spawn(serve(Arc::new(socket), forwarder));
+
+        log::debug!("Started DNS-over-TLS stub on {}", local_addr);
+
+        Ok(Self { local_addr, task })
+    }
+
+    /// Returns the address that the stub listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for TlsForwarder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(socket: Arc<UdpSocket>, forwarder: Arc<Forwarder>) {
+    let mut buffer = vec![0u8; usize::from(u16::MAX)];
+    loop {
+        let (len, client) = match socket.recv_from(&mut buffer).await {
+            Ok(result) => result,
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to receive DNS query")
+                );
+                continue;
+            }
+        };
+
+        let query = buffer[..len].to_vec();
+        let socket = socket.clone();
+        let forwarder = forwarder.clone();
+        tokio::spawn(async move {
+            let Some(response) = forwarder.resolve(&query).await else {
+                return;
+            };
+            if let Err(error) = socket.send_to(&response, client).await {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to send DNS response")
+                );
+            }
+        });
+    }
+}
+
+struct Forwarder {
+    connector: TlsConnector,
+    upstreams: Vec<Upstream>,
+    on_certificate_error: CertificateErrorCallback,
+    reported_certificate_error: AtomicBool,
+}
+
+impl Forwarder {
+    /// Returns the response of the first upstream server that answers `query`, or a SERVFAIL
+    /// response if none of them do. Returns `None` if `query` is not a DNS message.
+    async fn resolve(&self, query: &[u8]) -> Option<Vec<u8>> {
+        if query.len() < HEADER_LEN {
+            return None;
+        }
+
+        for upstream in &self.upstreams {
+            match tokio::time::timeout(QUERY_TIMEOUT, upstream.query(&self.connector, query)).await
+            {
+                Ok(Ok(response)) => return Some(response),
+                Ok(Err(error)) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "DNS-over-TLS query to {} failed",
+                            upstream.hostname
+                        ))
+                    );
+                    if matches!(error, Error::InvalidCertificate(..))
+                        && !self.reported_certificate_error.swap(true, Ordering::SeqCst)
+                    {
+                        (self.on_certificate_error)(&error);
+                    }
+                }
+                Err(_) => {
+                    log::warn!("DNS-over-TLS query to {} timed out", upstream.hostname);
+                }
+            }
+        }
+
+        Some(servfail_response(query))
+    }
+}
+
+/// An upstream server along with its idle connections.
+struct Upstream {
+    address: SocketAddr,
+    hostname: String,
+    server_name: ServerName,
+    idle_connections: Mutex<Vec<TlsStream<TcpStream>>>,
+}
+
+impl Upstream {
+    fn new(server: &TlsDnsServer, port: u16) -> Result<Self, Error> {
+        let server_name = ServerName::try_from(server.hostname.as_str())
+            .map_err(|_| Error::InvalidHostname(server.hostname.clone()))?;
+        Ok(Self {
+            address: SocketAddr::new(server.address, port),
+            hostname: server.hostname.clone(),
+            server_name,
+            idle_connections: Mutex::new(vec![]),
+        })
+    }
+
+    async fn query(&self, connector: &TlsConnector, query: &[u8]) -> Result<Vec<u8>, Error> {
+        let idle_connection = self.idle_connections.lock().await.pop();
+        if let Some(mut stream) = idle_connection {
+            // The server may have closed the connection while it was idle, so retry using a new
+            // connection if this fails.
+            if let Ok(response) = exchange(&mut stream, query).await {
+                self.release(stream).await;
+                return Ok(response);
+            }
+        }
+
+        let mut stream = self.connect(connector).await?;
+        let response = exchange(&mut stream, query).await.map_err(Error::Query)?;
+        self.release(stream).await;
+        Ok(response)
+    }
+
+    async fn connect(&self, connector: &TlsConnector) -> Result<TlsStream<TcpStream>, Error> {
+        let stream = TcpStream::connect(self.address)
+            .await
+            .map_err(Error::Connect)?;
+        connector
+            .connect(self.server_name.clone(), stream)
+            .await
+            .map_err(|error| match certificate_error(&error) {
+                Some(cert_error) => Error::InvalidCertificate(self.hostname.clone(), cert_error),
+                None => Error::Connect(error),
+            })
+    }
+
+    async fn release(&self, stream: TlsStream<TcpStream>) {
+        let mut idle_connections = self.idle_connections.lock().await;
+        if idle_connections.len() < MAX_IDLE_CONNECTIONS {
+            idle_connections.push(stream);
+        }
+    }
+}
+
+/// Returns the certificate error that caused a TLS handshake to fail, if any.
+fn certificate_error(error: &io::Error) -> Option<rustls::Error> {
+    match error.get_ref()?.downcast_ref::<rustls::Error>()? {
+        error @ rustls::Error::InvalidCertificate(_) => Some(error.clone()),
+        _ => None,
+    }
+}
+
+/// Sends `query` on `stream` and reads the response, using the framing of DNS over TCP.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    query: &[u8],
+) -> io::Result<Vec<u8>> {
+    let len = u16::try_from(query.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "DNS query is too large"))?;
+    let mut message = Vec::with_capacity(2 + query.len());
+    message.extend_from_slice(&len.to_be_bytes());
+    message.extend_from_slice(query);
+    stream.write_all(&message).await?;
+    stream.flush().await?;
+
+    let len = stream.read_u16().await?;
+    let mut response = vec![0u8; usize::from(len)];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+/// Returns a SERVFAIL response to `query`, which must contain a full header.
+fn servfail_response(query: &[u8]) -> Vec<u8> {
+    let mut response = query[..HEADER_LEN].to_vec();
+    // Set QR and keep the opcode and RD bits of the query
+    response[2] = 0x80 | (query[2] & 0x79);
+    // Set RA and the response code
+    response[3] = 0x80 | RCODE_SERVFAIL;
+    // Do not include any records
+    response[4..HEADER_LEN].fill(0);
+    response
+}
+
+fn default_client_config() -> Arc<ClientConfig> {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    Arc::new(
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{net::Ipv4Addr, sync::atomic::AtomicUsize};
+    use tokio::net::TcpListener;
+    use tokio_rustls::{
+        rustls::{Certificate, PrivateKey, ServerConfig},
+        TlsAcceptor,
+    };
+
+    const SERVER_HOSTNAME: &str = "dns.test";
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    /// A DNS-over-TLS server that answers every query with the query itself, with the QR bit set.
+    struct TestServer {
+        port: u16,
+        certificate: Certificate,
+        accepted_connections: Arc<AtomicUsize>,
+    }
+
+    async fn spawn_test_server() -> TestServer {
+        let generated = rcgen::generate_simple_self_signed(vec![SERVER_HOSTNAME.to_owned()])
+            .expect("failed to generate certificate");
+        let certificate = Certificate(generated.serialize_der().unwrap());
+        let private_key = PrivateKey(generated.serialize_private_key_der());
+
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.clone()], private_key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted_connections = Arc::new(AtomicUsize::new(0));
+
+        let accepted = accepted_connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut query = vec![0u8; usize::from(len)];
+                        if stream.read_exact(&mut query).await.is_err() {
+                            return;
+                        }
+                        let response = echo_response(&query);
+                        let mut message = (response.len() as u16).to_be_bytes().to_vec();
+                        message.extend_from_slice(&response);
+                        if stream.write_all(&message).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        TestServer {
+            port,
+            certificate,
+            accepted_connections,
+        }
+    }
+
+    fn echo_response(query: &[u8]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] |= 0x80;
+        response
+    }
+
+    fn query(id: u16) -> Vec<u8> {
+        let mut query = id.to_be_bytes().to_vec();
+        // RD set, one question
+        query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        // example.com IN A
+        query.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        query
+    }
+
+    fn start_forwarder(
+        server: &TestServer,
+        hostname: &str,
+        certificate_errors: Arc<AtomicUsize>,
+    ) -> TlsForwarder {
+        let mut root_store = RootCertStore::empty();
+        root_store.add(&server.certificate).unwrap();
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+
+        TlsForwarder::start_with_config(
+            &Handle::current(),
+            SocketAddr::new(LOCALHOST, 0),
+            &[TlsDnsServer {
+                address: LOCALHOST,
+                hostname: hostname.to_owned(),
+            }],
+            server.port,
+            Arc::new(config),
+            Box::new(move |_| {
+                certificate_errors.fetch_add(1, Ordering::SeqCst);
+            }),
+        )
+        .unwrap()
+    }
+
+    async fn send_query(forwarder: &TlsForwarder, query: &[u8]) -> Vec<u8> {
+        let socket = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+        socket.send_to(query, forwarder.local_addr()).await.unwrap();
+        let mut buffer = vec![0u8; 512];
+        let len = tokio::time::timeout(Duration::from_secs(10), socket.recv(&mut buffer))
+            .await
+            .expect("timed out waiting for response")
+            .unwrap();
+        buffer.truncate(len);
+        buffer
+    }
+
+    #[tokio::test]
+    async fn test_forward_query() {
+        let server = spawn_test_server().await;
+        let forwarder = start_forwarder(&server, SERVER_HOSTNAME, Default::default());
+
+        let query = query(0x1234);
+        assert_eq!(send_query(&forwarder, &query).await, echo_response(&query));
+    }
+
+    #[tokio::test]
+    async fn test_reuse_connection() {
+        let server = spawn_test_server().await;
+        let forwarder = start_forwarder(&server, SERVER_HOSTNAME, Default::default());
+
+        for id in 0..3 {
+            let query = query(id);
+            assert_eq!(send_query(&forwarder, &query).await, echo_response(&query));
+        }
+        assert_eq!(server.accepted_connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reject_invalid_hostname() {
+        let server = spawn_test_server().await;
+        let certificate_errors = Arc::new(AtomicUsize::new(0));
+        let forwarder = start_forwarder(&server, "other.test", certificate_errors.clone());
+
+        for id in 0..2 {
+            let response = send_query(&forwarder, &query(id)).await;
+            assert_eq!(response, servfail_response(&query(id)));
+            assert_eq!(response[3] & 0x0f, RCODE_SERVFAIL);
+        }
+        assert_eq!(certificate_errors.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_servfail_response() {
+        let query = query(0xabcd);
+        let response = servfail_response(&query);
+        assert_eq!(
+            response,
+            vec![0xab, 0xcd, 0x81, 0x82, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+}
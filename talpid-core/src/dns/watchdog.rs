@@ -0,0 +1,431 @@
+//! Helpers for enforcing the DNS config when other programs overwrite it.
+
+use super::DnsTamperedSender;
+use crate::tunnel_state_machine::TunnelCommand;
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    net::IpAddr,
+    sync::{mpsc as sync_mpsc, Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::{tunnel::ErrorStateCause, ErrorExt};
+
+/// Maximum number of times the DNS config is restored within [`RESTORATION_INTERVAL`] before
+/// giving up.
+const MAX_RESTORATIONS: usize = 5;
+/// Interval during which restorations are counted.
+const RESTORATION_INTERVAL: Duration = Duration::from_secs(60);
+/// Delay before the second restoration within [`RESTORATION_INTERVAL`]. The delay is doubled for
+/// every subsequent restoration.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Interval at which DNS configs that cannot be watched for changes are checked.
+pub(super) const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What to do after detecting that the DNS config has been overwritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Re-apply the desired DNS config after waiting for the given delay.
+    Restore { delay: Duration },
+    /// Stop fighting over the DNS config and enter the error state.
+    GiveUp,
+}
+
+/// Keeps track of recent restorations of the DNS config, to avoid fighting indefinitely with a
+/// program that keeps overwriting it.
+pub struct RestoreThrottle {
+    restorations: VecDeque<Instant>,
+    max_restorations: usize,
+    interval: Duration,
+    initial_backoff: Duration,
+}
+
+impl RestoreThrottle {
+    pub fn new() -> Self {
+        Self::with_limits(MAX_RESTORATIONS, RESTORATION_INTERVAL, INITIAL_BACKOFF)
+    }
+
+    fn with_limits(max_restorations: usize, interval: Duration, initial_backoff: Duration) -> Self {
+        Self {
+            restorations: VecDeque::with_capacity(max_restorations),
+            max_restorations,
+            interval,
+            initial_backoff,
+        }
+    }
+
+    /// Registers that the DNS config was found to be overwritten at `now` and returns whether it
+    /// should be restored.
+    pub fn register(&mut self, now: Instant) -> Decision {
+        while let Some(oldest) = self.restorations.front() {
+            if now.saturating_duration_since(*oldest) < self.interval {
+                break;
+            }
+            self.restorations.pop_front();
+        }
+
+        if self.restorations.len() >= self.max_restorations {
+            return Decision::GiveUp;
+        }
+
+        let delay = match self.restorations.len() {
+            0 => Duration::ZERO,
+            n => self.initial_backoff * (1 << (n - 1)),
+        };
+        self.restorations.push_back(now);
+        Decision::Restore { delay }
+    }
+}
+
+/// Reports DNS tampering to the daemon and the tunnel state machine.
+#[derive(Clone)]
+pub struct TamperNotifier {
+    tsm_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    tampered_tx: DnsTamperedSender,
+}
+
+impl TamperNotifier {
+    pub fn new(
+        tsm_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+        tampered_tx: DnsTamperedSender,
+    ) -> Self {
+        Self {
+            tsm_tx,
+            tampered_tx,
+        }
+    }
+
+    /// Notifies that the DNS config was restored after another program overwrote it.
+    pub fn restored(&self) {
+        let _ = self.tampered_tx.unbounded_send(());
+    }
+
+    /// Tells the tunnel state machine to block, since the DNS config cannot be enforced.
+    pub fn give_up(&self) {
+        if let Some(tx) = self.tsm_tx.upgrade() {
+            let _ = tx.unbounded_send(TunnelCommand::Block(ErrorStateCause::SetDnsError));
+        }
+    }
+}
+
+/// Returns whether both lists contain the same servers, in any order.
+pub fn same_servers(a: &[IpAddr], b: &[IpAddr]) -> bool {
+    a.iter().all(|server| b.contains(server)) && b.iter().all(|server| a.contains(server))
+}
+
+/// A DNS config that is enforced by a [`PollingWatchdog`].
+pub trait PolledDnsConfig: Send + 'static {
+    type Error: std::error::Error;
+
+    /// Returns a description of the current config if another program has overwritten the
+    /// desired config, or `None` if the desired config is still in place.
+    fn check(&mut self) -> Result<Option<String>, Self::Error>;
+
+    /// Re-applies the desired config.
+    fn restore(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Periodically checks a DNS config that cannot be watched for changes, and restores it when
+/// another program has overwritten it. The config is no longer enforced once the watchdog is
+/// stopped or dropped.
+pub struct PollingWatchdog {
+    active: Arc<Mutex<bool>>,
+    _cancel_tx: sync_mpsc::Sender<()>,
+}
+
+impl PollingWatchdog {
+    /// Starts enforcing `config`. `name` describes the config in logs.
+    pub fn start(
+        name: &'static str,
+        config: impl PolledDnsConfig,
+        notifier: TamperNotifier,
+    ) -> Self {
+        Self::with_throttle(
+            name,
+            config,
+            notifier,
+            RestoreThrottle::new(),
+            POLL_INTERVAL,
+        )
+    }
+
+    fn with_throttle(
+        name: &'static str,
+        mut config: impl PolledDnsConfig,
+        notifier: TamperNotifier,
+        mut throttle: RestoreThrottle,
+        interval: Duration,
+    ) -> Self {
+        let active = Arc::new(Mutex::new(true));
+        let (cancel_tx, cancel_rx) = sync_mpsc::channel();
+        let thread_active = active.clone();
+        thread::spawn(move || {
+            // The lock is held while the config is checked and restored, so that nothing is
+            // restored once `stop` has returned
+            let wait = |delay| {
+                matches!(
+                    cancel_rx.recv_timeout(delay),
+                    Err(sync_mpsc::RecvTimeoutError::Timeout)
+                )
+            };
+            while wait(interval) {
+                let active = thread_active.lock();
+                if !*active {
+                    break;
+                }
+                let overwritten = match config.check() {
+                    Ok(Some(overwritten)) => overwritten,
+                    Ok(None) => continue,
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg(&format!("Failed to check {name}"))
+                        );
+                        continue;
+                    }
+                };
+                log::warn!("{name} was overwritten by another program. New config: {overwritten}");
+
+                let delay = match throttle.register(Instant::now()) {
+                    Decision::Restore { delay } => delay,
+                    Decision::GiveUp => {
+                        log::error!("{name} keeps being overwritten, assuming DNS can't be set");
+                        notifier.give_up();
+                        break;
+                    }
+                };
+                let _active = if delay.is_zero() {
+                    active
+                } else {
+                    log::debug!("Restoring {name} in {delay:?}");
+                    drop(active);
+                    if !wait(delay) {
+                        break;
+                    }
+                    let active = thread_active.lock();
+                    if !*active {
+                        break;
+                    }
+                    active
+                };
+
+                match config.restore() {
+                    Ok(()) => notifier.restored(),
+                    Err(error) => log::error!(
+                        "{}",
+                        error.display_chain_with_msg(&format!("Failed to restore {name}"))
+                    ),
+                }
+            }
+        });
+        Self {
+            active,
+            _cancel_tx: cancel_tx,
+        }
+    }
+
+    /// Stops enforcing the config. Once this returns, the config is no longer restored.
+    pub fn stop(&self) {
+        *self.active.lock() = false;
+    }
+}
+
+impl Drop for PollingWatchdog {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn throttle() -> RestoreThrottle {
+        RestoreThrottle::with_limits(3, Duration::from_secs(60), Duration::from_secs(1))
+    }
+
+    #[test]
+    fn test_backoff_until_give_up() {
+        let mut throttle = throttle();
+        let start = Instant::now();
+
+        let decisions: Vec<_> = (0..4)
+            .map(|n| throttle.register(start + Duration::from_secs(n)))
+            .collect();
+
+        assert_eq!(
+            decisions,
+            vec![
+                Decision::Restore {
+                    delay: Duration::ZERO
+                },
+                Decision::Restore {
+                    delay: Duration::from_secs(1)
+                },
+                Decision::Restore {
+                    delay: Duration::from_secs(2)
+                },
+                Decision::GiveUp,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sparse_changes_are_restored() {
+        let mut throttle = throttle();
+        let start = Instant::now();
+
+        for n in 0..10 {
+            assert_eq!(
+                throttle.register(start + Duration::from_secs(61 * n)),
+                Decision::Restore {
+                    delay: Duration::ZERO
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_old_restorations_expire() {
+        let mut throttle = throttle();
+        let start = Instant::now();
+
+        throttle.register(start);
+        throttle.register(start + Duration::from_secs(30));
+        throttle.register(start + Duration::from_secs(40));
+
+        // The first restoration is no longer within the interval.
+        assert_eq!(
+            throttle.register(start + Duration::from_secs(60)),
+            Decision::Restore {
+                delay: Duration::from_secs(2)
+            }
+        );
+        assert_eq!(
+            throttle.register(start + Duration::from_secs(61)),
+            Decision::GiveUp
+        );
+    }
+
+    #[test]
+    fn test_same_servers() {
+        let tunnel: IpAddr = "10.64.0.1".parse().unwrap();
+        let tunnel_v6: IpAddr = "fc00:bbbb:bbbb:bb01::1".parse().unwrap();
+        let other: IpAddr = "192.168.1.1".parse().unwrap();
+
+        assert!(same_servers(&[tunnel_v6, tunnel], &[tunnel, tunnel_v6]));
+        assert!(!same_servers(&[tunnel], &[tunnel, tunnel_v6]));
+        assert!(!same_servers(&[other], &[tunnel]));
+        assert!(!same_servers(&[tunnel, other], &[tunnel]));
+    }
+
+    /// A config that is overwritten whenever `overwrites` is positive.
+    struct FakeConfig {
+        overwrites: Arc<AtomicUsize>,
+        restorations: Arc<AtomicUsize>,
+    }
+
+    impl PolledDnsConfig for FakeConfig {
+        type Error = std::io::Error;
+
+        fn check(&mut self) -> Result<Option<String>, Self::Error> {
+            Ok((self.overwrites.load(Ordering::SeqCst) > 0).then(|| "nameserver 1.1.1.1".into()))
+        }
+
+        fn restore(&mut self) -> Result<(), Self::Error> {
+            self.restorations.fetch_add(1, Ordering::SeqCst);
+            let _ = self
+                .overwrites
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+            Ok(())
+        }
+    }
+
+    struct Harness {
+        watchdog: PollingWatchdog,
+        overwrites: Arc<AtomicUsize>,
+        restorations: Arc<AtomicUsize>,
+        tampered_rx: mpsc::UnboundedReceiver<()>,
+        commands_rx: mpsc::UnboundedReceiver<TunnelCommand>,
+        _commands_tx: Arc<mpsc::UnboundedSender<TunnelCommand>>,
+    }
+
+    fn start_watchdog() -> Harness {
+        let overwrites = Arc::new(AtomicUsize::new(0));
+        let restorations = Arc::new(AtomicUsize::new(0));
+        let (tampered_tx, tampered_rx) = mpsc::unbounded();
+        let (commands_tx, commands_rx) = mpsc::unbounded();
+        let commands_tx = Arc::new(commands_tx);
+        let config = FakeConfig {
+            overwrites: overwrites.clone(),
+            restorations: restorations.clone(),
+        };
+        let watchdog = PollingWatchdog::with_throttle(
+            "test config",
+            config,
+            TamperNotifier::new(Arc::downgrade(&commands_tx), tampered_tx),
+            RestoreThrottle::with_limits(3, Duration::from_secs(60), Duration::from_millis(1)),
+            Duration::from_millis(5),
+        );
+        Harness {
+            watchdog,
+            overwrites,
+            restorations,
+            tampered_rx,
+            commands_rx,
+            _commands_tx: commands_tx,
+        }
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_polling_watchdog_restores_config() {
+        let mut harness = start_watchdog();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(harness.restorations.load(Ordering::SeqCst), 0);
+
+        harness.overwrites.store(1, Ordering::SeqCst);
+        wait_for(|| harness.restorations.load(Ordering::SeqCst) == 1);
+        wait_for(|| harness.tampered_rx.try_next().is_ok());
+        assert!(harness.commands_rx.try_next().is_err());
+    }
+
+    #[test]
+    fn test_polling_watchdog_gives_up() {
+        let mut harness = start_watchdog();
+        harness.overwrites.store(usize::MAX, Ordering::SeqCst);
+
+        let start = Instant::now();
+        let command = loop {
+            if let Ok(command) = harness.commands_rx.try_next() {
+                break command;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(5));
+        };
+        assert!(matches!(
+            command,
+            Some(TunnelCommand::Block(ErrorStateCause::SetDnsError))
+        ));
+        assert_eq!(harness.restorations.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_stopped_polling_watchdog_does_not_restore() {
+        let harness = start_watchdog();
+        harness.watchdog.stop();
+        harness.overwrites.store(1, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(harness.restorations.load(Ordering::SeqCst), 0);
+    }
+}
@@ -1,5 +1,4 @@
-use super::{iphlpapi, netsh, tcpip};
-use crate::dns::DnsMonitorT;
+use super::{iphlpapi, netsh, tcpip, DnsModule};
 use windows_sys::Win32::System::Rpc::RPC_S_SERVER_UNAVAILABLE;
 
 pub struct DnsMonitor {
@@ -40,7 +39,7 @@ impl InnerMonitor {
     }
 }
 
-impl DnsMonitorT for DnsMonitor {
+impl DnsModule for DnsMonitor {
     type Error = super::Error;
 
     fn new() -> Result<Self, Self::Error> {
@@ -3,15 +3,9 @@
 //! it requires at least Windows 10, build 19041. For that reason, use run-time linking and fall
 //! back on other methods if it is not available.
 
-use crate::dns::DnsMonitorT;
+use super::DnsModule;
 use once_cell::sync::OnceCell;
-use std::{
-    ffi::OsString,
-    io,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    os::windows::ffi::OsStrExt,
-    ptr,
-};
+use std::{ffi::OsString, io, net::IpAddr, os::windows::ffi::OsStrExt, ptr};
 use talpid_types::win32_err;
 use talpid_windows_net::{guid_from_luid, luid_from_alias};
 use windows_sys::{
@@ -118,7 +112,7 @@ impl DnsMonitor {
     }
 }
 
-impl DnsMonitorT for DnsMonitor {
+impl DnsModule for DnsMonitor {
     type Error = Error;
 
     fn new() -> Result<Self, Error> {
@@ -129,23 +123,11 @@ impl DnsMonitorT for DnsMonitor {
         let guid = guid_from_luid(&luid_from_alias(interface).map_err(Error::ObtainInterfaceLuid)?)
             .map_err(Error::ObtainInterfaceGuid)?;
 
-        let mut v4_servers = vec![];
-        let mut v6_servers = vec![];
-
-        for server in servers {
-            match server {
-                IpAddr::V4(addr) => v4_servers.push(addr),
-                IpAddr::V6(addr) => v6_servers.push(addr),
-            }
-        }
-
         self.current_guid = Some(guid);
 
-        if !v4_servers.is_empty() {
-            set_interface_dns_servers_v4(&guid, &v4_servers)?;
-        }
-        if !v6_servers.is_empty() {
-            set_interface_dns_servers_v6(&guid, &v6_servers)?;
+        // Always set both families, so that stale servers of the other family are cleared
+        for (flags, nameservers) in nameserver_settings(servers) {
+            set_interface_dns_servers(&guid, flags, &nameservers)?;
         }
 
         flush_dns_cache()?;
@@ -155,9 +137,11 @@ impl DnsMonitorT for DnsMonitor {
 
     fn reset(&mut self) -> Result<(), Error> {
         if let Some(guid) = self.current_guid.take() {
-            set_interface_dns_servers_v4(&guid, &[])
-                .and(set_interface_dns_servers_v6(&guid, &[]))
-                .and(flush_dns_cache())?;
+            let mut result = Ok(());
+            for (flags, nameservers) in nameserver_settings(&[]) {
+                result = result.and(set_interface_dns_servers(&guid, flags, &nameservers));
+            }
+            result.and(flush_dns_cache())?;
         }
         Ok(())
     }
@@ -169,27 +153,26 @@ impl DnsMonitorT for DnsMonitor {
     }
 }
 
-fn set_interface_dns_servers_v4(guid: &GUID, servers: &[&Ipv4Addr]) -> Result<(), Error> {
-    set_interface_dns_servers(guid, servers, DNS_SETTING_NAMESERVER)
-}
-
-fn set_interface_dns_servers_v6(guid: &GUID, servers: &[&Ipv6Addr]) -> Result<(), Error> {
-    set_interface_dns_servers(guid, servers, DNS_SETTING_NAMESERVER | DNS_SETTING_IPV6)
+/// Returns the `DNS_INTERFACE_SETTINGS` flags and comma-separated nameserver list to set for
+/// each address family. An empty list clears the nameservers of that family.
+fn nameserver_settings(servers: &[IpAddr]) -> [(u32, String); 2] {
+    let join = |is_ipv4: bool| {
+        servers
+            .iter()
+            .filter(|addr| addr.is_ipv4() == is_ipv4)
+            .map(|addr| addr.to_string())
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    [
+        (DNS_SETTING_NAMESERVER, join(true)),
+        (DNS_SETTING_NAMESERVER | DNS_SETTING_IPV6, join(false)),
+    ]
 }
 
-fn set_interface_dns_servers<T: ToString>(
-    guid: &GUID,
-    servers: &[T],
-    flags: u32,
-) -> Result<(), Error> {
+fn set_interface_dns_servers(guid: &GUID, flags: u32, nameservers: &str) -> Result<(), Error> {
     let iphlpapi = IPHLPAPI_HANDLE.get_or_try_init(IphlpApi::new)?;
 
-    // Create comma-separated nameserver list
-    let nameservers = servers
-        .iter()
-        .map(|addr| addr.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
     let mut nameservers: Vec<u16> = OsString::from(nameservers)
         .encode_wide()
         .chain(std::iter::once(0u16))
@@ -217,3 +200,47 @@ fn set_interface_dns_servers<T: ToString>(
 fn flush_dns_cache() -> Result<(), Error> {
     super::dnsapi::flush_resolver_cache().map_err(Error::FlushResolverCache)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nameserver_settings() {
+        let servers: Vec<IpAddr> = vec![
+            "10.64.0.1".parse().unwrap(),
+            "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
+            "9.9.9.9".parse().unwrap(),
+            "2620:fe::fe".parse().unwrap(),
+        ];
+        assert_eq!(
+            nameserver_settings(&servers),
+            [
+                (DNS_SETTING_NAMESERVER, "10.64.0.1,9.9.9.9".to_owned()),
+                (
+                    DNS_SETTING_NAMESERVER | DNS_SETTING_IPV6,
+                    "fc00:bbbb:bbbb:bb01::1,2620:fe::fe".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nameserver_settings_clear_missing_family() {
+        let servers: Vec<IpAddr> = vec!["10.64.0.1".parse().unwrap()];
+        assert_eq!(
+            nameserver_settings(&servers),
+            [
+                (DNS_SETTING_NAMESERVER, "10.64.0.1".to_owned()),
+                (DNS_SETTING_NAMESERVER | DNS_SETTING_IPV6, String::new()),
+            ]
+        );
+        assert_eq!(
+            nameserver_settings(&[]),
+            [
+                (DNS_SETTING_NAMESERVER, String::new()),
+                (DNS_SETTING_NAMESERVER | DNS_SETTING_IPV6, String::new()),
+            ]
+        );
+    }
+}
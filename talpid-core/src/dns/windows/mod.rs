@@ -1,6 +1,14 @@
-use std::{env, fmt, net::IpAddr};
-
-use super::DnsMonitorT;
+use std::{env, fmt, net::IpAddr, sync::Weak};
+
+use super::{
+    watchdog::{same_servers, PolledDnsConfig, PollingWatchdog, TamperNotifier},
+    DnsMonitorT, DnsTamperedSender,
+};
+use crate::tunnel_state_machine::TunnelCommand;
+use futures::channel::mpsc;
+use talpid_types::ErrorExt;
+use talpid_windows_net::{guid_from_luid, luid_from_alias};
+use windows_sys::core::GUID;
 
 mod auto;
 mod dnsapi;
@@ -24,14 +32,34 @@ pub enum Error {
     Tcpip(#[error(source)] tcpip::Error),
 }
 
+/// Implemented by the modules that can set DNS on Windows.
+trait DnsModule: Sized {
+    type Error: std::error::Error;
+
+    fn new() -> Result<Self, Self::Error>;
+
+    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
+
+    fn reset(&mut self) -> Result<(), Self::Error>;
+
+    fn reset_before_interface_removal(&mut self) -> Result<(), Self::Error> {
+        self.reset()
+    }
+}
+
 pub struct DnsMonitor {
     inner: DnsMonitorHolder,
+    notifier: TamperNotifier,
+    watchdog: Option<PollingWatchdog>,
 }
 
 impl DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new() -> Result<Self, Error> {
+    fn new(
+        tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+        tampered_tx: DnsTamperedSender,
+    ) -> Result<Self, Error> {
         let dns_module = env::var_os("TALPID_DNS_MODULE");
 
         let inner = match dns_module.as_ref().and_then(|value| value.to_str()) {
@@ -43,20 +71,29 @@ impl DnsMonitorT for DnsMonitor {
 
         log::debug!("DNS monitor: {}", inner);
 
-        Ok(DnsMonitor { inner })
+        Ok(DnsMonitor {
+            inner,
+            notifier: TamperNotifier::new(tx, tampered_tx),
+            watchdog: None,
+        })
     }
 
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Error> {
+        self.watchdog = None;
         match self.inner {
             DnsMonitorHolder::Auto(ref mut inner) => inner.set(interface, servers)?,
             DnsMonitorHolder::Iphlpapi(ref mut inner) => inner.set(interface, servers)?,
             DnsMonitorHolder::Netsh(ref mut inner) => inner.set(interface, servers)?,
             DnsMonitorHolder::Tcpip(ref mut inner) => inner.set(interface, servers)?,
         }
+        if !servers.is_empty() {
+            self.start_watchdog(interface, servers);
+        }
         Ok(())
     }
 
     fn reset(&mut self) -> Result<(), Error> {
+        self.watchdog = None;
         match self.inner {
             DnsMonitorHolder::Auto(ref mut inner) => inner.reset()?,
             DnsMonitorHolder::Iphlpapi(ref mut inner) => inner.reset()?,
@@ -67,6 +104,7 @@ impl DnsMonitorT for DnsMonitor {
     }
 
     fn reset_before_interface_removal(&mut self) -> Result<(), Error> {
+        self.watchdog = None;
         match self.inner {
             DnsMonitorHolder::Auto(ref mut inner) => inner.reset_before_interface_removal()?,
             DnsMonitorHolder::Iphlpapi(ref mut inner) => inner.reset_before_interface_removal()?,
@@ -77,6 +115,55 @@ impl DnsMonitorT for DnsMonitor {
     }
 }
 
+impl DnsMonitor {
+    fn start_watchdog(&mut self, interface: &str, servers: &[IpAddr]) {
+        let guid = match luid_from_alias(interface).and_then(|luid| guid_from_luid(&luid)) {
+            Ok(guid) => guid,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain GUID of the tunnel interface")
+                );
+                return;
+            }
+        };
+        let config = InterfaceDnsConfig {
+            guid,
+            servers: servers.to_vec(),
+        };
+        self.watchdog = Some(PollingWatchdog::start(
+            "tunnel interface DNS config",
+            config,
+            self.notifier.clone(),
+        ));
+    }
+}
+
+/// The DNS servers of the tunnel interface, which are enforced by a [`PollingWatchdog`]. All DNS
+/// modules persist the servers in the TCP/IP parameters of the interface, so other servers there
+/// mean that they have been overwritten. They are restored by writing the parameters directly.
+struct InterfaceDnsConfig {
+    guid: GUID,
+    servers: Vec<IpAddr>,
+}
+
+impl PolledDnsConfig for InterfaceDnsConfig {
+    type Error = tcpip::Error;
+
+    fn check(&mut self) -> Result<Option<String>, tcpip::Error> {
+        let servers = tcpip::get_dns(&self.guid)?;
+        if same_servers(&servers, &self.servers) {
+            return Ok(None);
+        }
+        Ok(Some(format!("servers {servers:?}")))
+    }
+
+    fn restore(&mut self) -> Result<(), tcpip::Error> {
+        tcpip::set_dns(&self.guid, &self.servers)?;
+        tcpip::flush_dns_cache()
+    }
+}
+
 enum DnsMonitorHolder {
     Auto(auto::DnsMonitor),
     Iphlpapi(iphlpapi::DnsMonitor),
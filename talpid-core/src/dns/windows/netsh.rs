@@ -1,4 +1,4 @@
-use crate::dns::DnsMonitorT;
+use super::DnsModule;
 use std::{
     ffi::OsString,
     io::{self, Write},
@@ -61,7 +61,7 @@ pub struct DnsMonitor {
     current_index: Option<u32>,
 }
 
-impl DnsMonitorT for DnsMonitor {
+impl DnsModule for DnsMonitor {
     type Error = Error;
 
     fn new() -> Result<Self, Error> {
@@ -77,36 +77,7 @@ impl DnsMonitorT for DnsMonitor {
 
         self.current_index = Some(interface_index);
 
-        let mut added_ipv4_server = false;
-        let mut added_ipv6_server = false;
-
-        let mut netsh_input = String::new();
-
-        for server in servers {
-            let is_additional_server;
-
-            if server.is_ipv4() {
-                is_additional_server = added_ipv4_server;
-                added_ipv4_server = true;
-            } else {
-                is_additional_server = added_ipv6_server;
-                added_ipv6_server = true;
-            };
-
-            if is_additional_server {
-                netsh_input.push_str(&create_netsh_add_command(interface_index, server));
-            } else {
-                netsh_input.push_str(&create_netsh_set_command(interface_index, server));
-            }
-        }
-
-        if !added_ipv4_server {
-            netsh_input.push_str(&create_netsh_flush_command(interface_index, IpVersion::V4));
-        }
-        if !added_ipv6_server {
-            netsh_input.push_str(&create_netsh_flush_command(interface_index, IpVersion::V6));
-        }
-
+        let netsh_input = create_netsh_set_input(interface_index, servers);
         run_netsh_with_timeout(netsh_input, NETSH_TIMEOUT)?;
 
         Ok(())
@@ -114,9 +85,7 @@ impl DnsMonitorT for DnsMonitor {
 
     fn reset(&mut self) -> Result<(), Error> {
         if let Some(index) = self.current_index.take() {
-            let mut netsh_input = String::new();
-            netsh_input.push_str(&create_netsh_flush_command(index, IpVersion::V4));
-            netsh_input.push_str(&create_netsh_flush_command(index, IpVersion::V6));
+            let netsh_input = create_netsh_reset_input(index);
 
             if let Err(error) = run_netsh_with_timeout(netsh_input, NETSH_TIMEOUT) {
                 log::error!("{}", error.display_chain_with_msg("Failed to reset DNS"));
@@ -177,6 +146,50 @@ fn wait_for_child(subproc: &mut Child, timeout: Duration) -> io::Result<Option<E
     }
 }
 
+/// Returns the netsh commands that set the DNS servers of both families on an interface. The
+/// servers of a family that is missing from `servers` are cleared.
+fn create_netsh_set_input(interface_index: u32, servers: &[IpAddr]) -> String {
+    let mut added_ipv4_server = false;
+    let mut added_ipv6_server = false;
+
+    let mut netsh_input = String::new();
+
+    for server in servers {
+        let is_additional_server;
+
+        if server.is_ipv4() {
+            is_additional_server = added_ipv4_server;
+            added_ipv4_server = true;
+        } else {
+            is_additional_server = added_ipv6_server;
+            added_ipv6_server = true;
+        };
+
+        if is_additional_server {
+            netsh_input.push_str(&create_netsh_add_command(interface_index, server));
+        } else {
+            netsh_input.push_str(&create_netsh_set_command(interface_index, server));
+        }
+    }
+
+    if !added_ipv4_server {
+        netsh_input.push_str(&create_netsh_flush_command(interface_index, IpVersion::V4));
+    }
+    if !added_ipv6_server {
+        netsh_input.push_str(&create_netsh_flush_command(interface_index, IpVersion::V6));
+    }
+
+    netsh_input
+}
+
+/// Returns the netsh commands that clear the DNS servers of both families on an interface.
+fn create_netsh_reset_input(interface_index: u32) -> String {
+    let mut netsh_input = String::new();
+    netsh_input.push_str(&create_netsh_flush_command(interface_index, IpVersion::V4));
+    netsh_input.push_str(&create_netsh_flush_command(interface_index, IpVersion::V6));
+    netsh_input
+}
+
 fn create_netsh_set_command(interface_index: u32, server: &IpAddr) -> String {
     // Set primary DNS server:
     // netsh interface ipv4 set dnsservers name="Mullvad" source=static address=10.64.0.1
@@ -216,3 +229,42 @@ fn get_system_dir() -> io::Result<PathBuf> {
         &sysdir[0..(len as usize)],
     )))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_both_families() {
+        let servers: Vec<IpAddr> = vec![
+            "10.64.0.1".parse().unwrap(),
+            "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
+            "9.9.9.9".parse().unwrap(),
+        ];
+        assert_eq!(
+            create_netsh_set_input(5, &servers),
+            "interface ipv4 set dnsservers name=5 source=static address=10.64.0.1 validate=no\r\n\
+             interface ipv6 set dnsservers name=5 source=static address=fc00:bbbb:bbbb:bb01::1 validate=no\r\n\
+             interface ipv4 add dnsservers name=5 address=9.9.9.9 validate=no\r\n"
+        );
+    }
+
+    #[test]
+    fn test_clear_missing_family() {
+        let servers: Vec<IpAddr> = vec!["10.64.0.1".parse().unwrap()];
+        assert_eq!(
+            create_netsh_set_input(5, &servers),
+            "interface ipv4 set dnsservers name=5 source=static address=10.64.0.1 validate=no\r\n\
+             interface ipv6 set dnsservers name=5 source=static address=none validate=no\r\n"
+        );
+    }
+
+    #[test]
+    fn test_reset_both_families() {
+        assert_eq!(
+            create_netsh_reset_input(5),
+            "interface ipv4 set dnsservers name=5 source=static address=none validate=no\r\n\
+             interface ipv6 set dnsservers name=5 source=static address=none validate=no\r\n"
+        );
+    }
+}
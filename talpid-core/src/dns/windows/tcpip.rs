@@ -1,10 +1,10 @@
-use crate::dns::DnsMonitorT;
+use super::DnsModule;
 use std::{io, net::IpAddr};
 use talpid_types::ErrorExt;
 use talpid_windows_net::{guid_from_luid, luid_from_alias};
 use windows_sys::{core::GUID, Win32::System::Com::StringFromGUID2};
 use winreg::{
-    enums::{HKEY_LOCAL_MACHINE, KEY_SET_VALUE},
+    enums::{HKEY_LOCAL_MACHINE, KEY_READ, KEY_SET_VALUE},
     transaction::Transaction,
     RegKey,
 };
@@ -28,6 +28,10 @@ pub enum Error {
     /// Failed to update DNS servers for interface.
     #[error(display = "Failed to update interface DNS servers")]
     SetResolvers(#[error(source)] io::Error),
+
+    /// Failed to read DNS servers of interface.
+    #[error(display = "Failed to read interface DNS servers")]
+    GetResolvers(#[error(source)] io::Error),
 }
 
 pub struct DnsMonitor {
@@ -35,7 +39,7 @@ pub struct DnsMonitor {
     should_flush: bool,
 }
 
-impl DnsMonitorT for DnsMonitor {
+impl DnsModule for DnsMonitor {
     type Error = Error;
 
     fn new() -> Result<Self, Error> {
@@ -74,7 +78,7 @@ impl DnsMonitor {
     }
 }
 
-fn set_dns(interface: &GUID, servers: &[IpAddr]) -> Result<(), Error> {
+pub(super) fn set_dns(interface: &GUID, servers: &[IpAddr]) -> Result<(), Error> {
     let transaction = Transaction::new().map_err(Error::SetResolvers)?;
     let result = match set_dns_inner(&transaction, interface, servers) {
         Ok(()) => transaction.commit(),
@@ -107,6 +111,34 @@ fn set_dns_inner(
     Ok(())
 }
 
+/// Returns the DNS servers that are set in the TCP/IP parameters of the interface.
+pub(super) fn get_dns(interface: &GUID) -> Result<Vec<IpAddr>, Error> {
+    let guid_str = string_from_guid(interface);
+    let mut servers = vec![];
+    for service in ["Tcpip", "Tcpip6"] {
+        let reg_path = format!(
+            r#"SYSTEM\CurrentControlSet\Services\{service}\Parameters\Interfaces\{guid_str}"#
+        );
+        let adapter_key =
+            match RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey_with_flags(reg_path, KEY_READ) {
+                Ok(adapter_key) => adapter_key,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(Error::GetResolvers(error)),
+            };
+        let nameservers: String = match adapter_key.get_value("NameServer") {
+            Ok(nameservers) => nameservers,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(Error::GetResolvers(error)),
+        };
+        servers.extend(
+            nameservers
+                .split([',', ' '])
+                .filter_map(|server| server.parse::<IpAddr>().ok()),
+        );
+    }
+    Ok(servers)
+}
+
 fn config_interface<'a>(
     transaction: &Transaction,
     guid: &str,
@@ -156,7 +188,7 @@ fn config_interface<'a>(
     Ok(())
 }
 
-fn flush_dns_cache() -> Result<(), Error> {
+pub(super) fn flush_dns_cache() -> Result<(), Error> {
     super::dnsapi::flush_resolver_cache().map_err(Error::FlushResolverCache)
 }
 
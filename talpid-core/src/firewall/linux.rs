@@ -1,4 +1,4 @@
-use super::{FirewallArguments, FirewallPolicy};
+use super::{FirewallArguments, FirewallPolicy, IPV6_LINK_LOCAL};
 use crate::{split_tunnel, tunnel};
 use ipnetwork::IpNetwork;
 use libc;
@@ -9,17 +9,52 @@ use nftnl::{
 };
 use once_cell::sync::Lazy;
 use std::{
+    collections::BTreeMap,
     env,
     ffi::{CStr, CString},
-    fs, io,
+    fs, io, mem,
     net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+};
+use talpid_types::{
+    net::{AllowedTunnelTraffic, Endpoint, TransportProtocol},
+    split_tunnel::{ExcludedDestination, PortRange, SplitTunnelMode},
+    ErrorExt,
 };
-use talpid_types::net::{AllowedTunnelTraffic, Endpoint, TransportProtocol};
 
 /// Priority for rules that tag split tunneling packets. Equals NF_IP_PRI_MANGLE.
 const MANGLE_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_MANGLE;
 const PREROUTING_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_CONNTRACK + 1;
-const PROC_SYS_NET_IPV4_CONF_SRC_VALID_MARK: &str = "/proc/sys/net/ipv4/conf/all/src_valid_mark";
+/// Makes the reverse path filter take the firewall mark into account. Replies that are marked in
+/// the prerouting chain, such as those from the relay or a DHCP server, would otherwise be dropped
+/// by strict reverse path filtering, since the reverse path without the mark is the tunnel.
+const SRC_VALID_MARK_SYSCTL: &str = "net/ipv4/conf/all/src_valid_mark";
+
+/// DHCP client traffic, as its direction along with its UDP source and destination ports. It is
+/// routed outside the tunnel, so that leases can be renewed with servers that are not on the local
+/// subnet.
+const DHCP_CLIENT_TRAFFIC: [(Direction, u16, u16); 4] = [
+    (
+        Direction::Out,
+        super::DHCPV4_CLIENT_PORT,
+        super::DHCPV4_SERVER_PORT,
+    ),
+    (
+        Direction::In,
+        super::DHCPV4_SERVER_PORT,
+        super::DHCPV4_CLIENT_PORT,
+    ),
+    (
+        Direction::Out,
+        super::DHCPV6_CLIENT_PORT,
+        super::DHCPV6_SERVER_PORT,
+    ),
+    (
+        Direction::In,
+        super::DHCPV6_SERVER_PORT,
+        super::DHCPV6_CLIENT_PORT,
+    ),
+];
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -65,6 +100,7 @@ static FORWARD_CHAIN_NAME: Lazy<CString> = Lazy::new(|| CString::new("forward").
 static PREROUTING_CHAIN_NAME: Lazy<CString> = Lazy::new(|| CString::new("prerouting").unwrap());
 static MANGLE_CHAIN_NAME: Lazy<CString> = Lazy::new(|| CString::new("mangle").unwrap());
 static NAT_CHAIN_NAME: Lazy<CString> = Lazy::new(|| CString::new("nat").unwrap());
+static NAT_OUTPUT_CHAIN_NAME: Lazy<CString> = Lazy::new(|| CString::new("natoutput").unwrap());
 
 /// Allows controlling whether firewall rules should have packet counters or not from an env
 /// variable. Useful for debugging the rules.
@@ -92,26 +128,581 @@ enum End {
     Dst,
 }
 
+/// A single match of a rule that is generated from a list, so that the list can be tested without
+/// netfilter.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Match {
+    Ip(End, IpAddr),
+    Net(End, IpNetwork),
+    Port(TransportProtocol, End, u16),
+    Traffic(ExcludedTraffic),
+    Destination(ExcludedDestination),
+    Interface(ExcludedInterface),
+    /// Traffic that does not arrive on the interface with this name.
+    NotInterface(CString),
+    /// Traffic of connections that are marked with [`split_tunnel::MARK`].
+    Marked,
+    /// The first packet of a connection.
+    NewConnection,
+}
+
+impl Match {
+    fn add_to(&self, rule: &mut Rule<'_>) {
+        match *self {
+            Match::Ip(end, ip) => check_ip(rule, end, ip),
+            Match::Net(end, net) => check_net(rule, end, net),
+            Match::Port(protocol, end, port) => check_port(rule, protocol, end, port),
+            Match::Traffic(traffic) => traffic.add_match(rule),
+            Match::Interface(ref interface) => interface.add_match(rule),
+            Match::NotInterface(ref name) => {
+                rule.add_expr(&nft_expr!(meta iifname));
+                rule.add_expr(&nft_expr!(cmp != expr::InterfaceName::Exact(name.clone())));
+            }
+            Match::Marked => {
+                rule.add_expr(&nft_expr!(ct mark));
+                rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
+            }
+            Match::NewConnection => {
+                let new_state = nftnl::expr::ct::States::NEW.bits();
+                rule.add_expr(&nft_expr!(ct state));
+                rule.add_expr(&nft_expr!(bitwise mask new_state, xor 0u32));
+                rule.add_expr(&nft_expr!(cmp != 0u32));
+            }
+            Match::Destination(destination) => {
+                check_net(rule, End::Dst, destination.network);
+                match destination.ports {
+                    Some(ports) => check_port_range(rule, destination.protocol, End::Dst, ports),
+                    None => check_l4proto(rule, destination.protocol),
+                }
+            }
+        }
+    }
+}
+
+/// What a split tunnel rule does with the traffic that it matches.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum SplitTunnelAction {
+    /// Stops processing the chain, so that the rules that follow do not apply to the traffic.
+    Skip,
+    /// Marks the connection and the packet with [`split_tunnel::MARK`] and the fwmark, so that
+    /// it is routed outside the tunnel and accepted by the filter chains.
+    Mark,
+    /// Marks the traffic like [`SplitTunnelAction::Mark`], and redirects it to `target`.
+    Redirect { target: IpAddr },
+    /// Accepts the traffic in a filter chain.
+    Accept,
+    /// Drops the traffic in a filter chain.
+    Drop,
+}
+
+/// A split tunnel rule that is generated from a list, so that the list can be tested without
+/// netfilter.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct SplitTunnelRule {
+    matches: Vec<Match>,
+    action: SplitTunnelAction,
+}
+
+impl SplitTunnelRule {
+    fn new(matches: Vec<Match>, action: SplitTunnelAction) -> Self {
+        SplitTunnelRule { matches, action }
+    }
+}
+
+/// Identifies traffic from the processes that are selected for split tunneling. Traffic that is
+/// sent outside the tunnel is marked with [`split_tunnel::MARK`], so that it is handled the same
+/// way regardless of why it is excluded. In include mode, all other traffic is marked instead.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ExcludedTraffic {
+    /// Traffic from processes in the split tunnel cgroup.
+    CGroup,
+    /// Traffic from sockets owned by a user.
+    User(u32),
+    /// Traffic from sockets owned by a group.
+    Group(u32),
+}
+
+impl ExcludedTraffic {
+    fn add_match(&self, rule: &mut Rule<'_>) {
+        match *self {
+            ExcludedTraffic::CGroup => {
+                rule.add_expr(&nft_expr!(meta cgroup));
+                rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
+            }
+            ExcludedTraffic::User(uid) => {
+                rule.add_expr(&nft_expr!(meta skuid));
+                rule.add_expr(&nft_expr!(cmp == uid));
+            }
+            ExcludedTraffic::Group(gid) => {
+                rule.add_expr(&nft_expr!(meta skgid));
+                rule.add_expr(&nft_expr!(cmp == gid));
+            }
+        }
+    }
+}
+
+/// Returns the traffic that is excluded from the tunnel. Processes in the cgroup are always
+/// excluded, followed by the given users and groups. Duplicates and the root user and group are
+/// ignored, since excluding them would also exclude the daemon.
+fn excluded_traffic(owners: &split_tunnel::ExcludedOwners) -> Vec<ExcludedTraffic> {
+    let mut excluded = vec![ExcludedTraffic::CGroup];
+    let users = owners.users.iter().map(|uid| ExcludedTraffic::User(*uid));
+    let groups = owners.groups.iter().map(|gid| ExcludedTraffic::Group(*gid));
+    for traffic in users.chain(groups) {
+        match traffic {
+            ExcludedTraffic::User(super::ROOT_UID) | ExcludedTraffic::Group(0) => {
+                log::warn!("Not excluding the traffic of the root user or group");
+            }
+            traffic if !excluded.contains(&traffic) => excluded.push(traffic),
+            _ => (),
+        }
+    }
+    excluded
+}
+
+/// Returns the rules of the mangle chain that mark the traffic that is sent outside the tunnel.
+/// In include mode, the selected processes skip the chain, and everything else is marked.
+fn mark_rules(split_tunnel: &SplitTunnelRules, policy: &FirewallPolicy) -> Vec<SplitTunnelRule> {
+    let SplitTunnelRules {
+        ref excluded,
+        mode,
+        ref destinations,
+        ..
+    } = *split_tunnel;
+    if !splits_traffic(mode, policy) {
+        return vec![];
+    }
+    // Destinations are excluded for every process, so they must be marked before the selected
+    // processes skip the chain in include mode.
+    let mut rules: Vec<SplitTunnelRule> = destinations
+        .iter()
+        .map(|destination| {
+            SplitTunnelRule::new(
+                vec![Match::Destination(*destination)],
+                SplitTunnelAction::Mark,
+            )
+        })
+        .collect();
+    let selected = excluded
+        .iter()
+        .map(|traffic| vec![Match::Traffic(*traffic)]);
+    match mode {
+        SplitTunnelMode::Exclude => rules
+            .extend(selected.map(|matches| SplitTunnelRule::new(matches, SplitTunnelAction::Mark))),
+        SplitTunnelMode::Include => {
+            rules.extend(
+                selected.map(|matches| SplitTunnelRule::new(matches, SplitTunnelAction::Skip)),
+            );
+            rules.push(SplitTunnelRule::new(vec![], SplitTunnelAction::Mark));
+        }
+    }
+    rules
+}
+
+/// Returns the rules of the prerouting chain that mark the traffic that is forwarded from the
+/// excluded interfaces, such as that of containers, before it is routed. The mark is kept for the
+/// whole connection, so that the forward chain accepts the replies and the connection is
+/// masqueraded like that of excluded processes.
+fn forward_mark_rules(
+    split_tunnel: &SplitTunnelRules,
+    policy: &FirewallPolicy,
+) -> Vec<SplitTunnelRule> {
+    if !splits_traffic(split_tunnel.mode, policy) {
+        return vec![];
+    }
+    split_tunnel
+        .interfaces
+        .iter()
+        .map(|interface| {
+            SplitTunnelRule::new(
+                vec![Match::Interface(interface.clone())],
+                SplitTunnelAction::Mark,
+            )
+        })
+        .collect()
+}
+
+/// Returns the rules that mark connections to and from the excluded local ports like those of
+/// excluded processes, so that services that listen on them are reached, and reply, outside the
+/// tunnel. Incoming connections are marked in the prerouting chain, before they are routed, unless
+/// they arrive through the loopback interface or the tunnel, so that replies to peers in the
+/// tunnel stay in it. Outgoing connections that are opened from a port are marked in the mangle
+/// chain. The routing mark is not kept between packets, so it is restored on every outgoing packet
+/// of a marked connection. The mangle chain rules must be added before any rules that skip it.
+fn local_port_rules(
+    split_tunnel: &SplitTunnelRules,
+    policy: &FirewallPolicy,
+) -> Vec<(Direction, SplitTunnelRule)> {
+    if !splits_traffic(split_tunnel.mode, policy) {
+        return vec![];
+    }
+    let tunnel = match policy {
+        FirewallPolicy::Connecting { tunnel, .. } => tunnel.as_ref(),
+        FirewallPolicy::Connected { tunnel, .. } => Some(tunnel),
+        FirewallPolicy::Blocked { .. } | FirewallPolicy::RestrictDns { .. } => None,
+    };
+    let not_incoming_interfaces: Vec<Match> = ["lo"]
+        .into_iter()
+        .chain(tunnel.map(|tunnel| tunnel.interface.as_str()))
+        .filter_map(|name| CString::new(name).ok())
+        .map(Match::NotInterface)
+        .collect();
+
+    let mut rules = vec![];
+    for &(protocol, port) in &split_tunnel.local_ports {
+        let mut incoming = not_incoming_interfaces.clone();
+        incoming.push(Match::Port(protocol, End::Dst, port));
+        rules.push((
+            Direction::In,
+            SplitTunnelRule::new(incoming, SplitTunnelAction::Mark),
+        ));
+        for connection in [Match::NewConnection, Match::Marked] {
+            let outgoing = vec![Match::Port(protocol, End::Src, port), connection];
+            rules.push((
+                Direction::Out,
+                SplitTunnelRule::new(outgoing, SplitTunnelAction::Mark),
+            ));
+        }
+    }
+    rules
+}
+
+/// Returns the rules of the NAT output chain that redirect DNS requests from the traffic that is
+/// sent outside the tunnel, using the pairs of servers and targets from
+/// [`excluded_dns_redirects`]. The requests must be redirected before they reach the filter
+/// chains, since those only accept excluded traffic that is marked.
+fn dns_redirect_rules(
+    excluded: &[ExcludedTraffic],
+    mode: SplitTunnelMode,
+    redirects: &[(IpAddr, IpAddr)],
+) -> Vec<SplitTunnelRule> {
+    if redirects.is_empty() {
+        return vec![];
+    }
+    let mut rules = vec![];
+    let selected: Vec<Option<ExcludedTraffic>> = match mode {
+        SplitTunnelMode::Exclude => excluded.iter().copied().map(Some).collect(),
+        SplitTunnelMode::Include => {
+            rules.extend(excluded.iter().map(|traffic| {
+                SplitTunnelRule::new(vec![Match::Traffic(*traffic)], SplitTunnelAction::Skip)
+            }));
+            vec![None]
+        }
+    };
+    for &(server, target) in redirects {
+        for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+            for traffic in &selected {
+                let matches = traffic
+                    .map(Match::Traffic)
+                    .into_iter()
+                    .chain([
+                        Match::Ip(End::Dst, server),
+                        Match::Port(protocol, End::Dst, 53),
+                    ])
+                    .collect();
+                rules.push(SplitTunnelRule::new(
+                    matches,
+                    SplitTunnelAction::Redirect { target },
+                ));
+            }
+        }
+    }
+    rules
+}
+
+/// Returns the rules of the mangle chain that restore the routing mark on the packets that follow
+/// the first one of a DNS request that was redirected by [`dns_redirect_rules`]. Only the first
+/// packet of a connection passes through the NAT chains, so the rest of a TCP request, or another
+/// query sent from the same UDP socket, would otherwise be routed into the tunnel, where marked
+/// traffic is dropped. The rules must be added before the rules that accept DNS requests to the
+/// tunnel.
+fn dns_redirect_mark_rules(redirects: &[(IpAddr, IpAddr)]) -> Vec<SplitTunnelRule> {
+    let mut rules = vec![];
+    for &(server, _) in redirects {
+        for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+            let matches = vec![
+                Match::Marked,
+                Match::Ip(End::Dst, server),
+                Match::Port(protocol, End::Dst, 53),
+            ];
+            rules.push(SplitTunnelRule::new(matches, SplitTunnelAction::Mark));
+        }
+    }
+    rules
+}
+
+/// Returns the destinations to generate rules for. Destinations that are covered by another one
+/// are skipped, since their rules would never match.
+fn excluded_destinations(destinations: &[ExcludedDestination]) -> Vec<ExcludedDestination> {
+    let mut excluded: Vec<ExcludedDestination> = vec![];
+    for (i, destination) in destinations.iter().enumerate() {
+        let covered = destinations.iter().enumerate().any(|(j, other)| {
+            // Of two identical destinations, only the first one is kept
+            i != j && covers(other, destination) && (j < i || !covers(destination, other))
+        });
+        if !covered {
+            excluded.push(*destination);
+        }
+    }
+    excluded
+}
+
+/// Matches the name of an interface whose forwarded traffic is excluded from the tunnel.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum ExcludedInterface {
+    /// The interface with exactly this name.
+    Exact(CString),
+    /// All interfaces whose names start with this prefix.
+    Prefix(CString),
+}
+
+impl ExcludedInterface {
+    fn add_match(&self, rule: &mut Rule<'_>) {
+        rule.add_expr(&nft_expr!(meta iifname));
+        let name = match self {
+            ExcludedInterface::Exact(name) => expr::InterfaceName::Exact(name.clone()),
+            ExcludedInterface::Prefix(prefix) => expr::InterfaceName::StartingWith(prefix.clone()),
+        };
+        rule.add_expr(&nft_expr!(cmp == name));
+    }
+
+    /// Returns whether every interface matched by `other` is also matched by `self`.
+    fn covers(&self, other: &ExcludedInterface) -> bool {
+        match (self, other) {
+            (ExcludedInterface::Exact(name), ExcludedInterface::Exact(other)) => name == other,
+            (ExcludedInterface::Exact(_), ExcludedInterface::Prefix(_)) => false,
+            (
+                ExcludedInterface::Prefix(prefix),
+                ExcludedInterface::Exact(other) | ExcludedInterface::Prefix(other),
+            ) => other.to_bytes().starts_with(prefix.to_bytes()),
+        }
+    }
+}
+
+/// Returns the interfaces to generate rules for. A name that ends with `*`, such as `br-*`,
+/// matches all interfaces that start with the rest of it. Names that are matched by another entry
+/// and names that cannot be interface names are skipped.
+fn excluded_interfaces(names: &[String]) -> Vec<ExcludedInterface> {
+    let parsed: Vec<ExcludedInterface> = names
+        .iter()
+        .filter_map(|name| {
+            let (pattern, is_prefix) = match name.strip_suffix('*') {
+                Some(prefix) => (prefix, true),
+                None => (name.as_str(), false),
+            };
+            // An empty prefix would match every interface, including the physical ones
+            match CString::new(pattern) {
+                Ok(pattern) if !pattern.is_empty() && pattern.as_bytes().len() < libc::IFNAMSIZ => {
+                    Some(match is_prefix {
+                        true => ExcludedInterface::Prefix(pattern),
+                        false => ExcludedInterface::Exact(pattern),
+                    })
+                }
+                _ => {
+                    log::warn!("Not excluding invalid interface name {name:?}");
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let mut excluded: Vec<ExcludedInterface> = vec![];
+    for (i, interface) in parsed.iter().enumerate() {
+        let covered = parsed.iter().enumerate().any(|(j, other)| {
+            // Of two identical entries, only the first one is kept
+            i != j && other.covers(interface) && (j < i || !interface.covers(other))
+        });
+        if !covered {
+            excluded.push(interface.clone());
+        }
+    }
+    excluded
+}
+
+/// Returns the local ports to generate rules for, without duplicates, in the order that they were
+/// first given.
+fn excluded_local_ports(ports: &[(TransportProtocol, u16)]) -> Vec<(TransportProtocol, u16)> {
+    let mut excluded: Vec<(TransportProtocol, u16)> = vec![];
+    for port in ports {
+        if !excluded.contains(port) {
+            excluded.push(*port);
+        }
+    }
+    excluded
+}
+
+/// Returns whether all traffic to `inner` is also traffic to `outer`.
+fn covers(outer: &ExcludedDestination, inner: &ExcludedDestination) -> bool {
+    let ports_covered = match (outer.ports, inner.ports) {
+        (None, _) => true,
+        (Some(outer), Some(inner)) => outer.start <= inner.start && inner.end <= outer.end,
+        (Some(_), None) => false,
+    };
+    outer.protocol == inner.protocol
+        && outer.network.is_ipv4() == inner.network.is_ipv4()
+        && outer.network.prefix() <= inner.network.prefix()
+        && outer.network.contains(inner.network.ip())
+        && ports_covered
+}
+
+/// Returns whether any traffic is sent outside the tunnel under `policy`. Excluded processes
+/// bypass the firewall in every state, but in include mode, the traffic that is not selected is
+/// only allowed while connected, regardless of whether lockdown mode is enabled.
+fn splits_traffic(mode: SplitTunnelMode, policy: &FirewallPolicy) -> bool {
+    match mode {
+        SplitTunnelMode::Exclude => true,
+        SplitTunnelMode::Include => matches!(policy, FirewallPolicy::Connected { .. }),
+    }
+}
+
+/// Returns the rules of the input and output chains that block marked traffic to and from the
+/// LAN, when neither the policy nor `excluded_apps_allow_lan` allows it. They must be added before
+/// the rules that accept marked traffic. Otherwise, excluded processes reach the LAN through those
+/// rules, since only marked connections are accepted. Connections that are opened from the LAN
+/// cannot be attributed to a process before they are delivered, so they are only accepted if the
+/// policy allows LAN traffic for every process.
+fn excluded_lan_rules(
+    split_tunnel: &SplitTunnelRules,
+    policy: &FirewallPolicy,
+) -> Vec<(Direction, SplitTunnelRule)> {
+    let (allow_lan, excluded_dns_servers) = match policy {
+        FirewallPolicy::Connecting { allow_lan, .. }
+        | FirewallPolicy::Blocked { allow_lan, .. } => (*allow_lan, &[][..]),
+        FirewallPolicy::Connected {
+            allow_lan,
+            excluded_dns_servers,
+            ..
+        } => (*allow_lan, &excluded_dns_servers[..]),
+        // Everything but DNS is allowed in this state
+        FirewallPolicy::RestrictDns { .. } => (true, &[][..]),
+    };
+    if allow_lan
+        || split_tunnel.excluded_apps_allow_lan
+        || !splits_traffic(split_tunnel.mode, policy)
+    {
+        return vec![];
+    }
+
+    let directions = [(Direction::Out, End::Dst), (Direction::In, End::Src)];
+    let mut rules = vec![];
+    // The DNS requests of excluded processes are redirected to these servers
+    for server in excluded_dns_servers {
+        for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+            for (direction, end) in directions {
+                let matches = vec![
+                    Match::Ip(end, *server),
+                    Match::Port(protocol, end, 53),
+                    Match::Marked,
+                ];
+                rules.push((
+                    direction,
+                    SplitTunnelRule::new(matches, SplitTunnelAction::Accept),
+                ));
+            }
+        }
+    }
+    let lan_nets = super::ALLOWED_LAN_NETS
+        .iter()
+        .chain(&*super::ALLOWED_LAN_MULTICAST_NETS);
+    for net in lan_nets {
+        for (direction, end) in directions {
+            let matches = vec![Match::Net(end, *net), Match::Marked];
+            rules.push((
+                direction,
+                SplitTunnelRule::new(matches, SplitTunnelAction::Drop),
+            ));
+        }
+    }
+    rules
+}
+
+/// The split tunnel configuration that firewall rules are generated for.
+struct SplitTunnelRules {
+    excluded: Vec<ExcludedTraffic>,
+    mode: SplitTunnelMode,
+    destinations: Vec<ExcludedDestination>,
+    interfaces: Vec<ExcludedInterface>,
+    excluded_apps_allow_lan: bool,
+    local_ports: Vec<(TransportProtocol, u16)>,
+}
+
 /// The Linux implementation for the firewall and DNS.
 pub struct Firewall {
     fwmark: u32,
+    /// Users and groups whose traffic is excluded from the tunnel.
+    excluded_owners: split_tunnel::ExcludedOwners,
+    /// Split tunnel settings that only affect the firewall rules.
+    split_tunnel: split_tunnel::Config,
+    /// The policy that is currently applied, if any.
+    policy: Option<FirewallPolicy>,
+    /// Kernel parameters that have been changed, which are restored when the policy is reset.
+    sysctls: Sysctls,
 }
 
 impl Firewall {
     pub fn from_args(args: FirewallArguments) -> Result<Self> {
-        Firewall::new(args.fwmark)
+        let mut firewall = Firewall::new(args.fwmark)?;
+        firewall.excluded_owners = args.excluded_owners;
+        firewall.split_tunnel = args.split_tunnel;
+        Ok(firewall)
     }
 
     pub fn new(fwmark: u32) -> Result<Self> {
-        Ok(Firewall { fwmark })
+        Ok(Firewall {
+            fwmark,
+            excluded_owners: split_tunnel::ExcludedOwners::default(),
+            split_tunnel: split_tunnel::Config::default(),
+            policy: None,
+            sysctls: Sysctls::new(),
+        })
     }
 
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
         let table = Table::new(&*TABLE_NAME, ProtoFamily::Inet);
-        let batch = PolicyBatch::new(&table).finalize(&policy, self.fwmark)?;
+        let batch =
+            PolicyBatch::new(&table).finalize(&policy, self.fwmark, &self.split_tunnel_rules())?;
         Self::send_and_process(&batch)?;
-        Self::apply_kernel_config(&policy);
-        self.verify_tables(&[&TABLE_NAME])
+        self.apply_kernel_config(&policy);
+        self.verify_tables(&[&TABLE_NAME])?;
+        self.policy = Some(policy);
+        Ok(())
+    }
+
+    fn split_tunnel_rules(&self) -> SplitTunnelRules {
+        SplitTunnelRules {
+            excluded: excluded_traffic(&self.excluded_owners),
+            mode: self.split_tunnel.mode,
+            destinations: excluded_destinations(&self.split_tunnel.destinations),
+            interfaces: excluded_interfaces(&self.split_tunnel.interfaces),
+            excluded_apps_allow_lan: self.split_tunnel.excluded_apps_allow_lan,
+            local_ports: excluded_local_ports(&self.split_tunnel.local_ports),
+        }
+    }
+
+    /// Sets the users and groups whose traffic is excluded from the tunnel. The current policy, if
+    /// any, is applied again with the new rules.
+    pub fn set_excluded_owners(&mut self, owners: split_tunnel::ExcludedOwners) -> Result<()> {
+        if self.excluded_owners == owners {
+            return Ok(());
+        }
+        self.excluded_owners = owners;
+        match self.policy.clone() {
+            Some(policy) => self.apply_policy(policy),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the split tunnel settings that only affect the firewall rules. The current policy, if
+    /// any, is applied again with the new rules.
+    pub fn set_split_tunnel_config(&mut self, config: split_tunnel::Config) -> Result<()> {
+        if self.split_tunnel == config {
+            return Ok(());
+        }
+        self.split_tunnel = config;
+        match self.policy.clone() {
+            Some(policy) => self.apply_policy(policy),
+            None => Ok(()),
+        }
     }
 
     pub fn reset_policy(&mut self) -> Result<()> {
@@ -130,19 +721,24 @@ impl Firewall {
 
         log::debug!("Removing table and chain from netfilter");
         Self::send_and_process(&batch)?;
+        self.policy = None;
+        self.sysctls.restore();
 
         Ok(())
     }
 
-    fn apply_kernel_config(policy: &FirewallPolicy) {
+    fn apply_kernel_config(&mut self, policy: &FirewallPolicy) {
         if *DONT_SET_SRC_VALID_MARK {
             log::debug!("Not setting src_valid_mark");
             return;
         }
 
         if let FirewallPolicy::Connecting { .. } = policy {
-            if let Err(err) = set_src_valid_mark_sysctl() {
-                log::error!("Failed to apply src_valid_mark: {}", err);
+            if let Err(err) = self.sysctls.set(SRC_VALID_MARK_SYSCTL, "1") {
+                log::error!(
+                    "{}",
+                    err.display_chain_with_msg("Failed to apply src_valid_mark")
+                );
             }
         }
     }
@@ -215,6 +811,60 @@ impl Firewall {
     }
 }
 
+impl Drop for Firewall {
+    fn drop(&mut self) {
+        self.sysctls.restore();
+    }
+}
+
+/// Kernel parameters that have been changed, along with their original values.
+#[derive(Debug)]
+struct Sysctls {
+    root: PathBuf,
+    original: BTreeMap<&'static str, String>,
+}
+
+impl Sysctls {
+    fn new() -> Self {
+        Self::with_root("/proc/sys")
+    }
+
+    fn with_root(root: impl Into<PathBuf>) -> Self {
+        Sysctls {
+            root: root.into(),
+            original: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the kernel parameter `key`, which is a path relative to `/proc/sys`. The original value
+    /// is recorded the first time that the parameter is changed.
+    fn set(&mut self, key: &'static str, value: &str) -> io::Result<()> {
+        let path = self.root.join(key);
+        if !self.original.contains_key(key) {
+            let current = fs::read_to_string(&path)?;
+            let current = current.trim();
+            if current == value {
+                return Ok(());
+            }
+            self.original.insert(key, current.to_owned());
+        }
+        fs::write(path, value)
+    }
+
+    /// Restores the original values of the kernel parameters that have been changed.
+    fn restore(&mut self) {
+        for (key, value) in mem::take(&mut self.original) {
+            log::debug!("Restoring {key} to {value}");
+            if let Err(error) = fs::write(self.root.join(key), value) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Failed to restore {key}"))
+                );
+            }
+        }
+    }
+}
+
 struct PolicyBatch<'a> {
     batch: Batch,
     in_chain: Chain<'a>,
@@ -223,6 +873,7 @@ struct PolicyBatch<'a> {
     prerouting_chain: Chain<'a>,
     mangle_chain: Chain<'a>,
     nat_chain: Chain<'a>,
+    nat_output_chain: Chain<'a>,
 }
 
 impl<'a> PolicyBatch<'a> {
@@ -270,6 +921,12 @@ impl<'a> PolicyBatch<'a> {
         nat_chain.set_policy(nftnl::Policy::Accept);
         batch.add(&nat_chain, nftnl::MsgType::Add);
 
+        let mut nat_output_chain = Chain::new(&*NAT_OUTPUT_CHAIN_NAME, table);
+        nat_output_chain.set_hook(nftnl::Hook::Out, libc::NF_IP_PRI_NAT_DST);
+        nat_output_chain.set_type(nftnl::ChainType::Nat);
+        nat_output_chain.set_policy(nftnl::Policy::Accept);
+        batch.add(&nat_output_chain, nftnl::MsgType::Add);
+
         PolicyBatch {
             batch,
             in_chain,
@@ -278,33 +935,59 @@ impl<'a> PolicyBatch<'a> {
             prerouting_chain,
             mangle_chain,
             nat_chain,
+            nat_output_chain,
         }
     }
 
     /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
     /// policy.
-    pub fn finalize(mut self, policy: &FirewallPolicy, fwmark: u32) -> Result<FinalizedBatch> {
+    pub fn finalize(
+        mut self,
+        policy: &FirewallPolicy,
+        fwmark: u32,
+        split_tunnel: &SplitTunnelRules,
+    ) -> Result<FinalizedBatch> {
         self.add_loopback_rules()?;
-        self.add_split_tunneling_rules(policy, fwmark)?;
+        self.add_split_tunneling_rules(policy, fwmark, split_tunnel)?;
         self.add_dhcp_client_rules();
+        self.add_dhcp_client_routing_rules(fwmark);
         self.add_ndp_rules();
         self.add_policy_specific_rules(policy, fwmark)?;
 
         Ok(self.batch.finalize())
     }
 
-    fn add_split_tunneling_rules(&mut self, policy: &FirewallPolicy, fwmark: u32) -> Result<()> {
+    fn add_split_tunneling_rules(
+        &mut self,
+        policy: &FirewallPolicy,
+        fwmark: u32,
+        split_tunnel: &SplitTunnelRules,
+    ) -> Result<()> {
+        let SplitTunnelRules {
+            ref excluded, mode, ..
+        } = *split_tunnel;
+
         // Send select DNS requests in the tunnel
         if let FirewallPolicy::Connected {
             tunnel,
             dns_servers,
+            excluded_dns_servers,
             ..
         } = policy
         {
-            for server in dns_servers
+            let tunnel_dns_servers: Vec<IpAddr> = dns_servers
                 .iter()
                 .filter(|server| !is_local_dns_address(tunnel, server))
-            {
+                .copied()
+                .collect();
+            // Excluded processes should keep using the resolvers that would have been used
+            // without the tunnel.
+            let redirects = excluded_dns_redirects(&tunnel_dns_servers, excluded_dns_servers);
+            for rule in dns_redirect_mark_rules(&redirects) {
+                let rule = split_tunnel_rule(&self.mangle_chain, &rule, fwmark);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+            for server in &tunnel_dns_servers {
                 let allow_rule = allow_tunnel_dns_rule(
                     &self.mangle_chain,
                     &tunnel.interface,
@@ -320,25 +1003,48 @@ impl<'a> PolicyBatch<'a> {
                 )?;
                 self.batch.add(&allow_rule, nftnl::MsgType::Add);
             }
+            for rule in dns_redirect_rules(excluded, mode, &redirects) {
+                let rule = split_tunnel_rule(&self.nat_output_chain, &rule, fwmark);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
         }
 
-        let mut rule = Rule::new(&self.mangle_chain);
-        rule.add_expr(&nft_expr!(meta cgroup));
-        rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
-        rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
-        rule.add_expr(&nft_expr!(ct mark set));
-        rule.add_expr(&nft_expr!(immediate data fwmark));
-        rule.add_expr(&nft_expr!(meta mark set));
-        self.batch.add(&rule, nftnl::MsgType::Add);
+        for (direction, rule) in local_port_rules(split_tunnel, policy) {
+            let chain = match direction {
+                Direction::In => &self.prerouting_chain,
+                Direction::Out => &self.mangle_chain,
+            };
+            let rule = split_tunnel_rule(chain, &rule, fwmark);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+        for rule in mark_rules(split_tunnel, policy) {
+            let rule = split_tunnel_rule(&self.mangle_chain, &rule, fwmark);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+        for rule in forward_mark_rules(split_tunnel, policy) {
+            let rule = split_tunnel_rule(&self.prerouting_chain, &rule, fwmark);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
 
-        for chain in &[&self.in_chain, &self.out_chain, &self.forward_chain] {
-            let mut rule = Rule::new(chain);
-            rule.add_expr(&nft_expr!(ct mark));
-            rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-            add_verdict(&mut rule, &Verdict::Accept);
+        for (direction, rule) in excluded_lan_rules(split_tunnel, policy) {
+            let chain = match direction {
+                Direction::In => &self.in_chain,
+                Direction::Out => &self.out_chain,
+            };
+            let rule = split_tunnel_rule(chain, &rule, fwmark);
             self.batch.add(&rule, nftnl::MsgType::Add);
         }
 
+        if splits_traffic(mode, policy) {
+            for chain in &[&self.in_chain, &self.out_chain, &self.forward_chain] {
+                let mut rule = Rule::new(chain);
+                rule.add_expr(&nft_expr!(ct mark));
+                rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+
         // Block remaining marked outgoing in-tunnel traffic
         if let FirewallPolicy::Connected { tunnel, .. } = policy {
             let mut block_tunnel_rule = Rule::new(&self.nat_chain);
@@ -399,44 +1105,39 @@ impl<'a> PolicyBatch<'a> {
     }
 
     fn add_dhcp_client_rules(&mut self) {
-        use self::TransportProtocol::Udp;
-        // Outgoing DHCPv4 request
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            let mut out_v4 = Rule::new(chain);
-            check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
-            check_ip(&mut out_v4, End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST));
-            check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_SERVER_PORT);
-            add_verdict(&mut out_v4, &Verdict::Accept);
-            self.batch.add(&out_v4, nftnl::MsgType::Add);
-        }
-        // Incoming DHCPv4 response
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut in_v4 = Rule::new(chain);
-            check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
-            check_port(&mut in_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
-            add_verdict(&mut in_v4, &Verdict::Accept);
-            self.batch.add(&in_v4, nftnl::MsgType::Add);
+        for (direction, matches) in dhcp_client_filter_rules() {
+            let chains = match direction {
+                Direction::Out => [&self.out_chain, &self.forward_chain],
+                Direction::In => [&self.in_chain, &self.forward_chain],
+            };
+            for chain in chains {
+                let mut rule = Rule::new(chain);
+                for rule_match in &matches {
+                    rule_match.add_to(&mut rule);
+                }
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
         }
+    }
 
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
-                let mut out_v6 = Rule::new(chain);
-                check_net(&mut out_v6, End::Src, *super::IPV6_LINK_LOCAL);
-                check_port(&mut out_v6, Udp, End::Src, super::DHCPV6_CLIENT_PORT);
-                check_ip(&mut out_v6, End::Dst, *dhcpv6_server);
-                check_port(&mut out_v6, Udp, End::Dst, super::DHCPV6_SERVER_PORT);
-                add_verdict(&mut out_v6, &Verdict::Accept);
-                self.batch.add(&out_v6, nftnl::MsgType::Add);
+    /// Marks DHCP client traffic so that it is routed outside the tunnel, and so that replies pass
+    /// the reverse path filter.
+    fn add_dhcp_client_routing_rules(&mut self, fwmark: u32) {
+        for (direction, src_port, dst_port) in DHCP_CLIENT_TRAFFIC {
+            let chain = match direction {
+                Direction::Out => &self.mangle_chain,
+                Direction::In => &self.prerouting_chain,
+            };
+            let mut rule = Rule::new(chain);
+            check_port(&mut rule, TransportProtocol::Udp, End::Src, src_port);
+            check_port(&mut rule, TransportProtocol::Udp, End::Dst, dst_port);
+            rule.add_expr(&nft_expr!(immediate data fwmark));
+            rule.add_expr(&nft_expr!(meta mark set));
+            if *ADD_COUNTERS {
+                rule.add_expr(&nft_expr!(counter));
             }
-        }
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut in_v6 = Rule::new(chain);
-            check_net(&mut in_v6, End::Src, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Src, super::DHCPV6_SERVER_PORT);
-            check_net(&mut in_v6, End::Dst, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Dst, super::DHCPV6_CLIENT_PORT);
-            add_verdict(&mut in_v6, &Verdict::Accept);
-            self.batch.add(&in_v6, nftnl::MsgType::Add);
+            self.batch.add(&rule, nftnl::MsgType::Add);
         }
     }
 
@@ -517,6 +1218,7 @@ impl<'a> PolicyBatch<'a> {
                 allow_lan,
                 allowed_endpoint,
                 allowed_tunnel_traffic,
+                bypass_routes,
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint, fwmark);
                 self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
@@ -524,6 +1226,7 @@ impl<'a> PolicyBatch<'a> {
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
                 self.add_drop_dns_rule();
+                self.add_allow_bypass_route_rules(bypass_routes);
 
                 if let Some(tunnel) = tunnel {
                     match allowed_tunnel_traffic {
@@ -550,6 +1253,8 @@ impl<'a> PolicyBatch<'a> {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                bypass_routes,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint, fwmark);
                 self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Udp)?;
@@ -558,6 +1263,7 @@ impl<'a> PolicyBatch<'a> {
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 self.add_drop_dns_rule();
                 self.add_allow_tunnel_rules(&tunnel.interface)?;
+                self.add_allow_bypass_route_rules(bypass_routes);
                 if *allow_lan {
                     self.add_block_cve_2019_14899(tunnel);
                 }
@@ -566,15 +1272,34 @@ impl<'a> PolicyBatch<'a> {
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                lan_dns_servers,
             } => {
                 if let Some(endpoint) = allowed_endpoint {
                     self.add_allow_endpoint_rules(&endpoint.endpoint);
                 }
 
+                if *allow_lan {
+                    for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                        for resolver in lan_dns_servers {
+                            self.add_allow_local_dns_rule(None, protocol, *resolver)?;
+                        }
+                    }
+                }
+
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
                 self.add_drop_dns_rule();
                 *allow_lan
             }
+            FirewallPolicy::RestrictDns { dns_servers } => {
+                for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    for resolver in dns_servers {
+                        self.add_allow_local_dns_rule(None, protocol, *resolver)?;
+                    }
+                }
+                self.add_drop_dns_rule();
+                self.add_allow_all_rules();
+                false
+            }
         };
 
         if allow_lan {
@@ -662,7 +1387,7 @@ impl<'a> PolicyBatch<'a> {
             .partition(|server| is_local_dns_address(tunnel, server));
 
         for resolver in &local_resolvers {
-            self.add_allow_local_dns_rule(&tunnel.interface, protocol, *resolver)?;
+            self.add_allow_local_dns_rule(Some(&tunnel.interface), protocol, *resolver)?;
         }
 
         for resolver in &remote_resolvers {
@@ -685,9 +1410,10 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Allows DNS requests to `host` on any interface except `tunnel_interface`.
     fn add_allow_local_dns_rule(
         &mut self,
-        tunnel_interface: &str,
+        tunnel_interface: Option<&str>,
         protocol: TransportProtocol,
         host: IpAddr,
     ) -> Result<()> {
@@ -712,7 +1438,9 @@ impl<'a> PolicyBatch<'a> {
                 Direction::Out => End::Dst,
             };
 
-            check_not_iface(&mut allow_rule, *direction, tunnel_interface)?;
+            if let Some(tunnel_interface) = tunnel_interface {
+                check_not_iface(&mut allow_rule, *direction, tunnel_interface)?;
+            }
             check_port(&mut allow_rule, protocol, port_dir, 53);
             check_l3proto(&mut allow_rule, host);
 
@@ -726,6 +1454,15 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Allows all traffic that hasn't been rejected by a previous rule.
+    fn add_allow_all_rules(&mut self) {
+        for chain in &[&self.out_chain, &self.in_chain, &self.forward_chain] {
+            let mut rule = Rule::new(chain);
+            add_verdict(&mut rule, &Verdict::Accept);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+    }
+
     /// Blocks all outgoing DNS (port 53) on both TCP and UDP
     fn add_drop_dns_rule(&mut self) {
         for chain in &[&self.out_chain, &self.forward_chain] {
@@ -834,6 +1571,24 @@ impl<'a> PolicyBatch<'a> {
         self.add_dhcp_server_rules();
     }
 
+    /// Allows traffic to and from networks that are routed outside the tunnel. Must be added after
+    /// the DNS drop rule, so that DNS requests cannot leak to these networks.
+    fn add_allow_bypass_route_rules(&mut self, bypass_routes: &[IpNetwork]) {
+        for net in bypass_routes {
+            for chain in &[&self.out_chain, &self.forward_chain] {
+                let mut out_rule = Rule::new(chain);
+                check_net(&mut out_rule, End::Dst, *net);
+                add_verdict(&mut out_rule, &Verdict::Accept);
+                self.batch.add(&out_rule, nftnl::MsgType::Add);
+            }
+
+            let mut in_rule = Rule::new(&self.in_chain);
+            check_net(&mut in_rule, End::Src, *net);
+            add_verdict(&mut in_rule, &Verdict::Accept);
+            self.batch.add(&in_rule, nftnl::MsgType::Add);
+        }
+    }
+
     fn add_dhcp_server_rules(&mut self) {
         use TransportProtocol::Udp;
         // Outgoing DHCPv4 response
@@ -865,6 +1620,77 @@ fn is_local_dns_address(tunnel: &tunnel::TunnelMetadata, server: &IpAddr) -> boo
         && Some(server) != tunnel.ipv6_gateway.map(IpAddr::from).as_ref()
 }
 
+/// Pairs each DNS server in the tunnel with the resolver of the same IP version that DNS requests
+/// from excluded processes should be redirected to. Servers without such a resolver are left out,
+/// in which case requests from excluded processes to them are still sent through the tunnel.
+/// Only requests sent directly to the DNS servers in the tunnel are redirected. Requests to a local
+/// stub resolver, such as that of systemd-resolved, are sent on by the stub itself, which is not
+/// excluded, and a request to a loopback address cannot be redirected off the host.
+/// Link-local IPv6 resolvers are never used as targets, since a DNAT target cannot carry the scope
+/// ID that is needed to reach them.
+fn excluded_dns_redirects(
+    tunnel_dns_servers: &[IpAddr],
+    excluded_dns_servers: &[IpAddr],
+) -> Vec<(IpAddr, IpAddr)> {
+    tunnel_dns_servers
+        .iter()
+        .filter_map(|server| {
+            let target = excluded_dns_servers.iter().find(|target| {
+                target.is_ipv4() == server.is_ipv4()
+                    && !matches!(target, IpAddr::V6(target) if IPV6_LINK_LOCAL.contains(*target))
+            })?;
+            Some((*server, *target))
+        })
+        .collect()
+}
+
+fn split_tunnel_rule<'a>(chain: &'a Chain<'_>, rule: &SplitTunnelRule, fwmark: u32) -> Rule<'a> {
+    let mut nft_rule = Rule::new(chain);
+    for rule_match in &rule.matches {
+        rule_match.add_to(&mut nft_rule);
+    }
+    let target = match rule.action {
+        SplitTunnelAction::Skip | SplitTunnelAction::Accept => {
+            add_verdict(&mut nft_rule, &Verdict::Accept);
+            return nft_rule;
+        }
+        SplitTunnelAction::Drop => {
+            add_verdict(&mut nft_rule, &Verdict::Drop);
+            return nft_rule;
+        }
+        SplitTunnelAction::Mark => None,
+        SplitTunnelAction::Redirect { target } => Some(target),
+    };
+
+    nft_rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
+    nft_rule.add_expr(&nft_expr!(ct mark set));
+    nft_rule.add_expr(&nft_expr!(immediate data fwmark));
+    nft_rule.add_expr(&nft_expr!(meta mark set));
+    if *ADD_COUNTERS {
+        nft_rule.add_expr(&nft_expr!(counter));
+    }
+
+    if let Some(target) = target {
+        let family = match target {
+            IpAddr::V4(target) => {
+                nft_rule.add_expr(&nft_expr!(immediate data target));
+                ProtoFamily::Ipv4
+            }
+            IpAddr::V6(target) => {
+                nft_rule.add_expr(&nft_expr!(immediate data target));
+                ProtoFamily::Ipv6
+            }
+        };
+        nft_rule.add_expr(&expr::Nat {
+            nat_type: expr::NatType::DNat,
+            family,
+            ip_register: expr::Register::Reg1,
+            port_register: None,
+        });
+    }
+    nft_rule
+}
+
 fn allow_tunnel_dns_rule<'a>(
     chain: &'a Chain<'_>,
     iface: &str,
@@ -991,6 +1817,24 @@ fn check_port(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, port:
     rule.add_expr(&nft_expr!(cmp == port.to_be()));
 }
 
+fn check_port_range(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, ports: PortRange) {
+    if ports.start == ports.end {
+        return check_port(rule, protocol, end, ports.start);
+    }
+    // Must check transport layer protocol before loading transport layer payload
+    check_l4proto(rule, protocol);
+
+    rule.add_expr(&match (protocol, end) {
+        (TransportProtocol::Udp, End::Src) => nft_expr!(payload udp sport),
+        (TransportProtocol::Udp, End::Dst) => nft_expr!(payload udp dport),
+        (TransportProtocol::Tcp, End::Src) => nft_expr!(payload tcp sport),
+        (TransportProtocol::Tcp, End::Dst) => nft_expr!(payload tcp dport),
+    });
+    // Ports are compared in network byte order, which preserves their ordering
+    rule.add_expr(&nft_expr!(cmp >= ports.start.to_be()));
+    rule.add_expr(&nft_expr!(cmp <= ports.end.to_be()));
+}
+
 fn check_l3proto(rule: &mut Rule<'_>, ip: IpAddr) {
     rule.add_expr(&nft_expr!(meta nfproto));
     rule.add_expr(&nft_expr!(cmp == l3proto(ip)));
@@ -1022,8 +1866,51 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     rule.add_expr(verdict);
 }
 
-fn set_src_valid_mark_sysctl() -> io::Result<()> {
-    fs::write(PROC_SYS_NET_IPV4_CONF_SRC_VALID_MARK, b"1")
+/// Returns the matches of the rules that accept DHCP client traffic, along with the direction of
+/// the traffic.
+fn dhcp_client_filter_rules() -> Vec<(Direction, Vec<Match>)> {
+    use self::{End::*, TransportProtocol::Udp};
+
+    let mut rules = vec![
+        // Outgoing DHCPv4 request
+        (
+            Direction::Out,
+            vec![
+                Match::Port(Udp, Src, super::DHCPV4_CLIENT_PORT),
+                Match::Ip(Dst, IpAddr::V4(Ipv4Addr::BROADCAST)),
+                Match::Port(Udp, Dst, super::DHCPV4_SERVER_PORT),
+            ],
+        ),
+        // Incoming DHCPv4 response
+        (
+            Direction::In,
+            vec![
+                Match::Port(Udp, Src, super::DHCPV4_SERVER_PORT),
+                Match::Port(Udp, Dst, super::DHCPV4_CLIENT_PORT),
+            ],
+        ),
+    ];
+    for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
+        rules.push((
+            Direction::Out,
+            vec![
+                Match::Net(Src, IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+                Match::Port(Udp, Src, super::DHCPV6_CLIENT_PORT),
+                Match::Ip(Dst, IpAddr::V6(*dhcpv6_server)),
+                Match::Port(Udp, Dst, super::DHCPV6_SERVER_PORT),
+            ],
+        ));
+    }
+    rules.push((
+        Direction::In,
+        vec![
+            Match::Net(Src, IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+            Match::Port(Udp, Src, super::DHCPV6_SERVER_PORT),
+            Match::Net(Dst, IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+            Match::Port(Udp, Dst, super::DHCPV6_CLIENT_PORT),
+        ],
+    ));
+    rules
 }
 
 /// Tables that are no longer used but need to be deleted due to upgrades.
@@ -1042,3 +1929,619 @@ fn batch_deprecated_tables(batch: &mut Batch) {
         batch.add(table, nftnl::MsgType::Del);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn connected_policy() -> FirewallPolicy {
+        FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(ip("1.2.3.4"), 51820, TransportProtocol::Udp),
+            tunnel: tunnel::TunnelMetadata {
+                interface: "wg0-mullvad".to_owned(),
+                ips: vec![ip("10.64.0.2")],
+                ipv4_gateway: "10.64.0.1".parse().unwrap(),
+                ipv6_gateway: None,
+            },
+            allow_lan: false,
+            dns_servers: vec![ip("10.64.0.1")],
+            excluded_dns_servers: vec![],
+            bypass_routes: vec![],
+        }
+    }
+
+    fn blocked_policy() -> FirewallPolicy {
+        FirewallPolicy::Blocked {
+            allow_lan: true,
+            allowed_endpoint: None,
+            lan_dns_servers: vec![],
+        }
+    }
+
+    #[test]
+    fn test_splits_traffic() {
+        let connected = connected_policy();
+        let blocked = blocked_policy();
+
+        assert!(splits_traffic(SplitTunnelMode::Exclude, &connected));
+        assert!(splits_traffic(SplitTunnelMode::Exclude, &blocked));
+
+        // Traffic that is not selected must never leak in the blocked states
+        assert!(splits_traffic(SplitTunnelMode::Include, &connected));
+        assert!(!splits_traffic(SplitTunnelMode::Include, &blocked));
+    }
+
+    fn split_tunnel_rules(
+        excluded: &[ExcludedTraffic],
+        mode: SplitTunnelMode,
+        destinations: &[ExcludedDestination],
+    ) -> SplitTunnelRules {
+        SplitTunnelRules {
+            excluded: excluded.to_vec(),
+            mode,
+            destinations: destinations.to_vec(),
+            interfaces: vec![],
+            excluded_apps_allow_lan: true,
+            local_ports: vec![],
+        }
+    }
+
+    #[test]
+    fn test_mark_rules() {
+        let excluded = [ExcludedTraffic::CGroup, ExcludedTraffic::User(1000)];
+        let exclude = split_tunnel_rules(&excluded, SplitTunnelMode::Exclude, &[]);
+        let include = split_tunnel_rules(&excluded, SplitTunnelMode::Include, &[]);
+        let connected = connected_policy();
+
+        assert_eq!(
+            mark_rules(&exclude, &connected),
+            vec![
+                SplitTunnelRule::new(
+                    vec![Match::Traffic(ExcludedTraffic::CGroup)],
+                    SplitTunnelAction::Mark
+                ),
+                SplitTunnelRule::new(
+                    vec![Match::Traffic(ExcludedTraffic::User(1000))],
+                    SplitTunnelAction::Mark
+                ),
+            ]
+        );
+        // The selected processes skip the chain before everything else is marked
+        assert_eq!(
+            mark_rules(&include, &connected),
+            vec![
+                SplitTunnelRule::new(
+                    vec![Match::Traffic(ExcludedTraffic::CGroup)],
+                    SplitTunnelAction::Skip
+                ),
+                SplitTunnelRule::new(
+                    vec![Match::Traffic(ExcludedTraffic::User(1000))],
+                    SplitTunnelAction::Skip
+                ),
+                SplitTunnelRule::new(vec![], SplitTunnelAction::Mark),
+            ]
+        );
+        // Nothing is marked in the blocked states in include mode
+        assert_eq!(mark_rules(&include, &blocked_policy()), vec![]);
+        assert_eq!(mark_rules(&exclude, &blocked_policy()).len(), 2);
+    }
+
+    #[test]
+    fn test_mark_destination_rules() {
+        let excluded = [ExcludedTraffic::CGroup];
+        let nas = destination("192.168.1.10/32", Some((445, 445)), TransportProtocol::Tcp);
+        let mark_nas = SplitTunnelRule::new(vec![Match::Destination(nas)], SplitTunnelAction::Mark);
+
+        assert_eq!(
+            mark_rules(
+                &split_tunnel_rules(&excluded, SplitTunnelMode::Exclude, &[nas]),
+                &connected_policy()
+            ),
+            vec![
+                mark_nas.clone(),
+                SplitTunnelRule::new(
+                    vec![Match::Traffic(ExcludedTraffic::CGroup)],
+                    SplitTunnelAction::Mark
+                ),
+            ]
+        );
+        // Destinations are excluded for the selected processes too in include mode
+        let include = split_tunnel_rules(&excluded, SplitTunnelMode::Include, &[nas]);
+        assert_eq!(
+            mark_rules(&include, &connected_policy()),
+            vec![
+                mark_nas,
+                SplitTunnelRule::new(
+                    vec![Match::Traffic(ExcludedTraffic::CGroup)],
+                    SplitTunnelAction::Skip
+                ),
+                SplitTunnelRule::new(vec![], SplitTunnelAction::Mark),
+            ]
+        );
+        assert_eq!(mark_rules(&include, &blocked_policy()), vec![]);
+    }
+
+    #[test]
+    fn test_dns_redirect_rules() {
+        let excluded = [ExcludedTraffic::CGroup];
+        let redirects = [(ip("10.64.0.1"), ip("192.168.1.1"))];
+        let redirect = |traffic: Option<ExcludedTraffic>, protocol| {
+            let matches = traffic
+                .map(Match::Traffic)
+                .into_iter()
+                .chain([
+                    Match::Ip(End::Dst, ip("10.64.0.1")),
+                    Match::Port(protocol, End::Dst, 53),
+                ])
+                .collect();
+            SplitTunnelRule::new(
+                matches,
+                SplitTunnelAction::Redirect {
+                    target: ip("192.168.1.1"),
+                },
+            )
+        };
+
+        assert_eq!(
+            dns_redirect_rules(&excluded, SplitTunnelMode::Exclude, &redirects),
+            vec![
+                redirect(Some(ExcludedTraffic::CGroup), TransportProtocol::Udp),
+                redirect(Some(ExcludedTraffic::CGroup), TransportProtocol::Tcp),
+            ]
+        );
+        // In include mode, requests from the selected processes are sent through the tunnel
+        assert_eq!(
+            dns_redirect_rules(&excluded, SplitTunnelMode::Include, &redirects),
+            vec![
+                SplitTunnelRule::new(
+                    vec![Match::Traffic(ExcludedTraffic::CGroup)],
+                    SplitTunnelAction::Skip
+                ),
+                redirect(None, TransportProtocol::Udp),
+                redirect(None, TransportProtocol::Tcp),
+            ]
+        );
+        assert_eq!(
+            dns_redirect_rules(&excluded, SplitTunnelMode::Include, &[]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_dns_redirect_mark_rules() {
+        let redirects = [(ip("10.64.0.1"), ip("192.168.1.1"))];
+        let restore = |protocol| {
+            SplitTunnelRule::new(
+                vec![
+                    Match::Marked,
+                    Match::Ip(End::Dst, ip("10.64.0.1")),
+                    Match::Port(protocol, End::Dst, 53),
+                ],
+                SplitTunnelAction::Mark,
+            )
+        };
+
+        // The second packet of a redirected request is still addressed to the resolver in the
+        // tunnel when it reaches the mangle chain, and only the connection is marked
+        assert_eq!(
+            dns_redirect_mark_rules(&redirects),
+            vec![
+                restore(TransportProtocol::Udp),
+                restore(TransportProtocol::Tcp)
+            ]
+        );
+        assert_eq!(dns_redirect_mark_rules(&[]), vec![]);
+    }
+
+    #[test]
+    fn test_excluded_dns_redirects() {
+        let tunnel_dns_servers = [ip("10.64.0.1"), ip("fc00:bbbb:bbbb:bb01::1"), ip("9.9.9.9")];
+        let excluded_dns_servers = [ip("192.168.1.1"), ip("192.168.1.2"), ip("fd00::1")];
+
+        assert_eq!(
+            excluded_dns_redirects(&tunnel_dns_servers, &excluded_dns_servers),
+            vec![
+                (ip("10.64.0.1"), ip("192.168.1.1")),
+                (ip("fc00:bbbb:bbbb:bb01::1"), ip("fd00::1")),
+                (ip("9.9.9.9"), ip("192.168.1.1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_excluded_dns_redirects_without_matching_version() {
+        let tunnel_dns_servers = [ip("10.64.0.1"), ip("fc00:bbbb:bbbb:bb01::1")];
+
+        assert_eq!(
+            excluded_dns_redirects(&tunnel_dns_servers, &[ip("192.168.1.1")]),
+            vec![(ip("10.64.0.1"), ip("192.168.1.1"))]
+        );
+        assert!(excluded_dns_redirects(&tunnel_dns_servers, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_excluded_dns_redirects_skip_link_local() {
+        let tunnel_dns_servers = [ip("fc00:bbbb:bbbb:bb01::1")];
+
+        assert!(excluded_dns_redirects(&tunnel_dns_servers, &[ip("fe80::1")]).is_empty());
+        assert_eq!(
+            excluded_dns_redirects(&tunnel_dns_servers, &[ip("fe80::1"), ip("2001:db8::1")]),
+            vec![(ip("fc00:bbbb:bbbb:bb01::1"), ip("2001:db8::1"))]
+        );
+    }
+
+    #[test]
+    fn test_excluded_traffic() {
+        assert_eq!(
+            excluded_traffic(&split_tunnel::ExcludedOwners::default()),
+            vec![ExcludedTraffic::CGroup]
+        );
+
+        let owners = split_tunnel::ExcludedOwners {
+            users: vec![1001, 0, 1002, 1001],
+            groups: vec![0, 1001],
+        };
+        assert_eq!(
+            excluded_traffic(&owners),
+            vec![
+                ExcludedTraffic::CGroup,
+                ExcludedTraffic::User(1001),
+                ExcludedTraffic::User(1002),
+                ExcludedTraffic::Group(1001),
+            ]
+        );
+    }
+
+    fn destination(
+        network: &str,
+        ports: Option<(u16, u16)>,
+        protocol: TransportProtocol,
+    ) -> ExcludedDestination {
+        ExcludedDestination {
+            network: network.parse().unwrap(),
+            ports: ports.map(|(start, end)| PortRange::new(start, end).unwrap()),
+            protocol,
+        }
+    }
+
+    #[test]
+    fn test_excluded_destinations() {
+        let nas = destination("10.0.0.0/8", Some((445, 445)), TransportProtocol::Tcp);
+        let destinations = [
+            nas,
+            destination("10.1.0.0/16", Some((445, 445)), TransportProtocol::Tcp),
+            nas,
+            // Different protocols and address families are never covered
+            destination("10.1.0.0/16", Some((445, 445)), TransportProtocol::Udp),
+            destination("fd00::/8", Some((445, 445)), TransportProtocol::Tcp),
+            // A port range is not covered by a single port
+            destination("10.0.0.0/8", Some((8000, 8100)), TransportProtocol::Tcp),
+            destination("10.0.0.0/8", Some((8010, 8020)), TransportProtocol::Tcp),
+        ];
+
+        assert_eq!(
+            excluded_destinations(&destinations),
+            vec![
+                nas,
+                destination("10.1.0.0/16", Some((445, 445)), TransportProtocol::Udp),
+                destination("fd00::/8", Some((445, 445)), TransportProtocol::Tcp),
+                destination("10.0.0.0/8", Some((8000, 8100)), TransportProtocol::Tcp),
+            ]
+        );
+
+        // All ports cover any range
+        let all_ports = destination("192.168.0.0/16", None, TransportProtocol::Udp);
+        assert_eq!(
+            excluded_destinations(&[
+                destination("192.168.1.0/24", Some((53, 53)), TransportProtocol::Udp),
+                all_ports,
+            ]),
+            vec![all_ports]
+        );
+    }
+
+    #[test]
+    fn test_excluded_lan_rules() {
+        let mut split_tunnel =
+            split_tunnel_rules(&[ExcludedTraffic::CGroup], SplitTunnelMode::Exclude, &[]);
+        let policy = |allow_lan| match connected_policy() {
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                dns_servers,
+                bypass_routes,
+                ..
+            } => FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                dns_servers,
+                excluded_dns_servers: vec![ip("192.168.1.1")],
+                bypass_routes,
+            },
+            _ => unreachable!(),
+        };
+
+        // Nothing but the marked connections of excluded processes reach the LAN, so no rules
+        // are needed to allow it
+        for allow_lan in [true, false] {
+            split_tunnel.excluded_apps_allow_lan = true;
+            assert_eq!(
+                excluded_lan_rules(&split_tunnel, &policy(allow_lan)),
+                vec![]
+            );
+            split_tunnel.excluded_apps_allow_lan = false;
+            assert_eq!(
+                excluded_lan_rules(&split_tunnel, &policy(allow_lan)).is_empty(),
+                allow_lan
+            );
+        }
+
+        let rules = excluded_lan_rules(&split_tunnel, &policy(false));
+        // Only DNS requests to the resolver that they are redirected to are accepted
+        assert_eq!(
+            rules[0],
+            (
+                Direction::Out,
+                SplitTunnelRule::new(
+                    vec![
+                        Match::Ip(End::Dst, ip("192.168.1.1")),
+                        Match::Port(TransportProtocol::Udp, End::Dst, 53),
+                        Match::Marked,
+                    ],
+                    SplitTunnelAction::Accept
+                )
+            )
+        );
+        let dropped: Vec<_> = rules
+            .iter()
+            .filter(|(_, rule)| rule.action == SplitTunnelAction::Drop)
+            .collect();
+        assert_eq!(rules.len(), 4 + dropped.len());
+        assert!(dropped.contains(&&(
+            Direction::In,
+            SplitTunnelRule::new(
+                vec![
+                    Match::Net(End::Src, "192.168.0.0/16".parse().unwrap()),
+                    Match::Marked
+                ],
+                SplitTunnelAction::Drop
+            )
+        )));
+        // Every rule is limited to marked traffic
+        assert!(rules
+            .iter()
+            .all(|(_, rule)| rule.matches.contains(&Match::Marked)));
+
+        // Not selected traffic is blocked in the blocked states in include mode anyway
+        split_tunnel.mode = SplitTunnelMode::Include;
+        let blocked = FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: None,
+            lan_dns_servers: vec![],
+        };
+        assert_eq!(excluded_lan_rules(&split_tunnel, &blocked), vec![]);
+        split_tunnel.mode = SplitTunnelMode::Exclude;
+        assert!(!excluded_lan_rules(&split_tunnel, &blocked).is_empty());
+    }
+
+    #[test]
+    fn test_excluded_interfaces() {
+        let exact = |name: &str| ExcludedInterface::Exact(CString::new(name).unwrap());
+        let prefix = |name: &str| ExcludedInterface::Prefix(CString::new(name).unwrap());
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            excluded_interfaces(&names(&["docker0", "br-*", "br-3f2a1b0c9d8e", "docker0"])),
+            vec![exact("docker0"), prefix("br-")]
+        );
+        // A broader prefix replaces narrower ones regardless of the order
+        assert_eq!(
+            excluded_interfaces(&names(&["br-3f*", "br-*", "veth*"])),
+            vec![prefix("br-"), prefix("veth")]
+        );
+        // Names that cannot be interface names are skipped
+        assert_eq!(
+            excluded_interfaces(&names(&[
+                "",
+                "*",
+                "a-name-that-is-too-long",
+                "lxc\0",
+                "lxcbr0"
+            ])),
+            vec![exact("lxcbr0")]
+        );
+    }
+
+    #[test]
+    fn test_forward_mark_rules() {
+        let docker = ExcludedInterface::Exact(CString::new("docker0").unwrap());
+        let mut split_tunnel = split_tunnel_rules(&[], SplitTunnelMode::Exclude, &[]);
+        split_tunnel.interfaces = vec![docker.clone()];
+        let mark_docker =
+            SplitTunnelRule::new(vec![Match::Interface(docker)], SplitTunnelAction::Mark);
+
+        assert_eq!(
+            forward_mark_rules(&split_tunnel, &connected_policy()),
+            vec![mark_docker.clone()]
+        );
+        assert_eq!(
+            forward_mark_rules(&split_tunnel, &blocked_policy()),
+            vec![mark_docker.clone()]
+        );
+        // Forwarded traffic must never leak in the blocked states in include mode
+        split_tunnel.mode = SplitTunnelMode::Include;
+        assert_eq!(
+            forward_mark_rules(&split_tunnel, &connected_policy()),
+            vec![mark_docker]
+        );
+        assert_eq!(forward_mark_rules(&split_tunnel, &blocked_policy()), vec![]);
+    }
+
+    #[test]
+    fn test_local_port_rules() {
+        use TransportProtocol::Udp;
+
+        let mut split_tunnel = split_tunnel_rules(&[], SplitTunnelMode::Exclude, &[]);
+        split_tunnel.local_ports = vec![(Udp, 51820)];
+        let not_iface = |name: &str| Match::NotInterface(CString::new(name).unwrap());
+        let outgoing = vec![
+            (
+                Direction::Out,
+                SplitTunnelRule::new(
+                    vec![Match::Port(Udp, End::Src, 51820), Match::NewConnection],
+                    SplitTunnelAction::Mark,
+                ),
+            ),
+            (
+                Direction::Out,
+                SplitTunnelRule::new(
+                    vec![Match::Port(Udp, End::Src, 51820), Match::Marked],
+                    SplitTunnelAction::Mark,
+                ),
+            ),
+        ];
+
+        // Connections that arrive through the tunnel are not marked, so the replies stay in it
+        let mut expected = vec![(
+            Direction::In,
+            SplitTunnelRule::new(
+                vec![
+                    not_iface("lo"),
+                    not_iface("wg0-mullvad"),
+                    Match::Port(Udp, End::Dst, 51820),
+                ],
+                SplitTunnelAction::Mark,
+            ),
+        )];
+        expected.extend(outgoing.clone());
+        assert_eq!(
+            local_port_rules(&split_tunnel, &connected_policy()),
+            expected
+        );
+
+        let mut expected = vec![(
+            Direction::In,
+            SplitTunnelRule::new(
+                vec![not_iface("lo"), Match::Port(Udp, End::Dst, 51820)],
+                SplitTunnelAction::Mark,
+            ),
+        )];
+        expected.extend(outgoing);
+        assert_eq!(local_port_rules(&split_tunnel, &blocked_policy()), expected);
+
+        // Local ports must never leak in the blocked states in include mode
+        split_tunnel.mode = SplitTunnelMode::Include;
+        assert_eq!(
+            local_port_rules(&split_tunnel, &connected_policy()).len(),
+            3
+        );
+        assert_eq!(local_port_rules(&split_tunnel, &blocked_policy()), vec![]);
+    }
+
+    #[test]
+    fn test_excluded_local_ports() {
+        use TransportProtocol::{Tcp, Udp};
+
+        assert_eq!(
+            excluded_local_ports(&[(Udp, 51820), (Tcp, 25565), (Udp, 51820), (Udp, 25565)]),
+            vec![(Udp, 51820), (Tcp, 25565), (Udp, 25565)]
+        );
+    }
+
+    #[test]
+    fn test_dhcp_client_filter_rules() {
+        use super::super::{
+            DHCPV4_CLIENT_PORT, DHCPV4_SERVER_PORT, DHCPV6_CLIENT_PORT, DHCPV6_SERVER_PORT,
+        };
+        use End::*;
+        use TransportProtocol::Udp;
+
+        let rules = dhcp_client_filter_rules();
+        let outgoing: Vec<_> = rules
+            .iter()
+            .filter(|(direction, _)| *direction == Direction::Out)
+            .map(|(_, matches)| matches)
+            .collect();
+
+        // Outgoing requests are only accepted to the broadcast address and the DHCPv6 multicast
+        // addresses, never to arbitrary unicast servers.
+        assert_eq!(
+            outgoing[0],
+            &vec![
+                Match::Port(Udp, Src, DHCPV4_CLIENT_PORT),
+                Match::Ip(Dst, ip("255.255.255.255")),
+                Match::Port(Udp, Dst, DHCPV4_SERVER_PORT),
+            ]
+        );
+        assert_eq!(outgoing.len(), 3);
+        for (matches, server) in outgoing[1..].iter().zip(["ff02::1:2", "ff05::1:3"]) {
+            assert!(matches.contains(&Match::Port(Udp, Src, DHCPV6_CLIENT_PORT)));
+            assert!(matches.contains(&Match::Ip(Dst, ip(server))));
+        }
+
+        // Responses are accepted for both protocol versions
+        for (server_port, client_port) in [
+            (DHCPV4_SERVER_PORT, DHCPV4_CLIENT_PORT),
+            (DHCPV6_SERVER_PORT, DHCPV6_CLIENT_PORT),
+        ] {
+            assert!(rules.iter().any(|(direction, matches)| {
+                *direction == Direction::In
+                    && matches.contains(&Match::Port(Udp, Src, server_port))
+                    && matches.contains(&Match::Port(Udp, Dst, client_port))
+            }));
+        }
+    }
+
+    fn sysctl_root(value: &str) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join(SRC_VALID_MARK_SYSCTL);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, value).unwrap();
+        root
+    }
+
+    fn read_sysctl(root: &tempfile::TempDir) -> String {
+        fs::read_to_string(root.path().join(SRC_VALID_MARK_SYSCTL)).unwrap()
+    }
+
+    #[test]
+    fn test_sysctl_restored() {
+        let root = sysctl_root("0\n");
+        let mut sysctls = Sysctls::with_root(root.path());
+
+        sysctls.set(SRC_VALID_MARK_SYSCTL, "1").unwrap();
+        assert_eq!(read_sysctl(&root), "1");
+        // Reconnecting does not replace the original value
+        sysctls.set(SRC_VALID_MARK_SYSCTL, "1").unwrap();
+
+        sysctls.restore();
+        assert_eq!(read_sysctl(&root), "0");
+
+        // Restoring again does nothing
+        fs::write(root.path().join(SRC_VALID_MARK_SYSCTL), "1").unwrap();
+        sysctls.restore();
+        assert_eq!(read_sysctl(&root), "1");
+    }
+
+    #[test]
+    fn test_sysctl_unchanged_not_restored() {
+        let root = sysctl_root("1\n");
+        let mut sysctls = Sysctls::with_root(root.path());
+
+        sysctls.set(SRC_VALID_MARK_SYSCTL, "1").unwrap();
+        assert_eq!(read_sysctl(&root), "1\n");
+
+        sysctls.restore();
+        assert_eq!(read_sysctl(&root), "1\n");
+    }
+}
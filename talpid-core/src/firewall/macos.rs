@@ -19,7 +19,7 @@ const ANCHOR_NAME: &str = "mullvad";
 pub struct Firewall {
     pf: pfctl::PfCtl,
     pf_was_enabled: Option<bool>,
-    rule_logging: RuleLogging,
+    rules: PolicyRules,
 }
 
 impl Firewall {
@@ -42,7 +42,7 @@ impl Firewall {
         Ok(Firewall {
             pf: pfctl::PfCtl::new()?,
             pf_was_enabled: None,
-            rule_logging,
+            rules: PolicyRules { rule_logging },
         })
     }
 
@@ -62,12 +62,81 @@ impl Firewall {
     }
 
     fn set_rules(&mut self, policy: FirewallPolicy) -> Result<()> {
+        let mut anchor_change = pfctl::AnchorChange::new();
+        anchor_change.set_filter_rules(self.rules.filter_rules(&policy)?);
+        anchor_change.set_redirect_rules(self.rules.get_dns_redirect_rules(&policy)?);
+        self.pf.set_rules(ANCHOR_NAME, anchor_change)
+    }
+
+    fn remove_rules(&mut self) -> Result<()> {
+        // remove_anchor() does not deactivate active rules
+        self.pf
+            .flush_rules(ANCHOR_NAME, pfctl::RulesetKind::Filter)?;
+        Ok(())
+    }
+
+    fn enable(&mut self) -> Result<()> {
+        if self.pf_was_enabled.is_none() {
+            self.pf_was_enabled = Some(self.is_enabled());
+        }
+        self.pf.try_enable()
+    }
+
+    fn is_enabled(&self) -> bool {
+        let cmd = duct::cmd!("/sbin/pfctl", "-s", "info")
+            .stderr_null()
+            .stdout_capture();
+        const EXPECTED_OUTPUT: &[u8] = b"Status: Enabled";
+        match cmd.run() {
+            Ok(output) => output.stdout.as_slice().find(EXPECTED_OUTPUT).is_some(),
+            Err(err) => {
+                log::error!(
+                    "Failed to execute pfctl, assuming pf is not enabled: {}",
+                    err
+                );
+                false
+            }
+        }
+    }
+
+    fn restore_state(&mut self) -> Result<()> {
+        match self.pf_was_enabled.take() {
+            Some(true) => Ok(self.pf.try_enable()?),
+            Some(false) => Ok(self.pf.try_disable()?),
+            None => Ok(()),
+        }
+    }
+
+    fn add_anchor(&mut self) -> Result<()> {
+        self.pf
+            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
+        self.pf
+            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
+        Ok(())
+    }
+
+    fn remove_anchor(&mut self) -> Result<()> {
+        self.pf
+            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
+        self.pf
+            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
+        Ok(())
+    }
+}
+
+/// Produces the pf rules for firewall policies.
+struct PolicyRules {
+    rule_logging: RuleLogging,
+}
+
+impl PolicyRules {
+    fn filter_rules(&self, policy: &FirewallPolicy) -> Result<Vec<pfctl::FilterRule>> {
         let mut new_filter_rules = vec![];
 
         new_filter_rules.append(&mut self.get_allow_loopback_rules()?);
         new_filter_rules.append(&mut self.get_allow_dhcp_client_rules()?);
         new_filter_rules.append(&mut self.get_allow_ndp_rules()?);
-        new_filter_rules.append(&mut self.get_policy_specific_rules(&policy)?);
+        new_filter_rules.append(&mut self.get_policy_specific_rules(policy)?);
 
         let return_out_rule = self
             .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
@@ -82,16 +151,10 @@ impl Firewall {
             .build()?;
         new_filter_rules.push(drop_all_rule);
 
-        let mut anchor_change = pfctl::AnchorChange::new();
-        anchor_change.set_filter_rules(new_filter_rules);
-        anchor_change.set_redirect_rules(self.get_dns_redirect_rules(&policy)?);
-        self.pf.set_rules(ANCHOR_NAME, anchor_change)
+        Ok(new_filter_rules)
     }
 
-    fn get_dns_redirect_rules(
-        &mut self,
-        policy: &FirewallPolicy,
-    ) -> Result<Vec<pfctl::RedirectRule>> {
+    fn get_dns_redirect_rules(&self, policy: &FirewallPolicy) -> Result<Vec<pfctl::RedirectRule>> {
         let redirect_rules = match policy {
             FirewallPolicy::Blocked {
                 dns_redirect_port, ..
@@ -109,10 +172,7 @@ impl Firewall {
         Ok(redirect_rules)
     }
 
-    fn get_policy_specific_rules(
-        &mut self,
-        policy: &FirewallPolicy,
-    ) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_policy_specific_rules(&self, policy: &FirewallPolicy) -> Result<Vec<pfctl::FilterRule>> {
         match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
@@ -120,6 +180,7 @@ impl Firewall {
                 allow_lan,
                 allowed_endpoint,
                 allowed_tunnel_traffic,
+                bypass_routes,
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(*peer_endpoint)?];
                 rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
@@ -127,6 +188,7 @@ impl Firewall {
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
                 rules.append(&mut self.get_block_dns_rules()?);
+                rules.append(&mut self.get_allow_bypass_route_rules(bypass_routes)?);
 
                 if let Some(tunnel) = tunnel {
                     rules.extend(
@@ -144,6 +206,7 @@ impl Firewall {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                bypass_routes,
             } => {
                 let mut rules = vec![];
 
@@ -161,6 +224,7 @@ impl Firewall {
                     tunnel.interface.as_str(),
                     &AllowedTunnelTraffic::All,
                 )?);
+                rules.append(&mut self.get_allow_bypass_route_rules(bypass_routes)?);
 
                 if *allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
@@ -171,6 +235,7 @@ impl Firewall {
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                lan_dns_servers,
                 ..
             } => {
                 let mut rules = Vec::new();
@@ -179,11 +244,29 @@ impl Firewall {
                 }
 
                 if *allow_lan {
+                    for server in lan_dns_servers {
+                        rules.append(&mut self.get_allow_local_dns_rules(*server)?);
+                    }
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
                     rules.append(&mut self.get_allow_lan_rules()?);
                 }
 
+                Ok(rules)
+            }
+            FirewallPolicy::RestrictDns { dns_servers } => {
+                let mut rules = Vec::new();
+                for server in dns_servers {
+                    rules.append(&mut self.get_allow_local_dns_rules(*server)?);
+                }
+                rules.append(&mut self.get_block_dns_rules()?);
+
+                let allow_all_rule = self
+                    .create_rule_builder(FilterRuleAction::Pass)
+                    .quick(true)
+                    .build()?;
+                rules.push(allow_all_rule);
+
                 Ok(rules)
             }
         }
@@ -227,25 +310,7 @@ impl Firewall {
             rules.push(block_tunnel_udp);
 
             // Allow requests on other interfaces
-            let allow_nontunnel_tcp = self
-                .create_rule_builder(FilterRuleAction::Pass)
-                .direction(pfctl::Direction::Out)
-                .quick(true)
-                .proto(pfctl::Proto::Tcp)
-                .keep_state(pfctl::StatePolicy::Keep)
-                .tcp_flags(Self::get_tcp_flags())
-                .to(pfctl::Endpoint::new(server, 53))
-                .build()?;
-            rules.push(allow_nontunnel_tcp);
-            let allow_nontunnel_udp = self
-                .create_rule_builder(FilterRuleAction::Pass)
-                .direction(pfctl::Direction::Out)
-                .quick(true)
-                .proto(pfctl::Proto::Udp)
-                .keep_state(pfctl::StatePolicy::Keep)
-                .to(pfctl::Endpoint::new(server, 53))
-                .build()?;
-            rules.push(allow_nontunnel_udp);
+            rules.append(&mut self.get_allow_local_dns_rules(server)?);
         } else {
             // Allow outgoing requests on the tunnel interface only
             let allow_tunnel_tcp = self
@@ -273,6 +338,29 @@ impl Firewall {
         Ok(rules)
     }
 
+    /// Produces rules that allow DNS requests to `server` on any interface.
+    fn get_allow_local_dns_rules(&self, server: IpAddr) -> Result<Vec<pfctl::FilterRule>> {
+        let allow_tcp = self
+            .create_rule_builder(FilterRuleAction::Pass)
+            .direction(pfctl::Direction::Out)
+            .quick(true)
+            .proto(pfctl::Proto::Tcp)
+            .keep_state(pfctl::StatePolicy::Keep)
+            .tcp_flags(Self::get_tcp_flags())
+            .to(pfctl::Endpoint::new(server, 53))
+            .build()?;
+        let allow_udp = self
+            .create_rule_builder(FilterRuleAction::Pass)
+            .direction(pfctl::Direction::Out)
+            .quick(true)
+            .proto(pfctl::Proto::Udp)
+            .keep_state(pfctl::StatePolicy::Keep)
+            .to(pfctl::Endpoint::new(server, 53))
+            .build()?;
+
+        Ok(vec![allow_tcp, allow_udp])
+    }
+
     fn get_allow_relay_rule(&self, relay_endpoint: net::Endpoint) -> Result<pfctl::FilterRule> {
         let pfctl_proto = as_pfctl_proto(relay_endpoint.protocol);
 
@@ -439,6 +527,33 @@ impl Firewall {
         Ok(rules)
     }
 
+    /// Returns rules that allow traffic to and from networks that are routed outside the tunnel.
+    /// These must come after the rules that block DNS, so that DNS requests cannot leak to these
+    /// networks.
+    fn get_allow_bypass_route_rules(
+        &self,
+        bypass_routes: &[IpNetwork],
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for net in bypass_routes {
+            let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+            rule_builder.quick(true);
+            let allow_out = rule_builder
+                .direction(pfctl::Direction::Out)
+                .from(pfctl::Ip::Any)
+                .to(pfctl::Ip::from(*net))
+                .build()?;
+            let allow_in = rule_builder
+                .direction(pfctl::Direction::In)
+                .from(pfctl::Ip::from(*net))
+                .to(pfctl::Ip::Any)
+                .build()?;
+            rules.push(allow_out);
+            rules.push(allow_in);
+        }
+        Ok(rules)
+    }
+
     fn get_allow_dhcp_client_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let mut dhcp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
         dhcp_rule_builder.quick(true).proto(pfctl::Proto::Udp);
@@ -586,61 +701,6 @@ impl Firewall {
             &[pfctl::TcpFlag::Syn, pfctl::TcpFlag::Ack],
         )
     }
-
-    fn remove_rules(&mut self) -> Result<()> {
-        // remove_anchor() does not deactivate active rules
-        self.pf
-            .flush_rules(ANCHOR_NAME, pfctl::RulesetKind::Filter)?;
-        Ok(())
-    }
-
-    fn enable(&mut self) -> Result<()> {
-        if self.pf_was_enabled.is_none() {
-            self.pf_was_enabled = Some(self.is_enabled());
-        }
-        self.pf.try_enable()
-    }
-
-    fn is_enabled(&self) -> bool {
-        let cmd = duct::cmd!("/sbin/pfctl", "-s", "info")
-            .stderr_null()
-            .stdout_capture();
-        const EXPECTED_OUTPUT: &[u8] = b"Status: Enabled";
-        match cmd.run() {
-            Ok(output) => output.stdout.as_slice().find(EXPECTED_OUTPUT).is_some(),
-            Err(err) => {
-                log::error!(
-                    "Failed to execute pfctl, assuming pf is not enabled: {}",
-                    err
-                );
-                false
-            }
-        }
-    }
-
-    fn restore_state(&mut self) -> Result<()> {
-        match self.pf_was_enabled.take() {
-            Some(true) => Ok(self.pf.try_enable()?),
-            Some(false) => Ok(self.pf.try_disable()?),
-            None => Ok(()),
-        }
-    }
-
-    fn add_anchor(&mut self) -> Result<()> {
-        self.pf
-            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
-        self.pf
-            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
-        Ok(())
-    }
-
-    fn remove_anchor(&mut self) -> Result<()> {
-        self.pf
-            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
-        self.pf
-            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
-        Ok(())
-    }
 }
 
 fn as_pfctl_proto(protocol: net::TransportProtocol) -> pfctl::Proto {
@@ -657,3 +717,68 @@ enum RuleLogging {
     Drop,
     All,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tunnel::TunnelMetadata;
+
+    fn connected_policy(interface: &str) -> FirewallPolicy {
+        FirewallPolicy::Connected {
+            peer_endpoint: net::Endpoint::new(
+                Ipv4Addr::new(1, 2, 3, 4),
+                51820,
+                net::TransportProtocol::Udp,
+            ),
+            tunnel: TunnelMetadata {
+                interface: interface.to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+            },
+            allow_lan: false,
+            dns_servers: vec![],
+            bypass_routes: vec![],
+        }
+    }
+
+    /// Traffic that another VPN routes through its own tunnel interface, such as traffic to the
+    /// networks it adds routes for in coexistence mode, must not be allowed.
+    #[test]
+    fn test_connected_blocks_other_tunnels() {
+        let rules = PolicyRules {
+            rule_logging: RuleLogging::None,
+        };
+        let ours = rules.filter_rules(&connected_policy("utun4")).unwrap();
+
+        let allow_ours = rules
+            .base_rule(FilterRuleAction::Pass, "utun4")
+            .build()
+            .unwrap();
+        let allow_foreign = rules
+            .base_rule(FilterRuleAction::Pass, "utun3")
+            .build()
+            .unwrap();
+        assert!(ours.contains(&allow_ours));
+        assert!(!ours.contains(&allow_foreign));
+
+        // Everything that is not explicitly allowed is dropped
+        let drop_all = rules
+            .create_rule_builder(FilterRuleAction::Drop(DropAction::Drop))
+            .quick(true)
+            .build()
+            .unwrap();
+        assert_eq!(ours.last(), Some(&drop_all));
+
+        // Only the rules that are scoped to the tunnel interface depend on it, so no other rule
+        // allows traffic on the interface of another VPN
+        let theirs = rules.filter_rules(&connected_policy("utun3")).unwrap();
+        assert_eq!(ours.len(), theirs.len());
+        let differing: Vec<_> = ours
+            .iter()
+            .zip(&theirs)
+            .filter(|(ours, theirs)| ours != theirs)
+            .collect();
+        assert_eq!(differing, vec![(&allow_ours, &allow_foreign)]);
+    }
+}
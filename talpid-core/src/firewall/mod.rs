@@ -6,6 +6,8 @@ use std::{
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::DnsSource;
 use talpid_types::net::{AllowedEndpoint, AllowedTunnelTraffic, Endpoint};
 
 #[cfg(target_os = "macos")]
@@ -105,6 +107,79 @@ pub fn is_local_address(address: &IpAddr) -> bool {
         .any(|net| net.contains(address))
 }
 
+/// Returns whether an address belongs to a network that is reachable when "allow local network"
+/// is enabled.
+pub fn is_allowed_lan_address(address: &IpAddr) -> bool {
+    (*ALLOWED_LAN_NETS).iter().any(|net| net.contains(*address))
+}
+
+/// Returns whether all of `network` is reachable when "allow local network" is enabled.
+pub fn is_allowed_lan_network(network: &IpNetwork) -> bool {
+    (*ALLOWED_LAN_NETS)
+        .iter()
+        .any(|lan| lan.prefix() <= network.prefix() && lan.contains(network.ip()))
+}
+
+/// Returns the custom DNS servers on the LAN that may be reached in the blocked state. These are
+/// only allowed if LAN traffic is allowed and `allow_lan_dns_when_blocked` is set.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn blocked_lan_dns_servers(
+    dns_servers: Option<&[IpAddr]>,
+    allow_lan: bool,
+    allow_lan_dns_when_blocked: bool,
+) -> Vec<IpAddr> {
+    if !allow_lan || !allow_lan_dns_when_blocked {
+        return vec![];
+    }
+    dns_servers
+        .unwrap_or_default()
+        .iter()
+        .filter(|server| is_allowed_lan_address(server))
+        .copied()
+        .collect()
+}
+
+/// Returns the custom DNS servers that DNS requests are restricted to in the unblocked disconnected
+/// state. These are only used if `keep_custom_dns_while_disconnected` is set, and only while
+/// "block when disconnected" is disabled, since the blocked state already blocks all DNS.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn disconnected_dns_servers(
+    dns_servers: Option<&[IpAddr]>,
+    dns_source: DnsSource,
+    keep_custom_dns_while_disconnected: bool,
+    block_when_disconnected: bool,
+) -> Vec<IpAddr> {
+    if !keep_custom_dns_while_disconnected
+        || block_when_disconnected
+        || dns_source != DnsSource::Custom
+    {
+        return vec![];
+    }
+    dns_servers.unwrap_or_default().to_vec()
+}
+
+/// Returns the networks in `bypass_routes` that should be routed outside the tunnel while
+/// connecting to `peer_endpoint`. Networks that contain the relay are ignored, as are networks
+/// that would route all traffic outside the tunnel.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn bypass_routes(bypass_routes: &[IpNetwork], peer_endpoint: &Endpoint) -> Vec<IpNetwork> {
+    bypass_routes
+        .iter()
+        .filter(|network| {
+            if network.prefix() == 0 {
+                log::warn!("Ignoring bypass route {network} since it matches all traffic");
+                return false;
+            }
+            if network.contains(peer_endpoint.address.ip()) {
+                log::warn!("Ignoring bypass route {network} since it contains the relay");
+                return false;
+            }
+            true
+        })
+        .copied()
+        .collect()
+}
+
 /// A enum that describes network security strategy
 ///
 /// # Firewall block/allow specification.
@@ -125,6 +200,9 @@ pub enum FirewallPolicy {
         allowed_endpoint: AllowedEndpoint,
         /// Networks for which to permit in-tunnel traffic.
         allowed_tunnel_traffic: AllowedTunnelTraffic,
+        /// Networks that are routed outside the tunnel.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        bypass_routes: Vec<IpNetwork>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -141,6 +219,13 @@ pub enum FirewallPolicy {
         /// Servers that are allowed to respond to DNS requests.
         #[cfg(not(target_os = "android"))]
         dns_servers: Vec<IpAddr>,
+        /// Resolvers outside the tunnel that DNS requests from excluded processes to
+        /// `dns_servers` are redirected to. Requests to local stub resolvers are not redirected.
+        #[cfg(target_os = "linux")]
+        excluded_dns_servers: Vec<IpAddr>,
+        /// Networks that are routed outside the tunnel.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        bypass_routes: Vec<IpNetwork>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -152,11 +237,22 @@ pub enum FirewallPolicy {
         allow_lan: bool,
         /// Host that should be reachable while in the blocked state.
         allowed_endpoint: Option<AllowedEndpoint>,
+        /// DNS servers on the LAN that are allowed to respond to DNS requests. Only used if
+        /// `allow_lan` is set.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        lan_dns_servers: Vec<IpAddr>,
         /// Destination port for DNS traffic redirection. Traffic destined to `127.0.0.1:53` will
         /// be redirected to `127.0.0.1:$dns_redirect_port`.
         #[cfg(target_os = "macos")]
         dns_redirect_port: u16,
     },
+
+    /// Allow all network traffic except DNS requests to servers other than `dns_servers`.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    RestrictDns {
+        /// Servers that are allowed to respond to DNS requests.
+        dns_servers: Vec<IpAddr>,
+    },
 }
 
 impl fmt::Display for FirewallPolicy {
@@ -231,6 +327,16 @@ impl fmt::Display for FirewallPolicy {
                     .map(|endpoint| -> &dyn std::fmt::Display { endpoint })
                     .unwrap_or(&"none"),
             ),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            FirewallPolicy::RestrictDns { dns_servers } => write!(
+                f,
+                "Restricting DNS to {}",
+                dns_servers
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
         }
     }
 }
@@ -238,7 +344,14 @@ impl fmt::Display for FirewallPolicy {
 /// Manages network security of the computer/device. Can apply and enforce firewall policies
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
-    inner: imp::Firewall,
+    inner: Inner,
+}
+
+enum Inner {
+    System(imp::Firewall),
+    /// Accepts every policy without enforcing it, for testing without privileges.
+    #[cfg(feature = "mock-tunnel")]
+    Mock,
 }
 
 /// Arguments required when first initializing the firewall.
@@ -251,6 +364,12 @@ pub struct FirewallArguments {
     /// the tunnel and _leaked_ during blocked states.
     #[cfg(target_os = "linux")]
     pub fwmark: u32,
+    /// Users and groups whose traffic is excluded from the tunnel.
+    #[cfg(target_os = "linux")]
+    pub excluded_owners: crate::split_tunnel::ExcludedOwners,
+    /// Split tunnel settings that only affect the firewall rules.
+    #[cfg(target_os = "linux")]
+    pub split_tunnel: crate::split_tunnel::Config,
 }
 
 /// State to enter during firewall init.
@@ -265,31 +384,166 @@ impl Firewall {
     /// Creates a firewall instance with the given arguments.
     pub fn from_args(args: FirewallArguments) -> Result<Self, Error> {
         Ok(Firewall {
-            inner: imp::Firewall::from_args(args)?,
+            inner: Inner::System(imp::Firewall::from_args(args)?),
         })
     }
 
     /// Createsa new firewall instance.
     pub fn new(#[cfg(target_os = "linux")] fwmark: u32) -> Result<Self, Error> {
         Ok(Firewall {
-            inner: imp::Firewall::new(
+            inner: Inner::System(imp::Firewall::new(
                 #[cfg(target_os = "linux")]
                 fwmark,
-            )?,
+            )?),
         })
     }
 
+    /// Creates a firewall that only logs the policies that it is given. No rules are added.
+    #[cfg(feature = "mock-tunnel")]
+    pub fn mock() -> Self {
+        Firewall { inner: Inner::Mock }
+    }
+
     /// Applies and starts enforcing the given `FirewallPolicy` Makes sure it is being kept in place
     /// until this method is called again with another policy, or until `reset_policy` is called.
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
         log::info!("Applying firewall policy: {}", policy);
-        self.inner.apply_policy(policy)
+        match &mut self.inner {
+            Inner::System(inner) => inner.apply_policy(policy),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock => Ok(()),
+        }
+    }
+
+    /// Sets the users and groups whose traffic is excluded from the tunnel. The rules are replaced
+    /// immediately if a policy is being enforced.
+    #[cfg(target_os = "linux")]
+    pub fn set_excluded_owners(
+        &mut self,
+        owners: crate::split_tunnel::ExcludedOwners,
+    ) -> Result<(), Error> {
+        log::info!(
+            "Excluding users {:?} and groups {:?} from the tunnel",
+            owners.users,
+            owners.groups
+        );
+        match &mut self.inner {
+            Inner::System(inner) => inner.set_excluded_owners(owners),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock => Ok(()),
+        }
+    }
+
+    /// Sets the split tunnel settings that only affect the firewall rules. The rules are replaced
+    /// immediately if a policy is being enforced.
+    #[cfg(target_os = "linux")]
+    pub fn set_split_tunnel_config(
+        &mut self,
+        config: crate::split_tunnel::Config,
+    ) -> Result<(), Error> {
+        log::info!("Setting split tunnel config: {config:?}");
+        match &mut self.inner {
+            Inner::System(inner) => inner.set_split_tunnel_config(config),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock => Ok(()),
+        }
     }
 
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         log::info!("Resetting firewall policy");
-        self.inner.reset_policy()
+        match &mut self.inner {
+            Inner::System(inner) => inner.reset_policy(),
+            #[cfg(feature = "mock-tunnel")]
+            Inner::Mock => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod test {
+    use super::*;
+
+    const LAN_RESOLVER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2));
+    const PUBLIC_RESOLVER: IpAddr = IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9));
+    const LOOPBACK_RESOLVER: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 53));
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 1);
+
+    fn custom_servers() -> Vec<IpAddr> {
+        vec![
+            LAN_RESOLVER,
+            PUBLIC_RESOLVER,
+            LOOPBACK_RESOLVER,
+            IpAddr::V4(GATEWAY),
+        ]
+    }
+
+    #[test]
+    fn test_blocked_allow_lan() {
+        let servers = custom_servers();
+        assert_eq!(
+            blocked_lan_dns_servers(Some(&servers), true, true),
+            vec![LAN_RESOLVER, IpAddr::V4(GATEWAY)]
+        );
+        assert!(blocked_lan_dns_servers(Some(&servers), true, false).is_empty());
+        assert!(blocked_lan_dns_servers(None, true, true).is_empty());
+    }
+
+    #[test]
+    fn test_blocked_block_lan() {
+        let servers = custom_servers();
+        assert!(blocked_lan_dns_servers(Some(&servers), false, true).is_empty());
+        assert!(blocked_lan_dns_servers(Some(&servers), false, false).is_empty());
+    }
+
+    #[test]
+    fn test_keep_custom_dns_while_disconnected() {
+        let servers = vec![PUBLIC_RESOLVER];
+        assert_eq!(
+            disconnected_dns_servers(Some(&servers), DnsSource::Custom, true, false),
+            servers
+        );
+        assert!(disconnected_dns_servers(None, DnsSource::Custom, true, false).is_empty());
+    }
+
+    #[test]
+    fn test_dont_keep_dns_while_disconnected() {
+        let servers = vec![PUBLIC_RESOLVER];
+        // The option is disabled
+        assert!(
+            disconnected_dns_servers(Some(&servers), DnsSource::Custom, false, false).is_empty()
+        );
+        // The blocked state blocks all DNS anyway
+        assert!(disconnected_dns_servers(Some(&servers), DnsSource::Custom, true, true).is_empty());
+        // Content blockers are only reachable through the tunnel
+        assert!(
+            disconnected_dns_servers(Some(&servers), DnsSource::ContentBlockers, true, false)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_bypass_routes() {
+        let relay = Endpoint::new(
+            Ipv4Addr::new(185, 213, 154, 68),
+            51820,
+            talpid_types::net::TransportProtocol::Udp,
+        );
+        let routes: Vec<IpNetwork> = ["10.12.0.0/16", "0.0.0.0/0", "185.213.154.0/24", "::/0"]
+            .iter()
+            .map(|network| network.parse().unwrap())
+            .collect();
+        assert_eq!(bypass_routes(&routes, &relay), vec![routes[0]]);
+    }
+
+    #[test]
+    fn test_allowed_lan_network() {
+        let network = |network: &str| network.parse::<IpNetwork>().unwrap();
+        assert!(is_allowed_lan_network(&network("10.1.0.0/16")));
+        assert!(is_allowed_lan_network(&network("fd00::/8")));
+        // Networks that are only partly on the LAN are not allowed
+        assert!(!is_allowed_lan_network(&network("10.0.0.0/7")));
+        assert!(!is_allowed_lan_network(&network("9.9.9.9/32")));
     }
 }
@@ -15,6 +15,10 @@ pub mod window;
 
 mod offline;
 
+/// Notifications about the host suspending and resuming.
+#[cfg(not(target_os = "android"))]
+pub mod power;
+
 /// Split tunneling
 pub mod split_tunnel;
 
@@ -1,5 +1,5 @@
 use std::{
-    ffi::{self, CString},
+    ffi::{self, CStr, CString},
     io,
 };
 
@@ -18,6 +18,17 @@ pub fn iface_index(name: &str) -> Result<libc::c_uint, IfaceIndexLookupError> {
     }
 }
 
+/// Converts an interface index into the corresponding name.
+pub fn iface_name(index: libc::c_uint) -> io::Result<String> {
+    let mut buffer = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let name = unsafe { libc::if_indextoname(index, buffer.as_mut_ptr()) };
+    if name.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let name = unsafe { CStr::from_ptr(name) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
 #[derive(Debug, err_derive::Error)]
 pub enum IfaceIndexLookupError {
     #[error(display = "Invalid network interface name: {}", _0)]
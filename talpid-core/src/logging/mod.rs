@@ -1,25 +1,390 @@
-use std::{fs, io, path::Path};
+//! Size-based rotation of log files. Once a log has grown past [`RotationConfig::max_size`], its
+//! contents are compressed into `<name>.1.<ext>.gz`, older archives are shifted up by one, and the
+//! log is emptied. At most [`RotationConfig::max_archives`] archives are kept.
+//!
+//! Archives are written to a `.partial` file that is renamed into place once it is complete, so
+//! that a crash while compressing never leaves a truncated archive behind. Partial archives are
+//! removed the next time that the log is opened or rotated.
+
+use flate2::{write::GzEncoder, Compression};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{
+    fs,
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
 
 /// Unable to create new log file
 #[derive(err_derive::Error, Debug)]
 #[error(display = "Unable to create new log file")]
 pub struct RotateLogError(#[error(source)] io::Error);
 
-/// Create a new log file while backing up a previous version of it.
-///
-/// A new log file is created with the given file name, but if a file with that name already exists
-/// it is backed up with the extension changed to `.old.log`.
-pub fn rotate_log(file: &Path) -> Result<(), RotateLogError> {
-    let backup = file.with_extension("old.log");
-    if let Err(error) = fs::rename(file, &backup) {
-        if error.kind() != io::ErrorKind::NotFound {
-            log::warn!(
-                "Failed to rotate log file to {}: {}",
-                backup.display(),
+/// Suffix of archives that are still being written.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// How large logs may grow, and how many archives are kept of each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationConfig {
+    /// Size in bytes that a log may grow to before it is rotated.
+    pub max_size: u64,
+    /// Number of compressed archives to keep. If zero, logs are emptied without being archived.
+    pub max_archives: usize,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 10 * 1024 * 1024,
+            max_archives: 5,
+        }
+    }
+}
+
+static ROTATION_CONFIG: Lazy<Mutex<RotationConfig>> =
+    Lazy::new(|| Mutex::new(RotationConfig::default()));
+
+/// Changes the configuration used by [`RotatingLogWriter`] and for the tunnel logs.
+pub fn set_rotation_config(config: RotationConfig) {
+    *ROTATION_CONFIG.lock() = config;
+}
+
+/// Returns the configuration used by [`RotatingLogWriter`] and for the tunnel logs.
+pub fn rotation_config() -> RotationConfig {
+    *ROTATION_CONFIG.lock()
+}
+
+/// Rotates `file` if it has grown past the size in `config`. Logs that are written by other
+/// processes, which cannot be rotated while they are open, are passed through this before the
+/// processes start.
+pub fn rotate_log(file: &Path, config: &RotationConfig) -> Result<(), RotateLogError> {
+    remove_partial_archives(file);
+    let size = match fs::metadata(file) {
+        Ok(metadata) => metadata.len(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(RotateLogError(error)),
+    };
+    if size < config.max_size {
+        return Ok(());
+    }
+    archive(file, config.max_archives);
+    fs::File::create(file).map(|_| ()).map_err(RotateLogError)
+}
+
+/// Returns the path of the archive of `file` that is `index` rotations old, counting from 1.
+/// For `daemon.log`, the most recent archive is `daemon.1.log.gz`.
+pub fn archive_path(file: &Path, index: usize) -> PathBuf {
+    let mut name = file.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(".{index}"));
+    if let Some(extension) = file.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    name.push(".gz");
+    file.with_file_name(name)
+}
+
+/// Appends to a log file, and rotates it once it has grown past the size in
+/// [`rotation_config`]. The file is emptied in place rather than renamed, since open files cannot
+/// be renamed on Windows.
+pub struct RotatingLogWriter {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+}
+
+impl RotatingLogWriter {
+    /// Opens `path` for appending. The log is rotated first if it is already too large.
+    pub fn open(path: &Path) -> Result<Self, RotateLogError> {
+        rotate_log(path, &rotation_config())?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(path)
+            .map_err(RotateLogError)?;
+        let size = file.seek(SeekFrom::End(0)).map_err(RotateLogError)?;
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            size,
+        })
+    }
+
+    fn rotate(&mut self, config: &RotationConfig) -> io::Result<()> {
+        archive(&self.path, config.max_archives);
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    /// The size is checked when the writer is flushed, which the logger does after each record,
+    /// so that records are never split across two files.
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let config = rotation_config();
+        if self.size >= config.max_size {
+            self.rotate(&config)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compresses the contents of `file` into a new archive and shifts the older archives up by one,
+/// removing those that are more than `max_archives` old. `file` itself is left unchanged.
+/// Failures are only reported, since the log must be emptied either way.
+fn archive(file: &Path, max_archives: usize) {
+    if let Err(error) = try_archive(file, max_archives) {
+        // This may run inside the logger, so the error cannot be logged
+        eprintln!("Failed to archive log file {}: {}", file.display(), error);
+    }
+}
+
+fn try_archive(file: &Path, max_archives: usize) -> io::Result<()> {
+    remove_partial_archives(file);
+    let mut archives = archives(file)?;
+    archives.reverse();
+    for (index, path) in archives {
+        if index >= max_archives {
+            remove_file(&path)?;
+        } else {
+            fs::rename(&path, archive_path(file, index + 1))?;
+        }
+    }
+    if max_archives == 0 {
+        return Ok(());
+    }
+
+    let destination = archive_path(file, 1);
+    let mut partial = destination.clone().into_os_string();
+    partial.push(PARTIAL_SUFFIX);
+    let partial = PathBuf::from(partial);
+    if let Err(error) = compress(file, &partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(error);
+    }
+    fs::rename(&partial, &destination)
+}
+
+fn compress(source: &Path, destination: &Path) -> io::Result<()> {
+    let mut source = fs::File::open(source)?;
+    let mut encoder = GzEncoder::new(fs::File::create(destination)?, Compression::default());
+    io::copy(&mut source, &mut encoder)?;
+    encoder.finish()?.sync_all()
+}
+
+/// Returns the complete archives of `file` and their indices, most recent first.
+fn archives(file: &Path) -> io::Result<Vec<(usize, PathBuf)>> {
+    let mut archives: Vec<_> = archive_files(file)?
+        .into_iter()
+        .filter(|(_, path)| !is_partial(path))
+        .collect();
+    archives.sort_unstable_by_key(|(index, _)| *index);
+    Ok(archives)
+}
+
+/// Removes what is left of archives that were being written when the process was stopped.
+fn remove_partial_archives(file: &Path) {
+    let Ok(archives) = archive_files(file) else {
+        return;
+    };
+    for (_, path) in archives.into_iter().filter(|(_, path)| is_partial(path)) {
+        if let Err(error) = remove_file(&path) {
+            eprintln!(
+                "Failed to remove partial log archive {}: {}",
+                path.display(),
                 error
             );
         }
     }
+}
 
-    fs::File::create(file).map(|_| ()).map_err(RotateLogError)
+/// Returns all files next to `file` that are named like its archives, and their indices.
+fn archive_files(file: &Path) -> io::Result<Vec<(usize, PathBuf)>> {
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut archives = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let index = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| archive_index(file, name));
+        if let Some(index) = index {
+            archives.push((index, path));
+        }
+    }
+    Ok(archives)
+}
+
+/// Returns the index of the archive of `file` named `name`, whether it is partial or complete.
+fn archive_index(file: &Path, name: &str) -> Option<usize> {
+    let stem = file.file_stem()?.to_str()?;
+    let suffix = match file.extension() {
+        Some(extension) => format!(".{}.gz", extension.to_str()?),
+        None => ".gz".to_owned(),
+    };
+    let name = name.strip_suffix(PARTIAL_SUFFIX).unwrap_or(name);
+    let index = name
+        .strip_prefix(stem)?
+        .strip_prefix('.')?
+        .strip_suffix(suffix.as_str())?;
+    if index.is_empty() || !index.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    index.parse().ok().filter(|index| *index > 0)
+}
+
+fn is_partial(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(PARTIAL_SUFFIX)
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn config(max_size: u64, max_archives: usize) -> RotationConfig {
+        RotationConfig {
+            max_size,
+            max_archives,
+        }
+    }
+
+    fn decompress(path: &Path) -> String {
+        let mut contents = String::new();
+        GzDecoder::new(fs::File::open(path).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    fn indices(file: &Path) -> Vec<usize> {
+        archives(file)
+            .unwrap()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    #[test]
+    fn test_archive_path() {
+        assert_eq!(
+            archive_path(Path::new("/var/log/daemon.log"), 1),
+            Path::new("/var/log/daemon.1.log.gz")
+        );
+        assert_eq!(
+            archive_index(Path::new("/var/log/daemon.log"), "daemon.12.log.gz.partial"),
+            Some(12)
+        );
+        assert_eq!(
+            archive_index(Path::new("/var/log/daemon.log"), "daemon.old.log"),
+            None
+        );
+        assert_eq!(
+            archive_index(Path::new("/var/log/daemon.log"), "daemon.+1.log.gz"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rollover_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("openvpn.log");
+        let config = config(4, 2);
+
+        for generation in ["first", "second", "third"] {
+            fs::write(&log, generation).unwrap();
+            rotate_log(&log, &config).unwrap();
+            assert_eq!(fs::read(&log).unwrap(), b"");
+        }
+
+        // The oldest archive was dropped
+        assert_eq!(indices(&log), [1, 2]);
+        assert_eq!(decompress(&archive_path(&log, 1)), "third");
+        assert_eq!(decompress(&archive_path(&log, 2)), "second");
+
+        // Logs that are smaller than the limit are left alone
+        fs::write(&log, "abc").unwrap();
+        rotate_log(&log, &config).unwrap();
+        assert_eq!(fs::read(&log).unwrap(), b"abc");
+        assert_eq!(indices(&log), [1, 2]);
+    }
+
+    #[test]
+    fn test_writer_compresses_full_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("daemon.log");
+        fs::write(&log, "previous run\n").unwrap();
+
+        let mut writer = RotatingLogWriter::open(&log).unwrap();
+        let line = "x".repeat(rotation_config().max_size as usize);
+        writeln!(writer, "{line}").unwrap();
+        writer.flush().unwrap();
+        writeln!(writer, "after rotation").unwrap();
+        writer.flush().unwrap();
+
+        let archived = decompress(&archive_path(&log, 1));
+        assert!(archived.starts_with("previous run\n"));
+        assert!(archived.ends_with(&format!("{line}\n")));
+        assert!(fs::metadata(archive_path(&log, 1)).unwrap().len() < archived.len() as u64);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "after rotation\n");
+    }
+
+    #[test]
+    fn test_without_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("daemon.log");
+        fs::write(&log, "contents").unwrap();
+        fs::write(archive_path(&log, 1), "stale").unwrap();
+
+        rotate_log(&log, &config(1, 0)).unwrap();
+        assert_eq!(fs::read(&log).unwrap(), b"");
+        assert!(indices(&log).is_empty());
+    }
+
+    #[test]
+    fn test_partial_archives_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("daemon.log");
+        let partial = dir.path().join("daemon.1.log.gz.partial");
+        let unrelated = dir.path().join("openvpn.1.log.gz");
+
+        // A crash while compressing leaves a partial archive behind, which must not be mistaken
+        // for a complete one
+        fs::write(&log, "contents").unwrap();
+        fs::write(&partial, "truncated").unwrap();
+        fs::write(&unrelated, "other log").unwrap();
+        assert!(indices(&log).is_empty());
+
+        drop(RotatingLogWriter::open(&log).unwrap());
+        assert!(!partial.exists());
+        assert!(unrelated.exists());
+        assert_eq!(fs::read(&log).unwrap(), b"contents");
+
+        // They are also removed when rotating, before the new archive is written
+        fs::write(&partial, "truncated").unwrap();
+        rotate_log(&log, &config(1, 2)).unwrap();
+        assert!(!partial.exists());
+        assert_eq!(indices(&log), [1]);
+        assert_eq!(decompress(&archive_path(&log, 1)), "contents");
+    }
 }
@@ -10,7 +10,7 @@ use jnix::{
     JnixEnv,
 };
 use std::sync::{Arc, Weak};
-use talpid_types::{android::AndroidContext, ErrorExt};
+use talpid_types::{android::AndroidContext, net::Connectivity, ErrorExt};
 
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -114,6 +114,16 @@ impl MonitorHandle {
         }
     }
 
+    /// The IP versions cannot be told apart, so both are presumed to be reachable when the device
+    /// is connected.
+    pub async fn connectivity(&self) -> Connectivity {
+        let is_online = !self.host_is_offline().await;
+        Connectivity {
+            ipv4: is_online,
+            ipv6: is_online,
+        }
+    }
+
     fn get_is_connected(&self) -> Result<bool, Error> {
         let result = self.call_method(
             "isConnected",
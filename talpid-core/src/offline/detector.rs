@@ -0,0 +1,124 @@
+//! Decides whether the host is offline based on the default routes, which are looked up whenever
+//! the routes change. Transitions are debounced, since the default route briefly disappears when
+//! its priority is swapped between interfaces.
+
+use std::time::{Duration, Instant};
+
+/// Time that connectivity must remain lost or restored before the offline state changes.
+pub const DEBOUNCE_DELAY: Duration = Duration::from_secs(2);
+
+/// State of the default route found for an IP version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteState {
+    /// There is no default route.
+    Missing,
+    /// There is a default route. Its gateway responds, or was not probed.
+    Usable,
+    /// There is a default route, but its gateway does not respond.
+    DeadGateway,
+}
+
+/// Returns whether the host is offline given the default routes of all IP versions.
+pub fn is_offline(routes: &[RouteState]) -> bool {
+    !routes.contains(&RouteState::Usable)
+}
+
+/// Tracks the offline state of the host.
+#[derive(Debug)]
+pub struct OfflineDetector {
+    is_offline: bool,
+    /// When connectivity was first observed to differ from `is_offline`.
+    pending_since: Option<Instant>,
+}
+
+impl OfflineDetector {
+    pub fn new(is_offline: bool) -> Self {
+        OfflineDetector {
+            is_offline,
+            pending_since: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_offline(&self) -> bool {
+        self.is_offline
+    }
+
+    /// Updates the detector with the default `routes` that were found at `now`. If this starts or
+    /// continues a transition, returns when [`Self::poll`] should be called to complete it.
+    pub fn update(&mut self, routes: &[RouteState], now: Instant) -> Option<Instant> {
+        if is_offline(routes) == self.is_offline {
+            self.pending_since = None;
+            return None;
+        }
+        let since = *self.pending_since.get_or_insert(now);
+        Some(since + DEBOUNCE_DELAY)
+    }
+
+    /// Completes a pending transition if it has lasted for [`DEBOUNCE_DELAY`] at `now`. Returns
+    /// the new offline state if it changed.
+    pub fn poll(&mut self, now: Instant) -> Option<bool> {
+        match self.pending_since {
+            Some(since) if now >= since + DEBOUNCE_DELAY => {
+                self.pending_since = None;
+                self.is_offline = !self.is_offline;
+                Some(self.is_offline)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use RouteState::*;
+
+    #[test]
+    fn test_interface_swap() {
+        let start = Instant::now();
+        let mut detector = OfflineDetector::new(false);
+
+        // The default route is removed from the ethernet interface before it is added to Wi-Fi
+        let deadline = detector.update(&[Missing, Missing], start);
+        assert_eq!(deadline, Some(start + DEBOUNCE_DELAY));
+        assert_eq!(detector.poll(start + Duration::from_millis(500)), None);
+
+        assert_eq!(
+            detector.update(&[Usable, Missing], start + Duration::from_secs(1)),
+            None
+        );
+        assert_eq!(detector.poll(start + DEBOUNCE_DELAY), None);
+        assert!(!detector.is_offline());
+    }
+
+    #[test]
+    fn test_route_lost() {
+        let start = Instant::now();
+        let mut detector = OfflineDetector::new(false);
+
+        detector.update(&[Missing, Missing], start);
+        // Further route changes do not postpone the transition
+        assert_eq!(
+            detector.update(&[Missing, Missing], start + Duration::from_secs(1)),
+            Some(start + DEBOUNCE_DELAY)
+        );
+        assert_eq!(detector.poll(start + DEBOUNCE_DELAY), Some(true));
+        assert!(detector.is_offline());
+        assert_eq!(detector.poll(start + DEBOUNCE_DELAY * 2), None);
+    }
+
+    #[test]
+    fn test_dead_gateway() {
+        let start = Instant::now();
+        let mut detector = OfflineDetector::new(false);
+
+        assert!(detector.update(&[DeadGateway, Missing], start).is_some());
+        assert_eq!(detector.poll(start + DEBOUNCE_DELAY), Some(true));
+
+        // Any usable route is enough to be online
+        let later = start + Duration::from_secs(10);
+        assert!(detector.update(&[DeadGateway, Usable], later).is_some());
+        assert_eq!(detector.poll(later + DEBOUNCE_DELAY), Some(false));
+    }
+}
@@ -1,10 +1,13 @@
+use super::detector::{self, OfflineDetector, RouteState};
 use futures::{channel::mpsc::UnboundedSender, StreamExt};
+use once_cell::sync::Lazy;
 use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     sync::Arc,
+    time::{Duration, Instant},
 };
 use talpid_routing::{self, RouteManagerHandle};
-use talpid_types::ErrorExt;
+use talpid_types::{net::Connectivity, ErrorExt};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -25,10 +28,22 @@ const PUBLIC_INTERNET_ADDRESS_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(193, 138, 21
 const PUBLIC_INTERNET_ADDRESS_V6: IpAddr =
     IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6));
 
+/// How often the gateways are probed when probing is enabled. A gateway that stops responding
+/// does not change the routes. The kernel gives up on an unresponsive neighbour after a few
+/// seconds, so the result of a probe is read when the next probe is sent.
+const GATEWAY_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Consider the host offline if the gateways of the default routes do not respond to ARP/NDP.
+static PROBE_GATEWAYS: Lazy<bool> = Lazy::new(|| {
+    std::env::var("TALPID_OFFLINE_MONITOR_PROBE_GATEWAYS")
+        .map(|v| v != "0")
+        .unwrap_or(false)
+});
+
 impl MonitorHandle {
     pub async fn host_is_offline(&self) -> bool {
-        match public_ip_unreachable(&self.route_manager, self.fwmark).await {
-            Ok(is_offline) => is_offline,
+        match default_routes(&self.route_manager, self.fwmark, false).await {
+            Ok(routes) => detector::is_offline(&routes),
             Err(err) => {
                 log::error!(
                     "Failed to verify offline state: {}. Presuming connectivity",
@@ -38,6 +53,22 @@ impl MonitorHandle {
             }
         }
     }
+
+    pub async fn connectivity(&self) -> Connectivity {
+        match default_routes(&self.route_manager, self.fwmark, false).await {
+            Ok([v4_route, v6_route]) => Connectivity {
+                ipv4: v4_route == RouteState::Usable,
+                ipv6: v6_route == RouteState::Usable,
+            },
+            Err(err) => {
+                log::error!(
+                    "Failed to verify connectivity: {}. Presuming connectivity",
+                    err
+                );
+                Connectivity::PRESUME_ONLINE
+            }
+        }
+    }
 }
 
 pub async fn spawn_monitor(
@@ -45,7 +76,8 @@ pub async fn spawn_monitor(
     route_manager: RouteManagerHandle,
     fwmark: Option<u32>,
 ) -> Result<MonitorHandle> {
-    let mut is_offline = public_ip_unreachable(&route_manager, fwmark).await?;
+    let routes = default_routes(&route_manager, fwmark, false).await?;
+    let mut offline_detector = OfflineDetector::new(detector::is_offline(&routes));
 
     let mut listener = route_manager
         .change_listener()
@@ -61,24 +93,43 @@ pub async fn spawn_monitor(
     };
 
     tokio::spawn(async move {
-        while let Some(_event) = listener.next().await {
-            match sender.upgrade() {
-                Some(sender) => {
-                    let new_offline_state = public_ip_unreachable(&route_manager, fwmark)
-                        .await
-                        .unwrap_or_else(|err| {
-                            log::error!(
-                                "{}",
-                                err.display_chain_with_msg("Failed to infer offline state")
-                            );
-                            false
-                        });
-                    if new_offline_state != is_offline {
-                        is_offline = new_offline_state;
-                        let _ = sender.unbounded_send(is_offline);
+        let mut deadline = None;
+        let mut probe_interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + GATEWAY_PROBE_INTERVAL,
+            GATEWAY_PROBE_INTERVAL,
+        );
+
+        loop {
+            let (check_routes, probe) = tokio::select! {
+                event = listener.next() => {
+                    if event.is_none() {
+                        return;
                     }
+                    (true, false)
                 }
-                None => return,
+                _ = probe_interval.tick(), if *PROBE_GATEWAYS => (true, true),
+                _ = sleep_until(deadline) => (false, false),
+            };
+            let Some(sender) = sender.upgrade() else {
+                return;
+            };
+
+            let now = Instant::now();
+            if check_routes {
+                let routes = default_routes(&route_manager, fwmark, probe)
+                    .await
+                    .unwrap_or_else(|err| {
+                        log::error!(
+                            "{}",
+                            err.display_chain_with_msg("Failed to infer offline state")
+                        );
+                        [RouteState::Usable; 2]
+                    });
+                deadline = offline_detector.update(&routes, now);
+            }
+            if let Some(is_offline) = offline_detector.poll(now) {
+                deadline = None;
+                let _ = sender.unbounded_send(is_offline);
             }
         }
     });
@@ -86,15 +137,73 @@ pub async fn spawn_monitor(
     Ok(monitor_handle)
 }
 
-async fn public_ip_unreachable(handle: &RouteManagerHandle, fwmark: Option<u32>) -> Result<bool> {
-    Ok(handle
-        .get_destination_route(PUBLIC_INTERNET_ADDRESS_V4, fwmark)
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Returns the state of the default IPv4 and IPv6 routes. If `probe` is set, the gateways are also
+/// probed after their current state has been read.
+async fn default_routes(
+    handle: &RouteManagerHandle,
+    fwmark: Option<u32>,
+    probe: bool,
+) -> Result<[RouteState; 2]> {
+    let v4_route = route_state(handle, PUBLIC_INTERNET_ADDRESS_V4, fwmark, probe).await?;
+    let v6_route = route_state(handle, PUBLIC_INTERNET_ADDRESS_V6, fwmark, probe)
+        .await
+        .unwrap_or(RouteState::Missing);
+    Ok([v4_route, v6_route])
+}
+
+async fn route_state(
+    handle: &RouteManagerHandle,
+    destination: IpAddr,
+    fwmark: Option<u32>,
+    probe: bool,
+) -> Result<RouteState> {
+    let Some(route) = handle
+        .get_destination_route(destination, fwmark)
         .await
         .map_err(Error::RouteManagerError)?
-        .is_none()
-        && handle
-            .get_destination_route(PUBLIC_INTERNET_ADDRESS_V6, fwmark)
-            .await
-            .unwrap_or(None)
-            .is_none())
+    else {
+        return Ok(RouteState::Missing);
+    };
+    if !*PROBE_GATEWAYS {
+        return Ok(RouteState::Usable);
+    }
+    let node = route.get_node();
+    let (Some(gateway), Some(device)) = (node.get_address(), node.get_device()) else {
+        return Ok(RouteState::Usable);
+    };
+    let state = match handle
+        .gateway_is_reachable(gateway, device.to_owned())
+        .await
+    {
+        Ok(true) => RouteState::Usable,
+        Ok(false) => {
+            log::debug!("Gateway {gateway} on {device} is unreachable");
+            RouteState::DeadGateway
+        }
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to check state of gateway. Presuming it is reachable"
+                )
+            );
+            RouteState::Usable
+        }
+    };
+    if probe {
+        if let Err(error) = handle.probe_neighbour(gateway, device.to_owned()).await {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to probe gateway")
+            );
+        }
+    }
+    Ok(state)
 }
@@ -18,6 +18,7 @@ use std::{
     time::Duration,
 };
 use talpid_routing::{DefaultRouteEvent, RouteManagerHandle};
+use talpid_types::net::Connectivity;
 
 const SYNTHETIC_OFFLINE_DURATION: Duration = Duration::from_secs(1);
 
@@ -51,6 +52,16 @@ impl MonitorHandle {
         let state = self.state.lock().unwrap();
         !state.get_connectivity()
     }
+
+    /// Return the IP versions that have a default route
+    #[allow(clippy::unused_async)]
+    pub async fn connectivity(&self) -> Connectivity {
+        let state = self.state.lock().unwrap();
+        Connectivity {
+            ipv4: state.v4_connectivity,
+            ipv6: state.v6_connectivity,
+        }
+    }
 }
 
 pub async fn spawn_monitor(
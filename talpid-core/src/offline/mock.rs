@@ -0,0 +1,47 @@
+//! Offline monitor whose state is only changed by hand, for testing without the platform monitor.
+
+use futures::channel::mpsc::UnboundedSender;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use talpid_types::net::Connectivity;
+
+/// Reports the host as online until [`MonitorHandle::set_offline`] says otherwise. Clones share
+/// the same state.
+#[derive(Clone)]
+pub struct MonitorHandle {
+    is_offline: Arc<AtomicBool>,
+    notify_tx: UnboundedSender<bool>,
+}
+
+impl MonitorHandle {
+    /// Returns a monitor that sends its changes to `notify_tx`, like the platform monitor.
+    pub fn new(notify_tx: UnboundedSender<bool>) -> Self {
+        Self {
+            is_offline: Arc::new(AtomicBool::new(false)),
+            notify_tx,
+        }
+    }
+
+    /// Makes the host appear to be offline, or online again.
+    pub fn set_offline(&self, is_offline: bool) {
+        self.is_offline.store(is_offline, Ordering::SeqCst);
+        let _ = self.notify_tx.unbounded_send(is_offline);
+    }
+
+    pub async fn host_is_offline(&self) -> bool {
+        self.is_offline.load(Ordering::SeqCst)
+    }
+
+    pub async fn connectivity(&self) -> Connectivity {
+        if self.is_offline.load(Ordering::SeqCst) {
+            Connectivity {
+                ipv4: false,
+                ipv6: false,
+            }
+        } else {
+            Connectivity::PRESUME_ONLINE
+        }
+    }
+}
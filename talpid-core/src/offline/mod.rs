@@ -4,6 +4,7 @@ use once_cell::sync::Lazy;
 use talpid_routing::RouteManagerHandle;
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+use talpid_types::net::Connectivity;
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -17,10 +18,16 @@ mod imp;
 #[path = "linux.rs"]
 mod imp;
 
+#[cfg(target_os = "linux")]
+mod detector;
+
 #[cfg(target_os = "android")]
 #[path = "android.rs"]
 mod imp;
 
+#[cfg(feature = "mock-tunnel")]
+pub mod mock;
+
 /// Disables offline monitor
 static FORCE_DISABLE_OFFLINE_MONITOR: Lazy<bool> = Lazy::new(|| {
     std::env::var("TALPID_DISABLE_OFFLINE_MONITOR")
@@ -30,17 +37,53 @@ static FORCE_DISABLE_OFFLINE_MONITOR: Lazy<bool> = Lazy::new(|| {
 
 pub use self::imp::Error;
 
-pub struct MonitorHandle(Option<imp::MonitorHandle>);
+pub struct MonitorHandle(Monitor);
+
+enum Monitor {
+    /// The offline monitor is disabled, so the host is presumed to be online.
+    Disabled,
+    System(imp::MonitorHandle),
+    #[cfg(feature = "mock-tunnel")]
+    Mock(mock::MonitorHandle),
+}
 
 impl MonitorHandle {
     pub async fn host_is_offline(&self) -> bool {
-        match self.0.as_ref() {
-            Some(monitor) => monitor.host_is_offline().await,
-            None => false,
+        match &self.0 {
+            Monitor::Disabled => false,
+            Monitor::System(monitor) => monitor.host_is_offline().await,
+            #[cfg(feature = "mock-tunnel")]
+            Monitor::Mock(monitor) => monitor.host_is_offline().await,
+        }
+    }
+
+    /// Returns whether changes are only sent once they have lasted for a while, in which case they
+    /// should not be debounced again.
+    pub fn debounces_changes(&self) -> bool {
+        match &self.0 {
+            Monitor::System(_) => cfg!(target_os = "linux"),
+            _ => false,
+        }
+    }
+
+    /// Returns the IP versions that have a default route outside the tunnel.
+    pub async fn connectivity(&self) -> Connectivity {
+        match &self.0 {
+            Monitor::Disabled => Connectivity::PRESUME_ONLINE,
+            Monitor::System(monitor) => monitor.connectivity().await,
+            #[cfg(feature = "mock-tunnel")]
+            Monitor::Mock(monitor) => monitor.connectivity().await,
         }
     }
 }
 
+#[cfg(feature = "mock-tunnel")]
+impl From<mock::MonitorHandle> for MonitorHandle {
+    fn from(monitor: mock::MonitorHandle) -> Self {
+        MonitorHandle(Monitor::Mock(monitor))
+    }
+}
+
 pub async fn spawn_monitor(
     sender: UnboundedSender<bool>,
     #[cfg(not(target_os = "android"))] route_manager: RouteManagerHandle,
@@ -48,7 +91,7 @@ pub async fn spawn_monitor(
     #[cfg(target_os = "android")] android_context: AndroidContext,
 ) -> Result<MonitorHandle, Error> {
     let monitor = if !*FORCE_DISABLE_OFFLINE_MONITOR {
-        Some(
+        Monitor::System(
             imp::spawn_monitor(
                 sender,
                 #[cfg(not(target_os = "android"))]
@@ -61,7 +104,7 @@ pub async fn spawn_monitor(
             .await?,
         )
     } else {
-        None
+        Monitor::Disabled
     };
 
     Ok(MonitorHandle(monitor))
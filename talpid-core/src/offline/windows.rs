@@ -8,7 +8,7 @@ use std::{
     sync::{Arc, Weak},
     time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_types::{net::Connectivity, ErrorExt};
 use talpid_windows_net::AddressFamily;
 
 #[derive(err_derive::Error, Debug)]
@@ -143,6 +143,15 @@ impl BroadcastListener {
         let state = self.system_state.lock();
         state.is_offline_currently()
     }
+
+    #[allow(clippy::unused_async)]
+    pub async fn connectivity(&self) -> Connectivity {
+        let state = self.system_state.lock();
+        Connectivity {
+            ipv4: state.v4_connectivity && !state.suspended,
+            ipv6: state.v6_connectivity && !state.suspended,
+        }
+    }
 }
 
 #[derive(Debug)]
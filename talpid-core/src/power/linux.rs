@@ -0,0 +1,20 @@
+use super::PowerEvent;
+use futures::channel::mpsc::UnboundedSender;
+use talpid_types::ErrorExt;
+
+pub fn spawn_monitor(tx: UnboundedSender<PowerEvent>) {
+    let result = talpid_dbus::logind::listen_for_sleep(move |start| {
+        let event = if start {
+            PowerEvent::Suspend
+        } else {
+            PowerEvent::Resume
+        };
+        let _ = tx.unbounded_send(event);
+    });
+    if let Err(error) = result {
+        log::warn!(
+            "{}",
+            error.display_chain_with_msg("Unable to listen for sleep notifications from logind")
+        );
+    }
+}
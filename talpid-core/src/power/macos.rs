@@ -0,0 +1,101 @@
+use super::PowerEvent;
+use futures::channel::mpsc::UnboundedSender;
+use std::{
+    ffi::c_void,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+};
+use system_configuration::core_foundation::{
+    base::TCFType,
+    runloop::{kCFRunLoopCommonModes, CFRunLoop, CFRunLoopSource, CFRunLoopSourceRef},
+};
+
+type IoConnect = u32;
+type IoObject = u32;
+type IoNotificationPortRef = *mut c_void;
+type IoServiceInterestCallback =
+    extern "C" fn(refcon: *mut c_void, service: u32, message_type: u32, argument: *mut c_void);
+
+const K_IO_MESSAGE_CAN_SYSTEM_SLEEP: u32 = 0xe0000270;
+const K_IO_MESSAGE_SYSTEM_WILL_SLEEP: u32 = 0xe0000280;
+const K_IO_MESSAGE_SYSTEM_HAS_POWERED_ON: u32 = 0xe0000300;
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IORegisterForSystemPower(
+        refcon: *mut c_void,
+        port: *mut IoNotificationPortRef,
+        callback: IoServiceInterestCallback,
+        notifier: *mut IoObject,
+    ) -> IoConnect;
+    fn IONotificationPortGetRunLoopSource(port: IoNotificationPortRef) -> CFRunLoopSourceRef;
+    fn IOAllowPowerChange(kernel_port: IoConnect, notification_id: isize) -> i32;
+}
+
+struct Context {
+    root_port: AtomicU32,
+    tx: UnboundedSender<PowerEvent>,
+}
+
+extern "C" fn power_callback(
+    refcon: *mut c_void,
+    _service: u32,
+    message_type: u32,
+    argument: *mut c_void,
+) {
+    // SAFETY: `refcon` is the context that is leaked in `spawn_monitor`
+    let context = unsafe { &*(refcon as *const Context) };
+    match message_type {
+        K_IO_MESSAGE_CAN_SYSTEM_SLEEP | K_IO_MESSAGE_SYSTEM_WILL_SLEEP => {
+            if message_type == K_IO_MESSAGE_SYSTEM_WILL_SLEEP {
+                let _ = context.tx.unbounded_send(PowerEvent::Suspend);
+            }
+            // Sleep is delayed for 30 seconds unless the change is acknowledged
+            unsafe {
+                IOAllowPowerChange(context.root_port.load(Ordering::Acquire), argument as isize)
+            };
+        }
+        K_IO_MESSAGE_SYSTEM_HAS_POWERED_ON => {
+            let _ = context.tx.unbounded_send(PowerEvent::Resume);
+        }
+        _ => (),
+    }
+}
+
+pub fn spawn_monitor(tx: UnboundedSender<PowerEvent>) {
+    thread::spawn(move || {
+        // The context must outlive the run loop, which runs for as long as the process does
+        let context = Box::into_raw(Box::new(Context {
+            root_port: AtomicU32::new(0),
+            tx,
+        }));
+        let mut notification_port: IoNotificationPortRef = std::ptr::null_mut();
+        let mut notifier: IoObject = 0;
+        let root_port = unsafe {
+            IORegisterForSystemPower(
+                context as *mut c_void,
+                &mut notification_port,
+                power_callback,
+                &mut notifier,
+            )
+        };
+        if root_port == 0 {
+            log::warn!("Unable to register for system power notifications");
+            // SAFETY: The callback was not registered, so nothing else refers to the context
+            drop(unsafe { Box::from_raw(context) });
+            return;
+        }
+        unsafe { &*context }
+            .root_port
+            .store(root_port, Ordering::Release);
+
+        let run_loop_source = unsafe {
+            CFRunLoopSource::wrap_under_get_rule(IONotificationPortGetRunLoopSource(
+                notification_port,
+            ))
+        };
+        CFRunLoop::get_current().add_source(&run_loop_source, unsafe { kCFRunLoopCommonModes });
+        log::trace!("Entering power notification CFRunLoop");
+        CFRunLoop::run_current();
+    });
+}
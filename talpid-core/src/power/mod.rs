@@ -0,0 +1,74 @@
+//! Notifications about the host suspending and resuming. A tunnel may die while the host is
+//! asleep, and the OS may revert the routes and DNS settings, without the tunnel state machine
+//! noticing for a long time after waking up.
+
+use futures::{channel::mpsc, future, Stream, StreamExt};
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(target_os = "windows")]
+#[path = "windows.rs"]
+mod imp;
+
+/// A change in the power state of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEvent {
+    /// The host is about to suspend or hibernate.
+    Suspend,
+    /// The host has woken up.
+    Resume,
+}
+
+/// Starts listening for power events, and returns a stream that yields every time the host has
+/// resumed. Failing to listen is logged, in which case the stream never yields.
+pub fn spawn_monitor() -> impl Stream<Item = ()> {
+    let (tx, rx) = mpsc::unbounded();
+    imp::spawn_monitor(tx);
+    resumes(rx)
+}
+
+/// Returns the resumes in `events`. Resumes that do not follow a suspend are ignored, since some
+/// platforms report several events for every time the host wakes up.
+pub fn resumes(events: impl Stream<Item = PowerEvent>) -> impl Stream<Item = ()> {
+    let mut suspended = false;
+    events.filter_map(move |event| {
+        let resumed = match event {
+            PowerEvent::Suspend => {
+                suspended = true;
+                false
+            }
+            PowerEvent::Resume => std::mem::take(&mut suspended),
+        };
+        future::ready(resumed.then_some(()))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use PowerEvent::*;
+
+    async fn count_resumes(events: Vec<PowerEvent>) -> usize {
+        resumes(futures::stream::iter(events)).count().await
+    }
+
+    #[tokio::test]
+    async fn test_resumes() {
+        assert_eq!(count_resumes(vec![Suspend, Resume]).await, 1);
+        assert_eq!(
+            count_resumes(vec![Suspend, Resume, Suspend, Resume]).await,
+            2
+        );
+        // Windows reports both an automatic resume and one caused by the user
+        assert_eq!(count_resumes(vec![Suspend, Resume, Resume]).await, 1);
+        // There is nothing to revalidate if the host never slept
+        assert_eq!(count_resumes(vec![Resume]).await, 0);
+        assert_eq!(count_resumes(vec![Suspend, Suspend, Resume]).await, 1);
+    }
+}
@@ -0,0 +1,20 @@
+use super::PowerEvent;
+use crate::window::{PowerManagementEvent, PowerManagementListener};
+use futures::channel::mpsc::UnboundedSender;
+
+pub fn spawn_monitor(tx: UnboundedSender<PowerEvent>) {
+    let mut listener = PowerManagementListener::new();
+    tokio::spawn(async move {
+        while let Some(event) = listener.next().await {
+            let event = match event {
+                PowerManagementEvent::Suspend => PowerEvent::Suspend,
+                PowerManagementEvent::ResumeAutomatic | PowerManagementEvent::ResumeSuspend => {
+                    PowerEvent::Resume
+                }
+            };
+            if tx.unbounded_send(event).is_err() {
+                break;
+            }
+        }
+    });
+}
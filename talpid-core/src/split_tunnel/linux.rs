@@ -1,9 +1,20 @@
 use std::{
+    collections::HashSet,
     env, fs,
     io::{self, BufRead, BufReader, Write},
-    path::PathBuf,
+    mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+};
+use talpid_types::{
+    cgroup::{
+        find_net_cls_mount, invocation_cgroups, remove_empty_invocation_cgroups,
+        SPLIT_TUNNEL_CGROUP_NAME,
+    },
+    net::TransportProtocol,
+    split_tunnel::{ExcludedDestination, ExcludedProcess, ExclusionReason, SplitTunnelMode},
+    ErrorExt,
 };
-use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
 
 const DEFAULT_NET_CLS_DIR: &str = "/sys/fs/cgroup/net_cls";
 const NET_CLS_DIR_OVERRIDE_ENV_VAR: &str = "TALPID_NET_CLS_MOUNT_DIR";
@@ -15,6 +26,45 @@ pub const NET_CLS_CLASSID: u32 = 0x4d9f41;
 /// This should be an arbitrary but unique integer.
 pub const MARK: i32 = 0xf41;
 
+/// Users and groups whose processes are excluded from the tunnel. Their traffic is marked the
+/// same way as the traffic of the processes in the cgroup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExcludedOwners {
+    /// User IDs whose processes are excluded.
+    pub users: Vec<u32>,
+    /// Group IDs whose processes are excluded.
+    pub groups: Vec<u32>,
+}
+
+/// Split tunnel settings that only affect the firewall rules, other than the excluded owners.
+/// The rules are replaced as soon as any of them change, in every tunnel state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Whether the selected processes are excluded from the tunnel or are the only ones using it.
+    pub mode: SplitTunnelMode,
+    /// Destinations that are excluded from the tunnel regardless of the process.
+    pub destinations: Vec<ExcludedDestination>,
+    /// Interfaces whose forwarded traffic is excluded from the tunnel.
+    pub interfaces: Vec<String>,
+    /// Whether excluded processes may reach the LAN when `allow_lan` is disabled.
+    pub excluded_apps_allow_lan: bool,
+    /// Local ports whose traffic is excluded from the tunnel, so that services listening on them
+    /// are reached, and reply, outside the tunnel.
+    pub local_ports: Vec<(TransportProtocol, u16)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: SplitTunnelMode::Exclude,
+            destinations: vec![],
+            interfaces: vec![],
+            excluded_apps_allow_lan: true,
+            local_ports: vec![],
+        }
+    }
+}
+
 /// Errors related to split tunneling.
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -46,6 +96,10 @@ pub enum Error {
     /// Unable to read /proc/mounts
     #[error(display = "Failed to read /proc/mounts")]
     ListMounts(#[error(source)] io::Error),
+
+    /// Unable to list the running processes.
+    #[error(display = "Failed to list processes in /proc")]
+    ListProcesses(#[error(source)] io::Error),
 }
 
 /// Manages PIDs in the Linux Cgroup excluded from the VPN tunnel.
@@ -133,14 +187,32 @@ impl PidManager {
             .map_err(Error::RemoveCGroupPid)
     }
 
-    /// Return a list of all PIDs currently in the Cgroup excluded from the tunnel.
+    /// Return a list of all PIDs currently in the Cgroup excluded from the tunnel, including the
+    /// cgroups that `mullvad-exclude` creates in it.
     pub fn list(&self) -> Result<Vec<i32>, Error> {
-        let exclusions_path = self
-            .net_cls_path
-            .join(SPLIT_TUNNEL_CGROUP_NAME)
-            .join("cgroup.procs");
+        let exclusions_dir = self.net_cls_path.join(SPLIT_TUNNEL_CGROUP_NAME);
+
+        if let Err(error) = remove_empty_invocation_cgroups(&exclusions_dir) {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove unused split tunnel cgroups")
+            );
+        }
 
-        let file = fs::File::open(exclusions_path).map_err(Error::ListCGroupPids)?;
+        let mut pids = Self::list_cgroup(&exclusions_dir)?;
+        for cgroup in invocation_cgroups(&exclusions_dir).map_err(Error::ListCGroupPids)? {
+            match Self::list_cgroup(&cgroup) {
+                Ok(cgroup_pids) => pids.extend(cgroup_pids),
+                // The cgroup may have been removed since it was found
+                Err(Error::ListCGroupPids(error)) if error.kind() == io::ErrorKind::NotFound => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(pids)
+    }
+
+    fn list_cgroup(cgroup: &Path) -> Result<Vec<i32>, Error> {
+        let file = fs::File::open(cgroup.join("cgroup.procs")).map_err(Error::ListCGroupPids)?;
 
         let result: Result<Vec<i32>, io::Error> = BufReader::new(file)
             .lines()
@@ -169,6 +241,15 @@ impl PidManager {
         Ok(())
     }
 
+    /// Returns the processes that are excluded from the tunnel, either because they are in the
+    /// cgroup or because they are run by one of the excluded `owners`.
+    pub fn excluded_processes(
+        &self,
+        owners: &ExcludedOwners,
+    ) -> Result<Vec<ExcludedProcess>, Error> {
+        find_excluded_processes(&self.list()?, owners)
+    }
+
     fn open_parent_cgroup_handle(&self) -> io::Result<fs::File> {
         fs::OpenOptions::new()
             .write(true)
@@ -176,3 +257,273 @@ impl PidManager {
             .open(self.net_cls_path.join("cgroup.procs"))
     }
 }
+
+/// Multicast group of the process events connector.
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+/// Operation that subscribes to the process events.
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+
+/// Message that subscribes to the process events: a `nlmsghdr`, followed by a `cn_msg` and the
+/// operation.
+#[repr(C)]
+struct ProcListenMessage {
+    header: libc::nlmsghdr,
+    idx: u32,
+    val: u32,
+    seq: u32,
+    ack: u32,
+    len: u16,
+    flags: u16,
+    op: u32,
+}
+
+/// Receives events from the kernel when processes are created, exit or change their user or group
+/// IDs, which may change the processes that are excluded. Only the occurrence of events is
+/// reported, since the excluded processes are listed again anyway.
+pub struct ProcessEvents {
+    socket: OwnedFd,
+}
+
+impl ProcessEvents {
+    /// Subscribes to the process events. This requires `CAP_NET_ADMIN`.
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_CONNECTOR,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = CN_IDX_PROC;
+        if unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &address as *const _ as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let message = ProcListenMessage {
+            header: libc::nlmsghdr {
+                nlmsg_len: mem::size_of::<ProcListenMessage>() as u32,
+                nlmsg_type: libc::NLMSG_DONE as u16,
+                nlmsg_flags: 0,
+                nlmsg_seq: 0,
+                nlmsg_pid: std::process::id(),
+            },
+            idx: CN_IDX_PROC,
+            val: CN_VAL_PROC,
+            seq: 0,
+            ack: 0,
+            len: mem::size_of::<u32>() as u16,
+            flags: 0,
+            op: PROC_CN_MCAST_LISTEN,
+        };
+        if unsafe {
+            libc::send(
+                socket.as_raw_fd(),
+                &message as *const _ as *const libc::c_void,
+                mem::size_of::<ProcListenMessage>(),
+                0,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(ProcessEvents { socket })
+    }
+
+    /// Blocks until at least one event has been received.
+    pub fn wait(&self) -> io::Result<()> {
+        loop {
+            match self.receive(0) {
+                // Events were dropped because they were not received fast enough
+                Err(error) if error.raw_os_error() == Some(libc::ENOBUFS) => return Ok(()),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Discards the events that have already been received.
+    pub fn drain(&self) -> io::Result<()> {
+        loop {
+            match self.receive(libc::MSG_DONTWAIT) {
+                Ok(()) => continue,
+                Err(error) if error.raw_os_error() == Some(libc::ENOBUFS) => continue,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn receive(&self, flags: libc::c_int) -> io::Result<()> {
+        let mut buffer = [0u8; 1024];
+        let result = unsafe {
+            libc::recv(
+                self.socket.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                flags,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Returns the processes in `cgroup_pids`, followed by the other processes that have the effective
+/// user or group ID of one of `owners`. Processes that exit while they are being listed are
+/// skipped.
+fn find_excluded_processes(
+    cgroup_pids: &[i32],
+    owners: &ExcludedOwners,
+) -> Result<Vec<ExcludedProcess>, Error> {
+    let mut processes: Vec<ExcludedProcess> = cgroup_pids
+        .iter()
+        .map(|pid| ExcludedProcess {
+            pid: *pid as u32,
+            image: process_image(*pid as u32),
+            inherited: false,
+            reason: ExclusionReason::CGroup,
+        })
+        .collect();
+    if owners.users.is_empty() && owners.groups.is_empty() {
+        return Ok(processes);
+    }
+
+    let cgroup_pids: HashSet<u32> = cgroup_pids.iter().map(|pid| *pid as u32).collect();
+    for entry in fs::read_dir("/proc").map_err(Error::ListProcesses)? {
+        let entry = entry.map_err(Error::ListProcesses)?;
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if cgroup_pids.contains(&pid) {
+            continue;
+        }
+        let Ok(status) = fs::read_to_string(entry.path().join("status")) else {
+            continue;
+        };
+        let Some((uid, gid)) = parse_effective_ids(&status) else {
+            continue;
+        };
+        let reason = if owners.users.contains(&uid) {
+            ExclusionReason::User(uid)
+        } else if owners.groups.contains(&gid) {
+            ExclusionReason::Group(gid)
+        } else {
+            continue;
+        };
+        processes.push(ExcludedProcess {
+            pid,
+            image: process_image(pid),
+            inherited: false,
+            reason,
+        });
+    }
+    Ok(processes)
+}
+
+/// Returns the path of the image of the process `pid`, or an empty path if it cannot be read.
+fn process_image(pid: u32) -> PathBuf {
+    fs::read_link(format!("/proc/{pid}/exe")).unwrap_or_default()
+}
+
+/// Returns the effective user and group IDs in the contents of `/proc/<pid>/status`. These decide
+/// the owner of the sockets that the process creates.
+fn parse_effective_ids(status: &str) -> Option<(u32, u32)> {
+    // The lines contain the real, effective, saved set and filesystem IDs, such as
+    // `Uid:\t1000\t1000\t1000\t1000`
+    let effective_id = |key: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(key))?
+            .split_whitespace()
+            .nth(1)?
+            .parse()
+            .ok()
+    };
+    Some((effective_id("Uid:")?, effective_id("Gid:")?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_parse_effective_ids() {
+        let status =
+            "Name:\tsleep\nUmask:\t0022\nUid:\t1000\t1001\t1000\t1000\nGid:\t100\t101\t100\t100\n";
+        assert_eq!(parse_effective_ids(status), Some((1001, 101)));
+        assert_eq!(parse_effective_ids("Name:\tsleep\n"), None);
+    }
+
+    #[test]
+    fn test_find_excluded_processes() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = child.id();
+        let uid = unsafe { libc::geteuid() };
+        let gid = unsafe { libc::getegid() };
+
+        let owners = ExcludedOwners {
+            users: vec![],
+            groups: vec![gid],
+        };
+        let processes = find_excluded_processes(&[], &owners).unwrap();
+        let by_group = processes.iter().find(|process| process.pid == pid);
+
+        let owners = ExcludedOwners {
+            users: vec![uid],
+            groups: vec![],
+        };
+        // Processes in the cgroup are only listed once
+        let processes = find_excluded_processes(&[pid as i32], &owners).unwrap();
+        let matches: Vec<_> = processes
+            .iter()
+            .filter(|process| process.pid == pid)
+            .collect();
+
+        let _ = child.kill();
+        let _ = child.wait();
+
+        assert_eq!(
+            by_group.map(|process| process.reason.clone()),
+            Some(ExclusionReason::Group(gid))
+        );
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].reason, ExclusionReason::CGroup);
+        assert!(matches[0].image.ends_with("sleep"));
+    }
+
+    #[test]
+    #[ignore = "requires CAP_NET_ADMIN"]
+    fn test_process_events() {
+        let events = ProcessEvents::new().unwrap();
+        events.drain().unwrap();
+
+        let mut child = Command::new("true").spawn().unwrap();
+        let _ = child.wait();
+
+        events.wait().unwrap();
+        events.drain().unwrap();
+    }
+}
@@ -341,24 +341,13 @@ impl DeviceHandle {
             .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
     }
 
-    pub fn set_config<T: AsRef<OsStr>>(&self, apps: &[T]) -> io::Result<()> {
-        let mut device_paths = Vec::with_capacity(apps.len());
-        for app in apps {
-            match get_device_path(app.as_ref()) {
-                Err(error) if error.kind() == io::ErrorKind::NotFound => {
-                    log::debug!(
-                        "{}\nPath: {}",
-                        error.display_chain_with_msg("Ignoring path on unmounted volume"),
-                        Path::new(app.as_ref()).display()
-                    );
-                }
-                Err(error) => return Err(error),
-                Ok(path) => device_paths.push(path),
-            }
-        }
+    /// Sets the paths to exclude, and returns the status of each path.
+    pub fn set_config<T: AsRef<OsStr>>(&self, apps: &[T]) -> io::Result<Vec<PathStatus>> {
+        let (device_paths, statuses) = resolve_device_paths(apps, |app| get_device_path(app))?;
 
         if device_paths.is_empty() {
-            return self.clear_config();
+            self.clear_config()?;
+            return Ok(statuses);
         }
 
         log::debug!("Excluded device paths:");
@@ -375,7 +364,7 @@ impl DeviceHandle {
             0,
         )?;
 
-        Ok(())
+        Ok(statuses)
     }
 
     pub fn clear_config(&self) -> io::Result<()> {
@@ -395,6 +384,44 @@ impl AsRawHandle for DeviceHandle {
     }
 }
 
+/// Outcome of excluding a single path.
+#[derive(Debug)]
+pub enum PathStatus {
+    /// The path was passed to the driver.
+    Applied,
+    /// The path is on a volume that is not mounted. It is resolved again when volumes change.
+    Unmounted,
+}
+
+/// Returns the device paths of `apps` that are on mounted volumes, and the status of each of
+/// `apps`. Fails if any other device path cannot be resolved.
+pub fn resolve_device_paths<T: AsRef<OsStr>>(
+    apps: &[T],
+    get_device_path: impl Fn(&OsStr) -> io::Result<OsString>,
+) -> io::Result<(Vec<OsString>, Vec<PathStatus>)> {
+    let mut device_paths = Vec::with_capacity(apps.len());
+    let mut statuses = Vec::with_capacity(apps.len());
+    for app in apps {
+        let status = match get_device_path(app.as_ref()) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                log::debug!(
+                    "{}\nPath: {}",
+                    error.display_chain_with_msg("Ignoring path on unmounted volume"),
+                    Path::new(app.as_ref()).display()
+                );
+                PathStatus::Unmounted
+            }
+            Err(error) => return Err(error),
+            Ok(path) => {
+                device_paths.push(path);
+                PathStatus::Applied
+            }
+        };
+        statuses.push(status);
+    }
+    Ok((device_paths, statuses))
+}
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 struct SplitTunnelAddresses {
@@ -1,5 +1,7 @@
 mod driver;
 mod path_monitor;
+mod process_monitor;
+mod publisher;
 mod service;
 mod volume_monitor;
 mod windows;
@@ -7,7 +9,7 @@ mod windows;
 use crate::{tunnel::TunnelMetadata, tunnel_state_machine::TunnelCommand};
 use futures::channel::{mpsc, oneshot};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::TryFrom,
     ffi::{OsStr, OsString},
     io,
@@ -20,9 +22,18 @@ use std::{
     time::Duration,
 };
 use talpid_routing::{get_best_default_route, CallbackHandle, EventType, RouteManagerHandle};
-use talpid_types::{split_tunnel::ExcludedProcess, tunnel::ErrorStateCause, ErrorExt};
+use talpid_types::{
+    split_tunnel::{
+        AppExclusionState, AppExclusionStatus, ExcludedApp, ExcludedProcess, ExclusionReason,
+    },
+    tunnel::ErrorStateCause,
+    ErrorExt,
+};
 use talpid_windows_net::{get_ip_address_for_interface, AddressFamily};
-use windows_sys::Win32::Foundation::ERROR_OPERATION_ABORTED;
+use windows_sys::Win32::{
+    Foundation::{ERROR_INVALID_PARAMETER, ERROR_OPERATION_ABORTED},
+    System::Diagnostics::ToolHelp::TH32CS_SNAPPROCESS,
+};
 
 const DRIVER_EVENT_BUFFER_SIZE: usize = 2048;
 const RESERVED_IP_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 123);
@@ -100,6 +111,221 @@ pub enum Error {
     CannotResetEngaged,
 }
 
+/// Applications to exclude from the tunnel.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExcludedApps {
+    /// Paths to executables.
+    pub paths: Vec<OsString>,
+    /// Publishers whose executables are excluded, in the form `<signer>` or `<signer>/<product>`.
+    pub publishers: Vec<String>,
+}
+
+/// Excluded applications, with publishers resolved to the images that they have signed.
+#[derive(Default)]
+struct ResolvedApps {
+    paths: Vec<OsString>,
+    publisher_names: Vec<String>,
+    publishers: Vec<publisher::Publisher>,
+    /// Device paths of images that match a publisher, and the indices of the publishers that
+    /// they match.
+    matching_images: Vec<(OsString, Vec<usize>)>,
+    /// Device paths of images that have been checked and do not match any publisher.
+    unmatched_images: HashSet<OsString>,
+}
+
+impl ResolvedApps {
+    fn new(apps: &ExcludedApps) -> Self {
+        ResolvedApps {
+            paths: apps.paths.clone(),
+            publisher_names: apps.publishers.clone(),
+            publishers: apps
+                .publishers
+                .iter()
+                .map(|publisher| publisher::Publisher::new(publisher))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Checks whether the image at the device path `image` matches any publisher. Returns `true`
+    /// if the image was not previously known to match.
+    fn add_image(
+        &mut self,
+        image: &OsStr,
+        read_metadata: impl FnOnce(&Path) -> publisher::ImageMetadata,
+    ) -> bool {
+        if self.publishers.is_empty()
+            || self.matching_images.iter().any(|(known, _)| known == image)
+            || self.unmatched_images.contains(image)
+        {
+            return false;
+        }
+        let metadata = read_metadata(Path::new(&publisher::win32_path(image)));
+        let publishers: Vec<usize> = self
+            .publishers
+            .iter()
+            .enumerate()
+            .filter(|(_, publisher)| publisher.matches(&metadata))
+            .map(|(index, _)| index)
+            .collect();
+        if !publishers.is_empty() {
+            log::debug!("Excluding {} by publisher", Path::new(image).display());
+            self.matching_images.push((image.to_owned(), publishers));
+            true
+        } else {
+            self.unmatched_images.insert(image.to_owned());
+            false
+        }
+    }
+
+    /// Returns the paths to pass to the driver.
+    fn paths(&self) -> Vec<OsString> {
+        self.paths
+            .iter()
+            .cloned()
+            .chain(
+                self.matching_images
+                    .iter()
+                    .map(|(image, _)| publisher::win32_path(image)),
+            )
+            .collect()
+    }
+
+    /// Returns the reason that a process whose image is at the device path `image` is excluded.
+    fn exclusion_reason(&self, image: &OsStr) -> ExclusionReason {
+        self.matching_images
+            .iter()
+            .find(|(known, _)| known == image)
+            .map(|(_, publishers)| {
+                ExclusionReason::Publisher(self.publisher_names[publishers[0]].clone())
+            })
+            .unwrap_or(ExclusionReason::Path)
+    }
+
+    /// Returns the exclusion state of each application, given the paths that were passed to the
+    /// driver and the status of each path, or the error if the driver did not accept them.
+    fn statuses(
+        &self,
+        paths: &[OsString],
+        result: Result<&[driver::PathStatus], &Error>,
+    ) -> Vec<AppExclusionStatus> {
+        let path_state = |path: &OsStr| {
+            let status = match result {
+                Ok(statuses) => paths
+                    .iter()
+                    .zip(statuses)
+                    .find(|(known, _)| known.as_os_str() == path)
+                    .map(|(_, status)| status),
+                Err(error) => return AppExclusionState::Failed(error.display_chain()),
+            };
+            match status {
+                Some(driver::PathStatus::Applied) => AppExclusionState::Applied,
+                // Paths that have not been passed to the driver yet are excluded by a later update
+                Some(driver::PathStatus::Unmounted) | None => AppExclusionState::Pending,
+            }
+        };
+
+        let path_statuses = self.paths.iter().map(|path| AppExclusionStatus {
+            app: ExcludedApp::Path(PathBuf::from(path)),
+            state: path_state(path),
+        });
+        let publisher_statuses = self
+            .publisher_names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let states: Vec<_> = self
+                    .matching_images
+                    .iter()
+                    .filter(|(_, publishers)| publishers.contains(&index))
+                    .map(|(image, _)| path_state(&publisher::win32_path(image)))
+                    .collect();
+                // A publisher is pending until one of its images has been launched
+                let state = if states.contains(&AppExclusionState::Applied) {
+                    AppExclusionState::Applied
+                } else {
+                    states
+                        .into_iter()
+                        .find(|state| matches!(state, AppExclusionState::Failed(_)))
+                        .unwrap_or(AppExclusionState::Pending)
+                };
+                AppExclusionStatus {
+                    app: ExcludedApp::Publisher(name.clone()),
+                    state,
+                }
+            });
+        path_statuses.chain(publisher_statuses).collect()
+    }
+}
+
+/// Keeps track of the exclusion state of each application, and reports changes to it.
+#[derive(Clone)]
+struct AppStatusTracker {
+    resolved_apps: Arc<Mutex<ResolvedApps>>,
+    statuses: Arc<RwLock<Vec<AppExclusionStatus>>>,
+    status_tx: mpsc::UnboundedSender<Vec<AppExclusionStatus>>,
+}
+
+impl AppStatusTracker {
+    fn update(&self, paths: &[OsString], result: Result<&[driver::PathStatus], &Error>) {
+        let new_statuses = self.resolved_apps.lock().unwrap().statuses(paths, result);
+        let mut statuses = self.statuses.write().unwrap();
+        if *statuses != new_statuses {
+            *statuses = new_statuses.clone();
+            let _ = self.status_tx.unbounded_send(new_statuses);
+        }
+    }
+}
+
+/// Keeps track of the processes that are excluded, and reports changes to them.
+#[derive(Clone)]
+struct ExcludedProcessTracker {
+    processes: Arc<RwLock<HashMap<usize, ExcludedProcess>>>,
+    processes_tx: mpsc::UnboundedSender<Vec<ExcludedProcess>>,
+}
+
+impl ExcludedProcessTracker {
+    /// Applies `update` to the excluded processes, and reports them if they were changed.
+    fn update(&self, update: impl FnOnce(&mut HashMap<usize, ExcludedProcess>) -> bool) {
+        let mut processes = self.processes.write().unwrap();
+        if update(&mut processes) {
+            let _ = self
+                .processes_tx
+                .unbounded_send(processes.values().cloned().collect());
+        }
+    }
+}
+
+/// Returns the device paths of the images of all running processes that can be opened.
+fn running_images() -> io::Result<Vec<OsString>> {
+    let snap = windows::ProcessSnapshot::new(TH32CS_SNAPPROCESS, 0)?;
+    let mut images = vec![];
+    for entry in snap.entries() {
+        let entry = entry?;
+        let process = match windows::open_process(
+            windows::ProcessAccess::QueryLimitedInformation,
+            false,
+            entry.pid,
+        ) {
+            Ok(process) => process,
+            Err(error)
+                if error.kind() == io::ErrorKind::PermissionDenied
+                    || error.kind() == io::ErrorKind::InvalidInput
+                    || error.raw_os_error() == Some(ERROR_INVALID_PARAMETER as i32) =>
+            {
+                continue
+            }
+            Err(error) => return Err(error),
+        };
+        if let Ok(image) = windows::get_process_device_path(process.get_raw()) {
+            if !images.contains(&image) {
+                images.push(image);
+            }
+        }
+    }
+    Ok(images)
+}
+
 /// Manages applications whose traffic to exclude from the tunnel.
 pub struct SplitTunnel {
     runtime: tokio::runtime::Handle,
@@ -107,6 +333,9 @@ pub struct SplitTunnel {
     event_thread: Option<std::thread::JoinHandle<()>>,
     quit_event: Arc<windows::Event>,
     excluded_processes: Arc<RwLock<HashMap<usize, ExcludedProcess>>>,
+    resolved_apps: Arc<Mutex<ResolvedApps>>,
+    _process_monitor: Option<process_monitor::ProcessMonitor>,
+    app_statuses: Arc<RwLock<Vec<AppExclusionStatus>>>,
     _route_change_callback: Option<CallbackHandle>,
     daemon_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
     async_path_update_in_progress: Arc<AtomicBool>,
@@ -135,6 +364,7 @@ struct InterfaceAddresses {
 #[derive(Debug, Clone)]
 pub struct SplitTunnelHandle {
     excluded_processes: Weak<RwLock<HashMap<usize, ExcludedProcess>>>,
+    app_statuses: Weak<RwLock<Vec<AppExclusionStatus>>>,
 }
 
 impl SplitTunnelHandle {
@@ -148,6 +378,13 @@ impl SplitTunnelHandle {
         let processes = processes.read().unwrap();
         Ok(processes.values().cloned().collect())
     }
+
+    /// Return whether the exclusion of each configured application has been applied.
+    pub fn get_app_statuses(&self) -> Result<Vec<AppExclusionStatus>, Error> {
+        let statuses = self.app_statuses.upgrade().ok_or(Error::SplitTunnelDown)?;
+        let statuses = statuses.read().unwrap();
+        Ok(statuses.clone())
+    }
 }
 
 enum EventResult {
@@ -164,15 +401,38 @@ impl SplitTunnel {
         resource_dir: PathBuf,
         daemon_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
         volume_update_rx: mpsc::UnboundedReceiver<()>,
+        app_status_tx: mpsc::UnboundedSender<Vec<AppExclusionStatus>>,
+        excluded_processes_tx: mpsc::UnboundedSender<Vec<ExcludedProcess>>,
         route_manager: RouteManagerHandle,
     ) -> Result<Self, Error> {
         let excluded_processes = Arc::new(RwLock::new(HashMap::new()));
+        let process_tracker = ExcludedProcessTracker {
+            processes: excluded_processes.clone(),
+            processes_tx: excluded_processes_tx,
+        };
+        let resolved_apps = Arc::new(Mutex::new(ResolvedApps::default()));
+        let app_statuses = Arc::new(RwLock::new(vec![]));
 
-        let (request_tx, handle) =
-            Self::spawn_request_thread(resource_dir, volume_update_rx, excluded_processes.clone())?;
+        let (request_tx, handle) = Self::spawn_request_thread(
+            resource_dir,
+            volume_update_rx,
+            process_tracker.clone(),
+            AppStatusTracker {
+                resolved_apps: resolved_apps.clone(),
+                statuses: app_statuses.clone(),
+                status_tx: app_status_tx,
+            },
+        )?;
 
-        let (event_thread, quit_event) =
-            Self::spawn_event_listener(handle, excluded_processes.clone())?;
+        let (event_thread, quit_event) = Self::spawn_event_listener(
+            handle,
+            process_tracker,
+            resolved_apps.clone(),
+            request_tx.clone(),
+        )?;
+
+        let process_monitor =
+            Self::spawn_process_monitor(resolved_apps.clone(), request_tx.clone());
 
         Ok(SplitTunnel {
             runtime,
@@ -183,14 +443,54 @@ impl SplitTunnel {
             daemon_tx,
             async_path_update_in_progress: Arc::new(AtomicBool::new(false)),
             excluded_processes,
+            resolved_apps,
+            _process_monitor: process_monitor,
+            app_statuses,
             route_manager,
         })
     }
 
+    /// Starts monitoring the creation of processes, so that processes are excluded when they are
+    /// launched if their images match a publisher. The process is not excluded until the new
+    /// paths have been passed to the driver.
+    fn spawn_process_monitor(
+        resolved_apps: Arc<Mutex<ResolvedApps>>,
+        request_tx: RequestTx,
+    ) -> Option<process_monitor::ProcessMonitor> {
+        let on_process_created = move |pid| {
+            if resolved_apps.lock().unwrap().publishers.is_empty() {
+                return;
+            }
+            // The process may already have exited
+            let Ok(image) =
+                windows::open_process(windows::ProcessAccess::QueryLimitedInformation, false, pid)
+                    .and_then(|process| windows::get_process_device_path(process.get_raw()))
+            else {
+                return;
+            };
+            Self::refresh_publisher_images(&image, &resolved_apps, &request_tx);
+        };
+        match process_monitor::ProcessMonitor::spawn(on_process_created) {
+            Ok(monitor) => Some(monitor),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to monitor process creation. Apps matching a publisher are only \
+                         excluded when the excluded apps are updated"
+                    )
+                );
+                None
+            }
+        }
+    }
+
     /// Spawns an event loop thread that processes events from the driver service.
     fn spawn_event_listener(
         handle: Arc<driver::DeviceHandle>,
-        excluded_processes: Arc<RwLock<HashMap<usize, ExcludedProcess>>>,
+        process_tracker: ExcludedProcessTracker,
+        resolved_apps: Arc<Mutex<ResolvedApps>>,
+        request_tx: RequestTx,
     ) -> Result<(std::thread::JoinHandle<()>, Arc<windows::Event>), Error> {
         let mut event_overlapped = windows::Overlapped::new(Some(
             windows::Event::new(true, false).map_err(Error::EventThreadError)?,
@@ -226,7 +526,15 @@ impl SplitTunnel {
                     }
                 };
 
-                Self::handle_event(event_id, event_body, &excluded_processes);
+                if let (
+                    driver::EventId::StartSplittingProcess,
+                    driver::EventBody::SplittingEvent { image, .. },
+                ) = (&event_id, &event_body)
+                {
+                    Self::refresh_publisher_images(image, &resolved_apps, &request_tx);
+                }
+
+                Self::handle_event(event_id, event_body, &process_tracker, &resolved_apps);
             }
 
             log::debug!("Stopping split tunnel event thread");
@@ -315,10 +623,35 @@ impl SplitTunnel {
             })
     }
 
+    /// Adds the image of a process to the set of excluded paths if it matches a publisher. This is
+    /// done for processes that are created, and for processes that are being excluded, since
+    /// processes whose images are unknown are excluded because they were launched by an excluded
+    /// process, and must also be excluded when they are launched on their own.
+    fn refresh_publisher_images(
+        image: &OsStr,
+        resolved_apps: &Mutex<ResolvedApps>,
+        request_tx: &RequestTx,
+    ) {
+        let paths = {
+            let mut resolved = resolved_apps.lock().unwrap();
+            if !resolved.add_image(image, publisher::image_metadata) {
+                return;
+            }
+            resolved.paths()
+        };
+        if let Err(error) = Self::send_request_inner(request_tx, Request::SetPaths(paths)) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to exclude image matching a publisher")
+            );
+        }
+    }
+
     fn handle_event(
         event_id: driver::EventId,
         event_body: driver::EventBody,
-        excluded_processes: &Arc<RwLock<HashMap<usize, ExcludedProcess>>>,
+        process_tracker: &ExcludedProcessTracker,
+        resolved_apps: &Mutex<ResolvedApps>,
     ) {
         use driver::{EventBody, EventId};
 
@@ -338,28 +671,35 @@ impl SplitTunnel {
                 reason,
                 image,
             } => {
-                let mut pids = excluded_processes.write().unwrap();
                 match event_id {
                     EventId::StartSplittingProcess => {
-                        if let Some(prev_entry) = pids.get(&process_id) {
-                            log::error!("PID collision: {process_id} is already in the list of excluded processes. New image: {:?}. Current image: {:?}", image, prev_entry);
-                        }
-                        pids.insert(
-                            process_id,
-                            ExcludedProcess {
-                                pid: u32::try_from(process_id)
-                                    .expect("PID should be containable in a DWORD"),
-                                image: Path::new(&image).to_path_buf(),
-                                inherited: reason
-                                    .contains(driver::SplittingChangeReason::BY_INHERITANCE),
-                            },
-                        );
+                        let exclusion_reason =
+                            resolved_apps.lock().unwrap().exclusion_reason(&image);
+                        process_tracker.update(|pids| {
+                            if let Some(prev_entry) = pids.get(&process_id) {
+                                log::error!("PID collision: {process_id} is already in the list of excluded processes. New image: {:?}. Current image: {:?}", image, prev_entry);
+                            }
+                            pids.insert(
+                                process_id,
+                                ExcludedProcess {
+                                    pid: u32::try_from(process_id)
+                                        .expect("PID should be containable in a DWORD"),
+                                    image: Path::new(&image).to_path_buf(),
+                                    inherited: reason
+                                        .contains(driver::SplittingChangeReason::BY_INHERITANCE),
+                                    reason: exclusion_reason,
+                                },
+                            );
+                            true
+                        });
                     }
-                    EventId::StopSplittingProcess => {
-                        if pids.remove(&process_id).is_none() {
+                    EventId::StopSplittingProcess => process_tracker.update(|pids| {
+                        let removed = pids.remove(&process_id).is_some();
+                        if !removed {
                             log::error!("Inconsistent process tree: {process_id} was not found");
                         }
-                    }
+                        removed
+                    }),
                     _ => (),
                 }
 
@@ -388,7 +728,8 @@ impl SplitTunnel {
     fn spawn_request_thread(
         resource_dir: PathBuf,
         volume_update_rx: mpsc::UnboundedReceiver<()>,
-        excluded_processes: Arc<RwLock<HashMap<usize, ExcludedProcess>>>,
+        process_tracker: ExcludedProcessTracker,
+        app_status: AppStatusTracker,
     ) -> Result<(RequestTx, Arc<driver::DeviceHandle>), Error> {
         let (tx, rx): (RequestTx, _) = sync_mpsc::channel();
         let (init_tx, init_rx) = sync_mpsc::channel();
@@ -407,6 +748,8 @@ impl SplitTunnel {
             volume_update_rx,
         );
 
+        let monitor_app_status = app_status.clone();
+
         std::thread::spawn(move || {
             let init_fn = || {
                 service::install_driver_if_required(&resource_dir).map_err(Error::ServiceError)?;
@@ -436,8 +779,12 @@ impl SplitTunnel {
                         let result = if !paths.is_empty() {
                             handle.set_config(&paths).map_err(Error::SetConfiguration)
                         } else {
-                            handle.clear_config().map_err(Error::SetConfiguration)
+                            handle
+                                .clear_config()
+                                .map(|()| vec![])
+                                .map_err(Error::SetConfiguration)
                         };
+                        app_status.update(&paths, result.as_deref());
 
                         if result.is_ok() {
                             if let Err(error) = path_monitor.set_paths(&paths) {
@@ -449,7 +796,7 @@ impl SplitTunnel {
                             *monitored_paths_guard = paths.to_vec();
                         }
 
-                        result
+                        result.map(|_| ())
                     }
                     Request::RegisterIps(mut ips) => {
                         if ips.internet_ipv4.is_none() && ips.internet_ipv6.is_none() {
@@ -480,7 +827,11 @@ impl SplitTunnel {
                         }
 
                         monitored_paths.lock().unwrap().clear();
-                        excluded_processes.write().unwrap().clear();
+                        process_tracker.update(|pids| {
+                            let changed = !pids.is_empty();
+                            pids.clear();
+                            changed
+                        });
 
                         let _ = response_tx.send(Ok(()));
 
@@ -527,11 +878,15 @@ impl SplitTunnel {
                 } else {
                     continue;
                 };
-                if let Err(error) = result {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg("Failed to update excluded paths")
-                    );
+                match result {
+                    // Paths on volumes that were just mounted may now be excluded
+                    Ok(statuses) => monitor_app_status.update(&paths, Ok(&statuses)),
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to update excluded paths")
+                        );
+                    }
                 }
             }
         });
@@ -555,22 +910,38 @@ impl SplitTunnel {
             .map_err(|_| Error::RequestThreadStuck)?
     }
 
-    /// Set a list of applications to exclude from the tunnel.
-    pub fn set_paths_sync<T: AsRef<OsStr>>(&self, paths: &[T]) -> Result<(), Error> {
-        self.send_request(Request::SetPaths(
-            paths
-                .iter()
-                .map(|path| path.as_ref().to_os_string())
-                .collect(),
-        ))
+    /// Replaces the applications to exclude, and returns the paths to pass to the driver.
+    /// Publishers are resolved to the images of running processes that they have signed.
+    fn resolve_apps(resolved_apps: &Mutex<ResolvedApps>, apps: &ExcludedApps) -> Vec<OsString> {
+        let mut resolved = ResolvedApps::new(apps);
+        if !resolved.publishers.is_empty() {
+            match running_images() {
+                Ok(images) => {
+                    for image in images {
+                        resolved.add_image(&image, publisher::image_metadata);
+                    }
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to list images of running processes")
+                    );
+                }
+            }
+        }
+        let paths = resolved.paths();
+        *resolved_apps.lock().unwrap() = resolved;
+        paths
     }
 
-    /// Set a list of applications to exclude from the tunnel.
-    pub fn set_paths<T: AsRef<OsStr>>(
-        &self,
-        paths: &[T],
-        result_tx: oneshot::Sender<Result<(), Error>>,
-    ) {
+    /// Set the applications to exclude from the tunnel.
+    pub fn set_apps_sync(&self, apps: &ExcludedApps) -> Result<(), Error> {
+        let paths = Self::resolve_apps(&self.resolved_apps, apps);
+        self.send_request(Request::SetPaths(paths))
+    }
+
+    /// Set the applications to exclude from the tunnel.
+    pub fn set_apps(&self, apps: ExcludedApps, result_tx: oneshot::Sender<Result<(), Error>>) {
         let busy = self
             .async_path_update_in_progress
             .swap(true, Ordering::SeqCst);
@@ -579,15 +950,12 @@ impl SplitTunnel {
             return;
         }
         let (response_tx, response_rx) = sync_mpsc::channel();
-        let request = Request::SetPaths(
-            paths
-                .iter()
-                .map(|path| path.as_ref().to_os_string())
-                .collect(),
-        );
         let request_tx = self.request_tx.clone();
+        let resolved_apps = self.resolved_apps.clone();
 
+        // Resolving publishers may take a while, so it is done in the blocking task
         let wait_task = move || {
+            let request = Request::SetPaths(Self::resolve_apps(&resolved_apps, &apps));
             request_tx
                 .send((request, response_tx))
                 .map_err(|_| Error::SplitTunnelDown)?;
@@ -674,6 +1042,7 @@ impl SplitTunnel {
     pub fn handle(&self) -> SplitTunnelHandle {
         SplitTunnelHandle {
             excluded_processes: Arc::downgrade(&self.excluded_processes),
+            app_statuses: Arc::downgrade(&self.app_statuses),
         }
     }
 }
@@ -842,3 +1211,120 @@ fn split_tunnel_default_route_change_handler(
         maybe_send(TunnelCommand::Block(ErrorStateCause::SplitTunnelError));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mozilla_image(_path: &Path) -> publisher::ImageMetadata {
+        publisher::ImageMetadata {
+            signer: Some("Mozilla Corporation".to_owned()),
+            product_name: Some("Firefox".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_resolve_publisher_images() {
+        let mut resolved = ResolvedApps::new(&ExcludedApps {
+            paths: vec![OsString::from(r"C:\app.exe")],
+            publishers: vec!["Mozilla Corporation".to_owned()],
+        });
+        let image = OsStr::new(r"\Device\HarddiskVolume1\firefox.exe");
+
+        assert!(resolved.add_image(image, mozilla_image));
+        // Known images are not read again
+        assert!(!resolved.add_image(image, |_| unreachable!()));
+        assert_eq!(
+            resolved.paths(),
+            vec![
+                OsString::from(r"C:\app.exe"),
+                OsString::from(r"\\?\GLOBALROOT\Device\HarddiskVolume1\firefox.exe"),
+            ]
+        );
+
+        let unsigned = OsStr::new(r"\Device\HarddiskVolume1\other.exe");
+        assert!(!resolved.add_image(unsigned, |_| publisher::ImageMetadata::default()));
+        assert!(!resolved.add_image(unsigned, |_| unreachable!()));
+        assert_eq!(resolved.paths().len(), 2);
+
+        assert_eq!(
+            resolved.exclusion_reason(image),
+            ExclusionReason::Publisher("Mozilla Corporation".to_owned())
+        );
+        assert_eq!(resolved.exclusion_reason(unsigned), ExclusionReason::Path);
+    }
+
+    #[test]
+    fn test_app_statuses() {
+        let mut resolved = ResolvedApps::new(&ExcludedApps {
+            paths: vec![
+                OsString::from(r"C:\app.exe"),
+                OsString::from(r"E:\unmounted.exe"),
+            ],
+            publishers: vec![
+                "Mozilla Corporation".to_owned(),
+                "Other Corporation".to_owned(),
+            ],
+        });
+        resolved.add_image(
+            OsStr::new(r"\Device\HarddiskVolume1\firefox.exe"),
+            mozilla_image,
+        );
+        let paths = resolved.paths();
+
+        // Resolves device paths like the driver does
+        let (device_paths, path_statuses) = driver::resolve_device_paths(&paths, |path| {
+            if path == r"E:\unmounted.exe" {
+                Err(io::Error::from(io::ErrorKind::NotFound))
+            } else {
+                Ok(path.to_owned())
+            }
+        })
+        .unwrap();
+        assert_eq!(device_paths.len(), 2);
+
+        let state = |statuses: &[AppExclusionStatus], app: ExcludedApp| {
+            statuses
+                .iter()
+                .find(|status| status.app == app)
+                .map(|status| status.state.clone())
+                .unwrap()
+        };
+        let path = |path: &str| ExcludedApp::Path(PathBuf::from(path));
+        let publisher = |name: &str| ExcludedApp::Publisher(name.to_owned());
+
+        let statuses = resolved.statuses(&paths, Ok(&path_statuses));
+        assert_eq!(statuses.len(), 4);
+        assert_eq!(
+            state(&statuses, path(r"C:\app.exe")),
+            AppExclusionState::Applied
+        );
+        assert_eq!(
+            state(&statuses, path(r"E:\unmounted.exe")),
+            AppExclusionState::Pending
+        );
+        assert_eq!(
+            state(&statuses, publisher("Mozilla Corporation")),
+            AppExclusionState::Applied
+        );
+        // No images of this publisher have been launched
+        assert_eq!(
+            state(&statuses, publisher("Other Corporation")),
+            AppExclusionState::Pending
+        );
+
+        // Any other error rejects the whole configuration, so every app fails
+        let rejected = driver::resolve_device_paths(&paths, |path| {
+            if path == r"C:\app.exe" {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            } else {
+                Ok(path.to_owned())
+            }
+        });
+        let error = Error::SetConfiguration(rejected.unwrap_err());
+        let statuses = resolved.statuses(&paths, Err(&error));
+        assert!(statuses
+            .iter()
+            .all(|status| matches!(status.state, AppExclusionState::Failed(_))));
+    }
+}
@@ -0,0 +1,230 @@
+//! Used to monitor process creation, so that processes whose images are signed by an excluded
+//! publisher can be excluded from the tunnel when they are launched.
+//!
+//! Process creation events are received from the `Microsoft-Windows-Kernel-Process` ETW provider
+//! in a real-time trace session.
+use std::{ffi::OsStr, io, mem, os::windows::ffi::OsStrExt, ptr};
+use windows_sys::{
+    core::GUID,
+    Win32::{
+        Foundation::{ERROR_ALREADY_EXISTS, ERROR_SUCCESS, WIN32_ERROR},
+        System::Diagnostics::Etw::{
+            CloseTrace, ControlTraceW, EnableTraceEx2, OpenTraceW, ProcessTrace, StartTraceW,
+            CONTROLTRACE_HANDLE, EVENT_CONTROL_CODE_ENABLE_PROVIDER, EVENT_RECORD,
+            EVENT_TRACE_CONTROL_STOP, EVENT_TRACE_LOGFILEW, EVENT_TRACE_PROPERTIES,
+            EVENT_TRACE_REAL_TIME_MODE, PROCESSTRACE_HANDLE, PROCESS_TRACE_MODE_EVENT_RECORD,
+            PROCESS_TRACE_MODE_REAL_TIME, TRACE_LEVEL_INFORMATION, WNODE_FLAG_TRACED_GUID,
+        },
+    },
+};
+
+const SESSION_NAME: &str = "Mullvad Split Tunnel Process Monitor";
+
+/// `Microsoft-Windows-Kernel-Process`
+const KERNEL_PROCESS_PROVIDER: GUID = GUID::from_u128(0x22fb2cd6_0e7b_422b_a0c7_2fad1fd0e716);
+/// `WINEVENT_KEYWORD_PROCESS`
+const KEYWORD_PROCESS: u64 = 0x10;
+/// `ProcessStart` event. The first field of its payload is the ID of the new process.
+const PROCESS_START_EVENT_ID: u16 = 1;
+
+/// Returned by `OpenTraceW` on failure.
+const INVALID_PROCESSTRACE_HANDLE: PROCESSTRACE_HANDLE = u64::MAX;
+
+type ProcessCallback = Box<dyn Fn(u32) + Send>;
+
+/// Properties of the trace session, followed by space for the session name.
+#[repr(C)]
+struct SessionProperties {
+    properties: EVENT_TRACE_PROPERTIES,
+    session_name: [u16; SESSION_NAME.len() + 1],
+}
+
+impl SessionProperties {
+    fn new() -> Box<Self> {
+        let mut properties: Box<Self> = Box::new(unsafe { mem::zeroed() });
+        properties.properties.Wnode.BufferSize = mem::size_of::<Self>() as u32;
+        properties.properties.Wnode.Flags = WNODE_FLAG_TRACED_GUID;
+        // Use the QPC clock for timestamps
+        properties.properties.Wnode.ClientContext = 1;
+        properties.properties.LogFileMode = EVENT_TRACE_REAL_TIME_MODE;
+        properties.properties.LoggerNameOffset = mem::size_of::<EVENT_TRACE_PROPERTIES>() as u32;
+        properties
+    }
+}
+
+/// Monitors the creation of processes until dropped.
+pub(super) struct ProcessMonitor {
+    session: CONTROLTRACE_HANDLE,
+    trace: PROCESSTRACE_HANDLE,
+    thread: Option<std::thread::JoinHandle<()>>,
+    callback: *mut ProcessCallback,
+}
+
+// SAFETY: The callback is `Send` and is only called on the trace thread. The handles may be used
+// from any thread.
+unsafe impl Send for ProcessMonitor {}
+
+impl ProcessMonitor {
+    /// Starts monitoring process creation. `on_process_created` is called with the ID of every
+    /// process that is created, on a thread owned by the monitor.
+    pub fn spawn(on_process_created: impl Fn(u32) + Send + 'static) -> io::Result<Self> {
+        let session_name = wide_string(SESSION_NAME);
+
+        let session = match start_session(&session_name) {
+            Err(error) if error.raw_os_error() == Some(ERROR_ALREADY_EXISTS as i32) => {
+                // A session may remain if the daemon was not shut down cleanly
+                log::debug!("Replacing existing process monitor session");
+                stop_session(0, &session_name)?;
+                start_session(&session_name)?
+            }
+            result => result?,
+        };
+
+        let status = unsafe {
+            EnableTraceEx2(
+                session,
+                &KERNEL_PROCESS_PROVIDER,
+                EVENT_CONTROL_CODE_ENABLE_PROVIDER,
+                TRACE_LEVEL_INFORMATION as u8,
+                KEYWORD_PROCESS,
+                0,
+                0,
+                ptr::null(),
+            )
+        };
+        if let Err(error) = win32_result(status) {
+            let _ = stop_session(session, &session_name);
+            return Err(error);
+        }
+
+        let callback: ProcessCallback = Box::new(on_process_created);
+        let callback = Box::into_raw(Box::new(callback));
+
+        let mut session_name = session_name;
+        let mut logfile: EVENT_TRACE_LOGFILEW = unsafe { mem::zeroed() };
+        logfile.LoggerName = session_name.as_mut_ptr();
+        logfile.Anonymous1.ProcessTraceMode =
+            PROCESS_TRACE_MODE_REAL_TIME | PROCESS_TRACE_MODE_EVENT_RECORD;
+        logfile.Anonymous2.EventRecordCallback = Some(event_record_callback);
+        logfile.Context = callback as *mut _;
+
+        let trace = unsafe { OpenTraceW(&mut logfile) };
+        if trace == INVALID_PROCESSTRACE_HANDLE {
+            let error = io::Error::last_os_error();
+            let _ = stop_session(session, &session_name);
+            drop(unsafe { Box::from_raw(callback) });
+            return Err(error);
+        }
+
+        let thread = std::thread::spawn(move || {
+            // Returns when the trace is closed or the session is stopped
+            let status = unsafe { ProcessTrace(&trace, 1, ptr::null(), ptr::null()) };
+            if let Err(error) = win32_result(status) {
+                log::error!("Process monitor trace stopped: {error}");
+            }
+        });
+
+        Ok(ProcessMonitor {
+            session,
+            trace,
+            thread: Some(thread),
+            callback,
+        })
+    }
+}
+
+impl Drop for ProcessMonitor {
+    fn drop(&mut self) {
+        if let Err(error) = stop_session(self.session, &wide_string(SESSION_NAME)) {
+            log::error!("Failed to stop process monitor session: {error}");
+        }
+        unsafe { CloseTrace(self.trace) };
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // SAFETY: The trace has been processed to completion, so the callback is no longer used
+        drop(unsafe { Box::from_raw(self.callback) });
+    }
+}
+
+unsafe extern "system" fn event_record_callback(record: *mut EVENT_RECORD) {
+    let record = &*record;
+    if record.UserContext.is_null() || record.UserData.is_null() {
+        return;
+    }
+    let user_data =
+        std::slice::from_raw_parts(record.UserData as *const u8, record.UserDataLength as usize);
+    if let Some(pid) = process_start_pid(record.EventHeader.EventDescriptor.Id, user_data) {
+        let callback = &*(record.UserContext as *const ProcessCallback);
+        callback(pid);
+    }
+}
+
+/// Returns the ID of the new process if the event is a `ProcessStart` event.
+fn process_start_pid(event_id: u16, user_data: &[u8]) -> Option<u32> {
+    if event_id != PROCESS_START_EVENT_ID {
+        return None;
+    }
+    let pid = user_data.get(..mem::size_of::<u32>())?;
+    Some(u32::from_le_bytes(pid.try_into().unwrap()))
+}
+
+fn start_session(session_name: &[u16]) -> io::Result<CONTROLTRACE_HANDLE> {
+    let mut session = 0;
+    let mut properties = SessionProperties::new();
+    let status = unsafe {
+        StartTraceW(
+            &mut session,
+            session_name.as_ptr(),
+            &mut properties.properties,
+        )
+    };
+    win32_result(status).map(|()| session)
+}
+
+/// Stops the session with the given handle, or the session with the given name if the handle is
+/// zero.
+fn stop_session(session: CONTROLTRACE_HANDLE, session_name: &[u16]) -> io::Result<()> {
+    let mut properties = SessionProperties::new();
+    let status = unsafe {
+        ControlTraceW(
+            session,
+            session_name.as_ptr(),
+            &mut properties.properties,
+            EVENT_TRACE_CONTROL_STOP,
+        )
+    };
+    win32_result(status)
+}
+
+fn win32_result(status: WIN32_ERROR) -> io::Result<()> {
+    if status == ERROR_SUCCESS {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(status as i32))
+    }
+}
+
+fn wide_string(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_process_start_pid() {
+        // `ProcessID`, followed by `CreateTime` and more
+        let mut user_data = 1234u32.to_le_bytes().to_vec();
+        user_data.extend_from_slice(&[0; 8]);
+
+        assert_eq!(
+            process_start_pid(PROCESS_START_EVENT_ID, &user_data),
+            Some(1234)
+        );
+        // `ProcessStop`
+        assert_eq!(process_start_pid(2, &user_data), None);
+        assert_eq!(process_start_pid(PROCESS_START_EVENT_ID, &[0; 2]), None);
+    }
+}
@@ -0,0 +1,342 @@
+//! Matches executables against the publisher that signed them, so that applications keep being
+//! excluded after an update installs them to a new path.
+
+use std::{
+    ffi::{OsStr, OsString},
+    io, iter, mem,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    path::Path,
+    ptr,
+};
+use windows_sys::Win32::{
+    Foundation::{ERROR_SUCCESS, INVALID_HANDLE_VALUE},
+    Security::{
+        Cryptography::{CertGetNameStringW, CERT_NAME_SIMPLE_DISPLAY_TYPE},
+        WinTrust::{
+            WTHelperGetProvSignerFromChain, WTHelperProvDataFromStateData, WinVerifyTrust,
+            WINTRUST_ACTION_GENERIC_VERIFY_V2, WINTRUST_DATA, WINTRUST_DATA_0, WINTRUST_FILE_INFO,
+            WTD_CHOICE_FILE, WTD_REVOKE_NONE, WTD_STATEACTION_CLOSE, WTD_STATEACTION_VERIFY,
+            WTD_UI_NONE,
+        },
+    },
+    Storage::FileSystem::{GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW},
+};
+
+/// Prefix that makes a device path, such as `\Device\HarddiskVolume1\app.exe`, usable with
+/// functions that expect Win32 paths.
+const GLOBALROOT_PREFIX: &str = r"\\?\GLOBALROOT";
+
+/// Metadata of an executable image that is compared to a publisher.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageMetadata {
+    /// Name of the subject of the certificate that the image is signed with, or `None` if the
+    /// image does not have a valid signature.
+    pub signer: Option<String>,
+    /// Product name in the version info of the image.
+    pub product_name: Option<String>,
+}
+
+/// Applications signed by a publisher, optionally limited to a single product. It is parsed from
+/// strings of the form `<signer>` or `<signer>/<product>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publisher {
+    signer: String,
+    product: Option<String>,
+}
+
+impl Publisher {
+    pub fn new(publisher: &str) -> Self {
+        match publisher.split_once('/') {
+            Some((signer, product)) => Publisher {
+                signer: signer.trim().to_owned(),
+                product: Some(product.trim().to_owned()),
+            },
+            None => Publisher {
+                signer: publisher.trim().to_owned(),
+                product: None,
+            },
+        }
+    }
+
+    /// Returns whether an image with the given metadata is published by this publisher. Unsigned
+    /// images never match, since anyone can set their version info.
+    pub fn matches(&self, metadata: &ImageMetadata) -> bool {
+        let Some(signer) = &metadata.signer else {
+            return false;
+        };
+        if !names_match(signer, &self.signer) {
+            return false;
+        }
+        match (&self.product, &metadata.product_name) {
+            (None, _) => true,
+            (Some(product), Some(product_name)) => names_match(product, product_name),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+fn names_match(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+/// Returns a path that can be used to open the image at the device path `device_path`.
+pub fn win32_path(device_path: &OsStr) -> OsString {
+    let mut path = OsString::from(GLOBALROOT_PREFIX);
+    path.push(device_path);
+    path
+}
+
+/// Reads the signer and version info of the image at `path`. Metadata that cannot be read is left
+/// unset.
+pub fn image_metadata(path: &Path) -> ImageMetadata {
+    let signer = get_signer(path);
+    let product_name = get_product_name(path).unwrap_or_else(|error| {
+        log::trace!("Failed to read version info of {}: {error}", path.display());
+        None
+    });
+    ImageMetadata {
+        signer,
+        product_name,
+    }
+}
+
+fn to_wide(s: &OsStr) -> Vec<u16> {
+    s.encode_wide().chain(iter::once(0u16)).collect()
+}
+
+/// Returns the name of the subject of the certificate that the image is signed with, if the
+/// signature is valid. Revocation is not checked, since that may require network access.
+fn get_signer(path: &Path) -> Option<String> {
+    let path = to_wide(path.as_os_str());
+
+    let mut file_info: WINTRUST_FILE_INFO = unsafe { mem::zeroed() };
+    file_info.cbStruct = mem::size_of::<WINTRUST_FILE_INFO>() as u32;
+    file_info.pcwszFilePath = path.as_ptr();
+
+    let mut data: WINTRUST_DATA = unsafe { mem::zeroed() };
+    data.cbStruct = mem::size_of::<WINTRUST_DATA>() as u32;
+    data.dwUIChoice = WTD_UI_NONE;
+    data.fdwRevocationChecks = WTD_REVOKE_NONE;
+    data.dwUnionChoice = WTD_CHOICE_FILE;
+    data.dwStateAction = WTD_STATEACTION_VERIFY;
+    data.Anonymous = WINTRUST_DATA_0 {
+        pFile: &mut file_info,
+    };
+
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+    let status = unsafe {
+        WinVerifyTrust(
+            INVALID_HANDLE_VALUE,
+            &mut action,
+            &mut data as *mut WINTRUST_DATA as *mut _,
+        )
+    };
+
+    let signer = if status == ERROR_SUCCESS as i32 {
+        unsafe { signer_name(&data) }
+    } else {
+        None
+    };
+
+    // Release the state data
+    data.dwStateAction = WTD_STATEACTION_CLOSE;
+    unsafe {
+        WinVerifyTrust(
+            INVALID_HANDLE_VALUE,
+            &mut action,
+            &mut data as *mut WINTRUST_DATA as *mut _,
+        )
+    };
+
+    signer
+}
+
+/// Returns the subject name of the signing certificate in verified trust data.
+///
+/// # Safety
+///
+/// `data` must have been verified by `WinVerifyTrust` and not yet closed.
+unsafe fn signer_name(data: &WINTRUST_DATA) -> Option<String> {
+    let provider_data = WTHelperProvDataFromStateData(data.hWVTStateData);
+    if provider_data.is_null() {
+        return None;
+    }
+    let signer = WTHelperGetProvSignerFromChain(provider_data, 0, 0, 0);
+    if signer.is_null() || (*signer).csCertChain == 0 || (*signer).pasCertChain.is_null() {
+        return None;
+    }
+    let certificate = (*(*signer).pasCertChain).pCert;
+    if certificate.is_null() {
+        return None;
+    }
+
+    let len = CertGetNameStringW(
+        certificate,
+        CERT_NAME_SIMPLE_DISPLAY_TYPE,
+        0,
+        ptr::null(),
+        ptr::null_mut(),
+        0,
+    );
+    if len <= 1 {
+        return None;
+    }
+    let mut name = vec![0u16; len as usize];
+    CertGetNameStringW(
+        certificate,
+        CERT_NAME_SIMPLE_DISPLAY_TYPE,
+        0,
+        ptr::null(),
+        name.as_mut_ptr(),
+        len,
+    );
+    // Remove the null terminator
+    name.truncate(len as usize - 1);
+    Some(OsString::from_wide(&name).to_string_lossy().into_owned())
+}
+
+/// Returns the product name in the version info of the image, using its first translation.
+fn get_product_name(path: &Path) -> io::Result<Option<String>> {
+    let path = to_wide(path.as_os_str());
+
+    let size = unsafe { GetFileVersionInfoSizeW(path.as_ptr(), ptr::null_mut()) };
+    if size == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut info = vec![0u8; size as usize];
+    if unsafe { GetFileVersionInfoW(path.as_ptr(), 0, size, info.as_mut_ptr() as *mut _) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let Some(translation) = (unsafe { query_value(&info, r"\VarFileInfo\Translation", 1) }) else {
+        return Ok(None);
+    };
+    let Some((language, code_page)) = parse_translation(translation) else {
+        return Ok(None);
+    };
+
+    let query = product_name_query(language, code_page);
+    // String values are measured in characters rather than bytes
+    let Some(name) = (unsafe { query_value(&info, &query, mem::size_of::<u16>()) }) else {
+        return Ok(None);
+    };
+    Ok(parse_version_string(name))
+}
+
+/// Returns the value at `query` in a version info block. The length of the value is measured in
+/// units of `unit_size` bytes.
+///
+/// # Safety
+///
+/// `info` must contain a version info block returned by `GetFileVersionInfoW`.
+unsafe fn query_value<'a>(info: &'a [u8], query: &str, unit_size: usize) -> Option<&'a [u8]> {
+    let query = to_wide(OsStr::new(query));
+    let mut value = ptr::null_mut();
+    let mut len = 0u32;
+    if VerQueryValueW(
+        info.as_ptr() as *const _,
+        query.as_ptr(),
+        &mut value,
+        &mut len,
+    ) == 0
+        || value.is_null()
+    {
+        return None;
+    }
+    let start = value as usize - info.as_ptr() as usize;
+    info.get(start..start + len as usize * unit_size)
+}
+
+/// Returns the language and code page of the first translation in a `\VarFileInfo\Translation`
+/// value.
+fn parse_translation(translation: &[u8]) -> Option<(u16, u16)> {
+    let language = u16::from_le_bytes(translation.get(0..2)?.try_into().ok()?);
+    let code_page = u16::from_le_bytes(translation.get(2..4)?.try_into().ok()?);
+    Some((language, code_page))
+}
+
+fn product_name_query(language: u16, code_page: u16) -> String {
+    format!(r"\StringFileInfo\{language:04x}{code_page:04x}\ProductName")
+}
+
+/// Parses a null-terminated UTF-16 string value from a version info block.
+fn parse_version_string(value: &[u8]) -> Option<String> {
+    let wide: Vec<u16> = value
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    let value = String::from_utf16_lossy(&wide);
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn signed(signer: &str, product_name: Option<&str>) -> ImageMetadata {
+        ImageMetadata {
+            signer: Some(signer.to_owned()),
+            product_name: product_name.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_match_signer() {
+        let publisher = Publisher::new("Mozilla Corporation");
+
+        assert!(publisher.matches(&signed("Mozilla Corporation", Some("Firefox"))));
+        assert!(publisher.matches(&signed("mozilla corporation ", None)));
+        assert!(!publisher.matches(&signed("Mozilla Foundation", Some("Firefox"))));
+    }
+
+    #[test]
+    fn test_unsigned_images_never_match() {
+        let publisher = Publisher::new("Mozilla Corporation");
+        let unsigned = ImageMetadata {
+            signer: None,
+            product_name: Some("Mozilla Corporation".to_owned()),
+        };
+        assert!(!publisher.matches(&unsigned));
+    }
+
+    #[test]
+    fn test_match_product() {
+        let publisher = Publisher::new("Mozilla Corporation / Firefox");
+
+        assert!(publisher.matches(&signed("Mozilla Corporation", Some("Firefox"))));
+        assert!(!publisher.matches(&signed("Mozilla Corporation", Some("Thunderbird"))));
+        assert!(!publisher.matches(&signed("Mozilla Corporation", None)));
+        assert!(!publisher.matches(&signed("Other Corporation", Some("Firefox"))));
+    }
+
+    #[test]
+    fn test_parse_version_info() {
+        // English (US), Unicode
+        assert_eq!(
+            parse_translation(&[0x09, 0x04, 0xb0, 0x04]),
+            Some((0x0409, 0x04b0))
+        );
+        assert_eq!(parse_translation(&[0x09, 0x04]), None);
+        assert_eq!(
+            product_name_query(0x0409, 0x04b0),
+            r"\StringFileInfo\040904b0\ProductName"
+        );
+
+        let value: Vec<u8> = "Firefox\0"
+            .encode_utf16()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        assert_eq!(parse_version_string(&value), Some("Firefox".to_owned()));
+        assert_eq!(parse_version_string(&[0, 0]), None);
+    }
+
+    #[test]
+    fn test_win32_path() {
+        assert_eq!(
+            win32_path(OsStr::new(r"\Device\HarddiskVolume1\app.exe")),
+            OsString::from(r"\\?\GLOBALROOT\Device\HarddiskVolume1\app.exe")
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Tunnel that only pretends to connect. No tunnel device is created, and the tunnel comes up or
+//! fails after a delay, as instructed by the [`MockTunnelControl`].
+
+use super::{TunnelArgs, TunnelEvent, TunnelMetadata};
+use crate::tunnel_state_machine::mock::MockTunnelControl;
+use futures::{
+    channel::oneshot,
+    future::{self, Either},
+};
+use std::net::{IpAddr, Ipv4Addr};
+use talpid_types::net::AllowedTunnelTraffic;
+
+/// Name of the tunnel interface that is reported while connecting and connected.
+pub const MOCK_TUNNEL_INTERFACE: &str = "mock0";
+
+/// Gateway of the tunnel, which is also its resolver unless custom DNS servers are used.
+pub const MOCK_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 1);
+
+const MOCK_TUNNEL_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 2);
+
+/// Monitor of a single connection attempt of the mock tunnel.
+pub struct MockTunnelMonitor {
+    exit_rx: oneshot::Receiver<()>,
+}
+
+impl MockTunnelMonitor {
+    /// Starts an attempt to connect. The tunnel goes down when it is closed, or when the control
+    /// drops it.
+    pub fn start<L>(control: &MockTunnelControl, args: TunnelArgs<'_, L>) -> Self
+    where
+        L: (Fn(TunnelEvent) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>)
+            + Send
+            + Clone
+            + Sync
+            + 'static,
+    {
+        let attempt = control.next_attempt();
+        let control = control.clone();
+        let on_event = args.on_event;
+        let mut tunnel_close_rx = args.tunnel_close_rx;
+        // Network changes are accepted without doing anything, like a WireGuard tunnel that
+        // keeps working on the new network
+        let network_changed_rx = args.network_changed_rx;
+        let (exit_tx, exit_rx) = oneshot::channel();
+
+        args.runtime.spawn(async move {
+            let _network_changed_rx = network_changed_rx;
+
+            let delay = Box::pin(tokio::time::sleep(attempt.delay));
+            if let Either::Right(_) = future::select(delay, &mut tunnel_close_rx).await {
+                let _ = exit_tx.send(());
+                return;
+            }
+            if attempt.fail {
+                log::info!("Mock connection attempt failed");
+                let _ = exit_tx.send(());
+                return;
+            }
+
+            let metadata = TunnelMetadata {
+                interface: MOCK_TUNNEL_INTERFACE.to_owned(),
+                ips: vec![IpAddr::V4(MOCK_TUNNEL_ADDRESS)],
+                ipv4_gateway: MOCK_GATEWAY,
+                ipv6_gateway: None,
+            };
+            (on_event)(TunnelEvent::InterfaceUp(
+                metadata.clone(),
+                AllowedTunnelTraffic::All,
+            ))
+            .await;
+            (on_event)(TunnelEvent::Up(metadata)).await;
+
+            if let Either::Right(_) = future::select(tunnel_close_rx, control.tunnel_up()).await {
+                log::info!("Mock tunnel dropped");
+            }
+            (on_event)(TunnelEvent::Down).await;
+            let _ = exit_tx.send(());
+        });
+
+        MockTunnelMonitor { exit_rx }
+    }
+
+    /// Blocks until the tunnel is down, or the attempt has failed.
+    pub fn wait(self) {
+        let _ = futures::executor::block_on(self.exit_rx);
+    }
+}
@@ -6,7 +6,7 @@ use std::path;
 use talpid_openvpn;
 #[cfg(any(target_os = "linux", target_os = "windows"))]
 use talpid_routing::RouteManagerHandle;
-pub use talpid_tunnel::{TunnelArgs, TunnelEvent, TunnelMetadata};
+pub use talpid_tunnel::{TrafficCounters, TunnelArgs, TunnelEvent, TunnelMetadata};
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
 use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
@@ -14,6 +14,9 @@ use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
 /// A module for all WireGuard related tunnel management.
 use talpid_wireguard;
 
+#[cfg(feature = "mock-tunnel")]
+pub mod mock;
+
 const OPENVPN_LOG_FILENAME: &str = "openvpn.log";
 const WIREGUARD_LOG_FILENAME: &str = "wireguard.log";
 
@@ -103,6 +106,25 @@ impl TunnelMonitor {
         }
     }
 
+    /// Creates a new `TunnelMonitor` for a tunnel that only pretends to connect, as instructed by
+    /// `control`.
+    #[cfg(feature = "mock-tunnel")]
+    pub fn start_mock<L>(
+        control: &crate::tunnel_state_machine::mock::MockTunnelControl,
+        args: TunnelArgs<'_, L>,
+    ) -> Self
+    where
+        L: (Fn(TunnelEvent) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>)
+            + Send
+            + Clone
+            + Sync
+            + 'static,
+    {
+        TunnelMonitor {
+            monitor: InternalTunnelMonitor::Mock(mock::MockTunnelMonitor::start(control, args)),
+        }
+    }
+
     /// Returns a path to an executable that communicates with relay servers.
     #[cfg(windows)]
     pub fn get_relay_client(resource_dir: &path::Path, params: &TunnelParameters) -> path::PathBuf {
@@ -247,26 +269,8 @@ impl TunnelMonitor {
         }
     }
 
-    #[cfg(not(target_os = "windows"))]
-    fn prepare_tunnel_log_file(
-        parameters: &TunnelParameters,
-        log_dir: &Option<path::PathBuf>,
-    ) -> Result<Option<path::PathBuf>> {
-        if let Some(ref log_dir) = log_dir {
-            match parameters {
-                TunnelParameters::OpenVpn(_) => {
-                    let tunnel_log = log_dir.join(OPENVPN_LOG_FILENAME);
-                    logging::rotate_log(&tunnel_log)?;
-                    Ok(Some(tunnel_log))
-                }
-                TunnelParameters::Wireguard(_) => Ok(Some(log_dir.join(WIREGUARD_LOG_FILENAME))),
-            }
-        } else {
-            Ok(None)
-        }
-    }
-
-    #[cfg(target_os = "windows")]
+    /// Rotates the log of the tunnel if it has grown too large. The tunnel appends to the log, and
+    /// it cannot be rotated while the tunnel is running.
     fn prepare_tunnel_log_file(
         parameters: &TunnelParameters,
         log_dir: &Option<path::PathBuf>,
@@ -277,7 +281,7 @@ impl TunnelMonitor {
                 TunnelParameters::Wireguard(_) => WIREGUARD_LOG_FILENAME,
             };
             let tunnel_log = log_dir.join(filename);
-            logging::rotate_log(&tunnel_log)?;
+            logging::rotate_log(&tunnel_log, &logging::rotation_config())?;
             Ok(Some(tunnel_log))
         } else {
             Ok(None)
@@ -294,6 +298,8 @@ enum InternalTunnelMonitor {
     #[cfg(not(target_os = "android"))]
     OpenVpn(talpid_openvpn::OpenVpnMonitor),
     Wireguard(talpid_wireguard::WireguardMonitor),
+    #[cfg(feature = "mock-tunnel")]
+    Mock(mock::MockTunnelMonitor),
 }
 
 impl InternalTunnelMonitor {
@@ -302,6 +308,8 @@ impl InternalTunnelMonitor {
             #[cfg(not(target_os = "android"))]
             InternalTunnelMonitor::OpenVpn(tun) => tun.wait()?,
             InternalTunnelMonitor::Wireguard(tun) => tun.wait()?,
+            #[cfg(feature = "mock-tunnel")]
+            InternalTunnelMonitor::Mock(tun) => tun.wait(),
         }
 
         Ok(())
@@ -3,6 +3,8 @@ use super::{
     EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
     TunnelStateTransition, TunnelStateWrapper,
 };
+#[cfg(not(target_os = "android"))]
+use crate::dns::tls_forwarder;
 use crate::{
     firewall::FirewallPolicy,
     tunnel::{TunnelEvent, TunnelMetadata},
@@ -14,7 +16,7 @@ use futures::{
 };
 use std::net::IpAddr;
 use talpid_types::{
-    net::TunnelParameters,
+    net::{DnsSource, EffectiveDns, TunnelEndpoint, TunnelParameters, TunnelType},
     tunnel::{ErrorStateCause, FirewallPolicyError},
     BoxedError, ErrorExt,
 };
@@ -22,7 +24,7 @@ use talpid_types::{
 #[cfg(windows)]
 use crate::tunnel::TunnelMonitor;
 
-use super::connecting_state::TunnelCloseEvent;
+use super::connecting_state::{TunnelCloseEvent, TunnelCloseReason};
 
 pub(crate) type TunnelEventsReceiver =
     Fuse<mpsc::UnboundedReceiver<(TunnelEvent, oneshot::Sender<()>)>>;
@@ -33,6 +35,8 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: TunnelCloseEvent,
     pub tunnel_close_tx: oneshot::Sender<()>,
+    pub network_changed_tx: mpsc::UnboundedSender<()>,
+    pub retry_attempt: u32,
 }
 
 /// The tunnel is up and working.
@@ -42,6 +46,9 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    network_changed_tx: mpsc::UnboundedSender<()>,
+    /// The attempt that established the tunnel.
+    retry_attempt: u32,
 }
 
 impl ConnectedState {
@@ -52,6 +59,8 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             tunnel_close_tx: bootstrap.tunnel_close_tx,
+            network_changed_tx: bootstrap.network_changed_tx,
+            retry_attempt: bootstrap.retry_attempt,
         }
     }
 
@@ -80,26 +89,102 @@ impl ConnectedState {
             })
     }
 
+    /// Updates the resolvers that DNS requests from excluded processes are redirected to, and
+    /// applies them if they changed.
+    #[cfg(target_os = "linux")]
+    fn refresh_original_resolvers(
+        &self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> Result<(), FirewallPolicyError> {
+        if shared_values.dns_monitor.refresh_original_resolvers() {
+            self.set_firewall_policy(shared_values)?;
+        }
+        Ok(())
+    }
+
     #[allow(unused_variables)]
     fn get_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
         #[cfg(not(target_os = "android"))]
-        if let Some(ref servers) = shared_values.dns_servers {
+        if !shared_values.tls_dns_servers.is_empty() {
+            self.tls_stub_address().into_iter().collect()
+        } else if let Some(ref servers) = shared_values.dns_servers {
             servers.clone()
         } else {
-            let mut dns_ips = vec![self.metadata.ipv4_gateway.into()];
-            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-                dns_ips.push(ipv6_gateway.into());
-            };
-            dns_ips
+            gateway_dns_servers(&self.metadata)
         }
         #[cfg(target_os = "android")]
-        {
-            let mut dns_ips = vec![];
-            dns_ips.push(self.metadata.ipv4_gateway.into());
-            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-                dns_ips.push(ipv6_gateway.into());
-            };
-            dns_ips
+        gateway_dns_servers(&self.metadata)
+    }
+
+    /// Returns the tunnel address that the DNS-over-TLS stub listens on.
+    #[cfg(not(target_os = "android"))]
+    fn tls_stub_address(&self) -> Option<IpAddr> {
+        self.metadata
+            .ips
+            .iter()
+            .find(|ip| ip.is_ipv4())
+            .or_else(|| self.metadata.ips.first())
+            .copied()
+    }
+
+    /// Starts relaying DNS queries to the DNS-over-TLS servers, if there are any. A certificate
+    /// that cannot be validated causes the state machine to block, rather than falling back on
+    /// plaintext DNS.
+    #[cfg(not(target_os = "android"))]
+    fn start_tls_forwarder(
+        &self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> Result<(), BoxedError> {
+        // Stop any existing forwarder first, since it may be bound to the same address
+        shared_values.tls_forwarder = None;
+
+        if shared_values.tls_dns_servers.is_empty() {
+            return Ok(());
+        }
+        let stub_address = self.tls_stub_address().ok_or_else(|| {
+            BoxedError::new(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                "The tunnel has no address to bind the DNS-over-TLS stub to",
+            ))
+        })?;
+
+        let command_tx = shared_values.command_tx.clone();
+        let on_certificate_error = Box::new(move |error: &tls_forwarder::Error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Refusing to use DNS-over-TLS server")
+            );
+            if let Some(tx) = command_tx.upgrade() {
+                let _ = tx.unbounded_send(TunnelCommand::Block(ErrorStateCause::SetDnsError));
+            }
+        });
+        let forwarder = tls_forwarder::TlsForwarder::start(
+            &shared_values.runtime,
+            stub_address,
+            &shared_values.tls_dns_servers,
+            on_certificate_error,
+        )
+        .map_err(BoxedError::new)?;
+
+        log::info!(
+            "Relaying DNS queries received on {} to {}",
+            forwarder.local_addr(),
+            shared_values
+                .tls_dns_servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        shared_values.tls_forwarder = Some(forwarder);
+
+        Ok(())
+    }
+
+    fn tunnel_endpoint(&self) -> TunnelEndpoint {
+        TunnelEndpoint {
+            tunnel_interface: Some(self.metadata.interface.clone()),
+            ..self.tunnel_parameters.get_tunnel_endpoint()
         }
     }
 
@@ -110,6 +195,11 @@ impl ConnectedState {
             allow_lan: shared_values.allow_lan,
             #[cfg(not(target_os = "android"))]
             dns_servers: self.get_dns_servers(shared_values),
+            #[cfg(target_os = "linux")]
+            excluded_dns_servers: shared_values.dns_monitor.original_resolvers().servers(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            bypass_routes: shared_values
+                .bypass_routes(&self.tunnel_parameters.get_next_hop_endpoint()),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(
                 &shared_values.resource_dir,
@@ -118,7 +208,11 @@ impl ConnectedState {
         }
     }
 
-    fn set_dns(&self, shared_values: &mut SharedTunnelStateValues) -> Result<(), BoxedError> {
+    /// Configures the system DNS and returns the resulting configuration.
+    fn set_dns(
+        &self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> Result<EffectiveDns, BoxedError> {
         let dns_ips = self.get_dns_servers(shared_values);
 
         #[cfg(any(target_os = "linux", target_os = "windows"))]
@@ -128,18 +222,33 @@ impl ConnectedState {
                 !crate::firewall::is_local_address(ip)
                     || IpAddr::V4(self.metadata.ipv4_gateway) == *ip
                     || self.metadata.ipv6_gateway.map(IpAddr::V6) == Some(*ip)
+                    || self.metadata.ips.contains(ip)
             })
             .collect::<Vec<_>>();
 
+        #[cfg(not(target_os = "android"))]
+        self.start_tls_forwarder(shared_values)?;
+
         shared_values
             .dns_monitor
             .set(&self.metadata.interface, &dns_ips)
             .map_err(BoxedError::new)?;
 
-        Ok(())
+        #[cfg(target_os = "android")]
+        let dns_ips = shared_values.dns_servers.clone().unwrap_or(dns_ips);
+
+        Ok(effective_dns(
+            dns_ips,
+            shared_values.dns_servers.is_some(),
+            shared_values.dns_source,
+        ))
     }
 
     fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
+        #[cfg(not(target_os = "android"))]
+        {
+            shared_values.tls_forwarder = None;
+        }
         if let Err(error) = shared_values.dns_monitor.reset_before_interface_removal() {
             log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
         }
@@ -164,6 +273,18 @@ impl ConnectedState {
         }
     }
 
+    /// Lets a WireGuard tunnel move to the new network without reconnecting. The tunnel is closed
+    /// if it stops working, in which case a new tunnel is started. Returns `false` if the tunnel
+    /// cannot be moved and must be reconnected right away.
+    #[cfg(not(target_os = "android"))]
+    fn move_to_new_network(&self) -> bool {
+        if !matches!(self.tunnel_parameters, TunnelParameters::Wireguard(_)) {
+            return true;
+        }
+        log::debug!("Moving tunnel to new network");
+        self.network_changed_tx.unbounded_send(()).is_ok()
+    }
+
     fn disconnect(
         self,
         shared_values: &mut SharedTunnelStateValues,
@@ -182,6 +303,48 @@ impl ConnectedState {
         ))
     }
 
+    /// Re-applies the configuration that the OS may have reverted while the host was asleep, and
+    /// checks that the relay is still reachable.
+    #[cfg(not(target_os = "android"))]
+    fn revalidate(self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence {
+        let tunnel_type = self.tunnel_parameters.get_tunnel_endpoint().tunnel_type;
+        if resume_action(tunnel_type) == ResumeAction::Reconnect {
+            log::debug!("Reconnecting after resuming from sleep");
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+
+        log::debug!("Revalidating tunnel after resuming from sleep");
+        // The tunnel is closed unless the relay responds, in which case a new tunnel is started
+        let _ = self.network_changed_tx.unbounded_send(());
+
+        #[cfg(target_os = "macos")]
+        if let Ok(handle) = shared_values.route_manager.handle() {
+            let _ = handle.refresh_routes();
+        }
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            return self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+            );
+        }
+        match self.set_dns(shared_values) {
+            Ok(effective_dns) => {
+                let tunnel_endpoint = self.tunnel_endpoint();
+                EventConsequence::NewState((
+                    self.into(),
+                    TunnelStateTransition::Connected(tunnel_endpoint, effective_dns),
+                ))
+            }
+            Err(error) => {
+                log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                )
+            }
+        }
+    }
+
     fn handle_commands(
         self,
         command: Option<TunnelCommand>,
@@ -214,7 +377,9 @@ impl ConnectedState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
-            Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
+            Some(TunnelCommand::Dns(servers, source)) => match shared_values
+                .set_dns_servers(servers, source)
+            {
                 Ok(true) => {
                     if let Err(error) = self.set_firewall_policy(shared_values) {
                         return self.disconnect(
@@ -225,9 +390,12 @@ impl ConnectedState {
 
                     match self.set_dns(shared_values) {
                         #[cfg(target_os = "android")]
-                        Ok(()) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+                        Ok(_) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
                         #[cfg(not(target_os = "android"))]
-                        Ok(()) => SameState(self.into()),
+                        Ok(effective_dns) => {
+                            let _ = shared_values.effective_dns_tx.unbounded_send(effective_dns);
+                            SameState(self.into())
+                        }
                         Err(error) => {
                             log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
                             self.disconnect(
@@ -242,21 +410,109 @@ impl ConnectedState {
                     self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
                 }
             },
+            Some(TunnelCommand::TlsDnsServers(tls_dns_servers)) => {
+                if !shared_values.set_tls_dns_servers(tls_dns_servers) {
+                    return SameState(self.into());
+                }
+                if let Err(error) = self.set_firewall_policy(shared_values) {
+                    return self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    );
+                }
+                match self.set_dns(shared_values) {
+                    Ok(effective_dns) => {
+                        let _ = shared_values.effective_dns_tx.unbounded_send(effective_dns);
+                        SameState(self.into())
+                    }
+                    Err(error) => {
+                        log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                        self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                        )
+                    }
+                }
+            }
+            Some(TunnelCommand::PreserveSearchDomains(preserve_search_domains)) => {
+                if !shared_values.set_preserve_search_domains(preserve_search_domains) {
+                    return SameState(self.into());
+                }
+                match self.set_dns(shared_values) {
+                    Ok(_) => SameState(self.into()),
+                    Err(error) => {
+                        log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                        self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                        )
+                    }
+                }
+            }
+            Some(TunnelCommand::AllowLanDnsWhenBlocked(allow_lan_dns_when_blocked)) => {
+                shared_values.allow_lan_dns_when_blocked = allow_lan_dns_when_blocked;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::KeepCustomDnsWhileDisconnected(keep_custom_dns)) => {
+                shared_values.keep_custom_dns_while_disconnected = keep_custom_dns;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::ExcludedOwners(owners)) => {
+                shared_values.set_excluded_owners(owners);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SplitTunnelConfig(config)) => {
+                shared_values.set_split_tunnel_config(config);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::BypassRoutes(bypass_routes)) => {
+                if shared_values.set_bypass_routes(bypass_routes)
+                    && cfg!(any(target_os = "linux", target_os = "macos"))
+                {
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                } else {
+                    SameState(self.into())
+                }
+            }
+            Some(TunnelCommand::RetryBackoff(retry_backoff)) => {
+                shared_values.retry_scheduler.set_backoff(retry_backoff);
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
             Some(TunnelCommand::IsOffline(is_offline)) => {
-                shared_values.is_offline = is_offline;
+                shared_values.set_is_offline(is_offline);
                 if is_offline {
-                    self.disconnect(
+                    return self.disconnect(
                         shared_values,
                         AfterDisconnect::Block(ErrorStateCause::IsOffline),
-                    )
-                } else {
+                    );
+                }
+                // The resolvers used by excluded processes may have changed with the network
+                #[cfg(target_os = "linux")]
+                if let Err(error) = self.refresh_original_resolvers(shared_values) {
+                    return self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    );
+                }
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::NetworkChanged(network)) => {
+                log::debug!("Network changed: {network}");
+                if self.move_to_new_network() {
                     SameState(self.into())
+                } else {
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
                 }
             }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::Resumed) => self.revalidate(shared_values),
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
@@ -271,9 +527,19 @@ impl ConnectedState {
                 shared_values.bypass_socket(fd, done_tx);
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::RefreshOriginalResolvers) => {
+                match self.refresh_original_resolvers(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
             #[cfg(windows)]
-            Some(TunnelCommand::SetExcludedApps(result_tx, paths)) => {
-                shared_values.split_tunnel.set_paths(&paths, result_tx);
+            Some(TunnelCommand::SetExcludedApps(result_tx, apps)) => {
+                shared_values.split_tunnel.set_apps(apps, result_tx);
                 SameState(self.into())
             }
         }
@@ -296,21 +562,32 @@ impl ConnectedState {
 
     fn handle_tunnel_close_event(
         self,
-        block_reason: Option<ErrorStateCause>,
+        close_reason: Option<TunnelCloseReason>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
-        if let Some(block_reason) = block_reason {
-            Self::reset_dns(shared_values);
-            Self::reset_routes(shared_values);
-            return NewState(ErrorState::enter(shared_values, block_reason));
-        }
-
-        log::info!("Tunnel closed. Reconnecting.");
         Self::reset_dns(shared_values);
         Self::reset_routes(shared_values);
-        NewState(ConnectingState::enter(shared_values, 0))
+
+        match close_reason {
+            Some(TunnelCloseReason::Block(block_reason)) => {
+                NewState(ErrorState::enter(shared_values, block_reason))
+            }
+            // Continue from the attempt that established the tunnel, so that a different
+            // obfuscation method or port is used
+            Some(TunnelCloseReason::ObfuscatorFailed) => {
+                log::info!("Tunnel obfuscator failed. Reconnecting.");
+                NewState(ConnectingState::enter(
+                    shared_values,
+                    self.retry_attempt + 1,
+                ))
+            }
+            None => {
+                log::info!("Tunnel closed. Reconnecting.");
+                NewState(ConnectingState::enter(shared_values, 0))
+            }
+        }
     }
 }
 
@@ -323,11 +600,7 @@ impl TunnelState for ConnectedState {
         bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
         let connected_state = ConnectedState::from(bootstrap);
-        let tunnel_interface = Some(connected_state.metadata.interface.clone());
-        let tunnel_endpoint = talpid_types::net::TunnelEndpoint {
-            tunnel_interface,
-            ..connected_state.tunnel_parameters.get_tunnel_endpoint()
-        };
+        let tunnel_endpoint = connected_state.tunnel_endpoint();
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
             DisconnectingState::enter(
@@ -338,21 +611,45 @@ impl TunnelState for ConnectedState {
                     AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
                 ),
             )
-        } else if let Err(error) = connected_state.set_dns(shared_values) {
-            log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
-            DisconnectingState::enter(
-                shared_values,
-                (
-                    connected_state.tunnel_close_tx,
-                    connected_state.tunnel_close_event,
-                    AfterDisconnect::Block(ErrorStateCause::SetDnsError),
-                ),
-            )
         } else {
-            (
-                TunnelStateWrapper::from(connected_state),
-                TunnelStateTransition::Connected(tunnel_endpoint),
-            )
+            match connected_state.set_dns(shared_values) {
+                Ok(effective_dns) => {
+                    // The resolvers used by excluded processes are only known once DNS is set
+                    #[cfg(target_os = "linux")]
+                    if !shared_values.dns_monitor.original_resolvers().is_empty() {
+                        if let Err(error) = connected_state.set_firewall_policy(shared_values) {
+                            return DisconnectingState::enter(
+                                shared_values,
+                                (
+                                    connected_state.tunnel_close_tx,
+                                    connected_state.tunnel_close_event,
+                                    AfterDisconnect::Block(
+                                        ErrorStateCause::SetFirewallPolicyError(error),
+                                    ),
+                                ),
+                            );
+                        }
+                    }
+                    shared_values
+                        .retry_scheduler
+                        .on_connected(std::time::Instant::now());
+                    (
+                        TunnelStateWrapper::from(connected_state),
+                        TunnelStateTransition::Connected(tunnel_endpoint, effective_dns),
+                    )
+                }
+                Err(error) => {
+                    log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                    DisconnectingState::enter(
+                        shared_values,
+                        (
+                            connected_state.tunnel_close_tx,
+                            connected_state.tunnel_close_event,
+                            AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                        ),
+                    )
+                }
+            }
         }
     }
 
@@ -377,9 +674,224 @@ impl TunnelState for ConnectedState {
                 if result.is_err() {
                     log::warn!("Tunnel monitor thread has stopped unexpectedly");
                 }
-                let block_reason = result.unwrap_or(None);
-                self.handle_tunnel_close_event(block_reason, shared_values)
+                let close_reason = result.unwrap_or(None);
+                self.handle_tunnel_close_event(close_reason, shared_values)
             }
         }
     }
 }
+
+/// Returns the addresses of the resolver at the tunnel gateway.
+fn gateway_dns_servers(metadata: &TunnelMetadata) -> Vec<IpAddr> {
+    let mut dns_ips = vec![metadata.ipv4_gateway.into()];
+    if let Some(ipv6_gateway) = metadata.ipv6_gateway {
+        dns_ips.push(ipv6_gateway.into());
+    };
+    dns_ips
+}
+
+/// What to do with a connected tunnel when the host resumes from sleep.
+#[cfg_attr(target_os = "android", allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum ResumeAction {
+    /// Check that the relay is still reachable, and re-apply the routes, firewall and DNS.
+    Revalidate,
+    /// Replace the tunnel.
+    Reconnect,
+}
+
+#[cfg_attr(target_os = "android", allow(dead_code))]
+pub(super) fn resume_action(tunnel_type: TunnelType) -> ResumeAction {
+    match tunnel_type {
+        // A failed connectivity check closes the tunnel, which causes a reconnect
+        TunnelType::Wireguard => ResumeAction::Revalidate,
+        // OpenVPN cannot be made to check the connection on demand, and may not notice that the
+        // relay has dropped the session for minutes
+        TunnelType::OpenVpn => ResumeAction::Reconnect,
+    }
+}
+
+/// Returns the DNS config that results from applying `servers`. Unless `has_dns_servers` is set,
+/// `servers` are the gateway resolvers, and `source` is ignored.
+fn effective_dns(servers: Vec<IpAddr>, has_dns_servers: bool, source: DnsSource) -> EffectiveDns {
+    EffectiveDns {
+        servers,
+        source: if has_dns_servers {
+            source
+        } else {
+            DnsSource::Default
+        },
+        tampered: false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn metadata() -> TunnelMetadata {
+        TunnelMetadata {
+            interface: "wg-mullvad".to_owned(),
+            ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: Some(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1)),
+        }
+    }
+
+    #[test]
+    fn test_effective_dns_across_setting_changes() {
+        let gateway_servers = gateway_dns_servers(&metadata());
+        let custom_servers = vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))];
+        let other_custom_servers = vec![
+            IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9)),
+            IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+        ];
+        let blocker_servers = vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))];
+
+        // Sequence of DNS settings applied while connected.
+        let settings = [
+            (None, DnsSource::Default),
+            (Some(custom_servers.clone()), DnsSource::Custom),
+            (Some(other_custom_servers.clone()), DnsSource::Custom),
+            (Some(blocker_servers.clone()), DnsSource::ContentBlockers),
+            // A stale source must not be reported when falling back to the gateway.
+            (None, DnsSource::Custom),
+        ];
+        let expected = [
+            (gateway_servers.clone(), DnsSource::Default),
+            (custom_servers, DnsSource::Custom),
+            (other_custom_servers, DnsSource::Custom),
+            (blocker_servers, DnsSource::ContentBlockers),
+            (gateway_servers, DnsSource::Default),
+        ];
+
+        for ((dns_servers, source), (servers, expected_source)) in
+            settings.into_iter().zip(expected)
+        {
+            let has_dns_servers = dns_servers.is_some();
+            let applied = dns_servers.unwrap_or_else(|| gateway_dns_servers(&metadata()));
+            assert_eq!(
+                effective_dns(applied, has_dns_servers, source),
+                EffectiveDns {
+                    servers,
+                    source: expected_source,
+                    tampered: false,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_resume_action() {
+        assert_eq!(
+            resume_action(TunnelType::Wireguard),
+            ResumeAction::Revalidate
+        );
+        assert_eq!(resume_action(TunnelType::OpenVpn), ResumeAction::Reconnect);
+    }
+
+    #[test]
+    fn test_gateway_dns_servers() {
+        let mut metadata = metadata();
+        assert_eq!(
+            gateway_dns_servers(&metadata),
+            vec![
+                IpAddr::V4(metadata.ipv4_gateway),
+                IpAddr::V6(metadata.ipv6_gateway.unwrap())
+            ]
+        );
+
+        metadata.ipv6_gateway = None;
+        assert_eq!(
+            gateway_dns_servers(&metadata),
+            vec![IpAddr::V4(metadata.ipv4_gateway)]
+        );
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn wireguard_parameters() -> TunnelParameters {
+        use talpid_types::net::{wireguard, GenericTunnelOptions};
+
+        let private_key = wireguard::PrivateKey::new_from_random();
+        TunnelParameters::Wireguard(wireguard::TunnelParameters {
+            connection: wireguard::ConnectionConfig {
+                tunnel: wireguard::TunnelConfig {
+                    private_key: private_key.clone(),
+                    addresses: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                },
+                peer: wireguard::PeerConfig {
+                    public_key: private_key.public_key(),
+                    allowed_ips: talpid_types::net::all_of_the_internet(),
+                    endpoint: "192.0.2.1:51820".parse().unwrap(),
+                    psk: None,
+                },
+                exit_peer: None,
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+                #[cfg(target_os = "linux")]
+                fwmark: None,
+            },
+            options: wireguard::TunnelOptions {
+                mtu: None,
+                quantum_resistant: false,
+            },
+            generic_options: GenericTunnelOptions { enable_ipv6: false },
+            obfuscation: None,
+        })
+    }
+
+    /// Returns a connected state together with the receiving ends of its network change and
+    /// tunnel close channels.
+    #[cfg(not(target_os = "android"))]
+    fn connected_state(
+        tunnel_parameters: TunnelParameters,
+    ) -> (
+        ConnectedState,
+        mpsc::UnboundedReceiver<()>,
+        oneshot::Receiver<()>,
+    ) {
+        use futures::FutureExt;
+
+        let (_event_tx, event_rx) = mpsc::unbounded();
+        let (_close_event_tx, close_event_rx) = oneshot::channel();
+        let (tunnel_close_tx, tunnel_close_rx) = oneshot::channel();
+        let (network_changed_tx, network_changed_rx) = mpsc::unbounded();
+        let state = ConnectedState::from(ConnectedStateBootstrap {
+            metadata: metadata(),
+            tunnel_events: event_rx.fuse(),
+            tunnel_parameters,
+            tunnel_close_event: close_event_rx.fuse(),
+            tunnel_close_tx,
+            network_changed_tx,
+            retry_attempt: 0,
+        });
+        (state, network_changed_rx, tunnel_close_rx)
+    }
+
+    /// A WireGuard tunnel is kept when the network changes, and the tunnel monitor is asked to
+    /// verify that it works on the new network.
+    #[cfg(not(target_os = "android"))]
+    #[test]
+    fn test_network_change_moves_wireguard_tunnel() {
+        let (state, mut network_changed_rx, mut tunnel_close_rx) =
+            connected_state(wireguard_parameters());
+
+        assert!(state.move_to_new_network());
+        assert_eq!(network_changed_rx.try_next().unwrap(), Some(()));
+        assert_eq!(tunnel_close_rx.try_recv(), Ok(None));
+
+        assert!(state.move_to_new_network());
+        assert_eq!(network_changed_rx.try_next().unwrap(), Some(()));
+    }
+
+    /// The tunnel is reconnected if the tunnel monitor can no longer be asked to move the tunnel.
+    #[cfg(not(target_os = "android"))]
+    #[test]
+    fn test_network_change_reconnects_without_tunnel_monitor() {
+        let (state, network_changed_rx, _tunnel_close_rx) = connected_state(wireguard_parameters());
+        drop(network_changed_rx);
+
+        assert!(!state.move_to_new_network());
+    }
+}
@@ -1,41 +1,54 @@
 use super::{
+    network_wait::{Decision, NetworkWait},
     AfterDisconnect, ConnectedState, ConnectedStateBootstrap, DisconnectingState, ErrorState,
     EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
     TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
     firewall::FirewallPolicy,
-    tunnel::{self, TunnelMonitor},
+    tunnel::{self, TrafficCounters, TunnelMonitor},
 };
 use futures::{
     channel::{mpsc, oneshot},
-    future::Fuse,
+    future::{Either, Fuse},
     FutureExt, StreamExt,
 };
 use std::{
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
-    thread,
-    time::{Duration, Instant},
+    time::Instant,
 };
-use talpid_routing::RouteManager;
+use talpid_routing::{GatewayReachability, RouteManager, RouteManagerHandle};
 use talpid_tunnel::{tun_provider::TunProvider, TunnelArgs, TunnelEvent, TunnelMetadata};
 use talpid_types::{
-    net::{AllowedTunnelTraffic, TunnelParameters},
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    net::{AllowedTunnelTraffic, Connectivity, TunnelParameters, TunnelType},
+    tunnel::{ConnectingPhase, ConnectionAttempt, ErrorStateCause, FirewallPolicyError},
     ErrorExt,
 };
 
 #[cfg(target_os = "android")]
 use talpid_tunnel::tun_provider;
 
+#[cfg(feature = "mock-tunnel")]
+use super::mock::MockTunnelControl;
+
 use super::connected_state::TunnelEventsReceiver;
 
-pub(crate) type TunnelCloseEvent = Fuse<oneshot::Receiver<Option<ErrorStateCause>>>;
+pub(crate) type TunnelCloseEvent = Fuse<oneshot::Receiver<Option<TunnelCloseReason>>>;
+
+/// Reason for the tunnel closing, if it should not simply be reconnected.
+#[derive(Debug)]
+pub enum TunnelCloseReason {
+    /// The obfuscator failed or its connection stalled. The next attempt should use a different
+    /// obfuscation method or port.
+    ObfuscatorFailed,
+    /// The tunnel failed and traffic should be blocked.
+    Block(ErrorStateCause),
+}
 
 #[cfg(target_os = "android")]
 const MAX_ATTEMPTS_WITH_SAME_TUN: u32 = 5;
-const MIN_TUNNEL_ALIVE_TIME: Duration = Duration::from_millis(1000);
 #[cfg(target_os = "windows")]
 const MAX_RECOVERABLE_FAIL_RETRIES: u32 = 4;
 
@@ -49,6 +62,11 @@ pub struct ConnectingState {
     allowed_tunnel_traffic: AllowedTunnelTraffic,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    network_changed_tx: mpsc::UnboundedSender<()>,
+    /// Receives the phase of the attempt while the network is being waited for.
+    network_phase: futures::stream::Fuse<mpsc::UnboundedReceiver<ConnectingPhase>>,
+    /// Whether the attempt is held until the network comes up after the daemon started.
+    waiting_for_startup_network: bool,
     retry_attempt: u32,
 }
 
@@ -65,6 +83,8 @@ impl ConnectingState {
         let peer_endpoint = params.get_next_hop_endpoint();
 
         let policy = FirewallPolicy::Connecting {
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            bypass_routes: shared_values.bypass_routes(&peer_endpoint),
             peer_endpoint,
             tunnel: tunnel_metadata.clone(),
             allow_lan: shared_values.allow_lan,
@@ -91,6 +111,7 @@ impl ConnectingState {
             })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn start_tunnel(
         runtime: tokio::runtime::Handle,
         parameters: TunnelParameters,
@@ -98,7 +119,11 @@ impl ConnectingState {
         resource_dir: &Path,
         tun_provider: Arc<Mutex<TunProvider>>,
         route_manager: &RouteManager,
+        connectivity: Connectivity,
+        traffic_counters: TrafficCounters,
+        hold: Option<Instant>,
         retry_attempt: u32,
+        #[cfg(feature = "mock-tunnel")] mock_tunnel: Option<MockTunnelControl>,
     ) -> Self {
         let (event_tx, event_rx) = mpsc::unbounded();
         let on_tunnel_event =
@@ -116,17 +141,20 @@ impl ConnectingState {
 
         let (tunnel_close_tx, tunnel_close_rx) = oneshot::channel();
         let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
+        let (network_changed_tx, network_changed_rx) = mpsc::unbounded();
+        let (network_phase_tx, network_phase_rx) = mpsc::unbounded();
 
         let mut tunnel_parameters = parameters.clone();
+        let relay = parameters.get_next_hop_endpoint().address.ip();
 
         tokio::task::spawn_blocking(move || {
-            let start = Instant::now();
-
             let route_manager_handle = match route_manager_handle {
                 Ok(handle) => handle,
                 Err(error) => {
                     if tunnel_close_event_tx
-                        .send(Some(ErrorStateCause::StartTunnelError))
+                        .send(Some(TunnelCloseReason::Block(
+                            ErrorStateCause::StartTunnelError,
+                        )))
                         .is_err()
                     {
                         log::warn!(
@@ -141,20 +169,53 @@ impl ConnectingState {
                 }
             };
 
+            let mut tunnel_close_rx = tunnel_close_rx;
+            let proceed = if let Some(until) = hold {
+                runtime.block_on(Self::wait_until(
+                    until,
+                    &network_phase_tx,
+                    &mut tunnel_close_rx,
+                ))
+            } else {
+                runtime.block_on(Self::wait_for_network(
+                    &route_manager_handle,
+                    relay,
+                    &network_phase_tx,
+                    &mut tunnel_close_rx,
+                ))
+            };
+            if !proceed {
+                if tunnel_close_event_tx.send(None).is_err() {
+                    log::warn!("Tunnel state machine stopped before receiving tunnel closed event");
+                }
+                return;
+            }
+
             let args = TunnelArgs {
                 runtime,
                 resource_dir: &resource_dir,
                 on_event: on_tunnel_event,
                 tunnel_close_rx,
+                network_changed_rx,
                 tun_provider,
                 retry_attempt,
                 route_manager: route_manager_handle,
+                connectivity,
+                traffic_counters,
             };
 
-            let block_reason = match TunnelMonitor::start(&mut tunnel_parameters, &log_dir, args) {
+            #[cfg(feature = "mock-tunnel")]
+            let tunnel_monitor = match &mock_tunnel {
+                Some(control) => Ok(TunnelMonitor::start_mock(control, args)),
+                None => TunnelMonitor::start(&mut tunnel_parameters, &log_dir, args),
+            };
+            #[cfg(not(feature = "mock-tunnel"))]
+            let tunnel_monitor = TunnelMonitor::start(&mut tunnel_parameters, &log_dir, args);
+
+            let close_reason = match tunnel_monitor {
                 Ok(monitor) => {
                     let reason = Self::wait_for_tunnel_monitor(monitor, retry_attempt);
-                    log::debug!("Tunnel monitor exited with block reason: {:?}", reason);
+                    log::debug!("Tunnel monitor exited with close reason: {:?}", reason);
                     reason
                 }
                 Err(error) if should_retry(&error, retry_attempt) => {
@@ -188,17 +249,11 @@ impl ConnectingState {
                         ) => ErrorStateCause::InvalidDnsServers(addresses),
                         _ => ErrorStateCause::StartTunnelError,
                     };
-                    Some(block_reason)
+                    Some(TunnelCloseReason::Block(block_reason))
                 }
             };
 
-            if block_reason.is_none() {
-                if let Some(remaining_time) = MIN_TUNNEL_ALIVE_TIME.checked_sub(start.elapsed()) {
-                    thread::sleep(remaining_time);
-                }
-            }
-
-            if tunnel_close_event_tx.send(block_reason).is_err() {
+            if tunnel_close_event_tx.send(close_reason).is_err() {
                 log::warn!("Tunnel state machine stopped before receiving tunnel closed event");
             }
 
@@ -212,14 +267,95 @@ impl ConnectingState {
             allowed_tunnel_traffic: INITIAL_ALLOWED_TUNNEL_TRAFFIC,
             tunnel_close_event: tunnel_close_event_rx.fuse(),
             tunnel_close_tx,
+            network_changed_tx,
+            network_phase: network_phase_rx.fuse(),
+            waiting_for_startup_network: false,
             retry_attempt,
         }
     }
 
+    /// Probes the gateway of the route to `relay`, and holds the attempt until the gateway responds
+    /// or the hold times out. Changes to the phase of the attempt are sent on `phase_tx`. Returns
+    /// `false` if the attempt was cancelled.
+    async fn wait_for_network(
+        route_manager: &RouteManagerHandle,
+        relay: IpAddr,
+        phase_tx: &mpsc::UnboundedSender<ConnectingPhase>,
+        cancel: &mut oneshot::Receiver<()>,
+    ) -> bool {
+        let hold = async {
+            let probe_start = Instant::now();
+            let result = Self::probe_gateway(route_manager, relay).await;
+            let wait = match NetworkWait::start(result, probe_start) {
+                Some(wait) => wait,
+                None => return,
+            };
+            log::info!("The gateway is not responding. Waiting for the network before connecting");
+            let _ = phase_tx.unbounded_send(ConnectingPhase::WaitingForNetwork);
+
+            let mut decision = Decision::ProbeAt(wait.first_retry());
+            while let Decision::ProbeAt(at) = decision {
+                tokio::time::sleep_until(at.into()).await;
+                let probe_start = Instant::now();
+                let result = Self::probe_gateway(route_manager, relay).await;
+                decision = wait.on_probe(result, probe_start, Instant::now());
+            }
+            log::info!("Done waiting for the network");
+            let _ = phase_tx.unbounded_send(ConnectingPhase::EstablishingTunnel);
+        };
+        match futures::future::select(Box::pin(hold), cancel).await {
+            Either::Left(_) => true,
+            Either::Right(_) => false,
+        }
+    }
+
+    /// Holds the attempt until `until`, when the network has been stable for long enough and the
+    /// retry backoff has elapsed. The gateway is not probed, since the attempt is held anyway.
+    /// Returns `false` if the attempt was cancelled while it was held.
+    async fn wait_until(
+        until: Instant,
+        phase_tx: &mpsc::UnboundedSender<ConnectingPhase>,
+        cancel: &mut oneshot::Receiver<()>,
+    ) -> bool {
+        let hold = tokio::time::sleep_until(until.into());
+        match futures::future::select(Box::pin(hold), cancel).await {
+            Either::Left(_) => {
+                log::info!("Done holding the connection attempt");
+                let _ = phase_tx.unbounded_send(ConnectingPhase::EstablishingTunnel);
+                true
+            }
+            Either::Right(_) => false,
+        }
+    }
+
+    async fn probe_gateway(
+        route_manager: &RouteManagerHandle,
+        relay: IpAddr,
+    ) -> GatewayReachability {
+        #[cfg(not(target_os = "android"))]
+        {
+            route_manager
+                .probe_gateway(relay, super::network_wait::PROBE_TIMEOUT)
+                .await
+                .unwrap_or_else(|error| {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to probe the gateway")
+                    );
+                    GatewayReachability::Unknown
+                })
+        }
+        #[cfg(target_os = "android")]
+        {
+            let _ = (route_manager, relay);
+            GatewayReachability::Unknown
+        }
+    }
+
     fn wait_for_tunnel_monitor(
         tunnel_monitor: TunnelMonitor,
         retry_attempt: u32,
-    ) -> Option<ErrorStateCause> {
+    ) -> Option<TunnelCloseReason> {
         match tunnel_monitor.wait() {
             Ok(_) => None,
             Err(error) => match error {
@@ -229,6 +365,15 @@ impl ConnectingState {
                     log::debug!("WireGuard tunnel timed out");
                     None
                 }
+                tunnel::Error::WireguardTunnelMonitoringError(
+                    talpid_wireguard::Error::ObfuscatorError(_),
+                ) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg("Tunnel obfuscator stopped unexpectedly")
+                    );
+                    Some(TunnelCloseReason::ObfuscatorFailed)
+                }
                 error @ tunnel::Error::WireguardTunnelMonitoringError(..)
                     if !should_retry(&error, retry_attempt) =>
                 {
@@ -236,7 +381,7 @@ impl ConnectingState {
                         "{}",
                         error.display_chain_with_msg("Tunnel has stopped unexpectedly")
                     );
-                    Some(ErrorStateCause::StartTunnelError)
+                    Some(TunnelCloseReason::Block(ErrorStateCause::StartTunnelError))
                 }
                 error => {
                     log::warn!(
@@ -256,6 +401,8 @@ impl ConnectingState {
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             tunnel_close_tx: self.tunnel_close_tx,
+            network_changed_tx: self.network_changed_tx,
+            retry_attempt: self.retry_attempt,
         }
     }
 
@@ -350,19 +497,74 @@ impl ConnectingState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
-            Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
-                #[cfg(target_os = "android")]
-                Ok(true) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
-                Ok(_) => SameState(self.into()),
-                Err(cause) => self.disconnect(shared_values, AfterDisconnect::Block(cause)),
-            },
+            Some(TunnelCommand::Dns(servers, source)) => {
+                match shared_values.set_dns_servers(servers, source) {
+                    #[cfg(target_os = "android")]
+                    Ok(true) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+                    Ok(_) => SameState(self.into()),
+                    Err(cause) => self.disconnect(shared_values, AfterDisconnect::Block(cause)),
+                }
+            }
+            Some(TunnelCommand::TlsDnsServers(tls_dns_servers)) => {
+                shared_values.set_tls_dns_servers(tls_dns_servers);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::PreserveSearchDomains(preserve_search_domains)) => {
+                shared_values.set_preserve_search_domains(preserve_search_domains);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::AllowLanDnsWhenBlocked(allow_lan_dns_when_blocked)) => {
+                shared_values.allow_lan_dns_when_blocked = allow_lan_dns_when_blocked;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::KeepCustomDnsWhileDisconnected(keep_custom_dns)) => {
+                shared_values.keep_custom_dns_while_disconnected = keep_custom_dns;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::ExcludedOwners(owners)) => {
+                shared_values.set_excluded_owners(owners);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SplitTunnelConfig(config)) => {
+                shared_values.set_split_tunnel_config(config);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::BypassRoutes(bypass_routes)) => {
+                if shared_values.set_bypass_routes(bypass_routes)
+                    && cfg!(any(target_os = "linux", target_os = "macos"))
+                {
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                } else {
+                    SameState(self.into())
+                }
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::NetworkChanged(_)) => SameState(self.into()),
+            // The attempt may have timed out while the host was asleep, so start over
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::Resumed) => {
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            }
+            Some(TunnelCommand::RetryBackoff(retry_backoff)) => {
+                shared_values.retry_scheduler.set_backoff(retry_backoff);
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
             Some(TunnelCommand::IsOffline(is_offline)) => {
-                shared_values.is_offline = is_offline;
-                if is_offline {
+                shared_values.set_is_offline(is_offline);
+                if self.waiting_for_startup_network {
+                    if is_offline {
+                        SameState(self.into())
+                    } else {
+                        // Start over, now that the network is up
+                        self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                    }
+                } else if is_offline {
                     self.disconnect(
                         shared_values,
                         AfterDisconnect::Block(ErrorStateCause::IsOffline),
@@ -385,9 +587,11 @@ impl ConnectingState {
                 shared_values.bypass_socket(fd, done_tx);
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::RefreshOriginalResolvers) => SameState(self.into()),
             #[cfg(windows)]
-            Some(TunnelCommand::SetExcludedApps(result_tx, paths)) => {
-                shared_values.split_tunnel.set_paths(&paths, result_tx);
+            Some(TunnelCommand::SetExcludedApps(result_tx, apps)) => {
+                shared_values.split_tunnel.set_apps(apps, result_tx);
                 SameState(self.into())
             }
         }
@@ -463,12 +667,14 @@ impl ConnectingState {
 
     fn handle_tunnel_close_event(
         self,
-        block_reason: Option<ErrorStateCause>,
+        close_reason: Option<TunnelCloseReason>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
-        if let Some(block_reason) = block_reason {
+        // Obfuscator failures need no special handling here, since the next attempt already uses a
+        // different obfuscation method or port
+        if let Some(TunnelCloseReason::Block(block_reason)) = close_reason {
             Self::reset_routes(shared_values);
             return NewState(ErrorState::enter(shared_values, block_reason));
         }
@@ -485,6 +691,19 @@ impl ConnectingState {
     }
 }
 
+/// Returns the details of attempt `retry_attempt` (counting from 0) to connect a tunnel of type
+/// `tunnel_type`. OpenVPN retries on its own, so only WireGuard attempts have a known timeout.
+fn connection_attempt(tunnel_type: TunnelType, retry_attempt: u32) -> ConnectionAttempt {
+    let retry_timeout = match tunnel_type {
+        TunnelType::Wireguard => Some(talpid_wireguard::establish_timeout(retry_attempt)),
+        TunnelType::OpenVpn => None,
+    };
+    ConnectionAttempt {
+        attempt: retry_attempt.saturating_add(1),
+        retry_timeout,
+    }
+}
+
 #[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
 fn should_retry(error: &tunnel::Error, retry_attempt: u32) -> bool {
     use talpid_wireguard::{Error, TunnelError};
@@ -538,7 +757,10 @@ impl TunnelState for ConnectingState {
         shared_values: &mut SharedTunnelStateValues,
         retry_attempt: u32,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
-        if shared_values.is_offline {
+        let startup_hold = shared_values
+            .startup_gate
+            .hold(Instant::now(), shared_values.is_offline);
+        if shared_values.is_offline && startup_hold.is_none() {
             // FIXME: Temporary: Nudge route manager to update the default interface
             #[cfg(target_os = "macos")]
             if let Ok(handle) = shared_values.route_manager.handle() {
@@ -579,6 +801,9 @@ impl TunnelState for ConnectingState {
                         ErrorStateCause::SetFirewallPolicyError(error),
                     )
                 } else {
+                    #[cfg(any(target_os = "linux", target_os = "macos"))]
+                    shared_values.add_bypass_routes(&tunnel_parameters.get_next_hop_endpoint());
+
                     #[cfg(target_os = "android")]
                     {
                         if retry_attempt > 0 && retry_attempt % MAX_ATTEMPTS_WITH_SAME_TUN == 0 {
@@ -593,19 +818,63 @@ impl TunnelState for ConnectingState {
                         }
                     }
 
-                    let connecting_state = Self::start_tunnel(
+                    let connectivity = shared_values
+                        .runtime
+                        .block_on(shared_values.offline_monitor.connectivity());
+
+                    let now = Instant::now();
+                    let stable_network_hold = shared_values
+                        .stable_network_hold
+                        .take()
+                        .filter(|until| *until > now);
+                    let retry_hold = shared_values.retry_scheduler.on_attempt(now);
+                    let hold = stable_network_hold.max(retry_hold).max(startup_hold);
+                    // The phase describes whichever hold ends last
+                    let phase = if let Some(until) = startup_hold {
+                        log::info!(
+                            "Waiting for the network before the first connection attempt, for at \
+                             most {}s",
+                            until.saturating_duration_since(now).as_secs()
+                        );
+                        ConnectingPhase::WaitingForNetwork
+                    } else if let Some(until) =
+                        stable_network_hold.filter(|until| Some(*until) == hold)
+                    {
+                        log::info!(
+                            "The network is unstable. Holding the connection attempt for {}s",
+                            until.saturating_duration_since(Instant::now()).as_secs()
+                        );
+                        ConnectingPhase::WaitingForStableNetwork
+                    } else if let Some(until) = retry_hold {
+                        log::debug!(
+                            "Holding the connection attempt for {}ms before retrying",
+                            until.saturating_duration_since(now).as_millis()
+                        );
+                        ConnectingPhase::WaitingToRetry
+                    } else {
+                        ConnectingPhase::EstablishingTunnel
+                    };
+
+                    let mut connecting_state = Self::start_tunnel(
                         shared_values.runtime.clone(),
                         tunnel_parameters,
                         &shared_values.log_dir,
                         &shared_values.resource_dir,
                         shared_values.tun_provider.clone(),
                         &shared_values.route_manager,
+                        connectivity,
+                        shared_values.traffic_counters.clone(),
+                        hold,
                         retry_attempt,
+                        #[cfg(feature = "mock-tunnel")]
+                        shared_values.mock_tunnel.clone(),
                     );
-                    let params = connecting_state.tunnel_parameters.clone();
+                    connecting_state.waiting_for_startup_network = startup_hold.is_some();
+                    let endpoint = connecting_state.tunnel_parameters.get_tunnel_endpoint();
+                    let attempt = connection_attempt(endpoint.tunnel_type, retry_attempt);
                     (
                         TunnelStateWrapper::from(connecting_state),
-                        TunnelStateTransition::Connecting(params.get_tunnel_endpoint()),
+                        TunnelStateTransition::Connecting(endpoint, phase, attempt),
                     )
                 }
             }
@@ -620,12 +889,29 @@ impl TunnelState for ConnectingState {
     ) -> EventConsequence {
         let result = runtime.block_on(async {
             futures::select! {
-                command = commands.next() => EventResult::Command(command),
-                event = self.tunnel_events.next() => EventResult::Event(event),
-                result = &mut self.tunnel_close_event => EventResult::Close(result),
+                command = commands.next() => Ok(EventResult::Command(command)),
+                event = self.tunnel_events.next() => Ok(EventResult::Event(event)),
+                result = &mut self.tunnel_close_event => Ok(EventResult::Close(result)),
+                phase = self.network_phase.next() => Err(phase),
             }
         });
 
+        let result = match result {
+            Ok(result) => result,
+            Err(Some(phase)) => {
+                self.waiting_for_startup_network = false;
+                let endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+                let attempt = connection_attempt(endpoint.tunnel_type, self.retry_attempt);
+                return EventConsequence::NewState((
+                    self.into(),
+                    TunnelStateTransition::Connecting(endpoint, phase, attempt),
+                ));
+            }
+            // The wait is over, or the attempt was cancelled, which is handled once the tunnel
+            // closes
+            Err(None) => return EventConsequence::SameState(self.into()),
+        };
+
         match result {
             EventResult::Command(command) => self.handle_commands(command, shared_values),
             EventResult::Event(event) => self.handle_tunnel_events(event, shared_values),
@@ -633,9 +919,40 @@ impl TunnelState for ConnectingState {
                 if result.is_err() {
                     log::warn!("Tunnel monitor thread has stopped unexpectedly");
                 }
-                let block_reason = result.unwrap_or(None);
-                self.handle_tunnel_close_event(block_reason, shared_values)
+                let close_reason = result.unwrap_or(None);
+                self.handle_tunnel_close_event(close_reason, shared_values)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_connection_attempt_across_failures() {
+        // Each failed attempt enters the connecting state again with the next retry attempt
+        let attempts = (0..5)
+            .map(|retry_attempt| connection_attempt(TunnelType::Wireguard, retry_attempt))
+            .collect::<Vec<_>>();
+
+        let numbers = attempts.iter().map(|attempt| attempt.attempt);
+        assert!(numbers.eq(1..=5));
+
+        // The attempts are given longer to connect, up to a limit
+        let timeouts = attempts
+            .iter()
+            .map(|attempt| attempt.retry_timeout.unwrap().as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(timeouts, [4, 8, 15, 15, 15]);
+
+        assert_eq!(
+            connection_attempt(TunnelType::OpenVpn, 3),
+            ConnectionAttempt {
+                attempt: 4,
+                retry_timeout: None,
+            }
+        );
+    }
+}
@@ -2,10 +2,12 @@ use super::{
     ConnectingState, ErrorState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
     TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
-#[cfg(target_os = "macos")]
+#[cfg(any(target_os = "linux", target_os = "macos"))]
 use crate::dns;
 use crate::firewall::FirewallPolicy;
 use futures::StreamExt;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::net::IpAddr;
 #[cfg(target_os = "macos")]
 use std::net::Ipv4Addr;
 #[cfg(target_os = "macos")]
@@ -15,15 +17,57 @@ use talpid_types::ErrorExt;
 /// No tunnel is running.
 pub struct DisconnectedState;
 
+/// How the host is protected in the disconnected state.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[derive(Debug, PartialEq)]
+enum Protection {
+    /// Nothing is blocked, and the system DNS config is left to the system.
+    None,
+    /// All traffic is blocked since "block when disconnected" is enabled.
+    Blocked,
+    /// DNS requests may only be sent to the kept custom DNS servers, which the system DNS config
+    /// points to.
+    RestrictDns(Vec<IpAddr>),
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl Protection {
+    fn new(block_when_disconnected: bool, kept_dns_servers: Vec<IpAddr>) -> Self {
+        if block_when_disconnected {
+            Protection::Blocked
+        } else if !kept_dns_servers.is_empty() {
+            Protection::RestrictDns(kept_dns_servers)
+        } else {
+            Protection::None
+        }
+    }
+}
+
 impl DisconnectedState {
     fn set_firewall_policy(
         shared_values: &mut SharedTunnelStateValues,
         should_reset_firewall: bool,
     ) {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if let Protection::RestrictDns(dns_servers) = Self::protection(shared_values) {
+            let policy = FirewallPolicy::RestrictDns { dns_servers };
+            if let Err(error) = shared_values.firewall.apply_policy(policy) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to apply DNS restricting firewall policy for disconnected state"
+                    )
+                );
+            }
+            return;
+        }
+
         let result = if shared_values.block_when_disconnected {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
                 allowed_endpoint: Some(shared_values.allowed_endpoint.clone()),
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                lan_dns_servers: shared_values.blocked_lan_dns_servers(),
                 #[cfg(target_os = "macos")]
                 dns_redirect_port: shared_values.filtering_resolver.listening_port(),
             };
@@ -68,6 +112,64 @@ impl DisconnectedState {
         }
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn protection(shared_values: &SharedTunnelStateValues) -> Protection {
+        Protection::new(
+            shared_values.block_when_disconnected,
+            shared_values.disconnected_dns_servers(),
+        )
+    }
+
+    /// Configures the system DNS for the current [`Protection`]. On macOS, DNS is pointed at the
+    /// filtering resolver while blocking.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn set_dns(shared_values: &mut SharedTunnelStateValues) -> Result<(), dns::Error> {
+        match Self::protection(shared_values) {
+            Protection::RestrictDns(dns_servers) => {
+                Self::keep_custom_dns(shared_values, &dns_servers);
+                Ok(())
+            }
+            #[cfg(target_os = "macos")]
+            Protection::Blocked => Self::setup_local_dns_config(shared_values),
+            _ => shared_values.dns_monitor.reset(),
+        }
+    }
+
+    /// Reconfigures the system DNS if a setting change affected the [`Protection`].
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn update_dns(
+        shared_values: &mut SharedTunnelStateValues,
+        previous: &Protection,
+    ) -> Result<(), dns::Error> {
+        if Self::protection(shared_values) == *previous {
+            return Ok(());
+        }
+        Self::set_dns(shared_values)
+    }
+
+    /// Points the system DNS to the kept custom DNS servers.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn keep_custom_dns(shared_values: &mut SharedTunnelStateValues, dns_servers: &[IpAddr]) {
+        #[cfg(target_os = "linux")]
+        let interface = match shared_values.non_tunnel_interface(dns_servers[0]) {
+            Some(interface) => interface,
+            None => {
+                log::error!("Unable to keep custom DNS: No route to {}", dns_servers[0]);
+                return;
+            }
+        };
+        // The config is applied to all network services, regardless of interface
+        #[cfg(target_os = "macos")]
+        let interface = "lo".to_owned();
+
+        if let Err(error) = shared_values.dns_monitor.set(&interface, dns_servers) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Unable to keep custom DNS")
+            );
+        }
+    }
+
     fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
         if let Err(error) = shared_values.dns_monitor.reset() {
             log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
@@ -92,18 +194,12 @@ impl TunnelState for DisconnectedState {
         shared_values: &mut SharedTunnelStateValues,
         should_reset_firewall: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
-        #[cfg(target_os = "macos")]
-        if shared_values.block_when_disconnected {
-            if let Err(err) = Self::setup_local_dns_config(shared_values) {
-                log::error!(
-                    "{}",
-                    err.display_chain_with_msg("Failed to start filtering resolver:")
-                );
-            }
-        } else if let Err(error) = shared_values.dns_monitor.reset() {
+        shared_values.retry_scheduler.reset();
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if let Err(error) = Self::set_dns(shared_values) {
             log::error!(
                 "{}",
-                error.display_chain_with_msg("Unable to disable filtering resolver")
+                error.display_chain_with_msg("Failed to configure DNS for disconnected state")
             );
         }
 
@@ -150,40 +246,102 @@ impl TunnelState for DisconnectedState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
-            Some(TunnelCommand::Dns(servers)) => {
+            Some(TunnelCommand::Dns(servers, source)) => {
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                let protection = Self::protection(shared_values);
                 // Same situation as allow LAN above.
-                shared_values
-                    .set_dns_servers(servers)
+                let changed = shared_values
+                    .set_dns_servers(servers, source)
                     .expect("Failed to reconnect after changing custom DNS servers");
+                if changed {
+                    // The DNS restricting policy must be removed if the servers are no longer kept
+                    let should_reset_firewall = shared_values.keep_custom_dns_while_disconnected;
+                    Self::set_firewall_policy(shared_values, should_reset_firewall);
+                    #[cfg(any(target_os = "linux", target_os = "macos"))]
+                    if let Err(error) = Self::update_dns(shared_values, &protection) {
+                        log::error!("{}", error.display_chain_with_msg("Failed to update DNS"));
+                    }
+                }
 
                 SameState(self.into())
             }
+            Some(TunnelCommand::AllowLanDnsWhenBlocked(allow_lan_dns_when_blocked)) => {
+                if shared_values.allow_lan_dns_when_blocked != allow_lan_dns_when_blocked {
+                    shared_values.allow_lan_dns_when_blocked = allow_lan_dns_when_blocked;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::KeepCustomDnsWhileDisconnected(keep_custom_dns)) => {
+                if shared_values.keep_custom_dns_while_disconnected != keep_custom_dns {
+                    #[cfg(any(target_os = "linux", target_os = "macos"))]
+                    let protection = Self::protection(shared_values);
+                    shared_values.keep_custom_dns_while_disconnected = keep_custom_dns;
+                    Self::set_firewall_policy(shared_values, true);
+                    #[cfg(any(target_os = "linux", target_os = "macos"))]
+                    if let Err(error) = Self::update_dns(shared_values, &protection) {
+                        log::error!("{}", error.display_chain_with_msg("Failed to update DNS"));
+                    }
+                }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::TlsDnsServers(tls_dns_servers)) => {
+                shared_values.set_tls_dns_servers(tls_dns_servers);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::PreserveSearchDomains(preserve_search_domains)) => {
+                shared_values.set_preserve_search_domains(preserve_search_domains);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::ExcludedOwners(owners)) => {
+                shared_values.set_excluded_owners(owners);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SplitTunnelConfig(config)) => {
+                shared_values.set_split_tunnel_config(config);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::BypassRoutes(bypass_routes)) => {
+                shared_values.set_bypass_routes(bypass_routes);
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::NetworkChanged(_)) => SameState(self.into()),
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::Resumed) => SameState(self.into()),
+            Some(TunnelCommand::RetryBackoff(retry_backoff)) => {
+                shared_values.retry_scheduler.set_backoff(retry_backoff);
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 if shared_values.block_when_disconnected != block_when_disconnected {
+                    #[cfg(any(target_os = "linux", target_os = "macos"))]
+                    let protection = Self::protection(shared_values);
                     shared_values.block_when_disconnected = block_when_disconnected;
                     Self::set_firewall_policy(shared_values, true);
                     #[cfg(windows)]
                     Self::register_split_tunnel_addresses(shared_values, true);
-                    #[cfg(target_os = "macos")]
-                    if block_when_disconnected {
-                        if let Err(err) = Self::setup_local_dns_config(shared_values) {
-                            log::error!(
-                                "{}",
-                                err.display_chain_with_msg("Failed to configure host DNS")
-                            );
+                    #[cfg(any(target_os = "linux", target_os = "macos"))]
+                    if let Err(err) = Self::update_dns(shared_values, &protection) {
+                        log::error!(
+                            "{}",
+                            err.display_chain_with_msg("Failed to configure host DNS")
+                        );
+                        #[cfg(target_os = "macos")]
+                        if block_when_disconnected {
                             return NewState(ErrorState::enter(
                                 shared_values,
                                 ErrorStateCause::SetDnsError,
                             ));
                         }
-                    } else {
-                        Self::reset_dns(shared_values);
                     }
                 }
                 SameState(self.into())
             }
             Some(TunnelCommand::IsOffline(is_offline)) => {
-                shared_values.is_offline = is_offline;
+                shared_values.set_is_offline(is_offline);
                 SameState(self.into())
             }
             Some(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
@@ -196,9 +354,11 @@ impl TunnelState for DisconnectedState {
                 shared_values.bypass_socket(fd, done_tx);
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::RefreshOriginalResolvers) => SameState(self.into()),
             #[cfg(windows)]
-            Some(TunnelCommand::SetExcludedApps(result_tx, paths)) => {
-                shared_values.split_tunnel.set_paths(&paths, result_tx);
+            Some(TunnelCommand::SetExcludedApps(result_tx, apps)) => {
+                shared_values.split_tunnel.set_apps(apps, result_tx);
                 SameState(self.into())
             }
             None => {
@@ -209,3 +369,76 @@ impl TunnelState for DisconnectedState {
         }
     }
 }
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod test {
+    use super::Protection;
+    use crate::firewall::disconnected_dns_servers;
+    use std::net::{IpAddr, Ipv4Addr};
+    use talpid_types::net::DnsSource;
+
+    struct Settings {
+        dns_servers: Vec<IpAddr>,
+        dns_source: DnsSource,
+        keep_custom_dns_while_disconnected: bool,
+        block_when_disconnected: bool,
+    }
+
+    impl Settings {
+        fn protection(&self) -> Protection {
+            Protection::new(
+                self.block_when_disconnected,
+                disconnected_dns_servers(
+                    Some(&self.dns_servers),
+                    self.dns_source,
+                    self.keep_custom_dns_while_disconnected,
+                    self.block_when_disconnected,
+                ),
+            )
+        }
+    }
+
+    #[test]
+    fn test_protection_transitions() {
+        let custom_dns = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+        let other_custom_dns = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2));
+
+        let mut settings = Settings {
+            dns_servers: vec![custom_dns],
+            dns_source: DnsSource::Custom,
+            keep_custom_dns_while_disconnected: false,
+            block_when_disconnected: false,
+        };
+        assert_eq!(settings.protection(), Protection::None);
+
+        settings.keep_custom_dns_while_disconnected = true;
+        assert_eq!(
+            settings.protection(),
+            Protection::RestrictDns(vec![custom_dns])
+        );
+
+        settings.dns_servers = vec![other_custom_dns];
+        assert_eq!(
+            settings.protection(),
+            Protection::RestrictDns(vec![other_custom_dns])
+        );
+
+        // Blocking takes precedence over the kept servers
+        settings.block_when_disconnected = true;
+        assert_eq!(settings.protection(), Protection::Blocked);
+
+        settings.block_when_disconnected = false;
+        assert_eq!(
+            settings.protection(),
+            Protection::RestrictDns(vec![other_custom_dns])
+        );
+
+        // Only custom servers are kept
+        settings.dns_source = DnsSource::ContentBlockers;
+        assert_eq!(settings.protection(), Protection::None);
+
+        settings.dns_source = DnsSource::Custom;
+        settings.keep_custom_dns_while_disconnected = false;
+        assert_eq!(settings.protection(), Protection::None);
+    }
+}
@@ -1,7 +1,8 @@
 use super::{
-    connecting_state::TunnelCloseEvent, ConnectingState, DisconnectedState, ErrorState,
-    EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
-    TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    connecting_state::{TunnelCloseEvent, TunnelCloseReason},
+    ConnectingState, DisconnectedState, ErrorState, EventConsequence, EventResult,
+    SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
+    TunnelStateTransition, TunnelStateWrapper,
 };
 use futures::{channel::oneshot, future::FusedFuture, StreamExt};
 use talpid_types::tunnel::{ActionAfterDisconnect, ErrorStateCause};
@@ -32,8 +33,46 @@ impl DisconnectingState {
                     let _ = tx.send(());
                     AfterDisconnect::Nothing
                 }
-                Some(TunnelCommand::Dns(servers)) => {
-                    let _ = shared_values.set_dns_servers(servers);
+                Some(TunnelCommand::Dns(servers, source)) => {
+                    let _ = shared_values.set_dns_servers(servers, source);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::TlsDnsServers(tls_dns_servers)) => {
+                    shared_values.set_tls_dns_servers(tls_dns_servers);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::PreserveSearchDomains(preserve_search_domains)) => {
+                    shared_values.set_preserve_search_domains(preserve_search_domains);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::AllowLanDnsWhenBlocked(allow_lan_dns_when_blocked)) => {
+                    shared_values.allow_lan_dns_when_blocked = allow_lan_dns_when_blocked;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::KeepCustomDnsWhileDisconnected(keep_custom_dns)) => {
+                    shared_values.keep_custom_dns_while_disconnected = keep_custom_dns;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::ExcludedOwners(owners)) => {
+                    shared_values.set_excluded_owners(owners);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SplitTunnelConfig(config)) => {
+                    shared_values.set_split_tunnel_config(config);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::BypassRoutes(bypass_routes)) => {
+                    shared_values.set_bypass_routes(bypass_routes);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Nothing,
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::Resumed) => AfterDisconnect::Nothing,
+                Some(TunnelCommand::RetryBackoff(retry_backoff)) => {
+                    shared_values.retry_scheduler.set_backoff(retry_backoff);
                     AfterDisconnect::Nothing
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -41,7 +80,7 @@ impl DisconnectingState {
                     AfterDisconnect::Nothing
                 }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
-                    shared_values.is_offline = is_offline;
+                    shared_values.set_is_offline(is_offline);
                     AfterDisconnect::Nothing
                 }
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
@@ -52,9 +91,11 @@ impl DisconnectingState {
                     shared_values.bypass_socket(fd, done_tx);
                     AfterDisconnect::Nothing
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::RefreshOriginalResolvers) => AfterDisconnect::Nothing,
                 #[cfg(windows)]
-                Some(TunnelCommand::SetExcludedApps(result_tx, paths)) => {
-                    shared_values.split_tunnel.set_paths(&paths, result_tx);
+                Some(TunnelCommand::SetExcludedApps(result_tx, apps)) => {
+                    shared_values.split_tunnel.set_apps(apps, result_tx);
                     AfterDisconnect::Nothing
                 }
             },
@@ -68,8 +109,46 @@ impl DisconnectingState {
                     let _ = tx.send(());
                     AfterDisconnect::Block(reason)
                 }
-                Some(TunnelCommand::Dns(servers)) => {
-                    let _ = shared_values.set_dns_servers(servers);
+                Some(TunnelCommand::Dns(servers, source)) => {
+                    let _ = shared_values.set_dns_servers(servers, source);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::TlsDnsServers(tls_dns_servers)) => {
+                    shared_values.set_tls_dns_servers(tls_dns_servers);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::PreserveSearchDomains(preserve_search_domains)) => {
+                    shared_values.set_preserve_search_domains(preserve_search_domains);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::AllowLanDnsWhenBlocked(allow_lan_dns_when_blocked)) => {
+                    shared_values.allow_lan_dns_when_blocked = allow_lan_dns_when_blocked;
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::KeepCustomDnsWhileDisconnected(keep_custom_dns)) => {
+                    shared_values.keep_custom_dns_while_disconnected = keep_custom_dns;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::ExcludedOwners(owners)) => {
+                    shared_values.set_excluded_owners(owners);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SplitTunnelConfig(config)) => {
+                    shared_values.set_split_tunnel_config(config);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::BypassRoutes(bypass_routes)) => {
+                    shared_values.set_bypass_routes(bypass_routes);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Block(reason),
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::Resumed) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::RetryBackoff(retry_backoff)) => {
+                    shared_values.retry_scheduler.set_backoff(retry_backoff);
                     AfterDisconnect::Block(reason)
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -77,7 +156,7 @@ impl DisconnectingState {
                     AfterDisconnect::Block(reason)
                 }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
-                    shared_values.is_offline = is_offline;
+                    shared_values.set_is_offline(is_offline);
                     if !is_offline && matches!(reason, ErrorStateCause::IsOffline) {
                         AfterDisconnect::Reconnect(0)
                     } else {
@@ -92,9 +171,11 @@ impl DisconnectingState {
                     shared_values.bypass_socket(fd, done_tx);
                     AfterDisconnect::Block(reason)
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::RefreshOriginalResolvers) => AfterDisconnect::Block(reason),
                 #[cfg(windows)]
-                Some(TunnelCommand::SetExcludedApps(result_tx, paths)) => {
-                    shared_values.split_tunnel.set_paths(&paths, result_tx);
+                Some(TunnelCommand::SetExcludedApps(result_tx, apps)) => {
+                    shared_values.split_tunnel.set_apps(apps, result_tx);
                     AfterDisconnect::Block(reason)
                 }
                 None => AfterDisconnect::Block(reason),
@@ -109,8 +190,46 @@ impl DisconnectingState {
                     let _ = tx.send(());
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
-                Some(TunnelCommand::Dns(servers)) => {
-                    let _ = shared_values.set_dns_servers(servers);
+                Some(TunnelCommand::Dns(servers, source)) => {
+                    let _ = shared_values.set_dns_servers(servers, source);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::TlsDnsServers(tls_dns_servers)) => {
+                    shared_values.set_tls_dns_servers(tls_dns_servers);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::PreserveSearchDomains(preserve_search_domains)) => {
+                    shared_values.set_preserve_search_domains(preserve_search_domains);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::AllowLanDnsWhenBlocked(allow_lan_dns_when_blocked)) => {
+                    shared_values.allow_lan_dns_when_blocked = allow_lan_dns_when_blocked;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::KeepCustomDnsWhileDisconnected(keep_custom_dns)) => {
+                    shared_values.keep_custom_dns_while_disconnected = keep_custom_dns;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::ExcludedOwners(owners)) => {
+                    shared_values.set_excluded_owners(owners);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SplitTunnelConfig(config)) => {
+                    shared_values.set_split_tunnel_config(config);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::BypassRoutes(bypass_routes)) => {
+                    shared_values.set_bypass_routes(bypass_routes);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Reconnect(retry_attempt),
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::Resumed) => AfterDisconnect::Reconnect(retry_attempt),
+                Some(TunnelCommand::RetryBackoff(retry_backoff)) => {
+                    shared_values.retry_scheduler.set_backoff(retry_backoff);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -118,7 +237,7 @@ impl DisconnectingState {
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
-                    shared_values.is_offline = is_offline;
+                    shared_values.set_is_offline(is_offline);
                     if is_offline {
                         AfterDisconnect::Block(ErrorStateCause::IsOffline)
                     } else {
@@ -133,9 +252,13 @@ impl DisconnectingState {
                     shared_values.bypass_socket(fd, done_tx);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::RefreshOriginalResolvers) => {
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 #[cfg(windows)]
-                Some(TunnelCommand::SetExcludedApps(result_tx, paths)) => {
-                    shared_values.split_tunnel.set_paths(&paths, result_tx);
+                Some(TunnelCommand::SetExcludedApps(result_tx, apps)) => {
+                    shared_values.split_tunnel.set_apps(apps, result_tx);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
             },
@@ -146,10 +269,10 @@ impl DisconnectingState {
 
     fn after_disconnect(
         self,
-        block_reason: Option<ErrorStateCause>,
+        close_reason: Option<TunnelCloseReason>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
-        if let Some(reason) = block_reason {
+        if let Some(TunnelCloseReason::Block(reason)) = close_reason {
             return ErrorState::enter(shared_values, reason);
         }
 
@@ -210,8 +333,8 @@ impl TunnelState for DisconnectingState {
         match result {
             EventResult::Command(command) => self.handle_commands(command, shared_values),
             EventResult::Close(result) => {
-                let block_reason = result.unwrap_or(None);
-                NewState(self.after_disconnect(block_reason, shared_values))
+                let close_reason = result.unwrap_or(None);
+                NewState(self.after_disconnect(close_reason, shared_values))
             }
             _ => unreachable!("unexpected event result"),
         }
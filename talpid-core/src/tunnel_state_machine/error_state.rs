@@ -23,6 +23,8 @@ impl ErrorState {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
             allowed_endpoint: Some(shared_values.allowed_endpoint.clone()),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            lan_dns_servers: shared_values.blocked_lan_dns_servers(),
             #[cfg(target_os = "macos")]
             dns_redirect_port: shared_values.filtering_resolver.listening_port(),
         };
@@ -165,21 +167,71 @@ impl TunnelState for ErrorState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
-            Some(TunnelCommand::Dns(servers)) => {
-                if let Err(error_state_cause) = shared_values.set_dns_servers(servers) {
-                    NewState(Self::enter(shared_values, error_state_cause))
-                } else {
-                    SameState(self.into())
+            Some(TunnelCommand::Dns(servers, source)) => {
+                match shared_values.set_dns_servers(servers, source) {
+                    Ok(changed) => {
+                        if changed {
+                            let _ = Self::set_firewall_policy(shared_values);
+                        }
+                        SameState(self.into())
+                    }
+                    Err(error_state_cause) => {
+                        NewState(Self::enter(shared_values, error_state_cause))
+                    }
+                }
+            }
+            Some(TunnelCommand::TlsDnsServers(tls_dns_servers)) => {
+                shared_values.set_tls_dns_servers(tls_dns_servers);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::PreserveSearchDomains(preserve_search_domains)) => {
+                shared_values.set_preserve_search_domains(preserve_search_domains);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::AllowLanDnsWhenBlocked(allow_lan_dns_when_blocked)) => {
+                if shared_values.allow_lan_dns_when_blocked != allow_lan_dns_when_blocked {
+                    shared_values.allow_lan_dns_when_blocked = allow_lan_dns_when_blocked;
+                    let _ = Self::set_firewall_policy(shared_values);
                 }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::KeepCustomDnsWhileDisconnected(keep_custom_dns)) => {
+                shared_values.keep_custom_dns_while_disconnected = keep_custom_dns;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::ExcludedOwners(owners)) => {
+                shared_values.set_excluded_owners(owners);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SplitTunnelConfig(config)) => {
+                shared_values.set_split_tunnel_config(config);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::BypassRoutes(bypass_routes)) => {
+                shared_values.set_bypass_routes(bypass_routes);
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::NetworkChanged(_)) => SameState(self.into()),
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::Resumed) => SameState(self.into()),
+            Some(TunnelCommand::RetryBackoff(retry_backoff)) => {
+                shared_values.retry_scheduler.set_backoff(retry_backoff);
+                SameState(self.into())
             }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
             Some(TunnelCommand::IsOffline(is_offline)) => {
-                shared_values.is_offline = is_offline;
+                shared_values.set_is_offline(is_offline);
                 if !is_offline && matches!(self.block_reason, ErrorStateCause::IsOffline) {
                     Self::reset_dns(shared_values);
+                    shared_values.stable_network_hold = shared_values
+                        .reconnect_limiter
+                        .on_reconnect(std::time::Instant::now());
                     NewState(ConnectingState::enter(shared_values, 0))
                 } else {
                     SameState(self.into())
@@ -204,9 +256,11 @@ impl TunnelState for ErrorState {
                 shared_values.bypass_socket(fd, done_tx);
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::RefreshOriginalResolvers) => SameState(self.into()),
             #[cfg(windows)]
-            Some(TunnelCommand::SetExcludedApps(result_tx, paths)) => {
-                shared_values.split_tunnel.set_paths(&paths, result_tx);
+            Some(TunnelCommand::SetExcludedApps(result_tx, apps)) => {
+                shared_values.split_tunnel.set_apps(apps, result_tx);
                 SameState(self.into())
             }
         }
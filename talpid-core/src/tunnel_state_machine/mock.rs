@@ -0,0 +1,401 @@
+//! Mock tunnel for running the daemon without privileges in integration tests and during GUI
+//! development. The state machine runs as usual, but the tunnel, firewall, DNS, routing and
+//! offline monitor underneath it are replaced with in-memory fakes. No tunnel devices, firewall
+//! rules, DNS settings or routes are touched. Tunnel parameters are still generated for every
+//! attempt, so relays are selected as usual, but the attempts succeed or fail after a delay as
+//! instructed by [`MockTunnelScript`]s.
+//!
+//! The fakes are used when talpid-core is built with the `mock-tunnel` feature and
+//! `MULLVAD_MOCK_TUNNEL` is set to `1`.
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+compile_error!("The mock tunnel is only supported on Linux and macOS");
+
+use crate::offline;
+use futures::channel::{mpsc, oneshot};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use talpid_types::tunnel::MockTunnelScript;
+
+const MOCK_TUNNEL_ENV_VAR: &str = "MULLVAD_MOCK_TUNNEL";
+
+/// How long connection attempts take unless a script says otherwise.
+const DEFAULT_CONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// Returns whether the fakes should be used in place of the real tunnel, firewall, DNS and
+/// routing.
+pub fn is_enabled() -> bool {
+    std::env::var(MOCK_TUNNEL_ENV_VAR)
+        .map(|value| value == "1")
+        .unwrap_or(false)
+}
+
+/// Handle used to change the failures that the mock tunnel simulates while it runs.
+#[derive(Clone)]
+pub struct MockTunnelControl {
+    state: Arc<Mutex<ControlState>>,
+}
+
+struct ControlState {
+    connect_delay: Duration,
+    /// Number of upcoming connection attempts that fail.
+    fail_connects: u32,
+    /// Takes down the tunnel that is up, if any.
+    drop_tunnel_tx: Option<oneshot::Sender<()>>,
+    offline_monitor: Option<offline::mock::MonitorHandle>,
+}
+
+/// How the next connection attempt of the mock tunnel should go.
+pub(crate) struct MockAttempt {
+    pub delay: Duration,
+    pub fail: bool,
+}
+
+impl MockTunnelControl {
+    pub(super) fn new() -> Self {
+        MockTunnelControl {
+            state: Arc::new(Mutex::new(ControlState {
+                connect_delay: DEFAULT_CONNECT_DELAY,
+                fail_connects: 0,
+                drop_tunnel_tx: None,
+                offline_monitor: None,
+            })),
+        }
+    }
+
+    /// Makes the mock follow `script` from now on. Failures that were scripted before are
+    /// replaced.
+    pub fn run_script(&self, script: MockTunnelScript) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(delay) = script.connect_delay {
+            state.connect_delay = delay;
+        }
+        state.fail_connects = script.fail_connects;
+        if script.drop_tunnel {
+            match state.drop_tunnel_tx.take() {
+                Some(drop_tunnel_tx) => {
+                    let _ = drop_tunnel_tx.send(());
+                }
+                None => log::warn!("No mock tunnel is up, so none can be dropped"),
+            }
+        }
+        if let (Some(offline), Some(monitor)) = (script.offline, &state.offline_monitor) {
+            monitor.set_offline(offline);
+        }
+    }
+
+    /// Returns an offline monitor that is only changed by scripts.
+    pub(super) fn offline_monitor(
+        &self,
+        notify_tx: mpsc::UnboundedSender<bool>,
+    ) -> offline::MonitorHandle {
+        let monitor = offline::mock::MonitorHandle::new(notify_tx);
+        self.state.lock().unwrap().offline_monitor = Some(monitor.clone());
+        monitor.into()
+    }
+
+    /// Consumes one of the scripted failures, if any are left.
+    pub(crate) fn next_attempt(&self) -> MockAttempt {
+        let mut state = self.state.lock().unwrap();
+        let fail = state.fail_connects > 0;
+        state.fail_connects = state.fail_connects.saturating_sub(1);
+        MockAttempt {
+            delay: state.connect_delay,
+            fail,
+        }
+    }
+
+    /// Registers a tunnel that has come up. The returned channel is signalled when a script
+    /// drops it.
+    pub(crate) fn tunnel_up(&self) -> oneshot::Receiver<()> {
+        let (drop_tunnel_tx, drop_tunnel_rx) = oneshot::channel();
+        self.state.lock().unwrap().drop_tunnel_tx = Some(drop_tunnel_tx);
+        drop_tunnel_rx
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        tunnel::mock::{MOCK_GATEWAY, MOCK_TUNNEL_INTERFACE},
+        tunnel_state_machine::{
+            InitialTunnelState, TunnelCommand, TunnelParametersGenerator, TunnelStateMachine,
+            TunnelStateMachineInitArgs,
+        },
+    };
+    use futures::{future, StreamExt};
+    use std::{
+        future::Future,
+        net::{IpAddr, Ipv4Addr},
+        pin::Pin,
+    };
+    use talpid_tunnel::tun_provider::TunProvider;
+    use talpid_types::{
+        net::{
+            wireguard, AllowedEndpoint, DnsSource, Endpoint, GenericTunnelOptions, NetworkChange,
+            TransportProtocol, TunnelParameters,
+        },
+        tunnel::{
+            ActionAfterDisconnect, ConnectingPhase, ConnectionAttempt, ErrorStateCause,
+            ParameterGenerationError, RetryBackoff, TunnelStateTransition,
+        },
+    };
+
+    struct TestGenerator;
+
+    impl TunnelParametersGenerator for TestGenerator {
+        fn generate(
+            &mut self,
+            _retry_attempt: u32,
+        ) -> Pin<Box<dyn Future<Output = Result<TunnelParameters, ParameterGenerationError>>>>
+        {
+            let private_key = wireguard::PrivateKey::new_from_random();
+            let parameters = wireguard::TunnelParameters {
+                connection: wireguard::ConnectionConfig {
+                    tunnel: wireguard::TunnelConfig {
+                        private_key: private_key.clone(),
+                        addresses: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                    },
+                    peer: wireguard::PeerConfig {
+                        public_key: private_key.public_key(),
+                        allowed_ips: talpid_types::net::all_of_the_internet(),
+                        endpoint: "192.0.2.1:51820".parse().unwrap(),
+                        psk: None,
+                    },
+                    exit_peer: None,
+                    ipv4_gateway: MOCK_GATEWAY,
+                    ipv6_gateway: None,
+                    #[cfg(target_os = "linux")]
+                    fwmark: None,
+                },
+                options: wireguard::TunnelOptions {
+                    mtu: None,
+                    quantum_resistant: false,
+                },
+                generic_options: GenericTunnelOptions { enable_ipv6: false },
+                obfuscation: None,
+            };
+            Box::pin(future::ready(Ok(parameters.into())))
+        }
+    }
+
+    fn initial_state() -> InitialTunnelState {
+        InitialTunnelState {
+            allow_lan: false,
+            block_when_disconnected: false,
+            dns_servers: None,
+            dns_source: DnsSource::Default,
+            tls_dns_servers: vec![],
+            preserve_search_domains: false,
+            allow_lan_dns_when_blocked: false,
+            keep_custom_dns_while_disconnected: false,
+            retry_backoff: RetryBackoff {
+                initial: Duration::from_millis(10),
+                multiplier: 1.0,
+                max: Duration::from_millis(10),
+                reset_after_connected_for: Duration::from_secs(60),
+            },
+            wait_for_network_at_startup: false,
+            bypass_routes: vec![],
+            allowed_endpoint: AllowedEndpoint {
+                #[cfg(windows)]
+                clients: vec![],
+                endpoint: Endpoint::new(Ipv4Addr::new(192, 0, 2, 2), 443, TransportProtocol::Tcp),
+            },
+            reset_firewall: true,
+            #[cfg(target_os = "linux")]
+            excluded_owners: Default::default(),
+            #[cfg(target_os = "linux")]
+            split_tunnel: Default::default(),
+        }
+    }
+
+    /// The real state machine, running on top of the mock tunnel.
+    struct TestStateMachine {
+        commands: Arc<mpsc::UnboundedSender<TunnelCommand>>,
+        control: MockTunnelControl,
+        transitions: mpsc::UnboundedReceiver<TunnelStateTransition>,
+    }
+
+    impl TestStateMachine {
+        async fn start(fail_connects: u32) -> Self {
+            let control = MockTunnelControl::new();
+            control.run_script(MockTunnelScript {
+                fail_connects,
+                connect_delay: Some(Duration::from_millis(10)),
+                ..Default::default()
+            });
+
+            let (commands, commands_rx) = mpsc::unbounded();
+            let commands = Arc::new(commands);
+            let (offline_state_tx, _) = mpsc::unbounded();
+            let (dns_tampered_tx, _) = mpsc::unbounded();
+            let (effective_dns_tx, _) = mpsc::unbounded();
+            let (network_change_tx, _) = mpsc::unbounded();
+            let args = TunnelStateMachineInitArgs {
+                settings: initial_state(),
+                command_tx: Arc::downgrade(&commands),
+                offline_state_tx,
+                dns_tampered_tx,
+                effective_dns_tx,
+                network_change_tx,
+                tunnel_parameters_generator: TestGenerator,
+                tun_provider: TunProvider::new(),
+                log_dir: None,
+                resource_dir: std::env::temp_dir(),
+                commands_rx,
+                #[cfg(target_os = "linux")]
+                cache_dir: std::env::temp_dir(),
+                #[cfg(target_os = "linux")]
+                linux_ids: crate::tunnel_state_machine::LinuxNetworkingIdentifiers {
+                    fwmark: 0x6d6f6c65,
+                    table_id: 0x6d6f6c65,
+                    rule_priority: None,
+                },
+                mock_tunnel: Some(control.clone()),
+            };
+            let state_machine = TunnelStateMachine::new(args).await.unwrap();
+
+            let (transitions_tx, transitions) = mpsc::unbounded();
+            tokio::task::spawn_blocking(move || state_machine.run(transitions_tx));
+
+            Self {
+                commands,
+                control,
+                transitions,
+            }
+        }
+
+        fn send(&self, command: TunnelCommand) {
+            self.commands.unbounded_send(command).unwrap();
+        }
+
+        async fn next(&mut self) -> TunnelStateTransition {
+            tokio::time::timeout(Duration::from_secs(5), self.transitions.next())
+                .await
+                .expect("no state transition")
+                .unwrap()
+        }
+
+        /// Skips to the next connected or error state, and returns the attempts that were made
+        /// on the way.
+        async fn attempts_until_settled(
+            &mut self,
+        ) -> (Vec<ConnectionAttempt>, TunnelStateTransition) {
+            let mut attempts = vec![];
+            loop {
+                match self.next().await {
+                    TunnelStateTransition::Connecting(
+                        _,
+                        ConnectingPhase::EstablishingTunnel,
+                        attempt,
+                    ) => attempts.push(attempt),
+                    TunnelStateTransition::Connecting(..)
+                    | TunnelStateTransition::Disconnecting(_) => (),
+                    transition => return (attempts, transition),
+                }
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_connect_and_disconnect() {
+        let mut state_machine = TestStateMachine::start(0).await;
+        state_machine.send(TunnelCommand::Connect);
+
+        let (attempts, transition) = state_machine.attempts_until_settled().await;
+        assert!(attempts.iter().map(|attempt| attempt.attempt).eq([1]));
+        let TunnelStateTransition::Connected(endpoint, dns) = transition else {
+            panic!("expected the connected state, got {transition:?}");
+        };
+        assert_eq!(
+            endpoint.tunnel_interface.as_deref(),
+            Some(MOCK_TUNNEL_INTERFACE)
+        );
+        assert_eq!(dns.servers, [IpAddr::from(MOCK_GATEWAY)]);
+
+        state_machine.send(TunnelCommand::Disconnect);
+        assert!(matches!(
+            state_machine.next().await,
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Nothing)
+        ));
+        assert!(matches!(
+            state_machine.next().await,
+            TunnelStateTransition::Disconnected
+        ));
+    }
+
+    /// Each failed attempt enters the connecting state again with the next retry attempt, and
+    /// the attempts are given longer to connect, up to a limit.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_connection_attempt_across_failures() {
+        let mut state_machine = TestStateMachine::start(4).await;
+        state_machine.send(TunnelCommand::Connect);
+
+        let (attempts, transition) = state_machine.attempts_until_settled().await;
+        assert!(matches!(transition, TunnelStateTransition::Connected(..)));
+
+        let numbers = attempts.iter().map(|attempt| attempt.attempt);
+        assert!(numbers.eq(1..=5));
+        let timeouts = attempts
+            .iter()
+            .map(|attempt| attempt.retry_timeout.unwrap().as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(timeouts, [4, 8, 15, 15, 15]);
+    }
+
+    /// A WireGuard tunnel is kept when the network changes.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_network_change_moves_wireguard_tunnel() {
+        let mut state_machine = TestStateMachine::start(0).await;
+        state_machine.send(TunnelCommand::Connect);
+        let (_, transition) = state_machine.attempts_until_settled().await;
+        assert!(matches!(transition, TunnelStateTransition::Connected(..)));
+
+        state_machine.send(TunnelCommand::NetworkChanged(NetworkChange::default()));
+        state_machine.send(TunnelCommand::Disconnect);
+        assert!(matches!(
+            state_machine.next().await,
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Nothing)
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dropped_tunnel_and_offline() {
+        let mut state_machine = TestStateMachine::start(0).await;
+        state_machine.send(TunnelCommand::Connect);
+        let (_, transition) = state_machine.attempts_until_settled().await;
+        assert!(matches!(transition, TunnelStateTransition::Connected(..)));
+
+        // The tunnel goes down and is reconnected
+        state_machine.control.run_script(MockTunnelScript {
+            drop_tunnel: true,
+            ..Default::default()
+        });
+        let (attempts, transition) = state_machine.attempts_until_settled().await;
+        assert!(attempts.iter().map(|attempt| attempt.attempt).eq([1]));
+        assert!(matches!(transition, TunnelStateTransition::Connected(..)));
+
+        // Traffic is blocked while offline, and the tunnel is reconnected when the device is back
+        state_machine.control.run_script(MockTunnelScript {
+            offline: Some(true),
+            ..Default::default()
+        });
+        let (_, transition) = state_machine.attempts_until_settled().await;
+        let TunnelStateTransition::Error(error_state) = transition else {
+            panic!("expected the error state, got {transition:?}");
+        };
+        assert!(matches!(error_state.cause(), ErrorStateCause::IsOffline));
+        assert!(error_state.is_blocking());
+
+        state_machine.control.run_script(MockTunnelScript {
+            offline: Some(false),
+            ..Default::default()
+        });
+        let (_, transition) = state_machine.attempts_until_settled().await;
+        assert!(matches!(transition, TunnelStateTransition::Connected(..)));
+    }
+}
@@ -3,6 +3,12 @@ mod connecting_state;
 mod disconnected_state;
 mod disconnecting_state;
 mod error_state;
+#[cfg(feature = "mock-tunnel")]
+pub mod mock;
+mod network_debounce;
+mod network_wait;
+mod retry_backoff;
+mod startup_gate;
 
 use self::{
     connected_state::{ConnectedState, ConnectedStateBootstrap},
@@ -11,23 +17,23 @@ use self::{
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
 };
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 use crate::split_tunnel;
 use crate::{
-    dns::DnsMonitor,
+    dns::{DnsMonitor, DnsTamperedSender},
     firewall::{Firewall, FirewallArguments, InitialFirewallState},
     mpsc::Sender,
     offline,
+    tunnel::TrafficCounters,
 };
-#[cfg(windows)]
-use std::ffi::OsString;
-use talpid_routing::RouteManager;
+use talpid_routing::{RouteManager, RouteManagerHandle};
 use talpid_tunnel::{tun_provider::TunProvider, TunnelEvent};
 
 use futures::{
     channel::{mpsc, oneshot},
     stream, StreamExt,
 };
+use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
 use std::{
@@ -37,13 +43,18 @@ use std::{
     path::PathBuf,
     pin::Pin,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 #[cfg(target_os = "android")]
-use talpid_types::{android::AndroidContext, ErrorExt};
+use talpid_types::android::AndroidContext;
+#[cfg(target_os = "windows")]
+use talpid_types::split_tunnel::{AppExclusionStatus, ExcludedProcess};
 use talpid_types::{
-    net::{AllowedEndpoint, TunnelParameters},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    net::{
+        AllowedEndpoint, DnsSource, EffectiveDns, NetworkChange, TlsDnsServer, TunnelParameters,
+    },
+    tunnel::{ErrorStateCause, ParameterGenerationError, RetryBackoff, TunnelStateTransition},
+    ErrorExt,
 };
 
 const TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
@@ -94,14 +105,39 @@ pub struct InitialTunnelState {
     pub block_when_disconnected: bool,
     /// DNS servers to use. If `None`, the tunnel gateway is used.
     pub dns_servers: Option<Vec<IpAddr>>,
+    /// Where `dns_servers` come from.
+    pub dns_source: DnsSource,
+    /// DNS-over-TLS servers to use. These take precedence over `dns_servers`.
+    pub tls_dns_servers: Vec<TlsDnsServer>,
+    /// Whether the search domains of other interfaces should also be used for lookups through the
+    /// tunnel.
+    pub preserve_search_domains: bool,
+    /// Whether custom DNS servers on the LAN should be reachable in the blocked states.
+    pub allow_lan_dns_when_blocked: bool,
+    /// Whether custom DNS servers should keep being used in the unblocked disconnected state.
+    pub keep_custom_dns_while_disconnected: bool,
+    /// How long consecutive connection attempts are spaced apart.
+    pub retry_backoff: RetryBackoff,
+    /// Whether the first connection attempt should wait for the network to come up, e.g. because
+    /// the daemon is auto-connecting at boot.
+    pub wait_for_network_at_startup: bool,
+    /// Networks that are routed outside the tunnel, via the default route, while connecting and
+    /// connected. Only used on Linux and macOS.
+    pub bypass_routes: Vec<IpNetwork>,
     /// A single endpoint that is allowed to communicate outside the tunnel, i.e.
     /// in any of the blocking states.
     pub allowed_endpoint: AllowedEndpoint,
     /// Whether to reset any existing firewall rules when initializing the disconnected state.
     pub reset_firewall: bool,
+    /// Users and groups whose traffic is excluded from the tunnel.
+    #[cfg(target_os = "linux")]
+    pub excluded_owners: split_tunnel::ExcludedOwners,
+    /// Split tunnel settings that only affect the firewall rules.
+    #[cfg(target_os = "linux")]
+    pub split_tunnel: split_tunnel::Config,
     /// Programs to exclude from the tunnel using the split tunnel driver.
     #[cfg(windows)]
-    pub exclude_paths: Vec<OsString>,
+    pub exclude_apps: split_tunnel::ExcludedApps,
 }
 
 /// Identifiers for various network resources that should be unique to a given instance of a tunnel
@@ -113,9 +149,13 @@ pub struct LinuxNetworkingIdentifiers {
     /// The table ID will be used for the routing table that will route all traffic through the
     /// tunnel interface.
     pub table_id: u32,
+    /// Priority of the routing rules that direct traffic to the routing table. If `None`, the
+    /// kernel assigns the priorities.
+    pub rule_priority: Option<u32>,
 }
 
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
+#[allow(clippy::too_many_arguments)]
 pub async fn spawn(
     initial_settings: InitialTunnelState,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
@@ -123,10 +163,26 @@ pub async fn spawn(
     resource_dir: PathBuf,
     state_change_listener: impl Sender<TunnelStateTransition> + Send + 'static,
     offline_state_listener: mpsc::UnboundedSender<bool>,
+    dns_tampered_listener: DnsTamperedSender,
+    effective_dns_listener: mpsc::UnboundedSender<EffectiveDns>,
+    network_change_listener: mpsc::UnboundedSender<NetworkChange>,
     #[cfg(target_os = "windows")] volume_update_rx: mpsc::UnboundedReceiver<()>,
+    #[cfg(target_os = "windows")] app_status_listener: mpsc::UnboundedSender<
+        Vec<AppExclusionStatus>,
+    >,
+    #[cfg(target_os = "windows")] excluded_processes_listener: mpsc::UnboundedSender<
+        Vec<ExcludedProcess>,
+    >,
     #[cfg(target_os = "android")] android_context: AndroidContext,
+    #[cfg(target_os = "linux")] cache_dir: PathBuf,
     #[cfg(target_os = "linux")] linux_ids: LinuxNetworkingIdentifiers,
 ) -> Result<TunnelStateMachineHandle, Error> {
+    #[cfg(feature = "mock-tunnel")]
+    let mock_tunnel = mock::is_enabled().then(|| {
+        log::warn!("Using the mock tunnel. No tunnels are created, and traffic is never blocked");
+        mock::MockTunnelControl::new()
+    });
+
     let (command_tx, command_rx) = mpsc::unbounded();
     let command_tx = Arc::new(command_tx);
 
@@ -153,6 +209,9 @@ pub async fn spawn(
         settings: initial_settings,
         command_tx: weak_command_tx,
         offline_state_tx: offline_state_listener,
+        dns_tampered_tx: dns_tampered_listener,
+        effective_dns_tx: effective_dns_listener,
+        network_change_tx: network_change_listener,
         tunnel_parameters_generator,
         tun_provider,
         log_dir,
@@ -160,16 +219,26 @@ pub async fn spawn(
         commands_rx: command_rx,
         #[cfg(target_os = "windows")]
         volume_update_rx,
+        #[cfg(target_os = "windows")]
+        app_status_tx: app_status_listener,
+        #[cfg(target_os = "windows")]
+        excluded_processes_tx: excluded_processes_listener,
         #[cfg(target_os = "android")]
         android_context,
         #[cfg(target_os = "linux")]
+        cache_dir,
+        #[cfg(target_os = "linux")]
         linux_ids,
+        #[cfg(feature = "mock-tunnel")]
+        mock_tunnel: mock_tunnel.clone(),
     };
 
     let state_machine = TunnelStateMachine::new(init_args).await?;
 
     #[cfg(windows)]
     let split_tunnel = state_machine.shared_values.split_tunnel.handle();
+    let route_manager = state_machine.shared_values.route_manager.handle()?;
+    let traffic_counters = state_machine.shared_values.traffic_counters.clone();
 
     tokio::task::spawn_blocking(move || {
         state_machine.run(state_change_listener);
@@ -183,6 +252,10 @@ pub async fn spawn(
         shutdown_rx,
         #[cfg(windows)]
         split_tunnel,
+        route_manager,
+        traffic_counters,
+        #[cfg(feature = "mock-tunnel")]
+        mock_tunnel,
     })
 }
 
@@ -194,12 +267,37 @@ pub enum TunnelCommand {
     /// channel after attempting to set the firewall policy, regardless
     /// of whether it succeeded.
     AllowEndpoint(AllowedEndpoint, oneshot::Sender<()>),
-    /// Set DNS servers to use.
-    Dns(Option<Vec<IpAddr>>),
+    /// Set DNS servers to use, and where they come from.
+    Dns(Option<Vec<IpAddr>>, DnsSource),
+    /// Set DNS-over-TLS servers to use. These take precedence over the servers set using `Dns`.
+    TlsDnsServers(Vec<TlsDnsServer>),
+    /// Enable or disable using the search domains of other interfaces for lookups through the
+    /// tunnel.
+    PreserveSearchDomains(bool),
+    /// Enable or disable access to custom DNS servers on the LAN in the blocked states.
+    AllowLanDnsWhenBlocked(bool),
+    /// Enable or disable keeping custom DNS servers in the unblocked disconnected state.
+    KeepCustomDnsWhileDisconnected(bool),
+    /// Set networks that are routed outside the tunnel while connecting and connected.
+    BypassRoutes(Vec<IpNetwork>),
+    /// Set users and groups whose traffic is excluded from the tunnel.
+    #[cfg(target_os = "linux")]
+    ExcludedOwners(split_tunnel::ExcludedOwners),
+    /// Set the split tunnel settings that only affect the firewall rules.
+    #[cfg(target_os = "linux")]
+    SplitTunnelConfig(split_tunnel::Config),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
+    /// Set how long consecutive connection attempts are spaced apart.
+    RetryBackoff(RetryBackoff),
     /// Notify the state machine of the connectivity of the device.
     IsOffline(bool),
+    /// Notify the state machine that the network outside the tunnel changed.
+    #[cfg(not(target_os = "android"))]
+    NetworkChanged(NetworkChange),
+    /// Notify the state machine that the host has resumed from sleep.
+    #[cfg(not(target_os = "android"))]
+    Resumed,
     /// Open tunnel connection.
     Connect,
     /// Close tunnel connection.
@@ -209,11 +307,15 @@ pub enum TunnelCommand {
     /// Bypass a socket, allowing traffic to flow through outside the tunnel.
     #[cfg(target_os = "android")]
     BypassSocket(RawFd, oneshot::Sender<()>),
+    /// Update the resolvers that DNS requests from excluded processes are redirected to, since
+    /// they may have changed.
+    #[cfg(target_os = "linux")]
+    RefreshOriginalResolvers,
     /// Set applications that are allowed to send and receive traffic outside of the tunnel.
     #[cfg(windows)]
     SetExcludedApps(
         oneshot::Sender<Result<(), split_tunnel::Error>>,
-        Vec<OsString>,
+        split_tunnel::ExcludedApps,
     ),
 }
 
@@ -222,7 +324,7 @@ type TunnelCommandReceiver = stream::Fuse<mpsc::UnboundedReceiver<TunnelCommand>
 enum EventResult {
     Command(Option<TunnelCommand>),
     Event(Option<(TunnelEvent, oneshot::Sender<()>)>),
-    Close(Result<Option<ErrorStateCause>, oneshot::Canceled>),
+    Close(Result<Option<connecting_state::TunnelCloseReason>, oneshot::Canceled>),
 }
 
 /// Asynchronous handling of the tunnel state machine.
@@ -242,6 +344,11 @@ struct TunnelStateMachineInitArgs<G: TunnelParametersGenerator> {
     settings: InitialTunnelState,
     command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
     offline_state_tx: mpsc::UnboundedSender<bool>,
+    dns_tampered_tx: DnsTamperedSender,
+    effective_dns_tx: mpsc::UnboundedSender<EffectiveDns>,
+    /// Receives changes to the non-tunnel network. Changes are not reported on Android.
+    #[cfg_attr(target_os = "android", allow(dead_code))]
+    network_change_tx: mpsc::UnboundedSender<NetworkChange>,
     tunnel_parameters_generator: G,
     tun_provider: TunProvider,
     log_dir: Option<PathBuf>,
@@ -249,10 +356,22 @@ struct TunnelStateMachineInitArgs<G: TunnelParametersGenerator> {
     commands_rx: mpsc::UnboundedReceiver<TunnelCommand>,
     #[cfg(target_os = "windows")]
     volume_update_rx: mpsc::UnboundedReceiver<()>,
+    /// Receives the exclusion state of each application whenever it changes.
+    #[cfg(target_os = "windows")]
+    app_status_tx: mpsc::UnboundedSender<Vec<AppExclusionStatus>>,
+    /// Receives the processes that are excluded from the tunnel whenever they change.
+    #[cfg(target_os = "windows")]
+    excluded_processes_tx: mpsc::UnboundedSender<Vec<ExcludedProcess>>,
     #[cfg(target_os = "android")]
     android_context: AndroidContext,
+    /// Directory where the original DNS config is kept, so that it can be restored after a crash.
+    #[cfg(target_os = "linux")]
+    cache_dir: PathBuf,
     #[cfg(target_os = "linux")]
     linux_ids: LinuxNetworkingIdentifiers,
+    /// Replaces the tunnel, firewall, DNS, routing and offline monitor with fakes, if set.
+    #[cfg(feature = "mock-tunnel")]
+    mock_tunnel: Option<mock::MockTunnelControl>,
 }
 
 impl TunnelStateMachine {
@@ -269,14 +388,7 @@ impl TunnelStateMachine {
         #[cfg(target_os = "macos")]
         let filtering_resolver = crate::resolver::start_resolver().await?;
 
-        let route_manager = RouteManager::new(
-            #[cfg(target_os = "linux")]
-            args.linux_ids.fwmark,
-            #[cfg(target_os = "linux")]
-            args.linux_ids.table_id,
-        )
-        .await
-        .map_err(Error::InitRouteManagerError)?;
+        let route_manager = Self::new_route_manager(&args).await?;
 
         #[cfg(windows)]
         let split_tunnel = split_tunnel::SplitTunnel::new(
@@ -284,6 +396,8 @@ impl TunnelStateMachine {
             args.resource_dir.clone(),
             args.command_tx.clone(),
             volume_update_rx,
+            args.app_status_tx,
+            args.excluded_processes_tx,
             route_manager
                 .handle()
                 .map_err(Error::InitRouteManagerError)?,
@@ -300,51 +414,91 @@ impl TunnelStateMachine {
             allow_lan: args.settings.allow_lan,
             #[cfg(target_os = "linux")]
             fwmark: args.linux_ids.fwmark,
+            #[cfg(target_os = "linux")]
+            excluded_owners: args.settings.excluded_owners.clone(),
+            #[cfg(target_os = "linux")]
+            split_tunnel: args.settings.split_tunnel.clone(),
         };
 
-        let firewall = Firewall::from_args(fw_args).map_err(Error::InitFirewallError)?;
+        let firewall = Self::new_firewall(&args, fw_args)?;
 
-        let dns_monitor = DnsMonitor::new(
-            #[cfg(target_os = "linux")]
-            runtime.clone(),
-            #[cfg(target_os = "linux")]
-            route_manager
-                .handle()
-                .map_err(Error::InitRouteManagerError)?,
-            #[cfg(target_os = "macos")]
-            args.command_tx.clone(),
+        let mut dns_monitor = Self::new_dns_monitor(&args, &runtime, &route_manager)?;
+        dns_monitor.set_preserve_search_domains(args.settings.preserve_search_domains);
+        #[cfg(not(target_os = "android"))]
+        let command_tx = args.command_tx.clone();
+
+        let (offline_tx, offline_rx) = mpsc::unbounded();
+        let offline_monitor = Self::spawn_offline_monitor(
+            &args,
+            offline_tx,
+            &route_manager,
+            #[cfg(target_os = "android")]
+            android_context,
         )
-        .map_err(Error::InitDnsMonitorError)?;
+        .await?;
+        let is_offline = offline_monitor.host_is_offline().await;
+        let _ = args.offline_state_tx.unbounded_send(is_offline);
 
-        let (offline_tx, mut offline_rx) = mpsc::unbounded();
-        let initial_offline_state_tx = args.offline_state_tx.clone();
+        // Changes are only acted on once the network has been stable for a while. Monitors that
+        // already wait for that are not debounced twice.
+        let mut offline_rx = if offline_monitor.debounces_changes() {
+            offline_rx.boxed()
+        } else {
+            network_debounce::debounce(offline_rx, is_offline, network_debounce::stable_window())
+                .boxed()
+        };
+        let offline_state_tx = args.offline_state_tx;
+        let offline_command_tx = args.command_tx.clone();
         tokio::spawn(async move {
             while let Some(offline) = offline_rx.next().await {
-                if let Some(tx) = args.command_tx.upgrade() {
+                if let Some(tx) = offline_command_tx.upgrade() {
                     let _ = tx.unbounded_send(TunnelCommand::IsOffline(offline));
                 } else {
                     break;
                 }
-                let _ = args.offline_state_tx.unbounded_send(offline);
+                let _ = offline_state_tx.unbounded_send(offline);
             }
         });
-        let offline_monitor = offline::spawn_monitor(
-            offline_tx,
-            #[cfg(not(target_os = "android"))]
+
+        #[cfg(not(target_os = "android"))]
+        match talpid_routing::network_change::listen(
             route_manager.handle()?,
             #[cfg(target_os = "linux")]
-            Some(args.linux_ids.fwmark),
-            #[cfg(target_os = "android")]
-            android_context,
+            args.linux_ids.fwmark,
         )
         .await
-        .map_err(Error::OfflineMonitorError)?;
-        let is_offline = offline_monitor.host_is_offline().await;
-        let _ = initial_offline_state_tx.unbounded_send(is_offline);
+        {
+            Ok(mut network_changes) => {
+                let network_change_tx = args.network_change_tx;
+                let command_tx = command_tx.clone();
+                tokio::spawn(async move {
+                    // The initial network is only reported to the listener, since the tunnel has
+                    // not been set up on a previous network
+                    let mut initial = true;
+                    while let Some(change) = network_changes.next().await {
+                        if let Some(tx) = command_tx.upgrade().filter(|_| !initial) {
+                            let _ =
+                                tx.unbounded_send(TunnelCommand::NetworkChanged(change.clone()));
+                        }
+                        initial = false;
+                        if network_change_tx.unbounded_send(change).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to monitor network changes")
+            ),
+        }
+
+        #[cfg(not(target_os = "android"))]
+        forward_resumes(crate::power::spawn_monitor().boxed(), command_tx.clone());
 
         #[cfg(windows)]
         split_tunnel
-            .set_paths_sync(&args.settings.exclude_paths)
+            .set_apps_sync(&args.settings.exclude_apps)
             .map_err(Error::InitSplitTunneling)?;
 
         let mut shared_values = SharedTunnelStateValues {
@@ -354,20 +508,45 @@ impl TunnelStateMachine {
             firewall,
             dns_monitor,
             route_manager,
-            _offline_monitor: offline_monitor,
+            offline_monitor,
             allow_lan: args.settings.allow_lan,
             block_when_disconnected: args.settings.block_when_disconnected,
             is_offline,
+            reconnect_limiter: network_debounce::ReconnectLimiter::from_env(),
+            stable_network_hold: None,
+            retry_scheduler: retry_backoff::RetryScheduler::new(args.settings.retry_backoff),
+            startup_gate: if args.settings.wait_for_network_at_startup {
+                startup_gate::StartupGate::new(
+                    Instant::now(),
+                    startup_gate::STARTUP_NETWORK_TIMEOUT,
+                )
+            } else {
+                startup_gate::StartupGate::open()
+            },
             dns_servers: args.settings.dns_servers,
+            dns_source: args.settings.dns_source,
+            effective_dns_tx: args.effective_dns_tx,
+            tls_dns_servers: args.settings.tls_dns_servers,
+            #[cfg(not(target_os = "android"))]
+            tls_forwarder: None,
+            #[cfg(not(target_os = "android"))]
+            command_tx,
+            preserve_search_domains: args.settings.preserve_search_domains,
+            allow_lan_dns_when_blocked: args.settings.allow_lan_dns_when_blocked,
+            keep_custom_dns_while_disconnected: args.settings.keep_custom_dns_while_disconnected,
+            bypass_routes: args.settings.bypass_routes,
             allowed_endpoint: args.settings.allowed_endpoint,
             tunnel_parameters_generator: Box::new(args.tunnel_parameters_generator),
             tun_provider: Arc::new(Mutex::new(args.tun_provider)),
             log_dir: args.log_dir,
             resource_dir: args.resource_dir,
+            traffic_counters: TrafficCounters::default(),
             #[cfg(target_os = "linux")]
             connectivity_check_was_enabled: None,
             #[cfg(target_os = "macos")]
             filtering_resolver,
+            #[cfg(feature = "mock-tunnel")]
+            mock_tunnel: args.mock_tunnel,
         };
 
         tokio::task::spawn_blocking(move || {
@@ -384,6 +563,90 @@ impl TunnelStateMachine {
         .unwrap()
     }
 
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    async fn new_route_manager(
+        args: &TunnelStateMachineInitArgs<impl TunnelParametersGenerator>,
+    ) -> Result<RouteManager, Error> {
+        #[cfg(feature = "mock-tunnel")]
+        if args.mock_tunnel.is_some() {
+            return Ok(RouteManager::mock());
+        }
+        RouteManager::new(
+            #[cfg(target_os = "linux")]
+            args.linux_ids.fwmark,
+            #[cfg(target_os = "linux")]
+            args.linux_ids.table_id,
+            #[cfg(target_os = "linux")]
+            args.linux_ids.rule_priority,
+        )
+        .await
+        .map_err(Error::InitRouteManagerError)
+    }
+
+    #[cfg_attr(not(feature = "mock-tunnel"), allow(unused_variables))]
+    fn new_firewall(
+        args: &TunnelStateMachineInitArgs<impl TunnelParametersGenerator>,
+        fw_args: FirewallArguments,
+    ) -> Result<Firewall, Error> {
+        #[cfg(feature = "mock-tunnel")]
+        if args.mock_tunnel.is_some() {
+            return Ok(Firewall::mock());
+        }
+        Firewall::from_args(fw_args).map_err(Error::InitFirewallError)
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn new_dns_monitor(
+        args: &TunnelStateMachineInitArgs<impl TunnelParametersGenerator>,
+        runtime: &tokio::runtime::Handle,
+        route_manager: &RouteManager,
+    ) -> Result<DnsMonitor, Error> {
+        #[cfg(feature = "mock-tunnel")]
+        if args.mock_tunnel.is_some() {
+            return Ok(DnsMonitor::mock());
+        }
+        DnsMonitor::new(
+            #[cfg(target_os = "linux")]
+            runtime.clone(),
+            #[cfg(target_os = "linux")]
+            route_manager
+                .handle()
+                .map_err(Error::InitRouteManagerError)?,
+            #[cfg(target_os = "linux")]
+            args.linux_ids.fwmark,
+            #[cfg(target_os = "linux")]
+            args.cache_dir.clone(),
+            #[cfg(not(target_os = "android"))]
+            args.command_tx.clone(),
+            args.dns_tampered_tx.clone(),
+        )
+        .map_err(Error::InitDnsMonitorError)
+    }
+
+    #[cfg_attr(target_os = "android", allow(unused_variables))]
+    async fn spawn_offline_monitor(
+        args: &TunnelStateMachineInitArgs<impl TunnelParametersGenerator>,
+        offline_tx: mpsc::UnboundedSender<bool>,
+        route_manager: &RouteManager,
+        #[cfg(target_os = "android")] android_context: AndroidContext,
+    ) -> Result<offline::MonitorHandle, Error> {
+        #[cfg(feature = "mock-tunnel")]
+        if let Some(mock_tunnel) = &args.mock_tunnel {
+            return Ok(mock_tunnel.offline_monitor(offline_tx));
+        }
+        offline::spawn_monitor(
+            offline_tx,
+            #[cfg(not(target_os = "android"))]
+            route_manager.handle()?,
+            #[cfg(target_os = "linux")]
+            Some(args.linux_ids.fwmark),
+            #[cfg(target_os = "android")]
+            android_context,
+        )
+        .await
+        .map_err(Error::OfflineMonitorError)
+    }
+
     fn run(mut self, change_listener: impl Sender<TunnelStateTransition> + Send + 'static) {
         use EventConsequence::*;
 
@@ -414,6 +677,25 @@ impl TunnelStateMachine {
     }
 }
 
+/// Sends [`TunnelCommand::Resumed`] to the state machine every time `resumes` yields, until the
+/// state machine has stopped.
+#[cfg(not(target_os = "android"))]
+fn forward_resumes(
+    mut resumes: impl futures::Stream<Item = ()> + Unpin + Send + 'static,
+    command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
+) {
+    tokio::spawn(async move {
+        while resumes.next().await.is_some() {
+            match command_tx.upgrade() {
+                Some(tx) => {
+                    let _ = tx.unbounded_send(TunnelCommand::Resumed);
+                }
+                None => break,
+            }
+        }
+    });
+}
+
 /// Trait for any type that can provide a stream of `TunnelParameters` to the `TunnelStateMachine`.
 pub trait TunnelParametersGenerator: Send + 'static {
     /// Given the number of consecutive failed retry attempts, it should yield a `TunnelParameters`
@@ -436,15 +718,45 @@ struct SharedTunnelStateValues {
     firewall: Firewall,
     dns_monitor: DnsMonitor,
     route_manager: RouteManager,
-    _offline_monitor: offline::MonitorHandle,
+    offline_monitor: offline::MonitorHandle,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
     /// True when the computer is known to be offline.
     is_offline: bool,
+    /// Limits the reconnects caused by the computer coming back online.
+    reconnect_limiter: network_debounce::ReconnectLimiter,
+    /// When the next connection attempt may proceed, if it must be held until the network is
+    /// stable.
+    stable_network_hold: Option<Instant>,
+    /// Decides when connection attempts may start.
+    retry_scheduler: retry_backoff::RetryScheduler,
+    /// Holds the first connection attempt until the network is up.
+    startup_gate: startup_gate::StartupGate,
     /// DNS servers to use (overriding default).
     dns_servers: Option<Vec<IpAddr>>,
+    /// Where the DNS servers come from.
+    dns_source: DnsSource,
+    /// Receives the effective DNS config whenever it changes without leaving the connected state.
+    effective_dns_tx: mpsc::UnboundedSender<EffectiveDns>,
+    /// DNS-over-TLS servers to use (overriding `dns_servers`).
+    tls_dns_servers: Vec<TlsDnsServer>,
+    /// Local stub that relays DNS queries to `tls_dns_servers` while connected.
+    #[cfg(not(target_os = "android"))]
+    tls_forwarder: Option<crate::dns::tls_forwarder::TlsForwarder>,
+    /// Channel used to send commands to the state machine itself.
+    #[cfg(not(target_os = "android"))]
+    command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    /// Whether the search domains of other interfaces should also be used for lookups through the
+    /// tunnel.
+    preserve_search_domains: bool,
+    /// Whether custom DNS servers on the LAN should be reachable in the blocked states.
+    allow_lan_dns_when_blocked: bool,
+    /// Whether custom DNS servers should keep being used in the unblocked disconnected state.
+    keep_custom_dns_while_disconnected: bool,
+    /// Networks that are routed outside the tunnel while connecting and connected.
+    bypass_routes: Vec<IpNetwork>,
     /// Endpoint that should not be blocked by the firewall.
     allowed_endpoint: AllowedEndpoint,
     /// The generator of new `TunnelParameter`s
@@ -455,6 +767,8 @@ struct SharedTunnelStateValues {
     log_dir: Option<PathBuf>,
     /// Resource directory path.
     resource_dir: PathBuf,
+    /// Traffic counters of the current tunnel.
+    traffic_counters: TrafficCounters,
 
     /// NetworkManager's connecitivity check state.
     #[cfg(target_os = "linux")]
@@ -463,9 +777,22 @@ struct SharedTunnelStateValues {
     /// Filtering resolver handle
     #[cfg(target_os = "macos")]
     filtering_resolver: crate::resolver::ResolverHandle,
+
+    /// Tells the mock tunnel how its connection attempts should go, if it is used.
+    #[cfg(feature = "mock-tunnel")]
+    mock_tunnel: Option<mock::MockTunnelControl>,
 }
 
 impl SharedTunnelStateValues {
+    /// Records whether the host is offline. The retry backoff starts over when the network comes
+    /// back, since the attempts that failed while it was down say nothing about the new network.
+    pub fn set_is_offline(&mut self, is_offline: bool) {
+        if self.is_offline && !is_offline {
+            self.retry_scheduler.reset();
+        }
+        self.is_offline = is_offline;
+    }
+
     pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<(), ErrorStateCause> {
         if self.allow_lan != allow_lan {
             self.allow_lan = allow_lan;
@@ -488,10 +815,15 @@ impl SharedTunnelStateValues {
         Ok(())
     }
 
+    /// Returns whether the DNS servers or their source changed.
     pub fn set_dns_servers(
         &mut self,
         dns_servers: Option<Vec<IpAddr>>,
+        dns_source: DnsSource,
     ) -> Result<bool, ErrorStateCause> {
+        let source_changed = self.dns_source != dns_source;
+        self.dns_source = dns_source;
+
         if self.dns_servers != dns_servers {
             self.dns_servers = dns_servers;
 
@@ -515,10 +847,136 @@ impl SharedTunnelStateValues {
 
             Ok(true)
         } else {
-            Ok(false)
+            Ok(source_changed)
+        }
+    }
+
+    /// Returns whether the DNS-over-TLS servers changed. The new servers take effect the next time
+    /// DNS is set.
+    pub fn set_tls_dns_servers(&mut self, tls_dns_servers: Vec<TlsDnsServer>) -> bool {
+        if self.tls_dns_servers != tls_dns_servers {
+            self.tls_dns_servers = tls_dns_servers;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns whether the value changed. The new value takes effect the next time DNS is set.
+    pub fn set_preserve_search_domains(&mut self, preserve_search_domains: bool) -> bool {
+        if self.preserve_search_domains != preserve_search_domains {
+            self.preserve_search_domains = preserve_search_domains;
+            self.dns_monitor
+                .set_preserve_search_domains(preserve_search_domains);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns whether the bypass routes changed. The new routes take effect the next time a tunnel
+    /// is connected.
+    pub fn set_bypass_routes(&mut self, bypass_routes: Vec<IpNetwork>) -> bool {
+        if self.bypass_routes != bypass_routes {
+            self.bypass_routes = bypass_routes;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Sets the users and groups whose traffic is excluded from the tunnel. The firewall rules are
+    /// replaced immediately, in any state.
+    #[cfg(target_os = "linux")]
+    pub fn set_excluded_owners(&mut self, owners: split_tunnel::ExcludedOwners) {
+        if let Err(error) = self.firewall.set_excluded_owners(owners) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to exclude users and groups from the tunnel")
+            );
         }
     }
 
+    /// Sets the split tunnel settings that only affect the firewall rules. The rules are replaced
+    /// immediately, in any state.
+    #[cfg(target_os = "linux")]
+    pub fn set_split_tunnel_config(&mut self, config: split_tunnel::Config) {
+        if let Err(error) = self.firewall.set_split_tunnel_config(config) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set the split tunnel config")
+            );
+        }
+    }
+
+    /// Returns the bypass routes that may be used while connecting to `peer_endpoint`.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn bypass_routes(&self, peer_endpoint: &talpid_types::net::Endpoint) -> Vec<IpNetwork> {
+        crate::firewall::bypass_routes(&self.bypass_routes, peer_endpoint)
+    }
+
+    /// Routes the bypass routes via the default route outside the tunnel. They are removed along
+    /// with the other routes when the tunnel is closed.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn add_bypass_routes(&self, peer_endpoint: &talpid_types::net::Endpoint) {
+        let routes: std::collections::HashSet<_> = self
+            .bypass_routes(peer_endpoint)
+            .into_iter()
+            .map(bypass_route)
+            .collect();
+        if routes.is_empty() {
+            return;
+        }
+        let result = self
+            .route_manager
+            .handle()
+            .and_then(|handle| self.runtime.block_on(handle.add_routes(routes)));
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to add bypass routes")
+            );
+        }
+    }
+
+    /// Returns the custom DNS servers on the LAN that should be reachable in the blocked states.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn blocked_lan_dns_servers(&self) -> Vec<IpAddr> {
+        crate::firewall::blocked_lan_dns_servers(
+            self.dns_servers.as_deref(),
+            self.allow_lan,
+            self.allow_lan_dns_when_blocked,
+        )
+    }
+
+    /// Returns the custom DNS servers that should be used in the disconnected state, if any.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn disconnected_dns_servers(&self) -> Vec<IpAddr> {
+        crate::firewall::disconnected_dns_servers(
+            self.dns_servers.as_deref(),
+            self.dns_source,
+            self.keep_custom_dns_while_disconnected,
+            self.block_when_disconnected,
+        )
+    }
+
+    /// Returns the interface that DNS requests to `server` are sent on outside the tunnel.
+    #[cfg(target_os = "linux")]
+    pub fn non_tunnel_interface(&self, server: IpAddr) -> Option<String> {
+        let handle = self.route_manager.handle().ok()?;
+        let route = self
+            .runtime
+            .block_on(handle.get_destination_route(server, None))
+            .map_err(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain route to DNS server")
+                );
+            })
+            .ok()??;
+        route.get_node().get_device().map(str::to_owned)
+    }
+
     /// NetworkManager's connectivity check can get hung when DNS requests fail, thus the TSM
     /// should always disable it before applying firewall rules. The connectivity check should be
     /// reset whenever the firewall is cleared.
@@ -554,6 +1012,23 @@ impl SharedTunnelStateValues {
     }
 }
 
+/// Returns a route that routes `network` via the default route outside the tunnel.
+#[cfg(target_os = "linux")]
+fn bypass_route(network: IpNetwork) -> talpid_routing::RequiredRoute {
+    // Specific routes in the main table take precedence over the tunnel table, so networks that
+    // are already reachable on another interface keep using it.
+    talpid_routing::RequiredRoute::new(network, talpid_routing::NetNode::DefaultNode)
+        .use_main_table(false)
+        .purpose(talpid_routing::RoutePurpose::Bypass)
+}
+
+/// Returns a route that routes `network` via the default route outside the tunnel.
+#[cfg(target_os = "macos")]
+fn bypass_route(network: IpNetwork) -> talpid_routing::RequiredRoute {
+    talpid_routing::RequiredRoute::new(network, talpid_routing::NetNode::DefaultNode)
+        .purpose(talpid_routing::RoutePurpose::Bypass)
+}
+
 /// Asynchronous result of an attempt to progress a state.
 enum EventConsequence {
     /// Transition to a new state.
@@ -646,6 +1121,10 @@ pub struct TunnelStateMachineHandle {
     shutdown_rx: oneshot::Receiver<()>,
     #[cfg(windows)]
     split_tunnel: split_tunnel::SplitTunnelHandle,
+    route_manager: RouteManagerHandle,
+    traffic_counters: TrafficCounters,
+    #[cfg(feature = "mock-tunnel")]
+    mock_tunnel: Option<mock::MockTunnelControl>,
 }
 
 impl TunnelStateMachineHandle {
@@ -670,4 +1149,21 @@ impl TunnelStateMachineHandle {
     pub fn split_tunnel(&self) -> &split_tunnel::SplitTunnelHandle {
         &self.split_tunnel
     }
+
+    /// Returns a handle to the route manager, for inspecting the applied routes.
+    pub fn route_manager(&self) -> &RouteManagerHandle {
+        &self.route_manager
+    }
+
+    /// Returns the traffic counters of the current tunnel. They are only available for WireGuard
+    /// tunnels.
+    pub fn traffic_counters(&self) -> &TrafficCounters {
+        &self.traffic_counters
+    }
+
+    /// Returns a handle for injecting failures, if the mock tunnel is used.
+    #[cfg(feature = "mock-tunnel")]
+    pub fn mock_tunnel(&self) -> Option<&mock::MockTunnelControl> {
+        self.mock_tunnel.as_ref()
+    }
 }
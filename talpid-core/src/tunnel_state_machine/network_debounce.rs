@@ -0,0 +1,280 @@
+//! Keeps marginal networks from causing reconnect storms. Changes in connectivity are only passed
+//! on to the state machine once the network has been stable for a while, so that a burst of
+//! changes results in at most one reconnect. Reconnects caused by the network coming back are
+//! also limited to a number per minute, after which connection attempts are held until the limit
+//! allows them.
+
+use futures::{stream::FusedStream, FutureExt, Stream, StreamExt};
+use once_cell::sync::Lazy;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Time that the connectivity must remain unchanged before a change is passed on.
+pub const DEFAULT_STABLE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Number of reconnects caused by the network coming back that are allowed per
+/// [`RECONNECT_PERIOD`].
+pub const DEFAULT_MAX_RECONNECTS: u32 = 6;
+
+/// Period over which reconnects are counted.
+pub const RECONNECT_PERIOD: Duration = Duration::from_secs(60);
+
+/// Overrides the stable window, in milliseconds. Zero passes changes on immediately. This does not
+/// affect offline monitors that debounce changes themselves, such as the one on Linux.
+static STABLE_WINDOW: Lazy<Duration> = Lazy::new(|| {
+    std::env::var("TALPID_NETWORK_STABLE_WINDOW_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_STABLE_WINDOW)
+});
+
+/// Overrides the number of reconnects allowed per minute. Zero disables the limit.
+static MAX_RECONNECTS: Lazy<u32> = Lazy::new(|| {
+    std::env::var("TALPID_MAX_NETWORK_RECONNECTS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RECONNECTS)
+});
+
+/// Returns the configured stable window.
+pub fn stable_window() -> Duration {
+    *STABLE_WINDOW
+}
+
+/// Tracks whether the host is offline, and only reports a change once it has lasted for the
+/// stable window.
+#[derive(Debug)]
+pub struct OfflineDebounce {
+    window: Duration,
+    /// The state that was last reported.
+    reported: bool,
+    /// The latest state from the offline monitor and when it was observed, if it differs from
+    /// `reported`.
+    pending: Option<(bool, tokio::time::Instant)>,
+}
+
+impl OfflineDebounce {
+    pub fn new(window: Duration, is_offline: bool) -> Self {
+        Self {
+            window,
+            reported: is_offline,
+            pending: None,
+        }
+    }
+
+    /// Records that the offline monitor observed `is_offline` at `now`. Every change restarts the
+    /// stable window, and returning to the reported state cancels the pending change.
+    pub fn on_change(&mut self, is_offline: bool, now: tokio::time::Instant) {
+        if is_offline == self.reported {
+            self.pending = None;
+        } else if self.pending.map(|(pending, _)| pending) != Some(is_offline) {
+            self.pending = Some((is_offline, now));
+        }
+    }
+
+    /// Returns when the pending change should be reported, if there is one.
+    pub fn deadline(&self) -> Option<tokio::time::Instant> {
+        self.pending.map(|(_, since)| since + self.window)
+    }
+
+    /// Returns the state to report if the pending change has lasted for the stable window at
+    /// `now`.
+    pub fn poll(&mut self, now: tokio::time::Instant) -> Option<bool> {
+        let deadline = self.deadline()?;
+        if now < deadline {
+            return None;
+        }
+        let (is_offline, _) = self.pending.take()?;
+        self.reported = is_offline;
+        Some(is_offline)
+    }
+}
+
+/// Returns a stream of offline states that only contains the changes in `changes` that lasted
+/// for `window`. `is_offline` is the state that the receiver already knows about.
+pub fn debounce(
+    changes: impl Stream<Item = bool> + Unpin,
+    is_offline: bool,
+    window: Duration,
+) -> impl Stream<Item = bool> {
+    let state = (changes.fuse(), OfflineDebounce::new(window, is_offline));
+    futures::stream::unfold(state, |(mut changes, mut debounce)| async move {
+        loop {
+            let deadline = debounce.deadline();
+            // A change that is pending when the monitor goes away is still passed on once it has
+            // lasted for the window
+            if changes.is_terminated() && deadline.is_none() {
+                return None;
+            }
+            let sleep = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => futures::future::pending().await,
+                }
+            }
+            .fuse();
+            futures::pin_mut!(sleep);
+            futures::select! {
+                change = changes.next() => {
+                    if let Some(is_offline) = change {
+                        debounce.on_change(is_offline, tokio::time::Instant::now());
+                    }
+                },
+                () = sleep => {
+                    if let Some(is_offline) = debounce.poll(tokio::time::Instant::now()) {
+                        return Some((is_offline, (changes, debounce)));
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Limits how often the network coming back may cause a reconnect.
+#[derive(Debug)]
+pub struct ReconnectLimiter {
+    max_reconnects: u32,
+    /// When the reconnects in the current period were made, oldest first.
+    reconnects: VecDeque<Instant>,
+}
+
+impl ReconnectLimiter {
+    /// Returns a limiter that allows the configured number of reconnects per minute.
+    pub fn from_env() -> Self {
+        Self::new(*MAX_RECONNECTS)
+    }
+
+    /// Returns a limiter that allows `max_reconnects` per [`RECONNECT_PERIOD`], or any number of
+    /// reconnects if it is zero.
+    pub fn new(max_reconnects: u32) -> Self {
+        Self {
+            max_reconnects,
+            reconnects: VecDeque::new(),
+        }
+    }
+
+    /// Records a reconnect that is requested at `now`. Returns when the connection attempt may
+    /// proceed if it must be held to stay within the limit.
+    pub fn on_reconnect(&mut self, now: Instant) -> Option<Instant> {
+        if self.max_reconnects == 0 {
+            return None;
+        }
+        while self
+            .reconnects
+            .front()
+            .is_some_and(|reconnect| *reconnect + RECONNECT_PERIOD <= now)
+        {
+            self.reconnects.pop_front();
+        }
+
+        // Held reconnects are recorded at the time they are allowed to proceed, so they count
+        // towards the limit of the following period
+        let proceed_at = if self.reconnects.len() < self.max_reconnects as usize {
+            now
+        } else {
+            let index = self.reconnects.len() - self.max_reconnects as usize;
+            (self.reconnects[index] + RECONNECT_PERIOD).max(now)
+        };
+        self.reconnects.push_back(proceed_at);
+        Some(proceed_at).filter(|proceed_at| *proceed_at > now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::mpsc;
+
+    const WINDOW: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn test_change_is_reported_after_window() {
+        let start = tokio::time::Instant::now();
+        let mut debounce = OfflineDebounce::new(WINDOW, false);
+        assert_eq!(debounce.deadline(), None);
+
+        debounce.on_change(true, start);
+        assert_eq!(debounce.deadline(), Some(start + WINDOW));
+        assert_eq!(debounce.poll(start + WINDOW / 2), None);
+        assert_eq!(debounce.poll(start + WINDOW), Some(true));
+        assert_eq!(debounce.poll(start + WINDOW * 2), None);
+
+        // The reported state is not a change
+        debounce.on_change(true, start + WINDOW * 2);
+        assert_eq!(debounce.deadline(), None);
+    }
+
+    #[test]
+    fn test_burst_is_collapsed() {
+        let start = tokio::time::Instant::now();
+        let mut debounce = OfflineDebounce::new(WINDOW, false);
+
+        // Flapping within the window never reaches the state machine
+        for i in 0..10 {
+            let now = start + Duration::from_millis(300) * i;
+            debounce.on_change(i % 2 == 0, now);
+            assert_eq!(debounce.poll(now), None);
+        }
+        assert_eq!(debounce.deadline(), None);
+
+        // A burst that ends in a new state is reported once the last change has lasted
+        let last_change = start + Duration::from_secs(5);
+        debounce.on_change(true, start + Duration::from_secs(4));
+        debounce.on_change(false, start + Duration::from_millis(4500));
+        debounce.on_change(true, last_change);
+        assert_eq!(debounce.poll(start + Duration::from_millis(6500)), None);
+        assert_eq!(debounce.poll(last_change + WINDOW), Some(true));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_debounce_stream() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut debounced = Box::pin(debounce(rx, false, WINDOW));
+
+        let start = tokio::time::Instant::now();
+        tokio::spawn(async move {
+            for is_offline in [true, false, true] {
+                tx.unbounded_send(is_offline).unwrap();
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        });
+
+        // The flapping is reported as a single change once the last state has lasted
+        assert_eq!(debounced.next().await, Some(true));
+        assert_eq!(start.elapsed(), Duration::from_secs(1) + WINDOW);
+        assert_eq!(debounced.next().await, None);
+    }
+
+    #[test]
+    fn test_reconnects_are_limited() {
+        let start = Instant::now();
+        let mut limiter = ReconnectLimiter::new(3);
+
+        for i in 0..3 {
+            assert_eq!(limiter.on_reconnect(start + Duration::from_secs(i)), None);
+        }
+        // The fourth reconnect is held until the first one is a period old
+        let now = start + Duration::from_secs(10);
+        assert_eq!(limiter.on_reconnect(now), Some(start + RECONNECT_PERIOD));
+        assert_eq!(
+            limiter.on_reconnect(now),
+            Some(start + Duration::from_secs(1) + RECONNECT_PERIOD)
+        );
+
+        // Once the network has been stable for a while, reconnects are allowed again
+        let later = start + RECONNECT_PERIOD * 3;
+        assert_eq!(limiter.on_reconnect(later), None);
+    }
+
+    #[test]
+    fn test_unlimited_reconnects() {
+        let now = Instant::now();
+        let mut limiter = ReconnectLimiter::new(0);
+        for _ in 0..100 {
+            assert_eq!(limiter.on_reconnect(now), None);
+        }
+    }
+}
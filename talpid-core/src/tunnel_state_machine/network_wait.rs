@@ -0,0 +1,142 @@
+//! Decides how long a connection attempt is held while the gateway of the default route does not
+//! respond. After resuming from sleep, the routes may look fine before the network is usable, and
+//! an attempt that is made too early times out and wastes a full retry cycle.
+
+use std::time::{Duration, Instant};
+use talpid_routing::GatewayReachability;
+
+/// Time to wait for the gateway to respond to a single probe.
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Time from the start of one probe to the next while an attempt is held.
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum time that an attempt is held. If the gateway still does not respond, the attempt
+/// proceeds anyway, since the gateway may simply be ignoring ARP or NDP.
+pub const MAX_HOLD: Duration = Duration::from_secs(10);
+
+/// What to do after probing the gateway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Proceed with the connection attempt.
+    Proceed,
+    /// Keep holding the attempt, and probe the gateway again at the given time.
+    ProbeAt(Instant),
+}
+
+/// Tracks an attempt that is held until the gateway responds.
+#[derive(Debug)]
+pub struct NetworkWait {
+    started: Instant,
+}
+
+impl NetworkWait {
+    /// Returns a wait if the attempt should be held given the result of the first probe, which
+    /// started at `probe_start`.
+    pub fn start(result: GatewayReachability, probe_start: Instant) -> Option<Self> {
+        match result {
+            GatewayReachability::Unreachable => Some(NetworkWait {
+                started: probe_start,
+            }),
+            GatewayReachability::Reachable | GatewayReachability::Unknown => None,
+        }
+    }
+
+    /// Returns when the gateway should be probed again after the first probe.
+    pub fn first_retry(&self) -> Instant {
+        self.started + PROBE_INTERVAL
+    }
+
+    /// Returns what to do after a probe that started at `probe_start` and finished at `now`.
+    pub fn on_probe(
+        &self,
+        result: GatewayReachability,
+        probe_start: Instant,
+        now: Instant,
+    ) -> Decision {
+        let deadline = self.started + MAX_HOLD;
+        match result {
+            GatewayReachability::Reachable | GatewayReachability::Unknown => Decision::Proceed,
+            GatewayReachability::Unreachable if now >= deadline => Decision::Proceed,
+            GatewayReachability::Unreachable => {
+                Decision::ProbeAt((probe_start + PROBE_INTERVAL).max(now).min(deadline))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use GatewayReachability::*;
+
+    #[test]
+    fn test_no_hold_when_gateway_responds() {
+        let now = Instant::now();
+        assert!(NetworkWait::start(Reachable, now).is_none());
+        // Gateways that cannot be probed do not hold the attempt
+        assert!(NetworkWait::start(Unknown, now).is_none());
+    }
+
+    #[test]
+    fn test_release_when_gateway_responds() {
+        let start = Instant::now();
+        let wait = NetworkWait::start(Unreachable, start).unwrap();
+        assert_eq!(wait.first_retry(), start + PROBE_INTERVAL);
+
+        let probe_start = wait.first_retry();
+        let probe_end = probe_start + PROBE_TIMEOUT;
+        assert_eq!(
+            wait.on_probe(Unreachable, probe_start, probe_end),
+            Decision::ProbeAt(probe_start + PROBE_INTERVAL)
+        );
+
+        let probe_start = probe_start + PROBE_INTERVAL;
+        assert_eq!(
+            wait.on_probe(
+                Reachable,
+                probe_start,
+                probe_start + Duration::from_millis(5)
+            ),
+            Decision::Proceed
+        );
+    }
+
+    #[test]
+    fn test_slow_probe_is_retried_immediately() {
+        let start = Instant::now();
+        let wait = NetworkWait::start(Unreachable, start).unwrap();
+
+        let probe_end = start + PROBE_INTERVAL * 2;
+        assert_eq!(
+            wait.on_probe(Unreachable, start, probe_end),
+            Decision::ProbeAt(probe_end)
+        );
+    }
+
+    #[test]
+    fn test_hold_is_capped() {
+        let start = Instant::now();
+        let wait = NetworkWait::start(Unreachable, start).unwrap();
+
+        // The last probe is scheduled at the deadline rather than after it
+        let probe_start = start + MAX_HOLD - Duration::from_millis(300);
+        assert_eq!(
+            wait.on_probe(
+                Unreachable,
+                probe_start,
+                probe_start + Duration::from_millis(100)
+            ),
+            Decision::ProbeAt(start + MAX_HOLD)
+        );
+
+        assert_eq!(
+            wait.on_probe(
+                Unreachable,
+                start + MAX_HOLD,
+                start + MAX_HOLD + PROBE_TIMEOUT
+            ),
+            Decision::Proceed
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Spaces connection attempts apart according to a [`RetryBackoff`]. The scheduler only decides
+//! when attempts may start. The attempt number that relays are selected from is counted
+//! separately by the state machine, so that the relay selection does not depend on the timing.
+
+use std::time::Instant;
+use talpid_types::tunnel::RetryBackoff;
+
+/// Decides when the next connection attempt may start.
+#[derive(Debug)]
+pub struct RetryScheduler {
+    backoff: RetryBackoff,
+    /// Number of consecutive attempts that did not result in a lasting connection.
+    failures: u32,
+    /// When the previous attempt started, or was allowed to start.
+    last_attempt: Option<Instant>,
+    /// When the tunnel became connected, if it has been connected since the previous attempt.
+    connected_since: Option<Instant>,
+}
+
+impl RetryScheduler {
+    pub fn new(backoff: RetryBackoff) -> Self {
+        Self {
+            backoff,
+            failures: 0,
+            last_attempt: None,
+            connected_since: None,
+        }
+    }
+
+    /// Changes the backoff. Takes effect from the next attempt.
+    pub fn set_backoff(&mut self, backoff: RetryBackoff) {
+        self.backoff = backoff;
+    }
+
+    /// Forgets the previous attempts, so that the next one starts immediately. Used when the user
+    /// disconnects.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.last_attempt = None;
+        self.connected_since = None;
+    }
+
+    /// Records that the tunnel became connected at `now`.
+    pub fn on_connected(&mut self, now: Instant) {
+        self.connected_since = Some(now);
+    }
+
+    /// Records that an attempt is requested at `now`. Returns when the attempt may start if it
+    /// must be held to respect the backoff.
+    pub fn on_attempt(&mut self, now: Instant) -> Option<Instant> {
+        if let Some(connected_since) = self.connected_since.take() {
+            if now.saturating_duration_since(connected_since)
+                >= self.backoff.reset_after_connected_for
+            {
+                self.failures = 0;
+                self.last_attempt = None;
+            }
+        }
+
+        let start = match self.last_attempt {
+            Some(last_attempt) => {
+                self.failures = self.failures.saturating_add(1);
+                (last_attempt + self.backoff.delay(self.failures)).max(now)
+            }
+            None => now,
+        };
+        self.last_attempt = Some(start);
+        Some(start).filter(|start| *start > now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn exponential() -> RetryBackoff {
+        RetryBackoff {
+            initial: Duration::from_secs(1),
+            multiplier: 2.0,
+            max: Duration::from_secs(10),
+            reset_after_connected_for: Duration::from_secs(60),
+        }
+    }
+
+    /// Makes an attempt whenever the previous one is allowed to start, and returns the delays
+    /// between them.
+    fn delays(scheduler: &mut RetryScheduler, start: Instant, count: usize) -> Vec<u64> {
+        let mut now = start;
+        let mut delays = vec![];
+        for _ in 0..count {
+            let proceed_at = scheduler.on_attempt(now).unwrap_or(now);
+            delays.push((proceed_at - now).as_secs());
+            now = proceed_at;
+        }
+        delays
+    }
+
+    #[test]
+    fn test_default_curve() {
+        let start = Instant::now();
+        let mut scheduler = RetryScheduler::new(RetryBackoff::default());
+        assert_eq!(delays(&mut scheduler, start, 5), [0, 1, 1, 1, 1]);
+
+        // An attempt that lasted longer than the delay is not held
+        let later = start + Duration::from_secs(30);
+        assert_eq!(scheduler.on_attempt(later), None);
+    }
+
+    #[test]
+    fn test_curve_is_capped() {
+        let start = Instant::now();
+        let mut scheduler = RetryScheduler::new(exponential());
+        assert_eq!(delays(&mut scheduler, start, 7), [0, 1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn test_reset_after_connected() {
+        let start = Instant::now();
+        let mut scheduler = RetryScheduler::new(exponential());
+        delays(&mut scheduler, start, 4);
+        let connected_at = start + Duration::from_secs(7);
+
+        // A connection that is lost quickly does not reset the backoff
+        scheduler.on_connected(connected_at);
+        let lost_at = connected_at + Duration::from_secs(1);
+        assert_eq!(
+            scheduler.on_attempt(lost_at),
+            Some(start + Duration::from_secs(15))
+        );
+
+        // A connection that lasted resets it
+        let connected_at = start + Duration::from_secs(15);
+        scheduler.on_connected(connected_at);
+        let lost_at = connected_at + Duration::from_secs(60);
+        assert_eq!(scheduler.on_attempt(lost_at), None);
+        assert_eq!(
+            scheduler.on_attempt(lost_at),
+            Some(lost_at + Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let start = Instant::now();
+        let mut scheduler = RetryScheduler::new(exponential());
+        delays(&mut scheduler, start, 5);
+        scheduler.reset();
+        assert_eq!(
+            delays(&mut scheduler, start + Duration::from_secs(20), 3),
+            [0, 1, 2]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Holds the connection attempt that auto-connect makes when the daemon starts until the offline
+//! monitor reports a default route. At boot, the daemon usually starts before the network is up,
+//! and attempts made before then would only fail and grow the retry backoff. The gate opens after
+//! [`STARTUP_NETWORK_TIMEOUT`] regardless, so that the tunnel is never held back indefinitely.
+
+use std::time::{Duration, Instant};
+
+/// Longest time that the first connection attempt is held while waiting for the network.
+pub const STARTUP_NETWORK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct StartupGate {
+    /// When the gate opens regardless of the network. `None` once it has opened.
+    deadline: Option<Instant>,
+}
+
+impl StartupGate {
+    /// Returns a gate that holds attempts until the network is up or until `timeout` after `now`.
+    pub fn new(now: Instant, timeout: Duration) -> Self {
+        Self {
+            deadline: Some(now + timeout),
+        }
+    }
+
+    /// Returns a gate that never holds any attempts.
+    pub fn open() -> Self {
+        Self { deadline: None }
+    }
+
+    /// Returns when an attempt made at `now` should proceed if it must be held for the network.
+    /// The gate opens for good once the host is online or the deadline has passed.
+    pub fn hold(&mut self, now: Instant, is_offline: bool) -> Option<Instant> {
+        match self.deadline {
+            Some(deadline) if is_offline && now < deadline => Some(deadline),
+            _ => {
+                self.deadline = None;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_late_network() {
+        let boot = Instant::now();
+        let mut gate = StartupGate::new(boot, STARTUP_NETWORK_TIMEOUT);
+        let deadline = boot + STARTUP_NETWORK_TIMEOUT;
+
+        assert_eq!(gate.hold(boot, true), Some(deadline));
+        assert_eq!(
+            gate.hold(boot + Duration::from_secs(5), true),
+            Some(deadline)
+        );
+
+        // The network arrives, after which the gate stays open
+        assert_eq!(gate.hold(boot + Duration::from_secs(12), false), None);
+        assert_eq!(gate.hold(boot + Duration::from_secs(13), true), None);
+    }
+
+    #[test]
+    fn test_network_never_arrives() {
+        let boot = Instant::now();
+        let mut gate = StartupGate::new(boot, STARTUP_NETWORK_TIMEOUT);
+
+        assert!(gate.hold(boot + Duration::from_secs(29), true).is_some());
+        assert_eq!(gate.hold(boot + STARTUP_NETWORK_TIMEOUT, true), None);
+        assert_eq!(gate.hold(boot + STARTUP_NETWORK_TIMEOUT * 2, true), None);
+    }
+
+    #[test]
+    fn test_open_gate() {
+        let now = Instant::now();
+        assert_eq!(StartupGate::open().hold(now, true), None);
+        // A gate whose network is already up does not hold the first attempt
+        assert_eq!(
+            StartupGate::new(now, STARTUP_NETWORK_TIMEOUT).hold(now, false),
+            None
+        );
+    }
+}
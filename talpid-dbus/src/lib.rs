@@ -4,6 +4,7 @@ pub use dbus;
 use dbus::blocking::SyncConnection;
 use once_cell::sync::Lazy;
 use std::sync::{Arc, Mutex};
+pub mod logind;
 pub mod network_manager;
 pub mod systemd;
 pub mod systemd_resolved;
@@ -0,0 +1,49 @@
+//! Sleep notifications from systemd-logind.
+
+use dbus::{blocking::SyncConnection, message::MatchRule};
+use std::{thread, time::Duration};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to create a DBus connection")]
+    ConnectError(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to subscribe to the PrepareForSleep signal")]
+    MatchError(#[error(source)] dbus::Error),
+}
+
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const PREPARE_FOR_SLEEP: &str = "PrepareForSleep";
+
+const PROCESS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Calls `callback` with `true` when the host is about to suspend or hibernate, and with `false`
+/// once it has resumed. The signals are received on a separate connection and thread, so that
+/// they are not held up by other users of the shared connection.
+pub fn listen_for_sleep(callback: impl Fn(bool) + Send + Sync + 'static) -> Result<()> {
+    let connection = SyncConnection::new_system().map_err(Error::ConnectError)?;
+
+    let mut match_rule = MatchRule::new_signal(MANAGER_INTERFACE, PREPARE_FOR_SLEEP);
+    match_rule.path = Some(dbus::Path::from(LOGIND_PATH));
+    connection
+        .add_match(
+            match_rule,
+            move |(start,): (bool,), _connection, _message| {
+                callback(start);
+                true
+            },
+        )
+        .map_err(Error::MatchError)?;
+
+    thread::spawn(move || loop {
+        if let Err(error) = connection.process(PROCESS_TIMEOUT) {
+            log::error!("Stopped listening for sleep notifications: {}", error);
+            break;
+        }
+    });
+    Ok(())
+}
@@ -80,12 +80,20 @@ const LINK_INTERFACE: &str = "org.freedesktop.resolve1.Link";
 const MANAGER_INTERFACE: &str = "org.freedesktop.resolve1.Manager";
 const DNS_DOMAINS: &str = "Domains";
 const DNS_SERVERS: &str = "DNS";
+const DEFAULT_ROUTE: &str = "DefaultRoute";
 const GET_LINK_METHOD: &str = "GetLink";
 const SET_DNS_METHOD: &str = "SetDNS";
 const SET_DNS_OVER_TLS_METHOD: &str = "SetDNSOverTLS";
 const SET_DOMAINS_METHOD: &str = "SetDomains";
+const SET_DEFAULT_ROUTE_METHOD: &str = "SetDefaultRoute";
 const REVERT_METHOD: &str = "Revert";
 
+const UNKNOWN_METHOD_ERROR: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const UNKNOWN_OBJECT_ERROR: &str = "org.freedesktop.DBus.Error.UnknownObject";
+const UNKNOWN_PROPERTY_ERROR: &str = "org.freedesktop.DBus.Error.UnknownProperty";
+const INVALID_ARGS_ERROR: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+const NO_SUCH_LINK_ERROR: &str = "org.freedesktop.resolve1.NoSuchLink";
+
 #[derive(Clone)]
 pub struct SystemdResolved {
     pub dbus_connection: Arc<SyncConnection>,
@@ -285,6 +293,115 @@ impl SystemdResolved {
         self.set_link_dns_domains(&link_object_path, domains)
     }
 
+    /// Returns the domains of all links, as well as the global domains, represented as tuples of
+    /// interface index, domain and whether the domain is routing-only. Global domains have an
+    /// interface index of 0.
+    pub fn get_all_domains(&self) -> Result<Vec<(i32, String, bool)>> {
+        self.as_manager_object()
+            .get(MANAGER_INTERFACE, DNS_DOMAINS)
+            .map_err(Error::DBusRpcError)
+    }
+
+    /// Returns the DNS servers of all links, as well as the global DNS servers, represented as
+    /// tuples of interface index and server address. Global servers have an interface index of 0.
+    pub fn get_all_dns(&self) -> Result<Vec<(i32, IpAddr)>> {
+        let servers: Vec<(i32, i32, Vec<u8>)> = self
+            .as_manager_object()
+            .get(MANAGER_INTERFACE, DNS_SERVERS)
+            .map_err(Error::DBusRpcError)?;
+        Ok(servers
+            .into_iter()
+            .filter_map(|(interface_index, _family, addr)| {
+                Some((interface_index, ip_from_bytes(&addr)?))
+            })
+            .collect())
+    }
+
+    /// Returns whether the link is used for lookups that don't match any routing domain. Returns
+    /// `None` if the running version of systemd-resolved lacks this setting.
+    pub fn get_default_route(&self, interface_index: u32) -> Result<Option<bool>> {
+        let link_object_path = self
+            .fetch_link(interface_index)
+            .map_err(|e| Error::GetLinkError(Box::new(e)))?;
+
+        match self
+            .as_link_object(link_object_path)
+            .get(LINK_INTERFACE, DEFAULT_ROUTE)
+        {
+            Ok(default_route) => Ok(Some(default_route)),
+            Err(error)
+                if error.name() == Some(UNKNOWN_PROPERTY_ERROR)
+                    || error.name() == Some(INVALID_ARGS_ERROR) =>
+            {
+                Ok(None)
+            }
+            Err(error) => Err(Error::DBusRpcError(error)),
+        }
+    }
+
+    /// Sets whether the link should be used for lookups that don't match any routing domain.
+    /// Versions of systemd-resolved older than v240 lack this setting, in which case this is a
+    /// no-op.
+    pub fn set_default_route(&self, interface_index: u32, default_route: bool) -> Result<()> {
+        let link_object_path = self
+            .fetch_link(interface_index)
+            .map_err(|e| Error::GetLinkError(Box::new(e)))?;
+
+        self.as_link_object(link_object_path)
+            .method_call(LINK_INTERFACE, SET_DEFAULT_ROUTE_METHOD, (default_route,))
+            .or_else(|error| {
+                if error.name() == Some(UNKNOWN_METHOD_ERROR) {
+                    log::debug!(
+                        "Didn't set DefaultRoute because systemd-resolved doesn't have 'SetDefaultRoute' method. {}",
+                        error
+                    );
+                    Ok(())
+                } else {
+                    Err(error)
+                }
+            })
+            .map_err(Error::DBusRpcError)
+    }
+
+    /// Reverts all DNS settings of a link. Returns `false` if the running version of
+    /// systemd-resolved does not support reverting links, in which case the caller has to reset
+    /// the settings manually. Succeeds if the link no longer exists.
+    pub fn revert_link_by_index(&self, interface_index: u32) -> Result<bool> {
+        let link_object_path = match self.fetch_link(interface_index) {
+            Ok(path) => path,
+            Err(Error::DBusRpcError(error)) if error.name() == Some(NO_SUCH_LINK_ERROR) => {
+                log::trace!(
+                    "Not resetting DNS of interface {} because it no longer exists",
+                    interface_index
+                );
+                return Ok(true);
+            }
+            Err(error) => return Err(Error::GetLinkError(Box::new(error))),
+        };
+
+        match self
+            .as_link_object(link_object_path)
+            .method_call::<(), _, _, _>(LINK_INTERFACE, REVERT_METHOD, ())
+        {
+            Ok(()) => Ok(true),
+            Err(error) if error.name() == Some(UNKNOWN_METHOD_ERROR) => {
+                log::debug!(
+                    "Can't revert link because systemd-resolved doesn't have 'Revert' method. {}",
+                    error
+                );
+                Ok(false)
+            }
+            Err(error) if error.name() == Some(UNKNOWN_OBJECT_ERROR) => {
+                log::trace!(
+                    "Not resetting DNS of interface {} because it no longer exists",
+                    interface_index
+                );
+                Ok(true)
+            }
+            Err(error) => Err(Error::RevertDnsError(interface_index.to_string(), error)),
+        }
+    }
+
     fn fetch_link(&self, interface_index: u32) -> Result<dbus::Path<'static>> {
         self.as_manager_object()
             .method_call(
@@ -349,7 +466,7 @@ impl SystemdResolved {
             .map_err(Error::DBusRpcError)
     }
 
-    fn link_disable_dns_over_tls(&self, interface_index: u32) -> Result<()> {
+    pub fn link_disable_dns_over_tls(&self, interface_index: u32) -> Result<()> {
         let link_object_path = self
             .fetch_link(interface_index)
             .map_err(|e| Error::GetLinkError(Box::new(e)))?;
@@ -358,7 +475,7 @@ impl SystemdResolved {
 
         link_object.method_call(LINK_INTERFACE, SET_DNS_OVER_TLS_METHOD, ("no",))
             .or_else(|error| {
-            if error.name() == Some("org.freedesktop.DBus.Error.UnknownMethod") {
+            if error.name() == Some(UNKNOWN_METHOD_ERROR) {
                 log::debug!(
                     "Didn't disable DNSOverTLS because systemd-resolved doesn't have 'SetDnsOverTLS' method. {}",
                     error);
@@ -384,7 +501,7 @@ impl SystemdResolved {
         let link = self.as_link_object(dns_state.interface_path.clone());
 
         if let Err(error) = link.method_call::<(), _, _, _>(LINK_INTERFACE, REVERT_METHOD, ()) {
-            if error.name() == Some("org.freedesktop.DBus.Error.UnknownObject") {
+            if error.name() == Some(UNKNOWN_OBJECT_ERROR) {
                 log::trace!(
                     "Not resetting DNS of interface {} because it no longer exists",
                     dns_state.interface_index
@@ -24,7 +24,7 @@ use std::{
     time::Duration,
 };
 #[cfg(target_os = "linux")]
-use talpid_routing::{self, RequiredRoute};
+use talpid_routing::{self, RequiredRoute, RoutePurpose};
 use talpid_tunnel::TunnelEvent;
 use talpid_types::{net::openvpn, ErrorExt};
 use tokio::{sync::Mutex, task};
@@ -358,7 +358,11 @@ fn extract_routes(env: &HashMap<String, String>) -> Result<HashSet<RequiredRoute
     let tun_node = talpid_routing::Node::device(tun_interface.to_string());
     let mut routes = HashSet::new();
     for network in &["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()] {
-        routes.insert(RequiredRoute::new(*network, tun_node.clone()).use_main_table(false));
+        routes.insert(
+            RequiredRoute::new(*network, tun_node.clone())
+                .use_main_table(false)
+                .purpose(RoutePurpose::TunnelDefault),
+        );
     }
     Ok(routes)
 }
@@ -1350,6 +1354,64 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn proxy_auth_file_lifecycle() {
+        let no_auth = Some(openvpn::ProxySettings::Remote(
+            openvpn::RemoteProxySettings {
+                address: "1.2.3.4:1080".parse().unwrap(),
+                auth: None,
+            },
+        ));
+        assert!(
+            OpenVpnMonitor::<TestOpenVpnBuilder>::create_proxy_auth_file(&no_auth)
+                .unwrap()
+                .is_none()
+        );
+
+        let proxy_settings = Some(openvpn::ProxySettings::Remote(
+            openvpn::RemoteProxySettings {
+                address: "1.2.3.4:1080".parse().unwrap(),
+                auth: Some(openvpn::ProxyAuth {
+                    username: "user".to_owned(),
+                    password: "pass".to_owned(),
+                }),
+            },
+        ));
+        let proxy_auth_file =
+            OpenVpnMonitor::<TestOpenVpnBuilder>::create_proxy_auth_file(&proxy_settings)
+                .unwrap()
+                .expect("expected a proxy auth file");
+        let path = proxy_auth_file.to_path_buf();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "user\npass\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o400);
+        }
+
+        let builder = TestOpenVpnBuilder {
+            process_handle: Some(TestProcessHandle(0)),
+            ..Default::default()
+        };
+        let openvpn_init_args = OpenVpnTunnelInitArgs {
+            proxy_auth_file: Some(proxy_auth_file),
+            ..create_init_args()
+        };
+        let testee = OpenVpnMonitor::new_internal(
+            builder,
+            openvpn_init_args,
+            TestOpenvpnEventProxy {},
+            #[cfg(windows)]
+            Box::new(TestWintunContext {}),
+        )
+        .unwrap();
+        assert!(path.exists());
+
+        assert!(testee.wait().is_ok());
+        assert!(!path.exists());
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn failed_process_start() {
         let builder = TestOpenVpnBuilder::default();
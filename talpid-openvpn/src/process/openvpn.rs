@@ -148,7 +148,7 @@ impl OpenVpnCommand {
         self
     }
 
-    /// Sets a log file path.
+    /// Sets a log file path. OpenVPN appends to the file, rather than replacing it.
     pub fn log(&mut self, path: impl AsRef<Path>) -> &mut Self {
         self.log = Some(path.as_ref().to_path_buf());
         self
@@ -222,7 +222,7 @@ impl OpenVpnCommand {
         }
 
         if let Some(ref path) = self.log {
-            args.push(OsString::from("--log"));
+            args.push(OsString::from("--log-append"));
             args.push(OsString::from(path))
         }
 
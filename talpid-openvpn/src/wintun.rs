@@ -7,6 +7,7 @@ use std::{
     ptr,
     sync::{Arc, Mutex},
 };
+use talpid_tunnel::network_interface::MetricPin;
 use talpid_types::{win32_err, ErrorExt};
 use widestring::{U16CStr, U16CString};
 use windows_sys::{
@@ -70,6 +71,7 @@ pub struct WintunAdapter {
     dll_handle: Arc<WintunDll>,
     handle: RawHandle,
     name: U16CString,
+    metric_pin: Mutex<Option<MetricPin>>,
 }
 
 impl fmt::Debug for WintunAdapter {
@@ -95,19 +97,21 @@ impl WintunAdapter {
             dll_handle,
             handle,
             name: name.to_owned(),
+            metric_pin: Mutex::new(None),
         };
         adapter.restore_missing_component_id();
         Ok(adapter)
     }
 
     pub fn prepare_interface(&self) {
-        if let Err(error) =
-            talpid_tunnel::network_interface::initialize_interfaces(self.luid(), None)
-        {
-            log::error!(
-                "{}",
-                error.display_chain_with_msg("Failed to set tunnel interface metric"),
-            );
+        match talpid_tunnel::network_interface::initialize_interfaces(self.luid(), None) {
+            Ok(pin) => *self.metric_pin.lock().unwrap() = Some(pin),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set tunnel interface metric"),
+                );
+            }
         }
     }
 
@@ -172,6 +176,8 @@ impl WintunAdapter {
 
 impl Drop for WintunAdapter {
     fn drop(&mut self) {
+        // Restore the interface metric before the adapter is removed
+        let _ = self.metric_pin.lock().unwrap().take();
         unsafe { self.dll_handle.close_adapter(self.handle) };
     }
 }
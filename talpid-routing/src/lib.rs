@@ -4,11 +4,15 @@
 #![deny(rust_2018_idioms)]
 
 use ipnetwork::IpNetwork;
-use std::{fmt, net::IpAddr};
+use std::{collections::HashSet, fmt, net::IpAddr};
+use talpid_types::net::Connectivity;
 
 #[cfg(any(target_os = "windows", target_os = "macos"))]
 mod debounce;
 
+#[cfg(not(target_os = "android"))]
+pub mod network_change;
+
 #[cfg(target_os = "windows")]
 #[path = "windows/mod.rs"]
 mod imp;
@@ -62,6 +66,22 @@ impl Route {
     pub fn get_node(&self) -> &Node {
         &self.node
     }
+
+    /// Returns the destination of the route.
+    pub fn get_prefix(&self) -> IpNetwork {
+        self.prefix
+    }
+
+    /// Returns the metric of the route, if it has one.
+    pub fn get_metric(&self) -> Option<u32> {
+        self.metric
+    }
+
+    /// Returns the ID of the routing table that the route belongs to.
+    #[cfg(target_os = "linux")]
+    pub fn get_table_id(&self) -> u32 {
+        self.table_id
+    }
 }
 
 impl fmt::Display for Route {
@@ -87,6 +107,7 @@ pub struct RequiredRoute {
     /// Specifies whether the route should be added to the main routing table or not.
     #[cfg(target_os = "linux")]
     main_table: bool,
+    purpose: RoutePurpose,
 }
 
 impl RequiredRoute {
@@ -97,15 +118,163 @@ impl RequiredRoute {
             prefix,
             #[cfg(target_os = "linux")]
             main_table: true,
+            purpose: RoutePurpose::Other,
         }
     }
 
+    /// Sets the reason why the route is needed.
+    pub fn purpose(mut self, purpose: RoutePurpose) -> Self {
+        self.purpose = purpose;
+        self
+    }
+
+    /// Returns the node that the route uses.
+    pub fn get_node(&self) -> &NetNode {
+        &self.node
+    }
+
+    /// Returns the reason why the route is needed.
+    pub fn get_purpose(&self) -> RoutePurpose {
+        self.purpose
+    }
+
     /// Sets the routing table ID of the route.
     #[cfg(target_os = "linux")]
     pub fn use_main_table(mut self, main_table: bool) -> Self {
         self.main_table = main_table;
         self
     }
+
+    /// Returns whether the route can be added given the `connectivity` outside the tunnel. Routes
+    /// via the default node need a default route of the same IP version.
+    pub fn is_routable(&self, connectivity: Connectivity) -> bool {
+        match self.node {
+            NetNode::DefaultNode => connectivity.has_route_to(self.prefix.ip()),
+            NetNode::RealNode(_) => true,
+        }
+    }
+}
+
+/// Reason why a [`RequiredRoute`] is needed. Only used to describe the applied routes.
+#[derive(Debug, Default, Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+pub enum RoutePurpose {
+    /// Routes traffic to the relay outside the tunnel.
+    RelayExclusion,
+    /// Routes all other traffic into the tunnel.
+    TunnelDefault,
+    /// Any other route through the tunnel, such as the route to the tunnel gateway.
+    Tunnel,
+    /// Routes a network outside the tunnel on behalf of the user.
+    Bypass,
+    /// A route without a specific purpose.
+    #[default]
+    Other,
+}
+
+/// A route that the route manager has been asked to apply.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DesiredRoute {
+    /// Destination of the route.
+    pub prefix: IpNetwork,
+    /// Node that the route uses.
+    pub node: NetNode,
+    /// Routing table that the route is added to, on platforms with multiple routing tables.
+    pub table: Option<u32>,
+    /// Reason why the route is needed.
+    pub purpose: RoutePurpose,
+}
+
+/// Routes that the route manager has been asked to apply, along with the routes that are actually
+/// found in the routing tables.
+#[derive(Debug, Clone, Default)]
+pub struct RouteDump {
+    /// Routes that the route manager has been asked to apply, ordered by their purpose.
+    pub desired: Vec<DesiredRoute>,
+    /// Routes found in the routing tables used by the route manager, or `None` if they could not
+    /// be listed on this platform.
+    pub kernel: Option<Vec<Route>>,
+}
+
+/// Routes that were added and removed by a single operation of the route manager, such as
+/// applying the routes of a tunnel, clearing them, or following a new default route.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutesUpdate {
+    /// Routes that were added by the operation.
+    pub added: Vec<Route>,
+    /// Routes that were removed by the operation.
+    pub removed: Vec<Route>,
+}
+
+impl RoutesUpdate {
+    /// Returns the routes that are only in `current` as added, and the routes that are only in
+    /// `previous` as removed. The routes are sorted by destination and table.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn between(previous: &HashSet<Route>, current: &HashSet<Route>) -> Self {
+        fn sorted<'a>(routes: impl Iterator<Item = &'a Route>) -> Vec<Route> {
+            let mut routes: Vec<Route> = routes.cloned().collect();
+            routes.sort_by_key(|route| (route.prefix.ip(), route.prefix.prefix(), route.table_id));
+            routes
+        }
+        RoutesUpdate {
+            added: sorted(current.difference(previous)),
+            removed: sorted(previous.difference(current)),
+        }
+    }
+
+    /// Returns whether no routes were added or removed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Whether a gateway responded to ARP or NDP when it was probed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayReachability {
+    /// The gateway responded.
+    Reachable,
+    /// The gateway did not respond before the timeout.
+    Unreachable,
+    /// There is no gateway to probe, or it cannot be probed using the interface that it is on.
+    Unknown,
+}
+
+/// Routes that have been required since the routes were last cleared.
+#[derive(Debug, Default)]
+pub(crate) struct DesiredRoutes {
+    routes: HashSet<RequiredRoute>,
+}
+
+impl DesiredRoutes {
+    pub(crate) fn extend(&mut self, routes: &HashSet<RequiredRoute>) {
+        self.routes.extend(routes.iter().cloned());
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.routes.clear();
+    }
+
+    /// Returns the required routes, using `table` to look up the routing table of each route.
+    pub(crate) fn dump(&self, table: impl Fn(&RequiredRoute) -> Option<u32>) -> Vec<DesiredRoute> {
+        let mut routes: Vec<_> = self
+            .routes
+            .iter()
+            .map(|route| DesiredRoute {
+                prefix: route.prefix,
+                node: route.node.clone(),
+                table: table(route),
+                purpose: route.purpose,
+            })
+            .collect();
+        routes.sort_by_key(|route| {
+            (
+                route.purpose,
+                route.prefix.ip(),
+                route.prefix.prefix(),
+                route.table,
+            )
+        });
+        routes
+    }
 }
 
 /// A NetNode represents a network node - either a real one or a symbolic default one.
@@ -117,7 +286,6 @@ pub enum NetNode {
     RealNode(Node),
     /// A default node is a symbolic node that will resolve to the network node used in the current
     /// most preferable default route
-    #[cfg(not(target_os = "linux"))]
     DefaultNode,
 }
 
@@ -183,3 +351,184 @@ impl fmt::Display for Node {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn routes() -> [RequiredRoute; 3] {
+        [
+            RequiredRoute::new("185.213.154.68/32".parse().unwrap(), NetNode::DefaultNode),
+            RequiredRoute::new(
+                "2a03:1b20:5:f011::a09f/128".parse().unwrap(),
+                NetNode::DefaultNode,
+            ),
+            RequiredRoute::new(
+                "::/0".parse().unwrap(),
+                Node::device("wg0-mullvad".to_owned()),
+            ),
+        ]
+    }
+
+    fn routable(connectivity: Connectivity) -> Vec<bool> {
+        routes()
+            .iter()
+            .map(|route| route.is_routable(connectivity))
+            .collect()
+    }
+
+    #[test]
+    fn test_routable_on_ipv4_only_host() {
+        let connectivity = Connectivity {
+            ipv4: true,
+            ipv6: false,
+        };
+        // The IPv6 default route into the tunnel is still added
+        assert_eq!(routable(connectivity), vec![true, false, true]);
+    }
+
+    #[test]
+    fn test_routable_on_ipv6_only_host() {
+        let connectivity = Connectivity {
+            ipv4: false,
+            ipv6: true,
+        };
+        assert_eq!(routable(connectivity), vec![false, true, true]);
+    }
+
+    #[test]
+    fn test_routable_on_dual_stack_host() {
+        assert_eq!(
+            routable(Connectivity::PRESUME_ONLINE),
+            vec![true, true, true]
+        );
+    }
+
+    fn purposes(desired: &DesiredRoutes) -> Vec<(RoutePurpose, IpNetwork)> {
+        desired
+            .dump(|_| None)
+            .into_iter()
+            .map(|route| (route.purpose, route.prefix))
+            .collect()
+    }
+
+    #[test]
+    fn test_desired_routes_across_connect_cycle() {
+        let relay: IpNetwork = "185.213.154.68/32".parse().unwrap();
+        let bypass: IpNetwork = "10.10.0.0/16".parse().unwrap();
+        let default: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        let tunnel = Node::device("wg0-mullvad".to_owned());
+
+        let mut desired = DesiredRoutes::default();
+        assert!(desired.dump(|_| None).is_empty());
+
+        // Connecting
+        desired.extend(&HashSet::from([
+            RequiredRoute::new(relay, NetNode::DefaultNode).purpose(RoutePurpose::RelayExclusion),
+            RequiredRoute::new(bypass, NetNode::DefaultNode).purpose(RoutePurpose::Bypass),
+        ]));
+        // Connected
+        desired.extend(&HashSet::from([RequiredRoute::new(
+            default,
+            tunnel.clone(),
+        )
+        .purpose(RoutePurpose::TunnelDefault)]));
+        assert_eq!(
+            purposes(&desired),
+            vec![
+                (RoutePurpose::RelayExclusion, relay),
+                (RoutePurpose::TunnelDefault, default),
+                (RoutePurpose::Bypass, bypass),
+            ]
+        );
+
+        let dumped = desired.dump(|route| Some(if route.prefix == default { 1 } else { 254 }));
+        assert_eq!(
+            dumped[1],
+            DesiredRoute {
+                prefix: default,
+                node: NetNode::RealNode(tunnel),
+                table: Some(1),
+                purpose: RoutePurpose::TunnelDefault,
+            }
+        );
+
+        // Disconnected
+        desired.clear();
+        assert!(desired.dump(|_| None).is_empty());
+
+        // Reconnecting does not bring back the previous routes
+        desired.extend(&HashSet::from([RequiredRoute::new(
+            relay,
+            NetNode::DefaultNode,
+        )
+        .purpose(RoutePurpose::RelayExclusion)]));
+        assert_eq!(
+            purposes(&desired),
+            vec![(RoutePurpose::RelayExclusion, relay)]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_routes_update_per_operation() {
+        let relay: IpNetwork = "185.213.154.68/32".parse().unwrap();
+        let default: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        let old_gateway = Node::new("192.168.1.1".parse().unwrap(), "eth0".to_owned());
+        let new_gateway = Node::new("192.168.2.1".parse().unwrap(), "wlan0".to_owned());
+        let tunnel = Node::device("wg0-mullvad".to_owned());
+
+        let relay_route = Route::new(old_gateway, relay);
+        let tunnel_route = Route::new(tunnel, default).table(1836018789);
+        let mut applied = HashSet::new();
+
+        // Connecting: the relay is routed outside the tunnel
+        let previous = applied.clone();
+        applied.insert(relay_route.clone());
+        assert_eq!(
+            RoutesUpdate::between(&previous, &applied),
+            RoutesUpdate {
+                added: vec![relay_route.clone()],
+                removed: vec![],
+            }
+        );
+
+        // Connected: traffic is routed into the tunnel
+        let previous = applied.clone();
+        applied.insert(tunnel_route.clone());
+        assert_eq!(
+            RoutesUpdate::between(&previous, &applied),
+            RoutesUpdate {
+                added: vec![tunnel_route.clone()],
+                removed: vec![],
+            }
+        );
+
+        // Network change: the relay route follows the new default route in a single update
+        let previous = applied.clone();
+        let moved_route = Route::new(new_gateway, relay);
+        applied.remove(&relay_route);
+        applied.insert(moved_route.clone());
+        assert_eq!(
+            RoutesUpdate::between(&previous, &applied),
+            RoutesUpdate {
+                added: vec![moved_route.clone()],
+                removed: vec![relay_route],
+            }
+        );
+
+        // Re-applying the same routes is not an update
+        assert!(RoutesUpdate::between(&applied, &applied).is_empty());
+
+        // Disconnected: all routes are removed, ordered by destination
+        let previous = applied.clone();
+        applied.clear();
+        assert_eq!(
+            RoutesUpdate::between(&previous, &applied),
+            RoutesUpdate {
+                added: vec![],
+                removed: vec![tunnel_route, moved_route],
+            }
+        );
+    }
+}
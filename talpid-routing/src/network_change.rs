@@ -0,0 +1,402 @@
+//! Reports changes to the network that traffic outside the tunnel is routed through, e.g. when
+//! switching from Wi-Fi to ethernet.
+
+use crate::{Error, RouteManagerHandle};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future::{self, Either, FutureExt},
+    select, Future, Stream, StreamExt,
+};
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+use talpid_types::net::IpVersion;
+pub use talpid_types::net::NetworkChange;
+
+/// How long a network must remain unchanged before it is reported. This prevents flapping routes
+/// from resulting in a flood of events.
+const SETTLE_DURATION: Duration = Duration::from_secs(2);
+
+/// Returns a channel that receives the initial network, and then the network whenever it changes.
+/// On Linux, `fwmark` is used to look up the routes that bypass the tunnel.
+pub async fn listen(
+    handle: RouteManagerHandle,
+    #[cfg(target_os = "linux")] fwmark: u32,
+) -> Result<UnboundedReceiver<NetworkChange>, Error> {
+    imp::listen(
+        handle,
+        #[cfg(target_os = "linux")]
+        fwmark,
+    )
+    .await
+}
+
+/// A non-tunnel default route, as reported by the platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DefaultRoute {
+    interface: Option<String>,
+    gateway: Option<IpAddr>,
+}
+
+/// Combines the default routes for each IP version into a [`NetworkChange`].
+fn network_from_routes(v4: Option<DefaultRoute>, v6: Option<DefaultRoute>) -> NetworkChange {
+    let families = [(IpVersion::V4, v4.is_some()), (IpVersion::V6, v6.is_some())]
+        .into_iter()
+        .filter_map(|(family, has_route)| has_route.then_some(family))
+        .collect();
+    let preferred = v4.or(v6);
+    NetworkChange {
+        interface: preferred.as_ref().and_then(|route| route.interface.clone()),
+        gateway: preferred.and_then(|route| route.gateway),
+        families,
+    }
+}
+
+/// Drops networks that equal the last reported network, and holds back new networks until they
+/// have remained unchanged for a while.
+struct ChangeFilter {
+    settle_duration: Duration,
+    reported: NetworkChange,
+    pending: Option<(NetworkChange, Instant)>,
+}
+
+impl ChangeFilter {
+    fn new(initial: NetworkChange, settle_duration: Duration) -> Self {
+        Self {
+            settle_duration,
+            reported: initial,
+            pending: None,
+        }
+    }
+
+    /// Registers that `network` was observed at `now`.
+    fn observe(&mut self, network: NetworkChange, now: Instant) {
+        if network == self.reported {
+            self.pending = None;
+            return;
+        }
+        match &self.pending {
+            Some((pending, _)) if *pending == network => (),
+            _ => self.pending = Some((network, now)),
+        }
+    }
+
+    /// Returns when the pending network should be reported, if there is one.
+    fn deadline(&self) -> Option<Instant> {
+        self.pending
+            .as_ref()
+            .map(|(_, since)| *since + self.settle_duration)
+    }
+
+    /// Returns the pending network if it has remained unchanged until `now`.
+    fn poll(&mut self, now: Instant) -> Option<NetworkChange> {
+        if self.deadline()? > now {
+            return None;
+        }
+        let (network, _) = self.pending.take()?;
+        self.reported = network.clone();
+        Some(network)
+    }
+}
+
+/// Observes the network using `current_network` every time `triggers` yields, and sends changes
+/// on the returned channel. `guard` is kept alive for as long as the channel is open.
+fn spawn_monitor<F, Fut>(
+    triggers: impl Stream<Item = ()> + Send + Unpin + 'static,
+    current_network: F,
+    guard: impl Send + 'static,
+) -> UnboundedReceiver<NetworkChange>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = NetworkChange> + Send,
+{
+    let (tx, rx) = mpsc::unbounded();
+    tokio::spawn(async move {
+        let _guard = guard;
+        run_monitor(triggers, current_network, tx).await;
+        log::trace!("Stopped network change monitor");
+    });
+    rx
+}
+
+async fn run_monitor<F, Fut>(
+    triggers: impl Stream<Item = ()> + Unpin,
+    current_network: F,
+    tx: UnboundedSender<NetworkChange>,
+) where
+    F: Fn() -> Fut,
+    Fut: Future<Output = NetworkChange>,
+{
+    let initial = current_network().await;
+    log::debug!("Initial network: {initial}");
+    if tx.unbounded_send(initial.clone()).is_err() {
+        return;
+    }
+    let mut filter = ChangeFilter::new(initial, SETTLE_DURATION);
+    let mut triggers = triggers.fuse();
+
+    loop {
+        let timeout = match filter.deadline() {
+            Some(deadline) => Either::Left(Box::pin(tokio::time::sleep_until(deadline.into()))),
+            None => Either::Right(future::pending()),
+        };
+
+        select! {
+            trigger = triggers.next() => {
+                if trigger.is_none() {
+                    return;
+                }
+                filter.observe(current_network().await, Instant::now());
+            }
+            _ = timeout.fuse() => {
+                if let Some(network) = filter.poll(Instant::now()) {
+                    log::debug!("Network changed: {network}");
+                    if tx.unbounded_send(network).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl From<&crate::Route> for DefaultRoute {
+    fn from(route: &crate::Route) -> Self {
+        DefaultRoute {
+            interface: route.get_node().get_device().map(str::to_owned),
+            gateway: route.get_node().get_address(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    /// Public addresses used to look up the routes that traffic to the internet would take.
+    const PUBLIC_INTERNET_ADDRESS_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(193, 138, 218, 78));
+    const PUBLIC_INTERNET_ADDRESS_V6: IpAddr =
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6));
+
+    pub async fn listen(
+        handle: RouteManagerHandle,
+        fwmark: u32,
+    ) -> Result<UnboundedReceiver<NetworkChange>, Error> {
+        let triggers = handle.change_listener().await?.map(|_| ());
+        let current_network = move || {
+            let handle = handle.clone();
+            async move {
+                let v4 = default_route(&handle, PUBLIC_INTERNET_ADDRESS_V4, fwmark).await;
+                let v6 = default_route(&handle, PUBLIC_INTERNET_ADDRESS_V6, fwmark).await;
+                network_from_routes(v4, v6)
+            }
+        };
+        Ok(spawn_monitor(Box::pin(triggers), current_network, ()))
+    }
+
+    async fn default_route(
+        handle: &RouteManagerHandle,
+        destination: IpAddr,
+        fwmark: u32,
+    ) -> Option<DefaultRoute> {
+        let route = handle.get_destination_route(destination, Some(fwmark));
+        match route.await {
+            Ok(route) => route.as_ref().map(DefaultRoute::from),
+            Err(error) => {
+                log::debug!("Failed to get route to {destination}: {error}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use super::*;
+
+    pub async fn listen(
+        handle: RouteManagerHandle,
+    ) -> Result<UnboundedReceiver<NetworkChange>, Error> {
+        let triggers = handle.default_route_listener().await?.map(|_| ());
+        let current_network = move || {
+            let handle = handle.clone();
+            async move {
+                match handle.get_default_routes().await {
+                    Ok((v4, v6)) => network_from_routes(
+                        v4.as_ref().map(DefaultRoute::from),
+                        v6.as_ref().map(DefaultRoute::from),
+                    ),
+                    Err(error) => {
+                        log::debug!("Failed to get default routes: {error}");
+                        NetworkChange::default()
+                    }
+                }
+            }
+        };
+        Ok(spawn_monitor(Box::pin(triggers), current_network, ()))
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod imp {
+    use super::*;
+    use crate::{get_best_default_route, InterfaceAndGateway};
+    use talpid_windows_net::{alias_from_luid, AddressFamily};
+
+    pub async fn listen(
+        handle: RouteManagerHandle,
+    ) -> Result<UnboundedReceiver<NetworkChange>, Error> {
+        let (trigger_tx, triggers) = mpsc::unbounded();
+        let callback_handle = handle
+            .add_default_route_change_callback(Box::new(move |_event, _family| {
+                let _ = trigger_tx.unbounded_send(());
+            }))
+            .await?;
+        let current_network = || async {
+            network_from_routes(
+                default_route(AddressFamily::Ipv4),
+                default_route(AddressFamily::Ipv6),
+            )
+        };
+        Ok(spawn_monitor(triggers, current_network, callback_handle))
+    }
+
+    fn default_route(family: AddressFamily) -> Option<DefaultRoute> {
+        match get_best_default_route(family) {
+            Ok(route) => route.as_ref().map(DefaultRoute::from),
+            Err(error) => {
+                log::debug!("Failed to get default route: {error}");
+                None
+            }
+        }
+    }
+
+    impl From<&InterfaceAndGateway> for DefaultRoute {
+        fn from(route: &InterfaceAndGateway) -> Self {
+            DefaultRoute {
+                interface: alias_from_luid(&route.iface)
+                    .ok()
+                    .map(|alias| alias.to_string_lossy().into_owned()),
+                gateway: Some(route.gateway.ip()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn network(interface: &str, gateway: &str) -> NetworkChange {
+        network_from_routes(
+            Some(DefaultRoute {
+                interface: Some(interface.to_owned()),
+                gateway: Some(gateway.parse().unwrap()),
+            }),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_network_from_routes() {
+        let v4 = DefaultRoute {
+            interface: Some("eth0".to_owned()),
+            gateway: Some("192.168.1.1".parse().unwrap()),
+        };
+        let v6 = DefaultRoute {
+            interface: Some("wlan0".to_owned()),
+            gateway: Some("fe80::1".parse().unwrap()),
+        };
+
+        let network = network_from_routes(Some(v4.clone()), Some(v6.clone()));
+        assert_eq!(network.interface.as_deref(), Some("eth0"));
+        assert_eq!(network.gateway, v4.gateway);
+        assert_eq!(network.families, vec![IpVersion::V4, IpVersion::V6]);
+
+        let network = network_from_routes(None, Some(v6.clone()));
+        assert_eq!(network.interface.as_deref(), Some("wlan0"));
+        assert_eq!(network.gateway, v6.gateway);
+        assert_eq!(network.families, vec![IpVersion::V6]);
+
+        assert_eq!(network_from_routes(None, None), NetworkChange::default());
+    }
+
+    #[test]
+    fn test_report_initial_network() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let (tx, mut rx) = mpsc::unbounded();
+
+        runtime.block_on(run_monitor(
+            futures::stream::empty(),
+            || async { network("eth0", "192.168.1.1") },
+            tx,
+        ));
+
+        assert_eq!(rx.try_next().unwrap(), Some(network("eth0", "192.168.1.1")));
+        // The channel is closed when the triggers end
+        assert_eq!(rx.try_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_report_after_settling() {
+        let start = Instant::now();
+        let mut filter = ChangeFilter::new(network("wlan0", "10.0.0.1"), SETTLE_DURATION);
+        assert_eq!(filter.deadline(), None);
+
+        filter.observe(network("eth0", "192.168.1.1"), start);
+        assert_eq!(filter.deadline(), Some(start + SETTLE_DURATION));
+        assert_eq!(filter.poll(start + Duration::from_secs(1)), None);
+
+        // Observing the same network again does not postpone the report
+        filter.observe(
+            network("eth0", "192.168.1.1"),
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(
+            filter.poll(start + SETTLE_DURATION),
+            Some(network("eth0", "192.168.1.1"))
+        );
+        assert_eq!(filter.deadline(), None);
+        assert_eq!(filter.poll(start + SETTLE_DURATION * 10), None);
+    }
+
+    #[test]
+    fn test_ignore_unchanged_network() {
+        let start = Instant::now();
+        let mut filter = ChangeFilter::new(network("eth0", "192.168.1.1"), SETTLE_DURATION);
+
+        filter.observe(network("eth0", "192.168.1.1"), start);
+        assert_eq!(filter.deadline(), None);
+        assert_eq!(filter.poll(start + SETTLE_DURATION), None);
+    }
+
+    #[test]
+    fn test_ignore_flapping() {
+        let start = Instant::now();
+        let mut filter = ChangeFilter::new(network("eth0", "192.168.1.1"), SETTLE_DURATION);
+
+        // The route goes away and comes back before settling
+        filter.observe(NetworkChange::default(), start);
+        filter.observe(
+            network("eth0", "192.168.1.1"),
+            start + Duration::from_millis(500),
+        );
+        assert_eq!(filter.deadline(), None);
+        assert_eq!(filter.poll(start + SETTLE_DURATION), None);
+
+        // Alternating between two new networks keeps postponing the report
+        filter.observe(network("wlan0", "10.0.0.1"), start);
+        filter.observe(NetworkChange::default(), start + Duration::from_secs(1));
+        assert_eq!(filter.poll(start + SETTLE_DURATION), None);
+        assert_eq!(
+            filter.poll(start + Duration::from_secs(1) + SETTLE_DURATION),
+            Some(NetworkChange::default())
+        );
+    }
+}
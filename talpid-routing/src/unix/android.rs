@@ -1,4 +1,4 @@
-use crate::imp::RouteManagerCommand;
+use crate::{imp::RouteManagerCommand, DesiredRoutes, RouteDump};
 use futures::{channel::mpsc, stream::StreamExt};
 
 /// Stub error type for routing errors on Android.
@@ -7,16 +7,20 @@ use futures::{channel::mpsc, stream::StreamExt};
 pub struct Error;
 
 /// Stub route manager for Android
-pub struct RouteManagerImpl {}
+pub struct RouteManagerImpl {
+    desired_routes: DesiredRoutes,
+}
 
 impl RouteManagerImpl {
     #[allow(clippy::unused_async)]
     pub async fn new() -> Result<Self, Error> {
-        Ok(RouteManagerImpl {})
+        Ok(RouteManagerImpl {
+            desired_routes: DesiredRoutes::default(),
+        })
     }
 
     pub(crate) async fn run(
-        self,
+        mut self,
         manage_rx: mpsc::UnboundedReceiver<RouteManagerCommand>,
     ) -> Result<(), Error> {
         let mut manage_rx = manage_rx.fuse();
@@ -26,10 +30,20 @@ impl RouteManagerImpl {
                     tx.send(()).map_err(|()| Error)?;
                     break;
                 }
-                RouteManagerCommand::AddRoutes(_routes, tx) => {
+                RouteManagerCommand::AddRoutes(routes, tx) => {
+                    self.desired_routes.extend(&routes);
                     let _ = tx.send(Ok(()));
                 }
-                RouteManagerCommand::ClearRoutes => (),
+                RouteManagerCommand::ClearRoutes(done_tx) => {
+                    self.desired_routes.clear();
+                    let _ = done_tx.send(());
+                }
+                RouteManagerCommand::GetRoutes(tx) => {
+                    let _ = tx.send(RouteDump {
+                        desired: self.desired_routes.dump(|_| None),
+                        kernel: None,
+                    });
+                }
             }
         }
         Ok(())
@@ -1,30 +1,36 @@
 use crate::{
-    imp::{CallbackMessage, RouteManagerCommand},
-    NetNode, Node, RequiredRoute, Route,
+    imp::{CallbackMessage, RouteManagerCommand, RoutesUpdateListener},
+    DesiredRoute, DesiredRoutes, NetNode, Node, RequiredRoute, Route, RouteDump, RoutesUpdate,
 };
 use netlink_sys::AsyncSocket;
 use std::{
     collections::{BTreeMap, HashSet},
-    io,
+    fmt, io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::unix::io::AsRawFd,
+    time::Duration,
 };
 use talpid_types::ErrorExt;
 
 use futures::{
     channel::mpsc::{UnboundedReceiver, UnboundedSender},
-    future::FutureExt,
+    future::{Future, FutureExt},
     StreamExt, TryStream, TryStreamExt,
 };
 use ipnetwork::IpNetwork;
 use libc::{AF_INET, AF_INET6};
 use netlink_packet_route::{
-    constants::{ARPHRD_LOOPBACK, FIB_RULE_INVERT, FR_ACT_TO_TBL, NLM_F_REQUEST},
+    constants::{
+        ARPHRD_LOOPBACK, FIB_RULE_INVERT, FR_ACT_TO_TBL, NLM_F_REQUEST, NTF_USE, NUD_FAILED,
+        NUD_INCOMPLETE, NUD_NONE,
+    },
     link::{nlas::Nla as LinkNla, LinkMessage},
+    neighbour::nlas::Nla as NeighbourNla,
     route::{nlas::Nla as RouteNla, RouteHeader, RouteMessage},
     rtnl::{
         constants::{
             RTN_UNSPEC, RTPROT_UNSPEC, RT_SCOPE_LINK, RT_SCOPE_UNIVERSE, RT_TABLE_COMPAT,
-            RT_TABLE_MAIN,
+            RT_TABLE_LOCAL, RT_TABLE_MAIN,
         },
         RouteFlags,
     },
@@ -55,15 +61,113 @@ static SUPPRESS_RULE_V6: Lazy<RuleMessage> = Lazy::new(|| {
     v6_rule
 });
 
-fn all_rules(fwmark: u32, table: u32) -> [RuleMessage; 4] {
+/// Highest priority that may be used for the routing rules. The rules for the main and default
+/// routing tables use priorities 32766 and 32767.
+const MAX_RULE_PRIORITY: u32 = 32764;
+
+/// Size of the receive buffer of the netlink socket. Route dumps on hosts with many routes fail
+/// with `ENOBUFS` if the default buffer fills up.
+const NETLINK_RX_BUFFER_SIZE: libc::c_int = 1024 * 1024;
+
+/// Error codes of netlink requests that may succeed if the request is sent again.
+const TRANSIENT_ERRNOS: [i32; 3] = [libc::ENOBUFS, libc::EBUSY, libc::EAGAIN];
+/// Number of times that a netlink request that fails with a transient error is attempted.
+const MAX_NETLINK_ATTEMPTS: u32 = 3;
+/// Delay before sending a failed netlink request again. It is doubled after each attempt.
+const NETLINK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Addresses used to look up the default routes outside the tunnel.
+const PUBLIC_INTERNET_ADDRESS_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(193, 138, 218, 78));
+const PUBLIC_INTERNET_ADDRESS_V6: IpAddr =
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6));
+
+/// Returns the routing rules used to route traffic through the tunnel. If `priority` is given,
+/// the suppress rules use it and the rules for the tunnel table use the next priority, since the
+/// suppress rules must be evaluated first. Otherwise, the kernel assigns the priorities.
+fn all_rules(fwmark: u32, table: u32, priority: Option<u32>) -> [RuleMessage; 4] {
+    let tunnel_priority = priority.map(|priority| priority + 1);
     [
-        no_fwmark_rule_v4(fwmark, table),
-        no_fwmark_rule_v6(fwmark, table),
-        SUPPRESS_RULE_V4.clone(),
-        SUPPRESS_RULE_V6.clone(),
+        with_priority(no_fwmark_rule_v4(fwmark, table), tunnel_priority),
+        with_priority(no_fwmark_rule_v6(fwmark, table), tunnel_priority),
+        with_priority(SUPPRESS_RULE_V4.clone(), priority),
+        with_priority(SUPPRESS_RULE_V6.clone(), priority),
     ]
 }
 
+fn with_priority(mut rule: RuleMessage, priority: Option<u32>) -> RuleMessage {
+    if let Some(priority) = priority {
+        rule.nlas.push(RuleNla::Priority(priority));
+    }
+    rule
+}
+
+/// Returns whether `found_rule` is an instance of `rule`. The priority is ignored, so that rules
+/// added using a different priority are also matched.
+fn rule_matches(rule: &RuleMessage, found_rule: &RuleMessage) -> bool {
+    found_rule.header.family == rule.header.family
+        && found_rule.header.action == rule.header.action
+        && (found_rule.header.flags & rule.header.flags) == rule.header.flags
+        && rule
+            .nlas
+            .iter()
+            .filter(|nla| !matches!(nla, RuleNla::Priority(_)))
+            .all(|nla| found_rule.nlas.contains(nla))
+}
+
+fn rule_table(rule: &RuleMessage) -> u32 {
+    rule.nlas
+        .iter()
+        .find_map(|nla| match nla {
+            RuleNla::Table(table) => Some(*table),
+            _ => None,
+        })
+        .unwrap_or(u32::from(rule.header.table))
+}
+
+fn rule_priority(rule: &RuleMessage) -> u32 {
+    rule.nlas
+        .iter()
+        .find_map(|nla| match nla {
+            RuleNla::Priority(priority) => Some(*priority),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// Returns an error if the routing table or rule priorities cannot be used.
+fn validate_routing_ids(table: u32, priority: Option<u32>) -> Result<()> {
+    if table == 0 || (u32::from(RT_TABLE_COMPAT)..=u32::from(RT_TABLE_LOCAL)).contains(&table) {
+        return Err(Error::InvalidRoutingTable(table));
+    }
+    match priority {
+        Some(priority) if priority == 0 || priority > MAX_RULE_PRIORITY => {
+            Err(Error::InvalidRulePriority(priority))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns an error if any of `rules`, which must not include our own rules, uses the routing
+/// table or the rule priorities.
+fn check_conflicting_rules(rules: &[RuleMessage], table: u32, priority: Option<u32>) -> Result<()> {
+    let priorities = priority.map(|priority| [priority, priority + 1]);
+    for rule in rules {
+        if rule_table(rule) == table {
+            log::debug!("Conflicting routing rule: {:?}", rule);
+            return Err(Error::RoutingTableInUse(table));
+        }
+        if let Some(priority) = priorities
+            .iter()
+            .flatten()
+            .find(|priority| rule_priority(rule) == **priority)
+        {
+            log::debug!("Conflicting routing rule: {:?}", rule);
+            return Err(Error::RulePriorityInUse(*priority));
+        }
+    }
+    Ok(())
+}
+
 fn no_fwmark_rule_v4(fwmark: u32, table: u32) -> RuleMessage {
     RuleMessage {
         header: RuleHeader {
@@ -97,6 +201,13 @@ pub enum Error {
     #[error(display = "Netlink error")]
     Netlink(#[error(source)] rtnetlink::Error),
 
+    #[error(
+        display = "Netlink request failed with {} after {} attempts",
+        _0,
+        MAX_NETLINK_ATTEMPTS
+    )]
+    TransientNetlink(Errno, #[error(source)] rtnetlink::Error),
+
     #[error(display = "Route without a valid node")]
     InvalidRoute,
 
@@ -125,38 +236,231 @@ pub enum Error {
     #[error(display = "Cannot find a free routing table ID")]
     NoFreeRoutingTableId,
 
+    #[error(display = "Routing table {} is reserved and cannot be used", _0)]
+    InvalidRoutingTable(u32),
+
+    #[error(
+        display = "Invalid routing rule priority {}. It must be between 1 and {}",
+        _0,
+        MAX_RULE_PRIORITY
+    )]
+    InvalidRulePriority(u32),
+
+    /// Another program routes traffic using the same routing table.
+    #[error(
+        display = "Routing table {} is already used by routing rules that were not added by \
+                   this program",
+        _0
+    )]
+    RoutingTableInUse(u32),
+
+    /// Another program uses the same routing rule priority.
+    #[error(
+        display = "Routing rule priority {} is already used by routing rules that were not added \
+                   by this program",
+        _0
+    )]
+    RulePriorityInUse(u32),
+
     #[error(display = "Shutting down route manager")]
     Shutdown,
 }
 
+/// An error code returned by the kernel in response to a netlink request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(i32);
+
+impl Errno {
+    /// Returns the error code of `error`, if it is one that may go away if the request is sent
+    /// again.
+    fn transient(error: &rtnetlink::Error) -> Option<Self> {
+        match error {
+            rtnetlink::Error::NetlinkError(msg) if TRANSIENT_ERRNOS.contains(&-msg.code) => {
+                Some(Errno(-msg.code))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the raw error code.
+    pub fn code(&self) -> i32 {
+        self.0
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            libc::ENOBUFS => "ENOBUFS",
+            libc::EBUSY => "EBUSY",
+            libc::EAGAIN => "EAGAIN",
+            code => return write!(f, "errno {code}"),
+        };
+        write!(f, "{name} ({})", io::Error::from_raw_os_error(self.0))
+    }
+}
+
+/// Sends a netlink request using `request` until it succeeds or fails with an error that is not
+/// transient, waiting longer between each attempt. Errors that are not transient are converted
+/// using `map_err`.
+async fn retry_transient<T, F, Fut>(
+    map_err: impl FnOnce(rtnetlink::Error) -> Error,
+    mut request: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, rtnetlink::Error>>,
+{
+    let mut delay = NETLINK_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        let error = match request().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        let Some(errno) = Errno::transient(&error) else {
+            return Err(map_err(error));
+        };
+        if attempt >= MAX_NETLINK_ATTEMPTS {
+            return Err(Error::TransientNetlink(errno, error));
+        }
+        log::debug!("Netlink request failed with {errno}. Retrying in {delay:?}");
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Sends `request` and waits until the kernel acknowledges it.
+async fn request_ack(
+    mut handle: Handle,
+    request: NetlinkMessage<RtnlMessage>,
+) -> std::result::Result<(), rtnetlink::Error> {
+    let mut response = handle.request(request)?;
+    while let Some(message) = response.next().await {
+        if let NetlinkPayload::Error(error) = message.payload {
+            return Err(rtnetlink::Error::NetlinkError(error));
+        }
+    }
+    Ok(())
+}
+
+/// Routes that should use the node of the current default route, and the routes that were added
+/// for them.
+#[derive(Debug, Default)]
+struct DefaultNodeRoutes {
+    /// Prefixes of the required routes, along with the table to add them to.
+    required: HashSet<(IpNetwork, u32)>,
+    /// Routes currently added for `required`.
+    applied: HashSet<Route>,
+}
+
+/// Changes needed to route the default node routes via the current default routes.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RouteChanges {
+    /// Routes via a previous default route.
+    remove: HashSet<Route>,
+    /// Routes via the current default routes. These are added again even if they already exist,
+    /// since the kernel removes routes without notice when an interface goes down.
+    add: HashSet<Route>,
+}
+
+impl DefaultNodeRoutes {
+    fn insert(&mut self, prefix: IpNetwork, table: u32) {
+        self.required.insert((prefix, table));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.required.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.required.clear();
+        self.applied.clear();
+    }
+
+    /// Returns the changes needed to route the required prefixes via `v4_node` and `v6_node`, the
+    /// nodes of the current default routes. Prefixes of a family without a default route are not
+    /// routed.
+    fn update(&mut self, v4_node: Option<&Node>, v6_node: Option<&Node>) -> RouteChanges {
+        let routes: HashSet<Route> = self
+            .required
+            .iter()
+            .filter_map(|(prefix, table)| {
+                let node = if prefix.is_ipv4() { v4_node } else { v6_node }?;
+                Some(Route::new(node.clone(), *prefix).table(*table))
+            })
+            .collect();
+        let remove = self.applied.difference(&routes).cloned().collect();
+        self.applied = routes.clone();
+        RouteChanges {
+            remove,
+            add: routes,
+        }
+    }
+}
+
 pub struct RouteManagerImpl {
     handle: Handle,
     messages: UnboundedReceiver<(NetlinkMessage<RtnlMessage>, SocketAddr)>,
     iface_map: BTreeMap<u32, NetworkInterface>,
     listeners: Vec<UnboundedSender<CallbackMessage>>,
+    /// Listeners for the routes changed by each operation.
+    routes_update_listeners: Vec<RoutesUpdateListener>,
 
     // currently added routes
     added_routes: HashSet<Route>,
+    /// Routes that follow the default route.
+    default_node_routes: DefaultNodeRoutes,
+    /// Routes that have been required since the routes were last cleared.
+    desired_routes: DesiredRoutes,
 
     /// Tunnel specific routing table, traffic not marked will be routed via this routing table.
     table_id: u32,
     /// Firewall mark identifies traffic which shouldn't be routed via the tunnel routing table. It
     /// is used to construct a routing rule.
     fwmark: u32,
+    /// Priority of the routing rules, or `None` if the kernel should assign them.
+    rule_priority: Option<u32>,
+}
+
+/// Sets `SO_RCVBUF` on `socket`. The kernel doubles the value, and caps it at
+/// `net.core.rmem_max`.
+fn set_receive_buffer_size(socket: &impl AsRawFd, size: libc::c_int) -> io::Result<()> {
+    // SAFETY: The option value is a valid `c_int` that outlives the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
 }
 
 impl RouteManagerImpl {
-    pub async fn new(table_id: u32, fwmark: u32) -> Result<Self> {
+    pub async fn new(table_id: u32, fwmark: u32, rule_priority: Option<u32>) -> Result<Self> {
+        validate_routing_ids(table_id, rule_priority)?;
+
         let (mut connection, handle, messages) =
             rtnetlink::new_connection().map_err(Error::Connect)?;
 
         let mgroup_flags = RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_ROUTE | RTMGRP_LINK | RTMGRP_NOTIFY;
         let addr = SocketAddr::new(0, mgroup_flags);
-        connection
-            .socket_mut()
-            .socket_mut()
-            .bind(&addr)
-            .map_err(Error::Bind)?;
+        let socket = connection.socket_mut().socket_mut();
+        socket.bind(&addr).map_err(Error::Bind)?;
+        if let Err(error) = set_receive_buffer_size(socket, NETLINK_RX_BUFFER_SIZE) {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to increase netlink receive buffer size")
+            );
+        }
 
         tokio::spawn(connection);
 
@@ -167,12 +471,18 @@ impl RouteManagerImpl {
             messages,
             iface_map,
             listeners: vec![],
+            routes_update_listeners: vec![],
             added_routes: HashSet::new(),
+            default_node_routes: DefaultNodeRoutes::default(),
+            desired_routes: DesiredRoutes::default(),
             table_id,
             fwmark,
+            rule_priority,
         };
 
         monitor.clear_routing_rules().await?;
+        let rules = monitor.get_rules().await?;
+        check_conflicting_rules(&rules, table_id, rule_priority)?;
 
         Ok(monitor)
     }
@@ -182,59 +492,33 @@ impl RouteManagerImpl {
 
         self.clear_routing_rules().await?;
 
-        for rule in all_rules(self.fwmark, self.table_id)
+        for rule in all_rules(self.fwmark, self.table_id, self.rule_priority)
             .iter()
             .filter(|rule| rule.header.family as u16 == AF_INET || enable_ipv6)
         {
             let mut req = NetlinkMessage::from(RtnlMessage::NewRule((*rule).clone()));
             req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE;
 
-            let mut response = self.handle.request(req).map_err(Error::Netlink)?;
-
-            while let Some(message) = response.next().await {
-                if let NetlinkPayload::Error(error) = message.payload {
-                    return Err(Error::Netlink(rtnetlink::Error::NetlinkError(error)));
-                }
-            }
+            retry_transient(Error::Netlink, || {
+                request_ack(self.handle.clone(), req.clone())
+            })
+            .await?;
         }
         Ok(())
     }
 
     async fn clear_routing_rules(&mut self) -> Result<()> {
         let rules = self.get_rules().await?;
-        for rule in all_rules(self.fwmark, self.table_id) {
-            let mut matching_rule = None;
-
+        for rule in all_rules(self.fwmark, self.table_id, self.rule_priority) {
             // `RTM_DELRULE` is way too picky about which rules are considered the same.
-            // So iterate over all rules and ignore irrelevant attributes.
-            for found_rule in &rules {
-                // Match header
-                if found_rule.header.family != rule.header.family {
-                    continue;
-                }
-                if found_rule.header.action != rule.header.action {
-                    continue;
-                }
-                if (found_rule.header.flags & rule.header.flags) != rule.header.flags {
-                    continue;
-                }
-                // Match NLAs
-                let mut contains_nlas = true;
-                for nla in &rule.nlas {
-                    if !found_rule.nlas.contains(nla) {
-                        contains_nlas = false;
-                        break;
-                    }
-                }
-                if contains_nlas {
-                    log::trace!("Existing routing rule matched: {:?}", found_rule);
-                    matching_rule = Some(found_rule);
-                    break;
-                }
-            }
-
-            if let Some(rule) = matching_rule {
-                self.delete_rule_if_exists((*rule).clone()).await?;
+            // So iterate over all rules and ignore irrelevant attributes. All matching rules are
+            // removed, since a previous instance may have added them using other priorities.
+            for found_rule in rules
+                .iter()
+                .filter(|found_rule| rule_matches(&rule, found_rule))
+            {
+                log::trace!("Existing routing rule matched: {:?}", found_rule);
+                self.delete_rule_if_exists(found_rule.clone()).await?;
             }
         }
         Ok(())
@@ -246,22 +530,27 @@ impl RouteManagerImpl {
         let mut req = NetlinkMessage::from(RtnlMessage::GetRule(RuleMessage::default()));
         req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_DUMP;
 
-        let mut response = self.handle.request(req).map_err(Error::Netlink)?;
-
-        let mut rules = vec![];
-
-        while let Some(message) = response.next().await {
-            match message.payload {
-                NetlinkPayload::InnerMessage(RtnlMessage::NewRule(rule)) => {
-                    rules.push(rule);
-                }
-                NetlinkPayload::Error(error) => {
-                    return Err(Error::Netlink(rtnetlink::Error::NetlinkError(error)));
+        retry_transient(Error::Netlink, || {
+            let mut handle = self.handle.clone();
+            let req = req.clone();
+            async move {
+                let mut response = handle.request(req)?;
+                let mut rules = vec![];
+                while let Some(message) = response.next().await {
+                    match message.payload {
+                        NetlinkPayload::InnerMessage(RtnlMessage::NewRule(rule)) => {
+                            rules.push(rule);
+                        }
+                        NetlinkPayload::Error(error) => {
+                            return Err(rtnetlink::Error::NetlinkError(error));
+                        }
+                        _ => (),
+                    }
                 }
-                _ => (),
+                Ok(rules)
             }
-        }
-        Ok(rules)
+        })
+        .await
     }
 
     async fn delete_rule_if_exists(&mut self, rule: RuleMessage) -> Result<()> {
@@ -270,31 +559,33 @@ impl RouteManagerImpl {
         let mut req = NetlinkMessage::from(RtnlMessage::DelRule(rule));
         req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
 
-        let mut response = self.handle.request(req).map_err(Error::Netlink)?;
-
-        while let Some(message) = response.next().await {
-            if let NetlinkPayload::Error(error) = message.payload {
-                if error.to_io().kind() != io::ErrorKind::NotFound {
-                    return Err(Error::Netlink(rtnetlink::Error::NetlinkError(error)));
-                }
+        match retry_transient(Error::Netlink, || {
+            request_ack(self.handle.clone(), req.clone())
+        })
+        .await
+        {
+            Err(Error::Netlink(rtnetlink::Error::NetlinkError(error)))
+                if error.to_io().kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(())
             }
+            result => result,
         }
-        Ok(())
     }
 
     async fn add_required_routes(&mut self, required_routes: HashSet<RequiredRoute>) -> Result<()> {
         let mut required_normal_routes = HashSet::new();
+        self.desired_routes.extend(&required_routes);
 
         for route in required_routes {
+            let table = self.route_table(&route);
             match route.node {
                 NetNode::RealNode(node) => {
-                    let table = if route.main_table {
-                        RT_TABLE_MAIN.into()
-                    } else {
-                        self.table_id
-                    };
                     required_normal_routes.insert(Route::new(node, route.prefix).table(table));
                 }
+                NetNode::DefaultNode => {
+                    self.default_node_routes.insert(route.prefix, table);
+                }
             }
         }
 
@@ -302,21 +593,101 @@ impl RouteManagerImpl {
             self.add_route(normal_route).await?;
         }
 
+        self.update_default_node_routes().await
+    }
+
+    /// Returns the routing table that `route` is added to.
+    fn route_table(&self, route: &RequiredRoute) -> u32 {
+        if route.main_table {
+            RT_TABLE_MAIN.into()
+        } else {
+            self.table_id
+        }
+    }
+
+    /// Returns the routes in the tunnel routing table, and the routes in the main table whose
+    /// prefixes are required there.
+    async fn get_kernel_routes(&self, desired: &[DesiredRoute]) -> Result<Vec<Route>> {
+        let main_table = u32::from(RT_TABLE_MAIN);
+        let main_prefixes: HashSet<IpNetwork> = desired
+            .iter()
+            .filter(|route| route.table == Some(main_table))
+            .map(|route| route.prefix)
+            .collect();
+
+        let mut routes = vec![];
+        for ip_version in [IpVersion::V4, IpVersion::V6] {
+            let mut request = self.handle.route().get(ip_version).execute();
+            while let Some(message) = request.try_next().await.map_err(Error::GetRoute)? {
+                // Routes that cannot be parsed, such as those without a node, are not ours
+                let Ok(Some(route)) = self.parse_route_message(message) else {
+                    continue;
+                };
+                if route.table_id == self.table_id
+                    || (route.table_id == main_table && main_prefixes.contains(&route.prefix))
+                {
+                    routes.push(route);
+                }
+            }
+        }
+        Ok(routes)
+    }
+
+    /// Routes the default node routes via the current default routes outside the tunnel, and
+    /// removes those that used a previous default route.
+    async fn update_default_node_routes(&mut self) -> Result<()> {
+        if self.default_node_routes.is_empty() {
+            return Ok(());
+        }
+
+        let v4_node = self.get_default_node(PUBLIC_INTERNET_ADDRESS_V4).await;
+        let v6_node = self.get_default_node(PUBLIC_INTERNET_ADDRESS_V6).await;
+        let changes = self
+            .default_node_routes
+            .update(v4_node.as_ref(), v6_node.as_ref());
+
+        for route in changes.remove {
+            log::debug!("Removing route via previous default route: {}", route);
+            self.added_routes.remove(&route);
+            self.delete_route_if_exists(&route).await?;
+        }
+        for route in changes.add {
+            self.add_route(route).await?;
+        }
         Ok(())
     }
 
+    /// Returns the node of the route that is used to reach `destination` outside the tunnel.
+    async fn get_default_node(&self, destination: IpAddr) -> Option<Node> {
+        match self
+            .get_destination_route(&destination, Some(self.fwmark))
+            .await
+        {
+            Ok(route) => route.map(|route| route.node),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain default route")
+                );
+                None
+            }
+        }
+    }
+
     async fn initialize_link_map(
         handle: &rtnetlink::Handle,
     ) -> Result<BTreeMap<u32, NetworkInterface>> {
-        let mut link_map = BTreeMap::new();
-        let mut link_request = handle.link().get().execute();
-        while let Some(link) = link_request.try_next().await.map_err(Error::Netlink)? {
-            if let Some((idx, device)) = Self::map_interface(link) {
-                link_map.insert(idx, device);
+        retry_transient(Error::Netlink, || async move {
+            let mut link_map = BTreeMap::new();
+            let mut link_request = handle.link().get().execute();
+            while let Some(link) = link_request.try_next().await? {
+                if let Some((idx, device)) = Self::map_interface(link) {
+                    link_map.insert(idx, device);
+                }
             }
-        }
-
-        Ok(link_map)
+            Ok(link_map)
+        })
+        .await
     }
 
     fn find_iface_idx(&self, iface_name: &str) -> Option<u32> {
@@ -332,6 +703,8 @@ impl RouteManagerImpl {
     }
 
     async fn cleanup_routes(&mut self) {
+        self.default_node_routes.clear();
+        self.desired_routes.clear();
         for route in self.added_routes.drain().collect::<Vec<_>>().iter() {
             if let Err(e) = self.delete_route_if_exists(route).await {
                 log::error!("Failed to remove route: {}: {}", route, e);
@@ -350,8 +723,18 @@ impl RouteManagerImpl {
                     self.process_command(command).await?;
                 },
                 (route_change, _socket) = self.messages.select_next_some().fuse() => {
-                    if let Err(error) = self.process_netlink_message(route_change) {
-                        log::error!("{}", error.display_chain_with_msg("Failed to process netlink message"));
+                    match self.process_netlink_message(route_change) {
+                        Ok(true) => {
+                            let previous = self.added_routes.clone();
+                            if let Err(error) = self.update_default_node_routes().await {
+                                log::error!("{}", error.display_chain_with_msg("Failed to update routes via the default route"));
+                            }
+                            self.notify_routes_update(&previous);
+                        }
+                        Ok(false) => (),
+                        Err(error) => {
+                            log::error!("{}", error.display_chain_with_msg("Failed to process netlink message"));
+                        }
                     }
                 }
             };
@@ -369,7 +752,10 @@ impl RouteManagerImpl {
             }
             RouteManagerCommand::AddRoutes(routes, result_tx) => {
                 log::debug!("Adding routes: {:?}", routes);
-                let _ = result_tx.send(self.add_required_routes(routes.clone()).await);
+                let previous = self.added_routes.clone();
+                let result = self.add_required_routes(routes.clone()).await;
+                self.notify_routes_update(&previous);
+                let _ = result_tx.send(result);
             }
             RouteManagerCommand::CreateRoutingRules(enable_ipv6, result_tx) => {
                 let _ = result_tx.send(self.create_routing_rules(enable_ipv6).await);
@@ -380,21 +766,57 @@ impl RouteManagerImpl {
             RouteManagerCommand::NewChangeListener(result_tx) => {
                 let _ = result_tx.send(self.listen());
             }
+            RouteManagerCommand::NewRoutesUpdateListener(listener) => {
+                self.routes_update_listeners.push(listener);
+            }
             RouteManagerCommand::GetDestinationRoute(destination, mark, result_tx) => {
                 let _ = result_tx.send(self.get_destination_route(&destination, mark).await);
             }
             RouteManagerCommand::GetMtuForRoute(ip, result_tx) => {
                 let _ = result_tx.send(self.get_mtu_for_route(ip).await);
             }
-            RouteManagerCommand::ClearRoutes => {
+            RouteManagerCommand::GatewayIsReachable(gateway, device, result_tx) => {
+                let _ = result_tx.send(self.gateway_is_reachable(gateway, &device).await);
+            }
+            RouteManagerCommand::ProbeNeighbour(address, device, result_tx) => {
+                let _ = result_tx.send(self.probe_neighbour(address, &device).await);
+            }
+            RouteManagerCommand::GetGateway(destination, result_tx) => {
+                let route = self
+                    .get_destination_route(&destination, Some(self.fwmark))
+                    .await;
+                let _ = result_tx.send(route.map(|route| route.map(|route| route.node)));
+            }
+            RouteManagerCommand::GetRoutes(result_tx) => {
+                let desired = self
+                    .desired_routes
+                    .dump(|route| Some(self.route_table(route)));
+                let kernel = self
+                    .get_kernel_routes(&desired)
+                    .await
+                    .map_err(|error| {
+                        log::warn!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to list the kernel routes")
+                        );
+                    })
+                    .ok();
+                let _ = result_tx.send(RouteDump { desired, kernel });
+            }
+            RouteManagerCommand::ClearRoutes(done_tx) => {
                 log::debug!("Clearing routes");
+                let previous = self.added_routes.clone();
                 self.cleanup_routes().await;
+                self.notify_routes_update(&previous);
+                let _ = done_tx.send(());
             }
         }
         Ok(())
     }
 
-    fn process_netlink_message(&mut self, msg: NetlinkMessage<RtnlMessage>) -> Result<()> {
+    /// Returns whether a default route outside the tunnel was added or removed.
+    fn process_netlink_message(&mut self, msg: NetlinkMessage<RtnlMessage>) -> Result<bool> {
+        let mut default_route_changed = false;
         match msg.payload {
             NetlinkPayload::InnerMessage(RtnlMessage::NewLink(new_link)) => {
                 if let Some((idx, name)) = Self::map_interface(new_link) {
@@ -408,18 +830,35 @@ impl RouteManagerImpl {
             }
             NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(new_route)) => {
                 if let Some(addition) = self.parse_route_message(new_route)? {
+                    default_route_changed = self.is_non_tunnel_default_route(&addition);
                     self.notify_change_listeners(CallbackMessage::NewRoute(addition));
                 }
             }
             NetlinkPayload::InnerMessage(RtnlMessage::DelRoute(old_route)) => {
                 if let Some(deletion) = self.parse_route_message(old_route)? {
+                    default_route_changed = self.is_non_tunnel_default_route(&deletion);
                     self.process_deleted_route(&deletion)?;
                     self.notify_change_listeners(CallbackMessage::DelRoute(deletion));
                 }
             }
             _ => (),
         };
-        Ok(())
+        Ok(default_route_changed)
+    }
+
+    fn is_non_tunnel_default_route(&self, route: &Route) -> bool {
+        route.prefix.prefix() == 0 && route.table_id != self.table_id
+    }
+
+    /// Notifies the listeners of the routes that were added or removed since `previous`, if any.
+    fn notify_routes_update(&self, previous: &HashSet<Route>) {
+        let update = RoutesUpdate::between(previous, &self.added_routes);
+        if update.is_empty() {
+            return;
+        }
+        for listener in &self.routes_update_listeners {
+            listener.notify(update.clone());
+        }
     }
 
     fn notify_change_listeners(&mut self, message: CallbackMessage) {
@@ -628,12 +1067,10 @@ impl RouteManagerImpl {
             route_message.nlas.push(RouteNla::Priority(metric));
         }
 
-        self.handle
-            .route()
-            .del(route_message)
-            .execute()
-            .await
-            .map_err(Error::Netlink)
+        retry_transient(Error::Netlink, || {
+            self.handle.route().del(route_message.clone()).execute()
+        })
+        .await
     }
 
     async fn add_route_direct(&mut self, route: Route) -> Result<()> {
@@ -707,14 +1144,10 @@ impl RouteManagerImpl {
         let mut req = NetlinkMessage::from(RtnlMessage::NewRoute(add_message));
         req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE;
 
-        let mut response = self.handle.request(req).map_err(Error::Netlink)?;
-
-        while let Some(message) = response.next().await {
-            if let NetlinkPayload::Error(err) = message.payload {
-                return Err(Error::Netlink(rtnetlink::Error::NetlinkError(err)));
-            }
-        }
-        Ok(())
+        retry_transient(Error::Netlink, || {
+            request_ack(self.handle.clone(), req.clone())
+        })
+        .await
     }
 
     async fn add_route(&mut self, route: Route) -> Result<()> {
@@ -803,6 +1236,42 @@ impl RouteManagerImpl {
         Err(Error::LinkNotFound)
     }
 
+    async fn gateway_is_reachable(&self, gateway: IpAddr, device: &str) -> Result<bool> {
+        let iface_idx = self.find_iface_idx(device).ok_or(Error::LinkNotFound)?;
+        let destination = NeighbourNla::Destination(ip_to_bytes(gateway));
+        let neighbours = retry_transient(Error::Netlink, || {
+            self.handle
+                .neighbours()
+                .get()
+                .execute()
+                .try_collect::<Vec<_>>()
+        })
+        .await?;
+        Ok(neighbours
+            .iter()
+            .filter(|neighbour| {
+                neighbour.header.ifindex == iface_idx && neighbour.nlas.contains(&destination)
+            })
+            .all(|neighbour| neighbour.header.state & (NUD_FAILED | NUD_INCOMPLETE) == 0))
+    }
+
+    async fn probe_neighbour(&self, address: IpAddr, device: &str) -> Result<()> {
+        let iface_idx = self.find_iface_idx(device).ok_or(Error::LinkNotFound)?;
+        // `NTF_USE` makes the kernel resolve the neighbour as if a packet was about to be sent to
+        // it, without changing the state of the entry. Since no packet is sent, the firewall does
+        // not interfere.
+        retry_transient(Error::Netlink, || {
+            self.handle
+                .neighbours()
+                .add(iface_idx, address)
+                .state(NUD_NONE)
+                .flags(NTF_USE)
+                .replace()
+                .execute()
+        })
+        .await
+    }
+
     async fn get_destination_route(
         &self,
         destination: &IpAddr,
@@ -820,14 +1289,20 @@ impl RouteManagerImpl {
         message.header.destination_prefix_length = 8u8 * (octets.len() as u8);
         message.header.flags = RouteFlags::RTM_F_FIB_MATCH;
         message.nlas.push(RouteNla::Destination(octets));
-        let mut stream = execute_route_get_request(self.handle.clone(), message.clone());
-        match stream.try_next().await {
+        let response = retry_transient(Error::GetRoute, || {
+            let mut stream = execute_route_get_request(self.handle.clone(), message.clone());
+            async move { stream.try_next().await }
+        })
+        .await;
+        match response {
             Ok(Some(route_msg)) => self.parse_route_message(route_msg),
             Ok(None) => Err(Error::NoRoute),
-            Err(rtnetlink::Error::NetlinkError(nl_err)) if nl_err.code == -libc::ENETUNREACH => {
+            Err(Error::GetRoute(rtnetlink::Error::NetlinkError(nl_err)))
+                if nl_err.code == -libc::ENETUNREACH =>
+            {
                 Ok(None)
             }
-            Err(err) => Err(Error::GetRoute(err)),
+            Err(err) => Err(err),
         }
     }
 }
@@ -902,7 +1377,7 @@ mod test {
     fn test_drop_in_executor() {
         let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
         runtime.block_on(async {
-            let manager = RouteManagerImpl::new(0, 0)
+            let manager = RouteManagerImpl::new(1000, 0, None)
                 .await
                 .expect("Failed to initialize route manager");
             std::mem::drop(manager);
@@ -914,10 +1389,244 @@ mod test {
     fn test_drop() {
         let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
         let manager = runtime.block_on(async {
-            RouteManagerImpl::new(1000, 1000)
+            RouteManagerImpl::new(1000, 1000, Some(1000))
                 .await
                 .expect("Failed to initialize route manager")
         });
         std::mem::drop(manager);
     }
+
+    #[test]
+    fn test_rules_with_priority() {
+        let rules = all_rules(0x1234, 0x5678, Some(1000));
+
+        for rule in &rules[..2] {
+            assert_eq!(rule_table(rule), 0x5678);
+            assert_eq!(rule_priority(rule), 1001);
+            assert!(rule.nlas.contains(&RuleNla::FwMark(0x1234)));
+            assert_eq!(rule.header.flags, FIB_RULE_INVERT);
+        }
+        for rule in &rules[2..] {
+            assert_eq!(rule_table(rule), u32::from(RT_TABLE_MAIN));
+            assert_eq!(rule_priority(rule), 1000);
+            assert!(rule.nlas.contains(&RuleNla::SuppressPrefixLen(0)));
+        }
+        assert_eq!(rules[0].header.family, AF_INET as u8);
+        assert_eq!(rules[1].header.family, AF_INET6 as u8);
+    }
+
+    #[test]
+    fn test_rules_without_priority() {
+        for rule in all_rules(0x1234, 0x5678, None) {
+            assert!(!rule
+                .nlas
+                .iter()
+                .any(|nla| matches!(nla, RuleNla::Priority(_))));
+        }
+    }
+
+    #[test]
+    fn test_match_rules_with_other_priority() {
+        let rules = all_rules(0x1234, 0x5678, Some(1000));
+        let old_rules = all_rules(0x1234, 0x5678, None);
+        for (rule, old_rule) in rules.iter().zip(&old_rules) {
+            assert!(rule_matches(rule, old_rule));
+        }
+        let other_table_rule = no_fwmark_rule_v4(0x1234, 100);
+        assert!(!rule_matches(&rules[0], &other_table_rule));
+    }
+
+    #[test]
+    fn test_conflicting_rules() {
+        let foreign_rules = [
+            with_priority(no_fwmark_rule_v4(0x4321, 100), Some(200)),
+            with_priority(SUPPRESS_RULE_V6.clone(), Some(300)),
+        ];
+        assert!(check_conflicting_rules(&foreign_rules, 0x5678, Some(1000)).is_ok());
+        assert!(check_conflicting_rules(&foreign_rules, 0x5678, None).is_ok());
+        assert!(matches!(
+            check_conflicting_rules(&foreign_rules, 100, Some(1000)),
+            Err(Error::RoutingTableInUse(100))
+        ));
+        assert!(matches!(
+            check_conflicting_rules(&foreign_rules, 0x5678, Some(299)),
+            Err(Error::RulePriorityInUse(300))
+        ));
+    }
+
+    #[test]
+    fn test_validate_routing_ids() {
+        assert!(validate_routing_ids(0x6d6f6c65, None).is_ok());
+        assert!(validate_routing_ids(100, Some(MAX_RULE_PRIORITY)).is_ok());
+        for table in [0, 252, 253, 254, 255] {
+            assert!(matches!(
+                validate_routing_ids(table, None),
+                Err(Error::InvalidRoutingTable(_))
+            ));
+        }
+        for priority in [0, MAX_RULE_PRIORITY + 1, 32766] {
+            assert!(matches!(
+                validate_routing_ids(100, Some(priority)),
+                Err(Error::InvalidRulePriority(_))
+            ));
+        }
+    }
+
+    fn net(network: &str) -> IpNetwork {
+        network.parse().unwrap()
+    }
+
+    fn gateway(address: &str, device: &str) -> Node {
+        Node::new(address.parse().unwrap(), device.to_owned())
+    }
+
+    fn routes_via(node: &Node, prefixes: &[&str]) -> HashSet<Route> {
+        prefixes
+            .iter()
+            .map(|prefix| Route::new(node.clone(), net(prefix)).table(RT_TABLE_MAIN.into()))
+            .collect()
+    }
+
+    fn default_node_routes() -> DefaultNodeRoutes {
+        let mut routes = DefaultNodeRoutes::default();
+        routes.insert(net("10.12.0.0/16"), RT_TABLE_MAIN.into());
+        routes.insert(net("192.0.2.1/32"), RT_TABLE_MAIN.into());
+        routes.insert(net("2001:db8::/32"), RT_TABLE_MAIN.into());
+        routes
+    }
+
+    #[test]
+    fn test_default_node_routes_follow_gateway() {
+        let v4_gateway = gateway("192.168.1.1", "eth0");
+        let v6_gateway = gateway("fe80::1", "eth0");
+        let mut routes = default_node_routes();
+
+        let changes = routes.update(Some(&v4_gateway), Some(&v6_gateway));
+        assert!(changes.remove.is_empty());
+        let mut expected = routes_via(&v4_gateway, &["10.12.0.0/16", "192.0.2.1/32"]);
+        expected.extend(routes_via(&v6_gateway, &["2001:db8::/32"]));
+        assert_eq!(changes.add, expected);
+
+        // The IPv4 default route moved to another interface
+        let new_v4_gateway = gateway("10.0.0.1", "wlan0");
+        let changes = routes.update(Some(&new_v4_gateway), Some(&v6_gateway));
+        assert_eq!(
+            changes.remove,
+            routes_via(&v4_gateway, &["10.12.0.0/16", "192.0.2.1/32"])
+        );
+        let mut expected = routes_via(&new_v4_gateway, &["10.12.0.0/16", "192.0.2.1/32"]);
+        expected.extend(routes_via(&v6_gateway, &["2001:db8::/32"]));
+        assert_eq!(changes.add, expected);
+    }
+
+    #[test]
+    fn test_default_node_routes_reinstalled_when_unchanged() {
+        let v4_gateway = gateway("192.168.1.1", "eth0");
+        let mut routes = default_node_routes();
+        let first = routes.update(Some(&v4_gateway), None);
+
+        // The routes may have been flushed with the interface, so they are always added again
+        let second = routes.update(Some(&v4_gateway), None);
+        assert!(second.remove.is_empty());
+        assert_eq!(second.add, first.add);
+    }
+
+    #[test]
+    fn test_default_node_routes_without_default_route() {
+        let v4_gateway = gateway("192.168.1.1", "eth0");
+        let mut routes = default_node_routes();
+        let added = routes.update(Some(&v4_gateway), None).add;
+
+        let changes = routes.update(None, None);
+        assert_eq!(changes.remove, added);
+        assert!(changes.add.is_empty());
+
+        // The routes are added again when a default route appears
+        assert_eq!(routes.update(Some(&v4_gateway), None).add, added);
+
+        routes.clear();
+        assert!(routes.is_empty());
+        assert_eq!(
+            routes.update(Some(&v4_gateway), None),
+            RouteChanges::default()
+        );
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .expect("Failed to initialize runtime")
+            .block_on(future)
+    }
+
+    /// A netlink transport whose requests fail with the given error codes, in order, before they
+    /// succeed.
+    struct FlakyTransport {
+        errnos: Vec<i32>,
+        attempts: u32,
+    }
+
+    impl FlakyTransport {
+        fn new(errnos: &[i32]) -> Self {
+            FlakyTransport {
+                errnos: errnos.iter().rev().copied().collect(),
+                attempts: 0,
+            }
+        }
+
+        fn request(
+            &mut self,
+        ) -> futures::future::Ready<std::result::Result<u32, rtnetlink::Error>> {
+            self.attempts += 1;
+            futures::future::ready(match self.errnos.pop() {
+                Some(errno) => Err(rtnetlink::Error::NetlinkError(
+                    netlink_packet_route::ErrorMessage {
+                        header: vec![],
+                        code: -errno,
+                    },
+                )),
+                None => Ok(self.attempts),
+            })
+        }
+    }
+
+    #[test]
+    fn test_retry_transient_errors() {
+        let mut transport = FlakyTransport::new(&[libc::ENOBUFS, libc::EBUSY]);
+        let (result, elapsed) = block_on(async {
+            let start = tokio::time::Instant::now();
+            let result = retry_transient(Error::Netlink, || transport.request()).await;
+            (result, start.elapsed())
+        });
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(transport.attempts, 3);
+        // The delay is doubled after the first attempt
+        assert_eq!(elapsed, NETLINK_RETRY_DELAY * 3);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let mut transport = FlakyTransport::new(&[libc::EBUSY; 4]);
+        let result = block_on(retry_transient(Error::Netlink, || transport.request()));
+        assert_eq!(transport.attempts, MAX_NETLINK_ATTEMPTS);
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, Error::TransientNetlink(errno, _) if errno.code() == libc::EBUSY));
+        assert!(error
+            .to_string()
+            .starts_with("Netlink request failed with EBUSY"));
+    }
+
+    #[test]
+    fn test_no_retry_for_other_errors() {
+        let mut transport = FlakyTransport::new(&[libc::ESRCH]);
+        let result = block_on(retry_transient(Error::Netlink, || transport.request()));
+        assert_eq!(transport.attempts, 1);
+        assert!(matches!(
+            result,
+            Err(Error::Netlink(rtnetlink::Error::NetlinkError(msg))) if msg.code == -libc::ESRCH
+        ));
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{debounce::BurstGuard, NetNode, Node, RequiredRoute, Route};
+use crate::{debounce::BurstGuard, DesiredRoutes, NetNode, Node, RequiredRoute, Route, RouteDump};
 
 use futures::{
     channel::mpsc::{self, UnboundedReceiver},
@@ -82,6 +82,8 @@ pub struct RouteManagerImpl {
     v4_tunnel_default_route: Option<data::RouteMessage>,
     v6_tunnel_default_route: Option<data::RouteMessage>,
     applied_routes: BTreeMap<RouteDestination, RouteMessage>,
+    /// Routes that have been required since the routes were last cleared.
+    desired_routes: DesiredRoutes,
     v4_default_route: Option<data::RouteMessage>,
     v6_default_route: Option<data::RouteMessage>,
     update_trigger: BurstGuard,
@@ -90,6 +92,14 @@ pub struct RouteManagerImpl {
     unhandled_default_route_changes: bool,
     primary_interface_monitor: interface::PrimaryInterfaceMonitor,
     interface_change_rx: UnboundedReceiver<interface::InterfaceEvent>,
+    /// Whether default routes via the tunnel are applied as two more specific routes, so that
+    /// the default route is left alone.
+    coexistence_mode: bool,
+    /// Interfaces used by the routes that have been added, such as the tunnel interface.
+    route_interfaces: HashSet<String>,
+    /// Tunnel interface of another VPN that the default route uses, if any.
+    foreign_tunnel: Option<String>,
+    foreign_tunnel_listeners: Vec<mpsc::UnboundedSender<Option<String>>>,
 }
 
 impl RouteManagerImpl {
@@ -119,6 +129,7 @@ impl RouteManagerImpl {
             v4_tunnel_default_route: None,
             v6_tunnel_default_route: None,
             applied_routes: BTreeMap::new(),
+            desired_routes: DesiredRoutes::default(),
             v4_default_route: None,
             v6_default_route: None,
             update_trigger,
@@ -127,6 +138,10 @@ impl RouteManagerImpl {
             unhandled_default_route_changes: false,
             primary_interface_monitor,
             interface_change_rx,
+            coexistence_mode: false,
+            route_interfaces: HashSet::new(),
+            foreign_tunnel: None,
+            foreign_tunnel_listeners: vec![],
         })
     }
 
@@ -153,6 +168,7 @@ impl RouteManagerImpl {
             });
 
         self.debug_offline();
+        self.check_foreign_tunnel().await;
 
         let mut completion_tx = None;
 
@@ -214,6 +230,28 @@ impl RouteManagerImpl {
 
                             let _ = tx.send((v4_route, v6_route));
                         }
+                        Some(RouteManagerCommand::SetCoexistenceMode(enabled)) => {
+                            self.coexistence_mode = enabled;
+                        }
+                        Some(RouteManagerCommand::NewForeignTunnelListener(tx)) => {
+                            let (events_tx, events_rx) = mpsc::unbounded();
+                            if let Some(interface) = &self.foreign_tunnel {
+                                let _ = events_tx.unbounded_send(Some(interface.clone()));
+                            }
+                            self.foreign_tunnel_listeners.push(events_tx);
+                            let _ = tx.send(events_rx);
+                        }
+                        Some(RouteManagerCommand::GetGateway(destination, tx)) => {
+                            let default_route = if destination.is_ipv4() {
+                                self.v4_default_route.as_ref()
+                            } else {
+                                self.v6_default_route.as_ref()
+                            };
+                            let _ = tx.send(Ok(default_route.map(|route| Node {
+                                ip: route.gateway_ip(),
+                                device: interface_name(route.interface_index()),
+                            })));
+                        }
 
                         Some(RouteManagerCommand::AddRoutes(routes, tx)) => {
                             if !self.check_default_routes_restored.is_terminated() {
@@ -221,12 +259,20 @@ impl RouteManagerImpl {
                                 self.check_default_routes_restored = Box::pin(futures::stream::pending());
                             }
                             log::debug!("Adding routes: {routes:?}");
+                            self.desired_routes.extend(&routes);
                             let _ = tx.send(self.add_required_routes(routes).await);
                         }
-                        Some(RouteManagerCommand::ClearRoutes) => {
+                        Some(RouteManagerCommand::GetRoutes(tx)) => {
+                            let _ = tx.send(RouteDump {
+                                desired: self.desired_routes.dump(|_| None),
+                                kernel: None,
+                            });
+                        }
+                        Some(RouteManagerCommand::ClearRoutes(done_tx)) => {
                             if let Err(err) = self.cleanup_routes().await {
                                 log::error!("Failed to clean up rotues: {err}");
                             }
+                            let _ = done_tx.send(());
                         },
                         Some(RouteManagerCommand::RefreshRoutes) => {
                             if let Err(error) = self.refresh_routes().await {
@@ -265,6 +311,13 @@ impl RouteManagerImpl {
             }
         }
 
+        let routes_to_apply = tunnel_routes(routes_to_apply, self.coexistence_mode);
+        self.route_interfaces.extend(
+            routes_to_apply
+                .iter()
+                .filter_map(|route| route.node.device.clone()),
+        );
+
         // Map all interfaces to their link addresses
         let interface_link_addrs =
             interface::get_interface_link_addresses().map_err(Error::FetchLinkAddresses)?;
@@ -368,6 +421,7 @@ impl RouteManagerImpl {
         self.update_best_default_route(interface::Family::V6)?;
 
         self.debug_offline();
+        self.check_foreign_tunnel().await;
 
         if !self.unhandled_default_route_changes {
             return Ok(());
@@ -390,6 +444,39 @@ impl RouteManagerImpl {
         Ok(())
     }
 
+    /// Checks whether the unscoped default route uses the tunnel interface of another VPN, and
+    /// notifies the listeners if this changed. The other VPN keeps the default route in
+    /// coexistence mode, and otherwise competes with us for it.
+    async fn check_foreign_tunnel(&mut self) {
+        let mut foreign_tunnel = None;
+        for family in [interface::Family::V4, interface::Family::V6] {
+            let default_route = RouteMessage::new_route(family.default_network().into());
+            let Ok(Some(route)) = self.routing_table.get_route(&default_route).await else {
+                continue;
+            };
+            let Some(interface) = interface_name(route.interface_index()) else {
+                continue;
+            };
+            if is_foreign_tunnel(&interface, &self.route_interfaces) {
+                foreign_tunnel = Some(interface);
+                break;
+            }
+        }
+
+        if foreign_tunnel == self.foreign_tunnel {
+            return;
+        }
+        match &foreign_tunnel {
+            Some(interface) => {
+                log::warn!("The default route uses {interface}, which belongs to another VPN")
+            }
+            None => log::info!("The default route no longer uses the interface of another VPN"),
+        }
+        self.foreign_tunnel = foreign_tunnel.clone();
+        self.foreign_tunnel_listeners
+            .retain(|tx| tx.unbounded_send(foreign_tunnel.clone()).is_ok());
+    }
+
     fn debug_offline(&self) {
         if self.v4_default_route.is_none() && self.v6_default_route.is_none() {
             self.primary_interface_monitor.debug();
@@ -576,15 +663,23 @@ impl RouteManagerImpl {
     }
 
     async fn cleanup_routes(&mut self) -> Result<()> {
+        self.desired_routes.clear();
         self.remove_applied_routes(|_| true).await;
+        self.route_interfaces.clear();
+
+        // In coexistence mode, the default route was never replaced. Restoring it would replace
+        // the default route of any other VPN.
+        let replaced_default_route =
+            self.v4_tunnel_default_route.is_some() || self.v6_tunnel_default_route.is_some();
 
         // We have already removed the applied default routes
         self.v4_tunnel_default_route = None;
         self.v6_tunnel_default_route = None;
 
-        self.try_restore_default_routes().await;
-
-        self.check_default_routes_restored = Self::create_default_route_check_timer();
+        if replaced_default_route || !self.coexistence_mode {
+            self.try_restore_default_routes().await;
+            self.check_default_routes_restored = Self::create_default_route_check_timer();
+        }
 
         self.non_tunnel_routes.clear();
 
@@ -677,3 +772,125 @@ fn route_matches_interface(default_route: &RouteMessage, interface_route: &Route
     default_route.gateway_ip() == interface_route.gateway_ip()
         && default_route.interface_index() == interface_route.interface_index()
 }
+
+/// Returns the routes to apply for routes that use a specific interface. In coexistence mode,
+/// default routes are replaced with two routes that are more specific, so that they take
+/// precedence without replacing the default route, which may belong to another VPN.
+fn tunnel_routes(routes: Vec<Route>, coexistence_mode: bool) -> Vec<Route> {
+    if !coexistence_mode {
+        return routes;
+    }
+    routes
+        .into_iter()
+        .flat_map(|route| {
+            if route.prefix.prefix() != 0 {
+                return vec![route];
+            }
+            split_default_network(route.prefix)
+                .into_iter()
+                .map(|prefix| Route {
+                    prefix,
+                    ..route.clone()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the two halves of a default network.
+fn split_default_network(network: IpNetwork) -> [IpNetwork; 2] {
+    if network.is_ipv4() {
+        ["0.0.0.0/1".parse().unwrap(), "128.0.0.0/1".parse().unwrap()]
+    } else {
+        ["::/1".parse().unwrap(), "8000::/1".parse().unwrap()]
+    }
+}
+
+/// Returns whether `interface` is a tunnel interface that is not used by our routes. `ppp`
+/// interfaces are not considered tunnels, since PPPoE links use them as the physical uplink.
+fn is_foreign_tunnel(interface: &str, route_interfaces: &HashSet<String>) -> bool {
+    const TUNNEL_INTERFACE_PREFIXES: [&str; 2] = ["utun", "ipsec"];
+    TUNNEL_INTERFACE_PREFIXES
+        .iter()
+        .any(|prefix| interface.starts_with(prefix))
+        && !route_interfaces.contains(interface)
+}
+
+/// Returns the name of the interface with the given index.
+fn interface_name(index: u16) -> Option<String> {
+    let mut buffer = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: The buffer is large enough for any interface name
+    let name = unsafe { libc::if_indextoname(u32::from(index), buffer.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+    // SAFETY: `if_indextoname` returned a pointer to the nul-terminated name in `buffer`
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tunnel_route(prefix: &str) -> Route {
+        Route::new(Node::device("utun4".to_owned()), prefix.parse().unwrap())
+    }
+
+    fn prefixes(routes: &[Route]) -> Vec<String> {
+        routes
+            .iter()
+            .map(|route| route.prefix.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_tunnel_routes_normal_mode() {
+        let routes = vec![
+            tunnel_route("0.0.0.0/0"),
+            tunnel_route("::/0"),
+            tunnel_route("10.64.0.1/32"),
+        ];
+        // The default routes replace the default route of the system
+        assert_eq!(tunnel_routes(routes.clone(), false), routes);
+    }
+
+    #[test]
+    fn test_tunnel_routes_coexistence_mode() {
+        let routes = vec![
+            tunnel_route("0.0.0.0/0"),
+            tunnel_route("10.64.0.1/32"),
+            tunnel_route("::/0"),
+        ];
+        let applied = tunnel_routes(routes, true);
+        assert_eq!(
+            prefixes(&applied),
+            vec![
+                "0.0.0.0/1",
+                "128.0.0.0/1",
+                "10.64.0.1/32",
+                "::/1",
+                "8000::/1",
+            ]
+        );
+        // No route would replace the default route
+        assert!(applied.iter().all(|route| route.prefix.prefix() != 0));
+        // The halves still use the tunnel interface
+        assert!(applied
+            .iter()
+            .all(|route| route.node.device.as_deref() == Some("utun4")));
+    }
+
+    #[test]
+    fn test_is_foreign_tunnel() {
+        let ours = HashSet::from(["utun4".to_owned()]);
+        assert!(is_foreign_tunnel("utun3", &ours));
+        assert!(is_foreign_tunnel("ipsec0", &ours));
+        assert!(!is_foreign_tunnel("utun4", &ours));
+        assert!(!is_foreign_tunnel("en0", &ours));
+        // PPPoE uplinks are not VPNs
+        assert!(!is_foreign_tunnel("ppp0", &ours));
+        // Without a tunnel of our own, any tunnel is foreign
+        assert!(is_foreign_tunnel("utun4", &HashSet::new()));
+    }
+}
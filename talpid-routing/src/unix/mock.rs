@@ -0,0 +1,98 @@
+//! Route manager that only keeps the desired routes in memory. It lets the rest of the app run
+//! without the privileges that are needed to change the routing tables.
+
+use super::RouteManagerCommand;
+#[cfg(target_os = "linux")]
+use super::{CallbackMessage, RoutesUpdateListener};
+use crate::{DesiredRoutes, RouteDump};
+use futures::{channel::mpsc, stream::StreamExt};
+
+pub(crate) async fn run(manage_rx: mpsc::UnboundedReceiver<RouteManagerCommand>) {
+    let mut desired_routes = DesiredRoutes::default();
+    // Listeners are kept so that their streams stay open. No changes are ever reported.
+    #[cfg(target_os = "macos")]
+    let mut default_route_listeners = vec![];
+    #[cfg(target_os = "macos")]
+    let mut foreign_tunnel_listeners: Vec<mpsc::UnboundedSender<Option<String>>> = vec![];
+    #[cfg(target_os = "linux")]
+    let mut change_listeners: Vec<mpsc::UnboundedSender<CallbackMessage>> = vec![];
+    #[cfg(target_os = "linux")]
+    let mut routes_update_listeners: Vec<RoutesUpdateListener> = vec![];
+
+    let mut manage_rx = manage_rx.fuse();
+    while let Some(command) = manage_rx.next().await {
+        match command {
+            RouteManagerCommand::Shutdown(tx) => {
+                let _ = tx.send(());
+                break;
+            }
+            RouteManagerCommand::AddRoutes(routes, tx) => {
+                desired_routes.extend(&routes);
+                let _ = tx.send(Ok(()));
+            }
+            RouteManagerCommand::ClearRoutes(tx) => {
+                desired_routes.clear();
+                let _ = tx.send(());
+            }
+            RouteManagerCommand::GetRoutes(tx) => {
+                let _ = tx.send(RouteDump {
+                    desired: desired_routes.dump(|_| None),
+                    kernel: None,
+                });
+            }
+            #[cfg(target_os = "macos")]
+            RouteManagerCommand::RefreshRoutes | RouteManagerCommand::SetCoexistenceMode(_) => (),
+            #[cfg(target_os = "macos")]
+            RouteManagerCommand::NewDefaultRouteListener(tx) => {
+                let (events_tx, events_rx) = mpsc::unbounded();
+                default_route_listeners.push(events_tx);
+                let _ = tx.send(events_rx);
+            }
+            #[cfg(target_os = "macos")]
+            RouteManagerCommand::GetDefaultRoutes(tx) => {
+                let _ = tx.send((None, None));
+            }
+            #[cfg(target_os = "macos")]
+            RouteManagerCommand::NewForeignTunnelListener(tx) => {
+                let (events_tx, events_rx) = mpsc::unbounded();
+                foreign_tunnel_listeners.push(events_tx);
+                let _ = tx.send(events_rx);
+            }
+            #[cfg(target_os = "linux")]
+            RouteManagerCommand::CreateRoutingRules(_, tx)
+            | RouteManagerCommand::ClearRoutingRules(tx) => {
+                let _ = tx.send(Ok(()));
+            }
+            #[cfg(target_os = "linux")]
+            RouteManagerCommand::NewChangeListener(tx) => {
+                let (events_tx, events_rx) = mpsc::unbounded();
+                change_listeners.push(events_tx);
+                let _ = tx.send(events_rx);
+            }
+            #[cfg(target_os = "linux")]
+            RouteManagerCommand::NewRoutesUpdateListener(listener) => {
+                routes_update_listeners.push(listener);
+            }
+            #[cfg(target_os = "linux")]
+            RouteManagerCommand::GetMtuForRoute(_, tx) => {
+                let _ = tx.send(Ok(1500));
+            }
+            #[cfg(target_os = "linux")]
+            RouteManagerCommand::GetDestinationRoute(_, _, tx) => {
+                let _ = tx.send(Ok(None));
+            }
+            #[cfg(target_os = "linux")]
+            RouteManagerCommand::GatewayIsReachable(_, _, tx) => {
+                let _ = tx.send(Ok(true));
+            }
+            #[cfg(target_os = "linux")]
+            RouteManagerCommand::ProbeNeighbour(_, _, tx) => {
+                let _ = tx.send(Ok(()));
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            RouteManagerCommand::GetGateway(_, tx) => {
+                let _ = tx.send(Ok(None));
+            }
+        }
+    }
+}
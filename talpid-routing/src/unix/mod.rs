@@ -1,7 +1,7 @@
 #[cfg(any(target_os = "linux", target_os = "macos"))]
-use crate::Route;
+use crate::{GatewayReachability, Node, Route};
 
-use super::RequiredRoute;
+use super::{RequiredRoute, RouteDump};
 
 use futures::channel::{
     mpsc::{self, UnboundedSender},
@@ -13,7 +13,10 @@ use std::{collections::HashSet, io, sync::Arc};
 use futures::stream::Stream;
 
 #[cfg(target_os = "linux")]
-use std::net::IpAddr;
+use crate::RoutesUpdate;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::{net::IpAddr, time::Duration};
 
 #[allow(clippy::module_inception)]
 #[cfg(target_os = "macos")]
@@ -30,6 +33,12 @@ mod imp;
 #[path = "android.rs"]
 mod imp;
 
+#[cfg(feature = "mock")]
+mod mock;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod probe;
+
 pub use imp::Error as PlatformError;
 
 /// Errors that can be encountered whilst initializing RouteManager
@@ -50,6 +59,9 @@ pub enum Error {
     /// Attempt to use route manager that has been dropped
     #[error(display = "Cannot send message to route manager since it is down")]
     RouteManagerDown,
+    /// Failed to send or receive ARP or NDP packets
+    #[error(display = "Failed to probe the gateway")]
+    ProbeGateway(#[error(source, no_from)] io::Error),
 }
 
 /// Handle to a route manager.
@@ -71,6 +83,16 @@ impl RouteManagerHandle {
             .map_err(Error::PlatformError)
     }
 
+    /// Returns the routes that the route manager has been asked to apply, and the routes in the
+    /// routing tables where they are supported.
+    pub async fn get_routes(&self) -> Result<RouteDump, Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::GetRoutes(response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)
+    }
+
     /// Listen for non-tunnel default route changes.
     #[cfg(target_os = "macos")]
     pub async fn default_route_listener(
@@ -101,6 +123,30 @@ impl RouteManagerHandle {
             .map_err(|_| Error::RouteManagerDown)
     }
 
+    /// Sets whether default routes via the tunnel are applied as two more specific routes, rather
+    /// than by replacing the default route. This lets another VPN keep the default route. Takes
+    /// effect the next time the routes are applied.
+    #[cfg(target_os = "macos")]
+    pub fn set_coexistence_mode(&self, enabled: bool) -> Result<(), Error> {
+        self.tx
+            .unbounded_send(RouteManagerCommand::SetCoexistenceMode(enabled))
+            .map_err(|_| Error::RouteManagerDown)
+    }
+
+    /// Listen for changes to the tunnel interface of another VPN that the default route uses.
+    /// `None` is sent when the default route no longer uses such an interface. If it does when
+    /// the listener is created, the interface is sent first.
+    #[cfg(target_os = "macos")]
+    pub async fn foreign_tunnel_listener(
+        &self,
+    ) -> Result<impl Stream<Item = Option<String>>, Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::NewForeignTunnelListener(response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)
+    }
+
     /// Ensure that packets are routed using the correct tables.
     #[cfg(target_os = "linux")]
     pub async fn create_routing_rules(&self, enable_ipv6: bool) -> Result<(), Error> {
@@ -130,6 +176,21 @@ impl RouteManagerHandle {
             .map_err(Error::PlatformError)
     }
 
+    /// Registers a callback that receives the routes that are added and removed by each operation
+    /// of the route manager. The callback is called before the operation completes, so an update
+    /// is seen before anything that waits for the operation, such as a tunnel state change.
+    #[cfg(target_os = "linux")]
+    pub fn add_routes_update_listener(
+        &self,
+        listener: impl Fn(RoutesUpdate) + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        self.tx
+            .unbounded_send(RouteManagerCommand::NewRoutesUpdateListener(
+                RoutesUpdateListener(Box::new(listener)),
+            ))
+            .map_err(|_| Error::RouteManagerDown)
+    }
+
     /// Listen for route changes.
     #[cfg(target_os = "linux")]
     pub async fn change_listener(&self) -> Result<impl Stream<Item = CallbackMessage>, Error> {
@@ -161,6 +222,76 @@ impl RouteManagerHandle {
             .map_err(Error::PlatformError)
     }
 
+    /// Returns whether `gateway` on the interface `device` is reachable according to the neighbour
+    /// table, which the kernel keeps up to date using ARP and NDP. Gateways that are not in the
+    /// table are presumed to be reachable.
+    #[cfg(target_os = "linux")]
+    pub async fn gateway_is_reachable(
+        &self,
+        gateway: IpAddr,
+        device: String,
+    ) -> Result<bool, Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::GatewayIsReachable(
+                gateway,
+                device,
+                response_tx,
+            ))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::PlatformError)
+    }
+
+    /// Makes the kernel check that the neighbour `address` on the interface `device` is reachable,
+    /// using ARP or NDP. The result shows up in [`Self::gateway_is_reachable`] once the neighbour
+    /// has responded or the kernel has given up, which takes a few seconds.
+    #[cfg(target_os = "linux")]
+    pub async fn probe_neighbour(&self, address: IpAddr, device: String) -> Result<(), Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::ProbeNeighbour(
+                address,
+                device,
+                response_tx,
+            ))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::PlatformError)
+    }
+
+    /// Probes the gateway of the non-tunnel route to `destination` using ARP or NDP, and waits at
+    /// most `timeout` for it to respond.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub async fn probe_gateway(
+        &self,
+        destination: IpAddr,
+        timeout: Duration,
+    ) -> Result<GatewayReachability, Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::GetGateway(destination, response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        let gateway = response_rx
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::PlatformError)?;
+        let Some((address, device)) = gateway
+            .as_ref()
+            .and_then(|node| Some((node.get_address()?, node.get_device()?.to_owned())))
+        else {
+            return Ok(GatewayReachability::Unknown);
+        };
+        tokio::task::spawn_blocking(move || probe::probe(&device, address, timeout))
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::ProbeGateway)
+    }
+
     /// Listen for route changes.
     #[cfg(target_os = "linux")]
     pub async fn get_mtu_for_route(&self, ip: IpAddr) -> Result<u16, Error> {
@@ -186,7 +317,8 @@ pub(crate) enum RouteManagerCommand {
         HashSet<RequiredRoute>,
         oneshot::Sender<Result<(), PlatformError>>,
     ),
-    ClearRoutes,
+    ClearRoutes(oneshot::Sender<()>),
+    GetRoutes(oneshot::Sender<RouteDump>),
     Shutdown(oneshot::Sender<()>),
     #[cfg(target_os = "macos")]
     RefreshRoutes,
@@ -194,6 +326,10 @@ pub(crate) enum RouteManagerCommand {
     NewDefaultRouteListener(oneshot::Sender<mpsc::UnboundedReceiver<DefaultRouteEvent>>),
     #[cfg(target_os = "macos")]
     GetDefaultRoutes(oneshot::Sender<(Option<Route>, Option<Route>)>),
+    #[cfg(target_os = "macos")]
+    SetCoexistenceMode(bool),
+    #[cfg(target_os = "macos")]
+    NewForeignTunnelListener(oneshot::Sender<mpsc::UnboundedReceiver<Option<String>>>),
     #[cfg(target_os = "linux")]
     CreateRoutingRules(bool, oneshot::Sender<Result<(), PlatformError>>),
     #[cfg(target_os = "linux")]
@@ -201,6 +337,8 @@ pub(crate) enum RouteManagerCommand {
     #[cfg(target_os = "linux")]
     NewChangeListener(oneshot::Sender<mpsc::UnboundedReceiver<CallbackMessage>>),
     #[cfg(target_os = "linux")]
+    NewRoutesUpdateListener(RoutesUpdateListener),
+    #[cfg(target_os = "linux")]
     GetMtuForRoute(IpAddr, oneshot::Sender<Result<u16, PlatformError>>),
     /// Attempt to fetch a route for the given destination with an optional firewall mark.
     #[cfg(target_os = "linux")]
@@ -209,6 +347,15 @@ pub(crate) enum RouteManagerCommand {
         Option<Fwmark>,
         oneshot::Sender<Result<Option<Route>, PlatformError>>,
     ),
+    /// Check whether a gateway on the given interface is reachable.
+    #[cfg(target_os = "linux")]
+    GatewayIsReachable(IpAddr, String, oneshot::Sender<Result<bool, PlatformError>>),
+    /// Make the kernel resolve a neighbour on the given interface.
+    #[cfg(target_os = "linux")]
+    ProbeNeighbour(IpAddr, String, oneshot::Sender<Result<(), PlatformError>>),
+    /// Fetch the gateway and interface of the non-tunnel route to the given destination.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    GetGateway(IpAddr, oneshot::Sender<Result<Option<Node>, PlatformError>>),
 }
 
 /// Event that is sent when a preferred non-tunnel default route is
@@ -233,6 +380,25 @@ pub enum CallbackMessage {
     DelRoute(Route),
 }
 
+/// Callback that receives the routes changed by each operation of the route manager.
+#[cfg(target_os = "linux")]
+pub(crate) struct RoutesUpdateListener(Box<dyn Fn(RoutesUpdate) + Send + Sync>);
+
+#[cfg(target_os = "linux")]
+impl RoutesUpdateListener {
+    pub(crate) fn notify(&self, update: RoutesUpdate) {
+        (self.0)(update)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl std::fmt::Debug for RoutesUpdateListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoutesUpdateListener")
+            .finish_non_exhaustive()
+    }
+}
+
 /// RouteManager applies a set of routes to the route table.
 /// If a destination has to be routed through the default node,
 /// the route will be adjusted dynamically when the default route changes.
@@ -243,17 +409,24 @@ pub struct RouteManager {
 
 impl RouteManager {
     /// Construct a RouteManager.
+    ///
+    /// On Linux, `table_id` is the routing table used for the tunnel routes, and `rule_priority`
+    /// is the priority of the routing rules. If `rule_priority` is `None`, the kernel assigns the
+    /// priorities. Fails if another program already uses the table or priorities.
     pub async fn new(
         #[cfg(target_os = "linux")] fwmark: u32,
         #[cfg(target_os = "linux")] table_id: u32,
+        #[cfg(target_os = "linux")] rule_priority: Option<u32>,
     ) -> Result<Self, Error> {
         let (manage_tx, manage_rx) = mpsc::unbounded();
         let manage_tx = Arc::new(manage_tx);
         let manager = imp::RouteManagerImpl::new(
+            #[cfg(target_os = "linux")]
+            table_id,
             #[cfg(target_os = "linux")]
             fwmark,
             #[cfg(target_os = "linux")]
-            table_id,
+            rule_priority,
             #[cfg(target_os = "macos")]
             Arc::downgrade(&manage_tx),
         )
@@ -266,6 +439,19 @@ impl RouteManager {
         })
     }
 
+    /// Construct a RouteManager that only keeps the desired routes in memory, and never touches
+    /// the routing tables.
+    #[cfg(feature = "mock")]
+    pub fn mock() -> Self {
+        let (manage_tx, manage_rx) = mpsc::unbounded();
+        tokio::spawn(mock::run(manage_rx));
+
+        Self {
+            runtime: tokio::runtime::Handle::current(),
+            manage_tx: Some(Arc::new(manage_tx)),
+        }
+    }
+
     /// Stops RouteManager and removes all of the applied routes.
     pub async fn stop(&mut self) {
         if let Some(tx) = self.manage_tx.take() {
@@ -306,12 +492,19 @@ impl RouteManager {
     }
 
     /// Removes all routes previously applied in [`RouteManager::add_routes`].
+    /// Returns once the routes have been removed.
     pub fn clear_routes(&mut self) -> Result<(), Error> {
         if let Some(tx) = &self.manage_tx {
-            if tx.unbounded_send(RouteManagerCommand::ClearRoutes).is_err() {
+            let (done_tx, done_rx) = oneshot::channel();
+            if tx
+                .unbounded_send(RouteManagerCommand::ClearRoutes(done_tx))
+                .is_err()
+            {
                 return Err(Error::RouteManagerDown);
             }
-            Ok(())
+            self.runtime
+                .block_on(done_rx)
+                .map_err(|_| Error::ManagerChannelDown)
         } else {
             Err(Error::RouteManagerDown)
         }
@@ -344,3 +537,60 @@ impl Drop for RouteManager {
         self.runtime.clone().block_on(self.stop());
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+    use std::sync::Mutex;
+
+    /// The tunnel state machine changes state once `clear_routes` returns, so the routes update
+    /// must have been delivered by then.
+    #[test]
+    fn test_clear_routes_waits_for_update() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let (manage_tx, mut manage_rx) = mpsc::unbounded();
+        // Stands in for the route manager, which notifies the listeners before it completes an
+        // operation
+        runtime.spawn(async move {
+            let mut listeners = vec![];
+            while let Some(command) = manage_rx.next().await {
+                match command {
+                    RouteManagerCommand::NewRoutesUpdateListener(listener) => {
+                        listeners.push(listener)
+                    }
+                    RouteManagerCommand::ClearRoutes(done_tx) => {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        for listener in &listeners {
+                            listener.notify(RoutesUpdate::default());
+                        }
+                        let _ = done_tx.send(());
+                    }
+                    RouteManagerCommand::Shutdown(done_tx) => {
+                        let _ = done_tx.send(());
+                    }
+                    _ => (),
+                }
+            }
+        });
+        let mut route_manager = RouteManager {
+            runtime: runtime.handle().clone(),
+            manage_tx: Some(Arc::new(manage_tx)),
+        };
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let listener_events = events.clone();
+        route_manager
+            .handle()
+            .unwrap()
+            .add_routes_update_listener(move |_| listener_events.lock().unwrap().push("routes"))
+            .unwrap();
+
+        route_manager.clear_routes().unwrap();
+        events.lock().unwrap().push("state change");
+        assert_eq!(*events.lock().unwrap(), ["routes", "state change"]);
+    }
+}
@@ -0,0 +1,107 @@
+//! ARP requests are sent on a packet socket, so that they do not depend on the neighbour table or
+//! the firewall.
+
+use super::{is_arp_reply, open_socket, recv_timeout, ProbeSocket, ARP_PACKET_LEN};
+use std::{
+    io, mem,
+    net::Ipv4Addr,
+    os::unix::io::{AsRawFd, OwnedFd},
+    time::Duration,
+};
+
+const ETH_P_ARP: u16 = libc::ETH_P_ARP as u16;
+
+/// Sends ARP requests to a gateway and receives the replies.
+pub struct ArpSocket {
+    socket: OwnedFd,
+    gateway: Ipv4Addr,
+    request: [u8; ARP_PACKET_LEN],
+    broadcast: libc::sockaddr_ll,
+}
+
+impl ArpSocket {
+    pub fn open(
+        _interface: &str,
+        index: u32,
+        request: [u8; ARP_PACKET_LEN],
+        gateway: Ipv4Addr,
+    ) -> io::Result<Self> {
+        // The kernel adds the Ethernet header to datagram packet sockets
+        let socket = open_socket(
+            libc::AF_PACKET,
+            libc::SOCK_DGRAM,
+            libc::c_int::from(ETH_P_ARP.to_be()),
+        )?;
+
+        let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as libc::c_ushort;
+        address.sll_protocol = ETH_P_ARP.to_be();
+        address.sll_ifindex = index as libc::c_int;
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut broadcast = address;
+        broadcast.sll_halen = 6;
+        broadcast.sll_addr[..6].copy_from_slice(&[0xff; 6]);
+
+        Ok(ArpSocket {
+            socket,
+            gateway,
+            request,
+            broadcast,
+        })
+    }
+}
+
+impl ProbeSocket for ArpSocket {
+    fn send_request(&mut self) -> io::Result<()> {
+        let result = unsafe {
+            libc::sendto(
+                self.socket.as_raw_fd(),
+                self.request.as_ptr() as *const libc::c_void,
+                self.request.len(),
+                0,
+                &self.broadcast as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn recv_reply(&mut self, timeout: Duration) -> io::Result<bool> {
+        let mut buffer = [0u8; 1500];
+        let Some(len) = recv_timeout(self.socket.as_raw_fd(), &mut buffer, timeout)? else {
+            return Ok(false);
+        };
+        Ok(is_arp_reply(&buffer[..len], self.gateway))
+    }
+}
+
+/// Returns the Ethernet address in `address` if it is a link-layer address.
+///
+/// # Safety
+///
+/// `address` must point to a valid socket address.
+pub unsafe fn link_address(address: *const libc::sockaddr) -> Option<[u8; 6]> {
+    if libc::c_int::from((*address).sa_family) != libc::AF_PACKET {
+        return None;
+    }
+    let address = &*(address as *const libc::sockaddr_ll);
+    if address.sll_halen != 6 || address.sll_hatype != libc::ARPHRD_ETHER {
+        return None;
+    }
+    let mut link = [0u8; 6];
+    link.copy_from_slice(&address.sll_addr[..6]);
+    Some(link)
+}
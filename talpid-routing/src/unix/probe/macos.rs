@@ -0,0 +1,207 @@
+//! ARP requests are sent using a BPF device, so that they do not depend on the ARP table or the
+//! packet filter.
+
+use super::{is_arp_reply, poll_readable, ProbeSocket, ARP_PACKET_LEN};
+use std::{
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    net::Ipv4Addr,
+    os::unix::io::AsRawFd,
+    time::Duration,
+};
+
+/// Number of BPF devices to try before giving up.
+const MAX_BPF_DEVICES: usize = 256;
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_ARP: u16 = 0x0806;
+/// BPF records are aligned to the size of an `int32_t`.
+const BPF_ALIGNMENT: usize = 4;
+/// Offsets of `bh_caplen` and `bh_hdrlen` in `struct bpf_hdr`.
+const BPF_CAPLEN_OFFSET: usize = 8;
+const BPF_HDRLEN_OFFSET: usize = 16;
+const BPF_HEADER_MIN_LEN: usize = 18;
+/// Interface type of Ethernet interfaces, from `<net/if_types.h>`.
+const IFT_ETHER: u8 = 0x6;
+
+/// `struct ifreq`. Only the name is read by `BIOCSETIF`.
+#[repr(C)]
+struct IfReq {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    _data: [u8; 16],
+}
+
+/// Sends ARP requests to a gateway and receives the replies.
+pub struct ArpSocket {
+    device: File,
+    gateway: Ipv4Addr,
+    frame: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl ArpSocket {
+    pub fn open(
+        interface: &str,
+        _index: u32,
+        request: [u8; ARP_PACKET_LEN],
+        gateway: Ipv4Addr,
+    ) -> io::Result<Self> {
+        let device = open_bpf_device()?;
+        let fd = device.as_raw_fd();
+
+        let c_name = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+        let mut ifreq = IfReq {
+            name: [0; libc::IFNAMSIZ],
+            _data: [0; 16],
+        };
+        let name = c_name.as_bytes_with_nul();
+        if name.len() > ifreq.name.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Interface name is too long",
+            ));
+        }
+        for (dst, src) in ifreq.name.iter_mut().zip(name) {
+            *dst = *src as libc::c_char;
+        }
+        ioctl(fd, libc::BIOCSETIF, &mut ifreq)?;
+        // Return packets as soon as they are received
+        ioctl(fd, libc::BIOCIMMEDIATE, &mut 1 as *mut libc::c_uint)?;
+        // Use the source address in the frames that are written
+        ioctl(fd, libc::BIOCSHDRCMPLT, &mut 1 as *mut libc::c_uint)?;
+        let mut buffer_len: libc::c_uint = 0;
+        ioctl(fd, libc::BIOCGBLEN, &mut buffer_len)?;
+
+        let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + ARP_PACKET_LEN);
+        frame.extend_from_slice(&[0xff; 6]);
+        frame.extend_from_slice(&request[8..14]);
+        frame.extend_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+        frame.extend_from_slice(&request);
+
+        Ok(ArpSocket {
+            device,
+            gateway,
+            frame,
+            buffer: vec![0; buffer_len as usize],
+        })
+    }
+}
+
+impl ProbeSocket for ArpSocket {
+    fn send_request(&mut self) -> io::Result<()> {
+        self.device.write_all(&self.frame)
+    }
+
+    fn recv_reply(&mut self, timeout: Duration) -> io::Result<bool> {
+        if !poll_readable(self.device.as_raw_fd(), timeout)? {
+            return Ok(false);
+        }
+        let len = self.device.read(&mut self.buffer)?;
+        Ok(bpf_records(&self.buffer[..len]).any(|frame| {
+            frame.len() >= ETHERNET_HEADER_LEN
+                && frame[12..14] == ETHERTYPE_ARP.to_be_bytes()
+                && is_arp_reply(&frame[ETHERNET_HEADER_LEN..], self.gateway)
+        }))
+    }
+}
+
+fn open_bpf_device() -> io::Result<File> {
+    for i in 0..MAX_BPF_DEVICES {
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/dev/bpf{i}"))
+        {
+            Ok(device) => return Ok(device),
+            Err(error) if error.raw_os_error() == Some(libc::EBUSY) => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "No BPF device is available",
+    ))
+}
+
+fn ioctl<T>(fd: libc::c_int, request: libc::c_ulong, arg: *mut T) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, request, arg) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns the captured frames in a buffer read from a BPF device.
+fn bpf_records(mut buffer: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        if buffer.len() < BPF_HEADER_MIN_LEN {
+            return None;
+        }
+        let caplen = u32::from_ne_bytes(
+            buffer[BPF_CAPLEN_OFFSET..BPF_CAPLEN_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let hdrlen = usize::from(u16::from_ne_bytes(
+            buffer[BPF_HDRLEN_OFFSET..BPF_HDRLEN_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        ));
+        let frame = buffer.get(hdrlen..hdrlen + caplen)?;
+        let record_len = bpf_word_align(hdrlen + caplen);
+        buffer = buffer.get(record_len..).unwrap_or(&[]);
+        Some(frame)
+    })
+}
+
+fn bpf_word_align(len: usize) -> usize {
+    (len + BPF_ALIGNMENT - 1) & !(BPF_ALIGNMENT - 1)
+}
+
+/// Returns the Ethernet address in `address` if it is a link-layer address.
+///
+/// # Safety
+///
+/// `address` must point to a valid socket address.
+pub unsafe fn link_address(address: *const libc::sockaddr) -> Option<[u8; 6]> {
+    if libc::c_int::from((*address).sa_family) != libc::AF_LINK {
+        return None;
+    }
+    let address = &*(address as *const libc::sockaddr_dl);
+    if address.sdl_alen != 6 || address.sdl_type != IFT_ETHER {
+        return None;
+    }
+    // The link-layer address follows the interface name
+    let data = address.sdl_data.as_ptr().add(usize::from(address.sdl_nlen)) as *const u8;
+    let mut link = [0u8; 6];
+    link.copy_from_slice(std::slice::from_raw_parts(data, 6));
+    Some(link)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(frame: &[u8]) -> Vec<u8> {
+        let hdrlen = BPF_HEADER_MIN_LEN as u16;
+        let mut record = vec![0u8; BPF_HEADER_MIN_LEN];
+        record[BPF_CAPLEN_OFFSET..BPF_CAPLEN_OFFSET + 4]
+            .copy_from_slice(&(frame.len() as u32).to_ne_bytes());
+        record[BPF_HDRLEN_OFFSET..BPF_HDRLEN_OFFSET + 2].copy_from_slice(&hdrlen.to_ne_bytes());
+        record.extend_from_slice(frame);
+        record.resize(bpf_word_align(record.len()), 0);
+        record
+    }
+
+    #[test]
+    fn test_bpf_records() {
+        let mut buffer = record(&[1, 2, 3]);
+        buffer.extend(record(&[4, 5, 6, 7, 8]));
+
+        let frames: Vec<_> = bpf_records(&buffer).collect();
+        assert_eq!(frames, vec![&[1, 2, 3][..], &[4, 5, 6, 7, 8][..]]);
+
+        // Truncated records are ignored
+        assert_eq!(bpf_records(&buffer[..BPF_HEADER_MIN_LEN + 1]).count(), 0);
+    }
+}
@@ -0,0 +1,409 @@
+//! Probes whether a gateway responds to ARP requests or NDP neighbor solicitations. After the host
+//! resumes from sleep, the neighbour entry of the gateway may still look valid even though the
+//! gateway no longer answers, so the neighbour table alone cannot be relied upon.
+
+use crate::GatewayReachability;
+use std::{
+    ffi::{CStr, CString},
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod arp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod arp;
+
+/// Time between retransmitted requests while waiting for a reply.
+const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Length of an ARP packet for IPv4 over Ethernet.
+const ARP_PACKET_LEN: usize = 28;
+const ARP_OP_REQUEST: u16 = 1;
+const ARP_OP_REPLY: u16 = 2;
+
+const ICMPV6_NEIGHBOR_SOLICITATION: u8 = 135;
+const ICMPV6_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+const NDP_OPTION_SOURCE_LINK_ADDRESS: u8 = 1;
+
+/// Hop limit that NDP messages must be sent with, so that they are not accepted from off-link.
+const NDP_HOP_LIMIT: libc::c_int = 255;
+
+/// Sends requests to a gateway and receives the replies.
+trait ProbeSocket {
+    fn send_request(&mut self) -> io::Result<()>;
+
+    /// Waits at most `timeout` for a packet. Returns whether it was a reply from the gateway.
+    fn recv_reply(&mut self, timeout: Duration) -> io::Result<bool>;
+}
+
+/// Returns whether `gateway` on `interface` responds within `timeout`. This blocks until a reply
+/// is received or the timeout expires.
+pub fn probe(
+    interface: &str,
+    gateway: IpAddr,
+    timeout: Duration,
+) -> io::Result<GatewayReachability> {
+    let addresses = InterfaceAddresses::get(interface)?;
+    let Some(link_address) = addresses.link else {
+        // Interfaces without link-layer addresses, such as point-to-point links, do not use
+        // ARP or NDP
+        return Ok(GatewayReachability::Unknown);
+    };
+
+    let responded = match gateway {
+        IpAddr::V4(gateway) => {
+            let Some(source) = addresses.ipv4 else {
+                return Ok(GatewayReachability::Unknown);
+            };
+            let request = arp_request(link_address, source, gateway);
+            let mut socket = arp::ArpSocket::open(interface, addresses.index, request, gateway)?;
+            run_probe(&mut socket, timeout)?
+        }
+        IpAddr::V6(gateway) => {
+            let mut socket = NdpSocket::open(addresses.index, link_address, gateway)?;
+            run_probe(&mut socket, timeout)?
+        }
+    };
+
+    Ok(if responded {
+        GatewayReachability::Reachable
+    } else {
+        GatewayReachability::Unreachable
+    })
+}
+
+fn run_probe(socket: &mut impl ProbeSocket, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    let mut next_request = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        if now >= next_request {
+            socket.send_request()?;
+            next_request = now + RETRANSMIT_INTERVAL;
+        }
+        let wait = next_request.min(deadline).saturating_duration_since(now);
+        if socket.recv_reply(wait)? {
+            return Ok(true);
+        }
+    }
+}
+
+/// Addresses of a network interface that are needed to probe a gateway.
+#[derive(Debug)]
+struct InterfaceAddresses {
+    index: u32,
+    /// Ethernet address of the interface.
+    link: Option<[u8; 6]>,
+    ipv4: Option<Ipv4Addr>,
+}
+
+impl InterfaceAddresses {
+    fn get(interface: &str) -> io::Result<Self> {
+        let c_name = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+        let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut addresses = InterfaceAddresses {
+            index,
+            link: None,
+            ipv4: None,
+        };
+
+        let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut current = ifaddrs;
+        while let Some(ifaddr) = unsafe { current.as_ref() } {
+            current = ifaddr.ifa_next;
+            if ifaddr.ifa_addr.is_null() || unsafe { CStr::from_ptr(ifaddr.ifa_name) } != &*c_name {
+                continue;
+            }
+            let family = libc::c_int::from(unsafe { (*ifaddr.ifa_addr).sa_family });
+            match family {
+                libc::AF_INET => {
+                    let addr = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in) };
+                    addresses
+                        .ipv4
+                        .get_or_insert(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)));
+                }
+                _ => {
+                    if let Some(link) = unsafe { arp::link_address(ifaddr.ifa_addr) } {
+                        addresses.link = Some(link);
+                    }
+                }
+            }
+        }
+        unsafe { libc::freeifaddrs(ifaddrs) };
+
+        Ok(addresses)
+    }
+}
+
+/// Sends NDP neighbor solicitations to a gateway and receives the advertisements.
+struct NdpSocket {
+    socket: OwnedFd,
+    gateway: Ipv6Addr,
+    request: Vec<u8>,
+    destination: libc::sockaddr_in6,
+}
+
+impl NdpSocket {
+    fn open(index: u32, link_address: [u8; 6], gateway: Ipv6Addr) -> io::Result<Self> {
+        let socket = open_socket(libc::AF_INET6, libc::SOCK_RAW, libc::IPPROTO_ICMPV6)?;
+        set_socket_option(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MULTICAST_IF,
+            index as libc::c_uint,
+        )?;
+        set_socket_option(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MULTICAST_HOPS,
+            NDP_HOP_LIMIT,
+        )?;
+        set_socket_option(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_UNICAST_HOPS,
+            NDP_HOP_LIMIT,
+        )?;
+
+        let mut destination: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+        #[cfg(target_os = "macos")]
+        {
+            destination.sin6_len = mem::size_of::<libc::sockaddr_in6>() as u8;
+        }
+        destination.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        destination.sin6_addr.s6_addr = solicited_node_address(gateway).octets();
+        destination.sin6_scope_id = index;
+
+        Ok(NdpSocket {
+            socket,
+            gateway,
+            request: neighbor_solicitation(gateway, link_address),
+            destination,
+        })
+    }
+}
+
+impl ProbeSocket for NdpSocket {
+    fn send_request(&mut self) -> io::Result<()> {
+        // The kernel computes the ICMPv6 checksum
+        let result = unsafe {
+            libc::sendto(
+                self.socket.as_raw_fd(),
+                self.request.as_ptr() as *const libc::c_void,
+                self.request.len(),
+                0,
+                &self.destination as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn recv_reply(&mut self, timeout: Duration) -> io::Result<bool> {
+        let mut buffer = [0u8; 1500];
+        let Some(len) = recv_timeout(self.socket.as_raw_fd(), &mut buffer, timeout)? else {
+            return Ok(false);
+        };
+        Ok(is_neighbor_advertisement(&buffer[..len], self.gateway))
+    }
+}
+
+/// Returns an ARP request for `target`, sent from `sender_link` and `sender_ip`.
+fn arp_request(
+    sender_link: [u8; 6],
+    sender_ip: Ipv4Addr,
+    target: Ipv4Addr,
+) -> [u8; ARP_PACKET_LEN] {
+    let mut packet = [0u8; ARP_PACKET_LEN];
+    // Ethernet and IPv4
+    packet[0..2].copy_from_slice(&1u16.to_be_bytes());
+    packet[2..4].copy_from_slice(&0x0800u16.to_be_bytes());
+    packet[4] = 6;
+    packet[5] = 4;
+    packet[6..8].copy_from_slice(&ARP_OP_REQUEST.to_be_bytes());
+    packet[8..14].copy_from_slice(&sender_link);
+    packet[14..18].copy_from_slice(&sender_ip.octets());
+    // The target hardware address is unknown and left as zeros
+    packet[24..28].copy_from_slice(&target.octets());
+    packet
+}
+
+/// Returns whether `packet` is an ARP reply sent by `gateway`.
+fn is_arp_reply(packet: &[u8], gateway: Ipv4Addr) -> bool {
+    packet.len() >= ARP_PACKET_LEN
+        && packet[0..2] == 1u16.to_be_bytes()
+        && packet[2..4] == 0x0800u16.to_be_bytes()
+        && packet[6..8] == ARP_OP_REPLY.to_be_bytes()
+        && packet[14..18] == gateway.octets()
+}
+
+/// Returns a neighbor solicitation for `target`, which includes the link-layer address of the
+/// sender as required for multicast solicitations.
+fn neighbor_solicitation(target: Ipv6Addr, sender_link: [u8; 6]) -> Vec<u8> {
+    let mut packet = vec![ICMPV6_NEIGHBOR_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&target.octets());
+    // The option length is measured in units of 8 bytes
+    packet.extend_from_slice(&[NDP_OPTION_SOURCE_LINK_ADDRESS, 1]);
+    packet.extend_from_slice(&sender_link);
+    packet
+}
+
+/// Returns whether `packet` is a neighbor advertisement for `gateway`.
+fn is_neighbor_advertisement(packet: &[u8], gateway: Ipv6Addr) -> bool {
+    packet.len() >= 24
+        && packet[0] == ICMPV6_NEIGHBOR_ADVERTISEMENT
+        && packet[1] == 0
+        && packet[8..24] == gateway.octets()
+}
+
+/// Returns the solicited-node multicast address that neighbor solicitations for `address` are
+/// sent to.
+fn solicited_node_address(address: Ipv6Addr) -> Ipv6Addr {
+    let octets = address.octets();
+    Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | u16::from(octets[13]),
+        u16::from_be_bytes([octets[14], octets[15]]),
+    )
+}
+
+fn open_socket(domain: libc::c_int, ty: libc::c_int, protocol: libc::c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(domain, ty, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn set_socket_option<T>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: T,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Waits at most `timeout` for `fd` to become readable.
+fn poll_readable(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+    match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+        result if result < 0 => {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            Err(error)
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Receives a packet from `fd`, waiting at most `timeout` for one to arrive.
+fn recv_timeout(fd: RawFd, buffer: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+    if !poll_readable(fd, timeout)? {
+        return Ok(None);
+    }
+    let len = unsafe {
+        libc::recv(
+            fd,
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+            0,
+        )
+    };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(len as usize))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LINK: [u8; 6] = [0x02, 0x00, 0x5e, 0x10, 0x20, 0x30];
+
+    #[test]
+    fn test_arp_reply() {
+        let gateway = Ipv4Addr::new(192, 168, 1, 1);
+        let request = arp_request(LINK, Ipv4Addr::new(192, 168, 1, 10), gateway);
+        assert_eq!(&request[24..28], &gateway.octets());
+        // A request is not a reply
+        assert!(!is_arp_reply(&request, gateway));
+
+        let mut reply = request;
+        reply[6..8].copy_from_slice(&ARP_OP_REPLY.to_be_bytes());
+        reply[14..18].copy_from_slice(&gateway.octets());
+        assert!(is_arp_reply(&reply, gateway));
+        assert!(!is_arp_reply(&reply, Ipv4Addr::new(192, 168, 1, 2)));
+        assert!(!is_arp_reply(&reply[..20], gateway));
+    }
+
+    #[test]
+    fn test_neighbor_advertisement() {
+        let gateway: Ipv6Addr = "fe80::1:2:3".parse().unwrap();
+        let solicitation = neighbor_solicitation(gateway, LINK);
+        assert_eq!(solicitation.len(), 32);
+        assert!(!is_neighbor_advertisement(&solicitation, gateway));
+
+        let mut advertisement = solicitation[..24].to_vec();
+        advertisement[0] = ICMPV6_NEIGHBOR_ADVERTISEMENT;
+        assert!(is_neighbor_advertisement(&advertisement, gateway));
+        assert!(!is_neighbor_advertisement(
+            &advertisement,
+            "fe80::1".parse().unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_solicited_node_address() {
+        assert_eq!(
+            solicited_node_address("fe80::2aa:ff:fe28:9c5a".parse().unwrap()),
+            "ff02::1:ff28:9c5a".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::RequiredRoute;
+use crate::{DesiredRoutes, GatewayReachability, RequiredRoute, RouteDump};
 pub use default_route_monitor::EventType;
 use futures::{
     channel::{
@@ -10,12 +10,13 @@ use futures::{
 pub use get_best_default_route::{get_best_default_route, route_has_gateway, InterfaceAndGateway};
 use net::AddressFamily;
 pub use route_manager::{Callback, CallbackHandle, Route, RouteManagerInternal};
-use std::{collections::HashSet, io, net::IpAddr};
+use std::{collections::HashSet, io, net::IpAddr, time::Duration};
 use talpid_types::ErrorExt;
 use talpid_windows_net as net;
 
 mod default_route_monitor;
 mod get_best_default_route;
+mod probe;
 mod route_manager;
 
 /// Windows routing errors.
@@ -89,6 +90,9 @@ pub enum Error {
     /// Could not find device by gateway
     #[error(display = "Could not find device by gateway")]
     GetDeviceByGateway,
+    /// ResolveIpNetEntry2 windows API call failed
+    #[error(display = "Failed to resolve the link-layer address of the gateway")]
+    ResolveNeighbor(io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -137,12 +141,40 @@ impl RouteManagerHandle {
             .map_err(|_| Error::RouteManagerDown)?;
         response_rx.await.map_err(|_| Error::ManagerChannelDown)?
     }
+
+    /// Returns the routes that the route manager has been asked to apply.
+    pub async fn get_routes(&self) -> Result<RouteDump> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::GetRoutes(response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)
+    }
+
+    /// Probes the gateway of the best default route for the IP version of `destination` using ARP
+    /// or NDP, and waits at most `timeout` for it to respond.
+    pub async fn probe_gateway(
+        &self,
+        destination: IpAddr,
+        timeout: Duration,
+    ) -> Result<GatewayReachability> {
+        let family = if destination.is_ipv4() {
+            AddressFamily::Ipv4
+        } else {
+            AddressFamily::Ipv6
+        };
+        match get_best_default_route(family)? {
+            Some(route) => probe::probe(route, timeout).await,
+            None => Ok(GatewayReachability::Unknown),
+        }
+    }
 }
 
 pub enum RouteManagerCommand {
     AddRoutes(HashSet<RequiredRoute>, oneshot::Sender<Result<()>>),
     GetMtuForRoute(IpAddr, oneshot::Sender<Result<u16>>),
     ClearRoutes,
+    GetRoutes(oneshot::Sender<RouteDump>),
     RegisterDefaultRouteChangeCallback(Callback, oneshot::Sender<CallbackHandle>),
     Shutdown,
 }
@@ -198,9 +230,11 @@ impl RouteManager {
         mut manage_rx: UnboundedReceiver<RouteManagerCommand>,
         mut internal: RouteManagerInternal,
     ) {
+        let mut desired_routes = DesiredRoutes::default();
         while let Some(command) = manage_rx.next().await {
             match command {
                 RouteManagerCommand::AddRoutes(routes, tx) => {
+                    desired_routes.extend(&routes);
                     let routes: Vec<_> = routes
                         .into_iter()
                         .map(|route| Route {
@@ -228,7 +262,14 @@ impl RouteManager {
                     };
                     let _ = tx.send(res);
                 }
+                RouteManagerCommand::GetRoutes(tx) => {
+                    let _ = tx.send(RouteDump {
+                        desired: desired_routes.dump(|_| None),
+                        kernel: None,
+                    });
+                }
                 RouteManagerCommand::ClearRoutes => {
+                    desired_routes.clear();
                     if let Err(e) = internal.delete_applied_routes() {
                         log::error!("{}", e.display_chain_with_msg("Could not clear routes"));
                     }
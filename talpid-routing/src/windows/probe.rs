@@ -0,0 +1,40 @@
+//! Probes the gateway of the default route. Windows sends the ARP requests or NDP neighbor
+//! solicitations on behalf of the caller.
+
+use super::{Error, InterfaceAndGateway, Result};
+use crate::GatewayReachability;
+use std::time::Duration;
+use talpid_types::win32_err;
+use talpid_windows_net::inet_sockaddr_from_socketaddr;
+use windows_sys::Win32::{
+    Foundation::ERROR_BAD_NET_NAME,
+    NetworkManagement::IpHelper::{ResolveIpNetEntry2, MIB_IPNET_ROW2},
+};
+
+/// Returns whether the gateway of `route` responds within `timeout`.
+pub async fn probe(route: InterfaceAndGateway, timeout: Duration) -> Result<GatewayReachability> {
+    // `ResolveIpNetEntry2` blocks until the neighbor responds or the system gives up, which may
+    // take longer than `timeout`
+    let resolve = tokio::task::spawn_blocking(move || resolve_neighbor(&route));
+    match tokio::time::timeout(timeout, resolve).await {
+        Ok(result) => result.map_err(|_| Error::ManagerChannelDown)?,
+        Err(_) => Ok(GatewayReachability::Unreachable),
+    }
+}
+
+fn resolve_neighbor(route: &InterfaceAndGateway) -> Result<GatewayReachability> {
+    // SAFETY: MIB_IPNET_ROW2 is a plain C struct, and all zeroes is a valid value
+    let mut row: MIB_IPNET_ROW2 = unsafe { std::mem::zeroed() };
+    row.InterfaceLuid = route.iface;
+    row.Address = inet_sockaddr_from_socketaddr(route.gateway);
+
+    // SAFETY: `row` is a valid, initialized row, and a null source address is allowed
+    // This flushes any existing entry for the gateway before it is resolved again
+    match win32_err!(unsafe { ResolveIpNetEntry2(&mut row, std::ptr::null()) }) {
+        Ok(()) => Ok(GatewayReachability::Reachable),
+        Err(error) if error.raw_os_error() == Some(ERROR_BAD_NET_NAME as i32) => {
+            Ok(GatewayReachability::Unreachable)
+        }
+        Err(error) => Err(Error::ResolveNeighbor(error)),
+    }
+}
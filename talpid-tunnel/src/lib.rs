@@ -9,9 +9,15 @@ use std::{
 pub mod network_interface;
 
 pub mod tun_provider;
-use futures::{channel::oneshot, future::BoxFuture};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::BoxFuture,
+};
 use talpid_routing::RouteManagerHandle;
-use talpid_types::net::AllowedTunnelTraffic;
+use talpid_types::{
+    net::{AllowedTunnelTraffic, Connectivity},
+    tunnel::TrafficStats,
+};
 use tun_provider::TunProvider;
 
 /// Arguments for creating a tunnel.
@@ -27,12 +33,36 @@ where
     pub on_event: L,
     /// Receiver oneshot channel for closing the tunnel.
     pub tunnel_close_rx: oneshot::Receiver<()>,
+    /// Receives a message whenever the network outside the tunnel changes.
+    pub network_changed_rx: mpsc::UnboundedReceiver<()>,
     /// Mutex to tunnel provider.
     pub tun_provider: Arc<Mutex<TunProvider>>,
     /// Connection retry attempts.
     pub retry_attempt: u32,
     /// Route manager handle.
     pub route_manager: RouteManagerHandle,
+    /// Connectivity outside the tunnel when the tunnel was started.
+    pub connectivity: Connectivity,
+    /// Where the tunnel publishes its traffic counters.
+    pub traffic_counters: TrafficCounters,
+}
+
+/// The traffic counters of the current tunnel, as last read by the tunnel monitor. Clones share
+/// the same counters.
+#[derive(Clone, Debug, Default)]
+pub struct TrafficCounters(Arc<Mutex<Option<TrafficStats>>>);
+
+impl TrafficCounters {
+    /// Returns the counters of the current tunnel, or `None` if there is no tunnel or its
+    /// counters are not monitored.
+    pub fn get(&self) -> Option<TrafficStats> {
+        *self.0.lock().unwrap()
+    }
+
+    /// Sets the counters of the current tunnel.
+    pub fn set(&self, stats: Option<TrafficStats>) {
+        *self.0.lock().unwrap() = stats;
+    }
 }
 
 /// Information about a VPN tunnel.
@@ -1,13 +1,40 @@
-use std::io;
-use talpid_windows_net::{get_ip_interface_entry, set_ip_interface_entry, AddressFamily};
+use once_cell::sync::Lazy;
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+use talpid_types::ErrorExt;
+use talpid_windows_net::{
+    get_ip_interface_entry, notify_ip_interface_change, set_ip_interface_entry, AddressFamily,
+    IpNotifierHandle,
+};
 use windows_sys::Win32::{
-    Foundation::ERROR_NOT_FOUND, NetworkManagement::Ndis::NET_LUID_LH,
+    Foundation::ERROR_NOT_FOUND,
+    NetworkManagement::{
+        IpHelper::{MibParameterNotification, MIB_IPINTERFACE_ROW},
+        Ndis::NET_LUID_LH,
+    },
     Networking::WinSock::RouterDiscoveryDisabled,
 };
 
+/// Metric that is assigned to the tunnel interfaces, so that routes through the tunnel are
+/// preferred over routes through the physical interfaces.
+const DEFAULT_TUNNEL_METRIC: u32 = 1;
+
+/// Overrides the metric that is assigned to the tunnel interfaces.
+static TUNNEL_METRIC: Lazy<u32> = Lazy::new(|| {
+    std::env::var("TALPID_TUNNEL_METRIC")
+        .ok()
+        .and_then(|metric| metric.parse().ok())
+        .unwrap_or(DEFAULT_TUNNEL_METRIC)
+});
+
 /// Sets MTU, metric, and disables unnecessary features for the IP interfaces
-/// on the specified network interface (identified by `luid`).
-pub fn initialize_interfaces(luid: NET_LUID_LH, mtu: Option<u32>) -> io::Result<()> {
+/// on the specified network interface (identified by `luid`). The metric is kept
+/// until the returned [`MetricPin`] is dropped.
+pub fn initialize_interfaces(luid: NET_LUID_LH, mtu: Option<u32>) -> io::Result<MetricPin> {
+    let mut pin = MetricPin::new(luid);
+
     for family in &[AddressFamily::Ipv4, AddressFamily::Ipv6] {
         let mut row = match get_ip_interface_entry(*family, &luid) {
             Ok(row) => row,
@@ -27,11 +54,234 @@ pub fn initialize_interfaces(luid: NET_LUID_LH, mtu: Option<u32>) -> io::Result<
         row.OtherStatefulConfigurationSupported = 0;
 
         // Ensure lowest interface metric
-        row.Metric = 1;
-        row.UseAutomaticMetric = 0;
+        pin.record(*family, InterfaceMetric::from_row(&row));
+        InterfaceMetric::pinned().apply(&mut row);
 
         set_ip_interface_entry(&mut row)?;
     }
 
-    Ok(())
+    if let Err(error) = pin.watch() {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to monitor tunnel interface metric")
+        );
+    }
+
+    Ok(pin)
+}
+
+/// Metric settings of an IP interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceMetric {
+    /// Whether Windows assigns the metric based on the link speed.
+    pub automatic: bool,
+    /// The metric. It is ignored if `automatic` is set.
+    pub metric: u32,
+}
+
+impl InterfaceMetric {
+    /// Returns the metric settings of an IP interface.
+    pub fn from_row(row: &MIB_IPINTERFACE_ROW) -> Self {
+        InterfaceMetric {
+            automatic: row.UseAutomaticMetric != 0,
+            metric: row.Metric,
+        }
+    }
+
+    fn pinned() -> Self {
+        InterfaceMetric {
+            automatic: false,
+            metric: *TUNNEL_METRIC,
+        }
+    }
+
+    fn apply(&self, row: &mut MIB_IPINTERFACE_ROW) {
+        row.UseAutomaticMetric = u8::from(self.automatic);
+        row.Metric = self.metric;
+    }
+}
+
+/// Metrics that the IP interfaces had before their metrics were pinned.
+#[derive(Debug, Default)]
+struct MetricRecord {
+    ipv4: Option<InterfaceMetric>,
+    ipv6: Option<InterfaceMetric>,
+}
+
+impl MetricRecord {
+    /// Records the metric of an IP interface before it is pinned. Only the first metric is kept,
+    /// so that re-asserting the pinned metric does not replace the original one.
+    fn record(&mut self, family: AddressFamily, metric: InterfaceMetric) {
+        let previous = match family {
+            AddressFamily::Ipv4 => &mut self.ipv4,
+            AddressFamily::Ipv6 => &mut self.ipv6,
+        };
+        previous.get_or_insert(metric);
+    }
+
+    /// Removes and returns the metrics that should be restored.
+    fn take(&mut self) -> Vec<(AddressFamily, InterfaceMetric)> {
+        [
+            (AddressFamily::Ipv4, self.ipv4.take()),
+            (AddressFamily::Ipv6, self.ipv6.take()),
+        ]
+        .into_iter()
+        .filter_map(|(family, metric)| Some((family, metric?)))
+        .collect()
+    }
+}
+
+/// Keeps the metric of the IP interfaces on a network interface pinned. If something else changes
+/// the metric, it is changed back. The previous metrics are restored when this is dropped.
+pub struct MetricPin {
+    luid: NET_LUID_LH,
+    record: Arc<Mutex<MetricRecord>>,
+    notifier: Option<Box<IpNotifierHandle<'static>>>,
+}
+
+impl MetricPin {
+    fn new(luid: NET_LUID_LH) -> Self {
+        MetricPin {
+            luid,
+            record: Arc::new(Mutex::new(MetricRecord::default())),
+            notifier: None,
+        }
+    }
+
+    fn record(&mut self, family: AddressFamily, metric: InterfaceMetric) {
+        self.record.lock().unwrap().record(family, metric);
+    }
+
+    /// Re-asserts the pinned metric whenever the IP interfaces change.
+    fn watch(&mut self) -> io::Result<()> {
+        let luid = self.luid;
+        let notifier = notify_ip_interface_change(
+            move |row, notification_type| {
+                if notification_type != MibParameterNotification
+                    || unsafe { row.InterfaceLuid.Value != luid.Value }
+                {
+                    return;
+                }
+                let Ok(family) = AddressFamily::try_from_af_family(row.Family) else {
+                    return;
+                };
+                if let Err(error) = reassert_metric(family, luid) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to restore tunnel interface metric")
+                    );
+                }
+            },
+            None,
+        )?;
+        self.notifier = Some(notifier);
+        Ok(())
+    }
+}
+
+impl Drop for MetricPin {
+    fn drop(&mut self) {
+        // Stop re-asserting the metric before restoring it
+        self.notifier.take();
+
+        let previous = self.record.lock().unwrap().take();
+        for (family, metric) in previous {
+            if let Err(error) = set_metric(family, self.luid, metric) {
+                // The interface may already have been removed
+                if error.raw_os_error() != Some(ERROR_NOT_FOUND as i32) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to restore interface metric")
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn reassert_metric(family: AddressFamily, luid: NET_LUID_LH) -> io::Result<()> {
+    let row = get_ip_interface_entry(family, &luid)?;
+    let pinned = InterfaceMetric::pinned();
+    if InterfaceMetric::from_row(&row) == pinned {
+        return Ok(());
+    }
+    log::warn!(
+        "The tunnel interface metric was changed. Setting it to {}",
+        pinned.metric
+    );
+    set_metric(family, luid, pinned)
+}
+
+fn set_metric(family: AddressFamily, luid: NET_LUID_LH, metric: InterfaceMetric) -> io::Result<()> {
+    let mut row = get_ip_interface_entry(family, &luid)?;
+    // `SetIpInterfaceEntry` rejects the prefix length returned for IPv4 interfaces
+    row.SitePrefixLength = 0;
+    metric.apply(&mut row);
+    set_ip_interface_entry(&mut row)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const AUTOMATIC: InterfaceMetric = InterfaceMetric {
+        automatic: true,
+        metric: 35,
+    };
+    const MANUAL: InterfaceMetric = InterfaceMetric {
+        automatic: false,
+        metric: 5,
+    };
+
+    fn families(restored: &[(AddressFamily, InterfaceMetric)]) -> Vec<(u16, InterfaceMetric)> {
+        restored
+            .iter()
+            .map(|(family, metric)| (family.to_af_family(), *metric))
+            .collect()
+    }
+
+    #[test]
+    fn test_record_keeps_original_metric() {
+        let mut record = MetricRecord::default();
+        record.record(AddressFamily::Ipv4, AUTOMATIC);
+        // The pinned metric is seen when it is re-asserted
+        record.record(AddressFamily::Ipv4, InterfaceMetric::pinned());
+
+        assert_eq!(
+            families(&record.take()),
+            vec![(AddressFamily::Ipv4.to_af_family(), AUTOMATIC)]
+        );
+    }
+
+    #[test]
+    fn test_restore_each_family_once() {
+        let mut record = MetricRecord::default();
+        record.record(AddressFamily::Ipv6, MANUAL);
+        record.record(AddressFamily::Ipv4, AUTOMATIC);
+
+        assert_eq!(
+            families(&record.take()),
+            vec![
+                (AddressFamily::Ipv4.to_af_family(), AUTOMATIC),
+                (AddressFamily::Ipv6.to_af_family(), MANUAL),
+            ]
+        );
+        assert!(record.take().is_empty());
+    }
+
+    #[test]
+    fn test_apply_metric() {
+        let mut row: MIB_IPINTERFACE_ROW = unsafe { std::mem::zeroed() };
+        AUTOMATIC.apply(&mut row);
+        assert_eq!(InterfaceMetric::from_row(&row), AUTOMATIC);
+
+        InterfaceMetric::pinned().apply(&mut row);
+        assert_eq!(
+            InterfaceMetric::from_row(&row),
+            InterfaceMetric {
+                automatic: false,
+                metric: DEFAULT_TUNNEL_METRIC,
+            }
+        );
+    }
 }
@@ -1,7 +1,23 @@
-use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt, path::PathBuf};
+use std::{
+    ffi::OsStr,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
 
 pub const SPLIT_TUNNEL_CGROUP_NAME: &str = "mullvad-exclusions";
 
+/// Prefix of the cgroups that `mullvad-exclude` creates in the split tunnel cgroup, one for each
+/// program that it launches. Descendants of the program stay in the cgroup, since the kernel
+/// places forked processes in the cgroup of their parent.
+pub const INVOCATION_CGROUP_PREFIX: &str = "exclude-";
+
+/// Returns the name of the cgroup that `mullvad-exclude` uses for the program launched by the
+/// process `pid`.
+pub fn invocation_cgroup_name(pid: u32) -> String {
+    format!("{INVOCATION_CGROUP_PREFIX}{pid}")
+}
+
 /// Find the path of the cgroup v1 net_cls controller mount if it exists
 pub fn find_net_cls_mount() -> std::io::Result<Option<PathBuf>> {
     let mounts = fs::read("/proc/mounts")?;
@@ -14,6 +30,74 @@ fn find_net_cls_mount_inner(mounts: &[u8]) -> Option<PathBuf> {
         .find_map(parse_mount_line)
 }
 
+/// Find the path of a cgroup v2 mount if it exists. The net_cls controller is only available in
+/// cgroup v1, so split tunneling is unsupported if this is the only cgroup hierarchy.
+pub fn find_cgroup2_mount() -> std::io::Result<Option<PathBuf>> {
+    let mounts = fs::read("/proc/mounts")?;
+    Ok(find_cgroup2_mount_inner(&mounts))
+}
+
+fn find_cgroup2_mount_inner(mounts: &[u8]) -> Option<PathBuf> {
+    mounts.split(|byte| *byte == b'\n').find_map(|line| {
+        let mut parts = line.split(|byte| *byte == b' ');
+        let _device_type = parts.next()?;
+        let mount_path = parts.next()?;
+        let filesystem_type = parts.next()?;
+        (filesystem_type == b"cgroup2").then(|| PathBuf::from(OsStr::from_bytes(mount_path)))
+    })
+}
+
+/// Returns the net_cls cgroup of the process `pid`, relative to the root of the hierarchy, or
+/// `None` if the net_cls controller is not in use.
+pub fn net_cls_cgroup_of(pid: u32) -> io::Result<Option<PathBuf>> {
+    let cgroups = fs::read(format!("/proc/{pid}/cgroup"))?;
+    Ok(parse_net_cls_cgroup(&cgroups))
+}
+
+fn parse_net_cls_cgroup(cgroups: &[u8]) -> Option<PathBuf> {
+    // Each line is of the form `hierarchy-ID:controller-list:cgroup-path`, such as
+    // `7:net_cls,net_prio:/mullvad-exclusions`
+    cgroups.split(|byte| *byte == b'\n').find_map(|line| {
+        let mut parts = line.splitn(3, |byte| *byte == b':');
+        let _hierarchy_id = parts.next()?;
+        let controllers = parts.next()?;
+        let path = parts.next()?;
+        controllers
+            .split(|byte| *byte == b',')
+            .any(|controller| controller == b"net_cls")
+            .then(|| PathBuf::from(OsStr::from_bytes(path)))
+    })
+}
+
+/// Returns the cgroups that `mullvad-exclude` has created in the split tunnel cgroup at
+/// `exclusions_dir`.
+pub fn invocation_cgroups(exclusions_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut cgroups = vec![];
+    for entry in fs::read_dir(exclusions_dir)? {
+        let entry = entry?;
+        let is_invocation_cgroup = entry
+            .file_name()
+            .as_bytes()
+            .starts_with(INVOCATION_CGROUP_PREFIX.as_bytes());
+        if is_invocation_cgroup && entry.file_type()?.is_dir() {
+            cgroups.push(entry.path());
+        }
+    }
+    Ok(cgroups)
+}
+
+/// Removes the cgroups created by `mullvad-exclude` that no longer contain any processes.
+pub fn remove_empty_invocation_cgroups(exclusions_dir: &Path) -> io::Result<()> {
+    for cgroup in invocation_cgroups(exclusions_dir)? {
+        let procs = fs::read(cgroup.join("cgroup.procs"))?;
+        if procs.iter().all(u8::is_ascii_whitespace) {
+            // A cgroup directory is removed with `rmdir` even though it contains control files
+            fs::remove_dir(&cgroup)?;
+        }
+    }
+    Ok(())
+}
+
 fn parse_mount_line(line: &[u8]) -> Option<PathBuf> {
     // Each line contains multiple values separated by space.
     // `cgroup /sys/fs/cgroup/net_cls,net_prio cgroup
@@ -60,6 +144,33 @@ cgroup /sys/fs/cgroup/net_cls,net_prio cgroup rw,nosuid,nodev,noexec,relatime,ne
         )
     }
 
+    #[test]
+    fn test_find_cgroup2_path() {
+        let input =
+            br#"cgroup2 /sys/fs/cgroup cgroup2 rw,nosuid,nodev,noexec,relatime,nsdelegate 0 0
+"#;
+        assert_eq!(
+            find_cgroup2_mount_inner(input),
+            Some(PathBuf::from("/sys/fs/cgroup"))
+        );
+        assert_eq!(find_net_cls_mount_inner(input), None);
+    }
+
+    #[test]
+    fn test_parse_net_cls_cgroup() {
+        let input = br#"12:memory:/user.slice
+7:net_cls,net_prio:/mullvad-exclusions/exclude-1234
+0::/user.slice/user-1000.slice/session-2.scope
+"#;
+        assert_eq!(
+            parse_net_cls_cgroup(input),
+            Some(PathBuf::from("/mullvad-exclusions/exclude-1234"))
+        );
+
+        // Only cgroup v2 is in use
+        assert_eq!(parse_net_cls_cgroup(b"0::/user.slice\n"), None);
+    }
+
     #[test]
     fn test_fail_to_find_net_cls_path() {
         let input =
@@ -72,10 +72,27 @@ impl TunnelParameters {
 
     fn get_obfuscator_endpoint(obfuscator: &ObfuscatorConfig) -> Endpoint {
         match obfuscator {
-            ObfuscatorConfig::Udp2Tcp { endpoint } => Endpoint {
-                address: *endpoint,
+            ObfuscatorConfig::Udp2Tcp {
+                endpoint,
+                upstream_proxy,
+                ..
+            }
+            | ObfuscatorConfig::Tls {
+                endpoint,
+                upstream_proxy,
+                ..
+            } => Endpoint {
+                // The relay is only reached through the proxy, if there is one
+                address: upstream_proxy
+                    .as_ref()
+                    .map(|proxy| proxy.endpoint)
+                    .unwrap_or(*endpoint),
                 protocol: TransportProtocol::Tcp,
             },
+            ObfuscatorConfig::Quic { endpoint, .. } => Endpoint {
+                address: *endpoint,
+                protocol: TransportProtocol::Udp,
+            },
         }
     }
 
@@ -198,12 +215,18 @@ impl fmt::Display for TunnelEndpoint {
 pub enum ObfuscationType {
     #[serde(rename = "udp2tcp")]
     Udp2Tcp,
+    #[serde(rename = "quic")]
+    Quic,
+    #[serde(rename = "tls")]
+    Tls,
 }
 
 impl fmt::Display for ObfuscationType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
             ObfuscationType::Udp2Tcp => "Udp2Tcp".fmt(f),
+            ObfuscationType::Quic => "QUIC".fmt(f),
+            ObfuscationType::Tls => "TLS".fmt(f),
         }
     }
 }
@@ -215,23 +238,56 @@ impl fmt::Display for ObfuscationType {
 pub struct ObfuscationEndpoint {
     pub endpoint: Endpoint,
     pub obfuscation_type: ObfuscationType,
+    /// SOCKS5 proxy that `endpoint` is connected to through, if any.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub upstream_proxy: Option<Endpoint>,
 }
 
 impl From<&ObfuscatorConfig> for ObfuscationEndpoint {
     fn from(config: &ObfuscatorConfig) -> ObfuscationEndpoint {
-        let (endpoint, obfuscation_type) = match config {
-            ObfuscatorConfig::Udp2Tcp { endpoint } => (
+        let (endpoint, obfuscation_type, upstream_proxy) = match config {
+            ObfuscatorConfig::Udp2Tcp {
+                endpoint,
+                upstream_proxy,
+                ..
+            } => (
                 Endpoint {
                     address: *endpoint,
                     protocol: TransportProtocol::Tcp,
                 },
                 ObfuscationType::Udp2Tcp,
+                upstream_proxy.as_ref(),
+            ),
+            ObfuscatorConfig::Quic { endpoint, .. } => (
+                Endpoint {
+                    address: *endpoint,
+                    protocol: TransportProtocol::Udp,
+                },
+                ObfuscationType::Quic,
+                None,
+            ),
+            ObfuscatorConfig::Tls {
+                endpoint,
+                upstream_proxy,
+                ..
+            } => (
+                Endpoint {
+                    address: *endpoint,
+                    protocol: TransportProtocol::Tcp,
+                },
+                ObfuscationType::Tls,
+                upstream_proxy.as_ref(),
             ),
         };
 
         ObfuscationEndpoint {
             endpoint,
             obfuscation_type,
+            upstream_proxy: upstream_proxy.map(|proxy| Endpoint {
+                address: proxy.endpoint,
+                protocol: TransportProtocol::Tcp,
+            }),
         }
     }
 }
@@ -328,6 +384,111 @@ impl fmt::Display for AllowedTunnelTraffic {
     }
 }
 
+/// Where the DNS servers used while connected come from.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsSource {
+    /// The resolver in the tunnel, at the gateway address.
+    #[default]
+    Default,
+    /// Custom DNS servers set by the user.
+    Custom,
+    /// Mullvad's content blocking resolvers.
+    ContentBlockers,
+}
+
+impl fmt::Display for DnsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match *self {
+            DnsSource::Default => "default".fmt(f),
+            DnsSource::Custom => "custom".fmt(f),
+            DnsSource::ContentBlockers => "content blockers".fmt(f),
+        }
+    }
+}
+
+/// The DNS configuration that is in use while connected.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct EffectiveDns {
+    /// The DNS servers that the system has been configured to use.
+    pub servers: Vec<IpAddr>,
+    pub source: DnsSource,
+    /// Whether another program has overwritten the DNS config since it was applied. The config
+    /// is restored when this is detected.
+    #[serde(default)]
+    pub tampered: bool,
+}
+
+/// A DNS-over-TLS server. Queries are sent to `address`, and the server's certificate must be
+/// valid for `hostname`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct TlsDnsServer {
+    pub address: IpAddr,
+    pub hostname: String,
+}
+
+impl fmt::Display for TlsDnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{}#{}", self.address, self.hostname)
+    }
+}
+
+/// The network that traffic outside the tunnel is routed through, as given by the non-tunnel
+/// default routes.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct NetworkChange {
+    /// Interface of the preferred default route. The IPv4 route is preferred over the IPv6 route.
+    pub interface: Option<String>,
+    /// Gateway of the preferred default route.
+    pub gateway: Option<IpAddr>,
+    /// IP versions that have a default route. Empty if the host is offline.
+    pub families: Vec<IpVersion>,
+}
+
+impl fmt::Display for NetworkChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        if self.families.is_empty() {
+            return write!(f, "no default route");
+        }
+        match (&self.interface, &self.gateway) {
+            (Some(interface), Some(gateway)) => write!(f, "{interface} via {gateway}")?,
+            (Some(interface), None) => write!(f, "{interface}")?,
+            (None, Some(gateway)) => write!(f, "via {gateway}")?,
+            (None, None) => write!(f, "unknown interface")?,
+        }
+        let families = self
+            .families
+            .iter()
+            .map(|family| family.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, " ({families})")
+    }
+}
+
+/// IP versions that have a default route outside the tunnel, as seen by the offline monitor.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Connectivity {
+    pub ipv4: bool,
+    pub ipv6: bool,
+}
+
+impl Connectivity {
+    /// Connectivity that is presumed when it cannot be determined.
+    pub const PRESUME_ONLINE: Connectivity = Connectivity {
+        ipv4: true,
+        ipv6: true,
+    };
+
+    /// Returns whether there is a default route for the IP version of `address`.
+    pub fn has_route_to(&self, address: IpAddr) -> bool {
+        match address {
+            IpAddr::V4(_) => self.ipv4,
+            IpAddr::V6(_) => self.ipv6,
+        }
+    }
+}
+
 /// IP protocol version.
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -1,7 +1,35 @@
+use super::proxy::SocksProxy;
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
 pub enum ObfuscatorConfig {
-    Udp2Tcp { endpoint: SocketAddr },
+    Udp2Tcp {
+        endpoint: SocketAddr,
+        /// Send a keepalive if nothing has been sent for this long.
+        #[serde(default)]
+        keepalive_interval: Option<Duration>,
+        /// Restart the obfuscator if nothing is received for this long while handshakes are
+        /// unanswered.
+        #[serde(default)]
+        idle_timeout: Option<Duration>,
+        /// Connect to `endpoint` through this SOCKS5 server instead of directly.
+        #[serde(default)]
+        upstream_proxy: Option<SocksProxy>,
+    },
+    Quic {
+        endpoint: SocketAddr,
+        /// Server name to send in the TLS handshake.
+        server_name: String,
+        /// Application protocols to offer in the TLS handshake.
+        alpn: Vec<String>,
+    },
+    Tls {
+        endpoint: SocketAddr,
+        /// Server name to send in the TLS handshake.
+        server_name: String,
+        /// Connect to `endpoint` through this SOCKS5 server instead of directly.
+        #[serde(default)]
+        upstream_proxy: Option<SocksProxy>,
+    },
 }
@@ -3,7 +3,7 @@ use crate::net::{
     Endpoint, GenericTunnelOptions, TransportProtocol,
 };
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{fmt, net::SocketAddr};
 
 /// Information needed by `OpenVpnMonitor` to establish a tunnel connection.
 /// See [`crate::net::TunnelParameters`].
@@ -106,12 +106,23 @@ impl RemoteProxySettings {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+/// Credentials for a remote SOCKS5 proxy. The password is left out of the `Debug` output so that
+/// it never ends up in the logs.
+#[derive(Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct ProxyAuth {
     pub username: String,
     pub password: String,
 }
 
+impl fmt::Debug for ProxyAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyAuth")
+            .field("username", &self.username)
+            .field("password", &"[REDACTED]")
+            .finish()
+    }
+}
+
 /// Options for a bundled Shadowsocks proxy.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct ShadowsocksProxySettings {
@@ -198,3 +209,22 @@ pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
     };
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proxy_auth_debug_is_redacted() {
+        let proxy = ProxySettings::Remote(RemoteProxySettings {
+            address: "1.2.3.4:1080".parse().unwrap(),
+            auth: Some(ProxyAuth {
+                username: "user".to_owned(),
+                password: "hunter2".to_owned(),
+            }),
+        });
+        let debug = format!("{proxy:?}");
+        assert!(debug.contains("user"));
+        assert!(!debug.contains("hunter2"));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::net::Endpoint;
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{fmt, net::SocketAddr};
 
 /// Types of bridges that can be used to proxy a connection to a tunnel
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -27,3 +27,39 @@ pub struct ProxyEndpoint {
     pub endpoint: Endpoint,
     pub proxy_type: ProxyType,
 }
+
+/// SOCKS5 server that an obfuscator connects to the relay through, instead of connecting to it
+/// directly.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SocksProxy {
+    pub endpoint: SocketAddr,
+    /// Credentials to authenticate with, if the server requires them.
+    #[serde(default)]
+    pub authentication: Option<SocksAuth>,
+}
+
+/// Credentials for username/password authentication against a [`SocksProxy`]. The password is
+/// left out of the `Debug` output.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SocksAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for SocksAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SocksAuth")
+            .field("username", &self.username)
+            .field("password", &"[REDACTED]")
+            .finish()
+    }
+}
+
+impl fmt::Display for SocksProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.authentication {
+            Some(auth) => write!(f, "{}@{}", auth.username, self.endpoint),
+            None => write!(f, "{}", self.endpoint),
+        }
+    }
+}
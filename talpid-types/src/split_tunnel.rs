@@ -1,7 +1,114 @@
-use std::path::PathBuf;
+use crate::net::TransportProtocol;
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf, str::FromStr};
+
+/// Decides whether the applications that are selected for split tunneling are the only ones
+/// that are sent outside the tunnel, or the only ones that are sent through it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitTunnelMode {
+    /// Traffic of the selected applications is excluded from the tunnel.
+    #[default]
+    Exclude,
+    /// Only traffic of the selected applications is sent through the tunnel. Other traffic is
+    /// sent outside the tunnel while connected, and is blocked in every other secured state.
+    Include,
+}
+
+impl fmt::Display for SplitTunnelMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitTunnelMode::Exclude => f.write_str("exclude"),
+            SplitTunnelMode::Include => f.write_str("include"),
+        }
+    }
+}
+
+/// An inclusive range of ports. A single port is represented by a range where `start` and `end`
+/// are equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    /// Returns a range, or `None` if `start` is greater than `end`.
+    pub fn new(start: u16, end: u16) -> Option<Self> {
+        (start <= end).then_some(PortRange { start, end })
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = PortRangeParseError;
+
+    /// Parses a single port, such as `445`, or a range of ports, such as `8000-8100`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_port = |port: &str| port.trim().parse::<u16>().map_err(|_| PortRangeParseError);
+        match s.split_once('-') {
+            Some((start, end)) => {
+                PortRange::new(parse_port(start)?, parse_port(end)?).ok_or(PortRangeParseError)
+            }
+            None => {
+                let port = parse_port(s)?;
+                Ok(PortRange {
+                    start: port,
+                    end: port,
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Returned when `PortRange::from_str` fails to parse a port or a range of ports.
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+#[error(display = "Not a valid port or port range")]
+pub struct PortRangeParseError;
+
+/// Traffic to a destination that is excluded from the tunnel regardless of which process sends
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ExcludedDestination {
+    pub network: IpNetwork,
+    /// Destination ports to exclude. All ports are excluded if this is `None`.
+    pub ports: Option<PortRange>,
+    pub protocol: TransportProtocol,
+}
+
+impl ExcludedDestination {
+    /// Returns whether traffic using `protocol` to `port` on `network` is excluded.
+    pub fn contains_port(&self, protocol: TransportProtocol, port: u16) -> bool {
+        self.protocol == protocol && self.ports.map(|ports| ports.contains(port)).unwrap_or(true)
+    }
+}
+
+impl fmt::Display for ExcludedDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} over {}", self.network, self.protocol)?;
+        if let Some(ports) = self.ports {
+            write!(f, ", port {ports}")?;
+        }
+        Ok(())
+    }
+}
 
 /// A process that is being excluded from the tunnel.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ExcludedProcess {
     /// Process identifier.
     pub pid: u32,
@@ -10,4 +117,191 @@ pub struct ExcludedProcess {
     /// If true, then the process is split because its parent was split,
     /// not due to its path being in the config.
     pub inherited: bool,
+    /// What caused the process to be excluded.
+    pub reason: ExclusionReason,
+}
+
+/// Describes why a process is excluded from the tunnel.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExclusionReason {
+    /// The image of the process, or of one of its ancestors, matches an excluded application.
+    Path,
+    /// The image of the process is signed by an excluded publisher.
+    Publisher(String),
+    /// The process is in the split tunnel cgroup.
+    CGroup,
+    /// The process runs as an excluded user.
+    User(u32),
+    /// The process runs as an excluded group.
+    Group(u32),
+}
+
+impl fmt::Display for ExclusionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExclusionReason::Path => f.write_str("path"),
+            ExclusionReason::Publisher(name) => write!(f, "publisher {name}"),
+            ExclusionReason::CGroup => f.write_str("cgroup"),
+            ExclusionReason::User(uid) => write!(f, "uid {uid}"),
+            ExclusionReason::Group(gid) => write!(f, "gid {gid}"),
+        }
+    }
+}
+
+/// An application that is configured to be excluded from the tunnel.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExcludedApp {
+    /// The executable at a path.
+    Path(PathBuf),
+    /// Executables signed by a publisher.
+    Publisher(String),
+}
+
+impl fmt::Display for ExcludedApp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExcludedApp::Path(path) => write!(f, "{}", path.display()),
+            ExcludedApp::Publisher(publisher) => write!(f, "publisher: {publisher}"),
+        }
+    }
+}
+
+/// Whether the split tunnel backend has applied the exclusion of an application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppExclusionState {
+    /// The application is excluded from the tunnel.
+    Applied,
+    /// The application is excluded once it can be, such as when it is first launched or when the
+    /// volume that it is on is mounted.
+    Pending,
+    /// The application could not be excluded.
+    Failed(String),
+}
+
+impl fmt::Display for AppExclusionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppExclusionState::Applied => f.write_str("applied"),
+            AppExclusionState::Pending => f.write_str("pending"),
+            AppExclusionState::Failed(error) => write!(f, "failed: {error}"),
+        }
+    }
+}
+
+/// The exclusion state of a configured application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppExclusionStatus {
+    pub app: ExcludedApp,
+    pub state: AppExclusionState,
+}
+
+/// Kernel parameters that enable forwarding of IPv4 and IPv6 traffic between interfaces.
+#[cfg(target_os = "linux")]
+const IP_FORWARDING_SYSCTLS: [&str; 2] = ["net.ipv4.ip_forward", "net.ipv6.conf.all.forwarding"];
+
+/// Returns the IP forwarding kernel parameters that are disabled. Traffic that arrives on an
+/// excluded interface, such as a container bridge, can only leave the host using the IP versions
+/// that are forwarded. IPv6 forwarding is not reported if IPv6 is disabled in the kernel.
+#[cfg(target_os = "linux")]
+pub fn disabled_ip_forwarding() -> std::io::Result<Vec<&'static str>> {
+    disabled_ip_forwarding_in(std::path::Path::new("/proc/sys"))
+}
+
+#[cfg(target_os = "linux")]
+fn disabled_ip_forwarding_in(root: &std::path::Path) -> std::io::Result<Vec<&'static str>> {
+    let mut disabled = vec![];
+    for sysctl in IP_FORWARDING_SYSCTLS {
+        match std::fs::read_to_string(root.join(sysctl.replace('.', "/"))) {
+            Ok(value) if value.trim() == "0" => disabled.push(sysctl),
+            Ok(_) => (),
+            Err(error)
+                if error.kind() == std::io::ErrorKind::NotFound
+                    && sysctl.starts_with("net.ipv6") => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(disabled)
+}
+
+/// Returns the range of local ports that the kernel assigns to sockets that are not bound to a
+/// specific port, such as that of the WireGuard tunnel.
+#[cfg(target_os = "linux")]
+pub fn ephemeral_port_range() -> std::io::Result<PortRange> {
+    let value = std::fs::read_to_string("/proc/sys/net/ipv4/ip_local_port_range")?;
+    parse_ephemeral_port_range(&value).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid local port range: {value:?}"),
+        )
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn parse_ephemeral_port_range(value: &str) -> Option<PortRange> {
+    let mut ports = value.split_whitespace().map(|port| port.parse::<u16>());
+    match (ports.next(), ports.next(), ports.next()) {
+        (Some(Ok(start)), Some(Ok(end)), None) => PortRange::new(start, end),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!("445".parse(), Ok(PortRange::new(445, 445).unwrap()));
+        assert_eq!("8000-8100".parse(), Ok(PortRange::new(8000, 8100).unwrap()));
+        assert_eq!("8100-8000".parse::<PortRange>(), Err(PortRangeParseError));
+        assert_eq!("70000".parse::<PortRange>(), Err(PortRangeParseError));
+        assert_eq!("".parse::<PortRange>(), Err(PortRangeParseError));
+
+        assert_eq!(PortRange::new(445, 445).unwrap().to_string(), "445");
+        assert_eq!(PortRange::new(8000, 8100).unwrap().to_string(), "8000-8100");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_disabled_ip_forwarding() {
+        let root = std::env::temp_dir().join(format!("ip-forwarding-{}", std::process::id()));
+        let write = |sysctl: &str, value: &str| {
+            let path = root.join(sysctl.replace('.', "/"));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, value).unwrap();
+        };
+
+        // IPv6 forwarding is not reported if IPv6 is disabled
+        write("net.ipv4.ip_forward", "1\n");
+        assert_eq!(
+            disabled_ip_forwarding_in(&root).unwrap(),
+            Vec::<&str>::new()
+        );
+
+        write("net.ipv6.conf.all.forwarding", "0\n");
+        assert_eq!(
+            disabled_ip_forwarding_in(&root).unwrap(),
+            vec!["net.ipv6.conf.all.forwarding"]
+        );
+
+        write("net.ipv4.ip_forward", "0\n");
+        write("net.ipv6.conf.all.forwarding", "1\n");
+        assert_eq!(
+            disabled_ip_forwarding_in(&root).unwrap(),
+            vec!["net.ipv4.ip_forward"]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_parse_ephemeral_port_range() {
+        assert_eq!(
+            parse_ephemeral_port_range("32768\t60999\n"),
+            PortRange::new(32768, 60999)
+        );
+        assert_eq!(parse_ephemeral_port_range("32768"), None);
+        assert_eq!(parse_ephemeral_port_range("60999\t32768\n"), None);
+    }
 }
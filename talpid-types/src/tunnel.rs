@@ -1,10 +1,10 @@
-use crate::net::TunnelEndpoint;
+use crate::net::{EffectiveDns, TunnelEndpoint};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
 #[cfg(target_os = "android")]
 use std::net::IpAddr;
+use std::{fmt, time::Duration};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
@@ -13,15 +13,105 @@ pub enum TunnelStateTransition {
     /// No connection is established and network is unsecured.
     Disconnected,
     /// Network is secured but tunnel is still connecting.
-    Connecting(TunnelEndpoint),
-    /// Tunnel is connected.
-    Connected(TunnelEndpoint),
+    Connecting(TunnelEndpoint, ConnectingPhase, ConnectionAttempt),
+    /// Tunnel is connected, and DNS is configured as described by [`EffectiveDns`].
+    Connected(TunnelEndpoint, EffectiveDns),
     /// Disconnecting tunnel.
     Disconnecting(ActionAfterDisconnect),
     /// Tunnel is disconnected but usually secured by blocking all connections.
     Error(ErrorState),
 }
 
+/// Progress of a connection attempt while the tunnel is connecting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectingPhase {
+    /// The tunnel is being established.
+    #[default]
+    EstablishingTunnel,
+    /// The attempt is held until the gateway of the default route responds, so that it is not
+    /// wasted on a network that is not usable yet.
+    WaitingForNetwork,
+    /// The network has come back too many times recently, and the attempt is held until it has
+    /// been stable for a while.
+    WaitingForStableNetwork,
+    /// Previous attempts failed, and the attempt is held until the retry backoff has elapsed.
+    WaitingToRetry,
+}
+
+/// Details of the connection attempt that is in progress while the tunnel is connecting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionAttempt {
+    /// Number of the attempt since the user last connected, starting at 1. Zero if unknown.
+    pub attempt: u32,
+    /// How long the attempt is given to establish connectivity before it is abandoned and the
+    /// next attempt is made. Not set if the tunnel does not impose such a limit.
+    pub retry_timeout: Option<Duration>,
+}
+
+/// How long consecutive connection attempts are spaced apart. The delay before the first retry
+/// is `initial`, and every failed attempt multiplies it by `multiplier`, up to `max`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryBackoff {
+    /// Minimum time between the start of the first attempt and the start of the second one.
+    pub initial: Duration,
+    /// Factor that the delay grows by after every failed attempt.
+    pub multiplier: f64,
+    /// Upper bound of the delay.
+    pub max: Duration,
+    /// How long the tunnel must have been connected for the backoff to start over when the
+    /// connection is lost.
+    pub reset_after_connected_for: Duration,
+}
+
+impl Default for RetryBackoff {
+    /// Attempts are started at most once per second, which is what the app has always done.
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            multiplier: 1.0,
+            max: Duration::from_secs(1),
+            reset_after_connected_for: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryBackoff {
+    /// Returns the delay before the attempt following `failures` consecutive failed attempts,
+    /// counting from 1.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = i32::try_from(failures.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = self.initial.as_secs_f64() * self.multiplier.powi(exponent);
+        if delay.is_finite() && delay < self.max.as_secs_f64() {
+            Duration::from_secs_f64(delay)
+        } else {
+            self.max
+        }
+    }
+}
+
+/// Number of bytes that have been sent and received through a tunnel since it was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+/// Failures that the mock tunnel of the tunnel state machine should simulate, and how long its
+/// connection attempts take. Only used when testing without real tunnels.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MockTunnelScript {
+    /// Number of upcoming connection attempts that fail and are retried.
+    pub fail_connects: u32,
+    /// How long connection attempts take to succeed or fail. The current delay is kept if `None`.
+    pub connect_delay: Option<Duration>,
+    /// Whether the connected tunnel should go down, as if the relay stopped responding.
+    pub drop_tunnel: bool,
+    /// Whether the device should appear to be offline. Unchanged if `None`.
+    pub offline: Option<bool>,
+}
+
 /// Action that will be taken after disconnection is complete.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -98,6 +188,8 @@ pub enum ErrorStateCause {
     TunnelParameterError(ParameterGenerationError),
     /// This device is offline, no tunnels can be established.
     IsOffline,
+    /// The account has run out of time.
+    AccountExpired,
     /// The Android VPN permission was denied.
     #[cfg(target_os = "android")]
     VpnPermissionDenied,
@@ -111,6 +203,149 @@ impl ErrorStateCause {
     pub fn prevents_filtering_resolver(&self) -> bool {
         matches!(self, Self::SetDnsError)
     }
+
+    /// Returns the stable code that identifies this cause.
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            Self::AuthFailed(reason) => {
+                // The reason starts with an ID in brackets, e.g. "[EXPIRED_ACCOUNT] ..."
+                let id = reason
+                    .as_deref()
+                    .and_then(|reason| reason.trim_start().strip_prefix('['))
+                    .and_then(|reason| reason.split_once(']'))
+                    .map(|(id, _)| id);
+                match id {
+                    Some("INVALID_ACCOUNT") => ErrorCode::AuthFailedInvalidAccount,
+                    Some("EXPIRED_ACCOUNT") => ErrorCode::AuthFailedExpiredAccount,
+                    Some("TOO_MANY_CONNECTIONS") => ErrorCode::AuthFailedTooManyConnections,
+                    _ => ErrorCode::AuthFailed,
+                }
+            }
+            Self::Ipv6Unavailable => ErrorCode::Ipv6Unavailable,
+            Self::SetFirewallPolicyError(error) => match error {
+                FirewallPolicyError::Generic => ErrorCode::SetFirewallPolicyFailed,
+                #[cfg(windows)]
+                FirewallPolicyError::Locked(_) => ErrorCode::FirewallLocked,
+            },
+            Self::SetDnsError => ErrorCode::SetDnsFailed,
+            #[cfg(target_os = "android")]
+            Self::InvalidDnsServers(_) => ErrorCode::InvalidDnsServers,
+            Self::StartTunnelError => ErrorCode::StartTunnelFailed,
+            Self::TunnelParameterError(error) => match error {
+                ParameterGenerationError::NoMatchingRelay => ErrorCode::NoMatchingRelay,
+                ParameterGenerationError::NoMatchingBridgeRelay => ErrorCode::NoMatchingBridgeRelay,
+                ParameterGenerationError::NoWireguardKey => ErrorCode::NoWireguardKey,
+                ParameterGenerationError::CustomTunnelHostResultionError => {
+                    ErrorCode::CustomTunnelHostResolutionFailed
+                }
+            },
+            Self::IsOffline => ErrorCode::Offline,
+            Self::AccountExpired => ErrorCode::AccountExpired,
+            #[cfg(target_os = "android")]
+            Self::VpnPermissionDenied => ErrorCode::VpnPermissionDenied,
+            #[cfg(target_os = "windows")]
+            Self::SplitTunnelError => ErrorCode::SplitTunnelFailed,
+        }
+    }
+}
+
+/// Stable identifier of the reason for an [`ErrorState`], for programs that need to tell errors
+/// apart without parsing the description, which may change between releases.
+///
+/// The codes are the same on all platforms. Released codes are never changed or reused, and new
+/// codes are only ever appended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum ErrorCode {
+    /// Authentication failed for an unknown reason.
+    AuthFailed = 1,
+    AuthFailedInvalidAccount = 2,
+    AuthFailedExpiredAccount = 3,
+    AuthFailedTooManyConnections = 4,
+    Ipv6Unavailable = 5,
+    SetFirewallPolicyFailed = 6,
+    /// Another application prevented the firewall policy from being set.
+    FirewallLocked = 7,
+    SetDnsFailed = 8,
+    InvalidDnsServers = 9,
+    StartTunnelFailed = 10,
+    NoMatchingRelay = 11,
+    NoMatchingBridgeRelay = 12,
+    NoWireguardKey = 13,
+    CustomTunnelHostResolutionFailed = 14,
+    Offline = 15,
+    VpnPermissionDenied = 16,
+    SplitTunnelFailed = 17,
+    /// The account ran out of time while the tunnel was up or being set up.
+    AccountExpired = 18,
+}
+
+impl ErrorCode {
+    /// All codes, in order.
+    pub const ALL: [ErrorCode; 18] = [
+        Self::AuthFailed,
+        Self::AuthFailedInvalidAccount,
+        Self::AuthFailedExpiredAccount,
+        Self::AuthFailedTooManyConnections,
+        Self::Ipv6Unavailable,
+        Self::SetFirewallPolicyFailed,
+        Self::FirewallLocked,
+        Self::SetDnsFailed,
+        Self::InvalidDnsServers,
+        Self::StartTunnelFailed,
+        Self::NoMatchingRelay,
+        Self::NoMatchingBridgeRelay,
+        Self::NoWireguardKey,
+        Self::CustomTunnelHostResolutionFailed,
+        Self::Offline,
+        Self::VpnPermissionDenied,
+        Self::SplitTunnelFailed,
+        Self::AccountExpired,
+    ];
+
+    /// Returns the numeric code.
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the code with the given number, if it is known to this version.
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|error_code| error_code.code() == code)
+    }
+
+    /// Returns the string identifier of the code.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::AuthFailed => "auth_failed",
+            Self::AuthFailedInvalidAccount => "auth_failed.invalid_account",
+            Self::AuthFailedExpiredAccount => "auth_failed.expired_account",
+            Self::AuthFailedTooManyConnections => "auth_failed.too_many_connections",
+            Self::Ipv6Unavailable => "ipv6_unavailable",
+            Self::SetFirewallPolicyFailed => "set_firewall_policy_failed",
+            Self::FirewallLocked => "set_firewall_policy_failed.locked",
+            Self::SetDnsFailed => "set_dns_failed",
+            Self::InvalidDnsServers => "invalid_dns_servers",
+            Self::StartTunnelFailed => "start_tunnel_failed",
+            Self::NoMatchingRelay => "tunnel_parameter_error.no_matching_relay",
+            Self::NoMatchingBridgeRelay => "tunnel_parameter_error.no_matching_bridge_relay",
+            Self::NoWireguardKey => "tunnel_parameter_error.no_wireguard_key",
+            Self::CustomTunnelHostResolutionFailed => {
+                "tunnel_parameter_error.custom_tunnel_host_resolution_failed"
+            }
+            Self::Offline => "offline",
+            Self::VpnPermissionDenied => "vpn_permission_denied",
+            Self::SplitTunnelFailed => "split_tunnel_failed",
+            Self::AccountExpired => "account_expired",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.as_str(), self.code())
+    }
 }
 
 /// Errors that can occur when generating tunnel parameters.
@@ -199,6 +434,7 @@ impl fmt::Display for ErrorStateCause {
                 return write!(f, "Failure to generate tunnel parameters: {err}");
             }
             IsOffline => "This device is offline, no tunnels can be established",
+            AccountExpired => "The account is out of time",
             #[cfg(target_os = "android")]
             VpnPermissionDenied => "The Android VPN permission was denied when creating the tunnel",
             #[cfg(target_os = "windows")]
@@ -208,3 +444,106 @@ impl fmt::Display for ErrorStateCause {
         write!(f, "{description}")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Released codes must never change. New codes are appended to this list.
+    const RELEASED_CODES: [(ErrorCode, u32, &str); 18] = [
+        (ErrorCode::AuthFailed, 1, "auth_failed"),
+        (
+            ErrorCode::AuthFailedInvalidAccount,
+            2,
+            "auth_failed.invalid_account",
+        ),
+        (
+            ErrorCode::AuthFailedExpiredAccount,
+            3,
+            "auth_failed.expired_account",
+        ),
+        (
+            ErrorCode::AuthFailedTooManyConnections,
+            4,
+            "auth_failed.too_many_connections",
+        ),
+        (ErrorCode::Ipv6Unavailable, 5, "ipv6_unavailable"),
+        (
+            ErrorCode::SetFirewallPolicyFailed,
+            6,
+            "set_firewall_policy_failed",
+        ),
+        (
+            ErrorCode::FirewallLocked,
+            7,
+            "set_firewall_policy_failed.locked",
+        ),
+        (ErrorCode::SetDnsFailed, 8, "set_dns_failed"),
+        (ErrorCode::InvalidDnsServers, 9, "invalid_dns_servers"),
+        (ErrorCode::StartTunnelFailed, 10, "start_tunnel_failed"),
+        (
+            ErrorCode::NoMatchingRelay,
+            11,
+            "tunnel_parameter_error.no_matching_relay",
+        ),
+        (
+            ErrorCode::NoMatchingBridgeRelay,
+            12,
+            "tunnel_parameter_error.no_matching_bridge_relay",
+        ),
+        (
+            ErrorCode::NoWireguardKey,
+            13,
+            "tunnel_parameter_error.no_wireguard_key",
+        ),
+        (
+            ErrorCode::CustomTunnelHostResolutionFailed,
+            14,
+            "tunnel_parameter_error.custom_tunnel_host_resolution_failed",
+        ),
+        (ErrorCode::Offline, 15, "offline"),
+        (ErrorCode::VpnPermissionDenied, 16, "vpn_permission_denied"),
+        (ErrorCode::SplitTunnelFailed, 17, "split_tunnel_failed"),
+        (ErrorCode::AccountExpired, 18, "account_expired"),
+    ];
+
+    #[test]
+    fn test_codes_are_stable() {
+        assert_eq!(ErrorCode::ALL.len(), RELEASED_CODES.len());
+        for (error_code, (released, code, id)) in ErrorCode::ALL.into_iter().zip(RELEASED_CODES) {
+            assert_eq!(error_code, released);
+            assert_eq!(error_code.code(), code);
+            assert_eq!(error_code.as_str(), id);
+            assert_eq!(ErrorCode::from_code(code), Some(error_code));
+        }
+        assert_eq!(ErrorCode::from_code(0), None);
+    }
+
+    #[test]
+    fn test_cause_codes() {
+        let causes = [
+            (ErrorStateCause::AuthFailed(None), ErrorCode::AuthFailed),
+            (
+                ErrorStateCause::AuthFailed(Some("[EXPIRED_ACCOUNT] No time left".to_owned())),
+                ErrorCode::AuthFailedExpiredAccount,
+            ),
+            (
+                ErrorStateCause::AuthFailed(Some("[SOMETHING_NEW]".to_owned())),
+                ErrorCode::AuthFailed,
+            ),
+            (
+                ErrorStateCause::SetFirewallPolicyError(FirewallPolicyError::Generic),
+                ErrorCode::SetFirewallPolicyFailed,
+            ),
+            (
+                ErrorStateCause::TunnelParameterError(ParameterGenerationError::NoWireguardKey),
+                ErrorCode::NoWireguardKey,
+            ),
+            (ErrorStateCause::IsOffline, ErrorCode::Offline),
+            (ErrorStateCause::AccountExpired, ErrorCode::AccountExpired),
+        ];
+        for (cause, error_code) in causes {
+            assert_eq!(cause.error_code(), error_code, "{cause}");
+        }
+    }
+}
@@ -8,6 +8,8 @@ use std::{
     sync::{mpsc, Mutex, Weak},
     time::{Duration, Instant},
 };
+use talpid_tunnel::TrafficCounters;
+use talpid_types::tunnel::TrafficStats;
 
 use super::{Tunnel, TunnelError};
 
@@ -34,6 +36,32 @@ const ESTABLISH_TIMEOUT_MULTIPLIER: u32 = 2;
 const MAX_ESTABLISH_TIMEOUT: Duration = PING_TIMEOUT;
 /// Number of seconds to wait between sending ICMP packets
 const SECONDS_PER_PING: Duration = Duration::from_secs(3);
+/// Timeout for receiving traffic after the tunnel has been moved to a new network. Once this
+/// timeout is reached, it is assumed that the connection is lost.
+const ROAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns how long attempt `retry_attempt` (counting from 0) is given to establish connectivity
+/// before it fails.
+pub fn establish_timeout(retry_attempt: u32) -> Duration {
+    retry_timeout(
+        retry_attempt,
+        ESTABLISH_TIMEOUT,
+        ESTABLISH_TIMEOUT_MULTIPLIER,
+        MAX_ESTABLISH_TIMEOUT,
+    )
+}
+
+fn retry_timeout(
+    retry_attempt: u32,
+    timeout_initial: Duration,
+    timeout_multiplier: u32,
+    max_timeout: Duration,
+) -> Duration {
+    cmp::min(
+        max_timeout,
+        timeout_initial.saturating_mul(timeout_multiplier.saturating_pow(retry_attempt)),
+    )
+}
 
 /// Connectivity monitor errors
 #[derive(err_derive::Error, Debug)]
@@ -69,13 +97,23 @@ pub enum Error {
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
 /// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
+///
+/// A message on `roam_receiver` indicates that the tunnel has been moved to a new network. The
+/// monitor then starts pinging immediately, and the connection is considered broken unless
+/// traffic is received within `ROAM_TIMEOUT`.
+///
+/// The traffic counters that are read are published to `traffic_counters` until the monitor is
+/// dropped.
 pub struct ConnectivityMonitor {
     tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    traffic_counters: TrafficCounters,
     conn_state: ConnState,
     initial_ping_timestamp: Option<Instant>,
     num_pings_sent: u32,
     pinger: Box<dyn Pinger>,
     close_receiver: mpsc::Receiver<()>,
+    roam_receiver: mpsc::Receiver<()>,
+    roaming: bool,
 }
 
 impl ConnectivityMonitor {
@@ -83,7 +121,9 @@ impl ConnectivityMonitor {
         addr: Ipv4Addr,
         #[cfg(any(target_os = "macos", target_os = "linux"))] interface: String,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+        traffic_counters: TrafficCounters,
         close_receiver: mpsc::Receiver<()>,
+        roam_receiver: mpsc::Receiver<()>,
     ) -> Result<Self, Error> {
         let pinger = new_pinger(
             addr,
@@ -96,11 +136,14 @@ impl ConnectivityMonitor {
 
         Ok(Self {
             tunnel_handle,
+            traffic_counters,
             conn_state: ConnState::new(now, Default::default()),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
             close_receiver,
+            roam_receiver,
+            roaming: false,
         })
     }
 
@@ -128,9 +171,11 @@ impl ConnectivityMonitor {
             return Ok(true);
         }
 
-        let check_timeout = cmp::min(
+        let check_timeout = retry_timeout(
+            retry_attempt,
+            timeout_initial,
+            timeout_multiplier,
             max_timeout,
-            timeout_initial.saturating_mul(timeout_multiplier.saturating_pow(retry_attempt)),
         );
 
         let start = Instant::now();
@@ -162,6 +207,9 @@ impl ConnectivityMonitor {
         while !self.should_shut_down(iter_delay) {
             let mut current_iteration = Instant::now();
             let time_slept = current_iteration - last_iteration;
+            if self.roam_receiver.try_recv().is_ok() {
+                self.start_roaming(current_iteration)?;
+            }
             if time_slept < (iter_delay * 2) {
                 if !self.check_connectivity(Instant::now())? {
                     return Ok(());
@@ -197,18 +245,41 @@ impl ConnectivityMonitor {
             None => Ok(false),
             Some(new_stats) => {
                 let new_stats = new_stats?;
+                self.traffic_counters.set(Some(TrafficStats {
+                    rx_bytes: new_stats.values().map(|stats| stats.rx_bytes).sum(),
+                    tx_bytes: new_stats.values().map(|stats| stats.tx_bytes).sum(),
+                }));
 
                 if self.conn_state.update(now, new_stats) {
+                    if self.roaming {
+                        log::debug!("Tunnel is working on the new network");
+                        self.roaming = false;
+                    }
                     self.reset_pinger();
                     return Ok(true);
                 }
 
                 self.maybe_send_ping(now)?;
+                let timeout = if self.roaming {
+                    cmp::min(timeout, ROAM_TIMEOUT)
+                } else {
+                    timeout
+                };
                 Ok(!self.ping_timed_out(timeout) && self.conn_state.connected())
             }
         }
     }
 
+    /// Starts verifying that the tunnel works after it has been moved to a new network.
+    fn start_roaming(&mut self, now: Instant) -> Result<(), Error> {
+        self.reset_pinger();
+        self.roaming = true;
+        self.pinger.send_icmp().map_err(Error::PingError)?;
+        self.initial_ping_timestamp = Some(now);
+        self.num_pings_sent = 1;
+        Ok(())
+    }
+
     /// If None is returned, then the underlying tunnel has already been closed and all subsequent
     /// calls will also return None.
     fn get_stats(&self) -> Option<Result<StatsMap, Error>> {
@@ -224,7 +295,7 @@ impl ConnectivityMonitor {
         // Only send out a ping if we haven't received a byte in a while or no traffic has flowed
         // in the last 2 minutes, but if a ping already has been sent out, only send one out every
         // 3 seconds.
-        if (self.conn_state.rx_timed_out() || self.conn_state.traffic_timed_out())
+        if (self.roaming || self.conn_state.rx_timed_out() || self.conn_state.traffic_timed_out())
             && self
                 .initial_ping_timestamp
                 .map(|initial_ping_timestamp| {
@@ -255,6 +326,12 @@ impl ConnectivityMonitor {
     }
 }
 
+impl Drop for ConnectivityMonitor {
+    fn drop(&mut self) {
+        self.traffic_counters.set(None);
+    }
+}
+
 enum ConnState {
     Connecting {
         start: Instant,
@@ -558,6 +635,30 @@ mod test {
             }
         }
 
+        /// Returns a tunnel that always sends traffic, but only receives traffic while
+        /// `receiving` is set.
+        fn receiving_while(receiving: Arc<AtomicBool>) -> Self {
+            let mut map = stats::StatsMap::new();
+            map.insert(
+                Self::PEER,
+                stats::Stats {
+                    tx_bytes: 0,
+                    rx_bytes: 0,
+                },
+            );
+            let tunnel_stats = Mutex::new(map);
+            Self::new(move || {
+                let mut tunnel_stats = tunnel_stats.lock().unwrap();
+                for traffic in tunnel_stats.values_mut() {
+                    traffic.tx_bytes += 1;
+                    if receiving.load(Ordering::SeqCst) {
+                        traffic.rx_bytes += 1;
+                    }
+                }
+                Ok(tunnel_stats.clone())
+            })
+        }
+
         fn never_incrementing() -> Self {
             Self {
                 on_get_stats: Box::new(|| {
@@ -615,13 +716,17 @@ mod test {
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
         close_receiver: mpsc::Receiver<()>,
     ) -> ConnectivityMonitor {
+        let (_roam_sender, roam_receiver) = mpsc::channel();
         ConnectivityMonitor {
             conn_state: ConnState::new(now, Default::default()),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
             close_receiver,
+            roam_receiver,
+            roaming: false,
             tunnel_handle,
+            traffic_counters: TrafficCounters::default(),
         }
     }
 
@@ -693,6 +798,115 @@ mod test {
         assert!(monitor.check_connectivity(now).unwrap())
     }
 
+    #[test]
+    /// Verify that the tunnel is considered working after moving to a new network once traffic is
+    /// received.
+    fn test_roaming_succeeds() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::always_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pings_sent = Arc::new(AtomicBool::new(false));
+        let moved_pings_sent = pings_sent.clone();
+        let pinger = MockPinger {
+            on_send_ping: Some(Box::new(move || {
+                moved_pings_sent.store(true, Ordering::SeqCst);
+            })),
+        };
+        let now = Instant::now();
+        let start = now.checked_sub(Duration::from_secs(1)).unwrap();
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+        monitor.conn_state = connected_state(start);
+
+        monitor.start_roaming(start).unwrap();
+        assert!(pings_sent.load(Ordering::SeqCst));
+
+        assert!(monitor.check_connectivity(now).unwrap());
+        assert!(!monitor.roaming);
+        assert!(monitor.initial_ping_timestamp.is_none());
+    }
+
+    #[test]
+    /// Verify that `check_connectivity()` returns `false` if no traffic is received within
+    /// `ROAM_TIMEOUT` of moving to a new network, even though `PING_TIMEOUT` has not been reached.
+    fn test_roaming_times_out() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger::default();
+        let now = Instant::now();
+        let start = now
+            .checked_sub(ROAM_TIMEOUT + Duration::from_secs(1))
+            .unwrap();
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+        monitor.conn_state = connected_state(start);
+        assert!(monitor.check_connectivity(now).unwrap());
+
+        monitor.start_roaming(start).unwrap();
+        assert!(!monitor.check_connectivity(now).unwrap());
+    }
+
+    /// Starts a monitor that has established a connection and returns channels for moving it to a
+    /// new network, stopping it, and receiving the result of `run()`.
+    fn run_roaming_monitor(
+        tunnel: MockTunnel,
+    ) -> (
+        mpsc::Sender<()>,
+        mpsc::Sender<()>,
+        mpsc::Receiver<Result<(), Error>>,
+    ) {
+        let (roam_tx, roam_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let (result_tx, result_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (_tunnel_anchor, tunnel) = tunnel.into_locked();
+            let start = Instant::now().checked_sub(Duration::from_secs(1)).unwrap();
+            let mut monitor = mock_monitor(start, Box::<MockPinger>::default(), tunnel, stop_rx);
+            monitor.roam_receiver = roam_rx;
+            assert!(monitor.establish_connectivity(0).unwrap());
+            result_tx.send(monitor.run()).unwrap();
+        });
+        (roam_tx, stop_tx, result_rx)
+    }
+
+    #[test]
+    /// Verify that the tunnel is kept after moving to a new network if traffic is received again
+    /// within `ROAM_TIMEOUT`.
+    fn test_wait_loop_roaming() {
+        let receiving = Arc::new(AtomicBool::new(true));
+        let (roam_tx, stop_tx, result_rx) =
+            run_roaming_monitor(MockTunnel::receiving_while(receiving.clone()));
+        std::thread::sleep(Duration::from_secs(1));
+
+        receiving.store(false, Ordering::SeqCst);
+        roam_tx.send(()).unwrap();
+        std::thread::sleep(ROAM_TIMEOUT / 2);
+        receiving.store(true, Ordering::SeqCst);
+
+        assert!(result_rx.recv_timeout(ROAM_TIMEOUT).is_err());
+        stop_tx.send(()).unwrap();
+        assert!(result_rx
+            .recv_timeout(REGULAR_LOOP_SLEEP * 2)
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    /// Verify that the monitor stops, which closes the tunnel, if no traffic is received within
+    /// `ROAM_TIMEOUT` of moving to a new network.
+    fn test_wait_loop_roaming_timeout() {
+        let receiving = Arc::new(AtomicBool::new(true));
+        let (roam_tx, _stop_tx, result_rx) =
+            run_roaming_monitor(MockTunnel::receiving_while(receiving.clone()));
+        std::thread::sleep(Duration::from_secs(1));
+
+        receiving.store(false, Ordering::SeqCst);
+        roam_tx.send(()).unwrap();
+
+        // Without roaming, this would take `BYTES_RX_TIMEOUT` and `PING_TIMEOUT` combined.
+        assert!(result_rx
+            .recv_timeout(ROAM_TIMEOUT + REGULAR_LOOP_SLEEP * 2)
+            .unwrap()
+            .is_ok());
+    }
+
     #[test]
     /// Verify that the connectivity monitor doesn't fail if the tunnel constantly sends traffic,
     /// and it shuts down properly.
@@ -4,8 +4,7 @@
 #![deny(rust_2018_idioms)]
 
 use self::config::Config;
-use futures::future::{abortable, AbortHandle as FutureAbortHandle, BoxFuture, Future};
-#[cfg(windows)]
+use futures::future::{BoxFuture, Future};
 use futures::{channel::mpsc, StreamExt};
 #[cfg(target_os = "linux")]
 use once_cell::sync::Lazy;
@@ -19,11 +18,14 @@ use std::{
     net::IpAddr,
     path::Path,
     pin::Pin,
-    sync::{mpsc as sync_mpsc, Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc as sync_mpsc, Arc, Mutex,
+    },
     time::Duration,
 };
 use talpid_routing as routing;
-use talpid_routing::{self, RequiredRoute};
+use talpid_routing::{self, RequiredRoute, RoutePurpose};
 #[cfg(not(windows))]
 use talpid_tunnel::tun_provider;
 use talpid_tunnel::{tun_provider::TunProvider, TunnelArgs, TunnelEvent, TunnelMetadata};
@@ -32,14 +34,17 @@ use ipnetwork::IpNetwork;
 use talpid_types::{
     net::{
         obfuscation::ObfuscatorConfig,
+        proxy,
         wireguard::{PresharedKey, PrivateKey, PublicKey},
-        AllowedTunnelTraffic, Endpoint, TransportProtocol,
+        AllowedTunnelTraffic, Connectivity, Endpoint, TransportProtocol,
     },
     BoxedError, ErrorExt,
 };
 use tokio::sync::Mutex as AsyncMutex;
 use tunnel_obfuscation::{
-    create_obfuscator, Error as ObfuscationError, Settings as ObfuscationSettings, Udp2TcpSettings,
+    create_obfuscator, Error as ObfuscationError, ObfuscatorTask, QuicSettings,
+    Settings as ObfuscationSettings, SocksProxy, StatsCounters, TlsSettings, Udp2TcpSettings,
+    QUIC_MAX_TUNNEL_MTU,
 };
 
 /// WireGuard config data-types
@@ -58,6 +63,8 @@ mod wireguard_nt;
 #[cfg(wireguard_go)]
 use self::wireguard_go::WgGoTunnel;
 
+pub use connectivity_check::establish_timeout;
+
 type Result<T> = std::result::Result<T, Error>;
 type EventCallback = Box<dyn (Fn(TunnelEvent) -> BoxFuture<'static, ()>) + Send + Sync + 'static>;
 
@@ -124,20 +131,34 @@ const INITIAL_PSK_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(8);
 const MAX_PSK_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(48);
 const PSK_EXCHANGE_TIMEOUT_MULTIPLIER: u32 = 2;
 
-/// Simple wrapper that automatically cancels the future which runs an obfuscator.
+/// Number of times that the obfuscator of a tunnel has been replaced by this process.
+static OBFUSCATOR_RECONNECTS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns how many times the connection of an obfuscator has been re-established, by this or an
+/// earlier tunnel, during the lifetime of this process. Obfuscators that are created for new
+/// tunnels are not counted.
+pub fn obfuscator_reconnects() -> u64 {
+    OBFUSCATOR_RECONNECTS.load(Ordering::Relaxed)
+}
+
+/// Simple wrapper that automatically cancels the task which runs an obfuscator. It should be shut
+/// down instead of dropped, so that its sockets are closed before the next obfuscator is created.
 struct ObfuscatorHandle {
-    abort_handle: FutureAbortHandle,
+    task: Option<ObfuscatorTask>,
+    stats: Arc<StatsCounters>,
     #[cfg(target_os = "android")]
     remote_socket_fd: std::os::unix::io::RawFd,
 }
 
 impl ObfuscatorHandle {
     pub fn new(
-        abort_handle: FutureAbortHandle,
+        task: ObfuscatorTask,
+        stats: Arc<StatsCounters>,
         #[cfg(target_os = "android")] remote_socket_fd: std::os::unix::io::RawFd,
     ) -> Self {
         Self {
-            abort_handle,
+            task: Some(task),
+            stats,
             #[cfg(target_os = "android")]
             remote_socket_fd,
         }
@@ -148,14 +169,24 @@ impl ObfuscatorHandle {
         self.remote_socket_fd
     }
 
-    pub fn abort(&self) {
-        self.abort_handle.abort();
+    /// Stops the obfuscator and waits until it is gone.
+    pub async fn shutdown(mut self) {
+        if let Some(task) = self.task.take() {
+            task.shutdown().await;
+        }
     }
 }
 
 impl Drop for ObfuscatorHandle {
     fn drop(&mut self) {
-        self.abort_handle.abort();
+        let stats = self.stats.snapshot();
+        log::info!(
+            "Obfuscator stopped after relaying {} bytes to and {} bytes from the relay. \
+             Obfuscator reconnects: {}",
+            stats.tx_bytes,
+            stats.rx_bytes,
+            obfuscator_reconnects()
+        );
     }
 }
 
@@ -171,55 +202,107 @@ async fn maybe_create_obfuscator(
     config: &mut Config,
     close_msg_sender: sync_mpsc::Sender<CloseMsg>,
 ) -> Result<Option<ObfuscatorHandle>> {
+    let Some(ref obfuscator_config) = config.obfuscator_config else {
+        return Ok(None);
+    };
+    let to_socks_proxy = |upstream: &proxy::SocksProxy| SocksProxy {
+        peer: upstream.endpoint,
+        auth: upstream.authentication.clone(),
+    };
+    let settings = match obfuscator_config {
+        ObfuscatorConfig::Udp2Tcp {
+            endpoint,
+            keepalive_interval,
+            idle_timeout,
+            upstream_proxy,
+        } => {
+            log::trace!("Connecting to Udp2Tcp endpoint {:?}", *endpoint);
+            if let Some(proxy) = upstream_proxy {
+                log::trace!("Connecting through upstream proxy {}", proxy.endpoint);
+            }
+            ObfuscationSettings::Udp2Tcp(Udp2TcpSettings {
+                peer: *endpoint,
+                #[cfg(target_os = "linux")]
+                fwmark: config.fwmark,
+                keepalive_interval: *keepalive_interval,
+                idle_timeout: *idle_timeout,
+                upstream_proxy: upstream_proxy.as_ref().map(to_socks_proxy),
+            })
+        }
+        ObfuscatorConfig::Quic {
+            endpoint,
+            server_name,
+            alpn,
+        } => {
+            log::trace!("Connecting to QUIC endpoint {:?}", *endpoint);
+            ObfuscationSettings::Quic(QuicSettings {
+                peer: *endpoint,
+                server_name: server_name.clone(),
+                alpn: alpn.clone(),
+                #[cfg(target_os = "linux")]
+                fwmark: config.fwmark,
+            })
+        }
+        ObfuscatorConfig::Tls {
+            endpoint,
+            server_name,
+            upstream_proxy,
+        } => {
+            log::trace!("Connecting to TLS endpoint {:?}", *endpoint);
+            if let Some(proxy) = upstream_proxy {
+                log::trace!("Connecting through upstream proxy {}", proxy.endpoint);
+            }
+            ObfuscationSettings::Tls(TlsSettings {
+                peer: *endpoint,
+                server_name: server_name.clone(),
+                #[cfg(target_os = "linux")]
+                fwmark: config.fwmark,
+                upstream_proxy: upstream_proxy.as_ref().map(to_socks_proxy),
+            })
+        }
+    };
+    if matches!(settings, ObfuscationSettings::Quic(_)) && config.mtu > QUIC_MAX_TUNNEL_MTU {
+        // Larger packets would not fit in a QUIC datagram and be dropped
+        log::debug!("Lowering the tunnel MTU to {QUIC_MAX_TUNNEL_MTU} for QUIC obfuscation");
+        config.mtu = QUIC_MAX_TUNNEL_MTU;
+    }
+
+    // A task that was not shut down may still hold the local sockets of its obfuscator
+    tunnel_obfuscation::reap_orphaned_tasks().await;
+    let obfuscator = create_obfuscator(&settings)
+        .await
+        .map_err(Error::CreateObfuscatorError)?;
+    let endpoint = obfuscator.endpoint();
+    let stats = obfuscator.stats();
+
     // There are one or two peers.
     // The first one is always the entry relay.
     let first_peer = config.peers.get_mut(0).expect("missing peer");
+    log::trace!("Patching first WireGuard peer to become {:?}", endpoint);
+    first_peer.endpoint = endpoint;
 
-    if let Some(ref obfuscator_config) = config.obfuscator_config {
-        match obfuscator_config {
-            ObfuscatorConfig::Udp2Tcp { endpoint } => {
-                log::trace!("Connecting to Udp2Tcp endpoint {:?}", *endpoint);
-                let settings = Udp2TcpSettings {
-                    peer: *endpoint,
-                    #[cfg(target_os = "linux")]
-                    fwmark: config.fwmark,
-                };
-                let obfuscator = create_obfuscator(&ObfuscationSettings::Udp2Tcp(settings))
-                    .await
-                    .map_err(Error::CreateObfuscatorError)?;
-                let endpoint = obfuscator.endpoint();
-
-                log::trace!("Patching first WireGuard peer to become {:?}", endpoint);
-                first_peer.endpoint = endpoint;
-
-                #[cfg(target_os = "android")]
-                let remote_socket_fd = obfuscator.remote_socket_fd();
+    #[cfg(target_os = "android")]
+    let remote_socket_fd = obfuscator.remote_socket_fd();
 
-                let (runner, abort_handle) = abortable(async move {
-                    match obfuscator.run().await {
-                        Ok(_) => {
-                            let _ = close_msg_sender.send(CloseMsg::ObfuscatorExpired);
-                        }
-                        Err(error) => {
-                            log::error!(
-                                "{}",
-                                error.display_chain_with_msg("Obfuscation controller failed")
-                            );
-                            let _ = close_msg_sender
-                                .send(CloseMsg::ObfuscatorFailed(Error::ObfuscatorError(error)));
-                        }
-                    }
-                });
-                tokio::spawn(runner);
-                return Ok(Some(ObfuscatorHandle::new(
-                    abort_handle,
-                    #[cfg(target_os = "android")]
-                    remote_socket_fd,
-                )));
-            }
+    let task = tunnel_obfuscation::spawn(obfuscator, move |result| match result {
+        Ok(_) => {
+            let _ = close_msg_sender.send(CloseMsg::ObfuscatorExpired);
         }
-    }
-    Ok(None)
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Obfuscation controller failed")
+            );
+            let _ =
+                close_msg_sender.send(CloseMsg::ObfuscatorFailed(Error::ObfuscatorError(error)));
+        }
+    });
+    Ok(Some(ObfuscatorHandle::new(
+        task,
+        stats,
+        #[cfg(target_os = "android")]
+        remote_socket_fd,
+    )))
 }
 
 impl WireguardMonitor {
@@ -295,6 +378,7 @@ impl WireguardMonitor {
 
         let event_callback = Box::new(on_event.clone());
         let (pinger_tx, pinger_rx) = sync_mpsc::channel();
+        let (roam_tx, roam_rx) = sync_mpsc::channel();
         let monitor = WireguardMonitor {
             runtime: args.runtime.clone(),
             tunnel: Arc::new(Mutex::new(Some(tunnel))),
@@ -310,7 +394,9 @@ impl WireguardMonitor {
             #[cfg(any(target_os = "macos", target_os = "linux"))]
             iface_name.clone(),
             Arc::downgrade(&monitor.tunnel),
+            args.traffic_counters.clone(),
             pinger_rx,
+            roam_rx,
         )
         .map_err(Error::ConnectivityMonitorError)?;
 
@@ -346,7 +432,10 @@ impl WireguardMonitor {
                 .map_err(CloseMsg::SetupError)?;
 
             let routes = Self::get_pre_tunnel_routes(&iface_name, &config)
-                .chain(Self::get_endpoint_routes(&endpoint_addrs))
+                .chain(Self::get_endpoint_routes(
+                    &endpoint_addrs,
+                    args.connectivity,
+                ))
                 .collect();
 
             args.route_manager
@@ -400,6 +489,14 @@ impl WireguardMonitor {
             let metadata = Self::tunnel_metadata(&iface_name, &config);
             (on_event)(TunnelEvent::Up(metadata)).await;
 
+            let network_change_handler = tokio::spawn(Self::handle_network_changes(
+                tunnel.clone(),
+                config.clone(),
+                args.route_manager.clone(),
+                args.network_changed_rx,
+                roam_tx,
+            ));
+
             tokio::task::spawn_blocking(move || {
                 if let Err(error) = connectivity_monitor.run() {
                     log::error!(
@@ -410,6 +507,7 @@ impl WireguardMonitor {
             })
             .await
             .unwrap();
+            network_change_handler.abort();
 
             Err::<Infallible, CloseMsg>(CloseMsg::PingErr)
         };
@@ -431,6 +529,45 @@ impl WireguardMonitor {
         Ok(monitor)
     }
 
+    /// Moves the tunnel to the new network whenever the network outside the tunnel changes, and
+    /// lets the connectivity monitor verify that the tunnel still works.
+    #[cfg_attr(not(target_os = "macos"), allow(unused_variables))]
+    async fn handle_network_changes(
+        tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>>,
+        config: Config,
+        route_manager: talpid_routing::RouteManagerHandle,
+        mut network_changed_rx: mpsc::UnboundedReceiver<()>,
+        roam_tx: sync_mpsc::Sender<()>,
+    ) {
+        while network_changed_rx.next().await.is_some() {
+            // Setting the peers again resets their endpoints and the cached source addresses, so
+            // that packets are sent from the new interface.
+            let set_config = match tunnel.lock().unwrap().as_ref() {
+                Some(tunnel) => tunnel.set_config(config.clone()),
+                None => return,
+            };
+            if let Err(error) = set_config.await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to reset peers after network change")
+                );
+            }
+
+            // Route the relay endpoints via the new default route.
+            #[cfg(target_os = "macos")]
+            if let Err(error) = route_manager.refresh_routes() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to refresh routes after network change")
+                );
+            }
+
+            if roam_tx.send(()).is_err() {
+                return;
+            }
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     async fn psk_negotiation<F>(
         tunnel: &Arc<Mutex<Option<Box<dyn Tunnel>>>>,
@@ -556,10 +693,11 @@ impl WireguardMonitor {
     ) -> std::result::Result<Config, CloseMsg> {
         let mut obfs_guard = obfuscator.lock().await;
         if let Some(obfuscator_handle) = obfs_guard.take() {
-            obfuscator_handle.abort();
+            obfuscator_handle.shutdown().await;
             *obfs_guard = maybe_create_obfuscator(&mut config, close_obfs_sender)
                 .await
                 .map_err(CloseMsg::ObfuscatorFailed)?;
+            OBFUSCATOR_RECONNECTS.fetch_add(1, Ordering::Relaxed);
 
             // Exclude new remote obfuscation socket or bridge
             #[cfg(target_os = "android")]
@@ -811,6 +949,13 @@ impl WireguardMonitor {
 
         self.stop_tunnel();
 
+        // The next tunnel may be started as soon as this returns, so the obfuscator must be gone
+        self.runtime.block_on(async {
+            if let Some(obfuscator) = self.obfuscator.lock().await.take() {
+                obfuscator.shutdown().await;
+            }
+        });
+
         wait_result
     }
 
@@ -827,21 +972,39 @@ impl WireguardMonitor {
         }
     }
 
-    /// Returns routes to the peer endpoints (through the physical interface).
+    /// Returns routes for the relay endpoints via the default route outside the tunnel. Endpoints
+    /// of an IP version without a default route are skipped, since the routes cannot be added.
     #[cfg_attr(target_os = "linux", allow(unused_variables))]
-    fn get_endpoint_routes(endpoints: &[IpAddr]) -> impl Iterator<Item = RequiredRoute> + '_ {
+    fn get_endpoint_routes(
+        endpoints: &[IpAddr],
+        connectivity: Connectivity,
+    ) -> impl Iterator<Item = RequiredRoute> + '_ {
         #[cfg(target_os = "linux")]
         {
             // No need due to policy based routing.
             std::iter::empty::<RequiredRoute>()
         }
         #[cfg(not(target_os = "linux"))]
-        endpoints.iter().map(|ip| {
-            RequiredRoute::new(
-                ipnetwork::IpNetwork::from(*ip),
-                routing::NetNode::DefaultNode,
-            )
-        })
+        endpoints
+            .iter()
+            .map(|ip| {
+                RequiredRoute::new(
+                    ipnetwork::IpNetwork::from(*ip),
+                    routing::NetNode::DefaultNode,
+                )
+                .purpose(RoutePurpose::RelayExclusion)
+            })
+            .filter(move |route| {
+                let routable = route.is_routable(connectivity);
+                if !routable {
+                    log::warn!(
+                        "Not routing relay endpoint {} outside the tunnel, since there is no \
+                         default route for its IP version",
+                        route.prefix.ip()
+                    );
+                }
+                routable
+            })
     }
 
     #[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
@@ -876,7 +1039,8 @@ impl WireguardMonitor {
         ))
         .chain(config.ipv6_gateway.map(|gateway| {
             RequiredRoute::new(ipnetwork::Ipv6Network::from(gateway).into(), gateway_node)
-        }));
+        }))
+        .map(|route| route.purpose(RoutePurpose::Tunnel));
 
         let (node_v4, node_v6) = Self::get_tunnel_nodes(iface_name, config);
 
@@ -884,11 +1048,12 @@ impl WireguardMonitor {
             Self::get_tunnel_destinations(config)
                 .filter(|allowed_ip| allowed_ip.prefix() != 0)
                 .map(move |allowed_ip| {
-                    if allowed_ip.is_ipv4() {
-                        RequiredRoute::new(allowed_ip, node_v4.clone())
+                    let node = if allowed_ip.is_ipv4() {
+                        node_v4.clone()
                     } else {
-                        RequiredRoute::new(allowed_ip, node_v6.clone())
-                    }
+                        node_v6.clone()
+                    };
+                    RequiredRoute::new(allowed_ip, node).purpose(RoutePurpose::Tunnel)
                 }),
         );
 
@@ -905,11 +1070,12 @@ impl WireguardMonitor {
             .filter(|allowed_ip| allowed_ip.prefix() == 0)
             .flat_map(Self::replace_default_prefixes)
             .map(move |allowed_ip| {
-                if allowed_ip.is_ipv4() {
-                    RequiredRoute::new(allowed_ip, node_v4.clone())
+                let node = if allowed_ip.is_ipv4() {
+                    node_v4.clone()
                 } else {
-                    RequiredRoute::new(allowed_ip, node_v6.clone())
-                }
+                    node_v6.clone()
+                };
+                RequiredRoute::new(allowed_ip, node).purpose(RoutePurpose::TunnelDefault)
             });
         #[cfg(not(target_os = "linux"))]
         return iter;
@@ -34,8 +34,12 @@ static NULL_DEVICE: &str = "NUL";
 #[cfg(not(target_os = "windows"))]
 static NULL_DEVICE: &str = "/dev/null";
 
+/// Opens the log for appending. It is rotated by the caller before the tunnel starts.
 fn create_log_file(log_path: Option<&Path>) -> Result<fs::File, Error> {
-    fs::File::create(log_path.unwrap_or_else(|| NULL_DEVICE.as_ref()))
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path.unwrap_or_else(|| NULL_DEVICE.as_ref()))
         .map_err(Error::PrepareLogFileError)
 }
 
@@ -21,6 +21,7 @@ use std::{
     ptr,
     sync::{Arc, Mutex},
 };
+use talpid_tunnel::network_interface::MetricPin;
 use talpid_types::{BoxedError, ErrorExt};
 use talpid_windows_net as net;
 use widestring::{U16CStr, U16CString};
@@ -164,6 +165,7 @@ pub enum Error {
 
 pub struct WgNtTunnel {
     device: Arc<Mutex<Option<WgNtAdapter>>>,
+    metric_pin: Arc<Mutex<Option<MetricPin>>>,
     interface_name: String,
     setup_handle: tokio::task::JoinHandle<()>,
     _logger_handle: LoggerHandle,
@@ -444,9 +446,11 @@ impl WgNtTunnel {
         }
         device.set_config(config)?;
         let device = Arc::new(Mutex::new(Some(device)));
+        let metric_pin = Arc::new(Mutex::new(None));
 
         let setup_future = setup_ip_listener(
             device.clone(),
+            metric_pin.clone(),
             u32::from(config.mtu),
             config.tunnel.addresses.iter().any(|addr| addr.is_ipv6()),
         );
@@ -458,6 +462,7 @@ impl WgNtTunnel {
 
         Ok(WgNtTunnel {
             device,
+            metric_pin,
             interface_name,
             setup_handle,
             _logger_handle: logger_handle,
@@ -466,12 +471,15 @@ impl WgNtTunnel {
 
     fn stop_tunnel(&mut self) {
         self.setup_handle.abort();
+        // Restore the interface metric before the adapter is removed
+        let _ = self.metric_pin.lock().unwrap().take();
         let _ = self.device.lock().unwrap().take();
     }
 }
 
 async fn setup_ip_listener(
     device: Arc<Mutex<Option<WgNtAdapter>>>,
+    metric_pin: Arc<Mutex<Option<MetricPin>>>,
     mtu: u32,
     has_ipv6: bool,
 ) -> Result<()> {
@@ -486,8 +494,9 @@ async fn setup_ip_listener(
         .map_err(Error::IpInterfaces)?;
     log::debug!("Waiting for tunnel IP interfaces: Done");
 
-    talpid_tunnel::network_interface::initialize_interfaces(luid, Some(mtu))
+    let pin = talpid_tunnel::network_interface::initialize_interfaces(luid, Some(mtu))
         .map_err(Error::SetTunnelMtu)?;
+    *metric_pin.lock().unwrap() = Some(pin);
 
     if let Some(device) = &*device.lock().unwrap() {
         device
@@ -1,7 +1,22 @@
 use async_trait::async_trait;
-use std::net::SocketAddr;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
+mod quic;
+mod socks;
+mod task;
+mod tls;
 mod udp2tcp;
+mod watchdog;
+pub use quic::{QuicSettings, MAX_TUNNEL_MTU as QUIC_MAX_TUNNEL_MTU};
+pub use socks::{SocksAuth, SocksProxy};
+pub use task::{reap_orphaned_tasks, running_tasks, spawn, ObfuscatorTask};
+pub use tls::TlsSettings;
 pub use udp2tcp::Udp2TcpSettings;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -14,6 +29,18 @@ pub enum Error {
 
     #[error(display = "Failed to run Udp2Tcp obfuscator")]
     RunUdp2TcpObfuscator(#[error(source)] udp2tcp::Error),
+
+    #[error(display = "Failed to create QUIC obfuscator")]
+    CreateQuicObfuscator(#[error(source)] quic::Error),
+
+    #[error(display = "Failed to run QUIC obfuscator")]
+    RunQuicObfuscator(#[error(source)] quic::Error),
+
+    #[error(display = "Failed to create TLS obfuscator")]
+    CreateTlsObfuscator(#[error(source)] tls::Error),
+
+    #[error(display = "Failed to run TLS obfuscator")]
+    RunTlsObfuscator(#[error(source)] tls::Error),
 }
 
 #[async_trait]
@@ -23,13 +50,53 @@ pub trait Obfuscator: Send {
     /// Returns the address of the local socket.
     fn endpoint(&self) -> SocketAddr;
 
+    /// Returns the traffic counters of the obfuscator. They remain available after the obfuscator
+    /// has stopped.
+    fn stats(&self) -> Arc<StatsCounters>;
+
     /// Returns the file descriptor of the outbound socket.
     #[cfg(target_os = "android")]
     fn remote_socket_fd(&self) -> std::os::unix::io::RawFd;
 }
 
+/// Traffic relayed by an obfuscator.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ObfuscatorStats {
+    /// Bytes received from WireGuard and sent to the remote end
+    pub tx_bytes: u64,
+    /// Bytes received from the remote end and sent to WireGuard
+    pub rx_bytes: u64,
+}
+
+/// Counters that are updated by an obfuscator while it runs.
+#[derive(Debug, Default)]
+pub struct StatsCounters {
+    tx_bytes: AtomicU64,
+    rx_bytes: AtomicU64,
+}
+
+impl StatsCounters {
+    fn add_tx_bytes(&self, bytes: usize) {
+        self.tx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn add_rx_bytes(&self, bytes: usize) {
+        self.rx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the current values of the counters.
+    pub fn snapshot(&self) -> ObfuscatorStats {
+        ObfuscatorStats {
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
 pub enum Settings {
     Udp2Tcp(Udp2TcpSettings),
+    Quic(QuicSettings),
+    Tls(TlsSettings),
 }
 
 pub async fn create_obfuscator(settings: &Settings) -> Result<Box<dyn Obfuscator>> {
@@ -37,5 +104,11 @@ pub async fn create_obfuscator(settings: &Settings) -> Result<Box<dyn Obfuscator
         Settings::Udp2Tcp(s) => udp2tcp::create_obfuscator(s)
             .await
             .map_err(Error::CreateUdp2TcpObfuscator),
+        Settings::Quic(s) => quic::create_obfuscator(s)
+            .await
+            .map_err(Error::CreateQuicObfuscator),
+        Settings::Tls(s) => tls::create_obfuscator(s)
+            .await
+            .map_err(Error::CreateTlsObfuscator),
     }
 }
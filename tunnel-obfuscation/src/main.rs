@@ -23,6 +23,9 @@ async fn instantiate_requested(obfuscator_type: &str) -> Box<dyn Obfuscator> {
                 peer: SocketAddr::new("127.0.0.1".parse().unwrap(), 3030),
                 #[cfg(target_os = "linux")]
                 fwmark: Some(1337),
+                keepalive_interval: None,
+                idle_timeout: None,
+                upstream_proxy: None,
             };
 
             create_obfuscator(&Settings::Udp2Tcp(settings))
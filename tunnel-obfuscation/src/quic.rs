@@ -0,0 +1,340 @@
+//! Sends WireGuard datagrams to the relay as QUIC datagrams (RFC 9221), so that the traffic looks
+//! like an HTTP/3 connection.
+
+use crate::{tls, Obfuscator, StatsCounters};
+use async_trait::async_trait;
+use bytes::Bytes;
+use quinn::{ClientConfig, Endpoint, EndpointConfig, TokioRuntime, TransportConfig};
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+/// Largest UDP payload that fits in a 1500 byte packet over IPv6. Used as the initial MTU of the
+/// QUIC connection, since the smaller default would not fit full-sized WireGuard packets.
+const INITIAL_MTU: u16 = 1500 - 40 - 8;
+/// Largest tunnel MTU whose WireGuard packets fit in a QUIC datagram when the path MTU is 1500.
+/// A QUIC short header with the longest connection ID, its authentication tag and the datagram
+/// frame header take up 44 bytes, and WireGuard adds 32 bytes to each packet.
+pub const MAX_TUNNEL_MTU: u16 = INITIAL_MTU - 44 - 32;
+/// Interval between QUIC pings, which keep NAT mappings alive while WireGuard is idle.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// The connection fails if nothing is received from the relay for this long.
+const MAX_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest datagram that can be received from WireGuard.
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+pub struct QuicSettings {
+    pub peer: SocketAddr,
+    /// Server name to send in the TLS handshake. The certificate of the relay is not verified
+    /// against it, so it can be any name that the connection should look like it is made to.
+    pub server_name: String,
+    /// Application protocols to offer in the TLS handshake.
+    pub alpn: Vec<String>,
+    #[cfg(target_os = "linux")]
+    pub fwmark: Option<u32>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to bind the socket that WireGuard sends to
+    #[error(display = "Failed to bind local UDP socket")]
+    BindLocalSocket(#[error(source)] io::Error),
+
+    /// Failed to create the socket for the QUIC connection
+    #[error(display = "Failed to create remote UDP socket")]
+    CreateRemoteSocket(#[error(source)] io::Error),
+
+    /// Failed to set the firewall mark of the socket for the QUIC connection
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to set fwmark on remote UDP socket")]
+    SetFwmark(#[error(source)] io::Error),
+
+    /// Failed to create the QUIC endpoint
+    #[error(display = "Failed to create QUIC endpoint")]
+    CreateEndpoint(#[error(source)] io::Error),
+
+    /// The connection could not be initiated
+    #[error(display = "Failed to connect to QUIC endpoint")]
+    Connect(#[error(source)] quinn::ConnectError),
+
+    /// The QUIC handshake failed
+    #[error(display = "QUIC handshake failed")]
+    Handshake(#[error(source)] quinn::ConnectionError),
+
+    /// The relay does not accept QUIC datagrams
+    #[error(display = "The QUIC endpoint does not support datagrams")]
+    DatagramsUnsupported,
+
+    /// Failed to relay a datagram between WireGuard and the QUIC connection
+    #[error(display = "Failed to relay datagram")]
+    Relay(#[error(source)] io::Error),
+
+    /// Failed to send a datagram over the QUIC connection
+    #[error(display = "Failed to send QUIC datagram")]
+    SendDatagram(#[error(source)] quinn::SendDatagramError),
+
+    /// The QUIC connection was closed or timed out
+    #[error(display = "QUIC connection was lost")]
+    ConnectionLost(#[error(source)] quinn::ConnectionError),
+}
+
+struct Quic {
+    local_addr: SocketAddr,
+    client_socket: UdpSocket,
+    endpoint: Endpoint,
+    client_config: ClientConfig,
+    peer: SocketAddr,
+    server_name: String,
+    stats: Arc<StatsCounters>,
+    #[cfg(target_os = "android")]
+    remote_socket_fd: std::os::unix::io::RawFd,
+}
+
+impl Quic {
+    pub async fn new(settings: &QuicSettings) -> Result<Self> {
+        let (listen_ip, remote_ip) = if settings.peer.is_ipv4() {
+            (Ipv4Addr::LOCALHOST.into(), Ipv4Addr::UNSPECIFIED.into())
+        } else {
+            (Ipv6Addr::LOCALHOST.into(), Ipv6Addr::UNSPECIFIED.into())
+        };
+
+        let client_socket = UdpSocket::bind(SocketAddr::new(listen_ip, 0))
+            .await
+            .map_err(Error::BindLocalSocket)?;
+        let local_addr = client_socket.local_addr().map_err(Error::BindLocalSocket)?;
+
+        let remote_socket = std::net::UdpSocket::bind(SocketAddr::new(remote_ip, 0))
+            .map_err(Error::CreateRemoteSocket)?;
+        #[cfg(target_os = "linux")]
+        if let Some(fwmark) = settings.fwmark {
+            socket2::SockRef::from(&remote_socket)
+                .set_mark(fwmark)
+                .map_err(Error::SetFwmark)?;
+        }
+        #[cfg(target_os = "android")]
+        let remote_socket_fd = std::os::unix::io::AsRawFd::as_raw_fd(&remote_socket);
+
+        let endpoint = Endpoint::new(
+            EndpointConfig::default(),
+            None,
+            remote_socket,
+            Arc::new(TokioRuntime),
+        )
+        .map_err(Error::CreateEndpoint)?;
+
+        Ok(Self {
+            local_addr,
+            client_socket,
+            endpoint,
+            client_config: client_config(&settings.alpn),
+            peer: settings.peer,
+            server_name: settings.server_name.clone(),
+            stats: Arc::new(StatsCounters::default()),
+            #[cfg(target_os = "android")]
+            remote_socket_fd,
+        })
+    }
+
+    /// Connects to the relay, and relays datagrams until the connection is lost.
+    async fn relay(self) -> Result<()> {
+        let connection = self
+            .endpoint
+            .connect_with(self.client_config, self.peer, &self.server_name)
+            .map_err(Error::Connect)?
+            .await
+            .map_err(Error::Handshake)?;
+        if connection.max_datagram_size().is_none() {
+            return Err(Error::DatagramsUnsupported);
+        }
+
+        let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+        let mut client_addr = None;
+
+        loop {
+            tokio::select! {
+                result = self.client_socket.recv_from(&mut buffer) => {
+                    let (len, addr) = result.map_err(Error::Relay)?;
+                    client_addr = Some(addr);
+                    match connection.send_datagram(Bytes::copy_from_slice(&buffer[..len])) {
+                        Ok(()) => self.stats.add_tx_bytes(len),
+                        // Datagrams that do not fit in a QUIC packet are dropped, like packets
+                        // that exceed the MTU of a link
+                        Err(quinn::SendDatagramError::TooLarge) => (),
+                        Err(error) => return Err(Error::SendDatagram(error)),
+                    }
+                }
+                result = connection.read_datagram() => {
+                    let datagram = result.map_err(Error::ConnectionLost)?;
+                    if let Some(addr) = client_addr {
+                        self.client_socket
+                            .send_to(&datagram, addr)
+                            .await
+                            .map_err(Error::Relay)?;
+                        self.stats.add_rx_bytes(datagram.len());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn client_config(alpn: &[String]) -> ClientConfig {
+    let crypto = tls::client_config(alpn);
+
+    let mut transport = TransportConfig::default();
+    transport.initial_mtu(INITIAL_MTU);
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    transport.max_idle_timeout(Some(
+        MAX_IDLE_TIMEOUT
+            .try_into()
+            .expect("idle timeout is within bounds"),
+    ));
+
+    let mut config = ClientConfig::new(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+    config
+}
+
+#[async_trait]
+impl Obfuscator for Quic {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn run(self: Box<Self>) -> crate::Result<()> {
+        self.relay().await.map_err(crate::Error::RunQuicObfuscator)
+    }
+
+    fn stats(&self) -> Arc<StatsCounters> {
+        self.stats.clone()
+    }
+
+    #[cfg(target_os = "android")]
+    fn remote_socket_fd(&self) -> std::os::unix::io::RawFd {
+        self.remote_socket_fd
+    }
+}
+
+pub async fn create_obfuscator(settings: &QuicSettings) -> Result<Box<dyn Obfuscator>> {
+    Ok(Box::new(Quic::new(settings).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use quinn::crypto::rustls::HandshakeData;
+    use tokio::{sync::oneshot, time::timeout};
+
+    const SERVER_NAME: &str = "www.example.com";
+
+    /// Starts a QUIC server that echoes every datagram it receives, and reports the server name
+    /// and application protocol of the first connection.
+    fn spawn_echo_server(datagrams: bool) -> (SocketAddr, oneshot::Receiver<HandshakeData>) {
+        let generated = rcgen::generate_simple_self_signed(vec!["relay.test".to_owned()])
+            .expect("failed to generate certificate");
+        let certificate = rustls::Certificate(generated.serialize_der().unwrap());
+        let private_key = rustls::PrivateKey(generated.serialize_private_key_der());
+
+        let mut crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], private_key)
+            .unwrap();
+        crypto.alpn_protocols = vec![b"h3".to_vec()];
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        // Like the relays, the server must not start out with the minimum MTU, or the echoed
+        // datagrams would not fit
+        let mut transport = TransportConfig::default();
+        transport.initial_mtu(INITIAL_MTU);
+        if !datagrams {
+            transport.datagram_receive_buffer_size(None);
+        }
+        config.transport_config(Arc::new(transport));
+
+        let endpoint = Endpoint::server(config, (Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+        let addr = endpoint.local_addr().unwrap();
+        let (handshake_tx, handshake_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let handshake_data = connection
+                .handshake_data()
+                .unwrap()
+                .downcast::<HandshakeData>()
+                .unwrap();
+            let _ = handshake_tx.send(*handshake_data);
+            while let Ok(datagram) = connection.read_datagram().await {
+                connection
+                    .send_datagram(datagram)
+                    .expect("failed to echo datagram");
+            }
+        });
+
+        (addr, handshake_rx)
+    }
+
+    async fn start_obfuscator(peer: SocketAddr) -> Box<dyn Obfuscator> {
+        create_obfuscator(&QuicSettings {
+            peer,
+            server_name: SERVER_NAME.to_owned(),
+            alpn: vec!["h3".to_owned()],
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_datagrams_are_relayed() {
+        let (server_addr, handshake_rx) = spawn_echo_server(true);
+        let obfuscator = start_obfuscator(server_addr).await;
+        let endpoint = obfuscator.endpoint();
+        let stats = obfuscator.stats();
+        let obfuscator = tokio::spawn(obfuscator.run());
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(endpoint).await.unwrap();
+        // Full-sized WireGuard packets must fit in a datagram
+        let datagram = vec![0xabu8; usize::from(MAX_TUNNEL_MTU) + 32];
+        let mut response = vec![0u8; MAX_DATAGRAM_SIZE];
+        for _ in 0..3 {
+            client.send(&datagram).await.unwrap();
+            let len = timeout(Duration::from_secs(5), client.recv(&mut response))
+                .await
+                .expect("no response from echo server")
+                .unwrap();
+            assert_eq!(response[..len], datagram[..]);
+        }
+
+        let handshake_data = handshake_rx.await.unwrap();
+        assert_eq!(handshake_data.server_name.as_deref(), Some(SERVER_NAME));
+        assert_eq!(handshake_data.protocol.as_deref(), Some(&b"h3"[..]));
+
+        let stats = stats.snapshot();
+        assert_eq!(stats.tx_bytes, 3 * datagram.len() as u64);
+        assert_eq!(stats.rx_bytes, 3 * datagram.len() as u64);
+        obfuscator.abort();
+    }
+
+    #[tokio::test]
+    async fn test_datagrams_unsupported() {
+        let (server_addr, _handshake_rx) = spawn_echo_server(false);
+        let obfuscator = start_obfuscator(server_addr).await;
+
+        let result = timeout(Duration::from_secs(5), obfuscator.run())
+            .await
+            .expect("obfuscator did not fail");
+        assert!(matches!(
+            result,
+            Err(crate::Error::RunQuicObfuscator(Error::DatagramsUnsupported))
+        ));
+    }
+}
@@ -0,0 +1,280 @@
+//! Client side of the SOCKS5 protocol, used to reach relays through an upstream proxy.
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpSocket, TcpStream},
+};
+
+pub use talpid_types::net::proxy::SocksAuth;
+
+const SOCKS_VERSION: u8 = 5;
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+
+const METHOD_NO_AUTHENTICATION: u8 = 0;
+const METHOD_USERNAME_PASSWORD: u8 = 2;
+
+const COMMAND_CONNECT: u8 = 1;
+const ADDRESS_TYPE_IPV4: u8 = 1;
+const ADDRESS_TYPE_DOMAIN_NAME: u8 = 3;
+const ADDRESS_TYPE_IPV6: u8 = 4;
+const REPLY_SUCCEEDED: u8 = 0;
+
+/// A SOCKS5 server to connect through.
+#[derive(Debug, Clone)]
+pub struct SocksProxy {
+    pub peer: SocketAddr,
+    pub auth: Option<SocksAuth>,
+}
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to connect to the SOCKS5 server
+    #[error(display = "Failed to connect to the SOCKS5 server")]
+    Connect(#[error(source)] io::Error),
+
+    /// Failed to send or receive a handshake message
+    #[error(display = "Failed to exchange handshake messages with the SOCKS5 server")]
+    Io(#[error(source)] io::Error),
+
+    /// The server responded with an unexpected protocol version
+    #[error(display = "Unexpected protocol version in response: {}", _0)]
+    UnexpectedVersion(u8),
+
+    /// The server did not select the offered authentication method
+    #[error(display = "The SOCKS5 server accepts none of the offered authentication methods")]
+    NoAcceptableMethod,
+
+    /// The username or password cannot be encoded in the handshake
+    #[error(display = "The username and password must be between 1 and 255 bytes long")]
+    InvalidCredentials,
+
+    /// The server rejected the username and password
+    #[error(display = "The SOCKS5 server rejected the username and password")]
+    AuthenticationFailed,
+
+    /// The server failed to connect to the target
+    #[error(
+        display = "The SOCKS5 server failed to connect to the target (reply {})",
+        _0
+    )]
+    ConnectRejected(u8),
+
+    /// The server responded with an unknown address type
+    #[error(display = "Unknown address type in response: {}", _0)]
+    UnknownAddressType(u8),
+}
+
+/// Connects `socket` to `target` through `proxy`. The returned stream carries the traffic of
+/// `target`.
+pub async fn connect(
+    socket: TcpSocket,
+    proxy: &SocksProxy,
+    target: SocketAddr,
+) -> Result<TcpStream, Error> {
+    let mut stream = socket.connect(proxy.peer).await.map_err(Error::Connect)?;
+    // Disables the Nagle algorithm on the TCP socket. Improves performance
+    stream.set_nodelay(true).map_err(Error::Connect)?;
+    handshake(&mut stream, target, proxy.auth.as_ref()).await?;
+    Ok(stream)
+}
+
+/// Asks the SOCKS5 server at the other end of `stream` to connect to `target`, authenticating
+/// with `auth` if it is set. Once this returns, `stream` is connected to `target`.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: SocketAddr,
+    auth: Option<&SocksAuth>,
+) -> Result<(), Error> {
+    let method = match auth {
+        Some(auth) => {
+            let valid_len = |field: &str| (1..=255).contains(&field.len());
+            if !valid_len(&auth.username) || !valid_len(&auth.password) {
+                return Err(Error::InvalidCredentials);
+            }
+            METHOD_USERNAME_PASSWORD
+        }
+        None => METHOD_NO_AUTHENTICATION,
+    };
+    stream
+        .write_all(&[SOCKS_VERSION, 1, method])
+        .await
+        .map_err(Error::Io)?;
+
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.map_err(Error::Io)?;
+    if response[0] != SOCKS_VERSION {
+        return Err(Error::UnexpectedVersion(response[0]));
+    }
+    // This also covers the server accepting none of the methods
+    if response[1] != method {
+        return Err(Error::NoAcceptableMethod);
+    }
+    if let Some(auth) = auth {
+        authenticate(stream, auth).await?;
+    }
+
+    let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ADDRESS_TYPE_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(ADDRESS_TYPE_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await.map_err(Error::Io)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await.map_err(Error::Io)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::UnexpectedVersion(reply[0]));
+    }
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(Error::ConnectRejected(reply[1]));
+    }
+    // The bound address is of no use, but must be consumed
+    let address_len = match reply[3] {
+        ADDRESS_TYPE_IPV4 => Ipv4Addr::UNSPECIFIED.octets().len(),
+        ADDRESS_TYPE_IPV6 => Ipv6Addr::UNSPECIFIED.octets().len(),
+        ADDRESS_TYPE_DOMAIN_NAME => usize::from(stream.read_u8().await.map_err(Error::Io)?),
+        address_type => return Err(Error::UnknownAddressType(address_type)),
+    };
+    let mut bound_address = vec![0u8; address_len + 2];
+    stream
+        .read_exact(&mut bound_address)
+        .await
+        .map_err(Error::Io)?;
+    Ok(())
+}
+
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: &SocksAuth,
+) -> Result<(), Error> {
+    let username = auth.username.as_bytes();
+    let password = auth.password.as_bytes();
+
+    let mut request = vec![USERNAME_PASSWORD_VERSION, username.len() as u8];
+    request.extend_from_slice(username);
+    request.push(password.len() as u8);
+    request.extend_from_slice(password);
+    stream.write_all(&request).await.map_err(Error::Io)?;
+
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await.map_err(Error::Io)?;
+    if response[1] != 0 {
+        return Err(Error::AuthenticationFailed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+    fn target() -> SocketAddr {
+        "10.0.0.1:443".parse().unwrap()
+    }
+
+    fn auth() -> SocksAuth {
+        SocksAuth {
+            username: "user".to_owned(),
+            password: "secret".to_owned(),
+        }
+    }
+
+    /// Reads `expected` from the client and responds with `response`.
+    async fn exchange(server: &mut DuplexStream, expected: &[u8], response: &[u8]) {
+        let mut request = vec![0u8; expected.len()];
+        server.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, expected);
+        server.write_all(response).await.unwrap();
+    }
+
+    const CONNECT_REQUEST: [u8; 10] = [5, 1, 0, 1, 10, 0, 0, 1, 0x01, 0xbb];
+    const CONNECT_REPLY: [u8; 10] = [5, 0, 0, 1, 192, 168, 0, 1, 0x1f, 0x90];
+
+    #[tokio::test]
+    async fn test_handshake_without_authentication() {
+        let (mut client, mut server) = duplex(1024);
+        let server = tokio::spawn(async move {
+            exchange(&mut server, &[5, 1, 0], &[5, 0]).await;
+            exchange(&mut server, &CONNECT_REQUEST, &CONNECT_REPLY).await;
+            server
+        });
+
+        handshake(&mut client, target(), None).await.unwrap();
+
+        // Data that follows the handshake belongs to the target
+        let mut server = server.await.unwrap();
+        client.write_all(b"data").await.unwrap();
+        let mut data = [0u8; 4];
+        server.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"data");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_with_authentication() {
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            exchange(&mut server, &[5, 1, 2], &[5, 2]).await;
+            exchange(&mut server, b"\x01\x04user\x06secret", &[1, 0]).await;
+            // Replies with a domain name as the bound address
+            let mut reply = vec![5, 0, 0, 3, 4];
+            reply.extend_from_slice(b"host\x1f\x90");
+            exchange(&mut server, &CONNECT_REQUEST, &reply).await;
+            // Keep the stream open until the client is done
+            let _ = server.read_u8().await;
+        });
+
+        handshake(&mut client, target(), Some(&auth()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handshake_failures() {
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            exchange(&mut server, &[5, 1, 2], &[5, 2]).await;
+            exchange(&mut server, b"\x01\x04user\x06secret", &[1, 1]).await;
+        });
+        let result = handshake(&mut client, target(), Some(&auth())).await;
+        assert!(matches!(result, Err(Error::AuthenticationFailed)));
+
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            exchange(&mut server, &[5, 1, 0], &[5, METHOD_NONE_ACCEPTABLE]).await;
+        });
+        let result = handshake(&mut client, target(), None).await;
+        assert!(matches!(result, Err(Error::NoAcceptableMethod)));
+
+        // Host unreachable
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            exchange(&mut server, &[5, 1, 0], &[5, 0]).await;
+            exchange(&mut server, &CONNECT_REQUEST, &[5, 4, 0, 1]).await;
+        });
+        let result = handshake(&mut client, target(), None).await;
+        assert!(matches!(result, Err(Error::ConnectRejected(4))));
+
+        let (mut client, _server) = duplex(1024);
+        let auth = SocksAuth {
+            username: String::new(),
+            password: "secret".to_owned(),
+        };
+        let result = handshake(&mut client, target(), Some(&auth)).await;
+        assert!(matches!(result, Err(Error::InvalidCredentials)));
+    }
+}
@@ -0,0 +1,165 @@
+//! Tasks that run obfuscators. A task must be shut down before the next obfuscator is created, so
+//! that the local sockets of the old obfuscator are closed and cannot interfere with the new one.
+
+use crate::{Obfuscator, Result};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+/// Number of obfuscator tasks that have not stopped yet.
+static RUNNING_TASKS: AtomicUsize = AtomicUsize::new(0);
+/// Tasks that were dropped instead of being shut down, and had not stopped yet.
+static ORPHANED_TASKS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Time to wait for an orphaned task to stop.
+const REAP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returns the number of obfuscator tasks that have not stopped yet, including orphaned ones.
+pub fn running_tasks() -> usize {
+    RUNNING_TASKS.load(Ordering::SeqCst)
+}
+
+/// Counts a task as running until it is dropped, which happens once the task has stopped.
+struct RunningTask(());
+
+impl RunningTask {
+    fn new() -> Self {
+        RUNNING_TASKS.fetch_add(1, Ordering::SeqCst);
+        RunningTask(())
+    }
+}
+
+impl Drop for RunningTask {
+    fn drop(&mut self) {
+        RUNNING_TASKS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Handle to a task that runs an obfuscator. Dropping it aborts the task without waiting for it
+/// to stop, which leaves it to [`reap_orphaned_tasks`].
+pub struct ObfuscatorTask {
+    join_handle: Option<JoinHandle<()>>,
+}
+
+impl ObfuscatorTask {
+    /// Stops the obfuscator and waits until the task, along with its sockets, is gone.
+    pub async fn shutdown(mut self) {
+        if let Some(join_handle) = self.join_handle.take() {
+            join_handle.abort();
+            let _ = join_handle.await;
+        }
+    }
+}
+
+impl Drop for ObfuscatorTask {
+    fn drop(&mut self) {
+        if let Some(join_handle) = self.join_handle.take() {
+            join_handle.abort();
+            if !join_handle.is_finished() {
+                ORPHANED_TASKS.lock().unwrap().push(join_handle);
+            }
+        }
+    }
+}
+
+/// Runs `obfuscator` in a new task. `on_exit` is called with the result if the obfuscator stops by
+/// itself, but not if the task is shut down.
+pub fn spawn(
+    obfuscator: Box<dyn Obfuscator>,
+    on_exit: impl FnOnce(Result<()>) + Send + 'static,
+) -> ObfuscatorTask {
+    let running = RunningTask::new();
+    let join_handle = tokio::spawn(async move {
+        let _running = running;
+        on_exit(obfuscator.run().await);
+    });
+    ObfuscatorTask {
+        join_handle: Some(join_handle),
+    }
+}
+
+/// Waits for the tasks that were dropped without being shut down to stop. This should be done
+/// before an obfuscator is created, since an orphaned task may still hold its local sockets.
+pub async fn reap_orphaned_tasks() {
+    let orphans = std::mem::take(&mut *ORPHANED_TASKS.lock().unwrap());
+    for orphan in orphans {
+        if orphan.is_finished() {
+            continue;
+        }
+        log::warn!("Waiting for an orphaned obfuscator task to stop");
+        if tokio::time::timeout(REAP_TIMEOUT, orphan).await.is_err() {
+            log::error!("Orphaned obfuscator task did not stop");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{create_obfuscator, Settings, Udp2TcpSettings};
+    use std::net::SocketAddr;
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream, UdpSocket},
+        time::timeout,
+    };
+
+    /// Starts a udp2tcp obfuscator and waits until it has connected to `listener`. The connection
+    /// is returned so that it stays open.
+    async fn start_connected_obfuscator(
+        listener: &TcpListener,
+    ) -> (SocketAddr, ObfuscatorTask, TcpStream) {
+        let obfuscator = create_obfuscator(&Settings::Udp2Tcp(Udp2TcpSettings {
+            peer: listener.local_addr().unwrap(),
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+            keepalive_interval: None,
+            idle_timeout: None,
+            upstream_proxy: None,
+        }))
+        .await
+        .expect("failed to create obfuscator");
+        let endpoint = obfuscator.endpoint();
+        let task = spawn(obfuscator, |_| ());
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&[1u8; 148], endpoint).await.unwrap();
+        let (mut stream, _) = timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .expect("obfuscator did not connect")
+            .unwrap();
+        let mut frame = [0u8; 2 + 148];
+        stream.read_exact(&mut frame).await.unwrap();
+        (endpoint, task, stream)
+    }
+
+    /// The counter is shared by all tasks, so the scenarios must not run concurrently.
+    #[tokio::test]
+    async fn test_reconnect_cycles() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        for _ in 0..100 {
+            let (endpoint, task, _stream) = start_connected_obfuscator(&listener).await;
+            assert_eq!(running_tasks(), 1);
+            task.shutdown().await;
+            assert_eq!(running_tasks(), 0);
+            // The local socket is closed, so its port is free again
+            UdpSocket::bind(endpoint)
+                .await
+                .expect("local port is still bound");
+        }
+
+        // Dropped tasks are reaped before the next obfuscator is created
+        for _ in 0..10 {
+            let (_, task, _stream) = start_connected_obfuscator(&listener).await;
+            drop(task);
+            reap_orphaned_tasks().await;
+            assert_eq!(running_tasks(), 0);
+        }
+    }
+}
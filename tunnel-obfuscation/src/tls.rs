@@ -0,0 +1,264 @@
+//! Sends WireGuard datagrams to the relay over a TLS 1.3 connection, so that the traffic looks
+//! like HTTPS. This is udp2tcp with its TCP connection wrapped in TLS, so the datagrams are framed
+//! and relayed the same way and pass through the same watchdog.
+
+use crate::{
+    socks::SocksProxy,
+    udp2tcp::{self, Udp2Tcp, Udp2TcpSettings},
+    Obfuscator, StatsCounters,
+};
+use async_trait::async_trait;
+use std::{io, net::SocketAddr, sync::Arc, time::SystemTime};
+use tokio::net::TcpStream;
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+/// Application protocols that browsers offer when connecting to a website.
+const ALPN: [&str; 2] = ["h2", "http/1.1"];
+
+pub struct TlsSettings {
+    pub peer: SocketAddr,
+    /// Server name to send in the TLS handshake. The certificate of the relay is not verified
+    /// against it, so it can be any name that the connection should look like it is made to.
+    pub server_name: String,
+    #[cfg(target_os = "linux")]
+    pub fwmark: Option<u32>,
+    /// Connect to `peer` through this SOCKS5 server instead of directly.
+    pub upstream_proxy: Option<SocksProxy>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The server name cannot be sent in the TLS handshake
+    #[error(display = "Invalid TLS server name \"{}\"", _0)]
+    InvalidServerName(String),
+
+    /// The underlying udp2tcp obfuscator failed
+    #[error(display = "Failed to relay over the TCP connection")]
+    Udp2Tcp(#[error(source)] udp2tcp::Error),
+}
+
+/// Wraps the TCP connection of udp2tcp in a TLS session.
+pub(crate) struct TlsLayer {
+    connector: TlsConnector,
+    server_name: rustls::ServerName,
+}
+
+impl TlsLayer {
+    fn new(server_name: &str) -> Result<Self> {
+        let server_name = rustls::ServerName::try_from(server_name)
+            .map_err(|_| Error::InvalidServerName(server_name.to_owned()))?;
+        let alpn: Vec<String> = ALPN.iter().map(|protocol| protocol.to_string()).collect();
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(client_config(&alpn))),
+            server_name,
+        })
+    }
+
+    /// Performs the TLS handshake over `stream`.
+    pub(crate) async fn connect(self, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        self.connector.connect(self.server_name, stream).await
+    }
+}
+
+struct Tls(Udp2Tcp);
+
+/// Returns a TLS 1.3 configuration that offers the application protocols in `alpn` and accepts any
+/// certificate. It is shared with the QUIC obfuscator.
+pub(crate) fn client_config(alpn: &[String]) -> rustls::ClientConfig {
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .expect("TLS 1.3 is supported")
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+        .with_no_client_auth();
+    config.alpn_protocols = alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    config
+}
+
+/// Accepts any server certificate. The relay is authenticated by WireGuard, and the TLS session
+/// or QUIC connection only disguises the traffic, so the server name does not have to belong to
+/// the relay.
+struct AcceptAnyCertificate;
+
+impl rustls::client::ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+#[async_trait]
+impl Obfuscator for Tls {
+    fn endpoint(&self) -> SocketAddr {
+        self.0.endpoint()
+    }
+
+    async fn run(self: Box<Self>) -> crate::Result<()> {
+        self.0
+            .relay()
+            .await
+            .map_err(|error| crate::Error::RunTlsObfuscator(Error::Udp2Tcp(error)))
+    }
+
+    fn stats(&self) -> Arc<StatsCounters> {
+        self.0.stats()
+    }
+
+    #[cfg(target_os = "android")]
+    fn remote_socket_fd(&self) -> std::os::unix::io::RawFd {
+        self.0.remote_socket_fd()
+    }
+}
+
+pub async fn create_obfuscator(settings: &TlsSettings) -> Result<Box<dyn Obfuscator>> {
+    let tls = TlsLayer::new(&settings.server_name)?;
+    let udp2tcp_settings = Udp2TcpSettings {
+        peer: settings.peer,
+        #[cfg(target_os = "linux")]
+        fwmark: settings.fwmark,
+        keepalive_interval: None,
+        idle_timeout: None,
+        upstream_proxy: settings.upstream_proxy.clone(),
+    };
+    let udp2tcp = Udp2Tcp::with_tls(&udp2tcp_settings, Some(tls))
+        .await
+        .map_err(Error::Udp2Tcp)?;
+    Ok(Box::new(Tls(udp2tcp)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, UdpSocket},
+        sync::oneshot,
+        time::timeout,
+    };
+    use tokio_rustls::TlsAcceptor;
+
+    const SERVER_NAME: &str = "www.example.com";
+
+    /// Starts a TLS server that echoes everything it receives, and reports the server name and
+    /// application protocol of the first connection.
+    async fn spawn_echo_server() -> (SocketAddr, oneshot::Receiver<(String, Vec<u8>)>) {
+        let generated = rcgen::generate_simple_self_signed(vec!["relay.test".to_owned()])
+            .expect("failed to generate certificate");
+        let certificate = rustls::Certificate(generated.serialize_der().unwrap());
+        let private_key = rustls::PrivateKey(generated.serialize_private_key_der());
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], private_key)
+            .unwrap();
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (handshake_tx, handshake_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            let (_, connection) = stream.get_ref();
+            let _ = handshake_tx.send((
+                connection.server_name().unwrap_or_default().to_owned(),
+                connection.alpn_protocol().unwrap_or_default().to_vec(),
+            ));
+            let (mut reader, mut writer) = tokio::io::split(stream);
+            let _ = tokio::io::copy(&mut reader, &mut writer).await;
+        });
+
+        (addr, handshake_rx)
+    }
+
+    #[tokio::test]
+    async fn test_datagrams_are_relayed() {
+        let (server_addr, handshake_rx) = spawn_echo_server().await;
+        let obfuscator = create_obfuscator(&TlsSettings {
+            peer: server_addr,
+            server_name: SERVER_NAME.to_owned(),
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+            upstream_proxy: None,
+        })
+        .await
+        .unwrap();
+        let endpoint = obfuscator.endpoint();
+        let stats = obfuscator.stats();
+        let obfuscator = tokio::spawn(obfuscator.run());
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(endpoint).await.unwrap();
+        let mut response = vec![0u8; u16::MAX as usize];
+        for size in [148, 1420, 32] {
+            let datagram = vec![0xabu8; size];
+            client.send(&datagram).await.unwrap();
+            let len = timeout(Duration::from_secs(5), client.recv(&mut response))
+                .await
+                .expect("no response from echo server")
+                .unwrap();
+            assert_eq!(response[..len], datagram[..]);
+        }
+
+        let (server_name, alpn) = handshake_rx.await.unwrap();
+        assert_eq!(server_name, SERVER_NAME);
+        assert_eq!(alpn, b"h2");
+        let stats = stats.snapshot();
+        assert_eq!(stats.tx_bytes, 148 + 1420 + 32);
+        assert_eq!(stats.rx_bytes, 148 + 1420 + 32);
+        obfuscator.abort();
+    }
+
+    #[tokio::test]
+    async fn test_tls_layer_handshake() {
+        let (server_addr, handshake_rx) = spawn_echo_server().await;
+        let stream = TcpStream::connect(server_addr).await.unwrap();
+        let mut stream = TlsLayer::new(SERVER_NAME)
+            .unwrap()
+            .connect(stream)
+            .await
+            .unwrap();
+
+        stream.write_all(b"frame").await.unwrap();
+        let mut response = [0u8; 5];
+        timeout(Duration::from_secs(5), stream.read_exact(&mut response))
+            .await
+            .expect("no response from echo server")
+            .unwrap();
+        assert_eq!(&response, b"frame");
+
+        let (server_name, alpn) = handshake_rx.await.unwrap();
+        assert_eq!(server_name, SERVER_NAME);
+        assert_eq!(alpn, b"h2");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_server_name() {
+        let result = create_obfuscator(&TlsSettings {
+            peer: "127.0.0.1:443".parse().unwrap(),
+            server_name: "not a hostname".to_owned(),
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+            upstream_proxy: None,
+        })
+        .await;
+        assert!(matches!(result, Err(Error::InvalidServerName(_))));
+    }
+}
@@ -1,6 +1,17 @@
-use crate::Obfuscator;
+use crate::{
+    socks::{self, SocksProxy},
+    tls::TlsLayer,
+    watchdog::Watchdog,
+    Obfuscator, StatsCounters,
+};
 use async_trait::async_trait;
-use std::net::SocketAddr;
+use std::{
+    future,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpSocket};
 use udp_over_tcp::{
     udp2tcp::{self, Udp2Tcp as Udp2TcpImpl},
     TcpOptions,
@@ -10,6 +21,12 @@ pub struct Udp2TcpSettings {
     pub peer: SocketAddr,
     #[cfg(target_os = "linux")]
     pub fwmark: Option<u32>,
+    /// Send a keepalive over the TCP connection if nothing has been sent for this long.
+    pub keepalive_interval: Option<Duration>,
+    /// Fail if nothing is received for this long while WireGuard handshakes are unanswered.
+    pub idle_timeout: Option<Duration>,
+    /// Connect to `peer` through this SOCKS5 server instead of directly.
+    pub upstream_proxy: Option<SocksProxy>,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -28,24 +45,85 @@ pub enum Error {
     /// Failed to run obfuscator
     #[error(display = "Failed to run obfuscator")]
     RunObfuscator(#[error(source)] udp2tcp::Error),
+
+    /// Failed to create connection watchdog
+    #[error(display = "Failed to create connection watchdog")]
+    CreateWatchdog(#[error(source)] std::io::Error),
+
+    /// The connection watchdog failed or detected a stalled connection
+    #[error(display = "Connection watchdog stopped")]
+    Watchdog(#[error(source)] crate::watchdog::Error),
+
+    /// Failed to set up forwarding of the connection to the relay
+    #[error(display = "Failed to set up forwarding of the connection to the relay")]
+    CreateForwarder(#[error(source)] std::io::Error),
+
+    /// Failed to connect to the relay
+    #[error(display = "Failed to connect to the relay")]
+    Connect(#[error(source)] std::io::Error),
+
+    /// Failed to connect to the relay through the upstream proxy
+    #[error(display = "Failed to connect to the relay through the upstream proxy")]
+    UpstreamProxy(#[error(source)] socks::Error),
+
+    /// The TLS handshake with the relay failed
+    #[error(display = "TLS handshake failed")]
+    TlsHandshake(#[error(source)] std::io::Error),
+
+    /// Failed to forward traffic to the relay
+    #[error(display = "Failed to forward traffic to the relay")]
+    Forward(#[error(source)] std::io::Error),
 }
 
-struct Udp2Tcp {
+pub(crate) struct Udp2Tcp {
     local_addr: SocketAddr,
     instance: Udp2TcpImpl,
+    watchdog: Watchdog,
+    forwarder: Option<Forwarder>,
+    stats: Arc<StatsCounters>,
 }
 
 impl Udp2Tcp {
     pub async fn new(settings: &Udp2TcpSettings) -> Result<Self> {
+        Self::with_tls(settings, None).await
+    }
+
+    /// Creates an obfuscator whose TCP connection to the relay is wrapped in `tls`, if it is set.
+    pub(crate) async fn with_tls(
+        settings: &Udp2TcpSettings,
+        tls: Option<TlsLayer>,
+    ) -> Result<Self> {
         let listen_addr = if settings.peer.is_ipv4() {
             SocketAddr::new("127.0.0.1".parse().unwrap(), 0)
         } else {
             SocketAddr::new("::1".parse().unwrap(), 0)
         };
 
+        // udp-over-tcp can neither connect through a proxy nor speak TLS, so it is made to connect
+        // to a local socket whose connection is forwarded to the relay instead
+        let forwarder = if settings.upstream_proxy.is_some() || tls.is_some() {
+            Some(
+                Forwarder::new(
+                    listen_addr.ip(),
+                    settings.peer,
+                    settings.upstream_proxy.clone(),
+                    tls,
+                    #[cfg(target_os = "linux")]
+                    settings.fwmark,
+                )
+                .map_err(Error::CreateForwarder)?,
+            )
+        } else {
+            None
+        };
+        let tcp_peer = match &forwarder {
+            Some(forwarder) => forwarder.local_addr().map_err(Error::CreateForwarder)?,
+            None => settings.peer,
+        };
+
         let instance = Udp2TcpImpl::new(
             listen_addr,
-            settings.peer,
+            tcp_peer,
             TcpOptions {
                 #[cfg(target_os = "linux")]
                 fwmark: settings.fwmark,
@@ -56,15 +134,149 @@ impl Udp2Tcp {
         )
         .await
         .map_err(Error::CreateObfuscator)?;
-        let local_addr = instance
+        let obfuscator_addr = instance
             .local_udp_addr()
             .map_err(Error::GetUdpSocketDetails)?;
 
+        // The traffic is relayed through the watchdog so that it can be counted
+        let stats = Arc::new(StatsCounters::default());
+        let watchdog = Watchdog::new(
+            listen_addr,
+            obfuscator_addr,
+            settings.keepalive_interval,
+            settings.idle_timeout,
+            stats.clone(),
+        )
+        .await
+        .map_err(Error::CreateWatchdog)?;
+        let local_addr = watchdog.local_addr().map_err(Error::GetUdpSocketDetails)?;
+
         Ok(Self {
             local_addr,
             instance,
+            watchdog,
+            forwarder,
+            stats,
         })
     }
+
+    /// Relays datagrams until the connection to the relay is lost or the watchdog stops.
+    pub(crate) async fn relay(self) -> Result<()> {
+        let Udp2Tcp {
+            instance,
+            watchdog,
+            forwarder,
+            ..
+        } = self;
+        let forwarder = async move {
+            match forwarder {
+                Some(forwarder) => forwarder.run().await,
+                None => future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = instance.run() => result.map_err(Error::RunObfuscator),
+            result = watchdog.run() => result.map_err(Error::Watchdog),
+            result = forwarder => result,
+        }
+    }
+
+    #[cfg(target_os = "android")]
+    pub(crate) fn remote_socket_fd(&self) -> std::os::unix::io::RawFd {
+        match &self.forwarder {
+            Some(forwarder) => std::os::unix::io::AsRawFd::as_raw_fd(&forwarder.remote_socket),
+            None => self.instance.remote_tcp_fd(),
+        }
+    }
+}
+
+/// Forwards a connection accepted on a local socket to the relay, through a SOCKS5 proxy and
+/// wrapped in TLS if these are set.
+struct Forwarder {
+    listener: TcpListener,
+    /// Created up front so that it can be excluded from the tunnel
+    remote_socket: TcpSocket,
+    peer: SocketAddr,
+    upstream_proxy: Option<SocksProxy>,
+    tls: Option<TlsLayer>,
+}
+
+impl Forwarder {
+    fn new(
+        listen_ip: IpAddr,
+        peer: SocketAddr,
+        upstream_proxy: Option<SocksProxy>,
+        tls: Option<TlsLayer>,
+        #[cfg(target_os = "linux")] fwmark: Option<u32>,
+    ) -> std::io::Result<Self> {
+        let remote_addr = upstream_proxy
+            .as_ref()
+            .map(|proxy| proxy.peer)
+            .unwrap_or(peer);
+        let remote_socket = match remote_addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        #[cfg(target_os = "linux")]
+        if let Some(fwmark) = fwmark {
+            socket2::SockRef::from(&remote_socket).set_mark(fwmark)?;
+        }
+
+        let socket = match listen_ip {
+            IpAddr::V4(_) => TcpSocket::new_v4()?,
+            IpAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        // Lets the port be reused right away, even if connections of an earlier attempt linger.
+        // This is not done for the UDP sockets, where it would let several sockets receive the
+        // same datagrams, or on Windows, where it would let other sockets take over the port
+        #[cfg(unix)]
+        socket.set_reuseaddr(true)?;
+        socket.bind(SocketAddr::new(listen_ip, 0))?;
+        let listener = socket.listen(1)?;
+        Ok(Self {
+            listener,
+            remote_socket,
+            peer,
+            upstream_proxy,
+            tls,
+        })
+    }
+
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Forwards the first accepted connection until either end closes it. udp-over-tcp only
+    /// ever makes a single connection.
+    async fn run(self) -> Result<()> {
+        let (mut local_stream, _) = self.listener.accept().await.map_err(Error::Forward)?;
+        let mut remote_stream = match &self.upstream_proxy {
+            Some(proxy) => socks::connect(self.remote_socket, proxy, self.peer)
+                .await
+                .map_err(Error::UpstreamProxy)?,
+            None => {
+                let stream = self
+                    .remote_socket
+                    .connect(self.peer)
+                    .await
+                    .map_err(Error::Connect)?;
+                // Disables the Nagle algorithm on the TCP socket. Improves performance
+                stream.set_nodelay(true).map_err(Error::Connect)?;
+                stream
+            }
+        };
+        let result = match self.tls {
+            Some(tls) => {
+                let mut tls_stream = tls
+                    .connect(remote_stream)
+                    .await
+                    .map_err(Error::TlsHandshake)?;
+                tokio::io::copy_bidirectional(&mut local_stream, &mut tls_stream).await
+            }
+            None => tokio::io::copy_bidirectional(&mut local_stream, &mut remote_stream).await,
+        };
+        result.map(|_| ()).map_err(Error::Forward)
+    }
 }
 
 #[async_trait]
@@ -74,19 +286,212 @@ impl Obfuscator for Udp2Tcp {
     }
 
     async fn run(self: Box<Self>) -> crate::Result<()> {
-        self.instance
-            .run()
+        self.relay()
             .await
-            .map_err(Error::RunObfuscator)
             .map_err(crate::Error::RunUdp2TcpObfuscator)
     }
 
+    fn stats(&self) -> Arc<StatsCounters> {
+        self.stats.clone()
+    }
+
     #[cfg(target_os = "android")]
     fn remote_socket_fd(&self) -> std::os::unix::io::RawFd {
-        self.instance.remote_tcp_fd()
+        Udp2Tcp::remote_socket_fd(self)
     }
 }
 
 pub async fn create_obfuscator(settings: &Udp2TcpSettings) -> Result<Box<dyn Obfuscator>> {
     Ok(Box::new(Udp2Tcp::new(settings).await?))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, UdpSocket},
+        time::timeout,
+    };
+
+    /// A WireGuard handshake initiation, framed like udp-over-tcp sends it over TCP.
+    const FRAMED_HANDSHAKE_SIZE: usize = 2 + 148;
+
+    fn handshake_initiation() -> Vec<u8> {
+        let mut datagram = vec![0u8; 148];
+        datagram[0] = 1;
+        datagram
+    }
+
+    async fn start_obfuscator(
+        keepalive_interval: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) -> (TcpListener, Box<dyn Obfuscator>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let obfuscator = create_obfuscator(&Udp2TcpSettings {
+            peer: listener.local_addr().unwrap(),
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+            keepalive_interval,
+            idle_timeout,
+            upstream_proxy: None,
+        })
+        .await
+        .unwrap();
+        (listener, obfuscator)
+    }
+
+    #[tokio::test]
+    async fn test_stalled_connection_fails() {
+        let (listener, obfuscator) = start_obfuscator(None, Some(Duration::from_millis(300))).await;
+        let endpoint = obfuscator.endpoint();
+        let obfuscator = tokio::spawn(obfuscator.run());
+
+        // Echoes the first handshake, then stops responding without closing the connection
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut frame = [0u8; FRAMED_HANDSHAKE_SIZE];
+            stream.read_exact(&mut frame).await.unwrap();
+            stream.write_all(&frame).await.unwrap();
+            let mut sink = vec![0u8; 1024];
+            while stream.read(&mut sink).await.unwrap() > 0 {}
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(endpoint).await.unwrap();
+        client.send(&handshake_initiation()).await.unwrap();
+        let mut response = vec![0u8; 1024];
+        let len = timeout(Duration::from_secs(5), client.recv(&mut response))
+            .await
+            .expect("no response from echo server")
+            .unwrap();
+        assert_eq!(response[..len], handshake_initiation()[..]);
+
+        // A handshake that goes unanswered brings the obfuscator down
+        client.send(&handshake_initiation()).await.unwrap();
+        let result = timeout(Duration::from_secs(5), obfuscator)
+            .await
+            .expect("obfuscator did not detect the stalled connection")
+            .unwrap();
+        assert!(matches!(
+            result,
+            Err(crate::Error::RunUdp2TcpObfuscator(Error::Watchdog(
+                crate::watchdog::Error::Stalled(_)
+            )))
+        ));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_stats_are_counted() {
+        let (listener, obfuscator) = start_obfuscator(None, None).await;
+        let endpoint = obfuscator.endpoint();
+        let stats = obfuscator.stats();
+        let obfuscator = tokio::spawn(obfuscator.run());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (mut reader, mut writer) = stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(endpoint).await.unwrap();
+        let mut response = vec![0u8; 1024];
+        for _ in 0..3 {
+            client.send(&handshake_initiation()).await.unwrap();
+            timeout(Duration::from_secs(5), client.recv(&mut response))
+                .await
+                .expect("no response from echo server")
+                .unwrap();
+        }
+
+        let stats = stats.snapshot();
+        assert_eq!(stats.tx_bytes, 3 * 148);
+        assert_eq!(stats.rx_bytes, 3 * 148);
+
+        obfuscator.abort();
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_is_sent() {
+        let (listener, obfuscator) = start_obfuscator(Some(Duration::from_millis(100)), None).await;
+        let endpoint = obfuscator.endpoint();
+        let obfuscator = tokio::spawn(obfuscator.run());
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client
+            .send_to(&handshake_initiation(), endpoint)
+            .await
+            .unwrap();
+
+        let (mut stream, _) = timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut frame = [0u8; FRAMED_HANDSHAKE_SIZE];
+        stream.read_exact(&mut frame).await.unwrap();
+
+        // The keepalive is an empty datagram, so only the length header is sent
+        let mut keepalive = [0xffu8; 2];
+        timeout(Duration::from_secs(5), stream.read_exact(&mut keepalive))
+            .await
+            .expect("no keepalive was sent")
+            .unwrap();
+        assert_eq!(keepalive, [0, 0]);
+        obfuscator.abort();
+    }
+
+    #[tokio::test]
+    async fn test_upstream_proxy_is_used() {
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let obfuscator = create_obfuscator(&Udp2TcpSettings {
+            peer: relay,
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+            keepalive_interval: None,
+            idle_timeout: None,
+            upstream_proxy: Some(SocksProxy {
+                peer: proxy_listener.local_addr().unwrap(),
+                auth: None,
+            }),
+        })
+        .await
+        .unwrap();
+        let endpoint = obfuscator.endpoint();
+        let obfuscator = tokio::spawn(obfuscator.run());
+
+        // Accepts a CONNECT request for the relay, then acts as an echo server
+        let proxy = tokio::spawn(async move {
+            let (mut stream, _) = proxy_listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            stream.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [5, 1, 0, 1, 10, 0, 0, 1, 0x01, 0xbb]);
+            stream
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            let (mut reader, mut writer) = stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(endpoint).await.unwrap();
+        client.send(&handshake_initiation()).await.unwrap();
+        let mut response = vec![0u8; 1024];
+        let len = timeout(Duration::from_secs(5), client.recv(&mut response))
+            .await
+            .expect("no response through the proxy")
+            .unwrap();
+        assert_eq!(response[..len], handshake_initiation()[..]);
+
+        obfuscator.abort();
+        proxy.abort();
+    }
+}
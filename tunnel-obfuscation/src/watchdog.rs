@@ -0,0 +1,165 @@
+//! Relays datagrams between WireGuard and the local socket of an obfuscator, to count the relayed
+//! traffic, keep idle connections alive, and detect connections that have stopped delivering data.
+
+use crate::StatsCounters;
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
+
+/// Size of a WireGuard handshake initiation message.
+const HANDSHAKE_INITIATION_SIZE: usize = 148;
+/// Message type of a WireGuard handshake initiation, followed by three reserved zero bytes.
+const HANDSHAKE_INITIATION_HEADER: [u8; 4] = [1, 0, 0, 0];
+/// Largest interval between checks for stalled connections and keepalives.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Largest datagram that can be sent over udp-over-tcp.
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to relay a datagram
+    #[error(display = "Failed to relay datagram")]
+    Relay(#[error(source)] io::Error),
+
+    /// Handshakes have not been answered for too long
+    #[error(
+        display = "No data was received for {:?} while handshakes were unanswered",
+        _0
+    )]
+    Stalled(Duration),
+}
+
+pub struct Watchdog {
+    client_socket: UdpSocket,
+    obfuscator_socket: UdpSocket,
+    keepalive_interval: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    stats: Arc<StatsCounters>,
+}
+
+impl Watchdog {
+    /// Binds a socket for WireGuard to `listen_addr`, and relays datagrams sent to it to the
+    /// obfuscator listening on `obfuscator_addr`.
+    ///
+    /// If `keepalive_interval` is set, an empty datagram is sent to the obfuscator whenever
+    /// nothing has been sent for that long. WireGuard drops empty datagrams, but they keep the
+    /// connection of the obfuscator from looking idle. If `idle_timeout` is set, the watchdog
+    /// fails if nothing is received for that long after WireGuard has initiated a handshake.
+    /// Datagrams from and to WireGuard are counted in `stats`.
+    pub async fn new(
+        listen_addr: SocketAddr,
+        obfuscator_addr: SocketAddr,
+        keepalive_interval: Option<Duration>,
+        idle_timeout: Option<Duration>,
+        stats: Arc<StatsCounters>,
+    ) -> io::Result<Self> {
+        let client_socket = UdpSocket::bind(listen_addr).await?;
+        let obfuscator_socket = UdpSocket::bind(SocketAddr::new(listen_addr.ip(), 0)).await?;
+        obfuscator_socket.connect(obfuscator_addr).await?;
+
+        Ok(Self {
+            client_socket,
+            obfuscator_socket,
+            keepalive_interval,
+            idle_timeout,
+            stats,
+        })
+    }
+
+    /// Returns the address WireGuard should send datagrams to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.client_socket.local_addr()
+    }
+
+    /// Relays datagrams until the connection stalls or a socket fails.
+    pub async fn run(self) -> Result<(), Error> {
+        let mut client_buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+        let mut obfuscator_buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+        let mut client_addr = None;
+        let mut last_sent = Instant::now();
+        let mut unanswered_handshake_since = None;
+
+        let mut check_interval = tokio::time::interval(self.check_interval());
+        check_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                result = self.client_socket.recv_from(&mut client_buffer) => {
+                    let (len, addr) = result.map_err(Error::Relay)?;
+                    let datagram = &client_buffer[..len];
+                    client_addr = Some(addr);
+                    if is_handshake_initiation(datagram) && unanswered_handshake_since.is_none() {
+                        unanswered_handshake_since = Some(Instant::now());
+                    }
+                    self.obfuscator_socket.send(datagram).await.map_err(Error::Relay)?;
+                    self.stats.add_tx_bytes(len);
+                    last_sent = Instant::now();
+                }
+                result = self.obfuscator_socket.recv(&mut obfuscator_buffer) => {
+                    let len = result.map_err(Error::Relay)?;
+                    unanswered_handshake_since = None;
+                    if let Some(addr) = client_addr {
+                        self.stats.add_rx_bytes(len);
+                        self.client_socket
+                            .send_to(&obfuscator_buffer[..len], addr)
+                            .await
+                            .map_err(Error::Relay)?;
+                    }
+                }
+                _ = check_interval.tick() => {
+                    let now = Instant::now();
+                    if let (Some(timeout), Some(since)) =
+                        (self.idle_timeout, unanswered_handshake_since)
+                    {
+                        if now.saturating_duration_since(since) >= timeout {
+                            return Err(Error::Stalled(timeout));
+                        }
+                    }
+                    if let Some(interval) = self.keepalive_interval {
+                        // The obfuscator connects once WireGuard has sent something
+                        if client_addr.is_some()
+                            && now.saturating_duration_since(last_sent) >= interval
+                        {
+                            self.obfuscator_socket.send(&[]).await.map_err(Error::Relay)?;
+                            last_sent = now;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_interval(&self) -> Duration {
+        [self.keepalive_interval, self.idle_timeout]
+            .into_iter()
+            .flatten()
+            .fold(MAX_CHECK_INTERVAL, Duration::min)
+            .max(Duration::from_millis(10))
+    }
+}
+
+fn is_handshake_initiation(datagram: &[u8]) -> bool {
+    datagram.len() == HANDSHAKE_INITIATION_SIZE && datagram[..4] == HANDSHAKE_INITIATION_HEADER
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_handshake_initiation() {
+        let mut datagram = [0u8; HANDSHAKE_INITIATION_SIZE];
+        assert!(!is_handshake_initiation(&datagram));
+        datagram[0] = 1;
+        assert!(is_handshake_initiation(&datagram));
+        // Handshake responses and transport data use other message types
+        datagram[0] = 4;
+        assert!(!is_handshake_initiation(&datagram));
+        assert!(!is_handshake_initiation(&[1, 0, 0, 0]));
+    }
+}